@@ -15,7 +15,9 @@ extern crate vmm;
 
 mod parsed_request;
 mod request;
+mod response_queue;
 
+use std::os::unix::io::AsRawFd;
 use std::path::PathBuf;
 use std::sync::{mpsc, Arc, Mutex, RwLock};
 use std::{fmt, io};
@@ -28,8 +30,12 @@ pub use micro_http::{
 use mmds::data_store;
 use mmds::data_store::Mmds;
 use parsed_request::ParsedRequest;
+use response_queue::ResponseQueue;
 use seccomp::{BpfProgram, SeccompFilter};
+use utils::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
 use utils::eventfd::EventFd;
+use vmm::error_code::ErrorCategory;
+use vmm::resources::ConfigIssue;
 use vmm::rpc_interface::{VmmAction, VmmActionError, VmmData};
 use vmm::vmm_config::instance_info::InstanceInfo;
 
@@ -38,6 +44,14 @@ pub type ApiRequest = Box<VmmAction>;
 /// Shorthand type for a response containing a boxed Result.
 pub type ApiResponse = Box<std::result::Result<VmmData, VmmActionError>>;
 
+/// The maximum number of actions waiting for the VMM, including the one it runs. The actions
+/// requested beyond it are rejected, instead of piling up behind a long action.
+pub const MAX_QUEUED_ACTIONS: usize = 16;
+
+// The epoll tokens of the API thread.
+const SERVER_TOKEN: u64 = 0;
+const VMM_RESPONSE_TOKEN: u64 = 1;
+
 pub enum Error {
     Io(io::Error),
     Eventfd(io::Error),
@@ -75,6 +89,10 @@ pub struct ApiServer {
     /// FD on which we notify the VMM that we have sent at least one
     /// `VmmRequest`.
     to_vmm_fd: EventFd,
+    /// FD on which the VMM notifies us that it has sent at least one response.
+    from_vmm_fd: EventFd,
+    /// The responses to the requests, in order, while their actions wait for the VMM.
+    responses: ResponseQueue,
 }
 
 impl ApiServer {
@@ -84,6 +102,7 @@ impl ApiServer {
         api_request_sender: mpsc::Sender<ApiRequest>,
         vmm_response_receiver: mpsc::Receiver<ApiResponse>,
         to_vmm_fd: EventFd,
+        from_vmm_fd: EventFd,
     ) -> Result<Self> {
         Ok(ApiServer {
             mmds_info,
@@ -91,6 +110,8 @@ impl ApiServer {
             api_request_sender,
             vmm_response_receiver,
             to_vmm_fd,
+            from_vmm_fd,
+            responses: ResponseQueue::new(MAX_QUEUED_ACTIONS),
         })
     }
 
@@ -102,6 +123,9 @@ impl ApiServer {
         seccomp_filter: BpfProgram,
     ) -> Result<()> {
         let mut server = HttpServer::new(path).expect("Error creating the HTTP server");
+        // The requests of the clients and the responses of the VMM are waited for at once, so
+        // that the clients are served while the VMM runs an action.
+        let epoll = Epoll::new().map_err(Error::Io)?;
 
         if let Some(start_time) = start_time_us {
             let delta_us =
@@ -132,34 +156,62 @@ impl ApiServer {
         }
 
         server.start_server().unwrap();
+        epoll
+            .ctl(
+                ControlOperation::Add,
+                server.epoll().as_raw_fd(),
+                &EpollEvent::new(EventSet::IN, SERVER_TOKEN),
+            )
+            .map_err(Error::Io)?;
+        epoll
+            .ctl(
+                ControlOperation::Add,
+                self.from_vmm_fd.as_raw_fd(),
+                &EpollEvent::new(EventSet::IN, VMM_RESPONSE_TOKEN),
+            )
+            .map_err(Error::Eventfd)?;
+
+        let mut events = vec![EpollEvent::default(); 2];
         loop {
-            match server.requests() {
-                Ok(request_vec) => {
-                    for server_request in request_vec {
-                        server
-                            .respond(
-                                // Use `self.handle_request()` as the processing callback.
-                                server_request.process(|request| self.handle_request(request)),
-                            )
-                            .or_else(|e| {
-                                error!("API Server encountered an error on response: {}", e);
-                                Ok(())
-                            })?;
+            let event_count = match epoll.wait(events.len(), -1, &mut events[..]) {
+                Ok(event_count) => event_count,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => 0,
+                Err(e) => return Err(Error::Io(e)),
+            };
+            for event in events.iter().take(event_count) {
+                if event.data() == VMM_RESPONSE_TOKEN {
+                    self.collect_vmm_responses();
+                    continue;
+                }
+                match server.requests() {
+                    Ok(request_vec) => {
+                        for server_request in request_vec {
+                            self.handle_request(server_request.inner(), server_request.id());
+                        }
+                    }
+                    Err(e) => {
+                        error!(
+                            "API Server error on retrieving incoming request. Error: {}",
+                            e
+                        );
                     }
                 }
-                Err(e) => {
-                    error!(
-                        "API Server error on retrieving incoming request. Error: {}",
-                        e
-                    );
+            }
+            for (id, response) in self.responses.pop_ready() {
+                if let Err(e) = server.respond(ServerResponse::new(response, id)) {
+                    error!("API Server encountered an error on response: {}", e);
                 }
             }
         }
     }
 
-    fn handle_request(&self, request: &Request) -> Response {
-        match ParsedRequest::try_from_request(request) {
-            Ok(ParsedRequest::Sync(vmm_action)) => self.serve_vmm_action_request(vmm_action),
+    // Serves the request `id`, or sends its action to the VMM, in which case its response waits
+    // for the VMM.
+    fn handle_request(&mut self, request: &Request, id: u64) {
+        let response = match ParsedRequest::try_from_request(request) {
+            Ok(ParsedRequest::Sync(vmm_action)) => {
+                return self.serve_vmm_action_request(vmm_action, id)
+            }
             Ok(ParsedRequest::GetInstanceInfo) => self.get_instance_info(),
             Ok(ParsedRequest::GetMMDS) => self.get_mmds(),
             Ok(ParsedRequest::PatchMMDS(value)) => self.patch_mmds(value),
@@ -168,14 +220,38 @@ impl ApiServer {
                 error!("{}", e);
                 e.into()
             }
-        }
+        };
+        self.responses.push_response(id, response);
     }
 
-    fn serve_vmm_action_request(&self, vmm_action: VmmAction) -> Response {
+    // Sends the action of the request `id` to the VMM, unless too many actions wait for it
+    // already. The VMM runs the actions in the order they are sent.
+    fn serve_vmm_action_request(&mut self, vmm_action: VmmAction, id: u64) {
+        if self.responses.is_full() {
+            METRICS.api_server.action_queue_full_count.inc();
+            let err = VmmActionError::ActionQueueFull(self.responses.max_actions());
+            warn!("{}", err);
+            let mut response = Response::new(Version::Http11, StatusCode::ServiceUnavailable);
+            response.set_body(Body::new(ApiServer::json_action_fault(&err)));
+            self.responses.push_response(id, response);
+            return;
+        }
         self.api_request_sender.send(Box::new(vmm_action)).unwrap();
         self.to_vmm_fd.write(1).unwrap();
-        let vmm_outcome = *(self.vmm_response_receiver.recv().unwrap());
-        ParsedRequest::convert_to_response(vmm_outcome)
+        self.responses.push_action(id);
+    }
+
+    // Collects the responses of the VMM, which come in the order the actions were sent.
+    fn collect_vmm_responses(&mut self) {
+        // The VMM notifies us after sending each response, so all the responses notified of are
+        // received below.
+        let _ = self.from_vmm_fd.read();
+        while let Ok(vmm_outcome) = self.vmm_response_receiver.try_recv() {
+            let response = ParsedRequest::convert_to_response(*vmm_outcome);
+            if !self.responses.complete_action(response) {
+                error!("The VMM responded to no action.");
+            }
+        }
     }
 
     fn get_instance_info(&self) -> Response {
@@ -255,14 +331,44 @@ impl ApiServer {
     //  {
     //    "$k": "$v"
     //  }
-    // Mainly used for building fault message response json bodies.
+    // Mainly used for building fault message response json bodies. The value is escaped, so
+    // messages quoting paths or nested errors remain valid json.
     fn basic_json_body<K: AsRef<str>, V: AsRef<str>>(k: K, v: V) -> String {
-        format!("{{\n  \"{}\": \"{}\"\n}}", k.as_ref(), v.as_ref())
+        format!(
+            "{{\n  \"{}\": {}\n}}",
+            k.as_ref(),
+            serde_json::Value::from(v.as_ref())
+        )
     }
 
     fn json_fault_message<T: AsRef<str>>(msg: T) -> String {
         ApiServer::basic_json_body("fault_message", msg)
     }
+
+    // Builds the response json body of a failed action, which carries the stable code of the
+    // error next to its message, and the problems found with the configuration, if any.
+    fn json_action_fault(err: &VmmActionError) -> String {
+        let error_code = err.error_code();
+        let fault = ActionFault {
+            fault_message: err.to_string(),
+            error_code: error_code.code(),
+            error_category: err.error_category(),
+            retriable: err.retriable(),
+            config_issues: err.config_issues().to_vec(),
+        };
+        // Serializing plain strings, numbers and unit enum variants cannot fail.
+        serde_json::to_string_pretty(&fault).unwrap()
+    }
+}
+
+#[derive(Serialize)]
+struct ActionFault {
+    fault_message: String,
+    error_code: u32,
+    error_category: ErrorCategory,
+    retriable: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    config_issues: Vec<ConfigIssue>,
 }
 
 #[cfg(test)]
@@ -280,6 +386,7 @@ mod tests {
     use micro_http::HttpConnection;
     use mmds::MMDS;
     use vmm::builder::StartMicrovmError;
+    use vmm::probes::ProbeStatuses;
     use vmm::rpc_interface::VmmActionError;
     use vmm::vmm_config::instance_info::InstanceInfo;
 
@@ -311,6 +418,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_json_fault_message() {
+        assert_eq!(
+            ApiServer::json_fault_message("message"),
+            "{\n  \"fault_message\": \"message\"\n}"
+        );
+        // Quotes in messages are escaped.
+        let json = ApiServer::json_fault_message("Cannot open \"/tmp/foo\".");
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["fault_message"], "Cannot open \"/tmp/foo\".");
+    }
+
     #[test]
     fn test_serve_vmm_action_request() {
         let vmm_shared_info = Arc::new(RwLock::new(InstanceInfo {
@@ -318,29 +437,51 @@ mod tests {
             id: "test_serve_action_req".to_string(),
             vmm_version: "version 0.1.0".to_string(),
             app_name: "app name".to_string(),
+            probes: ProbeStatuses::default(),
         }));
 
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
-        let (api_request_sender, _from_api) = channel();
+        let from_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let to_api_fd = from_vmm_fd.try_clone().unwrap();
+        let (api_request_sender, from_api) = channel();
         let (to_api, vmm_response_receiver) = channel();
         let mmds_info = MMDS.clone();
 
-        let api_server = ApiServer::new(
+        let mut api_server = ApiServer::new(
             mmds_info,
             vmm_shared_info,
             api_request_sender,
             vmm_response_receiver,
             to_vmm_fd,
+            from_vmm_fd,
         )
         .unwrap();
 
+        // The response waits for the VMM.
+        api_server.serve_vmm_action_request(VmmAction::StartMicroVm, 1);
+        assert!(*from_api.try_recv().unwrap() == VmmAction::StartMicroVm);
+        assert!(api_server.responses.pop_ready().is_empty());
         to_api
             .send(Box::new(Err(VmmActionError::StartMicrovm(
                 StartMicrovmError::MicroVMAlreadyRunning,
             ))))
             .unwrap();
-        let response = api_server.serve_vmm_action_request(VmmAction::StartMicroVm);
-        assert_eq!(response.status(), StatusCode::BadRequest);
+        to_api_fd.write(1).unwrap();
+        api_server.collect_vmm_responses();
+        let ready = api_server.responses.pop_ready();
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].0, 1);
+        assert_eq!(ready[0].1.status(), StatusCode::BadRequest);
+
+        // The actions beyond the maximum are rejected right away.
+        for id in 0..=MAX_QUEUED_ACTIONS as u64 {
+            api_server.serve_vmm_action_request(VmmAction::StartMicroVm, id);
+        }
+        let ready = api_server.responses.pop_ready();
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].0, MAX_QUEUED_ACTIONS as u64);
+        assert_eq!(ready[0].1.status(), StatusCode::ServiceUnavailable);
+        assert_eq!(from_api.try_iter().count(), MAX_QUEUED_ACTIONS);
     }
 
     #[test]
@@ -350,6 +491,7 @@ mod tests {
             id: "test_get_instance_info".to_string(),
             vmm_version: "version 0.1.0".to_string(),
             app_name: "app name".to_string(),
+            probes: ProbeStatuses::default(),
         }));
 
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
@@ -363,6 +505,7 @@ mod tests {
             api_request_sender,
             vmm_response_receiver,
             to_vmm_fd,
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
         )
         .unwrap();
 
@@ -377,6 +520,7 @@ mod tests {
             id: "test_get_mmds".to_string(),
             vmm_version: "version 0.1.0".to_string(),
             app_name: "app name".to_string(),
+            probes: ProbeStatuses::default(),
         }));
 
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
@@ -390,6 +534,7 @@ mod tests {
             api_request_sender,
             vmm_response_receiver,
             to_vmm_fd,
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
         )
         .unwrap();
 
@@ -404,6 +549,7 @@ mod tests {
             id: "test_put_mmds".to_string(),
             vmm_version: "version 0.1.0".to_string(),
             app_name: "app name".to_string(),
+            probes: ProbeStatuses::default(),
         }));
 
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
@@ -417,6 +563,7 @@ mod tests {
             api_request_sender,
             vmm_response_receiver,
             to_vmm_fd,
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
         )
         .unwrap();
 
@@ -431,6 +578,7 @@ mod tests {
             id: "test_patch_mmds".to_string(),
             vmm_version: "version 0.1.0".to_string(),
             app_name: "app name".to_string(),
+            probes: ProbeStatuses::default(),
         }));
 
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
@@ -444,6 +592,7 @@ mod tests {
             api_request_sender,
             vmm_response_receiver,
             to_vmm_fd,
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
         )
         .unwrap();
 
@@ -467,26 +616,30 @@ mod tests {
             id: "test_handle_request".to_string(),
             vmm_version: "version 0.1.0".to_string(),
             app_name: "app name".to_string(),
+            probes: ProbeStatuses::default(),
         }));
 
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
         let (api_request_sender, _from_api) = channel();
-        let (to_api, vmm_response_receiver) = channel();
+        let (_to_api, vmm_response_receiver) = channel();
         let mmds_info = MMDS.clone();
 
-        let api_server = ApiServer::new(
+        let mut api_server = ApiServer::new(
             mmds_info,
             vmm_shared_info,
             api_request_sender,
             vmm_response_receiver,
             to_vmm_fd,
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
         )
         .unwrap();
-        to_api
-            .send(Box::new(Err(VmmActionError::StartMicrovm(
-                StartMicrovmError::MicroVMAlreadyRunning,
-            ))))
-            .unwrap();
+        // Serves the request, and returns the response sent right away.
+        let mut serve = |request: &Request| {
+            api_server.handle_request(request, 0);
+            let mut ready = api_server.responses.pop_ready();
+            assert_eq!(ready.len(), 1);
+            ready.remove(0).1
+        };
 
         // Test an Actions request.
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -503,21 +656,21 @@ mod tests {
             .unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
-        let response = api_server.handle_request(&req);
+        let response = serve(&req);
         assert_eq!(response.status(), StatusCode::BadRequest);
 
         // Test a Get Info request.
         sender.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
-        let response = api_server.handle_request(&req);
+        let response = serve(&req);
         assert_eq!(response.status(), StatusCode::OK);
 
         // Test a Get Mmds request.
         sender.write_all(b"GET /mmds HTTP/1.1\r\n\r\n").unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
-        let response = api_server.handle_request(&req);
+        let response = serve(&req);
         assert_eq!(response.status(), StatusCode::OK);
 
         // Test a Put Mmds request.
@@ -530,7 +683,7 @@ mod tests {
             .unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
-        let response = api_server.handle_request(&req);
+        let response = serve(&req);
         assert_eq!(response.status(), StatusCode::NoContent);
 
         // Test a Patch Mmds request.
@@ -543,7 +696,7 @@ mod tests {
             .unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
-        let response = api_server.handle_request(&req);
+        let response = serve(&req);
         assert_eq!(response.status(), StatusCode::NoContent);
 
         // Test erroneous request.
@@ -556,7 +709,7 @@ mod tests {
             .unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
-        let response = api_server.handle_request(&req);
+        let response = serve(&req);
         assert_eq!(response.status(), StatusCode::BadRequest);
     }
 
@@ -569,11 +722,14 @@ mod tests {
             id: "test_handle_request".to_string(),
             vmm_version: "version 0.1.0".to_string(),
             app_name: "app name".to_string(),
+            probes: ProbeStatuses::default(),
         }));
 
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
-        let (api_request_sender, _from_api) = channel();
-        let (_to_api, vmm_response_receiver) = channel();
+        let from_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let to_api_fd = from_vmm_fd.try_clone().unwrap();
+        let (api_request_sender, from_api) = channel();
+        let (to_api, vmm_response_receiver) = channel();
         let mmds_info = MMDS.clone();
 
         thread::Builder::new()
@@ -585,6 +741,7 @@ mod tests {
                     api_request_sender,
                     vmm_response_receiver,
                     to_vmm_fd,
+                    from_vmm_fd,
                 )
                 .expect("Cannot create API server")
                 .bind_and_run(
@@ -610,5 +767,29 @@ mod tests {
         assert!(sock.write_all(b"OPTIONS / HTTP/1.1\r\n\r\n").is_ok());
         let mut buf: [u8; 100] = [0; 100];
         assert!(sock.read(&mut buf[..]).unwrap() > 0);
+
+        // Send an action, which waits for the VMM.
+        let body = r#"{"action_type": "InstanceStart"}"#;
+        let request = format!(
+            "PUT /actions HTTP/1.1\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        assert!(sock.write_all(request.as_bytes()).is_ok());
+        assert!(*from_api.recv().unwrap() == VmmAction::StartMicroVm);
+
+        // The other clients are served meanwhile.
+        let mut other_sock =
+            UnixStream::connect(PathBuf::from(path_to_socket.to_string())).unwrap();
+        assert!(other_sock.write_all(b"GET / HTTP/1.1\r\n\r\n").is_ok());
+        let mut buf: [u8; 100] = [0; 100];
+        assert!(other_sock.read(&mut buf[..]).unwrap() > 0);
+
+        to_api.send(Box::new(Ok(VmmData::Empty))).unwrap();
+        to_api_fd.write(1).unwrap();
+        let mut buf: [u8; 100] = [0; 100];
+        assert!(sock.read(&mut buf[..]).unwrap() > 0);
+        assert!(buf.starts_with(b"HTTP/1.1 204"));
     }
 }
@@ -6,18 +6,49 @@ use serde_json::Value;
 use super::VmmData;
 use micro_http::{Body, Method, Request, Response, StatusCode, Version};
 use request::actions::parse_put_actions;
+use request::balloon::{parse_patch_balloon, parse_put_balloon};
 use request::boot_source::parse_put_boot_source;
-use request::drive::{parse_patch_drive, parse_put_drive};
+use request::cloud_init::parse_put_cloud_init;
+use request::console::parse_put_console;
+use request::devices::parse_get_devices;
+use request::drive::{
+    parse_delete_drive, parse_patch_drive, parse_put_drive, parse_put_drive_snapshot,
+    parse_put_drive_state,
+};
+use request::entropy::parse_put_entropy;
+use request::events::parse_get_events;
+use request::gpu::parse_put_gpu;
+use request::guest_dmesg::{parse_get_guest_dmesg, parse_put_guest_dmesg};
+use request::host_capabilities::parse_get_host_capabilities;
+use request::input::parse_put_input;
 use request::instance_info::parse_get_instance_info;
 use request::logger::parse_put_logger;
 use request::machine_configuration::{
     parse_get_machine_config, parse_patch_machine_config, parse_put_machine_config,
 };
+use request::memory_limits::parse_put_memory_limits;
 use request::metrics::parse_put_metrics;
 use request::mmds::{parse_get_mmds, parse_patch_mmds, parse_put_mmds};
-use request::net::{parse_patch_net, parse_put_net};
+use request::net::{parse_delete_net, parse_patch_net, parse_put_net, parse_put_net_link_state};
+use request::operations::parse_get_operation;
+use request::pci_passthrough::parse_put_pci_passthrough;
+use request::probe::parse_put_probe;
+use request::rate_limit_policy::parse_put_rate_limit_policy;
+use request::rate_limiters::parse_get_rate_limiters;
+use request::resource_usage::parse_get_resource_usage;
+use request::rtc::parse_put_rtc;
+#[cfg(feature = "sev")]
+use request::sev::{parse_get_launch_measurement, parse_put_sev};
+use request::shared_memory::parse_put_shared_memory;
 use request::snapshot::{parse_patch_vm_state, parse_put_snapshot};
-use request::vsock::parse_put_vsock;
+#[cfg(target_arch = "x86_64")]
+use request::snapshot::{parse_put_live_update, parse_put_migrate};
+use request::sound::parse_put_sound;
+use request::validate::parse_put_validate;
+use request::vcpu::parse_put_vcpu_state;
+use request::virtio_features::parse_put_virtio_features;
+use request::vm_config::parse_get_vm_config;
+use request::vsock::{parse_get_vsock, parse_put_vsock};
 use ApiServer;
 
 use vmm::rpc_interface::{VmmAction, VmmActionError};
@@ -46,24 +77,78 @@ impl ParsedRequest {
             path_tokens[0]
         };
 
-        match (request.method(), path, request.body.as_ref()) {
+        let parsed_request = match (request.method(), path, request.body.as_ref()) {
             (Method::Get, "", None) => parse_get_instance_info(),
+            (Method::Get, "devices", None) => {
+                parse_get_devices(path_tokens.get(1), path_tokens.get(2))
+            }
+            (Method::Get, "events", None) => parse_get_events(),
+            (Method::Get, "guest-dmesg", None) => parse_get_guest_dmesg(),
+            (Method::Get, "host-capabilities", None) => parse_get_host_capabilities(),
+            #[cfg(feature = "sev")]
+            (Method::Get, "launch-measurement", None) => parse_get_launch_measurement(),
             (Method::Get, "machine-config", None) => parse_get_machine_config(),
             (Method::Get, "mmds", None) => parse_get_mmds(),
+            (Method::Get, "operations", None) => parse_get_operation(path_tokens.get(1)),
+            (Method::Get, "rate-limiters", None) => parse_get_rate_limiters(),
+            (Method::Get, "resource-usage", None) => parse_get_resource_usage(),
+            (Method::Get, "vm", None) => parse_get_vm_config(path_tokens.get(1)),
+            (Method::Get, "vsock", None) => parse_get_vsock(path_tokens.get(1)),
             (Method::Get, _, Some(_)) => method_to_error(Method::Get),
             (Method::Put, "actions", Some(body)) => parse_put_actions(body),
+            (Method::Put, "balloon", Some(body)) => parse_put_balloon(body),
             (Method::Put, "boot-source", Some(body)) => parse_put_boot_source(body),
+            (Method::Put, "cloud-init", Some(body)) => parse_put_cloud_init(body),
+            (Method::Put, "console", Some(body)) => parse_put_console(body),
+            (Method::Put, "drives", Some(body)) if path_tokens.get(2) == Some(&"state") => {
+                parse_put_drive_state(body, path_tokens.get(1))
+            }
+            (Method::Put, "drives", Some(body)) if path_tokens.get(2) == Some(&"snapshot") => {
+                parse_put_drive_snapshot(body, path_tokens.get(1))
+            }
             (Method::Put, "drives", Some(body)) => parse_put_drive(body, path_tokens.get(1)),
+            (Method::Put, "entropy", Some(body)) => parse_put_entropy(body),
+            (Method::Put, "gpu", Some(body)) => parse_put_gpu(body),
+            (Method::Put, "guest-dmesg", Some(body)) => parse_put_guest_dmesg(body),
+            (Method::Put, "input", Some(body)) => parse_put_input(body),
+            #[cfg(target_arch = "x86_64")]
+            (Method::Put, "live-update", Some(body)) => parse_put_live_update(body),
             (Method::Put, "logger", Some(body)) => parse_put_logger(body),
             (Method::Put, "machine-config", Some(body)) => parse_put_machine_config(body),
+            (Method::Put, "memory-limits", Some(body)) => parse_put_memory_limits(body),
             (Method::Put, "metrics", Some(body)) => parse_put_metrics(body),
+            #[cfg(target_arch = "x86_64")]
+            (Method::Put, "migrate", Some(body)) => parse_put_migrate(body),
             (Method::Put, "mmds", Some(body)) => parse_put_mmds(body, path_tokens.get(1)),
+            (Method::Put, "network-interfaces", Some(body))
+                if path_tokens.get(2) == Some(&"link") =>
+            {
+                parse_put_net_link_state(body, path_tokens.get(1))
+            }
             (Method::Put, "network-interfaces", Some(body)) => {
                 parse_put_net(body, path_tokens.get(1))
             }
+            (Method::Put, "pci-passthrough", Some(body)) => {
+                parse_put_pci_passthrough(body, path_tokens.get(1))
+            }
+            (Method::Put, "probes", Some(body)) => parse_put_probe(body, path_tokens.get(1)),
+            (Method::Put, "rate-limit-policy", Some(body)) => parse_put_rate_limit_policy(body),
+            (Method::Put, "rtc", Some(body)) => parse_put_rtc(body),
+            #[cfg(feature = "sev")]
+            (Method::Put, "sev", Some(body)) => parse_put_sev(body),
+            (Method::Put, "shared-memory", Some(body)) => {
+                parse_put_shared_memory(body, path_tokens.get(1))
+            }
             (Method::Put, "snapshot", Some(body)) => parse_put_snapshot(body, path_tokens.get(1)),
-            (Method::Put, "vsock", Some(body)) => parse_put_vsock(body),
+            (Method::Put, "sound", Some(body)) => parse_put_sound(body),
+            (Method::Put, "validate", Some(body)) => parse_put_validate(body),
+            (Method::Put, "vcpus", Some(body)) if path_tokens.get(2) == Some(&"state") => {
+                parse_put_vcpu_state(body, path_tokens.get(1))
+            }
+            (Method::Put, "virtio-features", Some(body)) => parse_put_virtio_features(body),
+            (Method::Put, "vsock", Some(body)) => parse_put_vsock(body, path_tokens.get(1)),
             (Method::Put, _, None) => method_to_error(Method::Put),
+            (Method::Patch, "balloon", Some(body)) => parse_patch_balloon(body),
             (Method::Patch, "drives", Some(body)) => parse_patch_drive(body, path_tokens.get(1)),
             (Method::Patch, "machine-config", Some(body)) => parse_patch_machine_config(body),
             (Method::Patch, "mmds", Some(body)) => parse_patch_mmds(body),
@@ -72,9 +157,24 @@ impl ParsedRequest {
             }
             (Method::Patch, "vm", Some(body)) => parse_patch_vm_state(body),
             (Method::Patch, _, None) => method_to_error(Method::Patch),
+            (Method::Delete, "drives", None) => parse_delete_drive(path_tokens.get(1)),
+            (Method::Delete, "network-interfaces", None) => parse_delete_net(path_tokens.get(1)),
+            (Method::Delete, _, Some(_)) => method_to_error(Method::Delete),
             (method, unknown_uri, _) => {
                 Err(Error::InvalidPathMethod(unknown_uri.to_string(), method))
             }
+        }?;
+
+        // Retries of the requests changing the microVM are applied once per idempotency key.
+        let idempotency_key = match request.method() {
+            Method::Get => None,
+            _ => request.headers.idempotency_key(),
+        };
+        match (parsed_request, idempotency_key) {
+            (ParsedRequest::Sync(vmm_action), Some(key)) => Ok(ParsedRequest::Sync(
+                VmmAction::Idempotent(key.to_string(), Box::new(vmm_action)),
+            )),
+            (parsed_request, _) => Ok(parsed_request),
         }
     }
 
@@ -82,32 +182,32 @@ impl ParsedRequest {
         request_outcome: std::result::Result<VmmData, VmmActionError>,
     ) -> Response {
         match request_outcome {
-            Ok(vmm_data) => match vmm_data {
-                VmmData::Empty => {
-                    info!("The request was executed successfully. Status code: 204 No Content.");
-                    Response::new(Version::Http11, StatusCode::NoContent)
-                }
-                VmmData::MachineConfiguration(vm_config) => {
-                    info!("The request was executed successfully. Status code: 200 OK.");
-                    let mut response = Response::new(Version::Http11, StatusCode::OK);
-                    response.set_body(Body::new(vm_config.to_string()));
-                    response
-                }
-                VmmData::NotFound => {
-                    info!("The request was executed successfully, but there is not an implementation \
-                     for it at this moment. Status code: 501 Not Implemented.");
-                    Response::new(Version::Http11, StatusCode::NotImplemented)
+            Ok(VmmData::Empty) => {
+                info!("The request was executed successfully. Status code: 204 No Content.");
+                Response::new(Version::Http11, StatusCode::NoContent)
+            }
+            Ok(VmmData::NotFound) => {
+                info!(
+                    "The request was executed successfully, but there is not an implementation \
+                 for it at this moment. Status code: 501 Not Implemented."
+                );
+                Response::new(Version::Http11, StatusCode::NotImplemented)
+            }
+            Ok(vmm_data) => {
+                info!("The request was executed successfully. Status code: 200 OK.");
+                let mut response = Response::new(Version::Http11, StatusCode::OK);
+                if let Some(payload) = vmm_data.payload_json() {
+                    response.set_body(Body::new(payload));
                 }
-            },
+                response
+            }
             Err(vmm_action_error) => {
                 error!(
                     "Received Error. Status code: 400 Bad Request. Message: {}",
                     vmm_action_error
                 );
                 let mut response = Response::new(Version::Http11, StatusCode::BadRequest);
-                response.set_body(Body::new(ApiServer::json_fault_message(
-                    vmm_action_error.to_string(),
-                )));
+                response.set_body(Body::new(ApiServer::json_action_fault(&vmm_action_error)));
                 response
             }
         }
@@ -158,6 +258,10 @@ pub fn method_to_error(method: Method) -> Result<ParsedRequest, Error> {
             StatusCode::BadRequest,
             "Empty PATCH request.".to_string(),
         )),
+        Method::Delete => Err(Error::Generic(
+            StatusCode::BadRequest,
+            "DELETE request cannot have a body.".to_string(),
+        )),
     }
 }
 
@@ -238,6 +342,15 @@ mod tests {
 
     use micro_http::HttpConnection;
     use vmm::builder::StartMicrovmError;
+    use vmm::device_list::{
+        DeviceBackend, DeviceDescription, DeviceKind, DeviceLifecycleState,
+        VsockConnectionDescription, VsockConnectionState,
+    };
+    use vmm::events::VmmEvent;
+    use vmm::guest_dmesg::LogRecord;
+    use vmm::host_capabilities::HostCapabilities;
+    use vmm::resource_usage::{FdUsage, VmmResourceUsage};
+    use vmm::resources::{ConfigIssue, ConfigIssueKind, ConfigIssues, VmResources, VmmConfig};
     use vmm::rpc_interface::VmmActionError;
     use vmm::vmm_config::machine_config::VmConfig;
 
@@ -341,6 +454,29 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_invalid_delete() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"DELETE /drives/rootfs HTTP/1.1\r\n\
+                Content-Type: text/plain\r\n\
+                Content-Length: 4\r\n\r\nbody",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        match ParsedRequest::try_from_request(&req) {
+            Err(Error::Generic(StatusCode::BadRequest, err_msg)) => {
+                if err_msg != "DELETE request cannot have a body." {
+                    panic!("DELETE request with body.");
+                }
+            }
+            _ => panic!("DELETE request with body."),
+        };
+    }
+
     #[test]
     fn test_error_into_response() {
         // Generic error.
@@ -464,19 +600,174 @@ mod tests {
         assert_eq!(&buf[..], expected_response.as_bytes());
 
         // With Vmm data.
-        let mut buf: [u8; 241] = [0; 241];
+        let body = VmConfig::default().to_string();
+        let expected_response = format!(
+            "HTTP/1.1 200 \r\n\
+             Server: Firecracker API\r\n\
+             Connection: keep-alive\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let mut buf = vec![0u8; expected_response.len()];
         let response = ParsedRequest::convert_to_response(Ok(VmmData::MachineConfiguration(
             VmConfig::default(),
         )));
+        assert!(response.write_all(&mut buf.as_mut_slice()).is_ok());
+        assert_eq!(&buf[..], expected_response.as_bytes());
+
+        // With events.
+        let events = vec![VmmEvent::FreePagesReported { amount_kib: 4 }];
+        let body = serde_json::to_string(&events).unwrap();
+        let mut buf: [u8; 165] = [0; 165];
+        let response = ParsedRequest::convert_to_response(Ok(VmmData::Events(events)));
         assert!(response.write_all(&mut buf.as_mut()).is_ok());
         let expected_response = format!(
             "HTTP/1.1 200 \r\n\
              Server: Firecracker API\r\n\
              Connection: keep-alive\r\n\
              Content-Type: application/json\r\n\
-             Content-Length: 122\r\n\r\n{}",
-            VmConfig::default().to_string()
+             Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        assert_eq!(&buf[..], expected_response.as_bytes());
+
+        // With devices.
+        let devices = vec![DeviceDescription {
+            device_type: DeviceKind::Vsock,
+            id: String::from("vsock"),
+            mmio_addr: 0xd000_0000,
+            mmio_len: 0x1000,
+            irq: 5,
+            activated: true,
+            state: DeviceLifecycleState::Activated,
+            backend: Some(DeviceBackend::Vhost),
+            cmdline: None,
+            guest_name: None,
+        }];
+        let body = serde_json::to_string(&devices).unwrap();
+        let expected_response = format!(
+            "HTTP/1.1 200 \r\n\
+             Server: Firecracker API\r\n\
+             Connection: keep-alive\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let mut buf = vec![0u8; expected_response.len()];
+        let response = ParsedRequest::convert_to_response(Ok(VmmData::DeviceList(devices)));
+        assert!(response.write_all(&mut buf.as_mut_slice()).is_ok());
+        assert_eq!(&buf[..], expected_response.as_bytes());
+
+        // With vsock connections.
+        let connections = vec![VsockConnectionDescription {
+            vsock_id: String::from("vsock"),
+            host_port: 52,
+            guest_port: 1024,
+            state: VsockConnectionState::Established,
+            bytes_to_guest: 16,
+            bytes_to_host: 32,
+            pending_bytes_to_host: 0,
+        }];
+        let body = serde_json::to_string(&connections).unwrap();
+        let expected_response = format!(
+            "HTTP/1.1 200 \r\n\
+             Server: Firecracker API\r\n\
+             Connection: keep-alive\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let mut buf = vec![0u8; expected_response.len()];
+        let response =
+            ParsedRequest::convert_to_response(Ok(VmmData::VsockConnections(connections)));
+        assert!(response.write_all(&mut buf.as_mut_slice()).is_ok());
+        assert_eq!(&buf[..], expected_response.as_bytes());
+
+        // With the resource usage.
+        let usage = VmmResourceUsage {
+            rss_kib: 1024,
+            guest_rss_kib: 512,
+            overhead_rss_kib: 512,
+            threads: 4,
+            fds: FdUsage::default(),
+        };
+        let body = serde_json::to_string(&usage).unwrap();
+        let expected_response = format!(
+            "HTTP/1.1 200 \r\n\
+             Server: Firecracker API\r\n\
+             Connection: keep-alive\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let mut buf = vec![0u8; expected_response.len()];
+        let response = ParsedRequest::convert_to_response(Ok(VmmData::ResourceUsage(usage)));
+        assert!(response.write_all(&mut buf.as_mut_slice()).is_ok());
+        assert_eq!(&buf[..], expected_response.as_bytes());
+
+        // With the whole configuration.
+        let config = VmmConfig::from(&VmResources::default());
+        let body = serde_json::to_string(&config).unwrap();
+        let expected_response = format!(
+            "HTTP/1.1 200 \r\n\
+             Server: Firecracker API\r\n\
+             Connection: keep-alive\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let mut buf = vec![0u8; expected_response.len()];
+        let response =
+            ParsedRequest::convert_to_response(Ok(VmmData::FullConfiguration(Box::new(config))));
+        assert!(response.write_all(&mut buf.as_mut_slice()).is_ok());
+        assert_eq!(&buf[..], expected_response.as_bytes());
+
+        // With the kernel log of the guest.
+        let records = vec![LogRecord {
+            timestamp_ns: 1_000,
+            text: "Kernel panic - not syncing".to_string(),
+        }];
+        let body = serde_json::to_string(&records).unwrap();
+        let expected_response = format!(
+            "HTTP/1.1 200 \r\n\
+             Server: Firecracker API\r\n\
+             Connection: keep-alive\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let mut buf = vec![0u8; expected_response.len()];
+        let response = ParsedRequest::convert_to_response(Ok(VmmData::GuestDmesg(records)));
+        assert!(response.write_all(&mut buf.as_mut_slice()).is_ok());
+        assert_eq!(&buf[..], expected_response.as_bytes());
+
+        // With the host capabilities.
+        let capabilities = HostCapabilities {
+            tap: true,
+            ..Default::default()
+        };
+        let body = serde_json::to_string(&capabilities).unwrap();
+        let expected_response = format!(
+            "HTTP/1.1 200 \r\n\
+             Server: Firecracker API\r\n\
+             Connection: keep-alive\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
         );
+        let mut buf = vec![0u8; expected_response.len()];
+        let response =
+            ParsedRequest::convert_to_response(Ok(VmmData::HostCapabilities(capabilities)));
+        assert!(response.write_all(&mut buf.as_mut_slice()).is_ok());
         assert_eq!(&buf[..], expected_response.as_bytes());
 
         // Vmm data not found.
@@ -491,10 +782,14 @@ mod tests {
 
         // Error.
         let error = VmmActionError::StartMicrovm(StartMicrovmError::MissingKernelConfig);
-        let mut buf: [u8; 193] = [0; 193];
-        let json = ApiServer::json_fault_message(error.to_string());
+        let json = ApiServer::json_action_fault(&error);
+        let fault: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(fault["fault_message"], error.to_string().as_str());
+        assert_eq!(fault["error_code"], 1300);
+        assert_eq!(fault["error_category"], "invalid_argument");
+        assert_eq!(fault["retriable"], false);
+        assert!(fault.get("config_issues").is_none());
         let response = ParsedRequest::convert_to_response(Err(error));
-        response.write_all(&mut buf.as_mut()).unwrap();
 
         let expected_response = format!(
             "HTTP/1.1 400 \r\n\
@@ -505,7 +800,23 @@ mod tests {
             json.len(),
             json,
         );
+        let mut buf = vec![0; expected_response.len()];
+        response.write_all(&mut buf.as_mut_slice()).unwrap();
         assert_eq!(&buf[..], expected_response.as_bytes());
+
+        // Inconsistent configuration.
+        let issues = ConfigIssues(vec![ConfigIssue {
+            kind: ConfigIssueKind::ReservedVsockCid,
+            resource: "/vsock/vsock0".to_string(),
+            message: "The guest CID 2 is reserved. The guest CIDs start at 3.".to_string(),
+        }]);
+        let error = VmmActionError::StartMicrovm(StartMicrovmError::InconsistentConfig(issues));
+        let json = ApiServer::json_action_fault(&error);
+        let fault: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(fault["error_code"], 1107);
+        assert_eq!(fault["error_category"], "invalid_argument");
+        assert_eq!(fault["config_issues"][0]["kind"], "reserved_vsock_cid");
+        assert_eq!(fault["config_issues"][0]["resource"], "/vsock/vsock0");
     }
 
     #[test]
@@ -519,11 +830,16 @@ mod tests {
     }
 
     #[test]
-    fn test_try_from_get_machine_config() {
+    fn test_try_from_get_devices() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
         let mut connection = HttpConnection::new(receiver);
+        sender.write_all(b"GET /devices HTTP/1.1\r\n\r\n").unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
         sender
-            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .write_all(b"GET /devices/net0/state HTTP/1.1\r\n\r\n")
             .unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
@@ -531,27 +847,23 @@ mod tests {
     }
 
     #[test]
-    fn test_try_from_get_mmds() {
+    fn test_try_from_get_host_capabilities() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
         let mut connection = HttpConnection::new(receiver);
-        sender.write_all(b"GET /mmds HTTP/1.1\r\n\r\n").unwrap();
+        sender
+            .write_all(b"GET /host-capabilities HTTP/1.1\r\n\r\n")
+            .unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
     #[test]
-    fn test_try_from_put_actions() {
+    fn test_try_from_get_guest_dmesg() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
         let mut connection = HttpConnection::new(receiver);
         sender
-            .write_all(
-                b"PUT /actions HTTP/1.1\r\n\
-                Content-Type: application/json\r\n\
-                Content-Length: 33\r\n\r\n{ \
-                \"action_type\": \"FlushMetrics\" \
-                }",
-            )
+            .write_all(b"GET /guest-dmesg HTTP/1.1\r\n\r\n")
             .unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
@@ -559,18 +871,11 @@ mod tests {
     }
 
     #[test]
-    fn test_try_from_put_boot() {
+    fn test_try_from_get_rate_limiters() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
         let mut connection = HttpConnection::new(receiver);
         sender
-            .write_all(
-                b"PUT /boot-source HTTP/1.1\r\n\
-                Content-Type: application/json\r\n\
-                Content-Length: 56\r\n\r\n{ \
-                \"kernel_image_path\": \"string\", \
-                \"boot_args\": \"string\" \
-                }",
-            )
+            .write_all(b"GET /rate-limiters HTTP/1.1\r\n\r\n")
             .unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
@@ -578,33 +883,11 @@ mod tests {
     }
 
     #[test]
-    fn test_try_from_put_drives() {
+    fn test_try_from_get_resource_usage() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
         let mut connection = HttpConnection::new(receiver);
         sender
-            .write_all(
-                b"PUT /drives/string HTTP/1.1\r\n\
-                Content-Type: application/json\r\n\
-                Content-Length: 266\r\n\r\n{ \
-                \"drive_id\": \"string\", \
-                \"path_on_host\": \"string\", \
-                \"is_root_device\": true, \
-                \"partuuid\": \"string\", \
-                \"is_read_only\": true, \
-                \"rate_limiter\": { \
-                    \"bandwidth\": { \
-                        \"size\": 0, \
-                        \"one_time_burst\": 0, \
-                        \"refill_time\": 0 \
-                    }, \
-                    \"ops\": { \
-                        \"size\": 0, \
-                        \"one_time_burst\": 0, \
-                        \"refill_time\": 0 \
-                    } \
-                } \
-            }",
-            )
+            .write_all(b"GET /resource-usage HTTP/1.1\r\n\r\n")
             .unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
@@ -612,84 +895,67 @@ mod tests {
     }
 
     #[test]
-    fn test_try_from_put_logger() {
+    fn test_try_from_get_vm_config() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
         let mut connection = HttpConnection::new(receiver);
-
-        let req_as_bytes = b"PUT /logger HTTP/1.1\r\n\
-                Content-Type: application/json\r\n\
-                Content-Length: 91\r\n\r\n{ \
-                \"log_path\": \"string\", \
-                \"level\": \"Warning\", \
-                \"show_level\": false, \
-                \"show_log_origin\": false \
-            }";
-
-        sender.write_all(req_as_bytes).unwrap();
+        sender
+            .write_all(b"GET /vm/config HTTP/1.1\r\n\r\n")
+            .unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        sender.write_all(b"GET /vm HTTP/1.1\r\n\r\n").unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_err());
     }
 
     #[test]
-    fn test_try_from_put_machine_config() {
+    fn test_try_from_get_vsock_connections() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
         let mut connection = HttpConnection::new(receiver);
         sender
-            .write_all(
-                b"PUT /machine-config HTTP/1.1\r\n\
-                Content-Type: application/json\r\n\
-                Content-Length: 80\r\n\r\n{ \
-                \"vcpu_count\": 0, \
-                \"mem_size_mib\": 0, \
-                \"ht_enabled\": true, \
-                \"cpu_template\": \"C3\" \
-            }",
-            )
+            .write_all(b"GET /vsock/connections HTTP/1.1\r\n\r\n")
             .unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        sender.write_all(b"GET /vsock HTTP/1.1\r\n\r\n").unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_err());
     }
 
     #[test]
-    fn test_try_from_put_metrics() {
+    fn test_try_from_get_events() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
         let mut connection = HttpConnection::new(receiver);
-
-        let req_as_bytes = b"PUT /metrics HTTP/1.1\r\n\
-                Content-Type: application/json\r\n\
-                Content-Length: 28\r\n\r\n{ \
-                \"metrics_path\": \"string\" \
-            }";
-
-        sender.write_all(req_as_bytes).unwrap();
+        sender.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
     #[test]
-    fn test_try_from_put_mmds() {
+    fn test_try_from_get_operation() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
         let mut connection = HttpConnection::new(receiver);
         sender
-            .write_all(
-                b"PUT /mmds HTTP/1.1\r\n\
-                Content-Type: application/json\r\n\
-                Content-Length: 2\r\n\r\n{}",
-            )
+            .write_all(b"GET /operations/1 HTTP/1.1\r\n\r\n")
             .unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
 
+    #[test]
+    fn test_try_from_get_machine_config() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
         sender
-            .write_all(
-                b"PUT /mmds/config HTTP/1.1\r\n\
-                Content-Type: application/json\r\n\
-                Content-Length: 32\r\n\r\n{\"ipv4_address\":\"169.254.170.2\"}",
-            )
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
             .unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
@@ -697,58 +963,632 @@ mod tests {
     }
 
     #[test]
-    fn test_try_from_put_netif() {
+    fn test_try_from_get_mmds() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
         let mut connection = HttpConnection::new(receiver);
-        sender
-            .write_all(
-                b"PUT /network-interfaces/string HTTP/1.1\r\n\
-                Content-Type: application/json\r\n\
-                Content-Length: 416\r\n\r\n{ \
-                \"iface_id\": \"string\", \
-                \"guest_mac\": \"12:34:56:78:9a:BC\", \
-                \"host_dev_name\": \"string\", \
-                \"allow_mmds_requests\": true, \
-                \"rx_rate_limiter\": { \
-                    \"bandwidth\": { \
-                        \"size\": 0, \
-                        \"one_time_burst\": 0, \
-                        \"refill_time\": 0 \
-                    }, \
-                    \"ops\": { \
-                        \"size\": 0, \
-                        \"one_time_burst\": 0, \
-                        \"refill_time\": 0 \
-                    } \
-                }, \
-                \"tx_rate_limiter\": { \
-                    \"bandwidth\": { \
-                        \"size\": 0, \
-                        \"one_time_burst\": 0, \
-                        \"refill_time\": 0 \
-                    }, \
-                    \"ops\": { \
-                        \"size\": 0, \
-                        \"one_time_burst\": 0, \
-                        \"refill_time\": 0 \
-                    } \
-                } \
-            }",
-            )
-            .unwrap();
+        sender.write_all(b"GET /mmds HTTP/1.1\r\n\r\n").unwrap();
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
     #[test]
-    fn test_try_from_put_snapshot() {
+    fn test_try_from_put_actions() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
         let mut connection = HttpConnection::new(receiver);
-
         sender
             .write_all(
-                b"PUT /snapshot/create HTTP/1.1\r\n\
+                b"PUT /actions HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 33\r\n\r\n{ \
+                \"action_type\": \"FlushMetrics\" \
+                }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_idempotent_request() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /actions HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Idempotency-Key: flush-1\r\n\
+                Content-Length: 33\r\n\r\n{ \
+                \"action_type\": \"FlushMetrics\" \
+                }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(
+            ParsedRequest::try_from_request(&req).unwrap()
+                == ParsedRequest::Sync(VmmAction::Idempotent(
+                    "flush-1".to_string(),
+                    Box::new(VmmAction::FlushMetrics)
+                ))
+        );
+
+        // Reads aren't wrapped.
+        sender
+            .write_all(b"GET /machine-config HTTP/1.1\r\nIdempotency-Key: get-1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(
+            ParsedRequest::try_from_request(&req).unwrap()
+                == ParsedRequest::Sync(VmmAction::GetVmConfiguration)
+        );
+    }
+
+    #[test]
+    fn test_try_from_put_balloon() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /balloon HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 43\r\n\r\n{ \
+                \"amount_mib\": 0, \
+                \"deflate_on_oom\": true \
+                }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_entropy() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /entropy HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 2\r\n\r\n{}",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_patch_balloon() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PATCH /balloon HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 19\r\n\r\n{ \
+                \"amount_mib\": 1 \
+                }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_boot() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /boot-source HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 56\r\n\r\n{ \
+                \"kernel_image_path\": \"string\", \
+                \"boot_args\": \"string\" \
+                }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_drives() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /drives/string HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 266\r\n\r\n{ \
+                \"drive_id\": \"string\", \
+                \"path_on_host\": \"string\", \
+                \"is_root_device\": true, \
+                \"partuuid\": \"string\", \
+                \"is_read_only\": true, \
+                \"rate_limiter\": { \
+                    \"bandwidth\": { \
+                        \"size\": 0, \
+                        \"one_time_burst\": 0, \
+                        \"refill_time\": 0 \
+                    }, \
+                    \"ops\": { \
+                        \"size\": 0, \
+                        \"one_time_burst\": 0, \
+                        \"refill_time\": 0 \
+                    } \
+                } \
+            }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_gpu() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /gpu HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 34\r\n\r\n\
+                { \"socket_path\": \"/tmp/gpu.sock\" }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_sound() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /sound HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 47\r\n\r\n\
+                { \"backend\": \"file\", \"path\": \"/tmp/sound.pcm\" }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_validate() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /validate HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 16\r\n\r\n\
+                { \"drives\": [] }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        match ParsedRequest::try_from_request(&req) {
+            Ok(ParsedRequest::Sync(VmmAction::ValidateConfiguration(config_json))) => {
+                assert_eq!(config_json, "{ \"drives\": [] }")
+            }
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_try_from_put_rtc() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /rtc HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 26\r\n\r\n\
+                { \"base_time\": 946684800 }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_console() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /console HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 18\r\n\r\n\
+                { \"input\": \"api\" }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_input() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /input HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 2\r\n\r\n{}",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_logger() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+
+        let req_as_bytes = b"PUT /logger HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 91\r\n\r\n{ \
+                \"log_path\": \"string\", \
+                \"level\": \"Warning\", \
+                \"show_level\": false, \
+                \"show_log_origin\": false \
+            }";
+
+        sender.write_all(req_as_bytes).unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_machine_config() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /machine-config HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 80\r\n\r\n{ \
+                \"vcpu_count\": 0, \
+                \"mem_size_mib\": 0, \
+                \"ht_enabled\": true, \
+                \"cpu_template\": \"C3\" \
+            }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_metrics() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+
+        let req_as_bytes = b"PUT /metrics HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 28\r\n\r\n{ \
+                \"metrics_path\": \"string\" \
+            }";
+
+        sender.write_all(req_as_bytes).unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_mmds() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /mmds HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 2\r\n\r\n{}",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        sender
+            .write_all(
+                b"PUT /mmds/config HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 32\r\n\r\n{\"ipv4_address\":\"169.254.170.2\"}",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_netif() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /network-interfaces/string HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 416\r\n\r\n{ \
+                \"iface_id\": \"string\", \
+                \"guest_mac\": \"12:34:56:78:9a:BC\", \
+                \"host_dev_name\": \"string\", \
+                \"allow_mmds_requests\": true, \
+                \"rx_rate_limiter\": { \
+                    \"bandwidth\": { \
+                        \"size\": 0, \
+                        \"one_time_burst\": 0, \
+                        \"refill_time\": 0 \
+                    }, \
+                    \"ops\": { \
+                        \"size\": 0, \
+                        \"one_time_burst\": 0, \
+                        \"refill_time\": 0 \
+                    } \
+                }, \
+                \"tx_rate_limiter\": { \
+                    \"bandwidth\": { \
+                        \"size\": 0, \
+                        \"one_time_burst\": 0, \
+                        \"refill_time\": 0 \
+                    }, \
+                    \"ops\": { \
+                        \"size\": 0, \
+                        \"one_time_burst\": 0, \
+                        \"refill_time\": 0 \
+                    } \
+                } \
+            }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_drive_state() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /drives/string/state HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 40\r\n\r\n\
+                { \"drive_id\": \"string\", \"paused\": true }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        match ParsedRequest::try_from_request(&req) {
+            Ok(ParsedRequest::Sync(VmmAction::SetBlockDeviceState(drive_state))) => {
+                assert_eq!(drive_state.drive_id, "string");
+                assert!(drive_state.paused);
+            }
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_try_from_put_drive_snapshot() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /drives/string/snapshot HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 47\r\n\r\n\
+                { \"drive_id\": \"string\", \"dest_path\": \"backup\" }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        match ParsedRequest::try_from_request(&req) {
+            Ok(ParsedRequest::Sync(VmmAction::SnapshotBlockDevice(snapshot_params))) => {
+                assert_eq!(snapshot_params.drive_id, "string");
+                assert_eq!(snapshot_params.dest_path, "backup");
+            }
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_try_from_delete_drive() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(b"DELETE /drives/string HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        match ParsedRequest::try_from_request(&req) {
+            Ok(ParsedRequest::Sync(VmmAction::RemoveBlockDevice(drive_id))) => {
+                assert_eq!(drive_id, "string")
+            }
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_try_from_put_vcpu_state() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /vcpus/1/state HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 32\r\n\r\n\
+                { \"vcpu_id\": 1, \"paused\": true }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        match ParsedRequest::try_from_request(&req) {
+            Ok(ParsedRequest::Sync(VmmAction::SetVcpuState(vcpu_state))) => {
+                assert_eq!(vcpu_state.vcpu_id, 1);
+                assert!(vcpu_state.paused);
+            }
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_try_from_put_net_link_state() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /network-interfaces/string/link HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 37\r\n\r\n\
+                { \"iface_id\": \"string\", \"up\": false }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        match ParsedRequest::try_from_request(&req) {
+            Ok(ParsedRequest::Sync(VmmAction::SetNetworkLinkState(link_state))) => {
+                assert_eq!(link_state.iface_id, "string");
+                assert!(!link_state.up);
+            }
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_try_from_put_pci_passthrough() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /pci-passthrough/gpu HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 47\r\n\r\n\
+                { \"dev_id\": \"gpu\", \"host_bdf\": \"0000:01:00.0\" }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_shared_memory() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /shared-memory/shm HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 48\r\n\r\n\
+                { \"shm_id\": \"shm\", \"mem_path\": \"/dev/shm/data\" }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_probe() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /probes/ready HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 83\r\n\r\n\
+                { \"probe_id\": \"ready\", \"kind\": \"readiness\", \
+                \"check\": { \"type\": \"mmds_heartbeat\" } }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_rate_limit_policy() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /rate-limit-policy HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 92\r\n\r\n\
+                { \"signal\": { \"type\": \"psi\", \"resource\": \"io\" }, \
+                \"high_watermark\": 40, \"low_watermark\": 10 }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_cloud_init() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /cloud-init HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 28\r\n\r\n\
+                { \"user_data\": \"#!/bin/sh\" }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_virtio_features() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /virtio-features HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 27\r\n\r\n\
+                { \"net\": { \"deny\": 1024 } }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_snapshot() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+
+        sender
+            .write_all(
+                b"PUT /snapshot/create HTTP/1.1\r\n\
                 Content-Type: application/json\r\n\
                 Content-Length: 64\r\n\r\n{ \
                 \"snapshot_path\": \"foo\", \
@@ -778,6 +1618,42 @@ mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_try_from_put_live_update() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+
+        sender
+            .write_all(
+                b"PUT /live-update HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 24\r\n\r\n{ \"socket_path\": \"foo\" }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_try_from_put_migrate() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+
+        sender
+            .write_all(
+                b"PUT /migrate HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 24\r\n\r\n{ \"socket_path\": \"foo\" }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
     #[test]
     fn test_try_from_patch_vm() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -815,6 +1691,21 @@ mod tests {
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        sender
+            .write_all(
+                b"PUT /vsock/string HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 62\r\n\r\n{ \
+                \"vsock_id\": \"string\", \
+                \"guest_cid\": 0, \
+                \"uds_path\": \"string\" \
+            }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
     #[test]
@@ -890,4 +1781,21 @@ mod tests {
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
+
+    #[test]
+    fn test_try_from_delete_netif() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(b"DELETE /network-interfaces/string HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        match ParsedRequest::try_from_request(&req) {
+            Ok(ParsedRequest::Sync(VmmAction::RemoveNetworkInterface(iface_id))) => {
+                assert_eq!(iface_id, "string")
+            }
+            _ => panic!("Test failed."),
+        }
+    }
 }
@@ -3,18 +3,20 @@
 
 use super::super::VmmAction;
 use logger::{Metric, METRICS};
-#[cfg(target_arch = "aarch64")]
-use request::StatusCode;
-use request::{Body, Error, ParsedRequest};
+use request::{Body, Error, ParsedRequest, StatusCode};
+use vmm::vmm_config::input::InputEvent;
 
 // The names of the members from this enum must precisely correspond (as a string) to the possible
 // values of "action_type" from the json request body. This is useful to get a strongly typed
 // struct from the Serde deserialization process.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
 enum ActionType {
+    DrainVsockConnections,
     FlushMetrics,
     InstanceStart,
     SendCtrlAltDel,
+    SendInputEvent,
+    WriteToConsole,
 }
 
 // The model of the json body from a sync request. We use Serde to transform each associated
@@ -23,6 +25,10 @@ enum ActionType {
 #[serde(deny_unknown_fields)]
 struct ActionBody {
     action_type: ActionType,
+    // The events of a `SendInputEvent` action.
+    input_events: Option<Vec<InputEvent>>,
+    // The text of a `WriteToConsole` action.
+    console_input: Option<String>,
 }
 
 pub fn parse_put_actions(body: &Body) -> Result<ParsedRequest, Error> {
@@ -32,7 +38,26 @@ pub fn parse_put_actions(body: &Body) -> Result<ParsedRequest, Error> {
         Error::SerdeJson(e)
     })?;
 
+    if action_body.input_events.is_some() && action_body.action_type != ActionType::SendInputEvent {
+        METRICS.put_api_requests.actions_fails.inc();
+        return Err(Error::Generic(
+            StatusCode::BadRequest,
+            "Only the SendInputEvent action takes input events.".to_string(),
+        ));
+    }
+    if action_body.console_input.is_some() && action_body.action_type != ActionType::WriteToConsole
+    {
+        METRICS.put_api_requests.actions_fails.inc();
+        return Err(Error::Generic(
+            StatusCode::BadRequest,
+            "Only the WriteToConsole action takes console input.".to_string(),
+        ));
+    }
+
     match action_body.action_type {
+        ActionType::DrainVsockConnections => {
+            Ok(ParsedRequest::Sync(VmmAction::DrainVsockConnections))
+        }
         ActionType::FlushMetrics => Ok(ParsedRequest::Sync(VmmAction::FlushMetrics)),
         ActionType::InstanceStart => Ok(ParsedRequest::Sync(VmmAction::StartMicroVm)),
         ActionType::SendCtrlAltDel => {
@@ -46,6 +71,31 @@ pub fn parse_put_actions(body: &Body) -> Result<ParsedRequest, Error> {
             #[cfg(target_arch = "x86_64")]
             Ok(ParsedRequest::Sync(VmmAction::SendCtrlAltDel))
         }
+        ActionType::SendInputEvent => {
+            let input_events = action_body.input_events.unwrap_or_default();
+            if input_events.is_empty() {
+                METRICS.put_api_requests.actions_fails.inc();
+                return Err(Error::Generic(
+                    StatusCode::BadRequest,
+                    "The SendInputEvent action requires a non-empty list of input events."
+                        .to_string(),
+                ));
+            }
+            Ok(ParsedRequest::Sync(VmmAction::SendInputEvent(input_events)))
+        }
+        ActionType::WriteToConsole => {
+            let console_input = action_body.console_input.unwrap_or_default();
+            if console_input.is_empty() {
+                METRICS.put_api_requests.actions_fails.inc();
+                return Err(Error::Generic(
+                    StatusCode::BadRequest,
+                    "The WriteToConsole action requires a non-empty console input.".to_string(),
+                ));
+            }
+            Ok(ParsedRequest::Sync(VmmAction::WriteToConsole(
+                console_input.into_bytes(),
+            )))
+        }
     }
 }
 
@@ -90,6 +140,17 @@ mod tests {
             assert!(result.is_err());
         }
 
+        {
+            let json = r#"{
+                "action_type": "DrainVsockConnections"
+            }"#;
+
+            let req: ParsedRequest = ParsedRequest::Sync(VmmAction::DrainVsockConnections);
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.is_ok());
+            assert!(result.unwrap().eq(&req));
+        }
+
         {
             let json = r#"{
                 "action_type": "FlushMetrics"
@@ -100,5 +161,84 @@ mod tests {
             assert!(result.is_ok());
             assert!(result.unwrap().eq(&req));
         }
+
+        {
+            let json = r#"{
+                "action_type": "SendInputEvent",
+                "input_events": [
+                    { "type": 1, "code": 30, "value": 1 },
+                    { "type": 2, "code": 0, "value": -10 }
+                ]
+            }"#;
+
+            let req: ParsedRequest = ParsedRequest::Sync(VmmAction::SendInputEvent(vec![
+                InputEvent {
+                    event_type: 1,
+                    code: 30,
+                    value: 1,
+                },
+                InputEvent {
+                    event_type: 2,
+                    code: 0,
+                    value: -10,
+                },
+            ]));
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.is_ok());
+            assert!(result.unwrap().eq(&req));
+        }
+
+        {
+            // The events are mandatory for SendInputEvent, and only allowed for it.
+            let json = r#"{
+                "action_type": "SendInputEvent"
+            }"#;
+            assert!(parse_put_actions(&Body::new(json)).is_err());
+
+            let json = r#"{
+                "action_type": "SendInputEvent",
+                "input_events": []
+            }"#;
+            assert!(parse_put_actions(&Body::new(json)).is_err());
+
+            let json = r#"{
+                "action_type": "FlushMetrics",
+                "input_events": [{ "type": 1, "code": 30, "value": 1 }]
+            }"#;
+            assert!(parse_put_actions(&Body::new(json)).is_err());
+        }
+
+        {
+            let json = r#"{
+                "action_type": "WriteToConsole",
+                "console_input": "root\n\u0003"
+            }"#;
+
+            let req: ParsedRequest =
+                ParsedRequest::Sync(VmmAction::WriteToConsole(b"root\n\x03".to_vec()));
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.is_ok());
+            assert!(result.unwrap().eq(&req));
+        }
+
+        {
+            // The input is mandatory for WriteToConsole, and only allowed for it.
+            let json = r#"{
+                "action_type": "WriteToConsole"
+            }"#;
+            assert!(parse_put_actions(&Body::new(json)).is_err());
+
+            let json = r#"{
+                "action_type": "WriteToConsole",
+                "console_input": ""
+            }"#;
+            assert!(parse_put_actions(&Body::new(json)).is_err());
+
+            let json = r#"{
+                "action_type": "FlushMetrics",
+                "console_input": "root"
+            }"#;
+            assert!(parse_put_actions(&Body::new(json)).is_err());
+        }
     }
 }
@@ -0,0 +1,84 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Body, Error, ParsedRequest};
+use vmm::vmm_config::balloon::{BalloonDeviceConfig, BalloonUpdateConfig};
+
+pub fn parse_put_balloon(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.balloon_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::SetBalloonDevice(
+        serde_json::from_slice::<BalloonDeviceConfig>(body.raw()).map_err(|e| {
+            METRICS.put_api_requests.balloon_fails.inc();
+            Error::SerdeJson(e)
+        })?,
+    )))
+}
+
+pub fn parse_patch_balloon(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.patch_api_requests.balloon_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::UpdateBalloon(
+        serde_json::from_slice::<BalloonUpdateConfig>(body.raw()).map_err(|e| {
+            METRICS.patch_api_requests.balloon_fails.inc();
+            Error::SerdeJson(e)
+        })?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_put_balloon_request() {
+        let body = r#"{
+                "amount_mib": 1000,
+                "deflate_on_oom": true
+              }"#;
+        match parse_put_balloon(&Body::new(body)) {
+            Ok(ParsedRequest::Sync(VmmAction::SetBalloonDevice(config))) => {
+                assert_eq!(
+                    config,
+                    BalloonDeviceConfig {
+                        amount_mib: 1000,
+                        deflate_on_oom: true,
+                        free_page_reporting: false,
+                    }
+                )
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "amount_mib": 1000,
+                "deflate_on_oom": true,
+                "free_page_reporting": true
+              }"#;
+        assert!(parse_put_balloon(&Body::new(body)).is_ok());
+
+        let body = r#"{
+                "amount_mib": 1000
+              }"#;
+        assert!(parse_put_balloon(&Body::new(body)).is_err());
+    }
+
+    #[test]
+    fn test_parse_patch_balloon_request() {
+        let body = r#"{
+                "amount_mib": 1
+              }"#;
+        match parse_patch_balloon(&Body::new(body)) {
+            Ok(ParsedRequest::Sync(VmmAction::UpdateBalloon(config))) => {
+                assert_eq!(config, BalloonUpdateConfig { amount_mib: 1 })
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "amount_mib": 1,
+                "deflate_on_oom": true
+              }"#;
+        assert!(parse_patch_balloon(&Body::new(body)).is_err());
+    }
+}
@@ -19,6 +19,7 @@ pub fn parse_put_boot_source(body: &Body) -> Result<ParsedRequest, Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use vmm::vmm_config::boot_source::MmioDeviceEnumeration;
 
     #[test]
     fn test_parse_boot_request() {
@@ -32,12 +33,29 @@ mod tests {
         let same_body = BootSourceConfig {
             kernel_image_path: String::from("/foo/bar"),
             initrd_path: Some(String::from("/bar/foo")),
+            kernel_bytes: None,
             boot_args: Some(String::from("foobar")),
+            device_enumeration: MmioDeviceEnumeration::Cmdline,
         };
         let result = parse_put_boot_source(&Body::new(body));
         assert!(result.is_ok());
         let parsed_req = result.unwrap_or_else(|_e| panic!("Failed test."));
 
         assert!(parsed_req == ParsedRequest::Sync(VmmAction::ConfigureBootSource(same_body)));
+
+        let body = r#"{
+                "kernel_image_path": "/foo/bar",
+                "device_enumeration": "firmware"
+              }"#;
+        match parse_put_boot_source(&Body::new(body)) {
+            Ok(ParsedRequest::Sync(VmmAction::ConfigureBootSource(config))) => {
+                assert_eq!(config.device_enumeration, MmioDeviceEnumeration::Firmware)
+            }
+            _ => panic!("Failed test."),
+        }
+        assert!(parse_put_boot_source(&Body::new(
+            r#"{"kernel_image_path": "/foo/bar", "device_enumeration": "acpi"}"#
+        ))
+        .is_err());
     }
 }
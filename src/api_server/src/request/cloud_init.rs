@@ -0,0 +1,47 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Body, Error, ParsedRequest};
+use vmm::vmm_config::cloud_init::CloudInitConfig;
+
+pub fn parse_put_cloud_init(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.cloud_init_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::SetCloudInit(
+        serde_json::from_slice::<CloudInitConfig>(body.raw()).map_err(|e| {
+            METRICS.put_api_requests.cloud_init_fails.inc();
+            Error::SerdeJson(e)
+        })?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vmm::vmm_config::cloud_init::CloudInitTransport;
+
+    #[test]
+    fn test_parse_put_cloud_init_request() {
+        let body = r##"{
+                "user_data": "#cloud-config\nhostname: vm0\n",
+                "meta_data": "instance-id: vm0\n",
+                "transport": "seed"
+              }"##;
+        match parse_put_cloud_init(&Body::new(body)) {
+            Ok(ParsedRequest::Sync(VmmAction::SetCloudInit(config))) => {
+                assert_eq!(config.user_data, "#cloud-config\nhostname: vm0\n");
+                assert_eq!(config.meta_data(), "instance-id: vm0\n");
+                assert_eq!(config.transport, CloudInitTransport::Seed);
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "user_data": "",
+                "vendor_data": ""
+              }"#;
+        assert!(parse_put_cloud_init(&Body::new(body)).is_err());
+    }
+}
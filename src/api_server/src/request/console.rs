@@ -0,0 +1,43 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Body, Error, ParsedRequest};
+use vmm::vmm_config::console::ConsoleConfig;
+
+pub fn parse_put_console(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.console_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::SetConsoleConfiguration(
+        serde_json::from_slice::<ConsoleConfig>(body.raw()).map_err(|e| {
+            METRICS.put_api_requests.console_fails.inc();
+            Error::SerdeJson(e)
+        })?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vmm::vmm_config::console::ConsoleInput;
+
+    #[test]
+    fn test_parse_put_console_request() {
+        match parse_put_console(&Body::new(
+            r#"{ "input": "socket", "socket_path": "/tmp/console.sock" }"#,
+        )) {
+            Ok(ParsedRequest::Sync(VmmAction::SetConsoleConfiguration(config))) => assert_eq!(
+                config,
+                ConsoleConfig {
+                    input: ConsoleInput::Socket,
+                    socket_path: Some("/tmp/console.sock".to_string()),
+                }
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        assert!(parse_put_console(&Body::new(r#"{ "input": "tty" }"#)).is_err());
+        assert!(parse_put_console(&Body::new(r#"{ "output": "stdout" }"#)).is_err());
+    }
+}
@@ -0,0 +1,53 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{checked_id, Error, ParsedRequest};
+use Method;
+
+pub fn parse_get_devices(
+    id_from_path: Option<&&str>,
+    resource_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    match (id_from_path, resource_from_path) {
+        (None, _) => {
+            METRICS.get_api_requests.devices_count.inc();
+            Ok(ParsedRequest::Sync(VmmAction::ListDevices))
+        }
+        (Some(id), Some(&"state")) => {
+            METRICS.get_api_requests.device_state_count.inc();
+            Ok(ParsedRequest::Sync(VmmAction::DumpDeviceState(
+                checked_id(id)?.to_string(),
+            )))
+        }
+        (Some(id), Some(resource)) => Err(Error::InvalidPathMethod(
+            format!("/devices/{}/{}", id, resource),
+            Method::Get,
+        )),
+        (Some(id), None) => Err(Error::InvalidPathMethod(
+            format!("/devices/{}", id),
+            Method::Get,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_get_devices_request() {
+        match parse_get_devices(None, None) {
+            Ok(ParsedRequest::Sync(VmmAction::ListDevices)) => {}
+            _ => panic!("Test failed."),
+        }
+        match parse_get_devices(Some(&"net0"), Some(&"state")) {
+            Ok(ParsedRequest::Sync(VmmAction::DumpDeviceState(id))) => assert_eq!(id, "net0"),
+            _ => panic!("Test failed."),
+        }
+        assert!(parse_get_devices(Some(&"net0"), None).is_err());
+        assert!(parse_get_devices(Some(&"net0"), Some(&"queues")).is_err());
+        assert!(parse_get_devices(Some(&"net#0"), Some(&"state")).is_err());
+    }
+}
@@ -1,79 +1,12 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0<Paste>
 
-use serde_json::{Map, Value};
-
 use super::super::VmmAction;
 use logger::{Metric, METRICS};
 use request::{checked_id, Body, Error, ParsedRequest, StatusCode};
-use vmm::vmm_config::drive::BlockDeviceConfig;
-
-struct PatchDrivePayload {
-    // Leaving `fields` pub because ownership on it needs to be yielded to the
-    // Request enum object. A getter couldn't move `fields` out of the borrowed
-    // PatchDrivePayload object.
-    pub fields: Value,
-}
-
-impl PatchDrivePayload {
-    /// Checks that `field_key` exists and that the value has the type Value::String.
-    fn check_field_is_string(map: &Map<String, Value>, field_key: &str) -> Result<(), String> {
-        match map.get(field_key) {
-            None => {
-                return Err(format!(
-                    "Required key {} not present in the json.",
-                    field_key
-                ));
-            }
-            Some(id) => {
-                // Check that field is a string.
-                if id.as_str().is_none() {
-                    return Err(format!("Invalid type for key {}.", field_key));
-                }
-            }
-        }
-        Ok(())
-    }
-
-    /// Validates that only path_on_host and drive_id are present in the payload.
-    fn validate(&self) -> Result<(), Error> {
-        match self.fields.as_object() {
-            Some(fields_map) => {
-                // Check that field `drive_id` exists and its type is String.
-                PatchDrivePayload::check_field_is_string(fields_map, "drive_id")
-                    .map_err(|e| Error::Generic(StatusCode::BadRequest, e))?;
-                // Check that field `path_on_host` exists and its type is String.
-                PatchDrivePayload::check_field_is_string(fields_map, "path_on_host")
-                    .map_err(|e| Error::Generic(StatusCode::BadRequest, e))?;
-
-                // Check that there are no other fields in the object.
-                if fields_map.len() > 2 {
-                    return Err(Error::Generic(
-                        StatusCode::BadRequest,
-                        "Invalid PATCH payload. Only updates on path_on_host are allowed."
-                            .to_string(),
-                    ));
-                }
-                Ok(())
-            }
-            _ => Err(Error::Generic(
-                StatusCode::BadRequest,
-                "Invalid json.".to_string(),
-            )),
-        }
-    }
-
-    /// Returns the field specified by `field_key` as a string. This is unsafe if validate
-    /// is not called prior to calling this method.
-    fn get_string_field_unchecked(&self, field_key: &str) -> String {
-        self.fields
-            .get(field_key)
-            .unwrap()
-            .as_str()
-            .unwrap()
-            .to_string()
-    }
-}
+use vmm::vmm_config::drive::{
+    BlockDeviceConfig, BlockDeviceSnapshotParams, BlockDeviceStateConfig, BlockDeviceUpdateConfig,
+};
 
 pub fn parse_put_drive(body: &Body, id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
     METRICS.put_api_requests.drive_count.inc();
@@ -89,7 +22,7 @@ pub fn parse_put_drive(body: &Body, id_from_path: Option<&&str>) -> Result<Parse
         Error::SerdeJson(e)
     })?;
 
-    if id != device_cfg.drive_id {
+    if id != device_cfg.drive_id.as_str() {
         METRICS.put_api_requests.drive_fails.inc();
         Err(Error::Generic(
             StatusCode::BadRequest,
@@ -111,18 +44,13 @@ pub fn parse_patch_drive(body: &Body, id_from_path: Option<&&str>) -> Result<Par
         return Err(Error::EmptyID);
     };
 
-    let patch_drive_payload = PatchDrivePayload {
-        fields: serde_json::from_slice(body.raw()).map_err(|e| {
+    let block_device_update_cfg = serde_json::from_slice::<BlockDeviceUpdateConfig>(body.raw())
+        .map_err(|e| {
             METRICS.patch_api_requests.drive_fails.inc();
             Error::SerdeJson(e)
-        })?,
-    };
+        })?;
 
-    patch_drive_payload.validate()?;
-    let drive_id: String = patch_drive_payload.get_string_field_unchecked("drive_id");
-    let path_on_host: String = patch_drive_payload.get_string_field_unchecked("path_on_host");
-
-    if id != drive_id.as_str() {
+    if id != block_device_update_cfg.drive_id.as_str() {
         METRICS.patch_api_requests.drive_fails.inc();
         return Err(Error::Generic(
             StatusCode::BadRequest,
@@ -130,9 +58,94 @@ pub fn parse_patch_drive(body: &Body, id_from_path: Option<&&str>) -> Result<Par
         ));
     }
 
-    Ok(ParsedRequest::Sync(VmmAction::UpdateBlockDevicePath(
-        drive_id,
-        path_on_host,
+    if block_device_update_cfg.path_on_host.is_none()
+        && block_device_update_cfg.rate_limiter.is_none()
+    {
+        METRICS.patch_api_requests.drive_fails.inc();
+        return Err(Error::Generic(
+            StatusCode::BadRequest,
+            String::from(
+                "Invalid PATCH payload. Either path_on_host or rate_limiter must be updated.",
+            ),
+        ));
+    }
+
+    Ok(ParsedRequest::Sync(VmmAction::UpdateBlockDevice(
+        block_device_update_cfg,
+    )))
+}
+
+pub fn parse_put_drive_state(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.drive_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        METRICS.put_api_requests.drive_fails.inc();
+        return Err(Error::EmptyID);
+    };
+
+    let drive_state =
+        serde_json::from_slice::<BlockDeviceStateConfig>(body.raw()).map_err(|e| {
+            METRICS.put_api_requests.drive_fails.inc();
+            Error::SerdeJson(e)
+        })?;
+
+    if id != drive_state.drive_id.as_str() {
+        METRICS.put_api_requests.drive_fails.inc();
+        return Err(Error::Generic(
+            StatusCode::BadRequest,
+            "The id from the path does not match the id from the body!".to_string(),
+        ));
+    }
+    Ok(ParsedRequest::Sync(VmmAction::SetBlockDeviceState(
+        drive_state,
+    )))
+}
+
+pub fn parse_put_drive_snapshot(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.drive_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        METRICS.put_api_requests.drive_fails.inc();
+        return Err(Error::EmptyID);
+    };
+
+    let snapshot_params =
+        serde_json::from_slice::<BlockDeviceSnapshotParams>(body.raw()).map_err(|e| {
+            METRICS.put_api_requests.drive_fails.inc();
+            Error::SerdeJson(e)
+        })?;
+
+    if id != snapshot_params.drive_id.as_str() {
+        METRICS.put_api_requests.drive_fails.inc();
+        return Err(Error::Generic(
+            StatusCode::BadRequest,
+            "The id from the path does not match the id from the body!".to_string(),
+        ));
+    }
+    Ok(ParsedRequest::Sync(VmmAction::SnapshotBlockDevice(
+        snapshot_params,
+    )))
+}
+
+pub fn parse_delete_drive(id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
+    METRICS.delete_api_requests.drive_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        METRICS.delete_api_requests.drive_fails.inc();
+        return Err(Error::EmptyID);
+    };
+
+    Ok(ParsedRequest::Sync(VmmAction::RemoveBlockDevice(
+        id.to_string(),
     )))
 }
 
@@ -168,7 +181,7 @@ mod tests {
         let res = parse_patch_drive(&Body::new(body), Some(&"1000"));
         assert!(res.is_err());
 
-        // PATCH with missing path_on_host field.
+        // PATCH with nothing to update.
         let body = r#"{
                 "drive_id": "dummy_id"
               }"#;
@@ -182,7 +195,7 @@ mod tests {
         let res = parse_patch_drive(&Body::new(body), Some(&"1000"));
         assert!(res.is_err());
 
-        // PATCH that tries to update something else other than path_on_host.
+        // PATCH that tries to update something else other than path_on_host and rate_limiter.
         let body = r#"{
                 "drive_id": "dummy_id",
                 "path_on_host": "dummy_host",
@@ -202,14 +215,51 @@ mod tests {
                 "path_on_host": "dummy"
               }"#;
         match parse_patch_drive(&Body::new(body), Some(&"foo")) {
-            Ok(ParsedRequest::Sync(VmmAction::UpdateBlockDevicePath(a, b))) => {
-                assert_eq!(a, "foo".to_string());
-                assert_eq!(b, "dummy".to_string());
+            Ok(ParsedRequest::Sync(VmmAction::UpdateBlockDevice(cfg))) => {
+                assert_eq!(cfg.drive_id, "foo".to_string());
+                assert_eq!(cfg.path_on_host, Some("dummy".to_string()));
+                assert_eq!(cfg.rate_limiter, None);
             }
             Err(_e) => panic!("Test failed."),
             _ => panic!("Test failed: Invalid parameters"),
         };
 
+        // PATCH of the rate limiter alone.
+        let body = r#"{
+                "drive_id": "foo",
+                "rate_limiter": {
+                    "bandwidth": {
+                        "size": 1000,
+                        "one_time_burst": 100000,
+                        "refill_time": 100
+                    }
+                }
+              }"#;
+        match parse_patch_drive(&Body::new(body), Some(&"foo")) {
+            Ok(ParsedRequest::Sync(VmmAction::UpdateBlockDevice(cfg))) => {
+                assert_eq!(cfg.path_on_host, None);
+                let bandwidth = cfg.rate_limiter.unwrap().bandwidth.unwrap();
+                assert_eq!(bandwidth.one_time_burst, Some(100_000));
+            }
+            _ => panic!("Test failed."),
+        };
+
+        // PATCH of the backing file while the guest filesystems are frozen.
+        let body = r#"{
+                "drive_id": "foo",
+                "path_on_host": "dummy",
+                "guest_freeze": {"vsock_port": 52}
+              }"#;
+        match parse_patch_drive(&Body::new(body), Some(&"foo")) {
+            Ok(ParsedRequest::Sync(VmmAction::UpdateBlockDevice(cfg))) => {
+                let guest_freeze = cfg.guest_freeze.unwrap();
+                assert_eq!(guest_freeze.vsock_port, 52);
+                assert_eq!(guest_freeze.vsock_id, None);
+                assert_eq!(guest_freeze.timeout_ms, 5000);
+            }
+            _ => panic!("Test failed."),
+        };
+
         let body = r#"{
                 "drive_id": "foo",
                 "path_on_host": "dummy"
@@ -255,10 +305,62 @@ mod tests {
     }
 
     #[test]
-    fn test_validate() {
-        let pdp = PatchDrivePayload {
-            fields: Value::Null,
-        };
-        assert!(pdp.validate().is_err());
+    fn test_parse_put_drive_state_request() {
+        let body = r#"{"drive_id": "foo", "paused": true}"#;
+        assert!(parse_put_drive_state(&Body::new(body), Some(&"bar")).is_err());
+        assert!(parse_put_drive_state(&Body::new(body), None).is_err());
+
+        match parse_put_drive_state(&Body::new(body), Some(&"foo")) {
+            Ok(ParsedRequest::Sync(VmmAction::SetBlockDeviceState(drive_state))) => {
+                assert_eq!(
+                    drive_state,
+                    BlockDeviceStateConfig {
+                        drive_id: "foo".to_string(),
+                        paused: true,
+                    }
+                )
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{"drive_id": "foo", "paused": true, "path_on_host": "/dev/null"}"#;
+        assert!(parse_put_drive_state(&Body::new(body), Some(&"foo")).is_err());
+    }
+
+    #[test]
+    fn test_parse_put_drive_snapshot_request() {
+        let body = r#"{"drive_id": "foo", "dest_path": "/srv/backup/foo.ext4"}"#;
+        assert!(parse_put_drive_snapshot(&Body::new(body), Some(&"bar")).is_err());
+        assert!(parse_put_drive_snapshot(&Body::new(body), None).is_err());
+
+        match parse_put_drive_snapshot(&Body::new(body), Some(&"foo")) {
+            Ok(ParsedRequest::Sync(VmmAction::SnapshotBlockDevice(snapshot_params))) => {
+                assert_eq!(
+                    snapshot_params,
+                    BlockDeviceSnapshotParams {
+                        drive_id: "foo".to_string(),
+                        dest_path: "/srv/backup/foo.ext4".to_string(),
+                    }
+                )
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{"drive_id": "foo"}"#;
+        assert!(parse_put_drive_snapshot(&Body::new(body), Some(&"foo")).is_err());
+    }
+
+    #[test]
+    fn test_parse_delete_drive_request() {
+        assert!(parse_delete_drive(None).is_err());
+        assert!(parse_delete_drive(Some(&"")).is_err());
+        assert!(parse_delete_drive(Some(&"foo-bar")).is_err());
+
+        match parse_delete_drive(Some(&"foo")) {
+            Ok(ParsedRequest::Sync(VmmAction::RemoveBlockDevice(drive_id))) => {
+                assert_eq!(drive_id, "foo")
+            }
+            _ => panic!("Test failed."),
+        }
     }
 }
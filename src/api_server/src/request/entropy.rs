@@ -0,0 +1,34 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Body, Error, ParsedRequest};
+use vmm::vmm_config::entropy::EntropyDeviceConfig;
+
+pub fn parse_put_entropy(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.entropy_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::SetEntropyDevice(
+        serde_json::from_slice::<EntropyDeviceConfig>(body.raw()).map_err(|e| {
+            METRICS.put_api_requests.entropy_fails.inc();
+            Error::SerdeJson(e)
+        })?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_put_entropy_request() {
+        match parse_put_entropy(&Body::new("{}")) {
+            Ok(ParsedRequest::Sync(VmmAction::SetEntropyDevice(config))) => {
+                assert_eq!(config, EntropyDeviceConfig {})
+            }
+            _ => panic!("Test failed."),
+        }
+
+        assert!(parse_put_entropy(&Body::new(r#"{ "foo": 0 }"#)).is_err());
+    }
+}
@@ -0,0 +1,24 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Error, ParsedRequest};
+
+pub fn parse_get_events() -> Result<ParsedRequest, Error> {
+    METRICS.get_api_requests.events_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::GetEvents))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_get_events_request() {
+        match parse_get_events() {
+            Ok(ParsedRequest::Sync(VmmAction::GetEvents)) => {}
+            _ => panic!("Test failed."),
+        }
+    }
+}
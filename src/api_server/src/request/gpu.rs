@@ -0,0 +1,50 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Body, Error, ParsedRequest};
+use vmm::vmm_config::gpu::GpuDeviceConfig;
+
+pub fn parse_put_gpu(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.gpu_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::SetGpuDevice(
+        serde_json::from_slice::<GpuDeviceConfig>(body.raw()).map_err(|e| {
+            METRICS.put_api_requests.gpu_fails.inc();
+            Error::SerdeJson(e)
+        })?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_put_gpu_request() {
+        let body = r#"{
+                "socket_path": "/tmp/gpu.sock",
+                "width": 640,
+                "height": 480,
+                "blob": true
+              }"#;
+        match parse_put_gpu(&Body::new(body)) {
+            Ok(ParsedRequest::Sync(VmmAction::SetGpuDevice(config))) => assert_eq!(
+                config,
+                GpuDeviceConfig {
+                    socket_path: "/tmp/gpu.sock".to_string(),
+                    width: 640,
+                    height: 480,
+                    blob: true,
+                }
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        assert!(parse_put_gpu(&Body::new("{}")).is_err());
+        assert!(parse_put_gpu(&Body::new(
+            r#"{ "socket_path": "/tmp/gpu.sock", "foo": 0 }"#
+        ))
+        .is_err());
+    }
+}
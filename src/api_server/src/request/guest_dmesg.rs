@@ -0,0 +1,55 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Body, Error, ParsedRequest};
+use vmm::vmm_config::guest_dmesg::KernelLogSymbols;
+
+pub fn parse_get_guest_dmesg() -> Result<ParsedRequest, Error> {
+    METRICS.get_api_requests.guest_dmesg_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::GetGuestDmesg(None)))
+}
+
+pub fn parse_put_guest_dmesg(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.guest_dmesg_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::GetGuestDmesg(Some(
+        serde_json::from_slice::<KernelLogSymbols>(body.raw()).map_err(|e| {
+            METRICS.put_api_requests.guest_dmesg_fails.inc();
+            Error::SerdeJson(e)
+        })?,
+    ))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_get_guest_dmesg_request() {
+        match parse_get_guest_dmesg() {
+            Ok(ParsedRequest::Sync(VmmAction::GetGuestDmesg(None))) => {}
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_parse_put_guest_dmesg_request() {
+        let body = r#"{
+                "log_buf": "ffffffff82a3c5e8",
+                "log_buf_len": "ffffffff82a3c5e4",
+                "log_first_idx": "ffffffff8325a8d8",
+                "log_next_idx": "ffffffff8325a8e8"
+              }"#;
+        match parse_put_guest_dmesg(&Body::new(body)) {
+            Ok(ParsedRequest::Sync(VmmAction::GetGuestDmesg(Some(symbols)))) => {
+                assert_eq!(symbols.log_buf, "ffffffff82a3c5e8");
+                assert_eq!(symbols.log_next_idx, "ffffffff8325a8e8");
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{"log_buf": "ffffffff82a3c5e8"}"#;
+        assert!(parse_put_guest_dmesg(&Body::new(body)).is_err());
+    }
+}
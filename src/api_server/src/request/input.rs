@@ -0,0 +1,34 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Body, Error, ParsedRequest};
+use vmm::vmm_config::input::InputDeviceConfig;
+
+pub fn parse_put_input(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.input_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::SetInputDevice(
+        serde_json::from_slice::<InputDeviceConfig>(body.raw()).map_err(|e| {
+            METRICS.put_api_requests.input_fails.inc();
+            Error::SerdeJson(e)
+        })?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_put_input_request() {
+        match parse_put_input(&Body::new("{}")) {
+            Ok(ParsedRequest::Sync(VmmAction::SetInputDevice(config))) => {
+                assert_eq!(config, InputDeviceConfig {})
+            }
+            _ => panic!("Test failed."),
+        }
+
+        assert!(parse_put_input(&Body::new(r#"{ "foo": 0 }"#)).is_err());
+    }
+}
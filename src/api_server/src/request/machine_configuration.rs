@@ -40,7 +40,9 @@ pub fn parse_patch_machine_config(body: &Body) -> Result<ParsedRequest, Error> {
     if vm_config.vcpu_count.is_none()
         && vm_config.mem_size_mib.is_none()
         && vm_config.cpu_template.is_none()
+        && vm_config.cpu_features.is_none()
         && vm_config.ht_enabled.is_none()
+        && vm_config.tsc_khz.is_none()
     {
         return method_to_error(Method::Patch);
     }
@@ -53,7 +55,9 @@ pub fn parse_patch_machine_config(body: &Body) -> Result<ParsedRequest, Error> {
 mod tests {
     use super::*;
 
-    use vmm::vmm_config::machine_config::CpuFeaturesTemplate;
+    use vmm::vmm_config::machine_config::{
+        CpuFeaturesConfig, CpuFeaturesTemplate, LegacyDevicesConfig, VcpuRetryConfig,
+    };
 
     #[test]
     fn test_parse_get_machine_config_request() {
@@ -68,16 +72,54 @@ mod tests {
                 "vcpu_count": 8,
                 "mem_size_mib": 1024,
                 "ht_enabled": true,
+                "cores_per_socket": 2,
                 "cpu_template": "T2",
-                "track_dirty_pages": true
+                "cpu_features": ["avx2", "avx512f"],
+                "nested": true,
+                "tsc_khz": 2500000,
+                "track_dirty_pages": true,
+                "memfd_backed": true,
+                "mmio32_hole_size_mib": 256,
+                "mmio64_window_size_mib": 1024,
+                "split_irqchip": true,
+                "tickless": true,
+                "max_device_irq_rate": 5000,
+                "legacy_devices": {"serial": false, "i8042": false},
+                "vcpu_retry": {"max_retries": 5},
+                "strict_config": true
               }"#;
 
         let mut expected_config = VmConfig {
             vcpu_count: Some(8),
             mem_size_mib: Some(1024),
             ht_enabled: Some(true),
+            cores_per_socket: Some(2),
             cpu_template: Some(CpuFeaturesTemplate::T2),
+            cpu_features: Some(CpuFeaturesConfig::List(vec![
+                "avx2".to_string(),
+                "avx512f".to_string(),
+            ])),
+            nested: true,
+            tsc_khz: Some(2_500_000),
             track_dirty_pages: true,
+            memfd_backed: true,
+            mmio32_hole_size_mib: Some(256),
+            mmio64_window_size_mib: Some(1024),
+            gic_version: None,
+            gic_its: false,
+            split_irqchip: true,
+            tickless: true,
+            max_device_irq_rate: Some(5000),
+            legacy_devices: LegacyDevicesConfig {
+                serial: false,
+                i8042: false,
+                rtc: true,
+            },
+            vcpu_retry: VcpuRetryConfig {
+                max_retries: 5,
+                ..Default::default()
+            },
+            strict_config: true,
         };
         match parse_put_machine_config(&Body::new(body)) {
             Ok(ParsedRequest::Sync(VmmAction::SetVmConfiguration(config))) => {
@@ -95,8 +137,23 @@ mod tests {
             vcpu_count: Some(8),
             mem_size_mib: Some(1024),
             ht_enabled: Some(true),
+            cores_per_socket: None,
             cpu_template: None,
+            cpu_features: None,
+            nested: false,
+            tsc_khz: None,
             track_dirty_pages: false,
+            memfd_backed: false,
+            mmio32_hole_size_mib: None,
+            mmio64_window_size_mib: None,
+            gic_version: None,
+            gic_its: false,
+            split_irqchip: false,
+            tickless: false,
+            max_device_irq_rate: None,
+            legacy_devices: LegacyDevicesConfig::default(),
+            vcpu_retry: VcpuRetryConfig::default(),
+            strict_config: false,
         };
         match parse_put_machine_config(&Body::new(body)) {
             Ok(ParsedRequest::Sync(VmmAction::SetVmConfiguration(config))) => {
@@ -130,5 +187,9 @@ mod tests {
                 "ht_enabled": false
               }"#;
         assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+        let body = r#"{
+                "tsc_khz": 2500000
+              }"#;
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
     }
 }
@@ -0,0 +1,44 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Body, Error, ParsedRequest};
+use vmm::vmm_config::memory_limits::MemoryLimitsConfig;
+
+pub fn parse_put_memory_limits(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.memory_limits_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::SetMemoryLimits(
+        serde_json::from_slice::<MemoryLimitsConfig>(body.raw()).map_err(|e| {
+            METRICS.put_api_requests.memory_limits_fails.inc();
+            Error::SerdeJson(e)
+        })?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vmm::vmm_config::memory_limits::OomPolicy;
+
+    #[test]
+    fn test_parse_put_memory_limits_request() {
+        match parse_put_memory_limits(&Body::new(
+            r#"{ "address_space_mib": 8192, "oom_policy": "exit" }"#,
+        )) {
+            Ok(ParsedRequest::Sync(VmmAction::SetMemoryLimits(config))) => assert_eq!(
+                config,
+                MemoryLimitsConfig {
+                    address_space_mib: Some(8192),
+                    data_mib: None,
+                    oom_policy: OomPolicy::Exit,
+                }
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        assert!(parse_put_memory_limits(&Body::new(r#"{ "oom_policy": "kill" }"#)).is_err());
+        assert!(parse_put_memory_limits(&Body::new(r#"{ "foo": 0 }"#)).is_err());
+    }
+}
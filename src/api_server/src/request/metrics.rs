@@ -30,6 +30,7 @@ mod tests {
 
         let expected_cfg = MetricsConfig {
             metrics_path: PathBuf::from("metrics"),
+            exporters: vec![],
         };
         match parse_put_metrics(&Body::new(body)) {
             Ok(ParsedRequest::Sync(VmmAction::ConfigureMetrics(cfg))) => {
@@ -2,15 +2,41 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod actions;
+pub mod balloon;
 pub mod boot_source;
+pub mod cloud_init;
+pub mod console;
+pub mod devices;
 pub mod drive;
+pub mod entropy;
+pub mod events;
+pub mod gpu;
+pub mod guest_dmesg;
+pub mod host_capabilities;
+pub mod input;
 pub mod instance_info;
 pub mod logger;
 pub mod machine_configuration;
+pub mod memory_limits;
 pub mod metrics;
 pub mod mmds;
 pub mod net;
+pub mod operations;
+pub mod pci_passthrough;
+pub mod probe;
+pub mod rate_limit_policy;
+pub mod rate_limiters;
+pub mod resource_usage;
+pub mod rtc;
+#[cfg(feature = "sev")]
+pub mod sev;
+pub mod shared_memory;
 pub mod snapshot;
+pub mod sound;
+pub mod validate;
+pub mod vcpu;
+pub mod virtio_features;
+pub mod vm_config;
 pub mod vsock;
 pub use micro_http::{
     Body, HttpServer, Method, Request, RequestError, Response, StatusCode, Version,
@@ -4,7 +4,9 @@
 use super::super::VmmAction;
 use logger::{Metric, METRICS};
 use request::{checked_id, Body, Error, ParsedRequest, StatusCode};
-use vmm::vmm_config::net::{NetworkInterfaceConfig, NetworkInterfaceUpdateConfig};
+use vmm::vmm_config::net::{
+    NetworkInterfaceConfig, NetworkInterfaceUpdateConfig, NetworkLinkStateConfig,
+};
 
 pub fn parse_put_net(body: &Body, id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
     METRICS.put_api_requests.network_count.inc();
@@ -55,6 +57,48 @@ pub fn parse_patch_net(body: &Body, id_from_path: Option<&&str>) -> Result<Parse
     )))
 }
 
+pub fn parse_put_net_link_state(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.network_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        METRICS.put_api_requests.network_fails.inc();
+        return Err(Error::EmptyID);
+    };
+
+    let link_state = serde_json::from_slice::<NetworkLinkStateConfig>(body.raw()).map_err(|e| {
+        METRICS.put_api_requests.network_fails.inc();
+        Error::SerdeJson(e)
+    })?;
+    if id != link_state.iface_id {
+        METRICS.put_api_requests.network_fails.inc();
+        return Err(Error::Generic(
+            StatusCode::BadRequest,
+            "The id from the path does not match the id from the body!".to_string(),
+        ));
+    }
+    Ok(ParsedRequest::Sync(VmmAction::SetNetworkLinkState(
+        link_state,
+    )))
+}
+
+pub fn parse_delete_net(id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
+    METRICS.delete_api_requests.network_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        METRICS.delete_api_requests.network_fails.inc();
+        return Err(Error::EmptyID);
+    };
+
+    Ok(ParsedRequest::Sync(VmmAction::RemoveNetworkInterface(
+        id.to_string(),
+    )))
+}
+
 #[cfg(test)]
 mod tests {
     use serde_json;
@@ -67,6 +111,7 @@ mod tests {
                 "iface_id": "foo",
                 "host_dev_name": "bar",
                 "guest_mac": "12:34:56:78:9A:BC",
+                "mtu": 9000,
                 "allow_mmds_requests": false
               }"#;
         // 1. Exercise infamous "The id from the path does not match id from the body!".
@@ -111,6 +156,9 @@ mod tests {
                 "rx_rate_limiter": {
                 },
                 "tx_rate_limiter": {
+                },
+                "tx_impairment": {
+                    "delay_ms": 50
                 }
         }"#;
         // 1. Exercise infamous "The id from the path does not match id from the body!".
@@ -146,4 +194,41 @@ mod tests {
         }"#;
         assert!(parse_patch_net(&Body::new(body), Some(&"foo")).is_err());
     }
+
+    #[test]
+    fn test_parse_put_net_link_state_request() {
+        let body = r#"{"iface_id": "foo", "up": false}"#;
+        assert!(parse_put_net_link_state(&Body::new(body), Some(&"bar")).is_err());
+        assert!(parse_put_net_link_state(&Body::new(body), None).is_err());
+
+        match parse_put_net_link_state(&Body::new(body), Some(&"foo")) {
+            Ok(ParsedRequest::Sync(VmmAction::SetNetworkLinkState(link_state))) => {
+                assert_eq!(
+                    link_state,
+                    NetworkLinkStateConfig {
+                        iface_id: "foo".to_string(),
+                        up: false,
+                    }
+                )
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // The state of the link is mandatory.
+        let body = r#"{"iface_id": "foo"}"#;
+        assert!(parse_put_net_link_state(&Body::new(body), Some(&"foo")).is_err());
+    }
+
+    #[test]
+    fn test_parse_delete_net_request() {
+        assert!(parse_delete_net(None).is_err());
+        assert!(parse_delete_net(Some(&"foo-bar")).is_err());
+
+        match parse_delete_net(Some(&"foo")) {
+            Ok(ParsedRequest::Sync(VmmAction::RemoveNetworkInterface(iface_id))) => {
+                assert_eq!(iface_id, "foo")
+            }
+            _ => panic!("Test failed."),
+        }
+    }
 }
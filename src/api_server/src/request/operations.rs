@@ -0,0 +1,44 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Error, ParsedRequest, StatusCode};
+use Method;
+
+pub fn parse_get_operation(id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
+    match id_from_path {
+        Some(id) => {
+            METRICS.get_api_requests.operations_count.inc();
+            let operation_id = id.parse::<u64>().map_err(|_| {
+                Error::Generic(
+                    StatusCode::BadRequest,
+                    format!("Invalid operation ID: {}.", id),
+                )
+            })?;
+            Ok(ParsedRequest::Sync(VmmAction::GetOperationStatus(
+                operation_id,
+            )))
+        }
+        None => Err(Error::InvalidPathMethod(
+            "/operations".to_string(),
+            Method::Get,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_get_operation_request() {
+        match parse_get_operation(Some(&"3")) {
+            Ok(ParsedRequest::Sync(VmmAction::GetOperationStatus(id))) => assert_eq!(id, 3),
+            _ => panic!("Test failed."),
+        }
+        assert!(parse_get_operation(Some(&"-1")).is_err());
+        assert!(parse_get_operation(Some(&"snapshot")).is_err());
+        assert!(parse_get_operation(None).is_err());
+    }
+}
@@ -0,0 +1,72 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{checked_id, Body, Error, ParsedRequest, StatusCode};
+use vmm::vmm_config::pci_passthrough::PciPassthroughConfig;
+
+pub fn parse_put_pci_passthrough(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.pci_passthrough_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        METRICS.put_api_requests.pci_passthrough_fails.inc();
+        return Err(Error::EmptyID);
+    };
+
+    let config = serde_json::from_slice::<PciPassthroughConfig>(body.raw()).map_err(|e| {
+        METRICS.put_api_requests.pci_passthrough_fails.inc();
+        Error::SerdeJson(e)
+    })?;
+    if id != config.dev_id.as_str() {
+        METRICS.put_api_requests.pci_passthrough_fails.inc();
+        return Err(Error::Generic(
+            StatusCode::BadRequest,
+            "The id from the path does not match the id from the body!".to_string(),
+        ));
+    }
+    Ok(ParsedRequest::Sync(VmmAction::InsertPciPassthroughDevice(
+        config,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_put_pci_passthrough_request() {
+        let body = r#"{
+                "dev_id": "gpu",
+                "host_bdf": "0000:01:00.0"
+              }"#;
+        // The id from the path must match the id from the body.
+        assert!(parse_put_pci_passthrough(&Body::new(body), Some(&"nic")).is_err());
+        // The `id_from_path` cannot be None.
+        assert!(parse_put_pci_passthrough(&Body::new(body), None).is_err());
+
+        match parse_put_pci_passthrough(&Body::new(body), Some(&"gpu")) {
+            Ok(ParsedRequest::Sync(VmmAction::InsertPciPassthroughDevice(config))) => {
+                assert_eq!(
+                    config,
+                    PciPassthroughConfig {
+                        dev_id: "gpu".to_string(),
+                        host_bdf: "0000:01:00.0".to_string(),
+                    }
+                )
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "dev_id": "gpu",
+                "host_bdf": "0000:01:00.0",
+                "invalid_field": false
+              }"#;
+        assert!(parse_put_pci_passthrough(&Body::new(body), Some(&"gpu")).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{checked_id, Body, Error, ParsedRequest, StatusCode};
+use vmm::vmm_config::probe::ProbeConfig;
+
+pub fn parse_put_probe(body: &Body, id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.probe_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        METRICS.put_api_requests.probe_fails.inc();
+        return Err(Error::EmptyID);
+    };
+
+    let config = serde_json::from_slice::<ProbeConfig>(body.raw()).map_err(|e| {
+        METRICS.put_api_requests.probe_fails.inc();
+        Error::SerdeJson(e)
+    })?;
+    if id != config.probe_id.as_str() {
+        METRICS.put_api_requests.probe_fails.inc();
+        return Err(Error::Generic(
+            StatusCode::BadRequest,
+            "The id from the path does not match the id from the body!".to_string(),
+        ));
+    }
+    Ok(ParsedRequest::Sync(VmmAction::InsertProbe(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vmm::vmm_config::probe::{ProbeCheck, ProbeKind};
+
+    #[test]
+    fn test_parse_put_probe_request() {
+        let body = r#"{
+                "probe_id": "ready",
+                "kind": "readiness",
+                "check": {"type": "vsock_connect", "port": 52},
+                "period_ms": 500
+              }"#;
+        // The id from the path must match the id from the body.
+        assert!(parse_put_probe(&Body::new(body), Some(&"other")).is_err());
+        // The `id_from_path` cannot be None.
+        assert!(parse_put_probe(&Body::new(body), None).is_err());
+
+        match parse_put_probe(&Body::new(body), Some(&"ready")) {
+            Ok(ParsedRequest::Sync(VmmAction::InsertProbe(config))) => {
+                assert_eq!(config.kind, ProbeKind::Readiness);
+                assert_eq!(
+                    config.check,
+                    ProbeCheck::VsockConnect {
+                        port: 52,
+                        vsock_id: None,
+                    }
+                );
+                assert_eq!(config.period_ms, 500);
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "probe_id": "ready",
+                "kind": "readiness",
+                "check": {"type": "mmds_heartbeat"},
+                "invalid_field": false
+              }"#;
+        assert!(parse_put_probe(&Body::new(body), Some(&"ready")).is_err());
+    }
+}
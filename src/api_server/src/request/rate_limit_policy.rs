@@ -0,0 +1,56 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Body, Error, ParsedRequest};
+use vmm::vmm_config::rate_limit_policy::RateLimitPolicyConfig;
+
+pub fn parse_put_rate_limit_policy(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.rate_limit_policy_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::SetRateLimitPolicy(
+        serde_json::from_slice::<RateLimitPolicyConfig>(body.raw()).map_err(|e| {
+            METRICS.put_api_requests.rate_limit_policy_fails.inc();
+            Error::SerdeJson(e)
+        })?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vmm::vmm_config::rate_limit_policy::PressureSignal;
+
+    #[test]
+    fn test_parse_put_rate_limit_policy_request() {
+        let body = r#"{
+                "signal": {"type": "disk_latency", "device": "nvme0n1"},
+                "high_watermark": 5000,
+                "low_watermark": 1000,
+                "period_ms": 500
+              }"#;
+        match parse_put_rate_limit_policy(&Body::new(body)) {
+            Ok(ParsedRequest::Sync(VmmAction::SetRateLimitPolicy(config))) => {
+                assert_eq!(
+                    config.signal,
+                    PressureSignal::DiskLatency {
+                        device: "nvme0n1".to_string()
+                    }
+                );
+                assert_eq!(config.high_watermark, 5000);
+                assert_eq!(config.low_watermark, 1000);
+                assert_eq!(config.period_ms, 500);
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "signal": {"type": "psi", "resource": "io"},
+                "high_watermark": 40,
+                "low_watermark": 10,
+                "invalid_field": false
+              }"#;
+        assert!(parse_put_rate_limit_policy(&Body::new(body)).is_err());
+    }
+}
@@ -0,0 +1,24 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Error, ParsedRequest};
+
+pub fn parse_get_resource_usage() -> Result<ParsedRequest, Error> {
+    METRICS.get_api_requests.resource_usage_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::GetVmmResourceUsage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_get_resource_usage_request() {
+        match parse_get_resource_usage() {
+            Ok(ParsedRequest::Sync(VmmAction::GetVmmResourceUsage)) => {}
+            _ => panic!("Test failed."),
+        }
+    }
+}
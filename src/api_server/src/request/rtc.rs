@@ -0,0 +1,41 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Body, Error, ParsedRequest};
+use vmm::vmm_config::rtc::RtcConfig;
+
+pub fn parse_put_rtc(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.rtc_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::SetRtcConfiguration(
+        serde_json::from_slice::<RtcConfig>(body.raw()).map_err(|e| {
+            METRICS.put_api_requests.rtc_fails.inc();
+            Error::SerdeJson(e)
+        })?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_put_rtc_request() {
+        match parse_put_rtc(&Body::new(
+            r#"{ "base_time": 946684800, "utc_offset_minutes": 60 }"#,
+        )) {
+            Ok(ParsedRequest::Sync(VmmAction::SetRtcConfiguration(config))) => assert_eq!(
+                config,
+                RtcConfig {
+                    base_time: Some(946_684_800),
+                    utc_offset_minutes: 60
+                }
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        assert!(parse_put_rtc(&Body::new(r#"{ "base_time": "now" }"#)).is_err());
+        assert!(parse_put_rtc(&Body::new(r#"{ "foo": 0 }"#)).is_err());
+    }
+}
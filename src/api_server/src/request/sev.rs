@@ -0,0 +1,52 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Body, Error, ParsedRequest};
+use vmm::vmm_config::sev::SevConfig;
+
+pub fn parse_put_sev(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.sev_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::SetSevConfiguration(
+        serde_json::from_slice::<SevConfig>(body.raw()).map_err(|e| {
+            METRICS.put_api_requests.sev_fails.inc();
+            Error::SerdeJson(e)
+        })?,
+    )))
+}
+
+pub fn parse_get_launch_measurement() -> Result<ParsedRequest, Error> {
+    METRICS.get_api_requests.launch_measurement_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::GetLaunchMeasurement))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_put_sev_request() {
+        match parse_put_sev(&Body::new(r#"{ "policy": 5, "es": true }"#)) {
+            Ok(ParsedRequest::Sync(VmmAction::SetSevConfiguration(config))) => assert_eq!(
+                config,
+                SevConfig {
+                    policy: 5,
+                    es: true
+                }
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        assert!(parse_put_sev(&Body::new(r#"{ "policy": "none" }"#)).is_err());
+        assert!(parse_put_sev(&Body::new(r#"{ "foo": 0 }"#)).is_err());
+    }
+
+    #[test]
+    fn test_parse_get_launch_measurement_request() {
+        match parse_get_launch_measurement() {
+            Ok(ParsedRequest::Sync(VmmAction::GetLaunchMeasurement)) => {}
+            _ => panic!("Test failed."),
+        }
+    }
+}
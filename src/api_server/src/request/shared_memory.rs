@@ -0,0 +1,76 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{checked_id, Body, Error, ParsedRequest, StatusCode};
+use vmm::vmm_config::shared_memory::SharedMemoryConfig;
+
+pub fn parse_put_shared_memory(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.shared_memory_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        METRICS.put_api_requests.shared_memory_fails.inc();
+        return Err(Error::EmptyID);
+    };
+
+    let config = serde_json::from_slice::<SharedMemoryConfig>(body.raw()).map_err(|e| {
+        METRICS.put_api_requests.shared_memory_fails.inc();
+        Error::SerdeJson(e)
+    })?;
+    if id != config.shm_id.as_str() {
+        METRICS.put_api_requests.shared_memory_fails.inc();
+        return Err(Error::Generic(
+            StatusCode::BadRequest,
+            "The id from the path does not match the id from the body!".to_string(),
+        ));
+    }
+    Ok(ParsedRequest::Sync(VmmAction::InsertSharedMemoryDevice(
+        config,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_put_shared_memory_request() {
+        let body = r#"{
+                "shm_id": "shm",
+                "mem_path": "/dev/shm/data",
+                "peer_socket": "/tmp/peer.sock"
+              }"#;
+        // The id from the path must match the id from the body.
+        assert!(parse_put_shared_memory(&Body::new(body), Some(&"other")).is_err());
+        // The `id_from_path` cannot be None.
+        assert!(parse_put_shared_memory(&Body::new(body), None).is_err());
+
+        match parse_put_shared_memory(&Body::new(body), Some(&"shm")) {
+            Ok(ParsedRequest::Sync(VmmAction::InsertSharedMemoryDevice(config))) => {
+                assert_eq!(
+                    config,
+                    SharedMemoryConfig {
+                        shm_id: "shm".to_string(),
+                        mem_path: PathBuf::from("/dev/shm/data"),
+                        peer_socket: Some(PathBuf::from("/tmp/peer.sock")),
+                    }
+                )
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "shm_id": "shm",
+                "mem_path": "/dev/shm/data",
+                "invalid_field": false
+              }"#;
+        assert!(parse_put_shared_memory(&Body::new(body), Some(&"shm")).is_err());
+    }
+}
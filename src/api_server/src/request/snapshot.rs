@@ -4,6 +4,8 @@
 use super::super::VmmAction;
 use request::{Body, Error, ParsedRequest, StatusCode};
 use vmm::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams, Vm, VmState};
+#[cfg(target_arch = "x86_64")]
+use vmm::vmm_config::snapshot::{LiveUpdateParams, MigrationParams};
 use Method;
 
 pub fn parse_put_snapshot(
@@ -32,6 +34,20 @@ pub fn parse_put_snapshot(
     }
 }
 
+#[cfg(target_arch = "x86_64")]
+pub fn parse_put_live_update(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::Sync(VmmAction::LiveUpdate(
+        serde_json::from_slice::<LiveUpdateParams>(body.raw()).map_err(Error::SerdeJson)?,
+    )))
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn parse_put_migrate(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::Sync(VmmAction::Migrate(
+        serde_json::from_slice::<MigrationParams>(body.raw()).map_err(Error::SerdeJson)?,
+    )))
+}
+
 pub fn parse_patch_vm_state(body: &Body) -> Result<ParsedRequest, Error> {
     let vm = serde_json::from_slice::<Vm>(body.raw()).map_err(Error::SerdeJson)?;
 
@@ -46,7 +62,9 @@ mod tests {
     use std::path::PathBuf;
 
     use super::*;
-    use vmm::vmm_config::snapshot::SnapshotType;
+    use vmm::vmm_config::snapshot::{
+        MemBackendType, MemoryCompression, PostRestoreConfig, SnapshotType,
+    };
 
     #[test]
     fn test_parse_put_snapshot() {
@@ -62,6 +80,9 @@ mod tests {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
             version: Some(2),
+            compression: MemoryCompression::None,
+            checksums: false,
+            background: false,
         };
 
         match parse_put_snapshot(&Body::new(body), Some(&"create")) {
@@ -81,6 +102,34 @@ mod tests {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
             version: None,
+            compression: MemoryCompression::None,
+            checksums: false,
+            background: false,
+        };
+
+        match parse_put_snapshot(&Body::new(body), Some(&"create")) {
+            Ok(ParsedRequest::Sync(VmmAction::CreateSnapshot(cfg))) => {
+                assert_eq!(cfg, expected_cfg)
+            }
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "compression": "Zstd",
+                "checksums": true,
+                "background": true
+              }"#;
+
+        expected_cfg = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: PathBuf::from("foo"),
+            mem_file_path: PathBuf::from("bar"),
+            version: None,
+            compression: MemoryCompression::Zstd,
+            checksums: true,
+            background: true,
         };
 
         match parse_put_snapshot(&Body::new(body), Some(&"create")) {
@@ -106,6 +155,12 @@ mod tests {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
             enable_diff_snapshots: false,
+            mem_backend: MemBackendType::File,
+            uffd_socket_path: None,
+            profile_page_faults: false,
+            record_manifest_path: None,
+            prefetch_manifest_path: None,
+            post_restore: PostRestoreConfig::default(),
         };
         match parse_put_snapshot(&Body::new(body), Some(&"load")) {
             Ok(ParsedRequest::Sync(VmmAction::LoadSnapshot(cfg))) => assert_eq!(cfg, expected_cfg),
@@ -122,6 +177,93 @@ mod tests {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
             enable_diff_snapshots: true,
+            mem_backend: MemBackendType::File,
+            uffd_socket_path: None,
+            profile_page_faults: false,
+            record_manifest_path: None,
+            prefetch_manifest_path: None,
+            post_restore: PostRestoreConfig::default(),
+        };
+
+        match parse_put_snapshot(&Body::new(body), Some(&"load")) {
+            Ok(ParsedRequest::Sync(VmmAction::LoadSnapshot(cfg))) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "mem_backend": "Uffd",
+                "uffd_socket_path": "baz"
+              }"#;
+
+        expected_cfg = LoadSnapshotParams {
+            snapshot_path: PathBuf::from("foo"),
+            mem_file_path: PathBuf::from("bar"),
+            enable_diff_snapshots: false,
+            mem_backend: MemBackendType::Uffd,
+            uffd_socket_path: Some(PathBuf::from("baz")),
+            profile_page_faults: false,
+            record_manifest_path: None,
+            prefetch_manifest_path: None,
+            post_restore: PostRestoreConfig::default(),
+        };
+
+        match parse_put_snapshot(&Body::new(body), Some(&"load")) {
+            Ok(ParsedRequest::Sync(VmmAction::LoadSnapshot(cfg))) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "mem_backend": "Uffd",
+                "profile_page_faults": true,
+                "record_manifest_path": "baz",
+                "prefetch_manifest_path": "qux"
+              }"#;
+
+        expected_cfg = LoadSnapshotParams {
+            snapshot_path: PathBuf::from("foo"),
+            mem_file_path: PathBuf::from("bar"),
+            enable_diff_snapshots: false,
+            mem_backend: MemBackendType::Uffd,
+            uffd_socket_path: None,
+            profile_page_faults: true,
+            record_manifest_path: Some(PathBuf::from("baz")),
+            prefetch_manifest_path: Some(PathBuf::from("qux")),
+            post_restore: PostRestoreConfig::default(),
+        };
+
+        match parse_put_snapshot(&Body::new(body), Some(&"load")) {
+            Ok(ParsedRequest::Sync(VmmAction::LoadSnapshot(cfg))) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "post_restore": {
+                    "reseed_entropy": true,
+                    "mmds_data": { "instance-id": "i-1" },
+                    "notify_guest": true
+                }
+              }"#;
+
+        expected_cfg = LoadSnapshotParams {
+            snapshot_path: PathBuf::from("foo"),
+            mem_file_path: PathBuf::from("bar"),
+            enable_diff_snapshots: false,
+            mem_backend: MemBackendType::File,
+            uffd_socket_path: None,
+            profile_page_faults: false,
+            record_manifest_path: None,
+            prefetch_manifest_path: None,
+            post_restore: PostRestoreConfig {
+                reseed_entropy: true,
+                mmds_data: Some(json!({ "instance-id": "i-1" })),
+                notify_guest: true,
+            },
         };
 
         match parse_put_snapshot(&Body::new(body), Some(&"load")) {
@@ -133,6 +275,50 @@ mod tests {
         assert!(parse_put_snapshot(&Body::new(body), None).is_err());
     }
 
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_parse_put_live_update() {
+        let body = r#"{
+                "socket_path": "foo"
+              }"#;
+        let expected_cfg = LiveUpdateParams {
+            socket_path: PathBuf::from("foo"),
+        };
+
+        match parse_put_live_update(&Body::new(body)) {
+            Ok(ParsedRequest::Sync(VmmAction::LiveUpdate(cfg))) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        let invalid_body = r#"{
+                "socket_path": "foo",
+                "invalid_field": "bar"
+              }"#;
+        assert!(parse_put_live_update(&Body::new(invalid_body)).is_err());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_parse_put_migrate() {
+        let body = r#"{
+                "socket_path": "foo"
+              }"#;
+        let expected_cfg = MigrationParams {
+            socket_path: PathBuf::from("foo"),
+        };
+
+        match parse_put_migrate(&Body::new(body)) {
+            Ok(ParsedRequest::Sync(VmmAction::Migrate(cfg))) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        let invalid_body = r#"{
+                "socket_path": "foo",
+                "invalid_field": "bar"
+              }"#;
+        assert!(parse_put_migrate(&Body::new(invalid_body)).is_err());
+    }
+
     #[test]
     fn test_parse_patch_vm_state() {
         let mut body = r#"{
@@ -0,0 +1,51 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Body, Error, ParsedRequest};
+use vmm::vmm_config::sound::SoundDeviceConfig;
+
+pub fn parse_put_sound(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.sound_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::SetSoundDevice(
+        serde_json::from_slice::<SoundDeviceConfig>(body.raw()).map_err(|e| {
+            METRICS.put_api_requests.sound_fails.inc();
+            Error::SerdeJson(e)
+        })?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm::vmm_config::sound::SoundBackendType;
+
+    #[test]
+    fn test_parse_put_sound_request() {
+        let body = r#"{
+                "backend": "file",
+                "path": "/tmp/sound.pcm",
+                "rate": 44100,
+                "channels": 1
+              }"#;
+        match parse_put_sound(&Body::new(body)) {
+            Ok(ParsedRequest::Sync(VmmAction::SetSoundDevice(config))) => assert_eq!(
+                config,
+                SoundDeviceConfig {
+                    backend: SoundBackendType::File,
+                    path: "/tmp/sound.pcm".to_string(),
+                    rate: 44100,
+                    channels: 1,
+                }
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        assert!(parse_put_sound(&Body::new("{}")).is_err());
+        assert!(parse_put_sound(&Body::new(
+            r#"{ "backend": "alsa", "path": "/tmp/sound.pcm" }"#
+        ))
+        .is_err());
+    }
+}
@@ -0,0 +1,36 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Body, Error, ParsedRequest};
+
+pub fn parse_put_validate(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.validate_count.inc();
+    // The VMM checks the configuration itself, the same way as a `--config-file`.
+    serde_json::from_slice::<serde_json::Value>(body.raw()).map_err(|e| {
+        METRICS.put_api_requests.validate_fails.inc();
+        Error::SerdeJson(e)
+    })?;
+    Ok(ParsedRequest::Sync(VmmAction::ValidateConfiguration(
+        String::from_utf8_lossy(body.raw()).into_owned(),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_put_validate_request() {
+        let config = r#"{ "boot-source": { "kernel_image_path": "/foo/bar" }, "drives": [] }"#;
+        match parse_put_validate(&Body::new(config)) {
+            Ok(ParsedRequest::Sync(VmmAction::ValidateConfiguration(config_json))) => {
+                assert_eq!(config_json, config)
+            }
+            _ => panic!("Test failed."),
+        }
+
+        assert!(parse_put_validate(&Body::new(r#"{ "drives": [ }"#)).is_err());
+    }
+}
@@ -0,0 +1,60 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{checked_id, Body, Error, ParsedRequest, StatusCode};
+use vmm::vmm_config::machine_config::VcpuStateConfig;
+
+pub fn parse_put_vcpu_state(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.vcpu_state_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        METRICS.put_api_requests.vcpu_state_fails.inc();
+        return Err(Error::EmptyID);
+    };
+
+    let vcpu_state = serde_json::from_slice::<VcpuStateConfig>(body.raw()).map_err(|e| {
+        METRICS.put_api_requests.vcpu_state_fails.inc();
+        Error::SerdeJson(e)
+    })?;
+    if id != vcpu_state.vcpu_id.to_string() {
+        METRICS.put_api_requests.vcpu_state_fails.inc();
+        return Err(Error::Generic(
+            StatusCode::BadRequest,
+            "The id from the path does not match the id from the body!".to_string(),
+        ));
+    }
+    Ok(ParsedRequest::Sync(VmmAction::SetVcpuState(vcpu_state)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_put_vcpu_state_request() {
+        let body = r#"{"vcpu_id": 1, "paused": true}"#;
+        assert!(parse_put_vcpu_state(&Body::new(body), Some(&"2")).is_err());
+        assert!(parse_put_vcpu_state(&Body::new(body), Some(&"01")).is_err());
+        assert!(parse_put_vcpu_state(&Body::new(body), None).is_err());
+
+        match parse_put_vcpu_state(&Body::new(body), Some(&"1")) {
+            Ok(ParsedRequest::Sync(VmmAction::SetVcpuState(vcpu_state))) => assert_eq!(
+                vcpu_state,
+                VcpuStateConfig {
+                    vcpu_id: 1,
+                    paused: true,
+                }
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{"vcpu_id": "1", "paused": true}"#;
+        assert!(parse_put_vcpu_state(&Body::new(body), Some(&"1")).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Body, Error, ParsedRequest};
+use vmm::vmm_config::virtio_features::VirtioFeaturePolicyConfig;
+
+pub fn parse_put_virtio_features(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.virtio_features_count.inc();
+    Ok(ParsedRequest::Sync(VmmAction::SetVirtioFeaturePolicy(
+        serde_json::from_slice::<VirtioFeaturePolicyConfig>(body.raw()).map_err(|e| {
+            METRICS.put_api_requests.virtio_features_fails.inc();
+            Error::SerdeJson(e)
+        })?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vmm::vmm_config::virtio_features::VirtioFeatureMask;
+
+    #[test]
+    fn test_parse_put_virtio_features_request() {
+        let body = r#"{
+                "net": {"allow": 4295032831},
+                "block": {"deny": 512}
+              }"#;
+        match parse_put_virtio_features(&Body::new(body)) {
+            Ok(ParsedRequest::Sync(VmmAction::SetVirtioFeaturePolicy(config))) => {
+                assert_eq!(
+                    config.net,
+                    Some(VirtioFeatureMask {
+                        allow: Some(0x1_0000_ffff),
+                        deny: 0,
+                    })
+                );
+                assert_eq!(
+                    config.block,
+                    Some(VirtioFeatureMask {
+                        allow: None,
+                        deny: 512,
+                    })
+                );
+                assert!(config.vsock.is_none());
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "pmem": {"deny": 1}
+              }"#;
+        assert!(parse_put_virtio_features(&Body::new(body)).is_err());
+    }
+}
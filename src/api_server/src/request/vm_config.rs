@@ -0,0 +1,36 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use logger::{Metric, METRICS};
+use request::{Error, ParsedRequest};
+use Method;
+
+pub fn parse_get_vm_config(resource_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
+    match resource_from_path {
+        Some(&"config") => {
+            METRICS.get_api_requests.vm_config_count.inc();
+            Ok(ParsedRequest::Sync(VmmAction::GetFullConfiguration))
+        }
+        Some(&resource) => Err(Error::InvalidPathMethod(
+            format!("/vm/{}", resource),
+            Method::Get,
+        )),
+        None => Err(Error::InvalidPathMethod("/vm".to_string(), Method::Get)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_get_vm_config_request() {
+        match parse_get_vm_config(Some(&"config")) {
+            Ok(ParsedRequest::Sync(VmmAction::GetFullConfiguration)) => {}
+            _ => panic!("Test failed."),
+        }
+        assert!(parse_get_vm_config(Some(&"state")).is_err());
+        assert!(parse_get_vm_config(None).is_err());
+    }
+}
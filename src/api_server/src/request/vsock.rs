@@ -2,19 +2,60 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::super::VmmAction;
-use request::{Body, Error, ParsedRequest};
+use logger::{Metric, METRICS};
+use request::{checked_id, Body, Error, ParsedRequest, StatusCode};
 use vmm::vmm_config::vsock::VsockDeviceConfig;
+use Method;
 
-pub fn parse_put_vsock(body: &Body) -> Result<ParsedRequest, Error> {
-    Ok(ParsedRequest::Sync(VmmAction::SetVsockDevice(
-        serde_json::from_slice::<VsockDeviceConfig>(body.raw()).map_err(Error::SerdeJson)?,
-    )))
+pub fn parse_get_vsock(resource_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
+    match resource_from_path {
+        Some(&"connections") => {
+            METRICS.get_api_requests.vsock_connections_count.inc();
+            Ok(ParsedRequest::Sync(VmmAction::ListVsockConnections))
+        }
+        Some(&resource) => Err(Error::InvalidPathMethod(
+            format!("/vsock/{}", resource),
+            Method::Get,
+        )),
+        None => Err(Error::InvalidPathMethod("/vsock".to_string(), Method::Get)),
+    }
+}
+
+// `PUT /vsock` sets the only vsock device, as it did before several vsock devices were
+// supported, while `PUT /vsock/{vsock_id}` adds or updates one of them.
+pub fn parse_put_vsock(body: &Body, id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
+    let device_cfg =
+        serde_json::from_slice::<VsockDeviceConfig>(body.raw()).map_err(Error::SerdeJson)?;
+    match id_from_path {
+        Some(id) => {
+            if checked_id(id)? != device_cfg.vsock_id.as_str() {
+                return Err(Error::Generic(
+                    StatusCode::BadRequest,
+                    "The id from the path does not match the id from the body!".to_string(),
+                ));
+            }
+            Ok(ParsedRequest::Sync(VmmAction::InsertVsockDevice(
+                device_cfg,
+            )))
+        }
+        None => Ok(ParsedRequest::Sync(VmmAction::SetVsockDevice(device_cfg))),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_get_vsock_request() {
+        match parse_get_vsock(Some(&"connections")) {
+            Ok(ParsedRequest::Sync(VmmAction::ListVsockConnections)) => {}
+            _ => panic!("Test failed."),
+        }
+        assert!(parse_get_vsock(Some(&"listeners")).is_err());
+        assert!(parse_get_vsock(None).is_err());
+    }
+
     #[test]
     fn test_parse_put_vsock_request() {
         let body = r#"{
@@ -22,13 +63,61 @@ mod tests {
                 "guest_cid": 42,
                 "uds_path": "vsock.sock"
               }"#;
-        assert!(parse_put_vsock(&Body::new(body)).is_ok());
+        match parse_put_vsock(&Body::new(body), None) {
+            Ok(ParsedRequest::Sync(VmmAction::SetVsockDevice(_))) => {}
+            _ => panic!("Test failed."),
+        }
+        match parse_put_vsock(&Body::new(body), Some(&"foo")) {
+            Ok(ParsedRequest::Sync(VmmAction::InsertVsockDevice(_))) => {}
+            _ => panic!("Test failed."),
+        }
+        assert!(parse_put_vsock(&Body::new(body), Some(&"bar")).is_err());
+
+        let body = r#"{
+                "vsock_id": "foo",
+                "guest_cid": 42,
+                "backend": "vhost"
+              }"#;
+        assert!(parse_put_vsock(&Body::new(body), None).is_ok());
+
+        let body = r#"{
+                "vsock_id": "foo",
+                "backend": "vhost",
+                "cid_pool": {
+                    "first_cid": 100,
+                    "last_cid": 199,
+                    "registry_dir": "/run/firecracker/cids"
+                }
+              }"#;
+        match parse_put_vsock(&Body::new(body), None) {
+            Ok(ParsedRequest::Sync(VmmAction::SetVsockDevice(config))) => {
+                assert_eq!(config.guest_cid, 0);
+                assert_eq!(config.cid_pool.unwrap().last_cid, 199);
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "vsock_id": "foo",
+                "guest_cid": 42,
+                "uds_path": "vsock.sock",
+                "uds_mode": "0660",
+                "uds_gid": 1000
+              }"#;
+        match parse_put_vsock(&Body::new(body), None) {
+            Ok(ParsedRequest::Sync(VmmAction::SetVsockDevice(config))) => {
+                assert_eq!(config.uds_mode, Some("0660".to_string()));
+                assert_eq!(config.uds_uid, None);
+                assert_eq!(config.uds_gid, Some(1000));
+            }
+            _ => panic!("Test failed."),
+        }
 
         let body = r#"{
                 "vsock_id": "foo",
                 "guest_cid": 42,
                 "invalid_field": false
               }"#;
-        assert!(parse_put_vsock(&Body::new(body)).is_err());
+        assert!(parse_put_vsock(&Body::new(body), None).is_err());
     }
 }
@@ -0,0 +1,159 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Keeps the responses to the API requests in order while their actions wait for the VMM.
+//!
+//! The VMM runs the actions one at a time, in the order they are sent, and responds to them in
+//! the same order. Meanwhile, the API thread keeps serving the requests which don't need the VMM,
+//! so the responses of a connection are held back until the ones to its earlier requests are
+//! sent.
+
+use std::collections::VecDeque;
+
+use micro_http::Response;
+
+// The response to a request, once known.
+struct Entry {
+    // The identification token of the request.
+    id: u64,
+    // `None` while the action of the request waits for the VMM.
+    response: Option<Response>,
+}
+
+/// The responses to the requests of the API clients, in the order of the requests.
+pub(crate) struct ResponseQueue {
+    entries: VecDeque<Entry>,
+    max_actions: usize,
+    pending_actions: usize,
+}
+
+impl ResponseQueue {
+    /// Creates a queue holding the responses to at most `max_actions` actions waiting for the
+    /// VMM.
+    pub fn new(max_actions: usize) -> Self {
+        ResponseQueue {
+            entries: VecDeque::new(),
+            max_actions,
+            pending_actions: 0,
+        }
+    }
+
+    /// Returns the maximum number of actions waiting for the VMM.
+    pub fn max_actions(&self) -> usize {
+        self.max_actions
+    }
+
+    /// Returns true if no more actions can wait for the VMM, including the one it runs.
+    pub fn is_full(&self) -> bool {
+        self.pending_actions >= self.max_actions
+    }
+
+    /// Records that the response to the request `id` waits for its action, just sent to the
+    /// VMM.
+    pub fn push_action(&mut self, id: u64) {
+        self.entries.push_back(Entry { id, response: None });
+        self.pending_actions += 1;
+    }
+
+    /// Queues the response to the request `id`.
+    pub fn push_response(&mut self, id: u64, response: Response) {
+        self.entries.push_back(Entry {
+            id,
+            response: Some(response),
+        });
+    }
+
+    /// Records the response of the VMM to the oldest action waiting for it. Returns false if no
+    /// action waits for the VMM.
+    pub fn complete_action(&mut self, response: Response) -> bool {
+        match self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.response.is_none())
+        {
+            Some(entry) => {
+                entry.response = Some(response);
+                self.pending_actions -= 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the responses which can be sent, those following no response still waiting for
+    /// the VMM on the same connection, along with the identification tokens of their requests.
+    pub fn pop_ready(&mut self) -> Vec<(u64, Response)> {
+        let mut ready = Vec::new();
+        let mut waiting_ids = Vec::new();
+        let mut entries = VecDeque::with_capacity(self.entries.len());
+        for entry in self.entries.drain(..) {
+            match entry.response {
+                Some(response) if !waiting_ids.contains(&entry.id) => {
+                    ready.push((entry.id, response))
+                }
+                Some(_) => entries.push_back(entry),
+                None => {
+                    waiting_ids.push(entry.id);
+                    entries.push_back(entry);
+                }
+            }
+        }
+        self.entries = entries;
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use micro_http::{StatusCode, Version};
+
+    fn response(status: StatusCode) -> Response {
+        Response::new(Version::Http11, status)
+    }
+
+    fn statuses(ready: Vec<(u64, Response)>) -> Vec<(u64, StatusCode)> {
+        ready
+            .into_iter()
+            .map(|(id, response)| (id, response.status()))
+            .collect()
+    }
+
+    #[test]
+    fn test_response_queue() {
+        let mut queue = ResponseQueue::new(2);
+        assert_eq!(queue.max_actions(), 2);
+        assert!(!queue.complete_action(response(StatusCode::OK)));
+
+        // The responses which don't wait for the VMM are sent right away.
+        queue.push_response(1, response(StatusCode::OK));
+        assert_eq!(statuses(queue.pop_ready()), vec![(1, StatusCode::OK)]);
+
+        queue.push_action(1);
+        queue.push_action(2);
+        assert!(queue.is_full());
+        // The connection 1 waits for its action, while the connection 3 doesn't.
+        queue.push_response(1, response(StatusCode::OK));
+        queue.push_response(3, response(StatusCode::BadRequest));
+        assert_eq!(
+            statuses(queue.pop_ready()),
+            vec![(3, StatusCode::BadRequest)]
+        );
+
+        // The VMM responds to the actions in order.
+        assert!(queue.complete_action(response(StatusCode::NoContent)));
+        assert!(!queue.is_full());
+        assert_eq!(
+            statuses(queue.pop_ready()),
+            vec![(1, StatusCode::NoContent), (1, StatusCode::OK)]
+        );
+        assert!(queue.pop_ready().is_empty());
+        assert!(queue.complete_action(response(StatusCode::BadRequest)));
+        assert_eq!(
+            statuses(queue.pop_ready()),
+            vec![(2, StatusCode::BadRequest)]
+        );
+        assert!(!queue.complete_action(response(StatusCode::OK)));
+    }
+}
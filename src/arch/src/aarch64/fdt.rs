@@ -24,6 +24,8 @@ use vm_memory::{Address, Bytes, GuestAddress, GuestMemory, GuestMemoryError, Gue
 const GIC_PHANDLE: u32 = 1;
 // This is a value for uniquely identifying the FDT node containing the clock definition.
 const CLOCK_PHANDLE: u32 = 2;
+// This is a value for uniquely identifying the FDT node containing the ITS.
+const MSI_PHANDLE: u32 = 3;
 // Read the documentation specified when appending the root node to the FDT.
 const ADDRESS_CELLS: u32 = 0x2;
 const SIZE_CELLS: u32 = 0x2;
@@ -387,6 +389,21 @@ fn create_gic_node(fdt: &mut Vec<u8>, gic_device: &Box<dyn GICDevice>) -> Result
     let gic_intr_prop = generate_prop32(&gic_intr);
 
     append_property(fdt, "interrupts", &gic_intr_prop)?;
+
+    if let Some(its_properties) = gic_device.its_properties() {
+        // The ITS is described as a child node of the GIC, see
+        // https://www.kernel.org/doc/Documentation/devicetree/bindings/interrupt-controller/arm%2Cgic-v3.txt
+        let its_reg_prop = generate_prop64(its_properties);
+
+        append_begin_node(fdt, "msic")?;
+        append_property_string(fdt, "compatible", "arm,gic-v3-its")?;
+        append_property_null(fdt, "msi-controller")?;
+        append_property_u32(fdt, "#msi-cells", 1)?;
+        append_property(fdt, "reg", &its_reg_prop)?;
+        append_property_u32(fdt, "phandle", MSI_PHANDLE)?;
+        append_end_node(fdt)?;
+    }
+
     append_end_node(fdt)?;
 
     Ok(())
@@ -527,7 +544,7 @@ fn create_devices_node<T: DeviceInfoForFDT + Clone + Debug>(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use aarch64::gic::create_gic;
+    use aarch64::gic::{create_gic, GICConfig};
     use aarch64::{arch_memory_regions, layout};
     use kvm_ioctls::Kvm;
 
@@ -589,7 +606,7 @@ mod tests {
         .collect();
         let kvm = Kvm::new().unwrap();
         let vm = kvm.create_vm().unwrap();
-        let gic = create_gic(&vm, 1).unwrap();
+        let gic = create_gic(&vm, 1, &GICConfig::default()).unwrap();
         assert!(create_fdt(
             &mem,
             vec![0],
@@ -607,7 +624,7 @@ mod tests {
         let mem = GuestMemoryMmap::from_ranges(&regions).expect("Cannot initialize memory");
         let kvm = Kvm::new().unwrap();
         let vm = kvm.create_vm().unwrap();
-        let gic = create_gic(&vm, 1).unwrap();
+        let gic = create_gic(&vm, 1, &GICConfig::default()).unwrap();
         let mut dtb = create_fdt(
             &mem,
             vec![0],
@@ -652,7 +669,7 @@ mod tests {
         let mem = GuestMemoryMmap::from_ranges(&regions).expect("Cannot initialize memory");
         let kvm = Kvm::new().unwrap();
         let vm = kvm.create_vm().unwrap();
-        let gic = create_gic(&vm, 1).unwrap();
+        let gic = create_gic(&vm, 1, &GICConfig::default()).unwrap();
         let initrd = InitrdConfig {
             address: GuestAddress(0x10000000),
             size: 0x1000,
@@ -15,9 +15,31 @@ pub enum Error {
     CreateGIC(kvm_ioctls::Error),
     /// Error while setting device attributes for the GIC.
     SetDeviceAttribute(kvm_ioctls::Error),
+    /// The ITS can only be added to a GICv3.
+    ITSNotSupported,
 }
 type Result<T> = result::Result<T, Error>;
 
+/// Version of the GIC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GICVersion {
+    /// GICv2, limited to 8 vCPUs.
+    GICV2,
+    /// GICv3.
+    GICV3,
+}
+
+/// Configuration of the GIC.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GICConfig {
+    /// The GIC version. When not specified, a GICv3 is created if the host supports it and a
+    /// GICv2 otherwise.
+    pub version: Option<GICVersion>,
+    /// Adds an ITS (Interrupt Translation Service) to the GICv3, translating the MSIs written
+    /// by the devices to LPIs.
+    pub its: bool,
+}
+
 /// Trait for GIC devices.
 pub trait GICDevice {
     /// Returns the file descriptor of the GIC device
@@ -35,6 +57,11 @@ pub trait GICDevice {
     /// Returns the maint_irq fdt property of the device
     fn fdt_maint_irq(&self) -> u32;
 
+    /// Returns the address and size of the ITS, if the device has one
+    fn its_properties(&self) -> Option<&[u64]> {
+        None
+    }
+
     /// Returns the GIC version of the device
     fn version() -> u32
     where
@@ -138,10 +165,17 @@ pub trait GICDevice {
 
 /// Create a GIC device.
 ///
-/// It will try to create by default a GICv3 device. If that fails it will try
-/// to fall-back to a GICv2 device.
-pub fn create_gic(vm: &VmFd, vcpu_count: u64) -> Result<Box<dyn GICDevice>> {
-    GICv3::new(vm, vcpu_count).or_else(|_| GICv2::new(vm, vcpu_count))
+/// Unless the version is specified in `config`, it will try to create by default a GICv3
+/// device. If that fails it will try to fall-back to a GICv2 device, except when an ITS is
+/// requested since only a GICv3 can have one.
+pub fn create_gic(vm: &VmFd, vcpu_count: u64, config: &GICConfig) -> Result<Box<dyn GICDevice>> {
+    match (config.version, config.its) {
+        (Some(GICVersion::GICV2), true) => Err(Error::ITSNotSupported),
+        (Some(GICVersion::GICV2), false) => GICv2::new(vm, vcpu_count),
+        (_, true) => GICv3::new_with_its(vm, vcpu_count),
+        (Some(GICVersion::GICV3), false) => GICv3::new(vm, vcpu_count),
+        (None, false) => GICv3::new(vm, vcpu_count).or_else(|_| GICv2::new(vm, vcpu_count)),
+    }
 }
 
 #[cfg(test)]
@@ -154,6 +188,30 @@ mod tests {
     fn test_create_gic() {
         let kvm = Kvm::new().unwrap();
         let vm = kvm.create_vm().unwrap();
-        assert!(create_gic(&vm, 1).is_ok());
+        assert!(create_gic(&vm, 1, &GICConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn test_create_gic_with_config() {
+        let kvm = Kvm::new().unwrap();
+        let vm = kvm.create_vm().unwrap();
+        let config = GICConfig {
+            version: Some(GICVersion::GICV2),
+            its: true,
+        };
+        match create_gic(&vm, 1, &config) {
+            Err(Error::ITSNotSupported) => (),
+            _ => panic!("Only a GICv3 can have an ITS."),
+        }
+
+        // The host may not support a GICv3, in which case there is no fall-back.
+        let config = GICConfig {
+            version: Some(GICVersion::GICV3),
+            its: true,
+        };
+        if let Ok(gic) = create_gic(&vm, 1, &config) {
+            assert_eq!(gic.fdt_compatibility(), "arm,gic-v3");
+            assert_eq!(gic.its_properties().unwrap().len(), 2);
+        }
     }
 }
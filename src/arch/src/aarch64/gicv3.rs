@@ -3,7 +3,7 @@
 
 use std::{boxed::Box, result};
 
-use kvm_ioctls::DeviceFd;
+use kvm_ioctls::{DeviceFd, VmFd};
 
 use super::gic::{Error, GICDevice};
 
@@ -18,6 +18,12 @@ pub struct GICv3 {
 
     /// Number of CPUs handled by the device
     vcpu_count: u64,
+
+    /// The file descriptor for the KVM ITS device, if any
+    its_fd: Option<DeviceFd>,
+
+    /// ITS device properties, to be used for setting up the fdt entry
+    its_properties: [u64; 2],
 }
 
 impl GICv3 {
@@ -26,6 +32,7 @@ impl GICv3 {
     const SZ_64K: u64 = 0x0001_0000;
     const KVM_VGIC_V3_DIST_SIZE: u64 = GICv3::SZ_64K;
     const KVM_VGIC_V3_REDIST_SIZE: u64 = (2 * GICv3::SZ_64K);
+    const KVM_VGIC_V3_ITS_SIZE: u64 = (2 * GICv3::SZ_64K);
 
     // Device trees specific constants
     const ARCH_GIC_V3_MAINT_IRQ: u32 = 9;
@@ -49,6 +56,73 @@ impl GICv3 {
     fn get_redists_size(vcpu_count: u64) -> u64 {
         vcpu_count * GICv3::KVM_VGIC_V3_REDIST_SIZE
     }
+
+    /// Get the address of the ITS, placed below the redistributors.
+    fn get_its_addr(vcpu_count: u64) -> u64 {
+        GICv3::get_redists_addr(vcpu_count) - GICv3::KVM_VGIC_V3_ITS_SIZE
+    }
+
+    /// Create a GICv3 device with an ITS, which translates the MSIs written by the devices to
+    /// LPIs.
+    pub fn new_with_its(vm: &VmFd, vcpu_count: u64) -> Result<Box<dyn GICDevice>> {
+        let vgic_fd = Self::init_device(vm)?;
+
+        let mut its_device = kvm_bindings::kvm_create_device {
+            type_: kvm_bindings::kvm_device_type_KVM_DEV_TYPE_ARM_VGIC_ITS,
+            fd: 0,
+            flags: 0,
+        };
+        let its_fd = vm
+            .create_device(&mut its_device)
+            .map_err(Error::CreateGIC)?;
+        GICv3::init_its_attributes(&its_fd, vcpu_count)?;
+
+        let device: Box<dyn GICDevice> = Box::new(GICv3 {
+            its_fd: Some(its_fd),
+            ..GICv3::create(vgic_fd, vcpu_count)
+        });
+
+        Self::init_device_attributes(&device)?;
+
+        Self::finalize_device(&device)?;
+
+        Ok(device)
+    }
+
+    /// Create the GICv3 device object, without an ITS.
+    fn create(fd: DeviceFd, vcpu_count: u64) -> GICv3 {
+        GICv3 {
+            fd: fd,
+            properties: [
+                GICv3::get_dist_addr(),
+                GICv3::get_dist_size(),
+                GICv3::get_redists_addr(vcpu_count),
+                GICv3::get_redists_size(vcpu_count),
+            ],
+            vcpu_count: vcpu_count,
+            its_fd: None,
+            its_properties: [GICv3::get_its_addr(vcpu_count), GICv3::KVM_VGIC_V3_ITS_SIZE],
+        }
+    }
+
+    /// Setup the ITS attributes. The ITS has to be initialized before the GIC gets finalized.
+    fn init_its_attributes(its_fd: &DeviceFd, vcpu_count: u64) -> Result<()> {
+        Self::set_device_attribute(
+            its_fd,
+            kvm_bindings::KVM_DEV_ARM_VGIC_GRP_ADDR,
+            u64::from(kvm_bindings::KVM_VGIC_ITS_ADDR_TYPE),
+            &GICv3::get_its_addr(vcpu_count) as *const u64 as u64,
+            0,
+        )?;
+
+        Self::set_device_attribute(
+            its_fd,
+            kvm_bindings::KVM_DEV_ARM_VGIC_GRP_CTRL,
+            u64::from(kvm_bindings::KVM_DEV_ARM_VGIC_CTRL_INIT),
+            0,
+            0,
+        )
+    }
 }
 
 impl GICDevice for GICv3 {
@@ -76,17 +150,12 @@ impl GICDevice for GICv3 {
         GICv3::ARCH_GIC_V3_MAINT_IRQ
     }
 
+    fn its_properties(&self) -> Option<&[u64]> {
+        self.its_fd.as_ref().map(|_| &self.its_properties[..])
+    }
+
     fn create_device(fd: DeviceFd, vcpu_count: u64) -> Box<dyn GICDevice> {
-        Box::new(GICv3 {
-            fd: fd,
-            properties: [
-                GICv3::get_dist_addr(),
-                GICv3::get_dist_size(),
-                GICv3::get_redists_addr(vcpu_count),
-                GICv3::get_redists_size(vcpu_count),
-            ],
-            vcpu_count: vcpu_count,
-        })
+        Box::new(GICv3::create(fd, vcpu_count))
     }
 
     fn init_device_attributes(gic_device: &Box<dyn GICDevice>) -> Result<()> {
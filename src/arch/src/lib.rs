@@ -32,7 +32,8 @@ pub mod x86_64;
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::{
     arch_memory_regions, configure_system, get_kernel_start, initrd_load_addr,
-    layout::CMDLINE_MAX_SIZE, layout::IRQ_BASE, layout::IRQ_MAX, Error, MMIO_MEM_START,
+    layout::CMDLINE_MAX_SIZE, layout::IRQ_BASE, layout::IRQ_MAX, Error, MemoryLayout,
+    MMIO_MEM_START,
 };
 
 /// Type for returning public functions outcome.
@@ -62,8 +63,60 @@ pub struct InitrdConfig {
 /// Default (smallest) memory page size for the supported architectures.
 pub const PAGE_SIZE: usize = 4096;
 
+/// The largest guest memory region registered in a single KVM memory slot. KVM limits the slots
+/// to less than 2^31 pages, so the larger regions are split in regions of this size, which keeps
+/// the huge pages of the guest whole.
+pub const MAX_MEMORY_REGION_SIZE: usize = 1 << 42;
+
+/// Splits the guest memory `regions` larger than `MAX_MEMORY_REGION_SIZE`, so that each region
+/// fits in a KVM memory slot.
+pub fn split_memory_regions(
+    regions: &[(vm_memory::GuestAddress, usize)],
+) -> Vec<(vm_memory::GuestAddress, usize)> {
+    let mut split_regions = Vec::with_capacity(regions.len());
+    for &(start, size) in regions {
+        let mut offset = 0;
+        while offset < size {
+            let region_size = std::cmp::min(size - offset, MAX_MEMORY_REGION_SIZE);
+            split_regions.push((
+                vm_memory::GuestAddress(start.0 + offset as u64),
+                region_size,
+            ));
+            offset += region_size;
+        }
+    }
+    split_regions
+}
+
 impl fmt::Display for DeviceType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm_memory::GuestAddress;
+
+    #[test]
+    fn test_split_memory_regions() {
+        let regions = [(GuestAddress(0), 3 << 30), (GuestAddress(1 << 32), 1 << 30)];
+        assert_eq!(split_memory_regions(&regions), regions.to_vec());
+
+        let tib = 1usize << 40;
+        let regions = [
+            (GuestAddress(0), 3 << 30),
+            (GuestAddress(1 << 32), 9 * tib + (1 << 30)),
+        ];
+        assert_eq!(
+            split_memory_regions(&regions),
+            vec![
+                (GuestAddress(0), 3 << 30),
+                (GuestAddress(1 << 32), 4 * tib),
+                (GuestAddress((1 << 32) + 4 * tib as u64), 4 * tib),
+                (GuestAddress((1 << 32) + 8 * tib as u64), tib + (1 << 30)),
+            ]
+        );
+    }
+}
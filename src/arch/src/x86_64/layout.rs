@@ -23,6 +23,12 @@ pub const HIMEM_START: u64 = 0x0010_0000; //1 MB.
 pub const IRQ_BASE: u32 = 5;
 /// Last usable IRQ ID for virtio device interrupts on x86_64.
 pub const IRQ_MAX: u32 = 15;
+/// First usable IRQ ID for the INTx interrupts of the PCI passthrough devices on x86_64, past
+/// the 16 legacy IRQs.
+pub const PCI_IRQ_BASE: u32 = 16;
+/// Last usable IRQ ID for the INTx interrupts of the PCI passthrough devices on x86_64, the last
+/// pin of the IOAPIC.
+pub const PCI_IRQ_MAX: u32 = 23;
 
 /// Address for the TSS setup.
 pub const KVM_TSS_ADDRESS: u64 = 0xfffb_d000;
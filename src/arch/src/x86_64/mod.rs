@@ -22,6 +22,8 @@ use vm_memory::{
 };
 use InitrdConfig;
 
+pub use self::mptable::PciInterrupt;
+
 // This is a workaround to the Rust enforcement specifying that any implementation of a foreign
 // trait (in this case `ByteValued`) where:
 // *    the type that is implementing the trait is foreign or
@@ -50,25 +52,111 @@ pub enum Error {
 const EBDA_START: u64 = 0x9fc00;
 const FIRST_ADDR_PAST_32BITS: u64 = (1 << 32);
 const MEM_32BIT_GAP_SIZE: u64 = (768 << 20);
-/// The start of the memory area reserved for MMIO devices.
+/// The start of the memory area reserved for MMIO devices, with the default memory layout.
 pub const MMIO_MEM_START: u64 = FIRST_ADDR_PAST_32BITS - MEM_32BIT_GAP_SIZE;
+/// The smallest hole below 4GiB. Besides the MMIO devices, the hole holds the IOAPIC, the local
+/// APIC and the TSS, at the top of the 32-bit address space.
+pub const MMIO32_HOLE_MIN_SIZE: u64 = 64 << 20;
+/// The largest hole below 4GiB, leaving 2GiB of memory below 4GiB.
+pub const MMIO32_HOLE_MAX_SIZE: u64 = 2 << 30;
+/// The 64-bit MMIO window is aligned to 1GiB, so that it doesn't share a huge page with the
+/// guest memory.
+pub const MMIO64_ALIGNMENT: u64 = 1 << 30;
+/// The end of the guest physical address space which every host supported by Firecracker can
+/// address.
+pub const MMIO64_MEM_END: u64 = 1 << 40;
+
+/// Returns the end of the guest physical address space the host can address, past which neither
+/// the guest memory nor the 64-bit MMIO window may extend. It is at least `MMIO64_MEM_END`.
+pub fn guest_phys_addr_end() -> u64 {
+    use std::arch::x86_64::__cpuid;
+
+    // Safe because CPUID is available on every x86_64 CPU, and the leaf holding the number of
+    // physical address bits is only read if the CPU reports it.
+    let phys_addr_bits = unsafe {
+        if __cpuid(0x8000_0000).eax < 0x8000_0008 {
+            return MMIO64_MEM_END;
+        }
+        __cpuid(0x8000_0008).eax & 0xff
+    };
+    1u64.checked_shl(phys_addr_bits)
+        .map_or(MMIO64_MEM_END, |end| std::cmp::max(end, MMIO64_MEM_END))
+}
+
+/// Layout of the guest physical address space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryLayout {
+    /// Size of the hole at the end of the 32-bit address space, which is not backed by guest
+    /// memory. The guest memory not fitting below the hole is placed after 4GiB.
+    pub mmio32_hole_size: u64,
+    /// Size of the 64-bit MMIO window, placed after the guest memory. When the window is not
+    /// empty, the MMIO devices are placed in the window instead of the 32-bit hole.
+    pub mmio64_window_size: u64,
+}
+
+impl Default for MemoryLayout {
+    fn default() -> Self {
+        MemoryLayout {
+            mmio32_hole_size: MEM_32BIT_GAP_SIZE,
+            mmio64_window_size: 0,
+        }
+    }
+}
+
+impl MemoryLayout {
+    /// Returns the start of the hole at the end of the 32-bit address space.
+    pub fn mmio32_start(&self) -> u64 {
+        FIRST_ADDR_PAST_32BITS - self.mmio32_hole_size
+    }
+
+    /// Returns the guest memory regions for `size` bytes of guest memory.
+    pub fn memory_regions(&self, size: usize) -> Vec<(GuestAddress, usize)> {
+        // It's safe to cast the hole start to usize because it fits in a u32 variable
+        // (It points to an address in the 32 bit space).
+        let mmio32_start = self.mmio32_start() as usize;
+        match size.checked_sub(mmio32_start) {
+            // case1: guest memory fits before the gap
+            None | Some(0) => vec![(GuestAddress(0), size)],
+            // case2: guest memory extends beyond the gap
+            Some(remaining) => vec![
+                (GuestAddress(0), mmio32_start),
+                (GuestAddress(FIRST_ADDR_PAST_32BITS), remaining),
+            ],
+        }
+    }
+
+    /// Returns the size of the largest guest memory ending below `addr_end`, an address past 4GiB
+    /// aligned to `MMIO64_ALIGNMENT`.
+    pub fn max_memory_size(&self, addr_end: u64) -> u64 {
+        self.mmio32_start() + (addr_end - FIRST_ADDR_PAST_32BITS)
+    }
+
+    /// Returns the start of the 64-bit MMIO window for `size` bytes of guest memory: the first
+    /// aligned address past both 4GiB and the guest memory.
+    pub fn mmio64_start(&self, size: usize) -> u64 {
+        let mem_end = match (size as u64).checked_sub(self.mmio32_start()) {
+            None | Some(0) => FIRST_ADDR_PAST_32BITS,
+            Some(remaining) => FIRST_ADDR_PAST_32BITS.saturating_add(remaining),
+        };
+        mem_end.saturating_add(MMIO64_ALIGNMENT - 1) & !(MMIO64_ALIGNMENT - 1)
+    }
+
+    /// Returns the address where the MMIO devices are placed, for `size` bytes of guest memory.
+    pub fn mmio_devices_start(&self, size: usize) -> u64 {
+        if self.mmio64_window_size > 0 {
+            self.mmio64_start(size)
+        } else {
+            self.mmio32_start()
+        }
+    }
+}
 
 /// Returns a Vec of the valid memory addresses.
 /// These should be used to configure the GuestMemoryMmap structure for the platform.
 /// For x86_64 all addresses are valid from the start of the kernel except a
 /// carve out at the end of 32bit address space.
 pub fn arch_memory_regions(size: usize) -> Vec<(GuestAddress, usize)> {
-    // It's safe to cast MMIO_MEM_START to usize because it fits in a u32 variable
-    // (It points to an address in the 32 bit space).
-    match size.checked_sub(MMIO_MEM_START as usize) {
-        // case1: guest memory fits before the gap
-        None | Some(0) => vec![(GuestAddress(0), size)],
-        // case2: guest memory extends beyond the gap
-        Some(remaining) => vec![
-            (GuestAddress(0), MMIO_MEM_START as usize),
-            (GuestAddress(FIRST_ADDR_PAST_32BITS), remaining),
-        ],
-    }
+    MemoryLayout::default().memory_regions(size)
 }
 
 /// Returns the memory address where the kernel could be loaded.
@@ -101,24 +189,30 @@ pub fn initrd_load_addr(guest_mem: &GuestMemoryMmap, initrd_size: usize) -> supe
 /// * `cmdline_size` - Size of the kernel command line in bytes including the null terminator.
 /// * `initrd` - Information about where the ramdisk image was loaded in the `guest_mem`.
 /// * `num_cpus` - Number of virtual CPUs the guest will have.
+/// * `pci_interrupts` - The INTx interrupts of the devices plugged in the PCI bus.
 pub fn configure_system(
     guest_mem: &GuestMemoryMmap,
     cmdline_addr: GuestAddress,
     cmdline_size: usize,
     initrd: &Option<InitrdConfig>,
     num_cpus: u8,
+    pci_interrupts: &[PciInterrupt],
 ) -> super::Result<()> {
     const KERNEL_BOOT_FLAG_MAGIC: u16 = 0xaa55;
     const KERNEL_HDR_MAGIC: u32 = 0x5372_6448;
     const KERNEL_LOADER_OTHER: u8 = 0xff;
     const KERNEL_MIN_ALIGNMENT_BYTES: u32 = 0x0100_0000; // Must be non-zero.
     let first_addr_past_32bits = GuestAddress(FIRST_ADDR_PAST_32BITS);
-    let end_32bit_gap_start = GuestAddress(MMIO_MEM_START);
+    // The memory below 4GiB ends where the hole starts, whatever the size of the hole.
+    let end_32bit_gap_start = guest_mem
+        .find_region(GuestAddress(0))
+        .map(|region| GuestAddress(region.len() as u64))
+        .ok_or(Error::E820Configuration)?;
 
     let himem_start = GuestAddress(layout::HIMEM_START);
 
     // Note that this puts the mptable at the last 1k of Linux's 640k base RAM
-    mptable::setup_mptable(guest_mem, num_cpus).map_err(Error::MpTableSetup)?;
+    mptable::setup_mptable(guest_mem, num_cpus, pci_interrupts).map_err(Error::MpTableSetup)?;
 
     let mut params: BootParamsWrapper = BootParamsWrapper(boot_params::default());
 
@@ -136,7 +230,7 @@ pub fn configure_system(
     add_e820_entry(&mut params.0, 0, EBDA_START, E820_RAM)?;
 
     let last_addr = guest_mem.last_addr();
-    if last_addr < end_32bit_gap_start {
+    if last_addr < first_addr_past_32bits {
         add_e820_entry(
             &mut params.0,
             himem_start.raw_value() as u64,
@@ -216,11 +310,62 @@ mod tests {
         assert_eq!(GuestAddress(1u64 << 32), regions[1].0);
     }
 
+    #[test]
+    fn test_memory_layout() {
+        let layout = MemoryLayout::default();
+        assert_eq!(layout.mmio32_start(), MMIO_MEM_START);
+        assert_eq!(layout.mmio_devices_start(1 << 29), MMIO_MEM_START);
+        assert_eq!(layout.mmio64_start(1 << 29), 1 << 32);
+
+        let layout = MemoryLayout {
+            mmio32_hole_size: MMIO32_HOLE_MIN_SIZE,
+            mmio64_window_size: 1 << 30,
+        };
+        assert_eq!(layout.mmio32_start(), (1 << 32) - MMIO32_HOLE_MIN_SIZE);
+
+        // Less memory than fits below the hole.
+        let regions = layout.memory_regions(1 << 31);
+        assert_eq!(regions, vec![(GuestAddress(0), 1usize << 31)]);
+        assert_eq!(layout.mmio64_start(1 << 31), 1 << 32);
+
+        // More memory than fits below the hole.
+        let mem_size = (8usize << 30) + (1 << 20);
+        let regions = layout.memory_regions(mem_size);
+        assert_eq!(
+            regions,
+            vec![
+                (GuestAddress(0), layout.mmio32_start() as usize),
+                (
+                    GuestAddress(1 << 32),
+                    mem_size - layout.mmio32_start() as usize
+                ),
+            ]
+        );
+        let mem_end = regions[1].0.raw_value() + regions[1].1 as u64;
+        let mmio64_start = layout.mmio64_start(mem_size);
+        assert!(mmio64_start >= mem_end);
+        assert_eq!(mmio64_start % MMIO64_ALIGNMENT, 0);
+        assert_eq!(mmio64_start, 10 << 30);
+        assert_eq!(layout.mmio_devices_start(mem_size), mmio64_start);
+
+        // The largest memory ends right at the end of the address space.
+        let max_mem_size = layout.max_memory_size(MMIO64_MEM_END);
+        assert_eq!(layout.mmio64_start(max_mem_size as usize), MMIO64_MEM_END);
+        assert!(layout.mmio64_start(max_mem_size as usize + 1) > MMIO64_MEM_END);
+    }
+
+    #[test]
+    fn test_guest_phys_addr_end() {
+        let addr_end = guest_phys_addr_end();
+        assert!(addr_end >= MMIO64_MEM_END);
+        assert!(addr_end.is_power_of_two());
+    }
+
     #[test]
     fn test_system_configuration() {
         let no_vcpus = 4;
         let gm = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
-        let config_err = configure_system(&gm, GuestAddress(0), 0, &None, 1);
+        let config_err = configure_system(&gm, GuestAddress(0), 0, &None, 1, &[]);
         assert!(config_err.is_err());
         assert_eq!(
             config_err.unwrap_err(),
@@ -231,19 +376,36 @@ mod tests {
         let mem_size = 128 << 20;
         let arch_mem_regions = arch_memory_regions(mem_size);
         let gm = GuestMemoryMmap::from_ranges(&arch_mem_regions).unwrap();
-        configure_system(&gm, GuestAddress(0), 0, &None, no_vcpus).unwrap();
+        configure_system(&gm, GuestAddress(0), 0, &None, no_vcpus, &[]).unwrap();
 
         // Now assigning some memory that is equal to the start of the 32bit memory hole.
         let mem_size = 3328 << 20;
         let arch_mem_regions = arch_memory_regions(mem_size);
         let gm = GuestMemoryMmap::from_ranges(&arch_mem_regions).unwrap();
-        configure_system(&gm, GuestAddress(0), 0, &None, no_vcpus).unwrap();
+        configure_system(&gm, GuestAddress(0), 0, &None, no_vcpus, &[]).unwrap();
 
         // Now assigning some memory that falls after the 32bit memory hole.
         let mem_size = 3330 << 20;
         let arch_mem_regions = arch_memory_regions(mem_size);
         let gm = GuestMemoryMmap::from_ranges(&arch_mem_regions).unwrap();
-        configure_system(&gm, GuestAddress(0), 0, &None, no_vcpus).unwrap();
+        configure_system(&gm, GuestAddress(0), 0, &None, no_vcpus, &[]).unwrap();
+
+        // Now using a smaller hole, which the e820 map follows.
+        let layout = MemoryLayout {
+            mmio32_hole_size: MMIO32_HOLE_MIN_SIZE,
+            mmio64_window_size: 0,
+        };
+        let arch_mem_regions = layout.memory_regions(mem_size);
+        let gm = GuestMemoryMmap::from_ranges(&arch_mem_regions).unwrap();
+        configure_system(&gm, GuestAddress(0), 0, &None, no_vcpus, &[]).unwrap();
+        let params: BootParamsWrapper = gm.read_obj(GuestAddress(layout::ZERO_PAGE_START)).unwrap();
+        assert_eq!(params.0.e820_entries, 3);
+        // The e820 entries are packed, so copy the fields out before comparing them.
+        let (low_ram_addr, low_ram_size) = (params.0.e820_map[1].addr, params.0.e820_map[1].size);
+        assert_eq!(low_ram_addr + low_ram_size, layout.mmio32_start());
+        let (high_ram_addr, high_ram_size) = (params.0.e820_map[2].addr, params.0.e820_map[2].size);
+        assert_eq!(high_ram_addr, 1 << 32);
+        assert_eq!(high_ram_size, mem_size as u64 - layout.mmio32_start());
     }
 
     #[test]
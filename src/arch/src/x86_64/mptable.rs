@@ -80,6 +80,17 @@ pub type Result<T> = result::Result<T, Error>;
 // a large number for FC usecases.
 pub const MAX_SUPPORTED_CPUS: u32 = 254;
 
+/// The INTx interrupt of a device plugged in the PCI bus.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PciInterrupt {
+    /// The slot of the device on the PCI bus.
+    pub slot: u8,
+    /// The interrupt pin of the device, from 1 for INTA# to 4 for INTD#.
+    pub pin: u8,
+    /// The IOAPIC pin the interrupt is routed to.
+    pub gsi: u8,
+}
+
 // Convenience macro for making arrays of diverse character types.
 macro_rules! char_array {
     ($t:ty; $( $c:expr ),*) => ( [ $( $c as $t ),* ] )
@@ -92,6 +103,11 @@ const MPC_SPEC: i8 = 4;
 const MPC_OEM: [c_char; 8] = char_array!(c_char; 'F', 'C', ' ', ' ', ' ', ' ', ' ', ' ');
 const MPC_PRODUCT_ID: [c_char; 12] = ['0' as c_char; 12];
 const BUS_TYPE_ISA: [u8; 6] = char_array!(u8; 'I', 'S', 'A', ' ', ' ', ' ');
+const BUS_TYPE_PCI: [u8; 6] = char_array!(u8; 'P', 'C', 'I', ' ', ' ', ' ');
+const BUS_ID_ISA: u8 = 0;
+const BUS_ID_PCI: u8 = 1;
+// Level triggered, active high.
+const PCI_IRQ_FLAGS: u16 = 0x0d;
 const IO_APIC_DEFAULT_PHYS_BASE: u32 = 0xfec0_0000; // source: linux/arch/x86/include/asm/apicdef.h
 const APIC_DEFAULT_PHYS_BASE: u32 = 0xfee0_0000; // source: linux/arch/x86/include/asm/apicdef.h
 const APIC_VERSION: u8 = 0x14;
@@ -114,18 +130,29 @@ fn mpf_intel_compute_checksum(v: &mpspec::mpf_intel) -> u8 {
     (!checksum).wrapping_add(1)
 }
 
-fn compute_mp_size(num_cpus: u8) -> usize {
+fn compute_mp_size(num_cpus: u8, pci_interrupts: &[PciInterrupt]) -> usize {
+    let pci_bus_size = if pci_interrupts.is_empty() {
+        0
+    } else {
+        mem::size_of::<MpcBusWrapper>()
+    };
     mem::size_of::<MpfIntelWrapper>()
         + mem::size_of::<MpcTableWrapper>()
         + mem::size_of::<MpcCpuWrapper>() * (num_cpus as usize)
         + mem::size_of::<MpcIoapicWrapper>()
         + mem::size_of::<MpcBusWrapper>()
-        + mem::size_of::<MpcIntsrcWrapper>() * 16
+        + pci_bus_size
+        + mem::size_of::<MpcIntsrcWrapper>() * (16 + pci_interrupts.len())
         + mem::size_of::<MpcLintsrcWrapper>() * 2
 }
 
-/// Performs setup of the MP table for the given `num_cpus`.
-pub fn setup_mptable(mem: &GuestMemoryMmap, num_cpus: u8) -> Result<()> {
+/// Performs setup of the MP table for the given `num_cpus`, routing the INTx interrupts of the
+/// PCI devices as described by `pci_interrupts`.
+pub fn setup_mptable(
+    mem: &GuestMemoryMmap,
+    num_cpus: u8,
+    pci_interrupts: &[PciInterrupt],
+) -> Result<()> {
     if u32::from(num_cpus) > MAX_SUPPORTED_CPUS {
         return Err(Error::TooManyCpus);
     }
@@ -133,10 +160,11 @@ pub fn setup_mptable(mem: &GuestMemoryMmap, num_cpus: u8) -> Result<()> {
     // Used to keep track of the next base pointer into the MP table.
     let mut base_mp = GuestAddress(MPTABLE_START);
 
-    let mp_size = compute_mp_size(num_cpus);
+    let mp_size = compute_mp_size(num_cpus, pci_interrupts);
 
     let mut checksum: u8 = 0;
-    let ioapicid: u8 = num_cpus + 1;
+    // The IOAPIC takes the first APIC ID after the vCPUs, which stays below the broadcast ID.
+    let ioapicid: u8 = num_cpus;
 
     // The checked_add here ensures the all of the following base_mp.unchecked_add's will be without
     // overflow.
@@ -194,13 +222,24 @@ pub fn setup_mptable(mem: &GuestMemoryMmap, num_cpus: u8) -> Result<()> {
         let size = mem::size_of::<MpcBusWrapper>() as u64;
         let mut mpc_bus = MpcBusWrapper(mpspec::mpc_bus::default());
         mpc_bus.0.type_ = mpspec::MP_BUS as u8;
-        mpc_bus.0.busid = 0;
+        mpc_bus.0.busid = BUS_ID_ISA;
         mpc_bus.0.bustype = BUS_TYPE_ISA;
         mem.write_obj(mpc_bus, base_mp)
             .map_err(|_| Error::WriteMpcBus)?;
         base_mp = base_mp.unchecked_add(size);
         checksum = checksum.wrapping_add(compute_checksum(&mpc_bus.0));
     }
+    if !pci_interrupts.is_empty() {
+        let size = mem::size_of::<MpcBusWrapper>() as u64;
+        let mut mpc_bus = MpcBusWrapper(mpspec::mpc_bus::default());
+        mpc_bus.0.type_ = mpspec::MP_BUS as u8;
+        mpc_bus.0.busid = BUS_ID_PCI;
+        mpc_bus.0.bustype = BUS_TYPE_PCI;
+        mem.write_obj(mpc_bus, base_mp)
+            .map_err(|_| Error::WriteMpcBus)?;
+        base_mp = base_mp.unchecked_add(size);
+        checksum = checksum.wrapping_add(compute_checksum(&mpc_bus.0));
+    }
     {
         let size = mem::size_of::<MpcIoapicWrapper>() as u64;
         let mut mpc_ioapic = MpcIoapicWrapper(mpspec::mpc_ioapic::default());
@@ -221,7 +260,7 @@ pub fn setup_mptable(mem: &GuestMemoryMmap, num_cpus: u8) -> Result<()> {
         mpc_intsrc.0.type_ = mpspec::MP_INTSRC as u8;
         mpc_intsrc.0.irqtype = mpspec::mp_irq_source_types_mp_INT as u8;
         mpc_intsrc.0.irqflag = mpspec::MP_IRQDIR_DEFAULT as u16;
-        mpc_intsrc.0.srcbus = 0;
+        mpc_intsrc.0.srcbus = BUS_ID_ISA;
         mpc_intsrc.0.srcbusirq = i;
         mpc_intsrc.0.dstapic = ioapicid;
         mpc_intsrc.0.dstirq = i;
@@ -230,6 +269,23 @@ pub fn setup_mptable(mem: &GuestMemoryMmap, num_cpus: u8) -> Result<()> {
         base_mp = base_mp.unchecked_add(size);
         checksum = checksum.wrapping_add(compute_checksum(&mpc_intsrc.0));
     }
+    for pci_interrupt in pci_interrupts {
+        let size = mem::size_of::<MpcIntsrcWrapper>() as u64;
+        let mut mpc_intsrc = MpcIntsrcWrapper(mpspec::mpc_intsrc::default());
+        mpc_intsrc.0.type_ = mpspec::MP_INTSRC as u8;
+        mpc_intsrc.0.irqtype = mpspec::mp_irq_source_types_mp_INT as u8;
+        mpc_intsrc.0.irqflag = PCI_IRQ_FLAGS;
+        mpc_intsrc.0.srcbus = BUS_ID_PCI;
+        // The PCI bus IRQ holds the slot of the device, and its pin starting from 0 for INTA#.
+        mpc_intsrc.0.srcbusirq =
+            (pci_interrupt.slot << 2) | (pci_interrupt.pin.wrapping_sub(1) & 0x3);
+        mpc_intsrc.0.dstapic = ioapicid;
+        mpc_intsrc.0.dstirq = pci_interrupt.gsi;
+        mem.write_obj(mpc_intsrc, base_mp)
+            .map_err(|_| Error::WriteMpcIntsrc)?;
+        base_mp = base_mp.unchecked_add(size);
+        checksum = checksum.wrapping_add(compute_checksum(&mpc_intsrc.0));
+    }
     {
         let size = mem::size_of::<MpcLintsrcWrapper>() as u64;
         let mut mpc_lintsrc = MpcLintsrcWrapper(mpspec::mpc_lintsrc::default());
@@ -304,11 +360,11 @@ mod tests {
         let num_cpus = 4;
         let mem = GuestMemoryMmap::from_ranges(&[(
             GuestAddress(MPTABLE_START),
-            compute_mp_size(num_cpus),
+            compute_mp_size(num_cpus, &[]),
         )])
         .unwrap();
 
-        setup_mptable(&mem, num_cpus).unwrap();
+        setup_mptable(&mem, num_cpus, &[]).unwrap();
     }
 
     #[test]
@@ -316,11 +372,11 @@ mod tests {
         let num_cpus = 4;
         let mem = GuestMemoryMmap::from_ranges(&[(
             GuestAddress(MPTABLE_START),
-            compute_mp_size(num_cpus) - 1,
+            compute_mp_size(num_cpus, &[]) - 1,
         )])
         .unwrap();
 
-        assert!(setup_mptable(&mem, num_cpus).is_err());
+        assert!(setup_mptable(&mem, num_cpus, &[]).is_err());
     }
 
     #[test]
@@ -328,11 +384,11 @@ mod tests {
         let num_cpus = 1;
         let mem = GuestMemoryMmap::from_ranges(&[(
             GuestAddress(MPTABLE_START),
-            compute_mp_size(num_cpus),
+            compute_mp_size(num_cpus, &[]),
         )])
         .unwrap();
 
-        setup_mptable(&mem, num_cpus).unwrap();
+        setup_mptable(&mem, num_cpus, &[]).unwrap();
 
         let mpf_intel: MpfIntelWrapper = mem.read_obj(GuestAddress(MPTABLE_START)).unwrap();
 
@@ -347,11 +403,11 @@ mod tests {
         let num_cpus = 4;
         let mem = GuestMemoryMmap::from_ranges(&[(
             GuestAddress(MPTABLE_START),
-            compute_mp_size(num_cpus),
+            compute_mp_size(num_cpus, &[]),
         )])
         .unwrap();
 
-        setup_mptable(&mem, num_cpus).unwrap();
+        setup_mptable(&mem, num_cpus, &[]).unwrap();
 
         let mpf_intel: MpfIntelWrapper = mem.read_obj(GuestAddress(MPTABLE_START)).unwrap();
         let mpc_offset = GuestAddress(u64::from(mpf_intel.0.physptr));
@@ -380,12 +436,12 @@ mod tests {
     fn cpu_entry_count() {
         let mem = GuestMemoryMmap::from_ranges(&[(
             GuestAddress(MPTABLE_START),
-            compute_mp_size(MAX_SUPPORTED_CPUS as u8),
+            compute_mp_size(MAX_SUPPORTED_CPUS as u8, &[]),
         )])
         .unwrap();
 
         for i in 0..MAX_SUPPORTED_CPUS as u8 {
-            setup_mptable(&mem, i).unwrap();
+            setup_mptable(&mem, i, &[]).unwrap();
 
             let mpf_intel: MpfIntelWrapper = mem.read_obj(GuestAddress(MPTABLE_START)).unwrap();
             let mpc_offset = GuestAddress(u64::from(mpf_intel.0.physptr));
@@ -412,16 +468,115 @@ mod tests {
         }
     }
 
+    #[test]
+    fn ioapic_id() {
+        let num_cpus = MAX_SUPPORTED_CPUS as u8;
+        let mem = GuestMemoryMmap::from_ranges(&[(
+            GuestAddress(MPTABLE_START),
+            compute_mp_size(num_cpus, &[]),
+        )])
+        .unwrap();
+
+        setup_mptable(&mem, num_cpus, &[]).unwrap();
+
+        let mpf_intel: MpfIntelWrapper = mem.read_obj(GuestAddress(MPTABLE_START)).unwrap();
+        // The IOAPIC entry follows the CPU and bus entries.
+        let ioapic_offset = GuestAddress(u64::from(mpf_intel.0.physptr))
+            .checked_add(
+                (mem::size_of::<MpcTableWrapper>()
+                    + mem::size_of::<MpcCpuWrapper>() * num_cpus as usize
+                    + mem::size_of::<MpcBusWrapper>()) as u64,
+            )
+            .unwrap();
+        let mpc_ioapic: MpcIoapicWrapper = mem.read_obj(ioapic_offset).unwrap();
+        assert_eq!(u32::from(mpc_ioapic.0.type_), mpspec::MP_IOAPIC);
+        assert_eq!(mpc_ioapic.0.apicid, num_cpus);
+        assert_ne!(mpc_ioapic.0.apicid, 0xff);
+    }
+
     #[test]
     fn cpu_entry_count_max() {
         let cpus = MAX_SUPPORTED_CPUS + 1;
         let mem = GuestMemoryMmap::from_ranges(&[(
             GuestAddress(MPTABLE_START),
-            compute_mp_size(cpus as u8),
+            compute_mp_size(cpus as u8, &[]),
         )])
         .unwrap();
 
-        let result = setup_mptable(&mem, cpus as u8).unwrap_err();
+        let result = setup_mptable(&mem, cpus as u8, &[]).unwrap_err();
         assert_eq!(result, Error::TooManyCpus);
     }
+
+    #[test]
+    fn pci_interrupts() {
+        let num_cpus = 2;
+        let pci_interrupts = [
+            PciInterrupt {
+                slot: 1,
+                pin: 1,
+                gsi: 16,
+            },
+            PciInterrupt {
+                slot: 2,
+                pin: 2,
+                gsi: 17,
+            },
+        ];
+        let mem = GuestMemoryMmap::from_ranges(&[(
+            GuestAddress(MPTABLE_START),
+            compute_mp_size(num_cpus, &pci_interrupts),
+        )])
+        .unwrap();
+        assert!(setup_mptable(&mem, num_cpus, &pci_interrupts).is_ok());
+
+        let mpf_intel: MpfIntelWrapper = mem.read_obj(GuestAddress(MPTABLE_START)).unwrap();
+        let mpc_offset = GuestAddress(u64::from(mpf_intel.0.physptr));
+        let mpc_table: MpcTableWrapper = mem.read_obj(mpc_offset).unwrap();
+        let mpc_end = mpc_offset
+            .checked_add(u64::from(mpc_table.0.length))
+            .unwrap();
+
+        let mut entry_offset = mpc_offset
+            .checked_add(mem::size_of::<MpcTableWrapper>() as u64)
+            .unwrap();
+        let mut bus_types = Vec::new();
+        let mut pci_intsrcs = Vec::new();
+        while entry_offset < mpc_end {
+            let entry_type: u8 = mem.read_obj(entry_offset).unwrap();
+            match u32::from(entry_type) {
+                mpspec::MP_BUS => {
+                    let mpc_bus: MpcBusWrapper = mem.read_obj(entry_offset).unwrap();
+                    bus_types.push((mpc_bus.0.busid, mpc_bus.0.bustype));
+                }
+                mpspec::MP_INTSRC => {
+                    let mpc_intsrc: MpcIntsrcWrapper = mem.read_obj(entry_offset).unwrap();
+                    if mpc_intsrc.0.srcbus == BUS_ID_PCI {
+                        pci_intsrcs.push((mpc_intsrc.0.srcbusirq, mpc_intsrc.0.dstirq));
+                        assert_eq!(mpc_intsrc.0.irqflag, PCI_IRQ_FLAGS);
+                    }
+                }
+                _ => (),
+            }
+            entry_offset = entry_offset
+                .checked_add(table_entry_size(entry_type) as u64)
+                .unwrap();
+        }
+        assert_eq!(entry_offset, mpc_end);
+        assert_eq!(
+            bus_types,
+            vec![(BUS_ID_ISA, BUS_TYPE_ISA), (BUS_ID_PCI, BUS_TYPE_PCI)]
+        );
+        assert_eq!(pci_intsrcs, vec![(1 << 2, 16), ((2 << 2) | 1, 17)]);
+
+        // The PCI entries need room in the guest memory.
+        let mem = GuestMemoryMmap::from_ranges(&[(
+            GuestAddress(MPTABLE_START),
+            compute_mp_size(num_cpus, &[]),
+        )])
+        .unwrap();
+        assert_eq!(
+            setup_mptable(&mem, num_cpus, &pci_interrupts).unwrap_err(),
+            Error::NotEnoughMemory
+        );
+    }
 }
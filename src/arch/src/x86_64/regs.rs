@@ -86,11 +86,14 @@ pub fn setup_regs(vcpu: &VcpuFd, boot_ip: u64) -> Result<()> {
 ///
 /// * `mem` - The memory that will be passed to the guest.
 /// * `vcpu` - Structure for the VCPU that holds the VCPU's fd.
-pub fn setup_sregs(mem: &GuestMemoryMmap, vcpu: &VcpuFd) -> Result<()> {
+/// * `encryption_mask` - The bit set in the page table entries to map the memory as encrypted,
+///                       for SEV guests, or 0.
+pub fn setup_sregs(mem: &GuestMemoryMmap, vcpu: &VcpuFd, encryption_mask: u64) -> Result<()> {
     let mut sregs: kvm_sregs = vcpu.get_sregs().map_err(Error::GetStatusRegisters)?;
 
     configure_segments_and_sregs(mem, &mut sregs)?;
-    setup_page_tables(mem, &mut sregs)?; // TODO(dgreid) - Can this be done once per system instead?
+    // TODO(dgreid) - Can this be done once per system instead?
+    setup_page_tables(mem, &mut sregs, encryption_mask)?;
 
     vcpu.set_sregs(&sregs).map_err(Error::SetStatusRegisters)
 }
@@ -163,27 +166,42 @@ fn configure_segments_and_sregs(mem: &GuestMemoryMmap, sregs: &mut kvm_sregs) ->
     Ok(())
 }
 
-fn setup_page_tables(mem: &GuestMemoryMmap, sregs: &mut kvm_sregs) -> Result<()> {
+// The page tables of an SEV guest have `encryption_mask` set in each entry, as well as in CR3,
+// since the page tables themselves are encrypted along with the rest of the guest memory.
+fn setup_page_tables(
+    mem: &GuestMemoryMmap,
+    sregs: &mut kvm_sregs,
+    encryption_mask: u64,
+) -> Result<()> {
     // Puts PML4 right after zero page but aligned to 4k.
     let boot_pml4_addr = GuestAddress(PML4_START);
     let boot_pdpte_addr = GuestAddress(PDPTE_START);
     let boot_pde_addr = GuestAddress(PDE_START);
 
     // Entry covering VA [0..512GB)
-    mem.write_obj(boot_pdpte_addr.raw_value() as u64 | 0x03, boot_pml4_addr)
-        .map_err(|_| Error::WritePML4Address)?;
+    mem.write_obj(
+        boot_pdpte_addr.raw_value() as u64 | 0x03 | encryption_mask,
+        boot_pml4_addr,
+    )
+    .map_err(|_| Error::WritePML4Address)?;
 
     // Entry covering VA [0..1GB)
-    mem.write_obj(boot_pde_addr.raw_value() as u64 | 0x03, boot_pdpte_addr)
-        .map_err(|_| Error::WritePDPTEAddress)?;
+    mem.write_obj(
+        boot_pde_addr.raw_value() as u64 | 0x03 | encryption_mask,
+        boot_pdpte_addr,
+    )
+    .map_err(|_| Error::WritePDPTEAddress)?;
     // 512 2MB entries together covering VA [0..1GB). Note we are assuming
     // CPU supports 2MB pages (/proc/cpuinfo has 'pse'). All modern CPUs do.
     for i in 0..512 {
-        mem.write_obj((i << 21) + 0x83u64, boot_pde_addr.unchecked_add(i * 8))
-            .map_err(|_| Error::WritePDEAddress)?;
+        mem.write_obj(
+            ((i << 21) + 0x83u64) | encryption_mask,
+            boot_pde_addr.unchecked_add(i * 8),
+        )
+        .map_err(|_| Error::WritePDEAddress)?;
     }
 
-    sregs.cr3 = boot_pml4_addr.raw_value() as u64;
+    sregs.cr3 = boot_pml4_addr.raw_value() as u64 | encryption_mask;
     sregs.cr4 |= X86_CR4_PAE;
     sregs.cr0 |= X86_CR0_PG;
     Ok(())
@@ -233,14 +251,17 @@ mod tests {
         validate_segments_and_sregs(&gm, &sregs);
     }
 
-    fn validate_page_tables(gm: &GuestMemoryMmap, sregs: &kvm_sregs) {
-        assert_eq!(0xa003, read_u64(&gm, PML4_START));
-        assert_eq!(0xb003, read_u64(&gm, PDPTE_START));
+    fn validate_page_tables(gm: &GuestMemoryMmap, sregs: &kvm_sregs, encryption_mask: u64) {
+        assert_eq!(0xa003 | encryption_mask, read_u64(&gm, PML4_START));
+        assert_eq!(0xb003 | encryption_mask, read_u64(&gm, PDPTE_START));
         for i in 0..512 {
-            assert_eq!((i << 21) + 0x83u64, read_u64(&gm, PDE_START + (i * 8)));
+            assert_eq!(
+                ((i << 21) + 0x83u64) | encryption_mask,
+                read_u64(&gm, PDE_START + (i * 8))
+            );
         }
 
-        assert_eq!(PML4_START as u64, sregs.cr3);
+        assert_eq!(PML4_START as u64 | encryption_mask, sregs.cr3);
         assert!(sregs.cr4 & X86_CR4_PAE != 0);
         assert!(sregs.cr0 & X86_CR0_PG != 0);
     }
@@ -249,9 +270,13 @@ mod tests {
     fn test_setup_page_tables() {
         let mut sregs: kvm_sregs = Default::default();
         let gm = create_guest_mem();
-        setup_page_tables(&gm, &mut sregs).unwrap();
+        setup_page_tables(&gm, &mut sregs, 0).unwrap();
+        validate_page_tables(&gm, &sregs, 0);
 
-        validate_page_tables(&gm, &sregs);
+        // The page tables of an SEV guest map the memory as encrypted.
+        let mut sregs: kvm_sregs = Default::default();
+        setup_page_tables(&gm, &mut sregs, 1 << 47).unwrap();
+        validate_page_tables(&gm, &sregs, 1 << 47);
     }
 
     #[test]
@@ -305,7 +330,7 @@ mod tests {
         let gm = create_guest_mem();
 
         assert!(vcpu.set_sregs(&Default::default()).is_ok());
-        setup_sregs(&gm, &vcpu).unwrap();
+        setup_sregs(&gm, &vcpu, 0).unwrap();
 
         let mut sregs: kvm_sregs = vcpu.get_sregs().unwrap();
         // for AMD KVM_GET_SREGS returns g = 0 for each kvm_segment.
@@ -313,6 +338,6 @@ mod tests {
         sregs.gs.g = 1;
 
         validate_segments_and_sregs(&gm, &sregs);
-        validate_page_tables(&gm, &sregs);
+        validate_page_tables(&gm, &sregs, 0);
     }
 }
@@ -34,7 +34,8 @@ pub mod leaf_0x1 {
         pub const MONITOR_BITINDEX: u32 = 3;
         // CPL Qualified Debug Store
         pub const DS_CPL_SHIFT: u32 = 4;
-        // 5 = VMX (Virtual Machine Extensions)
+        // VMX = Virtual Machine Extensions
+        pub const VMX_BITINDEX: u32 = 5;
         // 6 = SMX (Safer Mode Extensions)
         // 7 = EIST (Enhanced Intel SpeedStep® technology)
         // TM2 = Thermal Monitor 2
@@ -49,9 +50,19 @@ pub mod leaf_0x1 {
         // PDCM = Perfmon and Debug Capability
         pub const PDCM_BITINDEX: u32 = 15;
         // 18 = DCA Direct Cache Access (prefetch data from a memory mapped device)
+        // X2APIC = x2APIC mode of the local APIC
+        pub const X2APIC_BITINDEX: u32 = 21;
         pub const MOVBE_BITINDEX: u32 = 22;
         pub const TSC_DEADLINE_TIMER_BITINDEX: u32 = 24;
+        // AES = AES instruction set
+        pub const AES_BITINDEX: u32 = 25;
         pub const OSXSAVE_BITINDEX: u32 = 27;
+        // AVX = Advanced Vector Extensions
+        pub const AVX_BITINDEX: u32 = 28;
+        // F16C = 16-bit floating-point conversion instructions
+        pub const F16C_BITINDEX: u32 = 29;
+        // RDRAND = RDRAND instruction
+        pub const RDRAND_BITINDEX: u32 = 30;
         // Cpu is running on a hypervisor.
         pub const HYPERVISOR_BITINDEX: u32 = 31;
     }
@@ -171,9 +182,20 @@ pub mod leaf_0x7 {
             // OSPKE = If 1, OS has set CR4.PKE to enable protection keys
             pub const OSPKE_BITINDEX: u32 = 4;
             // 5 = WAITPKG
-            // 7-6 reserved
-            // 8 = GFNI
-            // 13-09 reserved
+            // AVX512_VBMI2 = AVX-512 Vector Byte Manipulation Instructions 2
+            pub const AVX512_VBMI2_BITINDEX: u32 = 6;
+            // 7 = CET_SS (Control-flow Enforcement Technology Shadow Stack)
+            // GFNI = Galois Field instructions
+            pub const GFNI_BITINDEX: u32 = 8;
+            // VAES = Vector AES instructions
+            pub const VAES_BITINDEX: u32 = 9;
+            // VPCLMULQDQ = Carry-less multiplication on vectors
+            pub const VPCLMULQDQ_BITINDEX: u32 = 10;
+            // AVX512_VNNI = AVX-512 Vector Neural Network Instructions
+            pub const AVX512_VNNI_BITINDEX: u32 = 11;
+            // AVX512_BITALG = AVX-512 Bit Algorithms
+            pub const AVX512_BITALG_BITINDEX: u32 = 12;
+            // 13 reserved
             // AVX512_VPOPCNTDQ = Vector population count instruction (Intel® Xeon Phi™ only.)
             pub const AVX512_VPOPCNTDQ_BITINDEX: u32 = 14;
             // 21 - 17 = The value of MAWAU used by the BNDLDX and BNDSTX instructions in 64-bit mode.
@@ -239,6 +261,7 @@ pub mod leaf_0xd {
         pub mod eax {
             use bit_helper::BitRange;
 
+            pub const AVX_STATE_BITINDEX: u32 = 2;
             pub const MPX_STATE_BITRANGE: BitRange = bit_range!(4, 3);
             pub const AVX512_STATE_BITRANGE: BitRange = bit_range!(7, 5);
         }
@@ -253,6 +276,11 @@ pub mod leaf_0xd {
     }
 }
 
+// Time Stamp Counter and Nominal Core Crystal Clock Information Leaf
+pub mod leaf_0x15 {
+    pub const LEAF_NUM: u32 = 0x15;
+}
+
 pub mod leaf_0x80000000 {
     pub const LEAF_NUM: u32 = 0x8000_0000;
 
@@ -269,17 +297,57 @@ pub mod leaf_0x80000001 {
     pub mod ecx {
         pub const TOPOEXT_INDEX: u32 = 22;
         pub const PREFETCH_BITINDEX: u32 = 8; // 3DNow! PREFETCH/PREFETCHW instructions
+        pub const SVM_BITINDEX: u32 = 2; // secure virtual machine
         pub const LZCNT_BITINDEX: u32 = 5; // advanced bit manipulation
+        pub const SSE4A_BITINDEX: u32 = 6;
+        pub const MISALIGN_SSE_BITINDEX: u32 = 7; // misaligned SSE mode
+        pub const OSVW_BITINDEX: u32 = 9; // OS visible workaround
+        pub const IBS_BITINDEX: u32 = 10; // instruction based sampling
+        pub const XOP_BITINDEX: u32 = 11;
+        pub const SKINIT_BITINDEX: u32 = 12;
+        pub const WDT_BITINDEX: u32 = 13; // watchdog timer
+        pub const LWP_BITINDEX: u32 = 15; // lightweight profiling
+        pub const FMA4_BITINDEX: u32 = 16;
+        pub const TBM_BITINDEX: u32 = 21; // trailing bit manipulation
+        pub const PERFCTR_CORE_BITINDEX: u32 = 23; // core performance counter extensions
+        pub const PERFCTR_NB_BITINDEX: u32 = 24; // NB performance counter extensions
+        pub const DBX_BITINDEX: u32 = 26; // data breakpoint extensions
+        pub const PERFTSC_BITINDEX: u32 = 27; // performance TSC
+        pub const PCX_L2I_BITINDEX: u32 = 28; // L2I performance counter extensions
+        pub const MWAITX_BITINDEX: u32 = 29;
     }
 
     pub mod edx {
+        pub const MMXEXT_BITINDEX: u32 = 22; // AMD extensions to MMX instructions
+        pub const FFXSR_BITINDEX: u32 = 25; // FXSAVE/FXRSTOR optimizations
         pub const PDPE1GB_BITINDEX: u32 = 26; // 1-GByte pages are available if 1.
+        pub const AMD_3DNOWEXT_BITINDEX: u32 = 30; // AMD extensions to 3DNow! instructions
+        pub const AMD_3DNOW_BITINDEX: u32 = 31; // 3DNow! instructions
+    }
+}
+
+// Advanced Power Management Information Leaf
+pub mod leaf_0x80000007 {
+    pub const LEAF_NUM: u32 = 0x8000_0007;
+
+    pub mod edx {
+        // The TSC ticks at a constant rate in all the ACPI P-, C- and T-states.
+        pub const INVARIANT_TSC_BITINDEX: u32 = 8;
     }
 }
 
 pub mod leaf_0x80000008 {
     pub const LEAF_NUM: u32 = 0x8000_0008;
 
+    pub mod ebx {
+        pub const CLZERO_BITINDEX: u32 = 0;
+        pub const IRPERF_BITINDEX: u32 = 1; // instructions retired count
+        pub const XSAVEERPTR_BITINDEX: u32 = 2; // FXSAVE/XSAVE always save the error pointers
+        pub const RDPRU_BITINDEX: u32 = 4;
+        pub const MCOMMIT_BITINDEX: u32 = 8;
+        pub const WBNOINVD_BITINDEX: u32 = 9;
+    }
+
     pub mod ecx {
         use bit_helper::BitRange;
 
@@ -0,0 +1,433 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use bit_helper::{BitHelper, BitRangeExt};
+use cpu_leaf::leaf_0x1::ecx as ecx1;
+use cpu_leaf::leaf_0x7::index0::{ebx as ebx7, ecx as ecx7, edx as edx7};
+use cpu_leaf::*;
+use kvm_bindings::{kvm_cpuid_entry2, CpuId};
+use transformer::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Register {
+    Ebx,
+    Ecx,
+    Edx,
+}
+
+/// A host CPU feature which can be exposed to the guest on top of the CPU template.
+#[derive(Debug, PartialEq)]
+pub struct CpuFeature {
+    name: &'static str,
+    leaf: u32,
+    index: u32,
+    register: Register,
+    bit: u32,
+    // The XSAVE state components the feature needs, as a mask of the XCR0 bits.
+    xsave_components: u32,
+}
+
+impl CpuFeature {
+    /// Returns the name of the feature, as listed in `/proc/cpuinfo`.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns whether the feature is enabled in `cpuid`.
+    pub fn is_enabled(&self, cpuid: &CpuId) -> bool {
+        find_entry(cpuid.as_slice(), self.leaf, self.index).map_or(false, |entry| {
+            let value = match self.register {
+                Register::Ebx => entry.ebx,
+                Register::Ecx => entry.ecx,
+                Register::Edx => entry.edx,
+            };
+            value.read_bit(self.bit)
+        })
+    }
+
+    fn set(&self, cpuid: &mut CpuId, enabled: bool) {
+        if let Some(entry) = find_entry_mut(cpuid.as_mut_slice(), self.leaf, self.index) {
+            let value = match self.register {
+                Register::Ebx => &mut entry.ebx,
+                Register::Ecx => &mut entry.ecx,
+                Register::Edx => &mut entry.edx,
+            };
+            value.write_bit(self.bit, enabled);
+        }
+    }
+}
+
+fn find_entry(entries: &[kvm_cpuid_entry2], leaf: u32, index: u32) -> Option<&kvm_cpuid_entry2> {
+    entries
+        .iter()
+        .find(|entry| entry.function == leaf && entry.index == index)
+}
+
+fn find_entry_mut(
+    entries: &mut [kvm_cpuid_entry2],
+    leaf: u32,
+    index: u32,
+) -> Option<&mut kvm_cpuid_entry2> {
+    entries
+        .iter_mut()
+        .find(|entry| entry.function == leaf && entry.index == index)
+}
+
+macro_rules! cpu_feature {
+    ($name:expr, $leaf:path, $register:ident, $bit:expr) => {
+        cpu_feature!($name, $leaf, $register, $bit, 0)
+    };
+    ($name:expr, $leaf:path, $register:ident, $bit:expr, $xsave_components:expr) => {
+        CpuFeature {
+            name: $name,
+            leaf: $leaf,
+            index: 0,
+            register: Register::$register,
+            bit: $bit,
+            xsave_components: $xsave_components,
+        }
+    };
+}
+
+const LEAF_1: u32 = leaf_0x1::LEAF_NUM;
+const LEAF_7: u32 = leaf_0x7::LEAF_NUM;
+
+const AVX_STATE: u32 = 1 << leaf_0xd::index0::eax::AVX_STATE_BITINDEX;
+// The AVX-512 opmask and upper ZMM state components (`AVX512_STATE_BITRANGE` of leaf 0xD), on
+// top of the AVX state.
+const AVX512_STATE: u32 = AVX_STATE | (0b111 << 5);
+
+/// The host CPU features controlled through `set_cpu_features`.
+#[rustfmt::skip]
+pub static CPU_FEATURES: &[CpuFeature] = &[
+    cpu_feature!("fma", LEAF_1, Ecx, ecx1::FMA_BITINDEX, AVX_STATE),
+    cpu_feature!("movbe", LEAF_1, Ecx, ecx1::MOVBE_BITINDEX),
+    cpu_feature!("aes", LEAF_1, Ecx, ecx1::AES_BITINDEX),
+    cpu_feature!("avx", LEAF_1, Ecx, ecx1::AVX_BITINDEX, AVX_STATE),
+    cpu_feature!("f16c", LEAF_1, Ecx, ecx1::F16C_BITINDEX, AVX_STATE),
+    cpu_feature!("rdrand", LEAF_1, Ecx, ecx1::RDRAND_BITINDEX),
+    cpu_feature!("bmi1", LEAF_7, Ebx, ebx7::BMI1_BITINDEX),
+    cpu_feature!("avx2", LEAF_7, Ebx, ebx7::AVX2_BITINDEX, AVX_STATE),
+    cpu_feature!("bmi2", LEAF_7, Ebx, ebx7::BMI2_BITINDEX),
+    cpu_feature!("avx512f", LEAF_7, Ebx, ebx7::AVX512F_BITINDEX, AVX512_STATE),
+    cpu_feature!("avx512dq", LEAF_7, Ebx, ebx7::AVX512DQ_BITINDEX, AVX512_STATE),
+    cpu_feature!("rdseed", LEAF_7, Ebx, ebx7::RDSEED_BITINDEX),
+    cpu_feature!("adx", LEAF_7, Ebx, ebx7::ADX_BITINDEX),
+    cpu_feature!("avx512ifma", LEAF_7, Ebx, ebx7::AVX512IFMA_BITINDEX, AVX512_STATE),
+    cpu_feature!("clflushopt", LEAF_7, Ebx, ebx7::CLFLUSHOPT_BITINDEX),
+    cpu_feature!("clwb", LEAF_7, Ebx, ebx7::CLWB_BITINDEX),
+    cpu_feature!("avx512pf", LEAF_7, Ebx, ebx7::AVX512PF_BITINDEX, AVX512_STATE),
+    cpu_feature!("avx512er", LEAF_7, Ebx, ebx7::AVX512ER_BITINDEX, AVX512_STATE),
+    cpu_feature!("avx512cd", LEAF_7, Ebx, ebx7::AVX512CD_BITINDEX, AVX512_STATE),
+    cpu_feature!("sha_ni", LEAF_7, Ebx, ebx7::SHA_BITINDEX),
+    cpu_feature!("avx512bw", LEAF_7, Ebx, ebx7::AVX512BW_BITINDEX, AVX512_STATE),
+    cpu_feature!("avx512vl", LEAF_7, Ebx, ebx7::AVX512VL_BITINDEX, AVX512_STATE),
+    cpu_feature!("avx512vbmi", LEAF_7, Ecx, ecx7::AVX512_VBMI_BITINDEX, AVX512_STATE),
+    cpu_feature!("avx512_vbmi2", LEAF_7, Ecx, ecx7::AVX512_VBMI2_BITINDEX, AVX512_STATE),
+    cpu_feature!("gfni", LEAF_7, Ecx, ecx7::GFNI_BITINDEX),
+    cpu_feature!("vaes", LEAF_7, Ecx, ecx7::VAES_BITINDEX, AVX_STATE),
+    cpu_feature!("vpclmulqdq", LEAF_7, Ecx, ecx7::VPCLMULQDQ_BITINDEX, AVX_STATE),
+    cpu_feature!("avx512_vnni", LEAF_7, Ecx, ecx7::AVX512_VNNI_BITINDEX, AVX512_STATE),
+    cpu_feature!("avx512_bitalg", LEAF_7, Ecx, ecx7::AVX512_BITALG_BITINDEX, AVX512_STATE),
+    cpu_feature!("avx512_vpopcntdq", LEAF_7, Ecx, ecx7::AVX512_VPOPCNTDQ_BITINDEX, AVX512_STATE),
+    cpu_feature!("rdpid", LEAF_7, Ecx, ecx7::RDPID_BITINDEX),
+    cpu_feature!("avx512_4vnniw", LEAF_7, Edx, edx7::AVX512_4VNNIW_BITINDEX, AVX512_STATE),
+    cpu_feature!("avx512_4fmaps", LEAF_7, Edx, edx7::AVX512_4FMAPS_BITINDEX, AVX512_STATE),
+];
+
+/// Looks up the feature called `name` among `CPU_FEATURES`.
+pub fn find_cpu_feature(name: &str) -> Option<&'static CpuFeature> {
+    CPU_FEATURES.iter().find(|feature| feature.name == name)
+}
+
+/// Exposes exactly `features` out of `CPU_FEATURES` in `cpuid`, hiding the other ones.
+///
+/// The features are taken from `supported_cpuid`, the CPUID supported by KVM on the host, along
+/// with the XSAVE state components they need. Fails if the host doesn't support one of them.
+pub fn set_cpu_features(
+    cpuid: &mut CpuId,
+    supported_cpuid: &CpuId,
+    features: &[&CpuFeature],
+) -> Result<(), Error> {
+    if let Some(feature) = features
+        .iter()
+        .find(|feature| !feature.is_enabled(supported_cpuid))
+    {
+        return Err(Error::UnsupportedCpuFeature(feature.name));
+    }
+
+    let mut xsave_components = 0;
+    for feature in CPU_FEATURES {
+        let enabled = features.contains(&feature);
+        feature.set(cpuid, enabled);
+        if enabled {
+            xsave_components |= feature.xsave_components;
+        }
+    }
+
+    // Only advertise the state components of the exposed features, so that the guest doesn't
+    // enable the other ones in XCR0.
+    let managed_components = AVX_STATE | leaf_0xd::index0::eax::AVX512_STATE_BITRANGE.get_mask();
+    let supported_components =
+        find_entry(supported_cpuid.as_slice(), leaf_0xd::LEAF_NUM, 0).map_or(0, |entry| entry.eax);
+    if let Some(entry) = find_entry_mut(cpuid.as_mut_slice(), leaf_0xd::LEAF_NUM, 0) {
+        entry.eax = (entry.eax & !managed_components)
+            | (supported_components & xsave_components & managed_components);
+    }
+
+    Ok(())
+}
+
+/// Exposes the hardware virtualization extensions (VMX or SVM) supported by the host to the
+/// guest when `enabled` is true, or hides them otherwise.
+///
+/// Returns whether the host supports nested virtualization, i.e. whether KVM reports one of
+/// the extensions in `supported_cpuid`.
+pub fn set_nested_virtualization(
+    cpuid: &mut CpuId,
+    supported_cpuid: &CpuId,
+    enabled: bool,
+) -> bool {
+    let extensions = [
+        (leaf_0x1::LEAF_NUM, leaf_0x1::ecx::VMX_BITINDEX),
+        (
+            leaf_0x80000001::LEAF_NUM,
+            leaf_0x80000001::ecx::SVM_BITINDEX,
+        ),
+    ];
+
+    let mut supported = false;
+    for &(leaf, bit) in extensions.iter() {
+        let host_enabled = find_entry(supported_cpuid.as_slice(), leaf, 0)
+            .map_or(false, |entry| entry.ecx.read_bit(bit));
+        supported |= host_enabled;
+        if let Some(entry) = find_entry_mut(cpuid.as_mut_slice(), leaf, 0) {
+            entry.ecx.write_bit(bit, enabled && host_enabled);
+        }
+    }
+
+    supported
+}
+
+/// Exposes the invariant TSC of the host to the guest when `enabled` is true, or hides it
+/// otherwise. The TSC only stays invariant across snapshots when its frequency is pinned, so
+/// it should only be enabled along with a fixed TSC frequency. The TSC frequency leaf is then
+/// cleared, as it describes the TSC of the host rather than the one scaled by KVM, which makes
+/// the guest calibrate the TSC instead.
+///
+/// Returns whether the host supports the invariant TSC, i.e. whether KVM reports it in
+/// `supported_cpuid`.
+pub fn set_invariant_tsc(cpuid: &mut CpuId, supported_cpuid: &CpuId, enabled: bool) -> bool {
+    let leaf = leaf_0x80000007::LEAF_NUM;
+    let bit = leaf_0x80000007::edx::INVARIANT_TSC_BITINDEX;
+    let supported = find_entry(supported_cpuid.as_slice(), leaf, 0)
+        .map_or(false, |entry| entry.edx.read_bit(bit));
+    if let Some(entry) = find_entry_mut(cpuid.as_mut_slice(), leaf, 0) {
+        entry.edx.write_bit(bit, enabled && supported);
+    }
+    if enabled {
+        if let Some(entry) = find_entry_mut(cpuid.as_mut_slice(), leaf_0x15::LEAF_NUM, 0) {
+            entry.eax = 0;
+            entry.ebx = 0;
+        }
+    }
+
+    supported
+}
+
+/// Exposes the TSC deadline mode of the LAPIC timer to the guest when `enabled` is true, or
+/// hides it otherwise, in which case the guest falls back to the one-shot mode. KVM doesn't
+/// report the TSC deadline mode in its supported CPUID, as it's emulated whenever KVM has
+/// `KVM_CAP_TSC_DEADLINE_TIMER`, so it can't be filtered like the other features.
+pub fn set_tsc_deadline_timer(cpuid: &mut CpuId, enabled: bool) {
+    if let Some(entry) = find_entry_mut(cpuid.as_mut_slice(), leaf_0x1::LEAF_NUM, 0) {
+        entry
+            .ecx
+            .write_bit(ecx1::TSC_DEADLINE_TIMER_BITINDEX, enabled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpuid_entry(function: u32, value: u32) -> kvm_cpuid_entry2 {
+        kvm_cpuid_entry2 {
+            function,
+            index: 0,
+            flags: 0,
+            eax: value,
+            ebx: value,
+            ecx: value,
+            edx: value,
+            padding: [0, 0, 0],
+        }
+    }
+
+    fn cpuid(value: u32) -> CpuId {
+        CpuId::from_entries(&[
+            cpuid_entry(leaf_0x1::LEAF_NUM, value),
+            cpuid_entry(leaf_0x7::LEAF_NUM, value),
+            cpuid_entry(leaf_0xd::LEAF_NUM, value),
+        ])
+    }
+
+    #[test]
+    fn test_find_cpu_feature() {
+        assert_eq!(find_cpu_feature("avx512f").unwrap().name(), "avx512f");
+        assert!(find_cpu_feature("avx1024").is_none());
+        // The feature names are unique.
+        for (i, feature) in CPU_FEATURES.iter().enumerate() {
+            assert!(CPU_FEATURES[i + 1..]
+                .iter()
+                .all(|other| other.name != feature.name));
+        }
+    }
+
+    #[test]
+    fn test_set_cpu_features() {
+        let supported_cpuid = cpuid(u32::max_value());
+        let avx512f = find_cpu_feature("avx512f").unwrap();
+        let avx2 = find_cpu_feature("avx2").unwrap();
+
+        // Expose all the features.
+        let mut guest_cpuid = cpuid(0);
+        let features: Vec<_> = CPU_FEATURES.iter().collect();
+        set_cpu_features(&mut guest_cpuid, &supported_cpuid, &features).unwrap();
+        assert!(CPU_FEATURES
+            .iter()
+            .all(|feature| feature.is_enabled(&guest_cpuid)));
+        assert_eq!(guest_cpuid.as_slice()[2].eax, 0b1110_0100);
+
+        // Hide all the features.
+        let mut guest_cpuid = cpuid(u32::max_value());
+        set_cpu_features(&mut guest_cpuid, &supported_cpuid, &[]).unwrap();
+        assert!(CPU_FEATURES
+            .iter()
+            .all(|feature| !feature.is_enabled(&guest_cpuid)));
+        assert_eq!(guest_cpuid.as_slice()[2].eax & 0b1110_0100, 0);
+        // The other feature bits are left alone.
+        assert!(guest_cpuid.as_slice()[0]
+            .ecx
+            .read_bit(leaf_0x1::ecx::HYPERVISOR_BITINDEX));
+
+        // Expose a single feature, along with its XSAVE state.
+        set_cpu_features(&mut guest_cpuid, &supported_cpuid, &[avx512f]).unwrap();
+        assert!(avx512f.is_enabled(&guest_cpuid));
+        assert!(!avx2.is_enabled(&guest_cpuid));
+        assert_eq!(guest_cpuid.as_slice()[2].eax & 0b1110_0100, 0b1110_0100);
+
+        // The host has to support the features.
+        let mut supported_cpuid = cpuid(u32::max_value());
+        avx512f.set(&mut supported_cpuid, false);
+        match set_cpu_features(&mut guest_cpuid, &supported_cpuid, &[avx2, avx512f]) {
+            Err(Error::UnsupportedCpuFeature("avx512f")) => (),
+            _ => panic!("Unsupported features should not be exposed."),
+        }
+    }
+
+    #[test]
+    fn test_set_nested_virtualization() {
+        let vmx_enabled = |cpuid: &CpuId| cpuid.as_slice()[0].ecx.read_bit(ecx1::VMX_BITINDEX);
+        let svm_enabled = |cpuid: &CpuId| {
+            cpuid.as_slice()[1]
+                .ecx
+                .read_bit(leaf_0x80000001::ecx::SVM_BITINDEX)
+        };
+        let cpuid = |value: u32| {
+            CpuId::from_entries(&[
+                cpuid_entry(leaf_0x1::LEAF_NUM, value),
+                cpuid_entry(leaf_0x80000001::LEAF_NUM, value),
+            ])
+        };
+
+        // Intel host with nested virtualization enabled.
+        let mut supported_cpuid = cpuid(0);
+        supported_cpuid.as_mut_slice()[0]
+            .ecx
+            .write_bit(ecx1::VMX_BITINDEX, true);
+        let mut guest_cpuid = cpuid(0);
+        assert!(set_nested_virtualization(
+            &mut guest_cpuid,
+            &supported_cpuid,
+            true
+        ));
+        assert!(vmx_enabled(&guest_cpuid));
+        assert!(!svm_enabled(&guest_cpuid));
+
+        assert!(set_nested_virtualization(
+            &mut guest_cpuid,
+            &supported_cpuid,
+            false
+        ));
+        assert!(!vmx_enabled(&guest_cpuid));
+        assert!(!svm_enabled(&guest_cpuid));
+        // The other bits are left alone.
+        let mut guest_cpuid = cpuid(u32::max_value());
+        set_nested_virtualization(&mut guest_cpuid, &supported_cpuid, false);
+        assert_eq!(guest_cpuid.as_slice()[0].ecx, !(1 << ecx1::VMX_BITINDEX));
+
+        // The extensions are hidden when the host doesn't support them.
+        let mut guest_cpuid = cpuid(u32::max_value());
+        assert!(!set_nested_virtualization(
+            &mut guest_cpuid,
+            &cpuid(0),
+            true
+        ));
+        assert!(!vmx_enabled(&guest_cpuid));
+        assert!(!svm_enabled(&guest_cpuid));
+    }
+
+    #[test]
+    fn test_set_invariant_tsc() {
+        let invariant_tsc = |cpuid: &CpuId| {
+            cpuid.as_slice()[1]
+                .edx
+                .read_bit(leaf_0x80000007::edx::INVARIANT_TSC_BITINDEX)
+        };
+        let cpuid = |value: u32| {
+            CpuId::from_entries(&[
+                cpuid_entry(leaf_0x15::LEAF_NUM, value),
+                cpuid_entry(leaf_0x80000007::LEAF_NUM, value),
+            ])
+        };
+
+        let supported_cpuid = cpuid(u32::max_value());
+        let mut guest_cpuid = cpuid(u32::max_value());
+        assert!(set_invariant_tsc(&mut guest_cpuid, &supported_cpuid, false));
+        assert!(!invariant_tsc(&guest_cpuid));
+        // The TSC frequency is left alone unless it's pinned.
+        assert_eq!(guest_cpuid.as_slice()[0].ebx, u32::max_value());
+
+        assert!(set_invariant_tsc(&mut guest_cpuid, &supported_cpuid, true));
+        assert!(invariant_tsc(&guest_cpuid));
+        assert_eq!(guest_cpuid.as_slice()[0].eax, 0);
+        assert_eq!(guest_cpuid.as_slice()[0].ebx, 0);
+        // The crystal clock frequency is left alone.
+        assert_eq!(guest_cpuid.as_slice()[0].ecx, u32::max_value());
+
+        // The invariant TSC is hidden when the host doesn't support it.
+        let mut guest_cpuid = cpuid(u32::max_value());
+        assert!(!set_invariant_tsc(&mut guest_cpuid, &cpuid(0), true));
+        assert!(!invariant_tsc(&guest_cpuid));
+    }
+
+    #[test]
+    fn test_set_tsc_deadline_timer() {
+        let mut guest_cpuid = cpuid(u32::max_value());
+        set_tsc_deadline_timer(&mut guest_cpuid, false);
+        assert_eq!(
+            guest_cpuid.as_slice()[0].ecx,
+            !(1 << ecx1::TSC_DEADLINE_TIMER_BITINDEX)
+        );
+        // The other leaves are left alone.
+        assert_eq!(guest_cpuid.as_slice()[1].ecx, u32::max_value());
+
+        let mut guest_cpuid = cpuid(0);
+        set_tsc_deadline_timer(&mut guest_cpuid, true);
+        assert_eq!(
+            guest_cpuid.as_slice()[0].ecx,
+            1 << ecx1::TSC_DEADLINE_TIMER_BITINDEX
+        );
+    }
+}
@@ -25,6 +25,7 @@ pub mod bit_helper;
 mod template;
 pub use template::c3;
 pub use template::t2;
+pub use template::t2a;
 
 mod cpu_leaf;
 
@@ -32,6 +33,15 @@ mod transformer;
 use transformer::*;
 pub use transformer::{Error, VmSpec};
 
+mod topology;
+pub use topology::{CpuTopology, MAX_CORES_PER_SOCKET, MAX_CPUS_PER_SOCKET};
+
+mod features;
+pub use features::{
+    find_cpu_feature, set_cpu_features, set_invariant_tsc, set_nested_virtualization,
+    set_tsc_deadline_timer, CpuFeature, CPU_FEATURES,
+};
+
 mod brand_string;
 
 /// Sets up the CPUID entries for the given vcpu.
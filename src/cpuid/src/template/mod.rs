@@ -5,3 +5,5 @@
 pub mod c3;
 /// Follows a T2 template in setting up the CPUID.
 pub mod t2;
+/// Follows a T2A template, the AMD equivalent of T2, in setting up the CPUID.
+pub mod t2a;
@@ -0,0 +1,249 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use bit_helper::BitHelper;
+use common::VENDOR_ID_AMD;
+use cpu_leaf::*;
+use kvm_bindings::{kvm_cpuid_entry2, CpuId};
+use transformer::*;
+
+// The family, model and stepping of the host are kept, since the Intel ones of the T2 template
+// don't make sense on an AMD CPU.
+fn update_feature_info_entry(entry: &mut kvm_cpuid_entry2, _vm_spec: &VmSpec) -> Result<(), Error> {
+    use cpu_leaf::leaf_0x1::*;
+
+    // Disable Features
+    entry
+        .ecx
+        .write_bit(ecx::DTES64_BITINDEX, false)
+        .write_bit(ecx::MONITOR_BITINDEX, false)
+        .write_bit(ecx::DS_CPL_SHIFT, false)
+        .write_bit(ecx::TM2_BITINDEX, false)
+        .write_bit(ecx::CNXT_ID_BITINDEX, false)
+        .write_bit(ecx::SDBG_BITINDEX, false)
+        .write_bit(ecx::XTPR_UPDATE_BITINDEX, false)
+        .write_bit(ecx::PDCM_BITINDEX, false)
+        .write_bit(ecx::OSXSAVE_BITINDEX, false);
+
+    entry
+        .edx
+        .write_bit(edx::PSN_BITINDEX, false)
+        .write_bit(edx::DS_BITINDEX, false)
+        .write_bit(edx::ACPI_BITINDEX, false)
+        .write_bit(edx::SS_BITINDEX, false)
+        .write_bit(edx::TM_BITINDEX, false)
+        .write_bit(edx::PBE_BITINDEX, false);
+
+    Ok(())
+}
+
+fn update_structured_extended_entry(
+    entry: &mut kvm_cpuid_entry2,
+    _vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    use cpu_leaf::leaf_0x7::index0::*;
+
+    if entry.index == 0 {
+        entry
+            .ebx
+            .write_bit(ebx::SGX_BITINDEX, false)
+            .write_bit(ebx::HLE_BITINDEX, false)
+            .write_bit(ebx::FPDP_BITINDEX, false)
+            .write_bit(ebx::RTM_BITINDEX, false)
+            .write_bit(ebx::RDT_M_BITINDEX, false)
+            .write_bit(ebx::RDT_A_BITINDEX, false)
+            .write_bit(ebx::MPX_BITINDEX, false)
+            .write_bit(ebx::AVX512F_BITINDEX, false)
+            .write_bit(ebx::AVX512DQ_BITINDEX, false)
+            .write_bit(ebx::RDSEED_BITINDEX, false)
+            .write_bit(ebx::ADX_BITINDEX, false)
+            .write_bit(ebx::AVX512IFMA_BITINDEX, false)
+            .write_bit(ebx::CLFLUSHOPT_BITINDEX, false)
+            .write_bit(ebx::CLWB_BITINDEX, false)
+            .write_bit(ebx::PT_BITINDEX, false)
+            .write_bit(ebx::AVX512PF_BITINDEX, false)
+            .write_bit(ebx::AVX512ER_BITINDEX, false)
+            .write_bit(ebx::AVX512CD_BITINDEX, false)
+            .write_bit(ebx::SHA_BITINDEX, false)
+            .write_bit(ebx::AVX512BW_BITINDEX, false)
+            .write_bit(ebx::AVX512VL_BITINDEX, false);
+
+        // VAES and VPCLMULQDQ are available on Milan but not on the T2 hosts.
+        entry
+            .ecx
+            .write_bit(ecx::AVX512_VBMI_BITINDEX, false)
+            .write_bit(ecx::PKU_BITINDEX, false)
+            .write_bit(ecx::OSPKE_BITINDEX, false)
+            .write_bit(ecx::VAES_BITINDEX, false)
+            .write_bit(ecx::VPCLMULQDQ_BITINDEX, false)
+            .write_bit(ecx::AVX512_VPOPCNTDQ_BITINDEX, false)
+            .write_bit(ecx::RDPID_BITINDEX, false)
+            .write_bit(ecx::SGX_LC_BITINDEX, false);
+
+        entry
+            .edx
+            .write_bit(edx::AVX512_4VNNIW_BITINDEX, false)
+            .write_bit(edx::AVX512_4FMAPS_BITINDEX, false);
+    }
+
+    Ok(())
+}
+
+fn update_xsave_features_entry(
+    entry: &mut kvm_cpuid_entry2,
+    _vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    use cpu_leaf::leaf_0xd::*;
+
+    if entry.index == 0 {
+        // MPX is masked out with the current template so the size in bytes of the save
+        // area should be 0 (or invalid).
+        entry
+            .eax
+            .write_bits_in_range(&index0::eax::MPX_STATE_BITRANGE, 0);
+
+        // AVX-512 instructions are masked out with the current template so the size in bytes
+        // of the save area should be 0 (or invalid).
+        entry
+            .eax
+            .write_bits_in_range(&index0::eax::AVX512_STATE_BITRANGE, 0);
+    }
+
+    if entry.index == 1 {
+        entry
+            .eax
+            .write_bit(index1::eax::XSAVEC_SHIFT, false)
+            .write_bit(index1::eax::XGETBV_SHIFT, false)
+            .write_bit(index1::eax::XSAVES_SHIFT, false);
+    }
+
+    Ok(())
+}
+
+fn update_extended_feature_info_entry(
+    entry: &mut kvm_cpuid_entry2,
+    _vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    use cpu_leaf::leaf_0x80000001::*;
+
+    // Disable the AMD specific features, which the T2 hosts don't have. The topology
+    // extensions are kept, since they describe the CPU topology to the guest.
+    entry
+        .ecx
+        .write_bit(ecx::SSE4A_BITINDEX, false)
+        .write_bit(ecx::MISALIGN_SSE_BITINDEX, false)
+        .write_bit(ecx::PREFETCH_BITINDEX, false)
+        .write_bit(ecx::OSVW_BITINDEX, false)
+        .write_bit(ecx::IBS_BITINDEX, false)
+        .write_bit(ecx::XOP_BITINDEX, false)
+        .write_bit(ecx::SKINIT_BITINDEX, false)
+        .write_bit(ecx::WDT_BITINDEX, false)
+        .write_bit(ecx::LWP_BITINDEX, false)
+        .write_bit(ecx::FMA4_BITINDEX, false)
+        .write_bit(ecx::TBM_BITINDEX, false)
+        .write_bit(ecx::PERFCTR_CORE_BITINDEX, false)
+        .write_bit(ecx::PERFCTR_NB_BITINDEX, false)
+        .write_bit(ecx::DBX_BITINDEX, false)
+        .write_bit(ecx::PERFTSC_BITINDEX, false)
+        .write_bit(ecx::PCX_L2I_BITINDEX, false)
+        .write_bit(ecx::MWAITX_BITINDEX, false);
+
+    entry
+        .edx
+        .write_bit(edx::MMXEXT_BITINDEX, false)
+        .write_bit(edx::FFXSR_BITINDEX, false)
+        .write_bit(edx::PDPE1GB_BITINDEX, false)
+        .write_bit(edx::AMD_3DNOWEXT_BITINDEX, false)
+        .write_bit(edx::AMD_3DNOW_BITINDEX, false);
+
+    Ok(())
+}
+
+fn update_extended_feature_extensions_entry(
+    entry: &mut kvm_cpuid_entry2,
+    _vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    use cpu_leaf::leaf_0x80000008::*;
+
+    entry
+        .ebx
+        .write_bit(ebx::CLZERO_BITINDEX, false)
+        .write_bit(ebx::IRPERF_BITINDEX, false)
+        .write_bit(ebx::XSAVEERPTR_BITINDEX, false)
+        .write_bit(ebx::RDPRU_BITINDEX, false)
+        .write_bit(ebx::MCOMMIT_BITINDEX, false)
+        .write_bit(ebx::WBNOINVD_BITINDEX, false);
+
+    Ok(())
+}
+
+/// Sets up the cpuid entries for a given VCPU following a T2A template.
+struct T2ACpuidTransformer {}
+
+impl CpuidTransformer for T2ACpuidTransformer {
+    fn entry_transformer_fn(&self, entry: &mut kvm_cpuid_entry2) -> Option<EntryTransformerFn> {
+        match entry.function {
+            leaf_0x1::LEAF_NUM => Some(update_feature_info_entry),
+            leaf_0x7::LEAF_NUM => Some(update_structured_extended_entry),
+            leaf_0xd::LEAF_NUM => Some(update_xsave_features_entry),
+            leaf_0x80000001::LEAF_NUM => Some(update_extended_feature_info_entry),
+            leaf_0x80000008::LEAF_NUM => Some(update_extended_feature_extensions_entry),
+            _ => None,
+        }
+    }
+}
+
+/// Sets up the cpuid entries for a given VCPU following a T2A template, which exposes on AMD
+/// (Milan) hosts the same instruction set as the T2 template on Intel hosts.
+///
+/// Fails if the host CPU is not an AMD one.
+pub fn set_cpuid_entries(kvm_cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
+    if vm_spec.cpu_vendor_id() != VENDOR_ID_AMD {
+        return Err(Error::UnsupportedCpuVendor);
+    }
+    T2ACpuidTransformer {}.process_cpuid(kvm_cpuid, vm_spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_cpuid_entries() {
+        let mut cpuid = CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: leaf_0x7::LEAF_NUM,
+                index: 0,
+                ebx: u32::max_value(),
+                ecx: u32::max_value(),
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: leaf_0x80000001::LEAF_NUM,
+                ecx: u32::max_value(),
+                ..Default::default()
+            },
+        ]);
+        let vm_spec = VmSpec::new(0, 1, false).unwrap();
+
+        // The T2A template only applies to AMD hosts.
+        if vm_spec.cpu_vendor_id() != VENDOR_ID_AMD {
+            assert!(set_cpuid_entries(&mut cpuid, &vm_spec).is_err());
+            return;
+        }
+
+        set_cpuid_entries(&mut cpuid, &vm_spec).unwrap();
+        let entries = cpuid.as_slice();
+        assert!(!entries[0].ebx.read_bit(leaf_0x7::index0::ebx::SHA_BITINDEX));
+        assert!(!entries[0]
+            .ecx
+            .read_bit(leaf_0x7::index0::ecx::VAES_BITINDEX));
+        assert!(entries[0]
+            .ebx
+            .read_bit(leaf_0x7::index0::ebx::AVX2_BITINDEX));
+        assert!(!entries[1]
+            .ecx
+            .read_bit(leaf_0x80000001::ecx::SSE4A_BITINDEX));
+        assert!(entries[1].ecx.read_bit(leaf_0x80000001::ecx::TOPOEXT_INDEX));
+    }
+}
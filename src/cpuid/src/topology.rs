@@ -0,0 +1,156 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use transformer::Error;
+
+/// The maximum number of cores per socket. Leaf 0x4 holds the number of cores in a package
+/// minus 1 on 6 bits.
+pub const MAX_CORES_PER_SOCKET: u8 = 64;
+/// The maximum number of logical CPUs per socket. Leaf 0x1 holds the number of addressable
+/// logical CPUs in a package, rounded up to a power of 2, on 8 bits.
+pub const MAX_CPUS_PER_SOCKET: u8 = 128;
+
+// Number of bits needed to hold the IDs of `count` entities.
+fn id_width(count: u8) -> u32 {
+    u32::from(count).next_power_of_two().trailing_zeros()
+}
+
+/// The topology of the guest CPUs: sockets holding cores, holding 1 or 2 threads each.
+///
+/// The APIC ID of a logical CPU is made of its socket, core and thread IDs, from the most to
+/// the least significant bits. Since the cores per socket are a power of 2 when there are
+/// several sockets, the APIC IDs are contiguous and the APIC ID of each vCPU is its index.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CpuTopology {
+    threads_per_core: u8,
+    cores_per_socket: u8,
+    sockets: u8,
+}
+
+impl CpuTopology {
+    /// Creates the topology of `cpu_count` logical CPUs, with 2 threads per core when
+    /// hyper-threading is enabled.
+    ///
+    /// All the cores are placed in the same socket unless `cores_per_socket` is specified.
+    /// Fails if the logical CPUs can't be evenly split in sockets of `cores_per_socket` cores,
+    /// if the sockets would exceed the limits above or, when there are several sockets, if
+    /// `cores_per_socket` is not a power of 2.
+    pub fn new(
+        cpu_count: u8,
+        ht_enabled: bool,
+        cores_per_socket: Option<u8>,
+    ) -> Result<CpuTopology, Error> {
+        let threads_per_core = if ht_enabled && cpu_count > 1 { 2 } else { 1 };
+        if cpu_count == 0 || cpu_count % threads_per_core != 0 {
+            return Err(Error::InvalidTopology);
+        }
+        let core_count = cpu_count / threads_per_core;
+        let cores_per_socket = cores_per_socket.unwrap_or(core_count);
+        if cores_per_socket == 0 || core_count % cores_per_socket != 0 {
+            return Err(Error::InvalidTopology);
+        }
+        let sockets = core_count / cores_per_socket;
+        if (sockets > 1 && !cores_per_socket.is_power_of_two())
+            || cores_per_socket > MAX_CORES_PER_SOCKET
+            || cores_per_socket * threads_per_core > MAX_CPUS_PER_SOCKET
+        {
+            return Err(Error::InvalidTopology);
+        }
+
+        Ok(CpuTopology {
+            threads_per_core,
+            cores_per_socket,
+            sockets,
+        })
+    }
+
+    /// Returns the number of threads per core.
+    pub fn threads_per_core(&self) -> u8 {
+        self.threads_per_core
+    }
+
+    /// Returns the number of cores per socket.
+    pub fn cores_per_socket(&self) -> u8 {
+        self.cores_per_socket
+    }
+
+    /// Returns the number of sockets.
+    pub fn sockets(&self) -> u8 {
+        self.sockets
+    }
+
+    /// Returns the number of logical CPUs per socket.
+    pub fn cpus_per_socket(&self) -> u8 {
+        self.cores_per_socket * self.threads_per_core
+    }
+
+    /// Returns the number of APIC ID bits holding the thread ID.
+    pub fn thread_id_width(&self) -> u32 {
+        id_width(self.threads_per_core)
+    }
+
+    /// Returns the number of APIC ID bits holding the core and thread IDs, i.e. the shift of the
+    /// socket ID.
+    pub fn socket_id_shift(&self) -> u32 {
+        self.thread_id_width() + id_width(self.cores_per_socket)
+    }
+
+    /// Returns the ID of the core of `cpu_id`, unique across sockets.
+    pub fn core_id(&self, cpu_id: u8) -> u32 {
+        u32::from(cpu_id) >> self.thread_id_width()
+    }
+
+    /// Returns the ID of the socket of `cpu_id`.
+    pub fn socket_id(&self, cpu_id: u8) -> u32 {
+        u32::from(cpu_id) >> self.socket_id_shift()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_topology() {
+        // All the cores go in the same socket by default.
+        let topology = CpuTopology::new(6, true, None).unwrap();
+        assert_eq!(topology.threads_per_core(), 2);
+        assert_eq!(topology.cores_per_socket(), 3);
+        assert_eq!(topology.sockets(), 1);
+        assert_eq!(topology.cpus_per_socket(), 6);
+        assert_eq!(topology.thread_id_width(), 1);
+        assert_eq!(topology.socket_id_shift(), 3);
+        assert_eq!(topology.core_id(5), 2);
+        assert_eq!(topology.socket_id(5), 0);
+
+        // A single vCPU has no sibling thread.
+        let topology = CpuTopology::new(1, true, None).unwrap();
+        assert_eq!(topology.threads_per_core(), 1);
+        assert_eq!(topology.socket_id_shift(), 0);
+
+        let topology = CpuTopology::new(128, true, Some(16)).unwrap();
+        assert_eq!(topology.sockets(), 4);
+        assert_eq!(topology.cpus_per_socket(), 32);
+        assert_eq!(topology.socket_id_shift(), 5);
+        assert_eq!(topology.core_id(37), 18);
+        assert_eq!(topology.socket_id(37), 1);
+        assert_eq!(topology.socket_id(127), 3);
+
+        let topology = CpuTopology::new(254, false, Some(2)).unwrap();
+        assert_eq!(topology.sockets(), 127);
+        assert_eq!(topology.socket_id(253), 126);
+
+        // Invalid topologies.
+        assert!(CpuTopology::new(0, false, None).is_err());
+        assert!(CpuTopology::new(3, true, None).is_err());
+        assert!(CpuTopology::new(8, false, Some(0)).is_err());
+        assert!(CpuTopology::new(8, false, Some(3)).is_err());
+        // Several sockets need a power of 2 of cores.
+        assert!(CpuTopology::new(12, false, Some(6)).is_err());
+        assert!(CpuTopology::new(12, false, Some(12)).is_ok());
+        // Sockets too large to be described by the CPUID.
+        assert!(CpuTopology::new(128, false, None).is_err());
+        assert!(CpuTopology::new(128, false, Some(64)).is_ok());
+        assert!(CpuTopology::new(254, true, None).is_err());
+    }
+}
@@ -11,9 +11,6 @@ use transformer::common::use_host_cpuid_function;
 
 // Largest extended function. It has to be larger then 0x8000001d (Extended Cache Topology).
 const LARGEST_EXTENDED_FN: u32 = 0x8000_001f;
-// This value allows at most 64 logical threads within a package.
-// See also the documentation for leaf_0x80000008::ecx::THREAD_ID_SIZE_BITRANGE
-const THREAD_ID_MAX_SIZE: u32 = 6;
 // This value means there is 1 node per processor.
 // See also the documentation for leaf_0x8000001e::ecx::NODES_PER_PROCESSOR_BITRANGE.
 const NODES_PER_PROCESSOR: u32 = 0;
@@ -67,12 +64,17 @@ pub fn update_amd_features_entry(
 ) -> Result<(), Error> {
     use cpu_leaf::leaf_0x80000008::*;
 
-    // We don't support more then 64 threads right now.
-    // It's safe to put them all on the same processor.
+    // The thread ID size is the number of APIC ID bits identifying a thread within its socket.
     entry
         .ecx
-        .write_bits_in_range(&ecx::THREAD_ID_SIZE_BITRANGE, THREAD_ID_MAX_SIZE)
-        .write_bits_in_range(&ecx::NUM_THREADS_BITRANGE, u32::from(vm_spec.cpu_count - 1));
+        .write_bits_in_range(
+            &ecx::THREAD_ID_SIZE_BITRANGE,
+            vm_spec.topology.socket_id_shift(),
+        )
+        .write_bits_in_range(
+            &ecx::NUM_THREADS_BITRANGE,
+            u32::from(vm_spec.topology.cpus_per_socket() - 1),
+        );
 
     Ok(())
 }
@@ -92,7 +94,6 @@ pub fn update_extended_apic_id_entry(
 ) -> Result<(), Error> {
     use cpu_leaf::leaf_0x8000001e::*;
 
-    let mut core_id = u32::from(vm_spec.cpu_id);
     // When hyper-threading is enabled each pair of 2 consecutive logical CPUs
     // will have the same core id since they represent 2 threads in the same core.
     // For Example:
@@ -100,9 +101,7 @@ pub fn update_extended_apic_id_entry(
     // logical CPU 1 -> core id: 0
     // logical CPU 2 -> core id: 1
     // logical CPU 3 -> core id: 1
-    if vm_spec.ht_enabled {
-        core_id /= 2;
-    }
+    let core_id = vm_spec.topology.core_id(vm_spec.cpu_id);
 
     entry
         .eax
@@ -120,8 +119,11 @@ pub fn update_extended_apic_id_entry(
     entry
         .ecx
         .write_bits_in_range(&ecx::NODES_PER_PROCESSOR_BITRANGE, NODES_PER_PROCESSOR)
-        // Put all the cpus in the same node.
-        .write_bits_in_range(&ecx::NODE_ID_BITRANGE, 0);
+        // There is one node per socket.
+        .write_bits_in_range(
+            &ecx::NODE_ID_BITRANGE,
+            vm_spec.topology.socket_id(vm_spec.cpu_id),
+        );
 
     Ok(())
 }
@@ -250,7 +252,7 @@ mod tests {
         );
         assert_eq!(
             entry.ecx.read_bits_in_range(&ecx::THREAD_ID_SIZE_BITRANGE),
-            THREAD_ID_MAX_SIZE
+            u32::from(cpu_count).next_power_of_two().trailing_zeros()
         );
     }
 
@@ -352,4 +354,48 @@ mod tests {
         check_update_extended_apic_id_entry(0, 2, true, 0, 1);
         check_update_extended_apic_id_entry(1, 2, true, 0, 1);
     }
+
+    #[test]
+    fn test_multiple_sockets() {
+        // 2 sockets of 2 cores with 2 threads each.
+        let vm_spec = VmSpec::with_cores_per_socket(5, 8, true, Some(2)).unwrap();
+        let mut entry = kvm_cpuid_entry2 {
+            function: leaf_0x80000008::LEAF_NUM,
+            index: 0,
+            flags: 0,
+            eax: 0,
+            ebx: 0,
+            ecx: 0,
+            edx: 0,
+            padding: [0, 0, 0],
+        };
+        assert!(update_amd_features_entry(&mut entry, &vm_spec).is_ok());
+        assert_eq!(
+            entry
+                .ecx
+                .read_bits_in_range(&leaf_0x80000008::ecx::THREAD_ID_SIZE_BITRANGE),
+            2
+        );
+        assert_eq!(
+            entry
+                .ecx
+                .read_bits_in_range(&leaf_0x80000008::ecx::NUM_THREADS_BITRANGE),
+            3
+        );
+
+        entry.function = leaf_0x8000001e::LEAF_NUM;
+        assert!(update_extended_apic_id_entry(&mut entry, &vm_spec).is_ok());
+        assert_eq!(
+            entry
+                .ebx
+                .read_bits_in_range(&leaf_0x8000001e::ebx::CORE_ID_BITRANGE),
+            2
+        );
+        assert_eq!(
+            entry
+                .ecx
+                .read_bits_in_range(&leaf_0x8000001e::ecx::NODE_ID_BITRANGE),
+            1
+        );
+    }
 }
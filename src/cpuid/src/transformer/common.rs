@@ -33,10 +33,15 @@ pub fn update_feature_info_entry(
 ) -> Result<(), Error> {
     use cpu_leaf::leaf_0x1::*;
 
-    let max_cpus_per_package = u32::from(common::get_max_cpus_per_package(vm_spec.cpu_count)?);
+    let max_cpus_per_package = u32::from(common::get_max_cpus_per_package(
+        vm_spec.topology.cpus_per_socket(),
+    )?);
 
     // X86 hypervisor feature
     entry.ecx.write_bit(ecx::HYPERVISOR_BITINDEX, true);
+    // The in-kernel local APIC emulated by KVM always supports the x2APIC mode, which saves the
+    // guest the MMIO exits of the xAPIC accesses. Expose it even when a CPU template is used.
+    entry.ecx.write_bit(ecx::X2APIC_BITINDEX, true);
 
     entry
         .ebx
@@ -84,10 +89,10 @@ pub fn update_cache_parameters_entry(
         }
         // L3 Cache
         3 => {
-            // The L3 cache is shared among all the logical threads
+            // The L3 cache is shared among all the logical threads of a socket
             entry.eax.write_bits_in_range(
                 &eax::MAX_CPUS_PER_CORE_BITRANGE,
-                u32::from(vm_spec.cpu_count - 1),
+                u32::from(vm_spec.topology.cpus_per_socket() - 1),
             );
         }
         _ => (),
@@ -165,7 +170,8 @@ mod tests {
 
         assert!(update_feature_info_entry(&mut entry, &vm_spec).is_ok());
 
-        assert!(entry.edx.read_bit(edx::HTT_BITINDEX) == expected_htt)
+        assert!(entry.edx.read_bit(edx::HTT_BITINDEX) == expected_htt);
+        assert!(entry.ecx.read_bit(ecx::X2APIC_BITINDEX));
     }
 
     fn check_update_cache_parameters_entry(
@@ -5,10 +5,6 @@ use super::*;
 use bit_helper::BitHelper;
 use cpu_leaf::*;
 
-// The APIC ID shift in leaf 0xBh specifies the number of bits to shit the x2APIC ID to get a
-// unique topology of the next level. This allows 64 logical processors/package.
-const LEAFBH_INDEX1_APICID: u32 = 6;
-
 pub fn update_feature_info_entry(
     entry: &mut kvm_cpuid_entry2,
     vm_spec: &VmSpec,
@@ -30,10 +26,9 @@ fn update_deterministic_cache_entry(
 
     common::update_cache_parameters_entry(entry, vm_spec)?;
 
-    // Put all the cores in the same socket
     entry.eax.write_bits_in_range(
         &eax::MAX_CORES_PER_PACKAGE_BITRANGE,
-        u32::from(vm_spec.cpu_count - 1),
+        u32::from(vm_spec.topology.cores_per_socket() - 1),
     );
 
     Ok(())
@@ -103,9 +98,10 @@ fn update_extended_cache_topology_entry(
         }
         // Core Level Processor Topology; index = 1
         1 => {
+            // Shifting right by the width of the core and thread IDs yields the socket ID.
             entry
                 .eax
-                .write_bits_in_range(&eax::APICID_BITRANGE, LEAFBH_INDEX1_APICID);
+                .write_bits_in_range(&eax::APICID_BITRANGE, vm_spec.topology.socket_id_shift());
             entry
                 .ecx
                 .write_bits_in_range(&ecx::LEVEL_NUMBER_BITRANGE, entry.index as u32);
@@ -117,7 +113,7 @@ fn update_extended_cache_topology_entry(
             } else {
                 entry.ebx.write_bits_in_range(
                     &ebx::NUM_LOGICAL_PROCESSORS_BITRANGE,
-                    u32::from(vm_spec.cpu_count),
+                    u32::from(vm_spec.topology.cpus_per_socket()),
                 );
                 entry
                     .ecx
@@ -283,14 +279,7 @@ mod tests {
         // index 0
         check_update_extended_cache_topology_entry(1, false, 0, 0, 1, LEVEL_TYPE_CORE);
         // index 1
-        check_update_extended_cache_topology_entry(
-            1,
-            false,
-            1,
-            LEAFBH_INDEX1_APICID,
-            0,
-            LEVEL_TYPE_INVALID,
-        );
+        check_update_extended_cache_topology_entry(1, false, 1, 0, 0, LEVEL_TYPE_INVALID);
     }
 
     #[test]
@@ -307,14 +296,7 @@ mod tests {
         // index 0
         check_update_extended_cache_topology_entry(1, true, 0, 0, 1, LEVEL_TYPE_CORE);
         // index 1
-        check_update_extended_cache_topology_entry(
-            1,
-            true,
-            1,
-            LEAFBH_INDEX1_APICID,
-            0,
-            LEVEL_TYPE_INVALID,
-        );
+        check_update_extended_cache_topology_entry(1, true, 1, 0, 0, LEVEL_TYPE_INVALID);
     }
 
     #[test]
@@ -331,37 +313,66 @@ mod tests {
         // index 0
         check_update_extended_cache_topology_entry(2, false, 0, 0, 1, LEVEL_TYPE_THREAD);
         // index 1
-        check_update_extended_cache_topology_entry(
-            2,
-            false,
-            1,
-            LEAFBH_INDEX1_APICID,
-            2,
-            LEVEL_TYPE_CORE,
-        );
+        check_update_extended_cache_topology_entry(2, false, 1, 1, 2, LEVEL_TYPE_CORE);
     }
 
     #[test]
     fn test_2vcpu_ht_on() {
         // test update_deterministic_cache_entry
         // test L1
-        check_update_deterministic_cache_entry(2, true, 1, 1);
+        check_update_deterministic_cache_entry(2, true, 1, 0);
         // test L2
-        check_update_deterministic_cache_entry(2, true, 2, 1);
+        check_update_deterministic_cache_entry(2, true, 2, 0);
         // test L3
-        check_update_deterministic_cache_entry(2, true, 3, 1);
+        check_update_deterministic_cache_entry(2, true, 3, 0);
 
         // test update_extended_cache_topology_entry
         // index 0
         check_update_extended_cache_topology_entry(2, true, 0, 1, 2, LEVEL_TYPE_THREAD);
         // index 1
-        check_update_extended_cache_topology_entry(
-            2,
-            true,
-            1,
-            LEAFBH_INDEX1_APICID,
-            2,
-            LEVEL_TYPE_CORE,
+        check_update_extended_cache_topology_entry(2, true, 1, 1, 2, LEVEL_TYPE_CORE);
+    }
+
+    #[test]
+    fn test_multiple_sockets() {
+        use cpu_leaf::leaf_0xb::*;
+
+        // 2 sockets of 2 cores with 2 threads each.
+        let vm_spec = VmSpec::with_cores_per_socket(5, 8, true, Some(2)).unwrap();
+        let mut entry = kvm_cpuid_entry2 {
+            function: leaf_0x4::LEAF_NUM,
+            index: 0,
+            flags: 0,
+            eax: *(0 as u32).write_bits_in_range(&leaf_0x4::eax::CACHE_LEVEL_BITRANGE, 3),
+            ebx: 0,
+            ecx: 0,
+            edx: 0,
+            padding: [0, 0, 0],
+        };
+        assert!(update_deterministic_cache_entry(&mut entry, &vm_spec).is_ok());
+        assert_eq!(
+            entry
+                .eax
+                .read_bits_in_range(&leaf_0x4::eax::MAX_CORES_PER_PACKAGE_BITRANGE),
+            1
+        );
+        assert_eq!(
+            entry
+                .eax
+                .read_bits_in_range(&leaf_0x4::eax::MAX_CPUS_PER_CORE_BITRANGE),
+            3
+        );
+
+        entry.function = LEAF_NUM;
+        entry.index = 1;
+        assert!(update_extended_cache_topology_entry(&mut entry, &vm_spec).is_ok());
+        assert_eq!(entry.eax.read_bits_in_range(&eax::APICID_BITRANGE), 2);
+        assert_eq!(
+            entry
+                .ebx
+                .read_bits_in_range(&ebx::NUM_LOGICAL_PROCESSORS_BITRANGE),
+            4
         );
+        assert_eq!(entry.edx, 5);
     }
 }
@@ -10,6 +10,7 @@ pub use kvm_bindings::{kvm_cpuid_entry2, CpuId};
 use brand_string::BrandString;
 use brand_string::Reg as BsReg;
 use common::get_vendor_id;
+use topology::CpuTopology;
 
 /// Structure containing the specifications of the VM
 pub struct VmSpec {
@@ -21,6 +22,8 @@ pub struct VmSpec {
     cpu_count: u8,
     /// Specifies whether hyper-threading is enabled.
     ht_enabled: bool,
+    /// The topology of the logical cpus.
+    topology: CpuTopology,
     /// The desired brand string for the guest.
     brand_string: BrandString,
 }
@@ -28,14 +31,28 @@ pub struct VmSpec {
 impl VmSpec {
     /// Creates a new instance of VmSpec with the specified parameters
     /// The brand string is deduced from the vendor_id
+    /// All the cores are placed in the same socket.
     pub fn new(cpu_id: u8, cpu_count: u8, ht_enabled: bool) -> Result<VmSpec, Error> {
+        Self::with_cores_per_socket(cpu_id, cpu_count, ht_enabled, None)
+    }
+
+    /// Creates a new instance of VmSpec, with the cores split in sockets of `cores_per_socket`
+    /// cores. See `CpuTopology::new`.
+    pub fn with_cores_per_socket(
+        cpu_id: u8,
+        cpu_count: u8,
+        ht_enabled: bool,
+        cores_per_socket: Option<u8>,
+    ) -> Result<VmSpec, Error> {
         let cpu_vendor_id = get_vendor_id().map_err(Error::InternalError)?;
+        let topology = CpuTopology::new(cpu_count, ht_enabled, cores_per_socket)?;
 
         Ok(VmSpec {
             cpu_vendor_id,
             cpu_id,
             cpu_count,
             ht_enabled,
+            topology,
             brand_string: BrandString::from_vendor_id(&cpu_vendor_id),
         })
     }
@@ -55,6 +72,12 @@ pub enum Error {
     InternalError(super::common::Error),
     /// The maximum number of addressable logical CPUs cannot be stored in an `u8`.
     VcpuCountOverflow,
+    /// The logical CPUs can't be laid out in the requested topology.
+    InvalidTopology,
+    /// The host doesn't support the requested CPU feature.
+    UnsupportedCpuFeature(&'static str),
+    /// The CPU template doesn't apply to the CPU vendor of the host.
+    UnsupportedCpuVendor,
 }
 
 pub type EntryTransformerFn =
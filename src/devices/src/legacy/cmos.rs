@@ -0,0 +1,482 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! x86 CMOS Real Time Clock
+//!
+//! This module implements the MC146818 compatible RTC of the PC, along with the rest of the
+//! 128 bytes of CMOS memory. The guest selects a register through the index port (0x70) and
+//! accesses it through the data port (0x71).
+//! The clock follows the host real time clock, shifted by an offset which is set either by the
+//! VMM or by the guest writing the time registers. Alarm, periodic and update-ended interrupts
+//! are not emulated.
+
+use logger::{Metric, METRICS};
+use utils::time::{get_time, ClockType, NANOS_PER_SECOND};
+
+use crate::bus::BusDevice;
+
+/// Offset of the index port (port 0x70).
+const OFS_INDEX: u64 = 0;
+/// Offset of the data port (port 0x71).
+const OFS_DATA: u64 = 1;
+
+/// Size of the CMOS memory, in bytes.
+const CMOS_SIZE: usize = 128;
+/// Bits of the index port which select the register. Bit 7 masks the NMI, which we ignore.
+const INDEX_MASK: u8 = 0x7F;
+
+/// CMOS registers
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_WEEKDAY: u8 = 0x06;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_A: u8 = 0x0A;
+const REG_B: u8 = 0x0B;
+const REG_C: u8 = 0x0C;
+const REG_D: u8 = 0x0D;
+const REG_CENTURY: u8 = 0x32;
+
+/// Status register A bits
+const A_UIP: u8 = 0x80; // Update in progress
+const A_DEFAULT: u8 = 0x26; // 32.768 kHz time base, 1.024 kHz periodic rate
+
+/// Status register B bits
+const B_SET: u8 = 0x80; // The clock is stopped so that the guest can set it
+const B_DM_BINARY: u8 = 0x04; // 1 = binary, 0 = BCD time registers
+const B_24H: u8 = 0x02; // 1 = 24 hour, 0 = 12 hour mode
+
+/// Status register D bits
+const D_VRT: u8 = 0x80; // Valid RAM and time
+
+/// PM flag of the hours register in 12 hour mode.
+const HOURS_PM: u8 = 0x80;
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// Broken-down time, as held by the time registers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct DateTime {
+    year: i64,
+    month: i64,
+    day: i64,
+    hours: i64,
+    minutes: i64,
+    seconds: i64,
+}
+
+impl DateTime {
+    // The conversions between days and civil dates come from Howard Hinnant's
+    // `chrono`-compatible low-level date algorithms.
+    fn from_timestamp(timestamp: i64) -> Self {
+        let days = timestamp.div_euclid(SECONDS_PER_DAY);
+        let secs = timestamp.rem_euclid(SECONDS_PER_DAY);
+
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        DateTime {
+            year,
+            month,
+            day,
+            hours: secs / 3600,
+            minutes: secs % 3600 / 60,
+            seconds: secs % 60,
+        }
+    }
+
+    fn timestamp(&self) -> i64 {
+        let year = self.year - if self.month <= 2 { 1 } else { 0 };
+        let era = year.div_euclid(400);
+        let yoe = year - era * 400;
+        let mp = (self.month + 9) % 12;
+        let doy = (153 * mp + 2) / 5 + self.day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+
+        days * SECONDS_PER_DAY + self.hours * 3600 + self.minutes * 60 + self.seconds
+    }
+
+    /// Day of the week, from 1 (Sunday) to 7.
+    fn weekday(&self) -> i64 {
+        // The epoch was a Thursday.
+        (self.timestamp().div_euclid(SECONDS_PER_DAY) + 4).rem_euclid(7) + 1
+    }
+}
+
+fn host_time() -> i64 {
+    (get_time(ClockType::Real) / NANOS_PER_SECOND) as i64
+}
+
+/// A CMOS RTC device following the MC146818 specification.
+pub struct Cmos {
+    /// The register selected through the index port.
+    index: u8,
+    /// The CMOS memory. The time registers are refreshed from the clock whenever they are read.
+    data: [u8; CMOS_SIZE],
+    /// Offset of the guest clock from the host real time clock, in seconds.
+    time_offset: i64,
+}
+
+impl Default for Cmos {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cmos {
+    /// Constructs a CMOS device whose clock follows the host real time clock, in UTC.
+    pub fn new() -> Cmos {
+        let mut data = [0u8; CMOS_SIZE];
+        data[REG_A as usize] = A_DEFAULT;
+        data[REG_B as usize] = B_24H;
+        data[REG_D as usize] = D_VRT;
+        Cmos {
+            index: 0,
+            data,
+            time_offset: 0,
+        }
+    }
+
+    /// Sets the clock to `timestamp`, in seconds since the Unix epoch. The guest sees the time
+    /// held by the registers as is, so `timestamp` has to be shifted to the guest timezone if
+    /// the guest expects the RTC to hold the local time.
+    pub fn set_time(&mut self, timestamp: i64) {
+        self.time_offset = timestamp - host_time();
+    }
+
+    /// Returns the current time of the clock, in seconds since the Unix epoch.
+    pub fn time(&self) -> i64 {
+        host_time() + self.time_offset
+    }
+
+    /// Returns the offset of the clock from the host real time clock, in seconds.
+    pub fn time_offset(&self) -> i64 {
+        self.time_offset
+    }
+
+    /// Sets the offset of the clock from the host real time clock, in seconds, as saved by
+    /// `time_offset`.
+    pub fn set_time_offset(&mut self, time_offset: i64) {
+        self.time_offset = time_offset;
+    }
+
+    fn is_binary(&self) -> bool {
+        self.data[REG_B as usize] & B_DM_BINARY != 0
+    }
+
+    fn encode(&self, value: i64) -> u8 {
+        let value = value as u8;
+        if self.is_binary() {
+            value
+        } else {
+            (value / 10) << 4 | value % 10
+        }
+    }
+
+    fn decode(&self, value: u8) -> Option<i64> {
+        if self.is_binary() {
+            return Some(i64::from(value));
+        }
+        let (high, low) = (value >> 4, value & 0x0F);
+        if high > 9 || low > 9 {
+            return None;
+        }
+        Some(i64::from(high * 10 + low))
+    }
+
+    /// Refreshes the time registers from the clock.
+    fn latch_time(&mut self) {
+        let now = DateTime::from_timestamp(self.time());
+        let hours = if self.data[REG_B as usize] & B_24H != 0 {
+            self.encode(now.hours)
+        } else {
+            // Midnight and noon are 12 AM and 12 PM.
+            let hours = self.encode((now.hours + 11) % 12 + 1);
+            if now.hours >= 12 {
+                hours | HOURS_PM
+            } else {
+                hours
+            }
+        };
+
+        self.data[REG_SECONDS as usize] = self.encode(now.seconds);
+        self.data[REG_MINUTES as usize] = self.encode(now.minutes);
+        self.data[REG_HOURS as usize] = hours;
+        self.data[REG_WEEKDAY as usize] = self.encode(now.weekday());
+        self.data[REG_DAY as usize] = self.encode(now.day);
+        self.data[REG_MONTH as usize] = self.encode(now.month);
+        self.data[REG_YEAR as usize] = self.encode(now.year % 100);
+        self.data[REG_CENTURY as usize] = self.encode(now.year / 100);
+    }
+
+    /// Reads back the time set by the guest through the time registers.
+    fn registers_time(&self) -> Option<DateTime> {
+        let hours = self.data[REG_HOURS as usize];
+        let hours = if self.data[REG_B as usize] & B_24H != 0 {
+            self.decode(hours)?
+        } else {
+            let hours12 = self.decode(hours & !HOURS_PM)?;
+            if hours12 < 1 || hours12 > 12 {
+                return None;
+            }
+            hours12 % 12 + if hours & HOURS_PM != 0 { 12 } else { 0 }
+        };
+        let time = DateTime {
+            year: self.decode(self.data[REG_CENTURY as usize])? * 100
+                + self.decode(self.data[REG_YEAR as usize])?,
+            month: self.decode(self.data[REG_MONTH as usize])?,
+            day: self.decode(self.data[REG_DAY as usize])?,
+            hours,
+            minutes: self.decode(self.data[REG_MINUTES as usize])?,
+            seconds: self.decode(self.data[REG_SECONDS as usize])?,
+        };
+        if time.month < 1
+            || time.month > 12
+            || time.day < 1
+            || time.day > 31
+            || time.hours > 23
+            || time.minutes > 59
+            || time.seconds > 59
+        {
+            return None;
+        }
+        Some(time)
+    }
+
+    /// Moves the clock to the time set by the guest through the time registers.
+    fn commit_time(&mut self) {
+        match self.registers_time() {
+            Some(time) => self.set_time(time.timestamp()),
+            None => {
+                warn!("CMOS: ignoring invalid time set by the guest.");
+                METRICS.rtc.error_count.inc();
+            }
+        }
+    }
+
+    fn is_time_register(index: u8) -> bool {
+        match index {
+            REG_SECONDS | REG_MINUTES | REG_HOURS | REG_WEEKDAY | REG_DAY | REG_MONTH
+            | REG_YEAR | REG_CENTURY => true,
+            _ => false,
+        }
+    }
+
+    fn read_register(&mut self) -> u8 {
+        let set = self.data[REG_B as usize] & B_SET != 0;
+        match self.index {
+            // The registers are refreshed on every read, so an update is never in progress.
+            REG_A => self.data[REG_A as usize] & !A_UIP,
+            // No interrupt is ever raised.
+            REG_C => 0,
+            index if Self::is_time_register(index) && !set => {
+                self.latch_time();
+                self.data[index as usize]
+            }
+            index => self.data[index as usize],
+        }
+    }
+
+    fn write_register(&mut self, value: u8) {
+        let set = self.data[REG_B as usize] & B_SET != 0;
+        match self.index {
+            REG_A => self.data[REG_A as usize] = value & !A_UIP,
+            REG_B => {
+                if value & B_SET != 0 && !set {
+                    // The clock stops, holding the current time until the guest sets it.
+                    self.latch_time();
+                }
+                self.data[REG_B as usize] = value;
+                if value & B_SET == 0 && set {
+                    self.commit_time();
+                }
+            }
+            // Read-only registers.
+            REG_C | REG_D => METRICS.rtc.missed_write_count.inc(),
+            index if Self::is_time_register(index) && !set => {
+                // Without the SET bit, the new field applies to the running clock.
+                self.latch_time();
+                self.data[index as usize] = value;
+                self.commit_time();
+            }
+            index => self.data[index as usize] = value,
+        }
+    }
+}
+
+impl BusDevice for Cmos {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        // All our ports are byte-wide. We don't know how to handle any wider data.
+        if data.len() != 1 {
+            METRICS.rtc.missed_read_count.inc();
+            return;
+        }
+
+        match offset {
+            OFS_INDEX => data[0] = self.index,
+            OFS_DATA => data[0] = self.read_register(),
+            _ => METRICS.rtc.missed_read_count.inc(),
+        }
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        // All our ports are byte-wide. We don't know how to handle any wider data.
+        if data.len() != 1 {
+            METRICS.rtc.missed_write_count.inc();
+            return;
+        }
+
+        match offset {
+            OFS_INDEX => self.index = data[0] & INDEX_MASK,
+            OFS_DATA => self.write_register(data[0]),
+            _ => METRICS.rtc.missed_write_count.inc(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2038-01-19T03:14:07Z, a Tuesday.
+    const TIMESTAMP: i64 = 2_147_483_647;
+
+    fn read_reg(cmos: &mut Cmos, index: u8) -> u8 {
+        let mut data = [0];
+        cmos.write(OFS_INDEX, &[index]);
+        cmos.read(OFS_DATA, &mut data);
+        data[0]
+    }
+
+    fn write_reg(cmos: &mut Cmos, index: u8, value: u8) {
+        cmos.write(OFS_INDEX, &[index]);
+        cmos.write(OFS_DATA, &[value]);
+    }
+
+    #[test]
+    fn test_date_time() {
+        let time = DateTime::from_timestamp(TIMESTAMP);
+        assert_eq!(
+            time,
+            DateTime {
+                year: 2038,
+                month: 1,
+                day: 19,
+                hours: 3,
+                minutes: 14,
+                seconds: 7,
+            }
+        );
+        assert_eq!(time.timestamp(), TIMESTAMP);
+        assert_eq!(time.weekday(), 3);
+
+        // The epoch, a leap day, and a date before the epoch.
+        for timestamp in &[0, 951_782_400, -86_400] {
+            assert_eq!(DateTime::from_timestamp(*timestamp).timestamp(), *timestamp);
+        }
+        assert_eq!(DateTime::from_timestamp(951_782_400).month, 2);
+        assert_eq!(DateTime::from_timestamp(951_782_400).day, 29);
+        assert_eq!(DateTime::from_timestamp(0).weekday(), 5);
+    }
+
+    #[test]
+    fn test_read_time() {
+        let mut cmos = Cmos::new();
+        cmos.set_time(TIMESTAMP);
+        // Freeze the clock, so the registers can't tick while they're read.
+        write_reg(&mut cmos, REG_B, B_SET | B_24H);
+
+        // BCD, 24 hour mode.
+        assert_eq!(read_reg(&mut cmos, REG_YEAR), 0x38);
+        assert_eq!(read_reg(&mut cmos, REG_CENTURY), 0x20);
+        assert_eq!(read_reg(&mut cmos, REG_MONTH), 0x01);
+        assert_eq!(read_reg(&mut cmos, REG_DAY), 0x19);
+        assert_eq!(read_reg(&mut cmos, REG_WEEKDAY), 0x03);
+        assert_eq!(read_reg(&mut cmos, REG_HOURS), 0x03);
+        assert_eq!(read_reg(&mut cmos, REG_MINUTES), 0x14);
+        let seconds = read_reg(&mut cmos, REG_SECONDS);
+        assert!(seconds == 0x07 || seconds == 0x08);
+
+        // Binary, 12 hour mode.
+        write_reg(&mut cmos, REG_B, B_DM_BINARY);
+        cmos.set_time(TIMESTAMP + 12 * 3600);
+        write_reg(&mut cmos, REG_B, B_SET | B_DM_BINARY);
+        assert_eq!(read_reg(&mut cmos, REG_DAY), 19);
+        assert_eq!(read_reg(&mut cmos, REG_HOURS), 3 | HOURS_PM);
+        assert_eq!(read_reg(&mut cmos, REG_MINUTES), 14);
+
+        // The status registers.
+        assert_eq!(read_reg(&mut cmos, REG_A) & A_UIP, 0);
+        assert_eq!(read_reg(&mut cmos, REG_C), 0);
+        assert_eq!(read_reg(&mut cmos, REG_D), D_VRT);
+    }
+
+    #[test]
+    fn test_set_time() {
+        let mut cmos = Cmos::new();
+
+        // The guest sets the clock, the Linux way.
+        write_reg(&mut cmos, REG_B, B_SET | B_24H);
+        for (index, value) in &[
+            (REG_SECONDS, 0x07),
+            (REG_MINUTES, 0x14),
+            (REG_HOURS, 0x03),
+            (REG_DAY, 0x19),
+            (REG_MONTH, 0x01),
+            (REG_YEAR, 0x38),
+            (REG_CENTURY, 0x20),
+        ] {
+            write_reg(&mut cmos, *index, *value);
+        }
+        write_reg(&mut cmos, REG_B, B_24H);
+        assert!((cmos.time() - TIMESTAMP).abs() <= 1);
+
+        // Fields written to the running clock apply right away.
+        write_reg(&mut cmos, REG_YEAR, 0x37);
+        assert!((cmos.time() - (TIMESTAMP - 365 * SECONDS_PER_DAY)).abs() <= 1);
+
+        // Invalid times are ignored.
+        let errors = METRICS.rtc.error_count.count();
+        write_reg(&mut cmos, REG_MONTH, 0x13);
+        assert!((cmos.time() - (TIMESTAMP - 365 * SECONDS_PER_DAY)).abs() <= 1);
+        assert!(METRICS.rtc.error_count.count() > errors);
+
+        // The offset carries the time over to another clock, as done for snapshots.
+        let mut restored = Cmos::new();
+        restored.set_time_offset(cmos.time_offset());
+        assert!((restored.time() - (TIMESTAMP - 365 * SECONDS_PER_DAY)).abs() <= 1);
+    }
+
+    #[test]
+    fn test_nvram() {
+        let mut cmos = Cmos::new();
+        let mut data = [0];
+
+        // The NMI mask bit is ignored.
+        write_reg(&mut cmos, 0x80 | 0x40, 0x52);
+        cmos.read(OFS_INDEX, &mut data);
+        assert_eq!(data[0], 0x40);
+        assert_eq!(read_reg(&mut cmos, 0x40), 0x52);
+
+        // The status registers C and D are read-only.
+        write_reg(&mut cmos, REG_D, 0);
+        assert_eq!(read_reg(&mut cmos, REG_D), D_VRT);
+
+        // Invalid accesses.
+        let before = METRICS.rtc.missed_read_count.count();
+        let mut data = [0, 0];
+        cmos.read(OFS_DATA, &mut data);
+        cmos.read(2, &mut data[..1]);
+        assert!(METRICS.rtc.missed_read_count.count() >= before + 2);
+    }
+}
@@ -43,6 +43,19 @@ impl fmt::Display for Error {
 
 type Result<T> = result::Result<T, Error>;
 
+/// Describes how the guest asserted the CPU reset line of the i8042.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResetRequest {
+    /// The guest pulsed the reset line of the output port, with the given command
+    /// (e.g. `CMD_RESET_CPU`).
+    PulseOutputLine(u8),
+    /// The guest wrote the given value, which has the reset line cleared, to the output port.
+    WriteOutputPort(u8),
+}
+
+/// Callback used by the i8042 device to surface guest `ResetRequest`s.
+pub type ResetRequestSink = Box<dyn Fn(ResetRequest) + Send>;
+
 /// Offset of the status port (port 0x64)
 const OFS_STATUS: u64 = 4;
 
@@ -56,12 +69,17 @@ const CMD_WRITE_CTR: u8 = 0x60; // Write control register
 const CMD_READ_OUTP: u8 = 0xD0; // Read output port
 const CMD_WRITE_OUTP: u8 = 0xD1; // Write output port
 const CMD_RESET_CPU: u8 = 0xFE; // Reset CPU
+const CMD_PULSE_OUTP: u8 = 0xF0; // Pulse the output port lines cleared in the low nibble
 
 /// i8042 status register bits
 const SB_OUT_DATA_AVAIL: u8 = 0x0001; // Data available at port 0x60
 const SB_I8042_CMD_DATA: u8 = 0x0008; // i8042 expecting command parameter at port 0x60
 const SB_KBD_ENABLED: u8 = 0x0010; // 1 = kbd enabled, 0 = kbd locked
 
+/// i8042 output port bits
+const OUTP_RESET_LINE: u8 = 0x01; // 0 = CPU held in reset
+const OUTP_A20_GATE: u8 = 0x02; // A20 line enabled
+
 /// i8042 control register bits
 const CB_KBD_INT: u8 = 0x0001; // kbd interrupt enabled
 const CB_POST_OK: u8 = 0x0004; // POST ok (should always be 1)
@@ -76,9 +94,12 @@ const BUF_SIZE: usize = 16;
 
 /// A i8042 PS/2 controller that emulates just enough to shutdown the machine.
 pub struct I8042Device {
-    /// CPU reset eventfd. We will set this event when the guest issues CMD_RESET_CPU.
+    /// CPU reset eventfd. We will set this event when the guest asserts the CPU reset line.
     reset_evt: EventFd,
 
+    /// Callback notified of the guest reset requests, before `reset_evt` is set.
+    reset_sink: Option<ResetRequestSink>,
+
     /// Keyboard interrupt event (IRQ 1).
     kbd_interrupt_evt: EventFd,
 
@@ -105,10 +126,13 @@ impl I8042Device {
     pub fn new(reset_evt: EventFd, kbd_interrupt_evt: EventFd) -> I8042Device {
         I8042Device {
             reset_evt,
+            reset_sink: None,
             kbd_interrupt_evt,
             control: CB_POST_OK | CB_KBD_INT,
             cmd: 0,
-            outp: 0,
+            // The CPU isn't held in reset, so that guests rewriting the output port with
+            // just the A20 bit changed don't reset the machine.
+            outp: OUTP_RESET_LINE | OUTP_A20_GATE,
             status: SB_KBD_ENABLED,
             buf: [0; BUF_SIZE],
             bhead: Wrapping(0),
@@ -121,6 +145,11 @@ impl I8042Device {
         self.reset_evt.try_clone().map_err(Error::CloneCpuResetEvt)
     }
 
+    /// Sets the callback through which the device surfaces guest `ResetRequest`s.
+    pub fn set_reset_sink(&mut self, reset_sink: ResetRequestSink) {
+        self.reset_sink = Some(reset_sink);
+    }
+
     pub fn trigger_kbd_interrupt(&self) -> Result<()> {
         if (self.control & CB_KBD_INT) == 0 {
             warn!("Failed to trigger i8042 kbd interrupt (disabled by guest OS)");
@@ -160,6 +189,21 @@ impl I8042Device {
         Ok(())
     }
 
+    fn reset_cpu(&mut self, request: ResetRequest) {
+        // The guest wants to assert the CPU reset line. We handle that by triggering
+        // our exit event fd. Meaning Firecracker will be exiting as soon as the VMM
+        // thread wakes up to handle this event.
+        info!("i8042: the guest requested a CPU reset: {:?}", request);
+        if let Some(sink) = self.reset_sink.as_ref() {
+            sink(request);
+        }
+        if let Err(e) = self.reset_evt.write(1) {
+            error!("Failed to trigger i8042 reset event: {:?}", e);
+            METRICS.i8042.error_count.inc();
+        }
+        METRICS.i8042.reset_count.inc();
+    }
+
     #[inline]
     fn push_byte(&mut self, byte: u8) -> Result<()> {
         self.status |= SB_OUT_DATA_AVAIL;
@@ -242,15 +286,13 @@ impl BusDevice for I8042Device {
         let mut write_ok = true;
 
         match offset {
-            OFS_STATUS if data[0] == CMD_RESET_CPU => {
-                // The guest wants to assert the CPU reset line. We handle that by triggering
-                // our exit event fd. Meaning Firecracker will be exiting as soon as the VMM
-                // thread wakes up to handle this event.
-                if let Err(e) = self.reset_evt.write(1) {
-                    error!("Failed to trigger i8042 reset event: {:?}", e);
-                    METRICS.i8042.error_count.inc();
+            OFS_STATUS if data[0] & CMD_PULSE_OUTP == CMD_PULSE_OUTP => {
+                // The guest wants to pulse the output port lines which are cleared in the low
+                // nibble of the command. CMD_RESET_CPU is the most common of these commands.
+                // Only the CPU reset line is connected, the other pulses are no-ops.
+                if data[0] & OUTP_RESET_LINE == 0 {
+                    self.reset_cpu(ResetRequest::PulseOutputLine(data[0]));
                 }
-                METRICS.i8042.reset_count.inc();
             }
             OFS_STATUS if data[0] == CMD_READ_CTR => {
                 // The guest wants to read the control register.
@@ -293,12 +335,18 @@ impl BusDevice for I8042Device {
                 //    the status reg bit SB_I8042_CMD_DATA will be set, or
                 // 2. a direct command sent to the keyboard
                 // This match arm handles the first option (when the SB_I8042_CMD_DATA bit is set).
+                self.status &= !SB_I8042_CMD_DATA;
                 match self.cmd {
                     CMD_WRITE_CTR => self.control = data[0],
-                    CMD_WRITE_OUTP => self.outp = data[0],
+                    CMD_WRITE_OUTP => {
+                        self.outp = data[0];
+                        // Clearing the reset line of the output port holds the CPU in reset.
+                        if data[0] & OUTP_RESET_LINE == 0 {
+                            self.reset_cpu(ResetRequest::WriteOutputPort(data[0]));
+                        }
+                    }
                     _ => (),
                 }
-                self.status &= !SB_I8042_CMD_DATA;
             }
             OFS_DATA => {
                 // The guest is sending a command straight to the keyboard (so this byte is not
@@ -327,6 +375,8 @@ impl BusDevice for I8042Device {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex};
+
     use super::*;
 
     impl PartialEq for Error {
@@ -415,6 +465,61 @@ mod tests {
         assert_eq!(data[0], 0xFA);
     }
 
+    #[test]
+    fn test_i8042_reset() {
+        let mut i8042 = I8042Device::new(
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        );
+        let reset_evt = i8042.get_reset_evt_clone().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let sink_requests = requests.clone();
+        i8042.set_reset_sink(Box::new(move |request| {
+            sink_requests.lock().unwrap().push(request)
+        }));
+        let mut data = [0];
+
+        // The CPU isn't held in reset from the start.
+        data[0] = CMD_READ_OUTP;
+        i8042.write(OFS_STATUS, &data);
+        i8042.read(OFS_DATA, &mut data);
+        assert_ne!(data[0] & OUTP_RESET_LINE, 0);
+
+        // Pulses which leave the reset line alone are no-ops.
+        data[0] = 0xFF;
+        i8042.write(OFS_STATUS, &data);
+        data[0] = 0xFD;
+        i8042.write(OFS_STATUS, &data);
+        assert!(reset_evt.read().is_err());
+
+        // Any pulse of the reset line resets the CPU.
+        for cmd in &[CMD_RESET_CPU, CMD_PULSE_OUTP] {
+            data[0] = *cmd;
+            i8042.write(OFS_STATUS, &data);
+            assert_eq!(reset_evt.read().unwrap(), 1);
+        }
+
+        // Writing the output port resets the CPU only when the reset line is cleared.
+        for outp in &[OUTP_RESET_LINE | OUTP_A20_GATE, OUTP_A20_GATE] {
+            data[0] = CMD_WRITE_OUTP;
+            i8042.write(OFS_STATUS, &data);
+            data[0] = *outp;
+            i8042.write(OFS_DATA, &data);
+            assert_eq!(i8042.outp, *outp);
+            assert_eq!(i8042.status & SB_I8042_CMD_DATA, 0);
+        }
+        assert_eq!(reset_evt.read().unwrap(), 1);
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                ResetRequest::PulseOutputLine(CMD_RESET_CPU),
+                ResetRequest::PulseOutputLine(CMD_PULSE_OUTP),
+                ResetRequest::WriteOutputPort(OUTP_A20_GATE),
+            ]
+        );
+    }
+
     #[test]
     fn test_i8042_buffer() {
         let mut i8042 = I8042Device::new(
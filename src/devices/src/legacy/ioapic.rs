@@ -0,0 +1,300 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! x86 IOAPIC emulated in userspace
+//!
+//! With a split irqchip, KVM only emulates the local APICs, leaving the IOAPIC to the VMM. The
+//! guest programs the redirection table of the IOAPIC through the register window at its base
+//! address. Each unmasked pin is then routed by KVM to the local APICs as an MSI, so that the
+//! interrupts signaled through irqfds are still delivered without leaving the kernel. The routes
+//! are installed through an `MsiRouter`.
+//!
+//! The interrupts are delivered by KVM without the IOAPIC seeing them, so its delivery status and
+//! Remote IRR bits always read as 0. An irqfd routed as an MSI injects the interrupt once each
+//! time it is signaled, as an edge would, even for a pin the guest set as level-triggered: there
+//! is no line left asserted for the guest to EOI, and nothing resamples it. The EOIs of such
+//! vectors still exit to the VMM, as `KVM_EXIT_IOAPIC_EOI`, which ignores them. This suits the
+//! devices behind the IOAPIC, which signal their irqfd again for every new interrupt.
+
+use std::io;
+
+use crate::bus::BusDevice;
+
+/// The base address of the IOAPIC.
+pub const IOAPIC_START: u64 = 0xfec0_0000;
+/// The size of the MMIO range of the IOAPIC.
+pub const IOAPIC_SIZE: u64 = 0x1000;
+/// The number of pins of the IOAPIC, which are the first GSIs.
+pub const IOAPIC_NUM_PINS: usize = 24;
+
+/// Offset of the register select register.
+const OFS_IOREGSEL: u64 = 0x00;
+/// Offset of the register window.
+const OFS_IOWIN: u64 = 0x10;
+
+/// Indirect registers
+const REG_ID: u32 = 0x00;
+const REG_VERSION: u32 = 0x01;
+const REG_ARBITRATION: u32 = 0x02;
+const REG_REDIRECTION_TABLE: u32 = 0x10;
+
+/// Version of the 82093AA IOAPIC, along with the index of the last redirection table entry.
+const VERSION: u32 = 0x11 | ((IOAPIC_NUM_PINS as u32 - 1) << 16);
+/// The bits of the ID register holding the IOAPIC ID.
+const ID_MASK: u32 = 0x0f00_0000;
+
+/// Redirection table entry bits
+const RTE_VECTOR_MASK: u64 = 0xff;
+const RTE_DELIVERY_MODE_SHIFT: u64 = 8;
+const RTE_DELIVERY_MODE_MASK: u64 = 0x7;
+const RTE_DEST_MODE_LOGICAL: u64 = 1 << 11;
+const RTE_DELIVERY_STATUS: u64 = 1 << 12;
+const RTE_REMOTE_IRR: u64 = 1 << 14;
+const RTE_LEVEL_TRIGGERED: u64 = 1 << 15;
+const RTE_MASKED: u64 = 1 << 16;
+const RTE_DEST_SHIFT: u64 = 56;
+const RTE_READ_ONLY: u64 = RTE_DELIVERY_STATUS | RTE_REMOTE_IRR;
+
+/// MSI address and data bits
+const MSI_ADDRESS_BASE: u64 = 0xfee0_0000;
+const MSI_ADDRESS_DEST_SHIFT: u64 = 12;
+const MSI_ADDRESS_DEST_MODE_LOGICAL: u64 = 1 << 2;
+const MSI_DATA_DELIVERY_MODE_SHIFT: u64 = 8;
+const MSI_DATA_LEVEL_TRIGGERED: u64 = 1 << 15;
+
+/// The route of an IOAPIC pin to the local APICs, as an MSI.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MsiRoute {
+    /// The address the MSI is written to.
+    pub address: u64,
+    /// The data of the MSI.
+    pub data: u32,
+}
+
+/// Installs the routes of the IOAPIC pins.
+pub trait MsiRouter: Send {
+    /// Routes the pins of the IOAPIC, indexed by their GSIs, to the MSIs in `routes`. The pins
+    /// without a route are masked.
+    fn set_routes(&self, routes: &[Option<MsiRoute>]) -> io::Result<()>;
+}
+
+/// An IOAPIC whose pins are routed as MSIs.
+pub struct Ioapic {
+    id: u32,
+    reg_select: u32,
+    redirection_table: [u64; IOAPIC_NUM_PINS],
+    router: Box<dyn MsiRouter>,
+}
+
+impl Ioapic {
+    /// Creates an IOAPIC with all its pins masked, routing them through `router`.
+    pub fn new(router: Box<dyn MsiRouter>) -> Self {
+        Ioapic {
+            id: 0,
+            reg_select: 0,
+            redirection_table: [RTE_MASKED; IOAPIC_NUM_PINS],
+            router,
+        }
+    }
+
+    fn read_register(&self) -> u32 {
+        match self.reg_select {
+            REG_ID => self.id,
+            REG_VERSION => VERSION,
+            REG_ARBITRATION => self.id,
+            reg => match redirection_table_index(reg) {
+                Some((pin, high)) => {
+                    let entry = self.redirection_table[pin];
+                    if high {
+                        (entry >> 32) as u32
+                    } else {
+                        entry as u32
+                    }
+                }
+                None => 0,
+            },
+        }
+    }
+
+    fn write_register(&mut self, value: u32) {
+        match self.reg_select {
+            REG_ID => self.id = value & ID_MASK,
+            reg => {
+                if let Some((pin, high)) = redirection_table_index(reg) {
+                    let entry = &mut self.redirection_table[pin];
+                    *entry = if high {
+                        (*entry & 0xffff_ffff) | (u64::from(value) << 32)
+                    } else {
+                        (*entry & !0xffff_ffff) | u64::from(value)
+                    } & !RTE_READ_ONLY;
+                    self.update_routes();
+                }
+            }
+        }
+    }
+
+    fn update_routes(&self) {
+        let routes: Vec<_> = self
+            .redirection_table
+            .iter()
+            .map(|&e| msi_route(e))
+            .collect();
+        if let Err(e) = self.router.set_routes(&routes) {
+            error!("Failed to route the IOAPIC pins: {}", e);
+        }
+    }
+}
+
+// Returns the pin of the redirection table entry held by `reg`, and whether `reg` holds its
+// upper half.
+fn redirection_table_index(reg: u32) -> Option<(usize, bool)> {
+    let index = reg.checked_sub(REG_REDIRECTION_TABLE)? as usize;
+    if index >= 2 * IOAPIC_NUM_PINS {
+        return None;
+    }
+    Some((index / 2, index % 2 == 1))
+}
+
+// Returns the MSI the redirection table `entry` is delivered as, unless it is masked.
+fn msi_route(entry: u64) -> Option<MsiRoute> {
+    if entry & RTE_MASKED != 0 {
+        return None;
+    }
+    let mut address = MSI_ADDRESS_BASE | ((entry >> RTE_DEST_SHIFT) << MSI_ADDRESS_DEST_SHIFT);
+    if entry & RTE_DEST_MODE_LOGICAL != 0 {
+        address |= MSI_ADDRESS_DEST_MODE_LOGICAL;
+    }
+    let delivery_mode = (entry >> RTE_DELIVERY_MODE_SHIFT) & RTE_DELIVERY_MODE_MASK;
+    let mut data = (entry & RTE_VECTOR_MASK) | (delivery_mode << MSI_DATA_DELIVERY_MODE_SHIFT);
+    if entry & RTE_LEVEL_TRIGGERED != 0 {
+        data |= MSI_DATA_LEVEL_TRIGGERED;
+    }
+    Some(MsiRoute {
+        address,
+        data: data as u32,
+    })
+}
+
+impl BusDevice for Ioapic {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        if data.len() != 4 {
+            warn!("Invalid IOAPIC read of {} bytes", data.len());
+            return;
+        }
+        let value = match offset {
+            OFS_IOREGSEL => self.reg_select,
+            OFS_IOWIN => self.read_register(),
+            _ => 0,
+        };
+        data.copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        if data.len() != 4 {
+            warn!("Invalid IOAPIC write of {} bytes", data.len());
+            return;
+        }
+        let value = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        match offset {
+            OFS_IOREGSEL => self.reg_select = value & 0xff,
+            OFS_IOWIN => self.write_register(value),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct TestRouter(Arc<Mutex<Vec<Option<MsiRoute>>>>);
+
+    impl MsiRouter for TestRouter {
+        fn set_routes(&self, routes: &[Option<MsiRoute>]) -> io::Result<()> {
+            *self.0.lock().unwrap() = routes.to_vec();
+            Ok(())
+        }
+    }
+
+    fn read_reg(ioapic: &mut Ioapic, reg: u32) -> u32 {
+        ioapic.write(OFS_IOREGSEL, &reg.to_le_bytes());
+        let mut data = [0; 4];
+        ioapic.read(OFS_IOWIN, &mut data);
+        u32::from_le_bytes(data)
+    }
+
+    fn write_reg(ioapic: &mut Ioapic, reg: u32, value: u32) {
+        ioapic.write(OFS_IOREGSEL, &reg.to_le_bytes());
+        ioapic.write(OFS_IOWIN, &value.to_le_bytes());
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut ioapic = Ioapic::new(Box::new(TestRouter::default()));
+        assert_eq!(read_reg(&mut ioapic, REG_VERSION), 0x0017_0011);
+
+        write_reg(&mut ioapic, REG_ID, 0xffff_ffff);
+        assert_eq!(read_reg(&mut ioapic, REG_ID), 0x0f00_0000);
+        assert_eq!(read_reg(&mut ioapic, REG_ARBITRATION), 0x0f00_0000);
+
+        // The pins are masked until the guest programs them.
+        assert_eq!(
+            read_reg(&mut ioapic, REG_REDIRECTION_TABLE),
+            RTE_MASKED as u32
+        );
+        assert_eq!(read_reg(&mut ioapic, REG_REDIRECTION_TABLE + 1), 0);
+        // The delivery status and the remote IRR are read-only.
+        write_reg(&mut ioapic, REG_REDIRECTION_TABLE + 2, 0x0001_5030);
+        assert_eq!(
+            read_reg(&mut ioapic, REG_REDIRECTION_TABLE + 2),
+            0x0001_0030
+        );
+
+        // Past the redirection table.
+        assert_eq!(read_reg(&mut ioapic, 0x40), 0);
+        write_reg(&mut ioapic, 0x40, 0xffff_ffff);
+
+        // Only 32-bit accesses are handled.
+        let mut data = [0xff; 2];
+        ioapic.read(OFS_IOREGSEL, &mut data);
+        assert_eq!(data, [0xff; 2]);
+    }
+
+    #[test]
+    fn test_routes() {
+        let router = TestRouter::default();
+        let mut ioapic = Ioapic::new(Box::new(router.clone()));
+
+        // Pin 4 delivered to the local APIC 3 with vector 0x24, edge-triggered.
+        write_reg(&mut ioapic, REG_REDIRECTION_TABLE + 9, 0x0300_0000);
+        write_reg(&mut ioapic, REG_REDIRECTION_TABLE + 8, 0x0000_0024);
+        let routes = router.0.lock().unwrap().clone();
+        assert_eq!(routes.len(), IOAPIC_NUM_PINS);
+        assert_eq!(
+            routes[4],
+            Some(MsiRoute {
+                address: 0xfee0_3000,
+                data: 0x24,
+            })
+        );
+        assert!(routes.iter().filter(|route| route.is_some()).count() == 1);
+
+        // Pin 5 delivered in lowest priority to the logical destination 0xff, level-triggered.
+        write_reg(&mut ioapic, REG_REDIRECTION_TABLE + 11, 0xff00_0000);
+        write_reg(&mut ioapic, REG_REDIRECTION_TABLE + 10, 0x0000_8931);
+        assert_eq!(
+            router.0.lock().unwrap()[5],
+            Some(MsiRoute {
+                address: 0xfeef_f004,
+                data: 0x8131,
+            })
+        );
+
+        // Masking a pin removes its route.
+        write_reg(&mut ioapic, REG_REDIRECTION_TABLE + 8, 0x0001_0024);
+        assert_eq!(router.0.lock().unwrap()[4], None);
+    }
+}
@@ -5,13 +5,22 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+#[cfg(target_arch = "x86_64")]
+mod cmos;
 mod i8042;
+#[cfg(target_arch = "x86_64")]
+mod ioapic;
 #[cfg(target_arch = "aarch64")]
 mod rtc_pl031;
 mod serial;
 
+#[cfg(target_arch = "x86_64")]
+pub use self::cmos::Cmos;
 pub use self::i8042::Error as I8042DeviceError;
 pub use self::i8042::I8042Device;
+pub use self::i8042::{ResetRequest as I8042ResetRequest, ResetRequestSink as I8042ResetSink};
+#[cfg(target_arch = "x86_64")]
+pub use self::ioapic::{Ioapic, MsiRoute, MsiRouter, IOAPIC_NUM_PINS, IOAPIC_SIZE, IOAPIC_START};
 #[cfg(target_arch = "aarch64")]
 pub use self::rtc_pl031::RTC;
 pub use self::serial::{ReadableFd, Serial};
@@ -85,6 +85,15 @@ impl RTC {
         }
     }
 
+    /// Sets the clock to `secs`, in seconds since the Unix epoch.
+    pub fn set_time(&mut self, secs: u32) {
+        self.load = secs;
+        self.previous_now = Instant::now();
+        // If the unwrap fails, then the internal value of the clock has been corrupted and
+        // we want to terminate the execution of the process.
+        self.tick_offset = utils::time::seconds_to_nanoseconds(i64::from(secs)).unwrap();
+    }
+
     fn trigger_interrupt(&mut self) -> Result<()> {
         self.interrupt_evt.write(1).map_err(Error::InterruptFailure)
     }
@@ -106,13 +115,7 @@ impl RTC {
                 self.match_value = val;
                 METRICS.rtc.missed_write_count.inc();
             }
-            RTCLR => {
-                self.load = val;
-                self.previous_now = Instant::now();
-                // If the unwrap fails, then the internal value of the clock has been corrupted and
-                // we want to terminate the execution of the process.
-                self.tick_offset = utils::time::seconds_to_nanoseconds(i64::from(val)).unwrap();
-            }
+            RTCLR => self.set_time(val),
             RTCIMSC => {
                 self.imsc = val & 1;
                 self.trigger_interrupt()?;
@@ -271,4 +274,17 @@ mod tests {
         let index = AMBA_ID_LOW + 3;
         assert_eq!(data[0], PL031_ID[((index - AMBA_ID_LOW) >> 2) as usize]);
     }
+
+    #[test]
+    fn test_rtc_set_time() {
+        let mut rtc = RTC::new(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        let mut data = [0; 4];
+
+        rtc.set_time(1_000_000);
+        rtc.read(RTCDR, &mut data);
+        let v = byte_order::read_le_u32(&data[..]);
+        assert!(v >= 1_000_000 && v <= 1_000_001);
+        rtc.read(RTCLR, &mut data);
+        assert_eq!(byte_order::read_le_u32(&data[..]), 1_000_000);
+    }
 }
@@ -240,7 +240,9 @@ impl Serial {
         }
     }
 
-    fn raw_input(&mut self, data: &[u8]) -> io::Result<()> {
+    /// Queues `data` as input for the guest, and signals the guest it can read it. The input is
+    /// dropped when the port is in loopback mode.
+    pub fn raw_input(&mut self, data: &[u8]) -> io::Result<()> {
         if !self.is_loop() {
             self.in_buffer.extend(data);
             self.recv_data()?;
@@ -11,11 +11,14 @@ extern crate libc;
 
 extern crate dumbo;
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate logger;
 extern crate net_gen;
 extern crate polly;
 extern crate rate_limiter;
 extern crate snapshot;
+extern crate timerfd;
 extern crate versionize;
 extern crate versionize_derive;
 extern crate vm_memory;
@@ -25,6 +28,12 @@ use std::io;
 
 mod bus;
 pub mod legacy;
+#[cfg(target_arch = "x86_64")]
+pub mod pci;
+#[cfg(target_arch = "x86_64")]
+pub mod shared_memory;
+#[cfg(target_arch = "x86_64")]
+pub mod vfio;
 pub mod virtio;
 
 pub use self::bus::{Bus, BusDevice, Error as BusError};
@@ -39,6 +48,7 @@ pub(crate) fn report_net_event_fail(err: Error) {
 
 #[derive(Debug)]
 pub enum Error {
+    Balloon(virtio::balloon::Error),
     FailedReadingQueue {
         event_type: &'static str,
         underlying: io::Error,
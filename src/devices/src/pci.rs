@@ -0,0 +1,284 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Emulates the configuration space of a single PCI bus, accessed by the guest through the I/O
+//! ports of the configuration mechanism #1.
+//!
+//! The host bridge sits in slot 0, and the devices plugged in the bus in the following slots.
+//! Only function 0 of each slot is populated.
+
+use std::sync::{Arc, Mutex};
+
+use crate::bus::BusDevice;
+
+/// The first I/O port of the configuration mechanism #1: the address register at 0xcf8,
+/// followed by the data register at 0xcfc.
+pub const PCI_CONFIG_IO_PORT: u64 = 0xcf8;
+/// The number of I/O ports of the configuration mechanism #1.
+pub const PCI_CONFIG_IO_PORT_SIZE: u64 = 0x8;
+
+/// The number of slots of a PCI bus.
+pub const PCI_SLOT_COUNT: usize = 32;
+/// The number of 32-bit registers of the configuration space of a PCI function.
+pub const PCI_CONFIG_REGISTER_COUNT: usize = 64;
+
+// Offsets of the common registers of the configuration space.
+/// Offset of the command register.
+pub const PCI_COMMAND: usize = 0x04;
+/// Offset of the status register.
+pub const PCI_STATUS: usize = 0x06;
+/// Offset of the first base address register.
+pub const PCI_BASE_ADDRESS_0: usize = 0x10;
+/// Offset of the expansion ROM base address register.
+pub const PCI_ROM_ADDRESS: usize = 0x30;
+/// Offset of the pointer to the first capability.
+pub const PCI_CAPABILITY_LIST: usize = 0x34;
+/// Offset of the interrupt line register.
+pub const PCI_INTERRUPT_LINE: usize = 0x3c;
+/// Offset of the interrupt pin register.
+pub const PCI_INTERRUPT_PIN: usize = 0x3d;
+
+/// The status register bit advertising the capability list.
+pub const PCI_STATUS_CAP_LIST: u16 = 1 << 4;
+
+// Identification of the host bridge.
+const HOST_BRIDGE_VENDOR_ID: u16 = 0x8086;
+const HOST_BRIDGE_DEVICE_ID: u16 = 0x0d57;
+const PCI_CLASS_BRIDGE_HOST: u32 = 0x0600_0000;
+
+// Layout of the address register.
+const CONFIG_ADDRESS_ENABLE: u32 = 1 << 31;
+const CONFIG_ADDRESS_BUS_SHIFT: u32 = 16;
+const CONFIG_ADDRESS_SLOT_SHIFT: u32 = 11;
+const CONFIG_ADDRESS_FUNCTION_SHIFT: u32 = 8;
+const CONFIG_ADDRESS_REGISTER_MASK: u32 = 0xfc;
+
+/// A device plugged in the PCI bus, exposing its configuration space to the guest.
+pub trait PciDevice: Send {
+    /// Reads the 32-bit register at `reg_idx` of the configuration space.
+    fn read_config_register(&mut self, reg_idx: usize) -> u32;
+    /// Writes `data` at `offset` of the 32-bit register at `reg_idx` of the configuration
+    /// space.
+    fn write_config_register(&mut self, reg_idx: usize, offset: u64, data: &[u8]);
+}
+
+// The host bridge, which has no function besides identifying the bus.
+struct HostBridge;
+
+impl PciDevice for HostBridge {
+    fn read_config_register(&mut self, reg_idx: usize) -> u32 {
+        match reg_idx {
+            0 => (u32::from(HOST_BRIDGE_DEVICE_ID) << 16) | u32::from(HOST_BRIDGE_VENDOR_ID),
+            2 => PCI_CLASS_BRIDGE_HOST,
+            _ => 0,
+        }
+    }
+
+    fn write_config_register(&mut self, _reg_idx: usize, _offset: u64, _data: &[u8]) {}
+}
+
+/// The PCI bus, accessed through the I/O ports of the configuration mechanism #1.
+pub struct PciConfigIo {
+    config_address: u32,
+    devices: Vec<Arc<Mutex<dyn PciDevice>>>,
+}
+
+impl Default for PciConfigIo {
+    fn default() -> Self {
+        PciConfigIo::new()
+    }
+}
+
+impl PciConfigIo {
+    /// Creates a PCI bus holding only the host bridge.
+    pub fn new() -> Self {
+        PciConfigIo {
+            config_address: 0,
+            devices: vec![Arc::new(Mutex::new(HostBridge))],
+        }
+    }
+
+    /// Plugs `device` in the first free slot, and returns the slot number.
+    pub fn add_device(&mut self, device: Arc<Mutex<dyn PciDevice>>) -> Option<u8> {
+        if self.devices.len() >= PCI_SLOT_COUNT {
+            return None;
+        }
+        self.devices.push(device);
+        Some((self.devices.len() - 1) as u8)
+    }
+
+    // Returns the device and the register addressed by the address register, if any.
+    fn addressed_device(&self) -> Option<(&Arc<Mutex<dyn PciDevice>>, usize)> {
+        let address = self.config_address;
+        if address & CONFIG_ADDRESS_ENABLE == 0 {
+            return None;
+        }
+        let bus = (address >> CONFIG_ADDRESS_BUS_SHIFT) & 0xff;
+        let slot = (address >> CONFIG_ADDRESS_SLOT_SHIFT) & 0x1f;
+        let function = (address >> CONFIG_ADDRESS_FUNCTION_SHIFT) & 0x7;
+        if bus != 0 || function != 0 {
+            return None;
+        }
+        let reg_idx = ((address & CONFIG_ADDRESS_REGISTER_MASK) >> 2) as usize;
+        self.devices
+            .get(slot as usize)
+            .map(|device| (device, reg_idx))
+    }
+
+    fn read_config_data(&self, offset: u64, data: &mut [u8]) {
+        // Absent devices read as all ones.
+        let value = self
+            .addressed_device()
+            .map_or(0xffff_ffff, |(device, reg_idx)| {
+                device
+                    .lock()
+                    .expect("Poisoned lock")
+                    .read_config_register(reg_idx)
+            });
+        read_register_bytes(value, offset, data);
+    }
+
+    fn write_config_data(&self, offset: u64, data: &[u8]) {
+        if offset as usize + data.len() > 4 {
+            return;
+        }
+        if let Some((device, reg_idx)) = self.addressed_device() {
+            device
+                .lock()
+                .expect("Poisoned lock")
+                .write_config_register(reg_idx, offset, data);
+        }
+    }
+}
+
+/// Copies the bytes of the 32-bit register `value` starting at `offset` to `data`.
+pub fn read_register_bytes(value: u32, offset: u64, data: &mut [u8]) {
+    let bytes = value.to_le_bytes();
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte = bytes.get(offset as usize + i).copied().unwrap_or(0xff);
+    }
+}
+
+impl BusDevice for PciConfigIo {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        match offset {
+            0..=3 => read_register_bytes(self.config_address, offset, data),
+            4..=7 => self.read_config_data(offset - 4, data),
+            _ => read_register_bytes(0xffff_ffff, 0, data),
+        }
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        match offset {
+            0..=3 => {
+                // Only 32-bit writes update the address register, as the ports 0xcf8-0xcfb
+                // overlap the ports 0xcf9 (reset control) and 0xcfb of other mechanisms.
+                if offset == 0 && data.len() == 4 {
+                    let mut bytes = [0u8; 4];
+                    bytes.copy_from_slice(data);
+                    self.config_address = u32::from_le_bytes(bytes);
+                }
+            }
+            4..=7 => self.write_config_data(offset - 4, data),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct DummyDevice {
+        registers: [u32; PCI_CONFIG_REGISTER_COUNT],
+    }
+
+    impl PciDevice for DummyDevice {
+        fn read_config_register(&mut self, reg_idx: usize) -> u32 {
+            self.registers[reg_idx]
+        }
+
+        fn write_config_register(&mut self, reg_idx: usize, offset: u64, data: &[u8]) {
+            let mut bytes = self.registers[reg_idx].to_le_bytes();
+            bytes[offset as usize..offset as usize + data.len()].copy_from_slice(data);
+            self.registers[reg_idx] = u32::from_le_bytes(bytes);
+        }
+    }
+
+    fn config_address(slot: u32, function: u32, reg_offset: u32) -> [u8; 4] {
+        (CONFIG_ADDRESS_ENABLE
+            | (slot << CONFIG_ADDRESS_SLOT_SHIFT)
+            | (function << CONFIG_ADDRESS_FUNCTION_SHIFT)
+            | reg_offset)
+            .to_le_bytes()
+    }
+
+    fn read_u32(bus: &mut PciConfigIo) -> u32 {
+        let mut data = [0u8; 4];
+        bus.read(4, &mut data);
+        u32::from_le_bytes(data)
+    }
+
+    #[test]
+    fn test_pci_config_io() {
+        let mut bus = PciConfigIo::new();
+        let device = Arc::new(Mutex::new(DummyDevice {
+            registers: [0; PCI_CONFIG_REGISTER_COUNT],
+        }));
+        device.lock().unwrap().registers[0] = 0x1234_abcd;
+        assert_eq!(bus.add_device(device.clone()), Some(1));
+
+        // The address register reads back.
+        bus.write(0, &config_address(1, 0, 0));
+        let mut data = [0u8; 4];
+        bus.read(0, &mut data);
+        assert_eq!(data, config_address(1, 0, 0));
+        assert_eq!(read_u32(&mut bus), 0x1234_abcd);
+
+        // Partial accesses of the data register.
+        let mut data = [0u8; 2];
+        bus.read(6, &mut data);
+        assert_eq!(data, [0x34, 0x12]);
+        bus.write(0, &config_address(1, 0, PCI_INTERRUPT_LINE as u32));
+        bus.write(4, &[0x10]);
+        bus.write(7, &[0x20]);
+        assert_eq!(
+            device.lock().unwrap().registers[PCI_INTERRUPT_LINE >> 2],
+            0x2000_0010
+        );
+
+        // The host bridge is in slot 0.
+        bus.write(0, &config_address(0, 0, 8));
+        assert_eq!(read_u32(&mut bus), PCI_CLASS_BRIDGE_HOST);
+
+        // Absent slots and functions read as all ones.
+        bus.write(0, &config_address(2, 0, 0));
+        assert_eq!(read_u32(&mut bus), 0xffff_ffff);
+        bus.write(0, &config_address(1, 1, 0));
+        assert_eq!(read_u32(&mut bus), 0xffff_ffff);
+
+        // Accesses are ignored while the address register is disabled.
+        bus.write(0, &[0, 0, 0, 0]);
+        assert_eq!(read_u32(&mut bus), 0xffff_ffff);
+        // Byte writes don't update the address register.
+        bus.write(0, &[0x08]);
+        let mut data = [0u8; 4];
+        bus.read(0, &mut data);
+        assert_eq!(data, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_pci_config_io_slots() {
+        let mut bus = PciConfigIo::default();
+        for slot in 1..PCI_SLOT_COUNT {
+            let device = Arc::new(Mutex::new(DummyDevice {
+                registers: [0; PCI_CONFIG_REGISTER_COUNT],
+            }));
+            assert_eq!(bus.add_device(device), Some(slot as u8));
+        }
+        let device = Arc::new(Mutex::new(DummyDevice {
+            registers: [0; PCI_CONFIG_REGISTER_COUNT],
+        }));
+        assert!(bus.add_device(device).is_none());
+    }
+}
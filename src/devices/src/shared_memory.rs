@@ -0,0 +1,475 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Emulates an ivshmem-compatible PCI device, sharing a region of host memory with the guest.
+//!
+//! The shared memory is mapped at BAR2, and BAR0 holds the registers of the device:
+//! - the interrupt mask and status registers, the latter being cleared when read;
+//! - the IV position register, which reads as 0 as the guest is the only peer of the device;
+//! - the doorbell register, whose writes are forwarded to the host peer.
+//!
+//! The host peer is connected through a Unix stream socket. The device sends it the values
+//! written to the doorbell, and sets in the status register the bits of the values the peer
+//! sends back, both as 32-bit little-endian integers. The device raises its INTx interrupt as
+//! long as the status register has bits set which the mask register enables.
+
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+use logger::{Metric, METRICS};
+use polly::event_manager::{EventManager, Subscriber};
+use utils::epoll::{EpollEvent, EventSet};
+use utils::eventfd::EventFd;
+
+use crate::bus::BusDevice;
+use crate::pci::{
+    read_register_bytes, PciDevice, PCI_BASE_ADDRESS_0, PCI_COMMAND, PCI_CONFIG_REGISTER_COUNT,
+    PCI_INTERRUPT_LINE,
+};
+
+// Identification of the device, as expected by the ivshmem drivers.
+const SHARED_MEMORY_VENDOR_ID: u16 = 0x1af4;
+const SHARED_MEMORY_DEVICE_ID: u16 = 0x1110;
+const SHARED_MEMORY_REVISION: u32 = 1;
+const PCI_CLASS_MEMORY_RAM: u32 = 0x0500_0000;
+const SHARED_MEMORY_SUBSYSTEM: u32 = 0x1100_1af4;
+const PCI_SUBSYSTEM_VENDOR_ID: usize = 0x2c;
+// The device uses INTA#.
+const INTERRUPT_PIN: u8 = 1;
+
+// The BARs: the registers in a 32-bit memory BAR, and the shared memory in a prefetchable
+// 64-bit memory BAR.
+const REGISTERS_BAR_REG: usize = PCI_BASE_ADDRESS_0 >> 2;
+const MEMORY_BAR_REG: usize = (PCI_BASE_ADDRESS_0 >> 2) + 2;
+const PCI_BASE_ADDRESS_MEM_TYPE_64: u32 = 0x04;
+const PCI_BASE_ADDRESS_MEM_PREFETCH: u32 = 0x08;
+const PCI_BASE_ADDRESS_MEM_FLAGS_MASK: u32 = 0x0f;
+
+/// The size of the BAR holding the registers.
+pub const REGISTERS_BAR_SIZE: u64 = 0x100;
+
+// Offsets of the registers in BAR0.
+const INTR_MASK: u64 = 0x0;
+const INTR_STATUS: u64 = 0x4;
+const IV_POSITION: u64 = 0x8;
+const DOORBELL: u64 = 0xc;
+
+// A memory BAR, fixed at the address allocated by the VMM.
+struct Bar {
+    addr: u64,
+    size: u64,
+    flags: u32,
+    // Whether the guest is sizing the BAR.
+    sizing: bool,
+}
+
+impl Bar {
+    fn read(&self, upper: bool) -> u32 {
+        let value = if self.sizing {
+            !(self.size - 1)
+        } else {
+            self.addr
+        };
+        if upper {
+            (value >> 32) as u32
+        } else {
+            (value as u32 & !PCI_BASE_ADDRESS_MEM_FLAGS_MASK) | self.flags
+        }
+    }
+}
+
+/// An ivshmem-compatible PCI device, plugged in the PCI bus for its configuration space, in the
+/// MMIO bus for its registers, and in the event manager for its host peer and interrupt.
+pub struct SharedMemoryDevice {
+    registers_bar: Bar,
+    memory_bar: Bar,
+    command: u16,
+    interrupt_line: u8,
+    intr_mask: u32,
+    intr_status: u32,
+    trigger_evt: EventFd,
+    resample_evt: EventFd,
+    peer: Option<UnixStream>,
+    // The bytes of a partially received message of the peer.
+    peer_buf: Vec<u8>,
+}
+
+impl SharedMemoryDevice {
+    /// Creates a device whose registers are at `registers_addr` and whose `memory_size` bytes
+    /// of shared memory are at `memory_addr`. The interrupt is raised by signaling
+    /// `trigger_evt`, and re-raised when `resample_evt` gets signaled while it is still pending.
+    /// The messages are exchanged with `peer`, which has to be non-blocking.
+    pub fn new(
+        registers_addr: u64,
+        memory_addr: u64,
+        memory_size: u64,
+        trigger_evt: EventFd,
+        resample_evt: EventFd,
+        peer: Option<UnixStream>,
+    ) -> Self {
+        SharedMemoryDevice {
+            registers_bar: Bar {
+                addr: registers_addr,
+                size: REGISTERS_BAR_SIZE,
+                flags: 0,
+                sizing: false,
+            },
+            memory_bar: Bar {
+                addr: memory_addr,
+                size: memory_size,
+                flags: PCI_BASE_ADDRESS_MEM_TYPE_64 | PCI_BASE_ADDRESS_MEM_PREFETCH,
+                sizing: false,
+            },
+            command: 0,
+            interrupt_line: 0,
+            intr_mask: 0,
+            intr_status: 0,
+            trigger_evt,
+            resample_evt,
+            peer,
+            peer_buf: Vec::with_capacity(4),
+        }
+    }
+
+    /// Returns the interrupt pin of the device.
+    pub fn interrupt_pin(&self) -> u8 {
+        INTERRUPT_PIN
+    }
+
+    /// Sets the interrupt line reported to the guest.
+    pub fn set_interrupt_line(&mut self, interrupt_line: u8) {
+        self.interrupt_line = interrupt_line;
+    }
+
+    // Returns the BAR at `reg_idx`, and whether `reg_idx` is its upper half.
+    fn bar_mut(&mut self, reg_idx: usize) -> Option<(&mut Bar, bool)> {
+        match reg_idx {
+            REGISTERS_BAR_REG => Some((&mut self.registers_bar, false)),
+            MEMORY_BAR_REG => Some((&mut self.memory_bar, false)),
+            reg_idx if reg_idx == MEMORY_BAR_REG + 1 => Some((&mut self.memory_bar, true)),
+            _ => None,
+        }
+    }
+
+    // Raises the interrupt while any enabled interrupt is pending.
+    fn update_interrupt(&self) {
+        if self.intr_status & self.intr_mask != 0 {
+            if let Err(e) = self.trigger_evt.write(1) {
+                error!("Failed to signal the shared memory interrupt: {}", e);
+            }
+        }
+    }
+
+    fn ring_doorbell(&mut self, value: u32) {
+        METRICS.shared_memory.doorbell_count.inc();
+        if let Some(peer) = self.peer.as_mut() {
+            if let Err(e) = peer.write_all(&value.to_le_bytes()) {
+                warn!(
+                    "Failed to forward a doorbell to the shared memory peer: {}",
+                    e
+                );
+                METRICS.shared_memory.peer_fails.inc();
+            }
+        }
+    }
+
+    // Reads the messages of the peer, and returns whether the peer is still connected.
+    fn process_peer_input(&mut self) -> bool {
+        let peer = match self.peer.as_mut() {
+            Some(peer) => peer,
+            None => return false,
+        };
+        let mut buf = [0u8; 64];
+        loop {
+            match peer.read(&mut buf) {
+                Ok(0) => return false,
+                Ok(count) => {
+                    for &byte in &buf[..count] {
+                        self.peer_buf.push(byte);
+                        if self.peer_buf.len() == 4 {
+                            let mut bytes = [0u8; 4];
+                            bytes.copy_from_slice(&self.peer_buf);
+                            self.intr_status |= u32::from_le_bytes(bytes);
+                            self.peer_buf.clear();
+                            METRICS.shared_memory.interrupt_count.inc();
+                        }
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    warn!("Failed to read from the shared memory peer: {}", e);
+                    METRICS.shared_memory.peer_fails.inc();
+                    return false;
+                }
+            }
+        }
+        self.update_interrupt();
+        true
+    }
+}
+
+impl PciDevice for SharedMemoryDevice {
+    fn read_config_register(&mut self, reg_idx: usize) -> u32 {
+        if let Some((bar, upper)) = self.bar_mut(reg_idx) {
+            return bar.read(upper);
+        }
+        match reg_idx {
+            0 => (u32::from(SHARED_MEMORY_DEVICE_ID) << 16) | u32::from(SHARED_MEMORY_VENDOR_ID),
+            reg_idx if reg_idx == PCI_COMMAND >> 2 => u32::from(self.command),
+            2 => PCI_CLASS_MEMORY_RAM | SHARED_MEMORY_REVISION,
+            reg_idx if reg_idx == PCI_SUBSYSTEM_VENDOR_ID >> 2 => SHARED_MEMORY_SUBSYSTEM,
+            reg_idx if reg_idx == PCI_INTERRUPT_LINE >> 2 => {
+                (u32::from(INTERRUPT_PIN) << 8) | u32::from(self.interrupt_line)
+            }
+            reg_idx if reg_idx < PCI_CONFIG_REGISTER_COUNT => 0,
+            _ => 0xffff_ffff,
+        }
+    }
+
+    fn write_config_register(&mut self, reg_idx: usize, offset: u64, data: &[u8]) {
+        if let Some((bar, _)) = self.bar_mut(reg_idx) {
+            // The BARs are fixed, so only the sizing writes are taken into account.
+            bar.sizing = offset == 0 && data == [0xff; 4];
+            return;
+        }
+        let offset = offset as usize;
+        if reg_idx == PCI_COMMAND >> 2 && offset < 2 {
+            let mut bytes = self.command.to_le_bytes();
+            for (byte, &value) in bytes[offset..].iter_mut().zip(data) {
+                *byte = value;
+            }
+            self.command = u16::from_le_bytes(bytes);
+        } else if reg_idx == PCI_INTERRUPT_LINE >> 2 && offset == 0 && !data.is_empty() {
+            self.interrupt_line = data[0];
+        }
+    }
+}
+
+impl BusDevice for SharedMemoryDevice {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        let value = match offset {
+            _ if data.len() != 4 => None,
+            INTR_MASK => Some(self.intr_mask),
+            INTR_STATUS => Some(std::mem::replace(&mut self.intr_status, 0)),
+            IV_POSITION | DOORBELL => Some(0),
+            _ => None,
+        };
+        if value.is_none() {
+            METRICS.shared_memory.missed_access_count.inc();
+        }
+        read_register_bytes(value.unwrap_or(0), 0, data);
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        if data.len() != 4 {
+            METRICS.shared_memory.missed_access_count.inc();
+            return;
+        }
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(data);
+        let value = u32::from_le_bytes(bytes);
+        match offset {
+            INTR_MASK => {
+                self.intr_mask = value;
+                self.update_interrupt();
+            }
+            INTR_STATUS => {
+                self.intr_status = value;
+                self.update_interrupt();
+            }
+            DOORBELL => self.ring_doorbell(value),
+            _ => METRICS.shared_memory.missed_access_count.inc(),
+        }
+    }
+}
+
+impl Subscriber for SharedMemoryDevice {
+    // Handle the messages of the peer, and the acknowledgements of the interrupt.
+    fn process(&mut self, event: &EpollEvent, evmgr: &mut EventManager) {
+        let source = event.fd();
+        let event_set = event.event_set();
+
+        if source == self.resample_evt.as_raw_fd() {
+            // The interrupt got acknowledged, so raise it again if it's still pending.
+            let _ = self.resample_evt.read();
+            self.update_interrupt();
+            return;
+        }
+
+        let peer_fd = match self.peer.as_ref() {
+            Some(peer) if peer.as_raw_fd() == source => source,
+            _ => {
+                warn!("Shared memory: Spurious event received: {:?}", source);
+                return;
+            }
+        };
+        // The peer may have sent its last messages along with hanging up.
+        let connected = event_set.contains(EventSet::IN) && self.process_peer_input();
+        if !connected || event_set.intersects(EventSet::HANG_UP | EventSet::ERROR) {
+            warn!("Shared memory: The peer disconnected.");
+            evmgr.unregister(peer_fd).unwrap_or_else(|e| {
+                error!("Failed to unregister the shared memory peer: {:?}", e);
+            });
+            self.peer = None;
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        let mut events = vec![EpollEvent::new(
+            EventSet::IN,
+            self.resample_evt.as_raw_fd() as u64,
+        )];
+        if let Some(peer) = self.peer.as_ref() {
+            events.push(EpollEvent::new(EventSet::IN, peer.as_raw_fd() as u64));
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    const REGISTERS_ADDR: u64 = 0xf000_0000;
+    const MEMORY_ADDR: u64 = 0x1_0000_0000;
+    const MEMORY_SIZE: u64 = 0x10_0000;
+
+    fn default_device(peer: Option<UnixStream>) -> SharedMemoryDevice {
+        SharedMemoryDevice::new(
+            REGISTERS_ADDR,
+            MEMORY_ADDR,
+            MEMORY_SIZE,
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            peer,
+        )
+    }
+
+    fn read_register(device: &mut SharedMemoryDevice, offset: u64) -> u32 {
+        let mut data = [0u8; 4];
+        BusDevice::read(device, offset, &mut data);
+        u32::from_le_bytes(data)
+    }
+
+    fn write_register(device: &mut SharedMemoryDevice, offset: u64, value: u32) {
+        BusDevice::write(device, offset, &value.to_le_bytes());
+    }
+
+    #[test]
+    fn test_config_space() {
+        let mut device = default_device(None);
+        device.set_interrupt_line(5);
+
+        assert_eq!(device.read_config_register(0), 0x1110_1af4);
+        assert_eq!(device.read_config_register(2), 0x0500_0001);
+        assert_eq!(device.read_config_register(11), 0x1100_1af4);
+        assert_eq!(device.read_config_register(15), 0x0105);
+        assert_eq!(
+            device.read_config_register(PCI_CONFIG_REGISTER_COUNT),
+            0xffff_ffff
+        );
+
+        // The BARs.
+        assert_eq!(device.read_config_register(4), 0xf000_0000);
+        assert_eq!(device.read_config_register(5), 0);
+        assert_eq!(device.read_config_register(6), 0xc);
+        assert_eq!(device.read_config_register(7), 0x1);
+        device.write_config_register(4, 0, &[0xff; 4]);
+        assert_eq!(device.read_config_register(4), 0xffff_ff00);
+        device.write_config_register(4, 0, &0xf000_0000u32.to_le_bytes());
+        assert_eq!(device.read_config_register(4), 0xf000_0000);
+        device.write_config_register(6, 0, &[0xff; 4]);
+        assert_eq!(device.read_config_register(6), 0xfff0_000c);
+        device.write_config_register(7, 0, &[0xff; 4]);
+        assert_eq!(device.read_config_register(7), 0xffff_ffff);
+        device.write_config_register(7, 0, &[0x1, 0, 0, 0]);
+        assert_eq!(device.read_config_register(7), 0x1);
+
+        // The command register and the interrupt line are writable.
+        device.write_config_register(1, 0, &[0x06, 0x00]);
+        assert_eq!(device.read_config_register(1), 0x6);
+        device.write_config_register(15, 0, &[0x0a]);
+        assert_eq!(device.read_config_register(15), 0x010a);
+        // The other registers aren't.
+        device.write_config_register(0, 0, &[0; 4]);
+        assert_eq!(device.read_config_register(0), 0x1110_1af4);
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut device = default_device(None);
+
+        // Pending interrupts are only raised once enabled.
+        write_register(&mut device, INTR_STATUS, 0x3);
+        assert!(device.trigger_evt.read().is_err());
+        write_register(&mut device, INTR_MASK, 0x2);
+        assert_eq!(device.trigger_evt.read().unwrap(), 1);
+        assert_eq!(read_register(&mut device, INTR_MASK), 0x2);
+
+        // The interrupt is raised again when acknowledged while still pending.
+        device.resample_evt.write(1).unwrap();
+        let mut evmgr = EventManager::new().unwrap();
+        let event = EpollEvent::new(EventSet::IN, device.resample_evt.as_raw_fd() as u64);
+        device.process(&event, &mut evmgr);
+        assert_eq!(device.trigger_evt.read().unwrap(), 1);
+
+        // Reading the status clears it.
+        assert_eq!(read_register(&mut device, INTR_STATUS), 0x3);
+        assert_eq!(read_register(&mut device, INTR_STATUS), 0);
+        device.resample_evt.write(1).unwrap();
+        device.process(&event, &mut evmgr);
+        assert!(device.trigger_evt.read().is_err());
+
+        assert_eq!(read_register(&mut device, IV_POSITION), 0);
+        assert_eq!(read_register(&mut device, DOORBELL), 0);
+
+        // Accesses of other sizes or offsets are ignored.
+        let missed = METRICS.shared_memory.missed_access_count.count();
+        let mut data = [0xffu8; 2];
+        BusDevice::read(&mut device, INTR_MASK, &mut data);
+        assert_eq!(data, [0, 0]);
+        BusDevice::write(&mut device, INTR_MASK, &[0, 0]);
+        write_register(&mut device, 0x10, 0);
+        assert_eq!(read_register(&mut device, INTR_MASK), 0x2);
+        assert!(METRICS.shared_memory.missed_access_count.count() >= missed + 3);
+    }
+
+    #[test]
+    fn test_peer() {
+        let (peer, mut host) = UnixStream::pair().unwrap();
+        peer.set_nonblocking(true).unwrap();
+        host.set_nonblocking(true).unwrap();
+        let device = Arc::new(Mutex::new(default_device(Some(peer))));
+        let mut evmgr = EventManager::new().unwrap();
+        evmgr.add_subscriber(device.clone()).unwrap();
+        assert_eq!(device.lock().unwrap().interest_list().len(), 2);
+
+        // The doorbells are forwarded to the peer.
+        write_register(&mut device.lock().unwrap(), DOORBELL, 0x0001_0002);
+        let mut bytes = [0u8; 4];
+        host.read_exact(&mut bytes).unwrap();
+        assert_eq!(u32::from_le_bytes(bytes), 0x0001_0002);
+
+        // The messages of the peer set the status, even when split.
+        write_register(&mut device.lock().unwrap(), INTR_MASK, 0xffff_ffff);
+        host.write_all(&[0x1, 0x0, 0x0, 0x0, 0x4, 0x0]).unwrap();
+        evmgr.run_with_timeout(50).unwrap();
+        assert_eq!(read_register(&mut device.lock().unwrap(), INTR_STATUS), 0x1);
+        host.write_all(&[0x0, 0x0]).unwrap();
+        evmgr.run_with_timeout(50).unwrap();
+        assert_eq!(read_register(&mut device.lock().unwrap(), INTR_STATUS), 0x4);
+        assert!(device.lock().unwrap().trigger_evt.read().is_ok());
+
+        // The device drops the peer once it disconnects.
+        drop(host);
+        evmgr.run_with_timeout(50).unwrap();
+        assert!(device.lock().unwrap().peer.is_none());
+        assert_eq!(device.lock().unwrap().interest_list().len(), 1);
+        // The doorbells are then ignored.
+        write_register(&mut device.lock().unwrap(), DOORBELL, 0x1);
+    }
+}
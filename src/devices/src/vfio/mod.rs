@@ -0,0 +1,567 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Passes host PCI devices through to the guest, using the VFIO framework of the host kernel.
+//!
+//! The host device has to be bound to the `vfio-pci` driver beforehand. Its IOMMU group is
+//! attached to a VFIO container which maps the whole guest memory for DMA, with the guest
+//! physical addresses as I/O virtual addresses. The device regions are then either mapped in
+//! the guest physical address space or accessed through the VFIO device file.
+
+mod pci;
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::mem;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::result;
+
+use utils::eventfd::EventFd;
+use utils::ioctl::{ioctl, ioctl_with_mut_ref, ioctl_with_ptr, ioctl_with_ref, ioctl_with_val};
+use vm_memory::{GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+
+pub use self::pci::{PciBar, VfioPciBar, VfioPciDevice};
+
+/// Path to the VFIO container device.
+pub const VFIO_CONTAINER_PATH: &str = "/dev/vfio/vfio";
+/// Path to the sysfs directory of the host PCI devices.
+pub const PCI_SYSFS_DEVICES_PATH: &str = "/sys/bus/pci/devices";
+
+// VFIO ioctls, as defined in `include/uapi/linux/vfio.h`.
+const VFIO_GET_API_VERSION: u64 = 0x3b64;
+const VFIO_CHECK_EXTENSION: u64 = 0x3b65;
+const VFIO_SET_IOMMU: u64 = 0x3b66;
+const VFIO_GROUP_GET_STATUS: u64 = 0x3b67;
+const VFIO_GROUP_SET_CONTAINER: u64 = 0x3b68;
+const VFIO_GROUP_GET_DEVICE_FD: u64 = 0x3b6a;
+const VFIO_DEVICE_GET_INFO: u64 = 0x3b6b;
+const VFIO_DEVICE_GET_REGION_INFO: u64 = 0x3b6c;
+const VFIO_DEVICE_GET_IRQ_INFO: u64 = 0x3b6d;
+const VFIO_DEVICE_SET_IRQS: u64 = 0x3b6e;
+const VFIO_DEVICE_RESET: u64 = 0x3b6f;
+const VFIO_IOMMU_MAP_DMA: u64 = 0x3b71;
+
+const VFIO_API_VERSION: i32 = 0;
+const VFIO_TYPE1V2_IOMMU: u64 = 3;
+const VFIO_GROUP_FLAGS_VIABLE: u32 = 1 << 0;
+const VFIO_DEVICE_FLAGS_RESET: u32 = 1 << 0;
+const VFIO_DEVICE_FLAGS_PCI: u32 = 1 << 1;
+const VFIO_DMA_MAP_FLAG_READ: u32 = 1 << 0;
+const VFIO_DMA_MAP_FLAG_WRITE: u32 = 1 << 1;
+const VFIO_IRQ_SET_DATA_NONE: u32 = 1 << 0;
+const VFIO_IRQ_SET_DATA_EVENTFD: u32 = 1 << 2;
+const VFIO_IRQ_SET_ACTION_UNMASK: u32 = 1 << 4;
+const VFIO_IRQ_SET_ACTION_TRIGGER: u32 = 1 << 5;
+
+/// The region holding the flags of a region which can be mapped with `mmap`.
+pub const VFIO_REGION_INFO_FLAG_MMAP: u32 = 1 << 2;
+/// The index of the configuration space region of a PCI device.
+pub const VFIO_PCI_CONFIG_REGION_INDEX: u32 = 7;
+/// The index of the INTx interrupt of a PCI device.
+pub const VFIO_PCI_INTX_IRQ_INDEX: u32 = 0;
+
+// Mirrors `struct vfio_group_status`.
+#[repr(C)]
+#[derive(Default)]
+struct VfioGroupStatus {
+    argsz: u32,
+    flags: u32,
+}
+
+// Mirrors `struct vfio_device_info`.
+#[repr(C)]
+#[derive(Default)]
+struct VfioDeviceInfo {
+    argsz: u32,
+    flags: u32,
+    num_regions: u32,
+    num_irqs: u32,
+}
+
+// Mirrors `struct vfio_region_info`.
+#[repr(C)]
+#[derive(Default)]
+struct VfioRegionInfo {
+    argsz: u32,
+    flags: u32,
+    index: u32,
+    cap_offset: u32,
+    size: u64,
+    offset: u64,
+}
+
+// Mirrors `struct vfio_irq_info`.
+#[repr(C)]
+#[derive(Default)]
+struct VfioIrqInfo {
+    argsz: u32,
+    flags: u32,
+    index: u32,
+    count: u32,
+}
+
+// Mirrors `struct vfio_irq_set`, with the flexible data array holding at most one eventfd.
+#[repr(C)]
+#[derive(Default)]
+struct VfioIrqSet {
+    argsz: u32,
+    flags: u32,
+    index: u32,
+    start: u32,
+    count: u32,
+    data: [RawFd; 1],
+}
+
+// Mirrors `struct vfio_iommu_type1_dma_map`.
+#[repr(C)]
+#[derive(Default)]
+struct VfioIommuType1DmaMap {
+    argsz: u32,
+    flags: u32,
+    vaddr: u64,
+    iova: u64,
+    size: u64,
+}
+
+/// Errors associated with the VFIO devices.
+#[derive(Debug)]
+pub enum Error {
+    /// The host device is not a PCI device.
+    NotPciDevice,
+    /// Cannot open the VFIO container or group device.
+    OpenVfioDevice(PathBuf, io::Error),
+    /// Cannot find the IOMMU group of the host device, which is not bound to `vfio-pci`.
+    IommuGroup(PathBuf, io::Error),
+    /// Some devices of the IOMMU group are not bound to `vfio-pci`.
+    GroupNotViable(u32),
+    /// The VFIO API version or the type 1 IOMMU are not supported by the host kernel.
+    UnsupportedApi,
+    /// A VFIO ioctl failed.
+    VfioIoctl(&'static str, io::Error),
+    /// Cannot access a region of the device.
+    RegionAccess(io::Error),
+    /// Cannot map a region of the device.
+    RegionMmap(io::Error),
+    /// Cannot create an EventFd.
+    EventFd(io::Error),
+    /// The device has no space left for its BARs in the guest physical address space.
+    BarAllocation(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match self {
+            NotPciDevice => write!(f, "The VFIO device is not a PCI device."),
+            OpenVfioDevice(path, err) => write!(f, "Cannot open {}: {}", path.display(), err),
+            IommuGroup(path, err) => write!(
+                f,
+                "Cannot find the IOMMU group of {}, is it bound to vfio-pci? {}",
+                path.display(),
+                err
+            ),
+            GroupNotViable(group) => write!(
+                f,
+                "The IOMMU group {} is not viable: all its devices have to be bound to vfio-pci.",
+                group
+            ),
+            UnsupportedApi => write!(f, "The host kernel doesn't support the VFIO type 1 IOMMU."),
+            VfioIoctl(name, err) => write!(f, "The {} ioctl failed: {}", name, err),
+            RegionAccess(err) => write!(f, "Cannot access the device region: {}", err),
+            RegionMmap(err) => write!(f, "Cannot map the device region: {}", err),
+            EventFd(err) => write!(f, "Cannot create an EventFd: {}", err),
+            BarAllocation(index) => write!(
+                f,
+                "Cannot place the BAR {} in the guest physical address space.",
+                index
+            ),
+        }
+    }
+}
+
+type Result<T> = result::Result<T, Error>;
+
+fn ioctl_result(name: &'static str, ret: i32) -> Result<i32> {
+    if ret < 0 {
+        return Err(Error::VfioIoctl(name, io::Error::last_os_error()));
+    }
+    Ok(ret)
+}
+
+/// Returns the sysfs path of the host PCI device `bdf`, e.g. `0000:01:00.0`.
+pub fn pci_sysfs_path(bdf: &str) -> PathBuf {
+    Path::new(PCI_SYSFS_DEVICES_PATH).join(bdf)
+}
+
+/// Returns the IOMMU group of the host device at `sysfs_path`.
+pub fn iommu_group(sysfs_path: &Path) -> Result<u32> {
+    let link = fs::read_link(sysfs_path.join("iommu_group"))
+        .map_err(|e| Error::IommuGroup(sysfs_path.to_path_buf(), e))?;
+    link.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.parse().ok())
+        .ok_or_else(|| {
+            Error::IommuGroup(
+                sysfs_path.to_path_buf(),
+                io::Error::from(io::ErrorKind::InvalidData),
+            )
+        })
+}
+
+/// A VFIO container, holding the IOMMU groups of the passthrough devices, and mapping the
+/// guest memory for the DMAs of these devices.
+pub struct VfioContainer {
+    container: File,
+    // The groups attached to the container, by ID.
+    groups: HashMap<u32, File>,
+}
+
+impl VfioContainer {
+    /// Opens a new VFIO container.
+    pub fn new() -> Result<VfioContainer> {
+        let container = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(VFIO_CONTAINER_PATH)
+            .map_err(|e| Error::OpenVfioDevice(PathBuf::from(VFIO_CONTAINER_PATH), e))?;
+
+        // Safe because the ioctl doesn't take any argument.
+        let version = unsafe { ioctl(&container, VFIO_GET_API_VERSION) };
+        // Safe because the argument is passed by value.
+        let type1 = unsafe { ioctl_with_val(&container, VFIO_CHECK_EXTENSION, VFIO_TYPE1V2_IOMMU) };
+        if version != VFIO_API_VERSION || type1 != 1 {
+            return Err(Error::UnsupportedApi);
+        }
+
+        Ok(VfioContainer {
+            container,
+            groups: HashMap::new(),
+        })
+    }
+
+    // Opens and attaches the IOMMU group `group_id`, unless it is already attached. The IOMMU
+    // gets set up and maps the guest memory when the first group is attached.
+    fn attach_group(&mut self, group_id: u32, guest_mem: &GuestMemoryMmap) -> Result<&File> {
+        if !self.groups.contains_key(&group_id) {
+            let path = PathBuf::from(format!("/dev/vfio/{}", group_id));
+            let group = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .map_err(|e| Error::OpenVfioDevice(path, e))?;
+
+            let mut status = VfioGroupStatus {
+                argsz: mem::size_of::<VfioGroupStatus>() as u32,
+                flags: 0,
+            };
+            // Safe because the kernel only writes the status, which is sized by `argsz`.
+            ioctl_result("VFIO_GROUP_GET_STATUS", unsafe {
+                ioctl_with_mut_ref(&group, VFIO_GROUP_GET_STATUS, &mut status)
+            })?;
+            if status.flags & VFIO_GROUP_FLAGS_VIABLE == 0 {
+                return Err(Error::GroupNotViable(group_id));
+            }
+
+            let container_fd = self.container.as_raw_fd();
+            // Safe because the kernel only reads the container file descriptor.
+            ioctl_result("VFIO_GROUP_SET_CONTAINER", unsafe {
+                ioctl_with_ref(&group, VFIO_GROUP_SET_CONTAINER, &container_fd)
+            })?;
+
+            if self.groups.is_empty() {
+                // Safe because the argument is passed by value.
+                ioctl_result("VFIO_SET_IOMMU", unsafe {
+                    ioctl_with_val(&self.container, VFIO_SET_IOMMU, VFIO_TYPE1V2_IOMMU)
+                })?;
+                self.map_guest_memory(guest_mem)?;
+            }
+            self.groups.insert(group_id, group);
+        }
+        Ok(&self.groups[&group_id])
+    }
+
+    // Maps the guest memory for DMA, with the guest physical addresses as I/O virtual
+    // addresses. The kernel pins the guest memory as a side effect.
+    fn map_guest_memory(&self, guest_mem: &GuestMemoryMmap) -> Result<()> {
+        guest_mem.with_regions(|_, region| {
+            let dma_map = VfioIommuType1DmaMap {
+                argsz: mem::size_of::<VfioIommuType1DmaMap>() as u32,
+                flags: VFIO_DMA_MAP_FLAG_READ | VFIO_DMA_MAP_FLAG_WRITE,
+                vaddr: region.as_ptr() as u64,
+                iova: region.start_addr().0,
+                size: region.len(),
+            };
+            // Safe because the kernel only reads the mapping, which covers guest memory owned
+            // by the process.
+            ioctl_result("VFIO_IOMMU_MAP_DMA", unsafe {
+                ioctl_with_ref(&self.container, VFIO_IOMMU_MAP_DMA, &dma_map)
+            })
+            .map(|_| ())
+        })
+    }
+
+    /// Opens the host PCI device at `sysfs_path`, attaching its IOMMU group to the container.
+    pub fn open_device(
+        &mut self,
+        sysfs_path: &Path,
+        guest_mem: &GuestMemoryMmap,
+    ) -> Result<VfioDevice> {
+        let group_id = iommu_group(sysfs_path)?;
+        let group = self.attach_group(group_id, guest_mem)?;
+
+        let name = sysfs_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| CString::new(name).ok())
+            .ok_or(Error::NotPciDevice)?;
+        // Safe because the kernel only reads the null terminated device name.
+        let fd = ioctl_result("VFIO_GROUP_GET_DEVICE_FD", unsafe {
+            ioctl_with_ptr(group, VFIO_GROUP_GET_DEVICE_FD, name.as_ptr())
+        })?;
+        // Safe because the file descriptor was just returned by the kernel, and is owned by
+        // the new file.
+        let device = unsafe { File::from_raw_fd(fd) };
+
+        VfioDevice::new(device)
+    }
+}
+
+/// A region of a VFIO device, such as a BAR or the configuration space of a PCI device.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct VfioRegion {
+    /// The `VFIO_REGION_INFO_FLAG_*` flags of the region.
+    pub flags: u32,
+    /// The size of the region.
+    pub size: u64,
+    /// The offset of the region in the device file.
+    pub offset: u64,
+}
+
+/// A host device opened through VFIO.
+pub struct VfioDevice {
+    device: File,
+    flags: u32,
+    regions: Vec<VfioRegion>,
+    intx_count: u32,
+}
+
+impl VfioDevice {
+    fn new(device: File) -> Result<VfioDevice> {
+        let mut info = VfioDeviceInfo {
+            argsz: mem::size_of::<VfioDeviceInfo>() as u32,
+            ..Default::default()
+        };
+        // Safe because the kernel only writes the info, which is sized by `argsz`.
+        ioctl_result("VFIO_DEVICE_GET_INFO", unsafe {
+            ioctl_with_mut_ref(&device, VFIO_DEVICE_GET_INFO, &mut info)
+        })?;
+        if info.flags & VFIO_DEVICE_FLAGS_PCI == 0
+            || info.num_regions <= VFIO_PCI_CONFIG_REGION_INDEX
+        {
+            return Err(Error::NotPciDevice);
+        }
+
+        let mut regions = Vec::with_capacity(info.num_regions as usize);
+        for index in 0..info.num_regions {
+            let mut region_info = VfioRegionInfo {
+                argsz: mem::size_of::<VfioRegionInfo>() as u32,
+                index,
+                ..Default::default()
+            };
+            // Safe because the kernel only writes the info, which is sized by `argsz`. Some
+            // regions are not implemented by all devices, and are then left empty.
+            let ret = unsafe {
+                ioctl_with_mut_ref(&device, VFIO_DEVICE_GET_REGION_INFO, &mut region_info)
+            };
+            regions.push(if ret < 0 {
+                VfioRegion::default()
+            } else {
+                VfioRegion {
+                    flags: region_info.flags,
+                    size: region_info.size,
+                    offset: region_info.offset,
+                }
+            });
+        }
+
+        let mut irq_info = VfioIrqInfo {
+            argsz: mem::size_of::<VfioIrqInfo>() as u32,
+            index: VFIO_PCI_INTX_IRQ_INDEX,
+            ..Default::default()
+        };
+        // Safe because the kernel only writes the info, which is sized by `argsz`.
+        let intx_count = if info.num_irqs > VFIO_PCI_INTX_IRQ_INDEX
+            && unsafe { ioctl_with_mut_ref(&device, VFIO_DEVICE_GET_IRQ_INFO, &mut irq_info) } >= 0
+        {
+            irq_info.count
+        } else {
+            0
+        };
+
+        Ok(VfioDevice {
+            device,
+            flags: info.flags,
+            regions,
+            intx_count,
+        })
+    }
+
+    /// Returns the region at `index`, if the device implements it.
+    pub fn region(&self, index: u32) -> Option<&VfioRegion> {
+        self.regions
+            .get(index as usize)
+            .filter(|region| region.size > 0)
+    }
+
+    /// Returns whether the device can signal INTx interrupts.
+    pub fn has_intx(&self) -> bool {
+        self.intx_count > 0
+    }
+
+    /// Reads `data` at `offset` of the region at `index`.
+    pub fn read_region(&self, index: u32, offset: u64, data: &mut [u8]) -> Result<()> {
+        let region = self
+            .region(index)
+            .ok_or_else(|| Error::RegionAccess(io::Error::from(io::ErrorKind::NotFound)))?;
+        self.device
+            .read_exact_at(data, region.offset + offset)
+            .map_err(Error::RegionAccess)
+    }
+
+    /// Writes `data` at `offset` of the region at `index`.
+    pub fn write_region(&self, index: u32, offset: u64, data: &[u8]) -> Result<()> {
+        let region = self
+            .region(index)
+            .ok_or_else(|| Error::RegionAccess(io::Error::from(io::ErrorKind::NotFound)))?;
+        self.device
+            .write_all_at(data, region.offset + offset)
+            .map_err(Error::RegionAccess)
+    }
+
+    /// Maps the region at `index` in the process address space, and returns the address of the
+    /// mapping. The caller owns the mapping.
+    pub fn mmap_region(&self, index: u32) -> Result<*mut u8> {
+        let region = self
+            .region(index)
+            .ok_or_else(|| Error::RegionMmap(io::Error::from(io::ErrorKind::NotFound)))?;
+        // Safe because a new mapping is created, and the result is checked.
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                region.size as usize,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                self.device.as_raw_fd(),
+                region.offset as libc::off_t,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(Error::RegionMmap(io::Error::last_os_error()));
+        }
+        Ok(addr as *mut u8)
+    }
+
+    /// Signals the INTx interrupts of the device to `trigger_evt`. The interrupt is masked
+    /// until `unmask_evt` gets signaled, once the guest acknowledged it.
+    pub fn enable_intx(&self, trigger_evt: &EventFd, unmask_evt: &EventFd) -> Result<()> {
+        self.set_irq_eventfd(VFIO_IRQ_SET_ACTION_TRIGGER, trigger_evt.as_raw_fd())?;
+        self.set_irq_eventfd(VFIO_IRQ_SET_ACTION_UNMASK, unmask_evt.as_raw_fd())
+    }
+
+    fn set_irq_eventfd(&self, action: u32, fd: RawFd) -> Result<()> {
+        let irq_set = VfioIrqSet {
+            argsz: mem::size_of::<VfioIrqSet>() as u32,
+            flags: VFIO_IRQ_SET_DATA_EVENTFD | action,
+            index: VFIO_PCI_INTX_IRQ_INDEX,
+            start: 0,
+            count: 1,
+            data: [fd],
+        };
+        // Safe because the kernel only reads the request, which is sized by `argsz`.
+        ioctl_result("VFIO_DEVICE_SET_IRQS", unsafe {
+            ioctl_with_ref(&self.device, VFIO_DEVICE_SET_IRQS, &irq_set)
+        })
+        .map(|_| ())
+    }
+
+    /// Stops signaling the INTx interrupts.
+    pub fn disable_intx(&self) -> Result<()> {
+        let irq_set = VfioIrqSet {
+            argsz: (mem::size_of::<VfioIrqSet>() - mem::size_of::<RawFd>()) as u32,
+            flags: VFIO_IRQ_SET_DATA_NONE | VFIO_IRQ_SET_ACTION_TRIGGER,
+            index: VFIO_PCI_INTX_IRQ_INDEX,
+            start: 0,
+            count: 0,
+            data: [0],
+        };
+        // Safe because the kernel only reads the request, which is sized by `argsz`.
+        ioctl_result("VFIO_DEVICE_SET_IRQS", unsafe {
+            ioctl_with_ref(&self.device, VFIO_DEVICE_SET_IRQS, &irq_set)
+        })
+        .map(|_| ())
+    }
+
+    /// Resets the device, if it supports it, so that the guest finds it in its initial state.
+    pub fn reset(&self) -> Result<()> {
+        if self.flags & VFIO_DEVICE_FLAGS_RESET == 0 {
+            return Ok(());
+        }
+        // Safe because the ioctl doesn't take any argument.
+        ioctl_result("VFIO_DEVICE_RESET", unsafe {
+            ioctl(&self.device, VFIO_DEVICE_RESET)
+        })
+        .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uapi_sizes() {
+        // The sizes the kernel expects in `argsz`.
+        assert_eq!(mem::size_of::<VfioGroupStatus>(), 8);
+        assert_eq!(mem::size_of::<VfioDeviceInfo>(), 16);
+        assert_eq!(mem::size_of::<VfioRegionInfo>(), 32);
+        assert_eq!(mem::size_of::<VfioIrqInfo>(), 16);
+        assert_eq!(mem::size_of::<VfioIrqSet>(), 24);
+        assert_eq!(mem::size_of::<VfioIommuType1DmaMap>(), 32);
+    }
+
+    #[test]
+    fn test_iommu_group() {
+        let tmp_dir = utils::tempdir::TempDir::new().unwrap();
+        let sysfs_path = tmp_dir.as_path().join("0000:01:00.0");
+        fs::create_dir(&sysfs_path).unwrap();
+        match iommu_group(&sysfs_path) {
+            Err(Error::IommuGroup(_, _)) => (),
+            _ => panic!("The device has no IOMMU group."),
+        }
+
+        std::os::unix::fs::symlink(
+            "../../../kernel/iommu_groups/42",
+            sysfs_path.join("iommu_group"),
+        )
+        .unwrap();
+        assert_eq!(iommu_group(&sysfs_path).unwrap(), 42);
+
+        assert_eq!(
+            pci_sysfs_path("0000:01:00.0"),
+            PathBuf::from("/sys/bus/pci/devices/0000:01:00.0")
+        );
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let err = Error::VfioIoctl("VFIO_DEVICE_RESET", io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = Error::GroupNotViable(42);
+        let _ = format!("{}{:?}", err, err);
+    }
+}
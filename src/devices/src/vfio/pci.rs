@@ -0,0 +1,385 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Exposes a host PCI device opened through VFIO on the emulated PCI bus.
+//!
+//! The configuration space is read from the host device, except for the registers the VMM owns:
+//! the BARs, which are placed at fixed guest physical addresses, the expansion ROM, hidden from
+//! the guest, and the interrupt line. The device interrupts are forwarded as INTx interrupts, so
+//! the MSI and MSI-X capabilities are hidden from the guest as well.
+
+use std::sync::Arc;
+
+use utils::eventfd::EventFd;
+
+use super::{Error, Result, VfioDevice, VFIO_PCI_CONFIG_REGION_INDEX, VFIO_REGION_INFO_FLAG_MMAP};
+use crate::bus::BusDevice;
+use crate::pci::{
+    read_register_bytes, PciDevice, PCI_BASE_ADDRESS_0, PCI_CAPABILITY_LIST,
+    PCI_CONFIG_REGISTER_COUNT, PCI_INTERRUPT_LINE, PCI_INTERRUPT_PIN, PCI_ROM_ADDRESS, PCI_STATUS,
+    PCI_STATUS_CAP_LIST,
+};
+
+// The number of BARs of a PCI device.
+const PCI_BAR_COUNT: usize = 6;
+// The size of the configuration space of a PCI device.
+const PCI_CONFIG_SPACE_SIZE: usize = PCI_CONFIG_REGISTER_COUNT * 4;
+// The capabilities live after the standard header.
+const PCI_CAPABILITY_MIN_OFFSET: u8 = 0x40;
+
+// Layout of a memory BAR.
+const PCI_BASE_ADDRESS_SPACE_IO: u32 = 0x01;
+const PCI_BASE_ADDRESS_MEM_TYPE_64: u32 = 0x04;
+const PCI_BASE_ADDRESS_MEM_FLAGS_MASK: u32 = 0x0f;
+
+// The capabilities hidden from the guest.
+const PCI_CAP_ID_MSI: u8 = 0x05;
+const PCI_CAP_ID_MSIX: u8 = 0x11;
+
+// The BARs smaller than a page are trapped, as KVM maps memory with a page granularity.
+const PAGE_SIZE: u64 = 4096;
+
+/// A memory BAR of a passthrough device, placed in the guest physical address space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PciBar {
+    /// The index of the BAR, which is also the index of its VFIO region.
+    pub index: u32,
+    /// The guest physical address of the BAR.
+    pub addr: u64,
+    /// The size of the BAR.
+    pub size: u64,
+    /// Whether the BAR can be mapped in the guest physical address space, rather than trapped.
+    pub mmappable: bool,
+    // The read-only flags of the BAR register.
+    flags: u32,
+    // Whether the guest is sizing the BAR.
+    sizing: bool,
+}
+
+impl PciBar {
+    fn is_64bit(&self) -> bool {
+        self.flags & PCI_BASE_ADDRESS_MEM_TYPE_64 != 0
+    }
+}
+
+/// A host PCI device passed through to the guest.
+pub struct VfioPciDevice {
+    device: Arc<VfioDevice>,
+    bars: Vec<PciBar>,
+    // The capability pointer and the next pointers of the capabilities visible to the guest,
+    // by capability offset.
+    cap_pointer: u8,
+    cap_next: Vec<(u8, u8)>,
+    interrupt_pin: u8,
+    interrupt_line: u8,
+}
+
+impl VfioPciDevice {
+    /// Resets `device` and places its memory BARs at the guest physical addresses returned by
+    /// `allocate_bar`, which is called with the size of each BAR and whether it is a 64-bit BAR.
+    pub fn new(
+        device: VfioDevice,
+        allocate_bar: &mut dyn FnMut(u64, bool) -> Option<u64>,
+    ) -> Result<VfioPciDevice> {
+        device.reset()?;
+
+        let mut config = [0u8; PCI_CONFIG_SPACE_SIZE];
+        device.read_region(VFIO_PCI_CONFIG_REGION_INDEX, 0, &mut config)?;
+
+        let mut bars = Vec::new();
+        let mut index = 0;
+        while index < PCI_BAR_COUNT {
+            let bar_reg = config_u32(&config, PCI_BASE_ADDRESS_0 + index * 4);
+            let is_io = bar_reg & PCI_BASE_ADDRESS_SPACE_IO != 0;
+            let is_64bit = !is_io && bar_reg & PCI_BASE_ADDRESS_MEM_TYPE_64 != 0;
+            // The I/O BARs are hidden from the guest.
+            match device.region(index as u32) {
+                Some(region) if !is_io => {
+                    let size = region.size.next_power_of_two();
+                    let addr =
+                        allocate_bar(size, is_64bit).ok_or(Error::BarAllocation(index as u32))?;
+                    bars.push(PciBar {
+                        index: index as u32,
+                        addr,
+                        size,
+                        mmappable: region.flags & VFIO_REGION_INFO_FLAG_MMAP != 0
+                            && region.size == size
+                            && size % PAGE_SIZE == 0,
+                        flags: bar_reg & PCI_BASE_ADDRESS_MEM_FLAGS_MASK,
+                        sizing: false,
+                    });
+                }
+                _ => (),
+            }
+            index += if is_64bit { 2 } else { 1 };
+        }
+
+        let (cap_pointer, cap_next) = visible_capabilities(&config);
+        let interrupt_pin = if device.has_intx() {
+            config[PCI_INTERRUPT_PIN]
+        } else {
+            0
+        };
+
+        Ok(VfioPciDevice {
+            device: Arc::new(device),
+            bars,
+            cap_pointer,
+            cap_next,
+            interrupt_pin,
+            interrupt_line: 0,
+        })
+    }
+
+    /// Returns the memory BARs of the device.
+    pub fn bars(&self) -> &[PciBar] {
+        &self.bars
+    }
+
+    /// Returns the bus device trapping the guest accesses to `bar`.
+    pub fn bar_bus_device(&self, bar: &PciBar) -> VfioPciBar {
+        VfioPciBar {
+            device: self.device.clone(),
+            index: bar.index,
+        }
+    }
+
+    /// Maps `bar` in the process address space, and returns the address of the mapping.
+    pub fn mmap_bar(&self, bar: &PciBar) -> Result<*mut u8> {
+        self.device.mmap_region(bar.index)
+    }
+
+    /// Returns the INTx pin of the device (1 for INTA# to 4 for INTD#), if it has one.
+    pub fn interrupt_pin(&self) -> Option<u8> {
+        match self.interrupt_pin {
+            1..=4 => Some(self.interrupt_pin),
+            _ => None,
+        }
+    }
+
+    /// Routes the INTx interrupt of the device to the interrupt line `gsi`. The interrupts are
+    /// signaled to `trigger_evt`, and unmasked by the host once `resample_evt` is signaled.
+    pub fn enable_intx(
+        &mut self,
+        gsi: u8,
+        trigger_evt: &EventFd,
+        resample_evt: &EventFd,
+    ) -> Result<()> {
+        self.device.enable_intx(trigger_evt, resample_evt)?;
+        self.interrupt_line = gsi;
+        Ok(())
+    }
+
+    // Returns the BAR whose lower register, or upper register for a 64-bit BAR, is `reg_idx`.
+    fn bar_mut(&mut self, reg_idx: usize) -> Option<(&mut PciBar, bool)> {
+        let bar_idx = reg_idx.checked_sub(PCI_BASE_ADDRESS_0 >> 2)?;
+        self.bars.iter_mut().find_map(|bar| {
+            if bar.index as usize == bar_idx {
+                Some((bar, false))
+            } else if bar.is_64bit() && bar.index as usize + 1 == bar_idx {
+                Some((bar, true))
+            } else {
+                None
+            }
+        })
+    }
+
+    fn read_device_register(&self, reg_idx: usize) -> u32 {
+        let mut data = [0u8; 4];
+        match self.device.read_region(
+            VFIO_PCI_CONFIG_REGION_INDEX,
+            (reg_idx * 4) as u64,
+            &mut data,
+        ) {
+            Ok(()) => u32::from_le_bytes(data),
+            Err(e) => {
+                error!(
+                    "Failed to read the configuration space of a VFIO device: {}",
+                    e
+                );
+                0xffff_ffff
+            }
+        }
+    }
+}
+
+impl PciDevice for VfioPciDevice {
+    fn read_config_register(&mut self, reg_idx: usize) -> u32 {
+        if reg_idx >= PCI_CONFIG_REGISTER_COUNT {
+            return 0xffff_ffff;
+        }
+        if (PCI_BASE_ADDRESS_0 >> 2..PCI_ROM_ADDRESS >> 2).contains(&reg_idx) {
+            return self.bar_mut(reg_idx).map_or(0, |(bar, upper)| {
+                let value = if bar.sizing {
+                    !(bar.size - 1)
+                } else {
+                    bar.addr
+                };
+                if upper {
+                    (value >> 32) as u32
+                } else {
+                    (value as u32 & !PCI_BASE_ADDRESS_MEM_FLAGS_MASK) | bar.flags
+                }
+            });
+        }
+        if reg_idx == PCI_ROM_ADDRESS >> 2 {
+            return 0;
+        }
+
+        let mut bytes = self.read_device_register(reg_idx).to_le_bytes();
+        if reg_idx == PCI_STATUS >> 2 && self.cap_pointer == 0 {
+            let status = u16::from_le_bytes([bytes[2], bytes[3]]) & !PCI_STATUS_CAP_LIST;
+            bytes[2..].copy_from_slice(&status.to_le_bytes());
+        }
+        if reg_idx == PCI_CAPABILITY_LIST >> 2 {
+            bytes[0] = self.cap_pointer;
+        }
+        if reg_idx == PCI_INTERRUPT_LINE >> 2 {
+            bytes[0] = self.interrupt_line;
+            bytes[1] = self.interrupt_pin;
+        }
+        for &(offset, next) in self.cap_next.iter() {
+            if offset as usize >> 2 == reg_idx {
+                bytes[(offset & 0x3) as usize + 1] = next;
+            }
+        }
+        u32::from_le_bytes(bytes)
+    }
+
+    fn write_config_register(&mut self, reg_idx: usize, offset: u64, data: &[u8]) {
+        if reg_idx >= PCI_CONFIG_REGISTER_COUNT {
+            return;
+        }
+        if (PCI_BASE_ADDRESS_0 >> 2..PCI_ROM_ADDRESS >> 2).contains(&reg_idx) {
+            // The BARs are fixed, so only the sizing writes are taken into account.
+            if let Some((bar, _)) = self.bar_mut(reg_idx) {
+                bar.sizing = offset == 0 && data == [0xff; 4];
+            }
+            return;
+        }
+        if reg_idx == PCI_ROM_ADDRESS >> 2 || reg_idx == PCI_INTERRUPT_LINE >> 2 {
+            return;
+        }
+
+        if let Err(e) = self.device.write_region(
+            VFIO_PCI_CONFIG_REGION_INDEX,
+            (reg_idx * 4) as u64 + offset,
+            data,
+        ) {
+            error!(
+                "Failed to write the configuration space of a VFIO device: {}",
+                e
+            );
+        }
+    }
+}
+
+/// Traps the guest accesses to a BAR which can't be mapped in the guest physical address space.
+pub struct VfioPciBar {
+    device: Arc<VfioDevice>,
+    index: u32,
+}
+
+impl BusDevice for VfioPciBar {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        if let Err(e) = self.device.read_region(self.index, offset, data) {
+            error!(
+                "Failed to read the BAR {} of a VFIO device: {}",
+                self.index, e
+            );
+            read_register_bytes(0xffff_ffff, 0, data);
+        }
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        if let Err(e) = self.device.write_region(self.index, offset, data) {
+            error!(
+                "Failed to write the BAR {} of a VFIO device: {}",
+                self.index, e
+            );
+        }
+    }
+}
+
+fn config_u32(config: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&config[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+// Walks the capability list of `config`, skipping the MSI and MSI-X capabilities. Returns the
+// capability pointer and the next pointer of each visible capability, by capability offset.
+fn visible_capabilities(config: &[u8]) -> (u8, Vec<(u8, u8)>) {
+    let status = u16::from_le_bytes([config[PCI_STATUS], config[PCI_STATUS + 1]]);
+    if status & PCI_STATUS_CAP_LIST == 0 {
+        return (0, Vec::new());
+    }
+
+    let mut visible = Vec::new();
+    let mut offset = config[PCI_CAPABILITY_LIST] & !0x3;
+    // Bound the walk, in case the list loops.
+    for _ in 0..PCI_CONFIG_REGISTER_COUNT {
+        if offset < PCI_CAPABILITY_MIN_OFFSET {
+            break;
+        }
+        let id = config[offset as usize];
+        if id != PCI_CAP_ID_MSI && id != PCI_CAP_ID_MSIX {
+            visible.push(offset);
+        }
+        offset = config[offset as usize + 1] & !0x3;
+    }
+
+    let cap_pointer = visible.first().copied().unwrap_or(0);
+    let cap_next = visible
+        .iter()
+        .enumerate()
+        .map(|(i, &offset)| (offset, visible.get(i + 1).copied().unwrap_or(0)))
+        .collect();
+    (cap_pointer, cap_next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_capability(config: &mut [u8], offset: u8, id: u8, next: u8) {
+        config[offset as usize] = id;
+        config[offset as usize + 1] = next;
+    }
+
+    #[test]
+    fn test_visible_capabilities() {
+        let mut config = [0u8; PCI_CONFIG_SPACE_SIZE];
+        assert_eq!(visible_capabilities(&config), (0, vec![]));
+
+        config[PCI_STATUS] = PCI_STATUS_CAP_LIST as u8;
+        config[PCI_CAPABILITY_LIST] = 0x40;
+        add_capability(&mut config, 0x40, 0x01, 0x50);
+        add_capability(&mut config, 0x50, PCI_CAP_ID_MSI, 0x60);
+        add_capability(&mut config, 0x60, 0x10, 0x70);
+        add_capability(&mut config, 0x70, PCI_CAP_ID_MSIX, 0x00);
+        assert_eq!(
+            visible_capabilities(&config),
+            (0x40, vec![(0x40, 0x60), (0x60, 0x00)])
+        );
+
+        // The first capability is hidden.
+        config[PCI_CAPABILITY_LIST] = 0x50;
+        assert_eq!(visible_capabilities(&config), (0x60, vec![(0x60, 0x00)]));
+
+        // Only hidden capabilities.
+        config[PCI_CAPABILITY_LIST] = 0x70;
+        assert_eq!(visible_capabilities(&config), (0, vec![]));
+
+        // A looping list is walked a bounded number of times.
+        add_capability(&mut config, 0x70, PCI_CAP_ID_MSIX, 0x70);
+        assert_eq!(visible_capabilities(&config), (0, vec![]));
+    }
+
+    #[test]
+    fn test_config_u32() {
+        let config = [0x78, 0x56, 0x34, 0x12, 0xff];
+        assert_eq!(config_u32(&config, 0), 0x1234_5678);
+        assert_eq!(config_u32(&config, 1), 0xff12_3456);
+    }
+}
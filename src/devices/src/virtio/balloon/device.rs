@@ -0,0 +1,651 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::cmp;
+use std::io::Write;
+use std::result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use logger::{Metric, METRICS};
+use utils::byte_order;
+use utils::eventfd::EventFd;
+use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
+use vm_memory::{Address, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+
+use super::super::{
+    dirty_pages, ActivateError, ActivateResult, DeviceState, Queue, VirtioDevice,
+    VIRTIO_MMIO_INT_CONFIG, VIRTIO_MMIO_INT_VRING,
+};
+use super::{
+    BalloonEvent, BalloonEventSink, Error, Result, BALLOON_DEV_ID, CONFIG_SPACE_SIZE,
+    DEFLATE_INDEX, INFLATE_INDEX, MAX_NUM_QUEUES, MIB_TO_4K_PAGES, MIN_NUM_QUEUES, QUEUE_SIZE,
+    REPORTING_INDEX, TYPE_BALLOON, VIRTIO_BALLOON_F_DEFLATE_ON_OOM, VIRTIO_BALLOON_F_REPORTING,
+    VIRTIO_BALLOON_PAGE_SIZE, VIRTIO_BALLOON_PFN_SHIFT,
+};
+
+use crate::Error as DeviceError;
+
+// Size of a page frame number, as exchanged over the inflate and deflate queues.
+const PFN_SIZE: u32 = 4;
+
+/// The balloon device configuration space, as described by the virtio spec.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BalloonConfigSpace {
+    /// Number of 4 KiB pages the host wants the guest to give up.
+    pub num_pages: u32,
+    /// Number of 4 KiB pages the guest has actually given up, as reported by the driver.
+    pub actual_pages: u32,
+}
+
+impl BalloonConfigSpace {
+    fn to_bytes(&self) -> [u8; CONFIG_SPACE_SIZE] {
+        let mut config = [0u8; CONFIG_SPACE_SIZE];
+        byte_order::write_le_u32(&mut config[0..4], self.num_pages);
+        byte_order::write_le_u32(&mut config[4..8], self.actual_pages);
+        config
+    }
+}
+
+fn mib_to_pages(amount_mib: u32) -> Result<u32> {
+    amount_mib
+        .checked_mul(MIB_TO_4K_PAGES)
+        .ok_or(Error::TooManyPagesRequested)
+}
+
+fn pages_to_kib(pages: u64) -> u64 {
+    pages * (VIRTIO_BALLOON_PAGE_SIZE >> 10)
+}
+
+/// Gives the memory backing the guest range `[addr, addr + len)` back to the host.
+/// The guest will find zeroed pages when accessing this range again.
+pub(crate) fn discard_range(mem: &GuestMemoryMmap, addr: GuestAddress, len: u64) -> Result<()> {
+    if len == 0 {
+        return Ok(());
+    }
+    // The whole range has to be backed by a single contiguous host mapping.
+    let in_single_region = mem
+        .find_region(addr)
+        .and_then(|region| {
+            addr.checked_add(len - 1)
+                .map(|last| last <= region.last_addr())
+        })
+        .unwrap_or(false);
+    if !in_single_region {
+        return Err(Error::GuestMemory(
+            vm_memory::GuestMemoryError::InvalidGuestAddress(addr),
+        ));
+    }
+    let host_addr = mem.get_host_address(addr).map_err(Error::GuestMemory)?;
+
+    // Safe because the range is entirely within a guest memory region, which we own.
+    let ret = unsafe {
+        libc::madvise(
+            host_addr as *mut libc::c_void,
+            len as usize,
+            libc::MADV_DONTNEED,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::Discard(std::io::Error::last_os_error()));
+    }
+    // The pages discarded read as zeroes from now on.
+    dirty_pages::mark(mem, addr, len);
+    Ok(())
+}
+
+/// Virtio device which lets the host reclaim guest memory, with the guest driver's cooperation.
+pub struct Balloon {
+    // Virtio fields.
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+    pub(crate) config_space: BalloonConfigSpace,
+    pub(crate) activate_evt: EventFd,
+
+    // Transport related fields.
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) queue_evts: Vec<EventFd>,
+    pub(crate) interrupt_status: Arc<AtomicUsize>,
+    interrupt_evt: EventFd,
+    pub(crate) device_state: DeviceState,
+
+    // Implementation specific fields.
+    // Number of pages currently held by the balloon, as seen on the inflate and deflate queues.
+    pub(crate) inflated_pages: u64,
+    event_sink: Option<BalloonEventSink>,
+}
+
+impl Balloon {
+    /// Creates a new balloon device, asking the guest for `amount_mib` of its memory.
+    pub fn new(
+        amount_mib: u32,
+        deflate_on_oom: bool,
+        free_page_reporting: bool,
+    ) -> Result<Balloon> {
+        let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
+        if deflate_on_oom {
+            avail_features |= 1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM;
+        }
+        let num_queues = if free_page_reporting {
+            avail_features |= 1u64 << VIRTIO_BALLOON_F_REPORTING;
+            MAX_NUM_QUEUES
+        } else {
+            MIN_NUM_QUEUES
+        };
+
+        let mut queue_evts = Vec::with_capacity(num_queues);
+        for _ in 0..num_queues {
+            queue_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
+        }
+
+        Ok(Balloon {
+            avail_features,
+            acked_features: 0u64,
+            config_space: BalloonConfigSpace {
+                num_pages: mib_to_pages(amount_mib)?,
+                actual_pages: 0,
+            },
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queues: (0..num_queues).map(|_| Queue::new(QUEUE_SIZE)).collect(),
+            queue_evts,
+            interrupt_status: Arc::new(AtomicUsize::new(0)),
+            interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            device_state: DeviceState::Inactive,
+            inflated_pages: 0,
+            event_sink: None,
+        })
+    }
+
+    /// Provides the ID of the balloon device.
+    pub fn id(&self) -> &str {
+        BALLOON_DEV_ID
+    }
+
+    /// Returns the target size of the balloon, in MiB.
+    pub fn size_mib(&self) -> u32 {
+        self.config_space.num_pages / MIB_TO_4K_PAGES
+    }
+
+    /// Returns the size of the balloon reported by the guest driver, in MiB.
+    pub fn actual_mib(&self) -> u32 {
+        self.config_space.actual_pages / MIB_TO_4K_PAGES
+    }
+
+    /// Specifies if the guest is allowed to deflate the balloon when running out of memory.
+    pub fn deflate_on_oom(&self) -> bool {
+        self.avail_features & (1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM) != 0
+    }
+
+    /// Specifies if the guest may report its free pages through the balloon.
+    pub fn free_page_reporting(&self) -> bool {
+        self.avail_features & (1u64 << VIRTIO_BALLOON_F_REPORTING) != 0
+    }
+
+    /// Sets the callback through which the device surfaces `BalloonEvent`s.
+    pub fn set_event_sink(&mut self, event_sink: BalloonEventSink) {
+        self.event_sink = Some(event_sink);
+    }
+
+    /// Changes the target size of the balloon and lets the guest driver know about it.
+    pub fn update_size(&mut self, amount_mib: u32) -> result::Result<(), DeviceError> {
+        self.config_space.num_pages = mib_to_pages(amount_mib).map_err(DeviceError::Balloon)?;
+        if self.is_activated() {
+            self.signal(VIRTIO_MMIO_INT_CONFIG)?;
+        }
+        Ok(())
+    }
+
+    fn emit(&self, event: BalloonEvent) {
+        if let Some(sink) = self.event_sink.as_ref() {
+            sink(event);
+        }
+    }
+
+    pub(crate) fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
+        self.signal(VIRTIO_MMIO_INT_VRING)
+    }
+
+    fn signal(&self, int_type: u32) -> result::Result<(), DeviceError> {
+        self.interrupt_status
+            .fetch_or(int_type as usize, Ordering::SeqCst);
+        self.interrupt_evt.write(1).map_err(|e| {
+            error!("Failed to signal balloon interrupt: {:?}", e);
+            METRICS.balloon.event_fails.inc();
+            DeviceError::FailedSignalingUsedQueue(e)
+        })
+    }
+
+    // Pops all the descriptor chains of the inflate or deflate queue, returning the page frame
+    // numbers they carry.
+    fn pop_pfns(&mut self, queue_index: usize) -> (Vec<u32>, bool) {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem,
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        let queue = &mut self.queues[queue_index];
+        let mut pfns = Vec::new();
+        let mut used_any = false;
+
+        while let Some(head) = queue.pop(mem) {
+            if head.is_write_only() {
+                error!("balloon: {:?}", Error::UnexpectedWriteOnlyDescriptor);
+                METRICS.balloon.event_fails.inc();
+            } else if head.len % PFN_SIZE != 0 {
+                error!("balloon: {:?}", Error::DescriptorLengthInvalid);
+                METRICS.balloon.event_fails.inc();
+            } else {
+                for offset in (0..head.len).step_by(PFN_SIZE as usize) {
+                    match head
+                        .addr
+                        .checked_add(u64::from(offset))
+                        .ok_or_else(|| vm_memory::GuestMemoryError::InvalidGuestAddress(head.addr))
+                        .and_then(|addr| mem.read_obj::<u32>(addr))
+                    {
+                        // The pages out of the guest memory are neither discarded nor counted.
+                        Ok(pfn)
+                            if mem.address_in_range(GuestAddress(
+                                u64::from(pfn) << VIRTIO_BALLOON_PFN_SHIFT,
+                            )) =>
+                        {
+                            pfns.push(pfn)
+                        }
+                        Ok(pfn) => {
+                            error!("balloon: PFN {:#x} is out of the guest memory", pfn);
+                            METRICS.balloon.event_fails.inc();
+                        }
+                        Err(e) => {
+                            error!("balloon: {:?}", Error::GuestMemory(e));
+                            METRICS.balloon.event_fails.inc();
+                            break;
+                        }
+                    }
+                }
+            }
+            queue.add_used(mem, head.index, 0);
+            used_any = true;
+        }
+
+        (pfns, used_any)
+    }
+
+    pub(crate) fn process_inflate_queue(&mut self) -> bool {
+        let (mut pfns, used_any) = self.pop_pfns(INFLATE_INDEX);
+        if pfns.is_empty() {
+            return used_any;
+        }
+        pfns.sort_unstable();
+        pfns.dedup();
+        METRICS.balloon.inflated_pages.add(pfns.len());
+        self.inflated_pages += pfns.len() as u64;
+
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem,
+            DeviceState::Inactive => unreachable!(),
+        };
+        // Discard contiguous runs of pages at once, to save on syscalls.
+        let mut run_start = pfns[0];
+        let mut run_len = 1u64;
+        for window in pfns.windows(2) {
+            if window[1] == window[0] + 1 {
+                run_len += 1;
+                continue;
+            }
+            self.discard_pfn_run(mem, run_start, run_len);
+            run_start = window[1];
+            run_len = 1;
+        }
+        self.discard_pfn_run(mem, run_start, run_len);
+
+        used_any
+    }
+
+    fn discard_pfn_run(&self, mem: &GuestMemoryMmap, first_pfn: u32, num_pages: u64) {
+        let addr = GuestAddress(u64::from(first_pfn) << VIRTIO_BALLOON_PFN_SHIFT);
+        if let Err(e) = discard_range(mem, addr, num_pages * VIRTIO_BALLOON_PAGE_SIZE) {
+            error!("balloon: failed to discard inflated pages: {:?}", e);
+            METRICS.balloon.discard_fails.inc();
+        }
+    }
+
+    pub(crate) fn process_deflate_queue(&mut self) -> bool {
+        let (pfns, used_any) = self.pop_pfns(DEFLATE_INDEX);
+        if pfns.is_empty() {
+            return used_any;
+        }
+        // There's nothing to do memory-wise, the pages will be faulted back in on access.
+        METRICS.balloon.deflated_pages.add(pfns.len());
+        self.inflated_pages = self.inflated_pages.saturating_sub(pfns.len() as u64);
+        self.emit(BalloonEvent::Deflated {
+            amount_kib: pages_to_kib(pfns.len() as u64),
+            balloon_kib: pages_to_kib(self.inflated_pages),
+        });
+
+        used_any
+    }
+
+    pub(crate) fn process_reporting_queue(&mut self) -> bool {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem,
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        let queue = &mut self.queues[REPORTING_INDEX];
+        let mut reported_bytes = 0u64;
+        let mut used_any = false;
+
+        while let Some(head) = queue.pop(mem) {
+            let head_index = head.index;
+            let mut next_desc = Some(head);
+            // Each descriptor of the chain describes a free guest memory range.
+            while let Some(desc) = next_desc {
+                match discard_range(mem, desc.addr, u64::from(desc.len)) {
+                    Ok(()) => reported_bytes += u64::from(desc.len),
+                    Err(e) => {
+                        error!("balloon: failed to discard reported pages: {:?}", e);
+                        METRICS.balloon.discard_fails.inc();
+                    }
+                }
+                next_desc = desc.next_descriptor();
+            }
+            queue.add_used(mem, head_index, 0);
+            used_any = true;
+        }
+
+        if reported_bytes > 0 {
+            let reported_pages = reported_bytes / VIRTIO_BALLOON_PAGE_SIZE;
+            METRICS.balloon.reported_pages.add(reported_pages as usize);
+            self.emit(BalloonEvent::FreePagesReported {
+                amount_kib: reported_bytes >> 10,
+            });
+        }
+
+        used_any
+    }
+}
+
+impl VirtioDevice for Balloon {
+    fn device_type(&self) -> u32 {
+        TYPE_BALLOON
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.interrupt_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.interrupt_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) {
+        let config_space = self.config_space.to_bytes();
+        let config_len = config_space.len() as u64;
+        if offset >= config_len {
+            error!("Failed to read balloon config space");
+            METRICS.balloon.cfg_fails.inc();
+            return;
+        }
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            // This write can't fail, offset and end are checked against config_len.
+            data.write_all(&config_space[offset as usize..cmp::min(end, config_len) as usize])
+                .unwrap();
+        }
+    }
+
+    fn config_space_bytes(&self) -> Vec<u8> {
+        self.config_space.to_bytes().to_vec()
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        // The driver may only write the `actual` field.
+        if offset != 4 || data.len() != 4 {
+            error!("Failed to write balloon config space");
+            METRICS.balloon.cfg_fails.inc();
+            return;
+        }
+        self.config_space.actual_pages = byte_order::read_le_u32(data);
+    }
+
+    fn is_activated(&self) -> bool {
+        match self.device_state {
+            DeviceState::Inactive => false,
+            DeviceState::Activated(_) => true,
+        }
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
+        if self.activate_evt.write(1).is_err() {
+            error!("Balloon: Cannot write to activate_evt");
+            METRICS.balloon.activate_fails.inc();
+            return Err(ActivateError::BadActivate);
+        }
+        self.device_state = DeviceState::Activated(mem);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::virtio::queue::tests::*;
+
+    impl Balloon {
+        pub(crate) fn set_queue(&mut self, idx: usize, q: Queue) {
+            self.queues[idx] = q;
+        }
+    }
+
+    pub fn default_mem() -> GuestMemoryMmap {
+        GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap()
+    }
+
+    // Returns a balloon device whose events get recorded in the returned vector.
+    pub fn balloon_with_events(
+        free_page_reporting: bool,
+    ) -> (Balloon, Arc<Mutex<Vec<BalloonEvent>>>) {
+        let mut balloon = Balloon::new(0, true, free_page_reporting).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = events.clone();
+        balloon.set_event_sink(Box::new(move |event| {
+            events_clone.lock().unwrap().push(event)
+        }));
+        (balloon, events)
+    }
+
+    // Places the PFNs in guest memory at `addr` and makes them available on `vq`.
+    pub fn push_pfns(vq: &VirtQueue, mem: &GuestMemoryMmap, addr: u64, pfns: &[u32]) {
+        for (i, pfn) in pfns.iter().enumerate() {
+            mem.write_obj::<u32>(*pfn, GuestAddress(addr + 4 * i as u64))
+                .unwrap();
+        }
+        let idx = vq.avail.idx.get();
+        vq.dtable[idx as usize].set(addr, 4 * pfns.len() as u32, 0, 0);
+        vq.avail.ring[idx as usize].set(idx);
+        vq.avail.idx.set(idx + 1);
+    }
+
+    #[test]
+    fn test_virtio_features() {
+        let balloon = Balloon::new(0, false, false).unwrap();
+        assert_eq!(balloon.device_type(), TYPE_BALLOON);
+        assert_eq!(balloon.avail_features(), 1u64 << VIRTIO_F_VERSION_1);
+        assert_eq!(balloon.queues().len(), MIN_NUM_QUEUES);
+        assert_eq!(balloon.queue_events().len(), MIN_NUM_QUEUES);
+        assert!(!balloon.deflate_on_oom());
+        assert!(!balloon.free_page_reporting());
+
+        let balloon = Balloon::new(0, true, true).unwrap();
+        assert_eq!(
+            balloon.avail_features(),
+            1u64 << VIRTIO_F_VERSION_1
+                | 1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM
+                | 1u64 << VIRTIO_BALLOON_F_REPORTING
+        );
+        assert_eq!(balloon.queues().len(), MAX_NUM_QUEUES);
+        assert!(balloon.deflate_on_oom());
+        assert!(balloon.free_page_reporting());
+    }
+
+    #[test]
+    fn test_virtio_config() {
+        let mut balloon = Balloon::new(16, false, false).unwrap();
+        assert_eq!(balloon.size_mib(), 16);
+
+        let mut data = [0u8; 8];
+        balloon.read_config(0, &mut data);
+        assert_eq!(byte_order::read_le_u32(&data[0..4]), 16 * MIB_TO_4K_PAGES);
+        assert_eq!(byte_order::read_le_u32(&data[4..8]), 0);
+
+        // The driver reports the actual size of the balloon.
+        balloon.write_config(4, &[0, 1, 0, 0]);
+        assert_eq!(balloon.config_space.actual_pages, 256);
+        assert_eq!(balloon.actual_mib(), 1);
+
+        // Writes to the target size or outside the config space are ignored.
+        balloon.write_config(0, &[0, 0, 0, 0]);
+        balloon.write_config(8, &[0, 0, 0, 0]);
+        assert_eq!(balloon.size_mib(), 16);
+
+        // Reads outside the config space don't mutate the buffer.
+        let mut data = [0xffu8; 4];
+        balloon.read_config(8, &mut data);
+        assert_eq!(data, [0xffu8; 4]);
+
+        assert!(Balloon::new(std::u32::MAX, false, false).is_err());
+    }
+
+    #[test]
+    fn test_update_size() {
+        let mut balloon = Balloon::new(0, false, false).unwrap();
+        // The guest is not notified before the device is activated.
+        balloon.update_size(32).unwrap();
+        assert_eq!(balloon.size_mib(), 32);
+        assert!(balloon.interrupt_evt().read().is_err());
+
+        balloon.activate(default_mem()).unwrap();
+        balloon.update_size(64).unwrap();
+        assert_eq!(balloon.size_mib(), 64);
+        assert_eq!(balloon.interrupt_evt().read().unwrap(), 1);
+        assert_eq!(
+            balloon.interrupt_status().load(Ordering::SeqCst),
+            VIRTIO_MMIO_INT_CONFIG as usize
+        );
+
+        match balloon.update_size(std::u32::MAX) {
+            Err(DeviceError::Balloon(Error::TooManyPagesRequested)) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_inflate_deflate() {
+        let mem = default_mem();
+        let (mut balloon, events) = balloon_with_events(false);
+        let infq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        let defq = VirtQueue::new(GuestAddress(0x1000), &mem, 16);
+        balloon.set_queue(INFLATE_INDEX, infq.create_queue());
+        balloon.set_queue(DEFLATE_INDEX, defq.create_queue());
+        balloon.activate(mem.clone()).unwrap();
+
+        // Dirty the pages which are going to be inflated.
+        mem.write_obj::<u64>(0xdead_beef, GuestAddress(0x8000))
+            .unwrap();
+        mem.write_obj::<u64>(0xdead_beef, GuestAddress(0xa000))
+            .unwrap();
+
+        push_pfns(&infq, &mem, 0x4000, &[8, 9, 10, 0xa]);
+        assert!(balloon.process_inflate_queue());
+        assert_eq!(infq.used.idx.get(), 1);
+        // Duplicate PFNs are only accounted once.
+        assert_eq!(balloon.inflated_pages, 3);
+        // The inflated pages have been discarded.
+        assert_eq!(mem.read_obj::<u64>(GuestAddress(0x8000)).unwrap(), 0);
+        assert_eq!(mem.read_obj::<u64>(GuestAddress(0xa000)).unwrap(), 0);
+        // Inflating doesn't raise any event.
+        assert!(events.lock().unwrap().is_empty());
+
+        // PFNs outside of guest memory are skipped.
+        push_pfns(&infq, &mem, 0x4000, &[0x100, 0xb]);
+        assert!(balloon.process_inflate_queue());
+        assert_eq!(balloon.inflated_pages, 4);
+        push_pfns(&infq, &mem, 0x4000, &[0x100]);
+        assert!(balloon.process_inflate_queue());
+        assert_eq!(balloon.inflated_pages, 4);
+
+        push_pfns(&defq, &mem, 0x5000, &[8, 9]);
+        assert!(balloon.process_deflate_queue());
+        assert_eq!(defq.used.idx.get(), 1);
+        assert_eq!(
+            events.lock().unwrap().as_slice(),
+            &[BalloonEvent::Deflated {
+                amount_kib: 8,
+                balloon_kib: 8,
+            }]
+        );
+
+        // Nothing left to process.
+        assert!(!balloon.process_inflate_queue());
+        assert!(!balloon.process_deflate_queue());
+    }
+
+    #[test]
+    fn test_free_page_reporting() {
+        let mem = default_mem();
+        let (mut balloon, events) = balloon_with_events(true);
+        let repq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        balloon.set_queue(REPORTING_INDEX, repq.create_queue());
+        balloon.activate(mem.clone()).unwrap();
+
+        mem.write_obj::<u64>(0xdead_beef, GuestAddress(0x8000))
+            .unwrap();
+        // Report two free ranges, in a single descriptor chain.
+        repq.dtable[0].set(0x8000, 0x2000, VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE, 1);
+        repq.dtable[1].set(0xc000, 0x1000, VIRTQ_DESC_F_WRITE, 0);
+        repq.avail.ring[0].set(0);
+        repq.avail.idx.set(1);
+
+        assert!(balloon.process_reporting_queue());
+        assert_eq!(repq.used.idx.get(), 1);
+        assert_eq!(mem.read_obj::<u64>(GuestAddress(0x8000)).unwrap(), 0);
+        assert_eq!(
+            events.lock().unwrap().as_slice(),
+            &[BalloonEvent::FreePagesReported { amount_kib: 12 }]
+        );
+    }
+
+    #[test]
+    fn test_discard_range() {
+        let mem = default_mem();
+        assert!(discard_range(&mem, GuestAddress(0), 0x1000).is_ok());
+        assert!(discard_range(&mem, GuestAddress(0x1000), 0).is_ok());
+        // The range has to be within guest memory.
+        assert!(discard_range(&mem, GuestAddress(0xf000), 0x2000).is_err());
+        assert!(discard_range(&mem, GuestAddress(0x10000), 0x1000).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::os::unix::io::AsRawFd;
+
+use logger::{Metric, METRICS};
+use polly::event_manager::{EventManager, Subscriber};
+use utils::epoll::{EpollEvent, EventSet};
+
+use crate::virtio::balloon::device::Balloon;
+use crate::virtio::balloon::{DEFLATE_INDEX, INFLATE_INDEX, REPORTING_INDEX};
+use crate::virtio::VirtioDevice;
+
+impl Balloon {
+    fn process_activate_event(&self, event_manager: &mut EventManager) {
+        // The subscriber must exist as we previously registered activate_evt via
+        // `interest_list()`.
+        let self_subscriber = event_manager
+            .subscriber(self.activate_evt.as_raw_fd())
+            .unwrap();
+
+        for queue_evt in self.queue_evts.iter() {
+            event_manager
+                .register(
+                    queue_evt.as_raw_fd(),
+                    EpollEvent::new(EventSet::IN, queue_evt.as_raw_fd() as u64),
+                    self_subscriber.clone(),
+                )
+                .unwrap_or_else(|e| {
+                    error!(
+                        "Failed to register balloon queue with event manager: {:?}",
+                        e
+                    );
+                });
+        }
+
+        event_manager
+            .unregister(self.activate_evt.as_raw_fd())
+            .unwrap_or_else(|e| {
+                error!("Failed to unregister balloon activate evt: {:?}", e);
+            })
+    }
+
+    fn process_queue_event(&mut self, queue_index: usize) {
+        if let Err(e) = self.queue_evts[queue_index].read() {
+            error!("Failed to get balloon queue event: {:?}", e);
+            METRICS.balloon.event_fails.inc();
+            return;
+        }
+
+        let used_any = match queue_index {
+            INFLATE_INDEX => self.process_inflate_queue(),
+            DEFLATE_INDEX => self.process_deflate_queue(),
+            REPORTING_INDEX => self.process_reporting_queue(),
+            _ => false,
+        };
+        if used_any {
+            let _ = self.signal_used_queue();
+        }
+    }
+}
+
+impl Subscriber for Balloon {
+    // Handle an event for the inflate, deflate or free page reporting queues.
+    fn process(&mut self, event: &EpollEvent, evmgr: &mut EventManager) {
+        let source = event.fd();
+        let event_set = event.event_set();
+
+        let supported_events = EventSet::IN;
+        if !supported_events.contains(event_set) {
+            warn!(
+                "Balloon: Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
+
+        if self.is_activated() {
+            let activate_fd = self.activate_evt.as_raw_fd();
+            if let Some(queue_index) = self
+                .queue_evts
+                .iter()
+                .position(|evt| evt.as_raw_fd() == source)
+            {
+                self.process_queue_event(queue_index);
+            } else if activate_fd == source {
+                self.process_activate_event(evmgr);
+            } else {
+                warn!("Balloon: Spurious event received: {:?}", source);
+            }
+        } else {
+            warn!(
+                "Balloon: The device is not yet activated. Spurious event received: {:?}",
+                source
+            );
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        vec![EpollEvent::new(
+            EventSet::IN,
+            self.activate_evt.as_raw_fd() as u64,
+        )]
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::virtio::balloon::device::tests::*;
+    use crate::virtio::balloon::BalloonEvent;
+    use crate::virtio::queue::tests::*;
+    use vm_memory::GuestAddress;
+
+    #[test]
+    fn test_event_handler() {
+        let mut event_manager = EventManager::new().unwrap();
+        let mem = default_mem();
+        let (mut balloon, events) = balloon_with_events(false);
+        let defq = VirtQueue::new(GuestAddress(0x1000), &mem, 16);
+        balloon.set_queue(DEFLATE_INDEX, defq.create_queue());
+        push_pfns(&defq, &mem, 0x4000, &[1, 2, 3]);
+
+        let balloon = Arc::new(Mutex::new(balloon));
+        event_manager.add_subscriber(balloon.clone()).unwrap();
+
+        // Trigger the queue event. It is not processed before activation.
+        balloon.lock().unwrap().queue_evts[DEFLATE_INDEX]
+            .write(1)
+            .unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 0);
+
+        // Now activate the device, which registers the queue events.
+        balloon.lock().unwrap().activate(mem.clone()).unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+
+        // Handle the pending queue event through EventManager.
+        event_manager
+            .run_with_timeout(100)
+            .expect("Balloon event timeout or error.");
+        assert_eq!(balloon.lock().unwrap().interrupt_evt().read().unwrap(), 1);
+        assert_eq!(defq.used.idx.get(), 1);
+        assert_eq!(
+            events.lock().unwrap().as_slice(),
+            &[BalloonEvent::Deflated {
+                amount_kib: 12,
+                balloon_kib: 0,
+            }]
+        );
+    }
+}
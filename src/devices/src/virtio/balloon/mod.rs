@@ -0,0 +1,80 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod device;
+pub mod event_handler;
+pub mod persist;
+
+pub use self::device::{Balloon, BalloonConfigSpace};
+pub use self::event_handler::*;
+
+use vm_memory::GuestMemoryError;
+
+/// Device ID used in MMIO device identification.
+/// Because the balloon is unique per-vm, this ID can be hardcoded.
+pub const BALLOON_DEV_ID: &str = "balloon";
+/// Virtio balloon device ID, as defined in `include/uapi/linux/virtio_ids.h`.
+pub const TYPE_BALLOON: u32 = 5;
+pub const CONFIG_SPACE_SIZE: usize = 8;
+pub const QUEUE_SIZE: u16 = 256;
+/// The inflate and deflate queues are always present, the free page reporting one is optional.
+pub const MIN_NUM_QUEUES: usize = 2;
+pub const MAX_NUM_QUEUES: usize = 3;
+pub const INFLATE_INDEX: usize = 0;
+pub const DEFLATE_INDEX: usize = 1;
+pub const REPORTING_INDEX: usize = 2;
+
+/// The page frame numbers exchanged over the inflate and deflate queues always refer to
+/// 4 KiB pages, regardless of the guest page size.
+pub const VIRTIO_BALLOON_PFN_SHIFT: u64 = 12;
+pub const VIRTIO_BALLOON_PAGE_SIZE: u64 = 1 << VIRTIO_BALLOON_PFN_SHIFT;
+pub const MIB_TO_4K_PAGES: u32 = 256;
+
+/// Virtio balloon feature bits, as defined in `include/uapi/linux/virtio_balloon.h`.
+///
+/// The guest deflates the balloon under memory pressure, instead of triggering the OOM killer.
+pub const VIRTIO_BALLOON_F_DEFLATE_ON_OOM: u32 = 2;
+/// The guest reports its free pages to the device.
+pub const VIRTIO_BALLOON_F_REPORTING: u32 = 5;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Activation error.
+    Activate(super::ActivateError),
+    /// Guest gave us a descriptor with an unexpected length.
+    DescriptorLengthInvalid,
+    /// Guest gave us a write only descriptor that protocol says to read from.
+    UnexpectedWriteOnlyDescriptor,
+    /// Guest gave us bad memory addresses.
+    GuestMemory(GuestMemoryError),
+    /// Failed to discard the memory backing some guest pages.
+    Discard(std::io::Error),
+    /// Failed to create or signal an event fd.
+    EventFd(std::io::Error),
+    /// The requested balloon size exceeds the supported limit.
+    TooManyPagesRequested,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Events emitted by the balloon device, describing how the guest is using its memory.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BalloonEvent {
+    /// The guest driver deflated the balloon, taking back `amount_kib` of memory. When the
+    /// deflate-on-OOM feature is negotiated, this happens on the guest's own initiative whenever
+    /// it is under memory pressure.
+    Deflated {
+        /// Amount of memory released by the balloon.
+        amount_kib: u64,
+        /// Amount of memory still held by the balloon.
+        balloon_kib: u64,
+    },
+    /// The guest driver reported `amount_kib` of free memory, which was handed back to the host.
+    FreePagesReported {
+        /// Amount of memory reported as free.
+        amount_kib: u64,
+    },
+}
+
+/// Callback used by the balloon device to surface `BalloonEvent`s.
+pub type BalloonEventSink = Box<dyn Fn(BalloonEvent) + Send>;
@@ -0,0 +1,132 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the structures needed for saving/restoring balloon devices.
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use snapshot::Persist;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use vm_memory::GuestMemoryMmap;
+
+use super::*;
+
+use crate::virtio::persist::VirtioDeviceState;
+use crate::virtio::{DeviceState, Queue};
+
+#[derive(Versionize)]
+pub struct BalloonState {
+    num_pages: u32,
+    actual_pages: u32,
+    inflated_pages: u64,
+    virtio_state: VirtioDeviceState,
+}
+
+pub struct BalloonConstructorArgs {
+    pub mem: GuestMemoryMmap,
+}
+
+impl Persist for Balloon {
+    type State = BalloonState;
+    type ConstructorArgs = BalloonConstructorArgs;
+    type Error = Error;
+
+    fn save(&self) -> Self::State {
+        BalloonState {
+            num_pages: self.config_space.num_pages,
+            actual_pages: self.config_space.actual_pages,
+            inflated_pages: self.inflated_pages,
+            virtio_state: VirtioDeviceState::from_device(self),
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> std::result::Result<Self, Self::Error> {
+        let avail_features = state.virtio_state.avail_features;
+        let mut balloon = Balloon::new(
+            0,
+            avail_features & (1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM) != 0,
+            avail_features & (1u64 << VIRTIO_BALLOON_F_REPORTING) != 0,
+        )?;
+
+        // Safe to unwrap because Queue::restore() cannot fail.
+        balloon.queues = state
+            .virtio_state
+            .queues
+            .iter()
+            .map(|queue_state| Queue::restore((), &queue_state).unwrap())
+            .collect();
+        balloon.interrupt_status = Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
+        balloon.avail_features = avail_features;
+        balloon.acked_features = state.virtio_state.acked_features;
+        balloon.config_space = BalloonConfigSpace {
+            num_pages: state.num_pages,
+            actual_pages: state.actual_pages,
+        };
+        balloon.inflated_pages = state.inflated_pages;
+
+        if state.virtio_state.activated {
+            balloon.device_state = DeviceState::Activated(constructor_args.mem);
+            // Make the event handler register the queue events once it gets subscribed.
+            balloon.activate_evt.write(1).map_err(Error::EventFd)?;
+        }
+
+        Ok(balloon)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtio::balloon::device::tests::default_mem;
+    use crate::virtio::device::VirtioDevice;
+
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_persistence() {
+        let guest_mem = default_mem();
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+
+        let mut balloon = Balloon::new(64, true, true).unwrap();
+        balloon.activate(guest_mem.clone()).unwrap();
+        balloon.config_space.actual_pages = 128;
+        balloon.inflated_pages = 128;
+
+        // Save the balloon device.
+        <Balloon as Persist>::save(&balloon)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        // Restore the balloon device.
+        let restored_balloon = Balloon::restore(
+            BalloonConstructorArgs {
+                mem: guest_mem.clone(),
+            },
+            &BalloonState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+
+        // Test that virtio specific fields are the same.
+        assert_eq!(restored_balloon.device_type(), TYPE_BALLOON);
+        assert_eq!(restored_balloon.avail_features(), balloon.avail_features());
+        assert_eq!(restored_balloon.acked_features(), balloon.acked_features());
+        assert_eq!(restored_balloon.queues(), balloon.queues());
+        assert_eq!(
+            restored_balloon.interrupt_status().load(Ordering::Relaxed),
+            balloon.interrupt_status().load(Ordering::Relaxed)
+        );
+        assert_eq!(restored_balloon.is_activated(), balloon.is_activated());
+
+        // Test that balloon specific fields are the same.
+        assert_eq!(restored_balloon.config_space, balloon.config_space);
+        assert_eq!(restored_balloon.inflated_pages, balloon.inflated_pages);
+        assert!(restored_balloon.deflate_on_oom());
+        assert!(restored_balloon.free_page_reporting());
+    }
+}
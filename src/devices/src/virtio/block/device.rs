@@ -7,24 +7,30 @@
 
 use std::cmp;
 use std::convert::From;
-use std::fs::{File, OpenOptions};
+use std::fs::File;
 use std::io::{self, Seek, SeekFrom, Write};
 use std::os::linux::fs::MetadataExt;
-use std::path::PathBuf;
 use std::result;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
-use logger::{Metric, METRICS};
-use rate_limiter::{RateLimiter, TokenType};
+use logger::{BlockLatencyMetrics, Metric, METRICS};
+use rate_limiter::{RateLimiter, TokenBucket, TokenType};
 use utils::eventfd::EventFd;
 use virtio_gen::virtio_blk::*;
 use vm_memory::{Bytes, GuestMemoryMmap};
 
 use super::{
     super::{ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_BLOCK, VIRTIO_MMIO_INT_VRING},
+    adopt_disk_image, advise_disk_image,
+    encryption::DiskCipher,
+    open_disk_image,
     request::*,
-    Error, CONFIG_SPACE_SIZE, QUEUE_SIZES, SECTOR_SHIFT, SECTOR_SIZE,
+    scheduler::IoShare,
+    verity::HashTree,
+    BlockEvent, BlockEventSink, EnospcPolicy, Error, PageCacheAccess, CONFIG_SPACE_SIZE,
+    QUEUE_SIZES, SECTOR_SHIFT, SECTOR_SIZE, TOPOLOGY_CONFIG_SPACE_SIZE,
 };
 
 use crate::Error as DeviceError;
@@ -48,6 +54,25 @@ pub fn build_config_space(disk_size: u64) -> Vec<u8> {
     config
 }
 
+// Offsets of the topology fields in the configuration space.
+const PHYSICAL_BLOCK_EXP_OFFSET: usize = 24;
+const MIN_IO_SIZE_OFFSET: usize = 26;
+
+// Reports a physical block of `physical_block_size` bytes, which is also the smallest I/O the
+// guest should issue, in the configuration space. The sizes are counted in sectors.
+fn set_config_topology(config: &mut Vec<u8>, physical_block_size: Option<u32>) {
+    match physical_block_size {
+        Some(size) => {
+            let sectors = size as u64 >> SECTOR_SHIFT;
+            config.resize(TOPOLOGY_CONFIG_SPACE_SIZE, 0);
+            config[PHYSICAL_BLOCK_EXP_OFFSET] = sectors.trailing_zeros() as u8;
+            config[MIN_IO_SIZE_OFFSET..MIN_IO_SIZE_OFFSET + 2]
+                .copy_from_slice(&(sectors as u16).to_le_bytes());
+        }
+        None => config.truncate(CONFIG_SPACE_SIZE),
+    }
+}
+
 fn build_device_id(disk_image: &File) -> result::Result<String, Error> {
     let blk_metadata = disk_image.metadata().map_err(Error::GetFileMetadata)?;
     // This is how kvmtool does it.
@@ -61,9 +86,13 @@ fn build_device_id(disk_image: &File) -> result::Result<String, Error> {
     Ok(device_id)
 }
 
-fn build_disk_image_id(disk_image: &File) -> Vec<u8> {
+fn build_disk_image_id(disk_image: &File, serial: Option<&str>) -> Vec<u8> {
     let mut default_disk_image_id = vec![0; VIRTIO_BLK_ID_BYTES as usize];
-    match build_device_id(disk_image) {
+    let device_id = match serial {
+        Some(serial) => Ok(serial.to_string()),
+        None => build_device_id(disk_image),
+    };
+    match device_id {
         Err(_) => {
             warn!("Could not generate device id. We'll use a default.");
         }
@@ -81,10 +110,12 @@ fn build_disk_image_id(disk_image: &File) -> Vec<u8> {
 /// Virtio device for exposing block level read/write operations on a host file.
 pub struct Block {
     // Host file and properties.
-    disk_image: File,
+    disk_image: Arc<File>,
     pub(crate) disk_image_path: String,
+    pub(crate) no_atime: bool,
     disk_nsectors: u64,
     disk_image_id: Vec<u8>,
+    serial: Option<String>,
 
     // Virtio fields.
     pub(crate) avail_features: u64,
@@ -104,26 +135,56 @@ pub struct Block {
     pub(crate) partuuid: Option<String>,
     pub(crate) root_device: bool,
     pub(crate) rate_limiter: RateLimiter,
+    cipher: Option<DiskCipher>,
+    hash_tree: Option<HashTree>,
+    io_share: Option<IoShare>,
+    paused: bool,
+    // The number of requests served each time the queue is processed, if limited.
+    pub(crate) dispatch_budget: Option<usize>,
+    latency: Arc<BlockLatencyMetrics>,
+    on_enospc: EnospcPolicy,
+    // Whether running out of space was reported, and no write succeeded since.
+    out_of_space: bool,
+    event_sink: Option<BlockEventSink>,
+    page_cache_access: PageCacheAccess,
+    drop_written_pages: bool,
 }
 
 impl Block {
     /// Create a new virtio block device that operates on the given file.
     ///
-    /// The given file must be seekable and sizable.
+    /// The given file must be seekable and sizable. It is opened from `disk_image_path`, unless
+    /// `disk_image` is the file already opened by the caller. Read-only files are shared with
+    /// the other read-only block devices backed by the same file. When a cipher is given, the file holds
+    /// the encrypted sectors, which the device decrypts for the guest. When a hash tree is given,
+    /// the reads are checked against it, and it must cover the whole file.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: String,
         partuuid: Option<String>,
         disk_image_path: String,
+        disk_image: Option<File>,
         is_disk_read_only: bool,
+        no_atime: bool,
         is_disk_root: bool,
         rate_limiter: RateLimiter,
+        cipher: Option<DiskCipher>,
+        hash_tree: Option<HashTree>,
     ) -> io::Result<Block> {
-        let mut disk_image = OpenOptions::new()
-            .read(true)
-            .write(!is_disk_read_only)
-            .open(PathBuf::from(&disk_image_path))?;
+        let disk_image = match disk_image {
+            Some(file) => adopt_disk_image(file, is_disk_read_only, no_atime)?,
+            None => open_disk_image(&disk_image_path, is_disk_read_only, no_atime)?,
+        };
 
-        let disk_size = disk_image.seek(SeekFrom::End(0))? as u64;
+        let disk_size = (&*disk_image).seek(SeekFrom::End(0))? as u64;
+        if let Some(hash_tree) = hash_tree.as_ref() {
+            if hash_tree.data_size() != disk_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "The hash tree doesn't cover the disk image.",
+                ));
+            }
+        }
 
         let mut avail_features = (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_BLK_F_FLUSH);
 
@@ -136,12 +197,15 @@ impl Block {
         let queues = QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
 
         Ok(Block {
+            latency: METRICS.block_latency.get(&id),
             id,
             root_device: is_disk_root,
             partuuid,
-            disk_image_id: build_disk_image_id(&disk_image),
+            disk_image_id: build_disk_image_id(&disk_image, None),
+            serial: None,
             disk_image,
             disk_image_path: disk_image_path.clone(),
+            no_atime,
             disk_nsectors: disk_size / SECTOR_SIZE,
             avail_features,
             acked_features: 0u64,
@@ -153,6 +217,16 @@ impl Block {
             queues,
             device_state: DeviceState::Inactive,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK)?,
+            cipher,
+            hash_tree,
+            io_share: None,
+            paused: false,
+            dispatch_budget: None,
+            on_enospc: EnospcPolicy::default(),
+            out_of_space: false,
+            event_sink: None,
+            page_cache_access: PageCacheAccess::default(),
+            drop_written_pages: false,
         })
     }
 
@@ -166,6 +240,19 @@ impl Block {
         }
     }
 
+    // Serves the requests the driver made available, without waiting for the queue event.
+    pub(crate) fn poll_queue(&mut self) -> bool {
+        let has_requests = match self.device_state {
+            DeviceState::Activated(ref mem) => !self.queues[0].is_empty(mem),
+            DeviceState::Inactive => false,
+        };
+        if has_requests && !self.rate_limiter.is_blocked() && self.process_queue(0) {
+            let _ = self.signal_used_queue();
+            return true;
+        }
+        false
+    }
+
     pub(crate) fn process_rate_limiter_event(&mut self) {
         METRICS.block.rate_limiter_event_count.inc();
         // Upon rate limiter event, call the rate limiter handler
@@ -176,17 +263,39 @@ impl Block {
     }
 
     pub(crate) fn process_queue(&mut self, queue_index: usize) -> bool {
+        // The requests made to a paused device wait in the queue until it is resumed.
+        if self.paused {
+            return false;
+        }
         let mem = match self.device_state {
             DeviceState::Activated(ref mem) => mem,
             // This should never happen, it's been already validated in the event handler.
             DeviceState::Inactive => unreachable!(),
         };
         let queue = &mut self.queues[queue_index];
-        let mut used_any = false;
+        let mut served = 0;
+        let mut yielded = false;
+        let mut out_of_budget = false;
+        let mut out_of_space = false;
         while let Some(head) = queue.pop(mem) {
+            // Let the other devices sharing the event loop get their turn.
+            if Some(served) == self.dispatch_budget {
+                queue.undo_pop();
+                out_of_budget = true;
+                break;
+            }
             let len;
             match Request::parse(&head, mem) {
                 Ok(request) => {
+                    if let Some(io_share) = self.io_share.as_ref() {
+                        io_share.set_backlogged(true);
+                        // Let the other devices on the same host device catch up.
+                        if !io_share.may_dispatch() {
+                            queue.undo_pop();
+                            yielded = true;
+                            break;
+                        }
+                    }
                     // If limiter.consume() fails it means there is no more TokenType::Ops
                     // budget and rate limiting is in effect.
                     if !self.rate_limiter.consume(1, TokenType::Ops) {
@@ -213,17 +322,57 @@ impl Block {
                             break;
                         }
                     }
+                    let start = Instant::now();
                     let status = match request.execute(
-                        &mut self.disk_image,
+                        &mut &*self.disk_image,
                         self.disk_nsectors,
                         mem,
                         &self.disk_image_id,
+                        self.cipher.as_ref(),
+                        self.hash_tree.as_mut(),
                     ) {
                         Ok(l) => {
+                            if request.request_type == RequestType::Out
+                                || request.request_type == RequestType::Flush
+                            {
+                                self.out_of_space = false;
+                            }
+                            if request.request_type == RequestType::Out
+                                && self.drop_written_pages
+                                && advise_disk_image(
+                                    &self.disk_image,
+                                    request.sector << SECTOR_SHIFT,
+                                    u64::from(request.data_len),
+                                    libc::POSIX_FADV_DONTNEED,
+                                )
+                                .is_err()
+                            {
+                                // Only a hint, the write itself succeeded.
+                                METRICS.block.page_cache_advice_fails.inc();
+                            }
                             len = l;
                             VIRTIO_BLK_S_OK
                         }
+                        Err(ref e)
+                            if e.is_out_of_space() && self.on_enospc != EnospcPolicy::Error =>
+                        {
+                            METRICS.block.out_of_space_count.inc();
+                            out_of_space = true;
+                            // Hold the request back, to serve it again once the device is
+                            // resumed, hopefully after the host made room for the backing file.
+                            self.paused = true;
+                            // Only writes run out of space, so the bytes are given back too.
+                            self.rate_limiter.manual_replenish(1, TokenType::Ops);
+                            self.rate_limiter
+                                .manual_replenish(u64::from(request.data_len), TokenType::Bytes);
+                            queue.undo_pop();
+                            break;
+                        }
                         Err(e) => {
+                            if e.is_out_of_space() {
+                                METRICS.block.out_of_space_count.inc();
+                                out_of_space = true;
+                            }
                             error!("Failed to execute request: {:?}", e);
                             METRICS.block.invalid_reqs_count.inc();
                             len = 1; // We need at least 1 byte for the status.
@@ -233,6 +382,22 @@ impl Block {
                     // We use unwrap because the request parsing process already checked that the
                     // status_addr was valid.
                     mem.write_obj(status, request.status_addr).unwrap();
+                    let latency = match request.request_type {
+                        RequestType::In => Some(&self.latency.read),
+                        RequestType::Out => Some(&self.latency.write),
+                        RequestType::Flush => Some(&self.latency.flush),
+                        RequestType::GetDeviceID => Some(&self.latency.get_device_id),
+                        RequestType::Unsupported(_) => None,
+                    };
+                    if let Some(latency) = latency {
+                        latency.record_since(start);
+                    }
+                    if let Some(io_share) = self.io_share.as_ref() {
+                        io_share.charge(match request.request_type {
+                            RequestType::In | RequestType::Out => u64::from(request.data_len),
+                            _ => 0,
+                        });
+                    }
                 }
                 Err(e) => {
                     error!("Failed to parse available descriptor chain: {:?}", e);
@@ -241,14 +406,58 @@ impl Block {
                 }
             }
             queue.add_used(mem, head.index, len);
-            used_any = true;
+            served += 1;
+        }
+
+        if let Some(io_share) = self.io_share.as_ref() {
+            // Throttled devices, and devices without requests left, don't hold back the others.
+            io_share.set_backlogged(yielded || out_of_budget);
+            if yielded {
+                METRICS.block.io_yield_count.inc();
+            }
+        }
+        if out_of_budget {
+            METRICS.block.budget_yield_count.inc();
+        }
+        if yielded || out_of_budget {
+            // Get back to the remaining requests once the other devices have been served.
+            if let Err(e) = self.queue_evts[queue_index].write(1) {
+                error!("Failed to trigger the queue event: {:?}", e);
+                METRICS.block.event_fails.inc();
+            }
+        }
+
+        if out_of_space {
+            self.report_out_of_space();
         }
 
-        if !used_any {
+        if served == 0 && !yielded && !out_of_space {
             METRICS.block.no_avail_buffer.inc();
         }
 
-        used_any
+        served > 0
+    }
+
+    // Lets the control plane know that the host ran out of space for the backing file, once
+    // until a write succeeds again or the device is resumed.
+    fn report_out_of_space(&mut self) {
+        if self.paused {
+            METRICS.block.pause_count.inc();
+            warn!(
+                "Block device {} paused, the host ran out of space for {}.",
+                self.id, self.disk_image_path
+            );
+        }
+        if self.out_of_space {
+            return;
+        }
+        self.out_of_space = true;
+        if let Some(event_sink) = self.event_sink.as_ref() {
+            event_sink(BlockEvent::OutOfSpace {
+                drive_id: self.id.clone(),
+                policy: self.on_enospc,
+            });
+        }
     }
 
     pub(crate) fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
@@ -263,15 +472,39 @@ impl Block {
         Ok(())
     }
 
+    /// Serves the requests the driver already made available, unless the device is paused, and
+    /// flushes the host file backing the device, so that the requests completed before its
+    /// backing file is replaced all reach the old file.
+    pub fn quiesce(&mut self) -> io::Result<()> {
+        self.poll_queue();
+        if !self.is_read_only() {
+            self.disk_image.sync_all()?;
+        }
+        Ok(())
+    }
+
     /// Update the backing file for the Block device.
-    pub fn update_disk_image(&mut self, disk_image: File) -> result::Result<(), DeviceError> {
+    pub fn update_disk_image(
+        &mut self,
+        disk_image: Arc<File>,
+        disk_image_path: String,
+    ) -> result::Result<(), DeviceError> {
         self.disk_image = disk_image;
-        self.disk_nsectors = self
-            .disk_image
+        self.disk_image_path = disk_image_path;
+        self.disk_nsectors = (&*self.disk_image)
             .seek(SeekFrom::End(0))
             .map_err(DeviceError::IoError)?
             / SECTOR_SIZE;
-        self.disk_image_id = build_disk_image_id(&self.disk_image);
+        self.disk_image_id = build_disk_image_id(&self.disk_image, self.serial());
+        if self.page_cache_access != PageCacheAccess::Normal {
+            self.set_page_cache_access(self.page_cache_access)
+                .map_err(DeviceError::IoError)?;
+        }
+        // The new file may live on another host device.
+        if let Some(weight) = self.io_weight() {
+            self.set_io_weight(Some(weight))
+                .map_err(DeviceError::IoError)?;
+        }
         METRICS.block.update_count.inc();
         Ok(())
     }
@@ -286,6 +519,58 @@ impl Block {
         self.partuuid.as_ref()
     }
 
+    /// Provides the path of the host file backing this block device.
+    pub fn disk_image_path(&self) -> &str {
+        &self.disk_image_path
+    }
+
+    /// Provides the host file backing this block device.
+    pub fn disk_image(&self) -> &Arc<File> {
+        &self.disk_image
+    }
+
+    /// Provides the serial number the guest reads from this block device, if it doesn't derive
+    /// from the host file.
+    pub fn serial(&self) -> Option<&str> {
+        self.serial.as_ref().map(String::as_str)
+    }
+
+    /// Sets the serial number the guest reads from this block device, and names it by in
+    /// `/dev/disk/by-id`. It is truncated to 20 bytes. `None` derives it from the host file.
+    pub fn set_serial(&mut self, serial: Option<String>) {
+        self.serial = serial;
+        self.disk_image_id = build_disk_image_id(&self.disk_image, self.serial());
+    }
+
+    /// Provides the physical block size this block device reports to the guest, if any.
+    pub fn physical_block_size(&self) -> Option<u32> {
+        if self.config_space.len() < TOPOLOGY_CONFIG_SPACE_SIZE {
+            return None;
+        }
+        let sectors = u16::from_le_bytes([
+            self.config_space[MIN_IO_SIZE_OFFSET],
+            self.config_space[MIN_IO_SIZE_OFFSET + 1],
+        ]);
+        Some(u32::from(sectors) << SECTOR_SHIFT)
+    }
+
+    /// Reports to the guest that this block device has physical blocks of
+    /// `physical_block_size` bytes, a power of two of at least a sector, so that it aligns its
+    /// I/O on them. It must be set before the device is activated.
+    pub fn set_physical_block_size(&mut self, physical_block_size: Option<u32>) {
+        set_config_topology(&mut self.config_space, physical_block_size);
+        if physical_block_size.is_some() {
+            self.avail_features |= 1u64 << VIRTIO_BLK_F_TOPOLOGY;
+        } else {
+            self.avail_features &= !(1u64 << VIRTIO_BLK_F_TOPOLOGY);
+        }
+    }
+
+    /// Specifies if the host file backing this block device is opened with `O_NOATIME`.
+    pub fn no_atime(&self) -> bool {
+        self.no_atime
+    }
+
     /// Specifies if this block device is read only.
     pub fn is_read_only(&self) -> bool {
         self.avail_features & (1u64 << VIRTIO_BLK_F_RO) != 0
@@ -295,6 +580,118 @@ impl Block {
     pub fn is_root_device(&self) -> bool {
         self.root_device
     }
+
+    /// Provides the rate limiter of this block device.
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    /// Updates the parameters of the rate limiter.
+    pub fn update_rate_limiter(&mut self, bytes: Option<TokenBucket>, ops: Option<TokenBucket>) {
+        self.rate_limiter.update_buckets(bytes, ops);
+    }
+
+    /// Specifies if the host file backing this block device holds encrypted sectors.
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// Provides the weight of this block device in the I/O scheduling of its host device.
+    pub fn io_weight(&self) -> Option<u32> {
+        self.io_share.as_ref().map(IoShare::weight)
+    }
+
+    /// Shares the throughput of the host device backing this block device with the other block
+    /// devices scheduled on it, in proportion to `weight`. `None` stops scheduling the device.
+    pub fn set_io_weight(&mut self, weight: Option<u32>) -> io::Result<()> {
+        // Leave the current group before joining the new one.
+        self.io_share = None;
+        self.io_share = weight
+            .map(|weight| IoShare::new(&self.disk_image, weight))
+            .transpose()?;
+        Ok(())
+    }
+
+    /// Specifies if the reads from this block device are checked against a hash tree.
+    pub fn is_verified(&self) -> bool {
+        self.hash_tree.is_some()
+    }
+
+    /// Provides what this block device does when the host runs out of space for its backing
+    /// file.
+    pub fn enospc_policy(&self) -> EnospcPolicy {
+        self.on_enospc
+    }
+
+    /// Sets what this block device does when the host runs out of space for its backing file.
+    pub fn set_enospc_policy(&mut self, policy: EnospcPolicy) {
+        self.on_enospc = policy;
+    }
+
+    /// Provides how the guest is expected to access the host file backing this block device.
+    pub fn page_cache_access(&self) -> PageCacheAccess {
+        self.page_cache_access
+    }
+
+    /// Hints the host page cache that the guest accesses the host file backing this block device
+    /// in the `access` pattern, which sets how far the host reads ahead. The read-only block
+    /// devices sharing a file share its readahead state, and thus the last hint given.
+    pub fn set_page_cache_access(&mut self, access: PageCacheAccess) -> io::Result<()> {
+        let advice = match access {
+            PageCacheAccess::Normal => libc::POSIX_FADV_NORMAL,
+            PageCacheAccess::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+            PageCacheAccess::Random => libc::POSIX_FADV_RANDOM,
+        };
+        advise_disk_image(&self.disk_image, 0, 0, advice)?;
+        self.page_cache_access = access;
+        Ok(())
+    }
+
+    /// Specifies if the pages written by the guest are dropped from the host page cache.
+    pub fn drop_written_pages(&self) -> bool {
+        self.drop_written_pages
+    }
+
+    /// Drops the pages written by the guest from the host page cache after each write, instead
+    /// of leaving them to the host reclaim. The writeback of the pages is started, and the ones
+    /// still being written back stay cached.
+    pub fn set_drop_written_pages(&mut self, drop_written_pages: bool) {
+        self.drop_written_pages = drop_written_pages;
+    }
+
+    /// Starts reading `len` bytes of the host file backing this block device from `offset` into
+    /// the host page cache, ahead of the guest reading them. A `len` of 0 reads up to the end of
+    /// the file.
+    pub fn prefetch(&self, offset: u64, len: u64) -> io::Result<()> {
+        advise_disk_image(&self.disk_image, offset, len, libc::POSIX_FADV_WILLNEED).map_err(|e| {
+            METRICS.block.page_cache_advice_fails.inc();
+            e
+        })
+    }
+
+    /// Sets the callback through which the device surfaces `BlockEvent`s.
+    pub fn set_event_sink(&mut self, event_sink: BlockEventSink) {
+        self.event_sink = Some(event_sink);
+    }
+
+    /// Holds back the requests to this block device, e.g. while its backing file is replaced or
+    /// snapshotted on the host, or serves them again, starting with the ones held back.
+    pub fn set_paused(&mut self, paused: bool) -> result::Result<(), DeviceError> {
+        if paused == self.paused {
+            return Ok(());
+        }
+        self.paused = paused;
+        if paused {
+            METRICS.block.pause_count.inc();
+            return Ok(());
+        }
+        // Running out of space again once resumed is reported again.
+        self.out_of_space = false;
+        if self.is_activated() && !self.rate_limiter.is_blocked() && self.process_queue(0) {
+            self.signal_used_queue()?;
+        }
+        Ok(())
+    }
 }
 
 impl VirtioDevice for Block {
@@ -349,6 +746,22 @@ impl VirtioDevice for Block {
         }
     }
 
+    fn config_space_bytes(&self) -> Vec<u8> {
+        self.config_space.clone()
+    }
+
+    fn debug_state(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("disk_image_path", self.disk_image_path.clone()),
+            ("disk_nsectors", self.disk_nsectors.to_string()),
+            ("paused", self.paused.to_string()),
+            (
+                "rate_limiter_blocked",
+                self.rate_limiter.is_blocked().to_string(),
+            ),
+        ]
+    }
+
     fn write_config(&mut self, offset: u64, data: &[u8]) {
         let data_len = data.len() as u64;
         let config_len = self.config_space.len() as u64;
@@ -376,6 +789,20 @@ impl VirtioDevice for Block {
         self.device_state = DeviceState::Activated(mem);
         Ok(())
     }
+
+    fn reset(&mut self) -> bool {
+        // The requests are served synchronously, so none is in flight.
+        self.device_state = DeviceState::Inactive;
+        self.acked_features = 0;
+        if let Some(io_share) = self.io_share.as_ref() {
+            io_share.set_backlogged(false);
+        }
+        true
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused
+    }
 }
 
 #[cfg(test)]
@@ -410,10 +837,6 @@ pub(crate) mod tests {
         fn set_rate_limiter(&mut self, rl: RateLimiter) {
             self.rate_limiter = rl;
         }
-
-        fn rate_limiter(&mut self) -> &RateLimiter {
-            &self.rate_limiter
-        }
     }
 
     /// Create a default Block instance to be used in tests.
@@ -432,7 +855,19 @@ pub(crate) mod tests {
 
         let id = "test".to_string();
         // The default block device is read-write and non-root.
-        Block::new(id, None, path, false, false, rate_limiter).unwrap()
+        Block::new(
+            id,
+            None,
+            path,
+            None,
+            false,
+            false,
+            false,
+            rate_limiter,
+            None,
+            None,
+        )
+        .unwrap()
     }
 
     pub fn default_mem() -> GuestMemoryMmap {
@@ -508,6 +943,21 @@ pub(crate) mod tests {
         assert_eq!(block.acked_features, features);
     }
 
+    #[test]
+    fn test_reset() {
+        let mut block = default_block();
+        let mem = default_mem();
+        block.ack_features_by_page(0, u32::MAX);
+        block.activate(mem.clone()).unwrap();
+
+        // The driver of the next kernel negotiates the features and activates the device again.
+        assert!(block.reset());
+        assert!(!block.is_activated());
+        assert_eq!(block.acked_features, 0);
+        block.activate(mem).unwrap();
+        assert!(block.is_activated());
+    }
+
     #[test]
     fn test_virtio_read_config() {
         let block = default_block();
@@ -723,6 +1173,277 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn test_encrypted_read_write() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let cipher = DiskCipher::new(&[0x42; 64]).unwrap();
+        let mut block = Block::new(
+            "test".to_string(),
+            None,
+            f.as_path().to_str().unwrap().to_string(),
+            None,
+            false,
+            false,
+            false,
+            RateLimiter::default(),
+            Some(cipher),
+            None,
+        )
+        .unwrap();
+        assert!(block.is_encrypted());
+
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.set_queue(0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let data_addr = GuestAddress(vq.dtable[1].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        let plaintext: Vec<u8> = (0..2 * SECTOR_SIZE).map(|i| i as u8).collect();
+
+        // Write two sectors, starting at the second one.
+        {
+            mem.write_obj(RequestHeader::new(VIRTIO_BLK_T_OUT, 1), request_type_addr)
+                .unwrap();
+            vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+            vq.dtable[1].len.set(plaintext.len() as u32);
+            mem.write_slice(&plaintext, data_addr).unwrap();
+
+            invoke_handler_for_queue_event(&mut block);
+
+            assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+            // The file only holds the ciphertext.
+            let mut on_disk = vec![0u8; plaintext.len()];
+            let mut file = f.as_file();
+            file.seek(SeekFrom::Start(SECTOR_SIZE)).unwrap();
+            io::Read::read_exact(&mut file, &mut on_disk).unwrap();
+            assert_ne!(on_disk, plaintext);
+            let cipher = DiskCipher::new(&[0x42; 64]).unwrap();
+            cipher.decrypt(&mut on_disk, 1);
+            assert_eq!(on_disk, plaintext);
+        }
+
+        // Read them back.
+        {
+            vq.used.idx.set(0);
+            block.set_queue(0, vq.create_queue());
+
+            mem.write_obj(RequestHeader::new(VIRTIO_BLK_T_IN, 1), request_type_addr)
+                .unwrap();
+            vq.dtable[1]
+                .flags
+                .set(VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE);
+            mem.write_slice(&vec![0u8; plaintext.len()], data_addr)
+                .unwrap();
+
+            invoke_handler_for_queue_event(&mut block);
+
+            assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+            assert_eq!(vq.used.ring[0].get().len, plaintext.len() as u32);
+            let mut data = vec![0u8; plaintext.len()];
+            mem.read_slice(&mut data, data_addr).unwrap();
+            assert_eq!(data, plaintext);
+        }
+
+        // Partial sectors can't be encrypted.
+        {
+            vq.used.idx.set(0);
+            block.set_queue(0, vq.create_queue());
+            vq.dtable[1].len.set(8);
+
+            invoke_handler_for_queue_event(&mut block);
+
+            assert_eq!(vq.used.ring[0].get().len, 1);
+            assert_eq!(
+                mem.read_obj::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_IOERR
+            );
+        }
+    }
+
+    #[test]
+    fn test_verified_read() {
+        use crate::virtio::block::verity::tests::build_hash_tree;
+        use std::os::unix::fs::FileExt;
+
+        let data: Vec<u8> = (0..3 * 4096).map(|i| (i % 251) as u8).collect();
+        let f = TempFile::new().unwrap();
+        f.as_file().write_all(&data).unwrap();
+        let hash_file = TempFile::new().unwrap();
+        let root_hash = build_hash_tree(&data, &[], hash_file.as_file());
+        let new_hash_tree = |data_size| {
+            HashTree::new(
+                hash_file.as_file().try_clone().unwrap(),
+                0,
+                data_size,
+                root_hash.clone(),
+                Vec::new(),
+            )
+            .unwrap()
+        };
+        let new_block = |hash_tree| {
+            Block::new(
+                "test".to_string(),
+                None,
+                f.as_path().to_str().unwrap().to_string(),
+                None,
+                true,
+                false,
+                false,
+                RateLimiter::default(),
+                None,
+                Some(hash_tree),
+            )
+        };
+
+        // The hash tree has to cover the whole disk image.
+        assert!(new_block(new_hash_tree(2 * 4096)).is_err());
+        let mut block = new_block(new_hash_tree(3 * 4096)).unwrap();
+        assert!(block.is_verified());
+
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.set_queue(0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let data_addr = GuestAddress(vq.dtable[1].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+
+        // Read sectors spanning two data blocks, without starting on a block boundary.
+        mem.write_obj(RequestHeader::new(VIRTIO_BLK_T_IN, 3), request_type_addr)
+            .unwrap();
+        invoke_handler_for_queue_event(&mut block);
+        assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+        let mut read = vec![0u8; 0x1000];
+        mem.read_slice(&mut read, data_addr).unwrap();
+        assert_eq!(read[..], data[3 * 512..3 * 512 + 0x1000]);
+
+        // Corrupted data is refused.
+        f.as_file().write_all_at(&[0xff], 2 * 4096 + 7).unwrap();
+        vq.used.idx.set(0);
+        block.set_queue(0, vq.create_queue());
+        mem.write_obj(RequestHeader::new(VIRTIO_BLK_T_IN, 16), request_type_addr)
+            .unwrap();
+        vq.dtable[1].len.set(512);
+        check_metric_after_block!(
+            &METRICS.block.verity_fails,
+            1,
+            invoke_handler_for_queue_event(&mut block)
+        );
+        assert_eq!(vq.used.ring[0].get().len, 1);
+        assert_eq!(
+            mem.read_obj::<u32>(status_addr).unwrap(),
+            VIRTIO_BLK_S_IOERR
+        );
+    }
+
+    #[test]
+    fn test_io_scheduling() {
+        let mut block = default_block();
+        assert_eq!(block.io_weight(), None);
+        assert!(block.set_io_weight(Some(0)).is_err());
+        block.set_io_weight(Some(100)).unwrap();
+        assert_eq!(block.io_weight(), Some(100));
+
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.set_queue(0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        mem.write_obj::<u32>(VIRTIO_BLK_T_IN, request_type_addr)
+            .unwrap();
+
+        // Another device on the same host device has requests waiting, while this one ran ahead.
+        let f = TempFile::new().unwrap();
+        let other = IoShare::new(f.as_file(), 100).unwrap();
+        other.set_backlogged(true);
+        block.io_share.as_ref().unwrap().charge(2 << 20);
+
+        block.queue_evts[0].write(1).unwrap();
+        check_metric_after_block!(
+            &METRICS.block.io_yield_count,
+            1,
+            block.process_queue_event()
+        );
+        assert_eq!(vq.used.idx.get(), 0);
+        // The device gets back to its queue in the next round of the event loop.
+        assert_eq!(block.queue_evts[0].read().unwrap(), 1);
+
+        // Once the other device is done, the request is served.
+        other.set_backlogged(false);
+        block.queue_evts[0].write(1).unwrap();
+        block.process_queue_event();
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+
+        block.set_io_weight(None).unwrap();
+        assert_eq!(block.io_weight(), None);
+    }
+
+    #[test]
+    fn test_dispatch_budget() {
+        let mut block = default_block();
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.set_queue(0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        mem.write_obj::<u32>(VIRTIO_BLK_T_IN, request_type_addr)
+            .unwrap();
+        // The driver makes the same request available three times.
+        for i in 0..3 {
+            vq.avail.ring[i].set(0);
+        }
+        vq.avail.idx.set(3);
+
+        block.dispatch_budget = Some(2);
+        block.queue_evts[0].write(1).unwrap();
+        check_metric_after_block!(
+            &METRICS.block.budget_yield_count,
+            1,
+            block.process_queue_event()
+        );
+        assert_eq!(vq.used.idx.get(), 2);
+        // The device gets back to its queue in the next round of the event loop.
+        assert_eq!(block.queue_evts[0].read().unwrap(), 1);
+
+        block.queue_evts[0].write(1).unwrap();
+        check_metric_after_block!(
+            &METRICS.block.budget_yield_count,
+            0,
+            block.process_queue_event()
+        );
+        assert_eq!(vq.used.idx.get(), 3);
+        assert!(block.queue_evts[0].read().is_err());
+    }
+
+    #[test]
+    fn test_update_rate_limiter() {
+        let mut block = default_block();
+        block.set_rate_limiter(RateLimiter::new(10, None, 10, 2, None, 2).unwrap());
+
+        let bytes = TokenBucket::new(1000, Some(1001), 1002);
+        block.update_rate_limiter(Some(bytes.clone()), None);
+        let bandwidth = block.rate_limiter().bandwidth().unwrap();
+        assert_eq!(bandwidth.capacity(), bytes.capacity());
+        assert_eq!(bandwidth.one_time_burst(), bytes.one_time_burst());
+        assert_eq!(bandwidth.refill_time_ms(), bytes.refill_time_ms());
+        // The ops bucket is left unchanged.
+        assert_eq!(block.rate_limiter().ops().unwrap().capacity(), 2);
+
+        // A 0-sized bucket disables the limit.
+        block.update_rate_limiter(None, Some(TokenBucket::new(0, None, 0)));
+        assert!(block.rate_limiter().ops().is_none());
+    }
+
     #[test]
     fn test_flush() {
         let mut block = default_block();
@@ -734,6 +1455,8 @@ pub(crate) mod tests {
 
         let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
         let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        // Keep the latencies apart from the ones of the other tests.
+        block.latency = METRICS.block_latency.get("test_flush");
 
         // Flush completes successfully without a data descriptor.
         {
@@ -764,6 +1487,160 @@ pub(crate) mod tests {
             assert_eq!(vq.used.ring[0].get().len, 0);
             assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
         }
+
+        // The latency of each flush is recorded.
+        assert_eq!(block.latency.flush.count(), 2);
+        assert_eq!(block.latency.read.count(), 0);
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut block = default_block();
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.set_queue(0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+        vq.dtable[0].next.set(2);
+        mem.write_obj::<u32>(VIRTIO_BLK_T_FLUSH, GuestAddress(vq.dtable[0].addr.get()))
+            .unwrap();
+
+        assert!(!block.is_paused());
+        check_metric_after_block!(&METRICS.block.pause_count, 1, block.set_paused(true));
+        assert!(block.is_paused());
+        // Pausing a paused device is a no-op.
+        check_metric_after_block!(&METRICS.block.pause_count, 0, block.set_paused(true));
+
+        // The request is held back.
+        block.queue_evts[0].write(1).unwrap();
+        block.process(
+            &EpollEvent::new(EventSet::IN, block.queue_evts[0].as_raw_fd() as u64),
+            &mut EventManager::new().unwrap(),
+        );
+        assert_eq!(vq.used.idx.get(), 0);
+        assert!(block.interrupt_evt.read().is_err());
+
+        // And served once the device is resumed.
+        block.set_paused(false).unwrap();
+        assert!(!block.is_paused());
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(block.interrupt_evt.read().unwrap(), 1);
+        assert_eq!(
+            mem.read_obj::<u32>(GuestAddress(vq.dtable[2].addr.get()))
+                .unwrap(),
+            VIRTIO_BLK_S_OK
+        );
+    }
+
+    #[test]
+    fn test_out_of_space() {
+        let mut block = default_block();
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.set_queue(0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+        mem.write_obj::<u32>(VIRTIO_BLK_T_OUT, GuestAddress(vq.dtable[0].addr.get()))
+            .unwrap();
+        vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+        vq.dtable[1].len.set(SECTOR_SIZE as u32);
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink_events = events.clone();
+        block.set_event_sink(Box::new(move |event| {
+            sink_events.lock().unwrap().push(event)
+        }));
+        // Writes to /dev/full fail with ENOSPC.
+        let backing_file = block.disk_image.clone();
+        block.disk_image = Arc::new(
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open("/dev/full")
+                .unwrap(),
+        );
+        let out_of_space = BlockEvent::OutOfSpace {
+            drive_id: "test".to_string(),
+            policy: EnospcPolicy::Error,
+        };
+
+        // By default, the request fails, and running out of space is reported once.
+        assert_eq!(block.enospc_policy(), EnospcPolicy::Error);
+        for _ in 0..2 {
+            vq.used.idx.set(0);
+            block.set_queue(0, vq.create_queue());
+            check_metric_after_block!(
+                &METRICS.block.out_of_space_count,
+                1,
+                invoke_handler_for_queue_event(&mut block)
+            );
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(
+                mem.read_obj::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_IOERR
+            );
+        }
+        assert_eq!(*events.lock().unwrap(), vec![out_of_space]);
+        events.lock().unwrap().clear();
+
+        // The device can be paused instead, with the request held back.
+        block.set_enospc_policy(EnospcPolicy::PauseDrive);
+        // Running out of space is reported again once the device is resumed.
+        block.set_paused(true).unwrap();
+        block.set_paused(false).unwrap();
+        vq.used.idx.set(0);
+        block.set_queue(0, vq.create_queue());
+        mem.write_obj::<u32>(VIRTIO_BLK_S_UNSUPP, status_addr)
+            .unwrap();
+        block.queue_evts[0].write(1).unwrap();
+        check_metric_after_block!(
+            &METRICS.block.pause_count,
+            1,
+            block.process(
+                &EpollEvent::new(EventSet::IN, block.queue_evts[0].as_raw_fd() as u64),
+                &mut EventManager::new().unwrap(),
+            )
+        );
+        assert!(block.is_paused());
+        assert_eq!(vq.used.idx.get(), 0);
+        assert_eq!(
+            mem.read_obj::<u32>(status_addr).unwrap(),
+            VIRTIO_BLK_S_UNSUPP
+        );
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![BlockEvent::OutOfSpace {
+                drive_id: "test".to_string(),
+                policy: EnospcPolicy::PauseDrive,
+            }]
+        );
+
+        // Once the host made room, the request is served when the device is resumed.
+        block.disk_image = backing_file;
+        block.set_paused(false).unwrap();
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+        assert_eq!(block.interrupt_evt.read().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_quiesce() {
+        let mut block = default_block();
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.set_queue(0, vq.create_queue());
+        // An inactive device has nothing to serve.
+        block.quiesce().unwrap();
+
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+        vq.dtable[0].next.set(2);
+        mem.write_obj::<u32>(VIRTIO_BLK_T_FLUSH, GuestAddress(vq.dtable[0].addr.get()))
+            .unwrap();
+
+        // The requests made available are served, without waiting for the queue event.
+        block.quiesce().unwrap();
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(block.interrupt_evt.read().unwrap(), 1);
     }
 
     #[test]
@@ -832,6 +1709,114 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn test_serial() {
+        let mut block = default_block();
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.set_queue(0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+        vq.dtable[1].len.set(VIRTIO_BLK_ID_BYTES);
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let data_addr = GuestAddress(vq.dtable[1].addr.get());
+
+        // The serial is longer than the 20 bytes the driver reads.
+        block.set_serial(Some("serial_number_of_the_data_disk".to_string()));
+        assert_eq!(block.serial(), Some("serial_number_of_the_data_disk"));
+        mem.write_obj::<u32>(VIRTIO_BLK_T_GET_ID, request_type_addr)
+            .unwrap();
+        invoke_handler_for_queue_event(&mut block);
+        let mut buf = [0; VIRTIO_BLK_ID_BYTES as usize];
+        mem.read_slice(&mut buf, data_addr).unwrap();
+        assert_eq!(&buf, b"serial_number_of_the");
+
+        // The serial is kept when the backing file is replaced.
+        let f = TempFile::new().unwrap();
+        block
+            .update_disk_image(Arc::new(f.into_file()), "new".to_string())
+            .unwrap();
+        assert_eq!(&block.disk_image_id[..], b"serial_number_of_the");
+
+        // Without a serial, it derives from the host file again.
+        block.set_serial(Some("data".to_string()));
+        assert_eq!(&block.disk_image_id[..5], b"data\0");
+        block.set_serial(None);
+        assert_eq!(block.serial(), None);
+        assert_eq!(
+            block.disk_image_id,
+            build_disk_image_id(&block.disk_image, None)
+        );
+    }
+
+    #[test]
+    fn test_physical_block_size() {
+        let mut block = default_block();
+        assert_eq!(block.physical_block_size(), None);
+
+        block.set_physical_block_size(Some(4096));
+        assert_eq!(block.physical_block_size(), Some(4096));
+        assert_ne!(block.avail_features() & (1u64 << VIRTIO_BLK_F_TOPOLOGY), 0);
+        let mut config_space = [0u8; TOPOLOGY_CONFIG_SPACE_SIZE];
+        block.read_config(0, &mut config_space);
+        // The capacity is still 8 sectors.
+        assert_eq!(config_space[..CONFIG_SPACE_SIZE], [8, 0, 0, 0, 0, 0, 0, 0]);
+        // A physical block and the smallest I/O span 8 sectors.
+        assert_eq!(config_space[24..28], [3, 0, 8, 0]);
+        assert_eq!(config_space[28..], [0, 0, 0, 0]);
+
+        block.set_physical_block_size(None);
+        assert_eq!(block.physical_block_size(), None);
+        assert_eq!(block.avail_features() & (1u64 << VIRTIO_BLK_F_TOPOLOGY), 0);
+        assert_eq!(block.config_space_bytes().len(), CONFIG_SPACE_SIZE);
+    }
+
+    #[test]
+    fn test_page_cache() {
+        let mut block = default_block();
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.set_queue(0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+        mem.write_obj::<u32>(VIRTIO_BLK_T_OUT, GuestAddress(vq.dtable[0].addr.get()))
+            .unwrap();
+        vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+        vq.dtable[1].len.set(SECTOR_SIZE as u32);
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+
+        // The written pages are dropped, and the write is served as usual.
+        assert!(!block.drop_written_pages());
+        block.set_drop_written_pages(true);
+        assert!(block.drop_written_pages());
+        check_metric_after_block!(
+            &METRICS.block.page_cache_advice_fails,
+            0,
+            invoke_handler_for_queue_event(&mut block)
+        );
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+
+        block.prefetch(0, 0).unwrap();
+        check_metric_after_block!(
+            &METRICS.block.page_cache_advice_fails,
+            1,
+            assert!(block.prefetch(u64::max_value(), 0).is_err())
+        );
+
+        // The access pattern is hinted again for a new backing file.
+        assert_eq!(block.page_cache_access(), PageCacheAccess::Normal);
+        block
+            .set_page_cache_access(PageCacheAccess::Sequential)
+            .unwrap();
+        assert_eq!(block.page_cache_access(), PageCacheAccess::Sequential);
+        let f = TempFile::new().unwrap();
+        block
+            .update_disk_image(Arc::new(f.into_file()), "new".to_string())
+            .unwrap();
+        assert_eq!(block.page_cache_access(), PageCacheAccess::Sequential);
+    }
+
     #[test]
     fn test_bandwidth_rate_limiter() {
         let mut block = default_block();
@@ -988,12 +1973,16 @@ pub(crate) mod tests {
         id[..cmp::min(part_id.len(), VIRTIO_BLK_ID_BYTES as usize)]
             .clone_from_slice(&part_id[..cmp::min(part_id.len(), VIRTIO_BLK_ID_BYTES as usize)]);
 
-        block.update_disk_image(f.into_file()).unwrap();
+        let path_str = path.to_str().unwrap().to_string();
+        block
+            .update_disk_image(Arc::new(f.into_file()), path_str.clone())
+            .unwrap();
 
         assert_eq!(
             block.disk_image.metadata().unwrap().st_ino(),
             mdata.st_ino()
         );
         assert_eq!(block.disk_image_id, id);
+        assert_eq!(block.disk_image_path(), path_str);
     }
 }
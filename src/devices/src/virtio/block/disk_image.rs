@@ -0,0 +1,355 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Opens the host files backing the block devices.
+//!
+//! The block devices opened read-only share a single open file per backing file, refcounted by
+//! the devices using it. Besides holding one file descriptor instead of one per drive, the
+//! devices share the kernel readahead state of the file, instead of each of them warming up its
+//! own. The shared file offset is not an issue, since the devices seek before every access and
+//! all run on the VMM thread.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::linux::fs::MetadataExt;
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::{Arc, Mutex, Weak};
+
+use utils::fd_uri;
+
+// The shared files are keyed by the device and inode numbers of the backing file, and by whether
+// they were opened with `O_NOATIME`.
+type SharedDiskImageKey = (u64, u64, bool);
+
+// See include/uapi/linux/fs.h in the kernel code.
+const FICLONE: u64 = 0x4004_9409;
+// The size of the chunks the disk images are copied by, when they can't be reflinked.
+const COPY_CHUNK_SIZE: usize = 1 << 20;
+
+lazy_static! {
+    static ref SHARED_DISK_IMAGES: Mutex<HashMap<SharedDiskImageKey, Weak<File>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Opens the host file at `path`, backing a block device. If `path` is a `fd://<fd>` URI, the
+/// file descriptor it names is adopted instead, as by `adopt_disk_image`.
+///
+/// A read-only file is shared with the other read-only block devices backed by the same file.
+/// `no_atime` opens the file with `O_NOATIME`, so that guest reads don't update its access time.
+/// This requires the process to own the file, or to have the `CAP_FOWNER` capability.
+pub fn open_disk_image(path: &str, read_only: bool, no_atime: bool) -> io::Result<Arc<File>> {
+    if let Some(file) = fd_uri::dup(path) {
+        return adopt_disk_image(file?, read_only, no_atime);
+    }
+
+    let mut options = OpenOptions::new();
+    options.read(true).write(!read_only);
+    if no_atime {
+        options.custom_flags(libc::O_NOATIME);
+    }
+    // Key the shared files by the opened file, in case `path` gets replaced in the meantime.
+    let file = options.open(path)?;
+    share_disk_image(file, read_only, no_atime)
+}
+
+/// Backs a block device with `file`, the host file opened by the caller, e.g. by the process
+/// launching Firecracker on its behalf.
+///
+/// `file` must have been opened for writing, unless the device is `read_only`. Besides, it is
+/// shared as if it were opened by `open_disk_image`, and `no_atime` adds `O_NOATIME` to it.
+pub fn adopt_disk_image(file: File, read_only: bool, no_atime: bool) -> io::Result<Arc<File>> {
+    // Safe because the fcntls only read and change the flags of the file description, and we
+    // check their return values.
+    let flags = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if !read_only && flags & libc::O_ACCMODE == libc::O_RDONLY {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "The disk image was opened read-only.",
+        ));
+    }
+    if no_atime
+        && unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETFL, flags | libc::O_NOATIME) } < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    share_disk_image(file, read_only, no_atime)
+}
+
+/// Gives the host page cache the `advice`, one of the `POSIX_FADV_*` constants, about `len` bytes
+/// of `file` from `offset`. A `len` of 0 extends the range to the end of the file.
+pub(crate) fn advise_disk_image(
+    file: &File,
+    offset: u64,
+    len: u64,
+    advice: libc::c_int,
+) -> io::Result<()> {
+    // Safe because the call only reads its arguments, and we check its return value. The
+    // offsets beyond `off_t` wrap to negative ones, which are rejected.
+    let ret = unsafe {
+        libc::posix_fadvise(
+            file.as_raw_fd(),
+            offset as libc::off_t,
+            len as libc::off_t,
+            advice,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::from_raw_os_error(ret));
+    }
+    Ok(())
+}
+
+/// Reflinks `file`, backing a block device, to a new file at `dest_path`, which must not exist.
+/// The reflink shares the extents of `file`, which is instant and takes no space until either
+/// file gets written to. Returns `false`, leaving no file behind, when the filesystem doesn't
+/// support reflinks, for the data to be copied with `copy_disk_image` instead.
+pub fn reflink_disk_image(file: &File, dest_path: &Path) -> io::Result<bool> {
+    create_copy(dest_path, |dest| {
+        // Safe because the ioctl only reads the file descriptors, and we check its return value.
+        if unsafe { libc::ioctl(dest.as_raw_fd(), FICLONE as _, file.as_raw_fd()) } == 0 {
+            return Ok(true);
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            // The filesystem doesn't support reflinks, or the files live on different
+            // filesystems.
+            Some(libc::EOPNOTSUPP)
+            | Some(libc::EXDEV)
+            | Some(libc::EINVAL)
+            | Some(libc::ENOTTY) => Ok(false),
+            _ => Err(err),
+        }
+    })
+}
+
+/// Copies the data of `file`, backing a block device, to a new file at `dest_path`, which must
+/// not exist. The zeroed chunks are left as holes. This reads the whole file, so it is meant for
+/// a thread which doesn't emulate devices.
+///
+/// `file` is read at explicit offsets, leaving the offset shared by the block devices alone. A
+/// partial copy is removed.
+pub fn copy_disk_image(file: &File, dest_path: &Path) -> io::Result<()> {
+    create_copy(dest_path, |dest| copy_data(file, dest).map(|_| true)).map(|_| ())
+}
+
+// Creates the file at `dest_path`, which must not exist, and lets `fill` write it. The file is
+// synced if `fill` returns `true`, and removed if it returns `false` or fails.
+fn create_copy<F>(dest_path: &Path, fill: F) -> io::Result<bool>
+where
+    F: FnOnce(&File) -> io::Result<bool>,
+{
+    let dest = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(dest_path)?;
+    let result = fill(&dest).and_then(|filled| {
+        if filled {
+            dest.sync_all()?;
+        }
+        Ok(filled)
+    });
+    if result.as_ref().map_or(true, |filled| !filled) {
+        let _ = fs::remove_file(dest_path);
+    }
+    result
+}
+
+fn copy_data(file: &File, dest: &File) -> io::Result<()> {
+    let mut buf = vec![0u8; COPY_CHUNK_SIZE];
+    let mut offset = 0;
+    loop {
+        let len = match file.read_at(&mut buf, offset) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if buf[..len].iter().any(|&b| b != 0) {
+            dest.write_all_at(&buf[..len], offset)?;
+        }
+        offset += len as u64;
+    }
+    // Extend the copy over the trailing holes.
+    dest.set_len(offset)
+}
+
+// Shares the read-only `file` with the other block devices backed by the same file.
+fn share_disk_image(file: File, read_only: bool, no_atime: bool) -> io::Result<Arc<File>> {
+    if !read_only {
+        return Ok(Arc::new(file));
+    }
+
+    let metadata = file.metadata()?;
+    let key = (metadata.st_dev(), metadata.st_ino(), no_atime);
+    let mut shared_disk_images = SHARED_DISK_IMAGES.lock().expect("Poisoned lock");
+    if let Some(shared_file) = shared_disk_images.get(&key).and_then(Weak::upgrade) {
+        return Ok(shared_file);
+    }
+    let file = Arc::new(file);
+    // Forget the files no longer used by any device.
+    shared_disk_images.retain(|_, shared_file| shared_file.upgrade().is_some());
+    shared_disk_images.insert(key, Arc::downgrade(&file));
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tempfile::TempFile;
+
+    #[test]
+    fn test_open_disk_image() {
+        let f = TempFile::new().unwrap();
+        let path = f.as_path().to_str().unwrap();
+
+        // Read-only files are shared.
+        let file_1 = open_disk_image(path, true, false).unwrap();
+        let file_2 = open_disk_image(path, true, false).unwrap();
+        assert!(Arc::ptr_eq(&file_1, &file_2));
+        assert_eq!(Arc::strong_count(&file_1), 2);
+
+        // Writable files and files opened with different flags are not.
+        let file_3 = open_disk_image(path, false, false).unwrap();
+        assert!(!Arc::ptr_eq(&file_1, &file_3));
+        let file_4 = open_disk_image(path, true, true).unwrap();
+        assert!(!Arc::ptr_eq(&file_1, &file_4));
+        let file_5 = open_disk_image(path, true, true).unwrap();
+        assert!(Arc::ptr_eq(&file_4, &file_5));
+
+        // The file gets reopened once no device uses it anymore.
+        drop(file_2);
+        let weak_file = Arc::downgrade(&file_1);
+        drop(file_1);
+        assert!(weak_file.upgrade().is_none());
+        let file_6 = open_disk_image(path, true, false).unwrap();
+        assert_eq!(Arc::strong_count(&file_6), 1);
+
+        assert!(open_disk_image("/invalid/path", true, false).is_err());
+    }
+
+    #[test]
+    fn test_adopt_disk_image() {
+        let f = TempFile::new().unwrap();
+        let path = f.as_path().to_str().unwrap();
+
+        // The adopted read-only files are shared with the opened ones.
+        let file_1 = open_disk_image(path, true, false).unwrap();
+        let file_2 = adopt_disk_image(File::open(path).unwrap(), true, false).unwrap();
+        assert!(Arc::ptr_eq(&file_1, &file_2));
+
+        let file_3 = adopt_disk_image(File::open(path).unwrap(), true, true).unwrap();
+        assert!(!Arc::ptr_eq(&file_1, &file_3));
+        let flags = unsafe { libc::fcntl(file_3.as_raw_fd(), libc::F_GETFL) };
+        assert_ne!(flags & libc::O_NOATIME, 0);
+
+        // Writable devices need files opened for writing.
+        let writable = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        assert!(adopt_disk_image(writable, false, false).is_ok());
+        assert_eq!(
+            adopt_disk_image(File::open(path).unwrap(), false, false)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::PermissionDenied
+        );
+
+        // The file descriptors named by URIs are adopted.
+        fd_uri::record_inherited_fds().unwrap();
+        let file_4 = open_disk_image(&format!("fd://{}", f.as_file().as_raw_fd()), true, false);
+        assert!(Arc::ptr_eq(&file_1, &file_4.unwrap()));
+        assert!(open_disk_image("fd://1000000", true, false).is_err());
+    }
+
+    #[test]
+    fn test_copy_disk_image() {
+        let f = TempFile::new().unwrap();
+        let data: Vec<u8> = (0..COPY_CHUNK_SIZE).map(|i| i as u8).collect();
+        // A chunk of data between two holes.
+        f.as_file()
+            .write_all_at(&data, COPY_CHUNK_SIZE as u64)
+            .unwrap();
+        f.as_file().set_len(3 * COPY_CHUNK_SIZE as u64 + 1).unwrap();
+        let dest = TempFile::new().unwrap();
+        let dest_path = dest.as_path().to_path_buf();
+        drop(dest);
+
+        copy_disk_image(f.as_file(), &dest_path).unwrap();
+        let copy = fs::read(&dest_path).unwrap();
+        assert_eq!(copy.len(), 3 * COPY_CHUNK_SIZE + 1);
+        assert!(copy[..COPY_CHUNK_SIZE].iter().all(|&b| b == 0));
+        assert_eq!(&copy[COPY_CHUNK_SIZE..2 * COPY_CHUNK_SIZE], &data[..]);
+        assert!(copy[2 * COPY_CHUNK_SIZE..].iter().all(|&b| b == 0));
+
+        // Existing files are not overwritten.
+        assert_eq!(
+            copy_disk_image(f.as_file(), &dest_path).unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        assert_eq!(fs::read(&dest_path).unwrap(), copy);
+        fs::remove_file(&dest_path).unwrap();
+
+        // The partial copies are removed.
+        let writer = OpenOptions::new().write(true).open(f.as_path()).unwrap();
+        assert!(copy_disk_image(&writer, &dest_path).is_err());
+        assert!(!dest_path.exists());
+    }
+
+    #[test]
+    fn test_reflink_disk_image() {
+        let f = TempFile::new().unwrap();
+        f.as_file().write_all_at(b"data", 0).unwrap();
+        let dest = TempFile::new().unwrap();
+        let dest_path = dest.as_path().to_path_buf();
+
+        // Existing files are not overwritten.
+        assert_eq!(
+            reflink_disk_image(f.as_file(), &dest_path)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        drop(dest);
+
+        // The copy is only left behind if the filesystem supports reflinks.
+        if reflink_disk_image(f.as_file(), &dest_path).unwrap() {
+            assert_eq!(fs::read(&dest_path).unwrap(), b"data");
+            fs::remove_file(&dest_path).unwrap();
+        } else {
+            assert!(!dest_path.exists());
+        }
+    }
+
+    #[test]
+    fn test_advise_disk_image() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x10000).unwrap();
+
+        advise_disk_image(f.as_file(), 0, 0, libc::POSIX_FADV_SEQUENTIAL).unwrap();
+        advise_disk_image(f.as_file(), 0x1000, 0x1000, libc::POSIX_FADV_WILLNEED).unwrap();
+        // The ranges beyond the end of the file are ignored.
+        advise_disk_image(f.as_file(), 0x100000, 0x1000, libc::POSIX_FADV_DONTNEED).unwrap();
+
+        assert_eq!(
+            advise_disk_image(f.as_file(), u64::max_value(), 0, libc::POSIX_FADV_WILLNEED)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EINVAL)
+        );
+        assert_eq!(
+            advise_disk_image(f.as_file(), 0, 0, -1)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EINVAL)
+        );
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Encrypts the data of a block device at rest, so that the host file only ever holds
+//! ciphertext, without relying on the guest to set up disk encryption.
+//!
+//! The sectors are encrypted with AES-XTS, using their number as the tweak, which matches the
+//! `aes-xts-plain64` cipher of dm-crypt: a disk image encrypted by Firecracker can be opened on
+//! the host with `cryptsetup open --type plain --cipher aes-xts-plain64`, and vice versa.
+
+use aes::cipher::KeyInit;
+use aes::{Aes128, Aes256};
+use xts_mode::{get_tweak_default, Xts128};
+
+use super::SECTOR_SIZE;
+
+/// Length of an AES-128-XTS key, which holds two AES-128 keys.
+pub const AES_128_XTS_KEY_LEN: usize = 32;
+/// Length of an AES-256-XTS key, which holds two AES-256 keys.
+pub const AES_256_XTS_KEY_LEN: usize = 64;
+
+/// The cipher encrypting the sectors of a block device.
+pub enum DiskCipher {
+    Aes128Xts(Xts128<Aes128>),
+    Aes256Xts(Xts128<Aes256>),
+}
+
+impl DiskCipher {
+    /// Creates the cipher from a raw AES-XTS key, whose length selects AES-128 or AES-256.
+    ///
+    /// Returns `None` if the key is neither `AES_128_XTS_KEY_LEN` nor `AES_256_XTS_KEY_LEN`
+    /// bytes long.
+    pub fn new(key: &[u8]) -> Option<DiskCipher> {
+        let (key_1, key_2) = key.split_at(key.len() / 2);
+        match key.len() {
+            AES_128_XTS_KEY_LEN => Some(DiskCipher::Aes128Xts(Xts128::new(
+                Aes128::new_from_slice(key_1).ok()?,
+                Aes128::new_from_slice(key_2).ok()?,
+            ))),
+            AES_256_XTS_KEY_LEN => Some(DiskCipher::Aes256Xts(Xts128::new(
+                Aes256::new_from_slice(key_1).ok()?,
+                Aes256::new_from_slice(key_2).ok()?,
+            ))),
+            _ => None,
+        }
+    }
+
+    /// Encrypts in place the sectors in `data`, the first of which is `first_sector`.
+    ///
+    /// The length of `data` must be a multiple of `SECTOR_SIZE`.
+    pub fn encrypt(&self, data: &mut [u8], first_sector: u64) {
+        let first_sector = u128::from(first_sector);
+        match self {
+            DiskCipher::Aes128Xts(xts) => {
+                xts.encrypt_area(data, SECTOR_SIZE as usize, first_sector, get_tweak_default)
+            }
+            DiskCipher::Aes256Xts(xts) => {
+                xts.encrypt_area(data, SECTOR_SIZE as usize, first_sector, get_tweak_default)
+            }
+        }
+    }
+
+    /// Decrypts in place the sectors in `data`, the first of which is `first_sector`.
+    ///
+    /// The length of `data` must be a multiple of `SECTOR_SIZE`.
+    pub fn decrypt(&self, data: &mut [u8], first_sector: u64) {
+        let first_sector = u128::from(first_sector);
+        match self {
+            DiskCipher::Aes128Xts(xts) => {
+                xts.decrypt_area(data, SECTOR_SIZE as usize, first_sector, get_tweak_default)
+            }
+            DiskCipher::Aes256Xts(xts) => {
+                xts.decrypt_area(data, SECTOR_SIZE as usize, first_sector, get_tweak_default)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_key_length() {
+        assert!(DiskCipher::new(&[0; 16]).is_none());
+        assert!(DiskCipher::new(&[0; 48]).is_none());
+        match DiskCipher::new(&[0; AES_128_XTS_KEY_LEN]) {
+            Some(DiskCipher::Aes128Xts(_)) => (),
+            _ => panic!("Expected an AES-128-XTS cipher."),
+        }
+        match DiskCipher::new(&[0; AES_256_XTS_KEY_LEN]) {
+            Some(DiskCipher::Aes256Xts(_)) => (),
+            _ => panic!("Expected an AES-256-XTS cipher."),
+        }
+    }
+
+    #[test]
+    fn test_ieee_1619_vectors() {
+        // The vectors encrypt 32-byte data units, which are the first two blocks of a sector.
+        let cipher = DiskCipher::new(&[0; AES_128_XTS_KEY_LEN]).unwrap();
+        let mut sector = vec![0u8; SECTOR_SIZE as usize];
+        cipher.encrypt(&mut sector, 0);
+        assert_eq!(
+            sector[..32],
+            from_hex("917cf69ebd68b2ec9b9fe9a3eadda692cd43d2f59598ed858c02c2652fbf922e")[..]
+        );
+
+        let mut key = vec![0x11u8; AES_128_XTS_KEY_LEN / 2];
+        key.extend_from_slice(&[0x22; AES_128_XTS_KEY_LEN / 2]);
+        let cipher = DiskCipher::new(&key).unwrap();
+        let mut sector = vec![0x44u8; SECTOR_SIZE as usize];
+        cipher.encrypt(&mut sector, 0x33_3333_3333);
+        assert_eq!(
+            sector[..32],
+            from_hex("c454185e6a16936e39334038acef838bfb186fff7480adc4289382ecd6d394f0")[..]
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let key: Vec<u8> = (0..AES_256_XTS_KEY_LEN as u8).collect();
+        let cipher = DiskCipher::new(&key).unwrap();
+        let plaintext: Vec<u8> = (0..4 * SECTOR_SIZE).map(|i| i as u8).collect();
+
+        let mut data = plaintext.clone();
+        cipher.encrypt(&mut data, 7);
+        assert_ne!(data, plaintext);
+        // Each sector is encrypted with its own tweak.
+        let mut sector = plaintext[SECTOR_SIZE as usize..2 * SECTOR_SIZE as usize].to_vec();
+        cipher.encrypt(&mut sector, 8);
+        assert_eq!(
+            sector[..],
+            data[SECTOR_SIZE as usize..2 * SECTOR_SIZE as usize]
+        );
+
+        cipher.decrypt(&mut data, 7);
+        assert_eq!(data, plaintext);
+    }
+}
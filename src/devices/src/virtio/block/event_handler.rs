@@ -65,6 +65,7 @@ impl Subscriber for Block {
         }
 
         if self.is_activated() {
+            self.dispatch_budget = evmgr.device_budget();
             let queue_evt = self.queue_evts[0].as_raw_fd();
             let rate_limiter_evt = self.rate_limiter.as_raw_fd();
             let activate_fd = self.activate_evt.as_raw_fd();
@@ -90,6 +91,11 @@ impl Subscriber for Block {
             self.activate_evt.as_raw_fd() as u64,
         )]
     }
+
+    fn poll(&mut self, evmgr: &mut EventManager) -> bool {
+        self.dispatch_budget = evmgr.device_budget();
+        self.poll_queue()
+    }
 }
 
 #[cfg(test)]
@@ -171,4 +177,37 @@ pub mod tests {
         assert_eq!(vq.used.ring[0].get().len, 0);
         assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
     }
+
+    #[test]
+    fn test_busy_poll() {
+        let mut event_manager = EventManager::new().unwrap();
+        let mut block = default_block();
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.set_queue(0, vq.create_queue());
+        initialize_virtqueue(&vq);
+
+        let block = Arc::new(Mutex::new(block));
+        event_manager.add_subscriber(block.clone()).unwrap();
+        // Nothing is polled before activation.
+        assert!(!block.lock().unwrap().poll(&mut event_manager));
+        block.lock().unwrap().activate(mem.clone()).unwrap();
+        assert_eq!(event_manager.run_with_timeout(50).unwrap(), 1);
+
+        // Push a 'Write' operation, without triggering the queue event.
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        mem.write_obj::<u32>(VIRTIO_BLK_T_OUT, request_type_addr)
+            .unwrap();
+        vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+        vq.dtable[1].len.set(8);
+
+        // The request is served while busy polling.
+        event_manager.set_busy_poll(1000);
+        assert_eq!(event_manager.run_with_timeout(100).unwrap(), 0);
+        assert_eq!(block.lock().unwrap().interrupt_evt().read().unwrap(), 1);
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+        assert!(!block.lock().unwrap().poll(&mut event_manager));
+    }
 }
@@ -2,23 +2,89 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod device;
+mod disk_image;
+pub mod encryption;
 pub mod event_handler;
 pub mod persist;
 pub mod request;
+pub mod scheduler;
+pub mod verity;
 
 pub use self::device::Block;
+use self::disk_image::advise_disk_image;
+pub use self::disk_image::{
+    adopt_disk_image, copy_disk_image, open_disk_image, reflink_disk_image,
+};
 pub use self::event_handler::*;
 pub use self::request::*;
 
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
 use vm_memory::GuestMemoryError;
 
 pub const CONFIG_SPACE_SIZE: usize = 8;
+/// The size of the configuration space of the block devices reporting their topology.
+pub const TOPOLOGY_CONFIG_SPACE_SIZE: usize = 32;
+/// The largest physical block size a block device can report.
+pub const MAX_PHYSICAL_BLOCK_SIZE: u32 = 64 << 10;
 pub const SECTOR_SHIFT: u8 = 9;
 pub const SECTOR_SIZE: u64 = (0x01 as u64) << SECTOR_SHIFT;
 pub const QUEUE_SIZE: u16 = 256;
 pub const NUM_QUEUES: usize = 1;
 pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE];
 
+/// What a block device does when a write fails because the host ran out of space for its
+/// backing file, e.g. a sparse file on a full volume.
+#[derive(Clone, Copy, Debug, PartialEq, Versionize)]
+pub enum EnospcPolicy {
+    /// The request fails with an I/O error, like any other failure.
+    Error,
+    /// The device is paused, and the request is served again once it is resumed.
+    PauseDrive,
+    /// The device is paused, and the microVM is stopped.
+    StopVm,
+}
+
+impl Default for EnospcPolicy {
+    fn default() -> Self {
+        EnospcPolicy::Error
+    }
+}
+
+/// How the guest is expected to access the backing file of a block device, hinted to the host
+/// page cache.
+#[derive(Clone, Copy, Debug, PartialEq, Versionize)]
+pub enum PageCacheAccess {
+    /// No particular pattern, the host reads ahead as usual.
+    Normal,
+    /// Sequential accesses, for which the host reads further ahead.
+    Sequential,
+    /// Random accesses, for which the host doesn't read ahead.
+    Random,
+}
+
+impl Default for PageCacheAccess {
+    fn default() -> Self {
+        PageCacheAccess::Normal
+    }
+}
+
+/// Events emitted by the block devices.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockEvent {
+    /// The host ran out of space for the backing file of the device, which applied `policy`.
+    /// Emitted once, until a write succeeds again or the device is resumed.
+    OutOfSpace {
+        /// ID of the device.
+        drive_id: String,
+        /// What the device did about it.
+        policy: EnospcPolicy,
+    },
+}
+
+/// Callback used by the block devices to surface `BlockEvent`s.
+pub type BlockEventSink = Box<dyn Fn(BlockEvent) + Send>;
+
 #[derive(Debug)]
 pub enum Error {
     /// Guest gave us too few descriptors in a descriptor chain.
@@ -29,6 +95,8 @@ pub enum Error {
     GetFileMetadata(std::io::Error),
     /// Guest gave us bad memory addresses.
     GuestMemory(GuestMemoryError),
+    /// The data of a request to an encrypted device doesn't span whole sectors.
+    InvalidDataLength,
     /// The requested operation would cause a seek beyond disk end.
     InvalidOffset,
     /// Guest gave us a read only descriptor that protocol says to write to.
@@ -27,6 +27,60 @@ pub struct BlockState {
     disk_path: String,
     virtio_state: VirtioDeviceState,
     rate_limiter_state: RateLimiterState,
+    #[version(start = 2, default_fn = "default_no_atime")]
+    no_atime: bool,
+    #[version(start = 3, default_fn = "default_io_weight")]
+    io_weight: Option<u32>,
+    #[version(start = 4, default_fn = "default_serial")]
+    serial: Option<String>,
+    #[version(start = 4, default_fn = "default_physical_block_size")]
+    physical_block_size: Option<u32>,
+    #[version(start = 5, default_fn = "default_on_enospc")]
+    on_enospc: EnospcPolicy,
+    #[version(start = 6, default_fn = "default_page_cache_access")]
+    page_cache_access: PageCacheAccess,
+    #[version(start = 6, default_fn = "default_drop_written_pages")]
+    drop_written_pages: bool,
+}
+
+impl BlockState {
+    /// Provides the serial number of the saved block device, if any.
+    pub fn serial(&self) -> Option<&str> {
+        self.serial.as_ref().map(String::as_str)
+    }
+
+    /// Provides the physical block size the saved block device reports to the guest, if any.
+    pub fn physical_block_size(&self) -> Option<u32> {
+        self.physical_block_size
+    }
+
+    fn default_no_atime(_: u16) -> bool {
+        false
+    }
+
+    fn default_io_weight(_: u16) -> Option<u32> {
+        None
+    }
+
+    fn default_serial(_: u16) -> Option<String> {
+        None
+    }
+
+    fn default_physical_block_size(_: u16) -> Option<u32> {
+        None
+    }
+
+    fn default_on_enospc(_: u16) -> EnospcPolicy {
+        EnospcPolicy::default()
+    }
+
+    fn default_page_cache_access(_: u16) -> PageCacheAccess {
+        PageCacheAccess::default()
+    }
+
+    fn default_drop_written_pages(_: u16) -> bool {
+        false
+    }
 }
 
 pub struct BlockConstructorArgs {
@@ -46,6 +100,13 @@ impl Persist for Block {
             disk_path: self.disk_image_path.clone(),
             virtio_state: VirtioDeviceState::from_device(self),
             rate_limiter_state: self.rate_limiter.save(),
+            no_atime: self.no_atime,
+            io_weight: self.io_weight(),
+            serial: self.serial().map(String::from),
+            physical_block_size: self.physical_block_size(),
+            on_enospc: self.enospc_policy(),
+            page_cache_access: self.page_cache_access(),
+            drop_written_pages: self.drop_written_pages(),
         }
     }
 
@@ -60,11 +121,25 @@ impl Persist for Block {
             state.id.clone(),
             state.partuuid.clone(),
             state.disk_path.clone(),
+            None,
             is_disk_read_only,
+            state.no_atime,
             state.root_device,
             rate_limiter,
+            // Encrypted and verified block devices can't be snapshotted.
+            None,
+            None,
         )?;
 
+        block.set_serial(state.serial.clone());
+        block.set_physical_block_size(state.physical_block_size);
+        block.set_enospc_policy(state.on_enospc);
+        // Don't reset the hint given by the devices sharing the backing file.
+        if state.page_cache_access != PageCacheAccess::Normal {
+            block.set_page_cache_access(state.page_cache_access)?;
+        }
+        block.set_drop_written_pages(state.drop_written_pages);
+
         block.queues = state
             .virtio_state
             .queues
@@ -74,9 +149,12 @@ impl Persist for Block {
         block.interrupt_status = Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
         block.avail_features = state.virtio_state.avail_features;
         block.acked_features = state.virtio_state.acked_features;
+        block.set_io_weight(state.io_weight)?;
 
         if state.virtio_state.activated {
             block.device_state = DeviceState::Activated(constructor_args.mem);
+            // Make the event handler register the queue events once it gets subscribed.
+            block.activate_evt.write(1)?;
         }
 
         Ok(block)
@@ -104,9 +182,13 @@ mod tests {
             id,
             None,
             f.as_path().to_str().unwrap().to_string(),
+            None,
+            false,
             false,
             false,
             RateLimiter::default(),
+            None,
+            None,
         )
         .unwrap();
         let guest_mem = default_mem();
@@ -142,5 +224,267 @@ mod tests {
 
         // Test that block specific fields are the same.
         assert_eq!(&restored_block.disk_image_path, &block.disk_image_path);
+        assert_eq!(restored_block.no_atime, block.no_atime);
+    }
+
+    #[test]
+    fn test_persistence_no_atime() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let block = Block::new(
+            "test".to_string(),
+            None,
+            f.as_path().to_str().unwrap().to_string(),
+            None,
+            true,
+            true,
+            false,
+            RateLimiter::default(),
+            None,
+            None,
+        )
+        .unwrap();
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 2);
+
+        // Version 2 of the block state saves the `O_NOATIME` flag.
+        let mut mem = vec![0; 4096];
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert!(restored_block.no_atime());
+        assert!(restored_block.is_read_only());
+
+        // Older versions don't.
+        let mut mem = vec![0; 4096];
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+        assert!(!restored_block.no_atime());
+    }
+
+    #[test]
+    fn test_persistence_io_weight() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let mut block = Block::new(
+            "test".to_string(),
+            None,
+            f.as_path().to_str().unwrap().to_string(),
+            None,
+            false,
+            false,
+            false,
+            RateLimiter::default(),
+            None,
+            None,
+        )
+        .unwrap();
+        block.set_io_weight(Some(300)).unwrap();
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 2)
+            .new_version()
+            .set_type_version(BlockState::type_id(), 3);
+
+        // Version 3 of the block state saves the I/O weight.
+        let mut mem = vec![0; 4096];
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 3)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 3).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.io_weight(), Some(300));
+
+        // Older versions don't.
+        let mut mem = vec![0; 4096];
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.io_weight(), None);
+    }
+
+    #[test]
+    fn test_persistence_serial_and_topology() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let mut block = Block::new(
+            "test".to_string(),
+            None,
+            f.as_path().to_str().unwrap().to_string(),
+            None,
+            false,
+            false,
+            false,
+            RateLimiter::default(),
+            None,
+            None,
+        )
+        .unwrap();
+        block.set_serial(Some("data".to_string()));
+        block.set_physical_block_size(Some(4096));
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 2)
+            .new_version()
+            .set_type_version(BlockState::type_id(), 3)
+            .new_version()
+            .set_type_version(BlockState::type_id(), 4);
+
+        // Version 4 of the block state saves the serial and the physical block size.
+        let mut mem = vec![0; 4096];
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 4)
+            .unwrap();
+        let state = BlockState::deserialize(&mut mem.as_slice(), &version_map, 4).unwrap();
+        assert_eq!(state.serial(), Some("data"));
+        assert_eq!(state.physical_block_size(), Some(4096));
+        let restored_block =
+            Block::restore(BlockConstructorArgs { mem: default_mem() }, &state).unwrap();
+        assert_eq!(restored_block.serial(), Some("data"));
+        assert_eq!(restored_block.physical_block_size(), Some(4096));
+        assert_eq!(
+            restored_block.config_space_bytes(),
+            block.config_space_bytes()
+        );
+
+        // Older versions don't.
+        let mut mem = vec![0; 4096];
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 3)
+            .unwrap();
+        let state = BlockState::deserialize(&mut mem.as_slice(), &version_map, 3).unwrap();
+        assert_eq!(state.serial(), None);
+        assert_eq!(state.physical_block_size(), None);
+        let restored_block =
+            Block::restore(BlockConstructorArgs { mem: default_mem() }, &state).unwrap();
+        assert_eq!(restored_block.serial(), None);
+    }
+
+    #[test]
+    fn test_persistence_enospc_policy() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let mut block = Block::new(
+            "test".to_string(),
+            None,
+            f.as_path().to_str().unwrap().to_string(),
+            None,
+            false,
+            false,
+            false,
+            RateLimiter::default(),
+            None,
+            None,
+        )
+        .unwrap();
+        block.set_enospc_policy(EnospcPolicy::PauseDrive);
+        let mut version_map = VersionMap::new();
+        for version in 2..=5 {
+            version_map
+                .new_version()
+                .set_type_version(BlockState::type_id(), version);
+        }
+
+        // Version 5 of the block state saves the ENOSPC policy.
+        let mut mem = vec![0; 4096];
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 5)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 5).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.enospc_policy(), EnospcPolicy::PauseDrive);
+
+        // Older versions don't.
+        let mut mem = vec![0; 4096];
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 4)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 4).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.enospc_policy(), EnospcPolicy::Error);
+    }
+
+    #[test]
+    fn test_persistence_page_cache() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let mut block = Block::new(
+            "test".to_string(),
+            None,
+            f.as_path().to_str().unwrap().to_string(),
+            None,
+            false,
+            false,
+            false,
+            RateLimiter::default(),
+            None,
+            None,
+        )
+        .unwrap();
+        block
+            .set_page_cache_access(PageCacheAccess::Random)
+            .unwrap();
+        block.set_drop_written_pages(true);
+        let mut version_map = VersionMap::new();
+        for version in 2..=6 {
+            version_map
+                .new_version()
+                .set_type_version(BlockState::type_id(), version);
+        }
+
+        // Version 6 of the block state saves the page cache hints.
+        let mut mem = vec![0; 4096];
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 6)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 6).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.page_cache_access(), PageCacheAccess::Random);
+        assert!(restored_block.drop_written_pages());
+
+        // Older versions don't.
+        let mut mem = vec![0; 4096];
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 5)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 5).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.page_cache_access(), PageCacheAccess::Normal);
+        assert!(!restored_block.drop_written_pages());
     }
 }
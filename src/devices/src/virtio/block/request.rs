@@ -11,11 +11,16 @@ use std::result;
 
 use logger::{Metric, METRICS};
 use virtio_gen::virtio_blk::*;
-use vm_memory::{ByteValued, Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
+use vm_memory::{Address, ByteValued, Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
 
 use super::super::DescriptorChain;
+use super::encryption::DiskCipher;
+use super::verity::{HashTree, VerityError, VERITY_BLOCK_SIZE};
 use super::{Error, SECTOR_SHIFT, SECTOR_SIZE};
 
+/// The largest chunk of data an encrypted request goes through at once.
+const CRYPT_CHUNK_SIZE: usize = 64 << 10;
+
 #[derive(Debug)]
 pub enum ExecuteError {
     BadRequest(Error),
@@ -24,6 +29,7 @@ pub enum ExecuteError {
     Seek(io::Error),
     Write(GuestMemoryError),
     Unsupported(u32),
+    Verity(VerityError),
 }
 
 impl ExecuteError {
@@ -35,6 +41,19 @@ impl ExecuteError {
             ExecuteError::Seek(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::Write(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::Unsupported(_) => VIRTIO_BLK_S_UNSUPP,
+            ExecuteError::Verity(_) => VIRTIO_BLK_S_IOERR,
+        }
+    }
+
+    /// Specifies if the write failed because the host ran out of space for the backing file.
+    /// A flush failing so is a plain I/O error: the writes it covers were already completed,
+    /// so serving it again once there is room wouldn't bring back the data the host lost.
+    pub fn is_out_of_space(&self) -> bool {
+        match *self {
+            ExecuteError::Write(GuestMemoryError::IOError(ref e)) => {
+                e.raw_os_error() == Some(libc::ENOSPC)
+            }
+            _ => false,
         }
     }
 }
@@ -64,7 +83,7 @@ pub struct Request {
     pub request_type: RequestType,
     pub data_len: u32,
     pub status_addr: GuestAddress,
-    sector: u64,
+    pub(crate) sector: u64,
     data_addr: GuestAddress,
 }
 
@@ -181,6 +200,8 @@ impl Request {
         disk_nsectors: u64,
         mem: &GuestMemoryMmap,
         disk_id: &[u8],
+        cipher: Option<&DiskCipher>,
+        hash_tree: Option<&mut HashTree>,
     ) -> result::Result<u32, ExecuteError> {
         let mut top: u64 = u64::from(self.data_len) / SECTOR_SIZE;
         if u64::from(self.data_len) % SECTOR_SIZE != 0 {
@@ -192,21 +213,39 @@ impl Request {
         if top > disk_nsectors {
             return Err(ExecuteError::BadRequest(Error::InvalidOffset));
         }
+        // Sectors are only encrypted as a whole.
+        if cipher.is_some()
+            && (self.request_type == RequestType::In || self.request_type == RequestType::Out)
+            && u64::from(self.data_len) % SECTOR_SIZE != 0
+        {
+            return Err(ExecuteError::BadRequest(Error::InvalidDataLength));
+        }
 
         disk.seek(SeekFrom::Start(self.sector << SECTOR_SHIFT))
             .map_err(ExecuteError::Seek)?;
 
         match self.request_type {
             RequestType::In => {
-                mem.read_from(self.data_addr, disk, self.data_len as usize)
-                    .map_err(ExecuteError::Read)?;
+                match (cipher, hash_tree) {
+                    (Some(cipher), _) => self.read_decrypted(disk, mem, cipher)?,
+                    (None, Some(hash_tree)) => self.read_verified(disk, mem, hash_tree)?,
+                    (None, None) => {
+                        mem.read_from(self.data_addr, disk, self.data_len as usize)
+                            .map_err(ExecuteError::Read)?;
+                    }
+                }
                 METRICS.block.read_bytes.add(self.data_len as usize);
                 METRICS.block.read_count.inc();
                 return Ok(self.data_len);
             }
             RequestType::Out => {
-                mem.write_to(self.data_addr, disk, self.data_len as usize)
-                    .map_err(ExecuteError::Write)?;
+                match cipher {
+                    Some(cipher) => self.write_encrypted(disk, mem, cipher)?,
+                    None => {
+                        mem.write_to(self.data_addr, disk, self.data_len as usize)
+                            .map_err(ExecuteError::Write)?;
+                    }
+                }
                 METRICS.block.write_bytes.add(self.data_len as usize);
                 METRICS.block.write_count.inc();
             }
@@ -228,6 +267,88 @@ impl Request {
         };
         Ok(0)
     }
+
+    // Reads the requested sectors from the current position of `disk` into guest memory,
+    // decrypting them on the way.
+    fn read_decrypted<T: Read>(
+        &self,
+        disk: &mut T,
+        mem: &GuestMemoryMmap,
+        cipher: &DiskCipher,
+    ) -> result::Result<(), ExecuteError> {
+        let mut buf = vec![0u8; CRYPT_CHUNK_SIZE.min(self.data_len as usize)];
+        let mut done = 0;
+        while done < self.data_len as usize {
+            let chunk = &mut buf[..CRYPT_CHUNK_SIZE.min(self.data_len as usize - done)];
+            disk.read_exact(chunk)
+                .map_err(|e| ExecuteError::Read(GuestMemoryError::IOError(e)))?;
+            cipher.decrypt(chunk, self.sector + (done as u64 >> SECTOR_SHIFT));
+            mem.write_slice(chunk, self.data_addr.unchecked_add(done as u64))
+                .map_err(ExecuteError::Read)?;
+            done += chunk.len();
+        }
+        Ok(())
+    }
+
+    // Reads the requested sectors from `disk` into guest memory, after checking the whole data
+    // blocks holding them against the hash tree.
+    fn read_verified<T: Read + Seek>(
+        &self,
+        disk: &mut T,
+        mem: &GuestMemoryMmap,
+        hash_tree: &mut HashTree,
+    ) -> result::Result<(), ExecuteError> {
+        let start = self.sector << SECTOR_SHIFT;
+        let end = start + u64::from(self.data_len);
+        let mut block = start / VERITY_BLOCK_SIZE;
+        disk.seek(SeekFrom::Start(block * VERITY_BLOCK_SIZE))
+            .map_err(ExecuteError::Seek)?;
+
+        let mut buf = vec![0u8; VERITY_BLOCK_SIZE as usize];
+        while block * VERITY_BLOCK_SIZE < end {
+            let block_start = block * VERITY_BLOCK_SIZE;
+            disk.read_exact(&mut buf)
+                .map_err(|e| ExecuteError::Read(GuestMemoryError::IOError(e)))?;
+            hash_tree.verify_block(block, &buf).map_err(|e| {
+                if let VerityError::Corrupted(_) = e {
+                    METRICS.block.verity_fails.inc();
+                }
+                ExecuteError::Verity(e)
+            })?;
+
+            let from = start.max(block_start);
+            let to = end.min(block_start + VERITY_BLOCK_SIZE);
+            mem.write_slice(
+                &buf[(from - block_start) as usize..(to - block_start) as usize],
+                self.data_addr.unchecked_add(from - start),
+            )
+            .map_err(ExecuteError::Read)?;
+            block += 1;
+        }
+        Ok(())
+    }
+
+    // Writes the requested sectors from guest memory at the current position of `disk`,
+    // encrypting them on the way.
+    fn write_encrypted<T: Write>(
+        &self,
+        disk: &mut T,
+        mem: &GuestMemoryMmap,
+        cipher: &DiskCipher,
+    ) -> result::Result<(), ExecuteError> {
+        let mut buf = vec![0u8; CRYPT_CHUNK_SIZE.min(self.data_len as usize)];
+        let mut done = 0;
+        while done < self.data_len as usize {
+            let chunk = &mut buf[..CRYPT_CHUNK_SIZE.min(self.data_len as usize - done)];
+            mem.read_slice(chunk, self.data_addr.unchecked_add(done as u64))
+                .map_err(ExecuteError::Write)?;
+            cipher.encrypt(chunk, self.sector + (done as u64 >> SECTOR_SHIFT));
+            disk.write_all(chunk)
+                .map_err(|e| ExecuteError::Write(GuestMemoryError::IOError(e)))?;
+            done += chunk.len();
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -300,6 +421,24 @@ mod tests {
             VIRTIO_BLK_S_IOERR
         );
         assert_eq!(ExecuteError::Unsupported(42).status(), VIRTIO_BLK_S_UNSUPP);
+        assert_eq!(
+            ExecuteError::Verity(VerityError::Corrupted(42)).status(),
+            VIRTIO_BLK_S_IOERR
+        );
+    }
+
+    #[test]
+    fn test_execute_error_out_of_space() {
+        let enospc = || io::Error::from_raw_os_error(libc::ENOSPC);
+        assert!(ExecuteError::Write(GuestMemoryError::IOError(enospc())).is_out_of_space());
+        assert!(
+            !ExecuteError::Write(GuestMemoryError::IOError(io::Error::from_raw_os_error(
+                libc::EIO
+            )))
+            .is_out_of_space()
+        );
+        // The writes a flush covers were already completed.
+        assert!(!ExecuteError::Flush(enospc()).is_out_of_space());
     }
 
     #[test]
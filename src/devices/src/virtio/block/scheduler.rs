@@ -0,0 +1,266 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Shares the throughput of a host device between the block devices it backs, in proportion to
+//! their I/O weights.
+//!
+//! The block devices backed by the same host device form a group, in which each of them accrues
+//! a virtual time, growing with the bytes it transfers divided by its weight. A device running
+//! too far ahead of the other devices of the group which have requests waiting yields: it leaves
+//! its remaining requests in its queue for the next round of the event loop, once the others
+//! have been served. Devices which are throttled by their rate limiter or have no requests left
+//! don't hold back the others.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::os::linux::fs::MetadataExt;
+use std::os::unix::fs::FileTypeExt;
+use std::sync::{Arc, Mutex, Weak};
+
+/// The weight of a device which is neither favored nor penalized.
+pub const DEFAULT_IO_WEIGHT: u32 = 100;
+/// The smallest I/O weight.
+pub const MIN_IO_WEIGHT: u32 = 1;
+/// The largest I/O weight.
+pub const MAX_IO_WEIGHT: u32 = 1000;
+// Requests are charged a fixed cost on top of their data, so that small requests aren't free.
+const REQUEST_COST: u64 = 4096;
+// How far a device can run ahead of the others, in bytes transferred at the default weight.
+const MAX_LEAD: u64 = 1 << 20;
+
+lazy_static! {
+    static ref IO_GROUPS: Mutex<HashMap<u64, Weak<Mutex<IoGroup>>>> = Mutex::new(HashMap::new());
+}
+
+struct Member {
+    weight: u32,
+    vtime: u64,
+    backlogged: bool,
+}
+
+// The devices backed by the same host device.
+#[derive(Default)]
+struct IoGroup {
+    members: HashMap<u64, Member>,
+    next_id: u64,
+}
+
+impl IoGroup {
+    // The earliest virtual time of the backlogged devices other than `id`.
+    fn min_backlogged_vtime(&self, id: u64) -> Option<u64> {
+        self.members
+            .iter()
+            .filter(|(&other, member)| other != id && member.backlogged)
+            .map(|(_, member)| member.vtime)
+            .min()
+    }
+
+    // The latest virtual time of the devices other than `id`.
+    fn max_vtime(&self, id: u64) -> Option<u64> {
+        self.members
+            .iter()
+            .filter(|(&other, _)| other != id)
+            .map(|(_, member)| member.vtime)
+            .max()
+    }
+}
+
+// Identifies the host device holding `file`: the device itself for a block device, or the device
+// of the filesystem holding a regular file.
+fn host_device(file: &File) -> io::Result<u64> {
+    let metadata = file.metadata()?;
+    if metadata.file_type().is_block_device() {
+        Ok(metadata.st_rdev())
+    } else {
+        Ok(metadata.st_dev())
+    }
+}
+
+/// The membership of a block device in the group of its host device.
+pub struct IoShare {
+    group: Arc<Mutex<IoGroup>>,
+    id: u64,
+    weight: u32,
+}
+
+impl IoShare {
+    /// Joins the group of the host device holding `file`, with `weight`.
+    pub fn new(file: &File, weight: u32) -> io::Result<IoShare> {
+        if weight < MIN_IO_WEIGHT || weight > MAX_IO_WEIGHT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid I/O weight.",
+            ));
+        }
+        let key = host_device(file)?;
+        let mut groups = IO_GROUPS.lock().expect("Poisoned lock");
+        let group = match groups.get(&key).and_then(Weak::upgrade) {
+            Some(group) => group,
+            None => {
+                let group = Arc::new(Mutex::new(IoGroup::default()));
+                // Forget the groups no longer used by any device.
+                groups.retain(|_, group| group.upgrade().is_some());
+                groups.insert(key, Arc::downgrade(&group));
+                group
+            }
+        };
+        Ok(Self::join(group, weight))
+    }
+
+    fn join(group: Arc<Mutex<IoGroup>>, weight: u32) -> IoShare {
+        let id = {
+            let mut locked_group = group.lock().expect("Poisoned lock");
+            let id = locked_group.next_id;
+            locked_group.next_id += 1;
+            locked_group.members.insert(
+                id,
+                Member {
+                    weight,
+                    vtime: 0,
+                    backlogged: false,
+                },
+            );
+            id
+        };
+        IoShare { group, id, weight }
+    }
+
+    /// The weight of the device.
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+
+    /// Marks whether the device has requests it could serve.
+    pub fn set_backlogged(&self, backlogged: bool) {
+        let mut group = self.group.lock().expect("Poisoned lock");
+        let max_vtime = group.max_vtime(self.id);
+        let member = group
+            .members
+            .get_mut(&self.id)
+            .expect("Missing group member");
+        // A device waking up gets served right away, but doesn't get more credit than that for
+        // the time it was idle.
+        if backlogged && !member.backlogged {
+            if let Some(max_vtime) = max_vtime {
+                member.vtime = member.vtime.max(max_vtime.saturating_sub(MAX_LEAD));
+            }
+        }
+        member.backlogged = backlogged;
+    }
+
+    /// Specifies if the device can serve a request now, rather than yield to the other devices.
+    pub fn may_dispatch(&self) -> bool {
+        let group = self.group.lock().expect("Poisoned lock");
+        match group.min_backlogged_vtime(self.id) {
+            Some(min_vtime) => group.members[&self.id].vtime <= min_vtime.saturating_add(MAX_LEAD),
+            None => true,
+        }
+    }
+
+    /// Charges the device for a request transferring `bytes`.
+    pub fn charge(&self, bytes: u64) {
+        let mut group = self.group.lock().expect("Poisoned lock");
+        let member = group
+            .members
+            .get_mut(&self.id)
+            .expect("Missing group member");
+        let cost = (bytes + REQUEST_COST) * u64::from(DEFAULT_IO_WEIGHT) / u64::from(member.weight);
+        member.vtime = member.vtime.saturating_add(cost);
+    }
+}
+
+impl Drop for IoShare {
+    fn drop(&mut self) {
+        self.group
+            .lock()
+            .expect("Poisoned lock")
+            .members
+            .remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use utils::tempfile::TempFile;
+
+    // Serves the backlogged shares in rounds, the way the event loop does, each of them until it
+    // yields or runs out of its `requests`, and returns the number of requests each one served.
+    fn serve(shares: &[&IoShare], requests: &[usize], bytes: u64, rounds: usize) -> Vec<usize> {
+        let mut served = vec![0; shares.len()];
+        for _ in 0..rounds {
+            for (i, share) in shares.iter().enumerate() {
+                while served[i] < requests[i] {
+                    share.set_backlogged(true);
+                    if !share.may_dispatch() {
+                        break;
+                    }
+                    share.charge(bytes);
+                    served[i] += 1;
+                }
+                share.set_backlogged(served[i] < requests[i]);
+            }
+        }
+        served
+    }
+
+    #[test]
+    fn test_new() {
+        let file = TempFile::new().unwrap();
+        assert!(IoShare::new(file.as_file(), 0).is_err());
+        assert!(IoShare::new(file.as_file(), MAX_IO_WEIGHT + 1).is_err());
+
+        let share = IoShare::new(file.as_file(), 10).unwrap();
+        assert_eq!(share.weight(), 10);
+        // The files on the same filesystem share a group, until the last device leaves it.
+        let other_file = TempFile::new().unwrap();
+        let other_share = IoShare::new(other_file.as_file(), 20).unwrap();
+        assert!(Arc::ptr_eq(&share.group, &other_share.group));
+        assert_eq!(share.group.lock().unwrap().members.len(), 2);
+        drop(other_share);
+        assert_eq!(share.group.lock().unwrap().members.len(), 1);
+    }
+
+    #[test]
+    fn test_weighted_shares() {
+        let group = Arc::new(Mutex::new(IoGroup::default()));
+        let heavy = IoShare::join(group.clone(), 300);
+        let light = IoShare::join(group, 100);
+
+        // A device alone never yields.
+        assert_eq!(serve(&[&heavy], &[1000], 1 << 20, 1), vec![1000]);
+
+        // The light device doesn't get a credit for the time it was idle, and the backlogged
+        // devices share the throughput in proportion to their weights.
+        heavy.set_backlogged(true);
+        light.set_backlogged(true);
+        let served = serve(&[&heavy, &light], &[10_000, 10_000], 64 << 10, 100);
+        assert!(served[0] < 10_000 && served[1] < 10_000);
+        let ratio = served[0] as f64 / served[1] as f64;
+        assert!(ratio > 2.5 && ratio < 3.5, "{:?}", served);
+    }
+
+    #[test]
+    fn test_idle_devices() {
+        let group = Arc::new(Mutex::new(IoGroup::default()));
+        let writer = IoShare::join(group.clone(), 100);
+        let reader = IoShare::join(group, 100);
+
+        // The reader only holds back the writer while it has requests waiting.
+        reader.set_backlogged(true);
+        assert_eq!(serve(&[&writer], &[1000], 64 << 10, 1), vec![16]);
+        reader.set_backlogged(false);
+        assert_eq!(serve(&[&writer], &[1000], 64 << 10, 1), vec![1000]);
+
+        // A reader waking up is served right away, and holds back the writer.
+        reader.set_backlogged(true);
+        assert!(reader.may_dispatch());
+        assert_eq!(
+            serve(&[&writer, &reader], &[1000, 4], 64 << 10, 1),
+            vec![1, 4]
+        );
+        assert!(!reader.group.lock().unwrap().members[&reader.id].backlogged);
+    }
+}
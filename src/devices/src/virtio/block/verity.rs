@@ -0,0 +1,354 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Verifies the data read from an immutable block device against a hash tree, so that the
+//! guest never sees data which doesn't match a trusted root hash.
+//!
+//! The hash tree follows the layout of dm-verity (format version 1, SHA-256, 4 KiB data and
+//! hash blocks), as built by `veritysetup format`: the levels of the tree are stored from the
+//! root down, each hash block holds the salted digests of 128 blocks of the level below, and the
+//! salted digest of the single block at the top of the tree is the root hash.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+
+use sha2::{Digest, Sha256};
+
+/// Size of the data blocks and of the hash blocks.
+pub const VERITY_BLOCK_SIZE: u64 = 4096;
+/// Size of a SHA-256 digest.
+pub const VERITY_DIGEST_SIZE: usize = 32;
+// Each hash block holds 128 digests.
+const HASHES_PER_BLOCK_BITS: u32 = 7;
+const HASHES_PER_BLOCK: u64 = 1 << HASHES_PER_BLOCK_BITS;
+// Bounds the memory holding the hash blocks already verified, 4 MiB.
+const MAX_CACHED_HASH_BLOCKS: usize = 1024;
+
+/// Errors associated with the verification of a block device.
+#[derive(Debug)]
+pub enum VerityError {
+    /// The size of the data isn't a non-zero multiple of `VERITY_BLOCK_SIZE`.
+    InvalidDataSize(u64),
+    /// The root hash isn't a SHA-256 digest.
+    InvalidRootHash,
+    /// The hash file is too short to hold the hash tree of the data.
+    HashTreeTooShort,
+    /// Cannot access the hash file.
+    HashFile(io::Error),
+    /// The data block doesn't match the hash tree.
+    Corrupted(u64),
+}
+
+impl fmt::Display for VerityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::VerityError::*;
+        match self {
+            InvalidDataSize(size) => write!(
+                f,
+                "The data size {} is not a non-zero multiple of {} bytes.",
+                size, VERITY_BLOCK_SIZE
+            ),
+            InvalidRootHash => write!(f, "The root hash is not a SHA-256 digest."),
+            HashTreeTooShort => write!(f, "The hash file is too short to hold the hash tree."),
+            HashFile(e) => write!(f, "Cannot read the hash file: {}", e),
+            Corrupted(block) => write!(f, "Data block {} doesn't match the hash tree.", block),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, VerityError>;
+
+/// The hash tree of a block device.
+pub struct HashTree {
+    hash_file: File,
+    hash_offset: u64,
+    salt: Vec<u8>,
+    root_hash: Vec<u8>,
+    data_blocks: u64,
+    // Offsets of the levels of the tree in the hash file, in blocks, from the lowest level.
+    level_offsets: Vec<u64>,
+    // The hash blocks already verified up to the root, indexed by their offset in blocks.
+    verified: HashMap<u64, Vec<u8>>,
+}
+
+impl HashTree {
+    /// Creates the hash tree of `data_size` bytes of data, stored at `hash_offset` in
+    /// `hash_file`.
+    pub fn new(
+        hash_file: File,
+        hash_offset: u64,
+        data_size: u64,
+        root_hash: Vec<u8>,
+        salt: Vec<u8>,
+    ) -> Result<HashTree> {
+        if data_size == 0 || data_size % VERITY_BLOCK_SIZE != 0 {
+            return Err(VerityError::InvalidDataSize(data_size));
+        }
+        if root_hash.len() != VERITY_DIGEST_SIZE {
+            return Err(VerityError::InvalidRootHash);
+        }
+
+        let data_blocks = data_size / VERITY_BLOCK_SIZE;
+        let mut levels = 0;
+        while (data_blocks - 1) >> (HASHES_PER_BLOCK_BITS * levels) != 0 {
+            levels += 1;
+        }
+        let mut level_offsets = vec![0; levels as usize];
+        let mut hash_blocks = 0;
+        for level in (0..levels).rev() {
+            level_offsets[level as usize] = hash_blocks;
+            let shift = HASHES_PER_BLOCK_BITS * (level + 1);
+            hash_blocks += (data_blocks + (1 << shift) - 1) >> shift;
+        }
+
+        let hash_file_size = hash_file.metadata().map_err(VerityError::HashFile)?.len();
+        if hash_file_size < hash_offset.saturating_add(hash_blocks * VERITY_BLOCK_SIZE) {
+            return Err(VerityError::HashTreeTooShort);
+        }
+
+        Ok(HashTree {
+            hash_file,
+            hash_offset,
+            salt,
+            root_hash,
+            data_blocks,
+            level_offsets,
+            verified: HashMap::new(),
+        })
+    }
+
+    /// Size of the data covered by the hash tree.
+    pub fn data_size(&self) -> u64 {
+        self.data_blocks * VERITY_BLOCK_SIZE
+    }
+
+    fn digest(&self, data: &[u8]) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.salt);
+        hasher.update(data);
+        hasher.finalize().to_vec()
+    }
+
+    /// Checks that `data` is the content of the data block number `block`.
+    pub fn verify_block(&mut self, block: u64, data: &[u8]) -> Result<()> {
+        let mut digest = self.digest(data);
+        let mut pending = Vec::new();
+
+        for (level, level_offset) in self.level_offsets.iter().enumerate() {
+            let shift = HASHES_PER_BLOCK_BITS * level as u32;
+            let index = level_offset + (block >> (shift + HASHES_PER_BLOCK_BITS));
+            let entry = ((block >> shift) % HASHES_PER_BLOCK) as usize * VERITY_DIGEST_SIZE;
+
+            // A hash block verified earlier is trusted, along with the digests it holds.
+            if let Some(hash_block) = self.verified.get(&index) {
+                if hash_block[entry..entry + VERITY_DIGEST_SIZE] != digest[..] {
+                    return Err(VerityError::Corrupted(block));
+                }
+                digest = self.root_hash.clone();
+                break;
+            }
+
+            let mut hash_block = vec![0u8; VERITY_BLOCK_SIZE as usize];
+            self.hash_file
+                .read_exact_at(
+                    &mut hash_block,
+                    self.hash_offset + index * VERITY_BLOCK_SIZE,
+                )
+                .map_err(VerityError::HashFile)?;
+            if hash_block[entry..entry + VERITY_DIGEST_SIZE] != digest[..] {
+                return Err(VerityError::Corrupted(block));
+            }
+            digest = self.digest(&hash_block);
+            pending.push((index, hash_block));
+        }
+
+        if digest != self.root_hash {
+            return Err(VerityError::Corrupted(block));
+        }
+        if self.verified.len() + pending.len() > MAX_CACHED_HASH_BLOCKS {
+            self.verified.clear();
+        }
+        self.verified.extend(pending);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use utils::tempfile::TempFile;
+
+    /// Builds the hash tree of `data` in `hash_file`, and returns the root hash.
+    pub(crate) fn build_hash_tree(data: &[u8], salt: &[u8], mut hash_file: &File) -> Vec<u8> {
+        let digest = |block: &[u8]| {
+            let mut hasher = Sha256::new();
+            hasher.update(salt);
+            hasher.update(block);
+            hasher.finalize().to_vec()
+        };
+
+        // Build the levels from the data up, then store them from the root down.
+        let mut levels: Vec<Vec<u8>> = Vec::new();
+        let mut blocks: Vec<Vec<u8>> = data
+            .chunks(VERITY_BLOCK_SIZE as usize)
+            .map(|block| block.to_vec())
+            .collect();
+        while blocks.len() > 1 {
+            let mut level = Vec::new();
+            for children in blocks.chunks(HASHES_PER_BLOCK as usize) {
+                let mut hash_block = Vec::new();
+                for child in children {
+                    hash_block.extend(digest(child));
+                }
+                hash_block.resize(VERITY_BLOCK_SIZE as usize, 0);
+                level.extend_from_slice(&hash_block);
+            }
+            blocks = level
+                .chunks(VERITY_BLOCK_SIZE as usize)
+                .map(|block| block.to_vec())
+                .collect();
+            levels.push(level);
+        }
+        for level in levels.iter().rev() {
+            hash_file.write_all(level).unwrap();
+        }
+        digest(&blocks[0])
+    }
+
+    #[test]
+    fn test_new() {
+        let hash_file = TempFile::new().unwrap();
+        let new = |data_size, root_hash: Vec<u8>| {
+            HashTree::new(
+                hash_file.as_file().try_clone().unwrap(),
+                0,
+                data_size,
+                root_hash,
+                Vec::new(),
+            )
+        };
+
+        match new(0, vec![0; 32]) {
+            Err(VerityError::InvalidDataSize(0)) => (),
+            _ => panic!("Expected an invalid data size."),
+        }
+        match new(4097, vec![0; 32]) {
+            Err(VerityError::InvalidDataSize(4097)) => (),
+            _ => panic!("Expected an invalid data size."),
+        }
+        match new(4096, vec![0; 20]) {
+            Err(VerityError::InvalidRootHash) => (),
+            _ => panic!("Expected an invalid root hash."),
+        }
+
+        // A single data block needs no hash block.
+        let tree = new(4096, vec![0; 32]).unwrap();
+        assert!(tree.level_offsets.is_empty());
+        assert_eq!(tree.data_size(), 4096);
+
+        // 129 data blocks need two hash blocks in the lowest level, and the root hash block.
+        match new(129 * 4096, vec![0; 32]) {
+            Err(VerityError::HashTreeTooShort) => (),
+            _ => panic!("Expected a short hash tree."),
+        }
+        hash_file.as_file().set_len(3 * 4096).unwrap();
+        let tree = new(129 * 4096, vec![0; 32]).unwrap();
+        assert_eq!(tree.level_offsets, vec![1, 0]);
+    }
+
+    #[test]
+    fn test_verify_block() {
+        let salt = vec![0xa5; 16];
+        let data: Vec<u8> = (0..200 * VERITY_BLOCK_SIZE)
+            .map(|i| (i / VERITY_BLOCK_SIZE) as u8 ^ i as u8)
+            .collect();
+        let hash_file = TempFile::new().unwrap();
+        // Leave room for a superblock before the tree.
+        hash_file.as_file().write_all(&[0; 4096]).unwrap();
+        let root_hash = build_hash_tree(&data, &salt, hash_file.as_file());
+
+        let mut tree = HashTree::new(
+            hash_file.as_file().try_clone().unwrap(),
+            4096,
+            data.len() as u64,
+            root_hash.clone(),
+            salt,
+        )
+        .unwrap();
+        assert_eq!(tree.level_offsets.len(), 2);
+
+        let block_data = |block: u64| {
+            &data[(block * VERITY_BLOCK_SIZE) as usize..((block + 1) * VERITY_BLOCK_SIZE) as usize]
+        };
+        for &block in &[0, 1, 127, 128, 199] {
+            tree.verify_block(block, block_data(block)).unwrap();
+        }
+        // The hash blocks on the way to the root are now trusted.
+        assert_eq!(tree.verified.len(), 3);
+        tree.verify_block(2, block_data(2)).unwrap();
+
+        let mut corrupted = block_data(5).to_vec();
+        corrupted[42] ^= 1;
+        match tree.verify_block(5, &corrupted) {
+            Err(VerityError::Corrupted(5)) => (),
+            _ => panic!("Expected corrupted data."),
+        }
+        match tree.verify_block(6, block_data(5)) {
+            Err(VerityError::Corrupted(6)) => (),
+            _ => panic!("Expected corrupted data."),
+        }
+
+        // Tampering with the hash tree is caught by the root hash.
+        let mut hash_block = vec![0u8; 4096];
+        hash_file
+            .as_file()
+            .read_exact_at(&mut hash_block, 2 * 4096)
+            .unwrap();
+        // Corrupt the digest of block 1, so that only the root hash doesn't match for block 0.
+        hash_block[32] ^= 1;
+        hash_file
+            .as_file()
+            .write_all_at(&hash_block, 2 * 4096)
+            .unwrap();
+        tree.verified.clear();
+        match tree.verify_block(0, block_data(0)) {
+            Err(VerityError::Corrupted(0)) => (),
+            _ => panic!("Expected a corrupted hash tree."),
+        }
+        let mut root_hash = root_hash;
+        root_hash[0] ^= 1;
+        let mut tree = HashTree::new(
+            hash_file.as_file().try_clone().unwrap(),
+            4096,
+            data.len() as u64,
+            root_hash,
+            Vec::new(),
+        )
+        .unwrap();
+        match tree.verify_block(199, block_data(199)) {
+            Err(VerityError::Corrupted(199)) => (),
+            _ => panic!("Expected a wrong root hash."),
+        }
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::VerityError::*;
+
+        for err in &[
+            InvalidDataSize(0),
+            InvalidRootHash,
+            HashTreeTooShort,
+            HashFile(io::Error::from_raw_os_error(0)),
+            Corrupted(0),
+        ] {
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+}
@@ -19,6 +19,29 @@ pub enum DeviceState {
     Activated(GuestMemoryMmap),
 }
 
+/// The stage of its lifecycle a virtio device is in, as tracked by its transport.
+///
+/// A device starts `Inactive` and becomes `Activated` once its driver sets `DRIVER_OK`. An
+/// activated device which holds back the requests of the driver is `Paused`. A driver resetting
+/// the device, e.g. when the guest kexecs, leaves it `Reset` until the driver activates it again,
+/// or `Failed` if the device doesn't support being reset or the driver gave up on it.
+///
+/// The lifecycle is only reported, e.g. by `GET /devices`. Pausing stays up to the devices which
+/// support it, currently the block devices, and resetting up to `VirtioDevice::reset`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeviceLifecycle {
+    /// The driver is yet to activate the device.
+    Inactive,
+    /// The device serves the queues of the driver.
+    Activated,
+    /// The device is activated, but holds back the requests of the driver.
+    Paused,
+    /// The driver reset the device, which is yet to be activated again.
+    Reset,
+    /// The driver marked the device as failed, or reset a device not supporting it.
+    Failed,
+}
+
 /// Trait for virtio devices to be driven by a virtio transport.
 ///
 /// The lifecycle of a virtio device is to be moved to a virtio transport, which will then query the
@@ -103,10 +126,28 @@ pub trait VirtioDevice: AsAny + Send {
     /// Checks if the resources of this device are activated.
     fn is_activated(&self) -> bool;
 
-    /// Optionally deactivates this device and returns ownership of the guest memory map, interrupt
-    /// event, and queue events.
-    fn reset(&mut self) -> Option<(EventFd, Vec<EventFd>)> {
-        None
+    /// Deactivates this device when the driver resets it, e.g. when the guest kexecs into a new
+    /// kernel, so that the driver can activate it again. The transport resets the queues.
+    ///
+    /// Returns `false` if the device doesn't support being reset.
+    fn reset(&mut self) -> bool {
+        false
+    }
+
+    /// Whether the device holds back the requests of the driver, which it serves once resumed.
+    fn is_paused(&self) -> bool {
+        false
+    }
+
+    /// Returns the bytes of the configuration space, for debugging.
+    fn config_space_bytes(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Describes the internal state of the device handler, as pairs of names and values, for
+    /// debugging a device which stopped serving its driver.
+    fn debug_state(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
     }
 }
 
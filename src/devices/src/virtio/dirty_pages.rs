@@ -0,0 +1,119 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tracks the guest pages written by the VMM itself, e.g. by the device emulation.
+//!
+//! KVM only tracks the pages dirtied by the vCPUs, so the diff snapshots and the migrations would
+//! otherwise miss the buffers the devices fill and the used rings of their queues. The devices
+//! mark the pages they write here, and the VMM merges them with the dirty bitmap of KVM.
+//!
+//! The pages are tracked separately for each guest memory, so that the microVMs sharing the
+//! process, e.g. in the unit tests, don't take the pages written to each other.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+
+/// The size of the pages tracked, the same as the pages KVM tracks.
+pub const PAGE_SIZE: u64 = 4096;
+
+// Whether the pages written to any guest memory are tracked, sparing the lookup otherwise.
+static TRACKING: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    // The frame numbers of the pages written since they were last taken, for each guest memory
+    // tracked.
+    static ref DIRTY_PAGES: Mutex<HashMap<usize, HashSet<u64>>> = Mutex::new(HashMap::new());
+}
+
+// Tells apart the guest memories alive at the same time, which never share host mappings.
+fn memory_id(mem: &GuestMemoryMmap) -> usize {
+    mem.map_and_fold(
+        usize::max_value(),
+        |(_, region)| region.as_ptr() as usize,
+        std::cmp::min,
+    )
+}
+
+/// Starts tracking the pages written to `mem`, along with the dirty page tracking of KVM.
+///
+/// Forgets the pages marked for a previous guest memory mapped at the same host address.
+pub fn enable(mem: &GuestMemoryMmap) {
+    DIRTY_PAGES
+        .lock()
+        .expect("Poisoned lock")
+        .insert(memory_id(mem), HashSet::new());
+    TRACKING.store(true, Ordering::Release);
+}
+
+/// Whether the pages written to `mem` are tracked.
+pub fn enabled(mem: &GuestMemoryMmap) -> bool {
+    TRACKING.load(Ordering::Acquire)
+        && DIRTY_PAGES
+            .lock()
+            .expect("Poisoned lock")
+            .contains_key(&memory_id(mem))
+}
+
+/// Marks the pages of the `len` bytes at `addr` in `mem` as dirty, if the pages written to `mem`
+/// are tracked.
+///
+/// The pages are to be marked once written, so that a concurrent `take` never misses the write.
+pub fn mark(mem: &GuestMemoryMmap, addr: GuestAddress, len: u64) {
+    if len == 0 || !TRACKING.load(Ordering::Acquire) {
+        return;
+    }
+    let first = addr.0 / PAGE_SIZE;
+    let last = addr.0.saturating_add(len - 1) / PAGE_SIZE;
+    let mut dirty_pages = DIRTY_PAGES.lock().expect("Poisoned lock");
+    if let Some(dirty_pages) = dirty_pages.get_mut(&memory_id(mem)) {
+        for page in first..=last {
+            dirty_pages.insert(page);
+        }
+    }
+}
+
+/// Returns the frame numbers of the pages written to `mem` since the previous call.
+pub fn take(mem: &GuestMemoryMmap) -> HashSet<u64> {
+    DIRTY_PAGES
+        .lock()
+        .expect("Poisoned lock")
+        .get_mut(&memory_id(mem))
+        .map_or_else(HashSet::new, |dirty_pages| {
+            std::mem::replace(dirty_pages, HashSet::new())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mark() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x8000)]).unwrap();
+        let other_mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x8000)]).unwrap();
+
+        // Nothing is tracked before being enabled.
+        mark(&mem, GuestAddress(0), 1);
+        assert!(!enabled(&mem));
+        assert!(take(&mem).is_empty());
+
+        enable(&mem);
+        assert!(enabled(&mem));
+        mark(&mem, GuestAddress(0x0fff), 2);
+        mark(&mem, GuestAddress(0x3000), PAGE_SIZE);
+        mark(&mem, GuestAddress(0x5000), 0);
+        mark(&other_mem, GuestAddress(0x6000), 1);
+        let mut pages: Vec<u64> = take(&mem).into_iter().collect();
+        pages.sort();
+        assert_eq!(pages, vec![0, 1, 3]);
+
+        // The pages are only taken once.
+        assert!(take(&mem).is_empty());
+        // The pages written to another guest memory are not tracked along.
+        assert!(!enabled(&other_mem));
+        assert!(take(&other_mem).is_empty());
+    }
+}
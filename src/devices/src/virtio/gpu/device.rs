@@ -0,0 +1,1421 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::cmp;
+use std::collections::HashMap;
+use std::io::Write;
+use std::mem::size_of;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use logger::{Metric, METRICS};
+use utils::byte_order;
+use utils::eventfd::EventFd;
+use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
+use vm_memory::{Address, ByteValued, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap};
+
+use super::super::{
+    ActivateError, ActivateResult, DescriptorChain, DeviceState, Queue, VirtioDevice,
+    VIRTIO_MMIO_INT_VRING,
+};
+use super::protocol::*;
+use super::scanout::{FrameHeader, ScanoutServer};
+use super::{
+    Error, Result, CURSOR_INDEX, GPU_DEV_ID, MAX_BACKING_ENTRIES, MAX_HOST_MEMORY,
+    MAX_REQUEST_SIZE, NUM_QUEUES, NUM_SCANOUTS, QUEUE_SIZE, TYPE_GPU,
+};
+
+use crate::Error as DeviceError;
+
+// The reasons a command is rejected, each of them answered with its own response type.
+#[derive(Debug, PartialEq)]
+enum CommandError {
+    Unspec,
+    OutOfMemory,
+    InvalidScanoutId,
+    InvalidResourceId,
+    InvalidParameter,
+}
+
+impl CommandError {
+    fn response_type(&self) -> u32 {
+        match self {
+            CommandError::Unspec => VIRTIO_GPU_RESP_ERR_UNSPEC,
+            CommandError::OutOfMemory => VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY,
+            CommandError::InvalidScanoutId => VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID,
+            CommandError::InvalidResourceId => VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID,
+            CommandError::InvalidParameter => VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER,
+        }
+    }
+}
+
+enum Response {
+    NoData,
+    DisplayInfo(RespDisplayInfo),
+}
+
+type CommandResult = result::Result<Response, CommandError>;
+
+struct Resource {
+    // The guest memory attached to the resource.
+    backing: Vec<(GuestAddress, u32)>,
+    // The host copy of a 2D resource, which the guest updates through transfers. Blob resources
+    // have none, their contents being read straight from the backing.
+    host: Option<Vec<u8>>,
+    // The size of the resource, in bytes.
+    size: u64,
+    // The dimensions of a 2D resource.
+    format: u32,
+    width: u32,
+    height: u32,
+}
+
+// A framebuffer within a resource, of which the `r` rectangle is displayed by a scanout.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Scanout {
+    resource_id: u32,
+    format: u32,
+    stride: u32,
+    offset: u32,
+    r: Rect,
+}
+
+// Returns whether `r` is a non empty rectangle within a `width` x `height` area.
+fn rect_within(r: &Rect, width: u32, height: u32) -> bool {
+    r.width > 0
+        && r.height > 0
+        && u64::from(r.x) + u64::from(r.width) <= u64::from(width)
+        && u64::from(r.y) + u64::from(r.height) <= u64::from(height)
+}
+
+// Returns the intersection of `a` and `b`, if not empty.
+fn intersect(a: &Rect, b: &Rect) -> Option<Rect> {
+    let x = cmp::max(a.x, b.x);
+    let y = cmp::max(a.y, b.y);
+    let right = cmp::min(
+        u64::from(a.x) + u64::from(a.width),
+        u64::from(b.x) + u64::from(b.width),
+    );
+    let bottom = cmp::min(
+        u64::from(a.y) + u64::from(a.height),
+        u64::from(b.y) + u64::from(b.height),
+    );
+    if right <= u64::from(x) || bottom <= u64::from(y) {
+        return None;
+    }
+    Some(Rect {
+        x,
+        y,
+        width: (right - u64::from(x)) as u32,
+        height: (bottom - u64::from(y)) as u32,
+    })
+}
+
+// Copies `buf.len()` bytes from `offset` of the guest memory ranges in `backing`. Returns whether
+// the ranges were large enough, and within the guest memory.
+fn read_backing(
+    mem: &GuestMemoryMmap,
+    backing: &[(GuestAddress, u32)],
+    mut offset: u64,
+    buf: &mut [u8],
+) -> bool {
+    let mut done = 0;
+    for (addr, len) in backing {
+        if done == buf.len() {
+            break;
+        }
+        let len = u64::from(*len);
+        if offset >= len {
+            offset -= len;
+            continue;
+        }
+        let count = cmp::min(len - offset, (buf.len() - done) as u64) as usize;
+        let addr = match addr.checked_add(offset) {
+            Some(addr) => addr,
+            None => return false,
+        };
+        if mem.read_slice(&mut buf[done..done + count], addr).is_err() {
+            return false;
+        }
+        done += count;
+        offset = 0;
+    }
+    done == buf.len()
+}
+
+// Copies an object from `offset` of `buf`, if it fits.
+fn read_obj<T: ByteValued + Default>(buf: &[u8], offset: usize) -> Option<T> {
+    let mut obj = T::default();
+    let len = obj.as_slice().len();
+    obj.as_mut_slice()
+        .copy_from_slice(buf.get(offset..offset.checked_add(len)?)?);
+    Some(obj)
+}
+
+// Splits a descriptor chain in the command, copied out of the readable descriptors, and the
+// writable descriptors, which receive the response.
+fn parse_chain(
+    mem: &GuestMemoryMmap,
+    head: DescriptorChain,
+) -> Result<(Vec<u8>, Vec<(GuestAddress, u32)>)> {
+    let mut request = Vec::new();
+    let mut writable = Vec::new();
+    let mut next_desc = Some(head);
+    while let Some(desc) = next_desc {
+        if desc.is_write_only() {
+            writable.push((desc.addr, desc.len));
+        } else {
+            if !writable.is_empty() {
+                return Err(Error::UnexpectedReadableDescriptor);
+            }
+            let start = request.len();
+            if start + desc.len as usize > MAX_REQUEST_SIZE {
+                return Err(Error::RequestTooLarge);
+            }
+            request.resize(start + desc.len as usize, 0);
+            mem.read_slice(&mut request[start..], desc.addr)
+                .map_err(Error::GuestMemory)?;
+        }
+        next_desc = desc.next_descriptor();
+    }
+    Ok((request, writable))
+}
+
+// Writes the response to the writable descriptors, and returns the number of bytes written.
+fn write_response(
+    mem: &GuestMemoryMmap,
+    writable: &[(GuestAddress, u32)],
+    response: &[u8],
+) -> Result<u32> {
+    let mut written = 0;
+    for (addr, len) in writable {
+        if written == response.len() {
+            break;
+        }
+        let count = cmp::min(*len as usize, response.len() - written);
+        mem.write_slice(&response[written..written + count], *addr)
+            .map_err(Error::GuestMemory)?;
+        written += count;
+    }
+    Ok(written as u32)
+}
+
+/// Virtio device which lets the guest display its frames, and sends them to the clients of a
+/// Unix socket.
+pub struct Gpu {
+    // Virtio fields.
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+    pub(crate) activate_evt: EventFd,
+
+    // Transport related fields.
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) queue_evts: Vec<EventFd>,
+    pub(crate) interrupt_status: Arc<AtomicUsize>,
+    interrupt_evt: EventFd,
+    pub(crate) device_state: DeviceState,
+    config_space: VirtioGpuConfig,
+
+    // Display fields.
+    width: u32,
+    height: u32,
+    resources: HashMap<u32, Resource>,
+    host_memory: u64,
+    scanout: Option<Scanout>,
+    socket_path: String,
+    pub(crate) server: ScanoutServer,
+}
+
+impl Gpu {
+    /// Creates a new GPU device with a `width` x `height` display, whose contents are served
+    /// through the Unix socket at `socket_path`. The guest may use blob resources if `blob` is
+    /// set.
+    pub fn new<P: AsRef<Path>>(width: u32, height: u32, blob: bool, socket_path: P) -> Result<Gpu> {
+        let mut queue_evts = Vec::with_capacity(NUM_QUEUES);
+        for _ in 0..NUM_QUEUES {
+            queue_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
+        }
+
+        let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
+        if blob {
+            avail_features |= 1u64 << VIRTIO_GPU_F_RESOURCE_BLOB;
+        }
+
+        Ok(Gpu {
+            avail_features,
+            acked_features: 0u64,
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queues: (0..NUM_QUEUES).map(|_| Queue::new(QUEUE_SIZE)).collect(),
+            queue_evts,
+            interrupt_status: Arc::new(AtomicUsize::new(0)),
+            interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            device_state: DeviceState::Inactive,
+            config_space: VirtioGpuConfig {
+                num_scanouts: NUM_SCANOUTS,
+                ..Default::default()
+            },
+            width,
+            height,
+            resources: HashMap::new(),
+            host_memory: 0,
+            scanout: None,
+            socket_path: socket_path.as_ref().to_string_lossy().into_owned(),
+            server: ScanoutServer::bind(socket_path).map_err(Error::ScanoutSocket)?,
+        })
+    }
+
+    /// Provides the ID of the GPU device.
+    pub fn id(&self) -> &str {
+        GPU_DEV_ID
+    }
+
+    /// Provides the path of the scanout socket.
+    pub fn socket_path(&self) -> &str {
+        &self.socket_path
+    }
+
+    pub(crate) fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+        self.interrupt_evt.write(1).map_err(|e| {
+            error!("Failed to signal GPU device interrupt: {:?}", e);
+            METRICS.gpu.event_fails.inc();
+            DeviceError::FailedSignalingUsedQueue(e)
+        })
+    }
+
+    // Executes the pending commands of the queue at `queue_index`. Returns whether any command
+    // was completed.
+    pub(crate) fn process_queue(&mut self, queue_index: usize) -> bool {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem.clone(),
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        let mut used_any = false;
+
+        while let Some(head) = self.queues[queue_index].pop(&mem) {
+            let head_index = head.index;
+            let len = match parse_chain(&mem, head) {
+                Ok((request, writable)) => {
+                    let response = if queue_index == CURSOR_INDEX {
+                        self.process_cursor_command(&request)
+                    } else {
+                        self.process_control_command(&mem, &request)
+                    };
+                    write_response(&mem, &writable, &response).unwrap_or_else(|e| {
+                        error!("gpu: {:?}", e);
+                        METRICS.gpu.event_fails.inc();
+                        0
+                    })
+                }
+                Err(e) => {
+                    error!("gpu: {:?}", e);
+                    METRICS.gpu.event_fails.inc();
+                    0
+                }
+            };
+            self.queues[queue_index].add_used(&mem, head_index, len);
+            used_any = true;
+        }
+
+        used_any
+    }
+
+    fn process_control_command(&mut self, mem: &GuestMemoryMmap, request: &[u8]) -> Vec<u8> {
+        let hdr = read_obj::<CtrlHeader>(request, 0).unwrap_or_default();
+        let body = request.get(size_of::<CtrlHeader>()..).unwrap_or(&[]);
+        let result = match hdr.type_ {
+            VIRTIO_GPU_CMD_GET_DISPLAY_INFO => Ok(self.display_info()),
+            VIRTIO_GPU_CMD_RESOURCE_CREATE_2D => self.resource_create_2d(body),
+            VIRTIO_GPU_CMD_RESOURCE_UNREF => self.resource_unref(body),
+            VIRTIO_GPU_CMD_SET_SCANOUT => self.set_scanout(body),
+            VIRTIO_GPU_CMD_RESOURCE_FLUSH => self.resource_flush(mem, body),
+            VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D => self.transfer_to_host_2d(mem, body),
+            VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING => self.resource_attach_backing(mem, body),
+            VIRTIO_GPU_CMD_RESOURCE_DETACH_BACKING => self.resource_detach_backing(body),
+            VIRTIO_GPU_CMD_RESOURCE_CREATE_BLOB if self.blob_acked() => {
+                self.resource_create_blob(mem, body)
+            }
+            VIRTIO_GPU_CMD_SET_SCANOUT_BLOB if self.blob_acked() => self.set_scanout_blob(body),
+            // 3D and capabilities related commands.
+            _ => Err(CommandError::Unspec),
+        };
+        METRICS.gpu.cmd_count.inc();
+        build_response(&hdr, result)
+    }
+
+    // The cursor is not part of the frames, so its commands are only acknowledged.
+    fn process_cursor_command(&mut self, request: &[u8]) -> Vec<u8> {
+        let hdr = read_obj::<CtrlHeader>(request, 0).unwrap_or_default();
+        let result = match hdr.type_ {
+            VIRTIO_GPU_CMD_UPDATE_CURSOR | VIRTIO_GPU_CMD_MOVE_CURSOR => Ok(Response::NoData),
+            _ => Err(CommandError::Unspec),
+        };
+        METRICS.gpu.cmd_count.inc();
+        build_response(&hdr, result)
+    }
+
+    fn blob_acked(&self) -> bool {
+        self.acked_features & (1u64 << VIRTIO_GPU_F_RESOURCE_BLOB) != 0
+    }
+
+    fn display_info(&self) -> Response {
+        let mut info = RespDisplayInfo::default();
+        info.pmodes[0] = DisplayOne {
+            r: Rect {
+                x: 0,
+                y: 0,
+                width: self.width,
+                height: self.height,
+            },
+            enabled: 1,
+            flags: 0,
+        };
+        Response::DisplayInfo(info)
+    }
+
+    fn new_resource_id(&self, resource_id: u32) -> result::Result<(), CommandError> {
+        if resource_id == 0 || self.resources.contains_key(&resource_id) {
+            return Err(CommandError::InvalidResourceId);
+        }
+        Ok(())
+    }
+
+    fn resource_create_2d(&mut self, body: &[u8]) -> CommandResult {
+        let cmd = read_obj::<ResourceCreate2d>(body, 0).ok_or(CommandError::Unspec)?;
+        self.new_resource_id(cmd.resource_id)?;
+        if !is_supported_format(cmd.format) || cmd.width == 0 || cmd.height == 0 {
+            return Err(CommandError::InvalidParameter);
+        }
+        let size = u64::from(cmd.width) * u64::from(cmd.height) * u64::from(BYTES_PER_PIXEL);
+        if size > MAX_HOST_MEMORY - self.host_memory {
+            return Err(CommandError::OutOfMemory);
+        }
+        self.host_memory += size;
+        self.resources.insert(
+            cmd.resource_id,
+            Resource {
+                backing: Vec::new(),
+                host: Some(vec![0u8; size as usize]),
+                size,
+                format: cmd.format,
+                width: cmd.width,
+                height: cmd.height,
+            },
+        );
+        Ok(Response::NoData)
+    }
+
+    fn resource_unref(&mut self, body: &[u8]) -> CommandResult {
+        let cmd = read_obj::<ResourceUnref>(body, 0).ok_or(CommandError::Unspec)?;
+        let resource = self
+            .resources
+            .remove(&cmd.resource_id)
+            .ok_or(CommandError::InvalidResourceId)?;
+        if resource.host.is_some() {
+            self.host_memory -= resource.size;
+        }
+        if self
+            .scanout
+            .map_or(false, |scanout| scanout.resource_id == cmd.resource_id)
+        {
+            self.disable_scanout();
+        }
+        Ok(Response::NoData)
+    }
+
+    fn set_scanout(&mut self, body: &[u8]) -> CommandResult {
+        let cmd = read_obj::<SetScanout>(body, 0).ok_or(CommandError::Unspec)?;
+        if cmd.scanout_id >= NUM_SCANOUTS {
+            return Err(CommandError::InvalidScanoutId);
+        }
+        if cmd.resource_id == 0 {
+            self.disable_scanout();
+            return Ok(Response::NoData);
+        }
+        let resource = self
+            .resources
+            .get(&cmd.resource_id)
+            .ok_or(CommandError::InvalidResourceId)?;
+        if resource.host.is_none() || !rect_within(&cmd.r, resource.width, resource.height) {
+            return Err(CommandError::InvalidParameter);
+        }
+        self.scanout = Some(Scanout {
+            resource_id: cmd.resource_id,
+            format: resource.format,
+            stride: resource.width * BYTES_PER_PIXEL,
+            offset: 0,
+            r: cmd.r,
+        });
+        Ok(Response::NoData)
+    }
+
+    fn set_scanout_blob(&mut self, body: &[u8]) -> CommandResult {
+        let cmd = read_obj::<SetScanoutBlob>(body, 0).ok_or(CommandError::Unspec)?;
+        if cmd.scanout_id >= NUM_SCANOUTS {
+            return Err(CommandError::InvalidScanoutId);
+        }
+        if cmd.resource_id == 0 {
+            self.disable_scanout();
+            return Ok(Response::NoData);
+        }
+        let resource = self
+            .resources
+            .get(&cmd.resource_id)
+            .ok_or(CommandError::InvalidResourceId)?;
+        let stride = cmd.strides[0];
+        let row_len = u64::from(cmd.width) * u64::from(BYTES_PER_PIXEL);
+        if resource.host.is_some()
+            || !is_supported_format(cmd.format)
+            || cmd.height == 0
+            || u64::from(stride) < row_len
+            || !rect_within(&cmd.r, cmd.width, cmd.height)
+            || u64::from(cmd.offsets[0]) + u64::from(stride) * u64::from(cmd.height - 1) + row_len
+                > resource.size
+        {
+            return Err(CommandError::InvalidParameter);
+        }
+        self.scanout = Some(Scanout {
+            resource_id: cmd.resource_id,
+            format: cmd.format,
+            stride,
+            offset: cmd.offsets[0],
+            r: cmd.r,
+        });
+        Ok(Response::NoData)
+    }
+
+    fn resource_flush(&mut self, mem: &GuestMemoryMmap, body: &[u8]) -> CommandResult {
+        let cmd = read_obj::<ResourceFlush>(body, 0).ok_or(CommandError::Unspec)?;
+        if !self.resources.contains_key(&cmd.resource_id) {
+            return Err(CommandError::InvalidResourceId);
+        }
+        let scanout = match self.scanout {
+            Some(scanout) if scanout.resource_id == cmd.resource_id => scanout,
+            _ => return Ok(Response::NoData),
+        };
+        if !self.server.has_clients() {
+            return Ok(Response::NoData);
+        }
+        if let Some(rect) = intersect(&cmd.r, &scanout.r) {
+            let (header, pixels) = self
+                .scanout_frame(mem, &scanout, &rect)
+                .ok_or(CommandError::InvalidParameter)?;
+            self.server.broadcast(&header, &pixels);
+        }
+        Ok(Response::NoData)
+    }
+
+    fn transfer_to_host_2d(&mut self, mem: &GuestMemoryMmap, body: &[u8]) -> CommandResult {
+        let cmd = read_obj::<TransferToHost2d>(body, 0).ok_or(CommandError::Unspec)?;
+        let resource = self
+            .resources
+            .get_mut(&cmd.resource_id)
+            .ok_or(CommandError::InvalidResourceId)?;
+        if !rect_within(&cmd.r, resource.width, resource.height) {
+            return Err(CommandError::InvalidParameter);
+        }
+        let Resource {
+            backing,
+            host,
+            width,
+            ..
+        } = resource;
+        let host = host.as_mut().ok_or(CommandError::InvalidParameter)?;
+        if backing.is_empty() {
+            return Err(CommandError::Unspec);
+        }
+        let stride = (*width * BYTES_PER_PIXEL) as usize;
+        let row_len = (cmd.r.width * BYTES_PER_PIXEL) as usize;
+        for row in 0..cmd.r.height as usize {
+            let src = cmd
+                .offset
+                .checked_add((stride * row) as u64)
+                .ok_or(CommandError::InvalidParameter)?;
+            let dst = (cmd.r.y as usize + row) * stride + (cmd.r.x * BYTES_PER_PIXEL) as usize;
+            if !read_backing(mem, backing, src, &mut host[dst..dst + row_len]) {
+                return Err(CommandError::InvalidParameter);
+            }
+        }
+        Ok(Response::NoData)
+    }
+
+    // Reads `nr_entries` guest memory ranges from `offset` of `body`.
+    fn read_mem_entries(
+        mem: &GuestMemoryMmap,
+        body: &[u8],
+        offset: usize,
+        nr_entries: u32,
+    ) -> result::Result<Vec<(GuestAddress, u32)>, CommandError> {
+        if nr_entries == 0 || nr_entries > MAX_BACKING_ENTRIES {
+            return Err(CommandError::InvalidParameter);
+        }
+        let mut entries = Vec::with_capacity(nr_entries as usize);
+        for i in 0..nr_entries as usize {
+            let entry = read_obj::<MemEntry>(body, offset + i * size_of::<MemEntry>())
+                .ok_or(CommandError::Unspec)?;
+            let addr = GuestAddress(entry.addr);
+            if mem
+                .checked_offset(addr, (entry.length as usize).saturating_sub(1))
+                .is_none()
+            {
+                return Err(CommandError::InvalidParameter);
+            }
+            entries.push((addr, entry.length));
+        }
+        Ok(entries)
+    }
+
+    fn resource_attach_backing(&mut self, mem: &GuestMemoryMmap, body: &[u8]) -> CommandResult {
+        let cmd = read_obj::<ResourceAttachBacking>(body, 0).ok_or(CommandError::Unspec)?;
+        let entries = Self::read_mem_entries(
+            mem,
+            body,
+            size_of::<ResourceAttachBacking>(),
+            cmd.nr_entries,
+        )?;
+        let resource = self
+            .resources
+            .get_mut(&cmd.resource_id)
+            .ok_or(CommandError::InvalidResourceId)?;
+        if !resource.backing.is_empty() {
+            return Err(CommandError::Unspec);
+        }
+        resource.backing = entries;
+        Ok(Response::NoData)
+    }
+
+    fn resource_detach_backing(&mut self, body: &[u8]) -> CommandResult {
+        let cmd = read_obj::<ResourceDetachBacking>(body, 0).ok_or(CommandError::Unspec)?;
+        let resource = self
+            .resources
+            .get_mut(&cmd.resource_id)
+            .ok_or(CommandError::InvalidResourceId)?;
+        if resource.backing.is_empty() {
+            return Err(CommandError::Unspec);
+        }
+        resource.backing.clear();
+        Ok(Response::NoData)
+    }
+
+    fn resource_create_blob(&mut self, mem: &GuestMemoryMmap, body: &[u8]) -> CommandResult {
+        let cmd = read_obj::<ResourceCreateBlob>(body, 0).ok_or(CommandError::Unspec)?;
+        self.new_resource_id(cmd.resource_id)?;
+        // Only the blobs made of guest memory are supported.
+        if cmd.blob_mem != VIRTIO_GPU_BLOB_MEM_GUEST || cmd.size == 0 {
+            return Err(CommandError::InvalidParameter);
+        }
+        let backing =
+            Self::read_mem_entries(mem, body, size_of::<ResourceCreateBlob>(), cmd.nr_entries)?;
+        let backing_size: u64 = backing.iter().map(|(_, len)| u64::from(*len)).sum();
+        if backing_size < cmd.size {
+            return Err(CommandError::InvalidParameter);
+        }
+        self.resources.insert(
+            cmd.resource_id,
+            Resource {
+                backing,
+                host: None,
+                size: cmd.size,
+                format: 0,
+                width: 0,
+                height: 0,
+            },
+        );
+        Ok(Response::NoData)
+    }
+
+    fn disable_scanout(&mut self) {
+        if self.scanout.take().is_some() {
+            // Announce that the scanout got disabled.
+            self.server.broadcast(&FrameHeader::default(), &[]);
+        }
+    }
+
+    // Reads the `rect` rectangle of the framebuffer of `scanout`, and describes it relatively to
+    // the visible part of the framebuffer.
+    fn scanout_frame(
+        &self,
+        mem: &GuestMemoryMmap,
+        scanout: &Scanout,
+        rect: &Rect,
+    ) -> Option<(FrameHeader, Vec<u8>)> {
+        let resource = self.resources.get(&scanout.resource_id)?;
+        let row_len = (rect.width * BYTES_PER_PIXEL) as usize;
+        let mut pixels = vec![0u8; row_len * rect.height as usize];
+        for (row, buf) in pixels.chunks_mut(row_len).enumerate() {
+            let offset = u64::from(scanout.offset)
+                + (u64::from(rect.y) + row as u64) * u64::from(scanout.stride)
+                + u64::from(rect.x) * u64::from(BYTES_PER_PIXEL);
+            match resource.host {
+                Some(ref host) => {
+                    let start = offset as usize;
+                    buf.copy_from_slice(host.get(start..start + row_len)?);
+                }
+                None => {
+                    if !read_backing(mem, &resource.backing, offset, buf) {
+                        return None;
+                    }
+                }
+            }
+        }
+        let header = FrameHeader {
+            scanout_id: 0,
+            format: scanout.format,
+            width: scanout.r.width,
+            height: scanout.r.height,
+            x: rect.x - scanout.r.x,
+            y: rect.y - scanout.r.y,
+            rect_width: rect.width,
+            rect_height: rect.height,
+        };
+        Some((header, pixels))
+    }
+
+    /// Sends the whole visible contents of the scanout to the newly connected client with the
+    /// file descriptor `fd`, so that it doesn't wait for the next update to display something.
+    pub(crate) fn send_scanout(&mut self, fd: RawFd) {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem,
+            DeviceState::Inactive => return,
+        };
+        let scanout = match self.scanout {
+            Some(scanout) => scanout,
+            None => return,
+        };
+        if let Some((header, pixels)) = self.scanout_frame(mem, &scanout, &scanout.r) {
+            self.server.send_to(fd, &header, &pixels);
+        }
+    }
+}
+
+// Builds the response to the command with the `hdr` header.
+fn build_response(hdr: &CtrlHeader, result: CommandResult) -> Vec<u8> {
+    let mut resp_hdr = CtrlHeader::default();
+    // Commands complete synchronously, so their fences are already signaled.
+    if hdr.flags & VIRTIO_GPU_FLAG_FENCE != 0 {
+        resp_hdr.flags = VIRTIO_GPU_FLAG_FENCE;
+        resp_hdr.fence_id = hdr.fence_id;
+        resp_hdr.ctx_id = hdr.ctx_id;
+        resp_hdr.ring_idx = hdr.ring_idx;
+    }
+    match result {
+        Ok(Response::NoData) => {
+            resp_hdr.type_ = VIRTIO_GPU_RESP_OK_NODATA;
+            resp_hdr.as_slice().to_vec()
+        }
+        Ok(Response::DisplayInfo(mut info)) => {
+            resp_hdr.type_ = VIRTIO_GPU_RESP_OK_DISPLAY_INFO;
+            info.hdr = resp_hdr;
+            info.as_slice().to_vec()
+        }
+        Err(e) => {
+            warn!("gpu: Command {:#x} failed: {:?}", hdr.type_, e);
+            METRICS.gpu.cmd_fails.inc();
+            resp_hdr.type_ = e.response_type();
+            resp_hdr.as_slice().to_vec()
+        }
+    }
+}
+
+impl VirtioDevice for Gpu {
+    fn device_type(&self) -> u32 {
+        TYPE_GPU
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.interrupt_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.interrupt_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) {
+        let config_space_bytes = self.config_space.as_slice();
+        let config_len = config_space_bytes.len() as u64;
+        if offset >= config_len {
+            error!("Failed to read GPU device config space");
+            METRICS.gpu.cfg_fails.inc();
+            return;
+        }
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            // This write can't fail, offset and end are checked against config_len.
+            data.write_all(
+                &config_space_bytes[offset as usize..cmp::min(end, config_len) as usize],
+            )
+            .unwrap();
+        }
+    }
+
+    fn config_space_bytes(&self) -> Vec<u8> {
+        self.config_space.as_slice().to_vec()
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        // The driver may only write the `events_clear` field.
+        if offset != 4 || data.len() != 4 {
+            error!("Failed to write GPU device config space");
+            METRICS.gpu.cfg_fails.inc();
+            return;
+        }
+        let events_clear = byte_order::read_le_u32(data);
+        self.config_space.events_read &= !events_clear;
+    }
+
+    fn is_activated(&self) -> bool {
+        match self.device_state {
+            DeviceState::Inactive => false,
+            DeviceState::Activated(_) => true,
+        }
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
+        if self.activate_evt.write(1).is_err() {
+            error!("Gpu: Cannot write to activate_evt");
+            METRICS.gpu.activate_fails.inc();
+            return Err(ActivateError::BadActivate);
+        }
+        self.device_state = DeviceState::Activated(mem);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+    use crate::virtio::gpu::scanout::tests::temp_socket_path;
+    use crate::virtio::gpu::CONTROL_INDEX;
+    use crate::virtio::queue::tests::*;
+    use crate::virtio::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+
+    const REQUEST_ADDR: u64 = 0x8000;
+    const RESPONSE_ADDR: u64 = 0x10000;
+    const RESPONSE_SIZE: u32 = 0x200;
+    const BACKING_ADDR: u64 = 0x20000;
+
+    impl Gpu {
+        pub(crate) fn set_queue(&mut self, idx: usize, q: Queue) {
+            self.queues[idx] = q;
+        }
+    }
+
+    pub fn default_mem() -> GuestMemoryMmap {
+        GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x40000)]).unwrap()
+    }
+
+    pub fn default_gpu(blob: bool) -> Gpu {
+        Gpu::new(64, 32, blob, temp_socket_path()).unwrap()
+    }
+
+    // Sends the commands to the device through a queue, one after the other.
+    pub struct Driver<'a> {
+        mem: &'a GuestMemoryMmap,
+        queue: VirtQueue<'a>,
+        next: u16,
+    }
+
+    impl<'a> Driver<'a> {
+        pub fn new(gpu: &mut Gpu, mem: &'a GuestMemoryMmap, queue_index: usize) -> Self {
+            let queue = VirtQueue::new(GuestAddress(0), mem, 16);
+            gpu.set_queue(queue_index, queue.create_queue());
+            Driver {
+                mem,
+                queue,
+                next: 0,
+            }
+        }
+
+        // Places the command in the queue, without notifying the device.
+        pub fn queue_command(&mut self, request: &[u8]) {
+            self.mem
+                .write_slice(request, GuestAddress(REQUEST_ADDR))
+                .unwrap();
+            self.queue.dtable[0].set(REQUEST_ADDR, request.len() as u32, VIRTQ_DESC_F_NEXT, 1);
+            self.queue.dtable[1].set(RESPONSE_ADDR, RESPONSE_SIZE, VIRTQ_DESC_F_WRITE, 0);
+            self.queue.avail.ring[self.next as usize % 16].set(0);
+            self.next += 1;
+            self.queue.avail.idx.set(self.next);
+        }
+
+        // Executes the command, and returns the response.
+        pub fn command(&mut self, gpu: &mut Gpu, queue_index: usize, request: &[u8]) -> Vec<u8> {
+            self.queue_command(request);
+            assert!(gpu.process_queue(queue_index));
+            assert_eq!(self.queue.used.idx.get(), self.next);
+            let len = self.queue.used.ring[(self.next - 1) as usize % 16]
+                .get()
+                .len;
+            let mut response = vec![0u8; len as usize];
+            self.mem
+                .read_slice(&mut response, GuestAddress(RESPONSE_ADDR))
+                .unwrap();
+            response
+        }
+
+        // Executes the command, and returns the type of the response.
+        pub fn command_type(&mut self, gpu: &mut Gpu, request: &[u8]) -> u32 {
+            let response = self.command(gpu, CONTROL_INDEX, request);
+            read_obj::<CtrlHeader>(&response, 0).unwrap().type_
+        }
+    }
+
+    pub fn request<T: ByteValued>(type_: u32, body: &T, extra: &[u8]) -> Vec<u8> {
+        let hdr = CtrlHeader {
+            type_,
+            ..Default::default()
+        };
+        let mut request = hdr.as_slice().to_vec();
+        request.extend_from_slice(body.as_slice());
+        request.extend_from_slice(extra);
+        request
+    }
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn mem_entry(addr: u64, length: u32) -> Vec<u8> {
+        MemEntry {
+            addr,
+            length,
+            padding: 0,
+        }
+        .as_slice()
+        .to_vec()
+    }
+
+    fn create_2d(resource_id: u32, width: u32, height: u32) -> Vec<u8> {
+        request(
+            VIRTIO_GPU_CMD_RESOURCE_CREATE_2D,
+            &ResourceCreate2d {
+                resource_id,
+                format: VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM,
+                width,
+                height,
+            },
+            &[],
+        )
+    }
+
+    fn read_frame(client: &mut UnixStream) -> (FrameHeader, Vec<u8>) {
+        let mut header = FrameHeader::default();
+        client.read_exact(header.as_mut_slice()).unwrap();
+        let mut pixels =
+            vec![0u8; (header.rect_width * header.rect_height * BYTES_PER_PIXEL) as usize];
+        client.read_exact(&mut pixels).unwrap();
+        (header, pixels)
+    }
+
+    #[test]
+    fn test_virtio_features() {
+        let gpu = default_gpu(false);
+        assert_eq!(gpu.device_type(), TYPE_GPU);
+        assert_eq!(gpu.id(), GPU_DEV_ID);
+        assert_eq!(gpu.avail_features(), 1u64 << VIRTIO_F_VERSION_1);
+        assert_eq!(gpu.queues().len(), NUM_QUEUES);
+        assert_eq!(gpu.queue_events().len(), NUM_QUEUES);
+
+        let gpu = default_gpu(true);
+        assert_eq!(
+            gpu.avail_features(),
+            (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_GPU_F_RESOURCE_BLOB)
+        );
+
+        // The socket path is taken.
+        let path = temp_socket_path();
+        let gpu = Gpu::new(64, 32, false, &path).unwrap();
+        assert_eq!(gpu.socket_path(), path);
+        match Gpu::new(64, 32, false, &path) {
+            Err(Error::ScanoutSocket(_)) => (),
+            _ => panic!("The socket path is in use."),
+        }
+    }
+
+    #[test]
+    fn test_config_space() {
+        let mut gpu = default_gpu(false);
+        let mut data = [0u8; 4];
+        gpu.read_config(8, &mut data);
+        assert_eq!(byte_order::read_le_u32(&data), NUM_SCANOUTS);
+
+        gpu.config_space.events_read = 0x3;
+        let mut clear = [0u8; 4];
+        byte_order::write_le_u32(&mut clear, 0x1);
+        gpu.write_config(4, &clear);
+        gpu.read_config(0, &mut data);
+        assert_eq!(byte_order::read_le_u32(&data), 0x2);
+
+        // Only `events_clear` is writable, and reads past the config space are ignored.
+        gpu.write_config(8, &clear);
+        let mut data = [0xffu8; 4];
+        gpu.read_config(16, &mut data);
+        assert_eq!(data, [0xffu8; 4]);
+    }
+
+    #[test]
+    fn test_rects() {
+        assert!(rect_within(&rect(0, 0, 64, 32), 64, 32));
+        assert!(rect_within(&rect(63, 31, 1, 1), 64, 32));
+        assert!(!rect_within(&rect(0, 0, 0, 32), 64, 32));
+        assert!(!rect_within(&rect(1, 0, 64, 32), 64, 32));
+        assert!(!rect_within(&rect(std::u32::MAX, 0, 2, 1), 64, 32));
+
+        assert_eq!(
+            intersect(&rect(0, 0, 10, 10), &rect(5, 5, 10, 10)),
+            Some(rect(5, 5, 5, 5))
+        );
+        assert_eq!(intersect(&rect(0, 0, 5, 5), &rect(5, 5, 10, 10)), None);
+    }
+
+    #[test]
+    fn test_read_backing() {
+        let mem = default_mem();
+        mem.write_slice(&[1, 2, 3, 4], GuestAddress(0x1000))
+            .unwrap();
+        mem.write_slice(&[5, 6, 7, 8], GuestAddress(0x2000))
+            .unwrap();
+        let backing = [(GuestAddress(0x1000), 4), (GuestAddress(0x2000), 4)];
+
+        let mut buf = [0u8; 4];
+        assert!(read_backing(&mem, &backing, 2, &mut buf));
+        assert_eq!(buf, [3, 4, 5, 6]);
+        assert!(!read_backing(&mem, &backing, 6, &mut buf));
+        let backing = [(GuestAddress(0x3_fffe), 4)];
+        assert!(!read_backing(&mem, &backing, 0, &mut buf));
+    }
+
+    #[test]
+    fn test_display_info() {
+        let mem = default_mem();
+        let mut gpu = default_gpu(false);
+        gpu.activate(mem.clone()).unwrap();
+        let mut driver = Driver::new(&mut gpu, &mem, CONTROL_INDEX);
+
+        // Fenced commands signal their fence in the response.
+        let hdr = CtrlHeader {
+            type_: VIRTIO_GPU_CMD_GET_DISPLAY_INFO,
+            flags: VIRTIO_GPU_FLAG_FENCE,
+            fence_id: 42,
+            ..Default::default()
+        };
+        let response = driver.command(&mut gpu, CONTROL_INDEX, hdr.as_slice());
+        let info = read_obj::<RespDisplayInfo>(&response, 0).unwrap();
+        assert_eq!(info.hdr.type_, VIRTIO_GPU_RESP_OK_DISPLAY_INFO);
+        assert_eq!(info.hdr.flags, VIRTIO_GPU_FLAG_FENCE);
+        assert_eq!(info.hdr.fence_id, 42);
+        assert_eq!(info.pmodes[0].r, rect(0, 0, 64, 32));
+        assert_eq!(info.pmodes[0].enabled, 1);
+        assert_eq!(info.pmodes[1].enabled, 0);
+    }
+
+    #[test]
+    fn test_resources() {
+        let mem = default_mem();
+        let mut gpu = default_gpu(false);
+        gpu.activate(mem.clone()).unwrap();
+        let mut driver = Driver::new(&mut gpu, &mem, CONTROL_INDEX);
+
+        assert_eq!(
+            driver.command_type(&mut gpu, &create_2d(1, 64, 32)),
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        assert_eq!(gpu.host_memory, 64 * 32 * 4);
+        // The resource IDs must be unique and not 0.
+        assert_eq!(
+            driver.command_type(&mut gpu, &create_2d(1, 64, 32)),
+            VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &create_2d(0, 64, 32)),
+            VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &create_2d(2, 0, 32)),
+            VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER
+        );
+        // The host memory taken by the resources is bounded.
+        assert_eq!(
+            driver.command_type(&mut gpu, &create_2d(2, 16384, 16384)),
+            VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY
+        );
+        // Truncated commands are rejected.
+        let mut truncated = create_2d(2, 64, 32);
+        truncated.truncate(32);
+        assert_eq!(
+            driver.command_type(&mut gpu, &truncated),
+            VIRTIO_GPU_RESP_ERR_UNSPEC
+        );
+
+        // Attach and detach the backing.
+        let attach = request(
+            VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING,
+            &ResourceAttachBacking {
+                resource_id: 1,
+                nr_entries: 1,
+            },
+            &mem_entry(BACKING_ADDR, 64 * 32 * 4),
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &attach),
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &attach),
+            VIRTIO_GPU_RESP_ERR_UNSPEC
+        );
+        let detach = request(
+            VIRTIO_GPU_CMD_RESOURCE_DETACH_BACKING,
+            &ResourceDetachBacking {
+                resource_id: 1,
+                padding: 0,
+            },
+            &[],
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &detach),
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &detach),
+            VIRTIO_GPU_RESP_ERR_UNSPEC
+        );
+        // The backing must be within the guest memory.
+        let attach = request(
+            VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING,
+            &ResourceAttachBacking {
+                resource_id: 1,
+                nr_entries: 1,
+            },
+            &mem_entry(0x3_f000, 0x2000),
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &attach),
+            VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER
+        );
+
+        let unref = request(
+            VIRTIO_GPU_CMD_RESOURCE_UNREF,
+            &ResourceUnref {
+                resource_id: 1,
+                padding: 0,
+            },
+            &[],
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &unref),
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        assert_eq!(gpu.host_memory, 0);
+        assert_eq!(
+            driver.command_type(&mut gpu, &unref),
+            VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID
+        );
+
+        // 3D commands are not supported.
+        assert_eq!(
+            driver.command_type(&mut gpu, &request(0x0200, &CtrlHeader::default(), &[])),
+            VIRTIO_GPU_RESP_ERR_UNSPEC
+        );
+    }
+
+    #[test]
+    fn test_scanout_2d() {
+        let mem = default_mem();
+        let path = temp_socket_path();
+        let mut gpu = Gpu::new(64, 32, false, &path).unwrap();
+        gpu.activate(mem.clone()).unwrap();
+        let mut driver = Driver::new(&mut gpu, &mem, CONTROL_INDEX);
+
+        driver.command_type(&mut gpu, &create_2d(1, 64, 32));
+        driver.command_type(
+            &mut gpu,
+            &request(
+                VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING,
+                &ResourceAttachBacking {
+                    resource_id: 1,
+                    nr_entries: 2,
+                },
+                &[
+                    mem_entry(BACKING_ADDR, 0x1000),
+                    mem_entry(BACKING_ADDR + 0x2000, 64 * 32 * 4 - 0x1000),
+                ]
+                .concat(),
+            ),
+        );
+        // The second row of pixels starts at 0x100 in the backing.
+        mem.write_slice(&[0xaa; 8], GuestAddress(BACKING_ADDR + 0x108))
+            .unwrap();
+
+        // The transfer must be within the resource.
+        let transfer = |r: Rect, offset: u64| {
+            request(
+                VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D,
+                &TransferToHost2d {
+                    r,
+                    offset,
+                    resource_id: 1,
+                    padding: 0,
+                },
+                &[],
+            )
+        };
+        assert_eq!(
+            driver.command_type(&mut gpu, &transfer(rect(0, 0, 65, 32), 0)),
+            VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &transfer(rect(2, 1, 2, 2), 0x108)),
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        let host = gpu.resources[&1].host.as_ref().unwrap();
+        assert_eq!(&host[0x108..0x110], &[0xaa; 8]);
+        assert_eq!(&host[0x100..0x108], &[0; 8]);
+
+        let set_scanout = |scanout_id: u32, resource_id: u32, r: Rect| {
+            request(
+                VIRTIO_GPU_CMD_SET_SCANOUT,
+                &SetScanout {
+                    r,
+                    scanout_id,
+                    resource_id,
+                },
+                &[],
+            )
+        };
+        assert_eq!(
+            driver.command_type(&mut gpu, &set_scanout(1, 1, rect(0, 0, 64, 32))),
+            VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &set_scanout(0, 2, rect(0, 0, 64, 32))),
+            VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &set_scanout(0, 1, rect(0, 1, 64, 32))),
+            VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &set_scanout(0, 1, rect(1, 1, 32, 16))),
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+
+        // A new client gets the whole visible scanout.
+        let mut client = UnixStream::connect(&path).unwrap();
+        let fd = gpu.server.accept().unwrap();
+        gpu.send_scanout(fd);
+        let (header, pixels) = read_frame(&mut client);
+        assert_eq!(
+            header,
+            FrameHeader {
+                scanout_id: 0,
+                format: VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM,
+                width: 32,
+                height: 16,
+                x: 0,
+                y: 0,
+                rect_width: 32,
+                rect_height: 16,
+            }
+        );
+        assert_eq!(&pixels[4..12], &[0xaa; 8]);
+
+        // Flushes are clipped to the visible scanout.
+        let flush = request(
+            VIRTIO_GPU_CMD_RESOURCE_FLUSH,
+            &ResourceFlush {
+                r: rect(0, 0, 3, 3),
+                resource_id: 1,
+                padding: 0,
+            },
+            &[],
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &flush),
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        let (header, pixels) = read_frame(&mut client);
+        assert_eq!((header.x, header.y), (0, 0));
+        assert_eq!((header.rect_width, header.rect_height), (2, 2));
+        assert_eq!(pixels, [[0; 4], [0xaa; 4], [0; 4], [0; 4]].concat());
+
+        // Releasing the resource disables the scanout.
+        driver.command_type(
+            &mut gpu,
+            &request(
+                VIRTIO_GPU_CMD_RESOURCE_UNREF,
+                &ResourceUnref {
+                    resource_id: 1,
+                    padding: 0,
+                },
+                &[],
+            ),
+        );
+        assert!(gpu.scanout.is_none());
+        let (header, pixels) = read_frame(&mut client);
+        assert_eq!(header, FrameHeader::default());
+        assert!(pixels.is_empty());
+    }
+
+    #[test]
+    fn test_scanout_blob() {
+        let mem = default_mem();
+        let path = temp_socket_path();
+        let mut gpu = Gpu::new(64, 32, true, &path).unwrap();
+        gpu.activate(mem.clone()).unwrap();
+        let mut driver = Driver::new(&mut gpu, &mem, CONTROL_INDEX);
+
+        let create_blob = |resource_id: u32, blob_mem: u32, size: u64| {
+            request(
+                VIRTIO_GPU_CMD_RESOURCE_CREATE_BLOB,
+                &ResourceCreateBlob {
+                    resource_id,
+                    blob_mem,
+                    blob_flags: 0,
+                    nr_entries: 1,
+                    blob_id: 0,
+                    size,
+                },
+                &mem_entry(BACKING_ADDR, 0x2000),
+            )
+        };
+        // The blobs are only available once the driver acked the feature.
+        assert_eq!(
+            driver.command_type(&mut gpu, &create_blob(1, VIRTIO_GPU_BLOB_MEM_GUEST, 0x2000)),
+            VIRTIO_GPU_RESP_ERR_UNSPEC
+        );
+        gpu.set_acked_features(gpu.avail_features());
+        assert_eq!(
+            driver.command_type(&mut gpu, &create_blob(1, 2, 0x2000)),
+            VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &create_blob(1, VIRTIO_GPU_BLOB_MEM_GUEST, 0x3000)),
+            VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &create_blob(1, VIRTIO_GPU_BLOB_MEM_GUEST, 0x2000)),
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        // Blobs take no host memory.
+        assert_eq!(gpu.host_memory, 0);
+
+        let set_scanout_blob = |width: u32, height: u32, stride: u32| {
+            request(
+                VIRTIO_GPU_CMD_SET_SCANOUT_BLOB,
+                &SetScanoutBlob {
+                    r: rect(0, 0, width, height),
+                    scanout_id: 0,
+                    resource_id: 1,
+                    width,
+                    height,
+                    format: VIRTIO_GPU_FORMAT_B8G8R8A8_UNORM,
+                    padding: 0,
+                    strides: [stride, 0, 0, 0],
+                    offsets: [0x100, 0, 0, 0],
+                },
+                &[],
+            )
+        };
+        // The framebuffer must fit in the blob.
+        assert_eq!(
+            driver.command_type(&mut gpu, &set_scanout_blob(64, 32, 0x100)),
+            VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &set_scanout_blob(4, 4, 8)),
+            VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &set_scanout_blob(4, 4, 0x100)),
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        gpu.server.accept().unwrap();
+
+        // The contents are read straight from the guest memory.
+        mem.write_slice(&[0x55; 4], GuestAddress(BACKING_ADDR + 0x204))
+            .unwrap();
+        let flush = request(
+            VIRTIO_GPU_CMD_RESOURCE_FLUSH,
+            &ResourceFlush {
+                r: rect(1, 1, 1, 1),
+                resource_id: 1,
+                padding: 0,
+            },
+            &[],
+        );
+        assert_eq!(
+            driver.command_type(&mut gpu, &flush),
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        let (header, pixels) = read_frame(&mut client);
+        assert_eq!((header.width, header.height), (4, 4));
+        assert_eq!((header.x, header.y), (1, 1));
+        assert_eq!(pixels, [0x55; 4]);
+    }
+
+    #[test]
+    fn test_cursor_queue() {
+        let mem = default_mem();
+        let mut gpu = default_gpu(false);
+        gpu.activate(mem.clone()).unwrap();
+        let mut driver = Driver::new(&mut gpu, &mem, CURSOR_INDEX);
+
+        let response = driver.command(
+            &mut gpu,
+            CURSOR_INDEX,
+            &request(VIRTIO_GPU_CMD_MOVE_CURSOR, &Rect::default(), &[0; 32]),
+        );
+        assert_eq!(
+            read_obj::<CtrlHeader>(&response, 0).unwrap().type_,
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        let response = driver.command(
+            &mut gpu,
+            CURSOR_INDEX,
+            &request(VIRTIO_GPU_CMD_GET_DISPLAY_INFO, &Rect::default(), &[]),
+        );
+        assert_eq!(
+            read_obj::<CtrlHeader>(&response, 0).unwrap().type_,
+            VIRTIO_GPU_RESP_ERR_UNSPEC
+        );
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use logger::{Metric, METRICS};
+use polly::event_manager::{EventManager, Subscriber};
+use utils::epoll::{EpollEvent, EventSet};
+
+use crate::virtio::gpu::device::Gpu;
+use crate::virtio::gpu::{CONTROL_INDEX, CURSOR_INDEX};
+use crate::virtio::VirtioDevice;
+
+impl Gpu {
+    fn process_activate_event(&self, event_manager: &mut EventManager) {
+        // The subscriber must exist as we previously registered activate_evt via
+        // `interest_list()`.
+        let self_subscriber = event_manager
+            .subscriber(self.activate_evt.as_raw_fd())
+            .unwrap();
+
+        for queue_evt in self.queue_evts.iter() {
+            event_manager
+                .register(
+                    queue_evt.as_raw_fd(),
+                    EpollEvent::new(EventSet::IN, queue_evt.as_raw_fd() as u64),
+                    self_subscriber.clone(),
+                )
+                .unwrap_or_else(|e| {
+                    error!("Failed to register GPU queue with event manager: {:?}", e);
+                });
+        }
+
+        event_manager
+            .unregister(self.activate_evt.as_raw_fd())
+            .unwrap_or_else(|e| {
+                error!("Failed to unregister GPU activate evt: {:?}", e);
+            })
+    }
+
+    fn process_queue_event(&mut self, queue_index: usize) {
+        if let Err(e) = self.queue_evts[queue_index].read() {
+            error!("Failed to get GPU queue event: {:?}", e);
+            METRICS.gpu.event_fails.inc();
+            return;
+        }
+
+        if self.process_queue(queue_index) {
+            let _ = self.signal_used_queue();
+        }
+    }
+
+    fn process_scanout_accept(&mut self, event_manager: &mut EventManager) {
+        let fd = match self.server.accept() {
+            Some(fd) => fd,
+            None => return,
+        };
+        // The subscriber must exist as we previously registered the listener via
+        // `interest_list()`.
+        let self_subscriber = event_manager.subscriber(self.server.listener_fd()).unwrap();
+        // The client is only written to, once its socket becomes writable again after filling
+        // up. Hang ups are always reported.
+        if let Err(e) = event_manager.register(
+            fd,
+            EpollEvent::new(EventSet::OUT | EventSet::EDGE_TRIGGERED, fd as u64),
+            self_subscriber,
+        ) {
+            error!(
+                "Failed to register GPU scanout client with event manager: {:?}",
+                e
+            );
+            METRICS.gpu.scanout_client_fails.inc();
+            self.server.remove_client(fd);
+            return;
+        }
+        self.send_scanout(fd);
+    }
+
+    fn process_scanout_client_event(&mut self, fd: RawFd, event_set: EventSet) {
+        if event_set.intersects(EventSet::HANG_UP | EventSet::ERROR) {
+            self.server.drop_client(fd);
+        } else {
+            self.server.flush(fd);
+        }
+    }
+
+    // Unregisters and closes the clients that failed or hung up.
+    fn reap_scanout_clients(&mut self, event_manager: &mut EventManager) {
+        for client in self.server.take_failed_clients() {
+            event_manager
+                .unregister(client.as_raw_fd())
+                .unwrap_or_else(|e| {
+                    error!("Failed to unregister GPU scanout client: {:?}", e);
+                });
+        }
+    }
+}
+
+impl Subscriber for Gpu {
+    // Handle an event for the queues or the scanout socket.
+    fn process(&mut self, event: &EpollEvent, evmgr: &mut EventManager) {
+        let source = event.fd();
+        let event_set = event.event_set();
+
+        if source == self.server.listener_fd() {
+            self.process_scanout_accept(evmgr);
+        } else if self.server.is_client(source) {
+            self.process_scanout_client_event(source, event_set);
+        } else if self.is_activated() {
+            let control_fd = self.queue_evts[CONTROL_INDEX].as_raw_fd();
+            let cursor_fd = self.queue_evts[CURSOR_INDEX].as_raw_fd();
+            let activate_fd = self.activate_evt.as_raw_fd();
+            if control_fd == source {
+                self.process_queue_event(CONTROL_INDEX);
+            } else if cursor_fd == source {
+                self.process_queue_event(CURSOR_INDEX);
+            } else if activate_fd == source {
+                self.process_activate_event(evmgr);
+            } else {
+                warn!("Gpu: Spurious event received: {:?}", source);
+            }
+        } else {
+            warn!(
+                "Gpu: The device is not yet activated. Spurious event received: {:?}",
+                source
+            );
+        }
+
+        self.reap_scanout_clients(evmgr);
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        vec![
+            EpollEvent::new(EventSet::IN, self.activate_evt.as_raw_fd() as u64),
+            EpollEvent::new(EventSet::IN, self.server.listener_fd() as u64),
+        ]
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::os::unix::net::UnixStream;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::virtio::gpu::device::tests::{default_mem, request, Driver};
+    use crate::virtio::gpu::protocol::{CtrlHeader, VIRTIO_GPU_CMD_GET_DISPLAY_INFO};
+    use crate::virtio::gpu::scanout::tests::temp_socket_path;
+
+    #[test]
+    fn test_event_handler() {
+        let mut event_manager = EventManager::new().unwrap();
+        let mem = default_mem();
+        let path = temp_socket_path();
+        let mut gpu = Gpu::new(64, 32, false, &path).unwrap();
+        let mut driver = Driver::new(&mut gpu, &mem, CONTROL_INDEX);
+        driver.queue_command(&request(
+            VIRTIO_GPU_CMD_GET_DISPLAY_INFO,
+            &CtrlHeader::default(),
+            &[],
+        ));
+
+        let gpu = Arc::new(Mutex::new(gpu));
+        event_manager.add_subscriber(gpu.clone()).unwrap();
+
+        // Trigger the queue event. It is not processed before activation.
+        gpu.lock().unwrap().queue_evts[CONTROL_INDEX]
+            .write(1)
+            .unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 0);
+
+        // Now activate the device, which registers the queue events.
+        gpu.lock().unwrap().activate(mem.clone()).unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+
+        // Handle the pending queue event through EventManager.
+        event_manager
+            .run_with_timeout(100)
+            .expect("GPU event timeout or error.");
+        assert_eq!(gpu.lock().unwrap().interrupt_evt().read().unwrap(), 1);
+
+        // Scanout clients are accepted, and dropped once they hang up.
+        let client = UnixStream::connect(&path).unwrap();
+        let ev_count = event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(ev_count, 1);
+        assert!(gpu.lock().unwrap().server.has_clients());
+        drop(client);
+        // The socket is writable, and then hung up.
+        while gpu.lock().unwrap().server.has_clients() {
+            assert!(event_manager.run_with_timeout(100).unwrap() > 0);
+        }
+        assert!(gpu.lock().unwrap().server.take_failed_clients().is_empty());
+    }
+}
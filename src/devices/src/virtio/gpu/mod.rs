@@ -0,0 +1,48 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod device;
+pub mod event_handler;
+pub mod protocol;
+pub mod scanout;
+
+pub use self::device::Gpu;
+pub use self::event_handler::*;
+
+use vm_memory::GuestMemoryError;
+
+/// Device ID used in MMIO device identification.
+/// Because the GPU device is unique per-vm, this ID can be hardcoded.
+pub const GPU_DEV_ID: &str = "gpu";
+/// Virtio GPU device ID, as defined in `include/uapi/linux/virtio_ids.h`.
+pub const TYPE_GPU: u32 = 16;
+pub const QUEUE_SIZE: u16 = 256;
+pub const NUM_QUEUES: usize = 2;
+pub const CONTROL_INDEX: usize = 0;
+pub const CURSOR_INDEX: usize = 1;
+/// The number of scanouts, i.e. displays, of the device.
+pub const NUM_SCANOUTS: u32 = 1;
+/// Upper bound of the host memory taken by the 2D resources of the guest.
+pub const MAX_HOST_MEMORY: u64 = 256 << 20;
+/// Upper bound of the guest memory ranges backing a resource.
+pub const MAX_BACKING_ENTRIES: u32 = 16384;
+/// Upper bound of the size of a command.
+pub const MAX_REQUEST_SIZE: usize = 512 * 1024;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Activation error.
+    Activate(super::ActivateError),
+    /// Guest gave us a readable descriptor after a writable one.
+    UnexpectedReadableDescriptor,
+    /// Guest gave us a command larger than `MAX_REQUEST_SIZE`.
+    RequestTooLarge,
+    /// Guest gave us bad memory addresses.
+    GuestMemory(GuestMemoryError),
+    /// Failed to create or signal an event fd.
+    EventFd(std::io::Error),
+    /// Failed to bind the socket of the scanouts.
+    ScanoutSocket(std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
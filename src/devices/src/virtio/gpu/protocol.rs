@@ -0,0 +1,298 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The subset of the virtio-gpu protocol, as defined in `include/uapi/linux/virtio_gpu.h`,
+//! implemented by the device.
+
+use vm_memory::ByteValued;
+
+/// The device supports the blob resources.
+pub const VIRTIO_GPU_F_RESOURCE_BLOB: u64 = 3;
+
+/// The display configuration changed.
+pub const VIRTIO_GPU_EVENT_DISPLAY: u32 = 1 << 0;
+
+// 2D commands.
+pub const VIRTIO_GPU_CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+pub const VIRTIO_GPU_CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+pub const VIRTIO_GPU_CMD_RESOURCE_UNREF: u32 = 0x0102;
+pub const VIRTIO_GPU_CMD_SET_SCANOUT: u32 = 0x0103;
+pub const VIRTIO_GPU_CMD_RESOURCE_FLUSH: u32 = 0x0104;
+pub const VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+pub const VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+pub const VIRTIO_GPU_CMD_RESOURCE_DETACH_BACKING: u32 = 0x0107;
+pub const VIRTIO_GPU_CMD_RESOURCE_CREATE_BLOB: u32 = 0x010c;
+pub const VIRTIO_GPU_CMD_SET_SCANOUT_BLOB: u32 = 0x010d;
+
+// Cursor commands.
+pub const VIRTIO_GPU_CMD_UPDATE_CURSOR: u32 = 0x0300;
+pub const VIRTIO_GPU_CMD_MOVE_CURSOR: u32 = 0x0301;
+
+// Success responses.
+pub const VIRTIO_GPU_RESP_OK_NODATA: u32 = 0x1100;
+pub const VIRTIO_GPU_RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+
+// Error responses.
+pub const VIRTIO_GPU_RESP_ERR_UNSPEC: u32 = 0x1200;
+pub const VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY: u32 = 0x1201;
+pub const VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID: u32 = 0x1202;
+pub const VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID: u32 = 0x1203;
+pub const VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER: u32 = 0x1205;
+
+/// The response must be sent once the fence is signaled.
+pub const VIRTIO_GPU_FLAG_FENCE: u32 = 1 << 0;
+
+/// The blob resource is backed by guest memory.
+pub const VIRTIO_GPU_BLOB_MEM_GUEST: u32 = 1;
+
+/// The maximum number of scanouts the protocol can describe.
+pub const VIRTIO_GPU_MAX_SCANOUTS: usize = 16;
+
+// Pixel formats, all of them taking 4 bytes per pixel.
+pub const VIRTIO_GPU_FORMAT_B8G8R8A8_UNORM: u32 = 1;
+pub const VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM: u32 = 2;
+pub const VIRTIO_GPU_FORMAT_A8R8G8B8_UNORM: u32 = 3;
+pub const VIRTIO_GPU_FORMAT_X8R8G8B8_UNORM: u32 = 4;
+pub const VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM: u32 = 67;
+pub const VIRTIO_GPU_FORMAT_X8B8G8R8_UNORM: u32 = 68;
+pub const VIRTIO_GPU_FORMAT_A8B8G8R8_UNORM: u32 = 121;
+pub const VIRTIO_GPU_FORMAT_R8G8B8X8_UNORM: u32 = 134;
+
+/// The number of bytes of a pixel, for all the supported formats.
+pub const BYTES_PER_PIXEL: u32 = 4;
+
+/// Returns whether `format` is one of the supported pixel formats.
+pub fn is_supported_format(format: u32) -> bool {
+    match format {
+        VIRTIO_GPU_FORMAT_B8G8R8A8_UNORM
+        | VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM
+        | VIRTIO_GPU_FORMAT_A8R8G8B8_UNORM
+        | VIRTIO_GPU_FORMAT_X8R8G8B8_UNORM
+        | VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM
+        | VIRTIO_GPU_FORMAT_X8B8G8R8_UNORM
+        | VIRTIO_GPU_FORMAT_A8B8G8R8_UNORM
+        | VIRTIO_GPU_FORMAT_R8G8B8X8_UNORM => true,
+        _ => false,
+    }
+}
+
+/// The configuration space of the device.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct VirtioGpuConfig {
+    pub events_read: u32,
+    pub events_clear: u32,
+    pub num_scanouts: u32,
+    pub num_capsets: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for VirtioGpuConfig {}
+
+/// The header of all the commands and responses.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct CtrlHeader {
+    pub type_: u32,
+    pub flags: u32,
+    pub fence_id: u64,
+    pub ctx_id: u32,
+    pub ring_idx: u8,
+    pub padding: [u8; 3],
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for CtrlHeader {}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for Rect {}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct DisplayOne {
+    pub r: Rect,
+    pub enabled: u32,
+    pub flags: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for DisplayOne {}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct RespDisplayInfo {
+    pub hdr: CtrlHeader,
+    pub pmodes: [DisplayOne; VIRTIO_GPU_MAX_SCANOUTS],
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for RespDisplayInfo {}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct ResourceCreate2d {
+    pub resource_id: u32,
+    pub format: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for ResourceCreate2d {}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct ResourceUnref {
+    pub resource_id: u32,
+    pub padding: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for ResourceUnref {}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct SetScanout {
+    pub r: Rect,
+    pub scanout_id: u32,
+    pub resource_id: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for SetScanout {}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct ResourceFlush {
+    pub r: Rect,
+    pub resource_id: u32,
+    pub padding: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for ResourceFlush {}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct TransferToHost2d {
+    pub r: Rect,
+    pub offset: u64,
+    pub resource_id: u32,
+    pub padding: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for TransferToHost2d {}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct ResourceAttachBacking {
+    pub resource_id: u32,
+    pub nr_entries: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for ResourceAttachBacking {}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct MemEntry {
+    pub addr: u64,
+    pub length: u32,
+    pub padding: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for MemEntry {}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct ResourceDetachBacking {
+    pub resource_id: u32,
+    pub padding: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for ResourceDetachBacking {}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct ResourceCreateBlob {
+    pub resource_id: u32,
+    pub blob_mem: u32,
+    pub blob_flags: u32,
+    pub nr_entries: u32,
+    pub blob_id: u64,
+    pub size: u64,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for ResourceCreateBlob {}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct SetScanoutBlob {
+    pub r: Rect,
+    pub scanout_id: u32,
+    pub resource_id: u32,
+    pub width: u32,
+    pub height: u32,
+    pub format: u32,
+    pub padding: u32,
+    pub strides: [u32; 4],
+    pub offsets: [u32; 4],
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for SetScanoutBlob {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::size_of;
+
+    #[test]
+    fn test_struct_sizes() {
+        // The sizes of the structures in `include/uapi/linux/virtio_gpu.h`.
+        assert_eq!(size_of::<VirtioGpuConfig>(), 16);
+        assert_eq!(size_of::<CtrlHeader>(), 24);
+        assert_eq!(size_of::<Rect>(), 16);
+        assert_eq!(size_of::<RespDisplayInfo>(), 408);
+        assert_eq!(size_of::<CtrlHeader>() + size_of::<ResourceCreate2d>(), 40);
+        assert_eq!(size_of::<CtrlHeader>() + size_of::<ResourceUnref>(), 32);
+        assert_eq!(size_of::<CtrlHeader>() + size_of::<SetScanout>(), 48);
+        assert_eq!(size_of::<CtrlHeader>() + size_of::<ResourceFlush>(), 48);
+        assert_eq!(size_of::<CtrlHeader>() + size_of::<TransferToHost2d>(), 56);
+        assert_eq!(
+            size_of::<CtrlHeader>() + size_of::<ResourceAttachBacking>(),
+            32
+        );
+        assert_eq!(size_of::<MemEntry>(), 16);
+        assert_eq!(
+            size_of::<CtrlHeader>() + size_of::<ResourceDetachBacking>(),
+            32
+        );
+        assert_eq!(
+            size_of::<CtrlHeader>() + size_of::<ResourceCreateBlob>(),
+            56
+        );
+        assert_eq!(size_of::<CtrlHeader>() + size_of::<SetScanoutBlob>(), 96);
+    }
+
+    #[test]
+    fn test_is_supported_format() {
+        assert!(is_supported_format(VIRTIO_GPU_FORMAT_B8G8R8A8_UNORM));
+        assert!(is_supported_format(VIRTIO_GPU_FORMAT_R8G8B8X8_UNORM));
+        assert!(!is_supported_format(0));
+        assert!(!is_supported_format(5));
+    }
+}
@@ -0,0 +1,319 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Serves the contents of the scanouts to the clients connected to a Unix socket.
+//!
+//! Every update is sent as a `FrameHeader`, followed by the pixels of the updated rectangle, row
+//! by row, with no padding between the rows. A header with zero width and height announces that
+//! the scanout got disabled, and carries no pixels.
+
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+use logger::{Metric, METRICS};
+use vm_memory::ByteValued;
+
+/// Upper bound of the bytes queued for a client, beyond which the client is dropped.
+pub const MAX_PENDING_BYTES: usize = 64 << 20;
+/// Upper bound of the clients connected at the same time.
+pub const MAX_SCANOUT_CLIENTS: usize = 4;
+
+/// Describes the update of a rectangle of a scanout.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct FrameHeader {
+    /// Index of the scanout.
+    pub scanout_id: u32,
+    /// Pixel format of the scanout, as defined by virtio-gpu.
+    pub format: u32,
+    /// Width of the scanout.
+    pub width: u32,
+    /// Height of the scanout.
+    pub height: u32,
+    /// Horizontal position of the updated rectangle.
+    pub x: u32,
+    /// Vertical position of the updated rectangle.
+    pub y: u32,
+    /// Width of the updated rectangle.
+    pub rect_width: u32,
+    /// Height of the updated rectangle.
+    pub rect_height: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for FrameHeader {}
+
+struct Client {
+    stream: UnixStream,
+    // Bytes queued for the client, of which the first `sent` were already written.
+    pending: Vec<u8>,
+    sent: usize,
+    failed: bool,
+}
+
+impl Client {
+    fn queue(&mut self, header: &FrameHeader, pixels: &[u8]) {
+        if self.failed {
+            return;
+        }
+        self.pending.drain(..self.sent);
+        self.sent = 0;
+        if self.pending.len() + header.as_slice().len() + pixels.len() > MAX_PENDING_BYTES {
+            warn!("gpu: Dropping a scanout client that doesn't keep up with the frames");
+            self.fail();
+            return;
+        }
+        self.pending.extend_from_slice(header.as_slice());
+        self.pending.extend_from_slice(pixels);
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+        while !self.failed && self.sent < self.pending.len() {
+            match self.stream.write(&self.pending[self.sent..]) {
+                Ok(0) => self.fail(),
+                Ok(written) => self.sent += written,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    warn!("gpu: Failed to write to a scanout client: {:?}", e);
+                    self.fail();
+                }
+            }
+        }
+        if self.sent == self.pending.len() {
+            self.pending.clear();
+            self.sent = 0;
+        }
+    }
+
+    fn fail(&mut self) {
+        METRICS.gpu.scanout_client_fails.inc();
+        self.failed = true;
+        self.pending = Vec::new();
+        self.sent = 0;
+    }
+}
+
+/// Listens on a Unix socket for the clients of the scanouts.
+pub struct ScanoutServer {
+    listener: UnixListener,
+    clients: Vec<Client>,
+}
+
+impl ScanoutServer {
+    /// Binds the socket at `path`, which must not exist.
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(ScanoutServer {
+            listener,
+            clients: Vec::new(),
+        })
+    }
+
+    /// The file descriptor of the listening socket.
+    pub fn listener_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+
+    /// Accepts a pending client, and returns its file descriptor. Returns `None` if there was no
+    /// pending client, or if it was turned away.
+    pub fn accept(&mut self) -> Option<RawFd> {
+        let stream = match self.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return None,
+            Err(e) => {
+                error!("gpu: Failed to accept a scanout client: {:?}", e);
+                METRICS.gpu.scanout_client_fails.inc();
+                return None;
+            }
+        };
+        if self.clients.len() >= MAX_SCANOUT_CLIENTS {
+            warn!("gpu: Too many scanout clients");
+            METRICS.gpu.scanout_client_fails.inc();
+            return None;
+        }
+        if let Err(e) = stream.set_nonblocking(true) {
+            error!("gpu: Failed to set up a scanout client: {:?}", e);
+            METRICS.gpu.scanout_client_fails.inc();
+            return None;
+        }
+        METRICS.gpu.scanout_clients.inc();
+        let fd = stream.as_raw_fd();
+        self.clients.push(Client {
+            stream,
+            pending: Vec::new(),
+            sent: 0,
+            failed: false,
+        });
+        Some(fd)
+    }
+
+    /// Whether any client would receive the frames.
+    pub fn has_clients(&self) -> bool {
+        self.clients.iter().any(|client| !client.failed)
+    }
+
+    /// Whether `fd` belongs to a client.
+    pub fn is_client(&self, fd: RawFd) -> bool {
+        self.clients
+            .iter()
+            .any(|client| client.stream.as_raw_fd() == fd)
+    }
+
+    /// Queues an update for all the clients.
+    pub fn broadcast(&mut self, header: &FrameHeader, pixels: &[u8]) {
+        for client in self.clients.iter_mut() {
+            client.queue(header, pixels);
+        }
+        METRICS.gpu.frames_sent.inc();
+    }
+
+    /// Queues an update for the client with the file descriptor `fd`.
+    pub fn send_to(&mut self, fd: RawFd, header: &FrameHeader, pixels: &[u8]) {
+        if let Some(client) = self.client_mut(fd) {
+            client.queue(header, pixels);
+            METRICS.gpu.frames_sent.inc();
+        }
+    }
+
+    /// Writes the queued updates of the client with the file descriptor `fd`, once its socket is
+    /// writable again.
+    pub fn flush(&mut self, fd: RawFd) {
+        if let Some(client) = self.client_mut(fd) {
+            client.flush();
+        }
+    }
+
+    /// Drops the client with the file descriptor `fd`, e.g. after it hung up.
+    pub fn drop_client(&mut self, fd: RawFd) {
+        if let Some(client) = self.client_mut(fd) {
+            client.failed = true;
+        }
+    }
+
+    /// Closes the client with the file descriptor `fd` right away.
+    pub fn remove_client(&mut self, fd: RawFd) {
+        self.clients
+            .retain(|client| client.stream.as_raw_fd() != fd);
+    }
+
+    /// Removes the clients that failed or were dropped. Their sockets are returned, so that
+    /// they can be unregistered from the event loop before being closed.
+    pub fn take_failed_clients(&mut self) -> Vec<UnixStream> {
+        let (failed, clients) = self.clients.drain(..).partition(|client| client.failed);
+        self.clients = clients;
+        failed
+            .into_iter()
+            .map(|client: Client| client.stream)
+            .collect()
+    }
+
+    fn client_mut(&mut self, fd: RawFd) -> Option<&mut Client> {
+        self.clients
+            .iter_mut()
+            .find(|client| client.stream.as_raw_fd() == fd)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use utils::tempfile::TempFile;
+
+    pub(crate) fn temp_socket_path() -> String {
+        let mut socket = TempFile::new().unwrap();
+        // Remove the file so the path can be used by the socket.
+        socket.remove().unwrap();
+        String::from(socket.as_path().to_str().unwrap())
+    }
+
+    fn header(width: u32, height: u32) -> FrameHeader {
+        FrameHeader {
+            scanout_id: 0,
+            format: 1,
+            width,
+            height,
+            x: 0,
+            y: 0,
+            rect_width: width,
+            rect_height: height,
+        }
+    }
+
+    #[test]
+    fn test_scanout_server() {
+        let path = temp_socket_path();
+        let mut server = ScanoutServer::bind(&path).unwrap();
+        assert!(ScanoutServer::bind(&path).is_err());
+        assert!(server.accept().is_none());
+        assert!(!server.has_clients());
+
+        let mut first = UnixStream::connect(&path).unwrap();
+        let first_fd = server.accept().unwrap();
+        let mut second = UnixStream::connect(&path).unwrap();
+        let second_fd = server.accept().unwrap();
+        assert!(server.has_clients());
+        assert!(server.is_client(first_fd));
+        assert!(!server.is_client(server.listener_fd()));
+
+        let pixels = [0xabu8; 8];
+        server.broadcast(&header(2, 1), &pixels);
+        server.send_to(second_fd, &header(1, 1), &pixels[..4]);
+
+        let mut buf = [0u8; 40];
+        first.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..32], header(2, 1).as_slice());
+        assert_eq!(&buf[32..], &pixels);
+        let mut buf = [0u8; 76];
+        second.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[40..72], header(1, 1).as_slice());
+
+        // Dropped clients are handed back to be unregistered.
+        server.drop_client(first_fd);
+        assert!(server.is_client(first_fd));
+        let failed = server.take_failed_clients();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].as_raw_fd(), first_fd);
+        assert!(!server.is_client(first_fd));
+        assert!(server.is_client(second_fd));
+    }
+
+    #[test]
+    fn test_slow_client() {
+        let path = temp_socket_path();
+        let mut server = ScanoutServer::bind(&path).unwrap();
+        let _client = UnixStream::connect(&path).unwrap();
+        let fd = server.accept().unwrap();
+
+        // The client never reads, so the frames pile up until the client is dropped.
+        let pixels = vec![0u8; 16 << 20];
+        let frames = MAX_PENDING_BYTES / pixels.len() + 2;
+        for _ in 0..frames {
+            server.send_to(fd, &header(2048, 2048), &pixels);
+        }
+        assert!(!server.has_clients());
+        assert_eq!(server.take_failed_clients().len(), 1);
+    }
+
+    #[test]
+    fn test_max_clients() {
+        let path = temp_socket_path();
+        let mut server = ScanoutServer::bind(&path).unwrap();
+        let mut clients = Vec::new();
+        for _ in 0..MAX_SCANOUT_CLIENTS {
+            clients.push(UnixStream::connect(&path).unwrap());
+            assert!(server.accept().is_some());
+        }
+        let mut extra = UnixStream::connect(&path).unwrap();
+        assert!(server.accept().is_none());
+        // The extra client is disconnected.
+        let mut buf = [0u8; 1];
+        assert_eq!(extra.read(&mut buf).unwrap(), 0);
+    }
+}
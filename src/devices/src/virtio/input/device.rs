@@ -0,0 +1,565 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::mem::size_of;
+use std::result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use logger::{Metric, METRICS};
+use utils::eventfd::EventFd;
+use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
+use vm_memory::{ByteValued, Bytes, GuestMemoryMmap};
+
+use super::super::{
+    ActivateError, ActivateResult, DeviceState, Queue, VirtioDevice, VIRTIO_MMIO_INT_VRING,
+};
+use super::protocol::*;
+use super::{
+    Error, Result, EVENT_INDEX, INPUT_DEV_ID, MAX_PENDING_EVENTS, NUM_QUEUES, QUEUE_SIZE,
+    STATUS_INDEX, TYPE_INPUT,
+};
+
+use crate::Error as DeviceError;
+
+/// The name of the device, as reported to the guest.
+const DEVICE_NAME: &[u8] = b"Firecracker Virtio Input";
+
+/// Builds the bitmap in which the bits of `codes` are set.
+fn bitmap<I: Iterator<Item = u16>>(codes: I) -> Vec<u8> {
+    let mut bits = Vec::new();
+    for code in codes {
+        let byte = code as usize / 8;
+        if bits.len() <= byte {
+            bits.resize(byte + 1, 0);
+        }
+        bits[byte] |= 1 << (code % 8);
+    }
+    bits
+}
+
+/// Checks whether the device advertises `event`, so that the guest doesn't discard it.
+fn is_supported(event: &VirtioInputEvent) -> bool {
+    match event.type_ {
+        EV_SYN => event.code == SYN_REPORT,
+        // Key events release (0), press (1) or repeat (2) the key.
+        EV_KEY => {
+            ((KEY_FIRST <= event.code && event.code <= KEY_LAST)
+                || (BTN_FIRST <= event.code && event.code <= BTN_LAST))
+                && event.value >= 0
+                && event.value <= 2
+        }
+        EV_REL => match event.code {
+            REL_X | REL_Y | REL_HWHEEL | REL_WHEEL => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Virtio device which hands keyboard and mouse events from the host to the guest.
+pub struct Input {
+    // Virtio fields.
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+    pub(crate) config_space: VirtioInputConfig,
+    pub(crate) activate_evt: EventFd,
+
+    // Transport related fields.
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) queue_evts: Vec<EventFd>,
+    pub(crate) interrupt_status: Arc<AtomicUsize>,
+    interrupt_evt: EventFd,
+    pub(crate) device_state: DeviceState,
+
+    // Implementation specific fields.
+    // The events waiting for the guest to provide buffers.
+    pending: VecDeque<VirtioInputEvent>,
+}
+
+impl Input {
+    /// Creates a new input device.
+    pub fn new() -> Result<Input> {
+        let mut queue_evts = Vec::with_capacity(NUM_QUEUES);
+        for _ in 0..NUM_QUEUES {
+            queue_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
+        }
+
+        Ok(Input {
+            avail_features: 1u64 << VIRTIO_F_VERSION_1,
+            acked_features: 0u64,
+            config_space: VirtioInputConfig::default(),
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queues: (0..NUM_QUEUES).map(|_| Queue::new(QUEUE_SIZE)).collect(),
+            queue_evts,
+            interrupt_status: Arc::new(AtomicUsize::new(0)),
+            interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            device_state: DeviceState::Inactive,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Provides the ID of the input device.
+    pub fn id(&self) -> &str {
+        INPUT_DEV_ID
+    }
+
+    /// Queues `events` for the guest, and hands them over as soon as the guest provides buffers.
+    /// A `SYN_REPORT` is appended if the events don't end with one, so that the guest doesn't
+    /// wait for more events before processing them. Either all the events are queued, or none.
+    pub fn send_events(&mut self, events: &[VirtioInputEvent]) -> Result<()> {
+        if !self.is_activated() {
+            return Err(Error::NotActivated);
+        }
+        if let Some(event) = events.iter().find(|event| !is_supported(event)) {
+            return Err(Error::UnsupportedEvent(*event));
+        }
+        let needs_report = events.last().map_or(false, |event| !event.is_syn_report());
+        let count = events.len() + needs_report as usize;
+        if self.pending.len() + count > MAX_PENDING_EVENTS {
+            METRICS.input.events_dropped.add(count);
+            return Err(Error::TooManyPendingEvents);
+        }
+
+        self.pending.extend(events.iter().cloned());
+        if needs_report {
+            self.pending.push_back(VirtioInputEvent::syn_report());
+        }
+        if self.process_event_queue() {
+            let _ = self.signal_used_queue();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+        self.interrupt_evt.write(1).map_err(|e| {
+            error!("Failed to signal input device interrupt: {:?}", e);
+            METRICS.input.event_fails.inc();
+            DeviceError::FailedSignalingUsedQueue(e)
+        })
+    }
+
+    // Writes the pending events to the buffers of the event queue, one event per buffer.
+    // Returns whether any buffer was used.
+    pub(crate) fn process_event_queue(&mut self) -> bool {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem,
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        let queue = &mut self.queues[EVENT_INDEX];
+        let mut used_any = false;
+
+        while !self.pending.is_empty() {
+            let head = match queue.pop(mem) {
+                Some(head) => head,
+                None => break,
+            };
+            let result = if !head.is_write_only() {
+                Err(Error::UnexpectedReadOnlyDescriptor)
+            } else if (head.len as usize) < size_of::<VirtioInputEvent>() {
+                Err(Error::DescriptorTooSmall)
+            } else {
+                // Safe to unwrap, the loop checks that an event is pending.
+                mem.write_obj(*self.pending.front().unwrap(), head.addr)
+                    .map_err(Error::GuestMemory)
+            };
+            match result {
+                Ok(()) => {
+                    self.pending.pop_front();
+                    queue.add_used(mem, head.index, size_of::<VirtioInputEvent>() as u32);
+                    METRICS.input.events_count.inc();
+                }
+                Err(e) => {
+                    // The event is kept for the next buffer.
+                    error!("input: {:?}", e);
+                    METRICS.input.event_fails.inc();
+                    queue.add_used(mem, head.index, 0);
+                }
+            }
+            used_any = true;
+        }
+
+        used_any
+    }
+
+    // Consumes the status events sent by the guest, like keyboard LED updates, which have no
+    // host side effect. Returns whether any buffer was used.
+    pub(crate) fn process_status_queue(&mut self) -> bool {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem,
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        let queue = &mut self.queues[STATUS_INDEX];
+        let mut used_any = false;
+
+        while let Some(head) = queue.pop(mem) {
+            let result = if head.is_write_only() {
+                Err(Error::UnexpectedWriteOnlyDescriptor)
+            } else if (head.len as usize) < size_of::<VirtioInputEvent>() {
+                Err(Error::DescriptorTooSmall)
+            } else {
+                mem.read_obj::<VirtioInputEvent>(head.addr)
+                    .map_err(Error::GuestMemory)
+            };
+            match result {
+                Ok(event) => {
+                    debug!("input: Status event from the guest: {:?}", event);
+                    METRICS.input.status_count.inc();
+                }
+                Err(e) => {
+                    error!("input: {:?}", e);
+                    METRICS.input.event_fails.inc();
+                }
+            }
+            queue.add_used(mem, head.index, 0);
+            used_any = true;
+        }
+
+        used_any
+    }
+
+    // Fills the size and data of the configuration space, for the current selectors.
+    fn update_config(&mut self) {
+        let data = match self.config_space.select {
+            VIRTIO_INPUT_CFG_ID_NAME => DEVICE_NAME.to_vec(),
+            VIRTIO_INPUT_CFG_ID_DEVIDS => VirtioInputDevIds {
+                bustype: BUS_VIRTUAL,
+                vendor: 0,
+                product: 0,
+                version: 1,
+            }
+            .as_slice()
+            .to_vec(),
+            VIRTIO_INPUT_CFG_EV_BITS => match u16::from(self.config_space.subsel) {
+                EV_KEY => bitmap((KEY_FIRST..=KEY_LAST).chain(BTN_FIRST..=BTN_LAST)),
+                EV_REL => bitmap([REL_X, REL_Y, REL_HWHEEL, REL_WHEEL].iter().cloned()),
+                _ => Vec::new(),
+            },
+            // There is no serial number, property or absolute axis to report.
+            _ => Vec::new(),
+        };
+        self.config_space.size = data.len() as u8;
+        self.config_space.data = [0; VIRTIO_INPUT_CFG_DATA_SIZE];
+        self.config_space.data[..data.len()].copy_from_slice(&data);
+    }
+}
+
+impl VirtioDevice for Input {
+    fn device_type(&self) -> u32 {
+        TYPE_INPUT
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.interrupt_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.interrupt_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) {
+        let config_space_bytes = self.config_space.as_slice();
+        let config_len = config_space_bytes.len() as u64;
+        if offset >= config_len {
+            error!("Failed to read input device config space");
+            METRICS.input.cfg_fails.inc();
+            return;
+        }
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            // This write can't fail, offset and end are checked against config_len.
+            data.write_all(
+                &config_space_bytes[offset as usize..cmp::min(end, config_len) as usize],
+            )
+            .unwrap();
+        }
+    }
+
+    fn config_space_bytes(&self) -> Vec<u8> {
+        self.config_space.as_slice().to_vec()
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        // The driver may only write the `select` and `subsel` fields.
+        let end = offset.checked_add(data.len() as u64);
+        if end.map_or(true, |end| end > 2) {
+            error!("Failed to write input device config space");
+            METRICS.input.cfg_fails.inc();
+            return;
+        }
+        self.config_space.as_mut_slice()[offset as usize..offset as usize + data.len()]
+            .copy_from_slice(data);
+        self.update_config();
+    }
+
+    fn is_activated(&self) -> bool {
+        match self.device_state {
+            DeviceState::Inactive => false,
+            DeviceState::Activated(_) => true,
+        }
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
+        if self.activate_evt.write(1).is_err() {
+            error!("Input: Cannot write to activate_evt");
+            METRICS.input.activate_fails.inc();
+            return Err(ActivateError::BadActivate);
+        }
+        self.device_state = DeviceState::Activated(mem);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::virtio::queue::tests::*;
+    use crate::virtio::VIRTQ_DESC_F_WRITE;
+    use vm_memory::GuestAddress;
+
+    impl Input {
+        pub(crate) fn set_queue(&mut self, idx: usize, q: Queue) {
+            self.queues[idx] = q;
+        }
+    }
+
+    pub fn default_mem() -> GuestMemoryMmap {
+        GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap()
+    }
+
+    // Selects the configuration of `select` and `subsel`, and returns its data.
+    fn read_selected_config(input: &mut Input, select: u8, subsel: u8) -> Vec<u8> {
+        input.write_config(0, &[select]);
+        input.write_config(1, &[subsel]);
+        let mut size = [0u8];
+        input.read_config(2, &mut size);
+        let mut data = vec![0u8; size[0] as usize];
+        input.read_config(8, &mut data);
+        data
+    }
+
+    fn key(code: u16, value: i32) -> VirtioInputEvent {
+        VirtioInputEvent::new(EV_KEY, code, value)
+    }
+
+    #[test]
+    fn test_virtio_features() {
+        let input = Input::new().unwrap();
+        assert_eq!(input.device_type(), TYPE_INPUT);
+        assert_eq!(input.id(), INPUT_DEV_ID);
+        assert_eq!(input.avail_features(), 1u64 << VIRTIO_F_VERSION_1);
+        assert_eq!(input.queues().len(), NUM_QUEUES);
+        assert_eq!(input.queue_events().len(), NUM_QUEUES);
+    }
+
+    #[test]
+    fn test_config_space() {
+        let mut input = Input::new().unwrap();
+
+        assert_eq!(
+            read_selected_config(&mut input, VIRTIO_INPUT_CFG_ID_NAME, 0),
+            DEVICE_NAME
+        );
+        let devids = read_selected_config(&mut input, VIRTIO_INPUT_CFG_ID_DEVIDS, 0);
+        assert_eq!(devids, [BUS_VIRTUAL as u8, 0, 0, 0, 0, 0, 1, 0]);
+
+        // The keyboard keys and the mouse buttons.
+        let keys = read_selected_config(&mut input, VIRTIO_INPUT_CFG_EV_BITS, EV_KEY as u8);
+        assert_eq!(keys.len(), BTN_LAST as usize / 8 + 1);
+        assert_eq!(keys[0], 0xfe);
+        assert_eq!(keys[KEY_LAST as usize / 8], 0x01);
+        assert_eq!(keys[BTN_FIRST as usize / 8], 0xff);
+        assert_eq!(keys[0x100 / 8], 0);
+
+        let axes = read_selected_config(&mut input, VIRTIO_INPUT_CFG_EV_BITS, EV_REL as u8);
+        assert_eq!(axes, [0x43, 0x01]);
+
+        // Nothing else is reported.
+        for (select, subsel) in &[
+            (VIRTIO_INPUT_CFG_UNSET, 0),
+            (VIRTIO_INPUT_CFG_ID_SERIAL, 0),
+            (VIRTIO_INPUT_CFG_PROP_BITS, 0),
+            (VIRTIO_INPUT_CFG_EV_BITS, 0x03),
+            (VIRTIO_INPUT_CFG_ABS_INFO, 0),
+        ] {
+            assert!(read_selected_config(&mut input, *select, *subsel).is_empty());
+        }
+        // The data of the previous selection is cleared.
+        let mut data = [0xffu8; 4];
+        input.read_config(8, &mut data);
+        assert_eq!(data, [0u8; 4]);
+
+        // Only the selectors are writable.
+        input.write_config(0, &[VIRTIO_INPUT_CFG_ID_NAME, 0]);
+        input.write_config(2, &[0xff]);
+        input.write_config(1, &[0, 0]);
+        let mut size = [0u8];
+        input.read_config(2, &mut size);
+        assert_eq!(size[0] as usize, DEVICE_NAME.len());
+
+        // Reads past the config space don't mutate the buffer.
+        let mut data = [0xffu8; 4];
+        input.read_config(size_of::<VirtioInputConfig>() as u64, &mut data);
+        assert_eq!(data, [0xffu8; 4]);
+    }
+
+    #[test]
+    fn test_send_events() {
+        let mem = default_mem();
+        let mut input = Input::new().unwrap();
+        let eventq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        input.set_queue(EVENT_INDEX, eventq.create_queue());
+
+        // Events can't be sent before the guest driver is ready.
+        match input.send_events(&[key(30, 1)]) {
+            Err(Error::NotActivated) => (),
+            _ => panic!("The device isn't activated."),
+        }
+        input.activate(mem.clone()).unwrap();
+
+        // The guest only has room for two events, so the SYN_REPORT is kept pending.
+        for i in 0..2 {
+            eventq.dtable[i].set(0x4000 + 0x10 * i as u64, 8, VIRTQ_DESC_F_WRITE, 0);
+            eventq.avail.ring[i].set(i as u16);
+        }
+        eventq.avail.idx.set(2);
+        input
+            .send_events(&[key(30, 1), VirtioInputEvent::new(EV_REL, REL_X, -5)])
+            .unwrap();
+        assert_eq!(input.interrupt_evt().read().unwrap(), 1);
+        assert_eq!(eventq.used.idx.get(), 2);
+        assert_eq!(eventq.used.ring[1].get().len, 8);
+        assert_eq!(
+            mem.read_obj::<VirtioInputEvent>(GuestAddress(0x4000))
+                .unwrap(),
+            key(30, 1)
+        );
+        assert_eq!(
+            mem.read_obj::<VirtioInputEvent>(GuestAddress(0x4010))
+                .unwrap(),
+            VirtioInputEvent::new(EV_REL, REL_X, -5)
+        );
+        assert_eq!(input.pending.len(), 1);
+
+        // A read only buffer is returned unused, and the next one gets the event.
+        eventq.dtable[2].set(0x4020, 8, 0, 0);
+        eventq.dtable[3].set(0x4030, 8, VIRTQ_DESC_F_WRITE, 0);
+        eventq.avail.ring[2].set(2);
+        eventq.avail.ring[3].set(3);
+        eventq.avail.idx.set(4);
+        assert!(input.process_event_queue());
+        assert_eq!(eventq.used.idx.get(), 4);
+        assert_eq!(eventq.used.ring[2].get().len, 0);
+        assert_eq!(
+            mem.read_obj::<VirtioInputEvent>(GuestAddress(0x4030))
+                .unwrap(),
+            VirtioInputEvent::syn_report()
+        );
+        assert!(input.pending.is_empty());
+        assert!(!input.process_event_queue());
+
+        // An explicit SYN_REPORT isn't doubled.
+        input
+            .send_events(&[key(30, 0), VirtioInputEvent::syn_report()])
+            .unwrap();
+        assert_eq!(input.pending.len(), 2);
+        input.send_events(&[]).unwrap();
+        assert_eq!(input.pending.len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_events() {
+        let mem = default_mem();
+        let mut input = Input::new().unwrap();
+        input.activate(mem).unwrap();
+
+        let events = [
+            VirtioInputEvent::new(EV_SYN, 1, 0),
+            key(0, 1),
+            key(KEY_LAST + 1, 1),
+            key(BTN_LAST + 1, 1),
+            key(30, 3),
+            key(30, -1),
+            VirtioInputEvent::new(EV_REL, 0x02, 1),
+            VirtioInputEvent::new(0x03, 0, 0),
+        ];
+        for event in events.iter() {
+            match input.send_events(&[key(30, 1), *event]) {
+                Err(Error::UnsupportedEvent(e)) => assert_eq!(e, *event),
+                _ => panic!("Unsupported event: {:?}", event),
+            }
+        }
+        // None of the events were queued.
+        assert!(input.pending.is_empty());
+
+        // The pending events are bounded.
+        let events = vec![key(BTN_FIRST, 1); MAX_PENDING_EVENTS - 1];
+        input.send_events(&events).unwrap();
+        match input.send_events(&[key(BTN_FIRST, 0)]) {
+            Err(Error::TooManyPendingEvents) => (),
+            _ => panic!("Too many pending events."),
+        }
+        assert_eq!(input.pending.len(), MAX_PENDING_EVENTS);
+    }
+
+    #[test]
+    fn test_process_status_queue() {
+        let mem = default_mem();
+        let mut input = Input::new().unwrap();
+        let statusq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        input.set_queue(STATUS_INDEX, statusq.create_queue());
+        input.activate(mem.clone()).unwrap();
+
+        // A LED update, then buffers the device can't read from.
+        mem.write_obj(VirtioInputEvent::new(0x11, 0, 1), GuestAddress(0x4000))
+            .unwrap();
+        statusq.dtable[0].set(0x4000, 8, 0, 0);
+        statusq.dtable[1].set(0x4010, 8, VIRTQ_DESC_F_WRITE, 0);
+        statusq.dtable[2].set(0x4020, 4, 0, 0);
+        for i in 0..3 {
+            statusq.avail.ring[i].set(i as u16);
+        }
+        statusq.avail.idx.set(3);
+
+        assert!(input.process_status_queue());
+        assert_eq!(statusq.used.idx.get(), 3);
+        assert!(!input.process_status_queue());
+    }
+
+    #[test]
+    fn test_bitmap() {
+        assert!(bitmap(std::iter::empty()).is_empty());
+        assert_eq!(bitmap([0, 9, 15].iter().cloned()), [0x01, 0x82]);
+        assert_eq!(bitmap([17].iter().cloned()), [0, 0, 0x02]);
+    }
+}
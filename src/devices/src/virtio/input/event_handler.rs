@@ -0,0 +1,165 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::os::unix::io::AsRawFd;
+
+use logger::{Metric, METRICS};
+use polly::event_manager::{EventManager, Subscriber};
+use utils::epoll::{EpollEvent, EventSet};
+
+use crate::virtio::input::device::Input;
+use crate::virtio::input::{EVENT_INDEX, STATUS_INDEX};
+use crate::virtio::VirtioDevice;
+
+impl Input {
+    fn process_activate_event(&self, event_manager: &mut EventManager) {
+        // The subscriber must exist as we previously registered activate_evt via
+        // `interest_list()`.
+        let self_subscriber = event_manager
+            .subscriber(self.activate_evt.as_raw_fd())
+            .unwrap();
+
+        for queue_evt in self.queue_evts.iter() {
+            event_manager
+                .register(
+                    queue_evt.as_raw_fd(),
+                    EpollEvent::new(EventSet::IN, queue_evt.as_raw_fd() as u64),
+                    self_subscriber.clone(),
+                )
+                .unwrap_or_else(|e| {
+                    error!("Failed to register input queue with event manager: {:?}", e);
+                });
+        }
+
+        event_manager
+            .unregister(self.activate_evt.as_raw_fd())
+            .unwrap_or_else(|e| {
+                error!("Failed to unregister input activate evt: {:?}", e);
+            })
+    }
+
+    fn process_queue_event(&mut self, queue_index: usize) {
+        if let Err(e) = self.queue_evts[queue_index].read() {
+            error!("Failed to get input queue event: {:?}", e);
+            METRICS.input.event_fails.inc();
+            return;
+        }
+
+        // New buffers on the event queue make room for the pending events.
+        let used_any = match queue_index {
+            EVENT_INDEX => self.process_event_queue(),
+            _ => self.process_status_queue(),
+        };
+        if used_any {
+            let _ = self.signal_used_queue();
+        }
+    }
+}
+
+impl Subscriber for Input {
+    // Handle an event for the event or status queues.
+    fn process(&mut self, event: &EpollEvent, evmgr: &mut EventManager) {
+        let source = event.fd();
+        let event_set = event.event_set();
+
+        let supported_events = EventSet::IN;
+        if !supported_events.contains(event_set) {
+            warn!(
+                "Input: Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
+
+        if self.is_activated() {
+            let event_fd = self.queue_evts[EVENT_INDEX].as_raw_fd();
+            let status_fd = self.queue_evts[STATUS_INDEX].as_raw_fd();
+            let activate_fd = self.activate_evt.as_raw_fd();
+            if event_fd == source {
+                self.process_queue_event(EVENT_INDEX);
+            } else if status_fd == source {
+                self.process_queue_event(STATUS_INDEX);
+            } else if activate_fd == source {
+                self.process_activate_event(evmgr);
+            } else {
+                warn!("Input: Spurious event received: {:?}", source);
+            }
+        } else {
+            warn!(
+                "Input: The device is not yet activated. Spurious event received: {:?}",
+                source
+            );
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        vec![EpollEvent::new(
+            EventSet::IN,
+            self.activate_evt.as_raw_fd() as u64,
+        )]
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::virtio::input::device::tests::default_mem;
+    use crate::virtio::input::protocol::{VirtioInputEvent, EV_KEY};
+    use crate::virtio::queue::tests::*;
+    use crate::virtio::VIRTQ_DESC_F_WRITE;
+    use vm_memory::{Bytes, GuestAddress};
+
+    #[test]
+    fn test_event_handler() {
+        let mut event_manager = EventManager::new().unwrap();
+        let mem = default_mem();
+        let mut input = Input::new().unwrap();
+        let eventq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        input.set_queue(EVENT_INDEX, eventq.create_queue());
+
+        let input = Arc::new(Mutex::new(input));
+        event_manager.add_subscriber(input.clone()).unwrap();
+
+        // Trigger the queue event. It is not processed before activation.
+        input.lock().unwrap().queue_evts[EVENT_INDEX]
+            .write(1)
+            .unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 0);
+
+        // Now activate the device, which registers the queue events.
+        input.lock().unwrap().activate(mem.clone()).unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+
+        // The pending queue event finds nothing to do.
+        event_manager
+            .run_with_timeout(100)
+            .expect("Input event timeout or error.");
+        assert!(input.lock().unwrap().interrupt_evt().read().is_err());
+
+        // Events wait for the guest to provide buffers.
+        let event = VirtioInputEvent::new(EV_KEY, 30, 1);
+        input.lock().unwrap().send_events(&[event]).unwrap();
+        assert_eq!(eventq.used.idx.get(), 0);
+
+        eventq.dtable[0].set(0x4000, 8, VIRTQ_DESC_F_WRITE, 0);
+        eventq.avail.ring[0].set(0);
+        eventq.avail.idx.set(1);
+        input.lock().unwrap().queue_evts[EVENT_INDEX]
+            .write(1)
+            .unwrap();
+        event_manager
+            .run_with_timeout(100)
+            .expect("Input event timeout or error.");
+        assert_eq!(input.lock().unwrap().interrupt_evt().read().unwrap(), 1);
+        assert_eq!(eventq.used.idx.get(), 1);
+        assert_eq!(
+            mem.read_obj::<VirtioInputEvent>(GuestAddress(0x4000))
+                .unwrap(),
+            event
+        );
+    }
+}
@@ -0,0 +1,48 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod device;
+pub mod event_handler;
+pub mod protocol;
+
+pub use self::device::Input;
+pub use self::event_handler::*;
+pub use self::protocol::VirtioInputEvent;
+
+use vm_memory::GuestMemoryError;
+
+/// Device ID used in MMIO device identification.
+/// Because the input device is unique per-vm, this ID can be hardcoded.
+pub const INPUT_DEV_ID: &str = "input";
+/// Virtio input device ID, as defined in `include/uapi/linux/virtio_ids.h`.
+pub const TYPE_INPUT: u32 = 18;
+pub const QUEUE_SIZE: u16 = 256;
+pub const NUM_QUEUES: usize = 2;
+pub const EVENT_INDEX: usize = 0;
+pub const STATUS_INDEX: usize = 1;
+/// Upper bound of the events waiting for the guest to provide buffers.
+pub const MAX_PENDING_EVENTS: usize = 4096;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Activation error.
+    Activate(super::ActivateError),
+    /// Guest gave us a read only descriptor that protocol says to write to.
+    UnexpectedReadOnlyDescriptor,
+    /// Guest gave us a write only descriptor that protocol says to read from.
+    UnexpectedWriteOnlyDescriptor,
+    /// Guest gave us a descriptor too small to hold an event.
+    DescriptorTooSmall,
+    /// Guest gave us bad memory addresses.
+    GuestMemory(GuestMemoryError),
+    /// Failed to create or signal an event fd.
+    EventFd(std::io::Error),
+    /// The guest driver hasn't activated the device yet.
+    NotActivated,
+    /// The device doesn't advertise the type, code or value of the event.
+    UnsupportedEvent(VirtioInputEvent),
+    /// The guest didn't take enough of the pending events to make room for new ones.
+    TooManyPendingEvents,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
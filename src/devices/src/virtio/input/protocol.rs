@@ -0,0 +1,132 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The virtio-input protocol, as defined in `include/uapi/linux/virtio_input.h`, and the evdev
+//! codes of `include/uapi/linux/input-event-codes.h` used by the device.
+
+use vm_memory::ByteValued;
+
+// Configuration space selectors.
+pub const VIRTIO_INPUT_CFG_UNSET: u8 = 0x00;
+pub const VIRTIO_INPUT_CFG_ID_NAME: u8 = 0x01;
+pub const VIRTIO_INPUT_CFG_ID_SERIAL: u8 = 0x02;
+pub const VIRTIO_INPUT_CFG_ID_DEVIDS: u8 = 0x03;
+pub const VIRTIO_INPUT_CFG_PROP_BITS: u8 = 0x10;
+pub const VIRTIO_INPUT_CFG_EV_BITS: u8 = 0x11;
+pub const VIRTIO_INPUT_CFG_ABS_INFO: u8 = 0x12;
+
+/// The size of the union at the end of the configuration space.
+pub const VIRTIO_INPUT_CFG_DATA_SIZE: usize = 128;
+
+// Event types.
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+
+pub const SYN_REPORT: u16 = 0;
+
+// The keyboard keys, from KEY_ESC to KEY_MICMUTE.
+pub const KEY_FIRST: u16 = 1;
+pub const KEY_LAST: u16 = 248;
+// The mouse buttons, from BTN_LEFT to BTN_TASK.
+pub const BTN_FIRST: u16 = 0x110;
+pub const BTN_LAST: u16 = 0x117;
+
+// Relative axes.
+pub const REL_X: u16 = 0x00;
+pub const REL_Y: u16 = 0x01;
+pub const REL_HWHEEL: u16 = 0x06;
+pub const REL_WHEEL: u16 = 0x08;
+
+/// Bus type of virtual devices, as defined in `include/uapi/linux/input.h`.
+pub const BUS_VIRTUAL: u16 = 0x06;
+
+/// The device configuration space.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct VirtioInputConfig {
+    pub select: u8,
+    pub subsel: u8,
+    pub size: u8,
+    pub reserved: [u8; 5],
+    pub data: [u8; VIRTIO_INPUT_CFG_DATA_SIZE],
+}
+
+impl Default for VirtioInputConfig {
+    fn default() -> Self {
+        VirtioInputConfig {
+            select: VIRTIO_INPUT_CFG_UNSET,
+            subsel: 0,
+            size: 0,
+            reserved: [0; 5],
+            data: [0; VIRTIO_INPUT_CFG_DATA_SIZE],
+        }
+    }
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for VirtioInputConfig {}
+
+/// The identifiers of the device, read through `VIRTIO_INPUT_CFG_ID_DEVIDS`.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct VirtioInputDevIds {
+    pub bustype: u16,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for VirtioInputDevIds {}
+
+/// An evdev event, as carried by the event and status queues.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct VirtioInputEvent {
+    pub type_: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+impl VirtioInputEvent {
+    /// Creates an event of type `type_`.
+    pub fn new(type_: u16, code: u16, value: i32) -> Self {
+        VirtioInputEvent { type_, code, value }
+    }
+
+    /// Creates the event which ends a group of simultaneous events.
+    pub fn syn_report() -> Self {
+        Self::new(EV_SYN, SYN_REPORT, 0)
+    }
+
+    /// Checks whether the event ends a group of simultaneous events.
+    pub fn is_syn_report(&self) -> bool {
+        self.type_ == EV_SYN && self.code == SYN_REPORT
+    }
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for VirtioInputEvent {}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use super::*;
+
+    #[test]
+    fn test_sizes() {
+        // The sizes mandated by the virtio specification.
+        assert_eq!(size_of::<VirtioInputConfig>(), 136);
+        assert_eq!(size_of::<VirtioInputDevIds>(), 8);
+        assert_eq!(size_of::<VirtioInputEvent>(), 8);
+    }
+
+    #[test]
+    fn test_syn_report() {
+        assert!(VirtioInputEvent::syn_report().is_syn_report());
+        assert!(!VirtioInputEvent::new(EV_KEY, SYN_REPORT, 0).is_syn_report());
+        assert!(!VirtioInputEvent::new(EV_SYN, 1, 0).is_syn_report());
+    }
+}
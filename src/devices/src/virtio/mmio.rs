@@ -8,10 +8,13 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
 
+use logger::{Metric, VirtioQueueMetrics, METRICS};
 use utils::byte_order;
 use vm_memory::{GuestAddress, GuestMemoryMmap};
 
 use super::device_status;
+#[cfg(feature = "mmio-trace")]
+use super::mmio_trace;
 use super::*;
 use crate::bus::BusDevice;
 
@@ -24,6 +27,30 @@ const MMIO_MAGIC_VALUE: u32 = 0x7472_6976;
 //current version specified by the mmio standard (legacy devices used 1 here)
 const MMIO_VERSION: u32 = 2;
 
+/// The state of a virtio device and of its transport, for debugging a device which stopped
+/// serving its driver.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VirtioDeviceDump {
+    /// The status register, as set by the driver.
+    pub device_status: u32,
+    /// The features offered by the device.
+    pub avail_features: u64,
+    /// The features acknowledged by the driver.
+    pub acked_features: u64,
+    /// The interrupts the driver is yet to acknowledge.
+    pub interrupt_status: u32,
+    /// The generation of the configuration space.
+    pub config_generation: u32,
+    /// The queue the driver selected last.
+    pub queue_select: u32,
+    /// The queues of the device.
+    pub queues: Vec<QueueInfo>,
+    /// The bytes of the configuration space.
+    pub config_space: Vec<u8>,
+    /// The internal state of the device handler, as pairs of names and values.
+    pub device_state: Vec<(&'static str, String)>,
+}
+
 /// Implements the
 /// [MMIO](http://docs.oasis-open.org/virtio/virtio/v1.0/cs04/virtio-v1.0-cs04.html#x1-1090002)
 /// transport for virtio devices.
@@ -50,15 +77,30 @@ pub struct MmioTransport {
     pub(crate) config_generation: u32,
     mem: GuestMemoryMmap,
     pub(crate) interrupt_status: Arc<AtomicUsize>,
+    // Where the device is in its lifecycle, `Paused` aside, which the device tracks itself.
+    pub(crate) lifecycle: DeviceLifecycle,
+    // Where the malformed descriptor chains of the queues are counted, if anywhere.
+    queue_metrics: Option<Arc<VirtioQueueMetrics>>,
+    // The features of the device which may be offered to the guest driver.
+    feature_mask: u64,
+    // The ID the accesses of the guest driver are traced under, if they are.
+    #[cfg(feature = "mmio-trace")]
+    trace_id: Option<String>,
 }
 
 impl MmioTransport {
     /// Constructs a new MMIO transport for the given virtio device.
     pub fn new(mem: GuestMemoryMmap, device: Arc<Mutex<dyn VirtioDevice>>) -> MmioTransport {
-        let interrupt_status = device
-            .lock()
-            .expect("Poisoned device lock")
-            .interrupt_status();
+        let (interrupt_status, lifecycle) = {
+            let locked_device = device.lock().expect("Poisoned device lock");
+            // A device restored from a snapshot may be active already.
+            let lifecycle = if locked_device.is_activated() {
+                DeviceLifecycle::Activated
+            } else {
+                DeviceLifecycle::Inactive
+            };
+            (locked_device.interrupt_status(), lifecycle)
+        };
 
         MmioTransport {
             device,
@@ -69,7 +111,56 @@ impl MmioTransport {
             config_generation: 0,
             mem,
             interrupt_status,
+            lifecycle,
+            queue_metrics: None,
+            feature_mask: !0,
+            #[cfg(feature = "mmio-trace")]
+            trace_id: None,
+        }
+    }
+
+    /// Counts the malformed descriptor chains of the queues of the device in `metrics`, across
+    /// the resets of the device.
+    pub fn set_queue_metrics(&mut self, metrics: Arc<VirtioQueueMetrics>) {
+        for queue in self.locked_device().queues_mut() {
+            queue.set_violation_metrics(metrics.clone());
         }
+        self.queue_metrics = Some(metrics);
+    }
+
+    /// Withholds the features of the device outside of `mask` from the guest driver, which can
+    /// neither see nor acknowledge them. `VIRTIO_F_VERSION_1` is always offered.
+    pub fn set_feature_mask(&mut self, mask: u64) {
+        self.feature_mask = mask;
+    }
+
+    // The page `page` of the features which may be offered to the guest driver.
+    fn feature_mask_by_page(&self, page: u32) -> u32 {
+        match page {
+            0 => self.feature_mask as u32,
+            1 => (self.feature_mask >> 32) as u32,
+            _ => 0,
+        }
+    }
+
+    /// Traces the accesses of the guest driver to this transport under `device_id`.
+    #[cfg(feature = "mmio-trace")]
+    pub fn trace_as(&mut self, device_id: String) {
+        self.trace_id = Some(device_id);
+    }
+
+    /// Whether the accesses of the guest driver are traced, queue notifications included, which
+    /// then have to be written to the transport rather than to ioeventfds.
+    #[cfg(feature = "mmio-trace")]
+    pub fn is_traced(&self) -> bool {
+        self.trace_id.is_some()
+    }
+
+    /// Whether the accesses of the guest driver are traced, queue notifications included, which
+    /// then have to be written to the transport rather than to ioeventfds.
+    #[cfg(not(feature = "mmio-trace"))]
+    pub fn is_traced(&self) -> bool {
+        false
     }
 
     pub fn locked_device(&self) -> MutexGuard<dyn VirtioDevice + 'static> {
@@ -81,6 +172,43 @@ impl MmioTransport {
         self.device.clone()
     }
 
+    /// Where the encapsulated device is in its lifecycle. A device with a quarantined queue is
+    /// `Failed`.
+    pub fn lifecycle(&self) -> DeviceLifecycle {
+        match self.lifecycle {
+            DeviceLifecycle::Activated if self.needs_reset() => DeviceLifecycle::Failed,
+            DeviceLifecycle::Activated if self.locked_device().is_paused() => {
+                DeviceLifecycle::Paused
+            }
+            lifecycle => lifecycle,
+        }
+    }
+
+    // Whether a queue was quarantined after too many malformed descriptor chains, which the
+    // driver has to reset the device to recover from.
+    fn needs_reset(&self) -> bool {
+        self.locked_device()
+            .queues()
+            .iter()
+            .any(Queue::is_quarantined)
+    }
+
+    /// Dumps the state of the encapsulated device and of this transport.
+    pub fn dump(&self) -> VirtioDeviceDump {
+        let device = self.locked_device();
+        VirtioDeviceDump {
+            device_status: self.device_status,
+            avail_features: device.avail_features() & self.feature_mask,
+            acked_features: device.acked_features(),
+            interrupt_status: self.interrupt_status.load(Ordering::SeqCst) as u32,
+            config_generation: self.config_generation,
+            queue_select: self.queue_select,
+            queues: device.queues().iter().map(|q| q.info(&self.mem)).collect(),
+            config_space: device.config_space_bytes(),
+            device_state: device.debug_state(),
+        }
+    }
+
     fn check_device_status(&self, set: u32, clr: u32) -> bool {
         self.device_status & (set | clr) == set
     }
@@ -142,12 +270,20 @@ impl MmioTransport {
         self.queue_select = 0;
         self.interrupt_status.store(0, Ordering::SeqCst);
         self.device_status = device_status::INIT;
-        // . Keep interrupt_evt and queue_evts as is. There may be pending
-        //   notifications in those eventfds, but nothing will happen other
-        //   than supurious wakeups.
+        // . Drain the queue_evts: the notifications the old driver left pending would otherwise
+        //   be processed against the queues the new driver sets up. The eventfds are
+        //   non-blocking, so reading an empty one fails with EAGAIN, which is fine.
+        // . Keep interrupt_evt as is. There may be pending notifications in it, but nothing
+        //   will happen other than spurious wakeups.
         // . Do not reset config_generation and keep it monotonically increasing
+        for queue_evt in self.locked_device().queue_events() {
+            let _ = queue_evt.read();
+        }
         for queue in self.locked_device().queues_mut() {
             *queue = Queue::new(queue.get_max_size());
+            if let Some(metrics) = self.queue_metrics.as_ref() {
+                queue.set_violation_metrics(metrics.clone());
+            }
         }
     }
 
@@ -179,23 +315,27 @@ impl MmioTransport {
                     self.locked_device()
                         .activate(self.mem.clone())
                         .expect("Failed to activate device");
+                    self.lifecycle = DeviceLifecycle::Activated;
                 }
             }
             _ if (status & FAILED) != 0 => {
                 // TODO: notify backend driver to stop the device
                 self.device_status |= FAILED;
+                self.lifecycle = DeviceLifecycle::Failed;
             }
             _ if status == 0 => {
                 if self.locked_device().is_activated() {
-                    let mut device_status = self.device_status;
-                    let reset_result = self.locked_device().reset();
-                    match reset_result {
-                        Some((_interrupt_evt, mut _queue_evts)) => {}
-                        None => {
-                            device_status |= FAILED;
-                        }
+                    if self.locked_device().reset() {
+                        info!(
+                            "virtio-mmio: the driver reset the device of type {}",
+                            self.locked_device().device_type()
+                        );
+                        METRICS.vmm.device_resets.inc();
+                        self.lifecycle = DeviceLifecycle::Reset;
+                    } else {
+                        self.device_status |= FAILED;
+                        self.lifecycle = DeviceLifecycle::Failed;
                     }
-                    self.device_status = device_status;
                 }
 
                 // If the backend device driver doesn't support reset,
@@ -212,10 +352,8 @@ impl MmioTransport {
             }
         }
     }
-}
 
-impl BusDevice for MmioTransport {
-    fn read(&mut self, offset: u64, data: &mut [u8]) {
+    fn read_register(&mut self, offset: u64, data: &mut [u8]) {
         match offset {
             0x00..=0xff if data.len() == 4 => {
                 let v = match offset {
@@ -226,7 +364,8 @@ impl BusDevice for MmioTransport {
                     0x10 => {
                         let mut features = self
                             .locked_device()
-                            .avail_features_by_page(self.features_select);
+                            .avail_features_by_page(self.features_select)
+                            & self.feature_mask_by_page(self.features_select);
                         if self.features_select == 1 {
                             features |= 0x1; // enable support of VirtIO Version 1
                         }
@@ -235,6 +374,9 @@ impl BusDevice for MmioTransport {
                     0x34 => self.with_queue(0, |q| u32::from(q.get_max_size())),
                     0x44 => self.with_queue(0, |q| q.ready as u32),
                     0x60 => self.interrupt_status.load(Ordering::SeqCst) as u32,
+                    0x70 if self.needs_reset() => {
+                        self.device_status | device_status::DEVICE_NEEDS_RESET
+                    }
                     0x70 => self.device_status,
                     0xfc => self.config_generation,
                     _ => {
@@ -255,7 +397,7 @@ impl BusDevice for MmioTransport {
         };
     }
 
-    fn write(&mut self, offset: u64, data: &[u8]) {
+    fn write_register(&mut self, offset: u64, data: &[u8]) {
         fn hi(v: &mut GuestAddress, x: u32) {
             *v = (*v & 0xffff_ffff) | (u64::from(x) << 32)
         }
@@ -274,6 +416,7 @@ impl BusDevice for MmioTransport {
                             device_status::DRIVER,
                             device_status::FEATURES_OK | device_status::FAILED,
                         ) {
+                            let v = v & self.feature_mask_by_page(self.acked_features_select);
                             self.locked_device()
                                 .ack_features_by_page(self.acked_features_select, v);
                         } else {
@@ -287,6 +430,7 @@ impl BusDevice for MmioTransport {
                     0x30 => self.queue_select = v,
                     0x38 => self.update_queue_field(|q| q.size = v as u16),
                     0x44 => self.update_queue_field(|q| q.ready = v == 1),
+                    0x50 => self.notify_queue(v),
                     0x64 => {
                         if self.check_device_status(device_status::DRIVER_OK, 0) {
                             self.interrupt_status
@@ -322,6 +466,40 @@ impl BusDevice for MmioTransport {
         }
     }
 
+    // Signals the event of the queue `index`, as KVM does through the ioeventfd of the queue
+    // when the notifications don't exit to the VMM.
+    fn notify_queue(&self, index: u32) {
+        match self.locked_device().queue_events().get(index as usize) {
+            Some(queue_evt) => {
+                if let Err(e) = queue_evt.write(1) {
+                    error!("Failed to notify virtio queue {}: {:?}", index, e);
+                }
+            }
+            None => warn!("notification of invalid virtio queue: {}", index),
+        }
+    }
+
+    #[cfg(feature = "mmio-trace")]
+    fn trace_access(&self, write: bool, offset: u64, data: &[u8]) {
+        if let Some(device_id) = self.trace_id.as_ref() {
+            mmio_trace::record(device_id, write, offset, data);
+        }
+    }
+}
+
+impl BusDevice for MmioTransport {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        self.read_register(offset, data);
+        #[cfg(feature = "mmio-trace")]
+        self.trace_access(false, offset, data);
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        #[cfg(feature = "mmio-trace")]
+        self.trace_access(true, offset, data);
+        self.write_register(offset, data);
+    }
+
     fn interrupt(&self, irq_mask: u32) -> std::io::Result<()> {
         self.interrupt_status
             .fetch_or(irq_mask as usize, Ordering::SeqCst);
@@ -339,7 +517,7 @@ pub(crate) mod tests {
 
     use super::*;
     use utils::eventfd::EventFd;
-    use vm_memory::GuestMemoryMmap;
+    use vm_memory::{Bytes, GuestMemoryMmap};
 
     pub(crate) struct DummyDevice {
         acked_features: u64,
@@ -349,6 +527,8 @@ pub(crate) mod tests {
         queue_evts: Vec<EventFd>,
         queues: Vec<Queue>,
         device_activated: bool,
+        reset_supported: bool,
+        paused: bool,
         config_bytes: [u8; 0xeff],
     }
 
@@ -365,6 +545,8 @@ pub(crate) mod tests {
                 ],
                 queues: vec![Queue::new(16), Queue::new(32)],
                 device_activated: false,
+                reset_supported: false,
+                paused: false,
                 config_bytes: [0; 0xeff],
             }
         }
@@ -429,6 +611,25 @@ pub(crate) mod tests {
         fn is_activated(&self) -> bool {
             self.device_activated
         }
+
+        fn reset(&mut self) -> bool {
+            if self.reset_supported {
+                self.device_activated = false;
+            }
+            self.reset_supported
+        }
+
+        fn is_paused(&self) -> bool {
+            self.paused
+        }
+
+        fn config_space_bytes(&self) -> Vec<u8> {
+            self.config_bytes[..8].to_vec()
+        }
+
+        fn debug_state(&self) -> Vec<(&'static str, String)> {
+            vec![("paused", self.paused.to_string())]
+        }
     }
 
     fn set_device_status(d: &mut MmioTransport, status: u32) {
@@ -442,7 +643,7 @@ pub(crate) mod tests {
         let m = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
         let mut dummy = DummyDevice::new();
         // Validate reset is no-op.
-        assert!(dummy.reset().is_none());
+        assert!(!dummy.reset());
         let mut d = MmioTransport::new(m, Arc::new(Mutex::new(dummy)));
 
         // We just make sure here that the implementation of a mmio device behaves as we expect,
@@ -713,6 +914,7 @@ pub(crate) mod tests {
         assert!(!d.are_queues_valid());
         assert!(!d.locked_device().is_activated());
         assert_eq!(d.device_status, device_status::INIT);
+        assert_eq!(d.lifecycle(), DeviceLifecycle::Inactive);
 
         set_device_status(&mut d, device_status::ACKNOWLEDGE);
         set_device_status(&mut d, device_status::ACKNOWLEDGE | device_status::DRIVER);
@@ -774,6 +976,7 @@ pub(crate) mod tests {
                 | device_status::DRIVER_OK
         );
         assert!(d.locked_device().is_activated());
+        assert_eq!(d.lifecycle(), DeviceLifecycle::Activated);
 
         // A write which changes the size of a queue after activation; currently only triggers
         // a warning path and have no effect on queue state.
@@ -823,6 +1026,25 @@ pub(crate) mod tests {
         assert!(d.locked_device().is_activated());
     }
 
+    #[test]
+    fn test_bus_device_queue_notify() {
+        let m = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let mut d = MmioTransport::new(m, Arc::new(Mutex::new(DummyDevice::new())));
+        let mut buf = vec![0; 4];
+
+        // The notification of the second queue signals its event.
+        write_le_u32(&mut buf[..], 1);
+        d.write(u64::from(NOTIFY_REG_OFFSET), &buf[..]);
+        assert!(d.locked_device().queue_events()[0].read().is_err());
+        assert_eq!(d.locked_device().queue_events()[1].read().unwrap(), 1);
+
+        // The notification of a queue which doesn't exist is dropped.
+        write_le_u32(&mut buf[..], 2);
+        d.write(u64::from(NOTIFY_REG_OFFSET), &buf[..]);
+        assert!(d.locked_device().queue_events()[1].read().is_err());
+        assert!(!d.is_traced());
+    }
+
     #[test]
     fn test_bus_device_reset() {
         let m = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
@@ -839,12 +1061,179 @@ pub(crate) mod tests {
         d.write(0x70, &buf[..]);
         assert_eq!(d.device_status, 0x8f);
         assert!(d.locked_device().is_activated());
+        assert_eq!(d.lifecycle(), DeviceLifecycle::Failed);
 
         // Nothing happens when backend driver doesn't support reset
         write_le_u32(&mut buf[..], 0x0);
         d.write(0x70, &buf[..]);
         assert_eq!(d.device_status, 0x8f);
         assert!(d.locked_device().is_activated());
+        assert_eq!(d.lifecycle(), DeviceLifecycle::Failed);
+    }
+
+    #[test]
+    fn test_bus_device_reset_supported() {
+        let m = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let mut dummy = DummyDevice::new();
+        dummy.reset_supported = true;
+        let mut d = MmioTransport::new(m, Arc::new(Mutex::new(dummy)));
+        activate_device(&mut d);
+        d.interrupt_status
+            .store(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+        d.locked_device().queue_events()[0].write(1).unwrap();
+
+        // The driver resetting the device, e.g. when the guest kexecs, deactivates it and
+        // resets the transport, the queues, the interrupt status and the queue notifications.
+        let resets = METRICS.vmm.device_resets.count();
+        set_device_status(&mut d, 0);
+        assert_eq!(d.device_status, device_status::INIT);
+        assert!(!d.locked_device().is_activated());
+        assert!(!d.are_queues_valid());
+        assert_eq!(d.interrupt_status.load(Ordering::SeqCst), 0);
+        assert!(d.locked_device().queue_events()[0].read().is_err());
+        assert!(METRICS.vmm.device_resets.count() > resets);
+        assert_eq!(d.lifecycle(), DeviceLifecycle::Reset);
+
+        // The driver of the new kernel can activate the device again.
+        activate_device(&mut d);
+        assert_eq!(d.lifecycle(), DeviceLifecycle::Activated);
+    }
+
+    #[test]
+    fn test_feature_mask() {
+        let m = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let dummy_dev = Arc::new(Mutex::new(DummyDevice::new()));
+        dummy_dev
+            .lock()
+            .unwrap()
+            .set_avail_features(0x0000_0002_0000_0124);
+        let mut d = MmioTransport::new(m, dummy_dev.clone());
+        d.set_feature_mask(!0x0000_0002_0000_0100);
+        let mut buf = vec![0; 4];
+
+        // The features outside of the mask aren't offered, except for VIRTIO_F_VERSION_1.
+        d.features_select = 0;
+        d.read(0x10, &mut buf[..]);
+        assert_eq!(read_le_u32(&buf[..]), 0x24);
+        d.features_select = 1;
+        d.read(0x10, &mut buf[..]);
+        assert_eq!(read_le_u32(&buf[..]), 0x1);
+        assert_eq!(d.dump().avail_features, 0x24);
+
+        // Nor can they be acknowledged.
+        set_device_status(&mut d, device_status::ACKNOWLEDGE);
+        set_device_status(&mut d, device_status::ACKNOWLEDGE | device_status::DRIVER);
+        d.acked_features_select = 0;
+        write_le_u32(&mut buf[..], 0x124);
+        d.write(0x20, &buf[..]);
+        d.acked_features_select = 1;
+        write_le_u32(&mut buf[..], 0x2);
+        d.write(0x20, &buf[..]);
+        assert_eq!(dummy_dev.lock().unwrap().acked_features(), 0x24);
+    }
+
+    #[test]
+    fn test_quarantined_queue() {
+        let m = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let mut dummy = DummyDevice::new();
+        dummy.reset_supported = true;
+        let dummy = Arc::new(Mutex::new(dummy));
+        let mut d = MmioTransport::new(m, dummy.clone());
+        let metrics = Arc::new(VirtioQueueMetrics::default());
+        d.set_queue_metrics(metrics.clone());
+        activate_device(&mut d);
+        let mut buf = vec![0; 4];
+
+        // A quarantined queue fails the device, which the driver has to reset.
+        dummy.lock().unwrap().queues[1].violations = MAX_DESCRIPTOR_VIOLATIONS;
+        assert_eq!(d.lifecycle(), DeviceLifecycle::Failed);
+        d.read(0x70, &mut buf[..]);
+        assert_eq!(
+            read_le_u32(&buf[..]),
+            device_status::ACKNOWLEDGE
+                | device_status::DRIVER
+                | device_status::FEATURES_OK
+                | device_status::DRIVER_OK
+                | device_status::DEVICE_NEEDS_RESET
+        );
+
+        set_device_status(&mut d, 0);
+        assert_eq!(d.lifecycle(), DeviceLifecycle::Reset);
+        d.read(0x70, &mut buf[..]);
+        assert_eq!(read_le_u32(&buf[..]), device_status::INIT);
+        assert!(dummy
+            .lock()
+            .unwrap()
+            .queues
+            .iter()
+            .all(|q| q.violations == 0));
+        // The queues set up again keep counting their violations in the same metrics, shared
+        // with the transport and the test.
+        assert_eq!(Arc::strong_count(&metrics), 4);
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let m = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let dummy = Arc::new(Mutex::new(DummyDevice::new()));
+        let mut d = MmioTransport::new(m.clone(), dummy.clone());
+        assert_eq!(d.lifecycle(), DeviceLifecycle::Inactive);
+
+        // A paused device which the driver is yet to activate is still inactive.
+        dummy.lock().unwrap().paused = true;
+        assert_eq!(d.lifecycle(), DeviceLifecycle::Inactive);
+        dummy.lock().unwrap().paused = false;
+
+        activate_device(&mut d);
+        dummy.lock().unwrap().paused = true;
+        assert_eq!(d.lifecycle(), DeviceLifecycle::Paused);
+        dummy.lock().unwrap().paused = false;
+        assert_eq!(d.lifecycle(), DeviceLifecycle::Activated);
+
+        // A transport created for an active device, e.g. when restoring a snapshot, reports it
+        // as activated.
+        let d = MmioTransport::new(m, dummy);
+        assert_eq!(d.lifecycle(), DeviceLifecycle::Activated);
+    }
+
+    #[test]
+    fn test_dump() {
+        let m = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let dummy = Arc::new(Mutex::new(DummyDevice::new()));
+        let mut d = MmioTransport::new(m.clone(), dummy.clone());
+
+        let dump = d.dump();
+        assert_eq!(dump.device_status, device_status::INIT);
+        assert_eq!(dump.queues.len(), 2);
+        assert_eq!(dump.queues[1].max_size, 32);
+        // The rings of the queues which aren't ready are not read.
+        assert!(!dump.queues[0].ready);
+        assert_eq!(dump.queues[0].avail_idx, None);
+        assert_eq!(dump.config_space, vec![0; 8]);
+        assert_eq!(dump.device_state, vec![("paused", "false".to_string())]);
+
+        activate_device(&mut d);
+        m.write_obj::<u16>(3, GuestAddress(2)).unwrap();
+        let dump = d.dump();
+        assert_eq!(
+            dump.device_status,
+            device_status::ACKNOWLEDGE
+                | device_status::DRIVER
+                | device_status::FEATURES_OK
+                | device_status::DRIVER_OK
+        );
+        let queue = &dump.queues[0];
+        assert!(queue.ready);
+        assert_eq!(queue.size, 16);
+        assert_eq!(queue.next_avail, 0);
+        // All the rings of the queues set up by the tests are at address 0.
+        assert_eq!(queue.avail_ring, 0);
+        assert_eq!(queue.avail_idx, Some(3));
+        assert_eq!(queue.used_idx, Some(3));
+
+        // The rings out of guest memory are not read.
+        dummy.lock().unwrap().queues[1].used_ring = GuestAddress(0x1000);
+        assert_eq!(d.dump().queues[1].used_idx, None);
     }
 
     #[test]
@@ -0,0 +1,119 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Traces the accesses of the guest drivers to the registers, the configuration space and the
+//! queue notification register of the virtio MMIO transports, to debug the protocol mismatches
+//! between a driver and a device.
+//!
+//! Every access is written to the sink as one line:
+//!
+//! ```text
+//! <monotonic time in us> <device ID> <R|W> <register>@<offset> <bytes, in hex>
+//! ```
+
+use std::io::Write;
+use std::sync::Mutex;
+
+use utils::time::{get_time, ClockType};
+
+lazy_static! {
+    static ref TRACE_SINK: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+}
+
+/// Traces the accesses to the MMIO transports registered from now on to `sink`.
+pub fn set_sink(sink: Box<dyn Write + Send>) {
+    *TRACE_SINK.lock().expect("Poisoned lock") = Some(sink);
+}
+
+/// Whether the accesses to the MMIO transports are traced.
+pub fn enabled() -> bool {
+    TRACE_SINK.lock().expect("Poisoned lock").is_some()
+}
+
+// Names the register at `offset`, as in the virtio specification.
+fn register_name(offset: u64) -> &'static str {
+    match offset {
+        0x00 => "MagicValue",
+        0x04 => "Version",
+        0x08 => "DeviceID",
+        0x0c => "VendorID",
+        0x10 => "DeviceFeatures",
+        0x14 => "DeviceFeaturesSel",
+        0x20 => "DriverFeatures",
+        0x24 => "DriverFeaturesSel",
+        0x30 => "QueueSel",
+        0x34 => "QueueNumMax",
+        0x38 => "QueueNum",
+        0x44 => "QueueReady",
+        0x50 => "QueueNotify",
+        0x60 => "InterruptStatus",
+        0x64 => "InterruptACK",
+        0x70 => "Status",
+        0x80 => "QueueDescLow",
+        0x84 => "QueueDescHigh",
+        0x90 => "QueueDriverLow",
+        0x94 => "QueueDriverHigh",
+        0xa0 => "QueueDeviceLow",
+        0xa4 => "QueueDeviceHigh",
+        0xfc => "ConfigGeneration",
+        0x100..=0xfff => "Config",
+        _ => "Unknown",
+    }
+}
+
+fn format_access(time_us: u64, device: &str, write: bool, offset: u64, data: &[u8]) -> String {
+    let bytes: Vec<String> = data.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{} {} {} {}@{:#x} {}\n",
+        time_us,
+        device,
+        if write { "W" } else { "R" },
+        register_name(offset),
+        offset,
+        bytes.join("")
+    )
+}
+
+/// Traces the read, or the write if `write`, of `data` at `offset` in the MMIO transport of
+/// `device`. Tracing stops if the sink fails.
+pub(crate) fn record(device: &str, write: bool, offset: u64, data: &[u8]) {
+    let mut sink = TRACE_SINK.lock().expect("Poisoned lock");
+    if let Some(writer) = sink.as_mut() {
+        let line = format_access(
+            get_time(ClockType::Monotonic) / 1000,
+            device,
+            write,
+            offset,
+            data,
+        );
+        if let Err(err) = writer.write_all(line.as_bytes()) {
+            error!("Stopped tracing the MMIO accesses: {}", err);
+            *sink = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_access() {
+        assert_eq!(
+            format_access(42, "rootfs", false, 0x70, &[0x0f, 0, 0, 0]),
+            "42 rootfs R Status@0x70 0f000000\n"
+        );
+        assert_eq!(
+            format_access(43, "eth0", true, 0x50, &[1, 0, 0, 0]),
+            "43 eth0 W QueueNotify@0x50 01000000\n"
+        );
+        assert_eq!(
+            format_access(44, "eth0", false, 0x104, &[0xab, 0xcd]),
+            "44 eth0 R Config@0x104 abcd\n"
+        );
+        assert_eq!(
+            format_access(45, "eth0", true, 0x1000, &[]),
+            "45 eth0 W Unknown@0x1000 \n"
+        );
+    }
+}
@@ -10,20 +10,33 @@ use std;
 use std::any::Any;
 use std::io::Error as IOError;
 
+pub mod balloon;
 pub mod block;
 pub mod device;
+pub mod dirty_pages;
+pub mod gpu;
+pub mod input;
 mod mmio;
+#[cfg(feature = "mmio-trace")]
+pub mod mmio_trace;
 pub mod net;
 pub mod persist;
 mod queue;
+pub mod rng;
+pub mod sound;
 pub mod vsock;
 
+pub use self::balloon::{Balloon, BalloonEvent, BalloonEventSink, TYPE_BALLOON};
 pub use self::block::*;
 pub use self::device::*;
+pub use self::gpu::{Gpu, TYPE_GPU};
+pub use self::input::{Input, VirtioInputEvent, TYPE_INPUT};
 pub use self::mmio::*;
 pub use self::net::*;
 pub use self::persist::*;
 pub use self::queue::*;
+pub use self::rng::{Entropy, TYPE_RNG};
+pub use self::sound::{PcmSink, SinkKind, Sound, TYPE_SOUND};
 pub use self::vsock::*;
 
 /// When the driver initializes the device, it lets the device know about the
@@ -41,6 +54,7 @@ mod device_status {
     pub const FAILED: u32 = 128;
     pub const FEATURES_OK: u32 = 8;
     pub const DRIVER_OK: u32 = 4;
+    pub const DEVICE_NEEDS_RESET: u32 = 64;
 }
 
 /// Types taken from linux/virtio_ids.h.
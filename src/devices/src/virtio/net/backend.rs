@@ -0,0 +1,48 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The host side of the virtio net device, which the frames of the guest are sent to and the
+//! frames for the guest are received from.
+
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+
+use utils::net::Tap;
+
+/// A host endpoint exchanging frames with the net device. Every frame is prefixed by its
+/// `virtio_net_hdr_v1`, in both directions.
+///
+/// The device polls `as_raw_fd()` edge triggered for incoming frames, so once it is signaled,
+/// `read_frame()` is called until it fails with `WouldBlock`.
+pub trait NetBackend: AsRawFd + Send {
+    /// Receives the next frame into `buf`, returning its length.
+    fn read_frame(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Sends the frame in `buf`.
+    fn write_frame(&mut self, buf: &[u8]) -> io::Result<()>;
+
+    /// Whether the backend takes the frames with partial checksums and the segmentation
+    /// offloads their virtio-net header describes. Otherwise, the device completes the offloads
+    /// of the frames itself, and hands the backend the resulting frames with an empty header.
+    /// The offloads of the received frames are only offered to the guest if the backend takes
+    /// the offloads.
+    fn offloads(&self) -> bool {
+        false
+    }
+}
+
+impl NetBackend for Tap {
+    fn read_frame(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read(buf)
+    }
+
+    fn write_frame(&mut self, buf: &[u8]) -> io::Result<()> {
+        // A tap takes a frame in a single write.
+        self.write(buf).map(|_| ())
+    }
+
+    fn offloads(&self) -> bool {
+        // The offload flags of the tap are set when it's opened.
+        true
+    }
+}
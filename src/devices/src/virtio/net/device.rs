@@ -5,11 +5,18 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+use crate::virtio::net::impairment::{Impairment, ImpairmentParams};
+use crate::virtio::net::offload::{complete_offloads, needs_offloads};
+use crate::virtio::net::rx_filter::{RxFilter, RxFilterError};
 use crate::virtio::net::Error;
+use crate::virtio::net::NetBackend;
 use crate::virtio::net::Result;
-use crate::virtio::net::{MAX_BUFFER_SIZE, QUEUE_SIZE, QUEUE_SIZES, RX_INDEX, TX_INDEX};
+use crate::virtio::net::{
+    CTRL_INDEX, MAX_BUFFER_SIZE, MAX_CTRL_COMMAND_SIZE, QUEUE_SIZE, QUEUE_SIZES, RX_INDEX, TX_INDEX,
+};
 use crate::virtio::{
-    ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_NET, VIRTIO_MMIO_INT_VRING,
+    ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_NET, VIRTIO_MMIO_INT_CONFIG,
+    VIRTIO_MMIO_INT_VRING,
 };
 use crate::{report_net_event_fail, Error as DeviceError};
 use dumbo::ns::MmdsNetworkStack;
@@ -17,19 +24,18 @@ use dumbo::{EthernetFrame, MacAddr, MAC_ADDR_LEN};
 use libc::EAGAIN;
 use logger::{Metric, METRICS};
 use rate_limiter::{RateLimiter, TokenBucket, TokenType};
-#[cfg(not(test))]
-use std::io::Read;
-use std::io::Write;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Instant;
 use std::{cmp, io, mem, result};
 use utils::eventfd::EventFd;
 use utils::net::Tap;
 use virtio_gen::virtio_net::{
-    virtio_net_hdr_v1, VIRTIO_F_VERSION_1, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_GUEST_CSUM,
-    VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO,
-    VIRTIO_NET_F_MAC,
+    virtio_net_hdr_v1, VIRTIO_F_VERSION_1, VIRTIO_NET_ERR, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_CTRL_RX,
+    VIRTIO_NET_F_CTRL_VQ, VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_UFO,
+    VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC, VIRTIO_NET_F_MTU,
+    VIRTIO_NET_F_STATUS, VIRTIO_NET_OK, VIRTIO_NET_S_LINK_UP,
 };
 use vm_memory::{ByteValued, Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
 
@@ -57,14 +63,21 @@ fn init_vnet_hdr(buf: &mut [u8]) {
 }
 
 #[derive(Clone, Copy)]
+#[repr(C)]
 pub struct ConfigSpace {
     pub guest_mac: [u8; MAC_ADDR_LEN],
+    pub status: u16,
+    pub max_virtqueue_pairs: u16,
+    pub mtu: u16,
 }
 
 impl Default for ConfigSpace {
     fn default() -> ConfigSpace {
         ConfigSpace {
             guest_mac: [0; MAC_ADDR_LEN],
+            status: VIRTIO_NET_S_LINK_UP as u16,
+            max_virtqueue_pairs: 1,
+            mtu: 0,
         }
     }
 }
@@ -74,8 +87,10 @@ unsafe impl ByteValued for ConfigSpace {}
 pub struct Net {
     pub(crate) id: String,
 
-    pub(crate) tap: Tap,
+    pub(crate) backend: Box<dyn NetBackend>,
     pub(crate) tap_if_name: String,
+    // Whether the backend is a tap, which the device can be restored over.
+    tap_backed: bool,
 
     pub(crate) avail_features: u64,
     pub(crate) acked_features: u64,
@@ -86,8 +101,13 @@ pub struct Net {
     pub(crate) rx_rate_limiter: RateLimiter,
     pub(crate) tx_rate_limiter: RateLimiter,
 
+    pub(crate) rx_impairment: Impairment,
+    pub(crate) tx_impairment: Impairment,
+
     rx_deferred_frame: bool,
     rx_deferred_irqs: bool,
+    // Set when the frames left in the tap are to be received in the next round of the event loop.
+    rx_yielded: bool,
 
     rx_bytes_read: usize,
     rx_frame_buf: [u8; MAX_BUFFER_SIZE],
@@ -106,39 +126,113 @@ pub struct Net {
 
     pub(crate) mmds_ns: Option<MmdsNetworkStack>,
 
+    pub(crate) rx_filter: RxFilter,
+
+    // The number of frames handled each time a queue or the tap is processed, if limited.
+    pub(crate) dispatch_budget: Option<usize>,
+
     #[cfg(test)]
     test_mutators: tests::TestMutators,
 }
 
 impl Net {
-    /// Create a new virtio network device with the given TAP interface.
+    // Sets the TAP interface up to exchange the frames along with their virtio-net header.
+    fn configure_tap(tap: &Tap, mtu: Option<u16>) -> Result<()> {
+        // Set offload flags to match the virtio features of the device.
+        tap.set_offload(
+            net_gen::TUN_F_CSUM | net_gen::TUN_F_UFO | net_gen::TUN_F_TSO4 | net_gen::TUN_F_TSO6,
+        )
+        .map_err(Error::TapSetOffload)?;
+
+        let vnet_hdr_size = vnet_hdr_len() as i32;
+        tap.set_vnet_hdr_size(vnet_hdr_size)
+            .map_err(Error::TapSetVnetHdrSize)?;
+
+        if let Some(mtu) = mtu {
+            // Setting the MTU needs CAP_NET_ADMIN, which a jailed Firecracker lacks, so the TAP
+            // interfaces given the MTU on the host beforehand are left alone.
+            if tap.mtu().map_err(Error::TapSetMtu)? != i32::from(mtu) {
+                tap.set_mtu(i32::from(mtu)).map_err(Error::TapSetMtu)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Create a new virtio network device with the given TAP interface. If `mtu` is set, it is
+    /// the MTU of both the TAP interface and the guest interface.
     pub fn new_with_tap(
         id: String,
         tap_if_name: String,
         guest_mac: Option<&MacAddr>,
+        mtu: Option<u16>,
         rx_rate_limiter: RateLimiter,
         tx_rate_limiter: RateLimiter,
         allow_mmds_requests: bool,
     ) -> Result<Self> {
         let tap = Tap::open_named(&tap_if_name).map_err(Error::TapOpen)?;
-
-        // Set offload flags to match the virtio features below.
-        tap.set_offload(
-            net_gen::TUN_F_CSUM | net_gen::TUN_F_UFO | net_gen::TUN_F_TSO4 | net_gen::TUN_F_TSO6,
+        Self::new_with_opened_tap(
+            id,
+            tap,
+            guest_mac,
+            mtu,
+            rx_rate_limiter,
+            tx_rate_limiter,
+            allow_mmds_requests,
         )
-        .map_err(Error::TapSetOffload)?;
+    }
 
-        let vnet_hdr_size = vnet_hdr_len() as i32;
-        tap.set_vnet_hdr_size(vnet_hdr_size)
-            .map_err(Error::TapSetVnetHdrSize)?;
+    /// Create a new virtio network device with a TAP interface opened by the caller, e.g. with
+    /// `Tap::attach`. If `mtu` is set, it is the MTU of both the TAP interface and the guest
+    /// interface.
+    pub fn new_with_opened_tap(
+        id: String,
+        tap: Tap,
+        guest_mac: Option<&MacAddr>,
+        mtu: Option<u16>,
+        rx_rate_limiter: RateLimiter,
+        tx_rate_limiter: RateLimiter,
+        allow_mmds_requests: bool,
+    ) -> Result<Self> {
+        Self::configure_tap(&tap, mtu)?;
+        let mut net = Self::new_with_backend(
+            id,
+            tap.if_name(),
+            Box::new(tap),
+            guest_mac,
+            mtu,
+            rx_rate_limiter,
+            tx_rate_limiter,
+            allow_mmds_requests,
+        )?;
+        net.tap_backed = true;
+        Ok(net)
+    }
 
-        let mut avail_features = 1 << VIRTIO_NET_F_GUEST_CSUM
+    /// Create a new virtio network device exchanging the frames with `backend`, which is the host
+    /// interface `if_name`. If `mtu` is set, it is the MTU of the guest interface, which the
+    /// backend is expected to be set up for.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_backend(
+        id: String,
+        if_name: String,
+        backend: Box<dyn NetBackend>,
+        guest_mac: Option<&MacAddr>,
+        mtu: Option<u16>,
+        rx_rate_limiter: RateLimiter,
+        tx_rate_limiter: RateLimiter,
+        allow_mmds_requests: bool,
+    ) -> Result<Self> {
+        // The device completes the offloads of the transmitted frames the backend can't.
+        let mut avail_features = 1 << VIRTIO_NET_F_STATUS
+            | 1 << VIRTIO_F_VERSION_1
             | 1 << VIRTIO_NET_F_CSUM
-            | 1 << VIRTIO_NET_F_GUEST_TSO4
-            | 1 << VIRTIO_NET_F_GUEST_UFO
             | 1 << VIRTIO_NET_F_HOST_TSO4
-            | 1 << VIRTIO_NET_F_HOST_UFO
-            | 1 << VIRTIO_F_VERSION_1;
+            | 1 << VIRTIO_NET_F_HOST_UFO;
+        if backend.offloads() {
+            avail_features |= 1 << VIRTIO_NET_F_GUEST_CSUM
+                | 1 << VIRTIO_NET_F_GUEST_TSO4
+                | 1 << VIRTIO_NET_F_GUEST_UFO;
+        }
 
         let mut config_space = ConfigSpace::default();
         if let Some(mac) = guest_mac {
@@ -146,6 +240,14 @@ impl Net {
             // When this feature isn't available, the driver generates a random MAC address.
             // Otherwise, it should attempt to read the device MAC address from the config space.
             avail_features |= 1 << VIRTIO_NET_F_MAC;
+            // Filtering the received frames needs the primary MAC address of the guest, which
+            // the driver doesn't report.
+            avail_features |= 1 << VIRTIO_NET_F_CTRL_VQ | 1 << VIRTIO_NET_F_CTRL_RX;
+        }
+        if let Some(mtu) = mtu {
+            config_space.mtu = mtu;
+            // The driver then uses this MTU instead of the default 1500 bytes.
+            avail_features |= 1 << VIRTIO_NET_F_MTU;
         }
 
         let mut queue_evts = Vec::new();
@@ -162,16 +264,20 @@ impl Net {
         };
         Ok(Net {
             id,
-            tap,
-            tap_if_name,
+            backend,
+            tap_if_name: if_name,
+            tap_backed: false,
             avail_features,
             acked_features: 0u64,
             queues,
             queue_evts,
             rx_rate_limiter,
             tx_rate_limiter,
+            rx_impairment: Impairment::new().map_err(Error::ImpairmentTimer)?,
+            tx_impairment: Impairment::new().map_err(Error::ImpairmentTimer)?,
             rx_deferred_frame: false,
             rx_deferred_irqs: false,
+            rx_yielded: false,
             rx_bytes_read: 0,
             rx_frame_buf: [0u8; MAX_BUFFER_SIZE],
             tx_frame_buf: [0u8; MAX_BUFFER_SIZE],
@@ -182,7 +288,9 @@ impl Net {
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
             config_space,
             mmds_ns,
+            rx_filter: RxFilter::new(true),
             guest_mac: guest_mac.copied(),
+            dispatch_budget: None,
 
             #[cfg(test)]
             test_mutators: tests::TestMutators::default(),
@@ -199,16 +307,106 @@ impl Net {
         self.guest_mac.as_ref()
     }
 
+    /// Provides the MTU of this net device, if it was set.
+    pub fn mtu(&self) -> Option<u16> {
+        match self.config_space.mtu {
+            0 => None,
+            mtu => Some(mtu),
+        }
+    }
+
+    /// Provides the name of the host interface backing this net device.
+    pub fn tap_if_name(&self) -> &str {
+        &self.tap_if_name
+    }
+
+    /// Whether this net device is backed by a tap interface. Only such devices can be saved in a
+    /// snapshot, and restored.
+    pub fn is_tap_backed(&self) -> bool {
+        self.tap_backed
+    }
+
+    /// Provides the rate limiter of the receive path of this net device.
+    pub fn rx_rate_limiter(&self) -> &RateLimiter {
+        &self.rx_rate_limiter
+    }
+
+    /// Provides the rate limiter of the transmit path of this net device.
+    pub fn tx_rate_limiter(&self) -> &RateLimiter {
+        &self.tx_rate_limiter
+    }
+
+    /// Provides how the network path is degraded for the frames received by the guest.
+    pub fn rx_impairment(&self) -> &ImpairmentParams {
+        self.rx_impairment.params()
+    }
+
+    /// Provides how the network path is degraded for the frames transmitted by the guest.
+    pub fn tx_impairment(&self) -> &ImpairmentParams {
+        self.tx_impairment.params()
+    }
+
     /// Provides a mutable reference to the `MmdsNetworkStack`.
     pub fn mmds_ns_mut(&mut self) -> Option<&mut MmdsNetworkStack> {
         self.mmds_ns.as_mut()
     }
 
+    /// Specifies if the link of this net device is up.
+    pub fn link_up(&self) -> bool {
+        self.config_space.status & VIRTIO_NET_S_LINK_UP as u16 != 0
+    }
+
+    /// Brings the link of this net device up or down, and lets the guest driver know about it.
+    /// While the link is down, the frames sent or received by the guest are dropped, as if the
+    /// cable was pulled.
+    pub fn set_link_up(&mut self, up: bool) -> result::Result<(), DeviceError> {
+        if up == self.link_up() {
+            return Ok(());
+        }
+        if up {
+            self.config_space.status |= VIRTIO_NET_S_LINK_UP as u16;
+        } else {
+            self.config_space.status &= !(VIRTIO_NET_S_LINK_UP as u16);
+        }
+        if self.is_activated() {
+            self.signal(VIRTIO_MMIO_INT_CONFIG)?;
+        }
+        Ok(())
+    }
+
+    /// Provides the receive filter the guest driver set up.
+    pub fn rx_filter(&self) -> &RxFilter {
+        &self.rx_filter
+    }
+
+    /// Allows or denies the guest to turn the promiscuous mode of this net device on. Denying it
+    /// also turns the promiscuous mode off.
+    pub fn set_promisc_allowed(&mut self, allowed: bool) {
+        self.rx_filter.allow_promisc = allowed;
+        self.rx_filter.promisc &= allowed;
+    }
+
+    // Specifies if the guest receives the frame in `self.rx_frame_buf`. The frames are only
+    // filtered once the driver negotiated the control of the filter.
+    fn rx_filter_accepts(&self) -> bool {
+        self.acked_features & (1 << VIRTIO_NET_F_CTRL_RX) == 0
+            || self.rx_filter.accepts(
+                self.rx_frame_buf[..self.rx_bytes_read]
+                    .get(vnet_hdr_len()..)
+                    .unwrap_or(&[]),
+                self.guest_mac.as_ref(),
+            )
+    }
+
     fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
+        self.signal(VIRTIO_MMIO_INT_VRING)
+    }
+
+    fn signal(&self, int_type: u32) -> result::Result<(), DeviceError> {
         self.interrupt_status
-            .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+            .fetch_or(int_type as usize, Ordering::SeqCst);
         self.interrupt_evt.write(1).map_err(|e| {
-            error!("Failed to signal used queue: {:?}", e);
+            error!("Failed to signal net interrupt: {:?}", e);
             METRICS.net.event_fails.inc();
             DeviceError::FailedSignalingUsedQueue(e)
         })
@@ -322,15 +520,17 @@ impl Net {
         }
     }
 
-    // Tries to detour the frame to MMDS and if MMDS doesn't accept it, sends it on the host TAP.
+    // Tries to detour the frame to MMDS and if MMDS doesn't accept it, sends it to the backend,
+    // unless `impairment` loses it or holds it back.
     //
     // `frame_buf` should contain the frame bytes in a slice of exact length.
     // Returns whether MMDS consumed the frame.
     fn write_to_mmds_or_tap(
         mmds_ns: Option<&mut MmdsNetworkStack>,
         rate_limiter: &mut RateLimiter,
+        impairment: &mut Impairment,
         frame_buf: &[u8],
-        tap: &mut Tap,
+        backend: &mut dyn NetBackend,
         guest_mac: Option<MacAddr>,
     ) -> bool {
         if let Some(ns) = mmds_ns {
@@ -346,7 +546,7 @@ impl Net {
             }
         }
 
-        // This frame goes to the TAP.
+        // This frame goes to the backend.
 
         // Check for guest MAC spoofing.
         if let Some(mac) = guest_mac {
@@ -358,7 +558,33 @@ impl Net {
             });
         }
 
-        let write_result = tap.write(frame_buf);
+        if impairment.impair(frame_buf, Instant::now()) {
+            Self::write_to_backend(frame_buf, backend);
+        }
+        false
+    }
+
+    fn write_to_backend(frame_buf: &[u8], backend: &mut dyn NetBackend) {
+        if backend.offloads() || !needs_offloads(frame_buf) {
+            Self::write_frame_to_backend(frame_buf, backend);
+            return;
+        }
+        // The offloads are completed in place, so the frame is copied out of the shared buffer.
+        let mut buf = frame_buf.to_vec();
+        let mut frame_buf = vec![0u8; vnet_hdr_len()];
+        let result = complete_offloads(&mut buf, |frame| {
+            frame_buf.truncate(vnet_hdr_len());
+            frame_buf.extend_from_slice(frame);
+            Self::write_frame_to_backend(&frame_buf, backend);
+        });
+        if let Err(e) = result {
+            error!("Failed to complete the offloads of a frame: {}", e);
+            METRICS.net.tx_fails.inc();
+        }
+    }
+
+    fn write_frame_to_backend(frame_buf: &[u8], backend: &mut dyn NetBackend) {
+        let write_result = backend.write_frame(frame_buf);
         match write_result {
             Ok(_) => {
                 METRICS.net.tx_bytes_count.add(frame_buf.len());
@@ -370,11 +596,23 @@ impl Net {
                 METRICS.net.tx_fails.inc();
             }
         };
-        false
     }
 
-    // We currently prioritize packets from the MMDS over regular network packets.
-    fn read_from_mmds_or_tap(&mut self) -> io::Result<usize> {
+    // Sends the transmitted frames held back by the impairment which are due.
+    fn release_tx_frames(&mut self) {
+        while let Some(frame) = self.tx_impairment.release(Instant::now()) {
+            if self.link_up() {
+                Self::write_to_backend(&frame, self.backend.as_mut());
+            } else {
+                METRICS.net.link_down_drops.inc();
+            }
+        }
+    }
+
+    // We currently prioritize packets from the MMDS over regular network packets, and the
+    // frames held back by the impairment which are due over the ones left in the tap. Returns
+    // `None` when the frame read from the tap was lost or held back by the impairment.
+    fn read_from_mmds_or_tap(&mut self) -> io::Result<Option<usize>> {
         if let Some(ns) = self.mmds_ns.as_mut() {
             if let Some(len) = ns.write_next_frame(frame_bytes_from_buf_mut(&mut self.rx_frame_buf))
             {
@@ -382,20 +620,53 @@ impl Net {
                 METRICS.mmds.tx_frames.inc();
                 METRICS.mmds.tx_bytes.add(len);
                 init_vnet_hdr(&mut self.rx_frame_buf);
-                return Ok(vnet_hdr_len() + len);
+                return Ok(Some(vnet_hdr_len() + len));
             }
         }
 
-        self.read_tap()
+        let now = Instant::now();
+        if let Some(frame) = self.rx_impairment.release(now) {
+            self.rx_frame_buf[..frame.len()].copy_from_slice(&frame);
+            return Ok(Some(frame.len()));
+        }
+        let len = self.read_tap()?;
+        if self.rx_impairment.impair(&self.rx_frame_buf[..len], now) {
+            Ok(Some(len))
+        } else {
+            Ok(None)
+        }
     }
 
     fn process_rx(&mut self) -> result::Result<(), DeviceError> {
+        self.rx_yielded = false;
+        let mut frames = 0;
         // Read as many frames as possible.
         loop {
+            // Let the other devices sharing the event loop get their turn. The tap is edge
+            // triggered, so the RX queue event brings the device back to the frames left.
+            if Some(frames) == self.dispatch_budget {
+                self.rx_yielded = true;
+                METRICS.net.rx_budget_yield_count.inc();
+                if let Err(e) = self.queue_evts[RX_INDEX].write(1) {
+                    error!("Failed to trigger the rx queue event: {:?}", e);
+                    METRICS.net.event_fails.inc();
+                }
+                break;
+            }
             match self.read_from_mmds_or_tap() {
-                Ok(count) => {
+                Ok(None) => frames += 1,
+                Ok(Some(count)) => {
+                    frames += 1;
                     self.rx_bytes_read = count;
                     METRICS.net.rx_count.inc();
+                    if !self.link_up() {
+                        METRICS.net.link_down_drops.inc();
+                        continue;
+                    }
+                    if !self.rx_filter_accepts() {
+                        METRICS.net.rx_filtered_drops.inc();
+                        continue;
+                    }
                     if !self.rate_limited_rx_single_frame() {
                         self.rx_deferred_frame = true;
                         break;
@@ -438,6 +709,8 @@ impl Net {
             } else {
                 Ok(())
             }
+        } else if self.rx_yielded {
+            self.process_rx()
         } else {
             Ok(())
         }
@@ -455,10 +728,17 @@ impl Net {
         // trigger a process_rx() which checks if there are any new frames to be sent, starting
         // with the MMDS network stack.
         let mut process_rx_for_mmds = false;
-        let mut raise_irq = false;
+        let mut sent = 0;
+        let mut out_of_budget = false;
         let tx_queue = &mut self.queues[TX_INDEX];
 
         while let Some(head) = tx_queue.pop(mem) {
+            // Let the other devices sharing the event loop get their turn.
+            if Some(sent) == self.dispatch_budget {
+                tx_queue.undo_pop();
+                out_of_budget = true;
+                break;
+            }
             // If limiter.consume() fails it means there is no more TokenType::Ops
             // budget and rate limiting is in effect.
             if !self.tx_rate_limiter.consume(1, TokenType::Ops) {
@@ -523,11 +803,14 @@ impl Net {
                 }
             }
 
-            if Self::write_to_mmds_or_tap(
+            if !self.link_up() {
+                METRICS.net.link_down_drops.inc();
+            } else if Self::write_to_mmds_or_tap(
                 self.mmds_ns.as_mut(),
                 &mut self.tx_rate_limiter,
+                &mut self.tx_impairment,
                 &self.tx_frame_buf[..read_count],
-                &mut self.tap,
+                self.backend.as_mut(),
                 self.guest_mac,
             ) && !self.rx_deferred_frame
             {
@@ -536,10 +819,22 @@ impl Net {
             }
 
             tx_queue.add_used(mem, head_index, 0);
-            raise_irq = true;
+            sent += 1;
+        }
+
+        // The duplicates of the frames sent are due right away.
+        self.release_tx_frames();
+
+        if out_of_budget {
+            METRICS.net.tx_budget_yield_count.inc();
+            // Get back to the remaining frames once the other devices have been served.
+            if let Err(e) = self.queue_evts[TX_INDEX].write(1) {
+                error!("Failed to trigger the tx queue event: {:?}", e);
+                METRICS.net.event_fails.inc();
+            }
         }
 
-        if raise_irq {
+        if sent > 0 {
             self.signal_used_queue()?;
         } else {
             METRICS.net.no_tx_avail_buffer.inc();
@@ -553,6 +848,79 @@ impl Net {
         }
     }
 
+    // Applies the commands of the control queue to the receive filter, and acks them.
+    fn process_ctrl(&mut self) -> result::Result<(), DeviceError> {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem,
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        let ctrl_queue = &mut self.queues[CTRL_INDEX];
+        let mut used_any = false;
+
+        while let Some(head) = ctrl_queue.pop(mem) {
+            let head_index = head.index;
+            // The command is copied out of the readable descriptors, and acked in the first
+            // writable one.
+            let mut command = Vec::new();
+            let mut ack_addr = None;
+            let mut next_desc = Some(head);
+            while let Some(desc) = next_desc {
+                if desc.is_write_only() {
+                    ack_addr = Some(desc.addr);
+                    break;
+                }
+                let start = command.len();
+                if start + desc.len as usize > MAX_CTRL_COMMAND_SIZE {
+                    command.clear();
+                    break;
+                }
+                command.resize(start + desc.len as usize, 0);
+                if let Err(e) = mem.read_slice(&mut command[start..], desc.addr) {
+                    error!("Failed to read control command: {:?}", e);
+                    command.clear();
+                    break;
+                }
+                next_desc = desc.next_descriptor();
+            }
+
+            METRICS.net.ctrl_count.inc();
+            // The command starts with its class and its code.
+            let result = if command.len() >= 2 {
+                self.rx_filter
+                    .handle_command(command[0], command[1], &command[2..])
+            } else {
+                Err(RxFilterError::MalformedCommand)
+            };
+            let ack = match result {
+                Ok(()) => VIRTIO_NET_OK as u8,
+                Err(e) => {
+                    warn!("Net: control command failed: {}", e);
+                    METRICS.net.ctrl_fails.inc();
+                    VIRTIO_NET_ERR as u8
+                }
+            };
+            let len = match ack_addr {
+                Some(addr) => match mem.write_obj(ack, addr) {
+                    Ok(()) => 1,
+                    Err(e) => {
+                        error!("Failed to ack control command: {:?}", e);
+                        0
+                    }
+                },
+                None => 0,
+            };
+            ctrl_queue.add_used(mem, head_index, len);
+            used_any = true;
+        }
+
+        if used_any {
+            self.signal_used_queue()
+        } else {
+            Ok(())
+        }
+    }
+
     /// Updates the parameters for the rate limiters
     pub fn patch_rate_limiters(
         &mut self,
@@ -565,9 +933,19 @@ impl Net {
         self.tx_rate_limiter.update_buckets(tx_bytes, tx_ops);
     }
 
+    /// Changes how the network path is degraded, in each direction given.
+    pub fn set_impairments(&mut self, rx: Option<ImpairmentParams>, tx: Option<ImpairmentParams>) {
+        if let Some(rx) = rx {
+            self.rx_impairment.set_params(rx);
+        }
+        if let Some(tx) = tx {
+            self.tx_impairment.set_params(tx);
+        }
+    }
+
     #[cfg(not(test))]
     fn read_tap(&mut self) -> io::Result<usize> {
-        self.tap.read(&mut self.rx_frame_buf)
+        self.backend.read_frame(&mut self.rx_frame_buf)
     }
 
     pub fn process_rx_queue_event(&mut self) {
@@ -631,6 +1009,48 @@ impl Net {
         }
     }
 
+    pub fn process_ctrl_queue_event(&mut self) {
+        if let Err(e) = self.queue_evts[CTRL_INDEX].read() {
+            error!("Failed to get control queue event: {:?}", e);
+            METRICS.net.event_fails.inc();
+        } else {
+            self.process_ctrl().unwrap_or_else(report_net_event_fail);
+        }
+    }
+
+    /// Transmits the frames the driver made available, without waiting for the TX queue event.
+    pub fn poll_tx_queue(&mut self) -> bool {
+        let has_frames = match self.device_state {
+            DeviceState::Activated(ref mem) => !self.queues[TX_INDEX].is_empty(mem),
+            DeviceState::Inactive => false,
+        };
+        if !has_frames || self.tx_rate_limiter.is_blocked() {
+            return false;
+        }
+        self.process_tx().unwrap_or_else(report_net_event_fail);
+        true
+    }
+
+    pub fn process_rx_impairment_event(&mut self) {
+        self.rx_impairment.read_timer();
+        // The frames held back are received like the ones from the tap, once the frame the
+        // guest had no room for is received.
+        if self.rx_rate_limiter.is_blocked() {
+            return;
+        }
+        if self.rx_deferred_frame {
+            self.resume_rx()
+        } else {
+            self.process_rx()
+        }
+        .unwrap_or_else(report_net_event_fail);
+    }
+
+    pub fn process_tx_impairment_event(&mut self) {
+        self.tx_impairment.read_timer();
+        self.release_tx_frames();
+    }
+
     pub fn process_rx_rate_limiter_event(&mut self) {
         METRICS.net.rx_event_rate_limiter_count.inc();
         // Upon rate limiter event, call the rate limiter handler
@@ -719,11 +1139,43 @@ impl VirtioDevice for Net {
         }
     }
 
+    fn config_space_bytes(&self) -> Vec<u8> {
+        self.config_space.as_slice().to_vec()
+    }
+
+    fn debug_state(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("tap_if_name", self.tap_if_name().to_string()),
+            ("link_up", self.link_up().to_string()),
+            ("promisc", self.rx_filter.promisc().to_string()),
+            ("rx_deferred_frame", self.rx_deferred_frame.to_string()),
+            ("rx_deferred_irqs", self.rx_deferred_irqs.to_string()),
+            ("rx_yielded", self.rx_yielded.to_string()),
+            ("rx_bytes_read", self.rx_bytes_read.to_string()),
+            (
+                "rx_rate_limiter_blocked",
+                self.rx_rate_limiter.is_blocked().to_string(),
+            ),
+            (
+                "tx_rate_limiter_blocked",
+                self.tx_rate_limiter.is_blocked().to_string(),
+            ),
+            (
+                "rx_delayed_frames",
+                self.rx_impairment.delayed_frames().to_string(),
+            ),
+            (
+                "tx_delayed_frames",
+                self.tx_impairment.delayed_frames().to_string(),
+            ),
+        ]
+    }
+
     fn write_config(&mut self, offset: u64, data: &[u8]) {
         let data_len = data.len() as u64;
         let config_space_bytes = self.config_space.as_mut_slice();
-        let config_len = config_space_bytes.len() as u64;
-        if offset + data_len > config_len {
+        // Only the MAC address is writable, the link status is read-only for the driver.
+        if offset + data_len > MAC_ADDR_LEN as u64 {
             error!("Failed to write config space");
             METRICS.net.cfg_fails.inc();
             return;
@@ -751,6 +1203,17 @@ impl VirtioDevice for Net {
         self.device_state = DeviceState::Activated(mem);
         Ok(())
     }
+
+    fn reset(&mut self) -> bool {
+        // A frame deferred for lack of RX buffers is kept, and delivered once the driver provides
+        // new ones: the tap is edge triggered, so it won't signal the frames behind it again.
+        self.device_state = DeviceState::Inactive;
+        self.acked_features = 0;
+        self.rx_deferred_irqs = false;
+        // The driver of the next kernel sets up its own filter.
+        self.rx_filter = RxFilter::new(self.rx_filter.allow_promisc);
+        true
+    }
 }
 
 #[cfg(test)]
@@ -771,7 +1234,7 @@ pub(crate) mod tests {
     use crate::virtio::queue::tests::VirtQueue;
     use crate::virtio::{
         Net, Queue, VirtioDevice, MAX_BUFFER_SIZE, RX_INDEX, TX_INDEX, TYPE_NET,
-        VIRTIO_MMIO_INT_VRING, VIRTQ_DESC_F_WRITE,
+        VIRTIO_MMIO_INT_CONFIG, VIRTIO_MMIO_INT_VRING, VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE,
     };
     use dumbo::{
         EthIPv4ArpFrame, EthernetFrame, MacAddr, ETHERTYPE_ARP, ETH_IPV4_FRAME_LEN, MAC_ADDR_LEN,
@@ -781,9 +1244,11 @@ pub(crate) mod tests {
     use rate_limiter::{RateLimiter, TokenBucket, TokenType};
     use utils::epoll::{EpollEvent, EventSet};
     use virtio_gen::virtio_net::{
-        virtio_net_hdr_v1, VIRTIO_F_VERSION_1, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_GUEST_CSUM,
-        VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_TSO4,
-        VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC,
+        virtio_net_hdr_v1, VIRTIO_F_VERSION_1, VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_TABLE_SET,
+        VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_PROMISC, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_CTRL_RX,
+        VIRTIO_NET_F_CTRL_VQ, VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_TSO4,
+        VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC,
+        VIRTIO_NET_F_MTU, VIRTIO_NET_F_STATUS, VIRTIO_NET_HDR_F_NEEDS_CSUM,
     };
 
     static NEXT_INDEX: AtomicUsize = AtomicUsize::new(1);
@@ -816,16 +1281,21 @@ pub(crate) mod tests {
 
             let guest_mac = Net::default_guest_mac();
 
-            let mut net = Net::new_with_tap(
+            let tap = Tap::open_named(&tap_dev_name).unwrap();
+            Net::configure_tap(&tap, None).unwrap();
+            tap.enable().unwrap();
+            let mut net = Net::new_with_backend(
                 format!("net-device{}", next_tap),
                 tap_dev_name.clone(),
+                Box::new(tap),
                 Some(&guest_mac),
+                None,
                 RateLimiter::default(),
                 RateLimiter::default(),
                 true,
             )
             .unwrap();
-            net.tap.enable().unwrap();
+            net.tap_backed = true;
             net.test_mutators = test_mutators;
 
             net
@@ -864,9 +1334,8 @@ pub(crate) mod tests {
 
         // Assigns "guest virtio driver" activated queues to the net device.
         pub fn assign_queues(&mut self, rxq: Queue, txq: Queue) {
-            self.queues.clear();
-            self.queues.push(rxq);
-            self.queues.push(txq);
+            self.queues[RX_INDEX] = rxq;
+            self.queues[TX_INDEX] = txq;
         }
     }
 
@@ -933,6 +1402,9 @@ pub(crate) mod tests {
             | 1 << VIRTIO_NET_F_GUEST_UFO
             | 1 << VIRTIO_NET_F_HOST_TSO4
             | 1 << VIRTIO_NET_F_HOST_UFO
+            | 1 << VIRTIO_NET_F_STATUS
+            | 1 << VIRTIO_NET_F_CTRL_VQ
+            | 1 << VIRTIO_NET_F_CTRL_RX
             | 1 << VIRTIO_F_VERSION_1;
 
         assert_eq!(net.avail_features_by_page(0), features as u32);
@@ -988,6 +1460,172 @@ pub(crate) mod tests {
         assert_eq!(new_config, new_config_read);
     }
 
+    #[test]
+    fn test_ctrl_queue() {
+        let mut event_manager = EventManager::new().unwrap();
+        let mut net = Net::default_net(TestMutators::default());
+        let mem = Net::default_guest_memory();
+        let (rxq, txq) = Net::virtqueues(&mem);
+        let ctrlq = VirtQueue::new(GuestAddress(0x2000), &mem, 16);
+        net.assign_queues(rxq.create_queue(), txq.create_queue());
+        net.queues[CTRL_INDEX] = ctrlq.create_queue();
+        net.ack_features_by_page(0, u32::MAX);
+        net.activate(mem.clone()).unwrap();
+
+        let command_addr = 0x4000;
+        let ack_addr = 0x5000;
+        let ctrl_event =
+            EpollEvent::new(EventSet::IN, net.queue_evts[CTRL_INDEX].as_raw_fd() as u64);
+        let mut commands = 0;
+        // Sends the command to the device, and returns its ack.
+        let mut send = |net: &mut Net, command: &[u8]| {
+            mem.write_slice(command, GuestAddress(command_addr))
+                .unwrap();
+            ctrlq.dtable[0].set(command_addr, command.len() as u32, VIRTQ_DESC_F_NEXT, 1);
+            ctrlq.dtable[1].set(ack_addr, 1, VIRTQ_DESC_F_WRITE, 0);
+            ctrlq.avail.ring[commands % 16].set(0);
+            commands += 1;
+            ctrlq.avail.idx.set(commands as u16);
+            net.queue_evts[CTRL_INDEX].write(1).unwrap();
+            net.process(&ctrl_event, &mut event_manager);
+            assert_eq!(ctrlq.used.idx.get(), commands as u16);
+            assert_eq!(ctrlq.used.ring[(commands - 1) % 16].get().len, 1);
+            assert_eq!(net.interrupt_evt.read().unwrap(), 1);
+            mem.read_obj::<u8>(GuestAddress(ack_addr)).unwrap()
+        };
+
+        // Frames to the primary MAC address pass the filter, the others are dropped once the
+        // promiscuous mode is off.
+        net.rx_bytes_read = 1234;
+        net.rx_frame_buf[vnet_hdr_len()..vnet_hdr_len() + MAC_ADDR_LEN].copy_from_slice(&[5; 6]);
+        assert!(net.rx_filter_accepts());
+        let promisc_off = [
+            VIRTIO_NET_CTRL_RX as u8,
+            VIRTIO_NET_CTRL_RX_PROMISC as u8,
+            0,
+        ];
+        assert_eq!(send(&mut net, &promisc_off), VIRTIO_NET_OK as u8);
+        assert!(!net.rx_filter().promisc());
+        assert!(!net.rx_filter_accepts());
+        net.rx_frame_buf[vnet_hdr_len()..vnet_hdr_len() + MAC_ADDR_LEN]
+            .copy_from_slice(Net::default_guest_mac().get_bytes());
+        assert!(net.rx_filter_accepts());
+
+        // The MAC table lets more addresses through.
+        let mut mac_table_set = vec![
+            VIRTIO_NET_CTRL_MAC as u8,
+            VIRTIO_NET_CTRL_MAC_TABLE_SET as u8,
+        ];
+        mac_table_set.extend_from_slice(&[1, 0, 0, 0, 5, 5, 5, 5, 5, 5, 0, 0, 0, 0]);
+        assert_eq!(send(&mut net, &mac_table_set), VIRTIO_NET_OK as u8);
+        net.rx_frame_buf[vnet_hdr_len()..vnet_hdr_len() + MAC_ADDR_LEN].copy_from_slice(&[5; 6]);
+        assert!(net.rx_filter_accepts());
+
+        // The denied and malformed commands are acked with an error.
+        net.set_promisc_allowed(false);
+        let promisc_on = [
+            VIRTIO_NET_CTRL_RX as u8,
+            VIRTIO_NET_CTRL_RX_PROMISC as u8,
+            1,
+        ];
+        check_metric_after_block!(
+            &METRICS.net.ctrl_fails,
+            1,
+            assert_eq!(send(&mut net, &promisc_on), VIRTIO_NET_ERR as u8)
+        );
+        assert!(!net.rx_filter().promisc());
+        assert_eq!(send(&mut net, &[0]), VIRTIO_NET_ERR as u8);
+
+        // The filter is only applied if the driver negotiated it, and is reset along with the
+        // device.
+        net.acked_features &= !(1 << VIRTIO_NET_F_CTRL_RX);
+        net.rx_frame_buf[vnet_hdr_len()..vnet_hdr_len() + MAC_ADDR_LEN].copy_from_slice(&[7; 6]);
+        assert!(net.rx_filter_accepts());
+        assert!(net.reset());
+        assert_eq!(net.rx_filter(), &RxFilter::new(false));
+    }
+
+    #[test]
+    fn test_mtu() {
+        let next_tap = NEXT_INDEX.fetch_add(1, Ordering::SeqCst);
+        let mut net = Net::new_with_tap(
+            format!("net-device{}", next_tap),
+            format!("net-device{}", next_tap),
+            None,
+            Some(9000),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            false,
+        )
+        .unwrap();
+        assert_eq!(net.mtu(), Some(9000));
+        assert_ne!(net.avail_features() & (1 << VIRTIO_NET_F_MTU), 0);
+
+        // The MTU follows the MAC address, the link status and the number of queue pairs.
+        let mut mtu = [0u8; 2];
+        net.read_config(MAC_ADDR_LEN as u64 + 4, &mut mtu);
+        assert_eq!(u16::from_le_bytes(mtu), 9000);
+        // The MTU is read-only for the driver.
+        net.write_config(MAC_ADDR_LEN as u64 + 4, &[0, 0]);
+        assert_eq!(net.mtu(), Some(9000));
+
+        let net = Net::default_net(TestMutators::default());
+        assert_eq!(net.mtu(), None);
+        assert_eq!(net.avail_features() & (1 << VIRTIO_NET_F_MTU), 0);
+    }
+
+    #[test]
+    fn test_link_state() {
+        let mut event_manager = EventManager::new().unwrap();
+        let mut net = Net::default_net(TestMutators::default());
+        let mem = Net::default_guest_memory();
+        let (rxq, txq) = Net::virtqueues(&mem);
+        net.assign_queues(rxq.create_queue(), txq.create_queue());
+
+        // The link is up by default.
+        let mut status = [0u8; 2];
+        net.read_config(MAC_ADDR_LEN as u64, &mut status);
+        assert_eq!(status, [1, 0]);
+        assert!(net.link_up());
+
+        // The status is read-only for the driver.
+        net.write_config(MAC_ADDR_LEN as u64, &[0, 0]);
+        assert!(net.link_up());
+
+        // The driver is only notified once the device is activated.
+        net.set_link_up(false).unwrap();
+        assert!(!net.link_up());
+        net.read_config(MAC_ADDR_LEN as u64, &mut status);
+        assert_eq!(status, [0, 0]);
+        assert!(net.interrupt_evt.read().is_err());
+
+        net.activate(mem.clone()).unwrap();
+        net.set_link_up(true).unwrap();
+        assert!(net.link_up());
+        assert_eq!(net.interrupt_evt.read().unwrap(), 1);
+        assert_eq!(
+            net.interrupt_status.load(Ordering::SeqCst),
+            VIRTIO_MMIO_INT_CONFIG as usize
+        );
+        // Setting the same state again is a no-op.
+        net.set_link_up(true).unwrap();
+        assert!(net.interrupt_evt.read().is_err());
+
+        // The frames sent by the guest while the link is down are dropped.
+        net.set_link_up(false).unwrap();
+        txq.avail.idx.set(1);
+        txq.avail.ring[0].set(0);
+        txq.dtable[0].set(0x2000, 0x100, 0, 0);
+        net.queue_evts[TX_INDEX].write(1).unwrap();
+        let tx_event = EpollEvent::new(EventSet::IN, net.queue_evts[TX_INDEX].as_raw_fd() as u64);
+        check_metric_after_block!(
+            &METRICS.net.link_down_drops,
+            1,
+            net.process(&tx_event, &mut event_manager)
+        );
+        assert_eq!(txq.used.idx.get(), 1);
+    }
+
     #[test]
     fn test_event_processing() {
         let mut event_manager = EventManager::new().unwrap();
@@ -1068,7 +1706,7 @@ pub(crate) mod tests {
             rxq.dtable[0].set(daddr, 0x1000, VIRTQ_DESC_F_WRITE, 0);
 
             net.interrupt_evt.write(1).unwrap();
-            let tap_event = EpollEvent::new(EventSet::IN, net.tap.as_raw_fd() as u64);
+            let tap_event = EpollEvent::new(EventSet::IN, net.backend.as_raw_fd() as u64);
             net.process(&tap_event, &mut event_manager);
             assert!(net.rx_deferred_frame);
             assert_eq!(net.interrupt_evt.read().unwrap(), 3);
@@ -1188,8 +1826,9 @@ pub(crate) mod tests {
             assert!(Net::write_to_mmds_or_tap(
                 net.mmds_ns.as_mut(),
                 &mut net.tx_rate_limiter,
+                &mut net.tx_impairment,
                 &net.tx_frame_buf[..packet_len],
-                &mut net.tap,
+                net.backend.as_mut(),
                 Some(sha),
             ))
         );
@@ -1202,6 +1841,113 @@ pub(crate) mod tests {
         );
     }
 
+    #[test]
+    fn test_custom_backend() {
+        use std::os::unix::io::RawFd;
+        use std::os::unix::net::UnixDatagram;
+
+        struct DatagramBackend(UnixDatagram);
+
+        impl AsRawFd for DatagramBackend {
+            fn as_raw_fd(&self) -> RawFd {
+                self.0.as_raw_fd()
+            }
+        }
+
+        impl NetBackend for DatagramBackend {
+            fn read_frame(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.0.recv(buf)
+            }
+
+            fn write_frame(&mut self, buf: &[u8]) -> io::Result<()> {
+                self.0.send(buf).map(|_| ())
+            }
+        }
+
+        let (local, peer) = UnixDatagram::pair().unwrap();
+        let mut net = Net::new_with_backend(
+            "net-custom".to_string(),
+            "custom0".to_string(),
+            Box::new(DatagramBackend(local)),
+            None,
+            None,
+            RateLimiter::default(),
+            RateLimiter::default(),
+            false,
+        )
+        .unwrap();
+        assert_eq!(net.tap_if_name(), "custom0");
+        assert!(!net.is_tap_backed());
+        // The backend takes complete frames only, so the device completes the offloads.
+        assert_eq!(
+            net.avail_features(),
+            1 << VIRTIO_NET_F_STATUS
+                | 1 << VIRTIO_F_VERSION_1
+                | 1 << VIRTIO_NET_F_CSUM
+                | 1 << VIRTIO_NET_F_HOST_TSO4
+                | 1 << VIRTIO_NET_F_HOST_UFO
+        );
+
+        let mut frame = [7u8; 100];
+        init_vnet_hdr(&mut frame);
+        check_metric_after_block!(
+            &METRICS.net.tx_packets_count,
+            1,
+            Net::write_to_mmds_or_tap(
+                net.mmds_ns.as_mut(),
+                &mut net.tx_rate_limiter,
+                &mut net.tx_impairment,
+                &frame,
+                net.backend.as_mut(),
+                None,
+            )
+        );
+        let mut buf = [0u8; MAX_BUFFER_SIZE];
+        assert_eq!(peer.recv(&mut buf).unwrap(), frame.len());
+        assert_eq!(&buf[..frame.len()], &frame[..]);
+
+        // The partial checksum of the frame is completed before it reaches the backend, which
+        // gets an empty header.
+        frame[0] = VIRTIO_NET_HDR_F_NEEDS_CSUM as u8;
+        frame[6..8].copy_from_slice(&80u16.to_le_bytes());
+        frame[8..10].copy_from_slice(&6u16.to_le_bytes());
+        Net::write_to_mmds_or_tap(
+            net.mmds_ns.as_mut(),
+            &mut net.tx_rate_limiter,
+            &mut net.tx_impairment,
+            &frame,
+            net.backend.as_mut(),
+            None,
+        );
+        assert_eq!(peer.recv(&mut buf).unwrap(), frame.len());
+        assert_eq!(&buf[..vnet_hdr_len()], &vec![0u8; vnet_hdr_len()][..]);
+        let checksum_at = vnet_hdr_len() + 86;
+        assert_eq!(
+            &buf[vnet_hdr_len()..checksum_at],
+            &frame[vnet_hdr_len()..checksum_at]
+        );
+        assert_eq!(&buf[checksum_at..frame.len()], &[0xe3, 0xe3][..]);
+
+        // The frames whose offloads can't be completed are dropped.
+        frame[1] = 0xff;
+        check_metric_after_block!(
+            &METRICS.net.tx_fails,
+            1,
+            Net::write_to_mmds_or_tap(
+                net.mmds_ns.as_mut(),
+                &mut net.tx_rate_limiter,
+                &mut net.tx_impairment,
+                &frame,
+                net.backend.as_mut(),
+                None,
+            )
+        );
+
+        peer.send(&[9u8; 60]).unwrap();
+        assert_eq!(net.backend.read_frame(&mut buf).unwrap(), 60);
+        assert_eq!(&buf[..60], &[9u8; 60][..]);
+    }
+
     #[test]
     fn test_mac_spoofing_detection() {
         let mut net = Net::default_net(TestMutators::default());
@@ -1248,8 +1994,9 @@ pub(crate) mod tests {
             Net::write_to_mmds_or_tap(
                 net.mmds_ns.as_mut(),
                 &mut net.tx_rate_limiter,
+                &mut net.tx_impairment,
                 &net.tx_frame_buf[..packet_len],
-                &mut net.tap,
+                net.backend.as_mut(),
                 Some(guest_mac),
             )
         );
@@ -1261,8 +2008,9 @@ pub(crate) mod tests {
             Net::write_to_mmds_or_tap(
                 net.mmds_ns.as_mut(),
                 &mut net.tx_rate_limiter,
+                &mut net.tx_impairment,
                 &net.tx_frame_buf[..packet_len],
-                &mut net.tap,
+                net.backend.as_mut(),
                 Some(not_guest_mac),
             )
         );
@@ -1343,7 +2091,7 @@ pub(crate) mod tests {
         net.activate(mem.clone()).unwrap();
 
         // The RX queue is empty.
-        let tap_event = EpollEvent::new(EventSet::IN, net.tap.as_raw_fd() as u64);
+        let tap_event = EpollEvent::new(EventSet::IN, net.backend.as_raw_fd() as u64);
         check_metric_after_block!(
             &METRICS.net.no_rx_avail_buffer,
             1,
@@ -1481,7 +2229,7 @@ pub(crate) mod tests {
                 // leave at least one event here so that reading it later won't block
                 net.interrupt_evt.write(1).unwrap();
                 // trigger the RX handler
-                let rx_event = EpollEvent::new(EventSet::IN, net.tap.as_raw_fd() as u64);
+                let rx_event = EpollEvent::new(EventSet::IN, net.backend.as_raw_fd() as u64);
                 net.process(&rx_event, &mut event_manager);
 
                 // assert that limiter is blocked
@@ -1595,7 +2343,7 @@ pub(crate) mod tests {
                 // leave at least one event here so that reading it later won't block
                 net.interrupt_evt.write(1).unwrap();
                 // trigger the RX handler
-                let rx_event = EpollEvent::new(EventSet::IN, net.tap.as_raw_fd() as u64);
+                let rx_event = EpollEvent::new(EventSet::IN, net.backend.as_raw_fd() as u64);
                 net.process(&rx_event, &mut event_manager);
 
                 // assert that limiter is blocked
@@ -1673,6 +2421,65 @@ pub(crate) mod tests {
         compare_buckets(net.tx_rate_limiter.ops().unwrap(), &tx_ops);
     }
 
+    #[test]
+    fn test_impairments() {
+        let mut net = Net::default_net(TestMutators::default());
+        let tx_params = ImpairmentParams {
+            loss: 1.0,
+            ..Default::default()
+        };
+        net.set_impairments(None, Some(tx_params));
+        assert_eq!(*net.rx_impairment(), ImpairmentParams::default());
+        assert_eq!(*net.tx_impairment(), tx_params);
+
+        // The frames sent to the backend are lost.
+        let frame = [7u8; 100];
+        check_metric_after_block!(
+            &METRICS.net.impairment_drops,
+            1,
+            Net::write_to_mmds_or_tap(
+                net.mmds_ns.as_mut(),
+                &mut net.tx_rate_limiter,
+                &mut net.tx_impairment,
+                &frame,
+                net.backend.as_mut(),
+                None,
+            )
+        );
+
+        // The duplicates are sent along with the frames.
+        net.set_impairments(
+            None,
+            Some(ImpairmentParams {
+                duplication: 1.0,
+                ..Default::default()
+            }),
+        );
+        check_metric_after_block!(&METRICS.net.tx_packets_count, 2, {
+            Net::write_to_mmds_or_tap(
+                net.mmds_ns.as_mut(),
+                &mut net.tx_rate_limiter,
+                &mut net.tx_impairment,
+                &frame,
+                net.backend.as_mut(),
+                None,
+            );
+            net.release_tx_frames();
+        });
+
+        // The frames received are held back for the delay.
+        net.set_impairments(
+            Some(ImpairmentParams {
+                delay: Duration::from_millis(10),
+                ..Default::default()
+            }),
+            None,
+        );
+        assert!(net.read_from_mmds_or_tap().unwrap().is_none());
+        thread::sleep(Duration::from_millis(10));
+        assert!(net.read_from_mmds_or_tap().unwrap().is_some());
+    }
+
     #[test]
     fn test_tx_queue_interrupt() {
         // Regression test for https://github.com/firecracker-microvm/firecracker/issues/1436 .
@@ -1702,6 +2509,64 @@ pub(crate) mod tests {
         assert_eq!(net.interrupt_evt.read().unwrap(), 1);
     }
 
+    #[test]
+    fn test_dispatch_budget() {
+        let mut event_manager = EventManager::new().unwrap();
+        event_manager.set_device_budget(2);
+        let mut net = Net::default_net(TestMutators::default());
+        let mem = Net::default_guest_memory();
+        let (rxq, txq) = Net::virtqueues(&mem);
+        net.assign_queues(rxq.create_queue(), txq.create_queue());
+        net.activate(mem.clone()).unwrap();
+
+        let daddr = 0x2000;
+        assert!(daddr > txq.end().0);
+        for i in 0..3 {
+            let addr = daddr + 0x1000 * i as u64;
+            txq.avail.ring[i].set(i as u16);
+            txq.dtable[i].set(addr, 0x1000, 0, 0);
+            rxq.avail.ring[i].set(i as u16);
+            rxq.dtable[i].set(addr, 0x1000, VIRTQ_DESC_F_WRITE, 0);
+        }
+        txq.avail.idx.set(3);
+        rxq.avail.idx.set(3);
+
+        // The device sends as many frames as its budget allows, and gets back to the others in
+        // the next round of the event loop.
+        net.queue_evts[TX_INDEX].write(1).unwrap();
+        let tx_event = EpollEvent::new(EventSet::IN, net.queue_evts[TX_INDEX].as_raw_fd() as u64);
+        check_metric_after_block!(
+            &METRICS.net.tx_budget_yield_count,
+            1,
+            net.process(&tx_event, &mut event_manager)
+        );
+        assert_eq!(txq.used.idx.get(), 2);
+        assert_eq!(net.queue_evts[TX_INDEX].read().unwrap(), 1);
+        net.queue_evts[TX_INDEX].write(1).unwrap();
+        check_metric_after_block!(
+            &METRICS.net.tx_budget_yield_count,
+            0,
+            net.process(&tx_event, &mut event_manager)
+        );
+        assert_eq!(txq.used.idx.get(), 3);
+
+        // The frames left in the tap are received once the RX queue event comes back.
+        let tap_event = EpollEvent::new(EventSet::IN, net.backend.as_raw_fd() as u64);
+        check_metric_after_block!(
+            &METRICS.net.rx_budget_yield_count,
+            1,
+            net.process(&tap_event, &mut event_manager)
+        );
+        assert_eq!(rxq.used.idx.get(), 2);
+        assert!(net.rx_yielded);
+        let rx_event = EpollEvent::new(EventSet::IN, net.queue_evts[RX_INDEX].as_raw_fd() as u64);
+        net.process(&rx_event, &mut event_manager);
+        assert_eq!(rxq.used.idx.get(), 3);
+        assert!(!net.rx_yielded);
+        // The RX queue is full again.
+        assert!(net.rx_deferred_frame);
+    }
+
     #[test]
     fn test_virtio_device() {
         let mut net = Net::default_net(TestMutators::default());
@@ -1730,4 +2595,23 @@ pub(crate) mod tests {
         net.interrupt_evt().write(1).unwrap();
         assert_eq!(net.interrupt_evt().read().unwrap() as usize, 1);
     }
+
+    #[test]
+    fn test_reset() {
+        let mut net = Net::default_net(TestMutators::default());
+        let mem = Net::default_guest_memory();
+        net.ack_features_by_page(0, u32::MAX);
+        net.activate(mem.clone()).unwrap();
+        net.rx_deferred_frame = true;
+        net.rx_deferred_irqs = true;
+
+        // The deferred frame is kept for the driver of the next kernel.
+        assert!(net.reset());
+        assert!(!net.is_activated());
+        assert_eq!(net.acked_features, 0);
+        assert!(net.rx_deferred_frame);
+        assert!(!net.rx_deferred_irqs);
+        net.activate(mem).unwrap();
+        assert!(net.is_activated());
+    }
 }
@@ -8,7 +8,7 @@ use polly::event_manager::{EventManager, Subscriber};
 use utils::epoll::{EpollEvent, EventSet};
 
 use crate::virtio::net::device::Net;
-use crate::virtio::{VirtioDevice, RX_INDEX, TX_INDEX};
+use crate::virtio::{VirtioDevice, CTRL_INDEX, RX_INDEX, TX_INDEX};
 
 impl Net {
     fn process_activate_event(&self, event_manager: &mut EventManager) {
@@ -46,15 +46,28 @@ impl Net {
 
         event_manager
             .register(
-                self.tap.as_raw_fd(),
+                self.queue_evts[CTRL_INDEX].as_raw_fd(),
+                EpollEvent::new(EventSet::IN, self.queue_evts[CTRL_INDEX].as_raw_fd() as u64),
+                self_subscriber.clone(),
+            )
+            .unwrap_or_else(|e| {
+                error!(
+                    "Failed to register net control queue with event manager: {:?}",
+                    e
+                );
+            });
+
+        event_manager
+            .register(
+                self.backend.as_raw_fd(),
                 EpollEvent::new(
                     EventSet::IN | EventSet::EDGE_TRIGGERED,
-                    self.tap.as_raw_fd() as u64,
+                    self.backend.as_raw_fd() as u64,
                 ),
                 self_subscriber.clone(),
             )
             .unwrap_or_else(|e| {
-                error!("Failed to register net tap with event manager: {:?}", e);
+                error!("Failed to register net backend with event manager: {:?}", e);
             });
 
         event_manager
@@ -83,6 +96,32 @@ impl Net {
                 );
             });
 
+        event_manager
+            .register(
+                self.rx_impairment.as_raw_fd(),
+                EpollEvent::new(EventSet::IN, self.rx_impairment.as_raw_fd() as u64),
+                self_subscriber.clone(),
+            )
+            .unwrap_or_else(|e| {
+                error!(
+                    "Failed to register net rx impairment with event manager: {:?}",
+                    e
+                );
+            });
+
+        event_manager
+            .register(
+                self.tx_impairment.as_raw_fd(),
+                EpollEvent::new(EventSet::IN, self.tx_impairment.as_raw_fd() as u64),
+                self_subscriber.clone(),
+            )
+            .unwrap_or_else(|e| {
+                error!(
+                    "Failed to register net tx impairment with event manager: {:?}",
+                    e
+                );
+            });
+
         event_manager
             .unregister(self.activate_evt.as_raw_fd())
             .unwrap_or_else(|e| {
@@ -108,20 +147,27 @@ impl Subscriber for Net {
         }
 
         if self.is_activated() {
+            self.dispatch_budget = evmgr.device_budget();
             let virtq_rx_ev_fd = self.queue_evts[RX_INDEX].as_raw_fd();
             let virtq_tx_ev_fd = self.queue_evts[TX_INDEX].as_raw_fd();
+            let virtq_ctrl_ev_fd = self.queue_evts[CTRL_INDEX].as_raw_fd();
             let rx_rate_limiter_fd = self.rx_rate_limiter.as_raw_fd();
             let tx_rate_limiter_fd = self.tx_rate_limiter.as_raw_fd();
-            let tap_fd = self.tap.as_raw_fd();
+            let rx_impairment_fd = self.rx_impairment.as_raw_fd();
+            let tx_impairment_fd = self.tx_impairment.as_raw_fd();
+            let backend_fd = self.backend.as_raw_fd();
             let activate_fd = self.activate_evt.as_raw_fd();
 
             // Looks better than C style if/else if/else.
             match source {
                 _ if source == virtq_rx_ev_fd => self.process_rx_queue_event(),
-                _ if source == tap_fd => self.process_tap_rx_event(),
+                _ if source == backend_fd => self.process_tap_rx_event(),
                 _ if source == virtq_tx_ev_fd => self.process_tx_queue_event(),
+                _ if source == virtq_ctrl_ev_fd => self.process_ctrl_queue_event(),
                 _ if source == rx_rate_limiter_fd => self.process_rx_rate_limiter_event(),
                 _ if source == tx_rate_limiter_fd => self.process_tx_rate_limiter_event(),
+                _ if source == rx_impairment_fd => self.process_rx_impairment_event(),
+                _ if source == tx_impairment_fd => self.process_tx_impairment_event(),
                 _ if activate_fd == source => self.process_activate_event(evmgr),
                 _ => {
                     warn!("Net: Spurious event received: {:?}", source);
@@ -142,6 +188,12 @@ impl Subscriber for Net {
             self.activate_evt.as_raw_fd() as u64,
         )]
     }
+
+    fn poll(&mut self, evmgr: &mut EventManager) -> bool {
+        self.dispatch_budget = evmgr.device_budget();
+        // The received frames are signaled by the tap, which the event loop polls along.
+        self.poll_tx_queue()
+    }
 }
 
 #[cfg(test)]
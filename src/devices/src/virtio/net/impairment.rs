@@ -0,0 +1,315 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Degrades the network path of a net device the way `netem` does on a host interface: the
+//! frames are held back for a delay, varying with some jitter, and lost or duplicated at random.
+//! This lets the guest applications be tested under degraded networks, without the privileges
+//! `tc` needs on the host.
+//!
+//! Each direction of the path is degraded on its own. The frames held back are released in the
+//! order they came in, through a timer.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use logger::{Metric, METRICS};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::rand::xor_rng_u32;
+
+/// The most frames held back at once in a direction. Past it, the frames are lost, as they are
+/// past the default limit of `netem`.
+pub const MAX_DELAYED_FRAMES: usize = 1000;
+
+/// The longest delay, and the largest jitter, a frame can be held back with.
+pub const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// How a direction of the network path is degraded.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ImpairmentParams {
+    /// The time the frames are held back for.
+    pub delay: Duration,
+    /// The most the delay of a frame varies by, either way, at random.
+    pub jitter: Duration,
+    /// The probability of losing a frame, between 0 and 1.
+    pub loss: f64,
+    /// The probability of duplicating a frame, between 0 and 1.
+    pub duplication: f64,
+}
+
+/// Degrades a direction of the network path of a net device.
+pub struct Impairment {
+    params: ImpairmentParams,
+    rng_state: u64,
+    // The frames held back, along with the time they are released at, which never decreases.
+    delayed: VecDeque<(Instant, Vec<u8>)>,
+    timer_fd: TimerFd,
+}
+
+impl Impairment {
+    /// Creates an impairment leaving the frames untouched, until its parameters are set.
+    pub fn new() -> io::Result<Self> {
+        Ok(Impairment {
+            params: ImpairmentParams::default(),
+            // The state of the generator must not be 0.
+            rng_state: u64::from(xor_rng_u32()) << 1 | 1,
+            delayed: VecDeque::new(),
+            timer_fd: TimerFd::new_custom(ClockId::Monotonic, true, true)?,
+        })
+    }
+
+    /// Provides how the frames are degraded.
+    pub fn params(&self) -> &ImpairmentParams {
+        &self.params
+    }
+
+    /// Changes how the frames are degraded from now on. The frames already held back are still
+    /// released when they were due.
+    pub fn set_params(&mut self, params: ImpairmentParams) {
+        self.params = params;
+    }
+
+    /// Decides the fate of `frame`, coming in at `now`. Returns whether it goes through right
+    /// away. Otherwise, it is either lost or held back, to be taken later with `release`, along
+    /// with its duplicates.
+    pub fn impair(&mut self, frame: &[u8], now: Instant) -> bool {
+        if self.params == ImpairmentParams::default() && self.delayed.is_empty() {
+            return true;
+        }
+        if self.chance(self.params.loss) {
+            METRICS.net.impairment_drops.inc();
+            return false;
+        }
+        let copies = if self.chance(self.params.duplication) {
+            METRICS.net.impairment_duplicates.inc();
+            2
+        } else {
+            1
+        };
+        // The frames don't overtake the ones held back.
+        let passes = self.params.delay == Duration::default()
+            && self.params.jitter == Duration::default()
+            && self.delayed.is_empty();
+        for _ in usize::from(passes)..copies {
+            self.hold(frame, now);
+        }
+        passes
+    }
+
+    /// Takes the first frame held back, if it is due by `now`. Otherwise, arms the timer for
+    /// the time it is due at.
+    pub fn release(&mut self, now: Instant) -> Option<Vec<u8>> {
+        let due = self.delayed.front()?.0;
+        if due > now {
+            self.arm_timer(due - now);
+            return None;
+        }
+        self.delayed.pop_front().map(|(_, frame)| frame)
+    }
+
+    /// Returns the number of frames held back.
+    pub fn delayed_frames(&self) -> usize {
+        self.delayed.len()
+    }
+
+    /// Acknowledges the expiry of the timer.
+    pub fn read_timer(&mut self) {
+        self.timer_fd.read();
+    }
+
+    fn hold(&mut self, frame: &[u8], now: Instant) {
+        if self.delayed.len() >= MAX_DELAYED_FRAMES {
+            METRICS.net.impairment_drops.inc();
+            return;
+        }
+        // The delay is bounded, yet the clock could be past anything it can be added to.
+        let mut due = match now.checked_add(self.delay()) {
+            Some(due) => due,
+            None => {
+                METRICS.net.impairment_drops.inc();
+                return;
+            }
+        };
+        if let Some(&(last_due, _)) = self.delayed.back() {
+            due = cmp::max(due, last_due);
+        } else {
+            self.arm_timer(due - now);
+        }
+        self.delayed.push_back((due, frame.to_vec()));
+    }
+
+    // Returns the delay of a frame, which varies uniformly within the jitter.
+    fn delay(&mut self) -> Duration {
+        let jitter = self.params.jitter.as_nanos() as u64;
+        if jitter == 0 {
+            return self.params.delay;
+        }
+        let offset = self.next_random() % jitter.saturating_mul(2).saturating_add(1);
+        match self.params.delay.checked_add(Duration::from_nanos(offset)) {
+            Some(delay) => delay
+                .checked_sub(Duration::from_nanos(jitter))
+                .unwrap_or_default(),
+            None => self.params.delay,
+        }
+    }
+
+    // Returns true with the probability `p`.
+    fn chance(&mut self, p: f64) -> bool {
+        if p <= 0.0 {
+            return false;
+        }
+        // The 53 upper bits make a uniform float in [0, 1).
+        ((self.next_random() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+
+    // Xorshift, taken from https://en.wikipedia.org/wiki/Xorshift.
+    fn next_random(&mut self) -> u64 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng_state = x;
+        x
+    }
+
+    fn arm_timer(&mut self, timeout: Duration) {
+        // A zero timeout would disarm the timer.
+        let timeout = cmp::max(timeout, Duration::from_nanos(1));
+        self.timer_fd
+            .set_state(TimerState::Oneshot(timeout), SetTimeFlags::Default);
+    }
+}
+
+impl AsRawFd for Impairment {
+    fn as_raw_fd(&self) -> RawFd {
+        self.timer_fd.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pass_through() {
+        let mut impairment = Impairment::new().unwrap();
+        let now = Instant::now();
+        assert!(impairment.impair(&[1], now));
+        assert!(impairment.release(now).is_none());
+
+        // Frames which are neither delayed, lost nor duplicated go through.
+        impairment.set_params(ImpairmentParams {
+            loss: 0.0,
+            duplication: 0.0,
+            ..Default::default()
+        });
+        assert!(impairment.impair(&[1], now));
+        assert!(impairment.release(now).is_none());
+    }
+
+    #[test]
+    fn test_loss() {
+        let mut impairment = Impairment::new().unwrap();
+        impairment.set_params(ImpairmentParams {
+            loss: 1.0,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        for _ in 0..10 {
+            assert!(!impairment.impair(&[1], now));
+        }
+        assert!(impairment.release(now).is_none());
+
+        // About half of the frames are lost.
+        impairment.set_params(ImpairmentParams {
+            loss: 0.5,
+            ..Default::default()
+        });
+        let passed = (0..10_000).filter(|_| impairment.impair(&[1], now)).count();
+        assert!(passed > 4_000 && passed < 6_000, "{} frames passed", passed);
+    }
+
+    #[test]
+    fn test_duplication() {
+        let mut impairment = Impairment::new().unwrap();
+        impairment.set_params(ImpairmentParams {
+            duplication: 1.0,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        // The frame goes through, and its duplicate right after.
+        assert!(impairment.impair(&[1], now));
+        assert_eq!(impairment.release(now).unwrap(), vec![1]);
+        assert!(impairment.release(now).is_none());
+    }
+
+    #[test]
+    fn test_delay() {
+        let mut impairment = Impairment::new().unwrap();
+        let delay = Duration::from_millis(10);
+        impairment.set_params(ImpairmentParams {
+            delay,
+            jitter: Duration::from_millis(5),
+            ..Default::default()
+        });
+        let now = Instant::now();
+        for i in 0..100 {
+            assert!(!impairment.impair(&[i], now));
+        }
+        assert!(impairment.release(now + Duration::from_millis(4)).is_none());
+
+        // The frames are released in order, once the delay and the jitter elapsed.
+        impairment.set_params(ImpairmentParams::default());
+        let later = now + Duration::from_millis(15);
+        for i in 0..100 {
+            assert_eq!(impairment.release(later).unwrap(), vec![i]);
+        }
+        assert!(impairment.release(later).is_none());
+
+        // The frames don't overtake the ones held back, even with the delay removed.
+        impairment.set_params(ImpairmentParams {
+            delay,
+            ..Default::default()
+        });
+        assert!(!impairment.impair(&[1], now));
+        impairment.set_params(ImpairmentParams::default());
+        assert!(!impairment.impair(&[2], now));
+        assert!(impairment.release(now).is_none());
+        assert_eq!(impairment.release(now + delay).unwrap(), vec![1]);
+        assert_eq!(impairment.release(now + delay).unwrap(), vec![2]);
+        assert!(impairment.impair(&[3], now));
+
+        // The frames which can't be held back for that long are lost instead of overflowing.
+        impairment.set_params(ImpairmentParams {
+            delay: Duration::from_secs(u64::max_value()),
+            jitter: Duration::from_nanos(u64::max_value()),
+            ..Default::default()
+        });
+        let drops = METRICS.net.impairment_drops.count();
+        assert!(!impairment.impair(&[4], now));
+        assert_eq!(impairment.delayed_frames(), 0);
+        assert!(METRICS.net.impairment_drops.count() > drops);
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut impairment = Impairment::new().unwrap();
+        impairment.set_params(ImpairmentParams {
+            delay: Duration::from_secs(1),
+            ..Default::default()
+        });
+        let now = Instant::now();
+        let drops = METRICS.net.impairment_drops.count();
+        for _ in 0..=MAX_DELAYED_FRAMES {
+            assert!(!impairment.impair(&[1], now));
+        }
+        assert!(METRICS.net.impairment_drops.count() > drops);
+        let later = now + Duration::from_secs(1);
+        assert_eq!(
+            std::iter::from_fn(|| impairment.release(later)).count(),
+            MAX_DELAYED_FRAMES
+        );
+    }
+}
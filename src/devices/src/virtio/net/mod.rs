@@ -6,19 +6,37 @@ use utils::net::TapError;
 
 pub const MAX_BUFFER_SIZE: usize = 65562;
 pub const QUEUE_SIZE: u16 = 256;
-pub const NUM_QUEUES: usize = 2;
-pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE; NUM_QUEUES];
+pub const CTRL_QUEUE_SIZE: u16 = 64;
+pub const NUM_QUEUES: usize = 3;
+pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE, QUEUE_SIZE, CTRL_QUEUE_SIZE];
 // The index of the rx queue from Net device queues/queues_evts vector.
 pub const RX_INDEX: usize = 0;
 // The index of the tx queue from Net device queues/queues_evts vector.
 pub const TX_INDEX: usize = 1;
+// The index of the control queue from Net device queues/queues_evts vector.
+pub const CTRL_INDEX: usize = 2;
+// Upper bound of the size of a control command.
+pub const MAX_CTRL_COMMAND_SIZE: usize = 4096;
+// The smallest MTU of an IPv4 interface.
+pub const MIN_MTU: u16 = 68;
+// The largest MTU whose frames, along with their virtio-net header and a VLAN tag, fit in an rx
+// buffer.
+pub const MAX_MTU: u16 = 65532;
 
+pub mod backend;
 pub mod device;
 pub mod event_handler;
+pub mod impairment;
+pub mod offload;
 pub mod persist;
+pub mod rx_filter;
+#[cfg(feature = "xdp")]
+pub mod xdp;
 
+pub use self::backend::NetBackend;
 pub use self::device::Net;
 pub use self::event_handler::*;
+pub use self::impairment::{ImpairmentParams, MAX_DELAY as MAX_IMPAIRMENT_DELAY};
 
 #[derive(Debug)]
 pub enum Error {
@@ -30,8 +48,12 @@ pub enum Error {
     TapSetVnetHdrSize(TapError),
     /// Enabling tap interface failed.
     TapEnable(TapError),
+    /// Setting the MTU of the tap interface failed.
+    TapSetMtu(TapError),
     /// EventFd
     EventFd(io::Error),
+    /// Creating the timer of an impairment failed.
+    ImpairmentTimer(io::Error),
 }
 
 pub type Result<T> = result::Result<T, Error>;
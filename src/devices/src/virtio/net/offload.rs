@@ -0,0 +1,606 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Completes in software the offloads a guest driver relies on once it negotiated
+//! `VIRTIO_NET_F_CSUM`, `VIRTIO_NET_F_HOST_TSO4` or `VIRTIO_NET_F_HOST_UFO`.
+//!
+//! A tap device takes the frames sent by the guest along with their virtio-net header, and
+//! completes their checksums and segmentation itself. For the backends which can't, e.g. a user
+//! mode network stack, the device hands each frame to `complete_offloads` first: otherwise, the
+//! frames carrying a partial checksum or a segment larger than the MTU reach them corrupted.
+
+use std::cmp;
+use std::fmt;
+use std::mem;
+use std::result;
+
+use utils::byte_order::{read_be_u16, read_be_u32, read_le_u16, write_be_u16, write_be_u32};
+use virtio_gen::virtio_net::{
+    virtio_net_hdr_v1, VIRTIO_NET_HDR_F_NEEDS_CSUM, VIRTIO_NET_HDR_GSO_ECN,
+    VIRTIO_NET_HDR_GSO_NONE, VIRTIO_NET_HDR_GSO_TCPV4, VIRTIO_NET_HDR_GSO_TCPV6,
+    VIRTIO_NET_HDR_GSO_UDP,
+};
+
+const ETH_HDR_LEN: usize = 14;
+const ETHERTYPE_OFFSET: usize = 12;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const VLAN_TAG_LEN: usize = 4;
+
+const IPV4_MIN_HDR_LEN: usize = 20;
+const IPV4_MORE_FRAGMENTS: u16 = 0x2000;
+const IPV6_HDR_LEN: usize = 40;
+const PROTOCOL_TCP: u8 = 6;
+const PROTOCOL_UDP: u8 = 17;
+
+const TCP_FLAG_FIN: u8 = 0x01;
+const TCP_FLAG_PSH: u8 = 0x08;
+const TCP_FLAG_CWR: u8 = 0x80;
+const TCP_MIN_HDR_LEN: usize = 20;
+const UDP_HDR_LEN: usize = 8;
+
+/// Errors associated with completing the offloads of a frame.
+#[derive(Debug, PartialEq)]
+pub enum OffloadError {
+    /// The checksum of the frame starts or is stored out of the frame.
+    InvalidChecksumOffset,
+    /// The IPv4 or TCP header announces a length shorter than the header itself.
+    InvalidHeaderLength,
+    /// The segment size of the frame is too small.
+    InvalidSegmentSize(u16),
+    /// The packet doesn't match the GSO type of the frame.
+    MalformedPacket,
+    /// The frame is shorter than the headers it announces.
+    Truncated,
+    /// The GSO type of the frame is unknown.
+    UnsupportedGso(u8),
+}
+
+impl fmt::Display for OffloadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::OffloadError::*;
+        match self {
+            InvalidChecksumOffset => write!(f, "The checksum is out of the frame."),
+            InvalidHeaderLength => write!(f, "The header length is too small."),
+            InvalidSegmentSize(size) => write!(f, "Invalid segment size {}.", size),
+            MalformedPacket => write!(f, "The packet doesn't match its GSO type."),
+            Truncated => write!(f, "The frame is truncated."),
+            UnsupportedGso(gso_type) => write!(f, "Unsupported GSO type {}.", gso_type),
+        }
+    }
+}
+
+impl std::error::Error for OffloadError {}
+
+type Result<T> = result::Result<T, OffloadError>;
+
+fn vnet_hdr_len() -> usize {
+    mem::size_of::<virtio_net_hdr_v1>()
+}
+
+// The fields of a virtio-net header which describe the offloads of the frame.
+struct VnetHeader {
+    flags: u8,
+    gso_type: u8,
+    gso_size: u16,
+    csum_start: u16,
+    csum_offset: u16,
+}
+
+impl VnetHeader {
+    // The header is little endian, as `VIRTIO_F_VERSION_1` is always negotiated.
+    fn parse(buf: &[u8]) -> Result<Self> {
+        if buf.len() < vnet_hdr_len() {
+            return Err(OffloadError::Truncated);
+        }
+        Ok(VnetHeader {
+            flags: buf[0],
+            gso_type: buf[1],
+            gso_size: read_le_u16(&buf[4..]),
+            csum_start: read_le_u16(&buf[6..]),
+            csum_offset: read_le_u16(&buf[8..]),
+        })
+    }
+}
+
+// The offsets of the headers of a TCP or UDP frame.
+struct Layout {
+    l3: usize,
+    l4: usize,
+    payload: usize,
+    ipv6: bool,
+}
+
+impl Layout {
+    fn parse(frame: &[u8], protocol: u8) -> Result<Self> {
+        let byte = |offset: usize| frame.get(offset).cloned().ok_or(OffloadError::Truncated);
+        let be16 = |offset: usize| {
+            frame
+                .get(offset..offset + 2)
+                .map(read_be_u16)
+                .ok_or(OffloadError::Truncated)
+        };
+
+        let mut l3 = ETH_HDR_LEN;
+        let mut ethertype = be16(ETHERTYPE_OFFSET)?;
+        if ethertype == ETHERTYPE_VLAN {
+            l3 += VLAN_TAG_LEN;
+            ethertype = be16(ETHERTYPE_OFFSET + VLAN_TAG_LEN)?;
+        }
+        let (l4, ipv6) = match ethertype {
+            // IPv6 extension headers aren't supported.
+            ETHERTYPE_IPV6 if byte(l3 + 6)? == protocol => (l3 + IPV6_HDR_LEN, true),
+            ETHERTYPE_IPV4 if byte(l3 + 9)? == protocol => {
+                // The guest controls the lengths of the headers, which the offsets of their
+                // fields are relative to.
+                let hdr_len = usize::from(byte(l3)? & 0x0f) * 4;
+                if hdr_len < IPV4_MIN_HDR_LEN {
+                    return Err(OffloadError::InvalidHeaderLength);
+                }
+                (l3 + hdr_len, false)
+            }
+            _ => return Err(OffloadError::MalformedPacket),
+        };
+        let payload = match protocol {
+            PROTOCOL_TCP => {
+                let hdr_len = usize::from(byte(l4 + 12)? >> 4) * 4;
+                if hdr_len < TCP_MIN_HDR_LEN {
+                    return Err(OffloadError::InvalidHeaderLength);
+                }
+                l4 + hdr_len
+            }
+            _ => l4 + UDP_HDR_LEN,
+        };
+        if payload > frame.len() {
+            return Err(OffloadError::Truncated);
+        }
+        Ok(Layout {
+            l3,
+            l4,
+            payload,
+            ipv6,
+        })
+    }
+
+    // Returns the one's complement sum of the pseudo header of the TCP or UDP packet.
+    fn pseudo_header_sum(&self, packet: &[u8], protocol: u8) -> u64 {
+        let addresses = if self.ipv6 {
+            &packet[self.l3 + 8..self.l3 + IPV6_HDR_LEN]
+        } else {
+            &packet[self.l3 + 12..self.l3 + 20]
+        };
+        add_words(0, addresses) + u64::from(protocol) + (packet.len() - self.l4) as u64
+    }
+
+    // Sets the length of the IPv4 packet and recomputes its header checksum.
+    fn complete_ipv4_header(&self, packet: &mut [u8]) {
+        let l3 = self.l3;
+        let total_len = (packet.len() - l3) as u16;
+        write_be_u16(&mut packet[l3 + 2..], total_len);
+        write_be_u16(&mut packet[l3 + 10..], 0);
+        let checksum = fold(add_words(0, &packet[l3..self.l4]));
+        write_be_u16(&mut packet[l3 + 10..], checksum);
+    }
+}
+
+// Adds the 16 bit big endian words of `bytes` to the one's complement sum `sum`.
+fn add_words(mut sum: u64, bytes: &[u8]) -> u64 {
+    let mut words = bytes.chunks_exact(2);
+    for word in &mut words {
+        sum += u64::from(read_be_u16(word));
+    }
+    if let [last] = words.remainder() {
+        sum += u64::from(*last) << 8;
+    }
+    sum
+}
+
+// Folds the one's complement sum `sum` into a checksum.
+fn fold(mut sum: u64) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+// Computes the checksum from `csum_start` to the end of the frame, and stores it at
+// `csum_offset` from there. The guest already stored the sum of the pseudo header there.
+fn complete_checksum(frame: &mut [u8], hdr: &VnetHeader) -> Result<()> {
+    let start = usize::from(hdr.csum_start);
+    let field = start + usize::from(hdr.csum_offset);
+    if field + 2 > frame.len() {
+        return Err(OffloadError::InvalidChecksumOffset);
+    }
+    let checksum = match fold(add_words(0, &frame[start..])) {
+        // A UDP checksum of 0 means there is none, and 0xffff is the same in one's complement.
+        0 => 0xffff,
+        checksum => checksum,
+    };
+    write_be_u16(&mut frame[field..], checksum);
+    Ok(())
+}
+
+// Splits the TCP segment in `frame` into segments of at most `gso_size` bytes of payload.
+fn segment_tcp<F: FnMut(&[u8])>(frame: &[u8], hdr: &VnetHeader, emit: &mut F) -> Result<()> {
+    let mss = usize::from(hdr.gso_size);
+    if mss == 0 {
+        return Err(OffloadError::InvalidSegmentSize(hdr.gso_size));
+    }
+    let layout = Layout::parse(frame, PROTOCOL_TCP)?;
+    let gso_type = u32::from(hdr.gso_type & !(VIRTIO_NET_HDR_GSO_ECN as u8));
+    if layout.ipv6 != (gso_type == VIRTIO_NET_HDR_GSO_TCPV6) {
+        return Err(OffloadError::MalformedPacket);
+    }
+    let (l3, l4) = (layout.l3, layout.l4);
+    let (headers, payload) = frame.split_at(layout.payload);
+    let seq = read_be_u32(&headers[l4 + 4..]);
+    let id = read_be_u16(&headers[l3 + 4..]);
+
+    let count = cmp::max(1, (payload.len() + mss - 1) / mss);
+    let mut segment = Vec::with_capacity(headers.len() + mss);
+    for i in 0..count {
+        let chunk = &payload[i * mss..cmp::min((i + 1) * mss, payload.len())];
+        segment.clear();
+        segment.extend_from_slice(headers);
+        segment.extend_from_slice(chunk);
+
+        if layout.ipv6 {
+            let payload_len = (segment.len() - l4) as u16;
+            write_be_u16(&mut segment[l3 + 4..], payload_len);
+        } else {
+            write_be_u16(&mut segment[l3 + 4..], id.wrapping_add(i as u16));
+            layout.complete_ipv4_header(&mut segment);
+        }
+        write_be_u32(&mut segment[l4 + 4..], seq.wrapping_add((i * mss) as u32));
+        // Only the last segment finishes or pushes the stream, and only the first one reduces
+        // the congestion window.
+        if i + 1 < count {
+            segment[l4 + 13] &= !(TCP_FLAG_FIN | TCP_FLAG_PSH);
+        }
+        if i > 0 {
+            segment[l4 + 13] &= !TCP_FLAG_CWR;
+        }
+        write_be_u16(&mut segment[l4 + 16..], 0);
+        let sum = layout.pseudo_header_sum(&segment, PROTOCOL_TCP);
+        let checksum = fold(add_words(sum, &segment[l4..]));
+        write_be_u16(&mut segment[l4 + 16..], checksum);
+        emit(&segment);
+    }
+    Ok(())
+}
+
+// Splits the UDP datagram in `frame` into IPv4 fragments of at most `gso_size` bytes of payload.
+fn fragment_udp<F: FnMut(&[u8])>(frame: &mut [u8], hdr: &VnetHeader, emit: &mut F) -> Result<()> {
+    // The fragment offsets count 8 byte blocks.
+    let fragment_size = usize::from(hdr.gso_size) & !7;
+    if fragment_size == 0 {
+        return Err(OffloadError::InvalidSegmentSize(hdr.gso_size));
+    }
+    let layout = Layout::parse(frame, PROTOCOL_UDP)?;
+    // IPv6 fragments need an extension header.
+    if layout.ipv6 {
+        return Err(OffloadError::UnsupportedGso(hdr.gso_type));
+    }
+    // The checksum covers the whole datagram.
+    if u32::from(hdr.flags) & VIRTIO_NET_HDR_F_NEEDS_CSUM != 0 {
+        complete_checksum(frame, hdr)?;
+    }
+    let l3 = layout.l3;
+    let (headers, payload) = frame.split_at(layout.l4);
+
+    let count = cmp::max(1, (payload.len() + fragment_size - 1) / fragment_size);
+    let mut fragment = Vec::with_capacity(headers.len() + fragment_size);
+    for i in 0..count {
+        let offset = i * fragment_size;
+        let chunk = &payload[offset..cmp::min(offset + fragment_size, payload.len())];
+        fragment.clear();
+        fragment.extend_from_slice(headers);
+        fragment.extend_from_slice(chunk);
+
+        let mut flags_and_offset = (offset / 8) as u16;
+        if i + 1 < count {
+            flags_and_offset |= IPV4_MORE_FRAGMENTS;
+        }
+        write_be_u16(&mut fragment[l3 + 6..], flags_and_offset);
+        layout.complete_ipv4_header(&mut fragment);
+        emit(&fragment);
+    }
+    Ok(())
+}
+
+/// Whether the frame sent by the guest in `buf`, which starts with its virtio-net header, relies
+/// on an offload, i.e. whether `complete_offloads` has anything to complete.
+pub fn needs_offloads(buf: &[u8]) -> bool {
+    VnetHeader::parse(buf)
+        .map(|hdr| {
+            u32::from(hdr.flags) & VIRTIO_NET_HDR_F_NEEDS_CSUM != 0
+                || u32::from(hdr.gso_type) != VIRTIO_NET_HDR_GSO_NONE
+        })
+        .unwrap_or(false)
+}
+
+/// Completes the offloads of the frame sent by the guest in `buf`, which starts with its
+/// virtio-net header, and hands the resulting frames to `emit`, without a virtio-net header.
+///
+/// The partial checksum of the frame is completed, and a TCP segment or UDP datagram larger
+/// than its segment size is split into TCP segments or IPv4 fragments, which `emit` gets one by
+/// one. `buf` is left modified.
+pub fn complete_offloads<F: FnMut(&[u8])>(buf: &mut [u8], mut emit: F) -> Result<()> {
+    let hdr = VnetHeader::parse(buf)?;
+    let frame = &mut buf[vnet_hdr_len()..];
+    match u32::from(hdr.gso_type & !(VIRTIO_NET_HDR_GSO_ECN as u8)) {
+        VIRTIO_NET_HDR_GSO_NONE => {
+            if u32::from(hdr.flags) & VIRTIO_NET_HDR_F_NEEDS_CSUM != 0 {
+                complete_checksum(frame, &hdr)?;
+            }
+            emit(frame);
+            Ok(())
+        }
+        // The checksums of the segments are computed from scratch.
+        VIRTIO_NET_HDR_GSO_TCPV4 | VIRTIO_NET_HDR_GSO_TCPV6 => segment_tcp(frame, &hdr, &mut emit),
+        VIRTIO_NET_HDR_GSO_UDP => fragment_udp(frame, &hdr, &mut emit),
+        _ => Err(OffloadError::UnsupportedGso(hdr.gso_type)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use utils::byte_order::write_le_u16;
+
+    const SRC_ADDR: [u8; 4] = [10, 0, 0, 2];
+    const DST_ADDR: [u8; 4] = [10, 0, 0, 1];
+
+    fn vnet_hdr(flags: u32, gso_type: u32, gso_size: u16, csum: Option<(usize, usize)>) -> Vec<u8> {
+        let mut hdr = vec![0u8; vnet_hdr_len()];
+        hdr[0] = flags as u8;
+        hdr[1] = gso_type as u8;
+        write_le_u16(&mut hdr[4..], gso_size);
+        if let Some((start, offset)) = csum {
+            write_le_u16(&mut hdr[6..], start as u16);
+            write_le_u16(&mut hdr[8..], offset as u16);
+        }
+        hdr
+    }
+
+    // Builds an Ethernet frame carrying an IPv4 or IPv6 packet with `l4`, whose checksum only
+    // holds the sum of the pseudo header, as the guest leaves it for the device to complete.
+    fn frame(ipv6: bool, protocol: u8, l4: &[u8], checksum_offset: usize) -> Vec<u8> {
+        let mut frame = vec![0u8; ETH_HDR_LEN];
+        if ipv6 {
+            write_be_u16(&mut frame[ETHERTYPE_OFFSET..], ETHERTYPE_IPV6);
+            frame.extend_from_slice(&[0x60, 0, 0, 0]);
+            frame.extend_from_slice(&(l4.len() as u16).to_be_bytes());
+            frame.extend_from_slice(&[protocol, 64]);
+            frame.extend_from_slice(&[0xfe; 16]);
+            frame.extend_from_slice(&[0xfd; 16]);
+        } else {
+            write_be_u16(&mut frame[ETHERTYPE_OFFSET..], ETHERTYPE_IPV4);
+            frame.extend_from_slice(&[0x45, 0]);
+            frame.extend_from_slice(&((20 + l4.len()) as u16).to_be_bytes());
+            frame.extend_from_slice(&[0x12, 0x34, 0x40, 0, 64, protocol, 0, 0]);
+            frame.extend_from_slice(&SRC_ADDR);
+            frame.extend_from_slice(&DST_ADDR);
+        }
+        frame.extend_from_slice(l4);
+        let layout = Layout::parse(&frame, protocol).unwrap();
+        if !ipv6 {
+            layout.complete_ipv4_header(&mut frame);
+        }
+        let sum = !fold(layout.pseudo_header_sum(&frame, protocol));
+        write_be_u16(&mut frame[layout.l4 + checksum_offset..], sum);
+        frame
+    }
+
+    fn tcp_segment(payload_len: usize, flags: u8) -> Vec<u8> {
+        let mut segment = vec![0u8; 20];
+        write_be_u16(&mut segment[0..], 1234);
+        write_be_u16(&mut segment[2..], 80);
+        write_be_u32(&mut segment[4..], 0xffff_ff00);
+        segment[12] = 5 << 4;
+        segment[13] = flags;
+        segment.extend((0..payload_len).map(|i| i as u8));
+        segment
+    }
+
+    // Checks the checksums of a TCP or UDP packet, and returns its layout.
+    fn verify(packet: &[u8], protocol: u8) -> Layout {
+        let layout = Layout::parse(packet, protocol).unwrap();
+        if !layout.ipv6 {
+            assert_eq!(fold(add_words(0, &packet[layout.l3..layout.l4])), 0);
+        }
+        let sum = layout.pseudo_header_sum(packet, protocol);
+        assert_eq!(fold(add_words(sum, &packet[layout.l4..])), 0);
+        layout
+    }
+
+    #[test]
+    fn test_checksum() {
+        let mut udp = vec![0u8; UDP_HDR_LEN];
+        write_be_u16(&mut udp[4..], (UDP_HDR_LEN + 13) as u16);
+        udp.extend_from_slice(b"hello, world!");
+        let frame = frame(false, PROTOCOL_UDP, &udp, 6);
+        let l4 = ETH_HDR_LEN + 20;
+
+        let mut buf = vnet_hdr(VIRTIO_NET_HDR_F_NEEDS_CSUM, 0, 0, Some((l4, 6)));
+        buf.extend_from_slice(&frame);
+        assert!(needs_offloads(&buf));
+        let mut frames = Vec::new();
+        complete_offloads(&mut buf, |frame| frames.push(frame.to_vec())).unwrap();
+        assert_eq!(frames.len(), 1);
+        verify(&frames[0], PROTOCOL_UDP);
+
+        // Without offloads, the frame is handed over as it is.
+        let mut buf = vnet_hdr(0, 0, 0, None);
+        buf.extend_from_slice(&frame);
+        assert!(!needs_offloads(&buf));
+        assert!(!needs_offloads(&buf[..4]));
+        let mut frames = Vec::new();
+        complete_offloads(&mut buf, |frame| frames.push(frame.to_vec())).unwrap();
+        assert_eq!(frames, vec![frame.clone()]);
+
+        let mut buf = vnet_hdr(VIRTIO_NET_HDR_F_NEEDS_CSUM, 0, 0, Some((l4, 1000)));
+        buf.extend_from_slice(&frame);
+        assert_eq!(
+            complete_offloads(&mut buf, |_| ()),
+            Err(OffloadError::InvalidChecksumOffset)
+        );
+    }
+
+    #[test]
+    fn test_tcp_segmentation() {
+        for &ipv6 in &[false, true] {
+            let segment = tcp_segment(2500, TCP_FLAG_CWR | TCP_FLAG_PSH | TCP_FLAG_FIN);
+            let frame = frame(ipv6, PROTOCOL_TCP, &segment, 16);
+            let gso_type = if ipv6 {
+                VIRTIO_NET_HDR_GSO_TCPV6
+            } else {
+                VIRTIO_NET_HDR_GSO_TCPV4
+            };
+            let mut buf = vnet_hdr(VIRTIO_NET_HDR_F_NEEDS_CSUM, gso_type, 1000, None);
+            buf.extend_from_slice(&frame);
+
+            let mut frames = Vec::new();
+            complete_offloads(&mut buf, |frame| frames.push(frame.to_vec())).unwrap();
+            assert_eq!(frames.len(), 3);
+            let mut payload = Vec::new();
+            for (i, frame) in frames.iter().enumerate() {
+                let layout = verify(frame, PROTOCOL_TCP);
+                assert_eq!(layout.ipv6, ipv6);
+                let seq = read_be_u32(&frame[layout.l4 + 4..]);
+                assert_eq!(seq, 0xffff_ff00u32.wrapping_add(i as u32 * 1000));
+                let flags = frame[layout.l4 + 13];
+                assert_eq!(flags & TCP_FLAG_CWR != 0, i == 0);
+                assert_eq!(flags & (TCP_FLAG_PSH | TCP_FLAG_FIN) != 0, i == 2);
+                if !ipv6 {
+                    assert_eq!(read_be_u16(&frame[layout.l3 + 4..]), 0x1234 + i as u16);
+                }
+                payload.extend_from_slice(&frame[layout.payload..]);
+            }
+            assert_eq!(
+                frames[2].len() - ETH_HDR_LEN,
+                frames[0].len() - ETH_HDR_LEN - 500
+            );
+            assert_eq!(payload, &segment[20..]);
+        }
+
+        // The GSO type must match the packet.
+        let frame = frame(true, PROTOCOL_TCP, &tcp_segment(100, 0), 16);
+        let mut buf = vnet_hdr(0, VIRTIO_NET_HDR_GSO_TCPV4, 1000, None);
+        buf.extend_from_slice(&frame);
+        assert_eq!(
+            complete_offloads(&mut buf, |_| ()),
+            Err(OffloadError::MalformedPacket)
+        );
+        let mut buf = vnet_hdr(0, VIRTIO_NET_HDR_GSO_TCPV6, 0, None);
+        buf.extend_from_slice(&frame);
+        assert_eq!(
+            complete_offloads(&mut buf, |_| ()),
+            Err(OffloadError::InvalidSegmentSize(0))
+        );
+    }
+
+    #[test]
+    fn test_udp_fragmentation() {
+        let mut udp = vec![0u8; UDP_HDR_LEN];
+        write_be_u16(&mut udp[4..], (UDP_HDR_LEN + 3000) as u16);
+        udp.extend((0..3000).map(|i| i as u8));
+        let frame = frame(false, PROTOCOL_UDP, &udp, 6);
+        let l4 = ETH_HDR_LEN + 20;
+
+        // The fragment size is rounded down to a multiple of 8 bytes.
+        let mut buf = vnet_hdr(
+            VIRTIO_NET_HDR_F_NEEDS_CSUM,
+            VIRTIO_NET_HDR_GSO_UDP,
+            1004,
+            Some((l4, 6)),
+        );
+        buf.extend_from_slice(&frame);
+        let mut frames = Vec::new();
+        complete_offloads(&mut buf, |frame| frames.push(frame.to_vec())).unwrap();
+        assert_eq!(frames.len(), 4);
+
+        let mut datagram = frame[..l4].to_vec();
+        for (i, fragment) in frames.iter().enumerate() {
+            assert_eq!(fold(add_words(0, &fragment[ETH_HDR_LEN..l4])), 0);
+            let flags_and_offset = read_be_u16(&fragment[ETH_HDR_LEN + 6..]);
+            assert_eq!(
+                flags_and_offset & !IPV4_MORE_FRAGMENTS,
+                (i * 1000 / 8) as u16
+            );
+            assert_eq!(flags_and_offset & IPV4_MORE_FRAGMENTS != 0, i < 3);
+            datagram.extend_from_slice(&fragment[l4..]);
+        }
+        // The reassembled datagram carries a valid checksum.
+        assert_eq!(datagram.len(), frame.len());
+        write_be_u16(&mut datagram[ETH_HDR_LEN + 6..], 0);
+        Layout::parse(&datagram, PROTOCOL_UDP)
+            .unwrap()
+            .complete_ipv4_header(&mut datagram);
+        verify(&datagram, PROTOCOL_UDP);
+
+        // IPv6 datagrams can't be fragmented.
+        let frame = self::frame(true, PROTOCOL_UDP, &udp, 6);
+        let mut buf = vnet_hdr(0, VIRTIO_NET_HDR_GSO_UDP, 1000, None);
+        buf.extend_from_slice(&frame);
+        assert_eq!(
+            complete_offloads(&mut buf, |_| ()),
+            Err(OffloadError::UnsupportedGso(VIRTIO_NET_HDR_GSO_UDP as u8))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            complete_offloads(&mut [0u8; 4], |_| ()),
+            Err(OffloadError::Truncated)
+        );
+        let mut buf = vnet_hdr(0, 2, 1000, None);
+        buf.extend_from_slice(&[0u8; ETH_HDR_LEN]);
+        assert_eq!(
+            complete_offloads(&mut buf, |_| ()),
+            Err(OffloadError::UnsupportedGso(2))
+        );
+        // An ARP frame can't be segmented.
+        let mut buf = vnet_hdr(0, VIRTIO_NET_HDR_GSO_TCPV4, 1000, None);
+        buf.extend_from_slice(&[0u8; ETH_HDR_LEN]);
+        let len = buf.len();
+        write_be_u16(&mut buf[len - 2..], 0x0806);
+        assert_eq!(
+            complete_offloads(&mut buf, |_| ()),
+            Err(OffloadError::MalformedPacket)
+        );
+
+        // The headers can't be shorter than their fixed fields.
+        let frame = frame(false, PROTOCOL_TCP, &tcp_segment(100, 0), 16);
+        for &(offset, byte) in &[(ETH_HDR_LEN, 0x44), (ETH_HDR_LEN + 20 + 12, 4 << 4)] {
+            let mut buf = vnet_hdr(0, VIRTIO_NET_HDR_GSO_TCPV4, 1000, None);
+            buf.extend_from_slice(&frame);
+            buf[vnet_hdr_len() + offset] = byte;
+            assert_eq!(
+                complete_offloads(&mut buf, |_| ()),
+                Err(OffloadError::InvalidHeaderLength)
+            );
+        }
+        // Nor longer than the frame.
+        let mut buf = vnet_hdr(0, VIRTIO_NET_HDR_GSO_TCPV4, 1000, None);
+        buf.extend_from_slice(&frame[..ETH_HDR_LEN + 20 + 16]);
+        assert_eq!(
+            complete_offloads(&mut buf, |_| ()),
+            Err(OffloadError::Truncated)
+        );
+
+        for err in &[
+            OffloadError::InvalidChecksumOffset,
+            OffloadError::InvalidHeaderLength,
+            OffloadError::InvalidSegmentSize(0),
+            OffloadError::MalformedPacket,
+            OffloadError::Truncated,
+            OffloadError::UnsupportedGso(2),
+        ] {
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+}
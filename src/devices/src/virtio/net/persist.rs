@@ -12,9 +12,12 @@ use rate_limiter::{persist::RateLimiterState, RateLimiter};
 use snapshot::Persist;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
+use virtio_gen::virtio_net::VIRTIO_NET_S_LINK_UP;
 use vm_memory::GuestMemoryMmap;
 
 use super::device::{ConfigSpace, Net};
+use super::rx_filter::RxFilter;
+use super::QUEUE_SIZES;
 
 use crate::virtio::persist::VirtioDeviceState;
 use crate::virtio::{DeviceState, Queue};
@@ -22,6 +25,64 @@ use crate::virtio::{DeviceState, Queue};
 #[derive(Versionize)]
 pub struct NetConfigSpaceState {
     guest_mac: [u8; MAC_ADDR_LEN],
+    #[version(start = 2, default_fn = "default_status")]
+    status: u16,
+    #[version(start = 3, default_fn = "default_mtu")]
+    mtu: u16,
+}
+
+impl NetConfigSpaceState {
+    fn default_status(_: u16) -> u16 {
+        VIRTIO_NET_S_LINK_UP as u16
+    }
+
+    fn default_mtu(_: u16) -> u16 {
+        0
+    }
+}
+
+#[derive(Versionize)]
+pub struct RxFilterState {
+    promisc: bool,
+    allmulti: bool,
+    unicast: Vec<[u8; MAC_ADDR_LEN]>,
+    multicast: Vec<[u8; MAC_ADDR_LEN]>,
+    unicast_overflow: bool,
+    multicast_overflow: bool,
+    allow_promisc: bool,
+}
+
+impl Persist for RxFilter {
+    type State = RxFilterState;
+    type ConstructorArgs = ();
+    type Error = ();
+
+    fn save(&self) -> Self::State {
+        RxFilterState {
+            promisc: self.promisc,
+            allmulti: self.allmulti,
+            unicast: self.unicast.clone(),
+            multicast: self.multicast.clone(),
+            unicast_overflow: self.unicast_overflow,
+            multicast_overflow: self.multicast_overflow,
+            allow_promisc: self.allow_promisc,
+        }
+    }
+
+    fn restore(
+        _: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> std::result::Result<Self, Self::Error> {
+        Ok(RxFilter {
+            promisc: state.promisc,
+            allmulti: state.allmulti,
+            unicast: state.unicast.clone(),
+            multicast: state.multicast.clone(),
+            unicast_overflow: state.unicast_overflow,
+            multicast_overflow: state.multicast_overflow,
+            allow_promisc: state.allow_promisc,
+        })
+    }
 }
 
 #[derive(Versionize)]
@@ -33,6 +94,15 @@ pub struct NetState {
     mmds_ns: Option<MmdsNetworkStackState>,
     config_space: NetConfigSpaceState,
     virtio_state: VirtioDeviceState,
+    #[version(start = 2, default_fn = "default_rx_filter")]
+    rx_filter: RxFilterState,
+}
+
+impl NetState {
+    // The snapshots taken before the filter existed don't offer the control queue.
+    fn default_rx_filter(_: u16) -> RxFilterState {
+        RxFilter::new(true).save()
+    }
 }
 
 pub struct NetConstructorArgs {
@@ -59,8 +129,11 @@ impl Persist for Net {
             mmds_ns: self.mmds_ns.as_ref().map(|mmds| mmds.save()),
             config_space: NetConfigSpaceState {
                 guest_mac: self.config_space.guest_mac,
+                status: self.config_space.status,
+                mtu: self.config_space.mtu,
             },
             virtio_state: VirtioDeviceState::from_device(self),
+            rx_filter: self.rx_filter.save(),
         }
     }
 
@@ -77,6 +150,7 @@ impl Persist for Net {
             state.id.clone(),
             state.tap_if_name.clone(),
             None,
+            Some(state.config_space.mtu).filter(|&mtu| mtu != 0),
             rx_rate_limiter,
             tx_rate_limiter,
             state.mmds_ns.is_some(),
@@ -96,11 +170,20 @@ impl Persist for Net {
             .iter()
             .map(|queue_state| Queue::restore((), &queue_state).unwrap())
             .collect();
+        // The snapshots taken before the control queue existed only hold the RX and TX queues.
+        for &size in &QUEUE_SIZES[net.queues.len()..] {
+            net.queues.push(Queue::new(size));
+        }
+        // Safe to unwrap because RxFilter::restore() cannot fail.
+        net.rx_filter = RxFilter::restore((), &state.rx_filter).unwrap();
         net.interrupt_status = Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
         net.avail_features = state.virtio_state.avail_features;
         net.acked_features = state.virtio_state.acked_features;
         net.config_space = ConfigSpace {
             guest_mac: state.config_space.guest_mac,
+            status: state.config_space.status,
+            mtu: state.config_space.mtu,
+            ..ConfigSpace::default()
         };
 
         net.guest_mac = Some(MacAddr::from_bytes_unchecked(
@@ -109,6 +192,10 @@ impl Persist for Net {
 
         if state.virtio_state.activated {
             net.device_state = DeviceState::Activated(constructor_args.mem);
+            // Make the event handler register the queue events once it gets subscribed.
+            net.activate_evt
+                .write(1)
+                .map_err(|e| Error::CreateNet(super::Error::EventFd(e)))?;
         }
 
         Ok(net)
@@ -177,6 +264,126 @@ mod tests {
             assert_eq!(restored_net.mmds_ns.is_some(), allow_mmds_requests);
             assert_eq!(restored_net.rx_rate_limiter, RateLimiter::default());
             assert_eq!(restored_net.tx_rate_limiter, RateLimiter::default());
+            assert!(restored_net.link_up());
         }
     }
+
+    #[test]
+    fn test_persistence_link_state() {
+        let guest_mem = Net::default_guest_memory();
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetConfigSpaceState::type_id(), 2);
+
+        let mut net = Net::default_net(TestMutators::default());
+        net.set_link_up(false).unwrap();
+        let state = <Net as Persist>::save(&net);
+        drop(net);
+
+        // The link state is only saved from version 2 on.
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let restored_net = Net::restore(
+            NetConstructorArgs {
+                mem: guest_mem.clone(),
+            },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+        assert!(restored_net.link_up());
+        drop(restored_net);
+
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_net = Net::restore(
+            NetConstructorArgs { mem: guest_mem },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert!(!restored_net.link_up());
+    }
+
+    #[test]
+    fn test_persistence_rx_filter() {
+        let guest_mem = Net::default_guest_memory();
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+
+        let mut net = Net::default_net(TestMutators::default());
+        net.set_promisc_allowed(false);
+        net.rx_filter.multicast.push([1, 0, 0x5e, 0, 0, 1]);
+        let state = <Net as Persist>::save(&net);
+        let rx_filter = net.rx_filter.clone();
+        drop(net);
+
+        // The filter is only saved from version 2 on.
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let restored_net = Net::restore(
+            NetConstructorArgs {
+                mem: guest_mem.clone(),
+            },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_net.rx_filter(), &RxFilter::new(true));
+        drop(restored_net);
+
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_net = Net::restore(
+            NetConstructorArgs { mem: guest_mem },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_net.rx_filter(), &rx_filter);
+    }
+
+    #[test]
+    fn test_persistence_mtu() {
+        let guest_mem = Net::default_guest_memory();
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetConfigSpaceState::type_id(), 3);
+
+        let mut net = Net::default_net(TestMutators::default());
+        net.config_space.mtu = 9000;
+        let state = <Net as Persist>::save(&net);
+        drop(net);
+
+        // The MTU is only saved from version 2 on.
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let restored_net = Net::restore(
+            NetConstructorArgs {
+                mem: guest_mem.clone(),
+            },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_net.mtu(), None);
+        drop(restored_net);
+
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_net = Net::restore(
+            NetConstructorArgs { mem: guest_mem },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_net.mtu(), Some(9000));
+    }
 }
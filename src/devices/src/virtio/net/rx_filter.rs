@@ -0,0 +1,312 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Filters the frames received by the guest, as its driver requests with the
+//! `VIRTIO_NET_CTRL_RX` and `VIRTIO_NET_CTRL_MAC` commands of the control queue.
+
+use std::fmt;
+use std::result;
+
+use dumbo::{MacAddr, MAC_ADDR_LEN};
+use utils::byte_order::read_le_u32;
+use virtio_gen::virtio_net::{
+    VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_TABLE_SET, VIRTIO_NET_CTRL_RX,
+    VIRTIO_NET_CTRL_RX_ALLMULTI, VIRTIO_NET_CTRL_RX_PROMISC,
+};
+
+/// The most addresses each MAC table of the filter holds. Beyond, the filter accepts all the
+/// unicast or multicast frames, as the table would.
+pub const MAX_MAC_TABLE_ENTRIES: usize = 64;
+
+const BROADCAST_MAC: [u8; MAC_ADDR_LEN] = [0xff; MAC_ADDR_LEN];
+
+/// Errors associated with the control commands of the filter.
+#[derive(Debug, PartialEq)]
+pub enum RxFilterError {
+    /// The command is shorter or longer than its class and command announce.
+    MalformedCommand,
+    /// The promiscuous mode isn't allowed for the guest.
+    PromiscDenied,
+    /// The class or the command is unknown, or relies on a feature not offered.
+    UnsupportedCommand(u8, u8),
+}
+
+impl fmt::Display for RxFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::RxFilterError::*;
+        match self {
+            MalformedCommand => write!(f, "Malformed control command."),
+            PromiscDenied => write!(f, "The promiscuous mode is denied."),
+            UnsupportedCommand(class, command) => write!(
+                f,
+                "Unsupported control command {} of class {}.",
+                command, class
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RxFilterError {}
+
+type Result<T> = result::Result<T, RxFilterError>;
+
+/// The receive filter of a net device.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RxFilter {
+    pub(crate) promisc: bool,
+    pub(crate) allmulti: bool,
+    pub(crate) unicast: Vec<[u8; MAC_ADDR_LEN]>,
+    pub(crate) multicast: Vec<[u8; MAC_ADDR_LEN]>,
+    pub(crate) unicast_overflow: bool,
+    pub(crate) multicast_overflow: bool,
+    pub(crate) allow_promisc: bool,
+}
+
+impl RxFilter {
+    /// Creates the filter a driver starts with: promiscuous, unless it isn't allowed.
+    pub fn new(allow_promisc: bool) -> Self {
+        RxFilter {
+            promisc: allow_promisc,
+            allmulti: false,
+            unicast: Vec::new(),
+            multicast: Vec::new(),
+            unicast_overflow: false,
+            multicast_overflow: false,
+            allow_promisc,
+        }
+    }
+
+    /// Specifies if the promiscuous mode is on.
+    pub fn promisc(&self) -> bool {
+        self.promisc
+    }
+
+    /// Specifies if all the multicast frames are received.
+    pub fn allmulti(&self) -> bool {
+        self.allmulti
+    }
+
+    /// Specifies if the guest may turn the promiscuous mode on.
+    pub fn allow_promisc(&self) -> bool {
+        self.allow_promisc
+    }
+
+    /// Specifies if the guest, whose primary address is `guest_mac`, receives `frame`.
+    pub fn accepts(&self, frame: &[u8], guest_mac: Option<&MacAddr>) -> bool {
+        let dst = match frame.get(..MAC_ADDR_LEN) {
+            Some(dst) => dst,
+            // The guest driver drops the runt frames itself.
+            None => return true,
+        };
+        if self.promisc || dst == BROADCAST_MAC {
+            return true;
+        }
+        let table_has = |table: &[[u8; MAC_ADDR_LEN]]| table.iter().any(|mac| mac == dst);
+        if dst[0] & 1 != 0 {
+            self.allmulti || self.multicast_overflow || table_has(&self.multicast)
+        } else {
+            guest_mac.map_or(false, |mac| mac.get_bytes() == dst)
+                || self.unicast_overflow
+                || table_has(&self.unicast)
+        }
+    }
+
+    /// Applies the command `command` of class `class`, whose data is `data`.
+    pub fn handle_command(&mut self, class: u8, command: u8, data: &[u8]) -> Result<()> {
+        match (u32::from(class), u32::from(command)) {
+            (VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_PROMISC) => {
+                let on = Self::switch(data)?;
+                if on && !self.allow_promisc {
+                    return Err(RxFilterError::PromiscDenied);
+                }
+                self.promisc = on;
+            }
+            (VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_ALLMULTI) => {
+                self.allmulti = Self::switch(data)?;
+            }
+            (VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_TABLE_SET) => {
+                // The unicast table is followed by the multicast one.
+                let (unicast, unicast_overflow, rest) = Self::mac_table(data)?;
+                let (multicast, multicast_overflow, rest) = Self::mac_table(rest)?;
+                if !rest.is_empty() {
+                    return Err(RxFilterError::MalformedCommand);
+                }
+                self.unicast = unicast;
+                self.unicast_overflow = unicast_overflow;
+                self.multicast = multicast;
+                self.multicast_overflow = multicast_overflow;
+            }
+            _ => return Err(RxFilterError::UnsupportedCommand(class, command)),
+        }
+        Ok(())
+    }
+
+    fn switch(data: &[u8]) -> Result<bool> {
+        match data {
+            [on] => Ok(*on != 0),
+            _ => Err(RxFilterError::MalformedCommand),
+        }
+    }
+
+    // Parses a MAC table, made of its number of entries and the entries, and returns the
+    // entries, whether they overflow the table, and the data after the table.
+    fn mac_table(data: &[u8]) -> Result<(Vec<[u8; MAC_ADDR_LEN]>, bool, &[u8])> {
+        let entries = data
+            .get(..4)
+            .map(|entries| read_le_u32(entries) as usize)
+            .ok_or(RxFilterError::MalformedCommand)?;
+        let len = entries
+            .checked_mul(MAC_ADDR_LEN)
+            .and_then(|len| len.checked_add(4))
+            .filter(|&len| len <= data.len())
+            .ok_or(RxFilterError::MalformedCommand)?;
+        if entries > MAX_MAC_TABLE_ENTRIES {
+            return Ok((Vec::new(), true, &data[len..]));
+        }
+        let table = data[4..len]
+            .chunks_exact(MAC_ADDR_LEN)
+            .map(|entry| {
+                let mut mac = [0u8; MAC_ADDR_LEN];
+                mac.copy_from_slice(entry);
+                mac
+            })
+            .collect();
+        Ok((table, false, &data[len..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUEST_MAC: [u8; MAC_ADDR_LEN] = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc];
+    const UNICAST_MAC: [u8; MAC_ADDR_LEN] = [0x02, 0, 0, 0, 0, 1];
+    const MULTICAST_MAC: [u8; MAC_ADDR_LEN] = [0x01, 0, 0x5e, 0, 0, 1];
+
+    fn mac_table(entries: &[[u8; MAC_ADDR_LEN]]) -> Vec<u8> {
+        let mut table = (entries.len() as u32).to_le_bytes().to_vec();
+        for entry in entries {
+            table.extend_from_slice(entry);
+        }
+        table
+    }
+
+    fn frame_to(dst: [u8; MAC_ADDR_LEN]) -> Vec<u8> {
+        let mut frame = dst.to_vec();
+        frame.extend_from_slice(&[0u8; 8]);
+        frame
+    }
+
+    #[test]
+    fn test_promisc() {
+        let guest_mac = MacAddr::from_bytes_unchecked(&GUEST_MAC);
+        let class = VIRTIO_NET_CTRL_RX as u8;
+        let promisc = VIRTIO_NET_CTRL_RX_PROMISC as u8;
+
+        // The driver starts off promiscuous.
+        let mut filter = RxFilter::new(true);
+        assert!(filter.promisc());
+        assert!(filter.accepts(&frame_to(UNICAST_MAC), Some(&guest_mac)));
+
+        filter.handle_command(class, promisc, &[0]).unwrap();
+        assert!(!filter.promisc());
+        assert!(!filter.accepts(&frame_to(UNICAST_MAC), Some(&guest_mac)));
+        assert!(filter.accepts(&frame_to(GUEST_MAC), Some(&guest_mac)));
+        assert!(filter.accepts(&frame_to(BROADCAST_MAC), Some(&guest_mac)));
+        assert!(!filter.accepts(&frame_to(GUEST_MAC), None));
+        assert!(filter.accepts(&[0xff; 4], None));
+        filter.handle_command(class, promisc, &[1]).unwrap();
+        assert!(filter.promisc());
+
+        // Unless the promiscuous mode isn't allowed.
+        let mut filter = RxFilter::new(false);
+        assert!(!filter.promisc());
+        assert_eq!(
+            filter.handle_command(class, promisc, &[1]),
+            Err(RxFilterError::PromiscDenied)
+        );
+        assert!(!filter.promisc());
+        filter.handle_command(class, promisc, &[0]).unwrap();
+
+        assert_eq!(
+            filter.handle_command(class, promisc, &[]),
+            Err(RxFilterError::MalformedCommand)
+        );
+        assert_eq!(
+            filter.handle_command(class, promisc, &[0, 0]),
+            Err(RxFilterError::MalformedCommand)
+        );
+    }
+
+    #[test]
+    fn test_multicast() {
+        let class = VIRTIO_NET_CTRL_RX as u8;
+        let allmulti = VIRTIO_NET_CTRL_RX_ALLMULTI as u8;
+        let mut filter = RxFilter::new(false);
+        assert!(!filter.accepts(&frame_to(MULTICAST_MAC), None));
+
+        filter.handle_command(class, allmulti, &[1]).unwrap();
+        assert!(filter.allmulti());
+        assert!(filter.accepts(&frame_to(MULTICAST_MAC), None));
+        assert!(!filter.accepts(&frame_to(UNICAST_MAC), None));
+        filter.handle_command(class, allmulti, &[0]).unwrap();
+        assert!(!filter.accepts(&frame_to(MULTICAST_MAC), None));
+    }
+
+    #[test]
+    fn test_mac_table() {
+        let class = VIRTIO_NET_CTRL_MAC as u8;
+        let table_set = VIRTIO_NET_CTRL_MAC_TABLE_SET as u8;
+        let mut filter = RxFilter::new(false);
+
+        let mut data = mac_table(&[UNICAST_MAC]);
+        data.extend(mac_table(&[MULTICAST_MAC]));
+        filter.handle_command(class, table_set, &data).unwrap();
+        assert!(filter.accepts(&frame_to(UNICAST_MAC), None));
+        assert!(filter.accepts(&frame_to(MULTICAST_MAC), None));
+        assert!(!filter.accepts(&frame_to([0x01, 0, 0x5e, 0, 0, 2]), None));
+        assert!(!filter.accepts(&frame_to([0x02, 0, 0, 0, 0, 2]), None));
+
+        // A table too long for the filter accepts all the frames of its kind.
+        let mut data = mac_table(&[]);
+        data.extend(mac_table(&[MULTICAST_MAC; MAX_MAC_TABLE_ENTRIES + 1]));
+        filter.handle_command(class, table_set, &data).unwrap();
+        assert!(!filter.accepts(&frame_to(UNICAST_MAC), None));
+        assert!(filter.accepts(&frame_to([0x01, 0, 0x5e, 0, 0, 2]), None));
+
+        // The tables must fill the data exactly.
+        for data in &[
+            vec![],
+            mac_table(&[UNICAST_MAC]),
+            [mac_table(&[]), mac_table(&[]), vec![0]].concat(),
+            [vec![2, 0, 0, 0], UNICAST_MAC.to_vec(), mac_table(&[])].concat(),
+            [vec![0xff; 4], mac_table(&[])].concat(),
+        ] {
+            assert_eq!(
+                filter.handle_command(class, table_set, data),
+                Err(RxFilterError::MalformedCommand)
+            );
+        }
+        // The tables are left as they were.
+        assert!(filter.multicast_overflow);
+    }
+
+    #[test]
+    fn test_unsupported_commands() {
+        let mut filter = RxFilter::new(true);
+        for &(class, command) in &[(0, 2), (1, 1), (2, 0), (5, 0)] {
+            assert_eq!(
+                filter.handle_command(class, command, &[1]),
+                Err(RxFilterError::UnsupportedCommand(class, command))
+            );
+        }
+
+        for err in &[
+            RxFilterError::MalformedCommand,
+            RxFilterError::PromiscDenied,
+            RxFilterError::UnsupportedCommand(0, 2),
+        ] {
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+}
@@ -0,0 +1,727 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A net backend exchanging the frames of the guest with a queue of a host NIC through an
+//! `AF_XDP` socket, skipping the tap and the bridge of the host network stack.
+//!
+//! The frames go through the UMEM, an area of the process memory registered with the socket,
+//! which the NIC reads and writes directly when its driver supports zero-copy. The UMEM is split
+//! in frames of `FRAME_SIZE` bytes: the first `ring_size` frames receive, the others transmit.
+//!
+//! The frames of the queue are only delivered to the socket by an XDP program attached to the
+//! NIC, redirecting them to an `XSKMAP`. When this map is pinned, the socket is inserted into it
+//! at the index of the queue.
+
+use std::ffi::CString;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::result;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use virtio_gen::virtio_net::virtio_net_hdr_v1;
+
+use super::backend::NetBackend;
+use super::offload::{complete_offloads, needs_offloads};
+
+// `AF_XDP` socket definitions, as in `include/uapi/linux/if_xdp.h`.
+const AF_XDP: libc::c_int = 44;
+const SOL_XDP: libc::c_int = 283;
+const XDP_MMAP_OFFSETS: libc::c_int = 1;
+const XDP_RX_RING: libc::c_int = 2;
+const XDP_TX_RING: libc::c_int = 3;
+const XDP_UMEM_REG: libc::c_int = 4;
+const XDP_UMEM_FILL_RING: libc::c_int = 5;
+const XDP_UMEM_COMPLETION_RING: libc::c_int = 6;
+const XDP_COPY: u16 = 1 << 1;
+const XDP_ZEROCOPY: u16 = 1 << 2;
+const XDP_PGOFF_RX_RING: libc::off_t = 0;
+const XDP_PGOFF_TX_RING: libc::off_t = 0x8000_0000;
+const XDP_UMEM_PGOFF_FILL_RING: libc::off_t = 0x1_0000_0000;
+const XDP_UMEM_PGOFF_COMPLETION_RING: libc::off_t = 0x1_8000_0000;
+// The headroom the kernel keeps in front of the received frames.
+const XDP_PACKET_HEADROOM: usize = 256;
+
+// BPF commands, as in `include/uapi/linux/bpf.h`.
+const BPF_MAP_UPDATE_ELEM: libc::c_long = 2;
+const BPF_OBJ_GET: libc::c_long = 7;
+
+/// The size of a frame of the UMEM.
+pub const FRAME_SIZE: usize = 4096;
+/// The largest MTU whose frames, along with a VLAN tag, fit in a frame of the UMEM.
+pub const MAX_MTU: u16 = (FRAME_SIZE - XDP_PACKET_HEADROOM - 18) as u16;
+
+// Mirrors `struct sockaddr_xdp`.
+#[repr(C)]
+#[derive(Default)]
+struct SockaddrXdp {
+    family: u16,
+    flags: u16,
+    ifindex: u32,
+    queue_id: u32,
+    shared_umem_fd: u32,
+}
+
+// Mirrors `struct xdp_umem_reg`, whose last field is padding on the older kernels.
+#[repr(C)]
+#[derive(Default)]
+struct XdpUmemReg {
+    addr: u64,
+    len: u64,
+    chunk_size: u32,
+    headroom: u32,
+    flags: u32,
+    tx_metadata_len: u32,
+}
+
+// Mirrors `struct xdp_desc`.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct XdpDesc {
+    addr: u64,
+    len: u32,
+    options: u32,
+}
+
+// Mirrors the `BPF_OBJ_GET` member of `union bpf_attr`.
+#[repr(C)]
+#[derive(Default)]
+struct BpfObjGetAttr {
+    pathname: u64,
+    bpf_fd: u32,
+    file_flags: u32,
+}
+
+// Mirrors the `BPF_MAP_*_ELEM` member of `union bpf_attr`.
+#[repr(C)]
+#[derive(Default)]
+struct BpfMapElemAttr {
+    map_fd: u32,
+    pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+/// Errors associated with opening an `AF_XDP` socket.
+#[derive(Debug)]
+pub enum Error {
+    /// The size of the rings is not a power of 2.
+    InvalidRingSize(u32),
+    /// The UMEM doesn't hold twice as many frames as the rings.
+    InvalidUmemSize(u32),
+    /// The frames of the MTU don't fit in the frames of the UMEM.
+    MtuTooLarge(u16),
+    /// There is no host interface with this name.
+    InterfaceNotFound(String),
+    /// Cannot create the socket.
+    Socket(io::Error),
+    /// Cannot allocate or register the UMEM.
+    Umem(io::Error),
+    /// Cannot create or map the rings.
+    Rings(io::Error),
+    /// Cannot bind the socket to the queue of the interface.
+    Bind(io::Error),
+    /// Cannot insert the socket into the pinned `XSKMAP`.
+    XsksMap(PathBuf, io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match self {
+            InvalidRingSize(size) => {
+                write!(f, "Invalid ring size {}, it must be a power of 2.", size)
+            }
+            InvalidUmemSize(frames) => write!(
+                f,
+                "Invalid UMEM of {} frames, it must hold twice as many frames as the rings.",
+                frames
+            ),
+            MtuTooLarge(mtu) => write!(
+                f,
+                "Invalid MTU {}, the AF_XDP backend supports MTUs up to {}.",
+                mtu, MAX_MTU
+            ),
+            InterfaceNotFound(name) => write!(f, "There is no host interface named {}.", name),
+            Socket(err) => write!(f, "Cannot create the AF_XDP socket: {}", err),
+            Umem(err) => write!(f, "Cannot register the UMEM: {}", err),
+            Rings(err) => write!(f, "Cannot set the rings of the AF_XDP socket up: {}", err),
+            Bind(err) => write!(f, "Cannot bind the AF_XDP socket: {}", err),
+            XsksMap(path, err) => write!(
+                f,
+                "Cannot insert the AF_XDP socket into {}: {}",
+                path.display(),
+                err
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+type Result<T> = result::Result<T, Error>;
+
+/// The sizing of an `AF_XDP` socket and the queue it is bound to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct XdpOptions {
+    /// The queue of the interface the socket receives from and transmits to.
+    pub queue_id: u32,
+    /// The number of entries of each ring of the socket, a power of 2.
+    pub ring_size: u32,
+    /// The number of frames of the UMEM, at least twice `ring_size`.
+    pub umem_frames: u32,
+    /// Whether the NIC accesses the UMEM directly. Otherwise, the kernel copies the frames.
+    pub zero_copy: bool,
+    /// The pinned `XSKMAP` of the XDP program redirecting the frames of the queue, if any.
+    pub xsks_map: Option<PathBuf>,
+}
+
+// A mapping in the process address space, unmapped on drop.
+struct Mapping {
+    addr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    // Maps `len` bytes of `fd` at `offset`, or anonymous memory if `fd` is negative.
+    fn new(len: usize, fd: RawFd, offset: libc::off_t) -> io::Result<Mapping> {
+        let flags = if fd < 0 {
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS
+        } else {
+            libc::MAP_SHARED | libc::MAP_POPULATE
+        };
+        // Safe because a new mapping is created, and the result is checked.
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                flags,
+                fd,
+                offset,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping {
+            addr: addr as *mut u8,
+            len,
+        })
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // Safe because the mapping is owned, and not used past this point.
+        unsafe { libc::munmap(self.addr as *mut libc::c_void, self.len) };
+    }
+}
+
+// A ring shared with the kernel. Either the kernel produces its entries and the process consumes
+// them, or the other way round.
+struct Ring {
+    // Unmapped on drop.
+    _mapping: Mapping,
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    entries: *mut u8,
+    entry_size: usize,
+    size: u32,
+    // The index the process moves, i.e. the producer index of the rings the process produces.
+    local: u32,
+}
+
+impl Ring {
+    // Maps the ring of the socket `fd` at `pgoff`, given the offsets of its producer index, its
+    // consumer index and its entries.
+    #[allow(clippy::cast_ptr_alignment)]
+    fn new(
+        fd: RawFd,
+        pgoff: libc::off_t,
+        offsets: [u64; 3],
+        size: u32,
+        entry_size: usize,
+    ) -> io::Result<Ring> {
+        let mapping = Mapping::new(offsets[2] as usize + size as usize * entry_size, fd, pgoff)?;
+        // Safe because the offsets are within the mapping, as reported by the kernel.
+        let (producer, consumer, entries) = unsafe {
+            (
+                mapping.addr.add(offsets[0] as usize) as *const AtomicU32,
+                mapping.addr.add(offsets[1] as usize) as *const AtomicU32,
+                mapping.addr.add(offsets[2] as usize),
+            )
+        };
+        Ok(Ring {
+            _mapping: mapping,
+            producer,
+            consumer,
+            entries,
+            entry_size,
+            size,
+            local: 0,
+        })
+    }
+
+    fn producer(&self) -> u32 {
+        // Safe because the index lives as long as the mapping.
+        unsafe { (*self.producer).load(Ordering::Acquire) }
+    }
+
+    fn consumer(&self) -> u32 {
+        // Safe because the index lives as long as the mapping.
+        unsafe { (*self.consumer).load(Ordering::Acquire) }
+    }
+
+    fn entry(&self, index: u32) -> *mut u8 {
+        // Safe because the entry is within the mapping, the size being a power of 2.
+        unsafe {
+            self.entries
+                .add((index & (self.size - 1)) as usize * self.entry_size)
+        }
+    }
+
+    // Whether the ring the process produces has no free entry.
+    fn is_full(&self) -> bool {
+        self.local.wrapping_sub(self.consumer()) >= self.size
+    }
+
+    // Whether the ring the process consumes has no pending entry.
+    fn is_empty(&self) -> bool {
+        self.local == self.producer()
+    }
+
+    // Produces `entry` in a ring which isn't full.
+    #[allow(clippy::cast_ptr_alignment)]
+    fn push<T>(&mut self, entry: T) {
+        // Safe because the entry is within the mapping, and `T` has the size of the entries.
+        unsafe { ptr::write(self.entry(self.local) as *mut T, entry) };
+        self.local = self.local.wrapping_add(1);
+        // Safe because the index lives as long as the mapping.
+        unsafe { (*self.producer).store(self.local, Ordering::Release) };
+    }
+
+    // Consumes the next entry of a ring which isn't empty.
+    #[allow(clippy::cast_ptr_alignment)]
+    fn pop<T: Copy>(&mut self) -> T {
+        // Safe because the entry is within the mapping, and `T` has the size of the entries.
+        let entry = unsafe { ptr::read(self.entry(self.local) as *const T) };
+        self.local = self.local.wrapping_add(1);
+        // Safe because the index lives as long as the mapping.
+        unsafe { (*self.consumer).store(self.local, Ordering::Release) };
+        entry
+    }
+}
+
+// Splits the ring offsets reported by the kernel, for the RX, the TX, the fill and the
+// completion rings. The kernels older than 5.4 don't report the offset of the flags.
+fn split_ring_offsets(raw: &[u64], len: usize) -> [[u64; 3]; 4] {
+    let stride = len / mem::size_of::<u64>() / 4;
+    let mut offsets = [[0; 3]; 4];
+    for (ring, ring_offsets) in offsets.iter_mut().enumerate() {
+        ring_offsets.copy_from_slice(&raw[ring * stride..ring * stride + 3]);
+    }
+    offsets
+}
+
+fn set_option<T>(fd: RawFd, name: libc::c_int, value: &T) -> io::Result<()> {
+    // Safe because the size of the value is passed along, and the result is checked.
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            SOL_XDP,
+            name,
+            value as *const T as *const libc::c_void,
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn bpf<T>(cmd: libc::c_long, attr: &T) -> io::Result<libc::c_long> {
+    // Safe because the size of the attributes is passed along, and the result is checked.
+    let ret = unsafe { libc::syscall(libc::SYS_bpf, cmd, attr as *const T, mem::size_of::<T>()) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret)
+}
+
+// Inserts the socket `fd` into the `XSKMAP` pinned at `path`, at the index `queue_id`.
+fn insert_into_xsks_map(path: &Path, queue_id: u32, fd: RawFd) -> io::Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let map_fd = bpf(
+        BPF_OBJ_GET,
+        &BpfObjGetAttr {
+            pathname: c_path.as_ptr() as u64,
+            ..Default::default()
+        },
+    )?;
+    // Safe because the map file descriptor was just created, and is owned.
+    let map = unsafe { File::from_raw_fd(map_fd as RawFd) };
+    let value = fd as u32;
+    bpf(
+        BPF_MAP_UPDATE_ELEM,
+        &BpfMapElemAttr {
+            map_fd: map.as_raw_fd() as u32,
+            key: &queue_id as *const u32 as u64,
+            value: &value as *const u32 as u64,
+            ..Default::default()
+        },
+    )?;
+    Ok(())
+}
+
+fn vnet_hdr_len() -> usize {
+    mem::size_of::<virtio_net_hdr_v1>()
+}
+
+/// An `AF_XDP` socket bound to a queue of a host interface.
+///
+/// The NIC takes the frames without their virtio-net header, so the offloads of the transmitted
+/// frames are completed in software. The received frames get an empty header.
+pub struct XdpSocket {
+    socket: File,
+    rx: Ring,
+    tx: Ring,
+    fill: Ring,
+    completion: Ring,
+    // The transmit frames of the UMEM which are not in flight.
+    tx_frames: Vec<u64>,
+    // Dropped last, once the kernel doesn't use the UMEM anymore.
+    umem: Mapping,
+}
+
+// The raw pointers of the socket only point to its own mappings.
+unsafe impl Send for XdpSocket {}
+
+impl XdpSocket {
+    /// Opens an `AF_XDP` socket on the host interface `if_name`, for frames up to `mtu`.
+    pub fn open(if_name: &str, mtu: Option<u16>, options: &XdpOptions) -> Result<XdpSocket> {
+        if options.ring_size == 0 || !options.ring_size.is_power_of_two() {
+            return Err(Error::InvalidRingSize(options.ring_size));
+        }
+        if options.umem_frames / 2 < options.ring_size {
+            return Err(Error::InvalidUmemSize(options.umem_frames));
+        }
+        if let Some(mtu) = mtu.filter(|&mtu| mtu > MAX_MTU) {
+            return Err(Error::MtuTooLarge(mtu));
+        }
+        let ifindex = CString::new(if_name)
+            .ok()
+            // Safe because the name is a valid C string.
+            .map(|name| unsafe { libc::if_nametoindex(name.as_ptr()) })
+            .filter(|&ifindex| ifindex != 0)
+            .ok_or_else(|| Error::InterfaceNotFound(if_name.to_string()))?;
+
+        // Safe because the result is checked.
+        let fd = unsafe { libc::socket(AF_XDP, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(Error::Socket(io::Error::last_os_error()));
+        }
+        // Safe because the socket was just created, and is owned.
+        let socket = unsafe { File::from_raw_fd(fd) };
+
+        let umem_len = options.umem_frames as usize * FRAME_SIZE;
+        let umem = Mapping::new(umem_len, -1, 0).map_err(Error::Umem)?;
+        set_option(
+            fd,
+            XDP_UMEM_REG,
+            &XdpUmemReg {
+                addr: umem.addr as u64,
+                len: umem_len as u64,
+                chunk_size: FRAME_SIZE as u32,
+                ..Default::default()
+            },
+        )
+        .map_err(Error::Umem)?;
+
+        for &ring in &[
+            XDP_RX_RING,
+            XDP_TX_RING,
+            XDP_UMEM_FILL_RING,
+            XDP_UMEM_COMPLETION_RING,
+        ] {
+            set_option(fd, ring, &options.ring_size).map_err(Error::Rings)?;
+        }
+        let mut raw_offsets = [0u64; 16];
+        let mut len = mem::size_of_val(&raw_offsets) as libc::socklen_t;
+        // Safe because the size of the buffer is passed along, and the result is checked.
+        let ret = unsafe {
+            libc::getsockopt(
+                fd,
+                SOL_XDP,
+                XDP_MMAP_OFFSETS,
+                raw_offsets.as_mut_ptr() as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Rings(io::Error::last_os_error()));
+        }
+        let [rx_offsets, tx_offsets, fill_offsets, completion_offsets] =
+            split_ring_offsets(&raw_offsets, len as usize);
+        let size = options.ring_size;
+        let desc_size = mem::size_of::<XdpDesc>();
+        let addr_size = mem::size_of::<u64>();
+        let rx = Ring::new(fd, XDP_PGOFF_RX_RING, rx_offsets, size, desc_size);
+        let tx = Ring::new(fd, XDP_PGOFF_TX_RING, tx_offsets, size, desc_size);
+        let fill = Ring::new(fd, XDP_UMEM_PGOFF_FILL_RING, fill_offsets, size, addr_size);
+        let completion = Ring::new(
+            fd,
+            XDP_UMEM_PGOFF_COMPLETION_RING,
+            completion_offsets,
+            size,
+            addr_size,
+        );
+
+        let mut xdp_socket = XdpSocket {
+            socket,
+            rx: rx.map_err(Error::Rings)?,
+            tx: tx.map_err(Error::Rings)?,
+            fill: fill.map_err(Error::Rings)?,
+            completion: completion.map_err(Error::Rings)?,
+            tx_frames: (size..options.umem_frames)
+                .map(|frame| u64::from(frame) * FRAME_SIZE as u64)
+                .collect(),
+            umem,
+        };
+        // Hand the receive frames over to the kernel.
+        for frame in 0..size {
+            xdp_socket.fill.push(u64::from(frame) * FRAME_SIZE as u64);
+        }
+
+        let addr = SockaddrXdp {
+            family: AF_XDP as u16,
+            flags: if options.zero_copy {
+                XDP_ZEROCOPY
+            } else {
+                XDP_COPY
+            },
+            ifindex,
+            queue_id: options.queue_id,
+            ..Default::default()
+        };
+        // Safe because the size of the address is passed along, and the result is checked.
+        let ret = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const SockaddrXdp as *const libc::sockaddr,
+                mem::size_of::<SockaddrXdp>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Bind(io::Error::last_os_error()));
+        }
+
+        if let Some(path) = options.xsks_map.as_ref() {
+            insert_into_xsks_map(path, options.queue_id, fd)
+                .map_err(|err| Error::XsksMap(path.clone(), err))?;
+        }
+        Ok(xdp_socket)
+    }
+
+    // Takes the frames the kernel is done transmitting back.
+    fn reclaim_tx_frames(&mut self) {
+        while !self.completion.is_empty() {
+            let frame = self.completion.pop::<u64>();
+            self.tx_frames.push(frame);
+        }
+    }
+
+    // Wakes the kernel up to transmit the frames of the TX ring.
+    fn kick_tx(&self) -> io::Result<()> {
+        // Safe because an empty message is sent, and the result is checked.
+        let ret = unsafe {
+            let msg: libc::msghdr = mem::zeroed();
+            libc::sendmsg(self.socket.as_raw_fd(), &msg, libc::MSG_DONTWAIT)
+        };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            // The kernel is still busy with the previous frames, which it transmits along.
+            match err.raw_os_error() {
+                Some(libc::EAGAIN) | Some(libc::EBUSY) | Some(libc::ENOBUFS) => (),
+                _ => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    // Copies `frame` to a free transmit frame of the UMEM, and queues it on the TX ring.
+    fn push_tx_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        if frame.len() > FRAME_SIZE {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+        if self.tx.is_full() || self.tx_frames.is_empty() {
+            return Err(io::Error::from(io::ErrorKind::WouldBlock));
+        }
+        let addr = self.tx_frames.pop().unwrap();
+        self.umem_slice(addr, frame.len())?.copy_from_slice(frame);
+        self.tx.push(XdpDesc {
+            addr,
+            len: frame.len() as u32,
+            options: 0,
+        });
+        Ok(())
+    }
+
+    fn umem_slice(&mut self, addr: u64, len: usize) -> io::Result<&mut [u8]> {
+        if addr as usize + len > self.umem.len {
+            return Err(io::Error::from(io::ErrorKind::InvalidData));
+        }
+        // Safe because the range is within the UMEM, which is only accessed through `self`.
+        Ok(unsafe { std::slice::from_raw_parts_mut(self.umem.addr.add(addr as usize), len) })
+    }
+}
+
+impl AsRawFd for XdpSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+impl NetBackend for XdpSocket {
+    fn read_frame(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.rx.is_empty() {
+            return Err(io::Error::from(io::ErrorKind::WouldBlock));
+        }
+        let desc = self.rx.pop::<XdpDesc>();
+        let len = desc.len as usize;
+        let result = if vnet_hdr_len() + len > buf.len() {
+            Err(io::Error::from(io::ErrorKind::InvalidData))
+        } else {
+            self.umem_slice(desc.addr, len).map(|frame| {
+                for byte in &mut buf[..vnet_hdr_len()] {
+                    *byte = 0;
+                }
+                buf[vnet_hdr_len()..vnet_hdr_len() + len].copy_from_slice(frame);
+                vnet_hdr_len() + len
+            })
+        };
+        // Give the frame back to the kernel, which has as many fill entries as receive frames.
+        self.fill.push(desc.addr & !(FRAME_SIZE as u64 - 1));
+        result
+    }
+
+    fn write_frame(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.reclaim_tx_frames();
+        if !needs_offloads(buf) {
+            let frame = buf
+                .get(vnet_hdr_len()..)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+            self.push_tx_frame(frame)?;
+            return self.kick_tx();
+        }
+
+        // The offloads are completed in place, so the frame is copied out of the shared buffer.
+        let mut buf = buf.to_vec();
+        let mut result = Ok(());
+        complete_offloads(&mut buf, |frame| {
+            if result.is_ok() {
+                result = self.push_tx_frame(frame);
+            }
+        })
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+        // The frames queued before a failure are transmitted anyway.
+        let kicked = self.kick_tx();
+        result.and(kicked)
+    }
+
+    fn offloads(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> XdpOptions {
+        XdpOptions {
+            queue_id: 0,
+            ring_size: 64,
+            umem_frames: 128,
+            zero_copy: false,
+            xsks_map: None,
+        }
+    }
+
+    #[test]
+    fn test_open_invalid_options() {
+        let mut invalid = options();
+        invalid.ring_size = 48;
+        match XdpSocket::open("lo", None, &invalid) {
+            Err(Error::InvalidRingSize(48)) => (),
+            _ => panic!("Unexpected result."),
+        }
+        invalid.ring_size = 0;
+        match XdpSocket::open("lo", None, &invalid) {
+            Err(Error::InvalidRingSize(0)) => (),
+            _ => panic!("Unexpected result."),
+        }
+
+        let mut invalid = options();
+        invalid.umem_frames = 127;
+        match XdpSocket::open("lo", None, &invalid) {
+            Err(Error::InvalidUmemSize(127)) => (),
+            _ => panic!("Unexpected result."),
+        }
+
+        match XdpSocket::open("lo", Some(MAX_MTU + 1), &options()) {
+            Err(Error::MtuTooLarge(mtu)) => assert_eq!(mtu, MAX_MTU + 1),
+            _ => panic!("Unexpected result."),
+        }
+
+        match XdpSocket::open("fc-no-such-if", Some(MAX_MTU), &options()) {
+            Err(Error::InterfaceNotFound(name)) => assert_eq!(name, "fc-no-such-if"),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_split_ring_offsets() {
+        let raw: Vec<u64> = (0..16).collect();
+        // With the offsets of the flags.
+        assert_eq!(
+            split_ring_offsets(&raw, 128),
+            [[0, 1, 2], [4, 5, 6], [8, 9, 10], [12, 13, 14]]
+        );
+        // Without them.
+        assert_eq!(
+            split_ring_offsets(&raw, 96),
+            [[0, 1, 2], [3, 4, 5], [6, 7, 8], [9, 10, 11]]
+        );
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::Error::*;
+
+        for err in &[
+            InvalidRingSize(3),
+            InvalidUmemSize(1),
+            MtuTooLarge(9000),
+            InterfaceNotFound("eth9".to_string()),
+            Socket(io::Error::from_raw_os_error(0)),
+            Umem(io::Error::from_raw_os_error(0)),
+            Rings(io::Error::from_raw_os_error(0)),
+            Bind(io::Error::from_raw_os_error(0)),
+            XsksMap(
+                PathBuf::from("/sys/fs/bpf/xsks_map"),
+                io::Error::from_raw_os_error(0),
+            ),
+        ] {
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+}
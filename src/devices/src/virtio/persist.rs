@@ -5,7 +5,7 @@
 
 use super::device::*;
 use super::queue::*;
-use crate::virtio::MmioTransport;
+use crate::virtio::{device_status, MmioTransport};
 use crate::vm_memory::Address;
 use snapshot::Persist;
 use versionize::{VersionMap, Versionize, VersionizeResult};
@@ -71,6 +71,8 @@ impl Persist for Queue {
             used_ring: GuestAddress::new(state.used_ring),
             next_avail: state.next_avail,
             next_used: state.next_used,
+            violations: 0,
+            violation_metrics: ViolationMetrics::default(),
         })
     }
 }
@@ -140,6 +142,9 @@ impl Persist for MmioTransport {
         transport.queue_select = state.queue_select;
         transport.device_status = state.device_status;
         transport.config_generation = state.config_generation;
+        if state.device_status & device_status::FAILED != 0 {
+            transport.lifecycle = DeviceLifecycle::Failed;
+        }
         Ok(transport)
     }
 }
@@ -6,12 +6,23 @@
 // found in the THIRD-PARTY file.
 
 use std::cmp::min;
+use std::fmt;
 use std::num::Wrapping;
+use std::result;
 use std::sync::atomic::{fence, Ordering};
+use std::sync::Arc;
+
+use super::dirty_pages;
+use logger::{Metric, VirtioQueueMetrics};
 use vm_memory::{Address, ByteValued, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap};
 
 pub(super) const VIRTQ_DESC_F_NEXT: u16 = 0x1;
 pub(super) const VIRTQ_DESC_F_WRITE: u16 = 0x2;
+pub(super) const VIRTQ_DESC_F_INDIRECT: u16 = 0x4;
+
+/// The number of malformed descriptor chains a driver can make available on a queue before the
+/// queue is quarantined.
+pub const MAX_DESCRIPTOR_VIOLATIONS: u16 = 16;
 
 // GuestMemoryMmap::read_obj_from_addr() will be used to fetch the descriptor,
 // which has an explicit constraint that the entire descriptor doesn't
@@ -108,6 +119,48 @@ impl<'a> DescriptorChain<'a> {
         !self.has_next() || self.next < self.queue_size
     }
 
+    // Checks the buffer of this descriptor, regardless of the descriptors linked after it.
+    fn check_buffer(&self) -> result::Result<(), &'static str> {
+        // The devices never offer VIRTIO_RING_F_INDIRECT_DESC.
+        if self.flags & VIRTQ_DESC_F_INDIRECT != 0 {
+            return Err("indirect descriptor");
+        }
+        // An empty buffer is never accessed, wherever it is.
+        if self.len > 0
+            && (!self.mem.address_in_range(self.addr)
+                || self
+                    .mem
+                    .checked_offset(self.addr, self.len as usize - 1)
+                    .is_none())
+        {
+            return Err("buffer out of guest memory");
+        }
+        Ok(())
+    }
+
+    // Checks the whole chain headed by this descriptor: every descriptor has to be in the
+    // descriptor table, its buffer in guest memory, and the chain can't loop.
+    fn check_chain(&self) -> result::Result<(), &'static str> {
+        self.check_buffer()?;
+        let mut chain_len = 1;
+        let mut flags = self.flags;
+        let mut next = self.next;
+        while flags & VIRTQ_DESC_F_NEXT != 0 {
+            // A chain longer than the descriptor table loops.
+            if chain_len >= self.queue_size {
+                return Err("looping descriptor chain");
+            }
+            let desc =
+                DescriptorChain::checked_new(self.mem, self.desc_table, self.queue_size, next)
+                    .ok_or("descriptor out of the descriptor table")?;
+            desc.check_buffer()?;
+            chain_len += 1;
+            flags = desc.flags;
+            next = desc.next;
+        }
+        Ok(())
+    }
+
     /// Gets if this descriptor chain has another descriptor chain linked after it.
     pub fn has_next(&self) -> bool {
         self.flags & VIRTQ_DESC_F_NEXT != 0 && self.ttl > 1
@@ -139,6 +192,53 @@ impl<'a> DescriptorChain<'a> {
     }
 }
 
+/// The state of a virtio queue, along with the indexes of its rings in guest memory, for
+/// debugging.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueueInfo {
+    /// The maximal size in elements offered by the device.
+    pub max_size: u16,
+    /// The queue size in elements the driver selected.
+    pub size: u16,
+    /// Whether the driver finished configuring the queue.
+    pub ready: bool,
+    /// Guest physical address of the descriptor table.
+    pub desc_table: u64,
+    /// Guest physical address of the available ring.
+    pub avail_ring: u64,
+    /// Guest physical address of the used ring.
+    pub used_ring: u64,
+    /// The next entry of the available ring the device pops.
+    pub next_avail: u16,
+    /// The next entry of the used ring the device fills.
+    pub next_used: u16,
+    /// The index of the available ring, written by the driver, unless the queue isn't ready or
+    /// the ring is out of guest memory.
+    pub avail_idx: Option<u16>,
+    /// The index of the used ring, written by the device, unless the queue isn't ready or the
+    /// ring is out of guest memory.
+    pub used_idx: Option<u16>,
+    /// The number of malformed descriptor chains the driver made available.
+    pub violations: u16,
+}
+
+/// Where the malformed descriptor chains of a queue are counted, if anywhere. The metrics are no
+/// part of the state of the queue, so they always compare equal.
+#[derive(Clone, Default)]
+pub(crate) struct ViolationMetrics(Option<Arc<VirtioQueueMetrics>>);
+
+impl fmt::Debug for ViolationMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ViolationMetrics")
+    }
+}
+
+impl PartialEq for ViolationMetrics {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 /// A virtio queue's parameters.
 pub struct Queue {
@@ -162,6 +262,11 @@ pub struct Queue {
 
     pub(crate) next_avail: Wrapping<u16>,
     pub(crate) next_used: Wrapping<u16>,
+
+    // The number of malformed descriptor chains the driver made available since it set up the
+    // queue.
+    pub(crate) violations: u16,
+    pub(crate) violation_metrics: ViolationMetrics,
 }
 
 impl Queue {
@@ -176,6 +281,8 @@ impl Queue {
             used_ring: GuestAddress(0),
             next_avail: Wrapping(0),
             next_used: Wrapping(0),
+            violations: 0,
+            violation_metrics: ViolationMetrics::default(),
         }
     }
 
@@ -258,12 +365,38 @@ impl Queue {
         self.len(mem) == 0
     }
 
+    /// Whether the driver made available too many malformed descriptor chains, after which the
+    /// queue is no longer popped until the driver resets the device.
+    pub fn is_quarantined(&self) -> bool {
+        self.violations >= MAX_DESCRIPTOR_VIOLATIONS
+    }
+
+    /// Counts the malformed descriptor chains of the queue in `metrics`.
+    pub fn set_violation_metrics(&mut self, metrics: Arc<VirtioQueueMetrics>) {
+        self.violation_metrics = ViolationMetrics(Some(metrics));
+    }
+
     /// Pop the first available descriptor chain from the avail ring.
+    ///
+    /// The malformed descriptor chains are skipped, without being added to the used ring, until
+    /// the queue is quarantined.
     pub fn pop<'a, 'b>(&'a mut self, mem: &'b GuestMemoryMmap) -> Option<DescriptorChain<'b>> {
-        if self.len(mem) == 0 {
-            return None;
+        while !self.is_quarantined() && self.len(mem) != 0 {
+            let desc_index = self.next_avail_index(mem);
+            self.next_avail += Wrapping(1);
+            match DescriptorChain::checked_new(mem, self.desc_table, self.actual_size(), desc_index)
+                .ok_or("descriptor out of the descriptor table")
+                .and_then(|dc| dc.check_chain().map(|_| dc))
+            {
+                Ok(dc) => return Some(dc),
+                Err(reason) => self.record_violation(desc_index, reason),
+            }
         }
+        None
+    }
 
+    // Reads the index of the head of the next available descriptor chain.
+    fn next_avail_index(&self, mem: &GuestMemoryMmap) -> u16 {
         // We'll need to find the first available descriptor, that we haven't yet popped.
         // In a naive notation, that would be:
         // `descriptor_table[avail_ring[next_avail]]`.
@@ -292,16 +425,28 @@ impl Queue {
         // `self.is_valid()` already performed all the bound checks on the descriptor table
         // and virtq rings, so it's safe to unwrap guest memory reads and to use unchecked
         // offsets.
-        let desc_index: u16 = mem
-            .read_obj(self.avail_ring.unchecked_add(u64::from(index_offset)))
-            .unwrap();
+        mem.read_obj(self.avail_ring.unchecked_add(u64::from(index_offset)))
+            .unwrap()
+    }
 
-        DescriptorChain::checked_new(mem, self.desc_table, self.actual_size(), desc_index).map(
-            |dc| {
-                self.next_avail += Wrapping(1);
-                dc
-            },
-        )
+    fn record_violation(&mut self, desc_index: u16, reason: &str) {
+        error!(
+            "virtio queue: skipping malformed descriptor chain {}: {}",
+            desc_index, reason
+        );
+        self.violations += 1;
+        if let Some(metrics) = self.violation_metrics.0.as_ref() {
+            metrics.descriptor_violations.inc();
+            if self.is_quarantined() {
+                metrics.quarantined_queues.inc();
+            }
+        }
+        if self.is_quarantined() {
+            error!(
+                "virtio queue: quarantined after {} malformed descriptor chains",
+                self.violations
+            );
+        }
     }
 
     /// Undo the effects of the last `self.pop()` call.
@@ -336,6 +481,25 @@ impl Queue {
 
         mem.write_obj(self.next_used.0 as u16, used_ring.unchecked_add(2))
             .unwrap();
+
+        if dirty_pages::enabled(mem) {
+            self.mark_dirty(mem, desc_index);
+            dirty_pages::mark(mem, used_ring.unchecked_add(2), 2);
+            dirty_pages::mark(mem, used_elem, 8);
+        }
+    }
+
+    // Marks the buffers the device could write in the chain headed by `desc_index` as dirty.
+    // The length reported to the driver is not trusted to cover all the bytes written.
+    fn mark_dirty(&self, mem: &GuestMemoryMmap, desc_index: u16) {
+        let mut desc =
+            DescriptorChain::checked_new(mem, self.desc_table, self.actual_size(), desc_index);
+        while let Some(chain) = desc {
+            if chain.is_write_only() {
+                dirty_pages::mark(mem, chain.addr, u64::from(chain.len));
+            }
+            desc = chain.next_descriptor();
+        }
     }
 
     /// Goes back one position in the available descriptor chain offered by the driver.
@@ -345,6 +509,31 @@ impl Queue {
         self.next_avail -= Wrapping(1);
     }
 
+    /// Describes the queue, reading the indexes of its rings from guest memory. Unlike the
+    /// other accesses, this doesn't rely on the queue being valid.
+    pub fn info(&self, mem: &GuestMemoryMmap) -> QueueInfo {
+        let read_idx = |ring: GuestAddress| {
+            if !self.ready {
+                return None;
+            }
+            ring.checked_add(2)
+                .and_then(|addr| mem.read_obj::<u16>(addr).ok())
+        };
+        QueueInfo {
+            max_size: self.max_size,
+            size: self.size,
+            ready: self.ready,
+            desc_table: self.desc_table.raw_value(),
+            avail_ring: self.avail_ring.raw_value(),
+            used_ring: self.used_ring.raw_value(),
+            next_avail: self.next_avail.0,
+            next_used: self.next_used.0,
+            avail_idx: read_idx(self.avail_ring),
+            used_idx: read_idx(self.used_ring),
+            violations: self.violations,
+        }
+    }
+
     /// Fetch the available ring index (`virtq_avail->idx`) from guest memory.
     /// This is written by the driver, to indicate the next slot that will be filled in the avail
     /// ring.
@@ -774,6 +963,54 @@ pub(crate) mod tests {
         assert!(d.next_descriptor().is_none());
     }
 
+    #[test]
+    fn test_malformed_chains() {
+        let m = &GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let vq = VirtQueue::new(GuestAddress(0), m, 16);
+        let mut q = vq.create_queue();
+        let metrics = Arc::new(VirtioQueueMetrics::default());
+        q.set_violation_metrics(metrics.clone());
+
+        // A buffer crossing the end of guest memory.
+        vq.dtable[0].set(0xf000, 0x2000, 0, 0);
+        // An indirect descriptor.
+        vq.dtable[2].set(0x1000, 0x10, VIRTQ_DESC_F_INDIRECT, 0);
+        // A looping chain.
+        vq.dtable[3].set(0x1000, 0x1000, VIRTQ_DESC_F_NEXT, 4);
+        vq.dtable[4].set(0x2000, 0x1000, VIRTQ_DESC_F_NEXT, 3);
+        // A valid chain, whose empty buffer is never accessed.
+        vq.dtable[5].set(0x3000, 0x1000, VIRTQ_DESC_F_NEXT, 6);
+        vq.dtable[6].set(0xffff_ffff, 0, 0, 0);
+        // The head at 16 is out of the descriptor table.
+        for (i, &head) in [0, 16, 2, 3, 5].iter().enumerate() {
+            vq.avail.ring[i].set(head);
+        }
+        vq.avail.idx.set(5);
+
+        // The malformed chains are skipped.
+        assert_eq!(q.pop(m).unwrap().index, 5);
+        assert!(q.is_empty(m));
+        assert_eq!(q.violations, 4);
+        assert_eq!(metrics.descriptor_violations.count(), 4);
+        assert!(!q.is_quarantined());
+
+        // The queue is quarantined once the driver made too many malformed chains available,
+        // leaving the valid chain after them.
+        for i in 5..16 {
+            vq.avail.ring[i].set(16);
+        }
+        vq.avail.ring[0].set(16);
+        vq.avail.ring[1].set(5);
+        vq.avail.idx.set(18);
+        assert!(q.pop(m).is_none());
+        assert!(q.is_quarantined());
+        assert_eq!(q.violations, MAX_DESCRIPTOR_VIOLATIONS);
+        assert_eq!(q.len(m), 1);
+        assert_eq!(metrics.descriptor_violations.count(), 16);
+        assert_eq!(metrics.quarantined_queues.count(), 1);
+        assert_eq!(q.info(m).violations, MAX_DESCRIPTOR_VIOLATIONS);
+    }
+
     #[test]
     fn test_add_used() {
         let m = &GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
@@ -793,4 +1030,24 @@ pub(crate) mod tests {
         assert_eq!(x.id, 1);
         assert_eq!(x.len, 0x1000);
     }
+
+    #[test]
+    fn test_add_used_marks_dirty_pages() {
+        let base = 0x1_0000_0000;
+        let m = &GuestMemoryMmap::from_ranges(&[(GuestAddress(base), 0x10000)]).unwrap();
+        let vq = VirtQueue::new(GuestAddress(base), m, 16);
+        let mut q = vq.create_queue();
+        vq.dtable[0].set(base + 0x8000, 0x100, VIRTQ_DESC_F_NEXT, 1);
+        vq.dtable[1].set(base + 0x9ff0, 0x20, VIRTQ_DESC_F_WRITE, 0);
+
+        dirty_pages::enable(m);
+        q.add_used(m, 0, 0x20);
+        let pages = dirty_pages::take(m);
+        let page = |addr: u64| addr / dirty_pages::PAGE_SIZE;
+        // The writable buffer and the used ring are dirty, unlike the readable buffer.
+        assert!(pages.contains(&page(base + 0x9000)));
+        assert!(pages.contains(&page(base + 0xa000)));
+        assert!(pages.contains(&page(q.used_ring.raw_value())));
+        assert!(!pages.contains(&page(base + 0x8000)));
+    }
 }
@@ -0,0 +1,355 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::cmp;
+use std::io;
+use std::result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use logger::{Metric, METRICS};
+use utils::eventfd::EventFd;
+use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
+use vm_memory::{Bytes, GuestMemoryMmap};
+
+use super::super::{
+    ActivateError, ActivateResult, DeviceState, Queue, VirtioDevice, VIRTIO_MMIO_INT_VRING,
+};
+use super::{
+    Error, Result, ENTROPY_DEV_ID, MAX_REQUEST_SIZE, NUM_QUEUES, QUEUE_SIZE, REQUEST_INDEX,
+    TYPE_RNG,
+};
+
+use crate::Error as DeviceError;
+
+/// Fills `buf` with random bytes from the host.
+fn fill_random(buf: &mut [u8]) -> Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let remaining = &mut buf[filled..];
+        // Safe because the kernel writes at most `remaining.len()` bytes to a buffer we own, and
+        // we check the return value.
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_getrandom,
+                remaining.as_mut_ptr(),
+                remaining.len(),
+                0,
+            )
+        };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(Error::HostRng(err));
+        }
+        filled += ret as usize;
+    }
+    Ok(())
+}
+
+/// Virtio device which hands random bytes from the host to the guest.
+pub struct Entropy {
+    // Virtio fields.
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+    pub(crate) activate_evt: EventFd,
+
+    // Transport related fields.
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) queue_evts: Vec<EventFd>,
+    pub(crate) interrupt_status: Arc<AtomicUsize>,
+    interrupt_evt: EventFd,
+    pub(crate) device_state: DeviceState,
+}
+
+impl Entropy {
+    /// Creates a new entropy device.
+    pub fn new() -> Result<Entropy> {
+        let mut queue_evts = Vec::with_capacity(NUM_QUEUES);
+        for _ in 0..NUM_QUEUES {
+            queue_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
+        }
+
+        Ok(Entropy {
+            avail_features: 1u64 << VIRTIO_F_VERSION_1,
+            acked_features: 0u64,
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queues: (0..NUM_QUEUES).map(|_| Queue::new(QUEUE_SIZE)).collect(),
+            queue_evts,
+            interrupt_status: Arc::new(AtomicUsize::new(0)),
+            interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            device_state: DeviceState::Inactive,
+        })
+    }
+
+    /// Provides the ID of the entropy device.
+    pub fn id(&self) -> &str {
+        ENTROPY_DEV_ID
+    }
+
+    /// Fills the buffers the guest driver is waiting on with fresh random bytes, and notifies
+    /// it. Meant to be called after restoring a snapshot, so that requests issued before the
+    /// snapshot was taken are not left pending until the next queue notification.
+    ///
+    /// Returns the number of bytes handed to the guest.
+    pub fn inject_entropy(&mut self) -> result::Result<usize, DeviceError> {
+        if !self.is_activated() {
+            return Ok(0);
+        }
+        let (used_any, bytes) = self.process_request_queue();
+        if used_any {
+            self.signal_used_queue()?;
+        }
+        Ok(bytes)
+    }
+
+    pub(crate) fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+        self.interrupt_evt.write(1).map_err(|e| {
+            error!("Failed to signal entropy device interrupt: {:?}", e);
+            METRICS.entropy.event_fails.inc();
+            DeviceError::FailedSignalingUsedQueue(e)
+        })
+    }
+
+    // Fills the writable descriptors of all the pending requests with random bytes. Returns
+    // whether any request was completed, and the number of bytes handed to the guest.
+    pub(crate) fn process_request_queue(&mut self) -> (bool, usize) {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem,
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        let queue = &mut self.queues[REQUEST_INDEX];
+        let mut used_any = false;
+        let mut total_bytes = 0;
+
+        while let Some(head) = queue.pop(mem) {
+            let head_index = head.index;
+            let mut request_bytes = 0u32;
+            let mut next_desc = Some(head);
+            while let Some(desc) = next_desc {
+                if !desc.is_write_only() {
+                    error!("entropy: {:?}", Error::UnexpectedReadOnlyDescriptor);
+                    METRICS.entropy.event_fails.inc();
+                    break;
+                }
+                let len = cmp::min(desc.len, MAX_REQUEST_SIZE - request_bytes);
+                let mut buf = vec![0u8; len as usize];
+                if let Err(e) = fill_random(&mut buf) {
+                    error!("entropy: {:?}", e);
+                    METRICS.entropy.host_rng_fails.inc();
+                    break;
+                }
+                if let Err(e) = mem.write_slice(&buf, desc.addr) {
+                    error!("entropy: {:?}", Error::GuestMemory(e));
+                    METRICS.entropy.event_fails.inc();
+                    break;
+                }
+                request_bytes += len;
+                if request_bytes == MAX_REQUEST_SIZE {
+                    break;
+                }
+                next_desc = desc.next_descriptor();
+            }
+            queue.add_used(mem, head_index, request_bytes);
+            used_any = true;
+            total_bytes += request_bytes as usize;
+        }
+
+        METRICS.entropy.entropy_bytes.add(total_bytes);
+        (used_any, total_bytes)
+    }
+}
+
+impl VirtioDevice for Entropy {
+    fn device_type(&self) -> u32 {
+        TYPE_RNG
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.interrupt_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.interrupt_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, _offset: u64, _data: &mut [u8]) {
+        // The entropy device has no configuration space.
+        error!("Failed to read entropy device config space");
+        METRICS.entropy.cfg_fails.inc();
+    }
+
+    fn write_config(&mut self, _offset: u64, _data: &[u8]) {
+        error!("Failed to write entropy device config space");
+        METRICS.entropy.cfg_fails.inc();
+    }
+
+    fn is_activated(&self) -> bool {
+        match self.device_state {
+            DeviceState::Inactive => false,
+            DeviceState::Activated(_) => true,
+        }
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
+        if self.activate_evt.write(1).is_err() {
+            error!("Entropy: Cannot write to activate_evt");
+            METRICS.entropy.activate_fails.inc();
+            return Err(ActivateError::BadActivate);
+        }
+        self.device_state = DeviceState::Activated(mem);
+        Ok(())
+    }
+
+    fn reset(&mut self) -> bool {
+        self.device_state = DeviceState::Inactive;
+        self.acked_features = 0;
+        true
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::virtio::queue::tests::*;
+    use crate::virtio::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+    use vm_memory::GuestAddress;
+
+    impl Entropy {
+        pub(crate) fn set_queue(&mut self, idx: usize, q: Queue) {
+            self.queues[idx] = q;
+        }
+    }
+
+    pub fn default_mem() -> GuestMemoryMmap {
+        GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap()
+    }
+
+    #[test]
+    fn test_virtio_features() {
+        let entropy = Entropy::new().unwrap();
+        assert_eq!(entropy.device_type(), TYPE_RNG);
+        assert_eq!(entropy.id(), ENTROPY_DEV_ID);
+        assert_eq!(entropy.avail_features(), 1u64 << VIRTIO_F_VERSION_1);
+        assert_eq!(entropy.queues().len(), NUM_QUEUES);
+        assert_eq!(entropy.queue_events().len(), NUM_QUEUES);
+
+        // Reads of the inexistent config space don't mutate the buffer.
+        let mut data = [0xffu8; 4];
+        entropy.read_config(0, &mut data);
+        assert_eq!(data, [0xffu8; 4]);
+    }
+
+    #[test]
+    fn test_fill_random() {
+        let mut buf = [0u8; 64];
+        fill_random(&mut buf).unwrap();
+        // The odds of 64 random bytes being all zeroes are negligible.
+        assert!(buf.iter().any(|b| *b != 0));
+        fill_random(&mut []).unwrap();
+    }
+
+    #[test]
+    fn test_process_request_queue() {
+        let mem = default_mem();
+        let mut entropy = Entropy::new().unwrap();
+        let reqq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        entropy.set_queue(REQUEST_INDEX, reqq.create_queue());
+        entropy.activate(mem.clone()).unwrap();
+
+        // A request made of two writable buffers.
+        reqq.dtable[0].set(0x4000, 0x10, VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE, 1);
+        reqq.dtable[1].set(0x5000, 0x20, VIRTQ_DESC_F_WRITE, 0);
+        reqq.avail.ring[0].set(0);
+        // A request with a read only buffer, which is skipped.
+        reqq.dtable[2].set(0x6000, 0x10, 0, 0);
+        reqq.avail.ring[1].set(2);
+        reqq.avail.idx.set(2);
+
+        assert_eq!(entropy.process_request_queue(), (true, 0x30));
+        assert_eq!(reqq.used.idx.get(), 2);
+        assert_eq!(reqq.used.ring[0].get().len, 0x30);
+        assert_eq!(reqq.used.ring[1].get().len, 0);
+        let mut buf = [0u8; 0x20];
+        mem.read_slice(&mut buf, GuestAddress(0x5000)).unwrap();
+        assert!(buf.iter().any(|b| *b != 0));
+
+        // Nothing left to process.
+        assert_eq!(entropy.process_request_queue(), (false, 0));
+    }
+
+    #[test]
+    fn test_max_request_size() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x40000)]).unwrap();
+        let mut entropy = Entropy::new().unwrap();
+        let reqq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        entropy.set_queue(REQUEST_INDEX, reqq.create_queue());
+        entropy.activate(mem.clone()).unwrap();
+
+        reqq.dtable[0].set(0x10000, MAX_REQUEST_SIZE + 0x1000, VIRTQ_DESC_F_WRITE, 0);
+        reqq.avail.ring[0].set(0);
+        reqq.avail.idx.set(1);
+        assert_eq!(
+            entropy.process_request_queue(),
+            (true, MAX_REQUEST_SIZE as usize)
+        );
+        assert_eq!(reqq.used.ring[0].get().len, MAX_REQUEST_SIZE);
+    }
+
+    #[test]
+    fn test_inject_entropy() {
+        let mem = default_mem();
+        let mut entropy = Entropy::new().unwrap();
+        let reqq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        entropy.set_queue(REQUEST_INDEX, reqq.create_queue());
+
+        reqq.dtable[0].set(0x4000, 0x10, VIRTQ_DESC_F_WRITE, 0);
+        reqq.avail.ring[0].set(0);
+        reqq.avail.idx.set(1);
+
+        // Nothing is injected before the device is activated.
+        assert_eq!(entropy.inject_entropy().unwrap(), 0);
+        assert!(entropy.interrupt_evt().read().is_err());
+
+        entropy.activate(mem).unwrap();
+        assert_eq!(entropy.inject_entropy().unwrap(), 0x10);
+        assert_eq!(entropy.interrupt_evt().read().unwrap(), 1);
+        assert_eq!(
+            entropy.interrupt_status().load(Ordering::SeqCst),
+            VIRTIO_MMIO_INT_VRING as usize
+        );
+
+        // The guest is not notified when no request is pending.
+        assert_eq!(entropy.inject_entropy().unwrap(), 0);
+        assert!(entropy.interrupt_evt().read().is_err());
+    }
+}
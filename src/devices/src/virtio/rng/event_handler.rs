@@ -0,0 +1,143 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::os::unix::io::AsRawFd;
+
+use logger::{Metric, METRICS};
+use polly::event_manager::{EventManager, Subscriber};
+use utils::epoll::{EpollEvent, EventSet};
+
+use crate::virtio::rng::device::Entropy;
+use crate::virtio::rng::REQUEST_INDEX;
+use crate::virtio::VirtioDevice;
+
+impl Entropy {
+    fn process_activate_event(&self, event_manager: &mut EventManager) {
+        // The subscriber must exist as we previously registered activate_evt via
+        // `interest_list()`.
+        let self_subscriber = event_manager
+            .subscriber(self.activate_evt.as_raw_fd())
+            .unwrap();
+
+        for queue_evt in self.queue_evts.iter() {
+            event_manager
+                .register(
+                    queue_evt.as_raw_fd(),
+                    EpollEvent::new(EventSet::IN, queue_evt.as_raw_fd() as u64),
+                    self_subscriber.clone(),
+                )
+                .unwrap_or_else(|e| {
+                    error!(
+                        "Failed to register entropy queue with event manager: {:?}",
+                        e
+                    );
+                });
+        }
+
+        event_manager
+            .unregister(self.activate_evt.as_raw_fd())
+            .unwrap_or_else(|e| {
+                error!("Failed to unregister entropy activate evt: {:?}", e);
+            })
+    }
+
+    fn process_request_queue_event(&mut self) {
+        if let Err(e) = self.queue_evts[REQUEST_INDEX].read() {
+            error!("Failed to get entropy queue event: {:?}", e);
+            METRICS.entropy.event_fails.inc();
+            return;
+        }
+
+        let (used_any, _) = self.process_request_queue();
+        if used_any {
+            let _ = self.signal_used_queue();
+        }
+    }
+}
+
+impl Subscriber for Entropy {
+    // Handle an event for the request queue.
+    fn process(&mut self, event: &EpollEvent, evmgr: &mut EventManager) {
+        let source = event.fd();
+        let event_set = event.event_set();
+
+        let supported_events = EventSet::IN;
+        if !supported_events.contains(event_set) {
+            warn!(
+                "Entropy: Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
+
+        if self.is_activated() {
+            let request_fd = self.queue_evts[REQUEST_INDEX].as_raw_fd();
+            let activate_fd = self.activate_evt.as_raw_fd();
+            if request_fd == source {
+                self.process_request_queue_event();
+            } else if activate_fd == source {
+                self.process_activate_event(evmgr);
+            } else {
+                warn!("Entropy: Spurious event received: {:?}", source);
+            }
+        } else {
+            warn!(
+                "Entropy: The device is not yet activated. Spurious event received: {:?}",
+                source
+            );
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        vec![EpollEvent::new(
+            EventSet::IN,
+            self.activate_evt.as_raw_fd() as u64,
+        )]
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::virtio::queue::tests::*;
+    use crate::virtio::rng::device::tests::default_mem;
+    use crate::virtio::VIRTQ_DESC_F_WRITE;
+    use vm_memory::GuestAddress;
+
+    #[test]
+    fn test_event_handler() {
+        let mut event_manager = EventManager::new().unwrap();
+        let mem = default_mem();
+        let mut entropy = Entropy::new().unwrap();
+        let reqq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        entropy.set_queue(REQUEST_INDEX, reqq.create_queue());
+        reqq.dtable[0].set(0x4000, 0x10, VIRTQ_DESC_F_WRITE, 0);
+        reqq.avail.ring[0].set(0);
+        reqq.avail.idx.set(1);
+
+        let entropy = Arc::new(Mutex::new(entropy));
+        event_manager.add_subscriber(entropy.clone()).unwrap();
+
+        // Trigger the queue event. It is not processed before activation.
+        entropy.lock().unwrap().queue_evts[REQUEST_INDEX]
+            .write(1)
+            .unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 0);
+
+        // Now activate the device, which registers the queue events.
+        entropy.lock().unwrap().activate(mem.clone()).unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+
+        // Handle the pending queue event through EventManager.
+        event_manager
+            .run_with_timeout(100)
+            .expect("Entropy event timeout or error.");
+        assert_eq!(entropy.lock().unwrap().interrupt_evt().read().unwrap(), 1);
+        assert_eq!(reqq.used.idx.get(), 1);
+        assert_eq!(reqq.used.ring[0].get().len, 0x10);
+    }
+}
@@ -0,0 +1,38 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod device;
+pub mod event_handler;
+pub mod persist;
+
+pub use self::device::Entropy;
+pub use self::event_handler::*;
+
+use vm_memory::GuestMemoryError;
+
+/// Device ID used in MMIO device identification.
+/// Because the entropy device is unique per-vm, this ID can be hardcoded.
+pub const ENTROPY_DEV_ID: &str = "rng";
+/// Virtio entropy device ID, as defined in `include/uapi/linux/virtio_ids.h`.
+pub const TYPE_RNG: u32 = 4;
+pub const QUEUE_SIZE: u16 = 256;
+pub const NUM_QUEUES: usize = 1;
+pub const REQUEST_INDEX: usize = 0;
+/// Upper bound of the entropy handed to the guest for a single request.
+pub const MAX_REQUEST_SIZE: u32 = 64 * 1024;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Activation error.
+    Activate(super::ActivateError),
+    /// Guest gave us a read only descriptor that protocol says to write to.
+    UnexpectedReadOnlyDescriptor,
+    /// Guest gave us bad memory addresses.
+    GuestMemory(GuestMemoryError),
+    /// Failed to create or signal an event fd.
+    EventFd(std::io::Error),
+    /// Failed to get random bytes from the host.
+    HostRng(std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,110 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the structures needed for saving/restoring entropy devices.
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use snapshot::Persist;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use vm_memory::GuestMemoryMmap;
+
+use super::*;
+
+use crate::virtio::persist::VirtioDeviceState;
+use crate::virtio::{DeviceState, Queue};
+
+#[derive(Versionize)]
+pub struct EntropyState {
+    virtio_state: VirtioDeviceState,
+}
+
+pub struct EntropyConstructorArgs {
+    pub mem: GuestMemoryMmap,
+}
+
+impl Persist for Entropy {
+    type State = EntropyState;
+    type ConstructorArgs = EntropyConstructorArgs;
+    type Error = Error;
+
+    fn save(&self) -> Self::State {
+        EntropyState {
+            virtio_state: VirtioDeviceState::from_device(self),
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> std::result::Result<Self, Self::Error> {
+        let mut entropy = Entropy::new()?;
+
+        // Safe to unwrap because Queue::restore() cannot fail.
+        entropy.queues = state
+            .virtio_state
+            .queues
+            .iter()
+            .map(|queue_state| Queue::restore((), &queue_state).unwrap())
+            .collect();
+        entropy.interrupt_status = Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
+        entropy.avail_features = state.virtio_state.avail_features;
+        entropy.acked_features = state.virtio_state.acked_features;
+
+        if state.virtio_state.activated {
+            entropy.device_state = DeviceState::Activated(constructor_args.mem);
+            // Make the event handler register the queue events once it gets subscribed.
+            entropy.activate_evt.write(1).map_err(Error::EventFd)?;
+        }
+
+        Ok(entropy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtio::device::VirtioDevice;
+    use crate::virtio::rng::device::tests::default_mem;
+
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_persistence() {
+        let guest_mem = default_mem();
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+
+        let mut entropy = Entropy::new().unwrap();
+        entropy.activate(guest_mem.clone()).unwrap();
+
+        // Save the entropy device.
+        <Entropy as Persist>::save(&entropy)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        // Restore the entropy device.
+        let restored_entropy = Entropy::restore(
+            EntropyConstructorArgs {
+                mem: guest_mem.clone(),
+            },
+            &EntropyState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+
+        // Test that virtio specific fields are the same.
+        assert_eq!(restored_entropy.device_type(), TYPE_RNG);
+        assert_eq!(restored_entropy.avail_features(), entropy.avail_features());
+        assert_eq!(restored_entropy.acked_features(), entropy.acked_features());
+        assert_eq!(restored_entropy.queues(), entropy.queues());
+        assert_eq!(
+            restored_entropy.interrupt_status().load(Ordering::Relaxed),
+            entropy.interrupt_status().load(Ordering::Relaxed)
+        );
+        assert_eq!(restored_entropy.is_activated(), entropy.is_activated());
+        // The restored device registers its queue events once subscribed.
+        assert_eq!(restored_entropy.activate_evt.read().unwrap(), 1);
+    }
+}
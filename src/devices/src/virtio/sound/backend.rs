@@ -0,0 +1,194 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The host side of the sound device, which receives the samples played by the guest as raw,
+//! interleaved PCM frames.
+//!
+//! The sink never blocks the device: the samples the host doesn't take in time are dropped,
+//! in whole frames, so that the host always reads complete frames.
+
+use std::cmp;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixStream;
+
+/// The kind of host endpoint a `PcmSink` writes to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SinkKind {
+    /// A regular file or a FIFO.
+    File,
+    /// A Unix stream socket.
+    Socket,
+}
+
+/// Writes the played samples to a host file, FIFO or Unix socket.
+pub struct PcmSink {
+    writer: Box<dyn Write + Send>,
+    kind: SinkKind,
+    path: String,
+    // The end of the last frame that was only partially written.
+    partial: Vec<u8>,
+}
+
+impl PcmSink {
+    /// Opens the file at `path`, creating it if needed. FIFOs must already have a reader.
+    pub fn open_file(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)?;
+        Ok(Self::new(Box::new(file), SinkKind::File, path))
+    }
+
+    /// Connects to the Unix stream socket listening at `path`.
+    pub fn connect(path: &str) -> io::Result<Self> {
+        let stream = UnixStream::connect(path)?;
+        stream.set_nonblocking(true)?;
+        Ok(Self::new(Box::new(stream), SinkKind::Socket, path))
+    }
+
+    fn new(writer: Box<dyn Write + Send>, kind: SinkKind, path: &str) -> Self {
+        PcmSink {
+            writer,
+            kind,
+            path: path.to_string(),
+            partial: Vec::new(),
+        }
+    }
+
+    /// Provides the kind of host endpoint.
+    pub fn kind(&self) -> SinkKind {
+        self.kind
+    }
+
+    /// Provides the path of the host endpoint.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Writes the `samples`, made of `frame_size` bytes frames, and returns the number of bytes
+    /// dropped because the host didn't take them.
+    pub fn write(&mut self, samples: &[u8], frame_size: usize) -> io::Result<usize> {
+        if !self.partial.is_empty() {
+            let partial = mem::replace(&mut self.partial, Vec::new());
+            let written = self.write_some(&partial)?;
+            if written < partial.len() {
+                self.partial = partial[written..].to_vec();
+                return Ok(samples.len());
+            }
+        }
+
+        let written = self.write_some(samples)?;
+        // Finish the frame that got cut, the next time around.
+        let partial_len = cmp::min(
+            (frame_size - written % frame_size) % frame_size,
+            samples.len() - written,
+        );
+        self.partial
+            .extend_from_slice(&samples[written..written + partial_len]);
+        Ok(samples.len() - written - partial_len)
+    }
+
+    // Writes as much of `buf` as the host takes without blocking.
+    fn write_some(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            match self.writer.write(&buf[written..]) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                Ok(count) => written += count,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::fs;
+    use std::io::Read;
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+    use utils::tempfile::TempFile;
+
+    pub(crate) fn temp_path() -> String {
+        let mut file = TempFile::new().unwrap();
+        // Remove the file so the path can be reused.
+        file.remove().unwrap();
+        String::from(file.as_path().to_str().unwrap())
+    }
+
+    #[test]
+    fn test_file_sink() {
+        let path = temp_path();
+        let mut sink = PcmSink::open_file(&path).unwrap();
+        assert_eq!(sink.kind(), SinkKind::File);
+        assert_eq!(sink.path(), path);
+
+        assert_eq!(sink.write(&[1, 2, 3, 4], 4).unwrap(), 0);
+        assert_eq!(sink.write(&[5, 6, 7, 8], 4).unwrap(), 0);
+        assert_eq!(fs::read(&path).unwrap(), vec![1, 2, 3, 4, 5, 6, 7, 8]);
+        fs::remove_file(&path).unwrap();
+
+        // A FIFO without a reader can't be opened.
+        let c_path = std::ffi::CString::new(path.clone()).unwrap();
+        // Safe because the path is a valid C string, and we check the return value.
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        match PcmSink::open_file(&path) {
+            Err(ref e) if e.raw_os_error() == Some(libc::ENXIO) => (),
+            _ => panic!("The FIFO has no reader."),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_socket_sink() {
+        let path = temp_path();
+        assert!(PcmSink::connect(&path).is_err());
+
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut sink = PcmSink::connect(&path).unwrap();
+        let (mut host, _) = listener.accept().unwrap();
+        assert_eq!(sink.kind(), SinkKind::Socket);
+        host.set_nonblocking(true).unwrap();
+
+        // Fill the socket, so that the frames which don't fit are dropped.
+        let samples = vec![0xaau8; 4 << 20];
+        let dropped = sink.write(&samples, 4).unwrap();
+        assert!(dropped > 0);
+        let mut received = Vec::new();
+        let mut buf = vec![0u8; 64 << 10];
+        loop {
+            match host.read(&mut buf) {
+                Ok(count) => received.extend_from_slice(&buf[..count]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+
+        // The cut frame is completed before the next samples.
+        assert_eq!(sink.write(&[1, 2, 3, 4], 4).unwrap(), 0);
+        loop {
+            match host.read(&mut buf) {
+                Ok(count) => received.extend_from_slice(&buf[..count]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+        assert_eq!(received.len() % 4, 0);
+        assert_eq!(received[received.len() - 4..], [1, 2, 3, 4]);
+        assert_eq!(received.len() + dropped, samples.len() + 4);
+
+        // Writing to a closed socket fails.
+        drop(host);
+        assert!(sink.write(&[1, 2, 3, 4], 4).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
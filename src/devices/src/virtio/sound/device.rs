@@ -0,0 +1,1247 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::mem::size_of;
+use std::result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use logger::{Metric, METRICS};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::eventfd::EventFd;
+use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
+use vm_memory::{Address, ByteValued, Bytes, GuestAddress, GuestMemoryMmap};
+
+use super::super::{
+    ActivateError, ActivateResult, DescriptorChain, DeviceState, Queue, VirtioDevice,
+    VIRTIO_MMIO_INT_VRING,
+};
+use super::backend::PcmSink;
+use super::protocol::*;
+use super::{
+    Error, Result, CONTROL_INDEX, MAX_BUFFER_BYTES, MAX_CHANNELS, MAX_REQUEST_SIZE, NUM_QUEUES,
+    QUEUE_SIZE, RX_INDEX, SOUND_DEV_ID, TX_INDEX, TYPE_SOUND,
+};
+
+use crate::Error as DeviceError;
+
+/// The number of PCM streams of the device, i.e. its single output stream.
+const NUM_STREAMS: u32 = 1;
+
+// The states of a PCM stream, as defined by the virtio specification.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StreamState {
+    Idle,
+    ParamsSet,
+    Prepared,
+    Running,
+    Stopped,
+    Released,
+}
+
+// The buffer geometry the guest set for the stream.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct PcmParams {
+    buffer_bytes: u32,
+    period_bytes: u32,
+}
+
+// An I/O message of the output stream.
+struct PendingXfer {
+    head_index: u16,
+    samples: Vec<u8>,
+    // The writable descriptors, which end with the status of the message.
+    writable: Vec<(GuestAddress, u32)>,
+    // When the samples are played out, once they were handed to the sink.
+    deadline: Option<Instant>,
+}
+
+// Copies an object from `offset` of `buf`, if it fits.
+fn read_obj<T: ByteValued + Default>(buf: &[u8], offset: usize) -> Option<T> {
+    let mut obj = T::default();
+    let len = obj.as_slice().len();
+    obj.as_mut_slice()
+        .copy_from_slice(buf.get(offset..offset.checked_add(len)?)?);
+    Some(obj)
+}
+
+// Splits a descriptor chain in the message, copied out of the readable descriptors, and the
+// writable descriptors, which receive the response.
+fn parse_chain(
+    mem: &GuestMemoryMmap,
+    head: DescriptorChain,
+    max_len: usize,
+) -> Result<(Vec<u8>, Vec<(GuestAddress, u32)>)> {
+    let mut message = Vec::new();
+    let mut writable = Vec::new();
+    let mut next_desc = Some(head);
+    while let Some(desc) = next_desc {
+        if desc.is_write_only() {
+            writable.push((desc.addr, desc.len));
+        } else {
+            if !writable.is_empty() {
+                return Err(Error::UnexpectedReadableDescriptor);
+            }
+            let start = message.len();
+            if start + desc.len as usize > max_len {
+                return Err(Error::RequestTooLarge);
+            }
+            message.resize(start + desc.len as usize, 0);
+            mem.read_slice(&mut message[start..], desc.addr)
+                .map_err(Error::GuestMemory)?;
+        }
+        next_desc = desc.next_descriptor();
+    }
+    Ok((message, writable))
+}
+
+// Writes the response to the writable descriptors, and returns the number of bytes written.
+fn write_response(
+    mem: &GuestMemoryMmap,
+    writable: &[(GuestAddress, u32)],
+    response: &[u8],
+) -> Result<u32> {
+    let mut written = 0;
+    for (addr, len) in writable {
+        if written == response.len() {
+            break;
+        }
+        let count = cmp::min(*len as usize, response.len() - written);
+        mem.write_slice(&response[written..written + count], *addr)
+            .map_err(Error::GuestMemory)?;
+        written += count;
+    }
+    Ok(written as u32)
+}
+
+// Writes the `status` of an I/O message to the end of its writable descriptors, and returns the
+// number of bytes written.
+fn write_status(
+    mem: &GuestMemoryMmap,
+    writable: &[(GuestAddress, u32)],
+    status: u32,
+) -> Result<u32> {
+    let status = PcmStatus {
+        status,
+        latency_bytes: 0,
+    };
+    let bytes = status.as_slice();
+    let total: u64 = writable.iter().map(|(_, len)| u64::from(*len)).sum();
+    if total < bytes.len() as u64 {
+        return Err(Error::MissingResponseDescriptor);
+    }
+    let mut skip = total - bytes.len() as u64;
+    let mut written = 0;
+    for (addr, len) in writable {
+        let len = u64::from(*len);
+        if skip >= len {
+            skip -= len;
+            continue;
+        }
+        let count = cmp::min(len - skip, (bytes.len() - written) as u64) as usize;
+        let addr = addr
+            .checked_add(skip)
+            .ok_or(Error::MissingResponseDescriptor)?;
+        mem.write_slice(&bytes[written..written + count], addr)
+            .map_err(Error::GuestMemory)?;
+        written += count;
+        skip = 0;
+    }
+    Ok(written as u32)
+}
+
+/// Virtio device which plays the samples of the guest to a host file, FIFO or Unix socket, at
+/// the pace of a real sound card.
+pub struct Sound {
+    // Virtio fields.
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+    pub(crate) activate_evt: EventFd,
+
+    // Transport related fields.
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) queue_evts: Vec<EventFd>,
+    pub(crate) interrupt_status: Arc<AtomicUsize>,
+    interrupt_evt: EventFd,
+    pub(crate) device_state: DeviceState,
+    config_space: VirtioSndConfig,
+
+    // Playback fields.
+    rate: u32,
+    rate_index: u8,
+    channels: u8,
+    state: StreamState,
+    params: Option<PcmParams>,
+    pending: VecDeque<PendingXfer>,
+    // When the samples handed to the sink so far are played out, unless the stream ran dry.
+    played_until: Option<Instant>,
+    pub(crate) timer_fd: TimerFd,
+    sink: PcmSink,
+}
+
+impl Sound {
+    /// Creates a new sound device with an output stream of `channels` channels at `rate`
+    /// frames per second, whose samples are written to `sink`.
+    pub fn new(rate: u32, channels: u8, sink: PcmSink) -> Result<Sound> {
+        let rate_index = pcm_rate_index(rate).ok_or(Error::UnsupportedRate(rate))?;
+        if channels == 0 || channels > MAX_CHANNELS {
+            return Err(Error::UnsupportedChannels(channels));
+        }
+
+        let mut queue_evts = Vec::with_capacity(NUM_QUEUES);
+        for _ in 0..NUM_QUEUES {
+            queue_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
+        }
+
+        Ok(Sound {
+            avail_features: 1u64 << VIRTIO_F_VERSION_1,
+            acked_features: 0u64,
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queues: (0..NUM_QUEUES).map(|_| Queue::new(QUEUE_SIZE)).collect(),
+            queue_evts,
+            interrupt_status: Arc::new(AtomicUsize::new(0)),
+            interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            device_state: DeviceState::Inactive,
+            config_space: VirtioSndConfig {
+                jacks: 0,
+                streams: NUM_STREAMS,
+                chmaps: 0,
+            },
+            rate,
+            rate_index,
+            channels,
+            state: StreamState::Idle,
+            params: None,
+            pending: VecDeque::new(),
+            played_until: None,
+            timer_fd: TimerFd::new_custom(ClockId::Monotonic, true, true)
+                .map_err(Error::TimerFd)?,
+            sink,
+        })
+    }
+
+    /// Provides the ID of the sound device.
+    pub fn id(&self) -> &str {
+        SOUND_DEV_ID
+    }
+
+    /// Provides the host endpoint of the output stream.
+    pub fn sink(&self) -> &PcmSink {
+        &self.sink
+    }
+
+    pub(crate) fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+        self.interrupt_evt.write(1).map_err(|e| {
+            error!("Failed to signal sound device interrupt: {:?}", e);
+            METRICS.sound.event_fails.inc();
+            DeviceError::FailedSignalingUsedQueue(e)
+        })
+    }
+
+    fn mem(&self) -> GuestMemoryMmap {
+        match self.device_state {
+            DeviceState::Activated(ref mem) => mem.clone(),
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        }
+    }
+
+    fn frame_size(&self) -> usize {
+        usize::from(self.channels) * BYTES_PER_SAMPLE
+    }
+
+    // The time it takes to play `bytes` bytes of samples.
+    fn play_time(&self, bytes: usize) -> Duration {
+        let frames = (bytes / self.frame_size()) as u64;
+        Duration::from_nanos(frames * 1_000_000_000 / u64::from(self.rate))
+    }
+
+    /// Processes the control requests. Returns whether any request, or I/O message completed
+    /// by a request, was used.
+    pub(crate) fn process_control_queue(&mut self, now: Instant) -> bool {
+        let mem = self.mem();
+        let mut used_any = false;
+
+        while let Some(head) = self.queues[CONTROL_INDEX].pop(&mem) {
+            let head_index = head.index;
+            let len = match parse_chain(&mem, head, MAX_REQUEST_SIZE) {
+                Ok((request, writable)) => {
+                    let (response, tx_used) = self.process_control_request(&request, now);
+                    used_any |= tx_used;
+                    write_response(&mem, &writable, &response).unwrap_or_else(|e| {
+                        error!("sound: {:?}", e);
+                        METRICS.sound.event_fails.inc();
+                        0
+                    })
+                }
+                Err(e) => {
+                    error!("sound: {:?}", e);
+                    METRICS.sound.event_fails.inc();
+                    0
+                }
+            };
+            self.queues[CONTROL_INDEX].add_used(&mem, head_index, len);
+            used_any = true;
+        }
+
+        used_any
+    }
+
+    // Returns the response to the request, and whether it completed any I/O message.
+    fn process_control_request(&mut self, request: &[u8], now: Instant) -> (Vec<u8>, bool) {
+        METRICS.sound.ctrl_count.inc();
+        let mut tx_used = false;
+        let code = read_obj::<SndHeader>(request, 0).unwrap_or_default().code;
+        let result = match code {
+            VIRTIO_SND_R_PCM_INFO => self.pcm_info(request),
+            VIRTIO_SND_R_PCM_SET_PARAMS => self.pcm_set_params(request),
+            VIRTIO_SND_R_PCM_PREPARE
+            | VIRTIO_SND_R_PCM_RELEASE
+            | VIRTIO_SND_R_PCM_START
+            | VIRTIO_SND_R_PCM_STOP => self.pcm_transition(code, request, now).map(|used| {
+                tx_used = used;
+                Vec::new()
+            }),
+            // The device has neither jacks nor channel maps, so no item can be queried.
+            VIRTIO_SND_R_JACK_INFO | VIRTIO_SND_R_CHMAP_INFO => Err(VIRTIO_SND_S_BAD_MSG),
+            VIRTIO_SND_R_JACK_REMAP => Err(VIRTIO_SND_S_NOT_SUPP),
+            _ => Err(VIRTIO_SND_S_NOT_SUPP),
+        };
+
+        let (code, payload) = match result {
+            Ok(payload) => (VIRTIO_SND_S_OK, payload),
+            Err(code) => {
+                METRICS.sound.ctrl_fails.inc();
+                (code, Vec::new())
+            }
+        };
+        let mut response = SndHeader { code }.as_slice().to_vec();
+        response.extend_from_slice(&payload);
+        (response, tx_used)
+    }
+
+    fn pcm_info(&self, request: &[u8]) -> result::Result<Vec<u8>, u32> {
+        let query = read_obj::<QueryInfo>(request, 0).ok_or(VIRTIO_SND_S_BAD_MSG)?;
+        let end = query
+            .start_id
+            .checked_add(query.count)
+            .ok_or(VIRTIO_SND_S_BAD_MSG)?;
+        if end > NUM_STREAMS || query.size as usize > MAX_REQUEST_SIZE {
+            return Err(VIRTIO_SND_S_BAD_MSG);
+        }
+        let info = PcmInfo {
+            formats: 1u64 << VIRTIO_SND_PCM_FMT_S16,
+            rates: 1u64 << self.rate_index,
+            direction: VIRTIO_SND_D_OUTPUT,
+            channels_min: self.channels,
+            channels_max: self.channels,
+            ..Default::default()
+        };
+        // Each item takes `size` bytes, so that drivers may know of a shorter or longer version
+        // of the structure.
+        let mut item = info.as_slice().to_vec();
+        item.resize(query.size as usize, 0);
+        let mut items = Vec::new();
+        for _ in 0..query.count {
+            items.extend_from_slice(&item);
+        }
+        Ok(items)
+    }
+
+    fn pcm_set_params(&mut self, request: &[u8]) -> result::Result<Vec<u8>, u32> {
+        let params = read_obj::<PcmSetParams>(request, 0).ok_or(VIRTIO_SND_S_BAD_MSG)?;
+        if params.hdr.stream_id >= NUM_STREAMS {
+            return Err(VIRTIO_SND_S_BAD_MSG);
+        }
+        match self.state {
+            StreamState::Idle
+            | StreamState::ParamsSet
+            | StreamState::Prepared
+            | StreamState::Released => (),
+            _ => return Err(VIRTIO_SND_S_BAD_MSG),
+        }
+        if params.features != 0
+            || params.format != VIRTIO_SND_PCM_FMT_S16
+            || params.rate != self.rate_index
+            || params.channels != self.channels
+            || params.period_bytes == 0
+            || params.period_bytes as usize % self.frame_size() != 0
+            || params.buffer_bytes < params.period_bytes
+            || params.buffer_bytes > MAX_BUFFER_BYTES
+        {
+            return Err(VIRTIO_SND_S_NOT_SUPP);
+        }
+        self.params = Some(PcmParams {
+            buffer_bytes: params.buffer_bytes,
+            period_bytes: params.period_bytes,
+        });
+        self.state = StreamState::ParamsSet;
+        Ok(Vec::new())
+    }
+
+    // Moves the stream to the state requested by `code`. Returns whether any I/O message was
+    // completed on the way.
+    fn pcm_transition(
+        &mut self,
+        code: u32,
+        request: &[u8],
+        now: Instant,
+    ) -> result::Result<bool, u32> {
+        let hdr = read_obj::<PcmHeader>(request, 0).ok_or(VIRTIO_SND_S_BAD_MSG)?;
+        if hdr.stream_id >= NUM_STREAMS {
+            return Err(VIRTIO_SND_S_BAD_MSG);
+        }
+        let allowed = match code {
+            VIRTIO_SND_R_PCM_PREPARE => match self.state {
+                StreamState::ParamsSet | StreamState::Prepared | StreamState::Released => true,
+                _ => false,
+            },
+            VIRTIO_SND_R_PCM_START => match self.state {
+                StreamState::Prepared | StreamState::Stopped => true,
+                _ => false,
+            },
+            VIRTIO_SND_R_PCM_STOP => self.state == StreamState::Running,
+            VIRTIO_SND_R_PCM_RELEASE => match self.state {
+                StreamState::Prepared | StreamState::Stopped => true,
+                _ => false,
+            },
+            _ => false,
+        };
+        if !allowed {
+            return Err(VIRTIO_SND_S_BAD_MSG);
+        }
+
+        match code {
+            VIRTIO_SND_R_PCM_PREPARE => {
+                self.state = StreamState::Prepared;
+                Ok(false)
+            }
+            VIRTIO_SND_R_PCM_START => {
+                self.state = StreamState::Running;
+                self.played_until = None;
+                Ok(self.play(now))
+            }
+            VIRTIO_SND_R_PCM_STOP => {
+                // The message being played still completes once played out.
+                self.state = StreamState::Stopped;
+                Ok(false)
+            }
+            _ => {
+                // The device must complete all the pending messages on release.
+                self.state = StreamState::Released;
+                self.played_until = None;
+                let used_any = !self.pending.is_empty();
+                while let Some(xfer) = self.pending.pop_front() {
+                    self.complete_xfer(&xfer, VIRTIO_SND_S_OK);
+                }
+                Ok(used_any)
+            }
+        }
+    }
+
+    /// Queues the I/O messages of the output stream for playback. Returns whether any message
+    /// was used.
+    pub(crate) fn process_tx_queue(&mut self, now: Instant) -> bool {
+        let mem = self.mem();
+        let mut used_any = false;
+
+        while let Some(head) = self.queues[TX_INDEX].pop(&mem) {
+            let head_index = head.index;
+            let max_len = size_of::<PcmXfer>() + MAX_BUFFER_BYTES as usize;
+            let (message, writable) = match parse_chain(&mem, head, max_len) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    error!("sound: {:?}", e);
+                    METRICS.sound.event_fails.inc();
+                    self.queues[TX_INDEX].add_used(&mem, head_index, 0);
+                    used_any = true;
+                    continue;
+                }
+            };
+            let xfer = PendingXfer {
+                head_index,
+                samples: message.get(size_of::<PcmXfer>()..).unwrap_or(&[]).to_vec(),
+                writable,
+                deadline: None,
+            };
+            if self.xfer_allowed(&message) {
+                self.pending.push_back(xfer);
+            } else {
+                METRICS.sound.tx_fails.inc();
+                self.complete_xfer(&xfer, VIRTIO_SND_S_BAD_MSG);
+                used_any = true;
+            }
+        }
+
+        self.play(now) || used_any
+    }
+
+    // Returns whether the output stream takes the I/O message.
+    fn xfer_allowed(&self, message: &[u8]) -> bool {
+        let xfer = match read_obj::<PcmXfer>(message, 0) {
+            Some(xfer) => xfer,
+            None => return false,
+        };
+        let params = match self.params {
+            Some(params) => params,
+            None => return false,
+        };
+        let len = message.len() - size_of::<PcmXfer>();
+        let state_allowed = match self.state {
+            StreamState::Prepared | StreamState::Running | StreamState::Stopped => true,
+            _ => false,
+        };
+        xfer.stream_id < NUM_STREAMS
+            && state_allowed
+            && len % self.frame_size() == 0
+            && len <= params.buffer_bytes as usize
+    }
+
+    /// Completes the messages of the input queue. The device has no input stream, so they are
+    /// rejected. Returns whether any message was used.
+    pub(crate) fn process_rx_queue(&mut self) -> bool {
+        let mem = self.mem();
+        let mut used_any = false;
+
+        while let Some(head) = self.queues[RX_INDEX].pop(&mem) {
+            let head_index = head.index;
+            let len = match parse_chain(&mem, head, MAX_REQUEST_SIZE) {
+                Ok((_, writable)) => write_status(&mem, &writable, VIRTIO_SND_S_BAD_MSG),
+                Err(e) => Err(e),
+            }
+            .unwrap_or_else(|e| {
+                error!("sound: {:?}", e);
+                METRICS.sound.event_fails.inc();
+                0
+            });
+            self.queues[RX_INDEX].add_used(&mem, head_index, len);
+            used_any = true;
+        }
+
+        used_any
+    }
+
+    /// Plays the pending I/O messages, one after the other, and completes those that were
+    /// played out by `now`. Arms the timer for the next completion. Returns whether any message
+    /// was completed.
+    pub(crate) fn play(&mut self, now: Instant) -> bool {
+        let mut used_any = false;
+        loop {
+            let deadline = match self.pending.front() {
+                Some(xfer) => xfer.deadline,
+                None => return used_any,
+            };
+            match deadline {
+                Some(deadline) if deadline > now => {
+                    self.arm_timer(deadline - now);
+                    return used_any;
+                }
+                Some(_) => {
+                    // Safe to unwrap, the queue is not empty.
+                    let xfer = self.pending.pop_front().unwrap();
+                    self.complete_xfer(&xfer, VIRTIO_SND_S_OK);
+                    used_any = true;
+                    if self.pending.is_empty() {
+                        // The guest didn't keep up, so the stream restarts with the next message.
+                        self.played_until = None;
+                    }
+                }
+                None if self.state == StreamState::Running => self.start_front(now),
+                None => return used_any,
+            }
+        }
+    }
+
+    // Hands the samples of the first pending message to the sink, and schedules its completion.
+    fn start_front(&mut self, now: Instant) {
+        let frame_size = self.frame_size();
+        let start = self.played_until.unwrap_or(now);
+        // Safe to unwrap, the caller checked that the queue is not empty.
+        let xfer = self.pending.front_mut().unwrap();
+        let samples = std::mem::replace(&mut xfer.samples, Vec::new());
+        match self.sink.write(&samples, frame_size) {
+            Ok(dropped) => METRICS.sound.backend_dropped_bytes.add(dropped),
+            Err(e) => {
+                error!("sound: Failed to write to the backend: {:?}", e);
+                METRICS.sound.backend_fails.inc();
+            }
+        }
+        METRICS.sound.tx_count.inc();
+        METRICS.sound.tx_bytes_count.add(samples.len());
+
+        let deadline = start + self.play_time(samples.len());
+        // Borrow the message again, now that the play time is known.
+        if let Some(xfer) = self.pending.front_mut() {
+            xfer.deadline = Some(deadline);
+        }
+        self.played_until = Some(deadline);
+    }
+
+    fn complete_xfer(&mut self, xfer: &PendingXfer, status: u32) {
+        let mem = self.mem();
+        let len = write_status(&mem, &xfer.writable, status).unwrap_or_else(|e| {
+            error!("sound: {:?}", e);
+            METRICS.sound.event_fails.inc();
+            0
+        });
+        self.queues[TX_INDEX].add_used(&mem, xfer.head_index, len);
+    }
+
+    fn arm_timer(&mut self, timeout: Duration) {
+        self.timer_fd
+            .set_state(TimerState::Oneshot(timeout), SetTimeFlags::Default);
+    }
+}
+
+impl VirtioDevice for Sound {
+    fn device_type(&self) -> u32 {
+        TYPE_SOUND
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.interrupt_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.interrupt_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) {
+        let config_space_bytes = self.config_space.as_slice();
+        let config_len = config_space_bytes.len() as u64;
+        if offset >= config_len {
+            error!("Failed to read sound device config space");
+            METRICS.sound.cfg_fails.inc();
+            return;
+        }
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            // This write can't fail, offset and end are checked against config_len.
+            data.write_all(
+                &config_space_bytes[offset as usize..cmp::min(end, config_len) as usize],
+            )
+            .unwrap();
+        }
+    }
+
+    fn config_space_bytes(&self) -> Vec<u8> {
+        self.config_space.as_slice().to_vec()
+    }
+
+    fn write_config(&mut self, _offset: u64, _data: &[u8]) {
+        // The configuration space is read only.
+        error!("Failed to write sound device config space");
+        METRICS.sound.cfg_fails.inc();
+    }
+
+    fn is_activated(&self) -> bool {
+        match self.device_state {
+            DeviceState::Inactive => false,
+            DeviceState::Activated(_) => true,
+        }
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
+        if self.activate_evt.write(1).is_err() {
+            error!("Sound: Cannot write to activate_evt");
+            METRICS.sound.activate_fails.inc();
+            return Err(ActivateError::BadActivate);
+        }
+        self.device_state = DeviceState::Activated(mem);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::virtio::queue::tests::*;
+    use crate::virtio::sound::backend::tests::temp_path;
+    use crate::virtio::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+
+    const REQUEST_ADDR: u64 = 0x8000;
+    const RESPONSE_ADDR: u64 = 0x9000;
+    const RESPONSE_SIZE: u32 = 0x200;
+    const SAMPLES_ADDR: u64 = 0x10000;
+    const STATUS_ADDR: u64 = 0x30000;
+
+    // 48kHz stereo, so that a 4 bytes frame lasts 1/48000 seconds.
+    const RATE: u32 = 48000;
+    const CHANNELS: u8 = 2;
+    const PERIOD_BYTES: u32 = 192;
+
+    impl Sound {
+        pub(crate) fn set_queue(&mut self, idx: usize, q: Queue) {
+            self.queues[idx] = q;
+        }
+    }
+
+    pub fn default_mem() -> GuestMemoryMmap {
+        GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x40000)]).unwrap()
+    }
+
+    // Returns a device writing to a new file, and the path of the file.
+    pub fn default_sound() -> (Sound, String) {
+        let path = temp_path();
+        let sink = PcmSink::open_file(&path).unwrap();
+        (Sound::new(RATE, CHANNELS, sink).unwrap(), path)
+    }
+
+    // Sends the requests and messages to the device, as the guest driver would.
+    pub struct Driver<'a> {
+        mem: &'a GuestMemoryMmap,
+        control: VirtQueue<'a>,
+        tx: VirtQueue<'a>,
+        next_request: u16,
+        next_xfer: u16,
+    }
+
+    impl<'a> Driver<'a> {
+        pub fn new(sound: &mut Sound, mem: &'a GuestMemoryMmap) -> Self {
+            let control = VirtQueue::new(GuestAddress(0), mem, 16);
+            let tx = VirtQueue::new(GuestAddress(0x1000), mem, 16);
+            sound.set_queue(CONTROL_INDEX, control.create_queue());
+            sound.set_queue(TX_INDEX, tx.create_queue());
+            Driver {
+                mem,
+                control,
+                tx,
+                next_request: 0,
+                next_xfer: 0,
+            }
+        }
+
+        // Places the request in the control queue, without notifying the device.
+        pub fn queue_request(&mut self, request: &[u8]) {
+            self.mem
+                .write_slice(request, GuestAddress(REQUEST_ADDR))
+                .unwrap();
+            self.control.dtable[0].set(REQUEST_ADDR, request.len() as u32, VIRTQ_DESC_F_NEXT, 1);
+            self.control.dtable[1].set(RESPONSE_ADDR, RESPONSE_SIZE, VIRTQ_DESC_F_WRITE, 0);
+            self.control.avail.ring[self.next_request as usize % 16].set(0);
+            self.next_request += 1;
+            self.control.avail.idx.set(self.next_request);
+        }
+
+        // Executes the request, and returns the response.
+        pub fn request(&mut self, sound: &mut Sound, request: &[u8]) -> Vec<u8> {
+            self.queue_request(request);
+            assert!(sound.process_control_queue(Instant::now()));
+            assert_eq!(self.control.used.idx.get(), self.next_request);
+            let len = self.control.used.ring[(self.next_request - 1) as usize % 16]
+                .get()
+                .len;
+            let mut response = vec![0u8; len as usize];
+            self.mem
+                .read_slice(&mut response, GuestAddress(RESPONSE_ADDR))
+                .unwrap();
+            response
+        }
+
+        // Executes the request, and returns the status code of the response.
+        pub fn request_code(&mut self, sound: &mut Sound, request: &[u8]) -> u32 {
+            let response = self.request(sound, request);
+            read_obj::<SndHeader>(&response, 0).unwrap().code
+        }
+
+        // Places an I/O message in the TX queue, without notifying the device. Each message
+        // has its own descriptors, as several of them can be pending.
+        pub fn queue_xfer(&mut self, stream_id: u32, samples: &[u8]) {
+            let slot = self.next_xfer % 8;
+            let samples_addr = SAMPLES_ADDR + u64::from(slot) * 0x1000;
+            let status_addr = STATUS_ADDR + u64::from(slot) * 0x10;
+            let mut message = PcmXfer { stream_id }.as_slice().to_vec();
+            message.extend_from_slice(samples);
+            self.mem
+                .write_slice(&message, GuestAddress(samples_addr))
+                .unwrap();
+            self.tx.dtable[slot as usize * 2].set(
+                samples_addr,
+                message.len() as u32,
+                VIRTQ_DESC_F_NEXT,
+                slot * 2 + 1,
+            );
+            self.tx.dtable[slot as usize * 2 + 1].set(
+                status_addr,
+                size_of::<PcmStatus>() as u32,
+                VIRTQ_DESC_F_WRITE,
+                0,
+            );
+            self.tx.avail.ring[self.next_xfer as usize % 16].set(slot * 2);
+            self.next_xfer += 1;
+            self.tx.avail.idx.set(self.next_xfer);
+        }
+
+        // Returns the number of used I/O messages.
+        pub fn used_xfers(&self) -> u16 {
+            self.tx.used.idx.get()
+        }
+
+        // Returns the status of the `index`th used I/O message.
+        pub fn xfer_status(&self, index: u16) -> PcmStatus {
+            let used = self.tx.used.ring[index as usize % 16].get();
+            assert_eq!(used.len, size_of::<PcmStatus>() as u32);
+            let slot = u64::from(used.id / 2);
+            self.mem
+                .read_obj(GuestAddress(STATUS_ADDR + slot * 0x10))
+                .unwrap()
+        }
+    }
+
+    pub fn pcm_request(code: u32) -> Vec<u8> {
+        PcmHeader {
+            hdr: SndHeader { code },
+            stream_id: 0,
+        }
+        .as_slice()
+        .to_vec()
+    }
+
+    fn set_params(channels: u8, format: u8, rate: u8, period_bytes: u32) -> Vec<u8> {
+        PcmSetParams {
+            hdr: PcmHeader {
+                hdr: SndHeader {
+                    code: VIRTIO_SND_R_PCM_SET_PARAMS,
+                },
+                stream_id: 0,
+            },
+            buffer_bytes: period_bytes * 4,
+            period_bytes,
+            features: 0,
+            channels,
+            format,
+            rate,
+            padding: 0,
+        }
+        .as_slice()
+        .to_vec()
+    }
+
+    pub fn default_set_params() -> Vec<u8> {
+        set_params(CHANNELS, VIRTIO_SND_PCM_FMT_S16, 7, PERIOD_BYTES)
+    }
+
+    // Sets the parameters of the stream, and prepares it.
+    fn prepare(driver: &mut Driver, sound: &mut Sound) {
+        assert_eq!(
+            driver.request_code(sound, &default_set_params()),
+            VIRTIO_SND_S_OK
+        );
+        assert_eq!(
+            driver.request_code(sound, &pcm_request(VIRTIO_SND_R_PCM_PREPARE)),
+            VIRTIO_SND_S_OK
+        );
+    }
+
+    #[test]
+    fn test_virtio_features() {
+        let (sound, path) = default_sound();
+        assert_eq!(sound.device_type(), TYPE_SOUND);
+        assert_eq!(sound.id(), SOUND_DEV_ID);
+        assert_eq!(sound.avail_features(), 1u64 << VIRTIO_F_VERSION_1);
+        assert_eq!(sound.queues().len(), NUM_QUEUES);
+        assert_eq!(sound.queue_events().len(), NUM_QUEUES);
+        assert_eq!(sound.sink().path(), path);
+        fs::remove_file(&path).unwrap();
+
+        let sink = PcmSink::open_file(&path).unwrap();
+        match Sound::new(44000, CHANNELS, sink) {
+            Err(Error::UnsupportedRate(44000)) => (),
+            _ => panic!("The rate can't be described by virtio-snd."),
+        }
+        let sink = PcmSink::open_file(&path).unwrap();
+        match Sound::new(RATE, 0, sink) {
+            Err(Error::UnsupportedChannels(0)) => (),
+            _ => panic!("The stream must have channels."),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_config_space() {
+        let (mut sound, path) = default_sound();
+        let mut data = [0u8; 12];
+        sound.read_config(0, &mut data);
+        assert_eq!(data, [0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+
+        // Out of bounds reads and all writes are ignored.
+        let mut data = [0xffu8; 4];
+        sound.read_config(12, &mut data);
+        assert_eq!(data, [0xffu8; 4]);
+        sound.write_config(4, &[0u8; 4]);
+        sound.read_config(4, &mut data);
+        assert_eq!(data, [1, 0, 0, 0]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_status() {
+        let mem = default_mem();
+        let status = PcmStatus {
+            status: VIRTIO_SND_S_OK,
+            latency_bytes: 0,
+        };
+
+        // The status ends the writable descriptors, even when split across them.
+        let writable = [(GuestAddress(0x1000), 0x10), (GuestAddress(0x2000), 0x4)];
+        assert_eq!(write_status(&mem, &writable, VIRTIO_SND_S_OK).unwrap(), 8);
+        let mut bytes = [0u8; 8];
+        mem.read_slice(&mut bytes[..4], GuestAddress(0x100c))
+            .unwrap();
+        mem.read_slice(&mut bytes[4..], GuestAddress(0x2000))
+            .unwrap();
+        assert_eq!(bytes, status.as_slice());
+
+        match write_status(&mem, &[(GuestAddress(0x1000), 0x4)], VIRTIO_SND_S_OK) {
+            Err(Error::MissingResponseDescriptor) => (),
+            _ => panic!("The status doesn't fit."),
+        }
+    }
+
+    #[test]
+    fn test_pcm_info() {
+        let mem = default_mem();
+        let (mut sound, path) = default_sound();
+        let mut driver = Driver::new(&mut sound, &mem);
+        sound.activate(mem.clone()).unwrap();
+
+        let query = |start_id, count, size| {
+            QueryInfo {
+                hdr: SndHeader {
+                    code: VIRTIO_SND_R_PCM_INFO,
+                },
+                start_id,
+                count,
+                size,
+            }
+            .as_slice()
+            .to_vec()
+        };
+        let response = driver.request(&mut sound, &query(0, 1, size_of::<PcmInfo>() as u32));
+        assert_eq!(
+            read_obj::<SndHeader>(&response, 0).unwrap().code,
+            VIRTIO_SND_S_OK
+        );
+        assert_eq!(
+            response.len(),
+            size_of::<SndHeader>() + size_of::<PcmInfo>()
+        );
+        let info = read_obj::<PcmInfo>(&response, size_of::<SndHeader>()).unwrap();
+        assert_eq!(
+            info,
+            PcmInfo {
+                formats: 1 << VIRTIO_SND_PCM_FMT_S16,
+                rates: 1 << 7,
+                direction: VIRTIO_SND_D_OUTPUT,
+                channels_min: CHANNELS,
+                channels_max: CHANNELS,
+                ..Default::default()
+            }
+        );
+
+        // Longer items are padded.
+        let response = driver.request(&mut sound, &query(0, 1, 40));
+        assert_eq!(response.len(), size_of::<SndHeader>() + 40);
+
+        // Out of range queries are rejected.
+        assert_eq!(
+            driver.request_code(&mut sound, &query(0, 2, 32)),
+            VIRTIO_SND_S_BAD_MSG
+        );
+        assert_eq!(
+            driver.request_code(&mut sound, &query(u32::max_value(), 2, 32)),
+            VIRTIO_SND_S_BAD_MSG
+        );
+        assert_eq!(
+            driver.request_code(&mut sound, &query(0, 1, 1 << 20)),
+            VIRTIO_SND_S_BAD_MSG
+        );
+
+        // There are no jacks nor channel maps.
+        let mut jack_query = query(0, 1, 32);
+        jack_query[..4].copy_from_slice(&VIRTIO_SND_R_JACK_INFO.to_le_bytes());
+        assert_eq!(
+            driver.request_code(&mut sound, &jack_query),
+            VIRTIO_SND_S_BAD_MSG
+        );
+        assert_eq!(
+            driver.request_code(&mut sound, &pcm_request(0xffff)),
+            VIRTIO_SND_S_NOT_SUPP
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_pcm_set_params() {
+        let mem = default_mem();
+        let (mut sound, path) = default_sound();
+        let mut driver = Driver::new(&mut sound, &mem);
+        sound.activate(mem.clone()).unwrap();
+
+        // Only the configured stream parameters are supported.
+        for request in &[
+            set_params(1, VIRTIO_SND_PCM_FMT_S16, 7, PERIOD_BYTES),
+            set_params(CHANNELS, VIRTIO_SND_PCM_FMT_S16 + 1, 7, PERIOD_BYTES),
+            set_params(CHANNELS, VIRTIO_SND_PCM_FMT_S16, 6, PERIOD_BYTES),
+            set_params(CHANNELS, VIRTIO_SND_PCM_FMT_S16, 7, 0),
+            set_params(CHANNELS, VIRTIO_SND_PCM_FMT_S16, 7, 3),
+            set_params(CHANNELS, VIRTIO_SND_PCM_FMT_S16, 7, MAX_BUFFER_BYTES),
+        ] {
+            assert_eq!(
+                driver.request_code(&mut sound, request),
+                VIRTIO_SND_S_NOT_SUPP
+            );
+        }
+        assert_eq!(sound.params, None);
+        assert_eq!(
+            driver.request_code(&mut sound, &default_set_params()),
+            VIRTIO_SND_S_OK
+        );
+        assert_eq!(
+            sound.params,
+            Some(PcmParams {
+                buffer_bytes: PERIOD_BYTES * 4,
+                period_bytes: PERIOD_BYTES,
+            })
+        );
+
+        // Truncated requests are rejected.
+        assert_eq!(
+            driver.request_code(&mut sound, &default_set_params()[..20]),
+            VIRTIO_SND_S_BAD_MSG
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_pcm_transitions() {
+        let mem = default_mem();
+        let (mut sound, path) = default_sound();
+        let mut driver = Driver::new(&mut sound, &mem);
+        sound.activate(mem.clone()).unwrap();
+
+        // The stream can't be prepared before its parameters are set.
+        assert_eq!(
+            driver.request_code(&mut sound, &pcm_request(VIRTIO_SND_R_PCM_PREPARE)),
+            VIRTIO_SND_S_BAD_MSG
+        );
+        prepare(&mut driver, &mut sound);
+        assert_eq!(sound.state, StreamState::Prepared);
+
+        let transitions = [
+            (
+                VIRTIO_SND_R_PCM_STOP,
+                VIRTIO_SND_S_BAD_MSG,
+                StreamState::Prepared,
+            ),
+            (
+                VIRTIO_SND_R_PCM_START,
+                VIRTIO_SND_S_OK,
+                StreamState::Running,
+            ),
+            (
+                VIRTIO_SND_R_PCM_RELEASE,
+                VIRTIO_SND_S_BAD_MSG,
+                StreamState::Running,
+            ),
+            (VIRTIO_SND_R_PCM_STOP, VIRTIO_SND_S_OK, StreamState::Stopped),
+            (
+                VIRTIO_SND_R_PCM_START,
+                VIRTIO_SND_S_OK,
+                StreamState::Running,
+            ),
+            (VIRTIO_SND_R_PCM_STOP, VIRTIO_SND_S_OK, StreamState::Stopped),
+            (
+                VIRTIO_SND_R_PCM_RELEASE,
+                VIRTIO_SND_S_OK,
+                StreamState::Released,
+            ),
+            (
+                VIRTIO_SND_R_PCM_START,
+                VIRTIO_SND_S_BAD_MSG,
+                StreamState::Released,
+            ),
+            (
+                VIRTIO_SND_R_PCM_PREPARE,
+                VIRTIO_SND_S_OK,
+                StreamState::Prepared,
+            ),
+        ];
+        for (code, status, state) in transitions.iter() {
+            assert_eq!(
+                driver.request_code(&mut sound, &pcm_request(*code)),
+                *status
+            );
+            assert_eq!(sound.state, *state);
+        }
+
+        // The parameters can't change while the stream is running.
+        assert_eq!(
+            driver.request_code(&mut sound, &pcm_request(VIRTIO_SND_R_PCM_START)),
+            VIRTIO_SND_S_OK
+        );
+        assert_eq!(
+            driver.request_code(&mut sound, &default_set_params()),
+            VIRTIO_SND_S_BAD_MSG
+        );
+
+        // Only the output stream exists.
+        let mut request = pcm_request(VIRTIO_SND_R_PCM_STOP);
+        request[4] = 1;
+        assert_eq!(
+            driver.request_code(&mut sound, &request),
+            VIRTIO_SND_S_BAD_MSG
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_playback() {
+        let mem = default_mem();
+        let (mut sound, path) = default_sound();
+        let mut driver = Driver::new(&mut sound, &mem);
+        sound.activate(mem.clone()).unwrap();
+        prepare(&mut driver, &mut sound);
+
+        // The messages are held until the stream starts.
+        let period_time = sound.play_time(PERIOD_BYTES as usize);
+        assert_eq!(period_time, Duration::from_millis(1));
+        driver.queue_xfer(0, &[1u8; PERIOD_BYTES as usize]);
+        driver.queue_xfer(0, &[2u8; PERIOD_BYTES as usize]);
+        let now = Instant::now();
+        assert!(!sound.process_tx_queue(now));
+        assert_eq!(sound.pending.len(), 2);
+        assert!(fs::read(&path).unwrap().is_empty());
+
+        // Once started, the messages are played one after the other, and complete once played
+        // out.
+        assert!(!sound
+            .pcm_transition(VIRTIO_SND_R_PCM_START, &pcm_request(0), now)
+            .unwrap());
+        assert_eq!(fs::read(&path).unwrap(), vec![1u8; PERIOD_BYTES as usize]);
+        assert!(!sound.play(now + period_time / 2));
+        assert!(sound.play(now + period_time));
+        assert_eq!(driver.used_xfers(), 1);
+        assert_eq!(driver.xfer_status(0).status, VIRTIO_SND_S_OK);
+        assert_eq!(
+            fs::read(&path).unwrap(),
+            [[1u8; PERIOD_BYTES as usize], [2u8; PERIOD_BYTES as usize]].concat()
+        );
+        // A late timer catches up.
+        assert!(sound.play(now + period_time * 3));
+        assert_eq!(driver.used_xfers(), 2);
+        assert!(sound.pending.is_empty());
+        assert_eq!(sound.played_until, None);
+
+        // The stream restarts at the next message, after running dry.
+        let now = now + period_time * 10;
+        driver.queue_xfer(0, &[3u8; PERIOD_BYTES as usize]);
+        assert!(!sound.process_tx_queue(now));
+        assert_eq!(sound.played_until, Some(now + period_time));
+
+        // Stopping the stream lets the message being played complete, but no other one start.
+        driver.queue_xfer(0, &[4u8; PERIOD_BYTES as usize]);
+        assert!(!sound.process_tx_queue(now));
+        assert!(!sound
+            .pcm_transition(VIRTIO_SND_R_PCM_STOP, &pcm_request(0), now)
+            .unwrap());
+        assert!(sound.play(now + period_time * 2));
+        assert_eq!(driver.used_xfers(), 3);
+        assert_eq!(sound.pending.len(), 1);
+        assert_eq!(fs::read(&path).unwrap().len(), 3 * PERIOD_BYTES as usize);
+
+        // Releasing the stream completes the pending messages.
+        assert!(sound
+            .pcm_transition(VIRTIO_SND_R_PCM_RELEASE, &pcm_request(0), now)
+            .unwrap());
+        assert_eq!(driver.used_xfers(), 4);
+        assert_eq!(driver.xfer_status(3).status, VIRTIO_SND_S_OK);
+        assert!(sound.pending.is_empty());
+        assert_eq!(fs::read(&path).unwrap().len(), 3 * PERIOD_BYTES as usize);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_xfers() {
+        let mem = default_mem();
+        let (mut sound, path) = default_sound();
+        let mut driver = Driver::new(&mut sound, &mem);
+        sound.activate(mem.clone()).unwrap();
+        let now = Instant::now();
+
+        // Messages are rejected before the stream is prepared.
+        driver.queue_xfer(0, &[0u8; 4]);
+        assert!(sound.process_tx_queue(now));
+        assert_eq!(driver.xfer_status(0).status, VIRTIO_SND_S_BAD_MSG);
+
+        prepare(&mut driver, &mut sound);
+        // A message for an inexistent stream.
+        driver.queue_xfer(1, &[0u8; 4]);
+        // A message with a partial frame.
+        driver.queue_xfer(0, &[0u8; 6]);
+        // A message larger than the buffer of the stream.
+        driver.queue_xfer(0, &vec![0u8; PERIOD_BYTES as usize * 4 + 4]);
+        assert!(sound.process_tx_queue(now));
+        assert_eq!(driver.used_xfers(), 4);
+        for index in 1..4 {
+            assert_eq!(driver.xfer_status(index).status, VIRTIO_SND_S_BAD_MSG);
+        }
+        assert!(sound.pending.is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_rx_queue() {
+        let mem = default_mem();
+        let (mut sound, path) = default_sound();
+        let rxq = VirtQueue::new(GuestAddress(0x2000), &mem, 16);
+        sound.set_queue(RX_INDEX, rxq.create_queue());
+        sound.activate(mem.clone()).unwrap();
+
+        mem.write_obj(PcmXfer { stream_id: 0 }, GuestAddress(REQUEST_ADDR))
+            .unwrap();
+        rxq.dtable[0].set(REQUEST_ADDR, 4, VIRTQ_DESC_F_NEXT, 1);
+        rxq.dtable[1].set(RESPONSE_ADDR, 0x100, VIRTQ_DESC_F_WRITE, 0);
+        rxq.avail.ring[0].set(0);
+        rxq.avail.idx.set(1);
+
+        // There is no input stream.
+        assert!(sound.process_rx_queue());
+        assert_eq!(rxq.used.idx.get(), 1);
+        assert_eq!(rxq.used.ring[0].get().len, size_of::<PcmStatus>() as u32);
+        let status: PcmStatus = mem.read_obj(GuestAddress(RESPONSE_ADDR + 0xf8)).unwrap();
+        assert_eq!(status.status, VIRTIO_SND_S_BAD_MSG);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,186 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::os::unix::io::AsRawFd;
+use std::time::Instant;
+
+use logger::{Metric, METRICS};
+use polly::event_manager::{EventManager, Subscriber};
+use utils::epoll::{EpollEvent, EventSet};
+
+use crate::virtio::sound::device::Sound;
+use crate::virtio::sound::{CONTROL_INDEX, EVENT_INDEX, RX_INDEX, TX_INDEX};
+use crate::virtio::VirtioDevice;
+
+impl Sound {
+    fn process_activate_event(&self, event_manager: &mut EventManager) {
+        // The subscriber must exist as we previously registered activate_evt via
+        // `interest_list()`.
+        let self_subscriber = event_manager
+            .subscriber(self.activate_evt.as_raw_fd())
+            .unwrap();
+
+        let fds = self
+            .queue_evts
+            .iter()
+            .map(|queue_evt| queue_evt.as_raw_fd())
+            .chain(std::iter::once(self.timer_fd.as_raw_fd()));
+        for fd in fds {
+            event_manager
+                .register(
+                    fd,
+                    EpollEvent::new(EventSet::IN, fd as u64),
+                    self_subscriber.clone(),
+                )
+                .unwrap_or_else(|e| {
+                    error!("Failed to register sound fd with event manager: {:?}", e);
+                });
+        }
+
+        event_manager
+            .unregister(self.activate_evt.as_raw_fd())
+            .unwrap_or_else(|e| {
+                error!("Failed to unregister sound activate evt: {:?}", e);
+            })
+    }
+
+    fn process_queue_event(&mut self, queue_index: usize) {
+        if let Err(e) = self.queue_evts[queue_index].read() {
+            error!("Failed to get sound queue event: {:?}", e);
+            METRICS.sound.event_fails.inc();
+            return;
+        }
+
+        let used_any = match queue_index {
+            CONTROL_INDEX => self.process_control_queue(Instant::now()),
+            TX_INDEX => self.process_tx_queue(Instant::now()),
+            RX_INDEX => self.process_rx_queue(),
+            // The device never sends events, so the buffers of the event queue are kept.
+            _ => false,
+        };
+        if used_any {
+            let _ = self.signal_used_queue();
+        }
+    }
+
+    fn process_timer_event(&mut self) {
+        // Spurious wake ups are harmless, only the played out messages get completed.
+        self.timer_fd.read();
+        if self.play(Instant::now()) {
+            let _ = self.signal_used_queue();
+        }
+    }
+}
+
+impl Subscriber for Sound {
+    // Handle an event for the queues or the playback timer.
+    fn process(&mut self, event: &EpollEvent, evmgr: &mut EventManager) {
+        let source = event.fd();
+        let event_set = event.event_set();
+
+        let supported_events = EventSet::IN;
+        if !supported_events.contains(event_set) {
+            warn!(
+                "Sound: Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
+
+        if self.is_activated() {
+            let activate_fd = self.activate_evt.as_raw_fd();
+            let timer_fd = self.timer_fd.as_raw_fd();
+            let queue_index = self
+                .queue_evts
+                .iter()
+                .position(|queue_evt| queue_evt.as_raw_fd() == source);
+            if let Some(queue_index) = queue_index {
+                self.process_queue_event(queue_index);
+            } else if timer_fd == source {
+                self.process_timer_event();
+            } else if activate_fd == source {
+                self.process_activate_event(evmgr);
+            } else {
+                warn!("Sound: Spurious event received: {:?}", source);
+            }
+        } else {
+            warn!(
+                "Sound: The device is not yet activated. Spurious event received: {:?}",
+                source
+            );
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        vec![EpollEvent::new(
+            EventSet::IN,
+            self.activate_evt.as_raw_fd() as u64,
+        )]
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::virtio::sound::device::tests::{
+        default_mem, default_set_params, default_sound, pcm_request, Driver,
+    };
+    use crate::virtio::sound::protocol::{VIRTIO_SND_R_PCM_PREPARE, VIRTIO_SND_R_PCM_START};
+
+    #[test]
+    fn test_event_handler() {
+        let mut event_manager = EventManager::new().unwrap();
+        let mem = default_mem();
+        let (mut sound, path) = default_sound();
+        let mut driver = Driver::new(&mut sound, &mem);
+        driver.queue_request(&default_set_params());
+
+        let sound = Arc::new(Mutex::new(sound));
+        event_manager.add_subscriber(sound.clone()).unwrap();
+
+        // Trigger the queue event. It is not processed before activation.
+        sound.lock().unwrap().queue_evts[CONTROL_INDEX]
+            .write(1)
+            .unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 0);
+
+        // Now activate the device, which registers the queue events and the timer.
+        sound.lock().unwrap().activate(mem.clone()).unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+
+        // Handle the pending queue event through EventManager.
+        event_manager
+            .run_with_timeout(100)
+            .expect("Sound event timeout or error.");
+        assert_eq!(sound.lock().unwrap().interrupt_evt().read().unwrap(), 1);
+
+        // Start the stream, and play a 1ms message, which the timer completes.
+        for code in &[VIRTIO_SND_R_PCM_PREPARE, VIRTIO_SND_R_PCM_START] {
+            driver.queue_request(&pcm_request(*code));
+            sound.lock().unwrap().queue_evts[CONTROL_INDEX]
+                .write(1)
+                .unwrap();
+            event_manager.run_with_timeout(100).unwrap();
+        }
+        driver.queue_xfer(0, &[0u8; 192]);
+        sound.lock().unwrap().queue_evts[TX_INDEX].write(1).unwrap();
+        event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(driver.used_xfers(), 0);
+        while driver.used_xfers() == 0 {
+            assert!(event_manager.run_with_timeout(100).unwrap() > 0);
+        }
+        assert_eq!(fs::read(&path).unwrap().len(), 192);
+
+        // The buffers of the event queue are kept.
+        sound.lock().unwrap().queue_evts[EVENT_INDEX]
+            .write(1)
+            .unwrap();
+        assert_eq!(event_manager.run_with_timeout(100).unwrap(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,55 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod backend;
+pub mod device;
+pub mod event_handler;
+pub mod protocol;
+
+pub use self::backend::{PcmSink, SinkKind};
+pub use self::device::Sound;
+pub use self::event_handler::*;
+
+use vm_memory::GuestMemoryError;
+
+/// Device ID used in MMIO device identification.
+/// Because the sound device is unique per-vm, this ID can be hardcoded.
+pub const SOUND_DEV_ID: &str = "sound";
+/// Virtio sound device ID, as defined in `include/uapi/linux/virtio_ids.h`.
+pub const TYPE_SOUND: u32 = 25;
+pub const QUEUE_SIZE: u16 = 256;
+pub const NUM_QUEUES: usize = 4;
+pub const CONTROL_INDEX: usize = 0;
+pub const EVENT_INDEX: usize = 1;
+pub const TX_INDEX: usize = 2;
+pub const RX_INDEX: usize = 3;
+/// The largest number of channels of the output stream.
+pub const MAX_CHANNELS: u8 = 8;
+/// Upper bound of the ring buffer of the output stream, and so of a single I/O message.
+pub const MAX_BUFFER_BYTES: u32 = 4 << 20;
+/// Upper bound of the size of a control request.
+pub const MAX_REQUEST_SIZE: usize = 4096;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Activation error.
+    Activate(super::ActivateError),
+    /// Guest gave us a readable descriptor after a writable one.
+    UnexpectedReadableDescriptor,
+    /// Guest gave us a request larger than allowed.
+    RequestTooLarge,
+    /// Guest gave us a request without room for the response.
+    MissingResponseDescriptor,
+    /// Guest gave us bad memory addresses.
+    GuestMemory(GuestMemoryError),
+    /// Failed to create or signal an event fd.
+    EventFd(std::io::Error),
+    /// Failed to create the playback timer.
+    TimerFd(std::io::Error),
+    /// The frame rate can't be described by virtio-snd.
+    UnsupportedRate(u32),
+    /// The number of channels is zero or larger than `MAX_CHANNELS`.
+    UnsupportedChannels(u8),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
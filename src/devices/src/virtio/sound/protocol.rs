@@ -0,0 +1,174 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The subset of the virtio-snd protocol, as defined in `include/uapi/linux/virtio_snd.h`,
+//! implemented by the device.
+
+use vm_memory::ByteValued;
+
+// Jack control requests.
+pub const VIRTIO_SND_R_JACK_INFO: u32 = 1;
+pub const VIRTIO_SND_R_JACK_REMAP: u32 = 2;
+
+// PCM control requests.
+pub const VIRTIO_SND_R_PCM_INFO: u32 = 0x0100;
+pub const VIRTIO_SND_R_PCM_SET_PARAMS: u32 = 0x0101;
+pub const VIRTIO_SND_R_PCM_PREPARE: u32 = 0x0102;
+pub const VIRTIO_SND_R_PCM_RELEASE: u32 = 0x0103;
+pub const VIRTIO_SND_R_PCM_START: u32 = 0x0104;
+pub const VIRTIO_SND_R_PCM_STOP: u32 = 0x0105;
+
+// Channel map control requests.
+pub const VIRTIO_SND_R_CHMAP_INFO: u32 = 0x0200;
+
+// Status codes.
+pub const VIRTIO_SND_S_OK: u32 = 0x8000;
+pub const VIRTIO_SND_S_BAD_MSG: u32 = 0x8001;
+pub const VIRTIO_SND_S_NOT_SUPP: u32 = 0x8002;
+pub const VIRTIO_SND_S_IO_ERR: u32 = 0x8003;
+
+/// The stream plays samples from the guest.
+pub const VIRTIO_SND_D_OUTPUT: u8 = 0;
+
+/// Signed 16 bit little endian samples, the only supported format.
+pub const VIRTIO_SND_PCM_FMT_S16: u8 = 5;
+/// The size of a `VIRTIO_SND_PCM_FMT_S16` sample.
+pub const BYTES_PER_SAMPLE: usize = 2;
+
+/// The frame rates the protocol can describe, indexed by their `VIRTIO_SND_PCM_RATE_*` value.
+pub const PCM_RATES: [u32; 14] = [
+    5512, 8000, 11025, 16000, 22050, 32000, 44100, 48000, 64000, 88200, 96000, 176400, 192000,
+    384000,
+];
+
+/// Returns the `VIRTIO_SND_PCM_RATE_*` value of `rate`, if the protocol can describe it.
+pub fn pcm_rate_index(rate: u32) -> Option<u8> {
+    PCM_RATES.iter().position(|r| *r == rate).map(|i| i as u8)
+}
+
+/// The device configuration space.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct VirtioSndConfig {
+    pub jacks: u32,
+    pub streams: u32,
+    pub chmaps: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for VirtioSndConfig {}
+
+/// The header of the control requests, and of their responses.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct SndHeader {
+    pub code: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for SndHeader {}
+
+/// Queries the information about a range of items.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct QueryInfo {
+    pub hdr: SndHeader,
+    pub start_id: u32,
+    pub count: u32,
+    pub size: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for QueryInfo {}
+
+/// Describes a PCM stream.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct PcmInfo {
+    pub hda_fn_nid: u32,
+    pub features: u32,
+    pub formats: u64,
+    pub rates: u64,
+    pub direction: u8,
+    pub channels_min: u8,
+    pub channels_max: u8,
+    pub padding: [u8; 5],
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for PcmInfo {}
+
+/// The header of the requests targeting a PCM stream.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct PcmHeader {
+    pub hdr: SndHeader,
+    pub stream_id: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for PcmHeader {}
+
+/// Sets the parameters of a PCM stream.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct PcmSetParams {
+    pub hdr: PcmHeader,
+    pub buffer_bytes: u32,
+    pub period_bytes: u32,
+    pub features: u32,
+    pub channels: u8,
+    pub format: u8,
+    pub rate: u8,
+    pub padding: u8,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for PcmSetParams {}
+
+/// The header of the I/O messages.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct PcmXfer {
+    pub stream_id: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for PcmXfer {}
+
+/// The status of an I/O message, which ends its writable descriptors.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct PcmStatus {
+    pub status: u32,
+    pub latency_bytes: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for PcmStatus {}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use super::*;
+
+    #[test]
+    fn test_sizes() {
+        // The sizes mandated by the virtio specification.
+        assert_eq!(size_of::<VirtioSndConfig>(), 12);
+        assert_eq!(size_of::<QueryInfo>(), 16);
+        assert_eq!(size_of::<PcmInfo>(), 32);
+        assert_eq!(size_of::<PcmHeader>(), 8);
+        assert_eq!(size_of::<PcmSetParams>(), 24);
+        assert_eq!(size_of::<PcmStatus>(), 8);
+    }
+
+    #[test]
+    fn test_pcm_rate_index() {
+        assert_eq!(pcm_rate_index(5512), Some(0));
+        assert_eq!(pcm_rate_index(48000), Some(7));
+        assert_eq!(pcm_rate_index(384000), Some(13));
+        assert_eq!(pcm_rate_index(44000), None);
+    }
+}
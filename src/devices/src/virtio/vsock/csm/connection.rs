@@ -90,7 +90,7 @@ use super::super::packet::VsockPacket;
 use super::super::{Result as VsockResult, VsockChannel, VsockEpollListener, VsockError};
 use super::defs;
 use super::txbuf::TxBuf;
-use super::{ConnState, Error, PendingRx, PendingRxSet, Result};
+use super::{ConnState, Error, PendingRx, PendingRxSet, Result, VsockConnectionInfo};
 
 /// A self-managing connection object, that handles communication between a guest-side AF_VSOCK
 /// socket and a host-side `Read + Write + AsRawFd` stream.
@@ -588,6 +588,34 @@ where
         self.state
     }
 
+    /// Describe the connection, for the control plane.
+    pub fn info(&self) -> VsockConnectionInfo {
+        VsockConnectionInfo {
+            local_port: self.local_port,
+            peer_port: self.peer_port,
+            state: self.state,
+            rx_cnt: self.rx_cnt.0,
+            fwd_cnt: self.fwd_cnt.0,
+            tx_buf_len: self.tx_buf.len(),
+        }
+    }
+
+    /// Flush as much of the TX buffer as the host stream accepts without blocking, e.g. before
+    /// the connection gets dropped.
+    ///
+    /// Return the number of bytes left in the TX buffer.
+    pub fn flush_tx_buf(&mut self) -> usize {
+        match self.tx_buf.flush_to(&mut self.stream) {
+            Ok(flushed) => self.fwd_cnt += Wrapping(flushed as u32),
+            Err(Error::TxBufFlush(ref err)) if err.kind() == ErrorKind::WouldBlock => {}
+            Err(err) => warn!(
+                "vsock: error flushing TX buf for (lp={}, pp={}): {:?}",
+                self.local_port, self.peer_port, err
+            ),
+        }
+        self.tx_buf.len()
+    }
+
     /// Check if the credit information the peer has last received from us is outdated.
     fn peer_needs_credit_update(&self) -> bool {
         (self.fwd_cnt - self.last_fwd_cnt_to_peer).0 as usize >= defs::CONN_CREDIT_UPDATE_THRESHOLD
@@ -1099,6 +1127,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_info_and_flush() {
+        let mut ctx = CsmTestContext::new_established();
+
+        let mut stream = TestStream::new();
+        stream.write_state = StreamState::WouldBlock;
+        ctx.set_stream(stream);
+        let data = &[1, 2, 3, 4];
+        ctx.init_data_pkt(data);
+        ctx.send();
+
+        let info = ctx.conn.info();
+        assert_eq!(info.local_port, LOCAL_PORT);
+        assert_eq!(info.peer_port, PEER_PORT);
+        assert_eq!(info.state, ConnState::Established);
+        assert_eq!(info.fwd_cnt, 0);
+        assert_eq!(info.tx_buf_len, data.len());
+
+        // A stream that would block keeps the data in the TX buffer.
+        assert_eq!(ctx.conn.flush_tx_buf(), data.len());
+
+        ctx.set_stream(TestStream::new());
+        assert_eq!(ctx.conn.flush_tx_buf(), 0);
+        assert_eq!(ctx.conn.stream.write_buf, data);
+        let info = ctx.conn.info();
+        assert_eq!(info.fwd_cnt, data.len() as u32);
+        assert_eq!(info.tx_buf_len, 0);
+    }
+
     #[test]
     fn test_stream_write_error() {
         // Test case: sending a data packet to a broken / closed backing stream should kill it.
@@ -54,6 +54,23 @@ pub enum ConnState {
     Killed,
 }
 
+/// The state of a connection, as reported to the control plane.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VsockConnectionInfo {
+    /// The local (host) port.
+    pub local_port: u32,
+    /// The peer (guest) port.
+    pub peer_port: u32,
+    /// The current connection state.
+    pub state: ConnState,
+    /// Total number of bytes sent to the guest, modulo 2^32.
+    pub rx_cnt: u32,
+    /// Total number of bytes forwarded from the guest to the host stream, modulo 2^32.
+    pub fwd_cnt: u32,
+    /// Number of bytes sent by the guest and still waiting in the TX buffer.
+    pub tx_buf_len: usize,
+}
+
 /// An RX indication, used by `VsockConnection` to schedule future `recv_pkt()` responses.
 /// For instance, after being notified that there is available data to be read from the host stream
 /// (via `notify()`), the connection will store a `PendingRx::Rw` to be later inspected by
@@ -25,7 +25,7 @@ use std::sync::Arc;
 
 use utils::byte_order;
 use utils::eventfd::EventFd;
-use vm_memory::GuestMemoryMmap;
+use vm_memory::{Bytes, GuestMemoryMmap};
 
 use super::super::super::Error as DeviceError;
 use super::super::{
@@ -119,6 +119,10 @@ where
         &self.backend
     }
 
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
     /// Signal the guest driver that we've used some virtio buffers that it had previously made
     /// available.
     pub fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
@@ -204,6 +208,36 @@ where
 
         have_used
     }
+
+    /// Tell the guest driver that the transport was reset, e.g. because the microVM was restored
+    /// from a snapshot, so that it drops its connections and fetches the guest CID again.
+    /// Return `true` if the event was delivered, and `false` if the driver has not made any
+    /// event queue buffer available.
+    pub fn send_transport_reset_event(&mut self) -> result::Result<bool, DeviceError> {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem,
+            DeviceState::Inactive => return Ok(false),
+        };
+
+        let head = match self.queues[EVQ_INDEX].pop(mem) {
+            Some(head) => head,
+            None => return Ok(false),
+        };
+        let event_len = std::mem::size_of::<u32>() as u32;
+        let used_len = if !head.is_write_only() || head.len < event_len {
+            error!("vsock: {:?}", VsockError::UnwritableDescriptor);
+            0
+        } else if let Err(e) = mem.write_obj(uapi::VIRTIO_VSOCK_EVENT_TRANSPORT_RESET, head.addr) {
+            error!("vsock: {:?}", VsockError::GuestMemoryMmap(e));
+            0
+        } else {
+            event_len
+        };
+        self.queues[EVQ_INDEX].add_used(mem, head.index, used_len);
+        self.signal_used_queue()?;
+
+        Ok(used_len != 0)
+    }
 }
 
 impl<B> VirtioDevice for Vsock<B>
@@ -263,6 +297,20 @@ where
         }
     }
 
+    fn config_space_bytes(&self) -> Vec<u8> {
+        self.cid().to_le_bytes().to_vec()
+    }
+
+    fn debug_state(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("cid", self.cid.to_string()),
+            (
+                "backend_pending_rx",
+                self.backend.has_pending_rx().to_string(),
+            ),
+        ]
+    }
+
     fn write_config(&mut self, offset: u64, data: &[u8]) {
         warn!(
             "vsock: guest driver attempted to write device config (offset={:x}, len={:x})",
@@ -304,6 +352,8 @@ mod tests {
     use super::super::tests::TestContext;
     use super::*;
     use crate::virtio::vsock::defs::uapi;
+    use crate::virtio::VIRTQ_DESC_F_WRITE;
+    use vm_memory::GuestAddress;
 
     #[test]
     fn test_virtio_device() {
@@ -374,4 +424,39 @@ mod tests {
         // Test a correct activation.
         ctx.device.activate(ctx.mem.clone()).unwrap();
     }
+
+    #[test]
+    fn test_send_transport_reset_event() {
+        let test_ctx = TestContext::new();
+        let mut ctx = test_ctx.create_event_handler_context();
+
+        // Nothing is sent before the device is activated.
+        assert!(!ctx.device.send_transport_reset_event().unwrap());
+
+        ctx.mock_activate(test_ctx.mem.clone());
+        // The driver made no event queue buffer available.
+        assert!(!ctx.device.send_transport_reset_event().unwrap());
+
+        let event_addr = GuestAddress(0x0060_0000);
+        test_ctx.mem.write_obj(0xffff_ffffu32, event_addr).unwrap();
+        ctx.guest_evvq.dtable[0].set(event_addr.0, 4, VIRTQ_DESC_F_WRITE, 0);
+        ctx.guest_evvq.avail.ring[0].set(0);
+        ctx.guest_evvq.avail.idx.set(1);
+        assert!(ctx.device.send_transport_reset_event().unwrap());
+        assert_eq!(ctx.guest_evvq.used.idx.get(), 1);
+        assert_eq!(ctx.guest_evvq.used.ring[0].get().len, 4);
+        assert_eq!(
+            test_ctx.mem.read_obj::<u32>(event_addr).unwrap(),
+            uapi::VIRTIO_VSOCK_EVENT_TRANSPORT_RESET
+        );
+        assert_eq!(ctx.device.interrupt_evt.read().unwrap(), 1);
+
+        // A read only buffer is returned unused.
+        ctx.guest_evvq.dtable[1].set(event_addr.0, 4, 0, 0);
+        ctx.guest_evvq.avail.ring[1].set(1);
+        ctx.guest_evvq.avail.idx.set(2);
+        assert!(!ctx.device.send_transport_reset_event().unwrap());
+        assert_eq!(ctx.guest_evvq.used.idx.get(), 2);
+        assert_eq!(ctx.guest_evvq.used.ring[1].get().len, 0);
+    }
 }
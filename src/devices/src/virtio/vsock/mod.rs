@@ -11,12 +11,15 @@ mod event_handler;
 mod packet;
 pub mod persist;
 mod unix;
+mod vhost;
 
 use std::os::unix::io::AsRawFd;
 
+pub use self::csm::{ConnState as VsockConnState, VsockConnectionInfo};
 pub use self::defs::uapi::VIRTIO_ID_VSOCK as TYPE_VSOCK;
 pub use self::device::Vsock;
 pub use self::unix::{Error as VsockUnixBackendError, VsockUnixBackend};
+pub use self::vhost::{Error as VhostVsockError, VhostVsock, VHOST_VSOCK_PATH};
 
 use utils::epoll::EventSet;
 use vm_memory::GuestMemoryError;
@@ -85,6 +88,13 @@ mod defs {
         pub const VSOCK_TYPE_STREAM: u16 = 1;
 
         pub const VSOCK_HOST_CID: u64 = 2;
+
+        /// Vsock event IDs.
+        /// Defined in `/include/uapi/linux/virtio_vsock.h`.
+        ///
+        /// The communication has been interrupted, e.g. by a snapshot restore, and the driver
+        /// has to reset its connections and fetch the guest CID again.
+        pub const VIRTIO_VSOCK_EVENT_TRANSPORT_RESET: u32 = 0;
     }
 }
 
@@ -38,7 +38,7 @@ pub enum VsockBackendState {
 #[derive(Versionize)]
 pub struct VsockUdsState {
     /// The path for the UDS socket.
-    pub(crate) path: String,
+    pub path: String,
 }
 
 /// A helper structure that holds the constructor arguments for VsockUnixBackend
@@ -113,6 +113,8 @@ where
         vsock.avail_features = state.virtio_state.avail_features;
         vsock.interrupt_status = Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
         vsock.device_state = if state.virtio_state.activated {
+            // Make the event handler register the queue events once it gets subscribed.
+            vsock.activate_evt.write(1).map_err(VsockError::EventFd)?;
             DeviceState::Activated(constructor_args.mem)
         } else {
             DeviceState::Inactive
@@ -37,7 +37,7 @@ use std::os::unix::net::{UnixListener, UnixStream};
 
 use utils::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
 
-use super::super::csm::ConnState;
+use super::super::csm::{ConnState, VsockConnectionInfo};
 use super::super::defs::uapi;
 use super::super::packet::VsockPacket;
 use super::super::{
@@ -300,13 +300,27 @@ impl VsockEpollListener for VsockMuxer {
 impl VsockBackend for VsockMuxer {}
 
 impl VsockMuxer {
+    /// Provides the path of the host-side Unix socket.
+    pub fn host_sock_path(&self) -> &str {
+        &self.host_sock_path
+    }
+
     /// Muxer constructor.
     pub fn new(cid: u64, host_sock_path: String) -> Result<Self> {
         // Open/bind on the host Unix socket, so we can accept host-initiated
         // connections.
-        let host_sock = UnixListener::bind(&host_sock_path)
-            .and_then(|sock| sock.set_nonblocking(true).map(|_| sock))
-            .map_err(Error::UnixBind)?;
+        let host_sock = UnixListener::bind(&host_sock_path).map_err(Error::UnixBind)?;
+        Self::with_listener(cid, host_sock, host_sock_path)
+    }
+
+    /// Builds a muxer accepting the host-initiated connections on `host_sock`, already bound to
+    /// `host_sock_path` and listening, e.g. by the process which launched Firecracker.
+    pub fn with_listener(
+        cid: u64,
+        host_sock: UnixListener,
+        host_sock_path: String,
+    ) -> Result<Self> {
+        host_sock.set_nonblocking(true).map_err(Error::UnixBind)?;
 
         let mut muxer = Self {
             cid,
@@ -326,6 +340,42 @@ impl VsockMuxer {
         Ok(muxer)
     }
 
+    /// Describe the active connections, ordered by (local port, peer port).
+    pub fn connections(&self) -> Vec<VsockConnectionInfo> {
+        let mut connections: Vec<_> = self.conn_map.values().map(|conn| conn.info()).collect();
+        connections.sort_by_key(|info| (info.local_port, info.peer_port));
+        connections
+    }
+
+    /// Drop all the active connections, after flushing the data the guest has sent through them
+    /// to their host streams, as far as these accept it without blocking. Closing the host
+    /// streams lets the host ends see a clean EOF.
+    ///
+    /// No RST packet is sent to the guest: the caller is expected to reset the transport
+    /// instead, so that the guest driver drops all its connections at once.
+    ///
+    /// Return the number of dropped connections.
+    pub fn drain(&mut self) -> usize {
+        let keys: Vec<ConnMapKey> = self.conn_map.keys().cloned().collect();
+        for key in keys.iter() {
+            if let Some(conn) = self.conn_map.get_mut(key) {
+                let unflushed = conn.flush_tx_buf();
+                if unflushed > 0 {
+                    warn!(
+                        "vsock: dropping {} unflushed bytes of (lp={}, pp={})",
+                        unflushed, key.local_port, key.peer_port
+                    );
+                }
+            }
+            self.remove_connection(*key);
+        }
+        // The pending RX indications and kill timers only concern the dropped connections, or
+        // the RST packets the guest won't need after the transport reset.
+        self.rxq = MuxerRxQ::new();
+        self.killq = MuxerKillQ::new();
+        keys.len()
+    }
+
     /// Handle/dispatch an epoll event to its listener.
     fn handle_event(&mut self, fd: RawFd, evset: EventSet) {
         debug!(
@@ -1091,6 +1141,52 @@ mod tests {
         assert_eq!(ctx.pkt.buf().unwrap()[..data.len()], data);
     }
 
+    #[test]
+    fn test_drain() {
+        let mut ctx = MuxerTestContext::new("drain");
+        let (mut stream1, local_port1) = ctx.local_connect(1025);
+        let (mut stream2, local_port2) = ctx.local_connect(1026);
+
+        let data = [1, 2, 3, 4];
+        ctx.init_data_pkt(local_port1, 1025, &data);
+        ctx.send();
+        // Leave some host -> guest data pending.
+        stream2.write_all(&data).unwrap();
+        ctx.notify_muxer();
+        assert!(ctx.muxer.has_pending_rx());
+
+        let connections = ctx.muxer.connections();
+        assert_eq!(connections.len(), 2);
+        let ports: Vec<_> = connections
+            .iter()
+            .map(|info| (info.local_port, info.peer_port))
+            .collect();
+        let mut expected = vec![(local_port1, 1025), (local_port2, 1026)];
+        expected.sort();
+        assert_eq!(ports, expected);
+        let info = connections
+            .iter()
+            .find(|info| info.local_port == local_port1)
+            .unwrap();
+        assert_eq!(info.state, ConnState::Established);
+        assert_eq!(info.fwd_cnt, data.len() as u32);
+        assert_eq!(info.tx_buf_len, 0);
+
+        assert_eq!(ctx.muxer.drain(), 2);
+        assert!(ctx.muxer.connections().is_empty());
+        assert!(!ctx.muxer.has_pending_rx());
+        // Only the host socket listener is left.
+        assert_eq!(ctx.count_epoll_listeners(), (0, 0));
+
+        // The host ends read the data sent by the guest, then EOF.
+        let mut buf = Vec::new();
+        stream1.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, data);
+        let mut buf = Vec::new();
+        stream2.read_to_end(&mut buf).unwrap();
+        assert!(buf.is_empty());
+    }
+
     #[test]
     fn test_local_close() {
         let peer_port = 1025;
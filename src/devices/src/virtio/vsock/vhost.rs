@@ -0,0 +1,548 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+/// This is the `VirtioDevice` implementation of a vsock device offloaded to the host kernel, through
+/// the `/dev/vhost-vsock` device.
+///
+/// The kernel processes the RX and TX queues directly, and relays the guest vsock connections to
+/// the host `AF_VSOCK` sockets. Firecracker only sets up the vhost device upon activation, and
+/// forwards the kernel notifications of used buffers to the guest, since the virtio-mmio
+/// interrupt status has to be updated before raising the interrupt.
+///
+/// Upon its activation, the vhost-vsock device registers handlers for the following events/FDs:
+/// - an RX queue call FD;
+/// - a TX queue call FD; and
+/// - an event queue FD.
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use polly::event_manager::{EventManager, Subscriber};
+use utils::byte_order;
+use utils::epoll::{EpollEvent, EventSet};
+use utils::eventfd::EventFd;
+use utils::ioctl::{ioctl, ioctl_with_mut_ref, ioctl_with_ref};
+use vm_memory::{Address, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+
+use super::super::super::Error as DeviceError;
+use super::super::{
+    ActivateError, ActivateResult, DeviceState, Queue as VirtQueue, VirtioDevice,
+    VIRTIO_MMIO_INT_VRING,
+};
+use super::device::{EVQ_INDEX, RXQ_INDEX, TXQ_INDEX};
+use super::{defs, defs::uapi};
+
+/// Path to the host kernel vhost-vsock device.
+pub const VHOST_VSOCK_PATH: &str = "/dev/vhost-vsock";
+
+// Vhost ioctls, as defined in `include/uapi/linux/vhost.h`.
+const VHOST_GET_FEATURES: u64 = 0x8008_af00;
+const VHOST_SET_FEATURES: u64 = 0x4008_af00;
+const VHOST_SET_OWNER: u64 = 0xaf01;
+const VHOST_SET_MEM_TABLE: u64 = 0x4008_af03;
+const VHOST_SET_VRING_NUM: u64 = 0x4008_af10;
+const VHOST_SET_VRING_ADDR: u64 = 0x4028_af11;
+const VHOST_SET_VRING_BASE: u64 = 0x4008_af12;
+const VHOST_SET_VRING_KICK: u64 = 0x4008_af20;
+const VHOST_SET_VRING_CALL: u64 = 0x4008_af21;
+const VHOST_VSOCK_SET_GUEST_CID: u64 = 0x4008_af60;
+const VHOST_VSOCK_SET_RUNNING: u64 = 0x4004_af61;
+
+/// Maximum number of guest memory regions accepted by the vhost device, as set by the default
+/// value of the `max_mem_regions` vhost module parameter.
+const VHOST_MAX_MEM_REGIONS: usize = 64;
+
+/// The virtio features we are willing to negotiate with the vhost-vsock device:
+/// - VIRTIO_F_VERSION_1: the device conforms to at least version 1.0 of the VirtIO spec.
+const VHOST_FEATURES_MASK: u64 = 1 << uapi::VIRTIO_F_VERSION_1 as u64;
+
+// Mirrors `struct vhost_memory_region` from `include/uapi/linux/vhost_types.h`.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct VhostMemoryRegion {
+    guest_phys_addr: u64,
+    memory_size: u64,
+    userspace_addr: u64,
+    flags_padding: u64,
+}
+
+// Mirrors `struct vhost_memory`, with the flexible array of regions bounded to the number of
+// regions the kernel accepts.
+#[repr(C)]
+struct VhostMemory {
+    nregions: u32,
+    padding: u32,
+    regions: [VhostMemoryRegion; VHOST_MAX_MEM_REGIONS],
+}
+
+// Mirrors `struct vhost_vring_state`.
+#[repr(C)]
+struct VhostVringState {
+    index: u32,
+    num: u32,
+}
+
+// Mirrors `struct vhost_vring_addr`.
+#[repr(C)]
+struct VhostVringAddr {
+    index: u32,
+    flags: u32,
+    desc_user_addr: u64,
+    used_user_addr: u64,
+    avail_user_addr: u64,
+    log_guest_addr: u64,
+}
+
+// Mirrors `struct vhost_vring_file`.
+#[repr(C)]
+struct VhostVringFile {
+    index: u32,
+    fd: i32,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Cannot create an EventFd.
+    EventFd(io::Error),
+    /// The guest memory has more regions than the vhost device accepts.
+    GuestMemoryRegions(usize),
+    /// A virtio queue is not backed by guest memory.
+    InvalidQueueAddress,
+    /// Cannot open the vhost-vsock device.
+    OpenVhostDevice(io::Error),
+    /// A vhost ioctl failed.
+    VhostIoctl(&'static str, io::Error),
+}
+
+type Result<T> = result::Result<T, Error>;
+
+pub struct VhostVsock {
+    cid: u64,
+    vhost_file: File,
+    queues: Vec<VirtQueue>,
+    queue_events: Vec<EventFd>,
+    // Written by the kernel when it adds buffers to the used rings of the RX and TX queues.
+    call_events: Vec<EventFd>,
+    avail_features: u64,
+    acked_features: u64,
+    interrupt_status: Arc<AtomicUsize>,
+    interrupt_evt: EventFd,
+    // Same as for the vsock device with the Unix backend, the call and event queue FDs are only
+    // registered once the device gets activated.
+    activate_evt: EventFd,
+    device_state: DeviceState,
+}
+
+impl VhostVsock {
+    /// Create a new vhost-vsock device with the given VM CID. The CID is claimed in the host
+    /// kernel right away, so that a CID already in use is reported at configuration time.
+    pub fn new(cid: u64) -> Result<VhostVsock> {
+        let vhost_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_CLOEXEC | libc::O_NONBLOCK)
+            .open(VHOST_VSOCK_PATH)
+            .map_err(Error::OpenVhostDevice)?;
+
+        // Safe because we know the file is a vhost device and we check the return value.
+        let ret = unsafe { ioctl(&vhost_file, VHOST_SET_OWNER) };
+        check_ioctl("VHOST_SET_OWNER", ret)?;
+
+        let mut vhost_features = 0u64;
+        // Safe because the kernel only writes a u64 and we check the return value.
+        let ret =
+            unsafe { ioctl_with_mut_ref(&vhost_file, VHOST_GET_FEATURES, &mut vhost_features) };
+        check_ioctl("VHOST_GET_FEATURES", ret)?;
+
+        // Safe because the kernel only reads a u64 and we check the return value.
+        let ret = unsafe { ioctl_with_ref(&vhost_file, VHOST_VSOCK_SET_GUEST_CID, &cid) };
+        check_ioctl("VHOST_VSOCK_SET_GUEST_CID", ret)?;
+
+        let mut queue_events = Vec::new();
+        for _ in 0..defs::NUM_QUEUES {
+            queue_events.push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
+        }
+        let mut call_events = Vec::new();
+        for _ in 0..EVQ_INDEX {
+            call_events.push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
+        }
+
+        Ok(VhostVsock {
+            cid,
+            vhost_file,
+            queues: defs::QUEUE_SIZES
+                .iter()
+                .map(|&max_size| VirtQueue::new(max_size))
+                .collect(),
+            queue_events,
+            call_events,
+            avail_features: vhost_features & VHOST_FEATURES_MASK,
+            acked_features: 0,
+            interrupt_status: Arc::new(AtomicUsize::new(0)),
+            interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            device_state: DeviceState::Inactive,
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        defs::VSOCK_DEV_ID
+    }
+
+    pub fn cid(&self) -> u64 {
+        self.cid
+    }
+
+    /// Signal the guest driver that the kernel has used some virtio buffers that it had
+    /// previously made available.
+    pub fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+        self.interrupt_evt.write(1).map_err(|e| {
+            error!("Failed to signal used queue: {:?}", e);
+            DeviceError::FailedSignalingUsedQueue(e)
+        })
+    }
+
+    // Hands the guest memory and the RX and TX queues over to the kernel, and starts the device.
+    fn setup_vhost(&self, mem: &GuestMemoryMmap) -> Result<()> {
+        let features = self.acked_features;
+        // Safe because the kernel only reads a u64 and we check the return value.
+        let ret = unsafe { ioctl_with_ref(&self.vhost_file, VHOST_SET_FEATURES, &features) };
+        check_ioctl("VHOST_SET_FEATURES", ret)?;
+
+        let num_regions = mem.num_regions();
+        if num_regions > VHOST_MAX_MEM_REGIONS {
+            return Err(Error::GuestMemoryRegions(num_regions));
+        }
+        let mut mem_table = VhostMemory {
+            nregions: num_regions as u32,
+            padding: 0,
+            regions: [VhostMemoryRegion::default(); VHOST_MAX_MEM_REGIONS],
+        };
+        mem.with_regions_mut(|index, region| {
+            mem_table.regions[index] = VhostMemoryRegion {
+                guest_phys_addr: region.start_addr().raw_value(),
+                memory_size: region.len(),
+                userspace_addr: region.as_ptr() as u64,
+                flags_padding: 0,
+            };
+            Ok::<(), Error>(())
+        })?;
+        // Safe because the kernel only reads `nregions` regions and we check the return value.
+        let ret = unsafe { ioctl_with_ref(&self.vhost_file, VHOST_SET_MEM_TABLE, &mem_table) };
+        check_ioctl("VHOST_SET_MEM_TABLE", ret)?;
+
+        for &index in [RXQ_INDEX, TXQ_INDEX].iter() {
+            self.setup_vring(mem, index)?;
+        }
+
+        let running = 1i32;
+        // Safe because the kernel only reads an int and we check the return value.
+        let ret = unsafe { ioctl_with_ref(&self.vhost_file, VHOST_VSOCK_SET_RUNNING, &running) };
+        check_ioctl("VHOST_VSOCK_SET_RUNNING", ret)
+    }
+
+    fn setup_vring(&self, mem: &GuestMemoryMmap, index: usize) -> Result<()> {
+        let queue = &self.queues[index];
+        let size = usize::from(queue.actual_size());
+        let host_address = |addr, len| {
+            mem.get_slice(addr, len)
+                .map(|slice| slice.as_ptr() as u64)
+                .map_err(|_| Error::InvalidQueueAddress)
+        };
+
+        let num = VhostVringState {
+            index: index as u32,
+            num: u32::from(queue.actual_size()),
+        };
+        // Safe because the kernel only reads the struct and we check the return value.
+        let ret = unsafe { ioctl_with_ref(&self.vhost_file, VHOST_SET_VRING_NUM, &num) };
+        check_ioctl("VHOST_SET_VRING_NUM", ret)?;
+
+        let addr = VhostVringAddr {
+            index: index as u32,
+            flags: 0,
+            desc_user_addr: host_address(queue.desc_table, 16 * size)?,
+            used_user_addr: host_address(queue.used_ring, 6 + 8 * size)?,
+            avail_user_addr: host_address(queue.avail_ring, 6 + 2 * size)?,
+            log_guest_addr: 0,
+        };
+        // Safe because the kernel only reads the struct and we check the return value.
+        let ret = unsafe { ioctl_with_ref(&self.vhost_file, VHOST_SET_VRING_ADDR, &addr) };
+        check_ioctl("VHOST_SET_VRING_ADDR", ret)?;
+
+        let base = VhostVringState {
+            index: index as u32,
+            num: u32::from(queue.next_avail.0),
+        };
+        // Safe because the kernel only reads the struct and we check the return value.
+        let ret = unsafe { ioctl_with_ref(&self.vhost_file, VHOST_SET_VRING_BASE, &base) };
+        check_ioctl("VHOST_SET_VRING_BASE", ret)?;
+
+        let kick = VhostVringFile {
+            index: index as u32,
+            fd: self.queue_events[index].as_raw_fd(),
+        };
+        // Safe because the kernel only reads the struct and we check the return value.
+        let ret = unsafe { ioctl_with_ref(&self.vhost_file, VHOST_SET_VRING_KICK, &kick) };
+        check_ioctl("VHOST_SET_VRING_KICK", ret)?;
+
+        let call = VhostVringFile {
+            index: index as u32,
+            fd: self.call_events[index].as_raw_fd(),
+        };
+        // Safe because the kernel only reads the struct and we check the return value.
+        let ret = unsafe { ioctl_with_ref(&self.vhost_file, VHOST_SET_VRING_CALL, &call) };
+        check_ioctl("VHOST_SET_VRING_CALL", ret)
+    }
+
+    fn handle_call_event(&mut self, index: usize) {
+        if let Err(e) = self.call_events[index].read() {
+            error!("Failed to get vhost-vsock call event: {:?}", e);
+        } else if let Err(e) = self.signal_used_queue() {
+            error!("Failed to signal the vhost-vsock used queue: {:?}", e);
+        }
+    }
+
+    fn handle_evq_event(&mut self) {
+        // The event queue is only used to notify the driver, there is nothing to process.
+        if let Err(e) = self.queue_events[EVQ_INDEX].read() {
+            error!("Failed to consume vhost-vsock evq event: {:?}", e);
+        }
+    }
+
+    fn handle_activate_event(&self, event_manager: &mut EventManager) {
+        debug!("vhost-vsock: activate event");
+        if let Err(e) = self.activate_evt.read() {
+            error!("Failed to consume vhost-vsock activate event: {:?}", e);
+        }
+
+        // The subscriber must exist as we previously registered activate_evt via
+        // `interest_list()`.
+        let self_subscriber = event_manager
+            .subscriber(self.activate_evt.as_raw_fd())
+            .unwrap();
+
+        let fds = self
+            .call_events
+            .iter()
+            .chain(std::iter::once(&self.queue_events[EVQ_INDEX]))
+            .map(|evt| evt.as_raw_fd());
+        for fd in fds {
+            event_manager
+                .register(
+                    fd,
+                    EpollEvent::new(EventSet::IN, fd as u64),
+                    self_subscriber.clone(),
+                )
+                .unwrap_or_else(|e| {
+                    error!("Failed to register vhost-vsock events: {:?}", e);
+                });
+        }
+
+        event_manager
+            .unregister(self.activate_evt.as_raw_fd())
+            .unwrap_or_else(|e| {
+                error!("Failed to unregister vhost-vsock activate evt: {:?}", e);
+            })
+    }
+}
+
+fn check_ioctl(name: &'static str, ret: i32) -> Result<()> {
+    if ret < 0 {
+        return Err(Error::VhostIoctl(name, io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+impl VirtioDevice for VhostVsock {
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features
+    }
+
+    fn device_type(&self) -> u32 {
+        uapi::VIRTIO_ID_VSOCK
+    }
+
+    fn queues(&self) -> &[VirtQueue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [VirtQueue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_events
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.interrupt_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.interrupt_status.clone()
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        match offset {
+            0 if data.len() == 8 => byte_order::write_le_u64(data, self.cid()),
+            0 if data.len() == 4 => {
+                byte_order::write_le_u32(data, (self.cid() & 0xffff_ffff) as u32)
+            }
+            4 if data.len() == 4 => {
+                byte_order::write_le_u32(data, ((self.cid() >> 32) & 0xffff_ffff) as u32)
+            }
+            _ => warn!(
+                "vhost-vsock: received invalid read request of {} bytes at offset {}",
+                data.len(),
+                offset
+            ),
+        }
+    }
+
+    fn config_space_bytes(&self) -> Vec<u8> {
+        self.cid().to_le_bytes().to_vec()
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        warn!(
+            "vhost-vsock: guest driver attempted to write device config (offset={:x}, len={:x})",
+            offset,
+            data.len()
+        );
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
+        if self.queues.len() != defs::NUM_QUEUES {
+            error!(
+                "Cannot perform activate. Expected {} queue(s), got {}",
+                defs::NUM_QUEUES,
+                self.queues.len()
+            );
+            return Err(ActivateError::BadActivate);
+        }
+
+        if let Err(e) = self.setup_vhost(&mem) {
+            error!("Cannot set up the vhost-vsock device: {:?}", e);
+            return Err(ActivateError::BadActivate);
+        }
+
+        if self.activate_evt.write(1).is_err() {
+            error!("Cannot write to activate_evt",);
+            return Err(ActivateError::BadActivate);
+        }
+
+        self.device_state = DeviceState::Activated(mem);
+
+        Ok(())
+    }
+
+    fn is_activated(&self) -> bool {
+        match self.device_state {
+            DeviceState::Inactive => false,
+            DeviceState::Activated(_) => true,
+        }
+    }
+}
+
+impl Subscriber for VhostVsock {
+    fn process(&mut self, event: &EpollEvent, event_manager: &mut EventManager) {
+        let source = event.fd();
+        let event_set = event.event_set();
+        let rxq_call = self.call_events[RXQ_INDEX].as_raw_fd();
+        let txq_call = self.call_events[TXQ_INDEX].as_raw_fd();
+        let evq = self.queue_events[EVQ_INDEX].as_raw_fd();
+        let activate_evt = self.activate_evt.as_raw_fd();
+
+        if event_set != EventSet::IN {
+            warn!("vhost-vsock: unexpected event {:?}", event_set);
+            return;
+        }
+
+        if self.is_activated() {
+            match source {
+                _ if source == rxq_call => self.handle_call_event(RXQ_INDEX),
+                _ if source == txq_call => self.handle_call_event(TXQ_INDEX),
+                _ if source == evq => self.handle_evq_event(),
+                _ if source == activate_evt => self.handle_activate_event(event_manager),
+                _ => warn!("Unexpected vhost-vsock event received: {:?}", source),
+            }
+        } else {
+            warn!(
+                "Vhost-vsock: The device is not yet activated. Spurious event received: {:?}",
+                source
+            );
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        vec![EpollEvent::new(
+            EventSet::IN,
+            self.activate_evt.as_raw_fd() as u64,
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vhost_structs() {
+        // The ioctl numbers encode the sizes of the kernel structs.
+        assert_eq!(std::mem::size_of::<VhostMemoryRegion>(), 32);
+        assert_eq!(
+            std::mem::size_of::<VhostMemory>(),
+            8 + 32 * VHOST_MAX_MEM_REGIONS
+        );
+        assert_eq!(std::mem::size_of::<VhostVringState>(), 8);
+        assert_eq!(std::mem::size_of::<VhostVringAddr>(), 0x28);
+        assert_eq!(std::mem::size_of::<VhostVringFile>(), 8);
+    }
+
+    #[test]
+    fn test_vhost_vsock() {
+        let mut vhost_vsock = match VhostVsock::new(3) {
+            Ok(vhost_vsock) => vhost_vsock,
+            // The host may not have the vhost-vsock module loaded.
+            Err(Error::OpenVhostDevice(_)) => return,
+            Err(e) => panic!("Cannot create the vhost-vsock device: {:?}", e),
+        };
+        assert_eq!(vhost_vsock.id(), defs::VSOCK_DEV_ID);
+        assert_eq!(vhost_vsock.cid(), 3);
+        assert_eq!(vhost_vsock.device_type(), uapi::VIRTIO_ID_VSOCK);
+        assert_eq!(vhost_vsock.avail_features() & !VHOST_FEATURES_MASK, 0);
+        assert_eq!(vhost_vsock.queues().len(), defs::NUM_QUEUES);
+        assert_eq!(vhost_vsock.queue_events().len(), defs::NUM_QUEUES);
+        assert!(!vhost_vsock.is_activated());
+
+        let mut data = [0u8; 8];
+        vhost_vsock.read_config(0, &mut data);
+        assert_eq!(byte_order::read_le_u64(&data), 3);
+
+        // Only the activate event is registered before the activation.
+        let interest_list = vhost_vsock.interest_list();
+        assert_eq!(interest_list.len(), 1);
+        assert_eq!(
+            interest_list[0].data() as i32,
+            vhost_vsock.activate_evt.as_raw_fd()
+        );
+
+        vhost_vsock.set_acked_features(vhost_vsock.avail_features());
+        assert_eq!(vhost_vsock.acked_features(), vhost_vsock.avail_features());
+    }
+}
@@ -3,6 +3,7 @@
 
 use std::os::unix::io::AsRawFd;
 use std::path::PathBuf;
+use std::process;
 use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
@@ -13,9 +14,14 @@ use polly::event_manager::{EventManager, Subscriber};
 use seccomp::BpfProgram;
 use utils::epoll::{EpollEvent, EventSet};
 use utils::eventfd::EventFd;
+use vmm::action_policy::{ActionPolicy, AllowAll};
+use vmm::checkpoint::CheckpointConfig;
+use vmm::idempotency::IdempotencyCache;
+use vmm::resources::VmResources;
 use vmm::rpc_interface::{PrebootApiController, RuntimeApiController};
 use vmm::vmm_config::instance_info::InstanceInfo;
 use vmm::vmm_config::machine_config::VmConfig;
+use vmm::vmm_config::preopened_fds::PreopenedFds;
 use vmm::Vmm;
 
 use super::FIRECRACKER_VERSION;
@@ -24,25 +30,38 @@ struct ApiServerAdapter {
     api_event_fd: EventFd,
     from_api: Receiver<ApiRequest>,
     to_api: Sender<ApiResponse>,
+    to_api_event_fd: EventFd,
     controller: RuntimeApiController,
 }
 
 impl ApiServerAdapter {
     /// Runs the vmm to completion, while any arising control events are deferred
     /// to a `RuntimeApiController`.
+    #[allow(clippy::too_many_arguments)]
     fn run_microvm(
         api_event_fd: EventFd,
         from_api: Receiver<ApiRequest>,
         to_api: Sender<ApiResponse>,
+        to_api_event_fd: EventFd,
         vm_config: VmConfig,
         vmm: Arc<Mutex<Vmm>>,
+        idempotency: IdempotencyCache,
+        action_policy: Arc<dyn ActionPolicy>,
+        vm_resources: Option<Arc<Mutex<VmResources>>>,
         event_manager: &mut EventManager,
     ) {
+        let mut controller = RuntimeApiController::new(vm_config, vmm, idempotency, action_policy);
+        // Only the API is served while the devices are held for an operation.
+        controller.set_api_pollables(vec![api_event_fd.as_raw_fd()]);
+        if let Some(vm_resources) = vm_resources {
+            controller.set_vm_resources(vm_resources);
+        }
         let api_adapter = Arc::new(Mutex::new(Self {
             api_event_fd,
             from_api,
             to_api,
-            controller: RuntimeApiController::new(vm_config, vmm),
+            to_api_event_fd,
+            controller,
         }));
         event_manager
             .add_subscriber(api_adapter.clone())
@@ -56,19 +75,22 @@ impl ApiServerAdapter {
 }
 impl Subscriber for ApiServerAdapter {
     /// Handle a read event (EPOLLIN).
-    fn process(&mut self, event: &EpollEvent, _: &mut EventManager) {
+    fn process(&mut self, event: &EpollEvent, event_manager: &mut EventManager) {
         let source = event.fd();
         let event_set = event.event_set();
 
         if source == self.api_event_fd.as_raw_fd() && event_set == EventSet::IN {
             match self.from_api.try_recv() {
                 Ok(api_request) => {
-                    let response = self.controller.handle_request(*api_request);
+                    let response = self.controller.handle_request(*api_request, event_manager);
                     // Send back the result.
                     self.to_api
                         .send(Box::new(response))
                         .map_err(|_| ())
                         .expect("one-shot channel closed");
+                    self.to_api_event_fd
+                        .write(1)
+                        .expect("VMM: Failed to write the API response event_fd");
                 }
                 Err(TryRecvError::Empty) => {
                     warn!("Got a spurious notification from api thread");
@@ -91,27 +113,41 @@ impl Subscriber for ApiServerAdapter {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn run_with_api(
     seccomp_filter: BpfProgram,
     config_json: Option<String>,
+    live_update_sock: Option<PathBuf>,
+    incoming_migration_sock: Option<PathBuf>,
     bind_path: PathBuf,
     instance_info: InstanceInfo,
     start_time_us: Option<u64>,
     start_time_cpu_us: Option<u64>,
+    busy_poll_us: u64,
+    device_budget: usize,
+    preopened_fds: PreopenedFds,
+    checkpoint: Option<CheckpointConfig>,
 ) {
     // FD to notify of API events. This is a blocking eventfd by design.
     // It is used in the config/pre-boot loop which is a simple blocking loop
-    // which only consumes API events.
-    let api_event_fd = EventFd::new(0).expect("Cannot create API Eventfd.");
+    // which only consumes API events. Each read consumes a single event, so
+    // that the requests queued while the VMM is busy are all accounted for.
+    let api_event_fd = EventFd::new(libc::EFD_SEMAPHORE).expect("Cannot create API Eventfd.");
+    // FD to notify the API thread of responses, which it waits for along with
+    // the API requests.
+    let to_api_event_fd =
+        EventFd::new(libc::EFD_NONBLOCK).expect("Cannot create API response Eventfd.");
     // Channels for both directions between Vmm and Api threads.
     let (to_vmm, from_api) = channel();
     let (to_api, from_vmm) = channel();
 
     // MMDS only supported with API.
     let mmds_info = MMDS.clone();
+    let instance_id = instance_info.id.clone();
     let api_shared_info = Arc::new(RwLock::new(instance_info));
     let vmm_shared_info = api_shared_info.clone();
     let to_vmm_event_fd = api_event_fd.try_clone().unwrap();
+    let from_vmm_event_fd = to_api_event_fd.try_clone().unwrap();
 
     let api_seccomp_filter = seccomp_filter.clone();
     // Start the separate API thread.
@@ -124,6 +160,7 @@ pub fn run_with_api(
                 to_vmm,
                 from_vmm,
                 to_vmm_event_fd,
+                from_vmm_event_fd,
             )
             .expect("Cannot create API server")
             .bind_and_run(
@@ -151,6 +188,8 @@ pub fn run_with_api(
         .expect("API thread spawn failed.");
 
     let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+    event_manager.set_busy_poll(busy_poll_us);
+    event_manager.set_device_budget(device_budget);
 
     // Create the firecracker metrics object responsible for periodically printing metrics.
     let firecracker_metrics = Arc::new(Mutex::new(super::metrics::PeriodicMetrics::new()));
@@ -158,31 +197,85 @@ pub fn run_with_api(
         .add_subscriber(firecracker_metrics.clone())
         .expect("Cannot register the metrics event to the event manager.");
 
-    // Configure, build and start the microVM.
-    let (vm_resources, vmm) = match config_json {
-        Some(json) => super::build_microvm_from_json(seccomp_filter, &mut event_manager, json),
-        None => PrebootApiController::build_microvm_from_requests(
-            seccomp_filter,
-            &mut event_manager,
-            FIRECRACKER_VERSION.to_string(),
-            || {
-                let req = from_api
-                    .recv()
-                    .expect("The channel's sending half was disconnected. Cannot receive data.");
-                // Also consume the API event along with the message. It is safe to unwrap()
-                // because this event_fd is blocking.
-                api_event_fd
-                    .read()
-                    .expect("VMM: Failed to read the API event_fd");
-                *req
-            },
-            |response| {
-                to_api
-                    .send(Box::new(response))
-                    .expect("one-shot channel closed")
-            },
-        ),
-    };
+    // The same policy decides which actions are carried out before and after boot.
+    let action_policy: Arc<dyn ActionPolicy> = Arc::new(AllowAll);
+
+    // Configure, build and start the microVM, unless it is handed over or migrated by another
+    // process. Only the microVMs configured through the API have actions to replay, and only the
+    // microVMs configured in this process have the resources they were built from, to rebuild
+    // the configuration they checkpoint from.
+    let (vm_config, vmm, idempotency, vm_resources) =
+        match (live_update_sock, incoming_migration_sock, config_json) {
+            (Some(socket_path), _, _) => {
+                let (vm_config, vmm) = receive_microvm(
+                    seccomp_filter,
+                    &mut event_manager,
+                    socket_path,
+                    &preopened_fds,
+                );
+                (vm_config, vmm, IdempotencyCache::default(), None)
+            }
+            (None, Some(socket_path), _) => {
+                let (vm_config, vmm) = receive_migrated_microvm(
+                    seccomp_filter,
+                    &mut event_manager,
+                    socket_path,
+                    &preopened_fds,
+                );
+                (vm_config, vmm, IdempotencyCache::default(), None)
+            }
+            (None, None, Some(json)) => {
+                let (vm_resources, vmm) = super::build_microvm_from_json(
+                    seccomp_filter,
+                    &mut event_manager,
+                    json,
+                    preopened_fds,
+                    instance_id.clone(),
+                );
+                (
+                    vm_resources.vm_config().clone(),
+                    vmm,
+                    IdempotencyCache::default(),
+                    Some(Arc::new(Mutex::new(vm_resources))),
+                )
+            }
+            (None, None, None) => {
+                let (vm_resources, vmm, idempotency) =
+                    PrebootApiController::build_microvm_from_requests(
+                        seccomp_filter,
+                        &mut event_manager,
+                        FIRECRACKER_VERSION.to_string(),
+                        action_policy.clone(),
+                        preopened_fds,
+                        instance_id.clone(),
+                        || {
+                            let req = from_api.recv().expect(
+                                "The channel's sending half was disconnected. Cannot receive data.",
+                            );
+                            // Also consume the API event along with the message. It is safe to unwrap()
+                            // because this event_fd is blocking.
+                            api_event_fd
+                                .read()
+                                .expect("VMM: Failed to read the API event_fd");
+                            *req
+                        },
+                        |response| {
+                            to_api
+                                .send(Box::new(response))
+                                .expect("one-shot channel closed");
+                            to_api_event_fd
+                                .write(1)
+                                .expect("VMM: Failed to write the API response event_fd");
+                        },
+                    );
+                (
+                    vm_resources.vm_config().clone(),
+                    vmm,
+                    idempotency,
+                    Some(Arc::new(Mutex::new(vm_resources))),
+                )
+            }
+        };
 
     // Start the metrics.
     firecracker_metrics
@@ -190,15 +283,101 @@ pub fn run_with_api(
         .expect("Metrics lock poisoned.")
         .start(super::metrics::WRITE_METRICS_PERIOD_MS);
 
+    if let Some(checkpoint) = checkpoint {
+        super::start_checkpoints(
+            &mut event_manager,
+            checkpoint,
+            vmm.clone(),
+            instance_id,
+            vm_resources.clone(),
+        );
+    }
+
     // Update the api shared instance info.
-    api_shared_info.write().unwrap().started = true;
+    {
+        let mut shared_info = api_shared_info.write().unwrap();
+        shared_info.started = true;
+        shared_info.probes = vmm.lock().expect("Poisoned lock").probe_statuses();
+    }
 
     ApiServerAdapter::run_microvm(
         api_event_fd,
         from_api,
         to_api,
-        vm_resources.vm_config().clone(),
+        to_api_event_fd,
+        vm_config,
         vmm,
+        idempotency,
+        action_policy,
+        vm_resources,
         &mut event_manager,
     );
 }
+
+// Adopts the microVM handed over by another Firecracker process.
+#[cfg(target_arch = "x86_64")]
+fn receive_microvm(
+    seccomp_filter: BpfProgram,
+    event_manager: &mut EventManager,
+    socket_path: PathBuf,
+    preopened_fds: &PreopenedFds,
+) -> (VmConfig, Arc<Mutex<Vmm>>) {
+    let (vm_config, vmm) = vmm::live_update::receive_microvm(
+        &socket_path,
+        preopened_fds,
+        event_manager,
+        &seccomp_filter,
+    )
+    .unwrap_or_else(|err| {
+        error!("Receiving the microVM failed: {}", err);
+        process::exit(i32::from(vmm::FcExitCode::BadConfiguration));
+    });
+    info!("Successfully received the microVM");
+
+    (vm_config, vmm)
+}
+
+#[cfg(target_arch = "aarch64")]
+fn receive_microvm(
+    _: BpfProgram,
+    _: &mut EventManager,
+    _: PathBuf,
+    _: &PreopenedFds,
+) -> (VmConfig, Arc<Mutex<Vmm>>) {
+    error!("Live update is only supported on x86_64.");
+    process::exit(i32::from(vmm::FcExitCode::BadConfiguration));
+}
+
+// Receives the microVM migrated by another Firecracker process.
+#[cfg(target_arch = "x86_64")]
+fn receive_migrated_microvm(
+    seccomp_filter: BpfProgram,
+    event_manager: &mut EventManager,
+    socket_path: PathBuf,
+    preopened_fds: &PreopenedFds,
+) -> (VmConfig, Arc<Mutex<Vmm>>) {
+    let (vm_config, vmm) = vmm::migration::receive_microvm(
+        &socket_path,
+        preopened_fds,
+        event_manager,
+        &seccomp_filter,
+    )
+    .unwrap_or_else(|err| {
+        error!("Receiving the migrated microVM failed: {}", err);
+        process::exit(i32::from(vmm::FcExitCode::BadConfiguration));
+    });
+    info!("Successfully received the migrated microVM");
+
+    (vm_config, vmm)
+}
+
+#[cfg(target_arch = "aarch64")]
+fn receive_migrated_microvm(
+    _: BpfProgram,
+    _: &mut EventManager,
+    _: PathBuf,
+    _: &PreopenedFds,
+) -> (VmConfig, Arc<Mutex<Vmm>>) {
+    error!("Live migration is only supported on x86_64.");
+    process::exit(i32::from(vmm::FcExitCode::BadConfiguration));
+}
@@ -19,7 +19,6 @@ mod metrics;
 use backtrace::Backtrace;
 
 use std::fs;
-use std::io;
 use std::panic;
 use std::path::PathBuf;
 use std::process;
@@ -29,13 +28,17 @@ use logger::{Metric, LOGGER, METRICS};
 use polly::event_manager::EventManager;
 use seccomp::{BpfProgram, SeccompLevel};
 use utils::arg_parser::{ArgParser, Argument};
-use utils::terminal::Terminal;
+use utils::fd_uri;
 use utils::validators::validate_instance_id;
+use vmm::checkpoint::{CheckpointConfig, Checkpointer, DEFAULT_CHECKPOINT_INTERVAL_MS};
 use vmm::default_syscalls::get_seccomp_filter;
+use vmm::probes::ProbeStatuses;
 use vmm::resources::VmResources;
 use vmm::signal_handler::register_signal_handlers;
 use vmm::vmm_config::instance_info::InstanceInfo;
 use vmm::vmm_config::logger::{init_logger, LoggerConfig, LoggerLevel};
+use vmm::vmm_config::memory_limits::OomPolicy;
+use vmm::vmm_config::preopened_fds::PreopenedFds;
 
 // The reason we place default API socket under /run is that API socket is a
 // runtime file.
@@ -45,18 +48,24 @@ const DEFAULT_INSTANCE_ID: &str = "anonymous-instance";
 const FIRECRACKER_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 fn main() {
+    // Nothing is opened yet, so the open file descriptors are the ones inherited from the
+    // launching process, which the `fd://<fd>` URIs can name.
+    let inherited_fds = fd_uri::record_inherited_fds();
+
     LOGGER
         .configure(Some(DEFAULT_INSTANCE_ID.to_string()))
         .expect("Failed to register logger");
 
+    if let Err(e) = inherited_fds {
+        error!("Failed to record the inherited file descriptors: {}", e);
+        process::exit(i32::from(vmm::FcExitCode::GenericError));
+    }
+
     if let Err(e) = register_signal_handlers() {
         error!("Failed to register signal handlers: {}", e);
-        process::exit(i32::from(vmm::FC_EXIT_CODE_GENERIC_ERROR));
+        process::exit(i32::from(vmm::FcExitCode::GenericError));
     }
 
-    // We need this so that we can reset terminal to canonical mode if panic occurs.
-    let stdin = io::stdin();
-
     // Start firecracker by setting up a panic hook, which will be called before
     // terminating as we're building with panic = "abort".
     // It's worth noting that the abort is caused by sending a SIG_ABORT signal to the process.
@@ -65,12 +74,8 @@ fn main() {
         // origin of the panic, including the payload passed to panic! and the source code location
         // from which the panic originated.
         error!("Firecracker {}", info);
-        if let Err(e) = stdin.lock().set_canon_mode() {
-            error!(
-                "Failure while trying to reset stdin to canonical mode: {}",
-                e
-            );
-        }
+        // Restore the terminal, if the serial console acquired it.
+        vmm::console::restore_console();
 
         METRICS.vmm.panic_count.inc();
         let bt = Backtrace::new();
@@ -127,6 +132,60 @@ fn main() {
                 .requires("config-file")
                 .help("Optional parameter which allows starting and using a microVM without an active API socket.")
         )
+        .arg(
+            Argument::new("live-update-sock")
+                .takes_value(true)
+                .help("Path to a unix domain socket on which to receive a running microVM from another Firecracker process.")
+        )
+        .arg(
+            Argument::new("incoming-migration-sock")
+                .takes_value(true)
+                .help("Path to a unix domain socket on which to receive a microVM migrated by another Firecracker process.")
+        )
+        .arg(
+            Argument::new("busy-poll-us")
+                .takes_value(true)
+                .help("Maximum number of microseconds the event loop polls the devices before \
+                    sleeping, trading CPU time for a lower I/O latency. Disabled by default.")
+        )
+        .arg(
+            Argument::new("device-budget")
+                .takes_value(true)
+                .help("Maximum number of descriptor chains a device processes from one of its \
+                    queues before yielding the event loop to the other devices. Unlimited by default.")
+        )
+        .arg(
+            Argument::new("checkpoint-path")
+                .takes_value(true)
+                .help("Path to a file to which the state of the microVM is saved periodically, \
+                    for the control plane to recover its view of the instance after a crash.")
+        )
+        .arg(
+            Argument::new("checkpoint-interval-ms")
+                .takes_value(true)
+                .requires("checkpoint-path")
+                .help("Number of milliseconds between two checkpoints of the state of the \
+                    microVM. Defaults to 1000.")
+        )
+        .arg(
+            Argument::new("kvm-fd")
+                .takes_value(true)
+                .help("File descriptor of /dev/kvm, opened by the launching process.")
+        )
+        .arg(
+            Argument::new("tun-fds")
+                .takes_value(true)
+                .help("Comma separated list of <tap name>=<fd> of the file descriptors of \
+                    /dev/net/tun, opened by the launching process, backing the network \
+                    interfaces with these host device names.")
+        )
+        .arg(
+            Argument::new("disk-image-fds")
+                .takes_value(true)
+                .help("Comma separated list of <path>=<fd> of the file descriptors of the disk \
+                    images, opened by the launching process, backing the drives with these \
+                    paths on host.")
+        )
         .arg(
             Argument::new("log-path")
                 .takes_value(true)
@@ -151,6 +210,14 @@ fn main() {
                 .requires("log-path")
                 .help("Whether or not to include the file path and line number of the log's origin.")
         );
+    #[cfg(feature = "mmio-trace")]
+    {
+        arg_parser = arg_parser.arg(
+            Argument::new("mmio-trace")
+                .takes_value(true)
+                .help("Path to a fifo or a file receiving the trace of the MMIO accesses."),
+        );
+    }
 
     let arguments = match arg_parser.parse_from_cmdline() {
         Err(err) => {
@@ -159,21 +226,21 @@ fn main() {
                  For more information try --help.",
                 err
             );
-            process::exit(i32::from(vmm::FC_EXIT_CODE_ARG_PARSING));
+            process::exit(i32::from(vmm::FcExitCode::ArgParsing));
         }
         _ => {
             if let Some(help) = arg_parser.arguments().value_as_bool("help") {
                 if help {
                     println!("Firecracker v{}\n", FIRECRACKER_VERSION);
                     println!("{}", arg_parser.formatted_help());
-                    process::exit(i32::from(vmm::FC_EXIT_CODE_OK));
+                    process::exit(i32::from(vmm::FcExitCode::Ok));
                 }
             }
 
             if let Some(version) = arg_parser.arguments().value_as_bool("version") {
                 if version {
                     println!("Firecracker v{}\n", FIRECRACKER_VERSION);
-                    process::exit(i32::from(vmm::FC_EXIT_CODE_OK));
+                    process::exit(i32::from(vmm::FcExitCode::Ok));
                 }
             }
 
@@ -181,6 +248,26 @@ fn main() {
         }
     };
 
+    let kvm_fd = arguments.value_as_string("kvm-fd");
+    let tun_fds = arguments.value_as_string("tun-fds");
+    let disk_image_fds = arguments.value_as_string("disk-image-fds");
+    // Safe because the file descriptors named on the command line are inherited from the
+    // launching process, and nothing else in Firecracker knows about them.
+    let preopened_fds = unsafe {
+        PreopenedFds::from_args(
+            kvm_fd.as_ref().map(String::as_str),
+            tun_fds.as_ref().map(String::as_str),
+            disk_image_fds.as_ref().map(String::as_str),
+        )
+    }
+    .unwrap_or_else(|err| {
+        error!(
+            "Invalid file descriptors opened by the launching process: {}",
+            err
+        );
+        process::exit(i32::from(vmm::FcExitCode::BadConfiguration));
+    });
+
     // It's safe to unwrap here because the field's been provided with a default value.
     let instance_id = arguments.value_as_string("id").unwrap();
     validate_instance_id(instance_id.as_str()).expect("Invalid instance ID");
@@ -205,6 +292,14 @@ fn main() {
         init_logger(logger_config, FIRECRACKER_VERSION).expect("Could not initialize logger.");
     }
 
+    #[cfg(feature = "mmio-trace")]
+    {
+        if let Some(trace_path) = arguments.value_as_string("mmio-trace") {
+            vmm::vmm_config::logger::init_mmio_trace(&PathBuf::from(trace_path))
+                .expect("Could not initialize the MMIO trace.");
+        }
+    }
+
     // It's safe to unwrap here because the field's been provided with a default value.
     let seccomp_level = arguments.value_as_string("seccomp-level").unwrap();
     let seccomp_filter = get_seccomp_filter(
@@ -223,6 +318,47 @@ fn main() {
 
     let api_enabled = !arguments.value_as_bool("no-api").unwrap_or(false);
 
+    let busy_poll_us = arguments.value_as_string("busy-poll-us").map_or(0, |s| {
+        s.parse::<u64>()
+            .expect("'busy-poll-us' parameter expected to be of 'u64' type.")
+    });
+
+    let device_budget = arguments.value_as_string("device-budget").map_or(0, |s| {
+        s.parse::<usize>()
+            .expect("'device-budget' parameter expected to be of 'usize' type.")
+    });
+
+    let checkpoint = arguments
+        .value_as_string("checkpoint-path")
+        .map(|path| CheckpointConfig {
+            path: PathBuf::from(path),
+            interval_ms: arguments.value_as_string("checkpoint-interval-ms").map_or(
+                DEFAULT_CHECKPOINT_INTERVAL_MS,
+                |s| {
+                    s.parse::<u64>().ok().filter(|&ms| ms > 0).expect(
+                        "'checkpoint-interval-ms' parameter expected to be a positive 'u64'.",
+                    )
+                },
+            ),
+        });
+
+    let live_update_sock = arguments
+        .value_as_string("live-update-sock")
+        .map(PathBuf::from);
+    if live_update_sock.is_some() && vmm_config_json.is_some() {
+        error!("A microVM received on live update cannot be configured from a file.");
+        process::exit(i32::from(vmm::FcExitCode::BadConfiguration));
+    }
+    let incoming_migration_sock = arguments
+        .value_as_string("incoming-migration-sock")
+        .map(PathBuf::from);
+    if incoming_migration_sock.is_some()
+        && (vmm_config_json.is_some() || live_update_sock.is_some())
+    {
+        error!("A migrated microVM cannot be configured from a file or received on live update.");
+        process::exit(i32::from(vmm::FcExitCode::BadConfiguration));
+    }
+
     if api_enabled {
         let bind_path = arguments
             .value_as_string("api-sock")
@@ -243,49 +379,103 @@ fn main() {
             started: false,
             vmm_version: FIRECRACKER_VERSION.to_string(),
             app_name: "Firecracker".to_string(),
+            probes: ProbeStatuses::default(),
         };
         api_server_adapter::run_with_api(
             seccomp_filter,
             vmm_config_json,
+            live_update_sock,
+            incoming_migration_sock,
             bind_path,
             instance_info,
             start_time_us,
             start_time_cpu_us,
+            busy_poll_us,
+            device_budget,
+            preopened_fds,
+            checkpoint,
         );
     } else {
-        run_without_api(seccomp_filter, vmm_config_json);
+        run_without_api(
+            seccomp_filter,
+            vmm_config_json,
+            busy_poll_us,
+            device_budget,
+            preopened_fds,
+            instance_id,
+            checkpoint,
+        );
     }
 }
 
+// Saves the state of the started microVM periodically, as configured by `checkpoint`.
+fn start_checkpoints(
+    event_manager: &mut EventManager,
+    checkpoint: CheckpointConfig,
+    vmm: Arc<Mutex<vmm::Vmm>>,
+    instance_id: String,
+    vm_resources: Option<Arc<Mutex<VmResources>>>,
+) {
+    let mut checkpointer = Checkpointer::new(checkpoint, vmm, instance_id, vm_resources)
+        .unwrap_or_else(|err| {
+            error!("Cannot save the state of the microVM: {}", err);
+            process::exit(i32::from(vmm::FcExitCode::GenericError));
+        });
+    checkpointer.start();
+    event_manager
+        .add_subscriber(Arc::new(Mutex::new(checkpointer)))
+        .expect("Cannot register the checkpoint event to the event manager.");
+}
+
 // Configure and start a microVM as described by the command-line JSON.
 fn build_microvm_from_json(
     seccomp_filter: BpfProgram,
     event_manager: &mut EventManager,
     config_json: String,
+    preopened_fds: PreopenedFds,
+    instance_id: String,
 ) -> (VmResources, Arc<Mutex<vmm::Vmm>>) {
-    let vm_resources =
-        VmResources::from_json(&config_json, FIRECRACKER_VERSION).unwrap_or_else(|err| {
+    let mut vm_resources = VmResources::from_json(&config_json, FIRECRACKER_VERSION, preopened_fds)
+        .unwrap_or_else(|err| {
             error!(
                 "Configuration for VMM from one single json failed: {:?}",
                 err
             );
-            process::exit(i32::from(vmm::FC_EXIT_CODE_BAD_CONFIGURATION));
+            process::exit(i32::from(vmm::FcExitCode::BadConfiguration));
         });
+    vm_resources.set_instance_id(instance_id);
     let vmm = vmm::builder::build_microvm(&vm_resources, event_manager, &seccomp_filter)
         .unwrap_or_else(|err| {
             error!(
                 "Building VMM configured from cmdline json failed: {:?}",
                 err
             );
-            process::exit(i32::from(vmm::FC_EXIT_CODE_BAD_CONFIGURATION));
+            let exit_code = if err.is_out_of_guest_memory()
+                && vm_resources.memory_limits().oom_policy == OomPolicy::Exit
+            {
+                vmm::FcExitCode::OutOfMemory
+            } else {
+                vmm::FcExitCode::BadConfiguration
+            };
+            process::exit(i32::from(exit_code));
         });
     info!("Successfully started microvm that was configured from one single json");
 
     (vm_resources, vmm)
 }
 
-fn run_without_api(seccomp_filter: BpfProgram, config_json: Option<String>) {
+fn run_without_api(
+    seccomp_filter: BpfProgram,
+    config_json: Option<String>,
+    busy_poll_us: u64,
+    device_budget: usize,
+    preopened_fds: PreopenedFds,
+    instance_id: String,
+    checkpoint: Option<CheckpointConfig>,
+) {
     let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+    event_manager.set_busy_poll(busy_poll_us);
+    event_manager.set_device_budget(device_budget);
 
     // Create the firecracker metrics object responsible for periodically printing metrics.
     let firecracker_metrics = Arc::new(Mutex::new(metrics::PeriodicMetrics::new()));
@@ -293,14 +483,15 @@ fn run_without_api(seccomp_filter: BpfProgram, config_json: Option<String>) {
         .add_subscriber(firecracker_metrics.clone())
         .expect("Cannot register the metrics event to the event manager.");
 
-    // Build the microVm. We can ignore the returned values here because:
-    // - VmResources is not used without api,
-    // - An `Arc` reference of the built `Vmm` is plugged in the `EventManager` by the builder.
-    build_microvm_from_json(
+    // Build the microVm. An `Arc` reference of the built `Vmm` is plugged in the `EventManager`
+    // by the builder, so the returned values are only needed for the checkpoints.
+    let (vm_resources, vmm) = build_microvm_from_json(
         seccomp_filter,
         &mut event_manager,
         // Safe to unwrap since '--no-api' requires this to be set.
         config_json.unwrap(),
+        preopened_fds,
+        instance_id.clone(),
     );
 
     // Start the metrics.
@@ -309,6 +500,16 @@ fn run_without_api(seccomp_filter: BpfProgram, config_json: Option<String>) {
         .expect("Metrics lock poisoned.")
         .start(metrics::WRITE_METRICS_PERIOD_MS);
 
+    if let Some(checkpoint) = checkpoint {
+        start_checkpoints(
+            &mut event_manager,
+            checkpoint,
+            vmm,
+            instance_id,
+            Some(Arc::new(Mutex::new(vm_resources))),
+        );
+    }
+
     // Run the EventManager that drives everything in the microVM.
     loop {
         event_manager.run().unwrap();
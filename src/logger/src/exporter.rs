@@ -0,0 +1,293 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines pluggable exporters which push the flushed metrics directly to a telemetry stack.
+//!
+//! Exporters are fed with the same serialized snapshot that gets written to the metrics file, so
+//! every exported value is the delta accumulated since the previous flush. Each leaf counter of
+//! the `FirecrackerMetrics` tree is exported under its dot separated path (e.g.
+//! `block.read_count`).
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use serde_json::Value;
+
+// Top level field of the metrics JSON which holds the flush timestamp rather than a counter.
+const TIMESTAMP_FIELD: &str = "utc_timestamp_ms";
+// Keep statsd datagrams below the typical Ethernet MTU to avoid IP fragmentation.
+const STATSD_MAX_DATAGRAM_SIZE: usize = 1432;
+// Bounds the time spent by the exporting thread on an unresponsive OTLP collector.
+const OTLP_TIMEOUT: Duration = Duration::from_millis(500);
+// Path of the OTLP/HTTP metrics endpoint, as defined by the OpenTelemetry protocol.
+const OTLP_METRICS_PATH: &str = "/v1/metrics";
+// Cumulative vs delta temporality as encoded by OTLP; our counters are reset on each flush.
+const OTLP_AGGREGATION_TEMPORALITY_DELTA: u8 = 1;
+
+/// Interface implemented by the backends which export the flushed metrics.
+pub trait MetricsExporter: Send {
+    /// Exports a flushed metrics snapshot, as serialized by the metrics system.
+    fn export(&mut self, metrics: &Value) -> io::Result<()>;
+}
+
+/// Flattens the serialized metrics into `(name, value)` pairs, one for each counter.
+pub fn flatten_metrics(metrics: &Value) -> Vec<(String, u64)> {
+    let mut flattened = Vec::new();
+    if let Value::Object(ref map) = metrics {
+        for (name, value) in map.iter().filter(|(name, _)| *name != TIMESTAMP_FIELD) {
+            flatten_into(name.clone(), value, &mut flattened);
+        }
+    }
+    flattened
+}
+
+fn flatten_into(prefix: String, value: &Value, flattened: &mut Vec<(String, u64)>) {
+    match value {
+        Value::Object(ref map) => {
+            for (name, child) in map.iter() {
+                flatten_into(format!("{}.{}", prefix, name), child, flattened);
+            }
+        }
+        Value::Number(ref n) => {
+            if let Some(n) = n.as_u64() {
+                flattened.push((prefix, n));
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Exports the metrics as statsd counters over UDP.
+pub struct StatsdExporter {
+    socket: UdpSocket,
+    prefix: String,
+}
+
+impl StatsdExporter {
+    /// Creates a statsd exporter which sends counters to `address`, each name being prepended
+    /// with `prefix`.
+    pub fn new(address: &str, prefix: &str) -> io::Result<StatsdExporter> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(address)?;
+        socket.set_nonblocking(true)?;
+        Ok(StatsdExporter {
+            socket,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    fn format_line(&self, name: &str, value: u64) -> String {
+        if self.prefix.is_empty() {
+            format!("{}:{}|c", name, value)
+        } else {
+            format!("{}.{}:{}|c", self.prefix, name, value)
+        }
+    }
+}
+
+impl MetricsExporter for StatsdExporter {
+    fn export(&mut self, metrics: &Value) -> io::Result<()> {
+        let mut datagram = String::new();
+        // A statsd counter that was not reported is implicitly 0, so we skip those.
+        for (name, value) in flatten_metrics(metrics).into_iter().filter(|(_, v)| *v > 0) {
+            let line = self.format_line(&name, value);
+            if !datagram.is_empty() && datagram.len() + line.len() + 1 > STATSD_MAX_DATAGRAM_SIZE {
+                self.socket.send(datagram.as_bytes())?;
+                datagram.clear();
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(&line);
+        }
+        if !datagram.is_empty() {
+            self.socket.send(datagram.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Exports the metrics as OTLP delta sums, JSON encoded over HTTP.
+pub struct OtlpExporter {
+    address: SocketAddr,
+    host: String,
+    service_name: String,
+}
+
+impl OtlpExporter {
+    /// Creates an OTLP exporter which posts to the collector listening on `endpoint`
+    /// (`host:port`), tagging the metrics with the `service.name` resource attribute.
+    pub fn new(endpoint: &str, service_name: &str) -> io::Result<OtlpExporter> {
+        let address = endpoint.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot resolve OTLP endpoint {}", endpoint),
+            )
+        })?;
+        Ok(OtlpExporter {
+            address,
+            host: endpoint.to_string(),
+            service_name: service_name.to_string(),
+        })
+    }
+
+    fn build_payload(&self, metrics: &Value) -> Value {
+        let time_unix_nano = utils::time::get_time(utils::time::ClockType::Real).to_string();
+        let otlp_metrics: Vec<Value> = flatten_metrics(metrics)
+            .into_iter()
+            .map(|(name, value)| {
+                json!({
+                    "name": name,
+                    "sum": {
+                        "aggregationTemporality": OTLP_AGGREGATION_TEMPORALITY_DELTA,
+                        "isMonotonic": true,
+                        "dataPoints": [{
+                            // OTLP/JSON encodes 64 bit integers as strings.
+                            "asInt": value.to_string(),
+                            "timeUnixNano": time_unix_nano,
+                        }],
+                    },
+                })
+            })
+            .collect();
+
+        json!({
+            "resourceMetrics": [{
+                "resource": {
+                    "attributes": [{
+                        "key": "service.name",
+                        "value": { "stringValue": self.service_name },
+                    }],
+                },
+                "scopeMetrics": [{
+                    "scope": { "name": "firecracker" },
+                    "metrics": otlp_metrics,
+                }],
+            }],
+        })
+    }
+}
+
+impl MetricsExporter for OtlpExporter {
+    fn export(&mut self, metrics: &Value) -> io::Result<()> {
+        let body = self.build_payload(metrics).to_string();
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            OTLP_METRICS_PATH,
+            self.host,
+            body.len(),
+            body
+        );
+
+        let mut stream = TcpStream::connect_timeout(&self.address, OTLP_TIMEOUT)?;
+        stream.set_read_timeout(Some(OTLP_TIMEOUT))?;
+        stream.set_write_timeout(Some(OTLP_TIMEOUT))?;
+        stream.write_all(request.as_bytes())?;
+
+        // We are only interested in the status code, e.g. `HTTP/1.1 200 OK`.
+        let mut status_line = [0u8; 12];
+        stream.read_exact(&mut status_line)?;
+        match &status_line[9..10] {
+            b"2" => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "OTLP collector replied with: {}",
+                    String::from_utf8_lossy(&status_line)
+                ),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn test_metrics() -> Value {
+        json!({
+            "utc_timestamp_ms": 1234,
+            "block": {
+                "read_count": 3,
+                "write_count": 0,
+            },
+            "vcpu": {
+                "exit_io_in": 7,
+            },
+        })
+    }
+
+    #[test]
+    fn test_flatten_metrics() {
+        let flattened = flatten_metrics(&test_metrics());
+        assert_eq!(
+            flattened,
+            vec![
+                ("block.read_count".to_string(), 3),
+                ("block.write_count".to_string(), 0),
+                ("vcpu.exit_io_in".to_string(), 7),
+            ]
+        );
+        assert!(flatten_metrics(&json!(5)).is_empty());
+    }
+
+    #[test]
+    fn test_statsd_exporter() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = receiver.local_addr().unwrap().to_string();
+
+        let mut exporter = StatsdExporter::new(&address, "fc").unwrap();
+        exporter.export(&test_metrics()).unwrap();
+
+        let mut buf = [0u8; STATSD_MAX_DATAGRAM_SIZE];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buf[..len]).unwrap(),
+            "fc.block.read_count:3|c\nfc.vcpu.exit_io_in:7|c"
+        );
+
+        let exporter = StatsdExporter::new(&address, "").unwrap();
+        assert_eq!(exporter.format_line("a.b", 1), "a.b:1|c");
+    }
+
+    #[test]
+    fn test_otlp_exporter() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let collector = thread::spawn(move || {
+            let mut received = Vec::new();
+            for reply in &[
+                "HTTP/1.1 200 OK\r\n\r\n",
+                "HTTP/1.1 400 Bad Request\r\n\r\n",
+            ] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut buf = vec![0u8; 64 * 1024];
+                let len = stream.read(&mut buf).unwrap();
+                received.push(String::from_utf8_lossy(&buf[..len]).to_string());
+                stream.write_all(reply.as_bytes()).unwrap();
+            }
+            received
+        });
+
+        let mut exporter = OtlpExporter::new(&address, "firecracker").unwrap();
+        assert!(exporter.export(&test_metrics()).is_ok());
+        assert!(exporter.export(&test_metrics()).is_err());
+
+        let received = collector.join().unwrap();
+        assert!(received[0].starts_with("POST /v1/metrics HTTP/1.1\r\n"));
+        let body: Value =
+            serde_json::from_str(received[0].split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        let metrics = &body["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+        assert_eq!(metrics.as_array().unwrap().len(), 3);
+        assert_eq!(metrics[0]["name"], "block.read_count");
+        assert_eq!(metrics[0]["sum"]["dataPoints"][0]["asInt"], "3");
+
+        assert!(OtlpExporter::new("not a valid endpoint", "firecracker").is_err());
+    }
+}
@@ -5,21 +5,27 @@
 #[macro_use]
 extern crate lazy_static;
 extern crate libc;
-#[cfg_attr(test, macro_use)]
+#[macro_use]
 extern crate log;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
 extern crate serde_json;
 extern crate utils;
 
+mod exporter;
 mod logger;
 mod metrics;
 
+pub use exporter::{flatten_metrics, MetricsExporter, OtlpExporter, StatsdExporter};
 pub use log::Level::*;
 pub use log::*;
 pub use logger::{LoggerError, LOGGER};
-pub use metrics::{Metric, MetricsError, METRICS};
+pub use metrics::{
+    BlockLatencyMetrics, IrqThrottleMetrics, LatencyHistogram, Metric, MetricsError,
+    PerDeviceMetrics, VirtioQueueMetrics, METRICS,
+};
 
 use std::io::Write;
 use std::sync::{Mutex, MutexGuard};
@@ -56,15 +56,21 @@
 //! something else, while working behind the same interface.
 
 use std;
+use std::collections::BTreeMap;
 use std::fmt;
-use std::io::Write;
+use std::io::{self, Write};
 use std::ops::Deref;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::Mutex;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Instant;
 
+use serde::ser::{SerializeMap, SerializeStruct};
 use serde::{Serialize, Serializer};
 
 use super::buf_guard;
+use exporter::MetricsExporter;
 
 lazy_static! {
     /// Static instance used for handling metrics.
@@ -78,17 +84,37 @@ pub struct Metrics<T: Serialize> {
     // Metrics will get flushed here.
     metrics_buf: Mutex<Option<Box<dyn Write + Send>>>,
     is_initialized: AtomicBool,
+    // Additional destinations to which each flushed snapshot is pushed, until they are handed
+    // to the exporting thread.
+    exporters: Mutex<Vec<Box<dyn MetricsExporter>>>,
+    has_exporters: AtomicBool,
+    // The snapshots flushed for the exporters, queued until the exporting thread takes them.
+    export_queue: Mutex<Sender<serde_json::Value>>,
+    export_receiver: Mutex<Option<Receiver<serde_json::Value>>>,
     pub app_metrics: T,
 }
 
+// The metrics are still usable if a thread panics while holding one of their locks.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
 impl<T: Serialize> Metrics<T> {
     /// Creates a new instance of the current metrics.
     // TODO: We need a better name than app_metrics (something that says that these are the actual
     // values that we are writing to the metrics_buf).
     pub fn new(app_metrics: T) -> Metrics<T> {
+        let (export_queue, export_receiver) = channel();
         Metrics {
             metrics_buf: Mutex::new(None),
             is_initialized: AtomicBool::new(false),
+            exporters: Mutex::new(Vec::new()),
+            has_exporters: AtomicBool::new(false),
+            export_queue: Mutex::new(export_queue),
+            export_receiver: Mutex::new(Some(export_receiver)),
             app_metrics,
         }
     }
@@ -112,6 +138,77 @@ impl<T: Serialize> Metrics<T> {
         Ok(())
     }
 
+    /// Registers an exporter which will receive every snapshot flushed by `write`, in addition
+    /// to the destination provided upon initialization, once the exporting thread is started.
+    pub fn add_exporter(&self, exporter: Box<dyn MetricsExporter>) {
+        lock(&self.exporters).push(exporter);
+        self.has_exporters.store(true, Ordering::Relaxed);
+    }
+
+    /// Starts the thread pushing the flushed snapshots to the registered exporters, so that the
+    /// exports, which reach out over the network, never hold the flushing thread. The snapshots
+    /// flushed before are pushed first. Does nothing if no exporter is registered, or if the
+    /// thread is already started.
+    ///
+    /// The thread runs `confine`, e.g. to load its seccomp filter, before pushing anything, and
+    /// exits if it fails.
+    pub fn start_exporting<F>(&self, confine: F) -> Result<(), MetricsError>
+    where
+        F: FnOnce() -> std::result::Result<(), String> + Send + 'static,
+    {
+        let mut export_receiver = lock(&self.export_receiver);
+        let mut exporters = lock(&self.exporters);
+        if export_receiver.is_none() || exporters.is_empty() {
+            return Ok(());
+        }
+        let receiver = export_receiver.take().unwrap();
+        let mut exporters: Vec<_> = exporters.drain(..).collect();
+
+        let (setup_sender, setup_receiver) = channel();
+        thread::Builder::new()
+            .name("fc_metrics".to_owned())
+            .spawn(move || {
+                let setup = confine();
+                let failed = setup.is_err();
+                // The starting thread waits for this message, so sending it can't fail.
+                let _ = setup_sender.send(setup);
+                if failed {
+                    return;
+                }
+                // A failing exporter doesn't prevent the others from receiving the snapshot.
+                for snapshot in receiver {
+                    for exporter in exporters.iter_mut() {
+                        if let Err(e) = exporter.export(&snapshot) {
+                            error!("Failed to export the metrics: {}", e);
+                        }
+                    }
+                }
+            })
+            .map_err(MetricsError::Export)?;
+
+        setup_receiver
+            .recv()
+            .unwrap_or_else(|_| Err("The exporting thread exited.".to_string()))
+            .map_err(|e| MetricsError::Export(io::Error::new(io::ErrorKind::Other, e)))
+    }
+
+    // Queues the serialized snapshot for the exporting thread, if any exporter is registered.
+    fn export(&self, msg: &str) -> Result<(), MetricsError> {
+        if !self.has_exporters.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let snapshot: serde_json::Value =
+            serde_json::from_str(msg).map_err(|e| MetricsError::Serde(e.to_string()))?;
+        // The exporting thread only exits when it can't be confined.
+        lock(&self.export_queue).send(snapshot).map_err(|_| {
+            MetricsError::Export(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "The exporting thread exited.",
+            ))
+        })
+    }
+
     /// Writes metrics to the destination provided as argument upon initialization of the metrics.
     /// Upon failure, an error is returned if metrics system is initialized and metrics could not be
     /// written.
@@ -121,6 +218,7 @@ impl<T: Serialize> Metrics<T> {
         if self.is_initialized.load(Ordering::Relaxed) {
             match serde_json::to_string(&self.app_metrics) {
                 Ok(msg) => {
+                    let export_result = self.export(&msg);
                     if let Some(guard) = buf_guard(&self.metrics_buf).as_mut() {
                         // No need to explicitly call flush because the underlying LineWriter flushes
                         // automatically whenever a newline is detected (and we always end with a
@@ -128,6 +226,7 @@ impl<T: Serialize> Metrics<T> {
                         return guard
                             .write_all(&(format!("{}\n", msg)).as_bytes())
                             .map_err(MetricsError::Write)
+                            .and(export_result)
                             .map(|_| true);
                     } else {
                         // We have not incremented `missed_metrics_count` as there is no way to push metrics
@@ -165,6 +264,8 @@ pub enum MetricsError {
     Serde(String),
     /// Writing the specified buffer failed.
     Write(std::io::Error),
+    /// Pushing the metrics through an exporter failed.
+    Export(std::io::Error),
 }
 
 impl fmt::Display for MetricsError {
@@ -176,6 +277,7 @@ impl fmt::Display for MetricsError {
             }
             MetricsError::Serde(ref e) => e.to_string(),
             MetricsError::Write(ref e) => format!("Failed to write metrics. Error: {}", e),
+            MetricsError::Export(ref e) => format!("Failed to export metrics. Error: {}", e),
         };
         write!(f, "{}", printable)
     }
@@ -239,6 +341,110 @@ impl Serialize for SharedMetric {
     }
 }
 
+/// Upper bounds of the buckets of the latency histograms, in microseconds. The latencies above
+/// the last bound fall in an extra bucket.
+pub const LATENCY_BUCKET_BOUNDS_US: [u64; 9] = [
+    100, 500, 1_000, 5_000, 10_000, 50_000, 100_000, 500_000, 1_000_000,
+];
+const LATENCY_BUCKETS: usize = 10;
+
+/// Histogram of latencies, in microseconds, which is expected to be recorded from more than one
+/// thread.
+// Like the counters, the number of latencies and their sum are serialized along with the
+// number of latencies in each bucket as the delta since the previous flush. The buckets are not
+// cumulative.
+#[derive(Default)]
+pub struct LatencyHistogram {
+    count: SharedMetric,
+    sum_us: SharedMetric,
+    buckets: [SharedMetric; LATENCY_BUCKETS],
+}
+
+impl LatencyHistogram {
+    /// Records a latency of `latency_us` microseconds.
+    pub fn record(&self, latency_us: u64) {
+        let bucket = LATENCY_BUCKET_BOUNDS_US
+            .iter()
+            .position(|&bound| latency_us <= bound)
+            .unwrap_or(LATENCY_BUCKETS - 1);
+        self.buckets[bucket].inc();
+        self.count.inc();
+        self.sum_us.add(latency_us as usize);
+    }
+
+    /// Records the time elapsed since `start`.
+    pub fn record_since(&self, start: Instant) {
+        self.record(start.elapsed().as_micros() as u64);
+    }
+
+    /// Returns the number of latencies recorded.
+    pub fn count(&self) -> usize {
+        self.count.count()
+    }
+}
+
+impl Serialize for LatencyHistogram {
+    /// Resets the histogram, like the counters.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut histogram = serializer.serialize_struct("LatencyHistogram", 3)?;
+        histogram.serialize_field("count", &self.count)?;
+        histogram.serialize_field("sum_us", &self.sum_us)?;
+        histogram.serialize_field("buckets_us", &LatencyBuckets(&self.buckets))?;
+        histogram.end()
+    }
+}
+
+// Serializes the buckets of a latency histogram by their upper bound, the last one as `inf`.
+struct LatencyBuckets<'a>(&'a [SharedMetric; LATENCY_BUCKETS]);
+
+impl<'a> Serialize for LatencyBuckets<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut buckets = serializer.serialize_map(Some(LATENCY_BUCKETS))?;
+        for (bound, bucket) in LATENCY_BUCKET_BOUNDS_US.iter().zip(self.0.iter()) {
+            buckets.serialize_entry(&bound.to_string(), bucket)?;
+        }
+        buckets.serialize_entry("inf", &self.0[LATENCY_BUCKETS - 1])?;
+        buckets.end()
+    }
+}
+
+/// Metrics kept for each device of a kind, by the ID of the device.
+// Only looking up the metrics of a device and flushing them take the lock: the devices keep
+// their own metrics and update them without locking.
+#[derive(Default)]
+pub struct PerDeviceMetrics<T> {
+    devices: Mutex<BTreeMap<String, Arc<T>>>,
+}
+
+impl<T: Default> PerDeviceMetrics<T> {
+    /// Returns the metrics of the device `id`, which are created the first time they are
+    /// requested and shared by the devices later created with the same ID.
+    pub fn get(&self, id: &str) -> Arc<T> {
+        let mut devices = match self.devices.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        devices
+            .entry(id.to_string())
+            .or_insert_with(|| Arc::new(T::default()))
+            .clone()
+    }
+}
+
+impl<T: Serialize> Serialize for PerDeviceMetrics<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let devices = match self.devices.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let mut map = serializer.serialize_map(Some(devices.len()))?;
+        for (id, metrics) in devices.iter() {
+            map.serialize_entry(id, &**metrics)?;
+        }
+        map.end()
+    }
+}
+
 // The following structs are used to define a certain organization for the set of metrics we
 // are interested in. Whenever the name of a field differs from its ideal textual representation
 // in the serialized form, we can use the #[serde(rename = "name")] attribute to, well, rename it.
@@ -254,6 +460,8 @@ pub struct ApiServerMetrics {
     pub sync_response_fails: SharedMetric,
     /// Number of timeouts during communication with the VMM.
     pub sync_vmm_send_timeout_count: SharedMetric,
+    /// Number of actions rejected since too many actions were waiting for the VMM.
+    pub action_queue_full_count: SharedMetric,
 }
 
 /// Metrics specific to GET API Requests for counting user triggered actions and/or failures.
@@ -267,6 +475,28 @@ pub struct GetRequestsMetrics {
     pub machine_cfg_count: SharedMetric,
     /// Number of failures during GETs for getting information on the instance.
     pub machine_cfg_fails: SharedMetric,
+    /// Number of GETs for draining the pending VMM events.
+    pub events_count: SharedMetric,
+    /// Number of GETs for listing the attached devices.
+    pub devices_count: SharedMetric,
+    /// Number of GETs for the status of an operation running in the background.
+    pub operations_count: SharedMetric,
+    /// Number of GETs for dumping the state of a device.
+    pub device_state_count: SharedMetric,
+    /// Number of GETs for the kernel log of the guest.
+    pub guest_dmesg_count: SharedMetric,
+    /// Number of GETs for probing what the host offers to the microVMs.
+    pub host_capabilities_count: SharedMetric,
+    /// Number of GETs for the launch measurement of a SEV guest.
+    pub launch_measurement_count: SharedMetric,
+    /// Number of GETs for listing the vsock connections.
+    pub vsock_connections_count: SharedMetric,
+    /// Number of GETs for listing the rate limiters.
+    pub rate_limiters_count: SharedMetric,
+    /// Number of GETs for the host resources used by the VMM process.
+    pub resource_usage_count: SharedMetric,
+    /// Number of GETs for the whole configuration of the microVM.
+    pub vm_config_count: SharedMetric,
 }
 
 /// Metrics specific to PUT API Requests for counting user triggered actions and/or failures.
@@ -276,14 +506,42 @@ pub struct PutRequestsMetrics {
     pub actions_count: SharedMetric,
     /// Number of failures in triggering an action on the VM.
     pub actions_fails: SharedMetric,
+    /// Number of PUTs for configuring the balloon device.
+    pub balloon_count: SharedMetric,
+    /// Number of failures in configuring the balloon device.
+    pub balloon_fails: SharedMetric,
     /// Number of PUTs for attaching source of boot.
     pub boot_source_count: SharedMetric,
     /// Number of failures during attaching source of boot.
     pub boot_source_fails: SharedMetric,
+    /// Number of PUTs for setting the cloud-init data.
+    pub cloud_init_count: SharedMetric,
+    /// Number of failures in setting the cloud-init data.
+    pub cloud_init_fails: SharedMetric,
+    /// Number of PUTs for configuring the serial console.
+    pub console_count: SharedMetric,
+    /// Number of failures in configuring the serial console.
+    pub console_fails: SharedMetric,
     /// Number of PUTs triggering a block attach.
     pub drive_count: SharedMetric,
     /// Number of failures in attaching a block device.
     pub drive_fails: SharedMetric,
+    /// Number of PUTs for configuring the entropy device.
+    pub entropy_count: SharedMetric,
+    /// Number of failures in configuring the entropy device.
+    pub entropy_fails: SharedMetric,
+    /// Number of PUTs for configuring the GPU device.
+    pub gpu_count: SharedMetric,
+    /// Number of failures in configuring the GPU device.
+    pub gpu_fails: SharedMetric,
+    /// Number of PUTs for the kernel log of the guest, located with the given kernel symbols.
+    pub guest_dmesg_count: SharedMetric,
+    /// Number of malformed kernel symbols locating the kernel log of the guest.
+    pub guest_dmesg_fails: SharedMetric,
+    /// Number of PUTs for configuring the input device.
+    pub input_count: SharedMetric,
+    /// Number of failures in configuring the input device.
+    pub input_fails: SharedMetric,
     /// Number of PUTs for initializing the logging system.
     pub logger_count: SharedMetric,
     /// Number of failures in initializing the logging system.
@@ -292,6 +550,10 @@ pub struct PutRequestsMetrics {
     pub machine_cfg_count: SharedMetric,
     /// Number of failures in configuring the machine.
     pub machine_cfg_fails: SharedMetric,
+    /// Number of PUTs for setting the memory limits of the process.
+    pub memory_limits_count: SharedMetric,
+    /// Number of failures in setting the memory limits of the process.
+    pub memory_limits_fails: SharedMetric,
     /// Number of PUTs for initializing the metrics system.
     pub metrics_count: SharedMetric,
     /// Number of failures in initializing the metrics system.
@@ -300,11 +562,55 @@ pub struct PutRequestsMetrics {
     pub network_count: SharedMetric,
     /// Number of failures in creating a new network interface.
     pub network_fails: SharedMetric,
+    /// Number of PUTs for passing a host PCI device through.
+    pub pci_passthrough_count: SharedMetric,
+    /// Number of failures in passing a host PCI device through.
+    pub pci_passthrough_fails: SharedMetric,
+    /// Number of PUTs for configuring the probes of the guest workload.
+    pub probe_count: SharedMetric,
+    /// Number of failures in configuring the probes of the guest workload.
+    pub probe_fails: SharedMetric,
+    /// Number of PUTs for configuring the adaptive rate limiting policy.
+    pub rate_limit_policy_count: SharedMetric,
+    /// Number of failures in configuring the adaptive rate limiting policy.
+    pub rate_limit_policy_fails: SharedMetric,
+    /// Number of PUTs for configuring the RTC.
+    pub rtc_count: SharedMetric,
+    /// Number of failures in configuring the RTC.
+    pub rtc_fails: SharedMetric,
+    /// Number of PUTs for configuring the launch of a SEV guest.
+    pub sev_count: SharedMetric,
+    /// Number of failures in configuring the launch of a SEV guest.
+    pub sev_fails: SharedMetric,
+    /// Number of PUTs for sharing memory between the host and the guest.
+    pub shared_memory_count: SharedMetric,
+    /// Number of failures in sharing memory between the host and the guest.
+    pub shared_memory_fails: SharedMetric,
+    /// Number of PUTs for configuring the sound device.
+    pub sound_count: SharedMetric,
+    /// Number of failures in configuring the sound device.
+    pub sound_fails: SharedMetric,
+    /// Number of PUTs for validating a microVM configuration.
+    pub validate_count: SharedMetric,
+    /// Number of malformed microVM configurations to validate.
+    pub validate_fails: SharedMetric,
+    /// Number of PUTs for parking or unparking a vCPU.
+    pub vcpu_state_count: SharedMetric,
+    /// Number of failures in parking or unparking a vCPU.
+    pub vcpu_state_fails: SharedMetric,
+    /// Number of PUTs for configuring the virtio feature policy.
+    pub virtio_features_count: SharedMetric,
+    /// Number of failures in configuring the virtio feature policy.
+    pub virtio_features_fails: SharedMetric,
 }
 
 /// Metrics specific to PATCH API Requests for counting user triggered actions and/or failures.
 #[derive(Default, Serialize)]
 pub struct PatchRequestsMetrics {
+    /// Number of tries to PATCH the balloon device.
+    pub balloon_count: SharedMetric,
+    /// Number of failures in PATCHing the balloon device.
+    pub balloon_fails: SharedMetric,
     /// Number of tries to PATCH a block device.
     pub drive_count: SharedMetric,
     /// Number of failures in PATCHing a block device.
@@ -319,6 +625,52 @@ pub struct PatchRequestsMetrics {
     pub machine_cfg_fails: SharedMetric,
 }
 
+/// Metrics specific to DELETE API Requests for counting user triggered actions and/or failures.
+#[derive(Default, Serialize)]
+pub struct DeleteRequestsMetrics {
+    /// Number of tries to DELETE a block device.
+    pub drive_count: SharedMetric,
+    /// Number of failures in DELETing a block device.
+    pub drive_fails: SharedMetric,
+    /// Number of tries to DELETE a net device.
+    pub network_count: SharedMetric,
+    /// Number of failures in DELETing a net device.
+    pub network_fails: SharedMetric,
+}
+
+/// Balloon Device associated metrics.
+#[derive(Default, Serialize)]
+pub struct BalloonDeviceMetrics {
+    /// Number of times when activate failed on the balloon device.
+    pub activate_fails: SharedMetric,
+    /// Number of times when interacting with the space config of the balloon device failed.
+    pub cfg_fails: SharedMetric,
+    /// Number of times when handling events on the balloon device failed.
+    pub event_fails: SharedMetric,
+    /// Number of pages inflated by the guest driver.
+    pub inflated_pages: SharedMetric,
+    /// Number of pages deflated by the guest driver.
+    pub deflated_pages: SharedMetric,
+    /// Number of free pages reported by the guest driver.
+    pub reported_pages: SharedMetric,
+    /// Number of failures in discarding the memory backing inflated or reported pages.
+    pub discard_fails: SharedMetric,
+}
+
+/// Latencies of the requests served by a block device, from their dispatch to their
+/// completion, in microseconds.
+#[derive(Default, Serialize)]
+pub struct BlockLatencyMetrics {
+    /// Latencies of the reads.
+    pub read: LatencyHistogram,
+    /// Latencies of the writes.
+    pub write: LatencyHistogram,
+    /// Latencies of the flushes.
+    pub flush: LatencyHistogram,
+    /// Latencies of the requests for the device ID.
+    pub get_device_id: LatencyHistogram,
+}
+
 /// Block Device associated metrics.
 #[derive(Default, Serialize)]
 pub struct BlockDeviceMetrics {
@@ -352,6 +704,72 @@ pub struct BlockDeviceMetrics {
     pub read_count: SharedMetric,
     /// Number of sucessful write operations.
     pub write_count: SharedMetric,
+    /// Number of reads refused because the data doesn't match the hash tree of the device.
+    pub verity_fails: SharedMetric,
+    /// Number of times the device yielded to the other devices on the same host device.
+    pub io_yield_count: SharedMetric,
+    /// Number of times the device yielded the event loop to the other devices, with requests
+    /// left in its queue, after running out of its dispatch budget.
+    pub budget_yield_count: SharedMetric,
+    /// Number of times a block device was paused, holding back the requests of the guest.
+    pub pause_count: SharedMetric,
+    /// Number of requests which failed because the host ran out of space for the backing file.
+    pub out_of_space_count: SharedMetric,
+    /// Number of page cache hints about the backing file which failed.
+    pub page_cache_advice_fails: SharedMetric,
+}
+
+/// Entropy Device associated metrics.
+#[derive(Default, Serialize)]
+pub struct EntropyDeviceMetrics {
+    /// Number of times when activate failed on the entropy device.
+    pub activate_fails: SharedMetric,
+    /// Number of times when interacting with the space config of the entropy device failed.
+    pub cfg_fails: SharedMetric,
+    /// Number of times when handling events on the entropy device failed.
+    pub event_fails: SharedMetric,
+    /// Number of random bytes handed to the guest.
+    pub entropy_bytes: SharedMetric,
+    /// Number of failures in getting random bytes from the host.
+    pub host_rng_fails: SharedMetric,
+}
+
+/// GPU Device associated metrics.
+#[derive(Default, Serialize)]
+pub struct GpuDeviceMetrics {
+    /// Number of times when activate failed on the GPU device.
+    pub activate_fails: SharedMetric,
+    /// Number of times when interacting with the space config of the GPU device failed.
+    pub cfg_fails: SharedMetric,
+    /// Number of times when handling events on the GPU device failed.
+    pub event_fails: SharedMetric,
+    /// Number of commands processed by the GPU device.
+    pub cmd_count: SharedMetric,
+    /// Number of commands rejected by the GPU device.
+    pub cmd_fails: SharedMetric,
+    /// Number of frames sent to the scanout clients.
+    pub frames_sent: SharedMetric,
+    /// Number of scanout clients that connected.
+    pub scanout_clients: SharedMetric,
+    /// Number of scanout clients dropped for not keeping up with the frames or failing.
+    pub scanout_client_fails: SharedMetric,
+}
+
+/// Input Device associated metrics.
+#[derive(Default, Serialize)]
+pub struct InputDeviceMetrics {
+    /// Number of times when activate failed on the input device.
+    pub activate_fails: SharedMetric,
+    /// Number of times when interacting with the space config of the input device failed.
+    pub cfg_fails: SharedMetric,
+    /// Number of times when handling events on the input device failed.
+    pub event_fails: SharedMetric,
+    /// Number of input events handed to the guest.
+    pub events_count: SharedMetric,
+    /// Number of input events rejected because the guest didn't take the pending ones.
+    pub events_dropped: SharedMetric,
+    /// Number of status events (e.g. LED updates) sent by the guest.
+    pub status_count: SharedMetric,
 }
 
 /// Metrics specific to the i8042 device.
@@ -371,6 +789,15 @@ pub struct I8042DeviceMetrics {
     pub write_count: SharedMetric,
 }
 
+/// Metrics of the interrupt throttling of a device.
+#[derive(Default, Serialize)]
+pub struct IrqThrottleMetrics {
+    /// Number of interrupts deferred because the device exceeded its interrupt rate.
+    pub deferred_irqs: SharedMetric,
+    /// Number of interrupts coalesced with an interrupt already deferred.
+    pub coalesced_irqs: SharedMetric,
+}
+
 /// Metrics for the logging subsystem.
 #[derive(Default, Serialize)]
 pub struct LoggerSystemMetrics {
@@ -384,6 +811,16 @@ pub struct LoggerSystemMetrics {
     pub log_fails: SharedMetric,
 }
 
+/// Metrics related to the guest memory shared with the other microVMs restored from the same
+/// snapshot.
+#[derive(Default, Serialize)]
+pub struct MemorySharingMetrics {
+    /// Number of guest pages copied on write, which are no longer shared.
+    pub cow_pages: SharedMetric,
+    /// Number of failures in counting the guest pages copied on write.
+    pub sample_fails: SharedMetric,
+}
+
 /// Metrics for the MMDS functionality.
 #[derive(Default, Serialize)]
 pub struct MmdsMetrics {
@@ -452,6 +889,60 @@ pub struct NetDeviceMetrics {
     pub tx_rate_limiter_event_count: SharedMetric,
     /// Number of packets with a spoofed mac, sent by the guest.
     pub tx_spoofed_mac_count: SharedMetric,
+    /// Number of frames dropped because the link of the device was down.
+    pub link_down_drops: SharedMetric,
+    /// Number of received frames dropped by the filter the guest set up.
+    pub rx_filtered_drops: SharedMetric,
+    /// Number of frames lost on purpose by the impairment of the network path, or because too
+    /// many frames were held back.
+    pub impairment_drops: SharedMetric,
+    /// Number of frames duplicated on purpose by the impairment of the network path.
+    pub impairment_duplicates: SharedMetric,
+    /// Number of commands received on the control queue.
+    pub ctrl_count: SharedMetric,
+    /// Number of commands of the control queue which failed or were denied.
+    pub ctrl_fails: SharedMetric,
+    /// Number of times the device yielded the event loop to the other devices, with frames left
+    /// to receive, after running out of its dispatch budget.
+    pub rx_budget_yield_count: SharedMetric,
+    /// Number of times the device yielded the event loop to the other devices, with frames left
+    /// to transmit, after running out of its dispatch budget.
+    pub tx_budget_yield_count: SharedMetric,
+}
+
+/// Metrics related to the guest page faults served on demand after a snapshot restore, which
+/// measure the working set of the restored microVM.
+#[derive(Default, Serialize)]
+pub struct PageFaultMetrics {
+    /// Number of guest pages faulted in, when profiling the page faults.
+    pub faults: SharedMetric,
+    /// Number of guest pages faulted in right after the page preceding them.
+    pub sequential_faults: SharedMetric,
+    /// Number of guest pages discarded by the balloon device and faulted in as zeroes.
+    pub zero_pages: SharedMetric,
+    /// Number of guest pages prefetched ahead of the guest accessing them.
+    pub prefetched_pages: SharedMetric,
+    /// Number of page faults timed, sampled out of the ones profiled.
+    pub sampled_faults: SharedMetric,
+    /// Time spent serving the timed page faults, in microseconds.
+    pub sampled_serve_us: SharedMetric,
+    /// Number of failures in serving a page fault.
+    pub serve_fails: SharedMetric,
+}
+
+/// Metrics related to the rate limiters of all the devices.
+#[derive(Default, Serialize)]
+pub struct RateLimiterMetrics {
+    /// Number of times a rate limiter ran out of budget and blocked its device.
+    pub blocked_count: SharedMetric,
+    /// Time spent blocked by the rate limiters, in microseconds.
+    pub blocked_time_us: SharedMetric,
+    /// Number of times the rate limiters were lowered because of the pressure on the host.
+    pub throttle_count: SharedMetric,
+    /// Number of times the rate limiters were raised back once the pressure on the host eased.
+    pub release_count: SharedMetric,
+    /// Number of failures in reading the pressure signal of the host.
+    pub pressure_sample_fails: SharedMetric,
 }
 
 /// Metrics specific to the i8042 device.
@@ -489,6 +980,44 @@ pub struct SerialDeviceMetrics {
     pub write_count: SharedMetric,
 }
 
+/// Metrics specific to the shared memory devices.
+#[derive(Default, Serialize)]
+pub struct SharedMemoryDeviceMetrics {
+    /// Number of doorbells rung by the guest.
+    pub doorbell_count: SharedMetric,
+    /// Number of interrupts requested by the host peers.
+    pub interrupt_count: SharedMetric,
+    /// Number of failures in exchanging messages with the host peers.
+    pub peer_fails: SharedMetric,
+    /// Number of guest accesses to the registers which were ignored.
+    pub missed_access_count: SharedMetric,
+}
+
+/// Sound Device associated metrics.
+#[derive(Default, Serialize)]
+pub struct SoundDeviceMetrics {
+    /// Number of times when activate failed on the sound device.
+    pub activate_fails: SharedMetric,
+    /// Number of times when interacting with the space config of the sound device failed.
+    pub cfg_fails: SharedMetric,
+    /// Number of times when handling events on the sound device failed.
+    pub event_fails: SharedMetric,
+    /// Number of control requests processed by the sound device.
+    pub ctrl_count: SharedMetric,
+    /// Number of control requests rejected by the sound device.
+    pub ctrl_fails: SharedMetric,
+    /// Number of played buffers.
+    pub tx_count: SharedMetric,
+    /// Number of played bytes.
+    pub tx_bytes_count: SharedMetric,
+    /// Number of buffers that could not be played.
+    pub tx_fails: SharedMetric,
+    /// Number of played bytes that were dropped because the backend did not keep up.
+    pub backend_dropped_bytes: SharedMetric,
+    /// Number of times when writing to the backend failed.
+    pub backend_fails: SharedMetric,
+}
+
 /// Metrics specific to VCPUs' mode of functioning.
 #[derive(Default, Serialize)]
 pub struct VcpuMetrics {
@@ -502,10 +1031,21 @@ pub struct VcpuMetrics {
     pub exit_mmio_write: SharedMetric,
     /// Number of errors during this VCPU's run.
     pub failures: SharedMetric,
+    /// Number of times `KVM_RUN` was retried after a transient failure.
+    pub kvm_run_retries: SharedMetric,
     /// Failures in configuring the CPUID.
     pub filter_cpuid: SharedMetric,
 }
 
+/// Metrics of the virtio queues of a device.
+#[derive(Default, Serialize)]
+pub struct VirtioQueueMetrics {
+    /// Number of malformed descriptor chains the guest driver made available.
+    pub descriptor_violations: SharedMetric,
+    /// Number of queues quarantined after too many malformed descriptor chains.
+    pub quarantined_queues: SharedMetric,
+}
+
 /// Metrics specific to the machine manager as a whole.
 #[derive(Default, Serialize)]
 pub struct VmmMetrics {
@@ -513,6 +1053,144 @@ pub struct VmmMetrics {
     pub device_events: SharedMetric,
     /// Metric for signaling a panic has occurred.
     pub panic_count: SharedMetric,
+    /// Number of events dropped because the control plane did not drain them in time.
+    pub dropped_events: SharedMetric,
+    /// Number of virtio devices reset by the guest driver, e.g. when the guest kexecs.
+    pub device_resets: SharedMetric,
+    /// Number of times a probe of the workload inside the guest changed state.
+    pub probe_state_changes: SharedMetric,
+    /// Number of reads of the guest memory made on behalf of the embedder.
+    pub guest_memory_reads: SharedMetric,
+    /// Number of writes to the guest memory made on behalf of the embedder.
+    pub guest_memory_writes: SharedMetric,
+    /// Number of reads and writes of the guest memory which were denied or failed.
+    pub guest_memory_access_fails: SharedMetric,
+    /// Number of checkpoints of the state of the microVM written.
+    pub checkpoints: SharedMetric,
+    /// Number of checkpoints of the state of the microVM which couldn't be written.
+    pub checkpoint_fails: SharedMetric,
+}
+
+/// Latencies of the actions handled by the VMM, from their dispatch to their response, in
+/// microseconds.
+#[derive(Default, Serialize)]
+pub struct VmmActionLatencyMetrics {
+    /// Latencies of the `ConfigureBootSource` action.
+    pub configure_boot_source: LatencyHistogram,
+    /// Latencies of the `ConfigureLogger` action.
+    pub configure_logger: LatencyHistogram,
+    /// Latencies of the `ConfigureMetrics` action.
+    pub configure_metrics: LatencyHistogram,
+    /// Latencies of the `CreateSnapshot` action.
+    pub create_snapshot: LatencyHistogram,
+    /// Latencies of the `DrainVsockConnections` action.
+    pub drain_vsock_connections: LatencyHistogram,
+    /// Latencies of the `DumpDeviceState` action.
+    pub dump_device_state: LatencyHistogram,
+    /// Latencies of the `FlushMetrics` action.
+    pub flush_metrics: LatencyHistogram,
+    /// Latencies of the `GetEvents` action.
+    pub get_events: LatencyHistogram,
+    /// Latencies of the `GetFullConfiguration` action.
+    pub get_full_configuration: LatencyHistogram,
+    /// Latencies of the `GetGuestDmesg` action.
+    pub get_guest_dmesg: LatencyHistogram,
+    /// Latencies of the `GetHostCapabilities` action.
+    pub get_host_capabilities: LatencyHistogram,
+    /// Latencies of the `GetLaunchMeasurement` action.
+    pub get_launch_measurement: LatencyHistogram,
+    /// Latencies of the `GetOperationStatus` action.
+    pub get_operation_status: LatencyHistogram,
+    /// Latencies of the `GetRateLimiterStats` action.
+    pub get_rate_limiter_stats: LatencyHistogram,
+    /// Latencies of the `GetVmConfiguration` action.
+    pub get_vm_configuration: LatencyHistogram,
+    /// Latencies of the `GetVmmResourceUsage` action.
+    pub get_vmm_resource_usage: LatencyHistogram,
+    /// Latencies of the `InsertBlockDevice` action.
+    pub insert_block_device: LatencyHistogram,
+    /// Latencies of the `InsertNetworkDevice` action.
+    pub insert_network_device: LatencyHistogram,
+    /// Latencies of the `InsertPciPassthroughDevice` action.
+    pub insert_pci_passthrough_device: LatencyHistogram,
+    /// Latencies of the `InsertProbe` action.
+    pub insert_probe: LatencyHistogram,
+    /// Latencies of the `InsertSharedMemoryDevice` action.
+    pub insert_shared_memory_device: LatencyHistogram,
+    /// Latencies of the `InsertVsockDevice` action.
+    pub insert_vsock_device: LatencyHistogram,
+    /// Latencies of the `ListDevices` action.
+    pub list_devices: LatencyHistogram,
+    /// Latencies of the `ListVsockConnections` action.
+    pub list_vsock_connections: LatencyHistogram,
+    /// Latencies of the `LiveUpdate` action.
+    pub live_update: LatencyHistogram,
+    /// Latencies of the `LoadSnapshot` action.
+    pub load_snapshot: LatencyHistogram,
+    /// Latencies of the `Migrate` action.
+    pub migrate: LatencyHistogram,
+    /// Latencies of the `Pause` action.
+    pub pause: LatencyHistogram,
+    /// Latencies of the `RemoveBlockDevice` action.
+    pub remove_block_device: LatencyHistogram,
+    /// Latencies of the `RemoveNetworkInterface` action.
+    pub remove_network_interface: LatencyHistogram,
+    /// Latencies of the `Resume` action.
+    pub resume: LatencyHistogram,
+    /// Latencies of the `SendCtrlAltDel` action.
+    pub send_ctrl_alt_del: LatencyHistogram,
+    /// Latencies of the `SendInputEvent` action.
+    pub send_input_event: LatencyHistogram,
+    /// Latencies of the `SetBalloonDevice` action.
+    pub set_balloon_device: LatencyHistogram,
+    /// Latencies of the `SetBlockDeviceState` action.
+    pub set_block_device_state: LatencyHistogram,
+    /// Latencies of the `SetCloudInit` action.
+    pub set_cloud_init: LatencyHistogram,
+    /// Latencies of the `SetConsoleConfiguration` action.
+    pub set_console_configuration: LatencyHistogram,
+    /// Latencies of the `SetEntropyDevice` action.
+    pub set_entropy_device: LatencyHistogram,
+    /// Latencies of the `SetGpuDevice` action.
+    pub set_gpu_device: LatencyHistogram,
+    /// Latencies of the `SetInputDevice` action.
+    pub set_input_device: LatencyHistogram,
+    /// Latencies of the `SetMemoryLimits` action.
+    pub set_memory_limits: LatencyHistogram,
+    /// Latencies of the `SetMmdsConfiguration` action.
+    pub set_mmds_configuration: LatencyHistogram,
+    /// Latencies of the `SetNetworkLinkState` action.
+    pub set_network_link_state: LatencyHistogram,
+    /// Latencies of the `SetRateLimitPolicy` action.
+    pub set_rate_limit_policy: LatencyHistogram,
+    /// Latencies of the `SetRtcConfiguration` action.
+    pub set_rtc_configuration: LatencyHistogram,
+    /// Latencies of the `SetSevConfiguration` action.
+    pub set_sev_configuration: LatencyHistogram,
+    /// Latencies of the `SetSoundDevice` action.
+    pub set_sound_device: LatencyHistogram,
+    /// Latencies of the `SetVcpuState` action.
+    pub set_vcpu_state: LatencyHistogram,
+    /// Latencies of the `SetVirtioFeaturePolicy` action.
+    pub set_virtio_feature_policy: LatencyHistogram,
+    /// Latencies of the `SetVmConfiguration` action.
+    pub set_vm_configuration: LatencyHistogram,
+    /// Latencies of the `SetVsockDevice` action.
+    pub set_vsock_device: LatencyHistogram,
+    /// Latencies of the `SnapshotBlockDevice` action.
+    pub snapshot_block_device: LatencyHistogram,
+    /// Latencies of the `StartMicroVm` action.
+    pub start_micro_vm: LatencyHistogram,
+    /// Latencies of the `UpdateBalloon` action.
+    pub update_balloon: LatencyHistogram,
+    /// Latencies of the `UpdateBlockDevice` action.
+    pub update_block_device: LatencyHistogram,
+    /// Latencies of the `UpdateNetworkInterface` action.
+    pub update_network_interface: LatencyHistogram,
+    /// Latencies of the `ValidateConfiguration` action.
+    pub validate_configuration: LatencyHistogram,
+    /// Latencies of the `WriteToConsole` action.
+    pub write_to_console: LatencyHistogram,
 }
 
 /// Metrics related to signals.
@@ -542,30 +1220,58 @@ pub struct FirecrackerMetrics {
     utc_timestamp_ms: SerializeToUtcTimestampMs,
     /// API Server related metrics.
     pub api_server: ApiServerMetrics,
+    /// The balloon device's related metrics.
+    pub balloon: BalloonDeviceMetrics,
     /// A block device's related metrics.
     pub block: BlockDeviceMetrics,
+    /// The latencies of the requests served by each block device, by drive ID.
+    pub block_latency: PerDeviceMetrics<BlockLatencyMetrics>,
+    /// Metrics related to API DELETE requests.
+    pub delete_api_requests: DeleteRequestsMetrics,
+    /// The entropy device's related metrics.
+    pub entropy: EntropyDeviceMetrics,
     /// Metrics related to API GET requests.
     pub get_api_requests: GetRequestsMetrics,
+    /// The GPU device's related metrics.
+    pub gpu: GpuDeviceMetrics,
     /// Metrics related to the i8042 device.
     pub i8042: I8042DeviceMetrics,
+    /// The input device's related metrics.
+    pub input: InputDeviceMetrics,
+    /// The interrupts throttled for each device, by device ID.
+    pub irq_throttle: PerDeviceMetrics<IrqThrottleMetrics>,
     /// Logging related metrics.
     pub logger: LoggerSystemMetrics,
+    /// Metrics related to the guest memory shared between snapshot clones.
+    pub memory_sharing: MemorySharingMetrics,
     /// Metrics specific to MMDS functionality.
     pub mmds: MmdsMetrics,
     /// A network device's related metrics.
     pub net: NetDeviceMetrics,
+    /// Metrics related to the guest page faults served on demand.
+    pub page_faults: PageFaultMetrics,
     /// Metrics related to API PATCH requests.
     pub patch_api_requests: PatchRequestsMetrics,
     /// Metrics related to API PUT requests.
     pub put_api_requests: PutRequestsMetrics,
+    /// Metrics related to the rate limiters.
+    pub rate_limiter: RateLimiterMetrics,
     /// Metrics related to the RTC device.
     pub rtc: RTCDeviceMetrics,
     /// Metrics related to seccomp filtering.
     pub seccomp: SeccompMetrics,
+    /// Metrics related to the shared memory devices.
+    pub shared_memory: SharedMemoryDeviceMetrics,
+    /// The sound device's related metrics.
+    pub sound: SoundDeviceMetrics,
     /// Metrics related to a vcpu's functioning.
     pub vcpu: VcpuMetrics,
+    /// The violations of the virtio queues of each device, by device ID.
+    pub virtio_queues: PerDeviceMetrics<VirtioQueueMetrics>,
     /// Metrics related to the virtual machine manager.
     pub vmm: VmmMetrics,
+    /// Metrics related to the latencies of the actions handled by the VMM.
+    pub vmm_action_latency: VmmActionLatencyMetrics,
     /// Metrics related to the UART device.
     pub uart: SerialDeviceMetrics,
     /// Metrics related to signals.
@@ -601,6 +1307,77 @@ mod tests {
         assert!(m.init(Box::new(f.into_file()),).is_err());
     }
 
+    struct TestExporter(Arc<Mutex<Vec<serde_json::Value>>>, bool);
+
+    impl MetricsExporter for TestExporter {
+        fn export(&mut self, metrics: &serde_json::Value) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(metrics.clone());
+            if self.1 {
+                return Err(std::io::Error::new(ErrorKind::Other, "export"));
+            }
+            Ok(())
+        }
+    }
+
+    // Waits for the exporting thread to push `count` snapshots.
+    fn wait_for_exports(
+        exported: &Arc<Mutex<Vec<serde_json::Value>>>,
+        count: usize,
+    ) -> Vec<serde_json::Value> {
+        for _ in 0..500 {
+            if exported.lock().unwrap().len() >= count {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        exported.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn test_exporters() {
+        let m = Metrics::new(FirecrackerMetrics::default());
+        let exported = Arc::new(Mutex::new(Vec::new()));
+        // A failing exporter does not prevent the others from exporting.
+        m.add_exporter(Box::new(TestExporter(exported.clone(), true)));
+        m.add_exporter(Box::new(TestExporter(exported.clone(), false)));
+
+        // Exporters are only fed once the metrics system is initialized.
+        assert!(!m.write().unwrap());
+
+        let f = TempFile::new().expect("Failed to create temporary metrics file");
+        m.init(Box::new(f.into_file())).unwrap();
+        m.block.read_count.add(5);
+        assert!(m.write().unwrap());
+        assert!(m.write().unwrap());
+        // The snapshots wait for the exporting thread.
+        assert!(exported.lock().unwrap().is_empty());
+
+        m.start_exporting(|| Ok(())).unwrap();
+        // The exporters receive the same deltas which were written to the file.
+        let snapshots = wait_for_exports(&exported, 4);
+        assert_eq!(snapshots.len(), 4);
+        assert_eq!(snapshots[0]["block"]["read_count"], 5);
+        assert_eq!(snapshots[1]["block"]["read_count"], 5);
+        assert_eq!(snapshots[3]["block"]["read_count"], 0);
+        assert!(m.write().unwrap());
+        assert_eq!(wait_for_exports(&exported, 6).len(), 6);
+
+        // The thread is only started once.
+        assert!(m.start_exporting(|| Err("seccomp".to_string())).is_ok());
+
+        // Nothing is exported by a thread which can't be confined.
+        let m = Metrics::new(FirecrackerMetrics::default());
+        m.add_exporter(Box::new(TestExporter(exported.clone(), false)));
+        match m.start_exporting(|| Err("seccomp".to_string())) {
+            Err(MetricsError::Export(_)) => (),
+            _ => panic!("Unexpected result."),
+        }
+
+        // Without exporters, no thread is started.
+        let m = Metrics::new(FirecrackerMetrics::default());
+        m.start_exporting(|| panic!("Unexpected thread.")).unwrap();
+    }
+
     #[test]
     fn test_metric() {
         // Test SharedMetric.
@@ -637,6 +1414,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_latency_histogram() {
+        let histogram = LatencyHistogram::default();
+        for &latency_us in &[0, 100, 101, 250_000, 1_000_000, 3_000_000] {
+            histogram.record(latency_us);
+        }
+        histogram.record_since(Instant::now());
+        assert_eq!(histogram.count(), 7);
+
+        let serialized = serde_json::to_value(&histogram).unwrap();
+        assert_eq!(serialized["count"], 7);
+        assert!(serialized["sum_us"].as_u64().unwrap() >= 4_250_201);
+        let buckets = &serialized["buckets_us"];
+        assert_eq!(buckets.as_object().unwrap().len(), LATENCY_BUCKETS);
+        assert_eq!(buckets["100"], 3);
+        assert_eq!(buckets["500"], 1);
+        assert_eq!(buckets["500000"], 1);
+        assert_eq!(buckets["1000000"], 1);
+        assert_eq!(buckets["inf"], 1);
+        assert_eq!(buckets["1000"], 0);
+
+        // The histogram is reset on each flush.
+        histogram.record(600);
+        let serialized = serde_json::to_value(&histogram).unwrap();
+        assert_eq!(serialized["count"], 1);
+        assert_eq!(serialized["sum_us"], 600);
+        assert_eq!(serialized["buckets_us"]["100"], 0);
+        assert_eq!(serialized["buckets_us"]["1000"], 1);
+    }
+
+    #[test]
+    fn test_per_device_metrics() {
+        let metrics = PerDeviceMetrics::<BlockLatencyMetrics>::default();
+        assert_eq!(
+            serde_json::to_value(&metrics).unwrap(),
+            serde_json::json!({})
+        );
+
+        metrics.get("rootfs").read.record(150);
+        // The devices with the same ID share their metrics.
+        metrics.get("rootfs").read.record(20);
+        metrics.get("scratch").flush.record(2_000);
+        assert_eq!(metrics.get("rootfs").read.count(), 2);
+
+        let serialized = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(serialized.as_object().unwrap().len(), 2);
+        assert_eq!(serialized["rootfs"]["read"]["count"], 2);
+        assert_eq!(serialized["rootfs"]["read"]["buckets_us"]["500"], 1);
+        assert_eq!(serialized["rootfs"]["flush"]["count"], 0);
+        assert_eq!(serialized["scratch"]["flush"]["buckets_us"]["5000"], 1);
+    }
+
     #[test]
     fn test_serialize() {
         let s = serde_json::to_string(&FirecrackerMetrics::default());
@@ -663,6 +1492,13 @@ mod tests {
             ),
             "Failed to write metrics. Error: write"
         );
+        assert_eq!(
+            format!(
+                "{}",
+                MetricsError::Export(std::io::Error::new(ErrorKind::Interrupted, "export"))
+            ),
+            "Failed to export metrics. Error: export"
+        );
         assert_eq!(
             format!(
                 "{}",
@@ -20,6 +20,8 @@ pub enum Header {
     Server,
     /// Header `Accept`
     Accept,
+    /// Header `Idempotency-Key`.
+    IdempotencyKey,
 }
 
 impl Header {
@@ -32,6 +34,7 @@ impl Header {
             Self::TransferEncoding => b"Transfer-Encoding",
             Self::Server => b"Server",
             Self::Accept => b"Accept",
+            Self::IdempotencyKey => b"Idempotency-Key",
         }
     }
 
@@ -51,6 +54,7 @@ impl Header {
                 "transfer-encoding" => Ok(Self::TransferEncoding),
                 "server" => Ok(Self::Server),
                 "accept" => Ok(Self::Accept),
+                "idempotency-key" => Ok(Self::IdempotencyKey),
                 _ => Err(RequestError::InvalidHeader),
             }
         } else {
@@ -87,6 +91,9 @@ pub struct Headers {
     /// `Accept` header might be used by HTTP clients to enforce server responses with content
     /// formatted in a specific way.
     accept: MediaType,
+    /// The `Idempotency-Key` header is set by HTTP clients which may retry the request, so that
+    /// the server can recognize the retries and apply the request only once.
+    idempotency_key: Option<String>,
 }
 
 impl Default for Headers {
@@ -99,6 +106,7 @@ impl Default for Headers {
             // The default `Accept` media type is plain text. This is inclusive enough
             // for structured and unstructured text.
             accept: MediaType::PlainText,
+            idempotency_key: None,
         }
     }
 }
@@ -169,6 +177,13 @@ impl Headers {
                             }
                             _ => Err(RequestError::InvalidHeader),
                         },
+                        Header::IdempotencyKey => match entry[1].trim() {
+                            "" => Err(RequestError::InvalidHeader),
+                            key => {
+                                self.idempotency_key = Some(key.to_string());
+                                Ok(())
+                            }
+                        },
                         Header::Server => Ok(()),
                     }
                 } else {
@@ -201,6 +216,11 @@ impl Headers {
         self.accept
     }
 
+    /// Returns the `Idempotency-Key` header, if there is one.
+    pub fn idempotency_key(&self) -> Option<&str> {
+        self.idempotency_key.as_ref().map(String::as_str)
+    }
+
     /// Parses a byte slice into a Headers structure for a HTTP request.
     ///
     /// The byte slice is expected to have the following format: </br>
@@ -324,6 +344,7 @@ mod tests {
                 expect,
                 chunked,
                 accept: MediaType::PlainText,
+                idempotency_key: None,
             }
         }
     }
@@ -464,6 +485,17 @@ mod tests {
             .is_ok());
         assert!(header.accept == MediaType::ApplicationJson);
         assert!(header.parse_header_line(b"Accept: text/plain").is_ok());
+
+        // Idempotency key.
+        assert_eq!(header.idempotency_key(), None);
+        assert_eq!(
+            header.parse_header_line(b"Idempotency-Key:  ").unwrap_err(),
+            RequestError::InvalidHeader
+        );
+        assert!(header
+            .parse_header_line(b"Idempotency-Key: 8e03978e-40d5")
+            .is_ok());
+        assert_eq!(header.idempotency_key(), Some("8e03978e-40d5"));
         assert!(header.accept == MediaType::PlainText);
 
         // Test invalid accept media type.
@@ -499,5 +531,8 @@ mod tests {
 
         let header = Header::try_from(b"Accept").unwrap();
         assert_eq!(header.raw(), b"Accept");
+
+        let header = Header::try_from(b"idempotency-key").unwrap();
+        assert_eq!(header.raw(), b"Idempotency-Key");
     }
 }
@@ -31,6 +31,8 @@ pub enum StatusCode {
     InternalServerError,
     /// 501, Not Implemented
     NotImplemented,
+    /// 503, Service Unavailable
+    ServiceUnavailable,
 }
 
 impl StatusCode {
@@ -45,6 +47,7 @@ impl StatusCode {
             Self::MethodNotAllowed => b"405",
             Self::InternalServerError => b"500",
             Self::NotImplemented => b"501",
+            Self::ServiceUnavailable => b"503",
         }
     }
 }
@@ -346,6 +349,7 @@ mod tests {
         assert_eq!(StatusCode::MethodNotAllowed.raw(), b"405");
         assert_eq!(StatusCode::InternalServerError.raw(), b"500");
         assert_eq!(StatusCode::NotImplemented.raw(), b"501");
+        assert_eq!(StatusCode::ServiceUnavailable.raw(), b"503");
     }
 
     #[test]
@@ -44,6 +44,12 @@ impl ServerRequest {
         &self.request
     }
 
+    /// Returns the identification token of the request, which a response computed later on
+    /// has to carry.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
     /// Calls the function provided on the inner request to obtain the response.
     /// The response is then wrapped in a `ServerResponse`.
     ///
@@ -66,7 +72,9 @@ pub struct ServerResponse {
 }
 
 impl ServerResponse {
-    fn new(response: Response, id: u64) -> Self {
+    /// Creates a new `ServerResponse` object from an existing `Response`, answering the
+    /// request identified by `id`.
+    pub fn new(response: Response, id: u64) -> Self {
         Self { response, id }
     }
 }
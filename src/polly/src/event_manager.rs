@@ -6,8 +6,9 @@ use std::fmt::Formatter;
 use std::io;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use utils::epoll::{self, Epoll, EpollEvent};
+use utils::epoll::{self, Epoll, EpollEvent, EventSet};
 
 pub type Result<T> = std::result::Result<T, Error>;
 pub type Pollable = RawFd;
@@ -61,12 +62,81 @@ pub trait Subscriber {
 
     /// Returns a list of `EpollEvent` that this subscriber is interested in.
     fn interest_list(&self) -> Vec<EpollEvent>;
+
+    /// Callback called repeatedly while the `EventManager` busy polls, to serve the work which
+    /// is ready without waiting for its event, e.g. the buffers available in a virtio queue.
+    ///
+    /// Returns `true` if any work was served. Subscribers with nothing to poll keep the default.
+    fn poll(&mut self, _event_manager: &mut EventManager) -> bool {
+        false
+    }
+}
+
+// Busy polling shorter than this isn't worth it: the window closes instead.
+const MIN_BUSY_POLL_WINDOW: Duration = Duration::from_micros(10);
+
+// Adapts how long the event loop busy polls before sleeping in epoll, the way halt polling
+// does: the window grows when the events came shortly after going to sleep, so polling would
+// have caught them, and shrinks when polling for the whole window was wasted.
+#[derive(Debug, Default)]
+struct BusyPoll {
+    max_window: Duration,
+    window: Duration,
+}
+
+impl BusyPoll {
+    fn grow(&mut self) {
+        self.window = (self.window * 2)
+            .max(MIN_BUSY_POLL_WINDOW)
+            .min(self.max_window);
+    }
+
+    fn shrink(&mut self) {
+        self.window /= 2;
+        if self.window < MIN_BUSY_POLL_WINDOW {
+            self.window = Duration::from_micros(0);
+        }
+    }
+}
+
+// A pollable registered with the epoll fd.
+struct Registration {
+    // What the epoll fd reports the events of the pollable with: the pollable in the low 32 bits
+    // and the generation of the registration in the high ones, so that the events reported for a
+    // pollable which was since unregistered, and possibly reused for another registration, are
+    // told apart and dropped.
+    token: u64,
+    // What the subscriber registered the pollable with, which its events are dispatched with.
+    data: u64,
+    // The events the pollable is monitored for.
+    event_set: EventSet,
+    // Whether the pollable is taken out of the epoll fd by `hold`.
+    held: bool,
+    subscriber: Arc<Mutex<dyn Subscriber>>,
+}
+
+// Whether `a` and `b` are the same subscriber. Comparing the vtables along with the data, as
+// `Arc::ptr_eq` does, could tell the same subscriber apart.
+fn same_subscriber(a: &Arc<Mutex<dyn Subscriber>>, b: &Arc<Mutex<dyn Subscriber>>) -> bool {
+    &**a as *const Mutex<dyn Subscriber> as *const u8
+        == &**b as *const Mutex<dyn Subscriber> as *const u8
 }
 
 /// Manages I/O notifications using epoll mechanism.
 pub struct EventManager {
     epoll: Epoll,
-    subscribers: HashMap<RawFd, Arc<Mutex<dyn Subscriber>>>,
+    subscribers: HashMap<RawFd, Registration>,
+    // Incremented with each registration, to build its token.
+    generation: u32,
+    // The subscribers added with `add_subscriber`, each once, called while busy polling.
+    pollers: Vec<Arc<Mutex<dyn Subscriber>>>,
+    // Whether a pollable was unregistered since the pollers left without any registered pollable
+    // were last dropped.
+    pollers_stale: bool,
+    busy_poll: BusyPoll,
+    device_budget: Option<usize>,
+    // Whether the pollables are held by `hold`.
+    held: bool,
     ready_events: Vec<EpollEvent>,
 }
 
@@ -86,6 +156,12 @@ impl EventManager {
         Ok(EventManager {
             epoll: epoll_fd,
             subscribers: HashMap::new(),
+            generation: 0,
+            pollers: Vec::new(),
+            pollers_stale: false,
+            busy_poll: BusyPoll::default(),
+            device_budget: None,
+            held: false,
             // This buffer is used for storing the events returned by `epoll_wait()`.
             // We preallocate memory for this buffer in order to not repeat this
             // operation every time `run()` loop is executed.
@@ -98,7 +174,7 @@ impl EventManager {
         self.subscribers
             .get(&fd)
             .ok_or(Error::NotFound(fd))
-            .map(|subscriber| subscriber.clone())
+            .map(|registration| registration.subscriber.clone())
     }
 
     /// Register a new subscriber. All events that the subscriber is interested are registered.
@@ -114,10 +190,38 @@ impl EventManager {
         for event in interest_list {
             self.register(event.data() as i32, event, subscriber.clone())?
         }
+        self.pollers.push(subscriber);
 
         Ok(())
     }
 
+    /// Busy polls for up to `max_us` microseconds before sleeping in epoll, trading CPU time
+    /// for a lower latency. The polling window adapts to how often polling finds work, within
+    /// `max_us`. Busy polling is disabled by default, or with a `max_us` of 0.
+    pub fn set_busy_poll(&mut self, max_us: u64) {
+        let max_window = Duration::from_micros(max_us);
+        self.busy_poll = BusyPoll {
+            max_window,
+            window: max_window,
+        };
+    }
+
+    /// Caps the work a subscriber does each time one of its events is dispatched, e.g. the
+    /// descriptor chains a device processes from one of its queues, so that a subscriber with a
+    /// lot of work doesn't starve the others of the event loop. A subscriber which runs out of
+    /// budget is expected to make its event ready again, to resume at the next iteration of the
+    /// event loop, once the events already ready have been dispatched. The work is not capped by
+    /// default, or with a `budget` of 0.
+    pub fn set_device_budget(&mut self, budget: usize) {
+        self.device_budget = if budget == 0 { None } else { Some(budget) };
+    }
+
+    /// Returns the work a subscriber may do each time one of its events is dispatched, if it is
+    /// capped.
+    pub fn device_budget(&self) -> Option<usize> {
+        self.device_budget
+    }
+
     /// Register a new `pollable` file descriptor with the corresponding `epoll_event`
     /// for `subscriber`.
     pub fn register(
@@ -130,18 +234,38 @@ impl EventManager {
             return Err(Error::AlreadyExists(pollable));
         };
 
+        self.generation = self.generation.wrapping_add(1);
+        let token = (u64::from(self.generation) << 32) | u64::from(pollable as u32);
         self.epoll
-            .ctl(epoll::ControlOperation::Add, pollable, &epoll_event)
+            .ctl(
+                epoll::ControlOperation::Add,
+                pollable,
+                &EpollEvent::new(epoll_event.event_set(), token),
+            )
             .map_err(Error::Poll)?;
 
-        self.subscribers.insert(pollable, subscriber);
+        self.subscribers.insert(
+            pollable,
+            Registration {
+                token,
+                data: epoll_event.data(),
+                event_set: epoll_event.event_set(),
+                held: false,
+                subscriber,
+            },
+        );
         Ok(())
     }
 
-    /// Unregister the `pollable` file descriptor.
+    /// Unregister the `pollable` file descriptor. The events already reported for it are not
+    /// dispatched anymore, and a subscriber added with `add_subscriber` which is left without any
+    /// registered pollable is dropped.
     pub fn unregister(&mut self, pollable: Pollable) -> Result<()> {
         match self.subscribers.remove(&pollable) {
+            // A held pollable is out of the epoll fd already.
+            Some(ref registration) if registration.held => self.pollers_stale = true,
             Some(_) => {
+                self.pollers_stale = true;
                 self.epoll
                     .ctl(
                         epoll::ControlOperation::Delete,
@@ -157,12 +281,38 @@ impl EventManager {
         Ok(())
     }
 
-    /// Update the events monitored by `pollable`.
+    /// Unregisters all the pollables of `subscriber` and stops polling it, e.g. when its device
+    /// is removed.
+    pub fn remove_subscriber(&mut self, subscriber: &Arc<Mutex<dyn Subscriber>>) -> Result<()> {
+        let pollables: Vec<Pollable> = self
+            .subscribers
+            .iter()
+            .filter(|(_, registration)| same_subscriber(&registration.subscriber, subscriber))
+            .map(|(pollable, _)| *pollable)
+            .collect();
+        for pollable in pollables {
+            self.unregister(pollable)?;
+        }
+        self.pollers
+            .retain(|poller| !same_subscriber(poller, subscriber));
+        Ok(())
+    }
+
+    /// Update the events monitored by `pollable`. The events of a held pollable are monitored
+    /// once it is released.
     pub fn modify(&mut self, pollable: Pollable, epoll_event: EpollEvent) -> Result<()> {
-        if self.subscribers.contains_key(&pollable) {
-            self.epoll
-                .ctl(epoll::ControlOperation::Modify, pollable, &epoll_event)
-                .map_err(Error::Poll)?;
+        if let Some(registration) = self.subscribers.get_mut(&pollable) {
+            if !registration.held {
+                self.epoll
+                    .ctl(
+                        epoll::ControlOperation::Modify,
+                        pollable,
+                        &EpollEvent::new(epoll_event.event_set(), registration.token),
+                    )
+                    .map_err(Error::Poll)?;
+            }
+            registration.data = epoll_event.data();
+            registration.event_set = epoll_event.event_set();
         } else {
             return Err(Error::NotFound(pollable));
         }
@@ -170,6 +320,54 @@ impl EventManager {
         Ok(())
     }
 
+    /// Stops dispatching the events of all the registered pollables but `keep`, until `release`
+    /// is called, e.g. so that the devices leave the guest memory alone while it is saved. The
+    /// events of the held pollables are dispatched once they are released, and busy polling is
+    /// disabled meanwhile. The pollables registered afterwards are not held.
+    pub fn hold(&mut self, keep: &[Pollable]) -> Result<()> {
+        for (pollable, registration) in self.subscribers.iter_mut() {
+            if registration.held || keep.contains(pollable) {
+                continue;
+            }
+            // Deleting the pollable from the epoll fd, rather than monitoring it for no events,
+            // keeps its hang ups from being reported meanwhile.
+            self.epoll
+                .ctl(
+                    epoll::ControlOperation::Delete,
+                    *pollable,
+                    &EpollEvent::default(),
+                )
+                .map_err(Error::Poll)?;
+            registration.held = true;
+        }
+        self.held = true;
+        Ok(())
+    }
+
+    /// Dispatches the events of the pollables held by `hold` again.
+    pub fn release(&mut self) -> Result<()> {
+        for (pollable, registration) in self.subscribers.iter_mut() {
+            if !registration.held {
+                continue;
+            }
+            self.epoll
+                .ctl(
+                    epoll::ControlOperation::Add,
+                    *pollable,
+                    &EpollEvent::new(registration.event_set, registration.token),
+                )
+                .map_err(Error::Poll)?;
+            registration.held = false;
+        }
+        self.held = false;
+        Ok(())
+    }
+
+    /// Returns whether the pollables are held by `hold`.
+    pub fn is_held(&self) -> bool {
+        self.held
+    }
+
     /// Wait for events, then dispatch to the registered event handlers.
     pub fn run(&mut self) -> Result<usize> {
         self.run_with_timeout(-1)
@@ -177,7 +375,45 @@ impl EventManager {
 
     /// Wait for events for a maximum timeout of `miliseconds`. Dispatch the events to the
     /// registered signal handlers.
+    ///
+    /// With busy polling enabled, the subscribers are polled and the ready events dispatched
+    /// in a loop during the polling window, before waiting for the rest of the timeout.
     pub fn run_with_timeout(&mut self, milliseconds: i32) -> Result<usize> {
+        if milliseconds == 0 || self.held || self.busy_poll.max_window == Duration::from_micros(0) {
+            return self.wait_and_dispatch(milliseconds);
+        }
+
+        let start = Instant::now();
+        while start.elapsed() < self.busy_poll.window {
+            let polled = self.poll_subscribers();
+            let event_count = self.wait_and_dispatch(0)?;
+            if polled || event_count > 0 {
+                return Ok(event_count);
+            }
+            std::sync::atomic::spin_loop_hint();
+        }
+
+        let polled_ms = start.elapsed().as_millis() as i32;
+        if milliseconds > 0 && polled_ms >= milliseconds {
+            return Ok(0);
+        }
+        let remaining_ms = if milliseconds > 0 {
+            milliseconds - polled_ms
+        } else {
+            milliseconds
+        };
+        let sleep_start = Instant::now();
+        let event_count = self.wait_and_dispatch(remaining_ms)?;
+        if event_count > 0 && sleep_start.elapsed() <= self.busy_poll.max_window {
+            self.busy_poll.grow();
+        } else {
+            self.busy_poll.shrink();
+        }
+
+        Ok(event_count)
+    }
+
+    fn wait_and_dispatch(&mut self, milliseconds: i32) -> Result<usize> {
         let event_count = match self.epoll.wait(
             EventManager::EVENT_BUFFER_SIZE,
             milliseconds,
@@ -192,22 +428,45 @@ impl EventManager {
         Ok(event_count)
     }
 
+    fn poll_subscribers(&mut self) -> bool {
+        if self.pollers_stale {
+            let subscribers = &self.subscribers;
+            self.pollers.retain(|poller| {
+                subscribers
+                    .values()
+                    .any(|registration| same_subscriber(&registration.subscriber, poller))
+            });
+            self.pollers_stale = false;
+        }
+
+        let mut polled = false;
+        for index in 0..self.pollers.len() {
+            let poller = self.pollers[index].clone();
+            polled |= poller.lock().unwrap().poll(self);
+        }
+        polled
+    }
+
     fn dispatch_events(&mut self, event_count: usize) {
         // Use the temporary, pre-allocated buffer to check ready events.
         for ev_index in 0..event_count {
-            let event = &self.ready_events[ev_index].clone();
-            let pollable = event.fd();
-
-            if self.subscribers.contains_key(&pollable) {
-                self.subscribers
-                    .get_mut(&pollable)
-                    .unwrap()
-                    .clone()
-                    .lock()
-                    .unwrap()
-                    .process(&event, self);
-            }
-            // TODO: Should we log an error in case the subscriber does not exist?
+            let ready_event = self.ready_events[ev_index].clone();
+            let pollable = ready_event.fd();
+
+            // The pollable may have been unregistered, or even registered again, or held, while
+            // dispatching the events before this one.
+            let (event, subscriber) = match self.subscribers.get(&pollable) {
+                Some(registration)
+                    if registration.token == ready_event.data() && !registration.held =>
+                {
+                    (
+                        EpollEvent::new(ready_event.event_set(), registration.data),
+                        registration.subscriber.clone(),
+                    )
+                }
+                _ => continue,
+            };
+            subscriber.lock().unwrap().process(&event, self);
         }
     }
 }
@@ -500,4 +759,330 @@ mod tests {
         assert!(event_manager.subscriber(dummy_fd).is_ok());
         assert!(event_manager.subscriber(-1).is_err());
     }
+
+    struct PollingSubscriber {
+        event_fd: EventFd,
+        pending: usize,
+        polls: usize,
+    }
+
+    impl Subscriber for PollingSubscriber {
+        fn process(&mut self, _: &EpollEvent, _: &mut EventManager) {
+            self.event_fd.read().unwrap();
+        }
+
+        fn interest_list(&self) -> Vec<EpollEvent> {
+            vec![EpollEvent::new(
+                EventSet::IN,
+                self.event_fd.as_raw_fd() as u64,
+            )]
+        }
+
+        fn poll(&mut self, _: &mut EventManager) -> bool {
+            self.polls += 1;
+            let served = self.pending > 0;
+            self.pending = 0;
+            served
+        }
+    }
+
+    #[test]
+    fn test_busy_poll() {
+        let mut event_manager = EventManager::new().unwrap();
+        let subscriber = Arc::new(Mutex::new(PollingSubscriber {
+            event_fd: EventFd::new(0).unwrap(),
+            pending: 0,
+            polls: 0,
+        }));
+        event_manager.add_subscriber(subscriber.clone()).unwrap();
+
+        // Busy polling is disabled by default.
+        assert_eq!(event_manager.run_with_timeout(10).unwrap(), 0);
+        assert_eq!(subscriber.lock().unwrap().polls, 0);
+
+        // The work polled is served without waiting for its event.
+        event_manager.set_busy_poll(1000);
+        subscriber.lock().unwrap().pending = 1;
+        assert_eq!(event_manager.run_with_timeout(-1).unwrap(), 0);
+        assert_eq!(subscriber.lock().unwrap().pending, 0);
+        assert_eq!(event_manager.busy_poll.window, Duration::from_micros(1000));
+
+        // The events ready while polling are dispatched.
+        subscriber.lock().unwrap().event_fd.write(1).unwrap();
+        assert_eq!(event_manager.run_with_timeout(-1).unwrap(), 1);
+
+        // Polling for the whole window without finding any work shrinks the window.
+        subscriber.lock().unwrap().polls = 0;
+        assert_eq!(event_manager.run_with_timeout(10).unwrap(), 0);
+        assert!(subscriber.lock().unwrap().polls > 0);
+        assert_eq!(event_manager.busy_poll.window, Duration::from_micros(500));
+
+        // A timeout of 0 doesn't poll.
+        subscriber.lock().unwrap().polls = 0;
+        assert_eq!(event_manager.run_with_timeout(0).unwrap(), 0);
+        assert_eq!(subscriber.lock().unwrap().polls, 0);
+    }
+
+    // Serves one unit of work per budget, and makes its event ready again while work is left.
+    struct BudgetedSubscriber {
+        event_fd: EventFd,
+        pending: usize,
+        served: Vec<usize>,
+    }
+
+    impl Subscriber for BudgetedSubscriber {
+        fn process(&mut self, _: &EpollEvent, event_manager: &mut EventManager) {
+            self.event_fd.read().unwrap();
+            let budget = event_manager.device_budget().unwrap_or(usize::max_value());
+            let served = std::cmp::min(self.pending, budget);
+            self.pending -= served;
+            self.served.push(served);
+            if self.pending > 0 {
+                self.event_fd.write(1).unwrap();
+            }
+        }
+
+        fn interest_list(&self) -> Vec<EpollEvent> {
+            vec![EpollEvent::new(
+                EventSet::IN,
+                self.event_fd.as_raw_fd() as u64,
+            )]
+        }
+    }
+
+    #[test]
+    fn test_device_budget() {
+        let mut event_manager = EventManager::new().unwrap();
+        assert_eq!(event_manager.device_budget(), None);
+        let subscriber = |pending| {
+            let subscriber = Arc::new(Mutex::new(BudgetedSubscriber {
+                event_fd: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+                pending,
+                served: Vec::new(),
+            }));
+            subscriber.lock().unwrap().event_fd.write(1).unwrap();
+            subscriber
+        };
+
+        // Without a budget, all the work is served at once.
+        let flooding = subscriber(10);
+        event_manager.add_subscriber(flooding.clone()).unwrap();
+        assert_eq!(event_manager.run_with_timeout(0).unwrap(), 1);
+        assert_eq!(flooding.lock().unwrap().served, vec![10]);
+
+        // With a budget, the work left is served at the next iterations, and the other
+        // subscribers are served in between.
+        event_manager.set_device_budget(4);
+        assert_eq!(event_manager.device_budget(), Some(4));
+        flooding.lock().unwrap().pending = 10;
+        flooding.lock().unwrap().event_fd.write(1).unwrap();
+        assert_eq!(event_manager.run_with_timeout(0).unwrap(), 1);
+        let other = subscriber(1);
+        event_manager.add_subscriber(other.clone()).unwrap();
+        assert_eq!(event_manager.run_with_timeout(0).unwrap(), 2);
+        assert_eq!(other.lock().unwrap().served, vec![1]);
+        assert_eq!(event_manager.run_with_timeout(0).unwrap(), 1);
+        assert_eq!(event_manager.run_with_timeout(0).unwrap(), 0);
+        assert_eq!(flooding.lock().unwrap().served, vec![10, 4, 4, 2]);
+
+        event_manager.set_device_budget(0);
+        assert_eq!(event_manager.device_budget(), None);
+    }
+
+    #[test]
+    fn test_remove_subscriber() {
+        let mut event_manager = EventManager::new().unwrap();
+        let subscriber = Arc::new(Mutex::new(PollingSubscriber {
+            event_fd: EventFd::new(0).unwrap(),
+            pending: 0,
+            polls: 0,
+        }));
+        event_manager.add_subscriber(subscriber.clone()).unwrap();
+        let fd = subscriber.lock().unwrap().event_fd.as_raw_fd();
+        let removed: Arc<Mutex<dyn Subscriber>> = subscriber.clone();
+
+        event_manager.remove_subscriber(&removed).unwrap();
+        drop(removed);
+        assert!(event_manager.subscriber(fd).is_err());
+        assert!(event_manager.pollers.is_empty());
+        assert_eq!(Arc::strong_count(&subscriber), 1);
+
+        // The subscriber isn't polled, nor its events dispatched, anymore.
+        event_manager.set_busy_poll(1000);
+        subscriber.lock().unwrap().event_fd.write(1).unwrap();
+        assert_eq!(event_manager.run_with_timeout(10).unwrap(), 0);
+        assert_eq!(subscriber.lock().unwrap().polls, 0);
+    }
+
+    #[test]
+    fn test_unregistered_pollers() {
+        let mut event_manager = EventManager::new().unwrap();
+        let subscriber = Arc::new(Mutex::new(PollingSubscriber {
+            event_fd: EventFd::new(0).unwrap(),
+            pending: 0,
+            polls: 0,
+        }));
+        event_manager.add_subscriber(subscriber.clone()).unwrap();
+
+        // A subscriber left without any registered pollable is dropped before polling.
+        let fd = subscriber.lock().unwrap().event_fd.as_raw_fd();
+        event_manager.unregister(fd).unwrap();
+        event_manager.set_busy_poll(1000);
+        assert_eq!(event_manager.run_with_timeout(10).unwrap(), 0);
+        assert_eq!(subscriber.lock().unwrap().polls, 0);
+        assert_eq!(Arc::strong_count(&subscriber), 1);
+    }
+
+    struct CountingSubscriber {
+        processed: usize,
+    }
+
+    impl Subscriber for CountingSubscriber {
+        fn process(&mut self, _: &EpollEvent, _: &mut EventManager) {
+            self.processed += 1;
+        }
+
+        fn interest_list(&self) -> Vec<EpollEvent> {
+            vec![]
+        }
+    }
+
+    // Owns two eventfds, and when first processing the event of either, replaces the other with
+    // a new eventfd, registered for `replacement`.
+    struct RacingSubscriber {
+        event_fds: Vec<EventFd>,
+        processed: usize,
+        replacement: Arc<Mutex<CountingSubscriber>>,
+        replacement_fd: Option<EventFd>,
+    }
+
+    impl Subscriber for RacingSubscriber {
+        fn process(&mut self, event: &EpollEvent, event_manager: &mut EventManager) {
+            self.processed += 1;
+            if self.replacement_fd.is_some() {
+                return;
+            }
+            let index = self
+                .event_fds
+                .iter()
+                .position(|event_fd| event_fd.as_raw_fd() != event.fd())
+                .unwrap();
+            let peer_fd = self.event_fds.remove(index);
+            event_manager.unregister(peer_fd.as_raw_fd()).unwrap();
+            drop(peer_fd);
+
+            // Most likely reuses the number of the eventfd just closed.
+            let event_fd = EventFd::new(0).unwrap();
+            event_manager
+                .register(
+                    event_fd.as_raw_fd(),
+                    EpollEvent::new(EventSet::OUT, event_fd.as_raw_fd() as u64),
+                    self.replacement.clone(),
+                )
+                .unwrap();
+            self.replacement_fd = Some(event_fd);
+        }
+
+        fn interest_list(&self) -> Vec<EpollEvent> {
+            self.event_fds
+                .iter()
+                .map(|event_fd| EpollEvent::new(EventSet::OUT, event_fd.as_raw_fd() as u64))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_stale_events() {
+        let mut event_manager = EventManager::new().unwrap();
+        let replacement = Arc::new(Mutex::new(CountingSubscriber { processed: 0 }));
+        let subscriber = Arc::new(Mutex::new(RacingSubscriber {
+            event_fds: vec![EventFd::new(0).unwrap(), EventFd::new(0).unwrap()],
+            processed: 0,
+            replacement: replacement.clone(),
+            replacement_fd: None,
+        }));
+        event_manager.add_subscriber(subscriber.clone()).unwrap();
+
+        // Both eventfds are ready for writing, but whichever is dispatched first unregisters the
+        // other one, whose event is then dropped, even if its number is reused in between.
+        assert_eq!(event_manager.run_with_timeout(0).unwrap(), 2);
+        assert_eq!(subscriber.lock().unwrap().processed, 1);
+        assert_eq!(replacement.lock().unwrap().processed, 0);
+
+        // The replacement gets the events of its own eventfd.
+        assert_eq!(event_manager.run_with_timeout(0).unwrap(), 2);
+        assert_eq!(replacement.lock().unwrap().processed, 1);
+    }
+
+    #[test]
+    fn test_hold() {
+        let mut event_manager = EventManager::new().unwrap();
+        let held = Arc::new(Mutex::new(CountingSubscriber { processed: 0 }));
+        let kept = Arc::new(Mutex::new(CountingSubscriber { processed: 0 }));
+        let held_fd = EventFd::new(0).unwrap();
+        let kept_fd = EventFd::new(0).unwrap();
+        event_manager
+            .register(
+                held_fd.as_raw_fd(),
+                EpollEvent::new(EventSet::OUT, 0),
+                held.clone(),
+            )
+            .unwrap();
+        event_manager
+            .register(
+                kept_fd.as_raw_fd(),
+                EpollEvent::new(EventSet::OUT, 0),
+                kept.clone(),
+            )
+            .unwrap();
+
+        event_manager.hold(&[kept_fd.as_raw_fd()]).unwrap();
+        assert!(event_manager.is_held());
+        assert_eq!(event_manager.run_with_timeout(0).unwrap(), 1);
+        assert_eq!(held.lock().unwrap().processed, 0);
+        assert_eq!(kept.lock().unwrap().processed, 1);
+        // Holding again changes nothing, and the held pollables can still be modified.
+        event_manager.hold(&[kept_fd.as_raw_fd()]).unwrap();
+        event_manager
+            .modify(held_fd.as_raw_fd(), EpollEvent::new(EventSet::IN, 0))
+            .unwrap();
+        assert_eq!(event_manager.run_with_timeout(0).unwrap(), 1);
+        assert_eq!(held.lock().unwrap().processed, 0);
+
+        // The held pollables are monitored for their latest events once released.
+        event_manager.release().unwrap();
+        assert!(!event_manager.is_held());
+        assert_eq!(event_manager.run_with_timeout(0).unwrap(), 1);
+        assert_eq!(held.lock().unwrap().processed, 0);
+        held_fd.write(1).unwrap();
+        assert_eq!(event_manager.run_with_timeout(0).unwrap(), 2);
+        assert_eq!(held.lock().unwrap().processed, 1);
+
+        // The held pollables can be unregistered.
+        event_manager.hold(&[]).unwrap();
+        event_manager.unregister(held_fd.as_raw_fd()).unwrap();
+        event_manager.release().unwrap();
+        assert_eq!(event_manager.run_with_timeout(0).unwrap(), 1);
+        assert_eq!(held.lock().unwrap().processed, 1);
+        assert_eq!(kept.lock().unwrap().processed, 5);
+    }
+
+    #[test]
+    fn test_busy_poll_window() {
+        let mut busy_poll = BusyPoll {
+            max_window: Duration::from_micros(30),
+            window: Duration::from_micros(0),
+        };
+        busy_poll.grow();
+        assert_eq!(busy_poll.window, MIN_BUSY_POLL_WINDOW);
+        busy_poll.grow();
+        busy_poll.grow();
+        busy_poll.grow();
+        assert_eq!(busy_poll.window, Duration::from_micros(30));
+
+        busy_poll.shrink();
+        assert_eq!(busy_poll.window, Duration::from_micros(15));
+        busy_poll.shrink();
+        assert_eq!(busy_poll.window, Duration::from_micros(0));
+    }
 }
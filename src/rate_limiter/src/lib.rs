@@ -34,6 +34,9 @@
 //! The granularity for 'wake up' events when the rate limiter is blocked is
 //! currently hardcoded to `100 milliseconds`.
 //!
+//! The rate limiter keeps track of how many times it blocked and for how long, so
+//! that `stats()` tells whether the configured limits are holding its user back.
+//!
 //! ## Limitations
 //!
 //! This rate limiter implementation relies on the *Linux kernel's timerfd* so its
@@ -53,6 +56,7 @@ extern crate snapshot;
 extern crate versionize;
 extern crate versionize_derive;
 
+use logger::{Metric, METRICS};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::time::{Duration, Instant};
 use std::{fmt, io};
@@ -227,6 +231,53 @@ impl TokenBucket {
     pub fn budget(&self) -> u64 {
         self.budget
     }
+
+    // Returns the budget the bucket would have if it were replenished now, without replenishing
+    // it.
+    fn refilled_budget(&self) -> u64 {
+        let time_delta = self.last_update.elapsed().as_nanos() as u64;
+        let refill =
+            time_delta.saturating_mul(self.processed_capacity) / self.processed_refill_time;
+        std::cmp::min(self.budget.saturating_add(refill), self.size)
+    }
+
+    /// Returns a snapshot of the current state of the bucket.
+    pub fn stats(&self) -> TokenBucketStats {
+        TokenBucketStats {
+            size: self.size,
+            budget: self.refilled_budget(),
+            one_time_burst: self.one_time_burst(),
+            refill_time_ms: self.refill_time,
+        }
+    }
+}
+
+/// A snapshot of the state of a `TokenBucket`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenBucketStats {
+    /// Total capacity of the bucket.
+    pub size: u64,
+    /// Tokens currently available, one time burst notwithstanding.
+    pub budget: u64,
+    /// Remaining one time burst.
+    pub one_time_burst: u64,
+    /// Time in milliseconds required to completely fill the bucket.
+    pub refill_time_ms: u64,
+}
+
+/// A snapshot of the state of a `RateLimiter`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimiterStats {
+    /// The state of the bandwidth token bucket, if bandwidth is limited.
+    pub bandwidth: Option<TokenBucketStats>,
+    /// The state of the ops token bucket, if ops are limited.
+    pub ops: Option<TokenBucketStats>,
+    /// Whether the rate limiter is currently blocked.
+    pub blocked: bool,
+    /// Number of times the rate limiter blocked.
+    pub blocked_count: u64,
+    /// Total time the rate limiter spent blocked, including the current blocking.
+    pub blocked_time: Duration,
 }
 
 /// Enum that describes the type of token used.
@@ -259,6 +310,11 @@ pub struct RateLimiter {
     timer_fd: TimerFd,
     // Internal flag that quickly determines timer state.
     timer_active: bool,
+
+    // Introspection of the time spent blocked.
+    blocked_count: u64,
+    blocked_since: Option<Instant>,
+    blocked_time: Duration,
 }
 
 impl PartialEq for RateLimiter {
@@ -349,6 +405,9 @@ impl RateLimiter {
             ops: ops_token_bucket,
             timer_fd,
             timer_active: false,
+            blocked_count: 0,
+            blocked_since: None,
+            blocked_time: Duration::default(),
         })
     }
 
@@ -377,6 +436,9 @@ impl RateLimiter {
             self.timer_fd
                 .set_state(TIMER_REFILL_STATE, SetTimeFlags::Default);
             self.timer_active = true;
+            self.blocked_count += 1;
+            self.blocked_since = Some(Instant::now());
+            METRICS.rate_limiter.blocked_count.inc();
         }
         success
     }
@@ -419,11 +481,24 @@ impl RateLimiter {
             )),
             _ => {
                 self.timer_active = false;
+                self.unblock();
                 Ok(())
             }
         }
     }
 
+    // Accounts for the time spent blocked, once the refill timer fired.
+    fn unblock(&mut self) {
+        if let Some(blocked_since) = self.blocked_since.take() {
+            let blocked_time = blocked_since.elapsed();
+            self.blocked_time += blocked_time;
+            METRICS
+                .rate_limiter
+                .blocked_time_us
+                .add(blocked_time.as_micros() as usize);
+        }
+    }
+
     /// Updates the parameters of the token buckets associated with this RateLimiter.
     // TODO: Please note that, right now, the buckets become full after being updated.
     pub fn update_buckets(&mut self, bytes: Option<TokenBucket>, ops: Option<TokenBucket>) {
@@ -454,6 +529,22 @@ impl RateLimiter {
     pub fn ops(&self) -> Option<&TokenBucket> {
         self.ops.as_ref()
     }
+
+    /// Returns a snapshot of the current state of the token buckets, and of the time spent
+    /// blocked so far.
+    pub fn stats(&self) -> RateLimiterStats {
+        let blocking_time = self
+            .blocked_since
+            .map(|blocked_since| blocked_since.elapsed())
+            .unwrap_or_default();
+        RateLimiterStats {
+            bandwidth: self.bandwidth.as_ref().map(TokenBucket::stats),
+            ops: self.ops.as_ref().map(TokenBucket::stats),
+            blocked: self.timer_active,
+            blocked_count: self.blocked_count,
+            blocked_time: self.blocked_time + blocking_time,
+        }
+    }
 }
 
 impl AsRawFd for RateLimiter {
@@ -784,4 +875,50 @@ pub(crate) mod tests {
             ),
         );
     }
+
+    #[test]
+    fn test_rate_limiter_stats() {
+        let mut l = RateLimiter::new(1000, Some(500), 1000, 0, None, 0).unwrap();
+        let stats = l.stats();
+        assert_eq!(
+            stats.bandwidth,
+            Some(TokenBucketStats {
+                size: 1000,
+                budget: 1000,
+                one_time_burst: 500,
+                refill_time_ms: 1000,
+            })
+        );
+        assert_eq!(stats.ops, None);
+        assert!(!stats.blocked);
+        assert_eq!(stats.blocked_count, 0);
+        assert_eq!(stats.blocked_time, Duration::default());
+
+        // Use up the one time burst, then the budget.
+        assert!(l.consume(1500, TokenType::Bytes));
+        let bandwidth = l.stats().bandwidth.unwrap();
+        assert_eq!(bandwidth.one_time_burst, 0);
+        assert!(bandwidth.budget < 100);
+
+        let blocked_count = METRICS.rate_limiter.blocked_count.count();
+        assert!(!l.consume(500, TokenType::Bytes));
+        assert!(METRICS.rate_limiter.blocked_count.count() > blocked_count);
+        let stats = l.stats();
+        assert!(stats.blocked);
+        assert_eq!(stats.blocked_count, 1);
+
+        // The budget is refilled in the stats, before the next `consume()`.
+        thread::sleep(Duration::from_millis(REFILL_TIMER_INTERVAL_MS));
+        let stats = l.stats();
+        assert!(stats.bandwidth.unwrap().budget >= 100);
+        assert!(stats.blocked_time >= Duration::from_millis(REFILL_TIMER_INTERVAL_MS));
+
+        // Once unblocked, the time spent blocked stops growing.
+        assert!(l.event_handler().is_ok());
+        let stats = l.stats();
+        assert!(!stats.blocked);
+        assert_eq!(stats.blocked_count, 1);
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(l.stats().blocked_time, stats.blocked_time);
+    }
 }
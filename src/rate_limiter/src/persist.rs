@@ -81,6 +81,9 @@ impl Persist for RateLimiter {
                 .map(|bw| TokenBucket::restore((), bw).unwrap()),
             timer_fd: TimerFd::new_custom(ClockId::Monotonic, true, true)?,
             timer_active: false,
+            blocked_count: 0,
+            blocked_since: None,
+            blocked_time: Duration::default(),
         };
 
         Ok(rate_limiter)
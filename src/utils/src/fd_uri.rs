@@ -0,0 +1,193 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The `fd://<fd>` URIs, naming a file descriptor of the process instead of a path, so that the
+//! files given to Firecracker need not be reachable from inside its jail: they are opened by the
+//! process launching it, e.g. after receiving them over a Unix socket with `SCM_RIGHTS`.
+//!
+//! Only the descriptors inherited by the process can be named, as recorded by
+//! `record_inherited_fds` at startup, so that the files Firecracker opens itself, e.g. the API
+//! socket or the guest memory, can't be handed to the guest.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::Mutex;
+
+/// The scheme of the URIs naming a file descriptor.
+pub const FD_URI_SCHEME: &str = "fd://";
+
+lazy_static! {
+    // The descriptors inherited by the process, the only ones the URIs can name.
+    static ref INHERITED_FDS: Mutex<Vec<RawFd>> = Mutex::new(Vec::new());
+}
+
+/// Records the file descriptors open in the process, other than the standard streams, as the
+/// ones the `fd://<fd>` URIs can name. This is to be called at startup, before the process opens
+/// any file, so that only the descriptors inherited from the launching process are recorded.
+pub fn record_inherited_fds() -> io::Result<()> {
+    let open_fds = std::fs::read_dir("/proc/self/fd")?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<RawFd>().ok())
+        .collect::<Vec<_>>();
+    // The descriptor listing `/proc/self/fd` is closed by now, so it is left out.
+    let inherited = open_fds
+        .into_iter()
+        // Safe because `F_GETFD` only reads the flags of the descriptor.
+        .filter(|&fd| fd > libc::STDERR_FILENO && unsafe { libc::fcntl(fd, libc::F_GETFD) } >= 0)
+        .collect();
+    *INHERITED_FDS.lock().expect("Poisoned lock") = inherited;
+    Ok(())
+}
+
+/// Returns the file descriptor named by `path`, if it is a `fd://<fd>` URI. The standard streams
+/// and the descriptors not inherited by the process can't be named.
+pub fn parse(path: &str) -> Option<io::Result<RawFd>> {
+    if !path.starts_with(FD_URI_SCHEME) {
+        return None;
+    }
+    let fd = path[FD_URI_SCHEME.len()..]
+        .parse::<RawFd>()
+        .ok()
+        .filter(|&fd| fd > libc::STDERR_FILENO)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid file descriptor URI {}.", path),
+            )
+        })
+        .and_then(|fd| {
+            if INHERITED_FDS.lock().expect("Poisoned lock").contains(&fd) {
+                Ok(fd)
+            } else {
+                Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "The file descriptor {} was not inherited by the process.",
+                        fd
+                    ),
+                ))
+            }
+        });
+    Some(fd)
+}
+
+/// Returns a duplicate of the file descriptor named by `path`, if it is a `fd://<fd>` URI.
+///
+/// The descriptor named is left open, so that it can be named again, e.g. by another drive
+/// backed by the same file.
+pub fn dup(path: &str) -> Option<io::Result<File>> {
+    parse(path).map(|fd| {
+        // Safe because the duplicate is a new descriptor, owned by the file only, and we check
+        // the return value.
+        let dup_fd = unsafe { libc::fcntl(fd?, libc::F_DUPFD_CLOEXEC, 0) };
+        if dup_fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { File::from_raw_fd(dup_fd) })
+    })
+}
+
+/// Checks that the file at `path` exists, or that the file descriptor it names is inherited and
+/// open if it is a `fd://<fd>` URI, without opening nor duplicating anything.
+pub fn check(path: &str) -> io::Result<()> {
+    match parse(path) {
+        // Safe because `F_GETFD` only reads the flags of the descriptor.
+        Some(fd) => match unsafe { libc::fcntl(fd?, libc::F_GETFD) } {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        },
+        None => std::fs::metadata(path).map(|_| ()),
+    }
+}
+
+/// Opens the file at `path` read-only, or duplicates the file descriptor it names if it is a
+/// `fd://<fd>` URI.
+pub fn open(path: &str) -> io::Result<File> {
+    dup(path).unwrap_or_else(|| File::open(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::os::unix::io::AsRawFd;
+
+    use super::*;
+    use crate::tempfile::TempFile;
+
+    #[test]
+    fn test_parse() {
+        assert!(parse("/path/to/rootfs").is_none());
+        assert!(parse("fd:/3").is_none());
+        for uri in &["fd://", "fd://2", "fd://-1", "fd://3/rootfs"] {
+            assert_eq!(
+                parse(uri).unwrap().unwrap_err().kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+        assert_eq!(
+            parse("fd://x").unwrap().unwrap_err().to_string(),
+            "Invalid file descriptor URI fd://x."
+        );
+
+        // Only the descriptors open when they are recorded can be named.
+        let tmp_file = TempFile::new().unwrap();
+        record_inherited_fds().unwrap();
+        let fd = tmp_file.as_file().as_raw_fd();
+        assert_eq!(parse(&format!("fd://{}", fd)).unwrap().unwrap(), fd);
+        let err = parse("fd://1000000").unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(
+            err.to_string(),
+            "The file descriptor 1000000 was not inherited by the process."
+        );
+    }
+
+    #[test]
+    fn test_open() {
+        let tmp_file = TempFile::new().unwrap();
+        tmp_file.as_file().write_all(b"kernel").unwrap();
+        record_inherited_fds().unwrap();
+        let uri = format!("fd://{}", tmp_file.as_file().as_raw_fd());
+
+        let mut file = open(&uri).unwrap();
+        assert_ne!(file.as_raw_fd(), tmp_file.as_file().as_raw_fd());
+        let mut content = String::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "kernel");
+        // Only the duplicate is closed.
+        drop(file);
+        assert!(dup(&uri).unwrap().is_ok());
+
+        let mut content = String::new();
+        open(tmp_file.as_path().to_str().unwrap())
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "kernel");
+
+        assert_eq!(
+            open("fd://1000000").unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert!(open("/invalid/path").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let tmp_file = TempFile::new().unwrap();
+        record_inherited_fds().unwrap();
+        check(tmp_file.as_path().to_str().unwrap()).unwrap();
+        check(&format!("fd://{}", tmp_file.as_file().as_raw_fd())).unwrap();
+
+        assert_eq!(
+            check("fd://1000000").unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert_eq!(
+            check("fd://x").unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(check("/invalid/path").is_err());
+    }
+}
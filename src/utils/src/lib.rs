@@ -4,13 +4,16 @@
 #[macro_use]
 extern crate bitflags;
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate vmm_sys_util;
 
-pub use vmm_sys_util::{errno, eventfd, ioctl, tempdir, tempfile, terminal};
+pub use vmm_sys_util::{errno, eventfd, ioctl, sock_ctrl_msg, tempdir, tempfile, terminal};
 
 pub mod arg_parser;
 pub mod byte_order;
 pub mod epoll;
+pub mod fd_uri;
 pub mod net;
 pub mod rand;
 pub mod signal;
@@ -99,7 +99,7 @@ impl Tap {
     /// Tap::open_named("doc-test-tap").unwrap();
     /// ```
     pub fn open_named(if_name: &str) -> Result<Tap> {
-        let terminated_if_name = build_terminated_if_name(if_name)?;
+        build_terminated_if_name(if_name)?;
 
         let fd = unsafe {
             // Open calls are safe because we give a constant null-terminated
@@ -115,6 +115,28 @@ impl Tap {
 
         // We just checked that the fd is valid.
         let tuntap = unsafe { File::from_raw_fd(fd) };
+        Self::attach(tuntap, if_name)
+    }
+
+    /// Create a TUN/TAP device given the interface name, on `tuntap`, a file description of
+    /// `/dev/net/tun` opened by the caller. This lets a process without access to `/dev/net/tun`
+    /// use the file descriptor passed to it by another process. `tuntap` is made non-blocking.
+    /// # Arguments
+    ///
+    /// * `tuntap` - `/dev/net/tun`, opened for reading and writing.
+    /// * `if_name` - the name of the interface.
+    pub fn attach(tuntap: File, if_name: &str) -> Result<Tap> {
+        let terminated_if_name = build_terminated_if_name(if_name)?;
+
+        // Safe because the fcntls only change the flags of the file description, and we check
+        // their return values.
+        let flags = unsafe { libc::fcntl(tuntap.as_raw_fd(), libc::F_GETFL) };
+        if flags < 0
+            || unsafe { libc::fcntl(tuntap.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) }
+                < 0
+        {
+            return Err(Error::IoctlError(IoError::last_os_error()));
+        }
 
         // This is pretty messy because of the unions used by ifreq. Since we
         // don't call as_mut on the same union field more than once, this block
@@ -143,6 +165,16 @@ impl Tap {
         })
     }
 
+    /// Returns the name of the interface.
+    pub fn if_name(&self) -> String {
+        let len = self
+            .if_name
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(IFACE_NAME_MAX_LEN);
+        String::from_utf8_lossy(&self.if_name[..len]).into_owned()
+    }
+
     /// Set the offload flags for the tap interface.
     pub fn set_offload(&self, flags: c_uint) -> Result<()> {
         // ioctl is safe. Called with a valid tap fd, and we check the return.
@@ -177,6 +209,50 @@ impl Tap {
         Ok(())
     }
 
+    /// Get the MTU of the tap interface.
+    pub fn mtu(&self) -> Result<c_int> {
+        let sock = create_socket()?;
+
+        let mut ifreq = self.get_ifreq();
+
+        // ioctl is safe. Called with a valid sock fd, and we check the return.
+        let ret = unsafe {
+            ioctl_with_mut_ref(
+                &sock,
+                c_ulong::from(net_gen::sockios::SIOCGIFMTU),
+                &mut ifreq,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::IoctlError(IoError::last_os_error()));
+        }
+
+        // We only access one field of the ifru union, hence this is safe.
+        Ok(unsafe { *ifreq.ifr_ifru.ifru_mtu.as_ref() })
+    }
+
+    /// Set the MTU of the tap interface. This needs `CAP_NET_ADMIN`.
+    pub fn set_mtu(&self, mtu: c_int) -> Result<()> {
+        let sock = create_socket()?;
+
+        let mut ifreq = self.get_ifreq();
+
+        // We only access one field of the ifru union, hence this is safe.
+        unsafe {
+            let ifru_mtu = ifreq.ifr_ifru.ifru_mtu.as_mut();
+            *ifru_mtu = mtu;
+        }
+
+        // ioctl is safe. Called with a valid sock fd, and we check the return.
+        let ret =
+            unsafe { ioctl_with_ref(&sock, c_ulong::from(net_gen::sockios::SIOCSIFMTU), &ifreq) };
+        if ret < 0 {
+            return Err(Error::IoctlError(IoError::last_os_error()));
+        }
+
+        Ok(())
+    }
+
     /// Set the size of the vnet hdr.
     pub fn set_vnet_hdr_size(&self, size: c_int) -> Result<()> {
         // ioctl is safe. Called with a valid tap fd, and we check the return.
@@ -366,6 +442,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_tap_attach() {
+        let tun = || {
+            std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("/dev/net/tun")
+                .unwrap()
+        };
+        let tap = Tap::attach(tun(), "attachedtap").unwrap();
+        assert_eq!(tap.if_name(), "attachedtap");
+        assert_eq!(tap_name_to_string(&tap), tap.if_name());
+        // The file description is made non-blocking.
+        let flags = unsafe { libc::fcntl(tap.as_raw_fd(), libc::F_GETFL) };
+        assert_ne!(flags & libc::O_NONBLOCK, 0);
+
+        match Tap::attach(tun(), "a123456789abcdef") {
+            Err(Error::InvalidIfname) => (),
+            _ => panic!("Expected Error::InvalidIfname"),
+        };
+        // The file has to be `/dev/net/tun`.
+        match Tap::attach(File::open("/dev/null").unwrap(), "attachedtap2") {
+            Err(Error::CreateTap(_)) => (),
+            _ => panic!("Expected Error::CreateTap"),
+        };
+    }
+
     #[test]
     fn test_tap_exclusive_open() {
         let _tap1 = Tap::open_named("exclusivetap").unwrap();
@@ -399,6 +502,8 @@ mod tests {
         let tap = Tap::new().unwrap();
         tap.set_vnet_hdr_size(16).unwrap();
         tap.set_offload(0).unwrap();
+        tap.set_mtu(9000).unwrap();
+        assert_eq!(tap.mtu().unwrap(), 9000);
 
         let faulty_tap = Tap {
             tap_file: unsafe { File::from_raw_fd(-1) },
@@ -406,6 +511,8 @@ mod tests {
         };
         assert!(faulty_tap.set_vnet_hdr_size(16).is_err());
         assert!(faulty_tap.set_offload(0).is_err());
+        assert!(faulty_tap.set_mtu(9000).is_err());
+        assert!(faulty_tap.mtu().is_err());
     }
 
     #[test]
@@ -0,0 +1,108 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Lets the embedders of the VMM decide which actions are carried out, e.g. to restrict the
+//! operations the tenant behind an API socket may perform, without changing how the actions are
+//! dispatched.
+
+use std::result;
+
+use rpc_interface::{VmmAction, VmmActionError};
+
+/// What to do with an action.
+#[derive(PartialEq)]
+pub enum ActionDecision {
+    /// Carry out the action as it is.
+    Allow,
+    /// Reject the action, for the given reason.
+    Deny(String),
+    /// Carry out the given action instead.
+    Modify(VmmAction),
+}
+
+/// Consulted by the API controllers before carrying out each action, both before and after
+/// booting the microVM.
+pub trait ActionPolicy: Send + Sync {
+    /// Decides what to do with `action`.
+    ///
+    /// The actions carrying an idempotency key are only checked once unwrapped, so `action` is
+    /// never `VmmAction::Idempotent`.
+    fn authorize(&self, action: &VmmAction) -> ActionDecision;
+}
+
+/// The default policy, which allows all the actions.
+#[derive(Debug, Default)]
+pub struct AllowAll;
+
+impl ActionPolicy for AllowAll {
+    fn authorize(&self, _action: &VmmAction) -> ActionDecision {
+        ActionDecision::Allow
+    }
+}
+
+/// Returns the action to carry out in place of `action`, as decided by `policy`.
+pub(crate) fn apply(
+    policy: &dyn ActionPolicy,
+    action: VmmAction,
+) -> result::Result<VmmAction, VmmActionError> {
+    if let VmmAction::Idempotent(_, _) = action {
+        return Ok(action);
+    }
+    match policy.authorize(&action) {
+        ActionDecision::Allow => Ok(action),
+        ActionDecision::Deny(reason) => Err(VmmActionError::ActionDenied(reason)),
+        ActionDecision::Modify(action) => Ok(action),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vmm_config::machine_config::VmConfig;
+
+    // Denies pausing the microVM, and caps its vCPU count to 2.
+    struct TenantPolicy;
+
+    impl ActionPolicy for TenantPolicy {
+        fn authorize(&self, action: &VmmAction) -> ActionDecision {
+            match action {
+                VmmAction::Pause => ActionDecision::Deny("Pausing is not allowed.".to_string()),
+                VmmAction::SetVmConfiguration(config) if config.vcpu_count > Some(2) => {
+                    ActionDecision::Modify(VmmAction::SetVmConfiguration(VmConfig {
+                        vcpu_count: Some(2),
+                        ..config.clone()
+                    }))
+                }
+                _ => ActionDecision::Allow,
+            }
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        assert!(apply(&AllowAll, VmmAction::Pause).unwrap() == VmmAction::Pause);
+
+        let policy = TenantPolicy;
+        assert!(apply(&policy, VmmAction::Resume).unwrap() == VmmAction::Resume);
+        match apply(&policy, VmmAction::Pause) {
+            Err(VmmActionError::ActionDenied(reason)) => {
+                assert_eq!(reason, "Pausing is not allowed.")
+            }
+            _ => panic!("Unexpected result"),
+        }
+
+        let config = VmConfig {
+            vcpu_count: Some(4),
+            ..Default::default()
+        };
+        match apply(&policy, VmmAction::SetVmConfiguration(config)) {
+            Ok(VmmAction::SetVmConfiguration(config)) => assert_eq!(config.vcpu_count, Some(2)),
+            _ => panic!("Unexpected result"),
+        }
+
+        // The wrapped action is only checked once unwrapped.
+        let action = VmmAction::Idempotent("key".to_string(), Box::new(VmmAction::Pause));
+        assert!(apply(&policy, action).is_ok());
+    }
+}
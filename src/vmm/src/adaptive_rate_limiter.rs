@@ -0,0 +1,647 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Adjusts the rate limiters of the network interfaces and drives to the pressure on the host.
+//!
+//! The pressure signal is sampled on the VMM thread, every time the timer of the policy expires.
+//! The limits the control plane set, at boot time or through `PATCH` requests, are the baseline:
+//! under pressure the rate limiters are scaled down from it, and scaled back up once the pressure
+//! is gone. An update of the control plane is noticed at the next adjustment, and becomes the new
+//! baseline.
+
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use devices::virtio::{Block, Net};
+use logger::{Metric, METRICS};
+use polly::event_manager::{EventManager, Subscriber};
+use rate_limiter::{RateLimiter, TokenBucket};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::{EpollEvent, EventSet};
+use vmm_config::rate_limit_policy::{PressureSignal, RateLimitPolicyConfig};
+
+/// Errors associated with the adaptive rate limiting.
+#[derive(Debug)]
+pub enum AdaptiveRateLimiterError {
+    /// Cannot read the pressure signal of the host.
+    Signal(PathBuf, io::Error),
+    /// Cannot create the timer of the policy.
+    TimerFd(io::Error),
+}
+
+impl Display for AdaptiveRateLimiterError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::AdaptiveRateLimiterError::*;
+        match self {
+            Signal(path, err) => write!(
+                f,
+                "Cannot read the pressure signal from {}: {}",
+                path.display(),
+                err
+            ),
+            TimerFd(err) => write!(f, "Cannot create the rate limiting policy timer: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for AdaptiveRateLimiterError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use self::AdaptiveRateLimiterError::*;
+        match self {
+            Signal(_, err) => Some(err),
+            TimerFd(err) => Some(err),
+        }
+    }
+}
+
+// Returns the share of time in percent some tasks stalled over the last 10 seconds, out of the
+// content of a `/proc/pressure` file.
+fn parse_psi(content: &str) -> Option<f64> {
+    content
+        .lines()
+        .find(|line| line.starts_with("some "))?
+        .split_whitespace()
+        .find(|field| field.starts_with("avg10="))?["avg10=".len()..]
+        .parse()
+        .ok()
+}
+
+// Returns the number of requests a block device completed, and the time in milliseconds they
+// took, out of the content of its `/sys/block/<device>/stat` file.
+fn parse_disk_stat(content: &str) -> Option<(u64, u64)> {
+    let fields = content
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<std::result::Result<Vec<u64>, _>>()
+        .ok()?;
+    if fields.len() < 8 {
+        return None;
+    }
+    // Reads completed, time spent reading, writes completed, time spent writing.
+    Some((fields[0] + fields[4], fields[3] + fields[7]))
+}
+
+// The signals are small procfs and sysfs files.
+const MAX_SIGNAL_SIZE: usize = 1024;
+
+// Reads a signal file with `read` alone, so that the seccomp filter doesn't need to allow the
+// syscalls which retrieve the size of the file.
+fn read_signal(path: &PathBuf) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut buf = [0u8; MAX_SIGNAL_SIZE];
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..])? {
+            0 => break,
+            count => len += count,
+        }
+    }
+    String::from_utf8(buf[..len].to_vec())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+// Reads the pressure signal of the host.
+enum Sampler {
+    Psi(PathBuf),
+    // The counters of the device, as of the previous sample.
+    DiskLatency {
+        path: PathBuf,
+        last: Option<(u64, u64)>,
+    },
+}
+
+impl Sampler {
+    fn new(signal: &PressureSignal) -> Self {
+        let path = signal.path();
+        match signal {
+            PressureSignal::Psi { .. } => Sampler::Psi(path),
+            PressureSignal::DiskLatency { .. } => Sampler::DiskLatency { path, last: None },
+        }
+    }
+
+    fn path(&self) -> &PathBuf {
+        match self {
+            Sampler::Psi(path) => path,
+            Sampler::DiskLatency { path, .. } => path,
+        }
+    }
+
+    // Returns the current value of the signal, or `None` when there is none yet.
+    fn sample(&mut self) -> io::Result<Option<f64>> {
+        let content = read_signal(self.path())?;
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, "Malformed signal");
+        match self {
+            Sampler::Psi(_) => parse_psi(&content).map(Some).ok_or_else(malformed),
+            Sampler::DiskLatency { last, .. } => {
+                let (requests, time_ms) = parse_disk_stat(&content).ok_or_else(malformed)?;
+                let latency_us = last.map(|(last_requests, last_time_ms)| {
+                    let requests = requests.saturating_sub(last_requests);
+                    if requests == 0 {
+                        return 0.0;
+                    }
+                    time_ms.saturating_sub(last_time_ms) as f64 * 1000.0 / requests as f64
+                });
+                *last = Some((requests, time_ms));
+                Ok(latency_us)
+            }
+        }
+    }
+}
+
+// The share of the baseline limits the rate limiters are scaled to, in percent.
+struct Scale {
+    percent: u64,
+    high_watermark: f64,
+    low_watermark: f64,
+    decrease_percent: u64,
+    increase_percent: u64,
+    floor_percent: u64,
+}
+
+impl Scale {
+    fn new(config: &RateLimitPolicyConfig) -> Self {
+        Scale {
+            percent: 100,
+            high_watermark: config.high_watermark as f64,
+            low_watermark: config.low_watermark as f64,
+            decrease_percent: u64::from(config.decrease_percent),
+            increase_percent: u64::from(config.increase_percent),
+            floor_percent: u64::from(config.floor_percent),
+        }
+    }
+
+    // Lowers the scale multiplicatively under pressure, and raises it additively otherwise, so
+    // that the limits back off quickly and recover gradually. Returns whether the scale changed.
+    fn adjust(&mut self, pressure: f64) -> bool {
+        let percent = if pressure > self.high_watermark {
+            std::cmp::max(
+                self.percent * (100 - self.decrease_percent) / 100,
+                self.floor_percent,
+            )
+        } else if pressure < self.low_watermark {
+            std::cmp::min(self.percent + self.increase_percent, 100)
+        } else {
+            self.percent
+        };
+        let changed = percent != self.percent;
+        self.percent = percent;
+        changed
+    }
+
+    fn apply(&self, size: u64) -> u64 {
+        // A bucket of size 0 would disable the limit altogether.
+        std::cmp::max(
+            (u128::from(size) * u128::from(self.percent) / 100) as u64,
+            1,
+        )
+    }
+}
+
+// The size and the refill time of a token bucket.
+type Limit = Option<(u64, u64)>;
+
+fn limit(bucket: Option<&TokenBucket>) -> Limit {
+    bucket.map(|bucket| (bucket.capacity(), bucket.refill_time_ms()))
+}
+
+fn limits(rate_limiter: &RateLimiter) -> [Limit; 2] {
+    [limit(rate_limiter.bandwidth()), limit(rate_limiter.ops())]
+}
+
+enum Device {
+    Net(Arc<Mutex<Net>>),
+    Block(Arc<Mutex<Block>>),
+}
+
+// A device whose rate limiters are adjusted.
+struct GovernedDevice {
+    device: Device,
+    // The limits of the control plane: bandwidth then ops, of the RX then TX rate limiter of the
+    // network interfaces.
+    baseline: Vec<Limit>,
+    // The limits set by the previous adjustment.
+    applied: Vec<Limit>,
+}
+
+impl GovernedDevice {
+    fn new(device: Device) -> Self {
+        let baseline = Self::current(&device);
+        GovernedDevice {
+            device,
+            applied: baseline.clone(),
+            baseline,
+        }
+    }
+
+    // Returns the limits the device currently enforces.
+    fn current(device: &Device) -> Vec<Limit> {
+        match device {
+            Device::Net(net) => {
+                let net = net.lock().expect("Poisoned lock");
+                let mut current = limits(net.rx_rate_limiter()).to_vec();
+                current.extend_from_slice(&limits(net.tx_rate_limiter()));
+                current
+            }
+            Device::Block(block) => {
+                limits(block.lock().expect("Poisoned lock").rate_limiter()).to_vec()
+            }
+        }
+    }
+
+    // Scales the rate limiters of the device to `scale` of the baseline. The limits which differ
+    // from the previous adjustment were updated by the control plane, and are the new baseline.
+    fn adjust(&mut self, scale: &Scale) {
+        let current = Self::current(&self.device);
+        for (index, limit) in current.into_iter().enumerate() {
+            if limit != self.applied[index] {
+                self.baseline[index] = limit;
+                self.applied[index] = limit;
+            }
+        }
+
+        let mut updated = false;
+        let buckets = self
+            .baseline
+            .iter()
+            .zip(self.applied.iter_mut())
+            .map(|(baseline, applied)| {
+                let target =
+                    baseline.map(|(size, refill_time_ms)| (scale.apply(size), refill_time_ms));
+                if target == *applied {
+                    return None;
+                }
+                updated = true;
+                *applied = target;
+                target.map(|(size, refill_time_ms)| TokenBucket::new(size, None, refill_time_ms))
+            })
+            .collect::<Vec<_>>();
+        if !updated {
+            return;
+        }
+
+        match &self.device {
+            Device::Net(net) => net.lock().expect("Poisoned lock").patch_rate_limiters(
+                buckets[0].clone(),
+                buckets[1].clone(),
+                buckets[2].clone(),
+                buckets[3].clone(),
+            ),
+            Device::Block(block) => block
+                .lock()
+                .expect("Poisoned lock")
+                .update_rate_limiter(buckets[0].clone(), buckets[1].clone()),
+        }
+    }
+}
+
+/// Adjusts the rate limiters of the devices of a microVM to the pressure on the host.
+pub struct AdaptiveRateLimiter {
+    sampler: Sampler,
+    scale: Scale,
+    devices: Vec<GovernedDevice>,
+    timer_fd: TimerFd,
+}
+
+impl AdaptiveRateLimiter {
+    /// Creates the controller of the rate limiters of `nets` and `blocks`, which starts adjusting
+    /// them after a period of the policy.
+    pub fn new<'a, N, B>(
+        config: &RateLimitPolicyConfig,
+        nets: N,
+        blocks: B,
+    ) -> Result<Self, AdaptiveRateLimiterError>
+    where
+        N: Iterator<Item = &'a Arc<Mutex<Net>>>,
+        B: Iterator<Item = &'a Arc<Mutex<Block>>>,
+    {
+        Self::with_sampler(config, Sampler::new(&config.signal), nets, blocks)
+    }
+
+    fn with_sampler<'a, N, B>(
+        config: &RateLimitPolicyConfig,
+        mut sampler: Sampler,
+        nets: N,
+        blocks: B,
+    ) -> Result<Self, AdaptiveRateLimiterError>
+    where
+        N: Iterator<Item = &'a Arc<Mutex<Net>>>,
+        B: Iterator<Item = &'a Arc<Mutex<Block>>>,
+    {
+        // Fail early on hosts which don't expose the signal.
+        sampler
+            .sample()
+            .map_err(|err| AdaptiveRateLimiterError::Signal(sampler.path().clone(), err))?;
+
+        let mut timer_fd = TimerFd::new_custom(ClockId::Monotonic, true, true)
+            .map_err(AdaptiveRateLimiterError::TimerFd)?;
+        let period = Duration::from_millis(config.period_ms);
+        timer_fd.set_state(
+            TimerState::Periodic {
+                current: period,
+                interval: period,
+            },
+            SetTimeFlags::Default,
+        );
+
+        let devices = nets
+            .map(|net| GovernedDevice::new(Device::Net(net.clone())))
+            .chain(blocks.map(|block| GovernedDevice::new(Device::Block(block.clone()))))
+            .collect();
+        Ok(AdaptiveRateLimiter {
+            sampler,
+            scale: Scale::new(config),
+            devices,
+            timer_fd,
+        })
+    }
+
+    fn adjust(&mut self) {
+        match self.sampler.sample() {
+            Ok(Some(pressure)) => {
+                let percent = self.scale.percent;
+                if self.scale.adjust(pressure) {
+                    info!(
+                        "Host pressure at {:.2}, scaling the rate limiters to {}% of their limits.",
+                        pressure, self.scale.percent
+                    );
+                    if self.scale.percent < percent {
+                        METRICS.rate_limiter.throttle_count.inc();
+                    } else {
+                        METRICS.rate_limiter.release_count.inc();
+                    }
+                }
+            }
+            Ok(None) => (),
+            Err(err) => {
+                warn!(
+                    "Cannot read the pressure signal from {}: {}",
+                    self.sampler.path().display(),
+                    err
+                );
+                METRICS.rate_limiter.pressure_sample_fails.inc();
+            }
+        }
+
+        for device in self.devices.iter_mut() {
+            device.adjust(&self.scale);
+        }
+    }
+}
+
+impl Subscriber for AdaptiveRateLimiter {
+    /// Handle a read event (EPOLLIN).
+    fn process(&mut self, event: &EpollEvent, _: &mut EventManager) {
+        let source = event.fd();
+        let event_set = event.event_set();
+
+        let supported_events = EventSet::IN;
+        if !supported_events.contains(event_set) {
+            warn!(
+                "Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
+
+        if source == self.timer_fd.as_raw_fd() {
+            self.timer_fd.read();
+            self.adjust();
+        } else {
+            error!("Spurious rate limiting policy event!");
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        vec![EpollEvent::new(
+            EventSet::IN,
+            self.timer_fd.as_raw_fd() as u64,
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+    use std::io::Write;
+
+    use utils::tempfile::TempFile;
+    use vmm_config::drive::{BlockBuilder, BlockDeviceConfig, EnospcPolicy};
+    use vmm_config::rate_limit_policy::PsiResource;
+    use vmm_config::{Identifier, RateLimiterConfig, TokenBucketConfig};
+
+    fn policy() -> RateLimitPolicyConfig {
+        RateLimitPolicyConfig {
+            signal: PressureSignal::Psi {
+                resource: PsiResource::Io,
+            },
+            high_watermark: 40,
+            low_watermark: 10,
+            period_ms: 1000,
+            decrease_percent: 50,
+            increase_percent: 20,
+            floor_percent: 20,
+        }
+    }
+
+    fn write_signal(file: &TempFile, content: &str) {
+        let mut file = File::create(file.as_path()).unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+    }
+
+    fn psi(avg10: &str) -> String {
+        format!(
+            "some avg10={} avg60=1.00 avg300=0.50 total=123456\n\
+             full avg10=0.00 avg60=0.00 avg300=0.00 total=1234\n",
+            avg10
+        )
+    }
+
+    #[test]
+    fn test_parse_signals() {
+        assert_eq!(parse_psi(&psi("12.34")), Some(12.34));
+        // The CPU pressure has no `full` line on older kernels.
+        assert_eq!(
+            parse_psi("some avg10=0.50 avg60=0.00 avg300=0.00 total=0\n"),
+            Some(0.5)
+        );
+        assert_eq!(parse_psi("full avg10=1.00 avg60=0.00 avg300=0.00\n"), None);
+        assert_eq!(parse_psi("some avg10=x\n"), None);
+
+        assert_eq!(
+            parse_disk_stat("  100 0 800 30  50 0 400 20  0 40 50 0 0 0 0\n"),
+            Some((150, 50))
+        );
+        assert_eq!(parse_disk_stat("100 0 800 30\n"), None);
+        assert_eq!(parse_disk_stat("100 0 800 x 50 0 400 20\n"), None);
+    }
+
+    #[test]
+    fn test_disk_latency_sampler() {
+        let file = TempFile::new().unwrap();
+        let mut sampler = Sampler::DiskLatency {
+            path: file.as_path().to_path_buf(),
+            last: None,
+        };
+
+        write_signal(&file, "100 0 800 30 50 0 400 20 0 40 50\n");
+        // There is no latency before the first period.
+        assert_eq!(sampler.sample().unwrap(), None);
+        // 10 requests completed in 25 ms.
+        write_signal(&file, "104 0 832 40 56 0 448 35 0 60 75\n");
+        assert_eq!(sampler.sample().unwrap(), Some(2500.0));
+        // No request completed.
+        assert_eq!(sampler.sample().unwrap(), Some(0.0));
+
+        write_signal(&file, "malformed\n");
+        assert!(sampler.sample().is_err());
+    }
+
+    #[test]
+    fn test_scale() {
+        let mut scale = Scale::new(&policy());
+        assert_eq!(scale.apply(1000), 1000);
+
+        // Between the watermarks, the scale doesn't change.
+        assert!(!scale.adjust(20.0));
+        // Above the high watermark, the limits are halved, down to the floor.
+        assert!(scale.adjust(50.0));
+        assert_eq!(scale.percent, 50);
+        assert!(scale.adjust(50.0));
+        assert_eq!(scale.percent, 25);
+        assert!(scale.adjust(50.0));
+        assert_eq!(scale.percent, 20);
+        assert!(!scale.adjust(50.0));
+        assert_eq!(scale.apply(1000), 200);
+        assert_eq!(scale.apply(1), 1);
+        assert_eq!(scale.apply(u64::max_value()), u64::max_value() / 5);
+
+        // Below the low watermark, the limits are raised back.
+        assert!(!scale.adjust(10.0));
+        for percent in &[40, 60, 80, 100] {
+            assert!(scale.adjust(5.0));
+            assert_eq!(scale.percent, *percent);
+        }
+        assert!(!scale.adjust(5.0));
+    }
+
+    #[test]
+    fn test_adjust_rate_limiters() {
+        let drive_file = TempFile::new().unwrap();
+        let mut blocks = BlockBuilder::new();
+        for (drive_id, rate_limiter) in &[
+            (
+                "limited",
+                Some(RateLimiterConfig {
+                    bandwidth: Some(TokenBucketConfig {
+                        size: 1000,
+                        one_time_burst: Some(5000),
+                        refill_time: 100,
+                    }),
+                    ops: None,
+                }),
+            ),
+            ("unlimited", None),
+        ] {
+            blocks
+                .insert(BlockDeviceConfig {
+                    drive_id: Identifier::try_from(*drive_id).unwrap(),
+                    path_on_host: drive_file.as_path().to_str().unwrap().to_string(),
+                    is_root_device: false,
+                    partuuid: None,
+                    is_read_only: true,
+                    no_atime: false,
+                    rate_limiter: *rate_limiter,
+                    encryption: None,
+                    verity: None,
+                    io_weight: None,
+                    serial: None,
+                    physical_block_size: None,
+                    on_enospc: EnospcPolicy::Error,
+                    page_cache: None,
+                })
+                .unwrap();
+        }
+
+        let signal_file = TempFile::new().unwrap();
+        let sampler = || Sampler::Psi(signal_file.as_path().to_path_buf());
+        let nets = std::iter::empty;
+        assert!(AdaptiveRateLimiter::with_sampler(
+            &policy(),
+            sampler(),
+            nets(),
+            blocks.list.iter()
+        )
+        .is_err());
+        write_signal(&signal_file, &psi("0.00"));
+        let mut controller =
+            AdaptiveRateLimiter::with_sampler(&policy(), sampler(), nets(), blocks.list.iter())
+                .unwrap();
+        assert_eq!(controller.interest_list().len(), 1);
+
+        let bandwidth = |index: usize| {
+            blocks.list[index]
+                .lock()
+                .unwrap()
+                .rate_limiter()
+                .bandwidth()
+                .map(|bucket| (bucket.capacity(), bucket.one_time_burst()))
+        };
+        // Without pressure, the limits are left alone.
+        controller.adjust();
+        assert_eq!(bandwidth(0), Some((1000, 5000)));
+
+        let throttle_count = METRICS.rate_limiter.throttle_count.count();
+        write_signal(&signal_file, &psi("75.00"));
+        controller.adjust();
+        assert_eq!(bandwidth(0), Some((500, 0)));
+        assert_eq!(bandwidth(1), None);
+        assert_eq!(
+            METRICS.rate_limiter.throttle_count.count(),
+            throttle_count + 1
+        );
+
+        // The control plane updates the limit, which is scaled from now on.
+        blocks.list[0]
+            .lock()
+            .unwrap()
+            .update_rate_limiter(Some(TokenBucket::new(2000, None, 100)), None);
+        controller.adjust();
+        assert_eq!(bandwidth(0), Some((500, 0)));
+        controller.adjust();
+        assert_eq!(bandwidth(0), Some((400, 0)));
+
+        write_signal(&signal_file, &psi("1.00"));
+        for _ in 0..4 {
+            controller.adjust();
+        }
+        assert_eq!(bandwidth(0), Some((2000, 0)));
+
+        // A signal which cannot be read leaves the limits alone.
+        let sample_fails = METRICS.rate_limiter.pressure_sample_fails.count();
+        write_signal(&signal_file, "malformed");
+        controller.adjust();
+        assert_eq!(bandwidth(0), Some((2000, 0)));
+        assert_eq!(
+            METRICS.rate_limiter.pressure_sample_fails.count(),
+            sample_fails + 1
+        );
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let err = AdaptiveRateLimiterError::Signal(
+            PathBuf::from("/proc/pressure/io"),
+            io::Error::from_raw_os_error(libc::ENOENT),
+        );
+        let _ = format!("{}{:?}", err, err);
+        let err = AdaptiveRateLimiterError::TimerFd(io::Error::from_raw_os_error(libc::EMFILE));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
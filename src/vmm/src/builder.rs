@@ -3,29 +3,79 @@
 
 //! Enables pre-boot setup, instantiation and booting of a Firecracker VMM.
 
+use std::collections::BTreeSet;
 use std::fmt::{Display, Formatter};
-use std::io::{self, Read, Seek, SeekFrom};
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::net::Ipv4Addr;
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex};
 
-use super::{Error, Vmm};
+use super::{Error, FcExitCode, Vmm};
 
+use adaptive_rate_limiter::{AdaptiveRateLimiter, AdaptiveRateLimiterError};
+#[cfg(target_arch = "aarch64")]
+use arch::aarch64::gic::GICConfig;
 use arch::InitrdConfig;
+use cloud_init::{self, CloudInitError, SEED_DISK_IMAGE_PATH, SEED_DRIVE_ID};
+use console::{self, ConsoleSocket};
 #[cfg(target_arch = "x86_64")]
 use device_manager::legacy::PortIODeviceManager;
-use device_manager::mmio::MMIODeviceManager;
+use device_manager::mmio::{virtio_block_device_name, MMIODeviceManager};
+#[cfg(target_arch = "x86_64")]
+use device_manager::pci::PciDeviceManager;
+use device_thread::{self, DeviceThreadError};
 use devices::legacy::Serial;
-use devices::virtio::{MmioTransport, Vsock, VsockUnixBackend};
-
+#[cfg(target_arch = "x86_64")]
+use devices::virtio::{
+    balloon::persist::BalloonConstructorArgs, block::persist::BlockConstructorArgs,
+    net::persist::NetConstructorArgs, persist::MmioTransportConstructorArgs,
+    persist::MmioTransportState, rng::persist::EntropyConstructorArgs,
+    vsock::persist::VsockBackendState, vsock::persist::VsockConstructorArgs,
+    vsock::persist::VsockUdsConstructorArgs, Net, VirtioDevice,
+};
+use devices::virtio::{
+    dirty_pages, Balloon, Block, BlockEvent, EnospcPolicy, Entropy, Gpu, Input, MmioTransport,
+    Sound, VhostVsock, Vsock, VsockUnixBackend,
+};
+use dumbo::ns::MmdsNetworkStack;
+use error_code::ErrorCategory;
+
+use events::EventChannel;
+#[cfg(target_arch = "x86_64")]
+use kernel::cmdline::Cmdline as KernelCmdline;
+use memory_snapshot::{create_memfd_guest_memory, Error as MemorySnapshotError};
+use operations::Operations;
+#[cfg(target_arch = "x86_64")]
+use persist::{DeviceStates, MicrovmState, MicrovmStateError, VmmResourcesState};
+#[cfg(target_arch = "x86_64")]
+use polly::event_manager::Subscriber;
 use polly::event_manager::{Error as EventManagerError, EventManager};
+use probes::{ProbeError, ProbeRunner, ProbeStatuses};
+use resources::ConfigIssues;
 use seccomp::BpfProgramRef;
+#[cfg(feature = "sev")]
+use sev::{self, SevLauncher};
+#[cfg(target_arch = "x86_64")]
+use snapshot::Persist;
 use utils::eventfd::EventFd;
-use utils::terminal::Terminal;
 use utils::time::TimestampUs;
 use vm_memory::{Bytes, GuestAddress, GuestMemoryMmap};
-use vmm_config::boot_source::BootConfig;
+use vmm_config::boot_source::{fill_boot_args, BootArgsVariable, BootConfig, KernelImage};
+use vmm_config::cloud_init::{CloudInitConfig, CloudInitTransport};
+use vmm_config::console::{ConsoleConfig, ConsoleInput};
 use vmm_config::drive::BlockBuilder;
+use vmm_config::machine_config::{LegacyDevicesConfig, VmConfig};
+use vmm_config::mmds::MmdsConfig;
 use vmm_config::net::NetBuilder;
+use vmm_config::preopened_fds::PreopenedFds;
+#[cfg(target_arch = "aarch64")]
+use vmm_config::rtc::RtcConfig;
+#[cfg(feature = "sev")]
+use vmm_config::sev::SevConfig;
+#[cfg(target_arch = "x86_64")]
+use vstate::VcpuState;
 use vstate::{KvmContext, Vcpu, VcpuConfig, Vm};
 use {device_manager, VmmEventsObserver};
 
@@ -34,12 +84,26 @@ use {device_manager, VmmEventsObserver};
 pub enum StartMicrovmError {
     /// Unable to attach block device to Vmm.
     AttachBlockDevice(io::Error),
+    /// Unable to pass a host PCI device through to the guest.
+    #[cfg(target_arch = "x86_64")]
+    AttachPciPassthroughDevice(device_manager::pci::Error),
+    /// Unable to share a host memory region with the guest.
+    #[cfg(target_arch = "x86_64")]
+    AttachSharedMemoryDevice(device_manager::pci::Error),
+    /// Cannot hand the cloud-init data to the guest.
+    CloudInit(CloudInitError),
+    /// Cannot listen on the socket feeding the serial console.
+    ConsoleSocket(io::Error),
+    /// Cannot create the memory file backing the guest memory.
+    CreateMemoryFile(MemorySnapshotError),
     /// Internal errors are due to resource exhaustion.
     CreateNetDevice(devices::virtio::net::Error),
     /// Failed to create a `RateLimiter` object.
     CreateRateLimiter(io::Error),
     /// Memory regions are overlapping or mmap fails.
     GuestMemoryMmap(vm_memory::Error),
+    /// The resources are valid on their own, but not together, and the configuration is strict.
+    InconsistentConfig(ConfigIssues),
     /// Cannot load initrd due to an invalid memory configuration.
     InitrdLoad,
     /// Cannot load initrd due to an invalid image.
@@ -58,18 +122,43 @@ pub enum StartMicrovmError {
     MissingKernelConfig,
     /// Cannot start the VM because the size of the guest memory  was not specified.
     MissingMemSizeConfig,
+    /// Cannot run the emulation of a net device on a thread of its own.
+    NetDeviceThread(DeviceThreadError),
     /// The net device configuration is missing the tap device.
     NetDeviceNotConfigured,
+    /// Cannot set up the probes of the workload inside the guest.
+    Probes(ProbeError),
+    /// Cannot set up the adaptive rate limiting of the devices.
+    RateLimitPolicy(AdaptiveRateLimiterError),
     /// Cannot open the block device backing file.
     OpenBlockDevice(io::Error),
+    /// Cannot initialize a MMIO Balloon Device or add a device to the MMIO Bus.
+    RegisterBalloonDevice(device_manager::mmio::Error),
     /// Cannot initialize a MMIO Block Device or add a device to the MMIO Bus.
     RegisterBlockDevice(device_manager::mmio::Error),
+    /// Cannot initialize a MMIO Entropy Device or add a device to the MMIO Bus.
+    RegisterEntropyDevice(device_manager::mmio::Error),
+    /// Cannot initialize a MMIO GPU Device or add a device to the MMIO Bus.
+    RegisterGpuDevice(device_manager::mmio::Error),
+    /// Cannot initialize a MMIO Input Device or add a device to the MMIO Bus.
+    RegisterInputDevice(device_manager::mmio::Error),
     /// Cannot register an EventHandler.
     RegisterEvent(EventManagerError),
     /// Cannot initialize a MMIO Network Device or add a device to the MMIO Bus.
     RegisterNetDevice(device_manager::mmio::Error),
+    /// Cannot initialize a MMIO Sound Device or add a device to the MMIO Bus.
+    RegisterSoundDevice(device_manager::mmio::Error),
     /// Cannot initialize a MMIO Vsock Device or add a device to the MMIO Bus.
     RegisterVsockDevice(device_manager::mmio::Error),
+    /// Cannot restore the microVM from its saved state.
+    #[cfg(target_arch = "x86_64")]
+    RestoreMicrovmState(MicrovmStateError),
+    /// Cannot launch the microVM as a SEV guest.
+    #[cfg(feature = "sev")]
+    SevLaunch(sev::Error),
+    /// The guest can't boot without a PIT, as KVM lacks the TSC deadline timer.
+    #[cfg(target_arch = "x86_64")]
+    TicklessNotSupported,
 }
 
 /// It's convenient to automatically convert `kernel::cmdline::Error`s
@@ -87,19 +176,25 @@ impl Display for StartMicrovmError {
             AttachBlockDevice(ref err) => {
                 write!(f, "Unable to attach block device to Vmm. Error: {}", err)
             }
+            #[cfg(target_arch = "x86_64")]
+            AttachPciPassthroughDevice(ref err) => {
+                write!(f, "Unable to attach a PCI passthrough device: {}", err)
+            }
+            #[cfg(target_arch = "x86_64")]
+            AttachSharedMemoryDevice(ref err) => {
+                write!(f, "Unable to attach a shared memory device: {}", err)
+            }
+            CloudInit(ref err) => write!(f, "Cannot hand the cloud-init data over: {}", err),
+            ConsoleSocket(ref err) => write!(f, "Cannot listen on the console socket: {}", err),
+            CreateMemoryFile(ref err) => write!(f, "Cannot create the guest memory: {}", err),
             CreateRateLimiter(ref err) => write!(f, "Cannot create RateLimiter: {}", err),
             CreateNetDevice(ref err) => {
-                let mut err_msg = format!("{:?}", err);
-                err_msg = err_msg.replace("\"", "");
-
-                write!(f, "Cannot create network device. {}", err_msg)
+                write!(f, "Cannot create network device. {:?}", err)
             }
             GuestMemoryMmap(ref err) => {
-                // Remove imbricated quotes from error message.
-                let mut err_msg = format!("{:?}", err);
-                err_msg = err_msg.replace("\"", "");
-                write!(f, "Invalid Memory Configuration: {}", err_msg)
+                write!(f, "Invalid Memory Configuration: {:?}", err)
             }
+            InconsistentConfig(ref issues) => write!(f, "Inconsistent configuration: {}", issues),
             InitrdLoad => write!(
                 f,
                 "Cannot load initrd due to an invalid memory configuration."
@@ -108,64 +203,171 @@ impl Display for StartMicrovmError {
             Internal(ref err) => write!(f, "Internal error while starting microVM: {:?}", err),
             KernelCmdline(ref err) => write!(f, "Invalid kernel command line: {}", err),
             KernelLoader(ref err) => {
-                let mut err_msg = format!("{}", err);
-                err_msg = err_msg.replace("\"", "");
                 write!(
                     f,
                     "Cannot load kernel due to invalid memory configuration or invalid kernel \
                      image. {}",
-                    err_msg
+                    err
                 )
             }
             LoadCommandline(ref err) => {
-                let mut err_msg = format!("{}", err);
-                err_msg = err_msg.replace("\"", "");
-                write!(f, "Cannot load command line string. {}", err_msg)
+                write!(f, "Cannot load command line string. {}", err)
             }
             MicroVMAlreadyRunning => write!(f, "Microvm already running."),
             MissingKernelConfig => write!(f, "Cannot start microvm without kernel configuration."),
             MissingMemSizeConfig => {
                 write!(f, "Cannot start microvm without guest mem_size config.")
             }
+            NetDeviceThread(ref err) => {
+                write!(f, "Cannot run the network device emulation: {}", err)
+            }
             NetDeviceNotConfigured => {
                 write!(f, "The net device configuration is missing the tap device.")
             }
+            Probes(ref err) => write!(f, "Cannot set up the probes: {}", err),
+            RateLimitPolicy(ref err) => {
+                write!(f, "Cannot set up the adaptive rate limiting: {}", err)
+            }
             OpenBlockDevice(ref err) => {
-                let mut err_msg = format!("{:?}", err);
-                err_msg = err_msg.replace("\"", "");
-
-                write!(f, "Cannot open the block device backing file. {}", err_msg)
+                write!(f, "Cannot open the block device backing file. {}", err)
+            }
+            RegisterBalloonDevice(ref err) => {
+                write!(
+                    f,
+                    "Cannot initialize a MMIO Balloon Device or add a device to the MMIO Bus. {}",
+                    err
+                )
             }
             RegisterBlockDevice(ref err) => {
-                let mut err_msg = format!("{}", err);
-                err_msg = err_msg.replace("\"", "");
                 write!(
                     f,
                     "Cannot initialize a MMIO Block Device or add a device to the MMIO Bus. {}",
-                    err_msg
+                    err
+                )
+            }
+            RegisterEntropyDevice(ref err) => {
+                write!(
+                    f,
+                    "Cannot initialize a MMIO Entropy Device or add a device to the MMIO Bus. {}",
+                    err
+                )
+            }
+            RegisterGpuDevice(ref err) => {
+                write!(
+                    f,
+                    "Cannot initialize a MMIO GPU Device or add a device to the MMIO Bus. {}",
+                    err
+                )
+            }
+            RegisterInputDevice(ref err) => {
+                write!(
+                    f,
+                    "Cannot initialize a MMIO Input Device or add a device to the MMIO Bus. {}",
+                    err
                 )
             }
             RegisterEvent(ref err) => write!(f, "Cannot register EventHandler. {:?}", err),
             RegisterNetDevice(ref err) => {
-                let mut err_msg = format!("{}", err);
-                err_msg = err_msg.replace("\"", "");
-
                 write!(
                     f,
                     "Cannot initialize a MMIO Network Device or add a device to the MMIO Bus. {}",
-                    err_msg
+                    err
+                )
+            }
+            RegisterSoundDevice(ref err) => {
+                write!(
+                    f,
+                    "Cannot initialize a MMIO Sound Device or add a device to the MMIO Bus. {}",
+                    err
                 )
             }
             RegisterVsockDevice(ref err) => {
-                let mut err_msg = format!("{}", err);
-                err_msg = err_msg.replace("\"", "");
-
                 write!(
                     f,
                     "Cannot initialize a MMIO Vsock Device or add a device to the MMIO Bus. {}",
-                    err_msg
+                    err
                 )
             }
+            #[cfg(target_arch = "x86_64")]
+            RestoreMicrovmState(ref err) => write!(f, "Cannot restore the microVM: {}", err),
+            #[cfg(feature = "sev")]
+            SevLaunch(ref err) => write!(f, "Cannot launch the SEV guest: {}", err),
+            #[cfg(target_arch = "x86_64")]
+            TicklessNotSupported => write!(
+                f,
+                "Cannot omit the PIT, as the host lacks KVM_CAP_TSC_DEADLINE_TIMER."
+            ),
+        }
+    }
+}
+
+impl StartMicrovmError {
+    /// Who can fix the cause of the error.
+    pub fn category(&self) -> ErrorCategory {
+        use self::StartMicrovmError::*;
+        match self {
+            GuestMemoryMmap(_)
+            | InconsistentConfig(_)
+            | InitrdLoad
+            | KernelCmdline(_)
+            | KernelLoader(_)
+            | LoadCommandline(_)
+            | MissingKernelConfig
+            | MissingMemSizeConfig
+            | NetDeviceNotConfigured
+            | RateLimitPolicy(_) => ErrorCategory::InvalidArgument,
+            Probes(ProbeError::Config(_)) => ErrorCategory::InvalidArgument,
+            MicroVMAlreadyRunning => ErrorCategory::InvalidState,
+            AttachBlockDevice(_) | CloudInit(_) | ConsoleSocket(_) | CreateMemoryFile(_)
+            | CreateNetDevice(_) | CreateRateLimiter(_) | InitrdRead(_) | NetDeviceThread(_)
+            | OpenBlockDevice(_) | Probes(_) => ErrorCategory::External,
+            #[cfg(target_arch = "x86_64")]
+            AttachPciPassthroughDevice(_) | AttachSharedMemoryDevice(_) | TicklessNotSupported => {
+                ErrorCategory::External
+            }
+            #[cfg(feature = "sev")]
+            SevLaunch(_) => ErrorCategory::External,
+            #[cfg(target_arch = "x86_64")]
+            RestoreMicrovmState(err) => err.category(),
+            Internal(_)
+            | RegisterBalloonDevice(_)
+            | RegisterBlockDevice(_)
+            | RegisterEntropyDevice(_)
+            | RegisterGpuDevice(_)
+            | RegisterInputDevice(_)
+            | RegisterEvent(_)
+            | RegisterNetDevice(_)
+            | RegisterSoundDevice(_)
+            | RegisterVsockDevice(_) => ErrorCategory::Internal,
+        }
+    }
+}
+
+impl std::error::Error for StartMicrovmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use self::StartMicrovmError::*;
+        match self {
+            AttachBlockDevice(err)
+            | ConsoleSocket(err)
+            | CreateRateLimiter(err)
+            | InitrdRead(err)
+            | OpenBlockDevice(err) => Some(err),
+            CloudInit(err) => Some(err),
+            Internal(err) => Some(err),
+            NetDeviceThread(err) => Some(err),
+            Probes(err) => Some(err),
+            RateLimitPolicy(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl StartMicrovmError {
+    /// Specifies if the host could not provide the guest memory.
+    pub fn is_out_of_guest_memory(&self) -> bool {
+        match self {
+            StartMicrovmError::CreateMemoryFile(_) | StartMicrovmError::GuestMemoryMmap(_) => true,
+            _ => false,
         }
     }
 }
@@ -195,20 +397,41 @@ impl devices::legacy::ReadableFd for SerialStdin {}
 
 impl VmmEventsObserver for SerialStdin {
     fn on_vmm_boot(&mut self) -> std::result::Result<(), utils::errno::Error> {
-        // Set raw mode for stdin.
-        self.0.lock().set_raw_mode().map_err(|e| {
+        // Set raw mode for stdin, saving the terminal settings to restore.
+        console::acquire_console().map_err(|e| {
             warn!("Cannot set raw mode for the terminal. {:?}", e);
-            e
+            utils::errno::Error::new(e.raw_os_error().unwrap_or(libc::EIO))
         })
     }
     fn on_vmm_stop(&mut self) -> std::result::Result<(), utils::errno::Error> {
-        self.0.lock().set_canon_mode().map_err(|e| {
-            warn!("Cannot set canonical mode for the terminal. {:?}", e);
-            e
+        console::release_console().map_err(|e| {
+            warn!("Cannot restore the terminal settings. {:?}", e);
+            utils::errno::Error::new(e.raw_os_error().unwrap_or(libc::EIO))
         })
     }
 }
 
+// Fails on the problems found by cross-checking the resources when the configuration is strict,
+// and logs them otherwise.
+fn check_config_issues(
+    vm_resources: &super::resources::VmResources,
+) -> std::result::Result<(), StartMicrovmError> {
+    let issues = vm_resources.config_issues();
+    if issues.is_empty() {
+        return Ok(());
+    }
+    if vm_resources.vm_config().strict_config {
+        return Err(StartMicrovmError::InconsistentConfig(issues));
+    }
+    for issue in issues.0.iter() {
+        warn!(
+            "Inconsistent configuration of {}: {}",
+            issue.resource, issue.message
+        );
+    }
+    Ok(())
+}
+
 /// Builds and starts a microVM based on the current Firecracker VmResources configuration.
 ///
 /// This is the default build recipe, one could build other microVM flavors by using the
@@ -224,48 +447,82 @@ pub fn build_microvm(
     let boot_config = vm_resources
         .boot_source()
         .ok_or(StartMicrovmError::MissingKernelConfig)?;
+    check_config_issues(vm_resources)?;
 
     // Timestamp for measuring microVM boot duration.
     let request_ts = TimestampUs::default();
 
-    let guest_memory = create_guest_memory(
-        vm_resources
-            .vm_config()
-            .mem_size_mib
-            .ok_or(StartMicrovmError::MissingMemSizeConfig)?,
-    )?;
+    let guest_memory = create_guest_memory(vm_resources.vm_config())?;
     let vcpu_config = vm_resources.vcpu_config();
     let track_dirty_pages = vm_resources.track_dirty_pages();
     let entry_addr = load_kernel(boot_config, &guest_memory)?;
     let initrd = load_initrd_from_config(boot_config, &guest_memory)?;
-    // Clone the command-line so that a failed boot doesn't pollute the original.
+    // Fill a copy of the command-line so that a failed boot doesn't pollute the original.
     #[allow(unused_mut)]
-    let mut kernel_cmdline = boot_config.cmdline.clone();
-    let mut vm = setup_kvm_vm(&guest_memory, track_dirty_pages)?;
+    let mut kernel_cmdline = fill_kernel_cmdline(&boot_config.cmdline, vm_resources)?;
+    let kvm_file = preopened_kvm(vm_resources.preopened_fds())?;
+    let mut vm = setup_kvm_vm(&guest_memory, track_dirty_pages, kvm_file)?;
+    #[cfg(target_arch = "x86_64")]
+    {
+        if vcpu_config.tickless {
+            setup_tickless_guest(&vm, &mut kernel_cmdline)?;
+        }
+    }
+    // The SEV context has to be set up before creating the vCPUs.
+    #[cfg(feature = "sev")]
+    let sev_launcher = match vm_resources.sev_config() {
+        Some(sev_config) => Some(setup_sev(&vm, &guest_memory, sev_config)?),
+        None => None,
+    };
+    // The boot page tables of an SEV guest map its memory as encrypted.
+    #[cfg(feature = "sev")]
+    let vcpu_config = VcpuConfig {
+        memory_encryption_mask: sev_launcher
+            .as_ref()
+            .map_or(0, |_| sev::memory_encryption_mask()),
+        ..vcpu_config
+    };
+
+    let events = EventChannel::default();
+    // The serial probes watch the output of the serial console.
+    let probe_runner = ProbeRunner::new(
+        vm_resources.probes.configs(),
+        &vm_resources.vsock.configs(),
+        events.sender(),
+    )
+    .map_err(StartMicrovmError::Probes)?;
 
-    // On x86_64 always create a serial device,
+    let legacy_devices = vm_resources.vm_config().legacy_devices;
+    // On x86_64 always create a serial device, unless the serial ports are omitted,
     // while on aarch64 only create it if 'console=' is specified in the boot args.
-    let serial_device = if cfg!(target_arch = "x86_64")
-        || (cfg!(target_arch = "aarch64") && kernel_cmdline.as_str().contains("console="))
+    let serial_device = if legacy_devices.serial
+        && (cfg!(target_arch = "x86_64")
+            || (cfg!(target_arch = "aarch64") && kernel_cmdline.as_str().contains("console=")))
     {
-        Some(setup_serial_device(
+        Some(setup_serial_console(
             event_manager,
-            Box::new(SerialStdin::get()),
-            Box::new(io::stdout()),
+            vm_resources.console_config(),
+            probe_runner.serial_output(Box::new(io::stdout())),
         )?)
     } else {
         None
     };
+    let console_input = match vm_resources.console_config().input {
+        ConsoleInput::Api => serial_device.clone(),
+        _ => None,
+    };
 
     let exit_evt = EventFd::new(libc::EFD_NONBLOCK)
         .map_err(Error::EventFd)
         .map_err(StartMicrovmError::Internal)?;
 
     #[cfg(target_arch = "x86_64")]
-    // Safe to unwrap 'serial_device' as it's always 'Some' on x86_64.
     // x86_64 uses the i8042 reset event as the Vmm exit event.
     let mut pio_device_manager = PortIODeviceManager::new(
-        serial_device.unwrap(),
+        match serial_device {
+            Some(serial_device) => serial_device,
+            None => setup_sink_serial()?,
+        },
         exit_evt
             .try_clone()
             .map_err(Error::EventFd)
@@ -273,23 +530,58 @@ pub fn build_microvm(
     )
     .map_err(Error::CreateLegacyDevice)
     .map_err(StartMicrovmError::Internal)?;
+    #[cfg(target_arch = "x86_64")]
+    {
+        if let Some(rtc_config) = vm_resources.rtc_config() {
+            pio_device_manager
+                .cmos
+                .lock()
+                .expect("Poisoned lock")
+                .set_time(rtc_config.rtc_time());
+        }
+    }
 
     // Instantiate the MMIO device manager.
     // 'mmio_base' address has to be an address which is protected by the kernel
     // and is architectural specific.
-    #[allow(unused_mut)]
-    let mut mmio_device_manager = MMIODeviceManager::new(
-        &mut (arch::MMIO_MEM_START as u64),
-        (arch::IRQ_BASE, arch::IRQ_MAX),
-    );
-
-    let vcpus;
+    #[cfg(target_arch = "x86_64")]
+    let mut mmio_base = {
+        let vm_config = vm_resources.vm_config();
+        // The guest memory got created, so its size is configured.
+        let mem_size = vm_config.mem_size_mib.unwrap_or_default() << 20;
+        vm_config.memory_layout().mmio_devices_start(mem_size)
+    };
+    #[cfg(target_arch = "aarch64")]
+    let mut mmio_base = arch::MMIO_MEM_START;
+    let mut mmio_device_manager =
+        MMIODeviceManager::new(&mut mmio_base, (arch::IRQ_BASE, arch::IRQ_MAX));
+    mmio_device_manager.set_max_irq_rate(vm_resources.vm_config().max_device_irq_rate);
+    mmio_device_manager.set_feature_policy(vm_resources.virtio_feature_policy().clone());
+    mmio_device_manager.set_device_enumeration(boot_config.device_enumeration);
+
+    let mut vcpus;
+    #[cfg(target_arch = "x86_64")]
+    let pci_device_manager;
     // For x86_64 we need to create the interrupt controller before calling `KVM_CREATE_VCPUS`
     // while on aarch64 we need to do it the other way around.
     #[cfg(target_arch = "x86_64")]
     {
-        setup_interrupt_controller(&mut vm)?;
-        attach_legacy_devices(&vm, &mut pio_device_manager)?;
+        if vm_resources.vm_config().split_irqchip {
+            setup_split_interrupt_controller(&mut vm, &mut mmio_device_manager.bus)?;
+        } else {
+            setup_interrupt_controller(&mut vm, !vcpu_config.tickless)?;
+        }
+        attach_legacy_devices(&vm, &mut pio_device_manager, &legacy_devices)?;
+        // The vCPUs get a copy of the I/O bus, so the PCI bus has to be plugged in beforehand.
+        pci_device_manager = attach_pci_devices(
+            &vm,
+            &guest_memory,
+            vm_resources,
+            &kernel_cmdline,
+            &mut pio_device_manager.io_bus,
+            &mut mmio_device_manager.bus,
+            event_manager,
+        )?;
 
         vcpus = create_vcpus_x86_64(
             &vm,
@@ -319,32 +611,93 @@ pub fn build_microvm(
         )
         .map_err(StartMicrovmError::Internal)?;
 
-        setup_interrupt_controller(&mut vm, vcpu_config.vcpu_count)?;
+        setup_interrupt_controller(
+            &mut vm,
+            vcpu_config.vcpu_count,
+            &vm_resources.vm_config().gic_config(),
+        )?;
         attach_legacy_devices(
             &vm,
             &mut mmio_device_manager,
             &mut kernel_cmdline,
             serial_device,
+            vm_resources.rtc_config(),
+            &legacy_devices,
         )?;
     }
 
     let mut vmm = Vmm {
-        events_observer: Some(Box::new(SerialStdin::get())),
+        events_observer: console_events_observer(vm_resources.console_config()),
         guest_memory,
         kernel_cmdline,
         vcpus_handles: Vec::new(),
+        vcpus_running: false,
+        parked_vcpus: BTreeSet::new(),
         exit_evt,
+        requested_exit_code: Arc::new(Mutex::new(None)),
         vm,
+        events,
+        probe_statuses: probe_runner.statuses(),
+        probes_running: probe_runner.running(),
+        operations: Operations::new()
+            .map_err(Error::EventFd)
+            .map_err(StartMicrovmError::Internal)?,
+        console_input,
         mmio_device_manager,
         #[cfg(target_arch = "x86_64")]
         pio_device_manager,
+        #[cfg(target_arch = "x86_64")]
+        pci_device_manager,
+        #[cfg(feature = "sev")]
+        launch_measurement: None,
+        cid_leases: vm_resources.vsock.cid_leases().cloned().collect(),
+        device_threads: Vec::new(),
     };
 
+    #[cfg(target_arch = "x86_64")]
+    attach_i8042_reset_sink(&vmm);
     attach_block_devices(&mut vmm, &vm_resources.block, event_manager)?;
-    if let Some(vsock) = vm_resources.vsock.get() {
+    if let Some(cloud_init) = vm_resources.cloud_init() {
+        attach_cloud_init(&mut vmm, vm_resources, cloud_init, event_manager)?;
+    }
+    for vsock in vm_resources.vsock.unix_devices() {
         attach_unixsock_vsock_device(&mut vmm, vsock, event_manager)?;
     }
-    attach_net_devices(&mut vmm, &vm_resources.net_builder, event_manager)?;
+    for vhost_vsock in vm_resources.vsock.vhost_devices() {
+        attach_vhost_vsock_device(&mut vmm, vhost_vsock, event_manager)?;
+    }
+    attach_net_devices(
+        &mut vmm,
+        &vm_resources.net_builder,
+        event_manager,
+        seccomp_filter,
+    )?;
+    if let Some(balloon) = vm_resources.balloon.get() {
+        attach_balloon_device(&mut vmm, balloon, event_manager)?;
+    }
+    if let Some(entropy) = vm_resources.entropy.get() {
+        attach_entropy_device(&mut vmm, entropy, event_manager)?;
+    }
+    if let Some(gpu) = vm_resources.gpu.get() {
+        attach_gpu_device(&mut vmm, gpu, event_manager)?;
+    }
+    if let Some(input) = vm_resources.input.get() {
+        attach_input_device(&mut vmm, input, event_manager)?;
+    }
+    if let Some(sound) = vm_resources.sound.get() {
+        attach_sound_device(&mut vmm, sound, event_manager)?;
+    }
+    let adaptive_rate_limiter = match vm_resources.rate_limit_policy() {
+        Some(policy) => Some(
+            AdaptiveRateLimiter::new(
+                policy,
+                vm_resources.net_builder.iter(),
+                vm_resources.block.list.iter(),
+            )
+            .map_err(StartMicrovmError::RateLimitPolicy)?,
+        ),
+        None => None,
+    };
 
     // Write the kernel command line to guest memory. This is x86_64 specific, since on
     // aarch64 the command line will be specified through the FDT.
@@ -353,25 +706,187 @@ pub fn build_microvm(
 
     vmm.configure_system(vcpus.as_slice(), &initrd)
         .map_err(StartMicrovmError::Internal)?;
+    // The guest is set up for boot, so its initial memory and state can be encrypted.
+    #[cfg(feature = "sev")]
+    {
+        if let Some(sev_launcher) = sev_launcher {
+            let measurement = sev_launcher
+                .launch(vmm.vm.fd(), &vmm.guest_memory)
+                .map_err(StartMicrovmError::SevLaunch)?;
+            vmm.launch_measurement = Some(measurement);
+        }
+    }
+    for vcpu in vcpus.iter_mut() {
+        vcpu.set_retry_config(vm_resources.vm_config().vcpu_retry);
+    }
     // Firecracker uses the same seccomp filter for all threads.
     vmm.start_vcpus(vcpus, seccomp_filter.to_vec(), seccomp_filter)
         .map_err(StartMicrovmError::Internal)?;
 
+    let irq_throttles = vmm.mmio_device_manager.take_irq_throttles();
     let vmm = Arc::new(Mutex::new(vmm));
     event_manager
         .add_subscriber(vmm.clone())
         .map_err(StartMicrovmError::RegisterEvent)?;
+    if !probe_runner.is_empty() {
+        event_manager
+            .add_subscriber(Arc::new(Mutex::new(probe_runner)))
+            .map_err(StartMicrovmError::RegisterEvent)?;
+    }
+    if let Some(adaptive_rate_limiter) = adaptive_rate_limiter {
+        event_manager
+            .add_subscriber(Arc::new(Mutex::new(adaptive_rate_limiter)))
+            .map_err(StartMicrovmError::RegisterEvent)?;
+    }
+    for irq_throttle in irq_throttles {
+        event_manager
+            .add_subscriber(irq_throttle)
+            .map_err(StartMicrovmError::RegisterEvent)?;
+    }
+
+    Ok(vmm)
+}
+
+/// Builds and starts a microVM based on a saved `MicrovmState`.
+///
+/// Unlike `build_microvm`, no kernel is loaded: `guest_memory` is expected to already hold the
+/// guest contents, while the KVM VM, the vCPUs and the devices are re-created and brought back
+/// to their saved state. The vCPUs are resumed before returning if `resume_vcpus` is set, and
+/// are left paused otherwise. The input of the serial console comes from where `console_config`
+/// says. The VM is created from the descriptor of `/dev/kvm` in `preopened_fds`, if given.
+#[cfg(target_arch = "x86_64")]
+#[allow(clippy::too_many_arguments)]
+pub fn build_microvm_from_state(
+    microvm_state: MicrovmState,
+    guest_memory: GuestMemoryMmap,
+    track_dirty_pages: bool,
+    resume_vcpus: bool,
+    console_config: &ConsoleConfig,
+    preopened_fds: &PreopenedFds,
+    event_manager: &mut EventManager,
+    seccomp_filter: BpfProgramRef,
+) -> std::result::Result<Arc<Mutex<Vmm>>, StartMicrovmError> {
+    use self::StartMicrovmError::*;
+
+    let request_ts = TimestampUs::default();
+    let kvm_file = preopened_kvm(preopened_fds)?;
+    let mut vm = setup_kvm_vm(&guest_memory, track_dirty_pages, kvm_file)?;
+    let legacy_devices = microvm_state.vm_info.legacy_devices();
+    let serial_device = if legacy_devices.serial {
+        setup_serial_console(event_manager, console_config, Box::new(io::stdout()))?
+    } else {
+        setup_sink_serial()?
+    };
+    let console_input = match console_config.input {
+        ConsoleInput::Api if legacy_devices.serial => Some(serial_device.clone()),
+        _ => None,
+    };
+    let exit_evt = EventFd::new(libc::EFD_NONBLOCK)
+        .map_err(Error::EventFd)
+        .map_err(Internal)?;
+    let mut pio_device_manager = PortIODeviceManager::new(
+        serial_device,
+        exit_evt
+            .try_clone()
+            .map_err(Error::EventFd)
+            .map_err(Internal)?,
+    )
+    .map_err(Error::CreateLegacyDevice)
+    .map_err(Internal)?;
+    pio_device_manager
+        .cmos
+        .lock()
+        .expect("Poisoned lock")
+        .set_time_offset(microvm_state.vm_info.rtc_time_offset);
+    let mmio_device_manager = MMIODeviceManager::new(
+        &mut (arch::MMIO_MEM_START as u64),
+        (arch::IRQ_BASE, arch::IRQ_MAX),
+    );
+
+    setup_interrupt_controller(&mut vm, microvm_state.vm_info.pit)?;
+    attach_legacy_devices(&vm, &mut pio_device_manager, &legacy_devices)?;
+    let vcpus = restore_vcpus_x86_64(
+        &vm,
+        microvm_state.vcpu_states,
+        request_ts,
+        &pio_device_manager.io_bus,
+        &exit_evt,
+    )?;
+    vm.restore_state(&microvm_state.vm_state)
+        .map_err(MicrovmStateError::RestoreVmState)
+        .map_err(RestoreMicrovmState)?;
+
+    let mut vmm = Vmm {
+        events_observer: console_events_observer(console_config),
+        guest_memory,
+        // The guest kernel is already running, so the command line is of no use anymore.
+        kernel_cmdline: KernelCmdline::new(arch::CMDLINE_MAX_SIZE),
+        vcpus_handles: Vec::new(),
+        vcpus_running: false,
+        parked_vcpus: BTreeSet::new(),
+        exit_evt,
+        requested_exit_code: Arc::new(Mutex::new(None)),
+        vm,
+        events: EventChannel::default(),
+        // The probes are not part of the snapshot.
+        probe_statuses: ProbeStatuses::default(),
+        probes_running: Arc::new(AtomicBool::new(false)),
+        operations: Operations::new()
+            .map_err(Error::EventFd)
+            .map_err(Internal)?,
+        console_input,
+        mmio_device_manager,
+        pio_device_manager,
+        // Microvms with passthrough devices can't be snapshotted.
+        pci_device_manager: None,
+        #[cfg(feature = "sev")]
+        launch_measurement: None,
+        // The CIDs of the restored vsock devices are not leased.
+        cid_leases: Vec::new(),
+        device_threads: Vec::new(),
+    };
+
+    attach_i8042_reset_sink(&vmm);
+    restore_mmio_devices(&mut vmm, &microvm_state.device_states, event_manager)
+        .map_err(RestoreMicrovmState)?;
+
+    if resume_vcpus {
+        vmm.start_vcpus(vcpus, seccomp_filter.to_vec(), seccomp_filter)
+    } else {
+        vmm.start_paused_vcpus(vcpus, seccomp_filter.to_vec(), seccomp_filter)
+    }
+    .map_err(Internal)?;
+
+    let vmm = Arc::new(Mutex::new(vmm));
+    event_manager
+        .add_subscriber(vmm.clone())
+        .map_err(RegisterEvent)?;
 
     Ok(vmm)
 }
 
-/// Creates GuestMemory of `mem_size_mib` MiB in size.
+/// Creates GuestMemory of the size configured in `vm_config`, laid out as configured on x86_64.
+///
+/// When `memfd_backed` is set, the guest memory lives in an anonymous memory file instead of
+/// anonymous mappings, so that it can be handed over to another process.
 pub fn create_guest_memory(
-    mem_size_mib: usize,
+    vm_config: &VmConfig,
 ) -> std::result::Result<GuestMemoryMmap, StartMicrovmError> {
-    let mem_size = mem_size_mib << 20;
+    let mem_size = vm_config
+        .mem_size_mib
+        .ok_or(StartMicrovmError::MissingMemSizeConfig)?
+        << 20;
+    #[cfg(target_arch = "x86_64")]
+    let arch_mem_regions = vm_config.memory_layout().memory_regions(mem_size);
+    #[cfg(target_arch = "aarch64")]
     let arch_mem_regions = arch::arch_memory_regions(mem_size);
+    // Each region is registered in a KVM memory slot of its own.
+    let arch_mem_regions = arch::split_memory_regions(&arch_mem_regions);
 
+    if vm_config.memfd_backed {
+        return create_memfd_guest_memory(&arch_mem_regions)
+            .map_err(StartMicrovmError::CreateMemoryFile);
+    }
     Ok(GuestMemoryMmap::from_ranges(&arch_mem_regions)
         .map_err(StartMicrovmError::GuestMemoryMmap)?)
 }
@@ -380,14 +895,20 @@ fn load_kernel(
     boot_config: &BootConfig,
     guest_memory: &GuestMemoryMmap,
 ) -> std::result::Result<GuestAddress, StartMicrovmError> {
-    let mut kernel_file = boot_config
-        .kernel_file
-        .try_clone()
-        .map_err(|e| StartMicrovmError::Internal(Error::KernelFile(e)))?;
-
-    let entry_addr =
-        kernel::loader::load_kernel(guest_memory, &mut kernel_file, arch::get_kernel_start())
-            .map_err(StartMicrovmError::KernelLoader)?;
+    let entry_addr = match boot_config.kernel_image {
+        KernelImage::File(ref kernel_file) => {
+            let mut kernel_file = kernel_file
+                .try_clone()
+                .map_err(|e| StartMicrovmError::Internal(Error::KernelFile(e)))?;
+            kernel::loader::load_kernel(guest_memory, &mut kernel_file, arch::get_kernel_start())
+        }
+        KernelImage::Bytes(ref kernel_bytes) => kernel::loader::load_kernel(
+            guest_memory,
+            &mut Cursor::new(&kernel_bytes.0[..]),
+            arch::get_kernel_start(),
+        ),
+    }
+    .map_err(StartMicrovmError::KernelLoader)?;
 
     Ok(entry_addr)
 }
@@ -463,11 +984,29 @@ fn load_cmdline(vmm: &Vmm) -> std::result::Result<(), StartMicrovmError> {
     .map_err(StartMicrovmError::LoadCommandline)
 }
 
+// Duplicates the descriptor of `/dev/kvm` in `preopened_fds`, if given.
+fn preopened_kvm(
+    preopened_fds: &PreopenedFds,
+) -> std::result::Result<Option<File>, StartMicrovmError> {
+    preopened_fds
+        .kvm()
+        .transpose()
+        .map_err(|e| {
+            let errno = kvm_ioctls::Error::new(e.raw_os_error().unwrap_or(libc::EBADF));
+            Error::KvmContext(vstate::Error::KvmOpen(errno))
+        })
+        .map_err(StartMicrovmError::Internal)
+}
+
+/// Creates the VM from `kvm_file` if it is the already opened `/dev/kvm`, or from `/dev/kvm`
+/// otherwise.
 pub(crate) fn setup_kvm_vm(
     guest_memory: &GuestMemoryMmap,
     track_dirty_pages: bool,
+    kvm_file: Option<File>,
 ) -> std::result::Result<Vm, StartMicrovmError> {
-    let kvm = KvmContext::new()
+    let kvm = kvm_file
+        .map_or_else(KvmContext::new, KvmContext::from_file)
         .map_err(Error::KvmContext)
         .map_err(StartMicrovmError::Internal)?;
     let mut vm = Vm::new(kvm.fd())
@@ -476,24 +1015,95 @@ pub(crate) fn setup_kvm_vm(
     vm.memory_init(&guest_memory, kvm.max_memslots(), track_dirty_pages)
         .map_err(Error::Vm)
         .map_err(StartMicrovmError::Internal)?;
+    // KVM doesn't see the pages written by the device emulation.
+    if track_dirty_pages {
+        dirty_pages::enable(guest_memory);
+    }
     Ok(vm)
 }
 
-/// Sets up the irqchip for a x86_64 microVM.
+// Initializes the SEV context of the VM and registers the guest memory as encrypted.
+#[cfg(feature = "sev")]
+fn setup_sev(
+    vm: &Vm,
+    guest_memory: &GuestMemoryMmap,
+    sev_config: &SevConfig,
+) -> std::result::Result<SevLauncher, StartMicrovmError> {
+    let sev_launcher = SevLauncher::new(sev_config).map_err(StartMicrovmError::SevLaunch)?;
+    sev_launcher
+        .init(vm.fd())
+        .map_err(StartMicrovmError::SevLaunch)?;
+    sev_launcher
+        .register_memory(vm.fd(), guest_memory)
+        .map_err(StartMicrovmError::SevLaunch)?;
+    Ok(sev_launcher)
+}
+
+/// Sets up the irqchip for a x86_64 microVM, along with the PIT when `pit` is true.
 #[cfg(target_arch = "x86_64")]
-pub fn setup_interrupt_controller(vm: &mut Vm) -> std::result::Result<(), StartMicrovmError> {
-    vm.setup_irqchip()
+pub fn setup_interrupt_controller(
+    vm: &mut Vm,
+    pit: bool,
+) -> std::result::Result<(), StartMicrovmError> {
+    vm.setup_irqchip(pit)
         .map_err(Error::Vm)
         .map_err(StartMicrovmError::Internal)
 }
 
+// Checks that the guest can keep time without a PIT, through kvm-clock and the TSC deadline
+// timer, and tells it not to check the timer interrupts of the PIT.
+#[cfg(target_arch = "x86_64")]
+fn setup_tickless_guest(
+    vm: &Vm,
+    kernel_cmdline: &mut KernelCmdline,
+) -> std::result::Result<(), StartMicrovmError> {
+    if !vm.optional_kvm_caps().tsc_deadline_timer {
+        return Err(StartMicrovmError::TicklessNotSupported);
+    }
+    if !kernel_cmdline
+        .as_str()
+        .split(' ')
+        .any(|arg| arg == "no_timer_check")
+    {
+        kernel_cmdline.insert_str("no_timer_check")?;
+    }
+    Ok(())
+}
+
+/// Sets up a split irqchip for a x86_64 microVM, along with the IOAPIC emulated in userspace.
+#[cfg(target_arch = "x86_64")]
+pub fn setup_split_interrupt_controller(
+    vm: &mut Vm,
+    mmio_bus: &mut devices::Bus,
+) -> std::result::Result<(), StartMicrovmError> {
+    use devices::legacy::{Ioapic, IOAPIC_SIZE, IOAPIC_START};
+
+    vm.setup_split_irqchip()
+        .map_err(Error::Vm)
+        .map_err(StartMicrovmError::Internal)?;
+    let router = vm
+        .msi_router()
+        .map_err(Error::Vm)
+        .map_err(StartMicrovmError::Internal)?;
+    mmio_bus
+        .insert(
+            Arc::new(Mutex::new(Ioapic::new(Box::new(router)))),
+            IOAPIC_START,
+            IOAPIC_SIZE,
+        )
+        .map_err(device_manager::mmio::Error::BusError)
+        .map_err(Error::RegisterMMIODevice)
+        .map_err(StartMicrovmError::Internal)
+}
+
 /// Sets up the irqchip for a aarch64 microVM.
 #[cfg(target_arch = "aarch64")]
 pub fn setup_interrupt_controller(
     vm: &mut Vm,
     vcpu_count: u8,
+    gic_config: &GICConfig,
 ) -> std::result::Result<(), StartMicrovmError> {
-    vm.setup_irqchip(vcpu_count)
+    vm.setup_irqchip(vcpu_count, gic_config)
         .map_err(Error::Vm)
         .map_err(StartMicrovmError::Internal)
 }
@@ -519,13 +1129,58 @@ pub fn setup_serial_device(
     Ok(serial)
 }
 
+/// Sets up the serial device, taking its input from where `console_config` says, and writing its
+/// output to `out`.
+pub fn setup_serial_console(
+    event_manager: &mut EventManager,
+    console_config: &ConsoleConfig,
+    out: Box<dyn io::Write + Send>,
+) -> std::result::Result<Arc<Mutex<Serial>>, StartMicrovmError> {
+    if console_config.input == ConsoleInput::Stdin {
+        return setup_serial_device(event_manager, Box::new(SerialStdin::get()), out);
+    }
+
+    let interrupt_evt = EventFd::new(libc::EFD_NONBLOCK)
+        .map_err(Error::EventFd)
+        .map_err(StartMicrovmError::Internal)?;
+    let serial = Arc::new(Mutex::new(Serial::new_out(interrupt_evt, out)));
+    // The socket path is only configured for the socket input.
+    if let Some(socket_path) = console_config.socket_path.as_ref() {
+        let console_socket = ConsoleSocket::new(socket_path, serial.clone())
+            .map_err(StartMicrovmError::ConsoleSocket)?;
+        event_manager
+            .add_subscriber(Arc::new(Mutex::new(console_socket)))
+            .map_err(StartMicrovmError::RegisterEvent)?;
+    }
+    Ok(serial)
+}
+
+// The terminal is only switched to raw mode while it feeds the serial console.
+fn console_events_observer(console_config: &ConsoleConfig) -> Option<Box<dyn VmmEventsObserver>> {
+    match console_config.input {
+        ConsoleInput::Stdin => Some(Box::new(SerialStdin::get())),
+        ConsoleInput::Api | ConsoleInput::Socket => None,
+    }
+}
+
+// Stands in for the serial console when the serial ports are omitted, as the port I/O device
+// manager needs one.
+#[cfg(target_arch = "x86_64")]
+fn setup_sink_serial() -> std::result::Result<Arc<Mutex<Serial>>, StartMicrovmError> {
+    let interrupt_evt = EventFd::new(libc::EFD_NONBLOCK)
+        .map_err(Error::EventFd)
+        .map_err(StartMicrovmError::Internal)?;
+    Ok(Arc::new(Mutex::new(Serial::new_sink(interrupt_evt))))
+}
+
 #[cfg(target_arch = "x86_64")]
 fn attach_legacy_devices(
     vm: &Vm,
     pio_device_manager: &mut PortIODeviceManager,
+    config: &LegacyDevicesConfig,
 ) -> std::result::Result<(), StartMicrovmError> {
     pio_device_manager
-        .register_devices()
+        .register_devices(config)
         .map_err(Error::LegacyIOBus)
         .map_err(StartMicrovmError::Internal)?;
 
@@ -542,19 +1197,90 @@ fn attach_legacy_devices(
         }};
     }
 
-    register_irqfd_evt!(com_evt_1_3, 4);
-    register_irqfd_evt!(com_evt_2_4, 3);
-    register_irqfd_evt!(kbd_evt, 1);
+    if config.serial {
+        register_irqfd_evt!(com_evt_1_3, 4);
+        register_irqfd_evt!(com_evt_2_4, 3);
+    }
+    if config.i8042 {
+        register_irqfd_evt!(kbd_evt, 1);
+    }
     Ok(())
 }
 
+// Plugs the PCI passthrough devices and the shared memory devices configured in `vm_resources`,
+// if any, in a PCI bus.
+#[cfg(target_arch = "x86_64")]
+fn attach_pci_devices(
+    vm: &Vm,
+    guest_memory: &GuestMemoryMmap,
+    vm_resources: &super::resources::VmResources,
+    kernel_cmdline: &KernelCmdline,
+    io_bus: &mut devices::Bus,
+    mmio_bus: &mut devices::Bus,
+    event_manager: &mut EventManager,
+) -> std::result::Result<Option<PciDeviceManager>, StartMicrovmError> {
+    use self::StartMicrovmError::{AttachPciPassthroughDevice, AttachSharedMemoryDevice};
+    use device_manager::pci::Error as PciError;
+
+    let configs = vm_resources.pci_passthrough.configs();
+    let shared_memory_configs = vm_resources.shared_memory.configs();
+    if configs.is_empty() && shared_memory_configs.is_empty() {
+        return Ok(None);
+    }
+    if kernel_cmdline
+        .as_str()
+        .split(' ')
+        .any(|arg| arg == "pci=off")
+    {
+        return Err(if configs.is_empty() {
+            AttachSharedMemoryDevice(PciError::PciDisabled)
+        } else {
+            AttachPciPassthroughDevice(PciError::PciDisabled)
+        });
+    }
+    if vm.split_irqchip() {
+        return Err(if configs.is_empty() {
+            AttachSharedMemoryDevice(PciError::SplitIrqchipNotSupported)
+        } else {
+            AttachPciPassthroughDevice(PciError::SplitIrqchipNotSupported)
+        });
+    }
+    if !configs.is_empty() && vm_resources.balloon.get().is_some() {
+        return Err(AttachPciPassthroughDevice(PciError::BalloonNotSupported));
+    }
+
+    let vm_config = vm_resources.vm_config();
+    // The guest memory got created, so its size is configured.
+    let mem_size = vm_config.mem_size_mib.unwrap_or_default() << 20;
+    let mut pci_device_manager =
+        PciDeviceManager::new(io_bus, &vm_config.memory_layout(), guest_memory, mem_size)
+            .map_err(AttachPciPassthroughDevice)?;
+    for config in configs {
+        pci_device_manager
+            .add_device(vm.fd(), guest_memory, mmio_bus, config)
+            .map_err(AttachPciPassthroughDevice)?;
+    }
+    for config in shared_memory_configs {
+        let device = pci_device_manager
+            .add_shared_memory(vm.fd(), mmio_bus, config)
+            .map_err(AttachSharedMemoryDevice)?;
+        event_manager
+            .add_subscriber(device)
+            .map_err(StartMicrovmError::RegisterEvent)?;
+    }
+    Ok(Some(pci_device_manager))
+}
+
 #[cfg(target_arch = "aarch64")]
 fn attach_legacy_devices(
     vm: &Vm,
     mmio_device_manager: &mut MMIODeviceManager,
     kernel_cmdline: &mut kernel::cmdline::Cmdline,
     serial: Option<Arc<Mutex<Serial>>>,
+    rtc_config: Option<&RtcConfig>,
+    config: &LegacyDevicesConfig,
 ) -> std::result::Result<(), StartMicrovmError> {
+    // The serial device only exists when the serial ports are selected.
     if let Some(serial) = serial {
         mmio_device_manager
             .register_mmio_serial(vm.fd(), kernel_cmdline, serial)
@@ -562,10 +1288,13 @@ fn attach_legacy_devices(
             .map_err(StartMicrovmError::Internal)?;
     }
 
-    mmio_device_manager
-        .register_mmio_rtc(vm.fd())
-        .map_err(Error::RegisterMMIODevice)
-        .map_err(StartMicrovmError::Internal)?;
+    if config.rtc {
+        mmio_device_manager
+            // The RTC configuration is validated, so its time fits in 32 bits.
+            .register_mmio_rtc(vm.fd(), rtc_config.map(|cfg| cfg.rtc_time() as u32))
+            .map_err(Error::RegisterMMIODevice)
+            .map_err(StartMicrovmError::Internal)?;
+    }
 
     Ok(())
 }
@@ -587,6 +1316,7 @@ fn create_vcpus_x86_64(
             vm.fd(),
             vm.supported_cpuid().clone(),
             vm.supported_msrs().clone(),
+            vm.optional_kvm_caps(),
             io_bus.clone(),
             exit_evt.try_clone().map_err(Error::EventFd)?,
             request_ts.clone(),
@@ -601,6 +1331,43 @@ fn create_vcpus_x86_64(
     Ok(vcpus)
 }
 
+#[cfg(target_arch = "x86_64")]
+fn restore_vcpus_x86_64(
+    vm: &Vm,
+    vcpu_states: Vec<VcpuState>,
+    request_ts: TimestampUs,
+    io_bus: &devices::Bus,
+    exit_evt: &EventFd,
+) -> std::result::Result<Vec<Vcpu>, StartMicrovmError> {
+    use self::StartMicrovmError::{Internal, RestoreMicrovmState};
+
+    let mut vcpus = Vec::with_capacity(vcpu_states.len());
+    for (cpu_index, state) in vcpu_states.into_iter().enumerate() {
+        let mut vcpu = Vcpu::new_x86_64(
+            cpu_index as u8,
+            vm.fd(),
+            vm.supported_cpuid().clone(),
+            vm.supported_msrs().clone(),
+            vm.optional_kvm_caps(),
+            io_bus.clone(),
+            exit_evt
+                .try_clone()
+                .map_err(Error::EventFd)
+                .map_err(Internal)?,
+            request_ts.clone(),
+        )
+        .map_err(Error::Vcpu)
+        .map_err(Internal)?;
+
+        vcpu.restore_state(state)
+            .map_err(MicrovmStateError::RestoreVcpuState)
+            .map_err(RestoreMicrovmState)?;
+
+        vcpus.push(vcpu);
+    }
+    Ok(vcpus)
+}
+
 #[cfg(target_arch = "aarch64")]
 fn create_vcpus_aarch64(
     vm: &Vm,
@@ -650,6 +1417,64 @@ fn attach_mmio_device(
     Ok(())
 }
 
+/// Returns the root device, as named by the `root=` kernel parameter, given the PARTUUID of its
+/// root partition if any.
+fn root_device(partuuid: Option<&String>) -> String {
+    if let Some(partuuid) = partuuid {
+        format!("PARTUUID={}", partuuid)
+    } else {
+        // If no PARTUUID was specified for the root device, try with the name of the first block
+        // device, which the root device is.
+        format!("/dev/{}", virtio_block_device_name(0))
+    }
+}
+
+/// Appends the kernel command line arguments telling the guest to mount the root device, with
+/// the root partition `partuuid` if any, as its root filesystem.
+pub(crate) fn insert_root_device_args(
+    kernel_cmdline: &mut KernelCmdline,
+    partuuid: Option<&String>,
+    is_read_only: bool,
+) -> std::result::Result<(), kernel::cmdline::Error> {
+    kernel_cmdline.insert_str(format!("root={}", root_device(partuuid)))?;
+
+    let flags = if is_read_only { "ro" } else { "rw" };
+    kernel_cmdline.insert_str(flags)
+}
+
+// Returns the IPv4 address the MMDS answers at in the guest.
+fn mmds_ipv4_addr(vm_resources: &super::resources::VmResources) -> Ipv4Addr {
+    vm_resources
+        .mmds_config
+        .as_ref()
+        .and_then(MmdsConfig::ipv4_addr)
+        .unwrap_or_else(MmdsNetworkStack::default_ipv4_addr)
+}
+
+/// Returns a copy of `cmdline`, its placeholders filled with the values of the variables they
+/// refer to for the microVM configured by `vm_resources`.
+pub(crate) fn fill_kernel_cmdline(
+    cmdline: &KernelCmdline,
+    vm_resources: &super::resources::VmResources,
+) -> std::result::Result<KernelCmdline, StartMicrovmError> {
+    let filled = fill_boot_args(cmdline.as_str(), |variable| match variable {
+        BootArgsVariable::InstanceId => vm_resources.instance_id().map(String::from),
+        BootArgsVariable::MmdsIp => Some(mmds_ipv4_addr(vm_resources).to_string()),
+        BootArgsVariable::RootDevice => vm_resources
+            .block
+            .list
+            .iter()
+            .map(|block| block.lock().expect("Poisoned lock"))
+            .find(|block| block.is_root_device())
+            .map(|block| root_device(block.partuuid())),
+    })
+    .map_err(|e| StartMicrovmError::KernelCmdline(e.to_string()))?;
+
+    let mut filled_cmdline = KernelCmdline::new(arch::CMDLINE_MAX_SIZE);
+    filled_cmdline.insert_str(filled)?;
+    Ok(filled_cmdline)
+}
+
 fn attach_block_devices(
     vmm: &mut Vmm,
     blocks: &BlockBuilder,
@@ -660,20 +1485,17 @@ fn attach_block_devices(
     for block in blocks.list.iter() {
         let id;
         {
-            let locked = block.lock().unwrap();
+            let mut locked = block.lock().unwrap();
             if locked.is_root_device() {
-                let kernel_cmdline = &mut vmm.kernel_cmdline;
-                kernel_cmdline.insert_str(if let Some(partuuid) = locked.partuuid() {
-                    format!("root=PARTUUID={}", partuuid)
-                } else {
-                    // If no PARTUUID was specified for the root device, try with the /dev/vda.
-                    "root=/dev/vda".to_string()
-                })?;
-
-                let flags = if locked.is_read_only() { "ro" } else { "rw" };
-                kernel_cmdline.insert_str(flags)?;
+                insert_root_device_args(
+                    &mut vmm.kernel_cmdline,
+                    locked.partuuid(),
+                    locked.is_read_only(),
+                )?;
             }
+            attach_block_event_sink(vmm, &mut locked).map_err(AttachBlockDevice)?;
             id = locked.id().clone();
+            prefetch_block_device(blocks, &locked);
         }
 
         event_manager
@@ -692,18 +1514,127 @@ fn attach_block_devices(
     Ok(())
 }
 
+// Surfaces the events of the block device to the control plane, and stops the microVM when the
+// host runs out of space for its backing file under the `stop_vm` policy.
+fn attach_block_event_sink(vmm: &Vmm, block: &mut Block) -> io::Result<()> {
+    let events = vmm.events.sender();
+    let stop_requester = vmm.stop_requester()?;
+    block.set_event_sink(Box::new(move |event| {
+        let stop = match event {
+            BlockEvent::OutOfSpace { policy, .. } => policy == EnospcPolicy::StopVm,
+        };
+        events.send(event);
+        if stop {
+            stop_requester.request_stop(FcExitCode::DriveOutOfSpace);
+        }
+    }));
+    Ok(())
+}
+
+// Starts reading the ranges of the backing file the guest is expected to read at boot into the
+// host page cache. These are only hints, which don't keep the microVM from starting.
+fn prefetch_block_device(blocks: &BlockBuilder, block: &Block) {
+    let page_cache = match blocks
+        .config(block.id())
+        .and_then(|config| config.page_cache.as_ref())
+    {
+        Some(page_cache) => page_cache,
+        None => return,
+    };
+    for range in page_cache.prefetch.iter() {
+        if let Err(e) = block.prefetch(range.offset, range.len) {
+            warn!(
+                "Cannot prefetch {} bytes at offset {} of drive {}: {}",
+                range.len,
+                range.offset,
+                block.id(),
+                e
+            );
+        }
+    }
+}
+
+// Hands the cloud-init data to the guest, either on a seed drive or through the MMDS.
+fn attach_cloud_init(
+    vmm: &mut Vmm,
+    vm_resources: &super::resources::VmResources,
+    cloud_init: &CloudInitConfig,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::*;
+
+    match cloud_init.transport {
+        CloudInitTransport::Seed => {
+            let seed = cloud_init::create_seed(cloud_init).map_err(CloudInit)?;
+            let block = devices::virtio::Block::new(
+                String::from(SEED_DRIVE_ID),
+                None,
+                String::from(SEED_DISK_IMAGE_PATH),
+                Some(seed),
+                true,
+                false,
+                false,
+                rate_limiter::RateLimiter::default(),
+                None,
+                None,
+            )
+            .map_err(AttachBlockDevice)?;
+            let block = Arc::new(Mutex::new(block));
+            event_manager
+                .add_subscriber(block.clone())
+                .map_err(RegisterEvent)?;
+            attach_mmio_device(
+                vmm,
+                String::from(SEED_DRIVE_ID),
+                MmioTransport::new(vmm.guest_memory().clone(), block),
+            )
+            .map_err(RegisterBlockDevice)?;
+        }
+        CloudInitTransport::Mmds => {
+            let mmds_reachable = vm_resources
+                .net_builder
+                .configs()
+                .iter()
+                .any(|config| config.allow_mmds_requests);
+            if !mmds_reachable {
+                return Err(CloudInit(CloudInitError::MmdsNotReachable));
+            }
+            cloud_init::publish_to_mmds(cloud_init).map_err(CloudInit)?;
+            vmm.kernel_cmdline
+                .insert_str(cloud_init::nocloud_net_kernel_param(mmds_ipv4_addr(
+                    vm_resources,
+                )))?;
+        }
+    }
+    Ok(())
+}
+
 fn attach_net_devices(
     vmm: &mut Vmm,
     net_builder: &NetBuilder,
     event_manager: &mut EventManager,
+    seccomp_filter: BpfProgramRef,
 ) -> std::result::Result<(), StartMicrovmError> {
     use self::StartMicrovmError::*;
 
     for net_device in net_builder.iter() {
-        event_manager
-            .add_subscriber(net_device.clone())
-            .map_err(RegisterEvent)?;
         let id = net_device.lock().unwrap().id().clone();
+        // Devices with an affinity are emulated on a thread of their own, with the same
+        // seccomp filter as the VMM thread.
+        match net_builder.affinity(&id) {
+            Some(cpus) => vmm.device_threads.push(
+                device_thread::spawn(
+                    format!("fc_net_{}", id),
+                    net_device.clone(),
+                    cpus,
+                    seccomp_filter.to_vec(),
+                )
+                .map_err(NetDeviceThread)?,
+            ),
+            None => event_manager
+                .add_subscriber(net_device.clone())
+                .map_err(RegisterEvent)?,
+        }
         // The device mutex mustn't be locked here otherwise it will deadlock.
         attach_mmio_device(
             vmm,
@@ -736,24 +1667,341 @@ fn attach_unixsock_vsock_device(
     )
     .map_err(RegisterVsockDevice)?;
 
-    Ok(())
+    Ok(())
+}
+
+fn attach_vhost_vsock_device(
+    vmm: &mut Vmm,
+    vhost_vsock: &Arc<Mutex<VhostVsock>>,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::*;
+
+    event_manager
+        .add_subscriber(vhost_vsock.clone())
+        .map_err(RegisterEvent)?;
+
+    let id = String::from(vhost_vsock.lock().unwrap().id());
+    // The device mutex mustn't be locked here otherwise it will deadlock.
+    attach_mmio_device(
+        vmm,
+        id,
+        MmioTransport::new(vmm.guest_memory().clone(), vhost_vsock.clone()),
+    )
+    .map_err(RegisterVsockDevice)?;
+
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn attach_i8042_reset_sink(vmm: &Vmm) {
+    // Surface the guest reset requests to the control plane.
+    let events = vmm.events.sender();
+    vmm.pio_device_manager
+        .i8042
+        .lock()
+        .expect("Poisoned lock")
+        .set_reset_sink(Box::new(move |request| events.send(request)));
+}
+
+fn attach_balloon_device(
+    vmm: &mut Vmm,
+    balloon: &Arc<Mutex<Balloon>>,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::*;
+
+    // Surface the balloon events to the control plane.
+    let events = vmm.events.sender();
+    balloon
+        .lock()
+        .expect("Poisoned lock")
+        .set_event_sink(Box::new(move |event| events.send(event)));
+
+    event_manager
+        .add_subscriber(balloon.clone())
+        .map_err(RegisterEvent)?;
+
+    let id = String::from(balloon.lock().expect("Poisoned lock").id());
+    // The device mutex mustn't be locked here otherwise it will deadlock.
+    attach_mmio_device(
+        vmm,
+        id,
+        MmioTransport::new(vmm.guest_memory().clone(), balloon.clone()),
+    )
+    .map_err(RegisterBalloonDevice)?;
+
+    Ok(())
+}
+
+fn attach_entropy_device(
+    vmm: &mut Vmm,
+    entropy: &Arc<Mutex<Entropy>>,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::*;
+
+    event_manager
+        .add_subscriber(entropy.clone())
+        .map_err(RegisterEvent)?;
+
+    let id = String::from(entropy.lock().expect("Poisoned lock").id());
+    // The device mutex mustn't be locked here otherwise it will deadlock.
+    attach_mmio_device(
+        vmm,
+        id,
+        MmioTransport::new(vmm.guest_memory().clone(), entropy.clone()),
+    )
+    .map_err(RegisterEntropyDevice)?;
+
+    Ok(())
+}
+
+fn attach_gpu_device(
+    vmm: &mut Vmm,
+    gpu: &Arc<Mutex<Gpu>>,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::*;
+
+    event_manager
+        .add_subscriber(gpu.clone())
+        .map_err(RegisterEvent)?;
+
+    let id = String::from(gpu.lock().expect("Poisoned lock").id());
+    // The device mutex mustn't be locked here otherwise it will deadlock.
+    attach_mmio_device(
+        vmm,
+        id,
+        MmioTransport::new(vmm.guest_memory().clone(), gpu.clone()),
+    )
+    .map_err(RegisterGpuDevice)?;
+
+    Ok(())
+}
+
+fn attach_input_device(
+    vmm: &mut Vmm,
+    input: &Arc<Mutex<Input>>,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::*;
+
+    event_manager
+        .add_subscriber(input.clone())
+        .map_err(RegisterEvent)?;
+
+    let id = String::from(input.lock().expect("Poisoned lock").id());
+    // The device mutex mustn't be locked here otherwise it will deadlock.
+    attach_mmio_device(
+        vmm,
+        id,
+        MmioTransport::new(vmm.guest_memory().clone(), input.clone()),
+    )
+    .map_err(RegisterInputDevice)?;
+
+    Ok(())
+}
+
+fn attach_sound_device(
+    vmm: &mut Vmm,
+    sound: &Arc<Mutex<Sound>>,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::*;
+
+    event_manager
+        .add_subscriber(sound.clone())
+        .map_err(RegisterEvent)?;
+
+    let id = String::from(sound.lock().expect("Poisoned lock").id());
+    // The device mutex mustn't be locked here otherwise it will deadlock.
+    attach_mmio_device(
+        vmm,
+        id,
+        MmioTransport::new(vmm.guest_memory().clone(), sound.clone()),
+    )
+    .map_err(RegisterSoundDevice)?;
+
+    Ok(())
+}
+
+/// Re-creates the MMIO devices described by `device_states` and registers them at their saved
+/// MMIO slots, so that the guest drivers keep talking to the same addresses and IRQs.
+#[cfg(target_arch = "x86_64")]
+fn restore_mmio_devices(
+    vmm: &mut Vmm,
+    device_states: &DeviceStates,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), MicrovmStateError> {
+    use self::MicrovmStateError::*;
+
+    let mem = vmm.guest_memory().clone();
+    for state in device_states.block_devices.iter() {
+        let mut device = Block::restore(
+            BlockConstructorArgs { mem: mem.clone() },
+            &state.device_state,
+        )
+        .map_err(RestoreBlock)?;
+        attach_block_event_sink(vmm, &mut device).map_err(RestoreBlock)?;
+        let id = device.id().clone();
+        restore_mmio_device(
+            vmm,
+            Arc::new(Mutex::new(device)),
+            id,
+            &state.transport_state,
+            &state.vmm_resources,
+            event_manager,
+        )?;
+    }
+    for state in device_states.net_devices.iter() {
+        let device = Net::restore(NetConstructorArgs { mem: mem.clone() }, &state.device_state)
+            .map_err(RestoreNet)?;
+        let id = device.id().clone();
+        restore_mmio_device(
+            vmm,
+            Arc::new(Mutex::new(device)),
+            id,
+            &state.transport_state,
+            &state.vmm_resources,
+            event_manager,
+        )?;
+    }
+    for state in device_states
+        .vsock_device
+        .iter()
+        .chain(device_states.extra_vsock_devices.iter())
+    {
+        let VsockBackendState::Uds(uds_state) = &state.device_state.backend;
+        // The socket file is left behind by the previous owner of the device.
+        let _ = std::fs::remove_file(&uds_state.path);
+        let backend = VsockUnixBackend::restore(
+            VsockUdsConstructorArgs {
+                cid: state.device_state.frontend.cid,
+            },
+            &state.device_state.backend,
+        )
+        .map_err(RestoreVsockBackend)?;
+        let device = Vsock::restore(
+            VsockConstructorArgs {
+                mem: mem.clone(),
+                backend,
+            },
+            &state.device_state.frontend,
+        )
+        .map_err(RestoreVsock)?;
+        let id = String::from(device.id());
+        restore_mmio_device(
+            vmm,
+            Arc::new(Mutex::new(device)),
+            id,
+            &state.transport_state,
+            &state.vmm_resources,
+            event_manager,
+        )?;
+    }
+    if let Some(state) = &device_states.balloon_device {
+        let mut device = Balloon::restore(
+            BalloonConstructorArgs { mem: mem.clone() },
+            &state.device_state,
+        )
+        .map_err(RestoreBalloon)?;
+        // Surface the balloon events to the control plane.
+        let events = vmm.events.sender();
+        device.set_event_sink(Box::new(move |event| events.send(event)));
+        let id = String::from(device.id());
+        restore_mmio_device(
+            vmm,
+            Arc::new(Mutex::new(device)),
+            id,
+            &state.transport_state,
+            &state.vmm_resources,
+            event_manager,
+        )?;
+    }
+    if let Some(state) = &device_states.entropy_device {
+        let device = Entropy::restore(EntropyConstructorArgs { mem }, &state.device_state)
+            .map_err(RestoreEntropy)?;
+        let id = String::from(device.id());
+        restore_mmio_device(
+            vmm,
+            Arc::new(Mutex::new(device)),
+            id,
+            &state.transport_state,
+            &state.vmm_resources,
+            event_manager,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn restore_mmio_device<T>(
+    vmm: &mut Vmm,
+    device: Arc<Mutex<T>>,
+    id: String,
+    transport_state: &MmioTransportState,
+    resources: &VmmResourcesState,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), MicrovmStateError>
+where
+    T: VirtioDevice + Subscriber + 'static,
+{
+    use self::MicrovmStateError::*;
+
+    event_manager
+        .add_subscriber(device.clone())
+        .map_err(RegisterEvent)?;
+
+    let type_id = device.lock().expect("Poisoned device lock").device_type();
+    let transport = MmioTransport::restore(
+        MmioTransportConstructorArgs {
+            mem: vmm.guest_memory().clone(),
+            device,
+        },
+        transport_state,
+    )
+    .expect("Restoring the MMIO transport cannot fail");
+
+    vmm.mmio_device_manager
+        .register_mmio_device_at(
+            vmm.vm.fd(),
+            transport,
+            type_id,
+            id,
+            resources.mmio_base,
+            resources.irqs[0],
+        )
+        .map_err(RegisterMmioDevice)
 }
 
 #[cfg(test)]
 pub mod tests {
+    use std::convert::TryFrom;
     use std::io::Cursor;
 
     use super::*;
     use arch::DeviceType;
-    use devices::virtio::{TYPE_BLOCK, TYPE_VSOCK};
+    use devices::virtio::{
+        TYPE_BALLOON, TYPE_BLOCK, TYPE_GPU, TYPE_INPUT, TYPE_NET, TYPE_RNG, TYPE_SOUND, TYPE_VSOCK,
+    };
     use kernel::cmdline::Cmdline;
     use polly::event_manager::EventManager;
     use utils::tempfile::TempFile;
-    use vmm_config::boot_source::DEFAULT_KERNEL_CMDLINE;
-    use vmm_config::drive::BlockDeviceConfig;
-    use vmm_config::net::NetworkInterfaceConfig;
+    use vmm_config::balloon::{BalloonBuilder, BalloonDeviceConfig};
+    use vmm_config::boot_source::{MmioDeviceEnumeration, DEFAULT_KERNEL_CMDLINE};
+    use vmm_config::drive::{BlockDeviceConfig, EnospcPolicy};
+    use vmm_config::entropy::{EntropyBuilder, EntropyDeviceConfig};
+    use vmm_config::gpu::{GpuBuilder, GpuDeviceConfig};
+    use vmm_config::input::{InputBuilder, InputDeviceConfig};
+    use vmm_config::net::{NetBackendType, NetworkInterfaceConfig};
+    #[cfg(target_arch = "x86_64")]
+    use vmm_config::shared_memory::SharedMemoryConfig;
+    use vmm_config::sound::{SoundBackendType, SoundBuilder, SoundDeviceConfig};
     use vmm_config::vsock::tests::{default_config, TempSockFile};
-    use vmm_config::vsock::{VsockBuilder, VsockDeviceConfig};
+    use vmm_config::vsock::{VsockBackendType, VsockBuilder, VsockDeviceConfig};
+    use vmm_config::Identifier;
 
     pub(crate) struct CustomBlockConfig {
         drive_id: String,
@@ -803,7 +2051,7 @@ pub mod tests {
     }
 
     pub(crate) fn default_vmm() -> Vmm {
-        let guest_memory = create_guest_memory(128).unwrap();
+        let guest_memory = create_guest_memory(&VmConfig::default()).unwrap();
         let kernel_cmdline = default_kernel_cmdline();
 
         let exit_evt = EventFd::new(libc::EFD_NONBLOCK)
@@ -811,7 +2059,7 @@ pub mod tests {
             .map_err(StartMicrovmError::Internal)
             .unwrap();
 
-        let vm = setup_kvm_vm(&guest_memory, false).unwrap();
+        let vm = setup_kvm_vm(&guest_memory, false, None).unwrap();
         let mmio_device_manager = default_mmio_device_manager();
         #[cfg(target_arch = "x86_64")]
         let pio_device_manager = default_portio_device_manager();
@@ -821,18 +2069,32 @@ pub mod tests {
             guest_memory,
             kernel_cmdline,
             vcpus_handles: Vec::new(),
+            vcpus_running: false,
+            parked_vcpus: BTreeSet::new(),
             exit_evt,
+            requested_exit_code: Arc::new(Mutex::new(None)),
             vm,
+            events: EventChannel::default(),
+            probe_statuses: ProbeStatuses::default(),
+            probes_running: Arc::new(AtomicBool::new(false)),
+            operations: Operations::new().unwrap(),
+            console_input: None,
             mmio_device_manager,
             #[cfg(target_arch = "x86_64")]
             pio_device_manager,
+            #[cfg(target_arch = "x86_64")]
+            pci_device_manager: None,
+            #[cfg(feature = "sev")]
+            launch_measurement: None,
+            cid_leases: Vec::new(),
+            device_threads: Vec::new(),
         };
 
         #[cfg(target_arch = "x86_64")]
-        setup_interrupt_controller(&mut vmm.vm).unwrap();
+        setup_interrupt_controller(&mut vmm.vm, true).unwrap();
 
         #[cfg(target_arch = "aarch64")]
-        setup_interrupt_controller(&mut vmm.vm, 1).unwrap();
+        setup_interrupt_controller(&mut vmm.vm, 1, &GICConfig::default()).unwrap();
 
         vmm
     }
@@ -847,7 +2109,7 @@ pub mod tests {
         for custom_block_cfg in &custom_block_cfgs {
             block_files.push(TempFile::new().unwrap());
             let block_device_config = BlockDeviceConfig {
-                drive_id: String::from(&custom_block_cfg.drive_id),
+                drive_id: Identifier::try_from(custom_block_cfg.drive_id.as_str()).unwrap(),
                 path_on_host: block_files
                     .last()
                     .unwrap()
@@ -858,7 +2120,15 @@ pub mod tests {
                 is_root_device: custom_block_cfg.is_root_device,
                 partuuid: custom_block_cfg.partuuid.clone(),
                 is_read_only: custom_block_cfg.is_read_only,
+                no_atime: false,
                 rate_limiter: None,
+                encryption: None,
+                verity: None,
+                io_weight: None,
+                serial: None,
+                physical_block_size: None,
+                on_enospc: EnospcPolicy::Error,
+                page_cache: None,
             };
             block_dev_configs.insert(block_device_config).unwrap();
         }
@@ -875,7 +2145,7 @@ pub mod tests {
         let mut net_builder = NetBuilder::new();
         net_builder.build(net_config).unwrap();
 
-        let res = attach_net_devices(vmm, &net_builder, event_manager);
+        let res = attach_net_devices(vmm, &net_builder, event_manager, &[]);
         assert!(res.is_ok());
     }
 
@@ -896,6 +2166,42 @@ pub mod tests {
             .is_some());
     }
 
+    pub(crate) fn insert_balloon_device(
+        vmm: &mut Vmm,
+        event_manager: &mut EventManager,
+        balloon_config: BalloonDeviceConfig,
+    ) {
+        let mut builder = BalloonBuilder::new();
+        assert!(builder.set(balloon_config).is_ok());
+        let balloon = builder.get().unwrap();
+
+        assert!(attach_balloon_device(vmm, balloon, event_manager).is_ok());
+
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(
+                DeviceType::Virtio(TYPE_BALLOON),
+                devices::virtio::balloon::BALLOON_DEV_ID
+            )
+            .is_some());
+    }
+
+    pub(crate) fn insert_entropy_device(vmm: &mut Vmm, event_manager: &mut EventManager) {
+        let mut builder = EntropyBuilder::new();
+        assert!(builder.set(EntropyDeviceConfig {}).is_ok());
+        let entropy = builder.get().unwrap();
+
+        assert!(attach_entropy_device(vmm, entropy, event_manager).is_ok());
+
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(
+                DeviceType::Virtio(TYPE_RNG),
+                devices::virtio::rng::ENTROPY_DEV_ID
+            )
+            .is_some());
+    }
+
     fn make_test_bin() -> Vec<u8> {
         let mut fake_bin = Vec::new();
         fake_bin.resize(1_000_000, 0xAA);
@@ -910,6 +2216,37 @@ pub mod tests {
         create_guest_mem_at(GuestAddress(0x0), size)
     }
 
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_load_kernel() {
+        use std::io::Write;
+
+        let kernel_bytes = include_bytes!("../../kernel/src/loader/test_elf.bin").to_vec();
+        let kernel_file = TempFile::new().unwrap();
+        kernel_file.as_file().write_all(&kernel_bytes).unwrap();
+        let boot_config = |kernel_image| BootConfig {
+            cmdline: Cmdline::new(arch::CMDLINE_MAX_SIZE),
+            kernel_image,
+            initrd_file: None,
+            device_enumeration: MmioDeviceEnumeration::Cmdline,
+        };
+        let gm = create_guest_mem_with_size(0x40_0000);
+
+        // The kernel held in memory is loaded as if it were read from a file.
+        let file_config = boot_config(KernelImage::File(
+            File::open(kernel_file.as_path()).unwrap(),
+        ));
+        let entry_addr = load_kernel(&file_config, &gm).unwrap();
+        let bytes_config = boot_config(KernelImage::Bytes(kernel_bytes.into()));
+        assert_eq!(load_kernel(&bytes_config, &gm).unwrap(), entry_addr);
+
+        let bad_config = boot_config(KernelImage::Bytes(vec![0; 64].into()));
+        match load_kernel(&bad_config, &gm) {
+            Err(StartMicrovmError::KernelLoader(_)) => (),
+            _ => panic!("Loading an invalid kernel should fail."),
+        }
+    }
+
     #[test]
     // Test that loading the initrd is successful on different archs.
     fn test_load_initrd() {
@@ -956,24 +2293,79 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn test_create_guest_memory() {
+        use memory_snapshot::SnapshotMemory;
+
+        let guest_memory = create_guest_memory(&VmConfig::default()).unwrap();
+        assert!(guest_memory.backing_file().is_err());
+
+        let vm_config = VmConfig {
+            memfd_backed: true,
+            ..Default::default()
+        };
+        let guest_memory = create_guest_memory(&vm_config).unwrap();
+        let memory_file = guest_memory.backing_file().unwrap();
+        assert_eq!(memory_file.metadata().unwrap().len(), 128 << 20);
+    }
+
     #[test]
     fn test_stdin_wrapper() {
         let wrapper = SerialStdin::get();
         assert_eq!(wrapper.as_raw_fd(), io::stdin().as_raw_fd())
     }
 
+    #[test]
+    fn test_setup_serial_console() {
+        let mut event_manager = EventManager::new().unwrap();
+        assert!(console_events_observer(&ConsoleConfig::default()).is_some());
+
+        // The terminal is left alone when the input doesn't come from the standard input.
+        let config = ConsoleConfig {
+            input: ConsoleInput::Api,
+            socket_path: None,
+        };
+        assert!(setup_serial_console(&mut event_manager, &config, Box::new(io::sink())).is_ok());
+        assert!(console_events_observer(&config).is_none());
+
+        let socket_file = TempFile::new().unwrap();
+        let socket_path = socket_file.as_path().to_str().unwrap().to_string();
+        let config = ConsoleConfig {
+            input: ConsoleInput::Socket,
+            socket_path: Some(socket_path.clone()),
+        };
+        assert!(setup_serial_console(&mut event_manager, &config, Box::new(io::sink())).is_ok());
+        assert!(console_events_observer(&config).is_none());
+        assert!(std::os::unix::net::UnixStream::connect(&socket_path).is_ok());
+
+        let config = ConsoleConfig {
+            input: ConsoleInput::Socket,
+            socket_path: Some("/invalid/path/console.sock".to_string()),
+        };
+        match setup_serial_console(&mut event_manager, &config, Box::new(io::sink())) {
+            Err(StartMicrovmError::ConsoleSocket(_)) => (),
+            _ => panic!("Unexpected result"),
+        }
+    }
+
     #[test]
     #[cfg(target_arch = "x86_64")]
     fn test_create_vcpus_x86_64() {
         let vcpu_count = 2;
 
-        let guest_memory = create_guest_memory(128).unwrap();
-        let mut vm = setup_kvm_vm(&guest_memory, false).unwrap();
-        setup_interrupt_controller(&mut vm).unwrap();
+        let guest_memory = create_guest_memory(&VmConfig::default()).unwrap();
+        let mut vm = setup_kvm_vm(&guest_memory, false, None).unwrap();
+        setup_interrupt_controller(&mut vm, true).unwrap();
         let vcpu_config = VcpuConfig {
             vcpu_count,
             ht_enabled: false,
+            cores_per_socket: None,
             cpu_template: None,
+            cpu_features: None,
+            nested: false,
+            tsc_khz: None,
+            tickless: false,
+            memory_encryption_mask: 0,
         };
 
         // Dummy entry_addr, vcpus will not boot.
@@ -992,17 +2384,58 @@ pub mod tests {
         assert_eq!(vcpu_vec.len(), vcpu_count as usize);
     }
 
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_setup_tickless_guest() {
+        let guest_memory = create_guest_memory(&VmConfig::default()).unwrap();
+        let mut vm = setup_kvm_vm(&guest_memory, false, None).unwrap();
+        let mut kernel_cmdline = KernelCmdline::new(arch::CMDLINE_MAX_SIZE);
+        kernel_cmdline.insert_str("console=ttyS0").unwrap();
+
+        // The hosts running the tests have the TSC deadline timer.
+        setup_tickless_guest(&vm, &mut kernel_cmdline).unwrap();
+        setup_tickless_guest(&vm, &mut kernel_cmdline).unwrap();
+        assert_eq!(kernel_cmdline.as_str(), "console=ttyS0 no_timer_check");
+
+        setup_interrupt_controller(&mut vm, false).unwrap();
+        assert!(vm.fd().get_pit2().is_err());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_setup_split_interrupt_controller() {
+        use devices::legacy::IOAPIC_START;
+
+        let guest_memory = create_guest_memory(&VmConfig::default()).unwrap();
+        let mut vm = setup_kvm_vm(&guest_memory, false, None).unwrap();
+        let mut mmio_bus = devices::Bus::new();
+        setup_split_interrupt_controller(&mut vm, &mut mmio_bus).unwrap();
+        assert!(vm.split_irqchip());
+
+        // The IOAPIC answers with its version, 0x11, and its 24 pins.
+        assert!(mmio_bus.write(IOAPIC_START, &[1, 0, 0, 0]));
+        let mut version = [0; 4];
+        assert!(mmio_bus.read(IOAPIC_START + 0x10, &mut version));
+        assert_eq!(u32::from_le_bytes(version), 0x0017_0011);
+    }
+
     #[test]
     #[cfg(target_arch = "aarch64")]
     fn test_create_vcpus_aarch64() {
-        let guest_memory = create_guest_memory(128).unwrap();
-        let vm = setup_kvm_vm(&guest_memory, false).unwrap();
+        let guest_memory = create_guest_memory(&VmConfig::default()).unwrap();
+        let vm = setup_kvm_vm(&guest_memory, false, None).unwrap();
         let vcpu_count = 2;
 
         let vcpu_config = VcpuConfig {
             vcpu_count,
             ht_enabled: false,
+            cores_per_socket: None,
             cpu_template: None,
+            cpu_features: None,
+            nested: false,
+            tsc_khz: None,
+            tickless: false,
+            memory_encryption_mask: 0,
         };
 
         // Dummy entry_addr, vcpus will not boot.
@@ -1019,18 +2452,140 @@ pub mod tests {
         assert_eq!(vcpu_vec.len(), vcpu_count as usize);
     }
 
+    #[test]
+    fn test_attach_cloud_init() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vm_resources = super::super::resources::VmResources::default();
+        let mut config = CloudInitConfig {
+            user_data: String::from("#cloud-config\n"),
+            meta_data: None,
+            transport: CloudInitTransport::Seed,
+        };
+
+        let mut vmm = default_vmm();
+        attach_cloud_init(&mut vmm, &vm_resources, &config, &mut event_manager).unwrap();
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_BLOCK), SEED_DRIVE_ID)
+            .is_some());
+        assert!(!vmm.kernel_cmdline.as_str().contains("ds=nocloud-net"));
+
+        // The guest has to reach the MMDS through a network interface.
+        config.transport = CloudInitTransport::Mmds;
+        let mut vmm = default_vmm();
+        match attach_cloud_init(&mut vmm, &vm_resources, &config, &mut event_manager) {
+            Err(StartMicrovmError::CloudInit(CloudInitError::MmdsNotReachable)) => (),
+            _ => panic!("The MMDS should be reachable."),
+        }
+        vm_resources
+            .build_net_device(NetworkInterfaceConfig {
+                iface_id: Identifier::try_from("netif").unwrap(),
+                host_dev_name: String::from("cloudinit"),
+                backend: NetBackendType::Tap,
+                xdp: None,
+                guest_mac: None,
+                mtu: None,
+                rx_rate_limiter: None,
+                tx_rate_limiter: None,
+                allow_mmds_requests: true,
+                allow_promiscuous: false,
+                rx_impairment: None,
+                tx_impairment: None,
+                affinity: None,
+            })
+            .unwrap();
+        attach_cloud_init(&mut vmm, &vm_resources, &config, &mut event_manager).unwrap();
+        assert!(vmm
+            .kernel_cmdline
+            .as_str()
+            .ends_with(" ds=nocloud-net;s=http://169.254.169.254/"));
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_BLOCK), SEED_DRIVE_ID)
+            .is_none());
+    }
+
+    #[test]
+    fn test_fill_kernel_cmdline() {
+        let mut vm_resources = super::super::resources::VmResources::default();
+        let mut cmdline = KernelCmdline::new(arch::CMDLINE_MAX_SIZE);
+        cmdline
+            .insert_str("console=ttyS0 ds=nocloud-net;s=http://${mmds_ip}/")
+            .unwrap();
+        assert_eq!(
+            fill_kernel_cmdline(&cmdline, &vm_resources)
+                .unwrap()
+                .as_str(),
+            "console=ttyS0 ds=nocloud-net;s=http://169.254.169.254/"
+        );
+
+        // The variables without a value can't be filled in.
+        let mut cmdline = KernelCmdline::new(arch::CMDLINE_MAX_SIZE);
+        cmdline
+            .insert_str("hostname=${instance_id} rootdev=${root_device}")
+            .unwrap();
+        match fill_kernel_cmdline(&cmdline, &vm_resources) {
+            Err(StartMicrovmError::KernelCmdline(err)) => assert_eq!(
+                err,
+                "The boot arguments refer to instance_id, which has no value for this microVM."
+            ),
+            _ => panic!("The instance ID should be unavailable."),
+        }
+        vm_resources.set_instance_id(String::from("vm0"));
+        match fill_kernel_cmdline(&cmdline, &vm_resources) {
+            Err(StartMicrovmError::KernelCmdline(err)) => assert_eq!(
+                err,
+                "The boot arguments refer to root_device, which has no value for this microVM."
+            ),
+            _ => panic!("The root device should be unavailable."),
+        }
+
+        let root_file = TempFile::new().unwrap();
+        vm_resources
+            .set_block_device(BlockDeviceConfig {
+                drive_id: Identifier::try_from("root").unwrap(),
+                path_on_host: root_file.as_path().to_str().unwrap().to_string(),
+                is_root_device: true,
+                partuuid: Some(String::from("0eaa91a0-01")),
+                is_read_only: true,
+                no_atime: false,
+                rate_limiter: None,
+                encryption: None,
+                verity: None,
+                io_weight: None,
+                serial: None,
+                physical_block_size: None,
+                on_enospc: EnospcPolicy::Error,
+                page_cache: None,
+            })
+            .unwrap();
+        assert_eq!(
+            fill_kernel_cmdline(&cmdline, &vm_resources)
+                .unwrap()
+                .as_str(),
+            "hostname=vm0 rootdev=PARTUUID=0eaa91a0-01"
+        );
+    }
+
     #[test]
     fn test_attach_net_devices() {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
         let mut vmm = default_vmm();
 
         let network_interface = NetworkInterfaceConfig {
-            iface_id: String::from("netif"),
+            iface_id: Identifier::try_from("netif").unwrap(),
             host_dev_name: String::from("hostname"),
+            backend: NetBackendType::Tap,
+            xdp: None,
             guest_mac: None,
+            mtu: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: true,
+            allow_promiscuous: true,
+            rx_impairment: None,
+            tx_impairment: None,
+            affinity: None,
         };
 
         insert_net_device(&mut vmm, &mut event_manager, network_interface.clone());
@@ -1038,6 +2593,34 @@ pub mod tests {
         // We can not attach it once more.
         let mut net_builder = NetBuilder::new();
         assert!(net_builder.build(network_interface).is_err());
+
+        // The emulation of a device with an affinity runs on a thread of its own.
+        let cpu = unsafe { libc::sched_getcpu() } as usize;
+        let network_interface = NetworkInterfaceConfig {
+            iface_id: Identifier::try_from("netif_pinned").unwrap(),
+            host_dev_name: String::from("hostname_pinned"),
+            backend: NetBackendType::Tap,
+            xdp: None,
+            guest_mac: None,
+            mtu: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            allow_mmds_requests: false,
+            allow_promiscuous: true,
+            rx_impairment: None,
+            tx_impairment: None,
+            affinity: Some(vec![cpu]),
+        };
+        insert_net_device(&mut vmm, &mut event_manager, network_interface);
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_NET), "netif_pinned")
+            .is_some());
+        assert_eq!(vmm.device_threads.len(), 1);
+        vmm.pause_device_threads().unwrap();
+        assert!(vmm.device_threads[0].is_paused());
+        vmm.resume_device_threads().unwrap();
+        assert!(!vmm.device_threads[0].is_paused());
     }
 
     #[test]
@@ -1170,6 +2753,20 @@ pub mod tests {
                 .get_device(DeviceType::Virtio(TYPE_BLOCK), drive_id.as_str())
                 .is_some());
         }
+
+        // Use case 7: the guest kernel finds the devices in its firmware tables.
+        {
+            let block_configs = vec![
+                CustomBlockConfig::new(String::from("root"), true, None, true),
+                CustomBlockConfig::new(String::from("secondary"), false, None, true),
+            ];
+            let mut vmm = default_vmm();
+            vmm.mmio_device_manager
+                .set_device_enumeration(MmioDeviceEnumeration::Firmware);
+            insert_block_devices(&mut vmm, &mut event_manager, block_configs);
+            assert!(vmm.kernel_cmdline.as_str().contains("root=/dev/vda ro"));
+            assert!(!vmm.kernel_cmdline.as_str().contains("virtio_mmio.device"));
+        }
     }
 
     #[test]
@@ -1183,12 +2780,327 @@ pub mod tests {
         insert_vsock_device(&mut vmm, &mut event_manager, vsock_config);
     }
 
+    #[test]
+    fn test_attach_vhost_vsock_device() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+
+        let tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        let mut vsock_config = default_config(&tmp_sock_file);
+        vsock_config.backend = VsockBackendType::Vhost;
+        let vhost_vsock = match VsockBuilder::create_vhost_vsock(vsock_config) {
+            Ok(vhost_vsock) => Arc::new(Mutex::new(vhost_vsock)),
+            // The host may not have the vhost-vsock module loaded.
+            Err(_) => return,
+        };
+
+        assert!(attach_vhost_vsock_device(&mut vmm, &vhost_vsock, &mut event_manager).is_ok());
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_VSOCK), "vsock")
+            .is_some());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_attach_i8042_reset_sink() {
+        use events::{ResetSource, VmmEvent};
+
+        let mut vmm = default_vmm();
+        vmm.pio_device_manager
+            .register_devices(&LegacyDevicesConfig::default())
+            .unwrap();
+        attach_i8042_reset_sink(&vmm);
+
+        // The guest pulses the CPU reset line of the i8042.
+        assert!(vmm.pio_device_manager.io_bus.write(0x64, &[0xFE]));
+        assert_eq!(
+            vmm.drain_events(),
+            vec![VmmEvent::GuestResetRequested {
+                source: ResetSource::I8042PulseOutputLine,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_stop_requester() {
+        let vmm = default_vmm();
+        let stop_requester = vmm.stop_requester().unwrap();
+
+        // The first exit code requested is kept.
+        stop_requester.request_stop(FcExitCode::DriveOutOfSpace);
+        stop_requester.request_stop(FcExitCode::Ok);
+        assert_eq!(vmm.exit_evt.read().unwrap(), 2);
+        assert_eq!(
+            *vmm.requested_exit_code.lock().unwrap(),
+            Some(FcExitCode::DriveOutOfSpace)
+        );
+    }
+
+    #[test]
+    fn test_guest_memory_access() {
+        use guest_dmesg::GuestDmesgError;
+        use guest_memory_access::GuestMemoryAccessError;
+
+        let mut vmm = default_vmm();
+        vmm.write_guest_memory(0x1000, b"config").unwrap();
+        assert_eq!(
+            vmm.read_guest_memory(0x1000, 6).unwrap(),
+            b"config".to_vec()
+        );
+
+        // The guest could observe a range halfway through.
+        vmm.vcpus_running = true;
+        match vmm.read_guest_memory(0x1000, 6) {
+            Err(GuestMemoryAccessError::NotPaused) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        match vmm.write_guest_memory(0x1000, b"config") {
+            Err(GuestMemoryAccessError::NotPaused) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        match vmm.guest_dmesg(None) {
+            Err(GuestDmesgError::NotPaused) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_set_vcpu_state() {
+        use std::sync::mpsc::channel;
+        use std::thread;
+        use vmm_config::machine_config::VcpuStateError;
+        use vstate::{VcpuEvent, VcpuHandle, VcpuResponse};
+
+        // Stands in for a vCPU thread, acknowledging the pause and resume events it gets.
+        fn fake_vcpu_handle() -> (VcpuHandle, Arc<Mutex<Vec<bool>>>) {
+            let (event_sender, event_receiver) = channel();
+            let (response_sender, response_receiver) = channel();
+            let states = Arc::new(Mutex::new(Vec::new()));
+            let thread_states = states.clone();
+            let thread = thread::spawn(move || {
+                while let Ok(event) = event_receiver.recv() {
+                    let (response, paused) = match event {
+                        VcpuEvent::Pause => (VcpuResponse::Paused, true),
+                        VcpuEvent::Resume => (VcpuResponse::Resumed, false),
+                        #[cfg(target_arch = "x86_64")]
+                        VcpuEvent::SaveState => continue,
+                    };
+                    thread_states.lock().unwrap().push(paused);
+                    let _ = response_sender.send(response);
+                }
+            });
+            (
+                VcpuHandle::new(event_sender, response_receiver, thread),
+                states,
+            )
+        }
+
+        Vcpu::register_kick_signal_handler();
+        let mut vmm = default_vmm();
+        assert_eq!(
+            vmm.set_vcpu_state(0, true),
+            Err(VcpuStateError::InvalidVcpuId(0))
+        );
+
+        let (handle0, states0) = fake_vcpu_handle();
+        let (handle1, states1) = fake_vcpu_handle();
+        vmm.vcpus_handles = vec![handle0, handle1];
+        vmm.resume_vcpus().unwrap();
+        assert_eq!(
+            vmm.set_vcpu_state(2, true),
+            Err(VcpuStateError::InvalidVcpuId(2))
+        );
+
+        // The parked vCPU is paused right away, and stays so when the microVM is paused and
+        // resumed.
+        vmm.set_vcpu_state(1, true).unwrap();
+        vmm.set_vcpu_state(1, true).unwrap();
+        assert_eq!(
+            vmm.set_vcpu_state(0, true),
+            Err(VcpuStateError::LastRunningVcpu(0))
+        );
+        vmm.pause_vcpus().unwrap();
+        vmm.resume_vcpus().unwrap();
+        assert_eq!(*states0.lock().unwrap(), vec![false, true, false]);
+        assert_eq!(*states1.lock().unwrap(), vec![false, true]);
+
+        // Unparking a vCPU of a paused microVM leaves it paused until the microVM resumes.
+        vmm.pause_vcpus().unwrap();
+        vmm.set_vcpu_state(1, false).unwrap();
+        assert_eq!(*states1.lock().unwrap(), vec![false, true]);
+        vmm.resume_vcpus().unwrap();
+        assert_eq!(*states1.lock().unwrap(), vec![false, true, false]);
+        assert!(vmm.parked_vcpus.is_empty());
+    }
+
+    #[test]
+    fn test_attach_balloon_device() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+
+        let balloon_config = BalloonDeviceConfig {
+            amount_mib: 0,
+            deflate_on_oom: false,
+            free_page_reporting: false,
+        };
+
+        insert_balloon_device(&mut vmm, &mut event_manager, balloon_config);
+        assert!(vmm.drain_events().is_empty());
+    }
+
+    #[test]
+    fn test_attach_entropy_device() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+
+        insert_entropy_device(&mut vmm, &mut event_manager);
+    }
+
+    #[test]
+    fn test_attach_gpu_device() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+
+        let socket = TempSockFile::new(TempFile::new().unwrap());
+        let mut builder = GpuBuilder::new();
+        builder
+            .set(GpuDeviceConfig {
+                socket_path: socket.path().clone(),
+                width: 640,
+                height: 480,
+                blob: true,
+            })
+            .unwrap();
+        let gpu = builder.get().unwrap();
+
+        assert!(attach_gpu_device(&mut vmm, gpu, &mut event_manager).is_ok());
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(
+                DeviceType::Virtio(TYPE_GPU),
+                devices::virtio::gpu::GPU_DEV_ID
+            )
+            .is_some());
+    }
+
+    #[test]
+    fn test_attach_input_device() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+
+        let mut builder = InputBuilder::new();
+        builder.set(InputDeviceConfig {}).unwrap();
+        let input = builder.get().unwrap();
+
+        assert!(attach_input_device(&mut vmm, input, &mut event_manager).is_ok());
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(
+                DeviceType::Virtio(TYPE_INPUT),
+                devices::virtio::input::INPUT_DEV_ID
+            )
+            .is_some());
+    }
+
+    #[test]
+    fn test_attach_sound_device() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+
+        let file = TempFile::new().unwrap();
+        let mut builder = SoundBuilder::new();
+        builder
+            .set(SoundDeviceConfig {
+                backend: SoundBackendType::File,
+                path: file.as_path().to_str().unwrap().to_string(),
+                rate: 48000,
+                channels: 2,
+            })
+            .unwrap();
+        let sound = builder.get().unwrap();
+
+        assert!(attach_sound_device(&mut vmm, sound, &mut event_manager).is_ok());
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(
+                DeviceType::Virtio(TYPE_SOUND),
+                devices::virtio::sound::SOUND_DEV_ID
+            )
+            .is_some());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_attach_pci_devices() {
+        let mut vmm = default_vmm();
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vm_resources = super::super::resources::VmResources::default();
+
+        // Without passthrough or shared memory devices, there is no PCI bus.
+        let pci_device_manager = attach_pci_devices(
+            &vmm.vm,
+            &vmm.guest_memory,
+            &vm_resources,
+            &vmm.kernel_cmdline,
+            &mut vmm.pio_device_manager.io_bus,
+            &mut vmm.mmio_device_manager.bus,
+            &mut event_manager,
+        )
+        .unwrap();
+        assert!(pci_device_manager.is_none());
+        assert!(vmm
+            .pio_device_manager
+            .io_bus
+            .get_device(devices::pci::PCI_CONFIG_IO_PORT)
+            .is_none());
+
+        // The default kernel command line disables the PCI bus.
+        let mem_file = TempFile::new().unwrap();
+        mem_file.as_file().set_len(0x1000).unwrap();
+        vm_resources
+            .set_shared_memory_device(SharedMemoryConfig {
+                shm_id: "shm".to_string(),
+                mem_path: mem_file.as_path().to_path_buf(),
+                peer_socket: None,
+            })
+            .unwrap();
+        match attach_pci_devices(
+            &vmm.vm,
+            &vmm.guest_memory,
+            &vm_resources,
+            &vmm.kernel_cmdline,
+            &mut vmm.pio_device_manager.io_bus,
+            &mut vmm.mmio_device_manager.bus,
+            &mut event_manager,
+        ) {
+            Err(StartMicrovmError::AttachSharedMemoryDevice(
+                device_manager::pci::Error::PciDisabled,
+            )) => (),
+            _ => panic!("Unexpected result"),
+        }
+    }
+
     #[test]
     fn test_error_messages() {
         use builder::StartMicrovmError::*;
         let err = AttachBlockDevice(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
+        #[cfg(target_arch = "x86_64")]
+        {
+            let err = AttachPciPassthroughDevice(device_manager::pci::Error::BusFull);
+            let _ = format!("{}{:?}", err, err);
+            let err = AttachSharedMemoryDevice(device_manager::pci::Error::BusFull);
+            let _ = format!("{}{:?}", err, err);
+        }
+
+        let err = CloudInit(CloudInitError::MmdsNotReachable);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = ConsoleSocket(io::Error::from_raw_os_error(libc::EADDRINUSE));
+        let _ = format!("{}{:?}", err, err);
+
         let err = CreateNetDevice(devices::virtio::net::Error::EventFd(
             io::Error::from_raw_os_error(0),
         ));
@@ -1197,6 +3109,9 @@ pub mod tests {
         let err = CreateRateLimiter(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
+        let err = InconsistentConfig(ConfigIssues::default());
+        let _ = format!("{}{:?}", err, err);
+
         let err = Internal(Error::Serial(io::Error::from_raw_os_error(0)));
         let _ = format!("{}{:?}", err, err);
 
@@ -1218,17 +3133,48 @@ pub mod tests {
         let err = MissingMemSizeConfig;
         let _ = format!("{}{:?}", err, err);
 
+        let err = NetDeviceThread(DeviceThreadError::Exited);
+        let _ = format!("{}{:?}", err, err);
+
         let err = NetDeviceNotConfigured;
         let _ = format!("{}{:?}", err, err);
 
+        let err = Probes(ProbeError::TimerFd(io::Error::from_raw_os_error(0)));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = RateLimitPolicy(AdaptiveRateLimiterError::TimerFd(
+            io::Error::from_raw_os_error(0),
+        ));
+        let _ = format!("{}{:?}", err, err);
+
         let err = OpenBlockDevice(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
+        let err = RegisterBalloonDevice(device_manager::mmio::Error::EventFd(
+            io::Error::from_raw_os_error(0),
+        ));
+        let _ = format!("{}{:?}", err, err);
+
         let err = RegisterBlockDevice(device_manager::mmio::Error::EventFd(
             io::Error::from_raw_os_error(0),
         ));
         let _ = format!("{}{:?}", err, err);
 
+        let err = RegisterEntropyDevice(device_manager::mmio::Error::EventFd(
+            io::Error::from_raw_os_error(0),
+        ));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = RegisterGpuDevice(device_manager::mmio::Error::EventFd(
+            io::Error::from_raw_os_error(0),
+        ));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = RegisterInputDevice(device_manager::mmio::Error::EventFd(
+            io::Error::from_raw_os_error(0),
+        ));
+        let _ = format!("{}{:?}", err, err);
+
         let err = RegisterEvent(EventManagerError::EpollCreate(
             io::Error::from_raw_os_error(0),
         ));
@@ -1239,6 +3185,11 @@ pub mod tests {
         ));
         let _ = format!("{}{:?}", err, err);
 
+        let err = RegisterSoundDevice(device_manager::mmio::Error::EventFd(
+            io::Error::from_raw_os_error(0),
+        ));
+        let _ = format!("{}{:?}", err, err);
+
         let err = RegisterVsockDevice(device_manager::mmio::Error::EventFd(
             io::Error::from_raw_os_error(0),
         ));
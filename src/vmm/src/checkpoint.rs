@@ -0,0 +1,296 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Saves the state of the microVM to a file periodically, so that a control plane recovering
+//! from a crash can rebuild its view of the instance from the VMM itself.
+//!
+//! The checkpoint holds the configuration of the microVM, rebuilt every time from the resources
+//! it was built from and the devices updated since, along with the devices attached to it and
+//! whether it is paused. The microVMs received on live update or migration have no such
+//! resources, so their checkpoints have no configuration. It is written on the VMM thread every
+//! time the timer expires, to a temporary file synced then renamed over the previous checkpoint,
+//! so that the readers never see a partial one, even after a host crash.
+
+use std::ffi::OsString;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use device_list::DeviceDescription;
+use logger::{Metric, METRICS};
+use polly::event_manager::{EventManager, Subscriber};
+use resources::{VmResources, VmmConfig};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::{EpollEvent, EventSet};
+use utils::time::{get_time, ClockType};
+use Vmm;
+
+/// The version of the layout of the checkpoints, bumped on incompatible changes.
+pub const CHECKPOINT_SCHEMA_VERSION: u32 = 1;
+/// How often the state of the microVM is saved, unless told otherwise.
+pub const DEFAULT_CHECKPOINT_INTERVAL_MS: u64 = 1000;
+
+/// Errors associated with the checkpoints.
+#[derive(Debug)]
+pub enum CheckpointError {
+    /// Cannot serialize the checkpoint.
+    Serialize(serde_json::Error),
+    /// Cannot create the timer of the checkpoints.
+    TimerFd(io::Error),
+    /// Cannot write the checkpoint to the file.
+    Write(PathBuf, io::Error),
+}
+
+impl Display for CheckpointError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::CheckpointError::*;
+        match self {
+            Serialize(err) => write!(f, "Cannot serialize the checkpoint: {}", err),
+            TimerFd(err) => write!(f, "Cannot create the checkpoint timer: {}", err),
+            Write(path, err) => write!(
+                f,
+                "Cannot write the checkpoint to {}: {}",
+                path.display(),
+                err
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use self::CheckpointError::*;
+        match self {
+            Serialize(err) => Some(err),
+            TimerFd(err) => Some(err),
+            Write(_, err) => Some(err),
+        }
+    }
+}
+
+/// Where and how often the state of the microVM is saved.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CheckpointConfig {
+    /// The file holding the last checkpoint.
+    pub path: PathBuf,
+    /// The time between two checkpoints, in milliseconds.
+    pub interval_ms: u64,
+}
+
+/// The state of the microVM, as saved for the control plane.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Checkpoint {
+    /// The version of the layout of the checkpoint.
+    pub schema_version: u32,
+    /// The ID of the microVM.
+    pub instance_id: String,
+    /// When the checkpoint was taken, in microseconds since the Unix epoch.
+    pub timestamp_us: u64,
+    /// Whether the microVM is paused.
+    pub paused: bool,
+    /// The configuration of the microVM, in the format of the configuration file, with the
+    /// backing files, rate limiters and impairments updated after boot. It is missing for the
+    /// microVMs received from another Firecracker process, and only holds what was configured
+    /// before loading the snapshot of the microVMs restored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub configuration: Option<VmmConfig>,
+    /// The devices attached to the microVM.
+    pub devices: Vec<DeviceDescription>,
+}
+
+impl Checkpoint {
+    /// Writes the checkpoint to `path`, replacing the previous one at once.
+    pub fn write(&self, path: &Path) -> Result<(), CheckpointError> {
+        let content = serde_json::to_vec(self).map_err(CheckpointError::Serialize)?;
+        let mut temp_path = OsString::from(path);
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temp_path)
+            .and_then(|mut file| {
+                file.write_all(&content)?;
+                // The rename could otherwise reach the disk before the content.
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&temp_path, path))
+            .map_err(|err| CheckpointError::Write(path.to_path_buf(), err))
+    }
+}
+
+/// Saves the state of the microVM every time its timer expires.
+pub struct Checkpointer {
+    config: CheckpointConfig,
+    timer: TimerFd,
+    vmm: Arc<Mutex<Vmm>>,
+    instance_id: String,
+    vm_resources: Option<Arc<Mutex<VmResources>>>,
+}
+
+impl Checkpointer {
+    /// Creates the checkpointer of the microVM `instance_id`, built from `vm_resources`.
+    pub fn new(
+        config: CheckpointConfig,
+        vmm: Arc<Mutex<Vmm>>,
+        instance_id: String,
+        vm_resources: Option<Arc<Mutex<VmResources>>>,
+    ) -> Result<Self, CheckpointError> {
+        let timer = TimerFd::new_custom(ClockId::Monotonic, true, true)
+            .map_err(CheckpointError::TimerFd)?;
+        Ok(Checkpointer {
+            config,
+            timer,
+            vmm,
+            instance_id,
+            vm_resources,
+        })
+    }
+
+    /// Saves the state of the microVM right away, then every `interval_ms` milliseconds.
+    pub fn start(&mut self) {
+        let interval = Duration::from_millis(self.config.interval_ms);
+        self.timer.set_state(
+            TimerState::Periodic {
+                current: interval,
+                interval,
+            },
+            SetTimeFlags::Default,
+        );
+        self.write_checkpoint();
+    }
+
+    fn write_checkpoint(&mut self) {
+        let configuration = self
+            .vm_resources
+            .as_ref()
+            .map(|vm_resources| VmmConfig::from(&*vm_resources.lock().expect("Poisoned lock")));
+        let checkpoint = {
+            let vmm = self.vmm.lock().expect("Poisoned lock");
+            Checkpoint {
+                schema_version: CHECKPOINT_SCHEMA_VERSION,
+                instance_id: self.instance_id.clone(),
+                timestamp_us: get_time(ClockType::Real) / 1000,
+                paused: !vmm.vcpus_running(),
+                configuration,
+                devices: vmm.list_devices(),
+            }
+        };
+        match checkpoint.write(&self.config.path) {
+            Ok(()) => METRICS.vmm.checkpoints.inc(),
+            Err(err) => {
+                METRICS.vmm.checkpoint_fails.inc();
+                error!("{}", err);
+            }
+        }
+    }
+}
+
+impl Subscriber for Checkpointer {
+    fn process(&mut self, event: &EpollEvent, _: &mut EventManager) {
+        let source = event.fd();
+        let event_set = event.event_set();
+
+        if source == self.timer.as_raw_fd() && event_set == EventSet::IN {
+            self.timer.read();
+            self.write_checkpoint();
+        } else {
+            error!("Spurious EventManager event for handler: Checkpointer");
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        vec![EpollEvent::new(EventSet::IN, self.timer.as_raw_fd() as u64)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use device_list::{DeviceKind, DeviceLifecycleState};
+    use utils::tempdir::TempDir;
+
+    fn checkpoint() -> Checkpoint {
+        Checkpoint {
+            schema_version: CHECKPOINT_SCHEMA_VERSION,
+            instance_id: "vm0".to_string(),
+            timestamp_us: 1_600_000_000_000_000,
+            paused: false,
+            configuration: Some(
+                serde_json::from_str(
+                    r#"{
+                        "boot-source": {"kernel_image_path": "vmlinux"},
+                        "drives": []
+                    }"#,
+                )
+                .unwrap(),
+            ),
+            devices: vec![DeviceDescription {
+                device_type: DeviceKind::Block,
+                id: "rootfs".to_string(),
+                mmio_addr: 0xd000_0000,
+                mmio_len: 0x1000,
+                irq: 5,
+                activated: true,
+                state: DeviceLifecycleState::Activated,
+                backend: None,
+                cmdline: None,
+                guest_name: Some("vda".to_string()),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_write_checkpoint() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join("checkpoint.json");
+
+        let mut checkpoint = checkpoint();
+        checkpoint.write(&path).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Checkpoint>(&content).unwrap(),
+            checkpoint
+        );
+        assert!(!dir.as_path().join("checkpoint.json.tmp").exists());
+
+        // The previous checkpoint is replaced.
+        checkpoint.paused = true;
+        checkpoint.configuration = None;
+        checkpoint.write(&path).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("configuration"));
+        assert_eq!(
+            serde_json::from_str::<Checkpoint>(&content).unwrap(),
+            checkpoint
+        );
+
+        match checkpoint.write(&dir.as_path().join("missing/checkpoint.json")) {
+            Err(CheckpointError::Write(_, _)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let err = CheckpointError::Write(
+            PathBuf::from("/checkpoint.json"),
+            io::Error::from_raw_os_error(libc::EACCES),
+        );
+        assert!(err
+            .to_string()
+            .starts_with("Cannot write the checkpoint to /checkpoint.json: "));
+        let err = CheckpointError::TimerFd(io::Error::from_raw_os_error(libc::EMFILE));
+        assert!(err
+            .to_string()
+            .starts_with("Cannot create the checkpoint timer: "));
+    }
+}
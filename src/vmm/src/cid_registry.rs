@@ -0,0 +1,154 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Keeps track of the vsock guest CIDs leased by the microVMs of a host, so that the VMM can
+//! allocate the CIDs itself instead of the control plane.
+//!
+//! The registry is a directory holding a file per CID leased, named after the CID and holding
+//! the ID of the instance leasing it. The file is locked for as long as the lease is held. The
+//! kernel releases the lock when the Firecracker process exits, even when it crashes, so the
+//! files left behind by the instances gone are taken over by the next ones.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::fs::{FileExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// A guest CID leased from a registry, released when dropped.
+#[derive(Debug)]
+pub struct CidLease {
+    cid: u32,
+    path: PathBuf,
+    // Holds the lock.
+    file: File,
+}
+
+impl CidLease {
+    /// Leases `cid` from the registry at `registry_dir` on behalf of the instance `owner`, or
+    /// returns `None` if another instance leases it.
+    pub fn acquire(registry_dir: &Path, cid: u32, owner: &str) -> io::Result<Option<CidLease>> {
+        let path = registry_dir.join(cid.to_string());
+        let file = loop {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .mode(0o644)
+                .open(&path)?;
+            if !lock(&file, libc::LOCK_EX)? {
+                return Ok(None);
+            }
+            // The previous owner may have removed the file between its opening and its locking,
+            // in which case the lock is worthless.
+            let metadata = file.metadata()?;
+            match fs::metadata(&path) {
+                Ok(current)
+                    if current.dev() == metadata.dev() && current.ino() == metadata.ino() =>
+                {
+                    break file
+                }
+                Ok(_) => continue,
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            }
+        };
+
+        let lease = CidLease { cid, path, file };
+        lease.set_owner(owner)?;
+        Ok(Some(lease))
+    }
+
+    /// Returns the CID leased.
+    pub fn cid(&self) -> u32 {
+        self.cid
+    }
+
+    /// Records `owner` as the ID of the instance leasing the CID.
+    pub fn set_owner(&self, owner: &str) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.write_all_at(format!("{}\n", owner).as_bytes(), 0)
+    }
+}
+
+impl Drop for CidLease {
+    fn drop(&mut self) {
+        // The file is removed while still locked, so that no other instance takes it over
+        // meanwhile. The lock is released when the file is closed.
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Returns the ID of the instance leasing `cid` from the registry at `registry_dir`, if any.
+pub fn owner(registry_dir: &Path, cid: u32) -> io::Result<Option<String>> {
+    let mut file = match File::open(registry_dir.join(cid.to_string())) {
+        Ok(file) => file,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    // The file of an instance gone can be locked, and is dropped right away.
+    if lock(&file, libc::LOCK_SH)? {
+        return Ok(None);
+    }
+    let mut owner = String::new();
+    file.read_to_string(&mut owner)?;
+    Ok(Some(owner.trim_end().to_string()))
+}
+
+// Locks `file` in `mode` without blocking. Returns false if another process holds a lock on it.
+fn lock(file: &File, mode: libc::c_int) -> io::Result<bool> {
+    // Safe because the descriptor is valid, and the return value is checked.
+    if unsafe { libc::flock(file.as_raw_fd(), mode | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+        Ok(false)
+    } else {
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use utils::tempdir::TempDir;
+
+    #[test]
+    fn test_lease() {
+        let registry = TempDir::new().unwrap();
+        let registry_dir = registry.as_path();
+        assert!(owner(registry_dir, 3).unwrap().is_none());
+
+        let lease = CidLease::acquire(registry_dir, 3, "vm0").unwrap().unwrap();
+        assert_eq!(lease.cid(), 3);
+        assert_eq!(owner(registry_dir, 3).unwrap().unwrap(), "vm0");
+        lease.set_owner("vm1").unwrap();
+        assert_eq!(owner(registry_dir, 3).unwrap().unwrap(), "vm1");
+
+        // The locks are per open file, so a second lease conflicts with the first one, just as
+        // the lease of another process would.
+        assert!(CidLease::acquire(registry_dir, 3, "vm2").unwrap().is_none());
+        let other = CidLease::acquire(registry_dir, 4, "vm2").unwrap().unwrap();
+        assert_eq!(owner(registry_dir, 4).unwrap().unwrap(), "vm2");
+
+        // Released leases can be taken over.
+        drop(lease);
+        assert!(!registry_dir.join("3").exists());
+        assert!(owner(registry_dir, 3).unwrap().is_none());
+        let lease = CidLease::acquire(registry_dir, 3, "vm3").unwrap().unwrap();
+        assert_eq!(owner(registry_dir, 3).unwrap().unwrap(), "vm3");
+        drop(other);
+        drop(lease);
+
+        // So can the leases of the instances gone.
+        fs::write(registry_dir.join("5"), "crashed\n").unwrap();
+        assert!(owner(registry_dir, 5).unwrap().is_none());
+        let lease = CidLease::acquire(registry_dir, 5, "vm4").unwrap().unwrap();
+        assert_eq!(owner(registry_dir, 5).unwrap().unwrap(), "vm4");
+        drop(lease);
+
+        assert!(CidLease::acquire(&registry_dir.join("missing"), 3, "vm0").is_err());
+    }
+}
@@ -0,0 +1,501 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Hands the cloud-init NoCloud data to the guest.
+//!
+//! The NoCloud datasource of cloud-init finds its data either on a filesystem labelled `CIDATA`,
+//! holding the `user-data` and `meta-data` files, or at the URL of the `ds=nocloud-net` kernel
+//! parameter. The seed is a FAT12 filesystem built in memory and backing a read-only drive, while
+//! the URL points at the MMDS, where the data is published under the same names.
+
+use std::ffi::CString;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::net::Ipv4Addr;
+use std::os::unix::io::FromRawFd;
+
+use mmds::data_store::Error as MmdsError;
+use mmds::MMDS;
+use vmm_config::cloud_init::CloudInitConfig;
+
+/// The ID of the drive holding the seed.
+pub const SEED_DRIVE_ID: &str = "cidata";
+/// The name of the memory file holding the seed, as the file descriptors of the process show it.
+pub const SEED_DISK_IMAGE_PATH: &str = "memfd:cloud_init_seed";
+
+const SECTOR_SIZE: usize = 512;
+// A single sector holds the root directory, which is more than the label and the files need.
+const ROOT_DIR_ENTRIES: usize = 16;
+const DIR_ENTRY_SIZE: usize = 32;
+// FAT12 filesystems have fewer than 4085 clusters, which is how the guest tells them apart.
+const MAX_CLUSTERS: usize = 4084;
+const MAX_SECTORS_PER_CLUSTER: usize = 128;
+const MEDIA_DESCRIPTOR: u8 = 0xf8;
+const VOLUME_ID: u32 = 0x4349_4441;
+const VOLUME_LABEL: &[u8; 11] = b"CIDATA     ";
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_LONG_NAME: u8 = 0x0f;
+// The last entry of a long name, which comes first in the directory.
+const LAST_LONG_ENTRY: u8 = 0x40;
+// The characters of the long name held by a directory entry.
+const LONG_NAME_CHARS: usize = 13;
+// 1980-01-01, the earliest date of FAT.
+const FAT_EPOCH_DATE: u16 = (1 << 5) | 1;
+const END_OF_CHAIN: u16 = 0xfff;
+
+/// Errors associated with handing the cloud-init data to the guest.
+#[derive(Debug)]
+pub enum CloudInitError {
+    /// Cannot create the memory file holding the seed.
+    CreateSeed(io::Error),
+    /// Cannot publish the data in the MMDS.
+    Mmds(MmdsError),
+    /// No network interface lets the guest reach the MMDS.
+    MmdsNotReachable,
+}
+
+impl Display for CloudInitError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::CloudInitError::*;
+        match self {
+            CreateSeed(err) => write!(f, "Cannot create the cloud-init seed: {}", err),
+            Mmds(err) => write!(f, "Cannot publish the cloud-init data in the MMDS: {}", err),
+            MmdsNotReachable => write!(
+                f,
+                "The cloud-init data is handed over the MMDS, which no network interface allows \
+                 requests to."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CloudInitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use self::CloudInitError::*;
+        match self {
+            CreateSeed(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Creates the NoCloud seed of `config`, in an anonymous memory file (memfd).
+pub fn create_seed(config: &CloudInitConfig) -> Result<File, CloudInitError> {
+    let name = CString::new("cloud_init_seed").expect("Invalid memfd name");
+    // Safe because the name is a valid C string and we check the return value.
+    let fd = unsafe { libc::syscall(libc::SYS_memfd_create, name.as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(CloudInitError::CreateSeed(io::Error::last_os_error()));
+    }
+    // Safe because we own the newly created file descriptor.
+    let mut file = unsafe { File::from_raw_fd(fd as i32) };
+    let image = seed_image(&[
+        SeedFile {
+            name: "user-data",
+            short_name: b"USER-D~1   ",
+            data: config.user_data.as_bytes(),
+        },
+        SeedFile {
+            name: "meta-data",
+            short_name: b"META-D~1   ",
+            data: config.meta_data().as_bytes(),
+        },
+    ]);
+    file.write_all(&image)
+        .and_then(|_| file.seek(SeekFrom::Start(0)))
+        .map_err(CloudInitError::CreateSeed)?;
+    Ok(file)
+}
+
+/// Publishes the data of `config` in the MMDS, as the `user-data` and `meta-data` keys. The
+/// other contents of the MMDS are kept.
+pub fn publish_to_mmds(config: &CloudInitConfig) -> Result<(), CloudInitError> {
+    let data = serde_json::json!({
+        "user-data": config.user_data,
+        "meta-data": config.meta_data(),
+    });
+    let mut mmds = MMDS.lock().expect("Poisoned lock");
+    match mmds.patch_data(data.clone()) {
+        Err(MmdsError::NotInitialized) => mmds.put_data(data),
+        res => res,
+    }
+    .map_err(CloudInitError::Mmds)
+}
+
+/// Returns the kernel parameter pointing the NoCloud datasource at the MMDS, reached at
+/// `mmds_ipv4_addr`.
+pub fn nocloud_net_kernel_param(mmds_ipv4_addr: Ipv4Addr) -> String {
+    format!("ds=nocloud-net;s=http://{}/", mmds_ipv4_addr)
+}
+
+// A file of the seed, in its root directory.
+struct SeedFile<'a> {
+    name: &'a str,
+    // The 8.3 name of the file, alongside its long name.
+    short_name: &'a [u8; 11],
+    data: &'a [u8],
+}
+
+// Builds a FAT12 filesystem labelled `CIDATA`, holding `files` in its root directory.
+fn seed_image(files: &[SeedFile]) -> Vec<u8> {
+    let clusters = |cluster_size: usize| -> usize {
+        files
+            .iter()
+            .map(|file| (file.data.len() + cluster_size - 1) / cluster_size)
+            .sum()
+    };
+    // The smallest clusters which fit the files in a FAT12 filesystem.
+    let sectors_per_cluster = (0..8)
+        .map(|shift| 1 << shift)
+        .find(|sectors| clusters(sectors * SECTOR_SIZE) <= MAX_CLUSTERS)
+        .unwrap_or(MAX_SECTORS_PER_CLUSTER);
+    let cluster_size = sectors_per_cluster * SECTOR_SIZE;
+    let data_clusters = clusters(cluster_size);
+
+    // Each FAT12 entry takes 12 bits, and the first two are reserved.
+    let fat_bytes = ((data_clusters + 2) * 3 + 1) / 2;
+    let fat_sectors = (fat_bytes + SECTOR_SIZE - 1) / SECTOR_SIZE;
+    let fat_size = fat_sectors * SECTOR_SIZE;
+    let root_dir_start = SECTOR_SIZE + 2 * fat_size;
+    let data_start = root_dir_start + ROOT_DIR_ENTRIES * DIR_ENTRY_SIZE;
+    let total_sectors = data_start / SECTOR_SIZE + data_clusters * sectors_per_cluster;
+    let mut image = vec![0u8; total_sectors * SECTOR_SIZE];
+
+    write_boot_sector(
+        &mut image[..SECTOR_SIZE],
+        sectors_per_cluster,
+        fat_sectors,
+        total_sectors,
+    );
+
+    let mut fat = vec![0u8; fat_size];
+    set_fat_entry(&mut fat, 0, 0xf00 | u16::from(MEDIA_DESCRIPTOR));
+    set_fat_entry(&mut fat, 1, END_OF_CHAIN);
+    let mut root_dir = Vec::with_capacity(ROOT_DIR_ENTRIES * DIR_ENTRY_SIZE);
+    root_dir.extend_from_slice(&dir_entry(VOLUME_LABEL, ATTR_VOLUME_ID, 0, 0));
+
+    let mut next_cluster = 2;
+    for file in files {
+        let file_clusters = (file.data.len() + cluster_size - 1) / cluster_size;
+        // Empty files have no cluster.
+        let first_cluster = if file_clusters > 0 { next_cluster } else { 0 };
+        for i in 0..file_clusters {
+            let cluster = next_cluster + i;
+            let next = if i + 1 == file_clusters {
+                END_OF_CHAIN
+            } else {
+                (cluster + 1) as u16
+            };
+            set_fat_entry(&mut fat, cluster, next);
+        }
+        let offset = data_start + (next_cluster - 2) * cluster_size;
+        image[offset..offset + file.data.len()].copy_from_slice(file.data);
+        next_cluster += file_clusters;
+
+        root_dir.extend_from_slice(&long_name_entries(file.name, file.short_name));
+        root_dir.extend_from_slice(&dir_entry(
+            file.short_name,
+            ATTR_READ_ONLY,
+            first_cluster as u16,
+            file.data.len() as u32,
+        ));
+    }
+
+    for i in 0..2 {
+        let offset = SECTOR_SIZE + i * fat_size;
+        image[offset..offset + fat_size].copy_from_slice(&fat);
+    }
+    image[root_dir_start..root_dir_start + root_dir.len()].copy_from_slice(&root_dir);
+    image
+}
+
+// Writes the boot sector, along with the BIOS parameter block describing the filesystem.
+fn write_boot_sector(
+    sector: &mut [u8],
+    sectors_per_cluster: usize,
+    fat_sectors: usize,
+    total_sectors: usize,
+) {
+    sector[0..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+    sector[3..11].copy_from_slice(b"MSWIN4.1");
+    sector[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+    sector[13] = sectors_per_cluster as u8;
+    // The reserved sectors, i.e. the boot sector.
+    sector[14..16].copy_from_slice(&1u16.to_le_bytes());
+    // The number of FATs.
+    sector[16] = 2;
+    sector[17..19].copy_from_slice(&(ROOT_DIR_ENTRIES as u16).to_le_bytes());
+    if total_sectors <= usize::from(u16::max_value()) {
+        sector[19..21].copy_from_slice(&(total_sectors as u16).to_le_bytes());
+    } else {
+        sector[32..36].copy_from_slice(&(total_sectors as u32).to_le_bytes());
+    }
+    sector[21] = MEDIA_DESCRIPTOR;
+    sector[22..24].copy_from_slice(&(fat_sectors as u16).to_le_bytes());
+    // The sectors per track and the heads, which are only relevant to floppy disks.
+    sector[24..26].copy_from_slice(&32u16.to_le_bytes());
+    sector[26..28].copy_from_slice(&64u16.to_le_bytes());
+    // The drive number and the extended boot signature.
+    sector[36] = 0x80;
+    sector[38] = 0x29;
+    sector[39..43].copy_from_slice(&VOLUME_ID.to_le_bytes());
+    sector[43..54].copy_from_slice(VOLUME_LABEL);
+    sector[54..62].copy_from_slice(b"FAT12   ");
+    sector[510..512].copy_from_slice(&[0x55, 0xaa]);
+}
+
+fn set_fat_entry(fat: &mut [u8], cluster: usize, value: u16) {
+    let offset = cluster * 3 / 2;
+    if cluster % 2 == 0 {
+        fat[offset] = value as u8;
+        fat[offset + 1] = (fat[offset + 1] & 0xf0) | ((value >> 8) as u8 & 0x0f);
+    } else {
+        fat[offset] = (fat[offset] & 0x0f) | ((value as u8) << 4);
+        fat[offset + 1] = (value >> 4) as u8;
+    }
+}
+
+fn dir_entry(
+    short_name: &[u8; 11],
+    attributes: u8,
+    first_cluster: u16,
+    size: u32,
+) -> [u8; DIR_ENTRY_SIZE] {
+    let mut entry = [0u8; DIR_ENTRY_SIZE];
+    entry[0..11].copy_from_slice(short_name);
+    entry[11] = attributes;
+    // The creation, access and modification dates.
+    for &offset in &[16, 18, 24] {
+        entry[offset..offset + 2].copy_from_slice(&FAT_EPOCH_DATE.to_le_bytes());
+    }
+    entry[26..28].copy_from_slice(&first_cluster.to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+    entry
+}
+
+// Returns the entries holding the long `name` of the file with `short_name`, in the order they
+// precede its entry in the directory.
+fn long_name_entries(name: &str, short_name: &[u8; 11]) -> Vec<u8> {
+    let mut chars: Vec<u16> = name.encode_utf16().collect();
+    let count = (chars.len() + LONG_NAME_CHARS - 1) / LONG_NAME_CHARS;
+    // The name is terminated by a NUL, unless it fills its last entry, and padded with 0xffff.
+    if chars.len() % LONG_NAME_CHARS != 0 {
+        chars.push(0);
+    }
+    chars.resize(count * LONG_NAME_CHARS, 0xffff);
+
+    let checksum = short_name_checksum(short_name);
+    let mut entries = Vec::with_capacity(count * DIR_ENTRY_SIZE);
+    for seq in (1..=count).rev() {
+        let mut entry = [0u8; DIR_ENTRY_SIZE];
+        entry[0] = seq as u8 | if seq == count { LAST_LONG_ENTRY } else { 0 };
+        entry[11] = ATTR_LONG_NAME;
+        entry[13] = checksum;
+        let entry_chars = &chars[(seq - 1) * LONG_NAME_CHARS..seq * LONG_NAME_CHARS];
+        // The characters are spread over three ranges of the entry.
+        let offsets = (1..11)
+            .step_by(2)
+            .chain((14..26).step_by(2))
+            .chain((28..32).step_by(2));
+        for (offset, c) in offsets.zip(entry_chars) {
+            entry[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+        }
+        entries.extend_from_slice(&entry);
+    }
+    entries
+}
+
+fn short_name_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0u8, |sum, &c| {
+        ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(c)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    use vmm_config::cloud_init::{CloudInitTransport, DEFAULT_META_DATA};
+
+    fn read_u16(image: &[u8], offset: usize) -> usize {
+        usize::from(u16::from_le_bytes([image[offset], image[offset + 1]]))
+    }
+
+    fn fat_entry(fat: &[u8], cluster: usize) -> usize {
+        let entry = read_u16(fat, cluster * 3 / 2);
+        if cluster % 2 == 0 {
+            entry & 0xfff
+        } else {
+            entry >> 4
+        }
+    }
+
+    // Reads the file with `short_name` from the root directory of the FAT12 `image`, along
+    // with its long name.
+    fn read_file(image: &[u8], short_name: &[u8; 11]) -> (String, Vec<u8>) {
+        let sectors_per_cluster = usize::from(image[13]);
+        let fat = &image[SECTOR_SIZE..];
+        let root_dir_start = SECTOR_SIZE * (1 + 2 * read_u16(image, 22));
+        let data_start = root_dir_start + read_u16(image, 17) * DIR_ENTRY_SIZE;
+        let cluster_size = sectors_per_cluster * SECTOR_SIZE;
+
+        let entries: Vec<&[u8]> = image[root_dir_start..data_start]
+            .chunks(DIR_ENTRY_SIZE)
+            .collect();
+        let index = entries
+            .iter()
+            .position(|entry| &entry[0..11] == short_name)
+            .unwrap();
+        let entry = entries[index];
+
+        // The long name entries precede the entry of the file, from the last one.
+        let mut name = Vec::new();
+        for long_entry in entries[..index].iter().rev() {
+            if long_entry[11] != ATTR_LONG_NAME {
+                break;
+            }
+            assert_eq!(long_entry[13], short_name_checksum(short_name));
+            for &offset in [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30].iter() {
+                name.push(read_u16(long_entry, offset) as u16);
+            }
+        }
+        let name: String = String::from_utf16(&name).unwrap();
+        let name = name.trim_end_matches(|c| c == '\0' || c == '\u{ffff}');
+
+        let size = read_u16(entry, 28) | (read_u16(entry, 30) << 16);
+        let mut data = Vec::new();
+        let mut cluster = read_u16(entry, 26);
+        while size > 0 && cluster != usize::from(END_OF_CHAIN) {
+            let offset = data_start + (cluster - 2) * cluster_size;
+            data.extend_from_slice(&image[offset..offset + cluster_size]);
+            cluster = fat_entry(fat, cluster);
+        }
+        data.truncate(size);
+        (name.to_string(), data)
+    }
+
+    #[test]
+    fn test_seed_image() {
+        let user_data = b"#cloud-config\n".repeat(100);
+        let image = seed_image(&[
+            SeedFile {
+                name: "user-data",
+                short_name: b"USER-D~1   ",
+                data: &user_data,
+            },
+            SeedFile {
+                name: "network-config",
+                short_name: b"NETWOR~1   ",
+                data: b"",
+            },
+            SeedFile {
+                name: "meta-data",
+                short_name: b"META-D~1   ",
+                data: DEFAULT_META_DATA.as_bytes(),
+            },
+        ]);
+
+        // One sector per cluster, 3 clusters for the user-data and 1 for the meta-data.
+        assert_eq!(image.len(), (1 + 2 + 1 + 4) * SECTOR_SIZE);
+        assert_eq!(image[13], 1);
+        assert_eq!(&image[43..54], VOLUME_LABEL);
+        assert_eq!(&image[54..62], b"FAT12   ");
+        assert_eq!(&image[510..512], &[0x55, 0xaa]);
+        assert_eq!(fat_entry(&image[SECTOR_SIZE..], 0), 0xff8);
+        // Both FATs are the same.
+        assert_eq!(
+            &image[SECTOR_SIZE..2 * SECTOR_SIZE],
+            &image[2 * SECTOR_SIZE..3 * SECTOR_SIZE]
+        );
+        // The label comes first in the root directory.
+        assert_eq!(&image[3 * SECTOR_SIZE..3 * SECTOR_SIZE + 11], VOLUME_LABEL);
+
+        assert_eq!(
+            read_file(&image, b"USER-D~1   "),
+            (String::from("user-data"), user_data)
+        );
+        assert_eq!(
+            read_file(&image, b"NETWOR~1   "),
+            (String::from("network-config"), vec![])
+        );
+        assert_eq!(
+            read_file(&image, b"META-D~1   "),
+            (
+                String::from("meta-data"),
+                DEFAULT_META_DATA.as_bytes().to_vec()
+            )
+        );
+    }
+
+    #[test]
+    fn test_seed_image_clusters() {
+        // The clusters grow so that the filesystem remains FAT12.
+        let user_data = vec![0xa5; 3 << 20];
+        let image = seed_image(&[SeedFile {
+            name: "user-data",
+            short_name: b"USER-D~1   ",
+            data: &user_data,
+        }]);
+        let sectors_per_cluster = usize::from(image[13]);
+        assert_eq!(sectors_per_cluster, 2);
+        assert_eq!(read_u16(&image, 22), 10);
+        let total_sectors = read_u16(&image, 19);
+        assert_eq!(total_sectors * SECTOR_SIZE, image.len());
+        assert_eq!((total_sectors - 1 - 2 * 10 - 1) / sectors_per_cluster, 3072);
+        assert_eq!(read_file(&image, b"USER-D~1   ").1, user_data);
+    }
+
+    #[test]
+    fn test_create_seed() {
+        let config = CloudInitConfig {
+            user_data: String::from("#!/bin/sh\necho hello\n"),
+            meta_data: None,
+            transport: CloudInitTransport::Seed,
+        };
+        let mut image = Vec::new();
+        create_seed(&config)
+            .unwrap()
+            .read_to_end(&mut image)
+            .unwrap();
+        assert_eq!(
+            read_file(&image, b"USER-D~1   ").1,
+            config.user_data.as_bytes()
+        );
+        assert_eq!(
+            read_file(&image, b"META-D~1   ").1,
+            DEFAULT_META_DATA.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_publish_to_mmds() {
+        let config = CloudInitConfig {
+            user_data: String::from("#cloud-config\n"),
+            meta_data: Some(String::from("instance-id: vm0\n")),
+            transport: CloudInitTransport::Mmds,
+        };
+        MMDS.lock()
+            .unwrap()
+            .put_data(serde_json::json!({"latest": {"instance-id": "i-0"}}))
+            .unwrap();
+        publish_to_mmds(&config).unwrap();
+        assert_eq!(
+            MMDS.lock().unwrap().get_data_str(),
+            serde_json::json!({
+                "latest": {"instance-id": "i-0"},
+                "meta-data": "instance-id: vm0\n",
+                "user-data": "#cloud-config\n"
+            })
+            .to_string()
+        );
+
+        assert_eq!(
+            nocloud_net_kernel_param(Ipv4Addr::new(169, 254, 169, 254)),
+            "ds=nocloud-net;s=http://169.254.169.254/"
+        );
+    }
+}
@@ -0,0 +1,368 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::{self, Read};
+use std::mem;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use devices::legacy::Serial;
+use polly::event_manager::{EventManager, Subscriber};
+use utils::epoll::{EpollEvent, EventSet};
+
+// The states of the terminal the serial console reads from.
+const RELEASED: usize = 0;
+// Being acquired: `SAVED_TERMIOS` is being written.
+const ACQUIRING: usize = 1;
+// The original settings of the terminal are in `SAVED_TERMIOS`, and the terminal is, or is
+// about to be, in raw mode.
+const ACQUIRED: usize = 2;
+
+static CONSOLE_STATE: AtomicUsize = AtomicUsize::new(RELEASED);
+// The terminal, and its settings from before it was acquired. Only written in the `ACQUIRING`
+// state, and only read in the `ACQUIRED` state, so that signal handlers can read it.
+static mut SAVED_TERMIOS: Option<(RawFd, libc::termios)> = None;
+
+/// Switches the terminal on the standard input to raw mode, so that every key press goes to the
+/// serial console, after saving its settings for `release_console()`.
+///
+/// Does nothing when the standard input is not a terminal, or when the console is already
+/// acquired. Until the console is released, Firecracker restores the terminal when it exits,
+/// panics, or gets killed by `SIGSYS`, `SIGBUS` or `SIGSEGV`.
+pub fn acquire_console() -> io::Result<()> {
+    acquire_terminal(libc::STDIN_FILENO)
+}
+
+/// Restores the settings the terminal on the standard input had when `acquire_console()` was
+/// called. Does nothing when the console is not acquired.
+pub fn release_console() -> io::Result<()> {
+    match CONSOLE_STATE.load(Ordering::SeqCst) {
+        RELEASED => return Ok(()),
+        ACQUIRING => return Err(io::Error::from_raw_os_error(libc::EBUSY)),
+        _ => (),
+    }
+    // Safe because `SAVED_TERMIOS` is not written in the `ACQUIRED` state.
+    if let Some((fd, termios)) = unsafe { SAVED_TERMIOS } {
+        // The terminal is restored before leaving the `ACQUIRED` state, so that it is also
+        // restored if Firecracker crashes meanwhile.
+        set_termios(fd, &termios)?;
+    }
+    CONSOLE_STATE.compare_and_swap(ACQUIRED, RELEASED, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Restores the terminal if the console is acquired, on the way out of the process.
+///
+/// Only calls async-signal-safe functions, so that signal handlers can call it. Errors are
+/// ignored, as there is nothing left to do about them.
+pub fn restore_console() {
+    if CONSOLE_STATE.load(Ordering::SeqCst) != ACQUIRED {
+        return;
+    }
+    // Safe because `SAVED_TERMIOS` is not written in the `ACQUIRED` state.
+    if let Some((fd, termios)) = unsafe { SAVED_TERMIOS } {
+        let _ = set_termios(fd, &termios);
+    }
+}
+
+fn acquire_terminal(fd: RawFd) -> io::Result<()> {
+    // Safe because `isatty` doesn't touch memory.
+    if unsafe { libc::isatty(fd) } == 0 {
+        return Ok(());
+    }
+    match CONSOLE_STATE.compare_and_swap(RELEASED, ACQUIRING, Ordering::SeqCst) {
+        RELEASED => (),
+        ACQUIRED => return Ok(()),
+        _ => return Err(io::Error::from_raw_os_error(libc::EBUSY)),
+    }
+
+    // Safe because `termios` is plain data, which `tcgetattr` overwrites.
+    let mut termios: libc::termios = unsafe { mem::zeroed() };
+    // Safe because the kernel only writes to `termios`, which is valid, and we check the result.
+    if unsafe { libc::tcgetattr(fd, &mut termios) } < 0 {
+        let err = io::Error::last_os_error();
+        CONSOLE_STATE.store(RELEASED, Ordering::SeqCst);
+        return Err(err);
+    }
+    // Safe because `SAVED_TERMIOS` is only written in the `ACQUIRING` state, which this
+    // thread holds.
+    unsafe { SAVED_TERMIOS = Some((fd, termios)) };
+    CONSOLE_STATE.store(ACQUIRED, Ordering::SeqCst);
+
+    // Same as `Terminal::set_raw_mode()`: the input is neither buffered, echoed nor turned into
+    // signals, but the output is processed as usual.
+    let mut raw_termios = termios;
+    raw_termios.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+    set_termios(fd, &raw_termios).map_err(|e| {
+        CONSOLE_STATE.store(RELEASED, Ordering::SeqCst);
+        e
+    })
+}
+
+fn set_termios(fd: RawFd, termios: &libc::termios) -> io::Result<()> {
+    // Safe because the kernel only reads `termios`, which is valid, and we check the result.
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, termios) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Listens on a Unix socket and hands the bytes its client sends to the serial console, in
+/// place of the standard input of the Firecracker process.
+///
+/// One client is served at a time: a new connection replaces the previous one, so that a client
+/// which crashed can reconnect right away.
+pub struct ConsoleSocket {
+    listener: UnixListener,
+    stream: Option<UnixStream>,
+    serial: Arc<Mutex<Serial>>,
+}
+
+impl ConsoleSocket {
+    /// Listens on `socket_path`, replacing the socket a previous run left behind. Any other
+    /// kind of file at `socket_path` is left alone, and fails the call.
+    pub fn new(socket_path: &str, serial: Arc<Mutex<Serial>>) -> io::Result<ConsoleSocket> {
+        match std::fs::symlink_metadata(socket_path) {
+            Ok(ref metadata) if metadata.file_type().is_socket() => {
+                std::fs::remove_file(socket_path)?
+            }
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket.", socket_path),
+                ))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        let listener = UnixListener::bind(socket_path)?;
+        listener.set_nonblocking(true)?;
+        Ok(ConsoleSocket {
+            listener,
+            stream: None,
+            serial,
+        })
+    }
+
+    fn accept(&mut self, event_manager: &mut EventManager) {
+        let stream = match self.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("Cannot accept a console connection: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = stream.set_nonblocking(true) {
+            warn!("Cannot set up the console connection: {}", e);
+            return;
+        }
+        self.disconnect(event_manager);
+
+        // The subscriber exists, as the listener was registered through `interest_list()`.
+        let self_subscriber = event_manager.subscriber(self.listener.as_raw_fd()).unwrap();
+        match event_manager.register(
+            stream.as_raw_fd(),
+            EpollEvent::new(EventSet::IN, stream.as_raw_fd() as u64),
+            self_subscriber,
+        ) {
+            Ok(()) => self.stream = Some(stream),
+            Err(e) => warn!("Cannot register the console connection: {:?}", e),
+        }
+    }
+
+    fn disconnect(&mut self, event_manager: &mut EventManager) {
+        if let Some(stream) = self.stream.take() {
+            if let Err(e) = event_manager.unregister(stream.as_raw_fd()) {
+                warn!("Cannot unregister the console connection: {:?}", e);
+            }
+        }
+    }
+
+    // Hands over everything the client sent, and drops the connection once the client closes
+    // it or fails.
+    fn read_input(&mut self, event_manager: &mut EventManager) {
+        let mut buf = [0u8; 64];
+        loop {
+            let count = match self.stream.as_mut().map(|stream| stream.read(&mut buf)) {
+                Some(Ok(count)) if count > 0 => count,
+                Some(Err(ref e)) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Some(Err(ref e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                None => return,
+                result => {
+                    if let Some(Err(e)) = result {
+                        warn!("Cannot read from the console connection: {}", e);
+                    }
+                    self.disconnect(event_manager);
+                    return;
+                }
+            };
+            self.serial
+                .lock()
+                .expect("Poisoned lock")
+                .raw_input(&buf[..count])
+                .unwrap_or_else(|e| warn!("Serial error on input: {}", e));
+        }
+    }
+}
+
+impl Subscriber for ConsoleSocket {
+    fn process(&mut self, event: &EpollEvent, event_manager: &mut EventManager) {
+        let source = event.fd();
+        if source == self.listener.as_raw_fd() {
+            self.accept(event_manager);
+        } else if self
+            .stream
+            .as_ref()
+            .map_or(false, |stream| stream.as_raw_fd() == source)
+        {
+            self.read_input(event_manager);
+        } else {
+            warn!("Console socket: unexpected event from source {}", source);
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        vec![EpollEvent::new(
+            EventSet::IN,
+            self.listener.as_raw_fd() as u64,
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use devices::BusDevice;
+    use utils::eventfd::EventFd;
+    use utils::tempfile::TempFile;
+
+    // The offset of the data register of the serial port, and of its line status register.
+    const DATA: u64 = 0;
+    const LSR: u64 = 5;
+    const LSR_DATA_BIT: u8 = 0x01;
+
+    fn read_serial(serial: &Arc<Mutex<Serial>>) -> Vec<u8> {
+        let mut serial = serial.lock().unwrap();
+        let mut input = Vec::new();
+        let mut data = [0u8];
+        loop {
+            serial.read(LSR, &mut data);
+            if data[0] & LSR_DATA_BIT == 0 {
+                return input;
+            }
+            serial.read(DATA, &mut data);
+            input.push(data[0]);
+        }
+    }
+
+    fn lflag(fd: RawFd) -> libc::tcflag_t {
+        let mut termios: libc::termios = unsafe { mem::zeroed() };
+        assert_eq!(unsafe { libc::tcgetattr(fd, &mut termios) }, 0);
+        termios.c_lflag
+    }
+
+    #[test]
+    fn test_acquire_release_console() {
+        // A pseudo-terminal stands for the terminal on the standard input.
+        let master = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) };
+        assert!(master >= 0);
+        assert_eq!(unsafe { libc::grantpt(master) }, 0);
+        assert_eq!(unsafe { libc::unlockpt(master) }, 0);
+        let mut name = [0 as libc::c_char; 64];
+        assert_eq!(
+            unsafe { libc::ptsname_r(master, name.as_mut_ptr(), name.len()) },
+            0
+        );
+        let terminal = unsafe { libc::open(name.as_ptr(), libc::O_RDWR | libc::O_NOCTTY) };
+        assert!(terminal >= 0);
+        let canon_lflag = lflag(terminal);
+        assert_ne!(canon_lflag & libc::ICANON, 0);
+
+        // Releasing a console which was not acquired does nothing.
+        release_console().unwrap();
+        assert_eq!(lflag(terminal), canon_lflag);
+        // Neither does acquiring something else than a terminal.
+        let file = TempFile::new().unwrap();
+        acquire_terminal(file.as_file().as_raw_fd()).unwrap();
+        assert_eq!(CONSOLE_STATE.load(Ordering::SeqCst), RELEASED);
+
+        acquire_terminal(terminal).unwrap();
+        assert_eq!(
+            lflag(terminal) & (libc::ICANON | libc::ECHO | libc::ISIG),
+            0
+        );
+        // Acquiring the console again keeps the settings saved the first time.
+        acquire_terminal(terminal).unwrap();
+
+        // Restoring the terminal on the way out keeps the console acquired.
+        restore_console();
+        assert_eq!(lflag(terminal), canon_lflag);
+        assert_eq!(CONSOLE_STATE.load(Ordering::SeqCst), ACQUIRED);
+
+        release_console().unwrap();
+        assert_eq!(lflag(terminal), canon_lflag);
+        assert_eq!(CONSOLE_STATE.load(Ordering::SeqCst), RELEASED);
+        // Once released, the terminal is left alone on the way out.
+        let mut termios: libc::termios = unsafe { mem::zeroed() };
+        assert_eq!(unsafe { libc::tcgetattr(terminal, &mut termios) }, 0);
+        termios.c_lflag &= !libc::ECHO;
+        set_termios(terminal, &termios).unwrap();
+        restore_console();
+        assert_eq!(lflag(terminal) & libc::ECHO, 0);
+
+        unsafe {
+            libc::close(terminal);
+            libc::close(master);
+        }
+    }
+
+    #[test]
+    fn test_console_socket() {
+        let mut socket_file = TempFile::new().unwrap();
+        let socket_path = socket_file.as_path().to_str().unwrap().to_string();
+        let serial = Arc::new(Mutex::new(Serial::new_sink(
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        )));
+        let mut event_manager = EventManager::new().unwrap();
+        // Only a socket left behind at the socket path gets replaced.
+        assert_eq!(
+            ConsoleSocket::new(&socket_path, serial.clone())
+                .err()
+                .unwrap()
+                .kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        assert!(socket_file.as_path().exists());
+        socket_file.remove().unwrap();
+        drop(UnixListener::bind(&socket_path).unwrap());
+        let console_socket = ConsoleSocket::new(&socket_path, serial.clone()).unwrap();
+        event_manager
+            .add_subscriber(Arc::new(Mutex::new(console_socket)))
+            .unwrap();
+
+        let mut client = UnixStream::connect(&socket_path).unwrap();
+        assert_eq!(event_manager.run_with_timeout(100).unwrap(), 1);
+        client.write_all(b"root\n").unwrap();
+        assert_eq!(event_manager.run_with_timeout(100).unwrap(), 1);
+        assert_eq!(read_serial(&serial), b"root\n");
+
+        // A new client replaces the previous one, which gets disconnected.
+        let mut other_client = UnixStream::connect(&socket_path).unwrap();
+        assert_eq!(event_manager.run_with_timeout(100).unwrap(), 1);
+        assert_eq!(client.read(&mut [0u8; 1]).unwrap(), 0);
+        other_client.write_all(b"ls\n").unwrap();
+        assert_eq!(event_manager.run_with_timeout(100).unwrap(), 1);
+        assert_eq!(read_serial(&serial), b"ls\n");
+
+        // The connection is dropped once the client closes it.
+        drop(other_client);
+        assert_eq!(event_manager.run_with_timeout(100).unwrap(), 1);
+        assert_eq!(event_manager.run_with_timeout(10).unwrap(), 0);
+    }
+}
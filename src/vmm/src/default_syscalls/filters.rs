@@ -21,6 +21,8 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
             allow_syscall(libc::SYS_accept4),
             allow_syscall(libc::SYS_brk),
             allow_syscall(libc::SYS_clock_gettime),
+            // Needed for the vCPUs backing off before retrying KVM_RUN.
+            allow_syscall(libc::SYS_clock_nanosleep),
             allow_syscall(libc::SYS_close),
             allow_syscall(libc::SYS_connect),
             allow_syscall(libc::SYS_dup),
@@ -30,6 +32,8 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
             allow_syscall(libc::SYS_epoll_wait),
             allow_syscall(libc::SYS_exit),
             allow_syscall(libc::SYS_exit_group),
+            // Needed for the page cache hints about the backing files of the drives.
+            allow_syscall(libc::SYS_fadvise64),
             allow_syscall_if(
                 libc::SYS_fcntl,
                 or![and![
@@ -38,6 +42,10 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
                 ]],
             ),
             allow_syscall(libc::SYS_fstat),
+            // Needed for flushing the backing file of a drive before replacing it.
+            allow_syscall(libc::SYS_fsync),
+            // Needed for sizing the sparse guest memory file of diff snapshots.
+            allow_syscall(libc::SYS_ftruncate),
             allow_syscall_if(
                 libc::SYS_futex,
                 or![
@@ -58,7 +66,17 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
                     )?],
                 ],
             ),
+            // Needed for counting the file descriptors of the process.
+            allow_syscall(libc::SYS_getdents64),
             allow_syscall(libc::SYS_getrandom),
+            // Needed for exporting the metrics over TCP.
+            allow_syscall_if(
+                libc::SYS_getsockopt,
+                or![and![
+                    Cond::new(1, ArgLen::DWORD, Eq, libc::SOL_SOCKET as u64)?,
+                    Cond::new(2, ArgLen::DWORD, Eq, libc::SO_ERROR as u64)?,
+                ]],
+            ),
             allow_syscall_if(libc::SYS_ioctl, super::create_ioctl_seccomp_rule()?),
             allow_syscall(libc::SYS_lseek),
             #[cfg(target_env = "musl")]
@@ -74,6 +92,8 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
             allow_syscall(libc::SYS_mmap),
             allow_syscall(libc::SYS_mremap),
             allow_syscall(libc::SYS_munmap),
+            // Needed for the vCPUs backing off before retrying KVM_RUN.
+            allow_syscall(libc::SYS_nanosleep),
             #[cfg(target_arch = "aarch64")]
             allow_syscall(libc::SYS_newfstatat),
             #[cfg(target_arch = "x86_64")]
@@ -81,16 +101,67 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
             allow_syscall(libc::SYS_openat),
             #[cfg(target_arch = "x86_64")]
             allow_syscall(libc::SYS_pipe),
+            // Needed for exporting the metrics over TCP, with a connection timeout.
+            #[cfg(target_arch = "x86_64")]
+            allow_syscall(libc::SYS_poll),
+            #[cfg(target_arch = "aarch64")]
+            allow_syscall(libc::SYS_ppoll),
+            // Needed for reading the guest pages of a snapshot restored through userfaultfd, the
+            // regions of the PCI passthrough devices, and the backing files of the drives being
+            // copied.
+            allow_syscall(libc::SYS_pread64),
+            // Needed for writing the regions of the PCI passthrough devices, and the copies of
+            // the backing files of the drives.
+            allow_syscall(libc::SYS_pwrite64),
             allow_syscall(libc::SYS_read),
+            // Needed for counting the file descriptors of the process.
+            #[cfg(target_arch = "x86_64")]
+            allow_syscall(libc::SYS_readlink),
+            #[cfg(target_arch = "aarch64")]
+            allow_syscall(libc::SYS_readlinkat),
             allow_syscall(libc::SYS_readv),
             allow_syscall(libc::SYS_recvfrom),
+            // Needed for replacing the checkpoint of the microVM state at once.
+            #[cfg(target_arch = "x86_64")]
+            allow_syscall(libc::SYS_rename),
+            #[cfg(target_arch = "aarch64")]
+            allow_syscall(libc::SYS_renameat),
             // SYS_rt_sigreturn is needed in case a fault does occur, so that the signal handler
             // can return. Otherwise we get stuck in a fault loop.
             allow_syscall(libc::SYS_rt_sigreturn),
+            // Needed for handing over the guest memory file on live update.
+            allow_syscall(libc::SYS_sendmsg),
+            // Needed for exporting the metrics.
+            allow_syscall(libc::SYS_sendto),
             allow_syscall(libc::SYS_sigaltstack),
+            // Needed for exporting the metrics over TCP.
+            allow_syscall_if(
+                libc::SYS_setsockopt,
+                or![
+                    and![
+                        Cond::new(1, ArgLen::DWORD, Eq, libc::SOL_SOCKET as u64)?,
+                        Cond::new(2, ArgLen::DWORD, Eq, libc::SO_RCVTIMEO as u64)?,
+                    ],
+                    and![
+                        Cond::new(1, ArgLen::DWORD, Eq, libc::SOL_SOCKET as u64)?,
+                        Cond::new(2, ArgLen::DWORD, Eq, libc::SO_SNDTIMEO as u64)?,
+                    ],
+                ],
+            ),
             allow_syscall_if(
                 libc::SYS_socket,
-                or![and![Cond::new(0, ArgLen::DWORD, Eq, libc::AF_UNIX as u64)?],],
+                or![
+                    and![Cond::new(0, ArgLen::DWORD, Eq, libc::AF_UNIX as u64)?],
+                    // Needed for exporting the metrics over TCP.
+                    and![
+                        Cond::new(0, ArgLen::DWORD, Eq, libc::AF_INET as u64)?,
+                        Cond::new(1, ArgLen::DWORD, Eq, super::SOCK_STREAM_CLOEXEC)?,
+                    ],
+                    and![
+                        Cond::new(0, ArgLen::DWORD, Eq, libc::AF_INET6 as u64)?,
+                        Cond::new(1, ArgLen::DWORD, Eq, super::SOCK_STREAM_CLOEXEC)?,
+                    ],
+                ],
             ),
             #[cfg(target_arch = "x86_64")]
             allow_syscall(libc::SYS_stat),
@@ -105,6 +176,11 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
             ),
             allow_syscall(libc::SYS_timerfd_create),
             allow_syscall(libc::SYS_timerfd_settime),
+            // Needed for removing the partial copies of the backing files of the drives.
+            #[cfg(target_arch = "x86_64")]
+            allow_syscall(libc::SYS_unlink),
+            #[cfg(target_arch = "aarch64")]
+            allow_syscall(libc::SYS_unlinkat),
             allow_syscall(libc::SYS_write),
             allow_syscall(libc::SYS_writev),
         ]
@@ -5,6 +5,7 @@ use seccomp::{
     Error, SeccompAction, SeccompCmpArgLen as ArgLen, SeccompCmpOp::Eq, SeccompCondition as Cond,
     SeccompRule,
 };
+use vstate::{KVM_GET_TSC_KHZ, KVM_SET_GSI_ROUTING, KVM_SET_TSC_KHZ};
 
 #[macro_use]
 mod macros;
@@ -17,6 +18,9 @@ pub use self::filters::get_seccomp_filter;
 const FCNTL_FD_CLOEXEC: u64 = 1;
 const FCNTL_F_SETFD: u64 = 2;
 
+// See include/linux/net.h in the kernel code: SOCK_STREAM | SOCK_CLOEXEC.
+const SOCK_STREAM_CLOEXEC: u64 = 0x8_0001;
+
 // See include/uapi/linux/futex.h in the kernel code.
 const FUTEX_WAIT: u64 = 0;
 const FUTEX_WAKE: u64 = 1;
@@ -37,6 +41,9 @@ const TIOCGWINSZ: u64 = 0x5413;
 const FIOCLEX: u64 = 0x5451;
 const FIONBIO: u64 = 0x5421;
 
+// See include/uapi/linux/fs.h in the kernel code.
+const FICLONE: u64 = 0x4004_9409;
+
 // See include/uapi/linux/if_tun.h in the kernel code.
 const KVM_GET_API_VERSION: u64 = 0xae00;
 const KVM_CREATE_VM: u64 = 0xae01;
@@ -48,6 +55,7 @@ const KVM_CREATE_IRQCHIP: u64 = 0xae60;
 const KVM_RUN: u64 = 0xae80;
 const KVM_SET_MSRS: u64 = 0x4008_ae89;
 const KVM_SET_CPUID2: u64 = 0x4008_ae90;
+const KVM_GET_DIRTY_LOG: u64 = 0x4010_ae42;
 const KVM_SET_USER_MEMORY_REGION: u64 = 0x4020_ae46;
 const KVM_IRQFD: u64 = 0x4020_ae76;
 const KVM_CREATE_PIT2: u64 = 0x4040_ae77;
@@ -56,17 +64,49 @@ const KVM_SET_REGS: u64 = 0x4090_ae82;
 const KVM_SET_SREGS: u64 = 0x4138_ae84;
 const KVM_SET_FPU: u64 = 0x41a0_ae8d;
 const KVM_SET_LAPIC: u64 = 0x4400_ae8f;
+const KVM_GET_MP_STATE: u64 = 0x8004_ae98;
+const KVM_GET_CLOCK: u64 = 0x8030_ae7c;
+const KVM_GET_VCPU_EVENTS: u64 = 0x8040_ae9f;
+const KVM_GET_PIT2: u64 = 0x8070_ae9f;
+const KVM_GET_DEBUGREGS: u64 = 0x8080_aea1;
+const KVM_GET_REGS: u64 = 0x8090_ae81;
 const KVM_GET_SREGS: u64 = 0x8138_ae83;
+const KVM_GET_XCRS: u64 = 0x8188_aea6;
 const KVM_GET_LAPIC: u64 = 0x8400_ae8e;
+const KVM_GET_XSAVE: u64 = 0x9000_aea4;
+const KVM_GET_MSRS: u64 = 0xc008_ae88;
 const KVM_GET_SUPPORTED_CPUID: u64 = 0xc008_ae05;
+const KVM_GET_IRQCHIP: u64 = 0xc208_ae62;
+#[cfg(feature = "sev")]
+const KVM_MEMORY_ENCRYPT_OP: u64 = 0xc008_aeba;
+#[cfg(feature = "sev")]
+const KVM_MEMORY_ENCRYPT_REG_REGION: u64 = 0x8010_aebb;
+
+// See include/uapi/linux/userfaultfd.h in the kernel code.
+const UFFDIO_COPY: u64 = 0xc028_aa03;
+const UFFDIO_ZEROPAGE: u64 = 0xc020_aa04;
 
 // See include/uapi/linux/if_tun.h in the kernel code.
 const TUNSETIFF: u64 = 0x4004_54ca;
 const TUNSETOFFLOAD: u64 = 0x4004_54d0;
 const TUNSETVNETHDRSZ: u64 = 0x4004_54d8;
 
+// See include/uapi/linux/vhost.h in the kernel code.
+const VHOST_GET_FEATURES: u64 = 0x8008_af00;
+const VHOST_SET_FEATURES: u64 = 0x4008_af00;
+const VHOST_SET_OWNER: u64 = 0xaf01;
+const VHOST_SET_MEM_TABLE: u64 = 0x4008_af03;
+const VHOST_SET_VRING_NUM: u64 = 0x4008_af10;
+const VHOST_SET_VRING_ADDR: u64 = 0x4028_af11;
+const VHOST_SET_VRING_BASE: u64 = 0x4008_af12;
+const VHOST_SET_VRING_KICK: u64 = 0x4008_af20;
+const VHOST_SET_VRING_CALL: u64 = 0x4008_af21;
+const VHOST_VSOCK_SET_GUEST_CID: u64 = 0x4008_af60;
+const VHOST_VSOCK_SET_RUNNING: u64 = 0x4004_af61;
+
 fn create_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, Error> {
-    Ok(or![
+    #[allow(unused_mut)]
+    let mut rules = or![
         and![Cond::new(1, ArgLen::DWORD, Eq, TCSETS)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, TCGETS)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, TIOCGWINSZ)?],
@@ -84,11 +124,27 @@ fn create_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, Error> {
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_USER_MEMORY_REGION,)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, FIOCLEX)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, FIONBIO)?],
+        // Needed for reflinking the backing files of the drives.
+        and![Cond::new(1, ArgLen::DWORD, Eq, FICLONE)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, TUNSETIFF)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, TUNSETOFFLOAD)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, TUNSETVNETHDRSZ)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_LAPIC)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_SREGS)?],
+        // Needed for saving the microVM state.
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_CLOCK)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_DEBUGREGS)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_IRQCHIP)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_MP_STATE)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_MSRS)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_PIT2)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_REGS)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_VCPU_EVENTS)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_XCRS)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_XSAVE)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_TSC_KHZ)?],
+        // Needed for tracking the guest pages dirtied during migration.
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_DIRTY_LOG)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_RUN)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_CPUID2)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_FPU)?],
@@ -96,7 +152,39 @@ fn create_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, Error> {
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_MSRS)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_REGS)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_SREGS)?],
-    ])
+        // Needed by the vCPUs routing the pins of the IOAPIC the guest reprograms. The irqchip
+        // is split before the filter is installed.
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_GSI_ROUTING)?],
+        // Needed for pinning the TSC frequency of the vCPUs.
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_TSC_KHZ)?],
+        // Needed for serving the guest pages of a snapshot restored through userfaultfd.
+        and![Cond::new(1, ArgLen::DWORD, Eq, UFFDIO_COPY)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, UFFDIO_ZEROPAGE)?],
+        // Needed for setting up the vhost-vsock device.
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_GET_FEATURES)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_FEATURES)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_OWNER)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_MEM_TABLE)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_VRING_NUM)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_VRING_ADDR)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_VRING_BASE)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_VRING_KICK)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_VRING_CALL)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_VSOCK_SET_GUEST_CID)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_VSOCK_SET_RUNNING)?],
+    ];
+    // Needed for launching SEV guests.
+    #[cfg(feature = "sev")]
+    rules.extend(or![
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_MEMORY_ENCRYPT_OP)?],
+        and![Cond::new(
+            1,
+            ArgLen::DWORD,
+            Eq,
+            KVM_MEMORY_ENCRYPT_REG_REGION
+        )?],
+    ]);
+    Ok(rules)
 }
 
 #[cfg(test)]
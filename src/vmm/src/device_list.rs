@@ -0,0 +1,948 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Describes the devices attached to the microVM, the connections of the vsock device and the
+//! state of the rate limiters, so that the control plane can introspect a running VMM instead of
+//! reconstructing its state from the configuration it sent. The whole state of a device can be
+//! dumped too, for inspecting offline a device which stopped serving the guest.
+
+use std::collections::BTreeMap;
+
+use arch::DeviceType;
+use device_manager::mmio::{
+    virtio_block_device_name, virtio_mmio_cmdline_value, MMIODeviceManager,
+};
+use devices::virtio::{
+    Block, DeviceLifecycle, Gpu, MmioTransport, Net, QueueInfo, SinkKind, Sound, VirtioDevice,
+    Vsock, VsockConnState, VsockConnectionInfo, VsockUnixBackend, TYPE_BALLOON, TYPE_BLOCK,
+    TYPE_GPU, TYPE_INPUT, TYPE_NET, TYPE_RNG, TYPE_SOUND, TYPE_VSOCK,
+};
+use rate_limiter::{RateLimiter, TokenBucketStats};
+use vmm_config::boot_source::MmioDeviceEnumeration;
+
+/// The kind of an attached device.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceKind {
+    /// Virtio balloon device.
+    Balloon,
+    /// Virtio block device.
+    Block,
+    /// Virtio entropy device.
+    Entropy,
+    /// Virtio GPU device.
+    Gpu,
+    /// Virtio input device.
+    Input,
+    /// Virtio net device.
+    Net,
+    /// Virtio sound device.
+    Sound,
+    /// Virtio vsock device.
+    Vsock,
+    /// Serial console.
+    #[cfg(target_arch = "aarch64")]
+    Serial,
+    /// Real-time clock.
+    #[cfg(target_arch = "aarch64")]
+    Rtc,
+}
+
+/// Where an attached device is in its lifecycle.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceLifecycleState {
+    /// The guest driver is yet to activate the device.
+    Inactive,
+    /// The device serves the guest driver. Non-virtio devices are always active.
+    Activated,
+    /// The device is activated, but holds back the requests of the guest driver.
+    Paused,
+    /// The guest driver reset the device and is yet to activate it again.
+    Reset,
+    /// The guest driver marked the device as failed, or reset a device not supporting it.
+    Failed,
+}
+
+impl From<DeviceLifecycle> for DeviceLifecycleState {
+    fn from(lifecycle: DeviceLifecycle) -> Self {
+        match lifecycle {
+            DeviceLifecycle::Inactive => DeviceLifecycleState::Inactive,
+            DeviceLifecycle::Activated => DeviceLifecycleState::Activated,
+            DeviceLifecycle::Paused => DeviceLifecycleState::Paused,
+            DeviceLifecycle::Reset => DeviceLifecycleState::Reset,
+            DeviceLifecycle::Failed => DeviceLifecycleState::Failed,
+        }
+    }
+}
+
+/// The host resource backing an attached device.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DeviceBackend {
+    /// A host file, block device or FIFO.
+    File {
+        /// Path of the file on the host.
+        path_on_host: String,
+    },
+    /// A host tap interface.
+    Tap {
+        /// Name of the tap interface.
+        host_dev_name: String,
+    },
+    /// An `AF_XDP` socket, bound to a queue of a host NIC.
+    Xdp {
+        /// Name of the NIC.
+        host_dev_name: String,
+    },
+    /// A host Unix domain socket, proxying the guest vsock connections, serving the GPU
+    /// scanout or receiving the sound samples.
+    Uds {
+        /// Path of the socket on the host.
+        uds_path: String,
+    },
+    /// The host kernel vhost-vsock device.
+    Vhost,
+}
+
+/// Description of a device attached to the microVM.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DeviceDescription {
+    /// The kind of device.
+    pub device_type: DeviceKind,
+    /// The device ID, as configured through the API.
+    pub id: String,
+    /// Base address of the device MMIO region.
+    pub mmio_addr: u64,
+    /// Length of the device MMIO region.
+    pub mmio_len: u64,
+    /// The device interrupt line.
+    pub irq: u32,
+    /// Whether the guest driver activated the device. Non-virtio devices are always active.
+    pub activated: bool,
+    /// Where the device is in its lifecycle.
+    pub state: DeviceLifecycleState,
+    /// The host resource backing the device, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<DeviceBackend>,
+    /// The kernel command line parameter telling the guest kernel about the device, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cmdline: Option<String>,
+    /// The name the guest kernel gives the block device, e.g. `vda`, unless the guest kernel
+    /// finds the devices in its firmware tables.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guest_name: Option<String>,
+}
+
+/// The state of a vsock connection.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VsockConnectionState {
+    /// A host process requested the connection, which the guest is yet to accept.
+    HostInit,
+    /// The guest requested the connection, which is yet to be acknowledged.
+    GuestInit,
+    /// Data can be exchanged.
+    Established,
+    /// The host end closed the connection.
+    HostClosed,
+    /// The guest shut the connection down.
+    GuestClosed,
+    /// The connection is about to be reset.
+    Killed,
+}
+
+/// Description of a connection proxied by a vsock device between a guest `AF_VSOCK` socket
+/// and a host Unix socket.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct VsockConnectionDescription {
+    /// The ID of the vsock device.
+    pub vsock_id: String,
+    /// The host port, to which guest-initiated connections are proxied as `<uds_path>_<port>`.
+    pub host_port: u32,
+    /// The guest port.
+    pub guest_port: u32,
+    /// The connection state.
+    pub state: VsockConnectionState,
+    /// Number of bytes sent to the guest, modulo 2^32.
+    pub bytes_to_guest: u32,
+    /// Number of bytes sent by the guest and written to the host socket, modulo 2^32.
+    pub bytes_to_host: u32,
+    /// Number of bytes sent by the guest and waiting for the host socket to accept them.
+    pub pending_bytes_to_host: usize,
+}
+
+impl VsockConnectionDescription {
+    /// Describes a connection of the vsock device `vsock_id`.
+    pub fn new(vsock_id: &str, info: VsockConnectionInfo) -> Self {
+        let state = match info.state {
+            VsockConnState::LocalInit => VsockConnectionState::HostInit,
+            VsockConnState::PeerInit => VsockConnectionState::GuestInit,
+            VsockConnState::Established => VsockConnectionState::Established,
+            VsockConnState::LocalClosed => VsockConnectionState::HostClosed,
+            VsockConnState::PeerClosed(_, _) => VsockConnectionState::GuestClosed,
+            VsockConnState::Killed => VsockConnectionState::Killed,
+        };
+        VsockConnectionDescription {
+            vsock_id: vsock_id.to_string(),
+            host_port: info.local_port,
+            guest_port: info.peer_port,
+            state,
+            bytes_to_guest: info.rx_cnt,
+            bytes_to_host: info.fwd_cnt,
+            pending_bytes_to_host: info.tx_buf_len,
+        }
+    }
+}
+
+/// The I/O path limited by a rate limiter.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimiterPath {
+    /// The requests of a block device.
+    Io,
+    /// The frames received by a network interface.
+    Rx,
+    /// The frames transmitted by a network interface.
+    Tx,
+}
+
+/// The state of a token bucket of a rate limiter.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TokenBucketDescription {
+    /// The total number of tokens of the bucket.
+    pub size: u64,
+    /// The tokens currently available, one time burst notwithstanding.
+    pub budget: u64,
+    /// The remaining one time burst.
+    pub one_time_burst: u64,
+    /// The time in milliseconds the bucket takes to refill completely.
+    pub refill_time: u64,
+}
+
+impl From<TokenBucketStats> for TokenBucketDescription {
+    fn from(stats: TokenBucketStats) -> Self {
+        TokenBucketDescription {
+            size: stats.size,
+            budget: stats.budget,
+            one_time_burst: stats.one_time_burst,
+            refill_time: stats.refill_time_ms,
+        }
+    }
+}
+
+/// Description of the state of a rate limiter in effect.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RateLimiterDescription {
+    /// The kind of device limited.
+    pub device_type: DeviceKind,
+    /// The device ID, as configured through the API.
+    pub id: String,
+    /// The I/O path limited.
+    pub path: RateLimiterPath,
+    /// The bandwidth token bucket, in bytes, if bandwidth is limited.
+    pub bandwidth: Option<TokenBucketDescription>,
+    /// The ops token bucket, if operations are limited.
+    pub ops: Option<TokenBucketDescription>,
+    /// Whether the device is currently waiting for the buckets to refill.
+    pub blocked: bool,
+    /// Number of times the device waited for the buckets to refill.
+    pub blocked_count: u64,
+    /// Total time the device waited for the buckets to refill, in microseconds.
+    pub blocked_time_us: u64,
+}
+
+impl RateLimiterDescription {
+    // Describes `rate_limiter`, unless it limits nothing.
+    fn new(
+        device_type: DeviceKind,
+        id: &str,
+        path: RateLimiterPath,
+        rate_limiter: &RateLimiter,
+    ) -> Option<Self> {
+        let stats = rate_limiter.stats();
+        if stats.bandwidth.is_none() && stats.ops.is_none() {
+            return None;
+        }
+        Some(RateLimiterDescription {
+            device_type,
+            id: id.to_string(),
+            path,
+            bandwidth: stats.bandwidth.map(TokenBucketDescription::from),
+            ops: stats.ops.map(TokenBucketDescription::from),
+            blocked: stats.blocked,
+            blocked_count: stats.blocked_count,
+            blocked_time_us: stats.blocked_time.as_micros() as u64,
+        })
+    }
+}
+
+/// The state of a virtio queue of a device.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct QueueStateDump {
+    /// The maximal size in elements offered by the device.
+    pub max_size: u16,
+    /// The queue size in elements the driver selected.
+    pub size: u16,
+    /// Whether the driver finished configuring the queue.
+    pub ready: bool,
+    /// Guest physical address of the descriptor table.
+    pub desc_table: u64,
+    /// Guest physical address of the available ring.
+    pub avail_ring: u64,
+    /// Guest physical address of the used ring.
+    pub used_ring: u64,
+    /// The next entry of the available ring the device pops.
+    pub next_avail: u16,
+    /// The next entry of the used ring the device fills.
+    pub next_used: u16,
+    /// The index of the available ring in guest memory, written by the driver.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avail_idx: Option<u16>,
+    /// The index of the used ring in guest memory, written by the device.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub used_idx: Option<u16>,
+    /// The number of malformed descriptor chains the driver made available. The queue is
+    /// quarantined once they reach `MAX_DESCRIPTOR_VIOLATIONS`.
+    pub violations: u16,
+}
+
+impl From<QueueInfo> for QueueStateDump {
+    fn from(info: QueueInfo) -> Self {
+        QueueStateDump {
+            max_size: info.max_size,
+            size: info.size,
+            ready: info.ready,
+            desc_table: info.desc_table,
+            avail_ring: info.avail_ring,
+            used_ring: info.used_ring,
+            next_avail: info.next_avail,
+            next_used: info.next_used,
+            avail_idx: info.avail_idx,
+            used_idx: info.used_idx,
+            violations: info.violations,
+        }
+    }
+}
+
+/// The whole state of a virtio device: its transport, its queues, its configuration space and
+/// the internal state of its handler.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DeviceStateDump {
+    /// The kind of device.
+    pub device_type: DeviceKind,
+    /// The device ID, as configured through the API.
+    pub id: String,
+    /// Where the device is in its lifecycle.
+    pub state: DeviceLifecycleState,
+    /// The status register of the transport, as set by the guest driver.
+    pub device_status: u32,
+    /// The features offered by the device.
+    pub avail_features: u64,
+    /// The features acknowledged by the guest driver.
+    pub acked_features: u64,
+    /// The interrupts the guest driver is yet to acknowledge.
+    pub interrupt_status: u32,
+    /// The generation of the configuration space.
+    pub config_generation: u32,
+    /// The queue the guest driver selected last.
+    pub queue_select: u32,
+    /// The virtio queues.
+    pub queues: Vec<QueueStateDump>,
+    /// The bytes of the configuration space, in hexadecimal.
+    pub config_space: String,
+    /// The internal state of the device handler, by name.
+    pub handler: BTreeMap<String, String>,
+}
+
+/// Dumps the state of the virtio device `device_id` registered with `device_manager`, if any.
+/// When devices of different kinds share the ID, the one at the lowest MMIO address is dumped.
+pub(crate) fn dump_device_state(
+    device_manager: &MMIODeviceManager,
+    device_id: &str,
+) -> Option<DeviceStateDump> {
+    let mut devices: Vec<_> = device_manager
+        .get_device_info()
+        .iter()
+        .filter_map(|((device_type, id), device_info)| match device_type {
+            DeviceType::Virtio(virtio_type) if id == device_id => {
+                Some((device_info.addr, *device_type, *virtio_type))
+            }
+            _ => None,
+        })
+        .collect();
+    devices.sort_by_key(|(mmio_addr, _, _)| *mmio_addr);
+    let (_, device_type, virtio_type) = devices.into_iter().next()?;
+
+    let bus_device = device_manager
+        .get_device(device_type, device_id)?
+        .lock()
+        .expect("Poisoned device lock");
+    let mmio_transport = bus_device
+        .as_any()
+        // Only MmioTransport implements BusDevice at this point.
+        .downcast_ref::<MmioTransport>()
+        .expect("Unexpected BusDevice type");
+    let (kind, _) = describe_virtio_device(virtio_type, &*mmio_transport.locked_device())?;
+    let state = DeviceLifecycleState::from(mmio_transport.lifecycle());
+    let dump = mmio_transport.dump();
+    Some(DeviceStateDump {
+        device_type: kind,
+        id: device_id.to_string(),
+        state,
+        device_status: dump.device_status,
+        avail_features: dump.avail_features,
+        acked_features: dump.acked_features,
+        interrupt_status: dump.interrupt_status,
+        config_generation: dump.config_generation,
+        queue_select: dump.queue_select,
+        queues: dump.queues.into_iter().map(QueueStateDump::from).collect(),
+        config_space: dump
+            .config_space
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+        handler: dump
+            .device_state
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect(),
+    })
+}
+
+/// Describes the devices registered with `device_manager`, ordered by MMIO address.
+pub(crate) fn describe(device_manager: &MMIODeviceManager) -> Vec<DeviceDescription> {
+    let mut descriptions: Vec<_> = device_manager
+        .get_device_info()
+        .iter()
+        .filter_map(|((device_type, device_id), device_info)| {
+            let (kind, activated, state, backend) = match device_type {
+                DeviceType::Virtio(virtio_type) => {
+                    let bus_device = device_manager
+                        .get_device(*device_type, device_id)?
+                        .lock()
+                        .expect("Poisoned device lock");
+                    let mmio_transport = bus_device
+                        .as_any()
+                        // Only MmioTransport implements BusDevice at this point.
+                        .downcast_ref::<MmioTransport>()
+                        .expect("Unexpected BusDevice type");
+                    let locked_device = mmio_transport.locked_device();
+                    let (kind, backend) = describe_virtio_device(*virtio_type, &*locked_device)?;
+                    let activated = locked_device.is_activated();
+                    // The transport locks the device again to tell whether it is paused.
+                    drop(locked_device);
+                    let state = DeviceLifecycleState::from(mmio_transport.lifecycle());
+                    (kind, activated, state, backend)
+                }
+                #[cfg(target_arch = "aarch64")]
+                DeviceType::Serial => (
+                    DeviceKind::Serial,
+                    true,
+                    DeviceLifecycleState::Activated,
+                    None,
+                ),
+                #[cfg(target_arch = "aarch64")]
+                DeviceType::RTC => (DeviceKind::Rtc, true, DeviceLifecycleState::Activated, None),
+            };
+            Some(DeviceDescription {
+                device_type: kind,
+                id: device_id.clone(),
+                mmio_addr: device_info.addr,
+                mmio_len: device_info.len,
+                irq: device_info.irq,
+                activated,
+                state,
+                backend,
+                cmdline: None,
+                guest_name: None,
+            })
+        })
+        .collect();
+    descriptions.sort_by_key(|description| description.mmio_addr);
+
+    let enumerated = device_manager.device_enumeration() == MmioDeviceEnumeration::Cmdline;
+    // The guest kernel probes the devices, and names the block devices, in MMIO address order.
+    let mut block_index = 0;
+    for description in descriptions.iter_mut() {
+        // Only the x86_64 guests are told about the devices on the command line, and all their
+        // MMIO devices are virtio devices.
+        if cfg!(target_arch = "x86_64") && enumerated {
+            description.cmdline = Some(format!(
+                "virtio_mmio.device={}",
+                virtio_mmio_cmdline_value(description.mmio_addr, description.irq)
+            ));
+        }
+        if description.device_type == DeviceKind::Block {
+            if enumerated {
+                description.guest_name = Some(virtio_block_device_name(block_index));
+            }
+            block_index += 1;
+        }
+    }
+    descriptions
+}
+
+/// Describes the rate limiters in effect on the devices registered with `device_manager`,
+/// ordered by MMIO address.
+pub(crate) fn describe_rate_limiters(
+    device_manager: &MMIODeviceManager,
+) -> Vec<RateLimiterDescription> {
+    let mut devices: Vec<_> = device_manager
+        .get_device_info()
+        .iter()
+        .filter_map(|((device_type, device_id), device_info)| {
+            match device_type {
+                DeviceType::Virtio(TYPE_BLOCK) | DeviceType::Virtio(TYPE_NET) => {}
+                _ => return None,
+            }
+            let bus_device = device_manager.get_device(*device_type, device_id)?;
+            Some((device_info.addr, bus_device))
+        })
+        .collect();
+    devices.sort_by_key(|(mmio_addr, _)| *mmio_addr);
+
+    let mut descriptions = Vec::new();
+    for (_, bus_device) in devices {
+        let bus_device = bus_device.lock().expect("Poisoned device lock");
+        let mmio_transport = bus_device
+            .as_any()
+            // Only MmioTransport implements BusDevice at this point.
+            .downcast_ref::<MmioTransport>()
+            .expect("Unexpected BusDevice type");
+        let locked_device = mmio_transport.locked_device();
+        if let Some(block) = locked_device.as_any().downcast_ref::<Block>() {
+            descriptions.extend(RateLimiterDescription::new(
+                DeviceKind::Block,
+                block.id(),
+                RateLimiterPath::Io,
+                block.rate_limiter(),
+            ));
+        } else if let Some(net) = locked_device.as_any().downcast_ref::<Net>() {
+            descriptions.extend(RateLimiterDescription::new(
+                DeviceKind::Net,
+                net.id(),
+                RateLimiterPath::Rx,
+                net.rx_rate_limiter(),
+            ));
+            descriptions.extend(RateLimiterDescription::new(
+                DeviceKind::Net,
+                net.id(),
+                RateLimiterPath::Tx,
+                net.tx_rate_limiter(),
+            ));
+        }
+    }
+    descriptions
+}
+
+fn describe_virtio_device(
+    virtio_type: u32,
+    device: &dyn VirtioDevice,
+) -> Option<(DeviceKind, Option<DeviceBackend>)> {
+    let description = match virtio_type {
+        TYPE_BALLOON => (DeviceKind::Balloon, None),
+        TYPE_BLOCK => {
+            let block = device.as_any().downcast_ref::<Block>()?;
+            let backend = DeviceBackend::File {
+                path_on_host: block.disk_image_path().to_string(),
+            };
+            (DeviceKind::Block, Some(backend))
+        }
+        TYPE_NET => {
+            let net = device.as_any().downcast_ref::<Net>()?;
+            let host_dev_name = net.tap_if_name().to_string();
+            // The AF_XDP socket is the only other net backend.
+            let backend = if net.is_tap_backed() {
+                DeviceBackend::Tap { host_dev_name }
+            } else {
+                DeviceBackend::Xdp { host_dev_name }
+            };
+            (DeviceKind::Net, Some(backend))
+        }
+        TYPE_RNG => (DeviceKind::Entropy, None),
+        TYPE_GPU => {
+            let gpu = device.as_any().downcast_ref::<Gpu>()?;
+            let backend = DeviceBackend::Uds {
+                uds_path: gpu.socket_path().to_string(),
+            };
+            (DeviceKind::Gpu, Some(backend))
+        }
+        TYPE_INPUT => (DeviceKind::Input, None),
+        TYPE_SOUND => {
+            let sink = device.as_any().downcast_ref::<Sound>()?.sink();
+            let backend = match sink.kind() {
+                SinkKind::File => DeviceBackend::File {
+                    path_on_host: sink.path().to_string(),
+                },
+                SinkKind::Socket => DeviceBackend::Uds {
+                    uds_path: sink.path().to_string(),
+                },
+            };
+            (DeviceKind::Sound, Some(backend))
+        }
+        TYPE_VSOCK => {
+            let backend = match device.as_any().downcast_ref::<Vsock<VsockUnixBackend>>() {
+                Some(vsock) => DeviceBackend::Uds {
+                    uds_path: vsock.backend().host_sock_path().to_string(),
+                },
+                // Otherwise, this is a vhost-vsock device.
+                None => DeviceBackend::Vhost,
+            };
+            (DeviceKind::Vsock, Some(backend))
+        }
+        _ => return None,
+    };
+    Some(description)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+    use builder::tests::{
+        default_vmm, insert_balloon_device, insert_block_devices, insert_entropy_device,
+        insert_net_device, insert_vsock_device, CustomBlockConfig,
+    };
+    use polly::event_manager::EventManager;
+    use utils::tempfile::TempFile;
+    use vmm_config::balloon::BalloonDeviceConfig;
+    use vmm_config::net::{NetBackendType, NetworkInterfaceConfig};
+    use vmm_config::vsock::tests::{default_config, TempSockFile};
+    use vmm_config::vsock::VsockConfigError;
+    use vmm_config::{Identifier, RateLimiterConfig, TokenBucketConfig};
+
+    #[test]
+    fn test_describe() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        assert!(describe(&vmm.mmio_device_manager).is_empty());
+
+        let block_configs = vec![CustomBlockConfig::new(
+            String::from("root"),
+            true,
+            None,
+            true,
+        )];
+        insert_block_devices(&mut vmm, &mut event_manager, block_configs);
+        let network_interface = NetworkInterfaceConfig {
+            iface_id: Identifier::try_from("netif").unwrap(),
+            host_dev_name: String::from("hostname"),
+            backend: NetBackendType::Tap,
+            xdp: None,
+            guest_mac: None,
+            mtu: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            allow_mmds_requests: false,
+            allow_promiscuous: true,
+            rx_impairment: None,
+            tx_impairment: None,
+            affinity: None,
+        };
+        insert_net_device(&mut vmm, &mut event_manager, network_interface);
+        let tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        insert_vsock_device(&mut vmm, &mut event_manager, default_config(&tmp_sock_file));
+        let balloon_config = BalloonDeviceConfig {
+            amount_mib: 0,
+            deflate_on_oom: false,
+            free_page_reporting: false,
+        };
+        insert_balloon_device(&mut vmm, &mut event_manager, balloon_config);
+        insert_entropy_device(&mut vmm, &mut event_manager);
+
+        let descriptions = describe(&vmm.mmio_device_manager);
+        let kinds: Vec<_> = descriptions
+            .iter()
+            .map(|description| description.device_type)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                DeviceKind::Block,
+                DeviceKind::Net,
+                DeviceKind::Vsock,
+                DeviceKind::Balloon,
+                DeviceKind::Entropy
+            ]
+        );
+        assert!(descriptions
+            .windows(2)
+            .all(|pair| pair[0].mmio_addr < pair[1].mmio_addr));
+        assert!(descriptions.iter().all(|description| !description.activated
+            && description.state == DeviceLifecycleState::Inactive));
+
+        assert_eq!(descriptions[0].id, "root");
+        match descriptions[0].backend {
+            Some(DeviceBackend::File { .. }) => (),
+            _ => panic!("Unexpected block device backend."),
+        }
+        assert_eq!(
+            descriptions[1].backend,
+            Some(DeviceBackend::Tap {
+                host_dev_name: String::from("hostname")
+            })
+        );
+        assert_eq!(
+            descriptions[2].backend,
+            Some(DeviceBackend::Uds {
+                uds_path: tmp_sock_file.path().clone()
+            })
+        );
+        assert_eq!(descriptions[3].backend, None);
+        assert_eq!(descriptions[4].backend, None);
+
+        assert_eq!(descriptions[0].guest_name, Some(String::from("vda")));
+        assert!(descriptions[1..]
+            .iter()
+            .all(|description| description.guest_name.is_none()));
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            descriptions[1].cmdline,
+            Some(format!(
+                "virtio_mmio.device=4K@0x{:08x}:{}",
+                descriptions[1].mmio_addr, descriptions[1].irq
+            ))
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert!(vmm
+            .kernel_cmdline
+            .as_str()
+            .contains(descriptions[1].cmdline.as_ref().unwrap()));
+
+        // The guest kernel finds the devices in its firmware tables, in an order Firecracker
+        // doesn't know.
+        vmm.mmio_device_manager
+            .set_device_enumeration(MmioDeviceEnumeration::Firmware);
+        let descriptions = describe(&vmm.mmio_device_manager);
+        assert!(descriptions
+            .iter()
+            .all(|description| description.cmdline.is_none() && description.guest_name.is_none()));
+    }
+
+    #[test]
+    fn test_dump_device_state() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        assert!(dump_device_state(&vmm.mmio_device_manager, "root").is_none());
+
+        let block_configs = vec![CustomBlockConfig::new(
+            String::from("root"),
+            true,
+            None,
+            true,
+        )];
+        insert_block_devices(&mut vmm, &mut event_manager, block_configs);
+        let dump = dump_device_state(&vmm.mmio_device_manager, "root").unwrap();
+        assert_eq!(dump.device_type, DeviceKind::Block);
+        assert_eq!(dump.id, "root");
+        assert_eq!(dump.state, DeviceLifecycleState::Inactive);
+        assert_eq!(dump.acked_features, 0);
+        assert_eq!(dump.queues.len(), 1);
+        assert!(!dump.queues[0].ready);
+        assert_eq!(dump.queues[0].avail_idx, None);
+        // The capacity of the disk, in sectors.
+        assert_eq!(dump.config_space.len(), 16);
+        assert_eq!(dump.handler["paused"], "false");
+        assert_eq!(dump.handler["rate_limiter_blocked"], "false");
+
+        let value = serde_json::to_value(&dump).unwrap();
+        assert_eq!(value["device_type"], "block");
+        assert_eq!(value["queues"][0]["max_size"], 256);
+        assert!(value["queues"][0].get("avail_idx").is_none());
+        assert_eq!(value["queues"][0]["violations"], 0);
+        assert!(dump_device_state(&vmm.mmio_device_manager, "missing").is_none());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let description = DeviceDescription {
+            device_type: DeviceKind::Net,
+            id: String::from("netif"),
+            mmio_addr: 0xd000_0000,
+            mmio_len: 0x1000,
+            irq: 5,
+            activated: true,
+            state: DeviceLifecycleState::Paused,
+            backend: Some(DeviceBackend::Tap {
+                host_dev_name: String::from("tap0"),
+            }),
+            cmdline: Some(String::from("virtio_mmio.device=4K@0xd0000000:5")),
+            guest_name: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&description).unwrap(),
+            json!({
+                "device_type": "net",
+                "id": "netif",
+                "mmio_addr": 0xd000_0000u64,
+                "mmio_len": 0x1000,
+                "irq": 5,
+                "activated": true,
+                "state": "paused",
+                "backend": {"type": "tap", "host_dev_name": "tap0"},
+                "cmdline": "virtio_mmio.device=4K@0xd0000000:5"
+            })
+        );
+
+        let description = DeviceDescription {
+            device_type: DeviceKind::Entropy,
+            id: String::from("rng"),
+            mmio_addr: 0xd000_1000,
+            mmio_len: 0x1000,
+            irq: 6,
+            activated: false,
+            state: DeviceLifecycleState::Reset,
+            backend: None,
+            cmdline: None,
+            guest_name: None,
+        };
+        let value = serde_json::to_value(&description).unwrap();
+        assert!(value.get("backend").is_none());
+        assert!(value.get("cmdline").is_none());
+        assert!(value.get("guest_name").is_none());
+        // The fields left out are read back as missing.
+        assert_eq!(
+            serde_json::from_value::<DeviceDescription>(value).unwrap(),
+            description
+        );
+    }
+
+    #[test]
+    fn test_vsock_connections() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        match vmm.list_vsock_connections() {
+            Err(VsockConfigError::DeviceNotFound) => (),
+            _ => panic!("Listing connections should require a vsock device."),
+        }
+        match vmm.drain_vsock_connections() {
+            Err(VsockConfigError::DeviceNotFound) => (),
+            _ => panic!("Draining connections should require a vsock device."),
+        }
+
+        let tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        insert_vsock_device(&mut vmm, &mut event_manager, default_config(&tmp_sock_file));
+        assert!(vmm.list_vsock_connections().unwrap().is_empty());
+        // The guest driver is not active, so there is nothing to signal.
+        assert_eq!(vmm.drain_vsock_connections().unwrap(), 0);
+
+        // The connections of every vsock device are listed and drained.
+        let other_tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        let mut other_config = default_config(&other_tmp_sock_file);
+        other_config.vsock_id = Identifier::try_from("other_vsock").unwrap();
+        other_config.guest_cid += 1;
+        insert_vsock_device(&mut vmm, &mut event_manager, other_config);
+        let mut ids = Vec::new();
+        assert_eq!(
+            vmm.for_each_virtio_device(TYPE_VSOCK, |vsock_id, _: &mut Vsock<VsockUnixBackend>| ids
+                .push(vsock_id.to_string())),
+            2
+        );
+        assert_eq!(ids, vec!["vsock", "other_vsock"]);
+        assert!(vmm.list_vsock_connections().unwrap().is_empty());
+        assert_eq!(vmm.drain_vsock_connections().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_serialize_vsock_connection() {
+        let info = VsockConnectionInfo {
+            local_port: 1_073_741_824,
+            peer_port: 52,
+            state: VsockConnState::PeerClosed(true, false),
+            rx_cnt: 100,
+            fwd_cnt: 200,
+            tx_buf_len: 16,
+        };
+        assert_eq!(
+            serde_json::to_value(&VsockConnectionDescription::new("vsock", info)).unwrap(),
+            json!({
+                "vsock_id": "vsock",
+                "host_port": 1_073_741_824,
+                "guest_port": 52,
+                "state": "guest_closed",
+                "bytes_to_guest": 100,
+                "bytes_to_host": 200,
+                "pending_bytes_to_host": 16
+            })
+        );
+    }
+
+    #[test]
+    fn test_describe_rate_limiters() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        assert!(describe_rate_limiters(&vmm.mmio_device_manager).is_empty());
+
+        // Only the rate limiters which limit something are described.
+        let block_configs = vec![CustomBlockConfig::new(
+            String::from("root"),
+            true,
+            None,
+            true,
+        )];
+        insert_block_devices(&mut vmm, &mut event_manager, block_configs);
+        let network_interface = NetworkInterfaceConfig {
+            iface_id: Identifier::try_from("netif").unwrap(),
+            host_dev_name: String::from("hostname"),
+            backend: NetBackendType::Tap,
+            xdp: None,
+            guest_mac: None,
+            mtu: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: Some(RateLimiterConfig {
+                bandwidth: Some(TokenBucketConfig {
+                    size: 1000,
+                    one_time_burst: Some(2000),
+                    refill_time: 100,
+                }),
+                ops: None,
+            }),
+            allow_mmds_requests: false,
+            allow_promiscuous: true,
+            rx_impairment: None,
+            tx_impairment: None,
+            affinity: None,
+        };
+        insert_net_device(&mut vmm, &mut event_manager, network_interface);
+
+        let descriptions = describe_rate_limiters(&vmm.mmio_device_manager);
+        assert_eq!(
+            descriptions,
+            vec![RateLimiterDescription {
+                device_type: DeviceKind::Net,
+                id: String::from("netif"),
+                path: RateLimiterPath::Tx,
+                bandwidth: Some(TokenBucketDescription {
+                    size: 1000,
+                    budget: 1000,
+                    one_time_burst: 2000,
+                    refill_time: 100,
+                }),
+                ops: None,
+                blocked: false,
+                blocked_count: 0,
+                blocked_time_us: 0,
+            }]
+        );
+        assert_eq!(
+            serde_json::to_value(&descriptions[0]).unwrap(),
+            json!({
+                "device_type": "net",
+                "id": "netif",
+                "path": "tx",
+                "bandwidth": {
+                    "size": 1000,
+                    "budget": 1000,
+                    "one_time_burst": 2000,
+                    "refill_time": 100
+                },
+                "ops": null,
+                "blocked": false,
+                "blocked_count": 0,
+                "blocked_time_us": 0
+            })
+        );
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Caps the rate at which a device interrupts the guest.
+//!
+//! A throttled device doesn't signal KVM directly: its interrupt event is handled on the VMM
+//! thread, which forwards it to the irqfd of the device, at most once per interval. The
+//! interrupts signaled sooner are deferred until the end of the interval, and the ones signaled
+//! while an interrupt is already deferred are coalesced with it. This is harmless for the virtio
+//! devices, whose drivers find out what the interrupt is about from the interrupt status and the
+//! used rings, no matter how many times they were interrupted.
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use logger::{IrqThrottleMetrics, Metric};
+use polly::event_manager::{EventManager, Subscriber};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::{EpollEvent, EventSet};
+use utils::eventfd::EventFd;
+
+/// Forwards the interrupts of a device to the guest, at most `rate` times per second.
+pub struct IrqThrottle {
+    // The interrupt event of the device.
+    device_evt: EventFd,
+    // The irqfd of the device, registered with KVM in place of its interrupt event.
+    irqfd: EventFd,
+    // Expires at the end of the interval, when an interrupt is deferred.
+    timer_fd: TimerFd,
+    interval: Duration,
+    last_irq: Option<Instant>,
+    deferred: bool,
+    metrics: Arc<IrqThrottleMetrics>,
+}
+
+impl IrqThrottle {
+    /// Creates the throttle of the device signaling `device_evt`, forwarding at most `rate`
+    /// interrupts per second.
+    pub fn new(
+        device_evt: &EventFd,
+        rate: u32,
+        metrics: Arc<IrqThrottleMetrics>,
+    ) -> io::Result<Self> {
+        Ok(IrqThrottle {
+            device_evt: device_evt.try_clone()?,
+            irqfd: EventFd::new(libc::EFD_NONBLOCK)?,
+            timer_fd: TimerFd::new_custom(ClockId::Monotonic, true, true)?,
+            interval: Duration::from_secs(1) / rate.max(1),
+            last_irq: None,
+            deferred: false,
+            metrics,
+        })
+    }
+
+    /// Returns the event to register with KVM as the irqfd of the device.
+    pub fn irqfd(&self) -> &EventFd {
+        &self.irqfd
+    }
+
+    fn on_device_irq(&mut self, now: Instant) {
+        if self.deferred {
+            self.metrics.coalesced_irqs.inc();
+            return;
+        }
+        let elapsed = self.last_irq.map(|last_irq| now.duration_since(last_irq));
+        match elapsed {
+            Some(elapsed) if elapsed < self.interval => {
+                self.deferred = true;
+                self.metrics.deferred_irqs.inc();
+                self.timer_fd.set_state(
+                    TimerState::Oneshot(self.interval - elapsed),
+                    SetTimeFlags::Default,
+                );
+            }
+            _ => self.inject(now),
+        }
+    }
+
+    fn on_timer(&mut self, now: Instant) {
+        if self.deferred {
+            self.deferred = false;
+            self.inject(now);
+        }
+    }
+
+    fn inject(&mut self, now: Instant) {
+        self.last_irq = Some(now);
+        if let Err(e) = self.irqfd.write(1) {
+            error!("Failed to signal the irqfd of a throttled device: {}", e);
+        }
+    }
+}
+
+impl Subscriber for IrqThrottle {
+    /// Handle a read event (EPOLLIN).
+    fn process(&mut self, event: &EpollEvent, _: &mut EventManager) {
+        let source = event.fd();
+        let event_set = event.event_set();
+
+        let supported_events = EventSet::IN;
+        if !supported_events.contains(event_set) {
+            warn!(
+                "Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
+
+        if source == self.device_evt.as_raw_fd() {
+            // The interrupts signaled since the last read are already coalesced by the event.
+            if let Err(e) = self.device_evt.read() {
+                error!(
+                    "Failed to read the interrupt event of a throttled device: {}",
+                    e
+                );
+                return;
+            }
+            self.on_device_irq(Instant::now());
+        } else if source == self.timer_fd.as_raw_fd() {
+            self.timer_fd.read();
+            self.on_timer(Instant::now());
+        } else {
+            error!("Spurious interrupt throttle event!");
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        vec![
+            EpollEvent::new(EventSet::IN, self.device_evt.as_raw_fd() as u64),
+            EpollEvent::new(EventSet::IN, self.timer_fd.as_raw_fd() as u64),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending_irqs(throttle: &IrqThrottle) -> u64 {
+        throttle.irqfd.read().unwrap_or(0)
+    }
+
+    #[test]
+    fn test_throttle() {
+        let device_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let metrics = Arc::new(IrqThrottleMetrics::default());
+        let mut throttle = IrqThrottle::new(&device_evt, 10, metrics.clone()).unwrap();
+        assert_eq!(throttle.interval, Duration::from_millis(100));
+        assert_eq!(throttle.interest_list().len(), 2);
+
+        // The first interrupt is forwarded right away.
+        let start = Instant::now();
+        throttle.on_device_irq(start);
+        assert_eq!(pending_irqs(&throttle), 1);
+        assert_eq!(throttle.timer_fd.get_state(), TimerState::Disarmed);
+
+        // The next ones within the interval are deferred and coalesced.
+        throttle.on_device_irq(start + Duration::from_millis(40));
+        throttle.on_device_irq(start + Duration::from_millis(50));
+        throttle.on_device_irq(start + Duration::from_millis(60));
+        assert_eq!(pending_irqs(&throttle), 0);
+        match throttle.timer_fd.get_state() {
+            TimerState::Oneshot(remaining) => assert!(remaining <= Duration::from_millis(60)),
+            state => panic!("Unexpected timer state {:?}", state),
+        }
+        assert_eq!(metrics.deferred_irqs.count(), 1);
+        assert_eq!(metrics.coalesced_irqs.count(), 2);
+
+        // They are forwarded as one at the end of the interval.
+        throttle.on_timer(start + Duration::from_millis(100));
+        assert_eq!(pending_irqs(&throttle), 1);
+        throttle.on_timer(start + Duration::from_millis(150));
+        assert_eq!(pending_irqs(&throttle), 0);
+
+        // The interval starts over from the deferred interrupt.
+        throttle.on_device_irq(start + Duration::from_millis(150));
+        assert_eq!(pending_irqs(&throttle), 0);
+        throttle.on_timer(start + Duration::from_millis(200));
+        assert_eq!(pending_irqs(&throttle), 1);
+        throttle.on_device_irq(start + Duration::from_millis(300));
+        assert_eq!(pending_irqs(&throttle), 1);
+        assert_eq!(metrics.deferred_irqs.count(), 2);
+        assert_eq!(metrics.coalesced_irqs.count(), 2);
+    }
+
+    #[test]
+    fn test_process() {
+        let device_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let metrics = Arc::new(IrqThrottleMetrics::default());
+        let mut throttle = IrqThrottle::new(&device_evt, 1, metrics).unwrap();
+        let mut event_manager = EventManager::new().unwrap();
+        // The throttle handles a duplicate of the interrupt event of the device.
+        let event = EpollEvent::new(EventSet::IN, throttle.device_evt.as_raw_fd() as u64);
+
+        device_evt.write(1).unwrap();
+        throttle.process(&event, &mut event_manager);
+        assert_eq!(pending_irqs(&throttle), 1);
+        // The interrupt event of the device is drained.
+        assert!(device_evt.read().is_err());
+
+        device_evt.write(1).unwrap();
+        throttle.process(&event, &mut event_manager);
+        assert_eq!(pending_irqs(&throttle), 0);
+        assert!(throttle.deferred);
+    }
+}
@@ -11,6 +11,7 @@ use std::sync::{Arc, Mutex};
 
 use devices;
 use utils::eventfd::EventFd;
+use vmm_config::machine_config::LegacyDevicesConfig;
 
 /// Errors corresponding to the `PortIODeviceManager`.
 #[derive(Debug)]
@@ -35,20 +36,23 @@ impl fmt::Display for Error {
 type Result<T> = ::std::result::Result<T, Error>;
 
 /// The `PortIODeviceManager` is a wrapper that is used for registering legacy devices
-/// on an I/O Bus. It currently manages the uart and i8042 devices.
+/// on an I/O Bus. It currently manages the uart, i8042 and CMOS devices.
 /// The `LegacyDeviceManger` should be initialized only by using the constructor.
 pub struct PortIODeviceManager {
     pub io_bus: devices::Bus,
     pub stdio_serial: Arc<Mutex<devices::legacy::Serial>>,
     pub i8042: Arc<Mutex<devices::legacy::I8042Device>>,
+    pub cmos: Arc<Mutex<devices::legacy::Cmos>>,
 
     pub com_evt_1_3: EventFd,
     pub com_evt_2_4: EventFd,
     pub kbd_evt: EventFd,
+
+    i8042_registered: bool,
 }
 
 impl PortIODeviceManager {
-    /// Create a new DeviceManager handling legacy devices (uart, i8042).
+    /// Create a new DeviceManager handling legacy devices (uart, i8042, CMOS).
     pub fn new(
         serial: Arc<Mutex<devices::legacy::Serial>>,
         i8042_reset_evfd: EventFd,
@@ -72,14 +76,39 @@ impl PortIODeviceManager {
             io_bus,
             stdio_serial: serial,
             i8042,
+            cmos: Arc::new(Mutex::new(devices::legacy::Cmos::new())),
             com_evt_1_3,
             com_evt_2_4,
             kbd_evt,
+            i8042_registered: false,
         })
     }
 
-    /// Register supported legacy devices.
-    pub fn register_devices(&mut self) -> Result<()> {
+    /// Register the supported legacy devices selected by `config`.
+    pub fn register_devices(&mut self, config: &LegacyDevicesConfig) -> Result<()> {
+        if config.serial {
+            self.register_serial_ports()?;
+        }
+        if config.i8042 {
+            self.io_bus
+                .insert(self.i8042.clone(), 0x060, 0x5)
+                .map_err(Error::BusError)?;
+            self.i8042_registered = true;
+        }
+        if config.rtc {
+            self.io_bus
+                .insert(self.cmos.clone(), 0x070, 0x2)
+                .map_err(Error::BusError)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the i8042 keyboard controller is on the I/O bus, i.e. reachable by the guest.
+    pub fn i8042_registered(&self) -> bool {
+        self.i8042_registered
+    }
+
+    fn register_serial_ports(&mut self) -> Result<()> {
         self.io_bus
             .insert(self.stdio_serial.clone(), 0x3f8, 0x8)
             .map_err(Error::BusError)?;
@@ -110,9 +139,6 @@ impl PortIODeviceManager {
                 0x8,
             )
             .map_err(Error::BusError)?;
-        self.io_bus
-            .insert(self.i8042.clone(), 0x060, 0x5)
-            .map_err(Error::BusError)?;
         Ok(())
     }
 }
@@ -129,7 +155,54 @@ mod tests {
             EventFd::new(libc::EFD_NONBLOCK).unwrap(),
         );
         assert!(ldm.is_ok());
-        assert!(&ldm.unwrap().register_devices().is_ok());
+        let mut ldm = ldm.unwrap();
+        assert!(ldm
+            .register_devices(&LegacyDevicesConfig::default())
+            .is_ok());
+        assert!(ldm.i8042_registered());
+    }
+
+    #[test]
+    fn test_omit_legacy_devices() {
+        let serial = devices::legacy::Serial::new_sink(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        let mut ldm = PortIODeviceManager::new(
+            Arc::new(Mutex::new(serial)),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        )
+        .unwrap();
+        ldm.register_devices(&LegacyDevicesConfig {
+            serial: false,
+            i8042: false,
+            rtc: true,
+        })
+        .unwrap();
+        assert!(!ldm.i8042_registered());
+
+        let mut data = [0];
+        for port in &[0x3f8, 0x2f8, 0x3e8, 0x2e8, 0x60, 0x64] {
+            assert!(!ldm.io_bus.read(*port, &mut data));
+        }
+        assert!(ldm.io_bus.write(0x70, &[0x09]));
+        assert!(ldm.io_bus.read(0x71, &mut data));
+    }
+
+    #[test]
+    fn test_cmos_time() {
+        let serial = devices::legacy::Serial::new_sink(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        let mut ldm = PortIODeviceManager::new(
+            Arc::new(Mutex::new(serial)),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        )
+        .unwrap();
+        ldm.register_devices(&LegacyDevicesConfig::default())
+            .unwrap();
+
+        // 2001-02-03T04:05:06Z.
+        ldm.cmos.lock().unwrap().set_time(981_173_106);
+        let mut data = [0];
+        assert!(ldm.io_bus.write(0x70, &[0x09]));
+        assert!(ldm.io_bus.read(0x71, &mut data));
+        assert_eq!(data[0], 0x01);
     }
 
     #[test]
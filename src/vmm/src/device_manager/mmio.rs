@@ -17,9 +17,14 @@ use devices;
 use devices::BusDevice;
 use kernel::cmdline as kernel_cmdline;
 use kvm_ioctls::{IoEventAddress, VmFd};
+use logger::METRICS;
 #[cfg(target_arch = "aarch64")]
 use utils::eventfd::EventFd;
 
+use super::irq_throttle::IrqThrottle;
+use vmm_config::boot_source::MmioDeviceEnumeration;
+use vmm_config::virtio_features::VirtioFeaturePolicyConfig;
+
 /// Errors for MMIO device manager.
 #[derive(Debug)]
 pub enum Error {
@@ -29,6 +34,8 @@ pub enum Error {
     Cmdline(kernel_cmdline::Error),
     /// Failure in creating or cloning an event fd.
     EventFd(io::Error),
+    /// Failed to create the interrupt throttle of a device.
+    IrqThrottle(io::Error),
     /// No more IRQs are available.
     IrqsExhausted,
     /// Registering an IO Event failed.
@@ -49,6 +56,9 @@ impl fmt::Display for Error {
                 write!(f, "unable to add device to kernel command line: {}", e)
             }
             Error::EventFd(ref e) => write!(f, "failed to create or clone event descriptor: {}", e),
+            Error::IrqThrottle(ref e) => {
+                write!(f, "failed to create the interrupt throttle: {}", e)
+            }
             Error::IrqsExhausted => write!(f, "no more IRQs are available"),
             Error::RegisterIoEvent(ref e) => write!(f, "failed to register IO event: {}", e),
             Error::RegisterIrqFd(ref e) => write!(f, "failed to register irqfd: {}", e),
@@ -70,6 +80,33 @@ const MMIO_LEN: u64 = 0x1000;
 /// to its configuration space.
 pub const MMIO_CFG_SPACE_OFF: u64 = 0x100;
 
+/// Returns the value of the `virtio_mmio.device=` kernel command line parameter telling the guest
+/// kernel about the virtio device at `mmio_base`, interrupting on `irq`.
+pub fn virtio_mmio_cmdline_value(mmio_base: u64, irq: u32) -> String {
+    // As per doc, [virtio_mmio.]device=<size>@<baseaddr>:<irq> needs to be appended to kernel
+    // commandline for virtio mmio devices to get recognized. The size parameter has to be
+    // transformed to KiB, so dividing hexadecimal value in bytes to 1024; further, the '{}'
+    // formatting rust construct will automatically transform it to decimal.
+    format!("{}K@0x{:08x}:{}", MMIO_LEN / 1024, mmio_base, irq)
+}
+
+/// Returns the name the guest kernel gives the virtio block device probed `index`-th, e.g. `vda`
+/// for the first one and `vdaa` for the 27th one.
+pub fn virtio_block_device_name(index: usize) -> String {
+    let mut index = index;
+    let mut suffix = Vec::new();
+    loop {
+        suffix.push(b'a' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    suffix.reverse();
+    // The suffix only holds lowercase ASCII letters.
+    format!("vd{}", String::from_utf8(suffix).unwrap())
+}
+
 /// Manages the complexities of registering a MMIO device.
 pub struct MMIODeviceManager {
     pub bus: devices::Bus,
@@ -77,6 +114,10 @@ pub struct MMIODeviceManager {
     irq: u32,
     last_irq: u32,
     id_to_dev_info: HashMap<(DeviceType, String), MMIODeviceInfo>,
+    max_irq_rate: Option<u32>,
+    irq_throttles: Vec<Arc<Mutex<IrqThrottle>>>,
+    feature_policy: VirtioFeaturePolicyConfig,
+    device_enumeration: MmioDeviceEnumeration,
 }
 
 impl MMIODeviceManager {
@@ -91,9 +132,41 @@ impl MMIODeviceManager {
             last_irq: irq_interval.1,
             bus: devices::Bus::new(),
             id_to_dev_info: HashMap::new(),
+            max_irq_rate: None,
+            irq_throttles: Vec::new(),
+            feature_policy: VirtioFeaturePolicyConfig::default(),
+            device_enumeration: MmioDeviceEnumeration::default(),
         }
     }
 
+    /// Sets how the guest kernel finds the virtio devices.
+    pub fn set_device_enumeration(&mut self, device_enumeration: MmioDeviceEnumeration) {
+        self.device_enumeration = device_enumeration;
+    }
+
+    /// Returns how the guest kernel finds the virtio devices.
+    pub fn device_enumeration(&self) -> MmioDeviceEnumeration {
+        self.device_enumeration
+    }
+
+    /// Restricts the features the virtio devices registered from now on offer to their guest
+    /// drivers to the ones allowed by `policy` for their type.
+    pub fn set_feature_policy(&mut self, policy: VirtioFeaturePolicyConfig) {
+        self.feature_policy = policy;
+    }
+
+    /// Caps the rate at which the virtio devices registered from now on interrupt the guest, to
+    /// `rate` interrupts per second.
+    pub fn set_max_irq_rate(&mut self, rate: Option<u32>) {
+        self.max_irq_rate = rate;
+    }
+
+    /// Hands over the interrupt throttles of the devices registered since the last call, which
+    /// have to be registered with the event manager for the devices to interrupt the guest.
+    pub fn take_irq_throttles(&mut self) -> Vec<Arc<Mutex<IrqThrottle>>> {
+        std::mem::replace(&mut self.irq_throttles, Vec::new())
+    }
+
     /// Register an already created MMIO device to be used via MMIO transport.
     pub fn register_mmio_device(
         &mut self,
@@ -106,42 +179,87 @@ impl MMIODeviceManager {
             return Err(Error::IrqsExhausted);
         }
 
-        for (i, queue_evt) in mmio_device
-            .locked_device()
-            .queue_events()
-            .iter()
-            .enumerate()
-        {
-            let io_addr = IoEventAddress::Mmio(
-                self.mmio_base + u64::from(devices::virtio::NOTIFY_REG_OFFSET),
-            );
+        let ret = (self.mmio_base, self.irq);
+        self.register_mmio_device_at(vm, mmio_device, type_id, device_id, ret.0, ret.1)?;
+        self.mmio_base += MMIO_LEN;
+        self.irq += 1;
+
+        Ok(ret)
+    }
 
-            vm.register_ioevent(queue_evt, &io_addr, i as u32)
-                .map_err(Error::RegisterIoEvent)?;
+    /// Register an already created MMIO device at the given MMIO address and IRQ, as recorded
+    /// when the device state was saved. Subsequently registered devices are placed after it.
+    pub fn register_mmio_device_at(
+        &mut self,
+        vm: &VmFd,
+        mmio_device: devices::virtio::MmioTransport,
+        type_id: u32,
+        device_id: String,
+        mmio_base: u64,
+        irq: u32,
+    ) -> Result<()> {
+        let mut mmio_device = mmio_device;
+        #[cfg(feature = "mmio-trace")]
+        {
+            if devices::virtio::mmio_trace::enabled() {
+                mmio_device.trace_as(device_id.clone());
+            }
+        }
+        mmio_device.set_queue_metrics(METRICS.virtio_queues.get(&device_id));
+        mmio_device.set_feature_mask(self.feature_policy.mask(type_id));
+
+        // The queue notifications to a traced device exit to the VMM, which traces them.
+        if !mmio_device.is_traced() {
+            for (i, queue_evt) in mmio_device
+                .locked_device()
+                .queue_events()
+                .iter()
+                .enumerate()
+            {
+                let io_addr =
+                    IoEventAddress::Mmio(mmio_base + u64::from(devices::virtio::NOTIFY_REG_OFFSET));
+
+                vm.register_ioevent(queue_evt, &io_addr, i as u32)
+                    .map_err(Error::RegisterIoEvent)?;
+            }
         }
 
-        vm.register_irqfd(mmio_device.locked_device().interrupt_evt(), self.irq)
-            .map_err(Error::RegisterIrqFd)?;
+        match self.max_irq_rate {
+            Some(rate) => {
+                let throttle = IrqThrottle::new(
+                    mmio_device.locked_device().interrupt_evt(),
+                    rate,
+                    METRICS.irq_throttle.get(&device_id),
+                )
+                .map_err(Error::IrqThrottle)?;
+                vm.register_irqfd(throttle.irqfd(), irq)
+                    .map_err(Error::RegisterIrqFd)?;
+                self.irq_throttles.push(Arc::new(Mutex::new(throttle)));
+            }
+            None => vm
+                .register_irqfd(mmio_device.locked_device().interrupt_evt(), irq)
+                .map_err(Error::RegisterIrqFd)?,
+        }
 
         self.bus
-            .insert(Arc::new(Mutex::new(mmio_device)), self.mmio_base, MMIO_LEN)
+            .insert(Arc::new(Mutex::new(mmio_device)), mmio_base, MMIO_LEN)
             .map_err(Error::BusError)?;
-        let ret = (self.mmio_base, self.irq);
         self.id_to_dev_info.insert(
             (DeviceType::Virtio(type_id), device_id),
             MMIODeviceInfo {
-                addr: self.mmio_base,
+                addr: mmio_base,
                 len: MMIO_LEN,
-                irq: self.irq,
+                irq,
             },
         );
-        self.mmio_base += MMIO_LEN;
-        self.irq += 1;
+        self.mmio_base = std::cmp::max(self.mmio_base, mmio_base + MMIO_LEN);
+        self.irq = std::cmp::max(self.irq, irq + 1);
 
-        Ok(ret)
+        Ok(())
     }
 
-    /// Append a registered MMIO device to the kernel cmdline.
+    /// Append a registered MMIO device to the kernel cmdline, unless the guest kernel finds the
+    /// devices in its firmware tables.
     #[cfg(target_arch = "x86_64")]
     pub fn add_device_to_cmdline(
         &mut self,
@@ -149,15 +267,25 @@ impl MMIODeviceManager {
         mmio_base: u64,
         irq: u32,
     ) -> Result<()> {
-        // as per doc, [virtio_mmio.]device=<size>@<baseaddr>:<irq> needs to be appended
-        // to kernel commandline for virtio mmio devices to get recognized
-        // the size parameter has to be transformed to KiB, so dividing hexadecimal value in
-        // bytes to 1024; further, the '{}' formatting rust construct will automatically
-        // transform it to decimal
+        if self.device_enumeration == MmioDeviceEnumeration::Firmware {
+            return Ok(());
+        }
+        cmdline
+            .insert(
+                "virtio_mmio.device",
+                &virtio_mmio_cmdline_value(mmio_base, irq),
+            )
+            .map_err(Error::Cmdline)
+    }
+
+    /// Appends to `cmdline` an argument as long as the longest one `add_device_to_cmdline` can
+    /// append, to check ahead of time that the command line has room for a device.
+    #[cfg(target_arch = "x86_64")]
+    pub fn reserve_device_cmdline(cmdline: &mut kernel_cmdline::Cmdline) -> Result<()> {
         cmdline
             .insert(
                 "virtio_mmio.device",
-                &format!("{}K@0x{:08x}:{}", MMIO_LEN / 1024, mmio_base, irq),
+                &virtio_mmio_cmdline_value(u64::from(u32::max_value()), arch::IRQ_MAX),
             )
             .map_err(Error::Cmdline)
     }
@@ -202,15 +330,18 @@ impl MMIODeviceManager {
     }
 
     #[cfg(target_arch = "aarch64")]
-    /// Register a MMIO RTC device.
-    pub fn register_mmio_rtc(&mut self, vm: &VmFd) -> Result<()> {
+    /// Register a MMIO RTC device, starting at `time` if set, or at the host time otherwise.
+    pub fn register_mmio_rtc(&mut self, vm: &VmFd, time: Option<u32>) -> Result<()> {
         if self.irq > self.last_irq {
             return Err(Error::IrqsExhausted);
         }
 
         // Attaching the RTC device.
         let rtc_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?;
-        let device = devices::legacy::RTC::new(rtc_evt.try_clone().map_err(Error::EventFd)?);
+        let mut device = devices::legacy::RTC::new(rtc_evt.try_clone().map_err(Error::EventFd)?);
+        if let Some(time) = time {
+            device.set_time(time);
+        }
         vm.register_irqfd(&rtc_evt, self.irq)
             .map_err(Error::RegisterIrqFd)?;
 
@@ -283,6 +414,8 @@ mod tests {
     use super::super::super::builder;
     use super::*;
     use arch;
+    #[cfg(target_arch = "aarch64")]
+    use arch::aarch64::gic::GICConfig;
     use devices::virtio::{ActivateResult, Queue, VirtioDevice, TYPE_BLOCK};
     use std::sync::atomic::AtomicUsize;
     use std::sync::Arc;
@@ -412,37 +545,107 @@ mod tests {
         let start_addr2 = GuestAddress(0x1000);
         let guest_mem =
             GuestMemoryMmap::from_ranges(&[(start_addr1, 0x1000), (start_addr2, 0x1000)]).unwrap();
-        let mut vm = builder::setup_kvm_vm(&guest_mem, false).unwrap();
+        let mut vm = builder::setup_kvm_vm(&guest_mem, false, None).unwrap();
         let mut device_manager =
             MMIODeviceManager::new(&mut 0xd000_0000, (arch::IRQ_BASE, arch::IRQ_MAX));
 
         let mut cmdline = kernel_cmdline::Cmdline::new(4096);
         let dummy = Arc::new(Mutex::new(DummyDevice::new()));
         #[cfg(target_arch = "x86_64")]
-        assert!(builder::setup_interrupt_controller(&mut vm).is_ok());
+        assert!(builder::setup_interrupt_controller(&mut vm, true).is_ok());
         #[cfg(target_arch = "aarch64")]
-        assert!(builder::setup_interrupt_controller(&mut vm, 1).is_ok());
+        assert!(builder::setup_interrupt_controller(&mut vm, 1, &GICConfig::default()).is_ok());
 
         assert!(device_manager
             .register_virtio_device(vm.fd(), guest_mem, dummy, &mut cmdline, 0, "dummy")
             .is_ok());
     }
 
+    #[test]
+    fn test_register_virtio_device_at() {
+        let guest_mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0x0), 0x1000)]).unwrap();
+        let mut vm = builder::setup_kvm_vm(&guest_mem, false, None).unwrap();
+        let mut device_manager =
+            MMIODeviceManager::new(&mut 0xd000_0000, (arch::IRQ_BASE, arch::IRQ_MAX));
+        #[cfg(target_arch = "x86_64")]
+        assert!(builder::setup_interrupt_controller(&mut vm, true).is_ok());
+        #[cfg(target_arch = "aarch64")]
+        assert!(builder::setup_interrupt_controller(&mut vm, 1, &GICConfig::default()).is_ok());
+
+        let base = device_manager.mmio_base + 2 * MMIO_LEN;
+        let irq = device_manager.irq + 2;
+        let mmio_device = devices::virtio::MmioTransport::new(
+            guest_mem.clone(),
+            Arc::new(Mutex::new(DummyDevice::new())),
+        );
+        device_manager
+            .register_mmio_device_at(vm.fd(), mmio_device, 0, "dummy".to_string(), base, irq)
+            .unwrap();
+        let info = &device_manager.get_device_info()[&(DeviceType::Virtio(0), "dummy".to_string())];
+        assert_eq!(info.addr, base);
+        assert_eq!(info.irq, irq);
+
+        // The next devices are allocated after the restored one.
+        let mmio_device = devices::virtio::MmioTransport::new(
+            guest_mem,
+            Arc::new(Mutex::new(DummyDevice::new())),
+        );
+        assert_eq!(
+            device_manager
+                .register_mmio_device(vm.fd(), mmio_device, 0, "dummy2".to_string())
+                .unwrap(),
+            (base + MMIO_LEN, irq + 1)
+        );
+    }
+
+    #[test]
+    fn test_register_throttled_device() {
+        let guest_mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0x0), 0x1000)]).unwrap();
+        let mut vm = builder::setup_kvm_vm(&guest_mem, false, None).unwrap();
+        let mut device_manager =
+            MMIODeviceManager::new(&mut 0xd000_0000, (arch::IRQ_BASE, arch::IRQ_MAX));
+        #[cfg(target_arch = "x86_64")]
+        assert!(builder::setup_interrupt_controller(&mut vm, true).is_ok());
+        #[cfg(target_arch = "aarch64")]
+        assert!(builder::setup_interrupt_controller(&mut vm, 1, &GICConfig::default()).is_ok());
+
+        let mmio_device = devices::virtio::MmioTransport::new(
+            guest_mem.clone(),
+            Arc::new(Mutex::new(DummyDevice::new())),
+        );
+        device_manager
+            .register_mmio_device(vm.fd(), mmio_device, 0, "dummy".to_string())
+            .unwrap();
+        assert!(device_manager.take_irq_throttles().is_empty());
+
+        // The devices registered once the rate is capped get a throttle.
+        device_manager.set_max_irq_rate(Some(1000));
+        let mmio_device = devices::virtio::MmioTransport::new(
+            guest_mem,
+            Arc::new(Mutex::new(DummyDevice::new())),
+        );
+        device_manager
+            .register_mmio_device(vm.fd(), mmio_device, 0, "dummy2".to_string())
+            .unwrap();
+        assert_eq!(device_manager.take_irq_throttles().len(), 1);
+        assert!(device_manager.take_irq_throttles().is_empty());
+    }
+
     #[test]
     fn test_register_too_many_devices() {
         let start_addr1 = GuestAddress(0x0);
         let start_addr2 = GuestAddress(0x1000);
         let guest_mem =
             GuestMemoryMmap::from_ranges(&[(start_addr1, 0x1000), (start_addr2, 0x1000)]).unwrap();
-        let mut vm = builder::setup_kvm_vm(&guest_mem, false).unwrap();
+        let mut vm = builder::setup_kvm_vm(&guest_mem, false, None).unwrap();
         let mut device_manager =
             MMIODeviceManager::new(&mut 0xd000_0000, (arch::IRQ_BASE, arch::IRQ_MAX));
 
         let mut cmdline = kernel_cmdline::Cmdline::new(4096);
         #[cfg(target_arch = "x86_64")]
-        assert!(builder::setup_interrupt_controller(&mut vm).is_ok());
+        assert!(builder::setup_interrupt_controller(&mut vm, true).is_ok());
         #[cfg(target_arch = "aarch64")]
-        assert!(builder::setup_interrupt_controller(&mut vm, 1).is_ok());
+        assert!(builder::setup_interrupt_controller(&mut vm, 1, &GICConfig::default()).is_ok());
 
         for _i in arch::IRQ_BASE..=arch::IRQ_MAX {
             device_manager
@@ -537,7 +740,7 @@ mod tests {
         let start_addr2 = GuestAddress(0x1000);
         let guest_mem =
             GuestMemoryMmap::from_ranges(&[(start_addr1, 0x1000), (start_addr2, 0x1000)]).unwrap();
-        let vm = builder::setup_kvm_vm(&guest_mem, false).unwrap();
+        let vm = builder::setup_kvm_vm(&guest_mem, false, None).unwrap();
         let mut device_manager =
             MMIODeviceManager::new(&mut 0xd000_0000, (arch::IRQ_BASE, arch::IRQ_MAX));
         let mut cmdline = kernel_cmdline::Cmdline::new(4096);
@@ -560,7 +763,7 @@ mod tests {
         let start_addr2 = GuestAddress(0x1000);
         let guest_mem =
             GuestMemoryMmap::from_ranges(&[(start_addr1, 0x1000), (start_addr2, 0x1000)]).unwrap();
-        let vm = builder::setup_kvm_vm(&guest_mem, false).unwrap();
+        let vm = builder::setup_kvm_vm(&guest_mem, false, None).unwrap();
         let mut device_manager =
             MMIODeviceManager::new(&mut 0xd000_0000, (arch::IRQ_BASE, arch::IRQ_MAX));
         let mut cmdline = kernel_cmdline::Cmdline::new(4096);
@@ -593,4 +796,14 @@ mod tests {
             .get_device(DeviceType::Virtio(type_id), &id)
             .is_none());
     }
+
+    #[test]
+    fn test_virtio_cmdline() {
+        assert_eq!(virtio_mmio_cmdline_value(0xd000_1000, 6), "4K@0xd0001000:6");
+        assert_eq!(virtio_block_device_name(0), "vda");
+        assert_eq!(virtio_block_device_name(25), "vdz");
+        assert_eq!(virtio_block_device_name(26), "vdaa");
+        assert_eq!(virtio_block_device_name(701), "vdzz");
+        assert_eq!(virtio_block_device_name(702), "vdaaa");
+    }
 }
@@ -5,7 +5,11 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+/// Caps the rate at which the devices interrupt the guest.
+pub mod irq_throttle;
 /// Legacy Device Manager.
 pub mod legacy;
 /// Memory Mapped I/O Manager.
 pub mod mmio;
+/// Manager of the PCI passthrough devices.
+pub mod pci;
@@ -0,0 +1,565 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+#![cfg(target_arch = "x86_64")]
+
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+
+use arch::x86_64::layout::{PCI_IRQ_BASE, PCI_IRQ_MAX};
+use arch::x86_64::{MemoryLayout, PciInterrupt};
+use devices;
+use devices::pci::{PciConfigIo, PciDevice, PCI_CONFIG_IO_PORT, PCI_CONFIG_IO_PORT_SIZE};
+use devices::shared_memory::{SharedMemoryDevice, REGISTERS_BAR_SIZE};
+use devices::vfio::{self, VfioContainer, VfioPciDevice};
+use kvm_bindings::{kvm_irqfd, kvm_userspace_memory_region};
+use kvm_ioctls::VmFd;
+use utils::eventfd::EventFd;
+use utils::ioctl::ioctl_with_ref;
+use vm_memory::{FileOffset, GuestMemory, GuestMemoryMmap, MmapRegion};
+use vmm_config::pci_passthrough::PciPassthroughConfig;
+use vmm_config::shared_memory::{
+    SharedMemoryConfig, MAX_SHARED_MEMORY_SIZE, MIN_SHARED_MEMORY_SIZE,
+};
+
+// The KVM_IRQFD ioctl, and the flag asking KVM to signal a second EventFd when the guest
+// acknowledges a level-triggered interrupt. The latter isn't supported by `VmFd::register_irqfd`.
+const KVM_IRQFD: u64 = 0x4020_ae76;
+const KVM_IRQFD_FLAG_RESAMPLE: u32 = 1 << 1;
+
+// The BARs are placed below the IOAPIC, in the upper half of the 32-bit MMIO hole, or in the
+// upper half of the 64-bit MMIO window, leaving the lower halves to the MMIO devices.
+const IOAPIC_START: u64 = 0xfec0_0000;
+
+/// Errors for the PCI device manager.
+#[derive(Debug)]
+pub enum Error {
+    /// Passthrough devices can't be used along with a balloon device, as their DMAs would miss
+    /// the guest pages reclaimed by the balloon.
+    BalloonNotSupported,
+    /// The BARs of a device don't fit in the MMIO areas.
+    BarsExhausted,
+    /// Failed to perform an operation on the bus.
+    BusError(devices::BusError),
+    /// The PCI bus has no free slots.
+    BusFull,
+    /// Failed to connect to the peer of a shared memory device.
+    ConnectPeer(io::Error),
+    /// Failed to create an EventFd.
+    EventFd(io::Error),
+    /// The size of the file backing a shared memory device changed since it got configured.
+    InvalidMemSize(u64),
+    /// No more IRQs are available.
+    IrqsExhausted,
+    /// Failed to map the file backing a shared memory device.
+    MapMemory(vm_memory::Error),
+    /// Failed to open the file backing a shared memory device.
+    OpenMemFile(io::Error),
+    /// The guest kernel is told not to probe the PCI bus by `pci=off`.
+    PciDisabled,
+    /// Registering an IRQ FD failed.
+    RegisterIrqFd(io::Error),
+    /// Mapping a BAR in the guest physical address space failed.
+    RegisterMemory(kvm_ioctls::Error),
+    /// PCI devices can't be used along with a split irqchip, which doesn't signal the end of
+    /// their level-triggered interrupts.
+    SplitIrqchipNotSupported,
+    /// Failed to open or set up a VFIO device.
+    Vfio(vfio::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+
+        match self {
+            BalloonNotSupported => write!(
+                f,
+                "PCI passthrough devices can't be used along with a balloon device."
+            ),
+            BarsExhausted => write!(f, "No more room for the BARs in the MMIO areas."),
+            BusError(err) => write!(f, "Failed to perform bus operation: {}", err),
+            BusFull => write!(f, "The PCI bus has no free slots."),
+            ConnectPeer(err) => write!(f, "Failed to connect to the shared memory peer: {}", err),
+            EventFd(err) => write!(f, "Failed to create EventFd: {}", err),
+            InvalidMemSize(size) => write!(f, "Invalid shared memory size {}.", size),
+            IrqsExhausted => write!(f, "No more IRQs are available."),
+            MapMemory(err) => write!(f, "Failed to map the shared memory: {:?}", err),
+            OpenMemFile(err) => write!(f, "Failed to open the shared memory file: {}", err),
+            PciDisabled => write!(
+                f,
+                "PCI devices require removing pci=off from the kernel command line."
+            ),
+            RegisterIrqFd(err) => write!(f, "Failed to register irqfd: {}", err),
+            RegisterMemory(err) => write!(f, "Failed to map a BAR in the guest: {}", err),
+            SplitIrqchipNotSupported => {
+                write!(f, "PCI devices can't be used along with a split irqchip.")
+            }
+            Vfio(err) => write!(f, "VFIO error: {}", err),
+        }
+    }
+}
+
+type Result<T> = ::std::result::Result<T, Error>;
+
+// Places the BARs top-down in a window of the guest physical address space.
+struct BarAllocator {
+    start: u64,
+    next: u64,
+}
+
+impl BarAllocator {
+    fn new(start: u64, end: u64) -> Self {
+        BarAllocator { start, next: end }
+    }
+
+    // BARs are naturally aligned.
+    fn allocate(&mut self, size: u64) -> Option<u64> {
+        if size == 0 {
+            return None;
+        }
+        let addr = self.next.checked_sub(size)? & !(size - 1);
+        if addr < self.start {
+            return None;
+        }
+        self.next = addr;
+        Some(addr)
+    }
+}
+
+// A passthrough device, along with its INTx trigger and resample EventFds, which are kept open
+// as long as the device uses them.
+struct PassthroughDevice {
+    _device: Arc<Mutex<VfioPciDevice>>,
+    _irq_evts: Option<(EventFd, EventFd)>,
+}
+
+// A shared memory device, along with the mapping of its memory, which is kept as long as the
+// guest may access it.
+struct SharedMemoryRegion {
+    _device: Arc<Mutex<SharedMemoryDevice>>,
+    _mapping: MmapRegion,
+}
+
+/// Manages the host PCI devices passed through to the guest and the shared memory devices,
+/// plugged in a PCI bus whose configuration space sits on the I/O bus.
+pub struct PciDeviceManager {
+    pci_bus: Arc<Mutex<PciConfigIo>>,
+    container: Option<VfioContainer>,
+    devices: Vec<PassthroughDevice>,
+    shared_memory_regions: Vec<SharedMemoryRegion>,
+    interrupts: Vec<PciInterrupt>,
+    next_irq: u32,
+    next_memslot: u32,
+    mmio32_allocator: BarAllocator,
+    mmio64_allocator: Option<BarAllocator>,
+}
+
+impl PciDeviceManager {
+    /// Creates the PCI bus and plugs its configuration space in `io_bus`. The BARs are placed
+    /// in the MMIO areas of `layout`, for `mem_size` bytes of guest memory, and mapped in the
+    /// memory slots following the ones of the guest memory.
+    pub fn new(
+        io_bus: &mut devices::Bus,
+        layout: &MemoryLayout,
+        guest_mem: &GuestMemoryMmap,
+        mem_size: usize,
+    ) -> Result<Self> {
+        let pci_bus = Arc::new(Mutex::new(PciConfigIo::new()));
+        io_bus
+            .insert(pci_bus.clone(), PCI_CONFIG_IO_PORT, PCI_CONFIG_IO_PORT_SIZE)
+            .map_err(Error::BusError)?;
+
+        let mmio32_start = layout.mmio32_start() + layout.mmio32_hole_size / 2;
+        let mmio64_allocator = if layout.mmio64_window_size > 0 {
+            let mmio64_start = layout.mmio64_start(mem_size);
+            Some(BarAllocator::new(
+                mmio64_start + layout.mmio64_window_size / 2,
+                mmio64_start + layout.mmio64_window_size,
+            ))
+        } else {
+            None
+        };
+
+        Ok(PciDeviceManager {
+            pci_bus,
+            container: None,
+            devices: Vec::new(),
+            shared_memory_regions: Vec::new(),
+            interrupts: Vec::new(),
+            next_irq: PCI_IRQ_BASE,
+            next_memslot: guest_mem.num_regions() as u32,
+            mmio32_allocator: BarAllocator::new(mmio32_start, IOAPIC_START),
+            mmio64_allocator,
+        })
+    }
+
+    /// Opens the host device of `config` and plugs it in the PCI bus. The BARs which can't be
+    /// mapped in the guest physical address space are trapped on `mmio_bus`.
+    pub fn add_device(
+        &mut self,
+        vm: &VmFd,
+        guest_mem: &GuestMemoryMmap,
+        mmio_bus: &mut devices::Bus,
+        config: &PciPassthroughConfig,
+    ) -> Result<()> {
+        if self.container.is_none() {
+            self.container = Some(VfioContainer::new().map_err(Error::Vfio)?);
+        }
+        // Safe to unwrap because the container got opened above.
+        let vfio_device = self
+            .container
+            .as_mut()
+            .unwrap()
+            .open_device(&vfio::pci_sysfs_path(&config.host_bdf), guest_mem)
+            .map_err(Error::Vfio)?;
+
+        // The 64-bit BARs go to the 64-bit MMIO window, when there is one.
+        let mmio32_allocator = &mut self.mmio32_allocator;
+        let mmio64_allocator = &mut self.mmio64_allocator;
+        let mut pci_device =
+            VfioPciDevice::new(
+                vfio_device,
+                &mut |size, is_64bit| match mmio64_allocator.as_mut() {
+                    Some(allocator) if is_64bit => allocator.allocate(size),
+                    _ => mmio32_allocator.allocate(size),
+                },
+            )
+            .map_err(Error::Vfio)?;
+
+        for bar in pci_device.bars() {
+            if bar.mmappable {
+                let host_addr = pci_device.mmap_bar(bar).map_err(Error::Vfio)?;
+                let memory_region = kvm_userspace_memory_region {
+                    slot: self.next_memslot,
+                    guest_phys_addr: bar.addr,
+                    memory_size: bar.size,
+                    userspace_addr: host_addr as u64,
+                    flags: 0,
+                };
+                // Safe because the BAR got mapped above, and doesn't overlap any other region.
+                unsafe { vm.set_user_memory_region(memory_region) }
+                    .map_err(Error::RegisterMemory)?;
+                self.next_memslot += 1;
+            } else {
+                mmio_bus
+                    .insert(
+                        Arc::new(Mutex::new(pci_device.bar_bus_device(bar))),
+                        bar.addr,
+                        bar.size,
+                    )
+                    .map_err(Error::BusError)?;
+            }
+        }
+
+        let pin = pci_device.interrupt_pin();
+        let gsi = self.next_irq;
+        let irq_evts = if pin.is_some() {
+            if gsi > PCI_IRQ_MAX {
+                return Err(Error::IrqsExhausted);
+            }
+            let trigger_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?;
+            let resample_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?;
+            register_resample_irqfd(vm, &trigger_evt, &resample_evt, gsi)?;
+            pci_device
+                .enable_intx(gsi as u8, &trigger_evt, &resample_evt)
+                .map_err(Error::Vfio)?;
+            self.next_irq += 1;
+            Some((trigger_evt, resample_evt))
+        } else {
+            None
+        };
+
+        let pci_device = Arc::new(Mutex::new(pci_device));
+        let slot = self
+            .pci_bus
+            .lock()
+            .expect("Poisoned lock")
+            .add_device(pci_device.clone() as Arc<Mutex<dyn PciDevice>>)
+            .ok_or(Error::BusFull)?;
+        if let Some(pin) = pin {
+            self.interrupts.push(PciInterrupt {
+                slot,
+                pin,
+                gsi: gsi as u8,
+            });
+        }
+        self.devices.push(PassthroughDevice {
+            _device: pci_device,
+            _irq_evts: irq_evts,
+        });
+
+        Ok(())
+    }
+
+    /// Maps the file of `config` in the guest physical address space, behind a shared memory
+    /// device plugged in the PCI bus, whose registers are trapped on `mmio_bus`. The device
+    /// has to be registered with the event manager to handle its peer and interrupt.
+    pub fn add_shared_memory(
+        &mut self,
+        vm: &VmFd,
+        mmio_bus: &mut devices::Bus,
+        config: &SharedMemoryConfig,
+    ) -> Result<Arc<Mutex<SharedMemoryDevice>>> {
+        let gsi = self.next_irq;
+        if gsi > PCI_IRQ_MAX {
+            return Err(Error::IrqsExhausted);
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&config.mem_path)
+            .map_err(Error::OpenMemFile)?;
+        // The size got validated along with the configuration, but the file may have been
+        // truncated since.
+        let size = file.metadata().map_err(Error::OpenMemFile)?.len();
+        if !size.is_power_of_two() || size < MIN_SHARED_MEMORY_SIZE || size > MAX_SHARED_MEMORY_SIZE
+        {
+            return Err(Error::InvalidMemSize(size));
+        }
+        let peer = match config.peer_socket {
+            Some(ref path) => {
+                let peer = UnixStream::connect(path).map_err(Error::ConnectPeer)?;
+                peer.set_nonblocking(true).map_err(Error::ConnectPeer)?;
+                Some(peer)
+            }
+            None => None,
+        };
+
+        // The shared memory goes to the 64-bit MMIO window, when there is one.
+        let memory_addr = match self.mmio64_allocator.as_mut() {
+            Some(allocator) => allocator.allocate(size),
+            None => self.mmio32_allocator.allocate(size),
+        }
+        .ok_or(Error::BarsExhausted)?;
+        let registers_addr = self
+            .mmio32_allocator
+            .allocate(REGISTERS_BAR_SIZE)
+            .ok_or(Error::BarsExhausted)?;
+
+        let mapping = MmapRegion::build(
+            Some(FileOffset::new(file, 0)),
+            size as usize,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_NORESERVE | libc::MAP_SHARED,
+        )
+        .map_err(vm_memory::Error::MmapRegion)
+        .map_err(Error::MapMemory)?;
+        let memory_region = kvm_userspace_memory_region {
+            slot: self.next_memslot,
+            guest_phys_addr: memory_addr,
+            memory_size: size,
+            userspace_addr: mapping.as_ptr() as u64,
+            flags: 0,
+        };
+        // Safe because the file got mapped above, and the region doesn't overlap any other.
+        unsafe { vm.set_user_memory_region(memory_region) }.map_err(Error::RegisterMemory)?;
+        self.next_memslot += 1;
+
+        let trigger_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?;
+        let resample_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?;
+        register_resample_irqfd(vm, &trigger_evt, &resample_evt, gsi)?;
+        self.next_irq += 1;
+
+        let mut device = SharedMemoryDevice::new(
+            registers_addr,
+            memory_addr,
+            size,
+            trigger_evt,
+            resample_evt,
+            peer,
+        );
+        device.set_interrupt_line(gsi as u8);
+        let pin = device.interrupt_pin();
+        let device = Arc::new(Mutex::new(device));
+        mmio_bus
+            .insert(device.clone(), registers_addr, REGISTERS_BAR_SIZE)
+            .map_err(Error::BusError)?;
+        let slot = self
+            .pci_bus
+            .lock()
+            .expect("Poisoned lock")
+            .add_device(device.clone() as Arc<Mutex<dyn PciDevice>>)
+            .ok_or(Error::BusFull)?;
+        self.interrupts.push(PciInterrupt {
+            slot,
+            pin,
+            gsi: gsi as u8,
+        });
+        self.shared_memory_regions.push(SharedMemoryRegion {
+            _device: device.clone(),
+            _mapping: mapping,
+        });
+
+        Ok(device)
+    }
+
+    /// Returns the number of passthrough devices.
+    pub fn device_count(&self) -> usize {
+        self.devices.len()
+    }
+
+    /// Returns the INTx interrupts of the devices, to be described to the guest.
+    pub fn interrupts(&self) -> &[PciInterrupt] {
+        &self.interrupts
+    }
+}
+
+// Routes the interrupts signaled to `trigger_evt` to `gsi`, as level-triggered interrupts which
+// are unmasked by signaling `resample_evt` once the guest acknowledges them.
+fn register_resample_irqfd(
+    vm: &VmFd,
+    trigger_evt: &EventFd,
+    resample_evt: &EventFd,
+    gsi: u32,
+) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let irqfd = kvm_irqfd {
+        fd: trigger_evt.as_raw_fd() as u32,
+        gsi,
+        flags: KVM_IRQFD_FLAG_RESAMPLE,
+        resamplefd: resample_evt.as_raw_fd() as u32,
+        ..Default::default()
+    };
+    // Safe because the kernel only reads the request.
+    let ret = unsafe { ioctl_with_ref(vm, KVM_IRQFD, &irqfd) };
+    if ret < 0 {
+        return Err(Error::RegisterIrqFd(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bar_allocator() {
+        let mut allocator = BarAllocator::new(0x1000_0000, 0x2000_0000);
+        assert_eq!(allocator.allocate(0x1000), Some(0x1fff_f000));
+        // Naturally aligned.
+        assert_eq!(allocator.allocate(0x10_0000), Some(0x1fe0_0000));
+        assert_eq!(allocator.allocate(0x1000), Some(0x1fdf_f000));
+        assert_eq!(allocator.allocate(0x1000_0000), None);
+        assert_eq!(allocator.allocate(0x800_0000), Some(0x1000_0000));
+        assert_eq!(allocator.allocate(0x1000), None);
+
+        let mut allocator = BarAllocator::new(0, 0x1000);
+        assert_eq!(allocator.allocate(0x2000), None);
+        assert_eq!(allocator.allocate(0), None);
+    }
+
+    #[test]
+    fn test_pci_device_manager() {
+        let guest_mem =
+            GuestMemoryMmap::from_ranges(&[(vm_memory::GuestAddress(0), 0x1000)]).unwrap();
+        let mut io_bus = devices::Bus::new();
+        let layout = MemoryLayout::default();
+        let pci_device_manager =
+            PciDeviceManager::new(&mut io_bus, &layout, &guest_mem, 0x1000).unwrap();
+        assert!(io_bus.get_device(PCI_CONFIG_IO_PORT).is_some());
+        assert_eq!(pci_device_manager.device_count(), 0);
+        assert!(pci_device_manager.interrupts().is_empty());
+        assert_eq!(pci_device_manager.next_memslot, 1);
+        assert!(pci_device_manager.mmio64_allocator.is_none());
+        assert_eq!(
+            pci_device_manager.mmio32_allocator.start,
+            layout.mmio32_start() + layout.mmio32_hole_size / 2
+        );
+
+        // The configuration space can only be plugged once.
+        assert!(PciDeviceManager::new(&mut io_bus, &layout, &guest_mem, 0x1000).is_err());
+
+        let layout = MemoryLayout {
+            mmio64_window_size: 1 << 30,
+            ..Default::default()
+        };
+        let pci_device_manager =
+            PciDeviceManager::new(&mut devices::Bus::new(), &layout, &guest_mem, 0x1000).unwrap();
+        let allocator = pci_device_manager.mmio64_allocator.unwrap();
+        assert_eq!(allocator.start, layout.mmio64_start(0x1000) + (1 << 29));
+        assert_eq!(allocator.next, layout.mmio64_start(0x1000) + (1 << 30));
+    }
+
+    #[test]
+    fn test_add_shared_memory() {
+        use builder;
+        use utils::tempfile::TempFile;
+
+        let guest_mem =
+            GuestMemoryMmap::from_ranges(&[(vm_memory::GuestAddress(0), 0x1000)]).unwrap();
+        let mut vm = builder::setup_kvm_vm(&guest_mem, false, None).unwrap();
+        builder::setup_interrupt_controller(&mut vm, true).unwrap();
+        let mut mmio_bus = devices::Bus::new();
+        let layout = MemoryLayout::default();
+        let mut pci_device_manager =
+            PciDeviceManager::new(&mut devices::Bus::new(), &layout, &guest_mem, 0x1000).unwrap();
+
+        let mem_file = TempFile::new().unwrap();
+        let mut config = SharedMemoryConfig {
+            shm_id: "shm".to_string(),
+            mem_path: mem_file.as_path().to_path_buf(),
+            peer_socket: None,
+        };
+        // The file got truncated after being configured.
+        match pci_device_manager.add_shared_memory(vm.fd(), &mut mmio_bus, &config) {
+            Err(Error::InvalidMemSize(0)) => (),
+            _ => panic!("Unexpected result"),
+        }
+
+        mem_file.as_file().set_len(0x10_0000).unwrap();
+        pci_device_manager
+            .add_shared_memory(vm.fd(), &mut mmio_bus, &config)
+            .unwrap();
+        // Both BARs are in the upper half of the 32-bit MMIO hole, without a 64-bit window.
+        assert_eq!(
+            pci_device_manager.mmio32_allocator.next,
+            IOAPIC_START - 0x10_0000 - REGISTERS_BAR_SIZE
+        );
+        assert!(mmio_bus
+            .get_device(pci_device_manager.mmio32_allocator.next)
+            .is_some());
+        assert_eq!(pci_device_manager.next_memslot, 2);
+        assert_eq!(pci_device_manager.next_irq, PCI_IRQ_BASE + 1);
+        assert_eq!(pci_device_manager.interrupts().len(), 1);
+        assert_eq!(pci_device_manager.interrupts()[0].slot, 1);
+        assert_eq!(pci_device_manager.interrupts()[0].gsi, PCI_IRQ_BASE as u8);
+        assert_eq!(pci_device_manager.device_count(), 0);
+
+        // The peer has to be listening.
+        config.peer_socket = Some(TempFile::new().unwrap().as_path().to_path_buf());
+        match pci_device_manager.add_shared_memory(vm.fd(), &mut mmio_bus, &config) {
+            Err(Error::ConnectPeer(_)) => (),
+            _ => panic!("Unexpected result"),
+        }
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::Error::*;
+
+        for err in &[
+            BalloonNotSupported,
+            BarsExhausted,
+            BusError(devices::BusError::Overlap),
+            BusFull,
+            ConnectPeer(io::Error::from_raw_os_error(0)),
+            EventFd(io::Error::from_raw_os_error(0)),
+            InvalidMemSize(0x3000),
+            IrqsExhausted,
+            MapMemory(vm_memory::Error::InvalidGuestRegion),
+            OpenMemFile(io::Error::from_raw_os_error(0)),
+            PciDisabled,
+            RegisterIrqFd(io::Error::from_raw_os_error(0)),
+            RegisterMemory(kvm_ioctls::Error::new(0)),
+            SplitIrqchipNotSupported,
+            Vfio(vfio::Error::NotPciDevice),
+        ] {
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+}
@@ -0,0 +1,363 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Runs the emulation of a device on a thread of its own instead of on the VMM thread.
+//!
+//! The thread drives the device with its own `EventManager`, and is only scheduled on the host
+//! CPUs it is given, e.g. the ones local to the NUMA node of the NIC backing a network device.
+//!
+//! Unlike the devices emulated on the VMM thread, which are held back while the VMM thread is
+//! busy, the device keeps running on its thread until it is paused through its `DeviceThread`
+//! handle, e.g. while the state of the microVM is saved. The thread takes the commands of the
+//! handle between two rounds of events, so a paused device is never in the middle of one.
+
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use polly::event_manager::{Error as EventManagerError, EventManager, Subscriber};
+use seccomp::{BpfProgram, SeccompFilter};
+use utils::epoll::{EpollEvent, EventSet};
+use utils::eventfd::EventFd;
+
+/// The number of host CPUs a device thread can be pinned to.
+pub const MAX_CPUS: usize = libc::CPU_SETSIZE as usize;
+
+/// Errors associated with the device threads.
+#[derive(Debug)]
+pub enum DeviceThreadError {
+    /// Cannot create or write the event fd waking the thread up.
+    EventFd(io::Error),
+    /// Cannot create the event manager of the thread, or register the device with it.
+    EventManager(EventManagerError),
+    /// The thread exited before it could run the device, or before it could pause it.
+    Exited,
+    /// Cannot apply the seccomp filter to the thread.
+    Seccomp(seccomp::Error),
+    /// Cannot pin the thread to its host CPUs.
+    SetAffinity(io::Error),
+    /// Cannot spawn the thread.
+    Spawn(io::Error),
+}
+
+impl Display for DeviceThreadError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::DeviceThreadError::*;
+        match self {
+            EventFd(err) => write!(f, "Cannot wake the device thread up: {}", err),
+            EventManager(err) => write!(f, "Cannot run the device event loop: {:?}", err),
+            Exited => write!(f, "The device thread exited unexpectedly."),
+            Seccomp(err) => write!(f, "Cannot apply the seccomp filter: {}", err),
+            SetAffinity(err) => write!(f, "Cannot set the CPU affinity: {}", err),
+            Spawn(err) => write!(f, "Cannot spawn the device thread: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DeviceThreadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DeviceThreadError::EventFd(err)
+            | DeviceThreadError::SetAffinity(err)
+            | DeviceThreadError::Spawn(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, DeviceThreadError>;
+
+// The commands the handle sends to its thread.
+enum Command {
+    Pause,
+    Resume,
+    Stop,
+}
+
+// Wakes the event loop of the thread up, so that it takes the commands of the handle.
+struct Kick(EventFd);
+
+impl Subscriber for Kick {
+    fn process(&mut self, _event: &EpollEvent, _event_manager: &mut EventManager) {
+        let _ = self.0.read();
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        vec![EpollEvent::new(EventSet::IN, self.0.as_raw_fd() as u64)]
+    }
+}
+
+/// Controls a device thread. The thread is stopped when the handle is dropped.
+pub struct DeviceThread {
+    commands: mpsc::Sender<Command>,
+    kick_evt: EventFd,
+    paused_receiver: mpsc::Receiver<()>,
+    paused: bool,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl DeviceThread {
+    /// Stops the thread from handling the events of the device, and returns once it did. The
+    /// device is left alone until it is resumed.
+    pub fn pause(&mut self) -> Result<()> {
+        if self.paused {
+            return Ok(());
+        }
+        self.send(Command::Pause)?;
+        self.paused_receiver
+            .recv()
+            .map_err(|_| DeviceThreadError::Exited)?;
+        self.paused = true;
+        Ok(())
+    }
+
+    /// Lets the thread handle the events of the device again.
+    pub fn resume(&mut self) -> Result<()> {
+        if !self.paused {
+            return Ok(());
+        }
+        self.send(Command::Resume)?;
+        self.paused = false;
+        Ok(())
+    }
+
+    /// Returns true if the device is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Stops the thread, paused or not, and waits for it to exit.
+    pub fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            // The thread is gone already if the command can't be sent.
+            let _ = self.send(Command::Stop);
+            let _ = handle.join();
+            self.paused = false;
+        }
+    }
+
+    fn send(&self, command: Command) -> Result<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| DeviceThreadError::Exited)?;
+        self.kick_evt.write(1).map_err(DeviceThreadError::EventFd)
+    }
+}
+
+impl Drop for DeviceThread {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Spawns the thread `name`, pinned to the host `cpus` and confined by `seccomp_filter`, which
+/// handles the events of `device` from then on.
+///
+/// Returns the handle of the thread once the device is registered with its event manager. The
+/// device must not be registered with any other event manager.
+pub fn spawn<T>(
+    name: String,
+    device: Arc<Mutex<T>>,
+    cpus: &[usize],
+    seccomp_filter: BpfProgram,
+) -> Result<DeviceThread>
+where
+    T: Subscriber + Send + 'static,
+{
+    let cpus = cpus.to_vec();
+    let kick_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(DeviceThreadError::EventFd)?;
+    let kick = Arc::new(Mutex::new(Kick(
+        kick_evt.try_clone().map_err(DeviceThreadError::EventFd)?,
+    )));
+    let (setup_sender, setup_receiver) = mpsc::channel();
+    let (commands, command_receiver) = mpsc::channel();
+    let (paused_sender, paused_receiver) = mpsc::channel();
+    let handle = thread::Builder::new()
+        .name(name)
+        .spawn(move || {
+            // The event manager is created by the thread itself, since it can't be sent to it.
+            let setup = set_affinity(&cpus)
+                .map_err(DeviceThreadError::SetAffinity)
+                .and_then(|_| EventManager::new().map_err(DeviceThreadError::EventManager))
+                .and_then(|mut event_manager| {
+                    event_manager
+                        .add_subscriber(device)
+                        .map_err(DeviceThreadError::EventManager)?;
+                    event_manager
+                        .add_subscriber(kick)
+                        .map_err(DeviceThreadError::EventManager)?;
+                    SeccompFilter::apply(seccomp_filter).map_err(DeviceThreadError::Seccomp)?;
+                    Ok(event_manager)
+                });
+            let mut event_manager = match setup {
+                Ok(event_manager) => {
+                    // The builder waits for this message, so sending it can't fail.
+                    let _ = setup_sender.send(Ok(()));
+                    event_manager
+                }
+                Err(err) => {
+                    let _ = setup_sender.send(Err(err));
+                    return;
+                }
+            };
+            loop {
+                event_manager
+                    .run()
+                    .expect("Failed to run the device event loop");
+                if !take_commands(&command_receiver, &paused_sender) {
+                    return;
+                }
+            }
+        })
+        .map_err(DeviceThreadError::Spawn)?;
+
+    setup_receiver
+        .recv()
+        .unwrap_or(Err(DeviceThreadError::Exited))?;
+    Ok(DeviceThread {
+        commands,
+        kick_evt,
+        paused_receiver,
+        paused: false,
+        handle: Some(handle),
+    })
+}
+
+// Carries out the commands sent to the thread since the last round of events, blocking while the
+// device is paused. Returns false once the thread has to exit.
+fn take_commands(commands: &mpsc::Receiver<Command>, paused: &mpsc::Sender<()>) -> bool {
+    let mut blocking = false;
+    loop {
+        let command = if blocking {
+            commands.recv().ok()
+        } else {
+            match commands.try_recv() {
+                Ok(command) => Some(command),
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => None,
+            }
+        };
+        match command {
+            Some(Command::Pause) => {
+                // The handle waits for this message, so sending it can't fail.
+                let _ = paused.send(());
+                blocking = true;
+            }
+            Some(Command::Resume) => blocking = false,
+            Some(Command::Stop) | None => return false,
+        }
+    }
+}
+
+/// Restricts the calling thread to the host `cpus`.
+fn set_affinity(cpus: &[usize]) -> io::Result<()> {
+    // Safe because `cpu_set_t` is plain data, and all zeroes is the empty set.
+    let mut cpu_set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    for &cpu in cpus {
+        if cpu >= MAX_CPUS {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        // Safe because `cpu` is within the bounds of `cpu_set`.
+        unsafe { libc::CPU_SET(cpu, &mut cpu_set) };
+    }
+    // Safe because the kernel only reads `cpu_set`, which is valid, and we check the result.
+    if unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &cpu_set) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    struct DummyDevice {
+        event: EventFd,
+        handled: mpsc::Sender<usize>,
+    }
+
+    impl Subscriber for DummyDevice {
+        fn process(&mut self, _event: &EpollEvent, _event_manager: &mut EventManager) {
+            self.event.read().unwrap();
+            // Report the CPU the device is emulated on.
+            let cpu = unsafe { libc::sched_getcpu() };
+            self.handled.send(cpu as usize).unwrap();
+        }
+
+        fn interest_list(&self) -> Vec<EpollEvent> {
+            vec![EpollEvent::new(EventSet::IN, self.event.as_raw_fd() as u64)]
+        }
+    }
+
+    #[test]
+    fn test_spawn() {
+        let cpu = unsafe { libc::sched_getcpu() };
+        assert!(cpu >= 0);
+        let cpu = cpu as usize;
+
+        let (sender, receiver) = mpsc::channel();
+        let event = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let device = Arc::new(Mutex::new(DummyDevice {
+            event: event.try_clone().unwrap(),
+            handled: sender,
+        }));
+        let mut thread = spawn(String::from("fc_test_device"), device, &[cpu], vec![]).unwrap();
+
+        event.write(1).unwrap();
+        assert_eq!(receiver.recv().unwrap(), cpu);
+
+        // The events of a paused device are left pending until it is resumed.
+        thread.pause().unwrap();
+        assert!(thread.is_paused());
+        thread.pause().unwrap();
+        event.write(1).unwrap();
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+        thread.resume().unwrap();
+        assert!(!thread.is_paused());
+        assert_eq!(receiver.recv().unwrap(), cpu);
+
+        // A paused thread can be stopped, and the device is dropped along with it.
+        thread.pause().unwrap();
+        thread.stop();
+        assert!(receiver.recv().is_err());
+        match thread.pause() {
+            Err(DeviceThreadError::Exited) => (),
+            _ => panic!("Unexpected result"),
+        }
+    }
+
+    #[test]
+    fn test_spawn_errors() {
+        let (sender, _receiver) = mpsc::channel();
+        let device = Arc::new(Mutex::new(DummyDevice {
+            event: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            handled: sender,
+        }));
+        match spawn(String::from("fc_test_device"), device, &[MAX_CPUS], vec![]) {
+            Err(DeviceThreadError::SetAffinity(_)) => (),
+            _ => panic!("Unexpected result"),
+        }
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let err = DeviceThreadError::EventFd(io::Error::from_raw_os_error(libc::EBADF));
+        let _ = format!("{}{:?}", err, err);
+        let err = DeviceThreadError::EventManager(EventManagerError::NotFound(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = DeviceThreadError::Exited;
+        let _ = format!("{}{:?}", err, err);
+        let err = DeviceThreadError::Seccomp(seccomp::Error::Load(libc::EINVAL));
+        let _ = format!("{}{:?}", err, err);
+        let err = DeviceThreadError::SetAffinity(io::Error::from_raw_os_error(libc::EINVAL));
+        let _ = format!("{}{:?}", err, err);
+        let err = DeviceThreadError::Spawn(io::Error::from_raw_os_error(libc::EAGAIN));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -0,0 +1,276 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Stable codes for the ways Firecracker fails: the exit codes of the process, and the codes of
+//! the errors of the API actions, which control planes can branch on instead of parsing error
+//! messages.
+//!
+//! The numeric values of the codes never change meaning. New kinds of failures get new values.
+
+/// The exit code of the Firecracker process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FcExitCode {
+    /// 0: success.
+    Ok = 0,
+    /// 1: generic error.
+    GenericError = 1,
+    /// 2: an error considered not possible to occur if the program logic is sound.
+    UnexpectedError = 2,
+    /// 148: Firecracker was shut down after intercepting a restricted system call.
+    BadSyscall = 148,
+    /// 149: Firecracker was shut down after intercepting `SIGBUS`.
+    SigBus = 149,
+    /// 150: Firecracker was shut down after intercepting `SIGSEGV`.
+    SigSegv = 150,
+    /// 151: the host could not provide the guest memory, and the OOM policy is to exit.
+    OutOfMemory = 151,
+    /// 152: bad configuration for the microVM resources, when using a single JSON file.
+    BadConfiguration = 152,
+    /// 153: command line arguments parsing error.
+    ArgParsing = 153,
+    /// 154: the host ran out of space for the backing file of a drive, and its ENOSPC policy is
+    /// to stop the microVM.
+    DriveOutOfSpace = 154,
+}
+
+impl From<FcExitCode> for i32 {
+    fn from(exit_code: FcExitCode) -> Self {
+        i32::from(exit_code as u8)
+    }
+}
+
+/// Tells who can fix the cause of an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    /// The request is malformed, or its values are invalid: the request has to be fixed.
+    InvalidArgument,
+    /// The request is not allowed in the current state of the microVM.
+    InvalidState,
+    /// A host resource or another process the action relies on failed.
+    External,
+    /// Firecracker failed internally.
+    Internal,
+}
+
+/// The kind of error an API action failed with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// 1000: Firecracker failed internally.
+    InternalVmm,
+    /// 1001: the action is not supported before starting the microVM.
+    OperationNotSupportedPreBoot,
+    /// 1002: the action is not supported after starting the microVM.
+    OperationNotSupportedPostBoot,
+    /// 1003: loading a snapshot is not allowed after configuring the microVM for boot.
+    LoadSnapshotNotAllowed,
+    /// 1004: the idempotency key was already used for another action.
+    IdempotencyKeyReused,
+    /// 1005: the action policy of the embedder rejected the action.
+    ActionDenied,
+    /// 1006: too many actions are waiting for the VMM already.
+    ActionQueueFull,
+    /// 1007: the action is not allowed while an operation runs in the background.
+    OperationInProgress,
+    /// 1008: no operation with the given ID.
+    OperationNotFound,
+    /// 1100: invalid boot source.
+    BootSource,
+    /// 1101: invalid machine configuration.
+    MachineConfig,
+    /// 1102: invalid logger configuration.
+    Logger,
+    /// 1103: invalid metrics configuration.
+    Metrics,
+    /// 1104: invalid MMDS configuration.
+    MmdsConfig,
+    /// 1105: invalid SEV configuration, or no launch measurement.
+    SevConfig,
+    /// 1106: invalid RTC configuration.
+    RtcConfig,
+    /// 1107: the configuration checked with `ValidateConfiguration` is invalid, or the resources
+    /// of the microVM are inconsistent and its configuration is strict.
+    InvalidConfiguration,
+    /// 1108: invalid memory limits, or the memory limits cannot be set.
+    MemoryLimitsConfig,
+    /// 1109: invalid console configuration, or the input cannot be written to the console.
+    ConsoleConfig,
+    /// 1110: invalid probe.
+    ProbeConfig,
+    /// 1111: invalid adaptive rate limiting policy.
+    RateLimitPolicy,
+    /// 1112: invalid virtio feature policy.
+    VirtioFeaturePolicy,
+    /// 1113: invalid cloud-init configuration.
+    CloudInit,
+    /// 1114: the vCPU cannot be parked or unparked.
+    VcpuState,
+    /// 1200: invalid drive, or the drive cannot be updated.
+    DriveConfig,
+    /// 1201: invalid network interface, or the network interface cannot be updated.
+    NetworkConfig,
+    /// 1202: invalid balloon device, or the balloon cannot be updated.
+    BalloonConfig,
+    /// 1203: invalid vsock device, or its connections cannot be listed or drained.
+    VsockConfig,
+    /// 1204: invalid entropy device.
+    EntropyConfig,
+    /// 1205: invalid GPU device.
+    GpuConfig,
+    /// 1206: invalid input device, or the input events cannot be sent.
+    InputConfig,
+    /// 1207: invalid sound device.
+    SoundConfig,
+    /// 1208: invalid PCI passthrough device.
+    PciPassthroughConfig,
+    /// 1209: invalid shared memory device.
+    SharedMemoryConfig,
+    /// 1210: no virtio device with the given ID.
+    DeviceNotFound,
+    /// 1300: the microVM failed to start.
+    StartMicrovm,
+    /// 1301: the snapshot cannot be created.
+    CreateSnapshot,
+    /// 1302: the snapshot cannot be loaded.
+    LoadSnapshot,
+    /// 1303: the live update failed.
+    LiveUpdate,
+    /// 1304: the migration failed.
+    Migration,
+    /// 1305: the kernel log of the guest cannot be extracted.
+    GuestDmesg,
+}
+
+impl ErrorCode {
+    /// The stable numeric value of the code.
+    pub fn code(self) -> u32 {
+        use self::ErrorCode::*;
+        match self {
+            InternalVmm => 1000,
+            OperationNotSupportedPreBoot => 1001,
+            OperationNotSupportedPostBoot => 1002,
+            LoadSnapshotNotAllowed => 1003,
+            IdempotencyKeyReused => 1004,
+            ActionDenied => 1005,
+            ActionQueueFull => 1006,
+            OperationInProgress => 1007,
+            OperationNotFound => 1008,
+            BootSource => 1100,
+            MachineConfig => 1101,
+            Logger => 1102,
+            Metrics => 1103,
+            MmdsConfig => 1104,
+            SevConfig => 1105,
+            RtcConfig => 1106,
+            InvalidConfiguration => 1107,
+            MemoryLimitsConfig => 1108,
+            ConsoleConfig => 1109,
+            ProbeConfig => 1110,
+            RateLimitPolicy => 1111,
+            VirtioFeaturePolicy => 1112,
+            CloudInit => 1113,
+            VcpuState => 1114,
+            DriveConfig => 1200,
+            NetworkConfig => 1201,
+            BalloonConfig => 1202,
+            VsockConfig => 1203,
+            EntropyConfig => 1204,
+            GpuConfig => 1205,
+            InputConfig => 1206,
+            SoundConfig => 1207,
+            PciPassthroughConfig => 1208,
+            SharedMemoryConfig => 1209,
+            DeviceNotFound => 1210,
+            StartMicrovm => 1300,
+            CreateSnapshot => 1301,
+            LoadSnapshot => 1302,
+            LiveUpdate => 1303,
+            Migration => 1304,
+            GuestDmesg => 1305,
+        }
+    }
+
+    /// Who can fix the cause of the error, for most errors of the code. The errors of starting,
+    /// live updating and migrating the microVM are categorized by their cause instead, with
+    /// `VmmActionError::error_category`.
+    pub fn category(self) -> ErrorCategory {
+        use self::ErrorCode::*;
+        match self {
+            InternalVmm | StartMicrovm => ErrorCategory::Internal,
+            OperationNotSupportedPreBoot
+            | OperationNotSupportedPostBoot
+            | LoadSnapshotNotAllowed
+            | ActionDenied
+            | ActionQueueFull
+            | OperationInProgress => ErrorCategory::InvalidState,
+            CreateSnapshot | LoadSnapshot | LiveUpdate | Migration | GuestDmesg => {
+                ErrorCategory::External
+            }
+            _ => ErrorCategory::InvalidArgument,
+        }
+    }
+
+    /// Specifies if the same request may succeed when retried later, without any other action,
+    /// e.g. once the VMM is done with the actions queued, or once the operation running in the
+    /// background completes. Whether a live update or a migration is retriable depends on its
+    /// cause instead, see `VmmActionError::retriable`.
+    pub fn retriable(self) -> bool {
+        match self {
+            ErrorCode::ActionQueueFull
+            | ErrorCode::LiveUpdate
+            | ErrorCode::Migration
+            | ErrorCode::OperationInProgress => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(i32::from(FcExitCode::Ok), 0);
+        assert_eq!(i32::from(FcExitCode::BadSyscall), 148);
+        assert_eq!(i32::from(FcExitCode::OutOfMemory), 151);
+        assert_eq!(i32::from(FcExitCode::ArgParsing), 153);
+        assert_eq!(i32::from(FcExitCode::DriveOutOfSpace), 154);
+    }
+
+    #[test]
+    fn test_error_codes() {
+        assert_eq!(ErrorCode::InternalVmm.code(), 1000);
+        assert_eq!(ErrorCode::DriveConfig.code(), 1200);
+        assert_eq!(ErrorCode::Migration.code(), 1304);
+
+        assert_eq!(
+            ErrorCode::DriveConfig.category(),
+            ErrorCategory::InvalidArgument
+        );
+        assert_eq!(
+            ErrorCode::OperationNotSupportedPreBoot.category(),
+            ErrorCategory::InvalidState
+        );
+        assert_eq!(
+            ErrorCode::ActionDenied.category(),
+            ErrorCategory::InvalidState
+        );
+        assert_eq!(ErrorCode::LoadSnapshot.category(), ErrorCategory::External);
+        assert!(ErrorCode::LiveUpdate.retriable());
+        assert!(ErrorCode::ActionQueueFull.retriable());
+        assert_eq!(ErrorCode::OperationInProgress.code(), 1007);
+        assert!(ErrorCode::OperationInProgress.retriable());
+        assert_eq!(
+            ErrorCode::OperationNotFound.category(),
+            ErrorCategory::InvalidArgument
+        );
+        assert!(!ErrorCode::DriveConfig.retriable());
+
+        assert_eq!(
+            serde_json::to_value(ErrorCategory::InvalidArgument).unwrap(),
+            "invalid_argument"
+        );
+    }
+}
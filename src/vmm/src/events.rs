@@ -0,0 +1,298 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the structured events which the VMM surfaces to the control plane.
+//!
+//! Devices and other VMM components push `VmmEvent`s through an `EventSender`. The events are
+//! buffered in the `EventChannel` until the control plane drains them through the API.
+
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+
+use devices::legacy::I8042ResetRequest;
+use devices::virtio::{BalloonEvent, BlockEvent};
+use logger::{Metric, METRICS};
+use operations::{OperationKind, OperationState, OperationStatus};
+use probes::ProbeState;
+use vmm_config::drive::EnospcPolicy;
+use vmm_config::probe::ProbeKind;
+
+/// Maximum number of events buffered while waiting for the control plane to drain them.
+/// Newer events are dropped once the channel is full.
+pub const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Events surfaced by the VMM to the control plane.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum VmmEvent {
+    /// The guest deflated the balloon, either on request or because it is under memory pressure.
+    BalloonDeflated {
+        /// Amount of memory taken back by the guest, in KiB.
+        amount_kib: u64,
+        /// Amount of memory still held by the balloon, in KiB.
+        balloon_kib: u64,
+    },
+    /// The host ran out of space for the backing file of a drive, e.g. a sparse file on a full
+    /// volume. Sent once, until a write to the drive succeeds again or the drive is resumed.
+    DriveOutOfSpace {
+        /// ID of the drive.
+        drive_id: String,
+        /// What the drive did about it. A paused drive is resumed through the API once the host
+        /// has more space.
+        policy: EnospcPolicy,
+    },
+    /// The guest reported free memory, which was handed back to the host.
+    FreePagesReported {
+        /// Amount of memory reported as free, in KiB.
+        amount_kib: u64,
+    },
+    /// The guest asked for the machine to be reset. Firecracker exits right after.
+    GuestResetRequested {
+        /// How the guest asked for the reset.
+        source: ResetSource,
+    },
+    /// An operation running in the background completed.
+    OperationCompleted {
+        /// ID of the operation.
+        operation_id: u64,
+        /// What the operation did.
+        kind: OperationKind,
+        /// Whether the operation succeeded.
+        state: OperationState,
+        /// Why the operation failed.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// A probe of the workload inside the guest changed state.
+    ProbeStateChanged {
+        /// ID of the probe.
+        probe_id: String,
+        /// What the probe tells the control plane.
+        kind: ProbeKind,
+        /// The new state of the probe.
+        state: ProbeState,
+    },
+    /// A vCPU retried `KVM_RUN` after it failed with errors which may go away, e.g. when the host
+    /// was short of memory. Sent once the vCPU recovers or gives up, in which case the microVM
+    /// stops.
+    VcpuRetried {
+        /// Index of the vCPU.
+        vcpu_id: u8,
+        /// The error number of the last failure.
+        errno: i32,
+        /// Number of consecutive failures.
+        failures: u32,
+        /// Whether `KVM_RUN` eventually succeeded.
+        recovered: bool,
+    },
+}
+
+/// The mechanisms through which the guest can ask for the machine to be reset.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResetSource {
+    /// The guest pulsed the CPU reset line of the i8042 controller.
+    I8042PulseOutputLine,
+    /// The guest cleared the CPU reset line of the i8042 output port.
+    I8042OutputPort,
+}
+
+impl From<BalloonEvent> for VmmEvent {
+    fn from(event: BalloonEvent) -> Self {
+        match event {
+            BalloonEvent::Deflated {
+                amount_kib,
+                balloon_kib,
+            } => VmmEvent::BalloonDeflated {
+                amount_kib,
+                balloon_kib,
+            },
+            BalloonEvent::FreePagesReported { amount_kib } => {
+                VmmEvent::FreePagesReported { amount_kib }
+            }
+        }
+    }
+}
+
+impl From<BlockEvent> for VmmEvent {
+    fn from(event: BlockEvent) -> Self {
+        match event {
+            BlockEvent::OutOfSpace { drive_id, policy } => VmmEvent::DriveOutOfSpace {
+                drive_id,
+                policy: policy.into(),
+            },
+        }
+    }
+}
+
+impl From<OperationStatus> for VmmEvent {
+    fn from(status: OperationStatus) -> Self {
+        VmmEvent::OperationCompleted {
+            operation_id: status.operation_id,
+            kind: status.kind,
+            state: status.state,
+            error: status.error,
+        }
+    }
+}
+
+impl From<I8042ResetRequest> for VmmEvent {
+    fn from(request: I8042ResetRequest) -> Self {
+        let source = match request {
+            I8042ResetRequest::PulseOutputLine(_) => ResetSource::I8042PulseOutputLine,
+            I8042ResetRequest::WriteOutputPort(_) => ResetSource::I8042OutputPort,
+        };
+        VmmEvent::GuestResetRequested { source }
+    }
+}
+
+/// Producer end of the `EventChannel`.
+#[derive(Clone)]
+pub struct EventSender(SyncSender<VmmEvent>);
+
+impl EventSender {
+    /// Pushes `event` in the channel, without blocking. The event is dropped if the channel is
+    /// full or if the control plane end of the channel is gone.
+    pub fn send<E: Into<VmmEvent>>(&self, event: E) {
+        match self.0.try_send(event.into()) {
+            Ok(()) => (),
+            Err(TrySendError::Full(event)) => {
+                METRICS.vmm.dropped_events.inc();
+                warn!("Event channel is full, dropping event: {:?}", event);
+            }
+            Err(TrySendError::Disconnected(_)) => METRICS.vmm.dropped_events.inc(),
+        }
+    }
+}
+
+/// Buffers the events surfaced to the control plane.
+pub struct EventChannel {
+    sender: EventSender,
+    receiver: Receiver<VmmEvent>,
+}
+
+impl Default for EventChannel {
+    fn default() -> Self {
+        Self::new(EVENT_CHANNEL_CAPACITY)
+    }
+}
+
+impl EventChannel {
+    /// Creates an `EventChannel` which can buffer up to `capacity` events.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, receiver) = sync_channel(capacity);
+        EventChannel {
+            sender: EventSender(sender),
+            receiver,
+        }
+    }
+
+    /// Returns a new producer end of the channel.
+    pub fn sender(&self) -> EventSender {
+        self.sender.clone()
+    }
+
+    /// Returns all the pending events, in the order they were sent.
+    pub fn drain(&self) -> Vec<VmmEvent> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_channel() {
+        let channel = EventChannel::new(2);
+        assert!(channel.drain().is_empty());
+
+        let sender = channel.sender();
+        sender.send(BalloonEvent::FreePagesReported { amount_kib: 4 });
+        sender.send(VmmEvent::BalloonDeflated {
+            amount_kib: 8,
+            balloon_kib: 0,
+        });
+        // The channel is full, so this event gets dropped.
+        let dropped_events = METRICS.vmm.dropped_events.count();
+        sender.send(VmmEvent::FreePagesReported { amount_kib: 16 });
+        assert_eq!(METRICS.vmm.dropped_events.count(), dropped_events + 1);
+
+        assert_eq!(
+            channel.drain(),
+            vec![
+                VmmEvent::FreePagesReported { amount_kib: 4 },
+                VmmEvent::BalloonDeflated {
+                    amount_kib: 8,
+                    balloon_kib: 0,
+                },
+            ]
+        );
+        assert!(channel.drain().is_empty());
+    }
+
+    #[test]
+    fn test_event_serialization() {
+        assert_eq!(
+            serde_json::to_string(&VmmEvent::BalloonDeflated {
+                amount_kib: 8,
+                balloon_kib: 4,
+            })
+            .unwrap(),
+            r#"{"type":"balloon_deflated","amount_kib":8,"balloon_kib":4}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&VmmEvent::from(BlockEvent::OutOfSpace {
+                drive_id: "scratch".to_string(),
+                policy: devices::virtio::EnospcPolicy::PauseDrive,
+            }))
+            .unwrap(),
+            r#"{"type":"drive_out_of_space","drive_id":"scratch","policy":"pause_drive"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&VmmEvent::FreePagesReported { amount_kib: 8 }).unwrap(),
+            r#"{"type":"free_pages_reported","amount_kib":8}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&VmmEvent::from(I8042ResetRequest::PulseOutputLine(0xFE)))
+                .unwrap(),
+            r#"{"type":"guest_reset_requested","source":"i8042_pulse_output_line"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&VmmEvent::from(I8042ResetRequest::WriteOutputPort(0x02)))
+                .unwrap(),
+            r#"{"type":"guest_reset_requested","source":"i8042_output_port"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&VmmEvent::from(OperationStatus {
+                operation_id: 1,
+                kind: OperationKind::CreateSnapshot,
+                state: OperationState::Succeeded,
+                error: None,
+            }))
+            .unwrap(),
+            concat!(
+                r#"{"type":"operation_completed","operation_id":1,"#,
+                r#""kind":"create_snapshot","state":"succeeded"}"#
+            )
+        );
+        assert_eq!(
+            serde_json::to_string(&VmmEvent::ProbeStateChanged {
+                probe_id: "ready".to_string(),
+                kind: ProbeKind::Readiness,
+                state: ProbeState::Passing,
+            })
+            .unwrap(),
+            r#"{"type":"probe_state_changed","probe_id":"ready","kind":"readiness","state":"passing"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&VmmEvent::VcpuRetried {
+                vcpu_id: 1,
+                errno: libc::ENOMEM,
+                failures: 3,
+                recovered: true,
+            })
+            .unwrap(),
+            r#"{"type":"vcpu_retried","vcpu_id":1,"errno":12,"failures":3,"recovered":true}"#
+        );
+    }
+}
@@ -0,0 +1,606 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Extracts the kernel log of the guest from the guest memory, to diagnose the guests which
+//! failed to boot without a serial console.
+//!
+//! The log buffer is located from the `VMCOREINFO` note the guest kernel publishes for crash
+//! dumps or, when the guest failed before publishing it, from the addresses of the kernel symbols
+//! given by the user. The records of Linux 3.11 to 5.9 are decoded, the lockless ring buffer of
+//! later kernels is not supported.
+
+use std::fmt;
+
+use vm_memory::{
+    Address, Bytes, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap, GuestMemoryRegion,
+};
+
+use vmm_config::guest_dmesg::KernelLogSymbols;
+
+/// The largest log buffer read from the guest memory.
+pub const MAX_LOG_BUF_LEN: usize = 256 << 20;
+
+// The start of the mapping of the kernel image.
+#[cfg(target_arch = "x86_64")]
+const START_KERNEL_MAP: u64 = 0xffff_ffff_8000_0000;
+// The start of the direct mapping of the physical memory, with 4-level paging.
+#[cfg(target_arch = "x86_64")]
+const PAGE_OFFSET: u64 = 0xffff_8880_0000_0000;
+// The size of the direct mapping of the physical memory.
+#[cfg(target_arch = "x86_64")]
+const DIRECT_MAP_SIZE: u64 = 64 << 40;
+
+const PAGE_SIZE: usize = 4096;
+// `Elf64_Nhdr` followed by the name of the note, padded to 4 bytes.
+const NOTE_HEADER_SIZE: usize = 24;
+const VMCOREINFO_NAME: &[u8] = b"VMCOREINFO\0\0";
+// The largest description of the note, as allocated by the kernel.
+const VMCOREINFO_BYTES: u32 = 4096;
+
+/// Errors associated with extracting the kernel log of the guest.
+#[derive(Debug)]
+pub enum GuestDmesgError {
+    /// The vCPUs are running.
+    NotPaused,
+    /// Extracting the kernel log is not supported on this architecture.
+    NotSupported,
+    /// The address of the given kernel symbol is not hexadecimal.
+    InvalidSymbol(&'static str, String),
+    /// The guest kernel didn't publish its `VMCOREINFO` note.
+    VmcoreinfoNotFound,
+    /// The `VMCOREINFO` note lacks the given entry.
+    MissingEntry(String),
+    /// The guest kernel logs to the lockless ring buffer of Linux 5.10 and later.
+    UnsupportedLogFormat,
+    /// The kernel virtual address is outside the mappings of the guest memory.
+    UnmappedAddress(u64),
+    /// The log buffer is larger than `MAX_LOG_BUF_LEN`.
+    LogBufferTooLarge(usize),
+    /// Reading the guest memory failed.
+    Memory(GuestMemoryError),
+}
+
+impl fmt::Display for GuestDmesgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::GuestDmesgError::*;
+        match self {
+            NotPaused => write!(
+                f,
+                "The kernel log of the guest can only be read while the microVM is paused."
+            ),
+            NotSupported => write!(
+                f,
+                "Reading the kernel log of the guest is not supported on this architecture."
+            ),
+            InvalidSymbol(name, value) => write!(
+                f,
+                "Invalid address {:?} of the kernel symbol {}, expected hexadecimal.",
+                value, name
+            ),
+            VmcoreinfoNotFound => write!(
+                f,
+                "The guest kernel didn't publish its VMCOREINFO note, the addresses of the \
+                 kernel symbols locating the log buffer are needed."
+            ),
+            MissingEntry(key) => write!(f, "The VMCOREINFO note lacks {}.", key),
+            UnsupportedLogFormat => write!(
+                f,
+                "The lockless ring buffer of Linux 5.10 and later is not supported."
+            ),
+            UnmappedAddress(addr) => write!(
+                f,
+                "The kernel address {:#x} is outside the mappings of the guest memory.",
+                addr
+            ),
+            LogBufferTooLarge(len) => write!(
+                f,
+                "The log buffer of {} bytes is larger than {} bytes.",
+                len, MAX_LOG_BUF_LEN
+            ),
+            Memory(err) => write!(f, "Cannot read the guest memory: {:?}", err),
+        }
+    }
+}
+
+impl std::error::Error for GuestDmesgError {}
+
+type Result<T> = std::result::Result<T, GuestDmesgError>;
+
+/// A record of the kernel log.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LogRecord {
+    /// Time since the boot of the guest, in nanoseconds.
+    pub timestamp_ns: u64,
+    /// The message.
+    pub text: String,
+}
+
+// Where the log buffer lies, and how `struct printk_log` is laid out.
+#[derive(Debug)]
+struct LogLayout {
+    // Kernel virtual addresses of the symbols.
+    log_buf: u64,
+    log_buf_len: u64,
+    log_first_idx: u64,
+    log_next_idx: u64,
+    // How far past its link address the kernel image was loaded.
+    #[cfg_attr(target_arch = "aarch64", allow(dead_code))]
+    phys_base: u64,
+    record_size: usize,
+    ts_nsec_offset: usize,
+    len_offset: usize,
+    text_len_offset: usize,
+}
+
+impl LogLayout {
+    fn from_symbols(symbols: &KernelLogSymbols) -> Result<Self> {
+        Ok(LogLayout {
+            log_buf: parse_address("log_buf", &symbols.log_buf)?,
+            log_buf_len: parse_address("log_buf_len", &symbols.log_buf_len)?,
+            log_first_idx: parse_address("log_first_idx", &symbols.log_first_idx)?,
+            log_next_idx: parse_address("log_next_idx", &symbols.log_next_idx)?,
+            phys_base: 0,
+            record_size: 16,
+            ts_nsec_offset: 0,
+            len_offset: 8,
+            text_len_offset: 10,
+        })
+    }
+
+    fn from_vmcoreinfo(vmcoreinfo: &str) -> Result<Self> {
+        // Linux 5.10 replaced the log buffer with a lockless ring buffer.
+        if vmcoreinfo_entry(vmcoreinfo, "SYMBOL(prb)").is_some() {
+            return Err(GuestDmesgError::UnsupportedLogFormat);
+        }
+        let entry = |key: &str| {
+            vmcoreinfo_entry(vmcoreinfo, key)
+                .ok_or_else(|| GuestDmesgError::MissingEntry(key.to_string()))
+        };
+        let symbol = |name: &'static str| {
+            entry(&format!("SYMBOL({})", name)).and_then(|value| parse_address(name, value))
+        };
+        let number = |key: &str| {
+            entry(key).and_then(|value| {
+                value
+                    .parse()
+                    .map_err(|_| GuestDmesgError::MissingEntry(key.to_string()))
+            })
+        };
+        Ok(LogLayout {
+            log_buf: symbol("log_buf")?,
+            log_buf_len: symbol("log_buf_len")?,
+            log_first_idx: symbol("log_first_idx")?,
+            log_next_idx: symbol("log_next_idx")?,
+            // Only published on x86_64, the signed value is kept as is.
+            phys_base: vmcoreinfo_entry(vmcoreinfo, "NUMBER(phys_base)")
+                .and_then(|value| value.parse::<i64>().ok())
+                .map_or(0, |phys_base| phys_base as u64),
+            record_size: number("SIZE(printk_log)")?,
+            ts_nsec_offset: number("OFFSET(printk_log.ts_nsec)")?,
+            len_offset: number("OFFSET(printk_log.len)")?,
+            text_len_offset: number("OFFSET(printk_log.text_len)")?,
+        })
+    }
+
+    // Translates a kernel virtual address to a guest physical address.
+    #[cfg(target_arch = "x86_64")]
+    fn virt_to_phys(&self, addr: u64) -> Result<GuestAddress> {
+        if addr >= START_KERNEL_MAP {
+            Ok(GuestAddress(
+                (addr - START_KERNEL_MAP).wrapping_add(self.phys_base),
+            ))
+        } else if addr >= PAGE_OFFSET && addr - PAGE_OFFSET < DIRECT_MAP_SIZE {
+            Ok(GuestAddress(addr - PAGE_OFFSET))
+        } else {
+            Err(GuestDmesgError::UnmappedAddress(addr))
+        }
+    }
+
+    // The layout of the kernel mappings is not fixed on aarch64.
+    #[cfg(target_arch = "aarch64")]
+    fn virt_to_phys(&self, _addr: u64) -> Result<GuestAddress> {
+        Err(GuestDmesgError::NotSupported)
+    }
+}
+
+fn parse_address(name: &'static str, value: &str) -> Result<u64> {
+    u64::from_str_radix(value.trim_start_matches("0x"), 16)
+        .map_err(|_| GuestDmesgError::InvalidSymbol(name, value.to_string()))
+}
+
+// Looks `key` up in the `KEY=value` lines of the `VMCOREINFO` note.
+fn vmcoreinfo_entry<'a>(vmcoreinfo: &'a str, key: &str) -> Option<&'a str> {
+    vmcoreinfo.lines().find_map(|line| {
+        let mut entry = line.splitn(2, '=');
+        match (entry.next(), entry.next()) {
+            (Some(k), Some(value)) if k == key => Some(value),
+            _ => None,
+        }
+    })
+}
+
+// Returns the `len` bytes of `buf` at `offset`, which the guest controls, so the bounds are
+// checked without overflowing.
+fn slice(buf: &[u8], offset: usize, len: usize) -> Option<&[u8]> {
+    buf.get(offset..offset.checked_add(len)?)
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> Option<u16> {
+    slice(buf, offset, 2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    slice(buf, offset, 4).map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> Option<u64> {
+    slice(buf, offset, 8).map(|bytes| {
+        let mut value = [0; 8];
+        value.copy_from_slice(bytes);
+        u64::from_le_bytes(value)
+    })
+}
+
+// Scans the guest memory for the `VMCOREINFO` note, which the kernel allocates at the start of a
+// page.
+fn find_vmcoreinfo(guest_memory: &GuestMemoryMmap) -> Result<String> {
+    let regions = guest_memory.map_and_fold(
+        Vec::new(),
+        |(_, region)| vec![(region.start_addr(), region.len())],
+        |mut regions, mut region| {
+            regions.append(&mut region);
+            regions
+        },
+    );
+    let mut header = [0u8; NOTE_HEADER_SIZE];
+    for (start, len) in regions {
+        for offset in (0..len).step_by(PAGE_SIZE) {
+            let addr = start.unchecked_add(offset);
+            guest_memory
+                .read_slice(&mut header, addr)
+                .map_err(GuestDmesgError::Memory)?;
+            let descsz = match (
+                read_u32(&header, 0),
+                read_u32(&header, 4),
+                read_u32(&header, 8),
+            ) {
+                (Some(11), Some(descsz), Some(0)) if descsz <= VMCOREINFO_BYTES => descsz,
+                _ => continue,
+            };
+            if &header[12..] != VMCOREINFO_NAME {
+                continue;
+            }
+            let mut desc = vec![0; descsz as usize];
+            guest_memory
+                .read_slice(&mut desc, addr.unchecked_add(NOTE_HEADER_SIZE as u64))
+                .map_err(GuestDmesgError::Memory)?;
+            // The note always starts with the release of the kernel.
+            if desc.starts_with(b"OSRELEASE=") {
+                return Ok(String::from_utf8_lossy(&desc).into_owned());
+            }
+        }
+    }
+    Err(GuestDmesgError::VmcoreinfoNotFound)
+}
+
+// Decodes the records of the log buffer `buf`, from the oldest one at `first_idx` up to
+// `next_idx`. A corrupt record ends the log.
+fn decode(layout: &LogLayout, buf: &[u8], first_idx: usize, next_idx: usize) -> Vec<LogRecord> {
+    let mut records = Vec::new();
+    let mut idx = first_idx;
+    let mut wrapped = false;
+    // Every record is at least as large as its header, which bounds the walk.
+    for _ in 0..=buf.len() / std::cmp::max(layout.record_size, 1) {
+        if idx == next_idx {
+            return records;
+        }
+        // Past the end of the buffer, the log can't overtake its oldest record.
+        if wrapped && idx >= first_idx {
+            break;
+        }
+        let len = match idx
+            .checked_add(layout.len_offset)
+            .and_then(|offset| read_u16(buf, offset))
+        {
+            Some(len) => len as usize,
+            None => break,
+        };
+        // A record of length 0 marks the end of the buffer, the log goes on from its start.
+        if len == 0 {
+            if wrapped {
+                break;
+            }
+            wrapped = true;
+            idx = 0;
+            continue;
+        }
+        let (timestamp_ns, text_len) = match (
+            idx.checked_add(layout.ts_nsec_offset)
+                .and_then(|offset| read_u64(buf, offset)),
+            idx.checked_add(layout.text_len_offset)
+                .and_then(|offset| read_u16(buf, offset)),
+        ) {
+            (Some(timestamp_ns), Some(text_len)) => (timestamp_ns, text_len as usize),
+            _ => break,
+        };
+        let text = match idx
+            .checked_add(layout.record_size)
+            .and_then(|text_start| slice(buf, text_start, text_len))
+        {
+            Some(text)
+                if layout
+                    .record_size
+                    .checked_add(text_len)
+                    .map_or(false, |size| size <= len) =>
+            {
+                text
+            }
+            _ => break,
+        };
+        records.push(LogRecord {
+            timestamp_ns,
+            text: String::from_utf8_lossy(text).into_owned(),
+        });
+        idx += len;
+    }
+    warn!(
+        "The kernel log of the guest is corrupt past {} records.",
+        records.len()
+    );
+    records
+}
+
+/// Extracts the kernel log of the guest from `guest_memory`, locating the log buffer with
+/// `symbols` if given, or else from the `VMCOREINFO` note of the guest kernel.
+pub fn read(
+    guest_memory: &GuestMemoryMmap,
+    symbols: Option<&KernelLogSymbols>,
+) -> Result<Vec<LogRecord>> {
+    let layout = match symbols {
+        Some(symbols) => LogLayout::from_symbols(symbols)?,
+        None => LogLayout::from_vmcoreinfo(&find_vmcoreinfo(guest_memory)?)?,
+    };
+    let read_var = |addr| {
+        guest_memory
+            .read_obj::<u32>(layout.virt_to_phys(addr)?)
+            .map_err(GuestDmesgError::Memory)
+    };
+    let log_buf = guest_memory
+        .read_obj::<u64>(layout.virt_to_phys(layout.log_buf)?)
+        .map_err(GuestDmesgError::Memory)?;
+    let log_buf_len = read_var(layout.log_buf_len)? as usize;
+    let first_idx = read_var(layout.log_first_idx)? as usize;
+    let next_idx = read_var(layout.log_next_idx)? as usize;
+    if log_buf_len > MAX_LOG_BUF_LEN {
+        return Err(GuestDmesgError::LogBufferTooLarge(log_buf_len));
+    }
+
+    let mut buf = vec![0; log_buf_len];
+    guest_memory
+        .read_slice(&mut buf, layout.virt_to_phys(log_buf)?)
+        .map_err(GuestDmesgError::Memory)?;
+    Ok(decode(&layout, &buf, first_idx, next_idx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Appends a record to `buf` at `idx`, returning the index of the next one.
+    fn put_record(buf: &mut [u8], idx: usize, timestamp_ns: u64, text: &str) -> usize {
+        // Records are aligned to 8 bytes.
+        let len = (16 + text.len() + 7) / 8 * 8;
+        buf[idx..idx + 8].copy_from_slice(&timestamp_ns.to_le_bytes());
+        buf[idx + 8..idx + 10].copy_from_slice(&(len as u16).to_le_bytes());
+        buf[idx + 10..idx + 12].copy_from_slice(&(text.len() as u16).to_le_bytes());
+        buf[idx + 16..idx + 16 + text.len()].copy_from_slice(text.as_bytes());
+        idx + len
+    }
+
+    fn record(timestamp_ns: u64, text: &str) -> LogRecord {
+        LogRecord {
+            timestamp_ns,
+            text: text.to_string(),
+        }
+    }
+
+    fn symbols(log_buf: u64) -> KernelLogSymbols {
+        KernelLogSymbols {
+            log_buf: format!("{:x}", log_buf),
+            log_buf_len: format!("{:x}", log_buf + 8),
+            log_first_idx: format!("{:x}", log_buf + 12),
+            log_next_idx: format!("0x{:x}", log_buf + 16),
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        let layout = LogLayout::from_symbols(&symbols(0)).unwrap();
+        let mut buf = vec![0u8; 128];
+
+        let idx = put_record(&mut buf, 0, 1_000, "Linux version");
+        let end = put_record(&mut buf, idx, 2_000, "Command line: console=ttyS0");
+        assert_eq!(
+            decode(&layout, &buf, 0, end),
+            vec![
+                record(1_000, "Linux version"),
+                record(2_000, "Command line: console=ttyS0")
+            ]
+        );
+        assert!(decode(&layout, &buf, idx, idx).is_empty());
+
+        // The log wraps around, past the record of length 0 marking the end of the buffer.
+        let mut buf = vec![0u8; 96];
+        let idx = put_record(&mut buf, 48, 3_000, "wrapped");
+        assert_eq!(idx, 72);
+        let next_idx = put_record(&mut buf, 0, 4_000, "at the start");
+        assert_eq!(
+            decode(&layout, &buf, 48, next_idx),
+            vec![record(3_000, "wrapped"), record(4_000, "at the start")]
+        );
+
+        // A corrupt record ends the log, and so does a walk which never meets `next_idx`.
+        buf[48 + 10] = 0xff;
+        assert!(decode(&layout, &buf, 48, next_idx).is_empty());
+        assert_eq!(
+            decode(&layout, &buf, 0, 7),
+            vec![record(4_000, "at the start")]
+        );
+        assert!(decode(&layout, &[0u8; 64], 0, 8).is_empty());
+
+        // Offsets taken from a corrupt `VMCOREINFO` note end the log instead of overflowing.
+        let mut layout = LogLayout::from_symbols(&symbols(0)).unwrap();
+        layout.len_offset = usize::max_value();
+        assert!(decode(&layout, &buf, 0, next_idx).is_empty());
+        let mut layout = LogLayout::from_symbols(&symbols(0)).unwrap();
+        layout.record_size = usize::max_value();
+        assert!(decode(&layout, &buf, 0, next_idx).is_empty());
+        assert!(decode(&layout, &buf, usize::max_value(), next_idx).is_empty());
+        assert_eq!(read_u16(&buf, usize::max_value()), None);
+    }
+
+    #[test]
+    fn test_vmcoreinfo() {
+        let vmcoreinfo = "OSRELEASE=5.4.0\n\
+                          PAGESIZE=4096\n\
+                          SYMBOL(log_buf)=ffffffff82a3c5e8\n\
+                          SYMBOL(log_buf_len)=ffffffff82a3c5e4\n\
+                          SYMBOL(log_first_idx)=ffffffff8325a8d8\n\
+                          SYMBOL(log_next_idx)=ffffffff8325a8e8\n\
+                          SIZE(printk_log)=16\n\
+                          OFFSET(printk_log.ts_nsec)=0\n\
+                          OFFSET(printk_log.len)=8\n\
+                          OFFSET(printk_log.text_len)=10\n\
+                          NUMBER(phys_base)=16777216\n";
+        let layout = LogLayout::from_vmcoreinfo(vmcoreinfo).unwrap();
+        assert_eq!(layout.log_buf, 0xffff_ffff_82a3_c5e8);
+        assert_eq!(layout.log_next_idx, 0xffff_ffff_8325_a8e8);
+        assert_eq!(layout.phys_base, 16 << 20);
+        assert_eq!(
+            layout.record_size,
+            LogLayout::from_symbols(&symbols(0)).unwrap().record_size
+        );
+
+        match LogLayout::from_vmcoreinfo(&vmcoreinfo.replace("SIZE(printk_log)=16\n", "")) {
+            Err(GuestDmesgError::MissingEntry(key)) => assert_eq!(key, "SIZE(printk_log)"),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        match LogLayout::from_vmcoreinfo("OSRELEASE=5.10.0\nSYMBOL(prb)=ffffffff82a3c5e8\n") {
+            Err(GuestDmesgError::UnsupportedLogFormat) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let mut invalid = symbols(0);
+        invalid.log_buf = "log_buf".to_string();
+        match LogLayout::from_symbols(&invalid) {
+            Err(GuestDmesgError::InvalidSymbol("log_buf", _)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_read() {
+        let guest_memory = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10_0000)]).unwrap();
+        let mut buf = vec![0u8; 0x100];
+        let idx = put_record(&mut buf, 0, 1_000, "Kernel panic - not syncing");
+        let next_idx = put_record(&mut buf, idx, 2_000, "VFS: Unable to mount root fs");
+
+        // The variables lie in the kernel image, the buffer in the direct mapping.
+        let vars = GuestAddress(0x2000);
+        let log_buf = GuestAddress(0x8000);
+        guest_memory
+            .write_obj(PAGE_OFFSET + log_buf.raw_value(), vars)
+            .unwrap();
+        guest_memory
+            .write_obj(buf.len() as u32, vars.unchecked_add(8))
+            .unwrap();
+        guest_memory
+            .write_obj(0u32, vars.unchecked_add(12))
+            .unwrap();
+        guest_memory
+            .write_obj(next_idx as u32, vars.unchecked_add(16))
+            .unwrap();
+        guest_memory.write_slice(&buf, log_buf).unwrap();
+
+        let symbols = symbols(START_KERNEL_MAP + vars.raw_value());
+        let expected = vec![
+            record(1_000, "Kernel panic - not syncing"),
+            record(2_000, "VFS: Unable to mount root fs"),
+        ];
+        assert_eq!(read(&guest_memory, Some(&symbols)).unwrap(), expected);
+
+        // Without symbols, the log buffer is located from the note.
+        match read(&guest_memory, None) {
+            Err(GuestDmesgError::VmcoreinfoNotFound) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        let vmcoreinfo = format!(
+            "OSRELEASE=5.4.0\n\
+             SYMBOL(log_buf)={}\n\
+             SYMBOL(log_buf_len)={}\n\
+             SYMBOL(log_first_idx)={}\n\
+             SYMBOL(log_next_idx)={}\n\
+             SIZE(printk_log)=16\n\
+             OFFSET(printk_log.ts_nsec)=0\n\
+             OFFSET(printk_log.len)=8\n\
+             OFFSET(printk_log.text_len)=10\n",
+            symbols.log_buf,
+            symbols.log_buf_len,
+            symbols.log_first_idx,
+            symbols.log_next_idx.trim_start_matches("0x")
+        );
+        let note = GuestAddress(0x5000);
+        guest_memory.write_obj(11u32, note).unwrap();
+        guest_memory
+            .write_obj(vmcoreinfo.len() as u32, note.unchecked_add(4))
+            .unwrap();
+        guest_memory
+            .write_slice(VMCOREINFO_NAME, note.unchecked_add(12))
+            .unwrap();
+        guest_memory
+            .write_slice(vmcoreinfo.as_bytes(), note.unchecked_add(24))
+            .unwrap();
+        assert_eq!(read(&guest_memory, None).unwrap(), expected);
+
+        // The buffer lies outside the guest memory.
+        guest_memory
+            .write_obj(PAGE_OFFSET + (1 << 30), vars)
+            .unwrap();
+        match read(&guest_memory, Some(&symbols)) {
+            Err(GuestDmesgError::Memory(_)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        guest_memory.write_obj(0x1000u64, vars).unwrap();
+        match read(&guest_memory, Some(&symbols)) {
+            Err(GuestDmesgError::UnmappedAddress(0x1000)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        guest_memory
+            .write_obj(MAX_LOG_BUF_LEN as u32 + 1, vars.unchecked_add(8))
+            .unwrap();
+        match read(&guest_memory, Some(&symbols)) {
+            Err(GuestDmesgError::LogBufferTooLarge(_)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::GuestDmesgError::*;
+
+        for err in &[
+            NotPaused,
+            NotSupported,
+            InvalidSymbol("log_buf", "xyz".to_string()),
+            VmcoreinfoNotFound,
+            MissingEntry("SIZE(printk_log)".to_string()),
+            UnsupportedLogFormat,
+            UnmappedAddress(0x1000),
+            LogBufferTooLarge(MAX_LOG_BUF_LEN + 1),
+            Memory(GuestMemoryError::InvalidBackendAddress),
+        ] {
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+}
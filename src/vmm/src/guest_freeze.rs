@@ -0,0 +1,189 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Asks a guest agent to freeze the filesystems of the guest, so that the backing file of a
+//! drive can be replaced with a filesystem-consistent image.
+//!
+//! The agent listens on a guest vsock port, reached through the Unix socket of a vsock device.
+//! The VMM sends `FREEZE\n`, and waits for the agent to answer `FROZEN\n` once the filesystems
+//! are frozen. It then sends `THAW\n`, without waiting for an answer. The vsock device is
+//! emulated on the VMM thread, so the freeze is waited for on the operations worker thread.
+
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+// The longest line read from the vsock device or the agent.
+const MAX_LINE_LEN: usize = 64;
+
+/// The filesystems of the guest, frozen by the guest agent until they are thawed.
+#[derive(Debug)]
+pub struct GuestFreeze {
+    stream: UnixStream,
+}
+
+impl GuestFreeze {
+    /// Connects to the agent listening on the guest vsock `port`, through the Unix socket
+    /// `uds_path` of a vsock device, and waits up to `timeout` for the agent to freeze the
+    /// filesystems of the guest.
+    pub fn freeze(uds_path: &str, port: u32, timeout: Duration) -> io::Result<Self> {
+        let deadline = Instant::now() + timeout;
+        let mut stream = UnixStream::connect(uds_path)?;
+        stream.set_write_timeout(Some(timeout))?;
+        stream.write_all(format!("CONNECT {}\n", port).as_bytes())?;
+        // The vsock device acknowledges the connections accepted by the guest with
+        // `OK <host port>`, and closes the others.
+        if !read_line(&stream, deadline)?.starts_with("OK ") {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("No guest agent listens on the vsock port {}.", port),
+            ));
+        }
+
+        stream.write_all(b"FREEZE\n")?;
+        let answer = read_line(&stream, deadline);
+        let freeze = GuestFreeze { stream };
+        match answer {
+            Ok(ref line) if line == "FROZEN" => Ok(freeze),
+            // The agent may still freeze the filesystems, so they are thawed right away.
+            Ok(line) => {
+                let _ = freeze.thaw();
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("The guest agent answered {:?} instead of FROZEN.", line),
+                ))
+            }
+            Err(err) => {
+                let _ = freeze.thaw();
+                Err(err)
+            }
+        }
+    }
+
+    /// Tells the agent to thaw the filesystems of the guest. This doesn't wait for the agent,
+    /// so it can be called on the VMM thread.
+    pub fn thaw(self) -> io::Result<()> {
+        let mut stream = self.stream;
+        stream.set_nonblocking(true)?;
+        stream.write_all(b"THAW\n")
+    }
+}
+
+// Reads a line from `stream`, without its end, failing once `deadline` is past.
+fn read_line(mut stream: &UnixStream, deadline: Instant) -> io::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "The guest agent didn't answer in time.",
+            ));
+        }
+        stream.set_read_timeout(Some(deadline - now))?;
+        match stream.read(&mut byte) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "The guest agent closed the connection.",
+                ))
+            }
+            Ok(_) if byte[0] == b'\n' => break,
+            Ok(_) if line.len() < MAX_LINE_LEN => line.push(byte[0]),
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "The guest agent answered too long a line.",
+                ))
+            }
+            Err(ref err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    use utils::tempfile::TempFile;
+
+    // Serves the connection of the VMM as the vsock device and the agent listening on the port
+    // 52 would, answering `answer` to `FREEZE`, and returns the lines the VMM sent.
+    fn serve(listener: UnixListener, answer: &'static str) -> thread::JoinHandle<Vec<String>> {
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut lines = Vec::new();
+            for line in BufReader::new(stream).lines() {
+                let line = line.unwrap();
+                // The vsock device closes the connections the guest doesn't accept.
+                let refused = line.starts_with("CONNECT ") && line != "CONNECT 52";
+                match line.as_str() {
+                    "CONNECT 52" => writer.write_all(b"OK 1073741824\n").unwrap(),
+                    "FREEZE" => writer.write_all(answer.as_bytes()).unwrap(),
+                    _ => (),
+                }
+                lines.push(line);
+                if refused {
+                    break;
+                }
+            }
+            lines
+        })
+    }
+
+    fn listen() -> (TempFile, String, UnixListener) {
+        let uds_file = TempFile::new().unwrap();
+        let uds_path = uds_file.as_path().to_str().unwrap().to_string();
+        std::fs::remove_file(&uds_path).unwrap();
+        let listener = UnixListener::bind(&uds_path).unwrap();
+        (uds_file, uds_path, listener)
+    }
+
+    #[test]
+    fn test_freeze() {
+        let (_uds_file, uds_path, listener) = listen();
+        let agent = serve(listener, "FROZEN\n");
+        let freeze = GuestFreeze::freeze(&uds_path, 52, Duration::from_secs(5)).unwrap();
+        freeze.thaw().unwrap();
+        assert_eq!(agent.join().unwrap(), vec!["CONNECT 52", "FREEZE", "THAW"]);
+    }
+
+    #[test]
+    fn test_freeze_failures() {
+        // No vsock device listens on the socket.
+        let (_uds_file, uds_path, listener) = listen();
+        drop(listener);
+        assert!(GuestFreeze::freeze(&uds_path, 52, Duration::from_secs(5)).is_err());
+
+        // The agent refuses to freeze the filesystems, which are thawed anyway.
+        let (_uds_file, uds_path, listener) = listen();
+        let agent = serve(listener, "BUSY\n");
+        let err = GuestFreeze::freeze(&uds_path, 52, Duration::from_secs(5)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(agent.join().unwrap(), vec!["CONNECT 52", "FREEZE", "THAW"]);
+
+        // The agent doesn't answer in time.
+        let (_uds_file, uds_path, listener) = listen();
+        let agent = serve(listener, "");
+        let err = GuestFreeze::freeze(&uds_path, 52, Duration::from_millis(100)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(agent.join().unwrap(), vec!["CONNECT 52", "FREEZE", "THAW"]);
+
+        // No agent listens on the guest port.
+        let (_uds_file, uds_path, listener) = listen();
+        let agent = serve(listener, "");
+        let err = GuestFreeze::freeze(&uds_path, 53, Duration::from_secs(5)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(agent.join().unwrap(), vec!["CONNECT 53"]);
+    }
+}
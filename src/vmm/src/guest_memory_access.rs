@@ -0,0 +1,233 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reads and writes ranges of the guest physical memory on behalf of the embedder, e.g. to inject
+//! a configuration blob or to read the log buffer of the guest kernel.
+//!
+//! The accesses are bounds checked against the guest memory, and only allowed while the vCPUs
+//! are not running, so that the guest doesn't observe or modify a range halfway through. Every
+//! access is logged along with its outcome, for auditing. The pages written are marked dirty,
+//! so that they make it into the next diff snapshot.
+
+use std::fmt;
+
+use devices::virtio::dirty_pages;
+use logger::{Metric, METRICS};
+use vm_memory::{Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
+
+/// The largest range of guest memory read or written at once.
+pub const MAX_ACCESS_SIZE: usize = 16 << 20;
+
+/// Errors associated with accessing the guest memory.
+#[derive(Debug)]
+pub enum GuestMemoryAccessError {
+    /// The vCPUs are running.
+    NotPaused,
+    /// The range is larger than `MAX_ACCESS_SIZE`.
+    TooLarge(usize),
+    /// The range, given by its address and its length, is not entirely backed by guest memory.
+    OutOfBounds(u64, usize),
+    /// Accessing the guest memory failed.
+    Memory(GuestMemoryError),
+}
+
+impl fmt::Display for GuestMemoryAccessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::GuestMemoryAccessError::*;
+        match self {
+            NotPaused => write!(
+                f,
+                "The guest memory can only be accessed while the microVM is paused."
+            ),
+            TooLarge(len) => write!(
+                f,
+                "Cannot access {} bytes of guest memory at once, the maximum is {}.",
+                len, MAX_ACCESS_SIZE
+            ),
+            OutOfBounds(addr, len) => write!(
+                f,
+                "The range of {} bytes at {:#x} is not backed by guest memory.",
+                len, addr
+            ),
+            Memory(err) => write!(f, "Cannot access the guest memory: {:?}", err),
+        }
+    }
+}
+
+impl std::error::Error for GuestMemoryAccessError {}
+
+type Result<T> = std::result::Result<T, GuestMemoryAccessError>;
+
+// Checks the access before touching the guest memory.
+fn check_access(vcpus_running: bool, addr: u64, len: usize) -> Result<()> {
+    if vcpus_running {
+        return Err(GuestMemoryAccessError::NotPaused);
+    }
+    if len > MAX_ACCESS_SIZE {
+        return Err(GuestMemoryAccessError::TooLarge(len));
+    }
+    if addr.checked_add(len as u64).is_none() {
+        return Err(GuestMemoryAccessError::OutOfBounds(addr, len));
+    }
+    Ok(())
+}
+
+fn map_memory_error(addr: u64, len: usize, err: GuestMemoryError) -> GuestMemoryAccessError {
+    match err {
+        GuestMemoryError::InvalidGuestAddress(_) | GuestMemoryError::PartialBuffer { .. } => {
+            GuestMemoryAccessError::OutOfBounds(addr, len)
+        }
+        err => GuestMemoryAccessError::Memory(err),
+    }
+}
+
+// Records the access in the log and the metrics.
+fn audit<T>(operation: &str, addr: u64, len: usize, result: &Result<T>) {
+    match result {
+        Ok(_) => info!(
+            "Guest memory access: {} of {} bytes at {:#x}.",
+            operation, len, addr
+        ),
+        Err(err) => {
+            METRICS.vmm.guest_memory_access_fails.inc();
+            warn!(
+                "Guest memory access: {} of {} bytes at {:#x} failed: {}",
+                operation, len, addr, err
+            );
+        }
+    }
+}
+
+/// Reads `len` bytes of `guest_memory`, starting at the guest physical address `addr`, unless
+/// `vcpus_running`.
+pub fn read(
+    guest_memory: &GuestMemoryMmap,
+    vcpus_running: bool,
+    addr: u64,
+    len: usize,
+) -> Result<Vec<u8>> {
+    METRICS.vmm.guest_memory_reads.inc();
+    let result = check_access(vcpus_running, addr, len).and_then(|_| {
+        let mut buf = vec![0; len];
+        guest_memory
+            .read_slice(&mut buf, GuestAddress(addr))
+            .map_err(|err| map_memory_error(addr, len, err))?;
+        Ok(buf)
+    });
+    audit("read", addr, len, &result);
+    result
+}
+
+/// Writes `data` to `guest_memory`, starting at the guest physical address `addr`, unless
+/// `vcpus_running`. Nothing is written unless the whole range is backed by guest memory.
+pub fn write(
+    guest_memory: &GuestMemoryMmap,
+    vcpus_running: bool,
+    addr: u64,
+    data: &[u8],
+) -> Result<()> {
+    METRICS.vmm.guest_memory_writes.inc();
+    let len = data.len();
+    let result = check_access(vcpus_running, addr, len)
+        .and_then(|_| {
+            // A partial write can't be rolled back, so check the whole range first.
+            guest_memory
+                .read_slice(&mut vec![0; len], GuestAddress(addr))
+                .map_err(|err| map_memory_error(addr, len, err))
+        })
+        .and_then(|_| {
+            guest_memory
+                .write_slice(data, GuestAddress(addr))
+                .map_err(|err| map_memory_error(addr, len, err))
+        })
+        .map(|_| dirty_pages::mark(guest_memory, GuestAddress(addr), len as u64));
+    audit("write", addr, len, &result);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guest_memory() -> GuestMemoryMmap {
+        // Two regions with a hole in between.
+        GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x2000), (GuestAddress(0x4000), 0x1000)])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_read_write() {
+        let mem = guest_memory();
+
+        write(&mem, false, 0x1ff0, b"firecracker").unwrap();
+        assert_eq!(
+            read(&mem, false, 0x1ff0, 11).unwrap(),
+            b"firecracker".to_vec()
+        );
+        assert_eq!(read(&mem, false, 0x4000, 0x1000).unwrap(), vec![0; 0x1000]);
+        assert!(read(&mem, false, 0, 0).unwrap().is_empty());
+
+        match write(&mem, true, 0, &[1]) {
+            Err(GuestMemoryAccessError::NotPaused) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert_eq!(read(&mem, false, 0, 1).unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn test_write_marks_dirty_pages() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0x2_0000_0000), 0x4000)]).unwrap();
+        dirty_pages::enable(&mem);
+
+        write(&mem, false, 0x2_0000_1ffc, &[1; 8]).unwrap();
+        let pages = dirty_pages::take(&mem);
+        let page = 0x2_0000_0000 / dirty_pages::PAGE_SIZE;
+        assert!(!pages.contains(&page));
+        assert!(pages.contains(&(page + 1)));
+        assert!(pages.contains(&(page + 2)));
+        assert!(!pages.contains(&(page + 3)));
+    }
+
+    #[test]
+    fn test_bounds() {
+        let mem = guest_memory();
+
+        // The range runs into the hole, so nothing is written.
+        match write(&mem, false, 0x1ffc, &[1; 8]) {
+            Err(GuestMemoryAccessError::OutOfBounds(0x1ffc, 8)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert_eq!(read(&mem, false, 0x1ffc, 4).unwrap(), vec![0; 4]);
+
+        match read(&mem, false, 0x3000, 1) {
+            Err(GuestMemoryAccessError::OutOfBounds(0x3000, 1)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        match read(&mem, false, 0x4800, 0x1000) {
+            Err(GuestMemoryAccessError::OutOfBounds(0x4800, 0x1000)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        match read(&mem, false, u64::max_value(), 2) {
+            Err(GuestMemoryAccessError::OutOfBounds(_, 2)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        match read(&mem, false, 0, MAX_ACCESS_SIZE + 1) {
+            Err(GuestMemoryAccessError::TooLarge(len)) => assert_eq!(len, MAX_ACCESS_SIZE + 1),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::GuestMemoryAccessError::*;
+
+        for err in &[
+            NotPaused,
+            TooLarge(MAX_ACCESS_SIZE + 1),
+            OutOfBounds(0x1000, 8),
+            Memory(GuestMemoryError::InvalidBackendAddress),
+        ] {
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Probes the host for what the microVMs need from it, so that schedulers can match the
+//! requirements of a microVM to the hosts able to run it before placing it there.
+//!
+//! The probes only open the device nodes and read `/proc/meminfo`: they neither create a VM nor
+//! a network interface, so they are cheap enough to run on every host, at any time.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader};
+
+use kvm_ioctls::Kvm;
+use vstate::{missing_kvm_caps, REQUIRED_KVM_CAPS};
+
+/// What KVM offers on the host.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct KvmCapabilities {
+    /// Whether `/dev/kvm` can be opened.
+    pub available: bool,
+    /// The version of the KVM API.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_version: Option<i32>,
+    /// The KVM extensions which microVMs can't do without, yet are missing, named as in the
+    /// KVM API.
+    pub missing_extensions: Vec<String>,
+    /// The maximum number of vCPUs of a VM.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_vcpus: Option<usize>,
+    /// The maximum number of memory slots of a VM.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_memslots: Option<usize>,
+}
+
+/// The huge pages of the default size reserved on the host.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct HugepageCapabilities {
+    /// The default size of the huge pages, in KiB.
+    pub page_size_kib: u64,
+    /// The number of huge pages reserved.
+    pub total: u64,
+    /// The number of huge pages reserved and not in use.
+    pub free: u64,
+}
+
+/// What the host offers to the microVMs.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct HostCapabilities {
+    /// What KVM offers.
+    pub kvm: KvmCapabilities,
+    /// Whether `/dev/net/tun` can be opened, to back the network interfaces with taps.
+    pub tap: bool,
+    /// Whether `/dev/vhost-vsock` can be opened, to offload the vsock devices to the host
+    /// kernel. The vsock devices backed by Unix sockets need nothing from the host.
+    pub vhost_vsock: bool,
+    /// The huge pages, unless the host kernel doesn't support them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hugepages: Option<HugepageCapabilities>,
+}
+
+/// Probes what the host offers to the microVMs.
+pub fn probe() -> HostCapabilities {
+    HostCapabilities {
+        kvm: probe_kvm(),
+        tap: can_open("/dev/net/tun"),
+        vhost_vsock: can_open("/dev/vhost-vsock"),
+        hugepages: File::open("/proc/meminfo")
+            .and_then(|meminfo| hugepages(BufReader::new(meminfo)))
+            .unwrap_or(None),
+    }
+}
+
+fn probe_kvm() -> KvmCapabilities {
+    let kvm = match Kvm::new() {
+        Ok(kvm) => kvm,
+        Err(_) => return KvmCapabilities::default(),
+    };
+    KvmCapabilities {
+        available: true,
+        api_version: Some(kvm.get_api_version()),
+        missing_extensions: missing_kvm_caps(&kvm, &REQUIRED_KVM_CAPS)
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        max_vcpus: Some(kvm.get_max_vcpus()),
+        max_memslots: Some(kvm.get_nr_memslots()),
+    }
+}
+
+fn can_open(path: &str) -> bool {
+    OpenOptions::new().read(true).write(true).open(path).is_ok()
+}
+
+// Reads the huge pages of the default size from `meminfo`, if the kernel supports them.
+fn hugepages<R: BufRead>(meminfo: R) -> io::Result<Option<HugepageCapabilities>> {
+    let mut page_size_kib = None;
+    let mut hugepages = HugepageCapabilities::default();
+    for line in meminfo.lines() {
+        let line = line?;
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("Hugepagesize:") => page_size_kib = Some(parse_count(fields.next())?),
+            Some("HugePages_Total:") => hugepages.total = parse_count(fields.next())?,
+            Some("HugePages_Free:") => hugepages.free = parse_count(fields.next())?,
+            _ => (),
+        }
+    }
+    Ok(page_size_kib.map(|page_size_kib| HugepageCapabilities {
+        page_size_kib,
+        ..hugepages
+    }))
+}
+
+fn parse_count(field: Option<&str>) -> io::Result<u64> {
+    field
+        .and_then(|count| count.parse::<u64>().ok())
+        .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidData))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hugepages() {
+        let meminfo = "\
+MemTotal:       16314528 kB
+HugePages_Total:      64
+HugePages_Free:       60
+HugePages_Rsvd:        0
+Hugepagesize:       2048 kB
+";
+        assert_eq!(
+            hugepages(meminfo.as_bytes()).unwrap(),
+            Some(HugepageCapabilities {
+                page_size_kib: 2048,
+                total: 64,
+                free: 60,
+            })
+        );
+
+        // Kernels without hugetlbfs don't report huge pages.
+        assert_eq!(
+            hugepages("MemTotal:       16314528 kB\n".as_bytes()).unwrap(),
+            None
+        );
+        assert!(hugepages("HugePages_Total: x\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_probe() {
+        let capabilities = probe();
+        // The tests need KVM with all the extensions the microVMs need.
+        assert!(capabilities.kvm.available);
+        assert!(capabilities.kvm.missing_extensions.is_empty());
+        assert!(capabilities.kvm.max_vcpus.unwrap() > 0);
+        assert!(capabilities.kvm.max_memslots.unwrap() > 0);
+        assert_eq!(
+            capabilities.kvm.api_version,
+            Some(kvm_bindings::KVM_API_VERSION as i32)
+        );
+    }
+
+    #[test]
+    fn test_serialize() {
+        let capabilities = HostCapabilities {
+            kvm: KvmCapabilities {
+                available: true,
+                api_version: Some(12),
+                missing_extensions: vec!["KVM_CAP_SET_TSS_ADDR".to_string()],
+                max_vcpus: Some(255),
+                max_memslots: Some(509),
+            },
+            tap: true,
+            vhost_vsock: false,
+            hugepages: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&capabilities).unwrap(),
+            json!({
+                "kvm": {
+                    "available": true,
+                    "api_version": 12,
+                    "missing_extensions": ["KVM_CAP_SET_TSS_ADDR"],
+                    "max_vcpus": 255,
+                    "max_memslots": 509
+                },
+                "tap": true,
+                "vhost_vsock": false
+            })
+        );
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::VecDeque;
+use std::result;
+
+use rpc_interface::{VmmAction, VmmActionError, VmmData};
+
+/// The number of idempotency keys remembered. Retries carrying older keys run their action again.
+pub const IDEMPOTENCY_CACHE_SIZE: usize = 128;
+
+/// Remembers the idempotency keys of the last actions which succeeded, so that a control plane
+/// retrying an action over a flaky channel doesn't apply it twice.
+///
+/// Only successful outcomes are remembered: an action which failed didn't take effect, and runs
+/// again when retried.
+#[derive(Debug, Default)]
+pub struct IdempotencyCache {
+    entries: VecDeque<(String, VmmAction)>,
+}
+
+impl IdempotencyCache {
+    /// Returns the outcome to replay for `action`, keyed by `key`, if it already succeeded.
+    ///
+    /// Fails if the key was used for another action, including the same kind of action with
+    /// other arguments, which the control plane can't mean to retry.
+    pub fn lookup(
+        &self,
+        key: &str,
+        action: &VmmAction,
+    ) -> result::Result<Option<VmmData>, VmmActionError> {
+        match self.entries.iter().find(|(other_key, _)| other_key == key) {
+            Some((_, recorded)) if recorded == action => Ok(Some(VmmData::Empty)),
+            Some(_) => Err(VmmActionError::IdempotencyKeyReused(key.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// Remembers `key` along with the `action` it was attached to, if the action succeeded.
+    pub fn record(
+        &mut self,
+        key: String,
+        action: VmmAction,
+        outcome: &result::Result<VmmData, VmmActionError>,
+    ) {
+        // The actions returning data only read the state of the microVM, so they are safe to
+        // run again.
+        if let Ok(VmmData::Empty) = outcome {
+            if self.entries.len() == IDEMPOTENCY_CACHE_SIZE {
+                self.entries.pop_front();
+            }
+            self.entries.push_back((key, action));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idempotency_cache() {
+        let mut cache = IdempotencyCache::default();
+
+        // Unknown keys run their action.
+        assert!(cache.lookup("a", &VmmAction::Pause).unwrap().is_none());
+
+        // Failures and reads aren't remembered.
+        cache.record(
+            "a".to_string(),
+            VmmAction::Pause,
+            &Err(VmmActionError::OperationNotSupportedPreBoot),
+        );
+        cache.record("a".to_string(), VmmAction::Pause, &Ok(VmmData::NotFound));
+        assert!(cache.lookup("a", &VmmAction::Pause).unwrap().is_none());
+
+        // Successes are replayed, but only for the same action.
+        cache.record("a".to_string(), VmmAction::Pause, &Ok(VmmData::Empty));
+        match cache.lookup("a", &VmmAction::Pause) {
+            Ok(Some(VmmData::Empty)) => (),
+            _ => panic!("Unexpected lookup result"),
+        }
+        match cache.lookup("a", &VmmAction::Resume) {
+            Err(VmmActionError::IdempotencyKeyReused(key)) => assert_eq!(key, "a"),
+            _ => panic!("Unexpected lookup result"),
+        }
+
+        // The same kind of action with other arguments is another action.
+        cache.record(
+            "b".to_string(),
+            VmmAction::RemoveBlockDevice("rootfs".to_string()),
+            &Ok(VmmData::Empty),
+        );
+        assert!(cache
+            .lookup("b", &VmmAction::RemoveBlockDevice("rootfs".to_string()))
+            .unwrap()
+            .is_some());
+        match cache.lookup("b", &VmmAction::RemoveBlockDevice("scratch".to_string())) {
+            Err(VmmActionError::IdempotencyKeyReused(key)) => assert_eq!(key, "b"),
+            _ => panic!("Unexpected lookup result"),
+        }
+
+        // The oldest keys are forgotten.
+        for i in 0..IDEMPOTENCY_CACHE_SIZE {
+            cache.record(i.to_string(), VmmAction::Pause, &Ok(VmmData::Empty));
+        }
+        assert!(cache.lookup("a", &VmmAction::Pause).unwrap().is_none());
+        assert!(cache.lookup("0", &VmmAction::Pause).unwrap().is_some());
+    }
+}
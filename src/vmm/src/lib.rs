@@ -13,11 +13,16 @@
 extern crate kvm_bindings;
 extern crate kvm_ioctls;
 extern crate libc;
+extern crate lz4;
 extern crate polly;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate sha2;
+extern crate timerfd;
+extern crate userfaultfd;
+extern crate zstd;
 
 extern crate arch;
 #[cfg(target_arch = "x86_64")]
@@ -27,6 +32,7 @@ extern crate kernel;
 #[macro_use]
 extern crate logger;
 extern crate dumbo;
+extern crate mmds;
 extern crate rate_limiter;
 extern crate seccomp;
 extern crate snapshot;
@@ -35,72 +41,140 @@ extern crate versionize;
 extern crate versionize_derive;
 extern crate vm_memory;
 
+/// Decides which API actions are carried out.
+pub mod action_policy;
+/// Adjusts the rate limiters of the devices to the pressure on the host.
+pub mod adaptive_rate_limiter;
 /// Handles setup and initialization a `Vmm` object.
 pub mod builder;
+/// Saves the state of the microVM periodically, for the control plane to recover from.
+pub mod checkpoint;
+/// Leases the vsock guest CIDs shared by the microVMs of a host.
+pub mod cid_registry;
+/// Hands the cloud-init NoCloud data to the guest.
+pub mod cloud_init;
+/// Owns the terminal the serial console reads from, and feeds the serial console from a Unix
+/// socket.
+pub mod console;
 /// Syscalls allowed through the seccomp filter.
 pub mod default_syscalls;
+/// Descriptions of the devices attached to the microVM.
+pub mod device_list;
 pub(crate) mod device_manager;
+/// Runs the emulation of devices on dedicated threads pinned to host CPUs.
+pub mod device_thread;
+/// Stable exit codes and API error codes.
+pub mod error_code;
+/// Structured events surfaced to the control plane.
+pub mod events;
+/// Extracts the kernel log of the guest from the guest memory.
+pub mod guest_dmesg;
+/// Asks a guest agent to freeze the filesystems of the guest.
+pub mod guest_freeze;
+/// Reads and writes the guest memory on behalf of the embedder.
+pub mod guest_memory_access;
+/// Probes what the host offers to the microVMs.
+pub mod host_capabilities;
+/// Replays the outcome of the API actions retried with the same idempotency key.
+pub mod idempotency;
+/// Hands over a running microVM to a new VMM process.
+pub mod live_update;
+/// Monitors the guest memory shared between snapshot clones.
+pub mod memory_sharing;
+/// Guest memory layout description and restoration.
+pub mod memory_snapshot;
+/// Migrates a running microVM to another VMM process.
+pub mod migration;
+/// Runs the long actions of the VMM in the background.
+pub mod operations;
+/// Hooks run on a microVM restored from a snapshot.
+pub mod post_restore;
+/// Evaluates the probes of the workload inside the guest.
+pub mod probes;
+/// Accounts for the host resources used by the VMM process.
+pub mod resource_usage;
 /// Resource store for configured microVM resources.
 pub mod resources;
 /// microVM RPC API adapters.
 pub mod rpc_interface;
+/// Launches AMD SEV guests.
+#[cfg(feature = "sev")]
+pub mod sev;
 /// Signal handling utilities.
 pub mod signal_handler;
 // Save/restore utilities.
 pub mod persist;
+/// Restores the guest memory on demand, through userfaultfd.
+pub mod uffd;
 /// Wrappers over structures used to configure the VMM.
 pub mod vmm_config;
 mod vstate;
 
+use std::collections::{BTreeSet, HashMap};
 use std::fmt::{Display, Formatter};
 use std::io;
-use std::os::unix::io::AsRawFd;
-use std::sync::Mutex;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use arch::DeviceType;
 use arch::InitrdConfig;
+use cid_registry::CidLease;
+use cloud_init::SEED_DRIVE_ID;
+use device_list::{
+    DeviceDescription, DeviceStateDump, RateLimiterDescription, VsockConnectionDescription,
+};
 #[cfg(target_arch = "x86_64")]
 use device_manager::legacy::PortIODeviceManager;
 use device_manager::mmio::MMIODeviceManager;
 #[cfg(target_arch = "x86_64")]
+use device_manager::pci::PciDeviceManager;
+use device_thread::{DeviceThread, DeviceThreadError};
+use devices::legacy::Serial;
+use devices::virtio::{dirty_pages, MmioTransport, Vsock, VsockUnixBackend, TYPE_VSOCK};
+#[cfg(target_arch = "x86_64")]
 use devices::virtio::{
-    vsock::persist::VsockState, Block, MmioTransport, Net, Vsock, VsockUnixBackend, TYPE_BLOCK,
-    TYPE_NET, TYPE_VSOCK,
+    vsock::persist::VsockState, Balloon, Block, Entropy, Net, TYPE_BALLOON, TYPE_BLOCK, TYPE_GPU,
+    TYPE_INPUT, TYPE_NET, TYPE_RNG, TYPE_SOUND,
 };
 use devices::BusDevice;
+pub use error_code::FcExitCode;
+use events::{EventChannel, VmmEvent};
+use guest_dmesg::{GuestDmesgError, LogRecord};
+use guest_memory_access::GuestMemoryAccessError;
 use kernel::cmdline::Cmdline as KernelCmdline;
 use logger::{LoggerError, MetricsError, METRICS};
 #[cfg(target_arch = "x86_64")]
+use memory_snapshot::SnapshotMemory;
+use operations::{FollowUp, Job, OperationError, OperationKind, OperationStatus, Operations};
+#[cfg(target_arch = "x86_64")]
 use persist::{
-    ConnectedBlockState, ConnectedNetState, ConnectedVsockState, DeviceStates, VmmResourcesState,
+    ConnectedBalloonState, ConnectedBlockState, ConnectedEntropyState, ConnectedNetState,
+    ConnectedVsockState, DeviceStates, MicrovmState, MicrovmStateError, VmInfo, VmmResourcesState,
 };
 use polly::event_manager::{self, EventManager, Subscriber};
+use probes::ProbeStatuses;
+use resource_usage::VmmResourceUsage;
 use seccomp::{BpfProgram, BpfProgramRef, SeccompFilter};
 #[cfg(target_arch = "x86_64")]
 use snapshot::Persist;
 use utils::epoll::{EpollEvent, EventSet};
 use utils::eventfd::EventFd;
+use utils::fd_uri;
 use utils::time::TimestampUs;
-use vm_memory::GuestMemoryMmap;
+use vm_memory::{GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+use vmm_config::console::ConsoleConfigError;
+use vmm_config::guest_dmesg::KernelLogSymbols;
+use vmm_config::machine_config::VcpuStateError;
+use vmm_config::vsock::VsockConfigError;
+#[cfg(target_arch = "x86_64")]
+use vstate::VcpuState;
 use vstate::{Vcpu, VcpuEvent, VcpuHandle, VcpuResponse, Vm};
 
-/// Success exit code.
-pub const FC_EXIT_CODE_OK: u8 = 0;
-/// Generic error exit code.
-pub const FC_EXIT_CODE_GENERIC_ERROR: u8 = 1;
-/// Generic exit code for an error considered not possible to occur if the program logic is sound.
-pub const FC_EXIT_CODE_UNEXPECTED_ERROR: u8 = 2;
-/// Firecracker was shut down after intercepting a restricted system call.
-pub const FC_EXIT_CODE_BAD_SYSCALL: u8 = 148;
-/// Firecracker was shut down after intercepting `SIGBUS`.
-pub const FC_EXIT_CODE_SIGBUS: u8 = 149;
-/// Firecracker was shut down after intercepting `SIGSEGV`.
-pub const FC_EXIT_CODE_SIGSEGV: u8 = 150;
-/// Bad configuration for microvm's resources, when using a single json.
-pub const FC_EXIT_CODE_BAD_CONFIGURATION: u8 = 152;
-/// Command line arguments parsing error.
-pub const FC_EXIT_CODE_ARG_PARSING: u8 = 153;
+/// Dirty page bitmaps of the guest memory regions, indexed by KVM memory slot. Each bit stands
+/// for a guest page.
+pub type DirtyBitmap = HashMap<usize, Vec<u64>>;
 
 /// Errors associated with the VMM internal logic. These errors cannot be generated by direct user
 /// input, but can result from bad configuration of the host (for example if Firecracker doesn't
@@ -109,10 +183,14 @@ pub const FC_EXIT_CODE_ARG_PARSING: u8 = 153;
 pub enum Error {
     /// This error is thrown by the minimal boot loader implementation.
     ConfigureSystem(arch::Error),
+    /// Cannot retrieve the dirty page bitmap from KVM.
+    DirtyBitmap(kvm_ioctls::Error),
     /// Legacy devices work with Event file descriptors and the creation can fail because
     /// of resource exhaustion.
     #[cfg(target_arch = "x86_64")]
     CreateLegacyDevice(device_manager::legacy::Error),
+    /// Cannot pause or resume the devices emulated on threads of their own.
+    DeviceThread(DeviceThreadError),
     /// Cannot read from an Event file descriptor.
     EventFd(io::Error),
     /// Polly error wrapper.
@@ -133,8 +211,15 @@ pub enum Error {
     Logger(LoggerError),
     /// Internal metrics system error.
     Metrics(MetricsError),
+    /// The microVM was built without the i8042 keyboard controller.
+    #[cfg(target_arch = "x86_64")]
+    NoI8042,
+    /// Cannot run the operations in the background.
+    Operations(OperationError),
     /// Cannot add a device to the MMIO Bus.
     RegisterMMIODevice(device_manager::mmio::Error),
+    /// Cannot sample the host resources used by the VMM process.
+    ResourceUsage(io::Error),
     /// Cannot build seccomp filters.
     SeccompFilters(seccomp::Error),
     /// Write to the serial console failed.
@@ -147,6 +232,8 @@ pub enum Error {
     VcpuEvent(vstate::Error),
     /// Cannot create a vCPU handle.
     VcpuHandle(vstate::Error),
+    /// vCPU pause failed.
+    VcpuPause,
     /// vCPU resume failed.
     VcpuResume,
     /// Cannot spawn a new Vcpu thread.
@@ -167,23 +254,33 @@ impl Display for Error {
             ConfigureSystem(e) => write!(f, "System configuration error: {:?}", e),
             #[cfg(target_arch = "x86_64")]
             CreateLegacyDevice(e) => write!(f, "Error creating legacy device: {:?}", e),
+            DirtyBitmap(e) => write!(f, "Error getting the dirty page bitmap: {}", e),
+            DeviceThread(e) => write!(f, "Device thread error: {}", e),
             EventFd(e) => write!(f, "Event fd error: {}", e),
             EventManager(e) => write!(f, "Event manager error: {:?}", e),
             I8042Error(e) => write!(f, "I8042 error: {}", e),
             KernelFile(e) => write!(f, "Cannot access kernel file: {}", e),
-            KvmContext(e) => write!(f, "Failed to validate KVM support: {:?}", e),
+            KvmContext(e) => write!(f, "Failed to validate KVM support: {}", e),
             #[cfg(target_arch = "x86_64")]
             LegacyIOBus(e) => write!(f, "Cannot add devices to the legacy I/O Bus. {}", e),
             LoadCommandline(e) => write!(f, "Cannot load command line: {}", e),
             Logger(e) => write!(f, "Logger error: {}", e),
             Metrics(e) => write!(f, "Metrics error: {}", e),
+            #[cfg(target_arch = "x86_64")]
+            NoI8042 => write!(
+                f,
+                "The microVM has no i8042 keyboard controller to send CTRL+ALT+DEL to."
+            ),
+            Operations(e) => write!(f, "Operations error: {}", e),
             RegisterMMIODevice(e) => write!(f, "Cannot add a device to the MMIO Bus. {}", e),
+            ResourceUsage(e) => write!(f, "Cannot sample the resource usage: {}", e),
             SeccompFilters(e) => write!(f, "Cannot build seccomp filters: {}", e),
             Serial(e) => write!(f, "Error writing to the serial console: {:?}", e),
             TimerFd(e) => write!(f, "Error creating timer fd: {}", e),
             Vcpu(e) => write!(f, "Vcpu error: {}", e),
             VcpuEvent(e) => write!(f, "Cannot send event to vCPU. {:?}", e),
             VcpuHandle(e) => write!(f, "Cannot create a vCPU handle. {}", e),
+            VcpuPause => write!(f, "vCPUs pause failed."),
             VcpuResume => write!(f, "vCPUs resume failed."),
             VcpuSpawn(e) => write!(f, "Cannot spawn Vcpu thread: {}", e),
             Vm(e) => write!(f, "Vm error: {}", e),
@@ -199,6 +296,8 @@ impl Display for Error {
     }
 }
 
+impl std::error::Error for Error {}
+
 /// Trait for objects that need custom initialization and teardown during the Vmm lifetime.
 pub trait VmmEventsObserver {
     /// This function will be called during microVm boot.
@@ -214,6 +313,26 @@ pub trait VmmEventsObserver {
 /// Shorthand result type for internal VMM commands.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Stops the microVM from outside the vCPUs.
+pub struct StopRequester {
+    exit_evt: EventFd,
+    exit_code: Arc<Mutex<Option<FcExitCode>>>,
+}
+
+impl StopRequester {
+    /// Asks the VMM to stop the microVM, and Firecracker to exit with `exit_code` unless a
+    /// vCPU exited with a code of its own, or a stop was already requested.
+    pub fn request_stop(&self, exit_code: FcExitCode) {
+        self.exit_code
+            .lock()
+            .expect("Poisoned lock")
+            .get_or_insert(exit_code);
+        if let Err(e) = self.exit_evt.write(1) {
+            error!("Failed to request the microVM to stop: {}", e);
+        }
+    }
+}
+
 /// Contains the state and associated methods required for the Firecracker VMM.
 pub struct Vmm {
     events_observer: Option<Box<dyn VmmEventsObserver>>,
@@ -224,13 +343,41 @@ pub struct Vmm {
     kernel_cmdline: KernelCmdline,
 
     vcpus_handles: Vec<VcpuHandle>,
+    // Whether the vCPUs were resumed since they were last paused.
+    vcpus_running: bool,
+    // The vCPUs kept paused while the others run.
+    parked_vcpus: BTreeSet<u8>,
     exit_evt: EventFd,
+    // The exit code of the stop requested from outside the vCPUs, if any.
+    requested_exit_code: Arc<Mutex<Option<FcExitCode>>>,
     vm: Vm,
+    // Events waiting to be drained by the control plane.
+    events: EventChannel,
+    // The states of the probes of the workload inside the guest.
+    probe_statuses: ProbeStatuses,
+    // Tells the probes whether the vCPUs run.
+    probes_running: Arc<AtomicBool>,
+    // The actions running in the background.
+    operations: Operations,
+    // The serial console, when its input comes from the API.
+    console_input: Option<Arc<Mutex<Serial>>>,
 
     // Guest VM devices.
     mmio_device_manager: MMIODeviceManager,
     #[cfg(target_arch = "x86_64")]
     pio_device_manager: PortIODeviceManager,
+    // The host PCI devices passed through to the guest, if any.
+    #[cfg(target_arch = "x86_64")]
+    pci_device_manager: Option<PciDeviceManager>,
+
+    // The launch measurement of a SEV guest.
+    #[cfg(feature = "sev")]
+    launch_measurement: Option<Vec<u8>>,
+    // The guest CIDs leased from pools, held for as long as the VMM runs.
+    #[allow(dead_code)]
+    cid_leases: Vec<Arc<CidLease>>,
+    // The devices emulated on threads of their own.
+    device_threads: Vec<DeviceThread>,
 }
 
 impl Vmm {
@@ -245,6 +392,19 @@ impl Vmm {
 
     /// Starts the microVM vcpus.
     pub fn start_vcpus(
+        &mut self,
+        vcpus: Vec<Vcpu>,
+        vmm_seccomp_filter: BpfProgram,
+        vcpu_seccomp_filter: BpfProgramRef,
+    ) -> Result<()> {
+        self.start_paused_vcpus(vcpus, vmm_seccomp_filter, vcpu_seccomp_filter)?;
+
+        // The vcpus start off in the `Paused` state, let them run.
+        self.resume_vcpus()
+    }
+
+    /// Starts the microVM vcpus, leaving them in the `Paused` state.
+    pub fn start_paused_vcpus(
         &mut self,
         mut vcpus: Vec<Vcpu>,
         vmm_seccomp_filter: BpfProgram,
@@ -262,6 +422,7 @@ impl Vmm {
 
         for mut vcpu in vcpus.drain(..) {
             vcpu.set_mmio_bus(self.mmio_device_manager.bus.clone());
+            vcpu.set_event_sender(self.events.sender());
 
             self.vcpus_handles.push(
                 vcpu.start_threaded(vcpu_seccomp_filter.to_vec())
@@ -269,25 +430,36 @@ impl Vmm {
             );
         }
 
+        // No thread can be spawned once the VMM thread is confined.
+        self.operations
+            .start_worker(vmm_seccomp_filter.clone())
+            .map_err(Error::Operations)?;
+        let exporting_seccomp_filter = vmm_seccomp_filter.clone();
+        METRICS
+            .start_exporting(move || {
+                SeccompFilter::apply(exporting_seccomp_filter).map_err(|e| e.to_string())
+            })
+            .map_err(Error::Metrics)?;
+
         // Load seccomp filters for the VMM thread.
         // Execution panics if filters cannot be loaded, use --seccomp-level=0 if skipping filters
         // altogether is the desired behaviour.
         SeccompFilter::apply(vmm_seccomp_filter).map_err(Error::SeccompFilters)?;
 
-        // The vcpus start off in the `Paused` state, let them run.
-        self.resume_vcpus()?;
-
         Ok(())
     }
 
-    /// Sends a resume command to the vcpus.
+    /// Sends a resume command to the vcpus, apart from the parked ones.
     pub fn resume_vcpus(&mut self) -> Result<()> {
-        for handle in self.vcpus_handles.iter() {
+        // Some vCPUs may run even if the others fail to resume.
+        self.vcpus_running = true;
+        self.probes_running.store(true, Ordering::Release);
+        for handle in self.unparked_vcpus_handles() {
             handle
                 .send_event(VcpuEvent::Resume)
                 .map_err(Error::VcpuEvent)?;
         }
-        for handle in self.vcpus_handles.iter() {
+        for handle in self.unparked_vcpus_handles() {
             match handle
                 .response_receiver()
                 .recv_timeout(Duration::from_millis(1000))
@@ -299,8 +471,90 @@ impl Vmm {
         Ok(())
     }
 
+    /// Sends a pause command to the vcpus. The parked ones are already paused.
+    pub fn pause_vcpus(&mut self) -> Result<()> {
+        for handle in self.unparked_vcpus_handles() {
+            handle
+                .send_event(VcpuEvent::Pause)
+                .map_err(Error::VcpuEvent)?;
+        }
+        for handle in self.unparked_vcpus_handles() {
+            match handle
+                .response_receiver()
+                .recv_timeout(Duration::from_millis(1000))
+            {
+                Ok(VcpuResponse::Paused) => (),
+                _ => return Err(Error::VcpuPause),
+            }
+        }
+        self.vcpus_running = false;
+        self.probes_running.store(false, Ordering::Release);
+        Ok(())
+    }
+
+    /// Parks the vCPU `vcpu_id`, keeping it paused while the others run, or unparks it. The
+    /// unparked vCPU runs again right away, unless the microVM is paused.
+    pub fn set_vcpu_state(
+        &mut self,
+        vcpu_id: u8,
+        paused: bool,
+    ) -> std::result::Result<(), VcpuStateError> {
+        let handle = self
+            .vcpus_handles
+            .get(usize::from(vcpu_id))
+            .ok_or(VcpuStateError::InvalidVcpuId(vcpu_id))?;
+        if paused == self.parked_vcpus.contains(&vcpu_id) {
+            return Ok(());
+        }
+        if paused && self.parked_vcpus.len() + 1 == self.vcpus_handles.len() {
+            return Err(VcpuStateError::LastRunningVcpu(vcpu_id));
+        }
+
+        // The vCPUs of a paused microVM are all paused already, only resuming it tells them
+        // apart.
+        if self.vcpus_running {
+            let event = if paused {
+                VcpuEvent::Pause
+            } else {
+                VcpuEvent::Resume
+            };
+            handle
+                .send_event(event)
+                .map_err(|_| VcpuStateError::NoResponse(vcpu_id))?;
+            match handle
+                .response_receiver()
+                .recv_timeout(Duration::from_millis(1000))
+            {
+                Ok(VcpuResponse::Paused) if paused => (),
+                Ok(VcpuResponse::Resumed) if !paused => (),
+                _ => return Err(VcpuStateError::NoResponse(vcpu_id)),
+            }
+        }
+
+        if paused {
+            self.parked_vcpus.insert(vcpu_id);
+        } else {
+            self.parked_vcpus.remove(&vcpu_id);
+        }
+        Ok(())
+    }
+
+    // The handles of the vCPUs which aren't parked.
+    fn unparked_vcpus_handles<'a>(&'a self) -> impl Iterator<Item = &'a VcpuHandle> + 'a {
+        self.vcpus_handles
+            .iter()
+            .enumerate()
+            .filter(move |(id, _)| !self.parked_vcpus.contains(&(*id as u8)))
+            .map(|(_, handle)| handle)
+    }
+
     /// Configures the system for boot.
     pub fn configure_system(&self, vcpus: &[Vcpu], initrd: &Option<InitrdConfig>) -> Result<()> {
+        #[cfg(target_arch = "x86_64")]
+        let pci_interrupts = match self.pci_device_manager {
+            Some(ref pci_device_manager) => pci_device_manager.interrupts(),
+            None => &[],
+        };
         #[cfg(target_arch = "x86_64")]
         arch::x86_64::configure_system(
             &self.guest_memory,
@@ -308,6 +562,7 @@ impl Vmm {
             self.kernel_cmdline.len() + 1,
             initrd,
             vcpus.len() as u8,
+            pci_interrupts,
         )
         .map_err(Error::ConfigureSystem)?;
 
@@ -335,9 +590,45 @@ impl Vmm {
         &self.guest_memory
     }
 
+    /// Reads `len` bytes of guest memory at the guest physical address `addr`. The vCPUs have to
+    /// be paused.
+    pub fn read_guest_memory(
+        &self,
+        addr: u64,
+        len: usize,
+    ) -> std::result::Result<Vec<u8>, GuestMemoryAccessError> {
+        guest_memory_access::read(&self.guest_memory, self.vcpus_running, addr, len)
+    }
+
+    /// Writes `data` to the guest memory at the guest physical address `addr`. The vCPUs have to
+    /// be paused.
+    pub fn write_guest_memory(
+        &mut self,
+        addr: u64,
+        data: &[u8],
+    ) -> std::result::Result<(), GuestMemoryAccessError> {
+        guest_memory_access::write(&self.guest_memory, self.vcpus_running, addr, data)
+    }
+
+    /// Extracts the kernel log of the guest, locating the log buffer with `symbols` if given, or
+    /// else from the `VMCOREINFO` note of the guest kernel. The vCPUs have to be paused.
+    pub fn guest_dmesg(
+        &self,
+        symbols: Option<&KernelLogSymbols>,
+    ) -> std::result::Result<Vec<LogRecord>, GuestDmesgError> {
+        if self.vcpus_running {
+            return Err(GuestDmesgError::NotPaused);
+        }
+        guest_dmesg::read(&self.guest_memory, symbols)
+    }
+
     /// Injects CTRL+ALT+DEL keystroke combo in the i8042 device.
     #[cfg(target_arch = "x86_64")]
     pub fn send_ctrl_alt_del(&mut self) -> Result<()> {
+        // The guest can't read the keystrokes from an i8042 which isn't on the I/O bus.
+        if !self.pio_device_manager.i8042_registered() {
+            return Err(Error::NoI8042);
+        }
         self.pio_device_manager
             .i8042
             .lock()
@@ -346,8 +637,29 @@ impl Vmm {
             .map_err(Error::I8042Error)
     }
 
+    /// Hands `bytes` to the serial console of the guest, as if typed on its terminal. Only works
+    /// when the input of the serial console comes from the API.
+    pub fn write_to_console(&self, bytes: &[u8]) -> std::result::Result<(), ConsoleConfigError> {
+        self.console_input
+            .as_ref()
+            .ok_or(ConsoleConfigError::InputNotFromApi)?
+            .lock()
+            .expect("Poisoned lock")
+            .raw_input(bytes)
+            .map_err(ConsoleConfigError::WriteInput)
+    }
+
+    /// Returns a handle through which the microVM can be stopped from outside the vCPUs, e.g.
+    /// by a device, with an exit code of its own.
+    pub fn stop_requester(&self) -> io::Result<StopRequester> {
+        Ok(StopRequester {
+            exit_evt: self.exit_evt.try_clone()?,
+            exit_code: self.requested_exit_code.clone(),
+        })
+    }
+
     /// Waits for all vCPUs to exit and terminates the Firecracker process.
-    pub fn stop(&mut self, exit_code: i32) {
+    pub fn stop(&mut self, exit_code: FcExitCode) {
         info!("Vmm is stopping.");
 
         if let Some(observer) = self.events_observer.as_mut() {
@@ -356,15 +668,28 @@ impl Vmm {
             }
         }
 
+        // The events which weren't drained can't be retrieved through the API anymore, so
+        // they end up in the log, e.g. the guest reset request behind this exit.
+        for event in self.events.drain() {
+            info!("Undelivered event: {:?}", event);
+        }
+
+        // The devices emulated on threads of their own are stopped before the process exits.
+        for device_thread in self.device_threads.iter_mut() {
+            device_thread.stop();
+        }
+
         // Write the metrics before exiting.
         if let Err(e) = METRICS.write() {
             error!("Failed to write metrics while stopping: {}", e);
         }
+        // In case the console was acquired outside of the events observer.
+        console::restore_console();
 
         // Exit from Firecracker using the provided exit code. Safe because we're terminating
         // the process anyway.
         unsafe {
-            libc::_exit(exit_code);
+            libc::_exit(i32::from(exit_code));
         }
     }
 
@@ -387,13 +712,428 @@ impl Vmm {
         &self.vm
     }
 
+    /// Returns the events surfaced to the control plane since the previous call.
+    pub fn drain_events(&self) -> Vec<VmmEvent> {
+        self.events.drain()
+    }
+
+    /// Starts an operation of `kind` in the background, running `job` on the worker thread, then
+    /// `follow_up` on the VMM thread once `job` succeeds. Returns the status of the operation.
+    ///
+    /// The operations which need the microVM to stay as it is hold the devices with
+    /// `hold_devices` first.
+    pub fn start_operation(
+        &mut self,
+        kind: OperationKind,
+        job: Job,
+        follow_up: Option<FollowUp>,
+    ) -> std::result::Result<OperationStatus, OperationError> {
+        self.operations.start(kind, job, follow_up)
+    }
+
+    /// Returns the ID of the operation running in the background, if any.
+    pub fn running_operation(&self) -> Option<u64> {
+        self.operations.running_id()
+    }
+
+    /// Returns the status of the operation `id`, unless it is unknown or its status was dropped.
+    pub fn operation_status(&self, id: u64) -> Option<OperationStatus> {
+        self.operations.status(id)
+    }
+
+    /// Stops `event_manager` from dispatching the events of the devices before an operation
+    /// starts running in the background, so that they leave the guest memory alone. The events
+    /// of the VMM itself and of the pollables `keep`, e.g. the API ones, are still dispatched.
+    /// The devices emulated on threads of their own are paused as well. The devices are released
+    /// once the operation completes.
+    pub fn hold_devices(&mut self, event_manager: &mut EventManager, keep: &[RawFd]) -> Result<()> {
+        if event_manager.is_held() {
+            return Ok(());
+        }
+        self.pause_device_threads()?;
+        let mut pollables: Vec<RawFd> = self
+            .interest_list()
+            .iter()
+            .map(|event| event.data() as RawFd)
+            .collect();
+        pollables.extend_from_slice(keep);
+        event_manager.hold(&pollables).map_err(Error::EventManager)
+    }
+
+    /// Pauses the devices emulated on threads of their own, and returns once they stopped
+    /// touching the guest memory and their queues. The devices emulated on the VMM thread need
+    /// no pausing, since they only run when the VMM thread is idle.
+    pub fn pause_device_threads(&mut self) -> Result<()> {
+        for device_thread in self.device_threads.iter_mut() {
+            device_thread.pause().map_err(Error::DeviceThread)?;
+        }
+        Ok(())
+    }
+
+    /// Resumes the devices paused with `pause_device_threads`.
+    pub fn resume_device_threads(&mut self) -> Result<()> {
+        for device_thread in self.device_threads.iter_mut() {
+            device_thread.resume().map_err(Error::DeviceThread)?;
+        }
+        Ok(())
+    }
+
+    // Records the outcome of the operation done in the background, if any, and surfaces it to
+    // the control plane.
+    fn complete_operation(&mut self) {
+        let (outcome, follow_up) = match self.operations.take_outcome() {
+            Some(outcome) => outcome,
+            None => return,
+        };
+        let outcome = match follow_up {
+            Some(follow_up) => outcome.and_then(|_| follow_up(self)),
+            None => outcome,
+        };
+        // The devices paused for the operation can run again, whatever its outcome.
+        let resumed = self.resume_device_threads().map_err(|e| e.to_string());
+        let outcome = outcome.and(resumed);
+        if let Some(status) = self.operations.finish(outcome) {
+            if let Some(ref err) = status.error {
+                error!("Operation {} failed: {}", status.operation_id, err);
+            }
+            self.events.sender().send(status);
+        }
+    }
+
+    /// Returns true if the vCPUs were resumed since they were last paused.
+    pub fn vcpus_running(&self) -> bool {
+        self.vcpus_running
+    }
+
+    /// Returns the states of the probes, kept up to date while the microVM runs.
+    pub fn probe_statuses(&self) -> ProbeStatuses {
+        self.probe_statuses.clone()
+    }
+
+    /// Describes the MMIO devices attached to the microVM.
+    pub fn list_devices(&self) -> Vec<DeviceDescription> {
+        device_list::describe(&self.mmio_device_manager)
+    }
+
+    /// Dumps the state of the virtio device `device_id`, if any.
+    pub fn dump_device_state(&self, device_id: &str) -> Option<DeviceStateDump> {
+        device_list::dump_device_state(&self.mmio_device_manager, device_id)
+    }
+
+    /// Samples the host resources used by the VMM process.
+    pub fn resource_usage(&self) -> Result<VmmResourceUsage> {
+        resource_usage::sample(Some(&self.guest_memory)).map_err(Error::ResourceUsage)
+    }
+
+    /// Describes the state of the rate limiters in effect on the devices of the microVM.
+    pub fn rate_limiter_stats(&self) -> Vec<RateLimiterDescription> {
+        device_list::describe_rate_limiters(&self.mmio_device_manager)
+    }
+
+    /// Describes the connections proxied by the vsock devices with a Unix backend.
+    pub fn list_vsock_connections(
+        &self,
+    ) -> std::result::Result<Vec<VsockConnectionDescription>, VsockConfigError> {
+        let mut connections = Vec::new();
+        let count = self.for_each_virtio_device(
+            TYPE_VSOCK,
+            |vsock_id, vsock: &mut Vsock<VsockUnixBackend>| {
+                connections.extend(
+                    vsock
+                        .backend()
+                        .connections()
+                        .into_iter()
+                        .map(|info| VsockConnectionDescription::new(vsock_id, info)),
+                )
+            },
+        );
+        if count == 0 {
+            return Err(self.vsock_connections_error());
+        }
+        Ok(connections)
+    }
+
+    /// Drops the connections proxied by the vsock devices with a Unix backend, once the data
+    /// sent by the guest is flushed to the host sockets, and tells the guest driver that the
+    /// transport was reset. The guest sockets then fail with `ECONNRESET`, and guest agents can
+    /// reconnect, e.g. after the microVM is restored from a snapshot.
+    ///
+    /// Returns the number of dropped connections.
+    pub fn drain_vsock_connections(&self) -> std::result::Result<usize, VsockConfigError> {
+        let mut drained = 0;
+        let mut result = Ok(());
+        let count = self.for_each_virtio_device(
+            TYPE_VSOCK,
+            |vsock_id, vsock: &mut Vsock<VsockUnixBackend>| {
+                drained += vsock.backend_mut().drain();
+                match vsock.send_transport_reset_event() {
+                    Ok(true) => (),
+                    Ok(false) => warn!(
+                        "The guest driver of the vsock device {} had no event buffer to notify \
+                         the transport reset through.",
+                        vsock_id
+                    ),
+                    // The other devices are drained all the same.
+                    Err(e) => {
+                        if result.is_ok() {
+                            result = Err(VsockConfigError::ResetTransport(e));
+                        }
+                    }
+                }
+            },
+        );
+        if count == 0 {
+            return Err(self.vsock_connections_error());
+        }
+        result.map(|()| drained)
+    }
+
+    // Tells apart a missing vsock device from a vhost-vsock one, which has no connections to
+    // list or drain.
+    fn vsock_connections_error(&self) -> VsockConfigError {
+        let vsock_type = DeviceType::Virtio(TYPE_VSOCK);
+        if self
+            .mmio_device_manager
+            .get_device_info()
+            .keys()
+            .any(|(device_type, _)| *device_type == vsock_type)
+        {
+            VsockConfigError::VhostConnections
+        } else {
+            VsockConfigError::DeviceNotFound
+        }
+    }
+
+    // Applies `f` to the virtio device registered as `device_type`, if any, and if it is a `T`.
+    pub(crate) fn with_virtio_device<T, F, R>(&self, device_type: u32, f: F) -> Option<R>
+    where
+        T: 'static,
+        F: FnOnce(&mut T) -> R,
+    {
+        let (_, device_id) = self
+            .mmio_device_manager
+            .get_device_info()
+            .keys()
+            .find(|(dev_type, _)| *dev_type == DeviceType::Virtio(device_type))?;
+        self.with_virtio_device_by_id(device_type, device_id, f)
+    }
+
+    // Applies `f` to each virtio device registered as `device_type` which is a `T`, along with
+    // the ID of the device, in the order of their MMIO addresses. Returns the number of devices
+    // `f` was applied to.
+    pub(crate) fn for_each_virtio_device<T, F>(&self, device_type: u32, mut f: F) -> usize
+    where
+        T: 'static,
+        F: FnMut(&str, &mut T),
+    {
+        let mut devices: Vec<(u64, &String)> = self
+            .mmio_device_manager
+            .get_device_info()
+            .iter()
+            .filter(|((dev_type, _), _)| *dev_type == DeviceType::Virtio(device_type))
+            .map(|((_, device_id), device_info)| (device_info.addr, device_id))
+            .collect();
+        devices.sort();
+        devices
+            .into_iter()
+            .filter_map(|(_, device_id)| {
+                self.with_virtio_device_by_id(device_type, device_id, |device: &mut T| {
+                    f(device_id, device)
+                })
+            })
+            .count()
+    }
+
+    fn with_virtio_device_by_id<T, F, R>(
+        &self,
+        device_type: u32,
+        device_id: &str,
+        f: F,
+    ) -> Option<R>
+    where
+        T: 'static,
+        F: FnOnce(&mut T) -> R,
+    {
+        let busdev = self
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(device_type), device_id)?;
+        let virtio_device = busdev
+            .lock()
+            .expect("Poisoned device lock")
+            .as_any()
+            .downcast_ref::<MmioTransport>()
+            // Only MmioTransport implements BusDevice at this point.
+            .expect("Unexpected BusDevice type")
+            .device();
+
+        let mut locked_device = virtio_device.lock().expect("Poisoned device lock");
+        // A vhost-vsock device is registered as a vsock device as well.
+        let device = locked_device.as_mut_any().downcast_mut::<T>()?;
+        Some(f(device))
+    }
+
+    /// Returns the launch measurement of the guest, if launched with SEV.
+    #[cfg(feature = "sev")]
+    pub fn launch_measurement(&self) -> Option<&[u8]> {
+        self.launch_measurement.as_ref().map(Vec::as_slice)
+    }
+
+    /// Retrieves the bitmap of the guest pages dirtied since the previous call, by the vCPUs or
+    /// by the device emulation.
+    ///
+    /// Requires the guest memory to be registered with dirty page tracking enabled.
+    pub fn get_dirty_bitmap(&self) -> Result<DirtyBitmap> {
+        let device_pages = dirty_pages::take(&self.guest_memory);
+        let mut bitmap: DirtyBitmap = HashMap::new();
+        self.guest_memory
+            .with_regions_mut(|slot, region| {
+                let mut bitmap_region = self
+                    .vm
+                    .fd()
+                    .get_dirty_log(slot as u32, region.len() as usize)?;
+                let first_page = region.start_addr().0 / dirty_pages::PAGE_SIZE;
+                let page_count = region.len() / dirty_pages::PAGE_SIZE;
+                for page in device_pages.iter() {
+                    if *page >= first_page && *page - first_page < page_count {
+                        let index = (*page - first_page) as usize;
+                        bitmap_region[index / 64] |= 1 << (index % 64);
+                    }
+                }
+                bitmap.insert(slot, bitmap_region);
+                Ok(())
+            })
+            .map_err(Error::DirtyBitmap)?;
+        Ok(bitmap)
+    }
+
+    /// Checks that the microVM state can be saved, before going through the trouble of saving it.
+    #[cfg(target_arch = "x86_64")]
+    pub fn check_state_saveable(&self) -> std::result::Result<(), MicrovmStateError> {
+        // The state of the host devices can't be saved.
+        if self.pci_device_manager.is_some() {
+            return Err(MicrovmStateError::NotAllowed(
+                "Cannot save the state of a microVM with PCI passthrough or shared memory devices."
+                    .to_string(),
+            ));
+        }
+        // The seed drive is backed by a memory file, which the restoring process can't open.
+        if self
+            .mmio_device_manager
+            .get_device(
+                DeviceType::Virtio(devices::virtio::TYPE_BLOCK),
+                SEED_DRIVE_ID,
+            )
+            .is_some()
+        {
+            return Err(MicrovmStateError::NotAllowed(
+                "Cannot save the state of a microVM with a cloud-init seed drive.".to_string(),
+            ));
+        }
+        // The guest memory and vCPU state of an SEV guest are encrypted with a key the host
+        // can't access, so they couldn't be restored.
+        #[cfg(feature = "sev")]
+        {
+            if self.launch_measurement.is_some() {
+                return Err(MicrovmStateError::NotAllowed(
+                    "Cannot save the state of an SEV guest.".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the microVM state. The vCPUs must be paused beforehand.
+    #[cfg(target_arch = "x86_64")]
+    pub fn save_state(&mut self) -> std::result::Result<MicrovmState, MicrovmStateError> {
+        self.check_state_saveable()?;
+        let vcpu_states = self.save_vcpu_states()?;
+        let vm_state = self
+            .vm
+            .save_state()
+            .map_err(MicrovmStateError::SaveVmState)?;
+        let device_states = self.save_mmio_device_states()?;
+        let memory_state = self.guest_memory.describe();
+        let mem_size_mib = memory_state
+            .regions
+            .iter()
+            .map(|region| region.size)
+            .sum::<u64>()
+            >> 20;
+        let rtc_time_offset = self
+            .pio_device_manager
+            .cmos
+            .lock()
+            .expect("Poisoned lock")
+            .time_offset();
+
+        Ok(MicrovmState {
+            // The CPU topology, the TSC frequency, the legacy devices and the PIT are filled in
+            // from the machine configuration.
+            vm_info: VmInfo {
+                mem_size_mib,
+                ht_enabled: false,
+                cores_per_socket: None,
+                tsc_khz: None,
+                legacy_serial: true,
+                legacy_i8042: true,
+                legacy_rtc: true,
+                pit: true,
+                rtc_time_offset,
+            },
+            memory_state,
+            vm_state,
+            vcpu_states,
+            device_states,
+        })
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn save_vcpu_states(&mut self) -> std::result::Result<Vec<VcpuState>, MicrovmStateError> {
+        for handle in self.vcpus_handles.iter() {
+            handle
+                .send_event(VcpuEvent::SaveState)
+                .map_err(MicrovmStateError::SignalVcpu)?;
+        }
+
+        // Gather all the responses before checking them, so that none is left behind in the
+        // channels to be mistaken for the response to a later event.
+        let responses: Vec<_> = self
+            .vcpus_handles
+            .iter()
+            .map(|handle| {
+                handle
+                    .response_receiver()
+                    .recv_timeout(Duration::from_millis(1000))
+            })
+            .collect();
+
+        let mut vcpu_states = Vec::with_capacity(responses.len());
+        for response in responses.into_iter() {
+            match response {
+                Ok(VcpuResponse::SavedState(state)) => vcpu_states.push(*state),
+                Ok(VcpuResponse::Error(e)) => return Err(MicrovmStateError::SaveVcpuState(e)),
+                Ok(VcpuResponse::NotAllowed(reason)) => {
+                    return Err(MicrovmStateError::NotAllowed(reason))
+                }
+                _ => return Err(MicrovmStateError::UnexpectedVcpuResponse),
+            }
+        }
+
+        Ok(vcpu_states)
+    }
+
     /// Saves the device states.
     #[cfg(target_arch = "x86_64")]
-    pub fn save_mmio_device_states(&mut self) -> DeviceStates {
+    pub fn save_mmio_device_states(
+        &mut self,
+    ) -> std::result::Result<DeviceStates, MicrovmStateError> {
         let mut states = DeviceStates {
             block_devices: Vec::new(),
             net_devices: Vec::new(),
             vsock_device: None,
+            balloon_device: None,
+            entropy_device: None,
+            extra_vsock_devices: Vec::new(),
         };
         let device_manager = &mut self.mmio_device_manager;
 
@@ -421,11 +1161,28 @@ impl Vmm {
             let locked_device = mmio_transport.locked_device();
             match locked_device.device_type() {
                 TYPE_BLOCK => {
-                    let block_state = locked_device
-                        .as_any()
-                        .downcast_ref::<Block>()
-                        .unwrap()
-                        .save();
+                    let block = locked_device.as_any().downcast_ref::<Block>().unwrap();
+                    // The key is not saved, so the disk couldn't be read after restoring.
+                    if block.is_encrypted() {
+                        return Err(MicrovmStateError::NotAllowed(
+                            "Cannot save the state of an encrypted block device.".to_string(),
+                        ));
+                    }
+                    // Neither is the hash tree, so the restored device wouldn't check its reads.
+                    if block.is_verified() {
+                        return Err(MicrovmStateError::NotAllowed(
+                            "Cannot save the state of a verified block device.".to_string(),
+                        ));
+                    }
+                    // The restored device reopens its backing file by path, and an inherited
+                    // file descriptor means nothing to the restoring process.
+                    if fd_uri::parse(block.disk_image_path()).is_some() {
+                        return Err(MicrovmStateError::NotAllowed(
+                            "Cannot save the state of a block device backed by a fd:// URI."
+                                .to_string(),
+                        ));
+                    }
+                    let block_state = block.save();
                     states.block_devices.push(ConnectedBlockState {
                         device_state: block_state,
                         transport_state,
@@ -433,7 +1190,15 @@ impl Vmm {
                     });
                 }
                 TYPE_NET => {
-                    let net_state = locked_device.as_any().downcast_ref::<Net>().unwrap().save();
+                    let net = locked_device.as_any().downcast_ref::<Net>().unwrap();
+                    // The restored device is backed by a tap of the same name.
+                    if !net.is_tap_backed() {
+                        return Err(MicrovmStateError::NotAllowed(
+                            "Cannot save the state of a net device not backed by a tap."
+                                .to_string(),
+                        ));
+                    }
+                    let net_state = net.save();
                     states.net_devices.push(ConnectedNetState {
                         device_state: net_state,
                         transport_state,
@@ -445,27 +1210,82 @@ impl Vmm {
                         .as_any()
                         // Currently, VsockUnixBackend is the only implementation of VsockBackend.
                         .downcast_ref::<Vsock<VsockUnixBackend>>()
-                        .unwrap();
+                        // Otherwise, this is a vhost-vsock device, whose connections live in the
+                        // host kernel.
+                        .ok_or_else(|| {
+                            MicrovmStateError::NotAllowed(
+                                "Cannot save the state of a vhost-vsock device.".to_string(),
+                            )
+                        })?;
+
                     let vsock_state = VsockState {
                         backend: vsock.backend().save(),
                         frontend: vsock.save(),
                     };
-                    states.vsock_device = Some(ConnectedVsockState {
+                    let vsock_state = ConnectedVsockState {
                         device_state: vsock_state,
                         transport_state,
                         vmm_resources,
+                    };
+                    // The first vsock device stays readable by older snapshot versions.
+                    if states.vsock_device.is_none() {
+                        states.vsock_device = Some(vsock_state);
+                    } else {
+                        states.extra_vsock_devices.push(vsock_state);
+                    }
+                }
+                TYPE_BALLOON => {
+                    let balloon_state = locked_device
+                        .as_any()
+                        .downcast_ref::<Balloon>()
+                        .unwrap()
+                        .save();
+                    states.balloon_device = Some(ConnectedBalloonState {
+                        device_state: balloon_state,
+                        transport_state,
+                        vmm_resources,
+                    });
+                }
+                TYPE_RNG => {
+                    let entropy_state = locked_device
+                        .as_any()
+                        .downcast_ref::<Entropy>()
+                        .unwrap()
+                        .save();
+                    states.entropy_device = Some(ConnectedEntropyState {
+                        device_state: entropy_state,
+                        transport_state,
+                        vmm_resources,
                     });
                 }
+                // The resources of the guest and the scanout clients are not saved.
+                TYPE_GPU => {
+                    return Err(MicrovmStateError::NotAllowed(
+                        "Cannot save the state of a GPU device.".to_string(),
+                    ))
+                }
+                // The events not yet taken by the guest are not saved.
+                TYPE_INPUT => {
+                    return Err(MicrovmStateError::NotAllowed(
+                        "Cannot save the state of an input device.".to_string(),
+                    ))
+                }
+                // The host endpoint and the stream being played are not saved.
+                TYPE_SOUND => {
+                    return Err(MicrovmStateError::NotAllowed(
+                        "Cannot save the state of a sound device.".to_string(),
+                    ))
+                }
                 _ => unreachable!(),
             };
         }
-        states
+        Ok(states)
     }
 }
 
 impl Subscriber for Vmm {
     /// Handle a read event (EPOLLIN).
-    fn process(&mut self, event: &EpollEvent, _: &mut EventManager) {
+    fn process(&mut self, event: &EpollEvent, event_manager: &mut EventManager) {
         let source = event.fd();
         let event_set = event.event_set();
 
@@ -473,8 +1293,9 @@ impl Subscriber for Vmm {
             let _ = self.exit_evt.read();
             // Query each vcpu for the exit_code.
             // If the exit_code can't be found on any vcpu, it means that the exit signal
-            // has been issued by the i8042 controller in which case we exit with
-            // FC_EXIT_CODE_OK.
+            // has been issued by a `StopRequester`, or by the i8042 controller in which case we
+            // exit with FcExitCode::Ok.
+            let requested_exit_code = *self.requested_exit_code.lock().expect("Poisoned lock");
             let exit_code = self
                 .vcpus_handles
                 .iter()
@@ -482,17 +1303,25 @@ impl Subscriber for Vmm {
                     Ok(VcpuResponse::Exited(exit_code)) => Some(exit_code),
                     _ => None,
                 })
-                .unwrap_or(FC_EXIT_CODE_OK);
-            self.stop(i32::from(exit_code));
+                .or(requested_exit_code)
+                .unwrap_or(FcExitCode::Ok);
+            self.stop(exit_code);
+        } else if source == self.operations.as_raw_fd() && event_set == EventSet::IN {
+            self.complete_operation();
+            if self.operations.running_id().is_none() && event_manager.is_held() {
+                event_manager
+                    .release()
+                    .expect("Cannot release the devices held during the operation");
+            }
         } else {
             error!("Spurious EventManager event for handler: Vmm");
         }
     }
 
     fn interest_list(&self) -> Vec<EpollEvent> {
-        vec![EpollEvent::new(
-            EventSet::IN,
-            self.exit_evt.as_raw_fd() as u64,
-        )]
+        vec![
+            EpollEvent::new(EventSet::IN, self.exit_evt.as_raw_fd() as u64),
+            EpollEvent::new(EventSet::IN, self.operations.as_raw_fd() as u64),
+        ]
     }
 }
@@ -0,0 +1,406 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Hands over a running microVM to a new Firecracker process, e.g. for an in-place upgrade of
+//! the Firecracker binary.
+//!
+//! The new process listens on a Unix domain socket, to which the running process connects. The
+//! running process pauses the vCPUs, saves the microVM state and sends it over the socket along
+//! with the file backing the guest memory, so the guest memory is shared rather than copied.
+//! Once the new process acknowledges the state, the running process exits, releasing the host
+//! resources (tap devices, vsock socket, disk files) for the new process to re-open.
+//!
+//! KVM file descriptors cannot be adopted by the KVM bindings in use, which only build a VM and
+//! its vCPUs through the ioctls creating them, so the new process re-creates the KVM VM and vCPUs
+//! and restores their saved state instead. This is the state restored from a snapshot, so the
+//! guest sees no difference; the handover only takes longer by the time needed to create them.
+//!
+//! Once connected, both processes give up on the handover when the other one does not answer
+//! within `HANDOVER_TIMEOUT`.
+
+// Currently only supports x86_64.
+#![cfg(target_arch = "x86_64")]
+
+use std::fmt::{Display, Formatter};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use builder::{build_microvm_from_state, StartMicrovmError};
+use error_code::ErrorCategory;
+use memory_snapshot::{self, SnapshotMemory};
+use mmds::MMDS;
+use persist::{version_map, MicrovmState, MicrovmStateError};
+use polly::event_manager::EventManager;
+use seccomp::BpfProgramRef;
+use snapshot::Snapshot;
+use utils::sock_ctrl_msg::ScmSocket;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use vm_memory::GuestMemoryMmap;
+use vmm_config::console::ConsoleConfig;
+use vmm_config::machine_config::VmConfig;
+use vmm_config::preopened_fds::PreopenedFds;
+use {Error as VmmError, Vmm};
+
+// Sent along with the guest memory file, announcing the microVM state.
+const HELLO: &[u8] = b"H";
+// Sent by the new process once it adopted the microVM state.
+const ACK: &[u8] = b"A";
+// How long to wait for the other Firecracker process when sending or receiving.
+const HANDOVER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Errors associated with handing over a microVM.
+#[derive(Debug)]
+pub enum LiveUpdateError {
+    /// Cannot accept the connection of the running Firecracker process.
+    Accept(io::Error),
+    /// Cannot bind the live update socket.
+    Bind(io::Error),
+    /// Cannot build the microVM from the received state.
+    BuildMicrovm(StartMicrovmError),
+    /// Cannot connect to the new Firecracker process.
+    Connect(io::Error),
+    /// Cannot deserialize the received state.
+    Deserialize(snapshot::Error),
+    /// The received microVM configuration is invalid.
+    InvalidConfig(serde_json::Error),
+    /// The guest memory cannot be shared with another process.
+    MemoryNotShareable(memory_snapshot::Error),
+    /// Cannot pause the microVM.
+    Pause(VmmError),
+    /// Cannot receive from the other Firecracker process.
+    Receive(io::Error),
+    /// The new Firecracker process did not adopt the microVM.
+    Rejected,
+    /// Cannot restore the guest memory from the received file.
+    RestoreMemory(memory_snapshot::Error),
+    /// Cannot resume the microVM after a failed handover.
+    Resume(VmmError),
+    /// Cannot save the microVM state.
+    SaveState(MicrovmStateError),
+    /// Cannot send to the other Firecracker process.
+    Send(io::Error),
+    /// Cannot serialize the microVM state.
+    Serialize(snapshot::Error),
+}
+
+impl Display for LiveUpdateError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::LiveUpdateError::*;
+        match self {
+            Accept(err) => write!(f, "Cannot accept the live update connection: {}", err),
+            Bind(err) => write!(f, "Cannot bind the live update socket: {}", err),
+            BuildMicrovm(err) => write!(f, "Cannot build the microVM: {}", err),
+            Connect(err) => write!(f, "Cannot connect to the live update socket: {}", err),
+            Deserialize(err) => write!(f, "Cannot deserialize the microVM state: {:?}", err),
+            InvalidConfig(err) => write!(f, "Invalid microVM configuration: {}", err),
+            MemoryNotShareable(err) => write!(
+                f,
+                "The guest memory cannot be handed over, make sure the microVM was started with \
+                 `memfd_backed` enabled: {}",
+                err
+            ),
+            Pause(err) => write!(f, "Cannot pause the microVM: {}", err),
+            Receive(err) => write!(f, "Cannot receive the microVM: {}", err),
+            Rejected => write!(f, "The new Firecracker process did not adopt the microVM."),
+            RestoreMemory(err) => write!(f, "Cannot restore the guest memory: {}", err),
+            Resume(err) => write!(f, "Cannot resume the microVM: {}", err),
+            SaveState(err) => write!(f, "Cannot save the microVM state: {}", err),
+            Send(err) => write!(f, "Cannot send the microVM: {}", err),
+            Serialize(err) => write!(f, "Cannot serialize the microVM state: {:?}", err),
+        }
+    }
+}
+
+impl LiveUpdateError {
+    /// Who can fix the cause of the error.
+    pub fn category(&self) -> ErrorCategory {
+        use self::LiveUpdateError::*;
+        match self {
+            BuildMicrovm(err) => err.category(),
+            SaveState(err) => err.category(),
+            MemoryNotShareable(_) => ErrorCategory::InvalidState,
+            Accept(_) | Bind(_) | Connect(_) | Deserialize(_) | InvalidConfig(_) | Receive(_)
+            | Rejected | RestoreMemory(_) | Send(_) => ErrorCategory::External,
+            Pause(_) | Resume(_) | Serialize(_) => ErrorCategory::Internal,
+        }
+    }
+
+    /// Specifies if the live update may succeed when retried, once the other Firecracker
+    /// process is ready.
+    pub fn retriable(&self) -> bool {
+        use self::LiveUpdateError::*;
+        match self {
+            Accept(_) | Connect(_) | Receive(_) | Rejected | Send(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl std::error::Error for LiveUpdateError {}
+
+type Result<T> = std::result::Result<T, LiveUpdateError>;
+
+/// Everything the new Firecracker process needs besides the guest memory.
+#[derive(Versionize)]
+struct LiveUpdateState {
+    /// The microVM configuration, serialized as JSON.
+    vm_config: String,
+    /// The MMDS contents, serialized as JSON.
+    mmds_data: String,
+    /// The microVM state.
+    microvm_state: MicrovmState,
+}
+
+/// Hands over `vmm` to the Firecracker process listening on `socket_path`.
+///
+/// On success, the microVM is paused and belongs to the new process: the caller is expected to
+/// terminate without resuming it. On failure, the microVM keeps running in this process.
+pub fn send_microvm(vmm: &mut Vmm, vm_config: &VmConfig, socket_path: &Path) -> Result<()> {
+    vmm.check_state_saveable()
+        .map_err(LiveUpdateError::SaveState)?;
+    let memory_file = vmm
+        .guest_memory()
+        .backing_file()
+        .map_err(LiveUpdateError::MemoryNotShareable)?;
+    let mut stream = UnixStream::connect(socket_path).map_err(LiveUpdateError::Connect)?;
+    set_timeouts(&stream).map_err(LiveUpdateError::Connect)?;
+
+    vmm.pause_vcpus().map_err(LiveUpdateError::Pause)?;
+    let result = vmm
+        .pause_device_threads()
+        .map_err(LiveUpdateError::Pause)
+        .and_then(|_| vmm.save_state().map_err(LiveUpdateError::SaveState))
+        .and_then(|microvm_state| {
+            let state = LiveUpdateState {
+                vm_config: serde_json::to_string(vm_config)
+                    .map_err(LiveUpdateError::InvalidConfig)?,
+                mmds_data: MMDS.lock().expect("Poisoned lock").get_data_str(),
+                microvm_state,
+            };
+            send_state(&mut stream, &memory_file, &state)
+        });
+
+    if let Err(e) = result {
+        // The new process did not take over, so the microVM has to keep running here.
+        vmm.resume_device_threads()
+            .map_err(LiveUpdateError::Resume)?;
+        vmm.resume_vcpus().map_err(LiveUpdateError::Resume)?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn set_timeouts(stream: &UnixStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(HANDOVER_TIMEOUT))?;
+    stream.set_write_timeout(Some(HANDOVER_TIMEOUT))
+}
+
+fn send_state(
+    stream: &mut UnixStream,
+    memory_file: &std::fs::File,
+    state: &LiveUpdateState,
+) -> Result<()> {
+    stream
+        .send_with_fd(HELLO, memory_file.as_raw_fd())
+        .map_err(|e| LiveUpdateError::Send(io::Error::from_raw_os_error(e.errno())))?;
+    // The state is sent in the latest snapshot format, which the new process is expected to
+    // support as well.
+    let version_map = version_map();
+    let version = version_map.latest_version();
+    Snapshot::new(version_map, version)
+        .save_with_crc64(stream, state)
+        .map_err(LiveUpdateError::Serialize)?;
+    stream.flush().map_err(LiveUpdateError::Send)?;
+
+    let mut ack = [0u8; 1];
+    match stream.read_exact(&mut ack) {
+        Ok(()) if ack == ACK => Ok(()),
+        _ => Err(LiveUpdateError::Rejected),
+    }
+}
+
+/// Adopts the microVM handed over by the Firecracker process connecting to `socket_path`.
+///
+/// This waits for the running process to connect for as long as it takes. Once the state is
+/// received and acknowledged, this waits for the previous process to exit, then re-creates the
+/// microVM and resumes it. A failure past the acknowledgement loses the microVM, since the
+/// previous process is gone by then.
+///
+/// Fails when the connected process stalls for longer than `HANDOVER_TIMEOUT`, including when it
+/// does not exit after the acknowledgement.
+///
+/// The VM is created from the descriptor of `/dev/kvm` in `preopened_fds`, if given.
+///
+/// Returns the microVM configuration and a running `Vmm`, which is also plugged in the
+/// `EventManager`.
+pub fn receive_microvm(
+    socket_path: &Path,
+    preopened_fds: &PreopenedFds,
+    event_manager: &mut EventManager,
+    seccomp_filter: BpfProgramRef,
+) -> Result<(VmConfig, Arc<Mutex<Vmm>>)> {
+    let listener = UnixListener::bind(socket_path).map_err(LiveUpdateError::Bind)?;
+    let (mut stream, _) = listener.accept().map_err(LiveUpdateError::Accept)?;
+    set_timeouts(&stream).map_err(LiveUpdateError::Accept)?;
+    // Only one handover is expected.
+    drop(listener);
+    let _ = std::fs::remove_file(socket_path);
+
+    let mut hello = [0u8; 1];
+    let memory_file = match stream.recv_with_fd(&mut hello) {
+        Ok((1, Some(file))) if hello == HELLO => file,
+        Ok(_) => {
+            return Err(LiveUpdateError::Receive(io::Error::from(
+                io::ErrorKind::InvalidData,
+            )))
+        }
+        Err(e) => {
+            return Err(LiveUpdateError::Receive(io::Error::from_raw_os_error(
+                e.errno(),
+            )))
+        }
+    };
+    let state: LiveUpdateState = Snapshot::load_with_crc64(&mut stream, version_map())
+        .map_err(LiveUpdateError::Deserialize)?;
+    let vm_config: VmConfig =
+        serde_json::from_str(&state.vm_config).map_err(LiveUpdateError::InvalidConfig)?;
+    let mmds_data =
+        serde_json::from_str(&state.mmds_data).map_err(LiveUpdateError::InvalidConfig)?;
+    let guest_memory = GuestMemoryMmap::restore(&memory_file, &state.microvm_state.memory_state)
+        .map_err(LiveUpdateError::RestoreMemory)?;
+
+    stream.write_all(ACK).map_err(LiveUpdateError::Send)?;
+    // The previous process closes the connection when exiting, which releases the host
+    // resources backing the devices.
+    let mut buf = [0u8; 1];
+    while stream.read(&mut buf).map_err(LiveUpdateError::Receive)? > 0 {}
+
+    MMDS.lock()
+        .expect("Poisoned lock")
+        .put_data(mmds_data)
+        .expect("Cannot restore the MMDS data");
+    let vmm = build_microvm_from_state(
+        state.microvm_state,
+        guest_memory,
+        vm_config.track_dirty_pages,
+        true,
+        // The microVM is received before the API server starts, so the serial console is fed
+        // from the standard input.
+        &ConsoleConfig::default(),
+        preopened_fds,
+        event_manager,
+        seccomp_filter,
+    )
+    .map_err(LiveUpdateError::BuildMicrovm)?;
+
+    Ok((vm_config, vmm))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use utils::tempfile::TempFile;
+    use vm_memory::{Bytes, GuestAddress};
+
+    use builder::tests::default_vmm;
+
+    #[test]
+    fn test_send_microvm_not_memfd_backed() {
+        let mut vmm = default_vmm();
+        let tmp_path = TempFile::new().unwrap().as_path().to_path_buf();
+
+        match send_microvm(&mut vmm, &VmConfig::default(), &tmp_path) {
+            Err(LiveUpdateError::MemoryNotShareable(_)) => (),
+            _ => panic!("The guest memory should not be shareable."),
+        }
+    }
+
+    #[test]
+    fn test_send_state() {
+        let guest_memory =
+            memory_snapshot::create_memfd_guest_memory(&[(GuestAddress(0), 0x1000)]).unwrap();
+        guest_memory.write_obj(0xAAu8, GuestAddress(0x10)).unwrap();
+        let memory_file = guest_memory.backing_file().unwrap();
+        let (mut sender, mut receiver) = UnixStream::pair().unwrap();
+
+        let receiver_thread = thread::spawn(move || {
+            let mut hello = [0u8; 1];
+            let (_, file) = receiver.recv_with_fd(&mut hello).unwrap();
+            let state: LiveUpdateState =
+                Snapshot::load_with_crc64(&mut receiver, version_map()).unwrap();
+            let guest_memory =
+                GuestMemoryMmap::restore(&file.unwrap(), &state.microvm_state.memory_state)
+                    .unwrap();
+            receiver.write_all(ACK).unwrap();
+            (
+                state.vm_config,
+                guest_memory.read_obj::<u8>(GuestAddress(0x10)).unwrap(),
+            )
+        });
+
+        // A microVM without vCPUs nor devices is enough for exercising the protocol.
+        let mut microvm_state = default_vmm().save_state().unwrap();
+        microvm_state.memory_state = guest_memory.describe();
+        let state = LiveUpdateState {
+            vm_config: String::from("{}"),
+            mmds_data: String::from("{}"),
+            microvm_state,
+        };
+        send_state(&mut sender, &memory_file, &state).unwrap();
+
+        let (vm_config, value) = receiver_thread.join().unwrap();
+        assert_eq!(vm_config, "{}");
+        assert_eq!(value, 0xAA);
+
+        // Nobody acknowledges the state anymore.
+        drop(sender);
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        drop(receiver);
+        match send_state(&mut sender, &memory_file, &state) {
+            Err(LiveUpdateError::Send(_)) | Err(LiveUpdateError::Rejected) => (),
+            _ => panic!("The handover should have failed."),
+        }
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::LiveUpdateError::*;
+
+        let err = Accept(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = Bind(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = BuildMicrovm(StartMicrovmError::MicroVMAlreadyRunning);
+        let _ = format!("{}{:?}", err, err);
+        let err = Connect(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = Deserialize(snapshot::Error::Crc64(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = MemoryNotShareable(memory_snapshot::Error::NotFileBacked);
+        let _ = format!("{}{:?}", err, err);
+        let err = Pause(VmmError::VcpuPause);
+        let _ = format!("{}{:?}", err, err);
+        let err = Receive(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = Rejected;
+        let _ = format!("{}{:?}", err, err);
+        let err = RestoreMemory(memory_snapshot::Error::NotFileBacked);
+        let _ = format!("{}{:?}", err, err);
+        let err = Resume(VmmError::VcpuResume);
+        let _ = format!("{}{:?}", err, err);
+        let err = SaveState(MicrovmStateError::UnexpectedVcpuResponse);
+        let _ = format!("{}{:?}", err, err);
+        let err = Send(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = Serialize(snapshot::Error::Crc64(0));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
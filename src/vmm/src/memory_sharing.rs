@@ -0,0 +1,210 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Monitors the guest memory shared between the microVMs restored from the same snapshot.
+//!
+//! A privately mapped guest memory file shares its clean pages with the other mappings of the
+//! file through the page cache. The first guest write to a page copies it into anonymous memory,
+//! breaking the sharing. The number of copied pages is sampled periodically from
+//! `/proc/self/smaps` and accumulated in the `memory_sharing.cow_pages` metric, so each metrics
+//! flush reports the pages copied during the last period.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+use logger::{Metric, METRICS};
+use polly::event_manager::{EventManager, Subscriber};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::{EpollEvent, EventSet};
+use vm_memory::{GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+
+/// Period of the sampling of the guest pages copied on write.
+pub const SAMPLE_PERIOD_MS: u64 = 1000;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Periodically counts the guest pages copied on write.
+pub struct CowMonitor {
+    sample_timer_fd: TimerFd,
+    // Host address ranges of the guest memory regions.
+    ranges: Vec<(u64, u64)>,
+    cow_pages: usize,
+}
+
+impl CowMonitor {
+    /// Creates a monitor of the pages of `guest_memory` copied on write, sampled every
+    /// `SAMPLE_PERIOD_MS`.
+    pub fn new(guest_memory: &GuestMemoryMmap) -> io::Result<Self> {
+        let ranges = guest_memory.map_and_fold(
+            Vec::new(),
+            |(_, region)| {
+                let start = region.as_ptr() as u64;
+                vec![(start, start + region.len() as u64)]
+            },
+            |mut ranges, mut range| {
+                ranges.append(&mut range);
+                ranges
+            },
+        );
+
+        let mut sample_timer_fd = TimerFd::new_custom(ClockId::Monotonic, true, true)?;
+        sample_timer_fd.set_state(
+            TimerState::Periodic {
+                current: Duration::from_millis(SAMPLE_PERIOD_MS),
+                interval: Duration::from_millis(SAMPLE_PERIOD_MS),
+            },
+            SetTimeFlags::Default,
+        );
+
+        Ok(CowMonitor {
+            sample_timer_fd,
+            ranges,
+            cow_pages: 0,
+        })
+    }
+
+    fn sample(&mut self) {
+        match File::open("/proc/self/smaps")
+            .and_then(|smaps| anonymous_pages(BufReader::new(smaps), &self.ranges))
+        {
+            Ok(cow_pages) => {
+                // Pages discarded by the balloon device are shared again, but the metric only
+                // accounts for the sharing being broken.
+                if cow_pages > self.cow_pages {
+                    METRICS
+                        .memory_sharing
+                        .cow_pages
+                        .add(cow_pages - self.cow_pages);
+                }
+                self.cow_pages = cow_pages;
+            }
+            Err(e) => {
+                METRICS.memory_sharing.sample_fails.inc();
+                error!("Failed to count the guest pages copied on write: {}", e);
+            }
+        }
+    }
+}
+
+impl Subscriber for CowMonitor {
+    /// Handle a read event (EPOLLIN).
+    fn process(&mut self, event: &EpollEvent, _: &mut EventManager) {
+        let source = event.fd();
+        let event_set = event.event_set();
+
+        let supported_events = EventSet::IN;
+        if !supported_events.contains(event_set) {
+            warn!(
+                "Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
+
+        if source == self.sample_timer_fd.as_raw_fd() {
+            self.sample_timer_fd.read();
+            self.sample();
+        } else {
+            error!("Spurious memory sharing event!");
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        vec![EpollEvent::new(
+            EventSet::IN,
+            self.sample_timer_fd.as_raw_fd() as u64,
+        )]
+    }
+}
+
+// Counts the anonymous pages of the mappings in `smaps` starting within `ranges`.
+fn anonymous_pages<R: BufRead>(smaps: R, ranges: &[(u64, u64)]) -> io::Result<usize> {
+    let mut in_ranges = false;
+    let mut anonymous_kib = 0;
+    for line in smaps.lines() {
+        let line = line?;
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("Anonymous:") if in_ranges => {
+                anonymous_kib += fields
+                    .next()
+                    .and_then(|kib| kib.parse::<usize>().ok())
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidData))?;
+            }
+            // The mapping headers start with the address range, e.g. `7f4a2c000000-7f4a2c021000`,
+            // while the fields of the mapping end with a colon.
+            Some(field) if !field.ends_with(':') => {
+                in_ranges = field
+                    .split('-')
+                    .next()
+                    .and_then(|start| u64::from_str_radix(start, 16).ok())
+                    .map(|start| ranges.iter().any(|&(s, e)| start >= s && start < e))
+                    .unwrap_or(false);
+            }
+            _ => (),
+        }
+    }
+    Ok(anonymous_kib * 1024 / PAGE_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vm_memory::{Bytes, GuestAddress};
+
+    const SMAPS: &str = "\
+7f0000000000-7f0000010000 rw-p 00000000 fd:01 1234    /tmp/mem_file
+Size:                 64 kB
+Rss:                  32 kB
+Anonymous:             8 kB
+VmFlags: rd wr mr mw me ac sd
+7f0000010000-7f0000020000 rw-p 00010000 fd:01 1234    /tmp/mem_file
+Size:                 64 kB
+Anonymous:            16 kB
+VmFlags: rd wr mr mw me ac sd
+7f1000000000-7f1000001000 rw-p 00000000 00:00 0
+Size:                  4 kB
+Anonymous:             4 kB
+VmFlags: rd wr mr mw me ac sd
+";
+
+    #[test]
+    fn test_anonymous_pages() {
+        let ranges = [(0x7f00_0000_0000, 0x7f00_0002_0000)];
+        assert_eq!(anonymous_pages(SMAPS.as_bytes(), &ranges).unwrap(), 6);
+
+        let ranges = [(0x7f00_0001_0000, 0x7f00_0002_0000)];
+        assert_eq!(anonymous_pages(SMAPS.as_bytes(), &ranges).unwrap(), 4);
+
+        assert_eq!(anonymous_pages(SMAPS.as_bytes(), &[]).unwrap(), 0);
+
+        let invalid_smaps = "7f0000000000-7f0000010000 rw-p 00000000 fd:01 1234\nAnonymous: x kB\n";
+        let ranges = [(0x7f00_0000_0000, 0x7f00_0002_0000)];
+        assert!(anonymous_pages(invalid_smaps.as_bytes(), &ranges).is_err());
+    }
+
+    #[test]
+    fn test_cow_monitor() {
+        let guest_memory = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let mut monitor = CowMonitor::new(&guest_memory).unwrap();
+        assert_eq!(monitor.ranges.len(), 1);
+        assert_eq!(monitor.ranges[0].1 - monitor.ranges[0].0, 0x1000);
+
+        let interest_list = monitor.interest_list();
+        assert_eq!(interest_list.len(), 1);
+        assert_eq!(
+            interest_list[0].data() as i32,
+            monitor.sample_timer_fd.as_raw_fd()
+        );
+
+        // Anonymous guest memory counts as copied once written.
+        let cow_pages = METRICS.memory_sharing.cow_pages.count();
+        guest_memory.write_obj(0xAAu8, GuestAddress(0)).unwrap();
+        monitor.sample();
+        assert_eq!(monitor.cow_pages, 1);
+        assert!(METRICS.memory_sharing.cow_pages.count() >= cow_pages + 1);
+    }
+}
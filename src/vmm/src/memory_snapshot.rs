@@ -0,0 +1,772 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines functionality for describing the guest memory layout and for saving the guest memory
+//! to, or restoring it from, a file.
+
+use std::ffi::CString;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::FromRawFd;
+
+use lz4;
+use sha2::{Digest, Sha256};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use vm_memory::{
+    Address, Bytes, FileOffset, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap,
+    GuestMemoryRegion, GuestRegionMmap, MmapRegion,
+};
+use zstd;
+use DirtyBitmap;
+
+// Granularity of the KVM dirty page tracking.
+const PAGE_SIZE: usize = 4096;
+
+/// Errors associated with the guest memory snapshotting operations.
+#[derive(Debug)]
+pub enum Error {
+    /// The contents of the guest memory region at the given address do not match its checksum.
+    ChecksumMismatch(u64),
+    /// Cannot compress the guest memory.
+    Compress(io::Error),
+    /// Cannot compute the checksum of a guest memory region.
+    ComputeChecksum(GuestMemoryError),
+    /// Cannot create the guest memory.
+    CreateMemory(vm_memory::Error),
+    /// Cannot create the file backing the guest memory.
+    CreateMemoryFile(io::Error),
+    /// Cannot decompress the guest memory.
+    Decompress(io::Error),
+    /// Cannot duplicate the handle of the file backing the guest memory.
+    FileHandle(io::Error),
+    /// The guest memory is not backed by a single file.
+    NotFileBacked,
+    /// Cannot read the guest memory from file.
+    ReadMemory(GuestMemoryError),
+    /// Cannot seek in the guest memory file.
+    SeekMemoryFile(io::Error),
+    /// Cannot write the guest memory to file.
+    WriteMemory(GuestMemoryError),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::Error::*;
+        match self {
+            ChecksumMismatch(addr) => write!(
+                f,
+                "The guest memory region at {:#x} does not match its checksum.",
+                addr
+            ),
+            Compress(err) => write!(f, "Cannot compress the guest memory: {}", err),
+            ComputeChecksum(err) => {
+                write!(f, "Cannot compute the guest memory checksum: {:?}", err)
+            }
+            CreateMemory(err) => write!(f, "Cannot create the guest memory: {:?}", err),
+            CreateMemoryFile(err) => {
+                write!(
+                    f,
+                    "Cannot create the file backing the guest memory: {}",
+                    err
+                )
+            }
+            Decompress(err) => write!(f, "Cannot decompress the guest memory: {}", err),
+            FileHandle(err) => write!(f, "Cannot duplicate the guest memory file handle: {}", err),
+            NotFileBacked => write!(f, "The guest memory is not backed by a single file."),
+            ReadMemory(err) => write!(f, "Cannot read the guest memory from file: {:?}", err),
+            SeekMemoryFile(err) => write!(f, "Cannot seek in the guest memory file: {}", err),
+            WriteMemory(err) => write!(f, "Cannot write the guest memory to file: {:?}", err),
+        }
+    }
+}
+
+/// Compression algorithm of the guest memory file.
+#[derive(Clone, Copy, Debug, PartialEq, Versionize)]
+pub enum Compression {
+    /// The guest memory is saved as is.
+    None,
+    /// Each region is compressed as a separate LZ4 frame.
+    Lz4,
+    /// Each region is compressed as a separate Zstandard frame.
+    Zstd,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::None
+    }
+}
+
+/// State of a guest memory region saved to file.
+#[derive(Debug, PartialEq, Versionize)]
+pub struct GuestMemoryRegionState {
+    /// Base address.
+    pub base_address: u64,
+    /// Region size.
+    pub size: u64,
+    /// Offset in the file where the region is saved.
+    pub offset: u64,
+    /// SHA-256 checksum of the region contents, validated when loading the region.
+    #[version(start = 2, default_fn = "default_sha256")]
+    pub sha256: Option<Vec<u8>>,
+}
+
+impl GuestMemoryRegionState {
+    fn default_sha256(_: u16) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Guest memory state.
+#[derive(Debug, Default, PartialEq, Versionize)]
+pub struct GuestMemoryState {
+    /// List of regions.
+    pub regions: Vec<GuestMemoryRegionState>,
+    /// Compression of the guest memory file.
+    #[version(start = 2, default_fn = "default_compression")]
+    pub compression: Compression,
+}
+
+impl GuestMemoryState {
+    fn default_compression(_: u16) -> Compression {
+        Compression::None
+    }
+}
+
+/// Defines the interface for snapshotting memory.
+pub trait SnapshotMemory
+where
+    Self: Sized,
+{
+    /// Describes the guest memory layout through a `GuestMemoryState` struct.
+    fn describe(&self) -> GuestMemoryState;
+    /// Returns the file backing all the guest memory regions, if any.
+    fn backing_file(&self) -> Result<File, Error>;
+    /// Creates the guest memory by mapping `file`, as described by `state`. The mappings are
+    /// shared, so the guest memory contents are not copied.
+    fn restore(file: &File, state: &GuestMemoryState) -> Result<Self, Error>;
+    /// Creates the guest memory by privately mapping `file`, as described by `state`. The clean
+    /// pages are shared with the other mappings of `file` through the page cache, while the
+    /// written pages are copied, so the guest writes never reach `file`.
+    fn restore_private(file: &File, state: &GuestMemoryState) -> Result<Self, Error>;
+    /// Writes all the guest memory to `writer`, one region after the other, compressed with
+    /// `compression`. Returns the layout of the written guest memory.
+    fn dump<T: Write + Seek>(
+        &self,
+        writer: &mut T,
+        compression: Compression,
+    ) -> Result<GuestMemoryState, Error>;
+    /// Writes the guest pages flagged in `dirty_bitmap` to `writer`, at the same offsets as
+    /// an uncompressed `dump`. The other pages are skipped, so `writer` is expected to be a
+    /// sparse file.
+    fn dump_dirty<T: Write + Seek>(
+        &self,
+        writer: &mut T,
+        dirty_bitmap: &DirtyBitmap,
+    ) -> Result<(), Error>;
+    /// Creates the guest memory described by `state`, with the contents read from `reader`. The
+    /// regions having a checksum are validated.
+    fn load<T: Read + Seek>(reader: &mut T, state: &GuestMemoryState) -> Result<Self, Error>;
+    /// Sets the checksum of each region in `state` to the one of its current contents.
+    fn compute_checksums(&self, state: &mut GuestMemoryState) -> Result<(), Error>;
+}
+
+impl SnapshotMemory for GuestMemoryMmap {
+    fn describe(&self) -> GuestMemoryState {
+        let mut next_offset = 0;
+        let regions = self.map_and_fold(
+            Vec::new(),
+            |(_, region)| {
+                vec![(
+                    region.start_addr().raw_value(),
+                    region.len() as u64,
+                    region.file_offset().map(FileOffset::start),
+                )]
+            },
+            |mut regions, mut region| {
+                regions.append(&mut region);
+                regions
+            },
+        );
+
+        GuestMemoryState {
+            regions: regions
+                .into_iter()
+                .map(|(base_address, size, file_offset)| {
+                    // Regions which are not file backed are laid out one after the other.
+                    let offset = file_offset.unwrap_or(next_offset);
+                    next_offset = offset + size;
+                    GuestMemoryRegionState {
+                        base_address,
+                        size,
+                        offset,
+                        sha256: None,
+                    }
+                })
+                .collect(),
+            compression: Compression::None,
+        }
+    }
+
+    fn backing_file(&self) -> Result<File, Error> {
+        let fds = self.map_and_fold(
+            Vec::new(),
+            |(_, region)| {
+                // Privately mapped files do not hold the guest writes.
+                let shared = region.flags() & libc::MAP_SHARED != 0;
+                vec![region
+                    .file_offset()
+                    .filter(|_| shared)
+                    .map(|f| f.file().try_clone())]
+            },
+            |mut fds, mut fd| {
+                fds.append(&mut fd);
+                fds
+            },
+        );
+
+        let mut files = fds.into_iter();
+        let file = match files.next() {
+            Some(Some(file)) => file.map_err(Error::FileHandle)?,
+            _ => return Err(Error::NotFileBacked),
+        };
+        // All the regions have to live in the same file, so that a single handle describes the
+        // whole guest memory.
+        let (dev, ino) = file_id(&file)?;
+        for other in files {
+            match other {
+                Some(other) if file_id(&other.map_err(Error::FileHandle)?)? == (dev, ino) => (),
+                _ => return Err(Error::NotFileBacked),
+            }
+        }
+        Ok(file)
+    }
+
+    fn restore(file: &File, state: &GuestMemoryState) -> Result<Self, Error> {
+        let mut regions = vec![];
+        for region in state.regions.iter() {
+            let f = file.try_clone().map_err(Error::FileHandle)?;
+            regions.push((
+                GuestAddress(region.base_address),
+                region.size as usize,
+                Some(FileOffset::new(f, region.offset)),
+            ));
+        }
+
+        GuestMemoryMmap::from_ranges_with_files(&regions).map_err(Error::CreateMemory)
+    }
+
+    fn restore_private(file: &File, state: &GuestMemoryState) -> Result<Self, Error> {
+        let mut regions = vec![];
+        for region in state.regions.iter() {
+            let f = file.try_clone().map_err(Error::FileHandle)?;
+            let mapping = MmapRegion::build(
+                Some(FileOffset::new(f, region.offset)),
+                region.size as usize,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_NORESERVE | libc::MAP_PRIVATE,
+            )
+            .map_err(vm_memory::Error::MmapRegion)
+            .map_err(Error::CreateMemory)?;
+            regions.push(
+                GuestRegionMmap::new(mapping, GuestAddress(region.base_address))
+                    .map_err(Error::CreateMemory)?,
+            );
+        }
+
+        GuestMemoryMmap::from_regions(regions).map_err(Error::CreateMemory)
+    }
+
+    fn dump<T: Write + Seek>(
+        &self,
+        writer: &mut T,
+        compression: Compression,
+    ) -> Result<GuestMemoryState, Error> {
+        let mut state = self.describe();
+        state.compression = compression;
+
+        let mut offset = writer
+            .seek(SeekFrom::Current(0))
+            .map_err(Error::SeekMemoryFile)?;
+        for region in state.regions.iter_mut() {
+            let addr = GuestAddress(region.base_address);
+            let len = region.size as usize;
+            // Compressing the regions separately allows seeking to each of them on load.
+            match compression {
+                Compression::None => self
+                    .write_all_to(addr, writer, len)
+                    .map_err(Error::WriteMemory)?,
+                Compression::Lz4 => {
+                    let mut encoder = lz4::EncoderBuilder::new()
+                        .build(&mut *writer)
+                        .map_err(Error::Compress)?;
+                    self.write_all_to(addr, &mut encoder, len)
+                        .map_err(Error::WriteMemory)?;
+                    encoder.finish().1.map_err(Error::Compress)?;
+                }
+                Compression::Zstd => {
+                    let mut encoder =
+                        zstd::stream::Encoder::new(&mut *writer, 0).map_err(Error::Compress)?;
+                    self.write_all_to(addr, &mut encoder, len)
+                        .map_err(Error::WriteMemory)?;
+                    encoder.finish().map_err(Error::Compress)?;
+                }
+            }
+            region.offset = offset;
+            offset = writer
+                .seek(SeekFrom::Current(0))
+                .map_err(Error::SeekMemoryFile)?;
+        }
+        Ok(state)
+    }
+
+    fn dump_dirty<T: Write + Seek>(
+        &self,
+        writer: &mut T,
+        dirty_bitmap: &DirtyBitmap,
+    ) -> Result<(), Error> {
+        // KVM memory slots are numbered after the guest memory regions.
+        for (slot, region) in self.describe().regions.iter().enumerate() {
+            let bitmap = match dirty_bitmap.get(&slot) {
+                Some(bitmap) => bitmap,
+                None => continue,
+            };
+            for (index, word) in bitmap.iter().enumerate() {
+                for bit in 0..64 {
+                    if (word >> bit) & 1 == 0 {
+                        continue;
+                    }
+                    let page_offset = ((index * 64 + bit) * PAGE_SIZE) as u64;
+                    writer
+                        .seek(SeekFrom::Start(region.offset + page_offset))
+                        .map_err(Error::SeekMemoryFile)?;
+                    self.write_all_to(
+                        GuestAddress(region.base_address + page_offset),
+                        writer,
+                        PAGE_SIZE,
+                    )
+                    .map_err(Error::WriteMemory)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn load<T: Read + Seek>(reader: &mut T, state: &GuestMemoryState) -> Result<Self, Error> {
+        let ranges: Vec<_> = state
+            .regions
+            .iter()
+            .map(|region| (GuestAddress(region.base_address), region.size as usize))
+            .collect();
+        let guest_memory = GuestMemoryMmap::from_ranges(&ranges).map_err(Error::CreateMemory)?;
+
+        for region in state.regions.iter() {
+            reader
+                .seek(SeekFrom::Start(region.offset))
+                .map_err(Error::SeekMemoryFile)?;
+            let addr = GuestAddress(region.base_address);
+            let len = region.size as usize;
+            match state.compression {
+                Compression::None => guest_memory.read_exact_from(addr, reader, len),
+                Compression::Lz4 => {
+                    let mut decoder = lz4::Decoder::new(&mut *reader).map_err(Error::Decompress)?;
+                    guest_memory.read_exact_from(addr, &mut decoder, len)
+                }
+                Compression::Zstd => {
+                    let mut decoder =
+                        zstd::stream::Decoder::new(&mut *reader).map_err(Error::Decompress)?;
+                    guest_memory.read_exact_from(addr, &mut decoder, len)
+                }
+            }
+            .map_err(Error::ReadMemory)?;
+
+            if let Some(sha256) = region.sha256.as_ref() {
+                if region_sha256(&guest_memory, region)? != *sha256 {
+                    return Err(Error::ChecksumMismatch(region.base_address));
+                }
+            }
+        }
+        Ok(guest_memory)
+    }
+
+    fn compute_checksums(&self, state: &mut GuestMemoryState) -> Result<(), Error> {
+        for region in state.regions.iter_mut() {
+            region.sha256 = Some(region_sha256(self, region)?);
+        }
+        Ok(())
+    }
+}
+
+/// Creates the guest memory with the `ranges` layout, backed by an anonymous memory file
+/// (memfd). Unlike anonymous mappings, the memory file can be handed over to another process.
+pub fn create_memfd_guest_memory(
+    ranges: &[(GuestAddress, usize)],
+) -> Result<GuestMemoryMmap, Error> {
+    let name = CString::new("guest_mem").expect("Invalid memfd name");
+    // Safe because the name is a valid C string and we check the return value.
+    let fd = unsafe { libc::syscall(libc::SYS_memfd_create, name.as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(Error::CreateMemoryFile(io::Error::last_os_error()));
+    }
+    // Safe because we own the newly created file descriptor.
+    let file = unsafe { File::from_raw_fd(fd as i32) };
+    let total_size: usize = ranges.iter().map(|(_, size)| size).sum();
+    file.set_len(total_size as u64)
+        .map_err(Error::CreateMemoryFile)?;
+
+    let mut offset = 0;
+    let mut regions = vec![];
+    for (base_address, size) in ranges.iter() {
+        let f = file.try_clone().map_err(Error::FileHandle)?;
+        regions.push((*base_address, *size, Some(FileOffset::new(f, offset))));
+        offset += *size as u64;
+    }
+
+    GuestMemoryMmap::from_ranges_with_files(&regions).map_err(Error::CreateMemory)
+}
+
+// Computes the SHA-256 checksum of the contents of a guest memory region.
+fn region_sha256(
+    guest_memory: &GuestMemoryMmap,
+    region: &GuestMemoryRegionState,
+) -> Result<Vec<u8>, Error> {
+    let mut hasher = Sha256::new();
+    guest_memory
+        .write_all_to(
+            GuestAddress(region.base_address),
+            &mut hasher,
+            region.size as usize,
+        )
+        .map_err(Error::ComputeChecksum)?;
+    Ok(hasher.finalize().to_vec())
+}
+
+// Identifies the file behind a handle, since duplicated handles have different descriptors.
+fn file_id(file: &File) -> Result<(u64, u64), Error> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = file.metadata().map_err(Error::FileHandle)?;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use utils::tempfile::TempFile;
+
+    #[test]
+    fn test_describe() {
+        let page_size: usize = 0x1000;
+        let ranges = [
+            (GuestAddress(0), page_size),
+            (GuestAddress(page_size as u64 * 2), page_size * 2),
+        ];
+        let expected_state = GuestMemoryState {
+            regions: vec![
+                GuestMemoryRegionState {
+                    base_address: 0,
+                    size: page_size as u64,
+                    offset: 0,
+                    sha256: None,
+                },
+                GuestMemoryRegionState {
+                    base_address: page_size as u64 * 2,
+                    size: page_size as u64 * 2,
+                    offset: page_size as u64,
+                    sha256: None,
+                },
+            ],
+            compression: Compression::None,
+        };
+
+        let guest_memory = GuestMemoryMmap::from_ranges(&ranges).unwrap();
+        assert_eq!(guest_memory.describe(), expected_state);
+        assert!(guest_memory.backing_file().is_err());
+
+        let guest_memory = create_memfd_guest_memory(&ranges).unwrap();
+        assert_eq!(guest_memory.describe(), expected_state);
+    }
+
+    #[test]
+    fn test_restore_shares_memory() {
+        let page_size: usize = 0x1000;
+        let ranges = [
+            (GuestAddress(0), page_size),
+            (GuestAddress(page_size as u64 * 2), page_size),
+        ];
+        let guest_memory = create_memfd_guest_memory(&ranges).unwrap();
+        guest_memory
+            .write_obj(0xAAu8, GuestAddress(page_size as u64 * 2))
+            .unwrap();
+
+        let mut file = guest_memory.backing_file().unwrap();
+        let restored_memory = GuestMemoryMmap::restore(&file, &guest_memory.describe()).unwrap();
+        assert_eq!(
+            restored_memory
+                .read_obj::<u8>(GuestAddress(page_size as u64 * 2))
+                .unwrap(),
+            0xAA
+        );
+
+        // Writes through the restored memory are visible through the original one.
+        restored_memory.write_obj(0x55u8, GuestAddress(0)).unwrap();
+        assert_eq!(guest_memory.read_obj::<u8>(GuestAddress(0)).unwrap(), 0x55);
+
+        // The second region lives right after the first one in the file.
+        let mut buf = [0u8; 1];
+        file.seek(SeekFrom::Start(page_size as u64)).unwrap();
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[0], 0xAA);
+    }
+
+    #[test]
+    fn test_restore_private() {
+        let page_size: usize = 0x1000;
+        let ranges = [
+            (GuestAddress(0), page_size),
+            (GuestAddress(page_size as u64 * 2), page_size),
+        ];
+        let guest_memory = GuestMemoryMmap::from_ranges(&ranges).unwrap();
+        guest_memory
+            .write_obj(0xAAu8, GuestAddress(page_size as u64 * 2))
+            .unwrap();
+        let mut file = TempFile::new().unwrap().into_file();
+        let state = guest_memory.dump(&mut file, Compression::None).unwrap();
+
+        let first_clone = GuestMemoryMmap::restore_private(&file, &state).unwrap();
+        let second_clone = GuestMemoryMmap::restore_private(&file, &state).unwrap();
+        assert_eq!(first_clone.describe(), state);
+        assert_eq!(
+            first_clone
+                .read_obj::<u8>(GuestAddress(page_size as u64 * 2))
+                .unwrap(),
+            0xAA
+        );
+
+        // Writes are neither visible through the other clones nor in the file.
+        first_clone
+            .write_obj(0x55u8, GuestAddress(page_size as u64 * 2))
+            .unwrap();
+        assert_eq!(
+            second_clone
+                .read_obj::<u8>(GuestAddress(page_size as u64 * 2))
+                .unwrap(),
+            0xAA
+        );
+        let mut buf = [0u8; 1];
+        file.seek(SeekFrom::Start(page_size as u64)).unwrap();
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[0], 0xAA);
+
+        // The file does not hold the guest memory contents anymore.
+        match first_clone.backing_file() {
+            Err(Error::NotFileBacked) => (),
+            _ => panic!("Privately mapped guest memory should not be handed over."),
+        }
+    }
+
+    #[test]
+    fn test_dump_and_load() {
+        let page_size: usize = 0x1000;
+        let ranges = [
+            (GuestAddress(0), page_size * 2),
+            (GuestAddress(page_size as u64 * 4), page_size * 2),
+        ];
+        let guest_memory = GuestMemoryMmap::from_ranges(&ranges).unwrap();
+        guest_memory.write_obj(0x11u8, GuestAddress(0)).unwrap();
+        guest_memory
+            .write_obj(0x22u8, GuestAddress(page_size as u64 * 5))
+            .unwrap();
+
+        let mut file = TempFile::new().unwrap().into_file();
+        let state = guest_memory.dump(&mut file, Compression::None).unwrap();
+        assert_eq!(state, guest_memory.describe());
+        assert_eq!(file.metadata().unwrap().len(), page_size as u64 * 4);
+
+        let restored_memory = GuestMemoryMmap::load(&mut file, &state).unwrap();
+        assert_eq!(restored_memory.describe(), guest_memory.describe());
+        assert_eq!(
+            restored_memory.read_obj::<u8>(GuestAddress(0)).unwrap(),
+            0x11
+        );
+        assert_eq!(
+            restored_memory
+                .read_obj::<u8>(GuestAddress(page_size as u64 * 5))
+                .unwrap(),
+            0x22
+        );
+
+        // The memory file is too short for the described layout.
+        file.set_len(page_size as u64).unwrap();
+        match GuestMemoryMmap::load(&mut file, &guest_memory.describe()) {
+            Err(Error::ReadMemory(_)) => (),
+            _ => panic!("Loading a truncated memory file should fail."),
+        }
+    }
+
+    #[test]
+    fn test_dump_compressed() {
+        let page_size: usize = 0x1000;
+        let ranges = [
+            (GuestAddress(0), page_size * 4),
+            (GuestAddress(page_size as u64 * 8), page_size * 4),
+        ];
+        let guest_memory = GuestMemoryMmap::from_ranges(&ranges).unwrap();
+        guest_memory.write_obj(0x11u8, GuestAddress(0)).unwrap();
+        guest_memory
+            .write_obj(0x22u8, GuestAddress(page_size as u64 * 9))
+            .unwrap();
+
+        for compression in [Compression::Lz4, Compression::Zstd].iter() {
+            let mut file = TempFile::new().unwrap().into_file();
+            let state = guest_memory.dump(&mut file, *compression).unwrap();
+            assert_eq!(state.compression, *compression);
+            assert_eq!(state.regions[0].offset, 0);
+            // The mostly zeroed regions compress well.
+            assert!(state.regions[1].offset < page_size as u64);
+            assert!(file.metadata().unwrap().len() < page_size as u64 * 2);
+
+            let restored_memory = GuestMemoryMmap::load(&mut file, &state).unwrap();
+            assert_eq!(
+                restored_memory.read_obj::<u8>(GuestAddress(0)).unwrap(),
+                0x11
+            );
+            assert_eq!(
+                restored_memory
+                    .read_obj::<u8>(GuestAddress(page_size as u64 * 9))
+                    .unwrap(),
+                0x22
+            );
+        }
+    }
+
+    #[test]
+    fn test_checksums() {
+        let page_size: usize = 0x1000;
+        let ranges = [
+            (GuestAddress(0), page_size),
+            (GuestAddress(page_size as u64 * 2), page_size),
+        ];
+        let guest_memory = GuestMemoryMmap::from_ranges(&ranges).unwrap();
+        guest_memory.write_obj(0x11u8, GuestAddress(0)).unwrap();
+
+        let mut file = TempFile::new().unwrap().into_file();
+        let mut state = guest_memory.dump(&mut file, Compression::None).unwrap();
+        guest_memory.compute_checksums(&mut state).unwrap();
+        assert!(state.regions.iter().all(|region| region.sha256.is_some()));
+        assert!(GuestMemoryMmap::load(&mut file, &state).is_ok());
+
+        // Corrupt the second region.
+        file.seek(SeekFrom::Start(page_size as u64)).unwrap();
+        file.write_all(&[0xFF]).unwrap();
+        match GuestMemoryMmap::load(&mut file, &state) {
+            Err(Error::ChecksumMismatch(addr)) => assert_eq!(addr, page_size as u64 * 2),
+            _ => panic!("The corrupted region should not match its checksum."),
+        }
+
+        // Regions without a checksum are not validated.
+        state.regions[1].sha256 = None;
+        assert!(GuestMemoryMmap::load(&mut file, &state).is_ok());
+    }
+
+    #[test]
+    fn test_guest_memory_state_versionize() {
+        let mut state = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)])
+            .unwrap()
+            .describe();
+        state.compression = Compression::Zstd;
+        state.regions[0].sha256 = Some(vec![0xAA; 32]);
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(GuestMemoryState::type_id(), 2)
+            .set_type_version(GuestMemoryRegionState::type_id(), 2);
+
+        let mut buf = vec![0; 1000];
+        state
+            .serialize(&mut buf.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_state =
+            GuestMemoryState::deserialize(&mut buf.as_slice(), &version_map, 2).unwrap();
+        assert_eq!(restored_state, state);
+
+        // Version 1 predates compression and checksums.
+        state
+            .serialize(&mut buf.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let restored_state =
+            GuestMemoryState::deserialize(&mut buf.as_slice(), &version_map, 1).unwrap();
+        assert_eq!(restored_state.compression, Compression::None);
+        assert_eq!(restored_state.regions[0].sha256, None);
+        assert_eq!(restored_state.regions[0].size, 0x1000);
+    }
+
+    #[test]
+    fn test_dump_dirty() {
+        let page_size: usize = 0x1000;
+        let ranges = [
+            (GuestAddress(0), page_size * 2),
+            (GuestAddress(page_size as u64 * 4), page_size * 2),
+        ];
+        let guest_memory = GuestMemoryMmap::from_ranges(&ranges).unwrap();
+        guest_memory.write_obj(0x11u8, GuestAddress(0)).unwrap();
+        guest_memory
+            .write_obj(0x22u8, GuestAddress(page_size as u64))
+            .unwrap();
+        guest_memory
+            .write_obj(0x33u8, GuestAddress(page_size as u64 * 5))
+            .unwrap();
+
+        // Only the second page of each region is dirty.
+        let mut dirty_bitmap = DirtyBitmap::new();
+        dirty_bitmap.insert(0, vec![0b10]);
+        dirty_bitmap.insert(1, vec![0b10]);
+
+        let mut file = TempFile::new().unwrap().into_file();
+        file.set_len(page_size as u64 * 4).unwrap();
+        guest_memory.dump_dirty(&mut file, &dirty_bitmap).unwrap();
+
+        let restored_memory = GuestMemoryMmap::load(&mut file, &guest_memory.describe()).unwrap();
+        assert_eq!(restored_memory.read_obj::<u8>(GuestAddress(0)).unwrap(), 0);
+        assert_eq!(
+            restored_memory
+                .read_obj::<u8>(GuestAddress(page_size as u64))
+                .unwrap(),
+            0x22
+        );
+        assert_eq!(
+            restored_memory
+                .read_obj::<u8>(GuestAddress(page_size as u64 * 5))
+                .unwrap(),
+            0x33
+        );
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let err = Error::ChecksumMismatch(0);
+        let _ = format!("{}{:?}", err, err);
+        let err = Error::Compress(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = Error::ComputeChecksum(GuestMemoryError::InvalidBackendAddress);
+        let _ = format!("{}{:?}", err, err);
+        let err = Error::CreateMemory(vm_memory::Error::NoMemoryRegion);
+        let _ = format!("{}{:?}", err, err);
+        let err = Error::CreateMemoryFile(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = Error::Decompress(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = Error::FileHandle(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = Error::NotFileBacked;
+        let _ = format!("{}{:?}", err, err);
+        let err = Error::ReadMemory(GuestMemoryError::InvalidBackendAddress);
+        let _ = format!("{}{:?}", err, err);
+        let err = Error::SeekMemoryFile(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = Error::WriteMemory(GuestMemoryError::InvalidBackendAddress);
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -0,0 +1,649 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Migrates a running microVM to another Firecracker process, possibly on another host.
+//!
+//! The guest memory is copied while the guest keeps running (pre-copy): a first round sends all
+//! the guest pages, then each round sends the pages dirtied during the previous one.
+//! Once few enough pages are dirtied, or after a bounded number of rounds, the vCPUs are paused
+//! and the remaining dirty pages are sent along with the microVM state (stop-and-copy). The
+//! destination process, started in incoming mode, then resumes the microVM.
+//!
+//! Both processes talk over a Unix domain socket. Migrating across hosts is done by having the
+//! control plane forward the socket, which leaves transport security to the control plane.
+//! Once connected, both processes give up on the migration when the other one does not answer
+//! within `MIGRATION_TIMEOUT`, and the source process then resumes the microVM.
+//!
+//! The migration runs on the VMM thread, which also emulates most devices, so their emulation is
+//! stalled during the migration. The devices emulated on threads of their own keep running
+//! until they are paused along with the vCPUs for the stop-and-copy. The pages the vCPUs write
+//! are tracked by KVM, and the pages the devices write by `devices::virtio::dirty_pages`.
+
+// Currently only supports x86_64.
+#![cfg(target_arch = "x86_64")]
+
+use std::fmt::{Display, Formatter};
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use builder::{build_microvm_from_state, create_guest_memory, StartMicrovmError};
+use error_code::ErrorCategory;
+use memory_snapshot::SnapshotMemory;
+use mmds::MMDS;
+use persist::{version_map, MicrovmState, MicrovmStateError};
+use polly::event_manager::EventManager;
+use seccomp::BpfProgramRef;
+use snapshot::Snapshot;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use vm_memory::{Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
+use vmm_config::console::ConsoleConfig;
+use vmm_config::machine_config::VmConfig;
+use vmm_config::preopened_fds::PreopenedFds;
+use {DirtyBitmap, Error as VmmError, Vmm};
+
+// Sent by the destination once it adopted the microVM state.
+const ACK: &[u8] = b"A";
+const PAGE_SIZE: usize = 4096;
+// Maximum number of contiguous pages sent in a single message.
+const MAX_PAGES_PER_MESSAGE: usize = 256;
+// Maximum number of rounds copying the dirty pages while the guest runs.
+const MAX_PRECOPY_ROUNDS: usize = 30;
+// The guest is stopped once fewer pages than this were dirtied during a round.
+const STOP_AND_COPY_PAGES: usize = 1024;
+// How long to wait for the other Firecracker process when sending or receiving.
+const MIGRATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Errors associated with migrating a microVM.
+#[derive(Debug)]
+pub enum MigrationError {
+    /// Cannot accept the connection of the source Firecracker process.
+    Accept(io::Error),
+    /// Cannot bind the incoming migration socket.
+    Bind(io::Error),
+    /// Cannot build the microVM from the received state.
+    BuildMicrovm(StartMicrovmError),
+    /// Cannot connect to the destination Firecracker process.
+    Connect(io::Error),
+    /// Cannot create the guest memory on the destination.
+    CreateMemory(StartMicrovmError),
+    /// Cannot deserialize a migration message.
+    Deserialize(snapshot::Error),
+    /// Cannot retrieve the guest pages dirtied by the vCPUs.
+    DirtyBitmap(VmmError),
+    /// The microVM was not started with dirty page tracking enabled.
+    DirtyPageTrackingDisabled,
+    /// The received microVM configuration is invalid.
+    InvalidConfig(serde_json::Error),
+    /// Cannot pause the microVM.
+    Pause(VmmError),
+    /// Cannot read the guest memory.
+    ReadMemory(GuestMemoryError),
+    /// Cannot receive from the other Firecracker process.
+    Receive(io::Error),
+    /// The destination Firecracker process did not adopt the microVM.
+    Rejected,
+    /// Cannot resume the microVM after a failed migration.
+    Resume(VmmError),
+    /// Cannot save the microVM state.
+    SaveState(MicrovmStateError),
+    /// Cannot send to the other Firecracker process.
+    Send(io::Error),
+    /// Cannot serialize a migration message.
+    Serialize(snapshot::Error),
+    /// A migration message arrived out of order.
+    UnexpectedMessage,
+    /// Cannot write the received pages to the guest memory.
+    WriteMemory(GuestMemoryError),
+}
+
+impl Display for MigrationError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::MigrationError::*;
+        match self {
+            Accept(err) => write!(f, "Cannot accept the migration connection: {}", err),
+            Bind(err) => write!(f, "Cannot bind the incoming migration socket: {}", err),
+            BuildMicrovm(err) => write!(f, "Cannot build the microVM: {}", err),
+            Connect(err) => write!(f, "Cannot connect to the migration socket: {}", err),
+            CreateMemory(err) => write!(f, "Cannot create the guest memory: {}", err),
+            Deserialize(err) => write!(f, "Cannot deserialize a migration message: {:?}", err),
+            DirtyBitmap(err) => write!(f, "Cannot retrieve the dirty guest pages: {}", err),
+            DirtyPageTrackingDisabled => write!(
+                f,
+                "The microVM has to be started with `track_dirty_pages` enabled."
+            ),
+            InvalidConfig(err) => write!(f, "Invalid microVM configuration: {}", err),
+            Pause(err) => write!(f, "Cannot pause the microVM: {}", err),
+            ReadMemory(err) => write!(f, "Cannot read the guest memory: {:?}", err),
+            Receive(err) => write!(f, "Cannot receive the microVM: {}", err),
+            Rejected => write!(
+                f,
+                "The destination Firecracker process did not adopt the microVM."
+            ),
+            Resume(err) => write!(f, "Cannot resume the microVM: {}", err),
+            SaveState(err) => write!(f, "Cannot save the microVM state: {}", err),
+            Send(err) => write!(f, "Cannot send the microVM: {}", err),
+            Serialize(err) => write!(f, "Cannot serialize a migration message: {:?}", err),
+            UnexpectedMessage => write!(f, "Unexpected migration message."),
+            WriteMemory(err) => write!(f, "Cannot write the guest memory: {:?}", err),
+        }
+    }
+}
+
+impl MigrationError {
+    /// Who can fix the cause of the error.
+    pub fn category(&self) -> ErrorCategory {
+        use self::MigrationError::*;
+        match self {
+            BuildMicrovm(err) | CreateMemory(err) => err.category(),
+            SaveState(err) => err.category(),
+            DirtyPageTrackingDisabled => ErrorCategory::InvalidState,
+            Accept(_) | Bind(_) | Connect(_) | Deserialize(_) | InvalidConfig(_) | Receive(_)
+            | Rejected | Send(_) | UnexpectedMessage => ErrorCategory::External,
+            DirtyBitmap(_) | Pause(_) | ReadMemory(_) | Resume(_) | Serialize(_)
+            | WriteMemory(_) => ErrorCategory::Internal,
+        }
+    }
+
+    /// Specifies if the migration may succeed when retried, once the destination Firecracker
+    /// process is ready.
+    pub fn retriable(&self) -> bool {
+        use self::MigrationError::*;
+        match self {
+            Accept(_) | Connect(_) | Receive(_) | Rejected | Send(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+type Result<T> = std::result::Result<T, MigrationError>;
+
+/// A run of contiguous guest pages.
+#[derive(Versionize)]
+struct GuestPages {
+    /// Guest address of the first page.
+    guest_address: u64,
+    /// Contents of the pages.
+    data: Vec<u8>,
+}
+
+/// The microVM state, sent once the vCPUs are paused.
+#[derive(Versionize)]
+struct MigrationState {
+    /// The MMDS contents, serialized as JSON.
+    mmds_data: String,
+    /// The microVM state.
+    microvm_state: MicrovmState,
+}
+
+/// The messages making up the migration stream, in order: one `Config`, any number of `Pages`,
+/// one `State`.
+#[allow(clippy::large_enum_variant)]
+#[derive(Versionize)]
+enum MigrationMessage {
+    /// The microVM configuration, serialized as JSON.
+    Config(String),
+    /// Guest pages, overwriting any previously sent contents.
+    Pages(GuestPages),
+    /// The microVM state, ending the stream.
+    State(MigrationState),
+}
+
+/// Migrates `vmm` to the Firecracker process listening on `socket_path`.
+///
+/// On success, the microVM is paused and belongs to the destination process: the caller is
+/// expected to terminate without resuming it. On failure, the microVM keeps running in this
+/// process.
+pub fn send_microvm(vmm: &mut Vmm, vm_config: &VmConfig, socket_path: &Path) -> Result<()> {
+    if !vm_config.track_dirty_pages {
+        return Err(MigrationError::DirtyPageTrackingDisabled);
+    }
+    // Fail before copying the guest memory when the state can't be saved in the end.
+    vmm.check_state_saveable()
+        .map_err(MigrationError::SaveState)?;
+    let mut stream = UnixStream::connect(socket_path).map_err(MigrationError::Connect)?;
+    set_timeouts(&stream).map_err(MigrationError::Connect)?;
+    send_message(
+        &mut stream,
+        &MigrationMessage::Config(
+            serde_json::to_string(vm_config).map_err(MigrationError::InvalidConfig)?,
+        ),
+    )?;
+
+    // Only the pages dirtied from now on need to be sent again.
+    vmm.get_dirty_bitmap()
+        .map_err(MigrationError::DirtyBitmap)?;
+    let mut sent_pages = send_all_pages(&mut stream, vmm.guest_memory())?;
+    info!("Migration round 0: sent {} pages.", sent_pages);
+
+    let mut dirty_bitmap = vmm
+        .get_dirty_bitmap()
+        .map_err(MigrationError::DirtyBitmap)?;
+    let mut round = 1;
+    while dirty_page_count(&dirty_bitmap) > STOP_AND_COPY_PAGES && round < MAX_PRECOPY_ROUNDS {
+        sent_pages = send_dirty_pages(&mut stream, vmm.guest_memory(), &dirty_bitmap)?;
+        info!("Migration round {}: sent {} pages.", round, sent_pages);
+        dirty_bitmap = vmm
+            .get_dirty_bitmap()
+            .map_err(MigrationError::DirtyBitmap)?;
+        round += 1;
+    }
+
+    vmm.pause_vcpus().map_err(MigrationError::Pause)?;
+    let result = vmm
+        .pause_device_threads()
+        .map_err(MigrationError::Pause)
+        .and_then(|_| stop_and_copy(vmm, &mut stream, dirty_bitmap));
+    if let Err(e) = result {
+        // The destination did not take over, so the microVM has to keep running here.
+        vmm.resume_device_threads()
+            .map_err(MigrationError::Resume)?;
+        vmm.resume_vcpus().map_err(MigrationError::Resume)?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn set_timeouts(stream: &UnixStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(MIGRATION_TIMEOUT))?;
+    stream.set_write_timeout(Some(MIGRATION_TIMEOUT))
+}
+
+fn stop_and_copy(
+    vmm: &mut Vmm,
+    stream: &mut UnixStream,
+    mut dirty_bitmap: DirtyBitmap,
+) -> Result<()> {
+    // The pages dirtied during the last round, before the vCPUs got paused.
+    let last_bitmap = vmm
+        .get_dirty_bitmap()
+        .map_err(MigrationError::DirtyBitmap)?;
+    for (slot, bitmap) in last_bitmap.into_iter() {
+        let pending = dirty_bitmap.entry(slot).or_insert_with(Vec::new);
+        pending.resize(bitmap.len(), 0);
+        for (pending_word, word) in pending.iter_mut().zip(bitmap.iter()) {
+            *pending_word |= *word;
+        }
+    }
+    let sent_pages = send_dirty_pages(stream, vmm.guest_memory(), &dirty_bitmap)?;
+    info!("Migration stop-and-copy: sent {} pages.", sent_pages);
+
+    let state = MigrationState {
+        mmds_data: MMDS.lock().expect("Poisoned lock").get_data_str(),
+        microvm_state: vmm.save_state().map_err(MigrationError::SaveState)?,
+    };
+    send_message(stream, &MigrationMessage::State(state))?;
+
+    let mut ack = [0u8; 1];
+    match stream.read_exact(&mut ack) {
+        Ok(()) if ack == ACK => Ok(()),
+        _ => Err(MigrationError::Rejected),
+    }
+}
+
+/// Receives the microVM migrated by the Firecracker process connecting to `socket_path`.
+///
+/// This waits for the source process to connect for as long as it takes. Once the state is
+/// received and acknowledged, this waits for the source process to exit, then re-creates the
+/// microVM and resumes it. A failure past the acknowledgement loses the microVM, since the source
+/// process is gone by then.
+///
+/// Fails when the connected process stalls for longer than `MIGRATION_TIMEOUT`, including when it
+/// does not exit after the acknowledgement.
+///
+/// The VM is created from the descriptor of `/dev/kvm` in `preopened_fds`, if given.
+///
+/// Returns the microVM configuration and a running `Vmm`, which is also plugged in the
+/// `EventManager`.
+pub fn receive_microvm(
+    socket_path: &Path,
+    preopened_fds: &PreopenedFds,
+    event_manager: &mut EventManager,
+    seccomp_filter: BpfProgramRef,
+) -> Result<(VmConfig, Arc<Mutex<Vmm>>)> {
+    let listener = UnixListener::bind(socket_path).map_err(MigrationError::Bind)?;
+    let (mut stream, _) = listener.accept().map_err(MigrationError::Accept)?;
+    set_timeouts(&stream).map_err(MigrationError::Accept)?;
+    // Only one migration is expected.
+    drop(listener);
+    let _ = std::fs::remove_file(socket_path);
+
+    let vm_config: VmConfig = match receive_message(&mut stream)? {
+        MigrationMessage::Config(vm_config) => {
+            serde_json::from_str(&vm_config).map_err(MigrationError::InvalidConfig)?
+        }
+        _ => return Err(MigrationError::UnexpectedMessage),
+    };
+    let guest_memory = create_guest_memory(&vm_config).map_err(MigrationError::CreateMemory)?;
+    let state = receive_pages(&mut stream, &guest_memory)?;
+    let mmds_data =
+        serde_json::from_str(&state.mmds_data).map_err(MigrationError::InvalidConfig)?;
+
+    stream.write_all(ACK).map_err(MigrationError::Send)?;
+    // The source process closes the connection when exiting, which releases the host resources
+    // backing the devices, in case both processes share the host.
+    let mut buf = [0u8; 1];
+    while stream.read(&mut buf).map_err(MigrationError::Receive)? > 0 {}
+
+    MMDS.lock()
+        .expect("Poisoned lock")
+        .put_data(mmds_data)
+        .expect("Cannot restore the MMDS data");
+    let vmm = build_microvm_from_state(
+        state.microvm_state,
+        guest_memory,
+        vm_config.track_dirty_pages,
+        true,
+        // The microVM is received before the API server starts, so the serial console is fed
+        // from the standard input.
+        &ConsoleConfig::default(),
+        preopened_fds,
+        event_manager,
+        seccomp_filter,
+    )
+    .map_err(MigrationError::BuildMicrovm)?;
+
+    Ok((vm_config, vmm))
+}
+
+// Writes the received pages to `guest_memory` until the microVM state arrives.
+fn receive_pages<T: Read>(
+    stream: &mut T,
+    guest_memory: &GuestMemoryMmap,
+) -> Result<MigrationState> {
+    loop {
+        match receive_message(stream)? {
+            MigrationMessage::Pages(pages) => guest_memory
+                .write_slice(&pages.data, GuestAddress(pages.guest_address))
+                .map_err(MigrationError::WriteMemory)?,
+            MigrationMessage::State(state) => return Ok(state),
+            MigrationMessage::Config(_) => return Err(MigrationError::UnexpectedMessage),
+        }
+    }
+}
+
+fn send_message<T: Write>(stream: &mut T, message: &MigrationMessage) -> Result<()> {
+    // The messages are sent in the latest snapshot format, which the destination process is
+    // expected to support as well.
+    let version_map = version_map();
+    let version = version_map.latest_version();
+    Snapshot::new(version_map, version)
+        .save_with_crc64(stream, message)
+        .map_err(MigrationError::Serialize)
+}
+
+fn receive_message<T: Read>(stream: &mut T) -> Result<MigrationMessage> {
+    Snapshot::load_with_crc64(stream, version_map()).map_err(MigrationError::Deserialize)
+}
+
+fn send_pages<T: Write>(
+    stream: &mut T,
+    guest_memory: &GuestMemoryMmap,
+    guest_address: u64,
+    len: usize,
+) -> Result<()> {
+    let mut data = vec![0u8; len];
+    guest_memory
+        .read_slice(&mut data, GuestAddress(guest_address))
+        .map_err(MigrationError::ReadMemory)?;
+    send_message(
+        stream,
+        &MigrationMessage::Pages(GuestPages {
+            guest_address,
+            data,
+        }),
+    )
+}
+
+fn send_all_pages<T: Write>(stream: &mut T, guest_memory: &GuestMemoryMmap) -> Result<usize> {
+    let mut sent_pages = 0;
+    for region in guest_memory.describe().regions.iter() {
+        let mut offset = 0;
+        while offset < region.size {
+            let len = std::cmp::min(
+                (region.size - offset) as usize,
+                MAX_PAGES_PER_MESSAGE * PAGE_SIZE,
+            );
+            send_pages(stream, guest_memory, region.base_address + offset, len)?;
+            offset += len as u64;
+            sent_pages += len / PAGE_SIZE;
+        }
+    }
+    Ok(sent_pages)
+}
+
+fn send_dirty_pages<T: Write>(
+    stream: &mut T,
+    guest_memory: &GuestMemoryMmap,
+    dirty_bitmap: &DirtyBitmap,
+) -> Result<usize> {
+    let mut sent_pages = 0;
+    // KVM memory slots are numbered after the guest memory regions.
+    for (slot, region) in guest_memory.describe().regions.iter().enumerate() {
+        let bitmap = match dirty_bitmap.get(&slot) {
+            Some(bitmap) => bitmap,
+            None => continue,
+        };
+        for (first_page, count) in dirty_runs(bitmap, MAX_PAGES_PER_MESSAGE) {
+            send_pages(
+                stream,
+                guest_memory,
+                region.base_address + (first_page * PAGE_SIZE) as u64,
+                count * PAGE_SIZE,
+            )?;
+            sent_pages += count;
+        }
+    }
+    Ok(sent_pages)
+}
+
+fn dirty_page_count(dirty_bitmap: &DirtyBitmap) -> usize {
+    dirty_bitmap
+        .values()
+        .flat_map(|bitmap| bitmap.iter())
+        .map(|word| word.count_ones() as usize)
+        .sum()
+}
+
+// Returns the runs of contiguous dirty pages in `bitmap`, as (first page, page count) pairs of at
+// most `max_len` pages.
+fn dirty_runs(bitmap: &[u64], max_len: usize) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    let mut current: Option<(usize, usize)> = None;
+    for (index, word) in bitmap.iter().enumerate() {
+        for bit in 0..64 {
+            let page = index * 64 + bit;
+            if (word >> bit) & 1 == 1 {
+                current = match current {
+                    Some((first, count)) if count < max_len => Some((first, count + 1)),
+                    Some(run) => {
+                        runs.push(run);
+                        Some((page, 1))
+                    }
+                    None => Some((page, 1)),
+                };
+            } else if let Some(run) = current.take() {
+                runs.push(run);
+            }
+        }
+    }
+    if let Some(run) = current {
+        runs.push(run);
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use builder::tests::default_vmm;
+    use utils::tempfile::TempFile;
+
+    #[test]
+    fn test_dirty_runs() {
+        assert!(dirty_runs(&[0, 0], 4).is_empty());
+        assert_eq!(dirty_runs(&[0b1011], 4), vec![(0, 2), (3, 1)]);
+        assert_eq!(dirty_runs(&[0b11_1111], 4), vec![(0, 4), (4, 2)]);
+        // Runs span the bitmap words.
+        assert_eq!(dirty_runs(&[1 << 63, 1], 4), vec![(63, 2)]);
+        assert_eq!(dirty_runs(&[std::u64::MAX], 64), vec![(0, 64)]);
+    }
+
+    #[test]
+    fn test_dirty_page_count() {
+        let mut dirty_bitmap = DirtyBitmap::new();
+        assert_eq!(dirty_page_count(&dirty_bitmap), 0);
+        dirty_bitmap.insert(0, vec![0b101, 0]);
+        dirty_bitmap.insert(1, vec![std::u64::MAX]);
+        assert_eq!(dirty_page_count(&dirty_bitmap), 66);
+    }
+
+    #[test]
+    fn test_send_microvm_without_dirty_page_tracking() {
+        let mut vmm = default_vmm();
+        let tmp_path = TempFile::new().unwrap().as_path().to_path_buf();
+
+        match send_microvm(&mut vmm, &VmConfig::default(), &tmp_path) {
+            Err(MigrationError::DirtyPageTrackingDisabled) => (),
+            _ => panic!("Migration should require dirty page tracking."),
+        }
+    }
+
+    #[test]
+    fn test_transfer_pages() {
+        let ranges = [
+            (GuestAddress(0), 0x4000),
+            (
+                GuestAddress(0x10000),
+                MAX_PAGES_PER_MESSAGE * PAGE_SIZE + 0x1000,
+            ),
+        ];
+        let src_memory = GuestMemoryMmap::from_ranges(&ranges).unwrap();
+        let dst_memory = GuestMemoryMmap::from_ranges(&ranges).unwrap();
+        src_memory.write_obj(0xAAu8, GuestAddress(0x1000)).unwrap();
+        src_memory
+            .write_obj(0xBBu8, GuestAddress(0x10000 + 0x2000))
+            .unwrap();
+        let (mut sender, mut receiver) = UnixStream::pair().unwrap();
+
+        let receiver_thread = thread::spawn(move || {
+            let state = receive_pages(&mut receiver, &dst_memory);
+            (state.map(|state| state.mmds_data), dst_memory)
+        });
+
+        assert_eq!(
+            send_all_pages(&mut sender, &src_memory).unwrap(),
+            4 + MAX_PAGES_PER_MESSAGE + 1
+        );
+        // Only the dirty pages are sent again.
+        src_memory.write_obj(0xCCu8, GuestAddress(0x1000)).unwrap();
+        src_memory.write_obj(0xDDu8, GuestAddress(0x3000)).unwrap();
+        let mut dirty_bitmap = DirtyBitmap::new();
+        dirty_bitmap.insert(0, vec![0b10]);
+        assert_eq!(
+            send_dirty_pages(&mut sender, &src_memory, &dirty_bitmap).unwrap(),
+            1
+        );
+        let mut microvm_state = default_vmm().save_state().unwrap();
+        microvm_state.memory_state = src_memory.describe();
+        send_message(
+            &mut sender,
+            &MigrationMessage::State(MigrationState {
+                mmds_data: String::from("{}"),
+                microvm_state,
+            }),
+        )
+        .unwrap();
+
+        let (mmds_data, dst_memory) = receiver_thread.join().unwrap();
+        assert_eq!(mmds_data.unwrap(), "{}");
+        assert_eq!(
+            dst_memory.read_obj::<u8>(GuestAddress(0x1000)).unwrap(),
+            0xCC
+        );
+        assert_eq!(
+            dst_memory
+                .read_obj::<u8>(GuestAddress(0x10000 + 0x2000))
+                .unwrap(),
+            0xBB
+        );
+        // The page wasn't reported as dirty, so the destination has the previous contents.
+        assert_eq!(dst_memory.read_obj::<u8>(GuestAddress(0x3000)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_unexpected_message() {
+        let guest_memory = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let (mut sender, mut receiver) = UnixStream::pair().unwrap();
+        send_message(&mut sender, &MigrationMessage::Config(String::from("{}"))).unwrap();
+
+        match receive_pages(&mut receiver, &guest_memory) {
+            Err(MigrationError::UnexpectedMessage) => (),
+            _ => panic!("The configuration should only be sent first."),
+        }
+
+        // Pages outside the guest memory are rejected.
+        send_message(
+            &mut sender,
+            &MigrationMessage::Pages(GuestPages {
+                guest_address: 0x1000,
+                data: vec![0u8; PAGE_SIZE],
+            }),
+        )
+        .unwrap();
+        match receive_pages(&mut receiver, &guest_memory) {
+            Err(MigrationError::WriteMemory(_)) => (),
+            _ => panic!("The pages should not fit in the guest memory."),
+        }
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::MigrationError::*;
+
+        let err = Accept(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = Bind(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = BuildMicrovm(StartMicrovmError::MicroVMAlreadyRunning);
+        let _ = format!("{}{:?}", err, err);
+        let err = Connect(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = CreateMemory(StartMicrovmError::MissingMemSizeConfig);
+        let _ = format!("{}{:?}", err, err);
+        let err = Deserialize(snapshot::Error::Crc64(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = DirtyBitmap(VmmError::VcpuPause);
+        let _ = format!("{}{:?}", err, err);
+        let err = DirtyPageTrackingDisabled;
+        let _ = format!("{}{:?}", err, err);
+        let err = Pause(VmmError::VcpuPause);
+        let _ = format!("{}{:?}", err, err);
+        let err = ReadMemory(GuestMemoryError::InvalidBackendAddress);
+        let _ = format!("{}{:?}", err, err);
+        let err = Receive(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = Rejected;
+        let _ = format!("{}{:?}", err, err);
+        let err = Resume(VmmError::VcpuResume);
+        let _ = format!("{}{:?}", err, err);
+        let err = SaveState(MicrovmStateError::UnexpectedVcpuResponse);
+        let _ = format!("{}{:?}", err, err);
+        let err = Send(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = Serialize(snapshot::Error::Crc64(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = UnexpectedMessage;
+        let _ = format!("{}{:?}", err, err);
+        let err = WriteMemory(GuestMemoryError::InvalidBackendAddress);
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -0,0 +1,404 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Runs the long actions of the VMM in the background, e.g. writing a snapshot, so that the API
+//! keeps serving the control plane meanwhile.
+//!
+//! The operations run one at a time, on a worker thread spawned along with the vCPUs, since no
+//! thread can be spawned once the seccomp filter of the VMM thread is applied. An operation gets
+//! an ID when it starts, which the control plane polls its status with. Once the operation is
+//! done, the worker thread signals the VMM thread, which records the outcome of the operation
+//! and surfaces it as an event.
+
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use seccomp::{BpfProgram, SeccompFilter};
+use utils::eventfd::EventFd;
+use Vmm;
+
+/// Maximum number of finished operations whose status is kept. The statuses of the older ones
+/// are dropped.
+pub const MAX_FINISHED_OPERATIONS: usize = 64;
+
+/// The kinds of actions which run in the background.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationKind {
+    /// Writing a snapshot of the microVM.
+    CreateSnapshot,
+    /// Copying the backing file of a drive, which can't be reflinked.
+    SnapshotBlockDevice,
+    /// Replacing the backing file of a drive while the guest filesystems are frozen.
+    UpdateBlockDevice,
+}
+
+/// The states of an operation.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationState {
+    /// The operation is running.
+    Running,
+    /// The operation completed successfully.
+    Succeeded,
+    /// The operation failed.
+    Failed,
+}
+
+/// The status of an operation.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OperationStatus {
+    /// The ID of the operation.
+    pub operation_id: u64,
+    /// What the operation does.
+    pub kind: OperationKind,
+    /// The state of the operation.
+    pub state: OperationState,
+    /// Why the operation failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Errors associated with the operations.
+#[derive(Debug)]
+pub enum OperationError {
+    /// Another operation is running, with the given ID.
+    InProgress(u64),
+    /// Cannot apply the seccomp filter to the worker thread.
+    Seccomp(seccomp::Error),
+    /// Cannot spawn the worker thread.
+    Spawn(io::Error),
+    /// The worker thread is not running.
+    WorkerUnavailable,
+}
+
+impl Display for OperationError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::OperationError::*;
+        match self {
+            InProgress(id) => write!(f, "The operation {} is still running.", id),
+            Seccomp(err) => write!(f, "Cannot apply the seccomp filter: {}", err),
+            Spawn(err) => write!(f, "Cannot spawn the operation thread: {}", err),
+            WorkerUnavailable => write!(f, "The operation thread is not running."),
+        }
+    }
+}
+
+impl std::error::Error for OperationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OperationError::Spawn(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// The work of an operation, run on the worker thread. Returns why it failed, if it did.
+pub type Job = Box<dyn FnOnce() -> Result<(), String> + Send>;
+
+/// What the VMM thread does once the work of an operation succeeded, before the operation
+/// completes. Returns why it failed, if it did.
+pub type FollowUp = Box<dyn FnOnce(&mut Vmm) -> Result<(), String> + Send>;
+
+// The operation running, along with what is left to do on the VMM thread once it succeeds.
+struct RunningOperation {
+    status: OperationStatus,
+    follow_up: Option<FollowUp>,
+}
+
+/// Runs the operations of the VMM in the background, one at a time, and keeps their statuses.
+pub struct Operations {
+    next_id: u64,
+    running: Option<RunningOperation>,
+    // The most recent ones last.
+    finished: VecDeque<OperationStatus>,
+    // Hands the jobs to the worker thread, once it is spawned.
+    jobs: Option<Sender<Job>>,
+    results_sender: Sender<Result<(), String>>,
+    results: Receiver<Result<(), String>>,
+    // Written by the worker thread whenever a job is done.
+    completion_evt: EventFd,
+}
+
+impl Operations {
+    /// Creates the keeper of the operations. None can run until the worker thread is spawned
+    /// with `start_worker`.
+    pub fn new() -> io::Result<Self> {
+        let (results_sender, results) = channel();
+        Ok(Operations {
+            next_id: 1,
+            running: None,
+            finished: VecDeque::new(),
+            jobs: None,
+            results_sender,
+            results,
+            completion_evt: EventFd::new(libc::EFD_NONBLOCK)?,
+        })
+    }
+
+    /// Spawns the worker thread, confined by `seccomp_filter`. Returns once the filter is
+    /// applied.
+    pub fn start_worker(&mut self, seccomp_filter: BpfProgram) -> Result<(), OperationError> {
+        let (jobs, job_receiver) = channel::<Job>();
+        let results = self.results_sender.clone();
+        let completion_evt = self
+            .completion_evt
+            .try_clone()
+            .map_err(OperationError::Spawn)?;
+        let (setup_sender, setup_receiver) = channel();
+        thread::Builder::new()
+            .name("fc_operations".to_owned())
+            .spawn(move || {
+                let setup = SeccompFilter::apply(seccomp_filter).map_err(OperationError::Seccomp);
+                let failed = setup.is_err();
+                // The VMM thread waits for this message, so sending it can't fail.
+                let _ = setup_sender.send(setup);
+                if failed {
+                    return;
+                }
+                // The thread exits once the keeper of the operations is dropped.
+                for job in job_receiver {
+                    if results.send(job()).is_err() {
+                        return;
+                    }
+                    if let Err(err) = completion_evt.write(1) {
+                        error!("Failed to signal the completion of an operation: {}", err);
+                    }
+                }
+            })
+            .map_err(OperationError::Spawn)?;
+
+        setup_receiver
+            .recv()
+            .unwrap_or(Err(OperationError::WorkerUnavailable))?;
+        self.jobs = Some(jobs);
+        Ok(())
+    }
+
+    /// Starts an operation of `kind`, running `job` on the worker thread, then `follow_up` on
+    /// the VMM thread once `job` succeeds. Returns the status of the operation.
+    pub fn start(
+        &mut self,
+        kind: OperationKind,
+        job: Job,
+        follow_up: Option<FollowUp>,
+    ) -> Result<OperationStatus, OperationError> {
+        if let Some(id) = self.running_id() {
+            return Err(OperationError::InProgress(id));
+        }
+        self.jobs
+            .as_ref()
+            .ok_or(OperationError::WorkerUnavailable)?
+            .send(job)
+            .map_err(|_| OperationError::WorkerUnavailable)?;
+
+        let status = OperationStatus {
+            operation_id: self.next_id,
+            kind,
+            state: OperationState::Running,
+            error: None,
+        };
+        self.next_id += 1;
+        self.running = Some(RunningOperation {
+            status: status.clone(),
+            follow_up,
+        });
+        Ok(status)
+    }
+
+    /// Returns the ID of the operation running, if any.
+    pub fn running_id(&self) -> Option<u64> {
+        self.running
+            .as_ref()
+            .map(|running| running.status.operation_id)
+    }
+
+    /// Returns the status of the operation `id`, unless it is unknown or its status was dropped.
+    pub fn status(&self, id: u64) -> Option<OperationStatus> {
+        self.running
+            .iter()
+            .map(|running| &running.status)
+            .chain(self.finished.iter())
+            .find(|status| status.operation_id == id)
+            .cloned()
+    }
+
+    /// Returns the outcome of the work of the operation running, once the worker thread is
+    /// done with it, along with what is left to do on the VMM thread if it succeeded. The
+    /// operation keeps running until `finish` is called.
+    pub fn take_outcome(&mut self) -> Option<(Result<(), String>, Option<FollowUp>)> {
+        let _ = self.completion_evt.read();
+        let outcome = self.results.try_recv().ok()?;
+        let follow_up = self
+            .running
+            .as_mut()
+            .and_then(|running| running.follow_up.take());
+        Some((outcome, follow_up))
+    }
+
+    /// Completes the operation running with `outcome`, and returns its final status.
+    pub fn finish(&mut self, outcome: Result<(), String>) -> Option<OperationStatus> {
+        let mut status = self.running.take()?.status;
+        match outcome {
+            Ok(()) => status.state = OperationState::Succeeded,
+            Err(err) => {
+                status.state = OperationState::Failed;
+                status.error = Some(err);
+            }
+        }
+        if self.finished.len() == MAX_FINISHED_OPERATIONS {
+            self.finished.pop_front();
+        }
+        self.finished.push_back(status.clone());
+        Some(status)
+    }
+}
+
+/// The `EventFd` signaling that the worker thread is done with the work of an operation.
+impl AsRawFd for Operations {
+    fn as_raw_fd(&self) -> RawFd {
+        self.completion_evt.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::sync_channel;
+
+    // Waits for the worker thread to be done with the work of the operation running.
+    fn wait_outcome(operations: &mut Operations) -> (Result<(), String>, Option<FollowUp>) {
+        loop {
+            if let Some(outcome) = operations.take_outcome() {
+                return outcome;
+            }
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn test_operations() {
+        let mut operations = Operations::new().unwrap();
+        match operations.start(OperationKind::CreateSnapshot, Box::new(|| Ok(())), None) {
+            Err(OperationError::WorkerUnavailable) => (),
+            _ => panic!("Operations should not run without the worker thread."),
+        }
+        operations.start_worker(vec![]).unwrap();
+        assert!(operations.take_outcome().is_none());
+        assert!(operations.finish(Ok(())).is_none());
+
+        // The job waits for the test to let it go.
+        let (release, released) = sync_channel::<()>(0);
+        let status = operations
+            .start(
+                OperationKind::CreateSnapshot,
+                Box::new(move || released.recv().map_err(|err| err.to_string())),
+                Some(Box::new(|_: &mut Vmm| Ok(()))),
+            )
+            .unwrap();
+        assert_eq!(
+            status,
+            OperationStatus {
+                operation_id: 1,
+                kind: OperationKind::CreateSnapshot,
+                state: OperationState::Running,
+                error: None,
+            }
+        );
+        assert_eq!(operations.running_id(), Some(1));
+        assert_eq!(operations.status(1), Some(status));
+        match operations.start(OperationKind::CreateSnapshot, Box::new(|| Ok(())), None) {
+            Err(OperationError::InProgress(1)) => (),
+            _ => panic!("Operations should run one at a time."),
+        }
+        assert!(operations.take_outcome().is_none());
+
+        release.send(()).unwrap();
+        let (outcome, follow_up) = wait_outcome(&mut operations);
+        assert_eq!(outcome, Ok(()));
+        assert!(follow_up.is_some());
+        // The operation runs until the VMM thread is done with it too.
+        assert_eq!(operations.running_id(), Some(1));
+        assert_eq!(
+            operations.finish(outcome).unwrap().state,
+            OperationState::Succeeded
+        );
+        assert_eq!(operations.running_id(), None);
+        assert_eq!(
+            operations.status(1).unwrap().state,
+            OperationState::Succeeded
+        );
+
+        operations
+            .start(
+                OperationKind::CreateSnapshot,
+                Box::new(|| Err("no space left".to_string())),
+                None,
+            )
+            .unwrap();
+        let (outcome, follow_up) = wait_outcome(&mut operations);
+        assert!(follow_up.is_none());
+        assert_eq!(
+            operations.finish(outcome).unwrap(),
+            OperationStatus {
+                operation_id: 2,
+                kind: OperationKind::CreateSnapshot,
+                state: OperationState::Failed,
+                error: Some("no space left".to_string()),
+            }
+        );
+        assert!(operations.status(3).is_none());
+
+        // Only the statuses of the latest operations are kept.
+        for _ in 0..MAX_FINISHED_OPERATIONS - 1 {
+            operations
+                .start(OperationKind::CreateSnapshot, Box::new(|| Ok(())), None)
+                .unwrap();
+            let (outcome, _) = wait_outcome(&mut operations);
+            operations.finish(outcome).unwrap();
+        }
+        assert!(operations.status(1).is_none());
+        assert!(operations.status(2).is_some());
+    }
+
+    #[test]
+    fn test_operation_status_serialization() {
+        assert_eq!(
+            serde_json::to_string(&OperationStatus {
+                operation_id: 1,
+                kind: OperationKind::CreateSnapshot,
+                state: OperationState::Running,
+                error: None,
+            })
+            .unwrap(),
+            r#"{"operation_id":1,"kind":"create_snapshot","state":"running"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&OperationStatus {
+                operation_id: 2,
+                kind: OperationKind::CreateSnapshot,
+                state: OperationState::Failed,
+                error: Some("Cannot write the guest memory".to_string()),
+            })
+            .unwrap(),
+            r#"{"operation_id":2,"kind":"create_snapshot","state":"failed","error":"Cannot write the guest memory"}"#
+        );
+    }
+
+    #[test]
+    fn test_error_messages() {
+        assert_eq!(
+            OperationError::InProgress(3).to_string(),
+            "The operation 3 is still running."
+        );
+        assert_eq!(
+            OperationError::WorkerUnavailable.to_string(),
+            "The operation thread is not running."
+        );
+    }
+}
@@ -6,14 +6,256 @@
 // Currently only supports x86_64.
 #![cfg(target_arch = "x86_64")]
 
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use builder::{build_microvm_from_state, StartMicrovmError};
+use device_manager::mmio::Error as MmioError;
 use devices::virtio::{
-    block::persist::BlockState, net::persist::NetState, persist::MmioTransportState,
-    vsock::persist::VsockState,
+    balloon::persist::BalloonState, balloon::Error as BalloonError, block::persist::BlockState,
+    net::persist::Error as NetPersistError, net::persist::NetConfigSpaceState,
+    net::persist::NetState, persist::MmioTransportState, rng::persist::EntropyState,
+    rng::Error as EntropyError, vsock::persist::VsockState, VsockError, VsockUnixBackendError,
+};
+use error_code::ErrorCategory;
+use memory_sharing::CowMonitor;
+use memory_snapshot::{
+    self, Compression, GuestMemoryRegionState, GuestMemoryState, SnapshotMemory,
 };
+use polly::event_manager::{Error as EventManagerError, EventManager};
+use post_restore::{self, PostRestoreError};
+use resources::VmResources;
+use seccomp::BpfProgramRef;
+use snapshot::Snapshot;
+use uffd::{self, FaultHandlerOptions, UffdError};
+use vm_memory::GuestMemoryMmap;
+use vmm_config::machine_config::{LegacyDevicesConfig, VmConfig, VmConfigError};
+use vmm_config::snapshot::{
+    CreateSnapshotParams, LoadSnapshotParams, MemBackendType, MemoryCompression, SnapshotType,
+};
+use {DirtyBitmap, Error as VmmError, Vmm};
 
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
-use vstate::{VcpuState, VmState};
+use vstate::{Error as VstateError, VcpuState, VmState};
+
+/// Errors related to saving and restoring the microVM state.
+#[derive(Debug)]
+pub enum MicrovmStateError {
+    /// A vCPU refused to save its state, e.g. because it was not paused.
+    NotAllowed(String),
+    /// Cannot register a restored device on the MMIO bus.
+    RegisterMmioDevice(MmioError),
+    /// Cannot register the event handler of a restored device.
+    RegisterEvent(EventManagerError),
+    /// Cannot restore the balloon device.
+    RestoreBalloon(BalloonError),
+    /// Cannot restore a block device.
+    RestoreBlock(io::Error),
+    /// Cannot restore the entropy device.
+    RestoreEntropy(EntropyError),
+    /// Cannot restore a net device.
+    RestoreNet(NetPersistError),
+    /// Cannot restore the state of a vCPU.
+    RestoreVcpuState(VstateError),
+    /// Cannot restore the VM state.
+    RestoreVmState(VstateError),
+    /// Cannot restore the vsock device.
+    RestoreVsock(VsockError),
+    /// Cannot restore the vsock device backend.
+    RestoreVsockBackend(VsockUnixBackendError),
+    /// Cannot save the state of a vCPU.
+    SaveVcpuState(VstateError),
+    /// Cannot save the VM state.
+    SaveVmState(VstateError),
+    /// Cannot signal a vCPU.
+    SignalVcpu(VstateError),
+    /// A vCPU did not respond as expected.
+    UnexpectedVcpuResponse,
+}
+
+impl Display for MicrovmStateError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::MicrovmStateError::*;
+        match self {
+            NotAllowed(msg) => write!(f, "Operation not allowed: {}", msg),
+            RegisterMmioDevice(err) => write!(f, "Cannot register a restored device: {}", err),
+            RegisterEvent(err) => write!(f, "Cannot register a restored device event: {:?}", err),
+            RestoreBalloon(err) => write!(f, "Cannot restore the balloon device: {:?}", err),
+            RestoreBlock(err) => write!(f, "Cannot restore a block device: {}", err),
+            RestoreEntropy(err) => write!(f, "Cannot restore the entropy device: {:?}", err),
+            RestoreNet(err) => write!(f, "Cannot restore a net device: {:?}", err),
+            RestoreVcpuState(err) => write!(f, "Cannot restore vcpu state: {}", err),
+            RestoreVmState(err) => write!(f, "Cannot restore vm state: {}", err),
+            RestoreVsock(err) => write!(f, "Cannot restore the vsock device: {:?}", err),
+            RestoreVsockBackend(err) => {
+                write!(f, "Cannot restore the vsock device backend: {:?}", err)
+            }
+            SaveVcpuState(err) => write!(f, "Cannot save vcpu state: {}", err),
+            SaveVmState(err) => write!(f, "Cannot save vm state: {}", err),
+            SignalVcpu(err) => write!(f, "Cannot signal vcpu: {}", err),
+            UnexpectedVcpuResponse => write!(f, "Unexpected vcpu response."),
+        }
+    }
+}
+
+impl MicrovmStateError {
+    /// Who can fix the cause of the error.
+    pub fn category(&self) -> ErrorCategory {
+        use self::MicrovmStateError::*;
+        match self {
+            NotAllowed(_) => ErrorCategory::InvalidState,
+            // The backing files and the host devices of the restored devices are opened anew.
+            RestoreBlock(_) | RestoreNet(_) | RestoreVsockBackend(_) => ErrorCategory::External,
+            _ => ErrorCategory::Internal,
+        }
+    }
+}
+
+/// Errors associated with creating a snapshot.
+#[derive(Debug)]
+pub enum CreateSnapshotError {
+    /// Diff snapshots do not support compression.
+    CompressedDiffSnapshot,
+    /// Cannot pause or resume the devices emulated on threads of their own.
+    DeviceThreads(VmmError),
+    /// Cannot retrieve the guest pages dirtied since the previous snapshot.
+    DirtyBitmap(VmmError),
+    /// Diff snapshots require the microVM to track the dirty pages.
+    DirtyPageTrackingDisabled,
+    /// The requested snapshot version is not supported, or predates the requested features.
+    InvalidVersion(u16),
+    /// Cannot write the guest memory.
+    Memory(memory_snapshot::Error),
+    /// Cannot open or size the guest memory file.
+    MemoryBackingFile(io::Error),
+    /// Cannot save the microVM state.
+    MicrovmState(MicrovmStateError),
+    /// Cannot serialize the microVM state.
+    SerializeMicrovmState(snapshot::Error),
+    /// Cannot open the microVM state file.
+    SnapshotBackingFile(io::Error),
+}
+
+impl Display for CreateSnapshotError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::CreateSnapshotError::*;
+        match self {
+            CompressedDiffSnapshot => write!(f, "Diff snapshots cannot be compressed."),
+            DeviceThreads(err) => write!(f, "Cannot pause or resume the devices: {}", err),
+            DirtyBitmap(err) => write!(f, "Cannot get the dirty page bitmap: {}", err),
+            DirtyPageTrackingDisabled => write!(
+                f,
+                "Diff snapshots require the microVM to track the dirty pages."
+            ),
+            InvalidVersion(version) => write!(f, "Invalid snapshot version: {}", version),
+            Memory(err) => write!(f, "Cannot write the guest memory: {}", err),
+            MemoryBackingFile(err) => write!(f, "Cannot open the guest memory file: {}", err),
+            MicrovmState(err) => write!(f, "Cannot save the microVM state: {}", err),
+            SerializeMicrovmState(err) => {
+                write!(f, "Cannot serialize the microVM state: {:?}", err)
+            }
+            SnapshotBackingFile(err) => write!(f, "Cannot open the snapshot file: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CreateSnapshotError {}
+
+/// Errors associated with loading a snapshot.
+#[derive(Debug)]
+pub enum LoadSnapshotError {
+    /// Cannot build the microVM from the loaded state.
+    BuildMicrovm(StartMicrovmError),
+    /// Compressed guest memory can only be copied into the guest memory.
+    CompressedMemory,
+    /// Cannot monitor the guest pages copied on write.
+    CowMonitor(io::Error),
+    /// Cannot load the guest memory.
+    DeserializeMemory(memory_snapshot::Error),
+    /// Cannot deserialize the microVM state.
+    DeserializeMicrovmState(snapshot::Error),
+    /// Cannot open the guest memory file.
+    MemoryBackingFile(io::Error),
+    /// Only the page faults served by this process can be profiled.
+    PageFaultProfiling,
+    /// Only the guest pages served by this process can be prefetched.
+    PagePrefetch,
+    /// A page server can only serve the guest pages with the `Uffd` memory backend.
+    PageServer,
+    /// A post-restore hook failed.
+    PostRestore(PostRestoreError),
+    /// Cannot register the monitor of the guest pages copied on write.
+    RegisterCowMonitor(EventManagerError),
+    /// Cannot open the microVM state file.
+    SnapshotBackingFile(io::Error),
+    /// Cannot restore the guest memory through userfaultfd.
+    Uffd(UffdError),
+    /// The loaded machine configuration is invalid.
+    VmConfig(VmConfigError),
+}
+
+impl Display for LoadSnapshotError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::LoadSnapshotError::*;
+        match self {
+            BuildMicrovm(err) => write!(f, "Cannot build the microVM: {}", err),
+            CompressedMemory => write!(
+                f,
+                "Compressed guest memory is only supported by the File memory backend."
+            ),
+            CowMonitor(err) => write!(f, "Cannot monitor the copied guest pages: {}", err),
+            DeserializeMemory(err) => write!(f, "Cannot load the guest memory: {}", err),
+            DeserializeMicrovmState(err) => {
+                write!(f, "Cannot deserialize the microVM state: {:?}", err)
+            }
+            MemoryBackingFile(err) => write!(f, "Cannot open the guest memory file: {}", err),
+            PageFaultProfiling => write!(
+                f,
+                "The page faults can only be profiled with the Uffd memory backend, without a \
+                 page server."
+            ),
+            PagePrefetch => write!(
+                f,
+                "The guest pages can only be prefetched with the Uffd memory backend, without a \
+                 page server."
+            ),
+            PageServer => write!(
+                f,
+                "A page server can only serve the guest pages with the Uffd memory backend."
+            ),
+            PostRestore(err) => write!(f, "Post-restore hook failed: {}", err),
+            RegisterCowMonitor(err) => {
+                write!(
+                    f,
+                    "Cannot register the copied guest pages monitor: {:?}",
+                    err
+                )
+            }
+            SnapshotBackingFile(err) => write!(f, "Cannot open the snapshot file: {}", err),
+            Uffd(err) => write!(f, "Cannot restore the guest memory: {}", err),
+            VmConfig(err) => write!(f, "Invalid machine configuration: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for LoadSnapshotError {}
+
+impl LoadSnapshotError {
+    /// Specifies if the host could not provide the guest memory.
+    pub fn is_out_of_guest_memory(&self) -> bool {
+        match self {
+            LoadSnapshotError::BuildMicrovm(err) => err.is_out_of_guest_memory(),
+            LoadSnapshotError::DeserializeMemory(memory_snapshot::Error::CreateMemory(_))
+            | LoadSnapshotError::DeserializeMemory(memory_snapshot::Error::CreateMemoryFile(_)) => {
+                true
+            }
+            _ => false,
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, Versionize)]
 /// Holds information related to how a device is registered in the mmio space.
@@ -59,6 +301,28 @@ pub struct ConnectedVsockState {
     pub vmm_resources: VmmResourcesState,
 }
 
+#[derive(Versionize)]
+/// Holds the state of a balloon device connected to the MMIO space.
+pub struct ConnectedBalloonState {
+    /// Device state.
+    pub device_state: BalloonState,
+    /// Mmio transport state.
+    pub transport_state: MmioTransportState,
+    /// VmmResources.
+    pub vmm_resources: VmmResourcesState,
+}
+
+#[derive(Versionize)]
+/// Holds the state of an entropy device connected to the MMIO space.
+pub struct ConnectedEntropyState {
+    /// Device state.
+    pub device_state: EntropyState,
+    /// Mmio transport state.
+    pub transport_state: MmioTransportState,
+    /// VmmResources.
+    pub vmm_resources: VmmResourcesState,
+}
+
 #[derive(Versionize)]
 /// Holds the device states.
 pub struct DeviceStates {
@@ -68,6 +332,24 @@ pub struct DeviceStates {
     pub net_devices: Vec<ConnectedNetState>,
     /// Vsock device tests.
     pub vsock_device: Option<ConnectedVsockState>,
+    /// Balloon device state.
+    pub balloon_device: Option<ConnectedBalloonState>,
+    /// Entropy device state.
+    #[version(start = 2, default_fn = "default_entropy_device")]
+    pub entropy_device: Option<ConnectedEntropyState>,
+    /// States of the vsock devices besides `vsock_device`.
+    #[version(start = 3, default_fn = "default_extra_vsock_devices")]
+    pub extra_vsock_devices: Vec<ConnectedVsockState>,
+}
+
+impl DeviceStates {
+    fn default_entropy_device(_: u16) -> Option<ConnectedEntropyState> {
+        None
+    }
+
+    fn default_extra_vsock_devices(_: u16) -> Vec<ConnectedVsockState> {
+        Vec::new()
+    }
 }
 
 /// Holds information related to the VM that is not part of VmState.
@@ -75,6 +357,72 @@ pub struct DeviceStates {
 pub struct VmInfo {
     /// Guest memory size.
     pub mem_size_mib: u64,
+    /// Whether hyperthreading is enabled.
+    #[version(start = 2, default_fn = "default_ht_enabled")]
+    pub ht_enabled: bool,
+    /// Number of cores per socket, when configured.
+    #[version(start = 2, default_fn = "default_cores_per_socket")]
+    pub cores_per_socket: Option<u8>,
+    /// Frequency of the guest TSC in kHz, when configured.
+    #[version(start = 3, default_fn = "default_tsc_khz")]
+    pub tsc_khz: Option<u32>,
+    /// Whether the serial ports are emulated.
+    #[version(start = 4, default_fn = "default_legacy_device")]
+    pub legacy_serial: bool,
+    /// Whether the i8042 is emulated.
+    #[version(start = 4, default_fn = "default_legacy_device")]
+    pub legacy_i8042: bool,
+    /// Whether the RTC is emulated.
+    #[version(start = 4, default_fn = "default_legacy_device")]
+    pub legacy_rtc: bool,
+    /// Whether the PIT is emulated (x86_64 only).
+    #[version(start = 5, default_fn = "default_pit")]
+    pub pit: bool,
+    /// Offset of the RTC from the host real time clock, in seconds (x86_64 only).
+    #[version(start = 6, default_fn = "default_rtc_time_offset")]
+    pub rtc_time_offset: i64,
+}
+
+impl VmInfo {
+    fn default_ht_enabled(_: u16) -> bool {
+        false
+    }
+
+    fn default_cores_per_socket(_: u16) -> Option<u8> {
+        None
+    }
+
+    fn default_tsc_khz(_: u16) -> Option<u32> {
+        None
+    }
+
+    fn default_legacy_device(_: u16) -> bool {
+        true
+    }
+
+    fn default_pit(_: u16) -> bool {
+        true
+    }
+
+    fn default_rtc_time_offset(_: u16) -> i64 {
+        0
+    }
+
+    /// Returns the legacy devices of the microVM.
+    pub fn legacy_devices(&self) -> LegacyDevicesConfig {
+        LegacyDevicesConfig {
+            serial: self.legacy_serial,
+            i8042: self.legacy_i8042,
+            rtc: self.legacy_rtc,
+        }
+    }
+
+    /// Records the legacy devices of the microVM.
+    pub fn set_legacy_devices(&mut self, legacy_devices: LegacyDevicesConfig) {
+        self.legacy_serial = legacy_devices.serial;
+        self.legacy_i8042 = legacy_devices.i8042;
+        self.legacy_rtc = legacy_devices.rtc;
+    }
 }
 
 /// Contains the necesary state for saving/restoring a microVM.
@@ -82,6 +430,8 @@ pub struct VmInfo {
 pub struct MicrovmState {
     /// Miscellaneous VM info.
     pub vm_info: VmInfo,
+    /// Memory state.
+    pub memory_state: GuestMemoryState,
     /// VM KVM state.
     pub vm_state: VmState,
     /// Vcpu states.
@@ -90,19 +440,486 @@ pub struct MicrovmState {
     pub device_states: DeviceStates,
 }
 
+/// Returns the version map of the snapshot format.
+///
+/// Version 2 adds the compression and the checksums of the guest memory, version 3 adds the
+/// entropy device, version 4 adds the `O_NOATIME` flag of the block devices, version 5 adds
+/// the CPU topology, version 6 adds the TSC frequency, version 7 adds the I/O weights of the
+/// block devices, version 8 adds the vsock devices besides the first one, version 9 adds the
+/// link state of the network interfaces, version 10 adds their MTU, version 11 adds their
+/// receive filter, version 12 adds the selection of legacy devices, version 13 adds the
+/// omission of the PIT, version 14 adds the serial numbers and the physical block sizes of the
+/// block devices, version 15 adds their ENOSPC policy, version 16 adds their page cache
+/// hints and version 17 adds the time offset of the RTC.
+pub fn version_map() -> VersionMap {
+    let mut version_map = VersionMap::new();
+    version_map
+        .new_version()
+        .set_type_version(GuestMemoryState::type_id(), 2)
+        .set_type_version(GuestMemoryRegionState::type_id(), 2);
+    version_map
+        .new_version()
+        .set_type_version(DeviceStates::type_id(), 2);
+    version_map
+        .new_version()
+        .set_type_version(BlockState::type_id(), 2);
+    version_map
+        .new_version()
+        .set_type_version(VmInfo::type_id(), 2);
+    version_map
+        .new_version()
+        .set_type_version(VmInfo::type_id(), 3)
+        .set_type_version(VcpuState::type_id(), 2);
+    version_map
+        .new_version()
+        .set_type_version(BlockState::type_id(), 3);
+    version_map
+        .new_version()
+        .set_type_version(DeviceStates::type_id(), 3);
+    version_map
+        .new_version()
+        .set_type_version(NetConfigSpaceState::type_id(), 2);
+    version_map
+        .new_version()
+        .set_type_version(NetConfigSpaceState::type_id(), 3);
+    version_map
+        .new_version()
+        .set_type_version(NetState::type_id(), 2);
+    version_map
+        .new_version()
+        .set_type_version(VmInfo::type_id(), 4);
+    version_map
+        .new_version()
+        .set_type_version(VmInfo::type_id(), 5);
+    version_map
+        .new_version()
+        .set_type_version(BlockState::type_id(), 4);
+    version_map
+        .new_version()
+        .set_type_version(BlockState::type_id(), 5);
+    version_map
+        .new_version()
+        .set_type_version(BlockState::type_id(), 6);
+    version_map
+        .new_version()
+        .set_type_version(VmInfo::type_id(), 6);
+    version_map
+}
+
+/// Creates a snapshot of the paused microVM, as described by `params`.
+///
+/// A full snapshot saves all the guest memory, while a diff snapshot only saves the guest pages
+/// dirtied since the previous snapshot, in a sparse file. Diff snapshots require
+/// `track_dirty_pages`. Compression and checksums require snapshot version 2 or newer,
+/// microVMs with an entropy device require version 3 or newer, microVMs with
+/// `cores_per_socket` configured require version 5 or newer, microVMs with `tsc_khz`
+/// configured require version 6 or newer, microVMs with several vsock devices require version
+/// 8 or newer, tickless microVMs require version 13 or newer and microVMs with block devices
+/// reporting a physical block size require version 14 or newer. The time the RTC was set to,
+/// through the API or by the guest, is kept from version 17 on, and is lost, with a warning, in
+/// older versions.
+pub fn create_snapshot(
+    vmm: &mut Vmm,
+    vm_config: &VmConfig,
+    params: &CreateSnapshotParams,
+) -> std::result::Result<(), CreateSnapshotError> {
+    let snapshot = prepare_snapshot(vmm, vm_config, params)?;
+    let resets_dirty_bitmap = snapshot.resets_dirty_bitmap();
+    let result = snapshot.write().and_then(|_| {
+        if resets_dirty_bitmap {
+            reset_dirty_bitmap(vmm)?;
+        }
+        Ok(())
+    });
+    vmm.resume_device_threads()
+        .map_err(CreateSnapshotError::DeviceThreads)?;
+    result
+}
+
+/// A snapshot of the paused microVM whose state is saved, and whose files are left to write.
+///
+/// The guest memory is written as it is then, so the microVM must stay paused, and its devices
+/// must leave the guest memory alone, until the snapshot is written.
+pub struct PendingSnapshot {
+    microvm_state: MicrovmState,
+    guest_memory: GuestMemoryMmap,
+    // The guest pages to write, for a diff snapshot.
+    dirty_bitmap: Option<DirtyBitmap>,
+    version: u16,
+    compression: Compression,
+    checksums: bool,
+    mem_file: File,
+    snapshot_file: File,
+    resets_dirty_bitmap: bool,
+}
+
+impl PendingSnapshot {
+    /// Whether the guest pages dirtied are to be reset with `reset_dirty_bitmap` once the
+    /// snapshot is written, so that the next diff snapshot only needs the pages dirtied from
+    /// then on.
+    pub fn resets_dirty_bitmap(&self) -> bool {
+        self.resets_dirty_bitmap
+    }
+
+    /// Writes the guest memory file, then the microVM state file.
+    pub fn write(mut self) -> std::result::Result<(), CreateSnapshotError> {
+        // The layout of the memory file is only known after writing it.
+        self.microvm_state.memory_state = match self.dirty_bitmap {
+            Some(ref dirty_bitmap) => {
+                // The clean pages are left as holes in the file.
+                let memory_state = self.guest_memory.describe();
+                let mem_size: u64 = memory_state.regions.iter().map(|region| region.size).sum();
+                self.mem_file
+                    .set_len(mem_size)
+                    .map_err(CreateSnapshotError::MemoryBackingFile)?;
+                self.guest_memory
+                    .dump_dirty(&mut self.mem_file, dirty_bitmap)
+                    .map_err(CreateSnapshotError::Memory)?;
+                memory_state
+            }
+            None => self
+                .guest_memory
+                .dump(&mut self.mem_file, self.compression)
+                .map_err(CreateSnapshotError::Memory)?,
+        };
+        if self.checksums {
+            self.guest_memory
+                .compute_checksums(&mut self.microvm_state.memory_state)
+                .map_err(CreateSnapshotError::Memory)?;
+        }
+
+        Snapshot::new(version_map(), self.version)
+            .save_with_crc64(&mut self.snapshot_file, &self.microvm_state)
+            .map_err(CreateSnapshotError::SerializeMicrovmState)
+    }
+}
+
+/// Saves the state of the paused microVM and opens the files of the snapshot described by
+/// `params`, which `create_snapshot` then writes. The snapshot can thus be written elsewhere
+/// than on the VMM thread.
+///
+/// The devices emulated on threads of their own are paused along with the snapshot, and are to
+/// be resumed with `Vmm::resume_device_threads` once it is written.
+pub fn prepare_snapshot(
+    vmm: &mut Vmm,
+    vm_config: &VmConfig,
+    params: &CreateSnapshotParams,
+) -> std::result::Result<PendingSnapshot, CreateSnapshotError> {
+    vmm.pause_device_threads()
+        .map_err(CreateSnapshotError::DeviceThreads)?;
+    let snapshot = pending_snapshot(vmm, vm_config, params);
+    if snapshot.is_err() {
+        if let Err(err) = vmm.resume_device_threads() {
+            error!("{}", err);
+        }
+    }
+    snapshot
+}
+
+fn pending_snapshot(
+    vmm: &mut Vmm,
+    vm_config: &VmConfig,
+    params: &CreateSnapshotParams,
+) -> std::result::Result<PendingSnapshot, CreateSnapshotError> {
+    let track_dirty_pages = vm_config.track_dirty_pages;
+    let version_map = version_map();
+    let version = params
+        .version
+        .unwrap_or_else(|| version_map.latest_version());
+    if version == 0 || version > version_map.latest_version() {
+        return Err(CreateSnapshotError::InvalidVersion(version));
+    }
+    let compression = match params.compression {
+        MemoryCompression::None => Compression::None,
+        MemoryCompression::Lz4 => Compression::Lz4,
+        MemoryCompression::Zstd => Compression::Zstd,
+    };
+    if (compression != Compression::None || params.checksums) && version < 2 {
+        return Err(CreateSnapshotError::InvalidVersion(version));
+    }
+    if params.snapshot_type == SnapshotType::Diff {
+        if !track_dirty_pages {
+            return Err(CreateSnapshotError::DirtyPageTrackingDisabled);
+        }
+        if compression != Compression::None {
+            return Err(CreateSnapshotError::CompressedDiffSnapshot);
+        }
+    }
+
+    // Saving the vCPU states fails unless the microVM is paused, so do it first.
+    let mut microvm_state = vmm
+        .save_state()
+        .map_err(CreateSnapshotError::MicrovmState)?;
+    if microvm_state.device_states.entropy_device.is_some() && version < 3 {
+        return Err(CreateSnapshotError::InvalidVersion(version));
+    }
+    if vm_config.cores_per_socket.is_some() && version < 5 {
+        return Err(CreateSnapshotError::InvalidVersion(version));
+    }
+    if vm_config.tsc_khz.is_some() && version < 6 {
+        return Err(CreateSnapshotError::InvalidVersion(version));
+    }
+    if !microvm_state.device_states.extra_vsock_devices.is_empty() && version < 8 {
+        return Err(CreateSnapshotError::InvalidVersion(version));
+    }
+    if vm_config.legacy_devices != LegacyDevicesConfig::default() && version < 12 {
+        return Err(CreateSnapshotError::InvalidVersion(version));
+    }
+    if vm_config.tickless && version < 13 {
+        return Err(CreateSnapshotError::InvalidVersion(version));
+    }
+    let reports_topology = microvm_state
+        .device_states
+        .block_devices
+        .iter()
+        .any(|block| block.device_state.physical_block_size().is_some());
+    if reports_topology && version < 14 {
+        return Err(CreateSnapshotError::InvalidVersion(version));
+    }
+    // The serial numbers default to the drive IDs, so refusing them would refuse every drive.
+    let has_serial = microvm_state
+        .device_states
+        .block_devices
+        .iter()
+        .any(|block| block.device_state.serial().is_some());
+    if has_serial && version < 14 {
+        warn!(
+            "Snapshot version {} does not keep the serial numbers of the drives, which the \
+             restored drives will derive from their backing files.",
+            version
+        );
+    }
+    microvm_state.vm_info.ht_enabled = vm_config.ht_enabled.unwrap_or(false);
+    microvm_state.vm_info.cores_per_socket = vm_config.cores_per_socket;
+    microvm_state.vm_info.tsc_khz = vm_config.tsc_khz;
+    microvm_state
+        .vm_info
+        .set_legacy_devices(vm_config.legacy_devices);
+    microvm_state.vm_info.pit = !vm_config.tickless;
+    if microvm_state.vm_info.rtc_time_offset != 0 && version < 17 {
+        warn!(
+            "Snapshot version {} does not keep the time of the RTC, which will follow the host \
+             clock once restored.",
+            version
+        );
+    }
+    // The files are opened before the dirty pages are retrieved, which would otherwise be lost
+    // if the files can't be opened.
+    let mem_file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&params.mem_file_path)
+        .map_err(CreateSnapshotError::MemoryBackingFile)?;
+    let snapshot_file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&params.snapshot_path)
+        .map_err(CreateSnapshotError::SnapshotBackingFile)?;
+    let dirty_bitmap = match params.snapshot_type {
+        SnapshotType::Diff => Some(
+            vmm.get_dirty_bitmap()
+                .map_err(CreateSnapshotError::DirtyBitmap)?,
+        ),
+        SnapshotType::Full => None,
+    };
+
+    Ok(PendingSnapshot {
+        microvm_state,
+        guest_memory: vmm.guest_memory().clone(),
+        dirty_bitmap,
+        version,
+        compression,
+        checksums: params.checksums,
+        mem_file,
+        snapshot_file,
+        resets_dirty_bitmap: params.snapshot_type == SnapshotType::Full && track_dirty_pages,
+    })
+}
+
+/// Resets the guest pages dirtied, once a full snapshot is written.
+pub fn reset_dirty_bitmap(vmm: &Vmm) -> std::result::Result<(), CreateSnapshotError> {
+    vmm.get_dirty_bitmap()
+        .map(|_| ())
+        .map_err(CreateSnapshotError::DirtyBitmap)
+}
+
+/// Loads the microVM snapshot described by `params`, leaving the vCPUs paused.
+///
+/// With the `PrivateMapping` memory backend, the guest memory file is mapped privately and the
+/// guest pages copied on write are counted in the metrics.
+///
+/// With the `Uffd` memory backend, the guest pages are served on first access, either by a
+/// thread of this process running under `seccomp_filter` or by the page server listening on
+/// `params.uffd_socket_path`. The checksums of the guest memory are not validated in that case.
+/// The page faults served by this process are profiled if `params.profile_page_faults` is set,
+/// and the guest pages first faulted in are recorded to `params.record_manifest_path`. The pages
+/// listed by `params.prefetch_manifest_path` are prefetched ahead of the guest accessing them.
+///
+/// The hooks requested by `params.post_restore` run on the loaded microVM before returning.
+///
+/// The machine configuration in `vm_resources` is updated to match the loaded microVM.
+pub fn load_snapshot(
+    vm_resources: &mut VmResources,
+    event_manager: &mut EventManager,
+    seccomp_filter: BpfProgramRef,
+    params: &LoadSnapshotParams,
+) -> std::result::Result<Arc<Mutex<Vmm>>, LoadSnapshotError> {
+    if params.uffd_socket_path.is_some() && params.mem_backend != MemBackendType::Uffd {
+        return Err(LoadSnapshotError::PageServer);
+    }
+    let in_process_uffd =
+        params.mem_backend == MemBackendType::Uffd && params.uffd_socket_path.is_none();
+    if (params.profile_page_faults || params.record_manifest_path.is_some()) && !in_process_uffd {
+        return Err(LoadSnapshotError::PageFaultProfiling);
+    }
+    if params.prefetch_manifest_path.is_some() && !in_process_uffd {
+        return Err(LoadSnapshotError::PagePrefetch);
+    }
+
+    let mut snapshot_file =
+        File::open(&params.snapshot_path).map_err(LoadSnapshotError::SnapshotBackingFile)?;
+    let microvm_state: MicrovmState = Snapshot::load_with_crc64(&mut snapshot_file, version_map())
+        .map_err(LoadSnapshotError::DeserializeMicrovmState)?;
+
+    let guest_memory = match params.mem_backend {
+        MemBackendType::File => {
+            let mut mem_file =
+                File::open(&params.mem_file_path).map_err(LoadSnapshotError::MemoryBackingFile)?;
+            GuestMemoryMmap::load(&mut mem_file, &microvm_state.memory_state)
+                .map_err(LoadSnapshotError::DeserializeMemory)?
+        }
+        MemBackendType::PrivateMapping => {
+            map_memory_privately(&microvm_state.memory_state, params, event_manager)?
+        }
+        MemBackendType::Uffd => {
+            load_memory_on_demand(&microvm_state.memory_state, params, seccomp_filter)?
+        }
+    };
+
+    vm_resources
+        .set_vm_config(&VmConfig {
+            vcpu_count: Some(microvm_state.vcpu_states.len() as u8),
+            mem_size_mib: Some(microvm_state.vm_info.mem_size_mib as usize),
+            ht_enabled: Some(microvm_state.vm_info.ht_enabled),
+            cores_per_socket: microvm_state.vm_info.cores_per_socket,
+            tsc_khz: microvm_state.vm_info.tsc_khz,
+            track_dirty_pages: params.enable_diff_snapshots,
+            legacy_devices: microvm_state.vm_info.legacy_devices(),
+            tickless: !microvm_state.vm_info.pit,
+            ..Default::default()
+        })
+        .map_err(LoadSnapshotError::VmConfig)?;
+
+    let vmm = build_microvm_from_state(
+        microvm_state,
+        guest_memory,
+        params.enable_diff_snapshots,
+        false,
+        vm_resources.console_config(),
+        vm_resources.preopened_fds(),
+        event_manager,
+        seccomp_filter,
+    )
+    .map_err(LoadSnapshotError::BuildMicrovm)?;
+    post_restore::run_hooks(
+        &mut vmm.lock().expect("Poisoned lock"),
+        &post_restore::hooks(&params.post_restore),
+    )
+    .map_err(LoadSnapshotError::PostRestore)?;
+
+    Ok(vmm)
+}
+
+fn map_memory_privately(
+    memory_state: &GuestMemoryState,
+    params: &LoadSnapshotParams,
+    event_manager: &mut EventManager,
+) -> std::result::Result<GuestMemoryMmap, LoadSnapshotError> {
+    if memory_state.compression != Compression::None {
+        return Err(LoadSnapshotError::CompressedMemory);
+    }
+
+    let mem_file =
+        File::open(&params.mem_file_path).map_err(LoadSnapshotError::MemoryBackingFile)?;
+    let guest_memory = GuestMemoryMmap::restore_private(&mem_file, memory_state)
+        .map_err(LoadSnapshotError::DeserializeMemory)?;
+    let cow_monitor = CowMonitor::new(&guest_memory).map_err(LoadSnapshotError::CowMonitor)?;
+    event_manager
+        .add_subscriber(Arc::new(Mutex::new(cow_monitor)))
+        .map_err(LoadSnapshotError::RegisterCowMonitor)?;
+
+    Ok(guest_memory)
+}
+
+fn load_memory_on_demand(
+    memory_state: &GuestMemoryState,
+    params: &LoadSnapshotParams,
+    seccomp_filter: BpfProgramRef,
+) -> std::result::Result<GuestMemoryMmap, LoadSnapshotError> {
+    if memory_state.compression != Compression::None {
+        return Err(LoadSnapshotError::CompressedMemory);
+    }
+
+    let (guest_memory, fault_fd, mappings) =
+        uffd::create_guest_memory(memory_state).map_err(LoadSnapshotError::Uffd)?;
+    match params.uffd_socket_path.as_ref() {
+        // The page server is expected to serve the pages from its own copy of the memory file.
+        Some(socket_path) => uffd::send_to_page_server(&fault_fd, &mappings, socket_path),
+        None => {
+            let mem_file =
+                File::open(&params.mem_file_path).map_err(LoadSnapshotError::MemoryBackingFile)?;
+            let record_manifest = params
+                .record_manifest_path
+                .as_ref()
+                .map(File::create)
+                .transpose()
+                .map_err(|e| LoadSnapshotError::Uffd(UffdError::CreateManifest(e)))?;
+            let prefetch_pages = params
+                .prefetch_manifest_path
+                .as_ref()
+                .map(|path| uffd::read_manifest(path, &mappings))
+                .transpose()
+                .map_err(LoadSnapshotError::Uffd)?
+                .unwrap_or_default();
+            let options = FaultHandlerOptions {
+                profile: params.profile_page_faults,
+                record_manifest,
+                prefetch_pages,
+            };
+            uffd::spawn_fault_handler(
+                fault_fd,
+                mem_file,
+                mappings,
+                options,
+                seccomp_filter.to_vec(),
+            )
+        }
+    }
+    .map_err(LoadSnapshotError::Uffd)?;
+
+    Ok(guest_memory)
+}
+
 #[cfg(test)]
 mod tests {
+    use std::convert::TryFrom;
+    use std::path::PathBuf;
+
     use super::*;
     use crate::builder::tests::{
-        default_vmm, insert_block_devices, insert_net_device, insert_vsock_device,
-        CustomBlockConfig,
+        default_vmm, insert_balloon_device, insert_block_devices, insert_entropy_device,
+        insert_net_device, insert_vsock_device, CustomBlockConfig,
     };
     use crate::vstate::tests::default_vcpu_state;
     use crate::Vmm;
     use polly::event_manager::EventManager;
     use utils::tempfile::TempFile;
-    use vmm_config::net::NetworkInterfaceConfig;
+    use vmm_config::balloon::BalloonDeviceConfig;
+    use vmm_config::net::{NetBackendType, NetworkInterfaceConfig};
     use vmm_config::vsock::tests::{default_config, TempSockFile};
+    use vmm_config::Identifier;
 
     impl PartialEq for ConnectedBlockState {
         fn eq(&self, other: &ConnectedBlockState) -> bool {
@@ -158,11 +975,50 @@ mod tests {
         }
     }
 
+    impl PartialEq for ConnectedBalloonState {
+        fn eq(&self, other: &ConnectedBalloonState) -> bool {
+            // Actual device state equality is checked by the device's tests.
+            self.transport_state == other.transport_state
+                && self.vmm_resources == other.vmm_resources
+        }
+    }
+
+    impl std::fmt::Debug for ConnectedBalloonState {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(
+                f,
+                "ConnectedBalloonDevice {{ transport_state: {:?}, vmm_resources: {:?} }}",
+                self.transport_state, self.vmm_resources
+            )
+        }
+    }
+
+    impl PartialEq for ConnectedEntropyState {
+        fn eq(&self, other: &ConnectedEntropyState) -> bool {
+            // Actual device state equality is checked by the device's tests.
+            self.transport_state == other.transport_state
+                && self.vmm_resources == other.vmm_resources
+        }
+    }
+
+    impl std::fmt::Debug for ConnectedEntropyState {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(
+                f,
+                "ConnectedEntropyDevice {{ transport_state: {:?}, vmm_resources: {:?} }}",
+                self.transport_state, self.vmm_resources
+            )
+        }
+    }
+
     impl PartialEq for DeviceStates {
         fn eq(&self, other: &DeviceStates) -> bool {
             self.block_devices == other.block_devices
                 && self.net_devices == other.net_devices
                 && self.vsock_device == other.vsock_device
+                && self.balloon_device == other.balloon_device
+                && self.entropy_device == other.entropy_device
+                && self.extra_vsock_devices == other.extra_vsock_devices
         }
     }
 
@@ -170,8 +1026,14 @@ mod tests {
         fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
             write!(
                 f,
-                "DevicesStates {{ block_devices: {:?}, net_devices: {:?}, vsock_device: {:?} }}",
-                self.block_devices, self.net_devices, self.vsock_device
+                "DevicesStates {{ block_devices: {:?}, net_devices: {:?}, vsock_device: {:?}, \
+                 balloon_device: {:?}, entropy_device: {:?}, extra_vsock_devices: {:?} }}",
+                self.block_devices,
+                self.net_devices,
+                self.vsock_device,
+                self.balloon_device,
+                self.entropy_device,
+                self.extra_vsock_devices
             )
         }
     }
@@ -186,12 +1048,19 @@ mod tests {
 
         // Add net device.
         let network_interface = NetworkInterfaceConfig {
-            iface_id: String::from("netif"),
+            iface_id: Identifier::try_from("netif").unwrap(),
             host_dev_name: String::from("hostname"),
+            backend: NetBackendType::Tap,
+            xdp: None,
             guest_mac: None,
+            mtu: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: true,
+            allow_promiscuous: true,
+            rx_impairment: None,
+            tx_impairment: None,
+            affinity: None,
         };
         insert_net_device(&mut vmm, event_manager, network_interface);
 
@@ -201,6 +1070,17 @@ mod tests {
 
         insert_vsock_device(&mut vmm, event_manager, vsock_config);
 
+        // Add balloon device.
+        let balloon_config = BalloonDeviceConfig {
+            amount_mib: 0,
+            deflate_on_oom: false,
+            free_page_reporting: false,
+        };
+        insert_balloon_device(&mut vmm, event_manager, balloon_config);
+
+        // Add entropy device.
+        insert_entropy_device(&mut vmm, event_manager);
+
         vmm
     }
 
@@ -208,35 +1088,264 @@ mod tests {
     fn test_microvmstate_versionize() {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
         let mut vmm = default_vmm_with_devices(&mut event_manager);
-        let states = vmm.save_mmio_device_states();
+        let tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        let mut vsock_config = default_config(&tmp_sock_file);
+        vsock_config.vsock_id = Identifier::try_from("other_vsock").unwrap();
+        vsock_config.guest_cid += 1;
+        insert_vsock_device(&mut vmm, &mut event_manager, vsock_config);
+        let states = vmm.save_mmio_device_states().unwrap();
 
         // Only checking that all devices are saved, actual device state
         // is tested by that device's tests.
         assert_eq!(states.block_devices.len(), 1);
         assert_eq!(states.net_devices.len(), 1);
         assert!(states.vsock_device.is_some());
+        assert_eq!(states.extra_vsock_devices.len(), 1);
+        assert!(states.balloon_device.is_some());
+        assert!(states.entropy_device.is_some());
 
         let microvm_state = MicrovmState {
-            vm_info: VmInfo { mem_size_mib: 1u64 },
+            vm_info: VmInfo {
+                mem_size_mib: 1u64,
+                ht_enabled: true,
+                cores_per_socket: Some(1),
+                tsc_khz: Some(2_500_000),
+                legacy_serial: true,
+                legacy_i8042: false,
+                legacy_rtc: false,
+                pit: true,
+                rtc_time_offset: -3600,
+            },
+            memory_state: GuestMemoryState::default(),
             vm_state: vmm.vm.save_state().unwrap(),
             vcpu_states: vec![default_vcpu_state()],
             device_states: states,
         };
 
         let mut buf = vec![0; 10000];
-        let version_map = VersionMap::new();
+        let version_map = version_map();
+        let version = version_map.latest_version();
 
         microvm_state
-            .serialize(&mut buf.as_mut_slice(), &version_map, 1)
+            .serialize(&mut buf.as_mut_slice(), &version_map, version)
             .unwrap();
 
         let restored_microvm_state =
-            MicrovmState::deserialize(&mut buf.as_slice(), &version_map, 1).unwrap();
+            MicrovmState::deserialize(&mut buf.as_slice(), &version_map, version).unwrap();
 
         assert_eq!(restored_microvm_state.vm_info, microvm_state.vm_info);
+        assert_eq!(
+            restored_microvm_state.memory_state,
+            microvm_state.memory_state
+        );
         assert_eq!(
             restored_microvm_state.device_states,
             microvm_state.device_states
         )
     }
+
+    #[test]
+    fn test_load_compressed_memory() {
+        let memory_state = GuestMemoryState {
+            regions: vec![],
+            compression: Compression::Lz4,
+        };
+        let params = LoadSnapshotParams {
+            snapshot_path: TempFile::new().unwrap().as_path().to_path_buf(),
+            mem_file_path: TempFile::new().unwrap().as_path().to_path_buf(),
+            enable_diff_snapshots: false,
+            mem_backend: MemBackendType::Uffd,
+            uffd_socket_path: None,
+            profile_page_faults: false,
+            record_manifest_path: None,
+            prefetch_manifest_path: None,
+            post_restore: Default::default(),
+        };
+        match load_memory_on_demand(&memory_state, &params, &[]) {
+            Err(LoadSnapshotError::CompressedMemory) => (),
+            _ => panic!("Compressed guest memory should not be served on demand."),
+        }
+
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        match map_memory_privately(&memory_state, &params, &mut event_manager) {
+            Err(LoadSnapshotError::CompressedMemory) => (),
+            _ => panic!("Compressed guest memory should not be mapped."),
+        }
+    }
+
+    #[test]
+    fn test_load_page_fault_profiling() {
+        let mut params = LoadSnapshotParams {
+            snapshot_path: PathBuf::from("/invalid/snapshot"),
+            mem_file_path: PathBuf::from("/invalid/mem_file"),
+            enable_diff_snapshots: false,
+            mem_backend: MemBackendType::File,
+            uffd_socket_path: None,
+            profile_page_faults: true,
+            record_manifest_path: None,
+            prefetch_manifest_path: None,
+            post_restore: Default::default(),
+        };
+        let mut vm_resources = VmResources::default();
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+
+        // The page faults are only seen by this process with the Uffd backend.
+        match load_snapshot(&mut vm_resources, &mut event_manager, &[], &params) {
+            Err(LoadSnapshotError::PageFaultProfiling) => (),
+            _ => panic!("The page faults should not be profiled with the File backend."),
+        }
+        // Only the Uffd backend has its guest pages served by a page server.
+        params.uffd_socket_path = Some(PathBuf::from("/invalid/socket"));
+        match load_snapshot(&mut vm_resources, &mut event_manager, &[], &params) {
+            Err(LoadSnapshotError::PageServer) => (),
+            _ => panic!("A page server should not serve the guest pages with the File backend."),
+        }
+        params.mem_backend = MemBackendType::Uffd;
+        match load_snapshot(&mut vm_resources, &mut event_manager, &[], &params) {
+            Err(LoadSnapshotError::PageFaultProfiling) => (),
+            _ => panic!("The page faults served by a page server should not be profiled."),
+        }
+        params.profile_page_faults = false;
+        params.record_manifest_path = Some(PathBuf::from("/invalid/manifest"));
+        match load_snapshot(&mut vm_resources, &mut event_manager, &[], &params) {
+            Err(LoadSnapshotError::PageFaultProfiling) => (),
+            _ => panic!("The page faults served by a page server should not be recorded."),
+        }
+        params.record_manifest_path = None;
+        params.prefetch_manifest_path = Some(PathBuf::from("/invalid/manifest"));
+        match load_snapshot(&mut vm_resources, &mut event_manager, &[], &params) {
+            Err(LoadSnapshotError::PagePrefetch) => (),
+            _ => panic!("The guest pages served by a page server should not be prefetched."),
+        }
+
+        params.uffd_socket_path = None;
+        params.profile_page_faults = true;
+        match load_snapshot(&mut vm_resources, &mut event_manager, &[], &params) {
+            Err(LoadSnapshotError::SnapshotBackingFile(_)) => (),
+            _ => panic!("The snapshot file should not exist."),
+        }
+    }
+
+    #[test]
+    fn test_version_map() {
+        let version_map = version_map();
+        assert_eq!(version_map.latest_version(), 17);
+        assert_eq!(
+            version_map.get_type_version(1, GuestMemoryState::type_id()),
+            1
+        );
+        assert_eq!(
+            version_map.get_type_version(2, GuestMemoryState::type_id()),
+            2
+        );
+        assert_eq!(
+            version_map.get_type_version(2, GuestMemoryRegionState::type_id()),
+            2
+        );
+        assert_eq!(version_map.get_type_version(2, DeviceStates::type_id()), 1);
+        assert_eq!(version_map.get_type_version(3, DeviceStates::type_id()), 2);
+        assert_eq!(version_map.get_type_version(7, DeviceStates::type_id()), 2);
+        assert_eq!(version_map.get_type_version(8, DeviceStates::type_id()), 3);
+        assert_eq!(version_map.get_type_version(3, BlockState::type_id()), 1);
+        assert_eq!(version_map.get_type_version(4, BlockState::type_id()), 2);
+        assert_eq!(version_map.get_type_version(6, BlockState::type_id()), 2);
+        assert_eq!(version_map.get_type_version(7, BlockState::type_id()), 3);
+        assert_eq!(version_map.get_type_version(13, BlockState::type_id()), 3);
+        assert_eq!(version_map.get_type_version(14, BlockState::type_id()), 4);
+        assert_eq!(version_map.get_type_version(15, BlockState::type_id()), 5);
+        assert_eq!(version_map.get_type_version(16, BlockState::type_id()), 6);
+        assert_eq!(version_map.get_type_version(4, VmInfo::type_id()), 1);
+        assert_eq!(version_map.get_type_version(5, VmInfo::type_id()), 2);
+        assert_eq!(version_map.get_type_version(6, VmInfo::type_id()), 3);
+        assert_eq!(version_map.get_type_version(11, VmInfo::type_id()), 3);
+        assert_eq!(version_map.get_type_version(12, VmInfo::type_id()), 4);
+        assert_eq!(version_map.get_type_version(13, VmInfo::type_id()), 5);
+        assert_eq!(version_map.get_type_version(16, VmInfo::type_id()), 5);
+        assert_eq!(version_map.get_type_version(17, VmInfo::type_id()), 6);
+        assert_eq!(version_map.get_type_version(5, VcpuState::type_id()), 1);
+        assert_eq!(version_map.get_type_version(6, VcpuState::type_id()), 2);
+        assert_eq!(
+            version_map.get_type_version(8, NetConfigSpaceState::type_id()),
+            1
+        );
+        assert_eq!(
+            version_map.get_type_version(9, NetConfigSpaceState::type_id()),
+            2
+        );
+        assert_eq!(
+            version_map.get_type_version(10, NetConfigSpaceState::type_id()),
+            3
+        );
+        assert_eq!(version_map.get_type_version(10, NetState::type_id()), 1);
+        assert_eq!(version_map.get_type_version(11, NetState::type_id()), 2);
+    }
+
+    #[test]
+    fn test_create_snapshot_invalid_params() {
+        let mut vmm = default_vmm();
+        let mut vm_config = VmConfig {
+            track_dirty_pages: true,
+            ..Default::default()
+        };
+        let mut params = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: TempFile::new().unwrap().as_path().to_path_buf(),
+            mem_file_path: TempFile::new().unwrap().as_path().to_path_buf(),
+            version: Some(1),
+            compression: MemoryCompression::Zstd,
+            checksums: false,
+            background: false,
+        };
+        match create_snapshot(&mut vmm, &vm_config, &params) {
+            Err(CreateSnapshotError::InvalidVersion(1)) => (),
+            _ => panic!("Compression should require snapshot version 2."),
+        }
+
+        params.compression = MemoryCompression::None;
+        params.checksums = true;
+        match create_snapshot(&mut vmm, &vm_config, &params) {
+            Err(CreateSnapshotError::InvalidVersion(1)) => (),
+            _ => panic!("Checksums should require snapshot version 2."),
+        }
+
+        params.version = None;
+        params.snapshot_type = SnapshotType::Diff;
+        params.compression = MemoryCompression::Lz4;
+        match create_snapshot(&mut vmm, &vm_config, &params) {
+            Err(CreateSnapshotError::CompressedDiffSnapshot) => (),
+            _ => panic!("Diff snapshots should not be compressed."),
+        }
+
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        insert_entropy_device(&mut vmm, &mut event_manager);
+        params.version = Some(2);
+        params.snapshot_type = SnapshotType::Full;
+        params.compression = MemoryCompression::None;
+        params.checksums = false;
+        match create_snapshot(&mut vmm, &vm_config, &params) {
+            Err(CreateSnapshotError::InvalidVersion(2)) => (),
+            _ => panic!("The entropy device should require snapshot version 3."),
+        }
+
+        params.version = Some(4);
+        vm_config.cores_per_socket = Some(1);
+        match create_snapshot(&mut vmm, &vm_config, &params) {
+            Err(CreateSnapshotError::InvalidVersion(4)) => (),
+            _ => panic!("The CPU topology should require snapshot version 5."),
+        }
+
+        params.version = Some(5);
+        vm_config.tsc_khz = Some(2_500_000);
+        match create_snapshot(&mut vmm, &vm_config, &params) {
+            Err(CreateSnapshotError::InvalidVersion(5)) => (),
+            _ => panic!("The TSC frequency should require snapshot version 6."),
+        }
+
+        params.version = Some(12);
+        vm_config.tickless = true;
+        match create_snapshot(&mut vmm, &vm_config, &params) {
+            Err(CreateSnapshotError::InvalidVersion(12)) => (),
+            _ => panic!("Omitting the PIT should require snapshot version 13."),
+        }
+    }
 }
@@ -0,0 +1,220 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Hooks run on a microVM restored from a snapshot, before it is resumed.
+//!
+//! All the microVMs restored from the same snapshot resume with the same guest state: the same
+//! kernel RNG state, the same MMDS contents and the same view of their vsock connections. The
+//! hooks let each clone diverge before any guest code runs.
+
+use std::fmt::{Display, Formatter};
+
+use devices::virtio::{Entropy, Vsock, VsockUnixBackend, TYPE_RNG, TYPE_VSOCK};
+use mmds::data_store::Error as MmdsError;
+use mmds::MMDS;
+use vmm_config::snapshot::PostRestoreConfig;
+use Vmm;
+
+/// Errors associated with running the post-restore hooks.
+#[derive(Debug)]
+pub enum PostRestoreError {
+    /// Cannot replace the MMDS contents.
+    Mmds(MmdsError),
+    /// The microVM has no entropy device to hand fresh entropy through.
+    NoEntropyDevice,
+    /// The microVM has no vsock device to notify the guest through.
+    NoVsockDevice,
+    /// Cannot signal the guest driver.
+    SignalGuest(devices::Error),
+}
+
+impl Display for PostRestoreError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::PostRestoreError::*;
+        match self {
+            Mmds(err) => write!(f, "Cannot replace the MMDS contents: {}", err),
+            NoEntropyDevice => write!(f, "The microVM has no entropy device."),
+            NoVsockDevice => write!(f, "The microVM has no vsock device."),
+            SignalGuest(err) => write!(f, "Cannot signal the guest driver: {:?}", err),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, PostRestoreError>;
+
+/// An action taken on a restored microVM, while its vCPUs are still paused.
+pub trait PostRestoreHook {
+    /// Short name of the hook, used for logging.
+    fn name(&self) -> &'static str;
+
+    /// Runs the hook on the restored microVM.
+    fn run(&self, vmm: &mut Vmm) -> Result<()>;
+}
+
+/// Hands fresh host entropy to the buffers the guest entropy driver posted before the snapshot
+/// was taken, so that the guest RNG gets reseeded as soon as the microVM resumes.
+pub struct ReseedEntropy;
+
+impl PostRestoreHook for ReseedEntropy {
+    fn name(&self) -> &'static str {
+        "reseed_entropy"
+    }
+
+    fn run(&self, vmm: &mut Vmm) -> Result<()> {
+        let bytes = vmm
+            .with_virtio_device(TYPE_RNG, |entropy: &mut Entropy| entropy.inject_entropy())
+            .ok_or(PostRestoreError::NoEntropyDevice)?
+            .map_err(PostRestoreError::SignalGuest)?;
+        if bytes == 0 {
+            warn!("The guest entropy driver had no pending request to reseed.");
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the MMDS contents, e.g. with the identity of the restored microVM.
+pub struct ReplaceMmdsData(pub serde_json::Value);
+
+impl PostRestoreHook for ReplaceMmdsData {
+    fn name(&self) -> &'static str {
+        "replace_mmds_data"
+    }
+
+    fn run(&self, _vmm: &mut Vmm) -> Result<()> {
+        MMDS.lock()
+            .expect("Poisoned lock")
+            .put_data(self.0.clone())
+            .map_err(PostRestoreError::Mmds)
+    }
+}
+
+/// Tells the guest vsock drivers that the transport was reset, so that they drop the connections
+/// inherited from the snapshot and fetches the guest CID again. Guest agents listening on vsock
+/// can use this as the signal to regenerate their own identity.
+pub struct NotifyGuest;
+
+impl PostRestoreHook for NotifyGuest {
+    fn name(&self) -> &'static str {
+        "notify_guest"
+    }
+
+    fn run(&self, vmm: &mut Vmm) -> Result<()> {
+        let mut result = Ok(());
+        let count = vmm.for_each_virtio_device(
+            TYPE_VSOCK,
+            |vsock_id, vsock: &mut Vsock<VsockUnixBackend>| {
+                match vsock.send_transport_reset_event() {
+                    Ok(true) => (),
+                    Ok(false) => warn!(
+                        "The guest driver of the vsock device {} had no event buffer to notify \
+                         the restore through.",
+                        vsock_id
+                    ),
+                    // The guest is notified through the other devices all the same.
+                    Err(e) => {
+                        if result.is_ok() {
+                            result = Err(PostRestoreError::SignalGuest(e));
+                        }
+                    }
+                }
+            },
+        );
+        if count == 0 {
+            return Err(PostRestoreError::NoVsockDevice);
+        }
+        result
+    }
+}
+
+/// Returns the hooks requested by `config`, in the order they have to run.
+pub fn hooks(config: &PostRestoreConfig) -> Vec<Box<dyn PostRestoreHook>> {
+    let mut hooks: Vec<Box<dyn PostRestoreHook>> = Vec::new();
+    // The MMDS contents have to be in place before the guest learns about the restore.
+    if let Some(data) = config.mmds_data.as_ref() {
+        hooks.push(Box::new(ReplaceMmdsData(data.clone())));
+    }
+    if config.reseed_entropy {
+        hooks.push(Box::new(ReseedEntropy));
+    }
+    if config.notify_guest {
+        hooks.push(Box::new(NotifyGuest));
+    }
+    hooks
+}
+
+/// Runs `hooks` on the restored microVM, stopping at the first failure.
+pub fn run_hooks(vmm: &mut Vmm, hooks: &[Box<dyn PostRestoreHook>]) -> Result<()> {
+    for hook in hooks.iter() {
+        hook.run(vmm)?;
+        info!("Ran the post-restore hook {}.", hook.name());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::tests::{default_vmm, insert_entropy_device, insert_vsock_device};
+    use polly::event_manager::EventManager;
+    use utils::tempfile::TempFile;
+    use vmm_config::vsock::tests::{default_config, TempSockFile};
+
+    #[test]
+    fn test_hooks() {
+        assert!(hooks(&PostRestoreConfig::default()).is_empty());
+
+        let config = PostRestoreConfig {
+            reseed_entropy: true,
+            mmds_data: Some(json!({"id": "clone-1"})),
+            notify_guest: true,
+        };
+        let names: Vec<_> = hooks(&config).iter().map(|hook| hook.name()).collect();
+        assert_eq!(
+            names,
+            vec!["replace_mmds_data", "reseed_entropy", "notify_guest"]
+        );
+    }
+
+    #[test]
+    fn test_missing_devices() {
+        let mut vmm = default_vmm();
+        match ReseedEntropy.run(&mut vmm) {
+            Err(PostRestoreError::NoEntropyDevice) => (),
+            _ => panic!("Reseeding should require an entropy device."),
+        }
+        match NotifyGuest.run(&mut vmm) {
+            Err(PostRestoreError::NoVsockDevice) => (),
+            _ => panic!("Notifying the guest should require a vsock device."),
+        }
+    }
+
+    #[test]
+    fn test_run_hooks() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        insert_entropy_device(&mut vmm, &mut event_manager);
+        let tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        insert_vsock_device(&mut vmm, &mut event_manager, default_config(&tmp_sock_file));
+
+        let config = PostRestoreConfig {
+            reseed_entropy: true,
+            mmds_data: Some(json!({"latest": {"meta-data": {"instance-id": "clone-2"}}})),
+            notify_guest: true,
+        };
+        // The guest drivers are not active, so there is nothing to signal.
+        run_hooks(&mut vmm, &hooks(&config)).unwrap();
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::PostRestoreError::*;
+        let err = Mmds(MmdsError::NotInitialized);
+        let _ = format!("{}{:?}", err, err);
+        let err = NoEntropyDevice;
+        let _ = format!("{}{:?}", err, err);
+        let err = NoVsockDevice;
+        let _ = format!("{}{:?}", err, err);
+        let err = SignalGuest(devices::Error::NoAvailBuffers);
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -0,0 +1,616 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Evaluates the probes which tell the control plane whether the workload inside the guest is
+//! ready, or still alive.
+//!
+//! Each probe is evaluated on the VMM thread, every time its timer expires. The changes of the
+//! state of the probes are surfaced as `VmmEvent`s, and the current states are shared with the
+//! API thread through `ProbeStatuses`, which are part of the instance information. The probes are
+//! not evaluated while the microVM is paused, since the guest can't answer them.
+
+use std::fmt::{Display, Formatter};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use events::{EventSender, VmmEvent};
+use logger::{Metric, METRICS};
+use polly::event_manager::{EventManager, Subscriber};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::{EpollEvent, EventSet};
+use vmm_config::probe::{vsock_uds_path, ProbeCheck, ProbeConfig, ProbeConfigError, ProbeKind};
+use vmm_config::vsock::VsockDeviceConfig;
+
+/// Errors associated with the probes.
+#[derive(Debug)]
+pub enum ProbeError {
+    /// The probe doesn't fit the configuration of the microVM.
+    Config(ProbeConfigError),
+    /// Cannot create the timer of the probe.
+    TimerFd(io::Error),
+}
+
+impl Display for ProbeError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            ProbeError::Config(err) => write!(f, "Invalid probe: {}", err),
+            ProbeError::TimerFd(err) => write!(f, "Cannot create the probe timer: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ProbeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProbeError::Config(err) => Some(err),
+            ProbeError::TimerFd(err) => Some(err),
+        }
+    }
+}
+
+/// The state of a probe.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeState {
+    /// The probe neither passed yet, nor failed as many times in a row as its threshold.
+    Pending,
+    /// The last evaluations of the probe passed.
+    Passing,
+    /// The probe failed as many times in a row as its threshold.
+    Failing,
+}
+
+/// The state of a probe, as shown to the control plane.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ProbeStatus {
+    /// ID of the probe.
+    pub probe_id: String,
+    /// What the probe tells the control plane.
+    pub kind: ProbeKind,
+    /// The state of the probe.
+    pub state: ProbeState,
+}
+
+/// The states of the probes of a microVM, updated by the VMM thread.
+#[derive(Clone, Debug, Default)]
+pub struct ProbeStatuses(Arc<Mutex<Vec<ProbeStatus>>>);
+
+impl ProbeStatuses {
+    /// Returns the current states of the probes.
+    pub fn get(&self) -> Vec<ProbeStatus> {
+        self.0.lock().expect("Poisoned lock").clone()
+    }
+
+    /// Specifies whether the microVM has no probes.
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().expect("Poisoned lock").is_empty()
+    }
+
+    fn set_state(&self, index: usize, state: ProbeState) {
+        self.0.lock().expect("Poisoned lock")[index].state = state;
+    }
+}
+
+impl Serialize for ProbeStatuses {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.get().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ProbeStatuses {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(|statuses| ProbeStatuses(Arc::new(Mutex::new(statuses))))
+    }
+}
+
+// How a probe checks the guest.
+enum Check {
+    // The connection attempt started by the previous evaluation, if any, is checked by the next
+    // one, so that the guest has a whole period to accept it.
+    VsockConnect {
+        uds_path: PathBuf,
+        port: u32,
+        attempt: Option<io::Result<UnixStream>>,
+    },
+    // Set by the `SerialWatcher` once the pattern was printed.
+    SerialPattern(Arc<AtomicBool>),
+    // The number of packets the guest sent to the MMDS, as of the previous evaluation.
+    MmdsHeartbeat(usize),
+}
+
+impl Check {
+    // Returns whether the check passed, or `None` when there is no verdict yet.
+    fn evaluate(&mut self) -> Option<bool> {
+        match self {
+            Check::VsockConnect {
+                uds_path,
+                port,
+                attempt,
+            } => {
+                let passed = attempt.take().map(|attempt| match attempt {
+                    Ok(mut stream) => vsock_accepted(&mut stream),
+                    Err(_) => false,
+                });
+                *attempt = Some(vsock_connect(uds_path, *port));
+                passed
+            }
+            Check::SerialPattern(printed) => Some(printed.load(Ordering::Relaxed)),
+            Check::MmdsHeartbeat(last_count) => {
+                let count = METRICS.mmds.rx_count.count();
+                let passed = count != *last_count;
+                *last_count = count;
+                Some(passed)
+            }
+        }
+    }
+
+    // Forgets what the guest couldn't answer while the microVM was paused.
+    fn skip(&mut self) {
+        match self {
+            Check::VsockConnect { attempt, .. } => *attempt = None,
+            Check::SerialPattern(_) => (),
+            Check::MmdsHeartbeat(last_count) => *last_count = METRICS.mmds.rx_count.count(),
+        }
+    }
+}
+
+// Asks the vsock device behind `uds_path` to connect to the guest `port`.
+fn vsock_connect(uds_path: &PathBuf, port: u32) -> io::Result<UnixStream> {
+    let mut stream = UnixStream::connect(uds_path)?;
+    stream.set_nonblocking(true)?;
+    stream.write_all(format!("CONNECT {}\n", port).as_bytes())?;
+    Ok(stream)
+}
+
+// The vsock device acknowledges the connections accepted by the guest with `OK <host port>`, and
+// closes the others.
+fn vsock_accepted(stream: &mut UnixStream) -> bool {
+    let mut buf = [0u8; 32];
+    match stream.read(&mut buf) {
+        Ok(len) => buf[..len].starts_with(b"OK "),
+        Err(_) => false,
+    }
+}
+
+struct Probe {
+    index: usize,
+    probe_id: String,
+    kind: ProbeKind,
+    failure_threshold: u32,
+    timer_fd: TimerFd,
+    check: Check,
+    failures: u32,
+    state: ProbeState,
+}
+
+impl Probe {
+    // Accounts for an evaluation, returning the new state of the probe if it changed.
+    fn record(&mut self, passed: bool) -> Option<ProbeState> {
+        let state = if passed {
+            self.failures = 0;
+            ProbeState::Passing
+        } else {
+            self.failures = self.failures.saturating_add(1);
+            if self.failures < self.failure_threshold {
+                return None;
+            }
+            ProbeState::Failing
+        };
+        if state == self.state {
+            return None;
+        }
+        self.state = state;
+        Some(state)
+    }
+}
+
+/// Evaluates the probes of a microVM.
+pub struct ProbeRunner {
+    probes: Vec<Probe>,
+    // The patterns the serial console output is watched for.
+    patterns: Vec<(Vec<u8>, Arc<AtomicBool>)>,
+    statuses: ProbeStatuses,
+    events: EventSender,
+    // Whether the vCPUs run, set by the VMM.
+    running: Arc<AtomicBool>,
+}
+
+impl ProbeRunner {
+    /// Creates the probes described by `configs`, which start being evaluated right away. The
+    /// vsock probes connect through the Unix sockets of `vsock_configs`, and the changes of the
+    /// states of the probes are sent through `events`.
+    pub fn new(
+        configs: &[ProbeConfig],
+        vsock_configs: &[VsockDeviceConfig],
+        events: EventSender,
+    ) -> Result<Self, ProbeError> {
+        let mut probes = Vec::with_capacity(configs.len());
+        let mut patterns = Vec::new();
+        let mut statuses = Vec::with_capacity(configs.len());
+        for (index, config) in configs.iter().enumerate() {
+            let check = match &config.check {
+                ProbeCheck::VsockConnect { port, vsock_id } => Check::VsockConnect {
+                    uds_path: vsock_uds_path(vsock_configs, vsock_id.as_ref())
+                        .map_err(ProbeError::Config)?,
+                    port: *port,
+                    attempt: None,
+                },
+                ProbeCheck::SerialPattern { pattern } => {
+                    let printed = Arc::new(AtomicBool::new(false));
+                    patterns.push((pattern.as_bytes().to_vec(), printed.clone()));
+                    Check::SerialPattern(printed)
+                }
+                ProbeCheck::MmdsHeartbeat => Check::MmdsHeartbeat(METRICS.mmds.rx_count.count()),
+            };
+
+            let mut timer_fd =
+                TimerFd::new_custom(ClockId::Monotonic, true, true).map_err(ProbeError::TimerFd)?;
+            timer_fd.set_state(
+                TimerState::Periodic {
+                    current: Duration::from_millis(config.initial_delay_ms + config.period_ms),
+                    interval: Duration::from_millis(config.period_ms),
+                },
+                SetTimeFlags::Default,
+            );
+
+            probes.push(Probe {
+                index,
+                probe_id: config.probe_id.clone(),
+                kind: config.kind,
+                failure_threshold: config.failure_threshold,
+                timer_fd,
+                check,
+                failures: 0,
+                state: ProbeState::Pending,
+            });
+            statuses.push(ProbeStatus {
+                probe_id: config.probe_id.clone(),
+                kind: config.kind,
+                state: ProbeState::Pending,
+            });
+        }
+
+        Ok(ProbeRunner {
+            probes,
+            patterns,
+            statuses: ProbeStatuses(Arc::new(Mutex::new(statuses))),
+            events,
+            running: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Specifies whether there are no probes to evaluate.
+    pub fn is_empty(&self) -> bool {
+        self.probes.is_empty()
+    }
+
+    /// Returns the states of the probes, kept up to date while the probes are evaluated.
+    pub fn statuses(&self) -> ProbeStatuses {
+        self.statuses.clone()
+    }
+
+    /// Returns the flag telling whether the vCPUs run. The probes are only evaluated while it is
+    /// set.
+    pub fn running(&self) -> Arc<AtomicBool> {
+        self.running.clone()
+    }
+
+    /// Returns the output for the serial console, which forwards everything to `out` while
+    /// looking for the patterns of the serial probes.
+    pub fn serial_output(&self, out: Box<dyn Write + Send>) -> Box<dyn Write + Send> {
+        if self.patterns.is_empty() {
+            return out;
+        }
+        Box::new(SerialWatcher::new(out, self.patterns.clone()))
+    }
+
+    // Evaluates the probe whose timer expired, unless the microVM is paused.
+    fn tick(&mut self, probe: usize) {
+        if self.running.load(Ordering::Acquire) {
+            self.evaluate(probe);
+        } else {
+            self.probes[probe].check.skip();
+        }
+    }
+
+    fn evaluate(&mut self, probe: usize) {
+        let probe = &mut self.probes[probe];
+        let state = match probe.check.evaluate() {
+            Some(passed) => probe.record(passed),
+            None => None,
+        };
+        if let Some(state) = state {
+            info!("The {} probe is now {:?}.", probe.probe_id, state);
+            METRICS.vmm.probe_state_changes.inc();
+            self.statuses.set_state(probe.index, state);
+            self.events.send(VmmEvent::ProbeStateChanged {
+                probe_id: probe.probe_id.clone(),
+                kind: probe.kind,
+                state,
+            });
+        }
+    }
+}
+
+impl Subscriber for ProbeRunner {
+    /// Handle a read event (EPOLLIN).
+    fn process(&mut self, event: &EpollEvent, _: &mut EventManager) {
+        let source = event.fd();
+        let event_set = event.event_set();
+
+        let supported_events = EventSet::IN;
+        if !supported_events.contains(event_set) {
+            warn!(
+                "Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
+
+        match self
+            .probes
+            .iter()
+            .position(|probe| probe.timer_fd.as_raw_fd() == source)
+        {
+            Some(probe) => {
+                self.probes[probe].timer_fd.read();
+                self.tick(probe);
+            }
+            None => error!("Spurious probe event!"),
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        self.probes
+            .iter()
+            .map(|probe| EpollEvent::new(EventSet::IN, probe.timer_fd.as_raw_fd() as u64))
+            .collect()
+    }
+}
+
+/// Forwards the output of the serial console, while looking for the patterns of the serial
+/// probes in it.
+pub struct SerialWatcher {
+    out: Box<dyn Write + Send>,
+    // The patterns not printed yet.
+    patterns: Vec<(Vec<u8>, Arc<AtomicBool>)>,
+    // The end of the output, which may be the start of a pattern printed by the next writes.
+    tail: Vec<u8>,
+}
+
+impl SerialWatcher {
+    fn new(out: Box<dyn Write + Send>, patterns: Vec<(Vec<u8>, Arc<AtomicBool>)>) -> Self {
+        SerialWatcher {
+            out,
+            patterns,
+            tail: Vec::new(),
+        }
+    }
+
+    fn scan(&mut self, bytes: &[u8]) {
+        if self.patterns.is_empty() {
+            return;
+        }
+        self.tail.extend_from_slice(bytes);
+        let tail = &self.tail;
+        self.patterns.retain(|(pattern, printed)| {
+            if tail
+                .windows(pattern.len())
+                .any(|window| window == &pattern[..])
+            {
+                printed.store(true, Ordering::Relaxed);
+                return false;
+            }
+            true
+        });
+
+        let keep = self
+            .patterns
+            .iter()
+            .map(|(pattern, _)| pattern.len() - 1)
+            .max()
+            .unwrap_or(0);
+        if self.tail.len() > keep {
+            let excess = self.tail.len() - keep;
+            self.tail.drain(..excess);
+        }
+    }
+}
+
+impl Write for SerialWatcher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.out.write(buf)?;
+        self.scan(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+    use std::os::unix::net::UnixListener;
+
+    use events::EventChannel;
+    use utils::tempfile::TempFile;
+    use vmm_config::vsock::VsockBackendType;
+    use vmm_config::Identifier;
+
+    fn config(probe_id: &str, check: ProbeCheck) -> ProbeConfig {
+        ProbeConfig {
+            probe_id: probe_id.to_string(),
+            kind: ProbeKind::Readiness,
+            check,
+            initial_delay_ms: 0,
+            period_ms: 1000,
+            failure_threshold: 2,
+        }
+    }
+
+    #[test]
+    fn test_serial_probe() {
+        let channel = EventChannel::new(8);
+        let mut runner = ProbeRunner::new(
+            &[config(
+                "ready",
+                ProbeCheck::SerialPattern {
+                    pattern: "login:".to_string(),
+                },
+            )],
+            &[],
+            channel.sender(),
+        )
+        .unwrap();
+        assert!(!runner.is_empty());
+        let statuses = runner.statuses();
+        assert_eq!(statuses.get()[0].state, ProbeState::Pending);
+
+        let mut output = runner.serial_output(Box::new(io::sink()));
+        // Not enough failures yet.
+        runner.evaluate(0);
+        assert_eq!(statuses.get()[0].state, ProbeState::Pending);
+        runner.evaluate(0);
+        assert_eq!(statuses.get()[0].state, ProbeState::Failing);
+
+        // The pattern is split across writes.
+        output.write_all(b"Welcome\nlog").unwrap();
+        output.write_all(b"in: ").unwrap();
+        runner.evaluate(0);
+        assert_eq!(statuses.get()[0].state, ProbeState::Passing);
+        assert_eq!(
+            channel.drain(),
+            vec![
+                VmmEvent::ProbeStateChanged {
+                    probe_id: "ready".to_string(),
+                    kind: ProbeKind::Readiness,
+                    state: ProbeState::Failing,
+                },
+                VmmEvent::ProbeStateChanged {
+                    probe_id: "ready".to_string(),
+                    kind: ProbeKind::Readiness,
+                    state: ProbeState::Passing,
+                },
+            ]
+        );
+
+        // The probe stays passing.
+        runner.evaluate(0);
+        assert!(channel.drain().is_empty());
+
+        assert_eq!(
+            serde_json::to_string(&statuses).unwrap(),
+            r#"[{"probe_id":"ready","kind":"readiness","state":"passing"}]"#
+        );
+    }
+
+    #[test]
+    fn test_vsock_probe() {
+        let uds_file = TempFile::new().unwrap();
+        let uds_path = uds_file.as_path().to_path_buf();
+        std::fs::remove_file(&uds_path).unwrap();
+        let listener = UnixListener::bind(&uds_path).unwrap();
+        let vsock_config = VsockDeviceConfig {
+            vsock_id: Identifier::try_from("vsock").unwrap(),
+            guest_cid: 3,
+            uds_path: uds_path.to_str().unwrap().to_string(),
+            backend: VsockBackendType::Uds,
+            uds_mode: None,
+            uds_uid: None,
+            uds_gid: None,
+            cid_pool: None,
+        };
+
+        let mut check = ProbeCheck::VsockConnect {
+            port: 52,
+            vsock_id: Some("other".to_string()),
+        };
+        match ProbeRunner::new(
+            &[config("alive", check.clone())],
+            &[vsock_config.clone()],
+            EventChannel::new(8).sender(),
+        ) {
+            Err(ProbeError::Config(ProbeConfigError::InvalidVsockDevice(Some(_)))) => (),
+            _ => panic!("Unexpected result"),
+        }
+
+        check = ProbeCheck::VsockConnect {
+            port: 52,
+            vsock_id: None,
+        };
+        let mut runner = ProbeRunner::new(
+            &[config("alive", check)],
+            &[vsock_config],
+            EventChannel::new(8).sender(),
+        )
+        .unwrap();
+        let statuses = runner.statuses();
+
+        // The first evaluation only starts connecting.
+        runner.evaluate(0);
+        assert_eq!(statuses.get()[0].state, ProbeState::Pending);
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0u8; 16];
+        let len = stream.read(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"CONNECT 52\n");
+        stream.write_all(b"OK 1073741824\n").unwrap();
+
+        runner.evaluate(0);
+        assert_eq!(statuses.get()[0].state, ProbeState::Passing);
+
+        // The probe isn't evaluated while the microVM is paused, and the connection attempted
+        // before is forgotten.
+        runner.tick(0);
+        drop(listener.accept().unwrap());
+        for _ in 0..3 {
+            runner.tick(0);
+        }
+        assert_eq!(statuses.get()[0].state, ProbeState::Passing);
+        runner.running().store(true, Ordering::Release);
+        runner.tick(0);
+        assert_eq!(statuses.get()[0].state, ProbeState::Passing);
+
+        // The guest doesn't accept the next connections.
+        for _ in 0..2 {
+            drop(listener.accept().unwrap());
+            runner.evaluate(0);
+        }
+        assert_eq!(statuses.get()[0].state, ProbeState::Failing);
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let err = ProbeError::Config(ProbeConfigError::TooManyProbes);
+        let _ = format!("{}{:?}", err, err);
+        let err = ProbeError::TimerFd(io::Error::from_raw_os_error(libc::EMFILE));
+        let _ = format!("{}{:?}", err, err);
+    }
+
+    #[test]
+    fn test_serialize_statuses() {
+        let statuses = ProbeStatuses::default();
+        assert_eq!(serde_json::to_string(&statuses).unwrap(), "[]");
+
+        let json = r#"[{"probe_id":"ready","kind":"readiness","state":"failing"}]"#;
+        let statuses: ProbeStatuses = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            statuses.get(),
+            vec![ProbeStatus {
+                probe_id: "ready".to_string(),
+                kind: ProbeKind::Readiness,
+                state: ProbeState::Failing,
+            }]
+        );
+        assert_eq!(serde_json::to_string(&statuses).unwrap(), json);
+    }
+}
@@ -0,0 +1,267 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Accounts for the host resources the VMM process uses on top of the guest memory, so that
+//! density planning can budget for the per-microVM overhead.
+//!
+//! The memory is sampled from `/proc/self/smaps`: the resident memory of the mappings backing
+//! the guest memory is reported apart from the rest, which is the overhead of the VMM. The file
+//! descriptors are listed from `/proc/self/fd` and sorted by what they refer to.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+
+use vm_memory::{GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+
+/// The file descriptors of the VMM process, by what they refer to.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct FdUsage {
+    /// All the file descriptors.
+    pub total: usize,
+    /// `/dev/kvm`, the VM and the vCPUs.
+    pub kvm: usize,
+    /// The event file descriptors, signaling the devices and the vCPUs.
+    pub eventfd: usize,
+    /// The epoll file descriptors of the event loops.
+    pub epoll: usize,
+    /// The timers, e.g. of the rate limiters and of the balloon statistics.
+    pub timerfd: usize,
+    /// The tap devices backing the network interfaces.
+    pub tap: usize,
+    /// The sockets, e.g. of the API server and of the vsock device.
+    pub socket: usize,
+    /// The memory files, e.g. backing the guest memory.
+    pub memfd: usize,
+    /// The VFIO containers, groups and devices of the PCI passthrough devices.
+    pub vfio: usize,
+    /// The other files, e.g. the drives, the kernel and the logs.
+    pub file: usize,
+    /// Everything else, e.g. pipes and userfaultfd.
+    pub other: usize,
+}
+
+impl FdUsage {
+    // Accounts for a file descriptor, given the target of its `/proc/self/fd` link.
+    fn count(&mut self, target: &str) {
+        self.total += 1;
+        let kind = if target == "/dev/kvm" || target.starts_with("anon_inode:kvm-") {
+            &mut self.kvm
+        } else if target == "anon_inode:[eventfd]" {
+            &mut self.eventfd
+        } else if target == "anon_inode:[eventpoll]" {
+            &mut self.epoll
+        } else if target == "anon_inode:[timerfd]" {
+            &mut self.timerfd
+        } else if target == "/dev/net/tun" {
+            &mut self.tap
+        } else if target.starts_with("socket:") {
+            &mut self.socket
+        } else if target.starts_with("/memfd:") {
+            &mut self.memfd
+        } else if target.starts_with("/dev/vfio/") || target == "anon_inode:[vfio-device]" {
+            &mut self.vfio
+        } else if target.starts_with('/') {
+            &mut self.file
+        } else {
+            &mut self.other
+        };
+        *kind += 1;
+    }
+}
+
+/// The host resources used by the VMM process.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct VmmResourceUsage {
+    /// The resident memory of the process, in KiB.
+    pub rss_kib: u64,
+    /// The resident memory backing the guest memory, in KiB.
+    pub guest_rss_kib: u64,
+    /// The resident memory of the process not backing the guest memory, in KiB.
+    pub overhead_rss_kib: u64,
+    /// The threads of the process.
+    pub threads: u64,
+    /// The file descriptors of the process.
+    pub fds: FdUsage,
+}
+
+/// Samples the host resources used by the VMM process, telling apart the memory backing
+/// `guest_memory`, if the microVM is running.
+pub fn sample(guest_memory: Option<&GuestMemoryMmap>) -> io::Result<VmmResourceUsage> {
+    let ranges = guest_memory.map_or_else(Vec::new, |guest_memory| {
+        guest_memory.map_and_fold(
+            Vec::new(),
+            |(_, region)| {
+                let start = region.as_ptr() as u64;
+                vec![(start, start + region.len() as u64)]
+            },
+            |mut ranges, mut range| {
+                ranges.append(&mut range);
+                ranges
+            },
+        )
+    });
+    let (rss_kib, guest_rss_kib) =
+        resident_kib(BufReader::new(File::open("/proc/self/smaps")?), &ranges)?;
+    let threads = threads(BufReader::new(File::open("/proc/self/status")?))?;
+
+    let mut fds = FdUsage::default();
+    for entry in fs::read_dir("/proc/self/fd")? {
+        // File descriptors closed while listing them vanish.
+        let target = match fs::read_link(entry?.path()) {
+            Ok(target) => target,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let target = target.to_string_lossy();
+        // Reading the directory takes a file descriptor, which isn't accounted for.
+        if !(target.starts_with("/proc/") && target.ends_with("/fd")) {
+            fds.count(&target);
+        }
+    }
+
+    Ok(VmmResourceUsage {
+        rss_kib,
+        guest_rss_kib,
+        overhead_rss_kib: rss_kib.saturating_sub(guest_rss_kib),
+        threads,
+        fds,
+    })
+}
+
+// Sums the resident memory of all the mappings in `smaps`, and of those starting within `ranges`.
+fn resident_kib<R: BufRead>(smaps: R, ranges: &[(u64, u64)]) -> io::Result<(u64, u64)> {
+    let mut in_ranges = false;
+    let mut rss_kib = 0;
+    let mut guest_rss_kib = 0;
+    for line in smaps.lines() {
+        let line = line?;
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("Rss:") => {
+                let kib = fields
+                    .next()
+                    .and_then(|kib| kib.parse::<u64>().ok())
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidData))?;
+                rss_kib += kib;
+                if in_ranges {
+                    guest_rss_kib += kib;
+                }
+            }
+            // The mapping headers start with the address range, e.g. `7f4a2c000000-7f4a2c021000`,
+            // while the fields of the mapping end with a colon.
+            Some(field) if !field.ends_with(':') => {
+                in_ranges = field
+                    .split('-')
+                    .next()
+                    .and_then(|start| u64::from_str_radix(start, 16).ok())
+                    .map(|start| ranges.iter().any(|&(s, e)| start >= s && start < e))
+                    .unwrap_or(false);
+            }
+            _ => (),
+        }
+    }
+    Ok((rss_kib, guest_rss_kib))
+}
+
+// Reads the number of threads from `status`.
+fn threads<R: BufRead>(status: R) -> io::Result<u64> {
+    for line in status.lines() {
+        let line = line?;
+        let mut fields = line.split_whitespace();
+        if fields.next() == Some("Threads:") {
+            return fields
+                .next()
+                .and_then(|threads| threads.parse::<u64>().ok())
+                .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidData));
+        }
+    }
+    Err(io::Error::from(io::ErrorKind::InvalidData))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vm_memory::{Bytes, GuestAddress};
+
+    const SMAPS: &str = "\
+7f0000000000-7f0000010000 rw-p 00000000 00:01 1234    /memfd:guest_mem (deleted)
+Size:                 64 kB
+Rss:                  32 kB
+VmFlags: rd wr mr mw me ac sd
+7f1000000000-7f1000001000 rw-p 00000000 00:00 0
+Size:                  4 kB
+Rss:                   4 kB
+VmFlags: rd wr mr mw me ac sd
+";
+
+    #[test]
+    fn test_resident_kib() {
+        let ranges = [(0x7f00_0000_0000, 0x7f00_0001_0000)];
+        assert_eq!(resident_kib(SMAPS.as_bytes(), &ranges).unwrap(), (36, 32));
+        assert_eq!(resident_kib(SMAPS.as_bytes(), &[]).unwrap(), (36, 0));
+
+        let invalid_smaps = "7f0000000000-7f0000010000 rw-p 00000000 fd:01 1234\nRss: x kB\n";
+        assert!(resident_kib(invalid_smaps.as_bytes(), &ranges).is_err());
+    }
+
+    #[test]
+    fn test_threads() {
+        let status = "Name:\tfirecracker\nThreads:\t4\nSigQ:\t0/63569\n";
+        assert_eq!(threads(status.as_bytes()).unwrap(), 4);
+        assert!(threads("Name:\tfirecracker\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_fd_usage() {
+        let mut fds = FdUsage::default();
+        for target in &[
+            "/dev/kvm",
+            "anon_inode:kvm-vm",
+            "anon_inode:kvm-vcpu:0",
+            "anon_inode:[eventfd]",
+            "anon_inode:[eventpoll]",
+            "anon_inode:[timerfd]",
+            "/dev/net/tun",
+            "socket:[31337]",
+            "/memfd:guest_mem (deleted)",
+            "/dev/vfio/vfio",
+            "/srv/rootfs.ext4",
+            "pipe:[42]",
+        ] {
+            fds.count(target);
+        }
+        assert_eq!(
+            fds,
+            FdUsage {
+                total: 12,
+                kvm: 3,
+                eventfd: 1,
+                epoll: 1,
+                timerfd: 1,
+                tap: 1,
+                socket: 1,
+                memfd: 1,
+                vfio: 1,
+                file: 1,
+                other: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_sample() {
+        let guest_memory = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
+        guest_memory.write_obj(0xAAu8, GuestAddress(0)).unwrap();
+
+        let file = File::open("/dev/null").unwrap();
+        let usage = sample(Some(&guest_memory)).unwrap();
+        assert_eq!(usage.guest_rss_kib, 4);
+        assert_eq!(usage.overhead_rss_kib, usage.rss_kib - 4);
+        assert!(usage.threads >= 1);
+        assert!(usage.fds.file >= 1);
+        drop(file);
+
+        assert_eq!(sample(None).unwrap().guest_rss_kib, 0);
+    }
+}
@@ -3,20 +3,53 @@
 
 #![deny(warnings)]
 
+use std::fmt::{Display, Formatter};
 use std::fs::File;
+use std::sync::Arc;
 
+use serde::{de, Deserialize};
+
+use builder::insert_root_device_args;
+#[cfg(target_arch = "x86_64")]
+use device_manager::mmio::MMIODeviceManager;
 use dumbo::ns::MmdsNetworkStack;
+use utils::fd_uri;
 use utils::net::ipv4addr::is_link_local_valid;
+use vmm_config::balloon::*;
+#[cfg(target_arch = "x86_64")]
+use vmm_config::boot_source::MmioDeviceEnumeration;
 use vmm_config::boot_source::{
-    BootConfig, BootSourceConfig, BootSourceConfigError, DEFAULT_KERNEL_CMDLINE,
+    fill_boot_args, BootConfig, BootSourceConfig, BootSourceConfigError, KernelImage,
+    DEFAULT_KERNEL_CMDLINE,
 };
+#[cfg(target_arch = "x86_64")]
+use vmm_config::cloud_init::CloudInitTransport;
+use vmm_config::cloud_init::{CloudInitConfig, CloudInitConfigError};
+use vmm_config::console::{ConsoleConfig, ConsoleConfigError};
 use vmm_config::drive::*;
+use vmm_config::entropy::*;
+use vmm_config::gpu::*;
+use vmm_config::input::*;
 use vmm_config::logger::{init_logger, LoggerConfig, LoggerConfigError};
-use vmm_config::machine_config::{VmConfig, VmConfigError};
+#[cfg(target_arch = "aarch64")]
+use vmm_config::machine_config::GicVersion;
+use vmm_config::machine_config::{CpuFeaturesConfig, VmConfig, VmConfigError};
+use vmm_config::memory_limits::{MemoryLimitsConfig, MemoryLimitsConfigError};
 use vmm_config::metrics::{init_metrics, MetricsConfig, MetricsConfigError};
 use vmm_config::mmds::{MmdsConfig, MmdsConfigError};
 use vmm_config::net::*;
+use vmm_config::pci_passthrough::*;
+use vmm_config::preopened_fds::PreopenedFds;
+use vmm_config::probe::{ProbeBuilder, ProbeConfig, ProbeConfigError};
+use vmm_config::rate_limit_policy::{RateLimitPolicyConfig, RateLimitPolicyError};
+use vmm_config::rtc::{RtcConfig, RtcConfigError};
+#[cfg(feature = "sev")]
+use vmm_config::sev::{SevConfig, SevConfigError};
+use vmm_config::shared_memory::*;
+use vmm_config::sound::*;
+use vmm_config::virtio_features::{VirtioFeaturePolicyConfig, VirtioFeaturePolicyError};
 use vmm_config::vsock::*;
+use vmm_config::{Identifier, RateLimiterConfig};
 use vstate::VcpuConfig;
 
 type Result<E> = std::result::Result<(), E>;
@@ -25,46 +58,301 @@ type Result<E> = std::result::Result<(), E>;
 #[derive(Debug)]
 pub enum Error {
     /// JSON is invalid.
-    InvalidJson,
+    InvalidJson(serde_json::Error),
+    /// Balloon device configuration error.
+    BalloonDevice(BalloonConfigError),
     /// Block device configuration error.
     BlockDevice(DriveError),
+    /// cloud-init configuration error.
+    CloudInit(CloudInitConfigError),
+    /// Entropy device configuration error.
+    EntropyDevice(EntropyConfigError),
+    /// GPU device configuration error.
+    GpuDevice(GpuConfigError),
+    /// Input device configuration error.
+    InputDevice(InputConfigError),
     /// Net device configuration error.
     NetDevice(NetworkInterfaceError),
+    /// PCI passthrough device configuration error.
+    PciPassthroughDevice(PciPassthroughConfigError),
+    /// Probe configuration error.
+    Probe(ProbeConfigError),
+    /// Adaptive rate limiting policy error.
+    RateLimitPolicy(RateLimitPolicyError),
     /// Boot source configuration error.
     BootSource(BootSourceConfigError),
+    /// Serial console configuration error.
+    ConsoleConfig(ConsoleConfigError),
     /// Logger configuration error.
     Logger(LoggerConfigError),
+    /// Memory limits configuration error.
+    MemoryLimits(MemoryLimitsConfigError),
     /// Metrics system configuration error.
     Metrics(MetricsConfigError),
+    /// RTC configuration error.
+    RtcConfig(RtcConfigError),
+    /// SEV configuration error.
+    #[cfg(feature = "sev")]
+    SevConfig(SevConfigError),
+    /// Shared memory device configuration error.
+    SharedMemoryDevice(SharedMemoryConfigError),
+    /// Sound device configuration error.
+    SoundDevice(SoundConfigError),
+    /// Virtio feature policy error.
+    VirtioFeaturePolicy(VirtioFeaturePolicyError),
     /// microVM vCpus or memory configuration error.
     VmConfig(VmConfigError),
     /// Vsock device configuration error.
     VsockDevice(VsockConfigError),
     /// MMDS configuration error.
     MmdsConfig(MmdsConfigError),
+    /// The resources are valid on their own, but not together.
+    InconsistentConfig(ConfigIssues),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::Error::*;
+
+        match self {
+            InvalidJson(err) => write!(f, "Invalid JSON: {}", err),
+            BalloonDevice(err) => write!(f, "Invalid balloon device: {}", err),
+            BlockDevice(err) => write!(f, "Invalid block device: {}", err),
+            CloudInit(err) => write!(f, "Invalid cloud-init configuration: {}", err),
+            EntropyDevice(err) => write!(f, "Invalid entropy device: {}", err),
+            GpuDevice(err) => write!(f, "Invalid GPU device: {}", err),
+            InputDevice(err) => write!(f, "Invalid input device: {}", err),
+            NetDevice(err) => write!(f, "Invalid network interface: {}", err),
+            PciPassthroughDevice(err) => write!(f, "Invalid PCI passthrough device: {}", err),
+            Probe(err) => write!(f, "Invalid probe: {}", err),
+            RateLimitPolicy(err) => write!(f, "Invalid rate limiting policy: {}", err),
+            BootSource(err) => write!(f, "Invalid boot source: {}", err),
+            ConsoleConfig(err) => write!(f, "Invalid console configuration: {}", err),
+            Logger(err) => write!(f, "Invalid logger configuration: {}", err),
+            MemoryLimits(err) => write!(f, "Invalid memory limits: {}", err),
+            Metrics(err) => write!(f, "Invalid metrics configuration: {}", err),
+            RtcConfig(err) => write!(f, "Invalid RTC configuration: {}", err),
+            #[cfg(feature = "sev")]
+            SevConfig(err) => write!(f, "Invalid SEV configuration: {}", err),
+            SharedMemoryDevice(err) => write!(f, "Invalid shared memory device: {}", err),
+            SoundDevice(err) => write!(f, "Invalid sound device: {}", err),
+            VirtioFeaturePolicy(err) => write!(f, "Invalid virtio feature policy: {}", err),
+            VmConfig(err) => write!(f, "Invalid machine configuration: {}", err),
+            VsockDevice(err) => write!(f, "Invalid vsock device: {}", err),
+            MmdsConfig(err) => write!(f, "Invalid MMDS configuration: {}", err),
+            InconsistentConfig(issues) => write!(f, "Inconsistent configuration: {}", issues),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use self::Error::*;
+
+        match self {
+            InvalidJson(err) => Some(err),
+            BalloonDevice(err) => Some(err),
+            BlockDevice(err) => Some(err),
+            CloudInit(err) => Some(err),
+            EntropyDevice(err) => Some(err),
+            GpuDevice(err) => Some(err),
+            InputDevice(err) => Some(err),
+            NetDevice(err) => Some(err),
+            PciPassthroughDevice(err) => Some(err),
+            Probe(err) => Some(err),
+            RateLimitPolicy(err) => Some(err),
+            BootSource(err) => Some(err),
+            ConsoleConfig(err) => Some(err),
+            Logger(err) => Some(err),
+            MemoryLimits(err) => Some(err),
+            Metrics(err) => Some(err),
+            RtcConfig(err) => Some(err),
+            #[cfg(feature = "sev")]
+            SevConfig(err) => Some(err),
+            SharedMemoryDevice(err) => Some(err),
+            SoundDevice(err) => Some(err),
+            VirtioFeaturePolicy(err) => Some(err),
+            VmConfig(err) => Some(err),
+            VsockDevice(err) => Some(err),
+            MmdsConfig(err) => Some(err),
+            InconsistentConfig(_) => None,
+        }
+    }
+}
+
+/// The kinds of problems found by cross-checking the resources of a microVM.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigIssueKind {
+    /// Two network interfaces have the same guest MAC address.
+    DuplicateGuestMac,
+    /// Two vsock devices have the same guest CID.
+    DuplicateVsockCid,
+    /// The guest has neither a root drive, an initrd, nor a `root=` boot argument to mount its
+    /// root filesystem from.
+    MissingRootDevice,
+    /// The guest CID of a vsock device is reserved for the hypervisor or the host.
+    ReservedVsockCid,
+    /// A token bucket of a rate limiter has a size or a refill time of 0, which leaves it
+    /// unlimited.
+    ZeroTokenBucket,
+}
+
+/// A problem found by cross-checking the resources of a microVM.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConfigIssue {
+    /// The kind of problem.
+    pub kind: ConfigIssueKind,
+    /// The API path of the resource with the problem, e.g. `/drives/rootfs`.
+    pub resource: String,
+    /// What is wrong with the resource.
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn new(kind: ConfigIssueKind, resource: String, message: String) -> Self {
+        ConfigIssue {
+            kind,
+            resource,
+            message,
+        }
+    }
+}
+
+/// All the problems found by cross-checking the resources of a microVM.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ConfigIssues(pub Vec<ConfigIssue>);
+
+impl ConfigIssues {
+    /// Specifies whether no problem was found.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Display for ConfigIssues {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let issues: Vec<String> = self
+            .0
+            .iter()
+            .map(|issue| format!("{}: {}", issue.resource, issue.message))
+            .collect();
+        write!(f, "{}", issues.join("; "))
+    }
 }
 
-/// Used for configuring a vmm from one single json passed to the Firecracker process.
-#[derive(Deserialize)]
+/// Used for configuring a vmm from one single json passed to the Firecracker process, and for
+/// describing the configuration of a microVM which is yet to start, in the same format.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
 pub struct VmmConfig {
-    #[serde(rename = "boot-source")]
-    boot_source: BootSourceConfig,
+    #[serde(rename = "balloon")]
+    balloon_device: Option<BalloonDeviceConfig>,
+    #[serde(rename = "boot-source", deserialize_with = "deserialize_boot_source")]
+    boot_source: Option<BootSourceConfig>,
+    #[serde(rename = "cloud-init")]
+    cloud_init: Option<CloudInitConfig>,
+    #[serde(rename = "console")]
+    console_config: Option<ConsoleConfig>,
     #[serde(rename = "drives")]
     block_devices: Vec<BlockDeviceConfig>,
+    #[serde(rename = "entropy")]
+    entropy_device: Option<EntropyDeviceConfig>,
+    #[serde(rename = "gpu")]
+    gpu_device: Option<GpuDeviceConfig>,
+    #[serde(rename = "input")]
+    input_device: Option<InputDeviceConfig>,
     #[serde(rename = "network-interfaces", default)]
     net_devices: Vec<NetworkInterfaceConfig>,
     #[serde(rename = "logger")]
     logger: Option<LoggerConfig>,
     #[serde(rename = "machine-config")]
     machine_config: Option<VmConfig>,
+    #[serde(rename = "memory-limits")]
+    memory_limits: Option<MemoryLimitsConfig>,
     #[serde(rename = "metrics")]
     metrics: Option<MetricsConfig>,
-    #[serde(rename = "vsock")]
+    #[serde(rename = "pci-passthrough", default)]
+    pci_passthrough_devices: Vec<PciPassthroughConfig>,
+    #[serde(rename = "probes", default)]
+    probes: Vec<ProbeConfig>,
+    #[serde(rename = "rate-limit-policy")]
+    rate_limit_policy: Option<RateLimitPolicyConfig>,
+    #[serde(rename = "rtc")]
+    rtc_config: Option<RtcConfig>,
+    #[cfg(feature = "sev")]
+    #[serde(rename = "sev")]
+    sev_config: Option<SevConfig>,
+    #[serde(rename = "shared-memory", default)]
+    shared_memory_devices: Vec<SharedMemoryConfig>,
+    #[serde(rename = "sound")]
+    sound_device: Option<SoundDeviceConfig>,
+    #[serde(rename = "virtio-features")]
+    virtio_feature_policy: Option<VirtioFeaturePolicyConfig>,
+    #[serde(rename = "vsock", skip_serializing)]
     vsock_device: Option<VsockDeviceConfig>,
+    #[serde(rename = "vsock-devices", default)]
+    vsock_devices: Vec<VsockDeviceConfig>,
     #[serde(rename = "mmds-config")]
     mmds_config: Option<MmdsConfig>,
 }
 
+// The boot source is required in the configuration file, although the configuration of a
+// microVM has none until it is set.
+fn deserialize_boot_source<'de, D>(d: D) -> std::result::Result<Option<BootSourceConfig>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    BootSourceConfig::deserialize(d).map(Some)
+}
+
+// Keeps the last of the configurations with the same ID, at the position of the first one, as
+// the builders do when a device is updated.
+fn dedup_by_id<T, F>(configs: Vec<T>, id: F) -> Vec<T>
+where
+    F: Fn(&T) -> &Identifier,
+{
+    let mut deduped: Vec<T> = Vec::new();
+    for config in configs.into_iter() {
+        match deduped.iter().position(|other| id(other) == id(&config)) {
+            Some(index) => deduped[index] = config,
+            None => deduped.push(config),
+        }
+    }
+    deduped
+}
+
+impl From<&VmResources> for VmmConfig {
+    fn from(resources: &VmResources) -> Self {
+        VmmConfig {
+            balloon_device: resources.balloon.get_config(),
+            boot_source: resources.boot_source_config.clone(),
+            cloud_init: resources.cloud_init.clone(),
+            console_config: Some(resources.console_config.clone()),
+            block_devices: resources.block.configs(),
+            entropy_device: resources.entropy.get_config(),
+            gpu_device: resources.gpu.get_config(),
+            input_device: resources.input.get_config(),
+            net_devices: resources.net_builder.configs(),
+            logger: resources.logger_config.clone(),
+            machine_config: Some(resources.vm_config.clone()),
+            memory_limits: Some(resources.memory_limits.clone()),
+            metrics: resources.metrics_config.clone(),
+            pci_passthrough_devices: resources.pci_passthrough.configs().to_vec(),
+            probes: resources.probes.configs().to_vec(),
+            rate_limit_policy: resources.rate_limit_policy.clone(),
+            rtc_config: resources.rtc_config.clone(),
+            #[cfg(feature = "sev")]
+            sev_config: resources.sev_config.clone(),
+            shared_memory_devices: resources.shared_memory.configs().to_vec(),
+            sound_device: resources.sound.get_config(),
+            virtio_feature_policy: Some(resources.virtio_feature_policy.clone()),
+            vsock_device: None,
+            vsock_devices: resources.vsock.configs(),
+            mmds_config: resources.mmds_config.clone(),
+        }
+    }
+}
+
 /// A data structure that encapsulates the device configurations
 /// held in the Vmm.
 #[derive(Default)]
@@ -73,43 +361,284 @@ pub struct VmResources {
     vm_config: VmConfig,
     /// The boot configuration for this microVM.
     boot_config: Option<BootConfig>,
+    /// The boot source configuration `boot_config` was set up from.
+    boot_source_config: Option<BootSourceConfig>,
+    /// The balloon device.
+    pub balloon: BalloonBuilder,
     /// The block devices.
     pub block: BlockBuilder,
-    /// The vsock device.
+    /// The entropy device.
+    pub entropy: EntropyBuilder,
+    /// The GPU device.
+    pub gpu: GpuBuilder,
+    /// The input device.
+    pub input: InputBuilder,
+    /// The sound device.
+    pub sound: SoundBuilder,
+    /// The vsock devices.
     pub vsock: VsockBuilder,
     /// The network devices builder.
     pub net_builder: NetBuilder,
+    /// The host PCI devices passed through to the guest.
+    pub pci_passthrough: PciPassthroughBuilder,
+    /// The host memory regions shared with the guest.
+    pub shared_memory: SharedMemoryBuilder,
+    /// The probes of the workload inside the guest.
+    pub probes: ProbeBuilder,
+    /// How the rate limiters of the devices are adjusted to the pressure on the host.
+    rate_limit_policy: Option<RateLimitPolicyConfig>,
+    /// The virtio features the devices may offer to their guest drivers, by device type.
+    virtio_feature_policy: VirtioFeaturePolicyConfig,
+    /// The cloud-init data handed to the guest.
+    cloud_init: Option<CloudInitConfig>,
     /// The configuration for `MmdsNetworkStack`.
     pub mmds_config: Option<MmdsConfig>,
+    /// The time the guest RTC starts at.
+    rtc_config: Option<RtcConfig>,
+    /// Where the input of the serial console comes from.
+    console_config: ConsoleConfig,
+    /// The memory limits of the process and the OOM policy.
+    memory_limits: MemoryLimitsConfig,
+    /// The SEV configuration, when launching the microVM as a SEV guest.
+    #[cfg(feature = "sev")]
+    sev_config: Option<SevConfig>,
+    /// The configuration the logger was initialized with.
+    logger_config: Option<LoggerConfig>,
+    /// The configuration the metrics system was initialized with.
+    metrics_config: Option<MetricsConfig>,
+    /// The file descriptors opened on behalf of Firecracker, used instead of the paths.
+    preopened_fds: Arc<PreopenedFds>,
+    /// The ID of the Firecracker instance, which the boot arguments can refer to.
+    instance_id: Option<String>,
 }
 
 impl VmResources {
-    /// Configures Vmm resources as described by the `config_json` param.
+    /// Configures Vmm resources as described by the `config_json` param, using `preopened_fds`
+    /// instead of the paths they were opened from.
     pub fn from_json(
         config_json: &str,
         firecracker_version: &str,
+        preopened_fds: PreopenedFds,
     ) -> std::result::Result<Self, Error> {
+        let mut vmm_config: VmmConfig = serde_json::from_slice::<VmmConfig>(config_json.as_bytes())
+            .map_err(Error::InvalidJson)?;
+
+        let logger = vmm_config.logger.take();
+        if let Some(logger) = logger.as_ref() {
+            init_logger(logger.clone(), firecracker_version).map_err(Error::Logger)?;
+        }
+
+        let metrics = vmm_config.metrics.take();
+        if let Some(metrics) = metrics.as_ref() {
+            init_metrics(metrics.clone()).map_err(Error::Metrics)?;
+        }
+
+        let memory_limits = vmm_config.memory_limits.take();
+        let mut resources = Self::from_vmm_config(vmm_config, preopened_fds)?;
+        resources.logger_config = logger;
+        resources.metrics_config = metrics;
+        if let Some(memory_limits) = memory_limits {
+            resources
+                .set_memory_limits(memory_limits)
+                .map_err(Error::MemoryLimits)?;
+        }
+        Ok(resources)
+    }
+
+    /// Runs the validation `from_json` does on `config_json` without building any device: the
+    /// files and the file descriptors it references are checked to exist, but are neither
+    /// opened nor duplicated, no tap is opened, no socket is bound and no guest CID is leased.
+    /// Checks as well that the kernel command line has room for the arguments describing the
+    /// devices, without initializing the logger and the metrics, nor setting the memory limits.
+    /// The configuration is then cross-checked, failing with all the problems found, whether
+    /// the configuration is strict or not.
+    pub fn validate_json(config_json: &str) -> std::result::Result<(), Error> {
         let vmm_config: VmmConfig = serde_json::from_slice::<VmmConfig>(config_json.as_bytes())
-            .map_err(|_| Error::InvalidJson)?;
+            .map_err(Error::InvalidJson)?;
+        if let Some(memory_limits) = vmm_config.memory_limits.as_ref() {
+            memory_limits.validate().map_err(Error::MemoryLimits)?;
+        }
+
+        // Only the resources set without side effects are set, the devices are merely checked.
+        let mut resources: Self = Self::default();
+        if let Some(machine_config) = vmm_config.machine_config {
+            resources
+                .set_vm_config(&machine_config)
+                .map_err(Error::VmConfig)?;
+        }
+
+        let cmdline = match vmm_config.boot_source.as_ref() {
+            Some(boot_source) => {
+                let kernel_image_path = boot_source.kernel_image_path.as_str();
+                fd_uri::check(kernel_image_path)
+                    .map_err(BootSourceConfigError::InvalidKernelPath)
+                    .map_err(Error::BootSource)?;
+                if let Some(initrd_path) = boot_source.initrd_path.as_ref() {
+                    fd_uri::check(initrd_path)
+                        .map_err(BootSourceConfigError::InvalidInitrdPath)
+                        .map_err(Error::BootSource)?;
+                }
+                Some(Self::boot_cmdline(boot_source).map_err(Error::BootSource)?)
+            }
+            None => None,
+        };
+
+        let block_configs = dedup_by_id(vmm_config.block_devices, |config| &config.drive_id);
+        for config in block_configs.iter() {
+            fd_uri::check(&config.path_on_host)
+                .map_err(DriveError::InvalidBlockDevicePath)
+                .and_then(|_| BlockBuilder::validate(config))
+                .map_err(Error::BlockDevice)?;
+        }
+        let root_drives = block_configs.iter().filter(|config| config.is_root_device);
+        if root_drives.count() > 1 {
+            return Err(Error::BlockDevice(DriveError::RootBlockDeviceAlreadyAdded));
+        }
+
+        let net_configs = dedup_by_id(vmm_config.net_devices, |config| &config.iface_id);
+        for config in net_configs.iter() {
+            NetBuilder::validate(config).map_err(Error::NetDevice)?;
+        }
+
+        for pci_passthrough_config in vmm_config.pci_passthrough_devices.into_iter() {
+            resources
+                .set_pci_passthrough_device(pci_passthrough_config)
+                .map_err(Error::PciPassthroughDevice)?;
+        }
+
+        for shared_memory_config in vmm_config.shared_memory_devices.into_iter() {
+            resources
+                .set_shared_memory_device(shared_memory_config)
+                .map_err(Error::SharedMemoryDevice)?;
+        }
+
+        let vsock_configs = dedup_by_id(
+            vmm_config
+                .vsock_device
+                .into_iter()
+                .chain(vmm_config.vsock_devices.into_iter())
+                .collect(),
+            |config| &config.vsock_id,
+        );
+        for config in vsock_configs.iter() {
+            VsockBuilder::validate(config).map_err(Error::VsockDevice)?;
+        }
+
+        for probe_config in vmm_config.probes.into_iter() {
+            resources
+                .probes
+                .insert(probe_config, &vsock_configs)
+                .map_err(Error::Probe)?;
+        }
+
+        if let Some(balloon_config) = vmm_config.balloon_device.as_ref() {
+            resources
+                .validate_balloon_device(balloon_config)
+                .map_err(Error::BalloonDevice)?;
+        }
+
+        if let Some(gpu_config) = vmm_config.gpu_device.as_ref() {
+            GpuBuilder::validate(gpu_config).map_err(Error::GpuDevice)?;
+        }
+
+        if let Some(sound_config) = vmm_config.sound_device.as_ref() {
+            SoundBuilder::validate(sound_config).map_err(Error::SoundDevice)?;
+        }
+
+        if let Some(policy) = vmm_config.rate_limit_policy {
+            resources
+                .set_rate_limit_policy(policy)
+                .map_err(Error::RateLimitPolicy)?;
+        }
+
+        if let Some(policy) = vmm_config.virtio_feature_policy {
+            resources
+                .set_virtio_feature_policy(policy)
+                .map_err(Error::VirtioFeaturePolicy)?;
+        }
+
+        if let Some(cloud_init) = vmm_config.cloud_init {
+            resources
+                .set_cloud_init(cloud_init)
+                .map_err(Error::CloudInit)?;
+        }
+
+        if let Some(rtc_config) = vmm_config.rtc_config {
+            resources
+                .set_rtc_config(rtc_config)
+                .map_err(Error::RtcConfig)?;
+        }
+
+        #[cfg(feature = "sev")]
+        {
+            if let Some(sev_config) = vmm_config.sev_config {
+                resources
+                    .set_sev_config(sev_config)
+                    .map_err(Error::SevConfig)?;
+            }
+        }
 
-        if let Some(logger) = vmm_config.logger {
-            init_logger(logger, firecracker_version).map_err(Error::Logger)?;
+        if let Some(console_config) = vmm_config.console_config {
+            resources
+                .set_console_config(console_config)
+                .map_err(Error::ConsoleConfig)?;
+        }
+
+        if let Some(mmds_config) = vmm_config.mmds_config {
+            resources
+                .set_mmds_config(mmds_config)
+                .map_err(Error::MmdsConfig)?;
+        }
+
+        if let (Some(boot_source), Some(cmdline)) = (vmm_config.boot_source.as_ref(), cmdline) {
+            let root_drive = block_configs.iter().find(|config| config.is_root_device);
+            let device_count = block_configs.len()
+                + net_configs.len()
+                + vsock_configs.len()
+                + [
+                    vmm_config.balloon_device.is_some(),
+                    vmm_config.entropy_device.is_some(),
+                    vmm_config.gpu_device.is_some(),
+                    vmm_config.input_device.is_some(),
+                    vmm_config.sound_device.is_some(),
+                    resources.has_seed_drive(),
+                ]
+                .iter()
+                .filter(|&&present| present)
+                .count();
+            Self::validate_kernel_cmdline(cmdline, boot_source, root_drive, device_count)
+                .map_err(Error::BootSource)?;
         }
 
-        if let Some(metrics) = vmm_config.metrics {
-            init_metrics(metrics).map_err(Error::Metrics)?;
+        let issues = Self::issues_of(
+            vmm_config.boot_source.as_ref(),
+            &block_configs,
+            &net_configs,
+            &vsock_configs,
+        );
+        if !issues.is_empty() {
+            return Err(Error::InconsistentConfig(issues));
         }
+        Ok(())
+    }
 
+    fn from_vmm_config(
+        vmm_config: VmmConfig,
+        preopened_fds: PreopenedFds,
+    ) -> std::result::Result<Self, Error> {
         let mut resources: Self = Self::default();
+        resources.set_preopened_fds(preopened_fds);
         if let Some(machine_config) = vmm_config.machine_config {
             resources
                 .set_vm_config(&machine_config)
                 .map_err(Error::VmConfig)?;
         }
 
-        resources
-            .set_boot_source(vmm_config.boot_source)
-            .map_err(Error::BootSource)?;
+        if let Some(boot_source) = vmm_config.boot_source {
+            resources
+                .set_boot_source(boot_source)
+                .map_err(Error::BootSource)?;
+        }
 
         for drive_config in vmm_config.block_devices.into_iter() {
             resources
@@ -123,12 +652,102 @@ impl VmResources {
                 .map_err(Error::NetDevice)?;
         }
 
-        if let Some(vsock_config) = vmm_config.vsock_device {
+        for pci_passthrough_config in vmm_config.pci_passthrough_devices.into_iter() {
+            resources
+                .set_pci_passthrough_device(pci_passthrough_config)
+                .map_err(Error::PciPassthroughDevice)?;
+        }
+
+        for shared_memory_config in vmm_config.shared_memory_devices.into_iter() {
+            resources
+                .set_shared_memory_device(shared_memory_config)
+                .map_err(Error::SharedMemoryDevice)?;
+        }
+
+        for vsock_config in vmm_config
+            .vsock_device
+            .into_iter()
+            .chain(vmm_config.vsock_devices.into_iter())
+        {
             resources
-                .set_vsock_device(vsock_config)
+                .insert_vsock_device(vsock_config)
                 .map_err(Error::VsockDevice)?;
         }
 
+        // The vsock probes connect through the vsock devices.
+        for probe_config in vmm_config.probes.into_iter() {
+            resources.set_probe(probe_config).map_err(Error::Probe)?;
+        }
+
+        if let Some(balloon_config) = vmm_config.balloon_device {
+            resources
+                .set_balloon_device(balloon_config)
+                .map_err(Error::BalloonDevice)?;
+        }
+
+        if let Some(entropy_config) = vmm_config.entropy_device {
+            resources
+                .set_entropy_device(entropy_config)
+                .map_err(Error::EntropyDevice)?;
+        }
+
+        if let Some(gpu_config) = vmm_config.gpu_device {
+            resources
+                .set_gpu_device(gpu_config)
+                .map_err(Error::GpuDevice)?;
+        }
+
+        if let Some(input_config) = vmm_config.input_device {
+            resources
+                .set_input_device(input_config)
+                .map_err(Error::InputDevice)?;
+        }
+
+        if let Some(sound_config) = vmm_config.sound_device {
+            resources
+                .set_sound_device(sound_config)
+                .map_err(Error::SoundDevice)?;
+        }
+
+        if let Some(policy) = vmm_config.rate_limit_policy {
+            resources
+                .set_rate_limit_policy(policy)
+                .map_err(Error::RateLimitPolicy)?;
+        }
+
+        if let Some(policy) = vmm_config.virtio_feature_policy {
+            resources
+                .set_virtio_feature_policy(policy)
+                .map_err(Error::VirtioFeaturePolicy)?;
+        }
+
+        if let Some(cloud_init) = vmm_config.cloud_init {
+            resources
+                .set_cloud_init(cloud_init)
+                .map_err(Error::CloudInit)?;
+        }
+
+        if let Some(rtc_config) = vmm_config.rtc_config {
+            resources
+                .set_rtc_config(rtc_config)
+                .map_err(Error::RtcConfig)?;
+        }
+
+        #[cfg(feature = "sev")]
+        {
+            if let Some(sev_config) = vmm_config.sev_config {
+                resources
+                    .set_sev_config(sev_config)
+                    .map_err(Error::SevConfig)?;
+            }
+        }
+
+        if let Some(console_config) = vmm_config.console_config {
+            resources
+                .set_console_config(console_config)
+                .map_err(Error::ConsoleConfig)?;
+        }
+
         if let Some(mmds_config) = vmm_config.mmds_config {
             resources
                 .set_mmds_config(mmds_config)
@@ -138,6 +757,189 @@ impl VmResources {
         Ok(resources)
     }
 
+    // Checks that the kernel command line `cmdline` of `boot_source` has room for the arguments
+    // the builder appends to it when attaching the `root_drive` and the `device_count` devices.
+    #[cfg_attr(target_arch = "aarch64", allow(unused_variables))]
+    fn validate_kernel_cmdline(
+        mut cmdline: kernel::cmdline::Cmdline,
+        boot_source: &BootSourceConfig,
+        root_drive: Option<&BlockDeviceConfig>,
+        device_count: usize,
+    ) -> Result<BootSourceConfigError> {
+        let to_config_error = |e: kernel::cmdline::Error| {
+            BootSourceConfigError::InvalidKernelCommandLine(e.to_string())
+        };
+
+        if let Some(root_drive) = root_drive {
+            let (partuuid, is_read_only) = (root_drive.partuuid.as_ref(), root_drive.is_read_only);
+            insert_root_device_args(&mut cmdline, partuuid, is_read_only)
+                .map_err(to_config_error)?;
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            if boot_source.device_enumeration == MmioDeviceEnumeration::Firmware {
+                return Ok(());
+            }
+            for _ in 0..device_count {
+                MMIODeviceManager::reserve_device_cmdline(&mut cmdline)
+                    .map_err(|e| BootSourceConfigError::InvalidKernelCommandLine(e.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Returns whether the cloud-init data is handed to the guest on a drive of its own.
+    #[cfg(target_arch = "x86_64")]
+    fn has_seed_drive(&self) -> bool {
+        self.cloud_init().map_or(false, |cloud_init| {
+            cloud_init.transport == CloudInitTransport::Seed
+        })
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn has_seed_drive(&self) -> bool {
+        false
+    }
+
+    /// Cross-checks the resources, which are each valid on their own, and returns all the
+    /// problems found.
+    pub fn config_issues(&self) -> ConfigIssues {
+        Self::issues_of(
+            self.boot_source_config.as_ref(),
+            &self.block.configs(),
+            &self.net_builder.configs(),
+            &self.vsock.configs(),
+        )
+    }
+
+    // Cross-checks the configurations of the boot source and of the devices.
+    fn issues_of(
+        boot_source: Option<&BootSourceConfig>,
+        block_configs: &[BlockDeviceConfig],
+        net_configs: &[NetworkInterfaceConfig],
+        vsock_configs: &[VsockDeviceConfig],
+    ) -> ConfigIssues {
+        let mut issues = Vec::new();
+
+        for (index, config) in net_configs.iter().enumerate() {
+            let resource = format!("/network-interfaces/{}", config.iface_id);
+            if let Some(guest_mac) = config.guest_mac.as_ref() {
+                let other = net_configs[..index]
+                    .iter()
+                    .find(|other| other.guest_mac.as_ref() == Some(guest_mac));
+                if let Some(other) = other {
+                    issues.push(ConfigIssue::new(
+                        ConfigIssueKind::DuplicateGuestMac,
+                        resource.clone(),
+                        format!(
+                            "The guest MAC address {} is also used by the network interface {}.",
+                            guest_mac, other.iface_id
+                        ),
+                    ));
+                }
+            }
+            let rx_rate_limiter = config.rx_rate_limiter;
+            Self::check_rate_limiter(&mut issues, &resource, "rx rate limiter", rx_rate_limiter);
+            let tx_rate_limiter = config.tx_rate_limiter;
+            Self::check_rate_limiter(&mut issues, &resource, "tx rate limiter", tx_rate_limiter);
+        }
+
+        for config in block_configs.iter() {
+            let resource = format!("/drives/{}", config.drive_id);
+            Self::check_rate_limiter(&mut issues, &resource, "rate limiter", config.rate_limiter);
+        }
+
+        for (index, config) in vsock_configs.iter().enumerate() {
+            let resource = format!("/vsock/{}", config.vsock_id);
+            // The CIDs leased from a pool, which holds no reserved CID, are only known once
+            // leased.
+            if config.cid_pool.is_some() && config.guest_cid == 0 {
+                continue;
+            }
+            // CIDs 0 to 2 address the hypervisor, the local host and the host, while the
+            // highest CID addresses any.
+            if config.guest_cid < 3 || config.guest_cid == u32::max_value() {
+                issues.push(ConfigIssue::new(
+                    ConfigIssueKind::ReservedVsockCid,
+                    resource.clone(),
+                    format!(
+                        "The guest CID {} is reserved. The guest CIDs start at 3.",
+                        config.guest_cid
+                    ),
+                ));
+            }
+            let other = vsock_configs[..index]
+                .iter()
+                .find(|other| other.guest_cid == config.guest_cid);
+            if let Some(other) = other {
+                issues.push(ConfigIssue::new(
+                    ConfigIssueKind::DuplicateVsockCid,
+                    resource,
+                    format!(
+                        "The guest CID {} is also used by the vsock device {}.",
+                        config.guest_cid, other.vsock_id
+                    ),
+                ));
+            }
+        }
+
+        if let Some(boot_source) = boot_source {
+            let boot_args = boot_source
+                .boot_args
+                .as_ref()
+                .map_or(DEFAULT_KERNEL_CMDLINE, String::as_str);
+            let has_root_arg = boot_args
+                .split_whitespace()
+                .any(|arg| arg.starts_with("root="));
+            let has_root_drive = block_configs.iter().any(|config| config.is_root_device);
+            if !has_root_drive && boot_source.initrd_path.is_none() && !has_root_arg {
+                issues.push(ConfigIssue::new(
+                    ConfigIssueKind::MissingRootDevice,
+                    "/boot-source".to_string(),
+                    "The guest has neither a root drive, an initrd, nor a root= boot argument \
+                     to mount its root filesystem from."
+                        .to_string(),
+                ));
+            }
+        }
+
+        ConfigIssues(issues)
+    }
+
+    // Reports the token buckets of `rate_limiter` which are left unlimited by a size or a refill
+    // time of 0. `name` tells the rate limiter apart from the others of the device.
+    fn check_rate_limiter(
+        issues: &mut Vec<ConfigIssue>,
+        resource: &str,
+        name: &str,
+        rate_limiter: Option<RateLimiterConfig>,
+    ) {
+        let rate_limiter = match rate_limiter {
+            Some(rate_limiter) => rate_limiter,
+            None => return,
+        };
+        let buckets = [
+            ("bandwidth", rate_limiter.bandwidth),
+            ("ops", rate_limiter.ops),
+        ];
+        for (bucket_name, bucket) in buckets.iter() {
+            if let Some(bucket) = bucket {
+                if bucket.size == 0 || bucket.refill_time == 0 {
+                    issues.push(ConfigIssue::new(
+                        ConfigIssueKind::ZeroTokenBucket,
+                        resource.to_string(),
+                        format!(
+                            "The {} bucket of the {} has a size or a refill time of 0, which \
+                             leaves it unlimited.",
+                            bucket_name, name
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+
     /// Returns a VcpuConfig based on the vm config.
     pub fn vcpu_config(&self) -> VcpuConfig {
         // The unwraps are ok to use because the values are initialized using defaults if not
@@ -145,7 +947,14 @@ impl VmResources {
         VcpuConfig {
             vcpu_count: self.vm_config().vcpu_count.unwrap(),
             ht_enabled: self.vm_config().ht_enabled.unwrap(),
+            cores_per_socket: self.vm_config().cores_per_socket,
             cpu_template: self.vm_config().cpu_template,
+            cpu_features: self.vm_config().cpu_features.clone(),
+            nested: self.vm_config().nested,
+            tsc_khz: self.vm_config().tsc_khz,
+            tickless: self.vm_config().tickless,
+            // Only known once the SEV context of the VM is set up.
+            memory_encryption_mask: 0,
         }
     }
 
@@ -183,10 +992,67 @@ impl VmResources {
             return Err(VmConfigError::InvalidVcpuCount);
         }
 
+        let mut new_vm_config = self.vm_config.clone();
+        new_vm_config.vcpu_count = Some(vcpu_count_value);
+        new_vm_config.ht_enabled = Some(ht_enabled);
+        if machine_config.cores_per_socket.is_some() {
+            new_vm_config.cores_per_socket = machine_config.cores_per_socket;
+        }
+        Self::validate_cpu_topology(&new_vm_config)?;
+        if let Some(ref cpu_features) = machine_config.cpu_features {
+            Self::validate_cpu_features(cpu_features)?;
+        }
+        Self::validate_nested(machine_config.nested)?;
+        if machine_config.tsc_khz.is_some() {
+            new_vm_config.tsc_khz = machine_config.tsc_khz;
+        }
+        Self::validate_tsc_khz(new_vm_config.tsc_khz)?;
+        if machine_config.gic_version.is_some() {
+            new_vm_config.gic_version = machine_config.gic_version;
+        }
+        new_vm_config.gic_its = machine_config.gic_its;
+        Self::validate_gic(&new_vm_config)?;
+        Self::validate_split_irqchip(machine_config.split_irqchip)?;
+        Self::validate_tickless(machine_config.tickless)?;
+        if machine_config.max_device_irq_rate == Some(0) {
+            return Err(VmConfigError::InvalidDeviceIrqRate);
+        }
+        let vcpu_retry = machine_config.vcpu_retry;
+        if vcpu_retry.initial_backoff_us > vcpu_retry.max_backoff_us {
+            return Err(VmConfigError::InvalidVcpuRetry);
+        }
+
+        if machine_config.mmio32_hole_size_mib.is_some() {
+            new_vm_config.mmio32_hole_size_mib = machine_config.mmio32_hole_size_mib;
+        }
+        if machine_config.mmio64_window_size_mib.is_some() {
+            new_vm_config.mmio64_window_size_mib = machine_config.mmio64_window_size_mib;
+        }
+        if machine_config.mem_size_mib.is_some() {
+            new_vm_config.mem_size_mib = machine_config.mem_size_mib;
+        }
+        Self::validate_memory_layout(&new_vm_config)?;
+
         // Update all the fields that have a new value.
         self.vm_config.vcpu_count = Some(vcpu_count_value);
         self.vm_config.ht_enabled = Some(ht_enabled);
+        self.vm_config.cores_per_socket = new_vm_config.cores_per_socket;
+        self.vm_config.nested = machine_config.nested;
+        self.vm_config.tsc_khz = new_vm_config.tsc_khz;
         self.vm_config.track_dirty_pages = machine_config.track_dirty_pages;
+        self.vm_config.memfd_backed = machine_config.memfd_backed;
+        self.vm_config.mmio32_hole_size_mib = new_vm_config.mmio32_hole_size_mib;
+        self.vm_config.mmio64_window_size_mib = new_vm_config.mmio64_window_size_mib;
+        self.vm_config.gic_version = new_vm_config.gic_version;
+        self.vm_config.gic_its = new_vm_config.gic_its;
+        self.vm_config.split_irqchip = machine_config.split_irqchip;
+        self.vm_config.tickless = machine_config.tickless;
+        if machine_config.max_device_irq_rate.is_some() {
+            self.vm_config.max_device_irq_rate = machine_config.max_device_irq_rate;
+        }
+        self.vm_config.legacy_devices = machine_config.legacy_devices;
+        self.vm_config.vcpu_retry = vcpu_retry;
+        self.vm_config.strict_config = machine_config.strict_config;
 
         if machine_config.mem_size_mib.is_some() {
             self.vm_config.mem_size_mib = machine_config.mem_size_mib;
@@ -196,61 +1062,281 @@ impl VmResources {
             self.vm_config.cpu_template = machine_config.cpu_template;
         }
 
+        if machine_config.cpu_features.is_some() {
+            self.vm_config.cpu_features = machine_config.cpu_features.clone();
+        }
+
         Ok(())
     }
 
-    /// Gets a reference to the boot source configuration.
-    pub fn boot_source(&self) -> Option<&BootConfig> {
-        self.boot_config.as_ref()
+    // Checks that the vCPUs configured in `vm_config` can be laid out in sockets.
+    #[cfg(target_arch = "x86_64")]
+    fn validate_cpu_topology(vm_config: &VmConfig) -> Result<VmConfigError> {
+        // The vCPU count and hyperthreading are always set in `vm_config`.
+        cpuid::CpuTopology::new(
+            vm_config.vcpu_count.unwrap(),
+            vm_config.ht_enabled.unwrap(),
+            vm_config.cores_per_socket,
+        )
+        .map(|_| ())
+        .map_err(|_| VmConfigError::InvalidCoresPerSocket)
     }
 
-    /// Set the guest boot source configuration.
-    pub fn set_boot_source(
-        &mut self,
-        boot_source_cfg: BootSourceConfig,
-    ) -> Result<BootSourceConfigError> {
-        use self::BootSourceConfigError::{
-            InvalidInitrdPath, InvalidKernelCommandLine, InvalidKernelPath,
-        };
+    #[cfg(target_arch = "aarch64")]
+    fn validate_cpu_topology(vm_config: &VmConfig) -> Result<VmConfigError> {
+        if vm_config.cores_per_socket.is_some() {
+            return Err(VmConfigError::CpuTopologyNotSupported);
+        }
+        Ok(())
+    }
 
-        // Validate boot source config.
-        let kernel_file =
-            File::open(&boot_source_cfg.kernel_image_path).map_err(InvalidKernelPath)?;
-        let initrd_file: Option<File> = match &boot_source_cfg.initrd_path {
-            Some(path) => Some(File::open(path).map_err(InvalidInitrdPath)?),
-            None => None,
-        };
-        let mut cmdline = kernel::cmdline::Cmdline::new(arch::CMDLINE_MAX_SIZE);
-        let boot_args = match boot_source_cfg.boot_args.as_ref() {
-            None => DEFAULT_KERNEL_CMDLINE,
-            Some(str) => str.as_str(),
-        };
-        cmdline
-            .insert_str(boot_args)
-            .map_err(|e| InvalidKernelCommandLine(e.to_string()))?;
+    // Checks that the features listed in `cpu_features` are known.
+    #[cfg(target_arch = "x86_64")]
+    fn validate_cpu_features(cpu_features: &CpuFeaturesConfig) -> Result<VmConfigError> {
+        if let CpuFeaturesConfig::List(ref names) = *cpu_features {
+            if let Some(name) = names
+                .iter()
+                .find(|name| cpuid::find_cpu_feature(name).is_none())
+            {
+                return Err(VmConfigError::InvalidCpuFeature(name.clone()));
+            }
+        }
+        Ok(())
+    }
 
-        self.boot_config = Some(BootConfig {
-            cmdline,
-            kernel_file,
-            initrd_file,
-        });
+    #[cfg(target_arch = "aarch64")]
+    fn validate_cpu_features(_cpu_features: &CpuFeaturesConfig) -> Result<VmConfigError> {
+        Err(VmConfigError::CpuFeaturesNotSupported)
+    }
+
+    // Checks that nested virtualization can be enabled on this architecture. Whether the host
+    // allows it is only known when configuring the vCPUs.
+    #[cfg(target_arch = "x86_64")]
+    fn validate_nested(_nested: bool) -> Result<VmConfigError> {
         Ok(())
     }
 
-    /// Inserts a block to be attached when the VM starts.
-    // Only call this function as part of user configuration.
-    // If the drive_id does not exist, a new Block Device Config is added to the list.
-    pub fn set_block_device(
-        &mut self,
-        block_device_config: BlockDeviceConfig,
-    ) -> Result<DriveError> {
-        self.block.insert(block_device_config)
+    #[cfg(target_arch = "aarch64")]
+    fn validate_nested(nested: bool) -> Result<VmConfigError> {
+        if nested {
+            return Err(VmConfigError::NestedVirtualizationNotSupported);
+        }
+        Ok(())
     }
 
-    /// Builds a network device to be attached when the VM starts.
-    pub fn build_net_device(
-        &mut self,
-        body: NetworkInterfaceConfig,
+    // Checks that the TSC frequency can be configured on this architecture. Whether KVM can
+    // scale the TSC to it is only known when configuring the vCPUs.
+    #[cfg(target_arch = "x86_64")]
+    fn validate_tsc_khz(tsc_khz: Option<u32>) -> Result<VmConfigError> {
+        if tsc_khz == Some(0) {
+            return Err(VmConfigError::InvalidTscFrequency);
+        }
+        Ok(())
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn validate_tsc_khz(tsc_khz: Option<u32>) -> Result<VmConfigError> {
+        if tsc_khz.is_some() {
+            return Err(VmConfigError::TscFrequencyNotSupported);
+        }
+        Ok(())
+    }
+
+    // Checks that the interrupt controller configured in `vm_config` can be created.
+    #[cfg(target_arch = "x86_64")]
+    fn validate_gic(vm_config: &VmConfig) -> Result<VmConfigError> {
+        if vm_config.gic_version.is_some() || vm_config.gic_its {
+            return Err(VmConfigError::GicNotSupported);
+        }
+        Ok(())
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn validate_gic(vm_config: &VmConfig) -> Result<VmConfigError> {
+        use vmm_config::machine_config::GICV2_MAX_VCPUS;
+
+        // The vCPU count is always set in `vm_config`.
+        if vm_config.gic_version == Some(GicVersion::V2)
+            && (vm_config.gic_its || vm_config.vcpu_count.unwrap() > GICV2_MAX_VCPUS)
+        {
+            return Err(VmConfigError::InvalidGicConfig);
+        }
+        Ok(())
+    }
+
+    // Checks that the irqchip can be split on this architecture. Whether KVM can split it is
+    // only known when creating the VM.
+    #[cfg(target_arch = "x86_64")]
+    fn validate_split_irqchip(_split_irqchip: bool) -> Result<VmConfigError> {
+        Ok(())
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn validate_split_irqchip(split_irqchip: bool) -> Result<VmConfigError> {
+        if split_irqchip {
+            return Err(VmConfigError::SplitIrqchipNotSupported);
+        }
+        Ok(())
+    }
+
+    // Checks that the PIT can be omitted on this architecture. Whether KVM has the TSC deadline
+    // timer the guest relies on instead is only known when creating the VM.
+    #[cfg(target_arch = "x86_64")]
+    fn validate_tickless(_tickless: bool) -> Result<VmConfigError> {
+        Ok(())
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn validate_tickless(tickless: bool) -> Result<VmConfigError> {
+        if tickless {
+            return Err(VmConfigError::TicklessNotSupported);
+        }
+        Ok(())
+    }
+
+    // Checks that the guest memory and the MMIO regions configured in `vm_config` fit in the
+    // guest physical address space.
+    #[cfg(target_arch = "x86_64")]
+    fn validate_memory_layout(vm_config: &VmConfig) -> Result<VmConfigError> {
+        use arch::x86_64::{guest_phys_addr_end, MMIO32_HOLE_MAX_SIZE, MMIO32_HOLE_MIN_SIZE};
+
+        let layout = vm_config.memory_layout();
+        if layout.mmio32_hole_size < MMIO32_HOLE_MIN_SIZE
+            || layout.mmio32_hole_size > MMIO32_HOLE_MAX_SIZE
+        {
+            return Err(VmConfigError::InvalidMmio32HoleSize);
+        }
+        // The memory size is validated separately.
+        let mem_size = vm_config.mem_size_mib.unwrap_or(0) << 20;
+        let addr_end = guest_phys_addr_end();
+        let mmio64_start = layout.mmio64_start(mem_size);
+        if mmio64_start > addr_end {
+            return Err(VmConfigError::GuestMemoryTooLarge(
+                layout.max_memory_size(addr_end) >> 20,
+            ));
+        }
+        match mmio64_start.checked_add(layout.mmio64_window_size) {
+            Some(mmio64_end) if mmio64_end <= addr_end => Ok(()),
+            _ => Err(VmConfigError::InvalidMmio64WindowSize),
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn validate_memory_layout(vm_config: &VmConfig) -> Result<VmConfigError> {
+        use arch::aarch64::layout::DRAM_MEM_MAX_SIZE;
+
+        if vm_config.mmio32_hole_size_mib.is_some() || vm_config.mmio64_window_size_mib.is_some() {
+            return Err(VmConfigError::MemoryLayoutNotSupported);
+        }
+        // The guest memory would otherwise be silently truncated.
+        if (vm_config.mem_size_mib.unwrap_or(0) as u64) << 20 > DRAM_MEM_MAX_SIZE {
+            return Err(VmConfigError::GuestMemoryTooLarge(DRAM_MEM_MAX_SIZE >> 20));
+        }
+        Ok(())
+    }
+
+    /// Gets a reference to the boot source configuration.
+    pub fn boot_source(&self) -> Option<&BootConfig> {
+        self.boot_config.as_ref()
+    }
+
+    /// Set the guest boot source configuration.
+    pub fn set_boot_source(
+        &mut self,
+        boot_source_cfg: BootSourceConfig,
+    ) -> Result<BootSourceConfigError> {
+        use self::BootSourceConfigError::{InvalidInitrdPath, InvalidKernelPath};
+
+        // Validate boot source config.
+        let kernel_image = match boot_source_cfg.kernel_bytes {
+            Some(ref kernel_bytes) => KernelImage::Bytes(kernel_bytes.clone()),
+            None => KernelImage::File(
+                fd_uri::open(&boot_source_cfg.kernel_image_path).map_err(InvalidKernelPath)?,
+            ),
+        };
+        let initrd_file: Option<File> = match &boot_source_cfg.initrd_path {
+            Some(path) => Some(fd_uri::open(path).map_err(InvalidInitrdPath)?),
+            None => None,
+        };
+        let cmdline = Self::boot_cmdline(&boot_source_cfg)?;
+
+        self.boot_config = Some(BootConfig {
+            cmdline,
+            kernel_image,
+            initrd_file,
+            device_enumeration: boot_source_cfg.device_enumeration,
+        });
+        self.boot_source_config = Some(boot_source_cfg);
+        Ok(())
+    }
+
+    // Returns the kernel command line holding the boot arguments of `boot_source_cfg`.
+    fn boot_cmdline(
+        boot_source_cfg: &BootSourceConfig,
+    ) -> std::result::Result<kernel::cmdline::Cmdline, BootSourceConfigError> {
+        use self::BootSourceConfigError::InvalidKernelCommandLine;
+
+        let mut cmdline = kernel::cmdline::Cmdline::new(arch::CMDLINE_MAX_SIZE);
+        let boot_args = match boot_source_cfg.boot_args.as_ref() {
+            None => DEFAULT_KERNEL_CMDLINE,
+            Some(str) => str.as_str(),
+        };
+        // The placeholders are filled when the microVM boots, only check that they can be.
+        fill_boot_args(boot_args, |_| Some(String::new()))
+            .map_err(|e| InvalidKernelCommandLine(e.to_string()))?;
+        cmdline
+            .insert_str(boot_args)
+            .map_err(|e| InvalidKernelCommandLine(e.to_string()))?;
+        Ok(cmdline)
+    }
+
+    /// Uses `preopened_fds` instead of the paths they were opened from, for the devices
+    /// configured from now on and for the KVM VM.
+    pub fn set_preopened_fds(&mut self, preopened_fds: PreopenedFds) {
+        let preopened_fds = Arc::new(preopened_fds);
+        self.block.set_preopened_fds(preopened_fds.clone());
+        self.net_builder.set_preopened_fds(preopened_fds.clone());
+        self.preopened_fds = preopened_fds;
+    }
+
+    /// Returns the file descriptors opened on behalf of Firecracker.
+    pub fn preopened_fds(&self) -> &PreopenedFds {
+        &self.preopened_fds
+    }
+
+    /// Sets the ID of the Firecracker instance, which the boot arguments can refer to.
+    pub fn set_instance_id(&mut self, instance_id: String) {
+        // The CIDs of the vsock devices already configured are leased on behalf of the instance.
+        if let Err(err) = self.vsock.set_instance_id(&instance_id) {
+            warn!("Cannot record the instance ID in the CID registry: {}", err);
+        }
+        self.instance_id = Some(instance_id);
+    }
+
+    /// Returns the ID of the Firecracker instance, if set.
+    pub fn instance_id(&self) -> Option<&str> {
+        self.instance_id.as_ref().map(String::as_str)
+    }
+
+    /// Inserts a block to be attached when the VM starts.
+    // Only call this function as part of user configuration.
+    // If the drive_id does not exist, a new Block Device Config is added to the list.
+    pub fn set_block_device(
+        &mut self,
+        block_device_config: BlockDeviceConfig,
+    ) -> Result<DriveError> {
+        self.block.insert(block_device_config)
+    }
+
+    /// Removes the block device `drive_id`, so that it isn't attached when the VM starts.
+    pub fn remove_block_device(&mut self, drive_id: &str) -> Result<DriveError> {
+        self.block.remove(drive_id)
+    }
+
+    /// Builds a network device to be attached when the VM starts.
+    pub fn build_net_device(
+        &mut self,
+        body: NetworkInterfaceConfig,
     ) -> Result<NetworkInterfaceError> {
         self.net_builder.build(body).map(|net_device| {
             // Update `Net` device `MmdsNetworkStack` IPv4 address.
@@ -265,11 +1351,201 @@ impl VmResources {
         })
     }
 
-    /// Sets a vsock device to be attached when the VM starts.
+    /// Removes the network device `iface_id`, so that it isn't attached when the VM starts.
+    pub fn remove_net_device(&mut self, iface_id: &str) -> Result<NetworkInterfaceError> {
+        self.net_builder.remove(iface_id)
+    }
+
+    /// Sets the only vsock device to be attached when the VM starts, replacing the vsock
+    /// devices set before.
     pub fn set_vsock_device(&mut self, config: VsockDeviceConfig) -> Result<VsockConfigError> {
+        self.vsock.set(config)
+    }
+
+    /// Adds a vsock device to be attached when the VM starts, or updates the vsock device with
+    /// the same ID.
+    pub fn insert_vsock_device(&mut self, config: VsockDeviceConfig) -> Result<VsockConfigError> {
         self.vsock.insert(config)
     }
 
+    /// Sets a balloon device to be attached when the VM starts.
+    pub fn set_balloon_device(
+        &mut self,
+        config: BalloonDeviceConfig,
+    ) -> Result<BalloonConfigError> {
+        self.validate_balloon_device(&config)?;
+        self.balloon.set(config)
+    }
+
+    fn validate_balloon_device(&self, config: &BalloonDeviceConfig) -> Result<BalloonConfigError> {
+        // The balloon cannot have a target size greater than the size of the guest memory.
+        if config.amount_mib as usize > self.vm_config.mem_size_mib.unwrap() {
+            return Err(BalloonConfigError::TooManyPagesRequested);
+        }
+        Ok(())
+    }
+
+    /// Sets an entropy device to be attached when the VM starts.
+    pub fn set_entropy_device(
+        &mut self,
+        config: EntropyDeviceConfig,
+    ) -> Result<EntropyConfigError> {
+        self.entropy.set(config)
+    }
+
+    /// Sets a GPU device to be attached when the VM starts.
+    pub fn set_gpu_device(&mut self, config: GpuDeviceConfig) -> Result<GpuConfigError> {
+        self.gpu.set(config)
+    }
+
+    /// Sets an input device to be attached when the VM starts.
+    pub fn set_input_device(&mut self, config: InputDeviceConfig) -> Result<InputConfigError> {
+        self.input.set(config)
+    }
+
+    /// Sets a sound device to be attached when the VM starts.
+    pub fn set_sound_device(&mut self, config: SoundDeviceConfig) -> Result<SoundConfigError> {
+        self.sound.set(config)
+    }
+
+    /// Adds a host PCI device to pass through to the guest when the VM starts, or updates the
+    /// one with the same ID.
+    pub fn set_pci_passthrough_device(
+        &mut self,
+        config: PciPassthroughConfig,
+    ) -> Result<PciPassthroughConfigError> {
+        self.pci_passthrough.insert(config)
+    }
+
+    /// Adds a host memory region to share with the guest when the VM starts, or updates the
+    /// one with the same ID.
+    pub fn set_shared_memory_device(
+        &mut self,
+        config: SharedMemoryConfig,
+    ) -> Result<SharedMemoryConfigError> {
+        self.shared_memory.insert(config)
+    }
+
+    /// Adds a probe to evaluate once the VM starts, or updates the one with the same ID. The
+    /// vsock device of a vsock probe has to be configured first.
+    pub fn set_probe(&mut self, config: ProbeConfig) -> Result<ProbeConfigError> {
+        self.probes.insert(config, &self.vsock.configs())
+    }
+
+    /// Sets how the rate limiters of the devices are adjusted to the pressure on the host once
+    /// the VM starts.
+    pub fn set_rate_limit_policy(
+        &mut self,
+        config: RateLimitPolicyConfig,
+    ) -> Result<RateLimitPolicyError> {
+        config.validate()?;
+        self.rate_limit_policy = Some(config);
+        Ok(())
+    }
+
+    /// Returns how the rate limiters of the devices are adjusted to the pressure on the host.
+    pub fn rate_limit_policy(&self) -> Option<&RateLimitPolicyConfig> {
+        self.rate_limit_policy.as_ref()
+    }
+
+    /// Sets the virtio features the devices may offer to their guest drivers, by device type.
+    pub fn set_virtio_feature_policy(
+        &mut self,
+        config: VirtioFeaturePolicyConfig,
+    ) -> Result<VirtioFeaturePolicyError> {
+        config.validate()?;
+        self.virtio_feature_policy = config;
+        Ok(())
+    }
+
+    /// Returns the virtio features the devices may offer to their guest drivers.
+    pub fn virtio_feature_policy(&self) -> &VirtioFeaturePolicyConfig {
+        &self.virtio_feature_policy
+    }
+
+    /// Sets the cloud-init data handed to the guest once the VM starts.
+    pub fn set_cloud_init(&mut self, config: CloudInitConfig) -> Result<CloudInitConfigError> {
+        config.validate()?;
+        self.cloud_init = Some(config);
+        Ok(())
+    }
+
+    /// Returns the cloud-init data handed to the guest.
+    pub fn cloud_init(&self) -> Option<&CloudInitConfig> {
+        self.cloud_init.as_ref()
+    }
+
+    /// Sets the time at which the guest RTC starts.
+    pub fn set_rtc_config(&mut self, config: RtcConfig) -> Result<RtcConfigError> {
+        config.validate()?;
+        self.rtc_config = Some(config);
+        Ok(())
+    }
+
+    /// Sets the memory limits of the Firecracker process, and the OOM policy.
+    pub fn set_memory_limits(
+        &mut self,
+        config: MemoryLimitsConfig,
+    ) -> Result<MemoryLimitsConfigError> {
+        config.apply()?;
+        self.memory_limits = config;
+        Ok(())
+    }
+
+    /// Returns the memory limits of the Firecracker process, and the OOM policy.
+    pub fn memory_limits(&self) -> &MemoryLimitsConfig {
+        &self.memory_limits
+    }
+
+    /// Returns the RTC configuration, if the guest RTC doesn't simply follow the host time.
+    pub fn rtc_config(&self) -> Option<&RtcConfig> {
+        self.rtc_config.as_ref()
+    }
+
+    /// Sets where the input of the serial console comes from.
+    pub fn set_console_config(&mut self, config: ConsoleConfig) -> Result<ConsoleConfigError> {
+        config.validate()?;
+        self.console_config = config;
+        Ok(())
+    }
+
+    /// Returns the serial console configuration.
+    pub fn console_config(&self) -> &ConsoleConfig {
+        &self.console_config
+    }
+
+    /// Launches the microVM as a SEV guest configured by `config`.
+    #[cfg(feature = "sev")]
+    pub fn set_sev_config(&mut self, config: SevConfig) -> Result<SevConfigError> {
+        config.validate()?;
+        self.sev_config = Some(config);
+        Ok(())
+    }
+
+    /// Returns the SEV configuration, if the microVM is to be launched as a SEV guest.
+    #[cfg(feature = "sev")]
+    pub fn sev_config(&self) -> Option<&SevConfig> {
+        self.sev_config.as_ref()
+    }
+
+    /// Initializes the logger, keeping its configuration.
+    pub fn set_logger_config(
+        &mut self,
+        config: LoggerConfig,
+        firecracker_version: &str,
+    ) -> Result<LoggerConfigError> {
+        init_logger(config.clone(), firecracker_version)?;
+        self.logger_config = Some(config);
+        Ok(())
+    }
+
+    /// Initializes the metrics system, keeping its configuration.
+    pub fn set_metrics_config(&mut self, config: MetricsConfig) -> Result<MetricsConfigError> {
+        init_metrics(config.clone())?;
+        self.metrics_config = Some(config);
+        Ok(())
+    }
+
     /// Setter for mmds config.
     pub fn set_mmds_config(&mut self, config: MmdsConfig) -> Result<MmdsConfigError> {
         // Check IPv4 address validity.
@@ -293,25 +1569,37 @@ impl VmResources {
 
 #[cfg(test)]
 mod tests {
+    use std::convert::TryFrom;
     use std::fs::File;
+    use std::io;
     use std::os::linux::fs::MetadataExt;
+    use std::os::unix::io::AsRawFd;
 
     use super::*;
     use dumbo::MacAddr;
     use logger::{LevelFilter, LOGGER};
     use resources::VmResources;
     use utils::tempfile::TempFile;
-    use vmm_config::boot_source::{BootConfig, BootSourceConfig, DEFAULT_KERNEL_CMDLINE};
-    use vmm_config::drive::{BlockBuilder, BlockDeviceConfig};
-    use vmm_config::machine_config::{CpuFeaturesTemplate, VmConfig, VmConfigError};
-    use vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
+    use vmm_config::boot_source::{
+        BootConfig, BootSourceConfig, KernelBytes, MmioDeviceEnumeration, DEFAULT_KERNEL_CMDLINE,
+    };
+    use vmm_config::cloud_init::{CloudInitTransport, DEFAULT_META_DATA, MAX_CLOUD_INIT_DATA_SIZE};
+    use vmm_config::console::ConsoleInput;
+    use vmm_config::drive::{BlockBuilder, BlockDeviceConfig, EnospcPolicy};
+    use vmm_config::machine_config::{
+        CpuFeaturesProfile, CpuFeaturesTemplate, GicVersion, LegacyDevicesConfig, VcpuRetryConfig,
+        VmConfig, VmConfigError,
+    };
+    use vmm_config::net::{NetBackendType, NetBuilder, NetworkInterfaceConfig};
+    use vmm_config::rate_limit_policy::{PressureSignal, PsiResource};
+    use vmm_config::virtio_features::VirtioFeatureMask;
     use vmm_config::vsock::tests::{default_config, TempSockFile};
-    use vmm_config::RateLimiterConfig;
+    use vmm_config::{Identifier, RateLimiterConfig, TokenBucketConfig};
     use vstate::VcpuConfig;
 
     fn default_net_cfg() -> NetworkInterfaceConfig {
         NetworkInterfaceConfig {
-            iface_id: "net_if1".to_string(),
+            iface_id: Identifier::try_from("net_if1").unwrap(),
             // TempFile::new_with_prefix("") generates a random file name used as random net_if name.
             host_dev_name: TempFile::new_with_prefix("")
                 .unwrap()
@@ -319,10 +1607,17 @@ mod tests {
                 .to_str()
                 .unwrap()
                 .to_string(),
+            backend: NetBackendType::Tap,
+            xdp: None,
             guest_mac: Some(MacAddr::parse_str("01:23:45:67:89:0a").unwrap()),
+            mtu: None,
             rx_rate_limiter: Some(RateLimiterConfig::default()),
             tx_rate_limiter: Some(RateLimiterConfig::default()),
             allow_mmds_requests: false,
+            allow_promiscuous: true,
+            rx_impairment: None,
+            tx_impairment: None,
+            affinity: None,
         }
     }
 
@@ -337,12 +1632,20 @@ mod tests {
         let tmp_file = TempFile::new().unwrap();
         (
             BlockDeviceConfig {
-                drive_id: "block1".to_string(),
+                drive_id: Identifier::try_from("block1").unwrap(),
                 path_on_host: tmp_file.as_path().to_str().unwrap().to_string(),
                 is_root_device: false,
                 partuuid: Some("0eaa91a0-01".to_string()),
                 is_read_only: false,
+                no_atime: false,
                 rate_limiter: Some(RateLimiterConfig::default()),
+                encryption: None,
+                verity: None,
+                io_weight: None,
+                serial: None,
+                physical_block_size: None,
+                on_enospc: EnospcPolicy::Error,
+                page_cache: None,
             },
             tmp_file,
         )
@@ -361,8 +1664,9 @@ mod tests {
         let tmp_file = TempFile::new().unwrap();
         BootConfig {
             cmdline: kernel_cmdline,
-            kernel_file: File::open(tmp_file.as_path()).unwrap(),
+            kernel_image: KernelImage::File(File::open(tmp_file.as_path()).unwrap()),
             initrd_file: Some(File::open(tmp_file.as_path()).unwrap()),
+            device_enumeration: MmioDeviceEnumeration::Cmdline,
         }
     }
 
@@ -370,18 +1674,52 @@ mod tests {
         VmResources {
             vm_config: VmConfig::default(),
             boot_config: Some(default_boot_cfg()),
+            boot_source_config: None,
+            balloon: Default::default(),
             block: default_blocks(),
+            entropy: Default::default(),
+            gpu: Default::default(),
+            input: Default::default(),
+            sound: Default::default(),
             vsock: Default::default(),
             net_builder: default_net_builder(),
+            pci_passthrough: Default::default(),
+            shared_memory: Default::default(),
+            probes: Default::default(),
+            rate_limit_policy: None,
+            virtio_feature_policy: Default::default(),
+            cloud_init: None,
             mmds_config: None,
+            rtc_config: None,
+            console_config: Default::default(),
+            memory_limits: Default::default(),
+            #[cfg(feature = "sev")]
+            sev_config: None,
+            logger_config: None,
+            metrics_config: None,
+            preopened_fds: Arc::new(PreopenedFds::default()),
+            instance_id: None,
+        }
+    }
+
+    impl PartialEq for KernelImage {
+        fn eq(&self, other: &Self) -> bool {
+            match (self, other) {
+                (KernelImage::File(file), KernelImage::File(other_file)) => {
+                    file.metadata().unwrap().st_ino() == other_file.metadata().unwrap().st_ino()
+                }
+                (KernelImage::Bytes(bytes), KernelImage::Bytes(other_bytes)) => {
+                    bytes == other_bytes
+                }
+                _ => false,
+            }
         }
     }
 
     impl PartialEq for BootConfig {
         fn eq(&self, other: &Self) -> bool {
             self.cmdline.as_str().eq(other.cmdline.as_str())
-                && self.kernel_file.metadata().unwrap().st_ino()
-                    == other.kernel_file.metadata().unwrap().st_ino()
+                && self.kernel_image == other.kernel_image
                 && self
                     .initrd_file
                     .as_ref()
@@ -428,7 +1766,7 @@ mod tests {
             rootfs_file.as_path().to_str().unwrap()
         );
 
-        match VmResources::from_json(json.as_str(), "some_version") {
+        match VmResources::from_json(json.as_str(), "some_version", PreopenedFds::default()) {
             Err(Error::BootSource(BootSourceConfigError::InvalidKernelPath(_))) => (),
             _ => unreachable!(),
         }
@@ -452,8 +1790,10 @@ mod tests {
             kernel_file.as_path().to_str().unwrap()
         );
 
-        match VmResources::from_json(json.as_str(), "some_version") {
-            Err(Error::BlockDevice(DriveError::InvalidBlockDevicePath)) => (),
+        match VmResources::from_json(json.as_str(), "some_version", PreopenedFds::default()) {
+            Err(Error::BlockDevice(DriveError::InvalidBlockDevicePath(e))) => {
+                assert_eq!(e.kind(), io::ErrorKind::NotFound)
+            }
             _ => unreachable!(),
         }
 
@@ -482,17 +1822,17 @@ mod tests {
             rootfs_file.as_path().to_str().unwrap()
         );
 
-        match VmResources::from_json(json.as_str(), "some_version") {
+        match VmResources::from_json(json.as_str(), "some_version", PreopenedFds::default()) {
             Err(Error::VmConfig(VmConfigError::InvalidVcpuCount)) => (),
             _ => unreachable!(),
         }
 
-        // Invalid memory size.
+        // Invalid PCI passthrough device.
         json = format!(
             r#"{{
                     "boot-source": {{
                         "kernel_image_path": "{}",
-                        "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                        "boot_args": "console=ttyS0 reboot=k panic=1"
                     }},
                     "drives": [
                         {{
@@ -502,27 +1842,31 @@ mod tests {
                             "is_read_only": false
                         }}
                     ],
-                    "machine-config": {{
-                        "vcpu_count": 2,
-                        "mem_size_mib": 0,
-                        "ht_enabled": false
-                    }}
+                    "pci-passthrough": [
+                        {{
+                            "dev_id": "gpu",
+                            "host_bdf": "01:00.0"
+                        }}
+                    ]
             }}"#,
             kernel_file.as_path().to_str().unwrap(),
             rootfs_file.as_path().to_str().unwrap()
         );
 
-        match VmResources::from_json(json.as_str(), "some_version") {
-            Err(Error::VmConfig(VmConfigError::InvalidMemorySize)) => (),
+        match VmResources::from_json(json.as_str(), "some_version", PreopenedFds::default()) {
+            #[cfg(target_arch = "x86_64")]
+            Err(Error::PciPassthroughDevice(PciPassthroughConfigError::InvalidHostBdf(_))) => (),
+            #[cfg(target_arch = "aarch64")]
+            Err(Error::PciPassthroughDevice(PciPassthroughConfigError::NotSupported)) => (),
             _ => unreachable!(),
         }
 
-        // Invalid path for logger pipe.
+        // Invalid shared memory device.
         json = format!(
             r#"{{
                     "boot-source": {{
                         "kernel_image_path": "{}",
-                        "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                        "boot_args": "console=ttyS0 reboot=k panic=1"
                     }},
                     "drives": [
                         {{
@@ -532,23 +1876,26 @@ mod tests {
                             "is_read_only": false
                         }}
                     ],
-                    "logger": {{
-	                    "log_path": "/invalid/path"
-                    }}
+                    "shared-memory": [
+                        {{
+                            "shm_id": "shm",
+                            "mem_path": "/invalid/path"
+                        }}
+                    ]
             }}"#,
             kernel_file.as_path().to_str().unwrap(),
             rootfs_file.as_path().to_str().unwrap()
         );
 
-        match VmResources::from_json(json.as_str(), "some_version") {
-            Err(Error::Logger(LoggerConfigError::InitializationFailure { .. })) => (),
+        match VmResources::from_json(json.as_str(), "some_version", PreopenedFds::default()) {
+            #[cfg(target_arch = "x86_64")]
+            Err(Error::SharedMemoryDevice(SharedMemoryConfigError::MemFileNotFound(_))) => (),
+            #[cfg(target_arch = "aarch64")]
+            Err(Error::SharedMemoryDevice(SharedMemoryConfigError::NotSupported)) => (),
             _ => unreachable!(),
         }
 
-        // The previous call enables the logger. We need to disable it.
-        LOGGER.set_max_level(LevelFilter::Off);
-
-        // Invalid path for metrics pipe.
+        // Invalid memory size.
         json = format!(
             r#"{{
                     "boot-source": {{
@@ -563,20 +1910,22 @@ mod tests {
                             "is_read_only": false
                         }}
                     ],
-                    "metrics": {{
-	                    "metrics_path": "/invalid/path"
+                    "machine-config": {{
+                        "vcpu_count": 2,
+                        "mem_size_mib": 0,
+                        "ht_enabled": false
                     }}
             }}"#,
             kernel_file.as_path().to_str().unwrap(),
             rootfs_file.as_path().to_str().unwrap()
         );
 
-        match VmResources::from_json(json.as_str(), "some_version") {
-            Err(Error::Metrics(MetricsConfigError::InitializationFailure { .. })) => (),
+        match VmResources::from_json(json.as_str(), "some_version", PreopenedFds::default()) {
+            Err(Error::VmConfig(VmConfigError::InvalidMemorySize)) => (),
             _ => unreachable!(),
         }
 
-        // Reuse of a host name.
+        // Balloon larger than the guest memory.
         json = format!(
             r#"{{
                     "boot-source": {{
@@ -591,31 +1940,26 @@ mod tests {
                             "is_read_only": false
                         }}
                     ],
-                    "network-interfaces": [
-                        {{
-                            "iface_id": "netif1",
-                            "host_dev_name": "hostname7"
-                        }},
-                        {{
-                            "iface_id": "netif2",
-                            "host_dev_name": "hostname7"
-                        }}
-                    ]
+                    "machine-config": {{
+                        "vcpu_count": 2,
+                        "mem_size_mib": 128,
+                        "ht_enabled": false
+                    }},
+                    "balloon": {{
+                        "amount_mib": 129,
+                        "deflate_on_oom": false
+                    }}
             }}"#,
             kernel_file.as_path().to_str().unwrap(),
             rootfs_file.as_path().to_str().unwrap()
         );
 
-        match VmResources::from_json(json.as_str(), "some_version") {
-            Err(Error::NetDevice(NetworkInterfaceError::CreateNetworkDevice(
-                devices::virtio::net::Error::TapOpen { .. },
-            ))) => (),
+        match VmResources::from_json(json.as_str(), "some_version", PreopenedFds::default()) {
+            Err(Error::BalloonDevice(BalloonConfigError::TooManyPagesRequested)) => (),
             _ => unreachable!(),
         }
 
-        // Let's try now passing a valid configuration. We won't include any logger
-        // or metrics configuration because these were already initialized in other
-        // tests of this module and the reinitialization of them will cause crashing.
+        // Invalid path for logger pipe.
         json = format!(
             r#"{{
                     "boot-source": {{
@@ -630,26 +1974,157 @@ mod tests {
                             "is_read_only": false
                         }}
                     ],
-                    "network-interfaces": [
-                        {{
-                            "iface_id": "netif",
-                            "host_dev_name": "hostname8",
-                            "allow_mmds_requests": true
-                        }}
-                    ],
-                    "machine-config": {{
-                        "vcpu_count": 2,
-                        "mem_size_mib": 1024,
-                        "ht_enabled": false
-                    }},
-                    "mmds-config": {{
-                        "ipv4_address": "169.254.170.2"
+                    "logger": {{
+	                    "log_path": "/invalid/path"
                     }}
             }}"#,
             kernel_file.as_path().to_str().unwrap(),
-            rootfs_file.as_path().to_str().unwrap(),
+            rootfs_file.as_path().to_str().unwrap()
+        );
+
+        match VmResources::from_json(json.as_str(), "some_version", PreopenedFds::default()) {
+            Err(Error::Logger(LoggerConfigError::InitializationFailure { .. })) => (),
+            _ => unreachable!(),
+        }
+
+        // The previous call enables the logger. We need to disable it.
+        LOGGER.set_max_level(LevelFilter::Off);
+
+        // Invalid path for metrics pipe.
+        json = format!(
+            r#"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}",
+                        "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                    }},
+                    "drives": [
+                        {{
+                            "drive_id": "rootfs",
+                            "path_on_host": "{}",
+                            "is_root_device": true,
+                            "is_read_only": false
+                        }}
+                    ],
+                    "metrics": {{
+	                    "metrics_path": "/invalid/path"
+                    }}
+            }}"#,
+            kernel_file.as_path().to_str().unwrap(),
+            rootfs_file.as_path().to_str().unwrap()
+        );
+
+        match VmResources::from_json(json.as_str(), "some_version", PreopenedFds::default()) {
+            Err(Error::Metrics(MetricsConfigError::InitializationFailure { .. })) => (),
+            _ => unreachable!(),
+        }
+
+        // Reuse of a host name.
+        json = format!(
+            r#"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}",
+                        "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                    }},
+                    "drives": [
+                        {{
+                            "drive_id": "rootfs",
+                            "path_on_host": "{}",
+                            "is_root_device": true,
+                            "is_read_only": false
+                        }}
+                    ],
+                    "network-interfaces": [
+                        {{
+                            "iface_id": "netif1",
+                            "host_dev_name": "hostname7"
+                        }},
+                        {{
+                            "iface_id": "netif2",
+                            "host_dev_name": "hostname7"
+                        }}
+                    ]
+            }}"#,
+            kernel_file.as_path().to_str().unwrap(),
+            rootfs_file.as_path().to_str().unwrap()
+        );
+
+        match VmResources::from_json(json.as_str(), "some_version", PreopenedFds::default()) {
+            Err(Error::NetDevice(NetworkInterfaceError::CreateNetworkDevice(
+                devices::virtio::net::Error::TapOpen { .. },
+            ))) => (),
+            _ => unreachable!(),
+        }
+
+        // Invalid drive ID.
+        json = format!(
+            r#"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}",
+                        "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                    }},
+                    "drives": [
+                        {{
+                            "drive_id": "root fs",
+                            "path_on_host": "{}",
+                            "is_root_device": true,
+                            "is_read_only": false
+                        }}
+                    ]
+            }}"#,
+            kernel_file.as_path().to_str().unwrap(),
+            rootfs_file.as_path().to_str().unwrap()
+        );
+
+        match VmResources::from_json(json.as_str(), "some_version", PreopenedFds::default()) {
+            Err(Error::InvalidJson(err)) => assert!(err
+                .to_string()
+                .contains("Invalid character ' ' at position 4 in device ID \"root fs\"")),
+            _ => unreachable!(),
+        }
+
+        // Let's try now passing a valid configuration. We won't include any logger
+        // or metrics configuration because these were already initialized in other
+        // tests of this module and the reinitialization of them will cause crashing.
+        json = format!(
+            r#"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}",
+                        "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                    }},
+                    "drives": [
+                        {{
+                            "drive_id": "rootfs",
+                            "path_on_host": "{}",
+                            "is_root_device": true,
+                            "is_read_only": false
+                        }}
+                    ],
+                    "network-interfaces": [
+                        {{
+                            "iface_id": "netif",
+                            "host_dev_name": "hostname8",
+                            "allow_mmds_requests": true
+                        }}
+                    ],
+                    "machine-config": {{
+                        "vcpu_count": 2,
+                        "mem_size_mib": 1024,
+                        "ht_enabled": false
+                    }},
+                    "balloon": {{
+                        "amount_mib": 0,
+                        "deflate_on_oom": true
+                    }},
+                    "mmds-config": {{
+                        "ipv4_address": "169.254.170.2"
+                    }}
+            }}"#,
+            kernel_file.as_path().to_str().unwrap(),
+            rootfs_file.as_path().to_str().unwrap(),
+        );
+        assert!(
+            VmResources::from_json(json.as_str(), "some_version", PreopenedFds::default()).is_ok()
         );
-        assert!(VmResources::from_json(json.as_str(), "some_version").is_ok());
 
         // Test all configuration, this time trying to configure the MMDS with an
         // empty body. It will make it access the code path in which it sets the
@@ -686,64 +2161,715 @@ mod tests {
             kernel_file.as_path().to_str().unwrap(),
             rootfs_file.as_path().to_str().unwrap(),
         );
-        assert!(VmResources::from_json(json.as_str(), "some_version").is_ok());
+        assert!(
+            VmResources::from_json(json.as_str(), "some_version", PreopenedFds::default()).is_ok()
+        );
+    }
+
+    #[test]
+    fn test_full_configuration() {
+        let config = VmmConfig::from(&VmResources::default());
+        assert_eq!(config.boot_source, None);
+        assert!(config.block_devices.is_empty());
+        assert_eq!(config.machine_config, Some(VmConfig::default()));
+
+        let kernel_file = TempFile::new().unwrap();
+        let rootfs_file = TempFile::new().unwrap();
+        let json = format!(
+            r##"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}",
+                        "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                    }},
+                    "cloud-init": {{
+                        "user_data": "#cloud-config\n",
+                        "transport": "mmds"
+                    }},
+                    "drives": [
+                        {{
+                            "drive_id": "rootfs",
+                            "path_on_host": "{}",
+                            "is_root_device": true,
+                            "is_read_only": false
+                        }}
+                    ],
+                    "network-interfaces": [
+                        {{
+                            "iface_id": "netif",
+                            "host_dev_name": "hostname10",
+                            "affinity": [0]
+                        }}
+                    ],
+                    "machine-config": {{
+                        "vcpu_count": 2,
+                        "mem_size_mib": 1024,
+                        "ht_enabled": false
+                    }},
+                    "mmds-config": {{
+                        "ipv4_address": "169.254.170.2"
+                    }},
+                    "probes": [
+                        {{
+                            "probe_id": "ready",
+                            "kind": "readiness",
+                            "check": {{"type": "serial_pattern", "pattern": "login:"}}
+                        }}
+                    ],
+                    "rate-limit-policy": {{
+                        "signal": {{"type": "psi", "resource": "io"}},
+                        "high_watermark": 40,
+                        "low_watermark": 10
+                    }},
+                    "virtio-features": {{
+                        "net": {{"deny": 1024}}
+                    }}
+            }}"##,
+            kernel_file.as_path().to_str().unwrap(),
+            rootfs_file.as_path().to_str().unwrap(),
+        );
+        let vm_resources =
+            VmResources::from_json(json.as_str(), "some_version", PreopenedFds::default()).unwrap();
+        let config = VmmConfig::from(&vm_resources);
+        assert_eq!(config.probes.len(), 1);
+        assert_eq!(config.probes[0].probe_id, "ready");
+        assert_eq!(
+            config.rate_limit_policy.as_ref().unwrap().high_watermark,
+            40
+        );
+        assert_eq!(
+            vm_resources
+                .virtio_feature_policy()
+                .mask(devices::virtio::TYPE_NET),
+            !1024
+        );
+        assert_eq!(
+            config.cloud_init.as_ref().unwrap().transport,
+            CloudInitTransport::Mmds
+        );
+        assert_eq!(
+            config.boot_source.as_ref().unwrap().kernel_image_path,
+            kernel_file.as_path().to_str().unwrap()
+        );
+        assert_eq!(config.block_devices.len(), 1);
+        assert_eq!(config.block_devices[0].drive_id, "rootfs");
+        assert_eq!(config.net_devices.len(), 1);
+        assert_eq!(config.net_devices[0].affinity, Some(vec![0]));
+        assert_eq!(config.machine_config.as_ref().unwrap().vcpu_count, Some(2));
+        assert!(config.mmds_config.is_some());
+
+        // The configuration can be fed back to Firecracker as it is.
+        let serialized = serde_json::to_string(&config).unwrap();
+        assert_eq!(
+            serde_json::from_str::<VmmConfig>(&serialized).unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn test_validate_json() {
+        let kernel_file = TempFile::new().unwrap();
+        let rootfs_file = TempFile::new().unwrap();
+        let config = |boot_args: &str, rootfs_path: &str| {
+            format!(
+                r#"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}",
+                        "boot_args": "{}"
+                    }},
+                    "drives": [
+                        {{
+                            "drive_id": "rootfs",
+                            "path_on_host": "{}",
+                            "is_root_device": true,
+                            "is_read_only": false
+                        }}
+                    ]
+                }}"#,
+                kernel_file.as_path().to_str().unwrap(),
+                boot_args,
+                rootfs_path
+            )
+        };
+        let rootfs_path = rootfs_file.as_path().to_str().unwrap();
+
+        VmResources::validate_json(&config("console=ttyS0", rootfs_path)).unwrap();
+
+        match VmResources::validate_json(&config("console=ttyS0", "/invalid/path")) {
+            Err(Error::BlockDevice(DriveError::InvalidBlockDevicePath(_))) => (),
+            _ => unreachable!(),
+        }
+
+        // The boot arguments fit in the command line, but not along with the root device.
+        let boot_args = "a".repeat(arch::CMDLINE_MAX_SIZE - 10);
+        match VmResources::validate_json(&config(&boot_args, rootfs_path)) {
+            Err(Error::BootSource(BootSourceConfigError::InvalidKernelCommandLine(_))) => (),
+            _ => unreachable!(),
+        }
+        VmResources::from_json(
+            &config(&boot_args, rootfs_path),
+            "some_version",
+            PreopenedFds::default(),
+        )
+        .unwrap();
+
+        // The boot arguments fit along with the root device, but not with the devices, unless
+        // the guest kernel finds them in its firmware tables.
+        #[cfg(target_arch = "x86_64")]
+        {
+            let boot_args = "a".repeat(arch::CMDLINE_MAX_SIZE - 40);
+            let json = config(&boot_args, rootfs_path);
+            match VmResources::validate_json(&json) {
+                Err(Error::BootSource(BootSourceConfigError::InvalidKernelCommandLine(_))) => (),
+                _ => unreachable!(),
+            }
+            let json = json.replacen(
+                r#""boot_args""#,
+                r#""device_enumeration": "firmware", "boot_args""#,
+                1,
+            );
+            VmResources::validate_json(&json).unwrap();
+        }
+
+        // The memory limits are checked, but not set.
+        let json = config("console=ttyS0", rootfs_path).replacen(
+            '{',
+            r#"{ "memory-limits": { "address_space_mib": 0 },"#,
+            1,
+        );
+        match VmResources::validate_json(&json) {
+            Err(Error::MemoryLimits(MemoryLimitsConfigError::InvalidLimit(0))) => (),
+            _ => unreachable!(),
+        }
+
+        // The devices are checked, but not built.
+        let mut uds_file = TempFile::new().unwrap();
+        uds_file.remove().unwrap();
+        let vsock = format!(
+            r#"{{ "vsock_id": "vsock", "guest_cid": 3, "uds_path": "{}" }}"#,
+            uds_file.as_path().to_str().unwrap()
+        );
+        let json = config("console=ttyS0", rootfs_path).replacen(
+            '{',
+            &format!(r#"{{ "vsock-devices": [{}],"#, vsock),
+            1,
+        );
+        VmResources::validate_json(&json).unwrap();
+        assert!(!uds_file.as_path().exists());
+        let json = config("console=ttyS0", rootfs_path).replacen(
+            r#""is_read_only": false"#,
+            r#""is_read_only": false, "serial": "\u0001""#,
+            1,
+        );
+        match VmResources::validate_json(&json) {
+            Err(Error::BlockDevice(DriveError::InvalidSerial(_))) => (),
+            _ => unreachable!(),
+        }
+
+        match VmResources::validate_json("{ \"drives\": [] }") {
+            Err(Error::InvalidJson(_)) => (),
+            _ => unreachable!(),
+        }
+
+        // The resources are cross-checked.
+        let json = config("console=ttyS0", rootfs_path).replacen(
+            r#""is_root_device": true"#,
+            r#""is_root_device": false"#,
+            1,
+        );
+        match VmResources::validate_json(&json) {
+            Err(Error::InconsistentConfig(issues)) => {
+                assert_eq!(issues.0.len(), 1);
+                assert_eq!(issues.0[0].kind, ConfigIssueKind::MissingRootDevice);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_config_issues() {
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.config_issues().is_empty());
+
+        // Neither a root drive, an initrd, nor a root= boot argument.
+        vm_resources.boot_source_config = Some(BootSourceConfig {
+            boot_args: Some("console=ttyS0".to_string()),
+            ..Default::default()
+        });
+        let issues = vm_resources.config_issues();
+        assert_eq!(issues.0.len(), 1);
+        assert_eq!(issues.0[0].kind, ConfigIssueKind::MissingRootDevice);
+        assert_eq!(issues.0[0].resource, "/boot-source");
+        vm_resources.boot_source_config = Some(BootSourceConfig {
+            boot_args: Some("console=ttyS0 root=/dev/vdb".to_string()),
+            ..Default::default()
+        });
+        assert!(vm_resources.config_issues().is_empty());
+        vm_resources.boot_source_config = Some(BootSourceConfig {
+            initrd_path: Some("initrd".to_string()),
+            boot_args: Some("console=ttyS0".to_string()),
+            ..Default::default()
+        });
+        assert!(vm_resources.config_issues().is_empty());
+
+        // An unlimited token bucket, and a reserved vsock CID.
+        let mut net_device_cfg = default_net_cfg();
+        net_device_cfg.tx_rate_limiter = Some(RateLimiterConfig {
+            bandwidth: Some(TokenBucketConfig {
+                size: 0,
+                one_time_burst: None,
+                refill_time: 100,
+            }),
+            ops: None,
+        });
+        vm_resources.build_net_device(net_device_cfg).unwrap();
+        let tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        let mut vsock_cfg = default_config(&tmp_sock_file);
+        vsock_cfg.guest_cid = 2;
+        vm_resources.set_vsock_device(vsock_cfg).unwrap();
+
+        let issues = vm_resources.config_issues();
+        assert_eq!(issues.0.len(), 2);
+        assert_eq!(issues.0[0].kind, ConfigIssueKind::ZeroTokenBucket);
+        assert_eq!(issues.0[0].resource, "/network-interfaces/net_if1");
+        assert_eq!(
+            issues.0[0].message,
+            "The bandwidth bucket of the tx rate limiter has a size or a refill time of 0, which \
+             leaves it unlimited."
+        );
+        assert_eq!(issues.0[1].kind, ConfigIssueKind::ReservedVsockCid);
+        assert_eq!(issues.0[1].resource, "/vsock/vsock");
+        assert!(issues
+            .to_string()
+            .starts_with("/network-interfaces/net_if1: The bandwidth"));
+    }
+
+    #[test]
+    fn test_vcpu_config() {
+        let vm_resources = default_vm_resources();
+        let expected_vcpu_config = VcpuConfig {
+            vcpu_count: vm_resources.vm_config().vcpu_count.unwrap(),
+            ht_enabled: vm_resources.vm_config().ht_enabled.unwrap(),
+            cores_per_socket: None,
+            cpu_template: vm_resources.vm_config().cpu_template,
+            cpu_features: None,
+            nested: false,
+            tsc_khz: None,
+            tickless: false,
+            memory_encryption_mask: 0,
+        };
+
+        let vcpu_config = vm_resources.vcpu_config();
+        assert_eq!(vcpu_config, expected_vcpu_config);
+    }
+
+    #[test]
+    fn test_vm_config() {
+        let vm_resources = default_vm_resources();
+        let expected_vm_cfg = VmConfig::default();
+
+        assert_eq!(vm_resources.vm_config(), &expected_vm_cfg);
+    }
+
+    #[test]
+    fn test_set_vm_config() {
+        let mut vm_resources = default_vm_resources();
+        let mut aux_vm_config = VmConfig {
+            vcpu_count: Some(32),
+            mem_size_mib: Some(512),
+            ht_enabled: Some(true),
+            cores_per_socket: None,
+            cpu_template: Some(CpuFeaturesTemplate::T2),
+            cpu_features: None,
+            nested: false,
+            tsc_khz: None,
+            track_dirty_pages: false,
+            memfd_backed: true,
+            mmio32_hole_size_mib: None,
+            mmio64_window_size_mib: None,
+            gic_version: None,
+            gic_its: false,
+            split_irqchip: false,
+            tickless: false,
+            max_device_irq_rate: Some(10_000),
+            legacy_devices: LegacyDevicesConfig {
+                serial: true,
+                i8042: false,
+                rtc: false,
+            },
+            vcpu_retry: VcpuRetryConfig::default(),
+            strict_config: false,
+        };
+
+        assert_ne!(vm_resources.vm_config, aux_vm_config);
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        assert_eq!(vm_resources.vm_config, aux_vm_config);
+
+        // Invalid vcpu count.
+        aux_vm_config.vcpu_count = Some(0);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidVcpuCount)
+        );
+        aux_vm_config.vcpu_count = Some(33);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidVcpuCount)
+        );
+        aux_vm_config.vcpu_count = Some(32);
+
+        // Invalid mem_size_mib.
+        aux_vm_config.mem_size_mib = Some(0);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidMemorySize)
+        );
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_set_cpu_topology() {
+        let mut vm_resources = default_vm_resources();
+        let mut aux_vm_config = VmConfig {
+            vcpu_count: Some(128),
+            ht_enabled: Some(true),
+            cores_per_socket: Some(16),
+            ..Default::default()
+        };
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        assert_eq!(vm_resources.vcpu_config().cores_per_socket, Some(16));
+
+        // Omitting the cores per socket leaves them unchanged, and they still have to split the
+        // new vCPU count evenly.
+        aux_vm_config.cores_per_socket = None;
+        aux_vm_config.vcpu_count = Some(64);
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        assert_eq!(vm_resources.vm_config.cores_per_socket, Some(16));
+        aux_vm_config.vcpu_count = Some(36);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidCoresPerSocket)
+        );
+
+        // Several sockets need a power of 2 of cores.
+        aux_vm_config.vcpu_count = Some(24);
+        aux_vm_config.cores_per_socket = Some(6);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidCoresPerSocket)
+        );
+        // A single socket can't hold that many cores.
+        aux_vm_config.vcpu_count = Some(254);
+        aux_vm_config.cores_per_socket = Some(127);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidCoresPerSocket)
+        );
+        assert_eq!(vm_resources.vm_config.vcpu_count, Some(64));
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_set_cpu_features() {
+        let mut vm_resources = default_vm_resources();
+        let mut aux_vm_config = VmConfig {
+            cpu_features: Some(CpuFeaturesConfig::List(vec![
+                "avx2".to_string(),
+                "avx512f".to_string(),
+            ])),
+            ..Default::default()
+        };
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        assert_eq!(
+            vm_resources.vcpu_config().cpu_features,
+            aux_vm_config.cpu_features
+        );
+
+        // Omitting the CPU features leaves them unchanged.
+        aux_vm_config.cpu_features = None;
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        assert!(vm_resources.vm_config.cpu_features.is_some());
+
+        aux_vm_config.cpu_features =
+            Some(CpuFeaturesConfig::Profile(CpuFeaturesProfile::Passthrough));
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        assert_eq!(
+            vm_resources.vm_config.cpu_features,
+            aux_vm_config.cpu_features
+        );
+
+        // Unknown feature names are rejected.
+        aux_vm_config.cpu_features = Some(CpuFeaturesConfig::List(vec![
+            "avx2".to_string(),
+            "avx1024".to_string(),
+        ]));
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidCpuFeature("avx1024".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_set_nested() {
+        let mut vm_resources = default_vm_resources();
+        let mut aux_vm_config = VmConfig {
+            nested: true,
+            ..Default::default()
+        };
+        #[cfg(target_arch = "x86_64")]
+        {
+            vm_resources.set_vm_config(&aux_vm_config).unwrap();
+            assert!(vm_resources.vcpu_config().nested);
+        }
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::NestedVirtualizationNotSupported)
+        );
+
+        aux_vm_config.nested = false;
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        assert!(!vm_resources.vcpu_config().nested);
+    }
+
+    #[test]
+    fn test_set_split_irqchip() {
+        let mut vm_resources = default_vm_resources();
+        let mut aux_vm_config = VmConfig {
+            split_irqchip: true,
+            ..Default::default()
+        };
+        #[cfg(target_arch = "x86_64")]
+        {
+            vm_resources.set_vm_config(&aux_vm_config).unwrap();
+            assert!(vm_resources.vm_config().split_irqchip);
+        }
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::SplitIrqchipNotSupported)
+        );
+
+        aux_vm_config.split_irqchip = false;
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        assert!(!vm_resources.vm_config().split_irqchip);
     }
 
     #[test]
-    fn test_vcpu_config() {
-        let vm_resources = default_vm_resources();
-        let expected_vcpu_config = VcpuConfig {
-            vcpu_count: vm_resources.vm_config().vcpu_count.unwrap(),
-            ht_enabled: vm_resources.vm_config().ht_enabled.unwrap(),
-            cpu_template: vm_resources.vm_config().cpu_template,
+    fn test_set_tickless() {
+        let mut vm_resources = default_vm_resources();
+        let mut aux_vm_config = VmConfig {
+            tickless: true,
+            ..Default::default()
         };
+        #[cfg(target_arch = "x86_64")]
+        {
+            vm_resources.set_vm_config(&aux_vm_config).unwrap();
+            assert!(vm_resources.vcpu_config().tickless);
+        }
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::TicklessNotSupported)
+        );
 
-        let vcpu_config = vm_resources.vcpu_config();
-        assert_eq!(vcpu_config, expected_vcpu_config);
+        aux_vm_config.tickless = false;
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        assert!(!vm_resources.vcpu_config().tickless);
     }
 
     #[test]
-    fn test_vm_config() {
-        let vm_resources = default_vm_resources();
-        let expected_vm_cfg = VmConfig::default();
+    fn test_set_max_device_irq_rate() {
+        let mut vm_resources = default_vm_resources();
+        let mut aux_vm_config = VmConfig {
+            max_device_irq_rate: Some(1000),
+            ..Default::default()
+        };
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        assert_eq!(vm_resources.vm_config().max_device_irq_rate, Some(1000));
 
-        assert_eq!(vm_resources.vm_config(), &expected_vm_cfg);
+        // The rate is kept when it's not specified.
+        aux_vm_config.max_device_irq_rate = None;
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        assert_eq!(vm_resources.vm_config().max_device_irq_rate, Some(1000));
+
+        aux_vm_config.max_device_irq_rate = Some(0);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidDeviceIrqRate)
+        );
+        assert_eq!(vm_resources.vm_config().max_device_irq_rate, Some(1000));
     }
 
     #[test]
-    fn test_set_vm_config() {
+    fn test_set_vcpu_retry() {
         let mut vm_resources = default_vm_resources();
         let mut aux_vm_config = VmConfig {
-            vcpu_count: Some(32),
-            mem_size_mib: Some(512),
-            ht_enabled: Some(true),
-            cpu_template: Some(CpuFeaturesTemplate::T2),
-            track_dirty_pages: false,
+            vcpu_retry: VcpuRetryConfig {
+                max_retries: 3,
+                initial_backoff_us: 10,
+                max_backoff_us: 10,
+            },
+            ..Default::default()
         };
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        assert_eq!(vm_resources.vm_config().vcpu_retry.max_retries, 3);
 
-        assert_ne!(vm_resources.vm_config, aux_vm_config);
+        aux_vm_config.vcpu_retry.initial_backoff_us = 11;
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidVcpuRetry)
+        );
+        assert_eq!(vm_resources.vm_config().vcpu_retry.initial_backoff_us, 10);
+    }
+
+    #[test]
+    fn test_set_tsc_khz() {
+        let mut vm_resources = default_vm_resources();
+        let mut aux_vm_config = VmConfig {
+            tsc_khz: Some(2_500_000),
+            ..Default::default()
+        };
+        #[cfg(target_arch = "x86_64")]
+        {
+            vm_resources.set_vm_config(&aux_vm_config).unwrap();
+            assert_eq!(vm_resources.vcpu_config().tsc_khz, Some(2_500_000));
+
+            // The frequency is kept when it's not specified.
+            aux_vm_config.tsc_khz = None;
+            vm_resources.set_vm_config(&aux_vm_config).unwrap();
+            assert_eq!(vm_resources.vm_config().tsc_khz, Some(2_500_000));
+
+            aux_vm_config.tsc_khz = Some(0);
+            assert_eq!(
+                vm_resources.set_vm_config(&aux_vm_config),
+                Err(VmConfigError::InvalidTscFrequency)
+            );
+            assert_eq!(vm_resources.vm_config().tsc_khz, Some(2_500_000));
+        }
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::TscFrequencyNotSupported)
+        );
+    }
+
+    #[test]
+    fn test_set_gic_config() {
+        let mut vm_resources = default_vm_resources();
+        let mut aux_vm_config = VmConfig {
+            gic_version: Some(GicVersion::V2),
+            ..Default::default()
+        };
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::GicNotSupported)
+        );
+        #[cfg(target_arch = "aarch64")]
+        {
+            vm_resources.set_vm_config(&aux_vm_config).unwrap();
+            assert_eq!(vm_resources.vm_config.gic_version, Some(GicVersion::V2));
+
+            // Omitting the version leaves it unchanged, and a GICv2 can't have an ITS.
+            aux_vm_config.gic_version = None;
+            aux_vm_config.gic_its = true;
+            assert_eq!(
+                vm_resources.set_vm_config(&aux_vm_config),
+                Err(VmConfigError::InvalidGicConfig)
+            );
+
+            aux_vm_config.gic_version = Some(GicVersion::V3);
+            vm_resources.set_vm_config(&aux_vm_config).unwrap();
+            assert!(vm_resources.vm_config.gic_config().its);
+
+            // A GICv2 handles at most 8 vCPUs.
+            aux_vm_config.gic_version = Some(GicVersion::V2);
+            aux_vm_config.gic_its = false;
+            aux_vm_config.vcpu_count = Some(16);
+            assert_eq!(
+                vm_resources.set_vm_config(&aux_vm_config),
+                Err(VmConfigError::InvalidGicConfig)
+            );
+            aux_vm_config.vcpu_count = Some(1);
+        }
+
+        aux_vm_config.gic_version = None;
+        aux_vm_config.gic_its = false;
+        assert!(vm_resources.set_vm_config(&aux_vm_config).is_ok());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_set_memory_layout() {
+        use arch::x86_64::guest_phys_addr_end;
+
+        let mut vm_resources = default_vm_resources();
+        let mut aux_vm_config = VmConfig {
+            mem_size_mib: Some(16 << 10),
+            mmio32_hole_size_mib: Some(256),
+            mmio64_window_size_mib: Some(1024),
+            ..Default::default()
+        };
         vm_resources.set_vm_config(&aux_vm_config).unwrap();
         assert_eq!(vm_resources.vm_config, aux_vm_config);
+        let layout = vm_resources.vm_config.memory_layout();
+        assert_eq!(layout.mmio32_hole_size, 256 << 20);
+        assert_eq!(layout.mmio64_window_size, 1 << 30);
 
-        // Invalid vcpu count.
-        aux_vm_config.vcpu_count = Some(0);
+        // Omitting the layout fields leaves them unchanged.
+        aux_vm_config.mmio32_hole_size_mib = None;
+        aux_vm_config.mmio64_window_size_mib = None;
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        assert_eq!(vm_resources.vm_config.mmio32_hole_size_mib, Some(256));
+        assert_eq!(vm_resources.vm_config.mmio64_window_size_mib, Some(1024));
+
+        // Invalid 32-bit hole sizes.
+        aux_vm_config.mmio32_hole_size_mib = Some(32);
         assert_eq!(
             vm_resources.set_vm_config(&aux_vm_config),
-            Err(VmConfigError::InvalidVcpuCount)
+            Err(VmConfigError::InvalidMmio32HoleSize)
         );
-        aux_vm_config.vcpu_count = Some(33);
+        aux_vm_config.mmio32_hole_size_mib = Some(4096);
         assert_eq!(
             vm_resources.set_vm_config(&aux_vm_config),
-            Err(VmConfigError::InvalidVcpuCount)
+            Err(VmConfigError::InvalidMmio32HoleSize)
         );
-        aux_vm_config.vcpu_count = Some(32);
+        aux_vm_config.mmio32_hole_size_mib = None;
 
-        // Invalid mem_size_mib.
-        aux_vm_config.mem_size_mib = Some(0);
+        // The 64-bit window has to fit after the guest memory.
+        aux_vm_config.mmio64_window_size_mib = Some(1 << 20);
         assert_eq!(
             vm_resources.set_vm_config(&aux_vm_config),
-            Err(VmConfigError::InvalidMemorySize)
+            Err(VmConfigError::InvalidMmio64WindowSize)
+        );
+        // The guest memory fills the address space the host can address, leaving no room for
+        // the window.
+        let max_mem_size_mib = vm_resources
+            .vm_config
+            .memory_layout()
+            .max_memory_size(guest_phys_addr_end())
+            >> 20;
+        aux_vm_config.mmio64_window_size_mib = Some(1024);
+        aux_vm_config.mem_size_mib = Some(max_mem_size_mib as usize);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidMmio64WindowSize)
         );
+        aux_vm_config.mmio64_window_size_mib = Some(0);
+        aux_vm_config.mem_size_mib = Some(max_mem_size_mib as usize + 1);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::GuestMemoryTooLarge(max_mem_size_mib))
+        );
+        // Guests larger than 1TiB fit in the address space of the hosts addressing more.
+        if max_mem_size_mib > 1 << 20 {
+            aux_vm_config.mem_size_mib = Some(1 << 20);
+            vm_resources.set_vm_config(&aux_vm_config).unwrap();
+            aux_vm_config.mem_size_mib = Some(16 << 10);
+            vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        }
+        // Nothing changed on failure.
+        assert_eq!(vm_resources.vm_config.mem_size_mib, Some(16 << 10));
     }
 
     #[test]
@@ -755,6 +2881,13 @@ mod tests {
         assert_eq!(actual_boot_cfg, expected_boot_cfg);
     }
 
+    fn kernel_ino(boot_cfg: &BootConfig) -> u64 {
+        match boot_cfg.kernel_image {
+            KernelImage::File(ref file) => file.metadata().unwrap().st_ino(),
+            KernelImage::Bytes(_) => panic!("The kernel image is not a file."),
+        }
+    }
+
     #[test]
     fn test_set_boot_source() {
         let tmp_file = TempFile::new().unwrap();
@@ -762,7 +2895,9 @@ mod tests {
         let expected_boot_cfg = BootSourceConfig {
             kernel_image_path: String::from(tmp_file.as_path().to_str().unwrap()),
             initrd_path: Some(String::from(tmp_file.as_path().to_str().unwrap())),
+            kernel_bytes: None,
             boot_args: Some(cmdline.to_string()),
+            device_enumeration: MmioDeviceEnumeration::Cmdline,
         };
 
         let mut vm_resources = default_vm_resources();
@@ -770,7 +2905,7 @@ mod tests {
         let tmp_ino = tmp_file.as_file().metadata().unwrap().st_ino();
 
         assert_ne!(boot_cfg.cmdline.as_str(), cmdline);
-        assert_ne!(boot_cfg.kernel_file.metadata().unwrap().st_ino(), tmp_ino);
+        assert_ne!(kernel_ino(boot_cfg), tmp_ino);
         assert_ne!(
             boot_cfg
                 .initrd_file
@@ -785,7 +2920,7 @@ mod tests {
         vm_resources.set_boot_source(expected_boot_cfg).unwrap();
         let boot_cfg = vm_resources.boot_source().unwrap();
         assert_eq!(boot_cfg.cmdline.as_str(), cmdline);
-        assert_eq!(boot_cfg.kernel_file.metadata().unwrap().st_ino(), tmp_ino);
+        assert_eq!(kernel_ino(boot_cfg), tmp_ino);
         assert_eq!(
             boot_cfg
                 .initrd_file
@@ -796,6 +2931,66 @@ mod tests {
                 .st_ino(),
             tmp_ino
         );
+
+        // The kernel can be given as a file descriptor.
+        let kernel_file = TempFile::new().unwrap();
+        fd_uri::record_inherited_fds().unwrap();
+        let boot_source_cfg = BootSourceConfig {
+            kernel_image_path: format!("fd://{}", kernel_file.as_file().as_raw_fd()),
+            initrd_path: None,
+            kernel_bytes: None,
+            boot_args: None,
+            device_enumeration: MmioDeviceEnumeration::Cmdline,
+        };
+        vm_resources.set_boot_source(boot_source_cfg).unwrap();
+        let boot_cfg = vm_resources.boot_source().unwrap();
+        assert_eq!(
+            kernel_ino(boot_cfg),
+            kernel_file.as_file().metadata().unwrap().st_ino()
+        );
+        assert!(boot_cfg.initrd_file.is_none());
+
+        // The kernel can be held in memory, in which case its path is ignored.
+        let kernel_bytes = KernelBytes::from(vec![0x7f, b'E', b'L', b'F']);
+        let boot_source_cfg = BootSourceConfig {
+            kernel_image_path: String::from("/invalid/path"),
+            initrd_path: None,
+            kernel_bytes: Some(kernel_bytes.clone()),
+            boot_args: None,
+            device_enumeration: MmioDeviceEnumeration::Firmware,
+        };
+        vm_resources.set_boot_source(boot_source_cfg).unwrap();
+        let boot_cfg = vm_resources.boot_source().unwrap();
+        assert_eq!(boot_cfg.kernel_image, KernelImage::Bytes(kernel_bytes));
+        assert_eq!(boot_cfg.device_enumeration, MmioDeviceEnumeration::Firmware);
+        assert_eq!(
+            format!("{:?}", boot_cfg.kernel_image),
+            "Bytes(KernelBytes(4 bytes))"
+        );
+
+        // The placeholders are kept until boot, but have to name known variables.
+        let mut boot_source_cfg = BootSourceConfig {
+            kernel_image_path: String::from("/invalid/path"),
+            initrd_path: None,
+            kernel_bytes: Some(KernelBytes::from(vec![0x7f, b'E', b'L', b'F'])),
+            boot_args: Some(String::from("root=${root_device} hostname=${instance_id}")),
+            device_enumeration: MmioDeviceEnumeration::Cmdline,
+        };
+        vm_resources
+            .set_boot_source(boot_source_cfg.clone())
+            .unwrap();
+        assert_eq!(
+            vm_resources.boot_source().unwrap().cmdline.as_str(),
+            "root=${root_device} hostname=${instance_id}"
+        );
+        boot_source_cfg.boot_args = Some(String::from("ip=${guest_ip}"));
+        match vm_resources.set_boot_source(boot_source_cfg) {
+            Err(BootSourceConfigError::InvalidKernelCommandLine(err)) => assert_eq!(
+                err,
+                "The boot arguments refer to an unknown variable: guest_ip"
+            ),
+            other => panic!("Unexpected result: {:?}", other),
+        }
     }
 
     #[test]
@@ -803,27 +2998,344 @@ mod tests {
         let mut vm_resources = default_vm_resources();
         let (mut new_block_device_cfg, _file) = default_block_cfg();
         let tmp_file = TempFile::new().unwrap();
-        new_block_device_cfg.drive_id = "block2".to_string();
+        new_block_device_cfg.drive_id = Identifier::try_from("block2").unwrap();
         new_block_device_cfg.path_on_host = tmp_file.as_path().to_str().unwrap().to_string();
         assert_eq!(vm_resources.block.list.len(), 1);
         vm_resources.set_block_device(new_block_device_cfg).unwrap();
         assert_eq!(vm_resources.block.list.len(), 2);
     }
 
+    #[test]
+    fn test_remove_block_device() {
+        let mut vm_resources = default_vm_resources();
+        let (mut new_block_device_cfg, _file) = default_block_cfg();
+        let tmp_file = TempFile::new().unwrap();
+        new_block_device_cfg.drive_id = Identifier::try_from("block2").unwrap();
+        new_block_device_cfg.path_on_host = tmp_file.as_path().to_str().unwrap().to_string();
+        vm_resources.set_block_device(new_block_device_cfg).unwrap();
+        assert_eq!(vm_resources.block.list.len(), 2);
+
+        vm_resources.remove_block_device("block1").unwrap();
+        assert_eq!(vm_resources.block.list.len(), 1);
+        assert_eq!(vm_resources.block.list[0].lock().unwrap().id(), "block2");
+        assert!(vm_resources.block.config("block1").is_none());
+        assert_eq!(vm_resources.block.configs().len(), 1);
+
+        match vm_resources.remove_block_device("block1") {
+            Err(DriveError::InvalidBlockDeviceID) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
     #[test]
     fn test_set_vsock_device() {
         let mut vm_resources = default_vm_resources();
         let tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
         let new_vsock_cfg = default_config(&tmp_sock_file);
-        assert!(vm_resources.vsock.get().is_none());
+        assert!(vm_resources.vsock.get("vsock").is_none());
         vm_resources
             .set_vsock_device(new_vsock_cfg.clone())
             .unwrap();
-        let actual_vsock_cfg = vm_resources.vsock.get().unwrap();
+        let actual_vsock_cfg = vm_resources.vsock.get("vsock").unwrap();
         assert_eq!(
             actual_vsock_cfg.lock().unwrap().id(),
             &new_vsock_cfg.vsock_id
         );
+
+        // Another vsock device is added next to the first one.
+        let other_tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        let mut other_vsock_cfg = default_config(&other_tmp_sock_file);
+        other_vsock_cfg.vsock_id = Identifier::try_from("other_vsock").unwrap();
+        other_vsock_cfg.guest_cid += 1;
+        vm_resources
+            .insert_vsock_device(other_vsock_cfg.clone())
+            .unwrap();
+        assert_eq!(vm_resources.vsock.len(), 2);
+
+        // Setting a vsock device replaces all the others.
+        vm_resources.set_vsock_device(other_vsock_cfg).unwrap();
+        assert_eq!(vm_resources.vsock.len(), 1);
+        assert!(vm_resources.vsock.get("vsock").is_none());
+        assert!(vm_resources.vsock.get("other_vsock").is_some());
+    }
+
+    #[test]
+    fn test_set_balloon_device() {
+        let mut vm_resources = default_vm_resources();
+        let mut new_balloon_cfg = BalloonDeviceConfig {
+            amount_mib: 0,
+            deflate_on_oom: false,
+            free_page_reporting: false,
+        };
+        assert!(vm_resources.balloon.get().is_none());
+        vm_resources
+            .set_balloon_device(new_balloon_cfg.clone())
+            .unwrap();
+        assert_eq!(vm_resources.balloon.get_config().unwrap(), new_balloon_cfg);
+
+        // The balloon cannot be larger than the guest memory.
+        new_balloon_cfg.amount_mib = vm_resources.vm_config().mem_size_mib.unwrap() as u32 + 1;
+        match vm_resources.set_balloon_device(new_balloon_cfg) {
+            Err(BalloonConfigError::TooManyPagesRequested) => (),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_set_entropy_device() {
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.entropy.get().is_none());
+        vm_resources
+            .set_entropy_device(EntropyDeviceConfig {})
+            .unwrap();
+        assert_eq!(
+            vm_resources.entropy.get_config().unwrap(),
+            EntropyDeviceConfig {}
+        );
+    }
+
+    #[test]
+    fn test_set_gpu_device() {
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.gpu.get().is_none());
+
+        let mut socket = TempFile::new().unwrap();
+        socket.remove().unwrap();
+        let config = GpuDeviceConfig {
+            socket_path: socket.as_path().to_str().unwrap().to_string(),
+            width: 640,
+            height: 480,
+            blob: false,
+        };
+        vm_resources.set_gpu_device(config.clone()).unwrap();
+        assert_eq!(vm_resources.gpu.get_config().unwrap(), config);
+        std::fs::remove_file(&config.socket_path).unwrap();
+    }
+
+    #[test]
+    fn test_set_input_device() {
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.input.get().is_none());
+        vm_resources.set_input_device(InputDeviceConfig {}).unwrap();
+        assert_eq!(
+            vm_resources.input.get_config().unwrap(),
+            InputDeviceConfig {}
+        );
+    }
+
+    #[test]
+    fn test_set_sound_device() {
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.sound.get().is_none());
+
+        let file = TempFile::new().unwrap();
+        let config = SoundDeviceConfig {
+            backend: SoundBackendType::File,
+            path: file.as_path().to_str().unwrap().to_string(),
+            rate: 44100,
+            channels: 1,
+        };
+        vm_resources.set_sound_device(config.clone()).unwrap();
+        assert_eq!(vm_resources.sound.get_config().unwrap(), config);
+    }
+
+    #[test]
+    fn test_set_pci_passthrough_device() {
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.pci_passthrough.configs().is_empty());
+
+        // There is no host device at this address.
+        let config = PciPassthroughConfig {
+            dev_id: "gpu".to_string(),
+            host_bdf: "ffff:ff:1f.7".to_string(),
+        };
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            vm_resources.set_pci_passthrough_device(config),
+            Err(PciPassthroughConfigError::HostDeviceNotFound(
+                "ffff:ff:1f.7".to_string()
+            ))
+        );
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            vm_resources.set_pci_passthrough_device(config),
+            Err(PciPassthroughConfigError::NotSupported)
+        );
+        assert!(vm_resources.pci_passthrough.configs().is_empty());
+    }
+
+    #[test]
+    fn test_set_shared_memory_device() {
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.shared_memory.configs().is_empty());
+
+        let mem_file = TempFile::new().unwrap();
+        mem_file.as_file().set_len(0x1000).unwrap();
+        let config = SharedMemoryConfig {
+            shm_id: "shm".to_string(),
+            mem_path: mem_file.as_path().to_path_buf(),
+            peer_socket: None,
+        };
+        #[cfg(target_arch = "x86_64")]
+        {
+            vm_resources
+                .set_shared_memory_device(config.clone())
+                .unwrap();
+            assert_eq!(vm_resources.shared_memory.configs(), &[config]);
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            assert_eq!(
+                vm_resources.set_shared_memory_device(config),
+                Err(SharedMemoryConfigError::NotSupported)
+            );
+            assert!(vm_resources.shared_memory.configs().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_set_rtc_config() {
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.rtc_config().is_none());
+
+        let mut rtc_config = RtcConfig {
+            base_time: Some(946_684_800),
+            utc_offset_minutes: 60,
+        };
+        vm_resources.set_rtc_config(rtc_config.clone()).unwrap();
+        assert_eq!(vm_resources.rtc_config(), Some(&rtc_config));
+
+        rtc_config.utc_offset_minutes = 24 * 60;
+        assert_eq!(
+            vm_resources.set_rtc_config(rtc_config),
+            Err(RtcConfigError::InvalidUtcOffset(24 * 60))
+        );
+    }
+
+    #[test]
+    fn test_set_rate_limit_policy() {
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.rate_limit_policy().is_none());
+
+        let mut policy = RateLimitPolicyConfig {
+            signal: PressureSignal::Psi {
+                resource: PsiResource::Memory,
+            },
+            high_watermark: 40,
+            low_watermark: 10,
+            period_ms: 1000,
+            decrease_percent: 25,
+            increase_percent: 10,
+            floor_percent: 10,
+        };
+        vm_resources.set_rate_limit_policy(policy.clone()).unwrap();
+        assert_eq!(vm_resources.rate_limit_policy(), Some(&policy));
+
+        policy.low_watermark = 50;
+        assert_eq!(
+            vm_resources.set_rate_limit_policy(policy),
+            Err(RateLimitPolicyError::InvalidWatermarks(50, 40))
+        );
+    }
+
+    #[test]
+    fn test_set_virtio_feature_policy() {
+        let mut vm_resources = default_vm_resources();
+        assert_eq!(
+            vm_resources.virtio_feature_policy(),
+            &VirtioFeaturePolicyConfig::default()
+        );
+
+        let mut policy = VirtioFeaturePolicyConfig {
+            block: Some(VirtioFeatureMask {
+                allow: Some(0x1_0000_0fff),
+                deny: 0,
+            }),
+            ..Default::default()
+        };
+        vm_resources
+            .set_virtio_feature_policy(policy.clone())
+            .unwrap();
+        assert_eq!(vm_resources.virtio_feature_policy(), &policy);
+
+        policy.block = Some(VirtioFeatureMask {
+            allow: Some(0xfff),
+            deny: 0,
+        });
+        assert_eq!(
+            vm_resources.set_virtio_feature_policy(policy),
+            Err(VirtioFeaturePolicyError::VersionWithheld("block"))
+        );
+    }
+
+    #[test]
+    fn test_set_cloud_init() {
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.cloud_init().is_none());
+
+        let mut config = CloudInitConfig {
+            user_data: String::from("#cloud-config\n"),
+            meta_data: None,
+            transport: CloudInitTransport::Seed,
+        };
+        vm_resources.set_cloud_init(config.clone()).unwrap();
+        assert_eq!(vm_resources.cloud_init(), Some(&config));
+
+        config.user_data = "x".repeat(MAX_CLOUD_INIT_DATA_SIZE);
+        assert_eq!(
+            vm_resources.set_cloud_init(config),
+            Err(CloudInitConfigError::DataTooLarge(
+                MAX_CLOUD_INIT_DATA_SIZE + DEFAULT_META_DATA.len()
+            ))
+        );
+        assert_eq!(
+            vm_resources.cloud_init().unwrap().user_data,
+            "#cloud-config\n"
+        );
+    }
+
+    #[test]
+    fn test_set_console_config() {
+        let mut vm_resources = default_vm_resources();
+        assert_eq!(vm_resources.console_config(), &ConsoleConfig::default());
+
+        let mut console_config = ConsoleConfig {
+            input: ConsoleInput::Api,
+            socket_path: None,
+        };
+        vm_resources
+            .set_console_config(console_config.clone())
+            .unwrap();
+        assert_eq!(vm_resources.console_config(), &console_config);
+
+        console_config.input = ConsoleInput::Socket;
+        match vm_resources.set_console_config(console_config) {
+            Err(ConsoleConfigError::MissingSocketPath) => (),
+            _ => panic!("Unexpected result"),
+        }
+        assert_eq!(vm_resources.console_config().input, ConsoleInput::Api);
+    }
+
+    #[test]
+    #[cfg(feature = "sev")]
+    fn test_set_sev_config() {
+        use vmm_config::sev::SEV_POLICY_ES;
+
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.sev_config().is_none());
+
+        let mut sev_config = SevConfig {
+            policy: SEV_POLICY_ES,
+            es: true,
+        };
+        vm_resources.set_sev_config(sev_config.clone()).unwrap();
+        assert_eq!(vm_resources.sev_config(), Some(&sev_config));
+
+        sev_config.es = false;
+        assert_eq!(
+            vm_resources.set_sev_config(sev_config),
+            Err(SevConfigError::InvalidPolicy)
+        );
     }
 
     #[test]
@@ -832,7 +3344,7 @@ mod tests {
 
         // Clone the existing net config in order to obtain a new one.
         let mut new_net_device_cfg = default_net_cfg();
-        new_net_device_cfg.iface_id = "new_net_if".to_string();
+        new_net_device_cfg.iface_id = Identifier::try_from("new_net_if").unwrap();
         new_net_device_cfg.guest_mac = Some(MacAddr::parse_str("01:23:45:67:89:0c").unwrap());
         new_net_device_cfg.host_dev_name = "dummy_path2".to_string();
         assert_eq!(vm_resources.net_builder.len(), 1);
@@ -840,4 +3352,19 @@ mod tests {
         vm_resources.build_net_device(new_net_device_cfg).unwrap();
         assert_eq!(vm_resources.net_builder.len(), 2);
     }
+
+    #[test]
+    fn test_remove_net_device() {
+        let mut vm_resources = default_vm_resources();
+        assert_eq!(vm_resources.net_builder.len(), 1);
+
+        vm_resources.remove_net_device("net_if1").unwrap();
+        assert_eq!(vm_resources.net_builder.len(), 0);
+        assert!(vm_resources.net_builder.configs().is_empty());
+
+        match vm_resources.remove_net_device("net_if1") {
+            Err(NetworkInterfaceError::DeviceIdNotFound) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
 }
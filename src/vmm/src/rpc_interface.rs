@@ -1,40 +1,93 @@
 // Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::convert::TryInto;
 use std::fmt::{Display, Formatter};
-use std::fs::OpenOptions;
 use std::io::{Seek, SeekFrom};
+use std::os::unix::io::RawFd;
 use std::path::Path;
 use std::result;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use super::Vmm;
 
 use super::Error as VmmError;
+use action_policy::{self, ActionPolicy, AllowAll};
 use arch::DeviceType;
 use builder::StartMicrovmError;
+use device_list::{
+    DeviceBackend, DeviceDescription, DeviceKind, DeviceStateDump, RateLimiterDescription,
+    VsockConnectionDescription,
+};
 use device_manager::mmio::MMIO_CFG_SPACE_OFF;
-use devices::virtio::{Block, MmioTransport, Net, TYPE_BLOCK, TYPE_NET};
-use logger::METRICS;
+use devices::virtio::balloon::BALLOON_DEV_ID;
+use devices::virtio::input::INPUT_DEV_ID;
+use devices::virtio::net::ImpairmentParams;
+use devices::virtio::{
+    copy_disk_image, open_disk_image, reflink_disk_image, Balloon, Block, Input, MmioTransport,
+    Net, VirtioDevice, VirtioInputEvent, TYPE_BALLOON, TYPE_BLOCK, TYPE_INPUT, TYPE_NET,
+};
+use error_code::{ErrorCategory, ErrorCode, FcExitCode};
+use events::VmmEvent;
+use guest_dmesg::{GuestDmesgError, LogRecord};
+use guest_freeze::GuestFreeze;
+use guest_memory_access::GuestMemoryAccessError;
+use host_capabilities::{self, HostCapabilities};
+use idempotency::IdempotencyCache;
+#[cfg(target_arch = "x86_64")]
+use live_update::{self, LiveUpdateError};
+use logger::{LatencyHistogram, METRICS};
+#[cfg(target_arch = "x86_64")]
+use migration::{self, MigrationError};
+use operations::{FollowUp, Job, OperationError, OperationKind, OperationStatus};
+#[cfg(target_arch = "x86_64")]
+use persist::{self, CreateSnapshotError, LoadSnapshotError};
 use polly::event_manager::EventManager;
-use resources::VmResources;
+use rate_limiter::TokenBucket;
+use resource_usage::{self, VmmResourceUsage};
+use resources::{self, ConfigIssue, VmResources, VmmConfig};
 use seccomp::BpfProgram;
 use vmm_config;
+use vmm_config::balloon::{BalloonConfigError, BalloonDeviceConfig, BalloonUpdateConfig};
 use vmm_config::boot_source::{BootSourceConfig, BootSourceConfigError};
-use vmm_config::drive::{BlockDeviceConfig, DriveError};
+use vmm_config::cloud_init::{CloudInitConfig, CloudInitConfigError};
+use vmm_config::console::{ConsoleConfig, ConsoleConfigError};
+use vmm_config::drive::{
+    BlockDeviceConfig, BlockDeviceSnapshotParams, BlockDeviceStateConfig, BlockDeviceUpdateConfig,
+    DriveError, GuestFreezeConfig,
+};
+use vmm_config::entropy::{EntropyConfigError, EntropyDeviceConfig};
+use vmm_config::gpu::{GpuConfigError, GpuDeviceConfig};
+use vmm_config::guest_dmesg::KernelLogSymbols;
+use vmm_config::input::{InputConfigError, InputDeviceConfig, InputEvent};
 use vmm_config::logger::{LoggerConfig, LoggerConfigError};
-use vmm_config::machine_config::{VmConfig, VmConfigError};
+use vmm_config::machine_config::{VcpuStateConfig, VcpuStateError, VmConfig, VmConfigError};
+use vmm_config::memory_limits::{MemoryLimitsConfig, MemoryLimitsConfigError, OomPolicy};
 use vmm_config::metrics::{MetricsConfig, MetricsConfigError};
 use vmm_config::mmds::{MmdsConfig, MmdsConfigError};
 use vmm_config::net::{
     NetworkInterfaceConfig, NetworkInterfaceError, NetworkInterfaceUpdateConfig,
+    NetworkLinkStateConfig,
 };
+use vmm_config::pci_passthrough::{PciPassthroughConfig, PciPassthroughConfigError};
+use vmm_config::preopened_fds::PreopenedFds;
+use vmm_config::probe::{ProbeConfig, ProbeConfigError};
+use vmm_config::rate_limit_policy::{RateLimitPolicyConfig, RateLimitPolicyError};
+use vmm_config::rtc::{RtcConfig, RtcConfigError};
+#[cfg(feature = "sev")]
+use vmm_config::sev::{LaunchMeasurement, SevConfig, SevConfigError};
+use vmm_config::shared_memory::{SharedMemoryConfig, SharedMemoryConfigError};
 use vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams};
+#[cfg(target_arch = "x86_64")]
+use vmm_config::snapshot::{LiveUpdateParams, MigrationParams};
+use vmm_config::sound::{SoundConfigError, SoundDeviceConfig};
+use vmm_config::virtio_features::{VirtioFeaturePolicyConfig, VirtioFeaturePolicyError};
 use vmm_config::vsock::{VsockConfigError, VsockDeviceConfig};
 
 /// This enum represents the public interface of the VMM. Each action contains various
 /// bits of information (ids, paths, etc.).
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub enum VmmAction {
     /// Configure the boot source of the microVM using as input the `ConfigureBootSource`. This
     /// action can only be called before the microVM has booted.
@@ -48,10 +101,54 @@ pub enum VmmAction {
     /// Create a snapshot using as input the `CreateSnapshotParams`. This action can only be called
     /// after the microVM has booted and only when the microVM is in `Paused` state.
     CreateSnapshot(CreateSnapshotParams),
+    /// Drop the connections of the vsock devices, once the data sent by the guest is flushed to
+    /// the host sockets, and notify the guest driver of a transport reset. This action can only
+    /// be called after the microVM has booted.
+    DrainVsockConnections,
+    /// Dump the whole state of the virtio device with the given ID: its transport, its queues,
+    /// its configuration space and the internal state of its handler. This action can only be
+    /// called after the microVM has booted.
+    DumpDeviceState(String),
+    /// Get the events surfaced by the VMM since the previous call. Before the microVM has booted,
+    /// there are no events to report.
+    GetEvents,
+    /// Get the state of the rate limiters in effect on the devices of the microVM. Before the
+    /// microVM has booted, there are no rate limiters to report.
+    GetRateLimiterStats,
+    /// Get the whole configuration of the microVM, in the format of the configuration file. This
+    /// action can only be called before the microVM has booted.
+    GetFullConfiguration,
+    /// Get the launch measurement of the SEV guest. This action can only be called after the
+    /// microVM has booted.
+    #[cfg(feature = "sev")]
+    GetLaunchMeasurement,
+    /// Get the status of the operation with the given ID, running in the background or finished.
+    /// Before the microVM has booted, there are no operations.
+    GetOperationStatus(u64),
     /// Get the configuration of the microVM.
     GetVmConfiguration,
+    /// Extract the kernel log of the guest from the guest memory, locating the log buffer with
+    /// the `KernelLogSymbols` if given, or else from the `VMCOREINFO` note of the guest kernel.
+    /// This action can only be called after the microVM has booted and only when the microVM is
+    /// in `Paused` state.
+    GetGuestDmesg(Option<KernelLogSymbols>),
+    /// Probe what the host offers to the microVMs: KVM and its extensions, taps, vhost-vsock
+    /// and huge pages.
+    GetHostCapabilities,
+    /// Get the host resources used by the VMM process: its memory, apart from the guest memory,
+    /// its threads and its file descriptors.
+    GetVmmResourceUsage,
+    /// List the devices attached to the microVM, along with their runtime state. Before the
+    /// microVM has booted, there are no devices to list.
+    ListDevices,
+    /// List the connections of the vsock devices. Before the microVM has booted, there are no
+    /// connections to list.
+    ListVsockConnections,
     /// Flush the metrics. This action can only be called after the logger has been configured.
     FlushMetrics,
+    /// Run the wrapped action at most once per idempotency key: a retry carrying the key of an
+    /// action which already succeeded gets its outcome without running it again.
+    Idempotent(String, Box<VmmAction>),
     /// Add a new block device or update one that already exists using the `BlockDeviceConfig` as
     /// input. This action can only be called before the microVM has booted.
     InsertBlockDevice(BlockDeviceConfig),
@@ -59,33 +156,139 @@ pub enum VmmAction {
     /// `NetworkInterfaceConfig` as input. This action can only be called before the microVM has
     /// booted.
     InsertNetworkDevice(NetworkInterfaceConfig),
+    /// Add a new host PCI device to pass through to the guest or update one that already exists
+    /// using the `PciPassthroughConfig` as input. This action can only be called before the
+    /// microVM has booted.
+    InsertPciPassthroughDevice(PciPassthroughConfig),
+    /// Add a new probe of the workload inside the guest or update one that already exists using
+    /// the `ProbeConfig` as input. This action can only be called before the microVM has booted.
+    InsertProbe(ProbeConfig),
+    /// Add a new host memory region to share with the guest or update one that already exists
+    /// using the `SharedMemoryConfig` as input. This action can only be called before the
+    /// microVM has booted.
+    InsertSharedMemoryDevice(SharedMemoryConfig),
+    /// Add a new vsock device or update the one with the same ID using the `VsockDeviceConfig`
+    /// as input, keeping the other vsock devices. The configuration of the device is returned
+    /// when its guest CID was leased from a pool. This action can only be called before the
+    /// microVM has booted.
+    InsertVsockDevice(VsockDeviceConfig),
+    /// Hand over the running microVM to the Firecracker process listening on the socket given by
+    /// `LiveUpdateParams`. This action can only be called after the microVM has booted. When
+    /// successful, this Firecracker process exits without responding.
+    #[cfg(target_arch = "x86_64")]
+    LiveUpdate(LiveUpdateParams),
     /// Load the microVM state using as input the `LoadSnapshotParams`. This action can only be
     /// called before the microVM has booted. If this action is successful, the loaded microVM will
     /// be in `Paused` state. Should change this state to `Resumed` for the microVM to run.
     LoadSnapshot(LoadSnapshotParams),
+    /// Migrate the running microVM to the Firecracker process listening on the socket given by
+    /// `MigrationParams`. This action can only be called after the microVM has booted. When
+    /// successful, this Firecracker process exits without responding.
+    #[cfg(target_arch = "x86_64")]
+    Migrate(MigrationParams),
     /// Pause the guest, by pausing the microVM VCPUs.
     Pause,
+    /// Remove the block device with the given ID, so that it isn't attached to the microVM. This
+    /// action can only be called before the microVM has booted.
+    RemoveBlockDevice(String),
+    /// Remove the network interface with the given ID, so that it isn't attached to the microVM.
+    /// This action can only be called before the microVM has booted.
+    RemoveNetworkInterface(String),
     /// Resume the guest, by resuming the microVM VCPUs.
     Resume,
-    /// Set the vsock device or update the one that already exists using the
-    /// `VsockDeviceConfig` as input. This action can only be called before the microVM has
+    /// Set the balloon device or update the one that already exists using the
+    /// `BalloonDeviceConfig` as input. This action can only be called before the microVM
+    /// has booted.
+    SetBalloonDevice(BalloonDeviceConfig),
+    /// Pause or resume a block device, using `BlockDeviceStateConfig` as input. The requests of
+    /// the guest to a paused block device wait until it is resumed. This action can only be
+    /// called after the microVM has booted.
+    SetBlockDeviceState(BlockDeviceStateConfig),
+    /// Set the cloud-init data handed to the guest, using `CloudInitConfig` as input. This
+    /// action can only be called before the microVM has booted.
+    SetCloudInit(CloudInitConfig),
+    /// Set where the input of the serial console comes from, using `ConsoleConfig` as input.
+    /// This action can only be called before the microVM has booted.
+    SetConsoleConfiguration(ConsoleConfig),
+    /// Set the entropy device or replace the one that already exists using the
+    /// `EntropyDeviceConfig` as input. This action can only be called before the microVM
+    /// has booted.
+    SetEntropyDevice(EntropyDeviceConfig),
+    /// Set the GPU device or replace the one that already exists using the `GpuDeviceConfig` as
+    /// input. This action can only be called before the microVM has booted.
+    SetGpuDevice(GpuDeviceConfig),
+    /// Set the input device or replace the one that already exists using the
+    /// `InputDeviceConfig` as input. This action can only be called before the microVM has
+    /// booted.
+    SetInputDevice(InputDeviceConfig),
+    /// Set the memory limits of the Firecracker process and the OOM policy, using
+    /// `MemoryLimitsConfig` as input. This action can only be called before the microVM has
+    /// booted.
+    SetMemoryLimits(MemoryLimitsConfig),
+    /// Bring the link of a network interface up or down, as if its cable was plugged in or
+    /// pulled, using `NetworkLinkStateConfig` as input. This action can only be called after the
+    /// microVM has booted.
+    SetNetworkLinkState(NetworkLinkStateConfig),
+    /// Set how the rate limiters of the devices are adjusted to the pressure on the host, using
+    /// `RateLimitPolicyConfig` as input. This action can only be called before the microVM has
+    /// booted.
+    SetRateLimitPolicy(RateLimitPolicyConfig),
+    /// Set the time at which the guest RTC starts, using `RtcConfig` as input. This action can
+    /// only be called before the microVM has booted.
+    SetRtcConfiguration(RtcConfig),
+    /// Launch the microVM as a SEV guest, configured by `SevConfig`. This action can only be
+    /// called before the microVM has booted.
+    #[cfg(feature = "sev")]
+    SetSevConfiguration(SevConfig),
+    /// Set the sound device or replace the one that already exists using the
+    /// `SoundDeviceConfig` as input. This action can only be called before the microVM has
+    /// booted.
+    SetSoundDevice(SoundDeviceConfig),
+    /// Park or unpark a vCPU, using `VcpuStateConfig` as input. A parked vCPU is kept paused
+    /// while the others run, e.g. to vacate a host core, until it is unparked. This action can
+    /// only be called after the microVM has booted.
+    SetVcpuState(VcpuStateConfig),
+    /// Set the virtio features the devices may offer to their guest drivers, by device type,
+    /// using `VirtioFeaturePolicyConfig` as input. This action can only be called before the
+    /// microVM has booted.
+    SetVirtioFeaturePolicy(VirtioFeaturePolicyConfig),
+    /// Set the only vsock device using the `VsockDeviceConfig` as input, replacing the vsock
+    /// devices configured before. The configuration of the device is returned when its guest
+    /// CID was leased from a pool. This action can only be called before the microVM has
     /// booted.
     SetVsockDevice(VsockDeviceConfig),
     /// Set the microVM configuration (memory & vcpu) using `VmConfig` as input. This
     /// action can only be called before the microVM has booted.
     SetVmConfiguration(VmConfig),
+    /// Copy the host file backing a block device, using `BlockDeviceSnapshotParams` as input,
+    /// e.g. for backing up a single drive. The requests of the guest to the block device wait
+    /// while it is copied. This action can only be called after the microVM has booted.
+    SnapshotBlockDevice(BlockDeviceSnapshotParams),
     /// Launch the microVM. This action can only be called before the microVM has booted.
     StartMicroVm,
     /// Send CTRL+ALT+DEL to the microVM, using the i8042 keyboard function. If an AT-keyboard
     /// driver is listening on the guest end, this can be used to shut down the microVM gracefully.
     #[cfg(target_arch = "x86_64")]
     SendCtrlAltDel,
-    /// Update the path of an existing block device. The data associated with this variant
-    /// represents the `drive_id` and the `path_on_host`.
-    UpdateBlockDevicePath(String, String),
+    /// Send keyboard and mouse events to the microVM through the input device. This action can
+    /// only be called after the microVM has booted.
+    SendInputEvent(Vec<InputEvent>),
+    /// Update the target size of the balloon, after microVM start.
+    UpdateBalloon(BalloonUpdateConfig),
+    /// Update a block device, after microVM start. Currently, the only updatable properties are
+    /// the path of the host file backing the device and the rate limiter.
+    UpdateBlockDevice(BlockDeviceUpdateConfig),
     /// Update a network interface, after microVM start. Currently, the only updatable properties
     /// are the RX and TX rate limiters.
     UpdateNetworkInterface(NetworkInterfaceUpdateConfig),
+    /// Validate the full microVM configuration given as JSON, the way it is validated when
+    /// passed to the Firecracker process with `--config-file`, without applying it. This action
+    /// can be called both before and after the microVM has booted.
+    ValidateConfiguration(String),
+    /// Hand the bytes to the serial console of the microVM, as if typed on its terminal. This
+    /// action can only be called after the microVM has booted, when the input of the serial
+    /// console comes from the API.
+    WriteToConsole(Vec<u8>),
     /// Set the MMDS configuration.
     SetMmdsConfiguration(MmdsConfig),
 }
@@ -93,28 +296,94 @@ pub enum VmmAction {
 /// Wrapper for all errors associated with VMM actions.
 #[derive(Debug)]
 pub enum VmmActionError {
+    /// The action policy rejected the action, for the given reason.
+    ActionDenied(String),
+    /// The action was not queued, since the given maximum number of actions are waiting for the
+    /// VMM already.
+    ActionQueueFull(usize),
+    /// One of the actions `SetBalloonDevice` or `UpdateBalloon` failed.
+    BalloonConfig(BalloonConfigError),
     /// The action `ConfigureBootSource` failed because of bad user input.
     BootSource(BootSourceConfigError),
-    /// One of the actions `InsertBlockDevice` or `UpdateBlockDevicePath`
-    /// failed because of bad user input.
+    /// The action `SetCloudInit` failed because of bad user input.
+    CloudInit(CloudInitConfigError),
+    /// One of the actions `SetConsoleConfiguration` or `WriteToConsole` failed.
+    ConsoleConfig(ConsoleConfigError),
+    /// The action `DumpDeviceState` found no virtio device with the given ID.
+    DeviceNotFound(String),
+    /// The action `CreateSnapshot` failed.
+    #[cfg(target_arch = "x86_64")]
+    CreateSnapshot(CreateSnapshotError),
+    /// One of the actions `InsertBlockDevice`, `RemoveBlockDevice`, `UpdateBlockDevice`,
+    /// `SetBlockDeviceState` or `SnapshotBlockDevice` failed.
     DriveConfig(DriveError),
+    /// The action `SetEntropyDevice` failed.
+    EntropyConfig(EntropyConfigError),
+    /// The action `SetGpuDevice` failed.
+    GpuConfig(GpuConfigError),
+    /// The action `GetGuestDmesg` failed.
+    GuestDmesg(GuestDmesgError),
+    /// The idempotency key of an `Idempotent` action was already used for another action.
+    IdempotencyKeyReused(String),
+    /// One of the actions `SetInputDevice` or `SendInputEvent` failed.
+    InputConfig(InputConfigError),
     /// Internal Vmm error.
     InternalVmm(VmmError),
+    /// The action `LiveUpdate` failed.
+    #[cfg(target_arch = "x86_64")]
+    LiveUpdate(LiveUpdateError),
+    /// The action `LoadSnapshot` failed.
+    #[cfg(target_arch = "x86_64")]
+    LoadSnapshot(LoadSnapshotError),
+    /// The action `LoadSnapshot` is not allowed after configuring the microVM for boot.
+    LoadSnapshotNotAllowed,
     /// The action `ConfigureLogger` failed because of bad user input.
     Logger(LoggerConfigError),
+    /// The action `Migrate` failed.
+    #[cfg(target_arch = "x86_64")]
+    Migration(MigrationError),
     /// One of the actions `GetVmConfiguration` or `SetVmConfiguration` failed because of bad input.
     MachineConfig(VmConfigError),
+    /// The action `SetMemoryLimits` failed.
+    MemoryLimitsConfig(MemoryLimitsConfigError),
     /// The action `ConfigureMetrics` failed because of bad user input.
     Metrics(MetricsConfigError),
-    /// The action `InsertNetworkDevice` failed because of bad user input.
+    /// One of the actions `InsertNetworkDevice`, `RemoveNetworkInterface`,
+    /// `UpdateNetworkInterface` or `SetNetworkLinkState` failed.
     NetworkConfig(NetworkInterfaceError),
+    /// The action was rejected while the operation with the given ID runs in the background.
+    OperationInProgress(u64),
+    /// The action `GetOperationStatus` found no operation with the given ID.
+    OperationNotFound(u64),
     /// The requested operation is not supported after starting the microVM.
     OperationNotSupportedPostBoot,
     /// The requested operation is not supported before starting the microVM.
     OperationNotSupportedPreBoot,
+    /// The action `InsertPciPassthroughDevice` failed because of bad user input.
+    PciPassthroughConfig(PciPassthroughConfigError),
+    /// The action `InsertProbe` failed because of bad user input.
+    ProbeConfig(ProbeConfigError),
+    /// The action `SetRateLimitPolicy` failed because of bad user input.
+    RateLimitPolicy(RateLimitPolicyError),
+    /// The action `SetRtcConfiguration` failed because of bad user input.
+    RtcConfig(RtcConfigError),
+    /// One of the actions `SetSevConfiguration` or `GetLaunchMeasurement` failed.
+    #[cfg(feature = "sev")]
+    SevConfig(SevConfigError),
+    /// The action `InsertSharedMemoryDevice` failed because of bad user input.
+    SharedMemoryConfig(SharedMemoryConfigError),
+    /// The action `SetSoundDevice` failed.
+    SoundConfig(SoundConfigError),
     /// The action `StartMicroVm` failed because of an internal error.
     StartMicrovm(StartMicrovmError),
-    /// The action `SetVsockDevice` failed because of bad user input.
+    /// The action `ValidateConfiguration` found the configuration invalid.
+    ValidateConfiguration(resources::Error),
+    /// The action `SetVcpuState` failed.
+    VcpuState(VcpuStateError),
+    /// The action `SetVirtioFeaturePolicy` failed because of bad user input.
+    VirtioFeaturePolicy(VirtioFeaturePolicyError),
+    /// The action `SetVsockDevice` or `InsertVsockDevice` failed because of bad user input, or
+    /// the connections of the vsock device cannot be listed or drained.
     VsockConfig(VsockConfigError),
     /// The action `SetMmdsConfiguration` failed because of bad user input.
     MmdsConfig(MmdsConfigError),
@@ -128,13 +397,49 @@ impl Display for VmmActionError {
             f,
             "{}",
             match self {
+                ActionDenied(reason) => format!("The action is not allowed: {}", reason),
+                ActionQueueFull(max) => format!(
+                    "The VMM is busy: {} actions are waiting for it already.",
+                    max
+                ),
+                BalloonConfig(err) => err.to_string(),
                 BootSource(err) => err.to_string(),
+                CloudInit(err) => err.to_string(),
+                ConsoleConfig(err) => err.to_string(),
+                #[cfg(target_arch = "x86_64")]
+                CreateSnapshot(err) => format!("Cannot create the snapshot: {}", err),
+                DeviceNotFound(id) => format!("No virtio device with the ID {}.", id),
                 DriveConfig(err) => err.to_string(),
+                EntropyConfig(err) => err.to_string(),
+                GpuConfig(err) => err.to_string(),
+                GuestDmesg(err) => format!("Cannot read the kernel log of the guest: {}", err),
+                IdempotencyKeyReused(key) => format!(
+                    "The idempotency key {} was already used for another action.",
+                    key
+                ),
+                InputConfig(err) => err.to_string(),
                 InternalVmm(err) => format!("Internal Vmm error: {}", err),
+                #[cfg(target_arch = "x86_64")]
+                LiveUpdate(err) => format!("Live update failed: {}", err),
+                #[cfg(target_arch = "x86_64")]
+                LoadSnapshot(err) => format!("Cannot load the snapshot: {}", err),
+                LoadSnapshotNotAllowed => {
+                    "Loading a snapshot is not allowed after configuring the microVM for boot."
+                        .to_string()
+                }
                 Logger(err) => err.to_string(),
                 MachineConfig(err) => err.to_string(),
+                MemoryLimitsConfig(err) => err.to_string(),
                 Metrics(err) => err.to_string(),
+                #[cfg(target_arch = "x86_64")]
+                Migration(err) => format!("Migration failed: {}", err),
                 NetworkConfig(err) => err.to_string(),
+                OperationInProgress(id) => format!(
+                    "The operation {} is running in the background: only the actions which \
+                     leave the microVM alone are allowed until it completes.",
+                    id
+                ),
+                OperationNotFound(id) => format!("No operation with the ID {}.", id),
                 OperationNotSupportedPostBoot => {
                     "The requested operation is not supported after starting the microVM."
                         .to_string()
@@ -143,8 +448,20 @@ impl Display for VmmActionError {
                     "The requested operation is not supported before starting the microVM."
                         .to_string()
                 }
+                PciPassthroughConfig(err) => err.to_string(),
+                ProbeConfig(err) => err.to_string(),
+                RateLimitPolicy(err) => err.to_string(),
+                RtcConfig(err) => err.to_string(),
+                #[cfg(feature = "sev")]
+                SevConfig(err) => err.to_string(),
+                SharedMemoryConfig(err) => err.to_string(),
+                SoundConfig(err) => err.to_string(),
                 StartMicrovm(err) => err.to_string(),
-                /// The action `SetVsockDevice` failed because of bad user input.
+                ValidateConfiguration(err) => format!("Invalid configuration: {}", err),
+                VcpuState(err) => err.to_string(),
+                VirtioFeaturePolicy(err) => err.to_string(),
+                /// The action `SetVsockDevice` or `InsertVsockDevice` failed because of bad user
+                /// input.
                 VsockConfig(err) => err.to_string(),
                 MmdsConfig(err) => err.to_string(),
             }
@@ -152,18 +469,258 @@ impl Display for VmmActionError {
     }
 }
 
+impl std::error::Error for VmmActionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use self::VmmActionError::*;
+
+        match self {
+            BalloonConfig(err) => Some(err),
+            BootSource(err) => Some(err),
+            CloudInit(err) => Some(err),
+            ConsoleConfig(err) => Some(err),
+            #[cfg(target_arch = "x86_64")]
+            CreateSnapshot(err) => Some(err),
+            DriveConfig(err) => Some(err),
+            EntropyConfig(err) => Some(err),
+            GpuConfig(err) => Some(err),
+            GuestDmesg(err) => Some(err),
+            ActionDenied(_) | DeviceNotFound(_) | IdempotencyKeyReused(_) => None,
+            ActionQueueFull(_) | OperationInProgress(_) | OperationNotFound(_) => None,
+            InputConfig(err) => Some(err),
+            InternalVmm(err) => Some(err),
+            #[cfg(target_arch = "x86_64")]
+            LiveUpdate(err) => Some(err),
+            #[cfg(target_arch = "x86_64")]
+            LoadSnapshot(err) => Some(err),
+            Logger(err) => Some(err),
+            MachineConfig(err) => Some(err),
+            MemoryLimitsConfig(err) => Some(err),
+            Metrics(err) => Some(err),
+            #[cfg(target_arch = "x86_64")]
+            Migration(err) => Some(err),
+            NetworkConfig(err) => Some(err),
+            PciPassthroughConfig(err) => Some(err),
+            ProbeConfig(err) => Some(err),
+            RateLimitPolicy(err) => Some(err),
+            RtcConfig(err) => Some(err),
+            #[cfg(feature = "sev")]
+            SevConfig(err) => Some(err),
+            SharedMemoryConfig(err) => Some(err),
+            SoundConfig(err) => Some(err),
+            StartMicrovm(err) => Some(err),
+            ValidateConfiguration(err) => Some(err),
+            VirtioFeaturePolicy(err) => Some(err),
+            VsockConfig(err) => Some(err),
+            MmdsConfig(err) => Some(err),
+            LoadSnapshotNotAllowed
+            | OperationNotSupportedPostBoot
+            | OperationNotSupportedPreBoot => None,
+        }
+    }
+}
+
+impl VmmActionError {
+    /// The stable code of the error, which control planes can branch on.
+    pub fn error_code(&self) -> ErrorCode {
+        use self::VmmActionError::*;
+
+        match self {
+            ActionDenied(_) => ErrorCode::ActionDenied,
+            ActionQueueFull(_) => ErrorCode::ActionQueueFull,
+            BalloonConfig(_) => ErrorCode::BalloonConfig,
+            BootSource(_) => ErrorCode::BootSource,
+            CloudInit(_) => ErrorCode::CloudInit,
+            ConsoleConfig(_) => ErrorCode::ConsoleConfig,
+            #[cfg(target_arch = "x86_64")]
+            CreateSnapshot(_) => ErrorCode::CreateSnapshot,
+            DeviceNotFound(_) => ErrorCode::DeviceNotFound,
+            DriveConfig(_) => ErrorCode::DriveConfig,
+            EntropyConfig(_) => ErrorCode::EntropyConfig,
+            GpuConfig(_) => ErrorCode::GpuConfig,
+            GuestDmesg(_) => ErrorCode::GuestDmesg,
+            IdempotencyKeyReused(_) => ErrorCode::IdempotencyKeyReused,
+            InputConfig(_) => ErrorCode::InputConfig,
+            InternalVmm(_) => ErrorCode::InternalVmm,
+            #[cfg(target_arch = "x86_64")]
+            LiveUpdate(_) => ErrorCode::LiveUpdate,
+            #[cfg(target_arch = "x86_64")]
+            LoadSnapshot(_) => ErrorCode::LoadSnapshot,
+            LoadSnapshotNotAllowed => ErrorCode::LoadSnapshotNotAllowed,
+            Logger(_) => ErrorCode::Logger,
+            MachineConfig(_) => ErrorCode::MachineConfig,
+            MemoryLimitsConfig(_) => ErrorCode::MemoryLimitsConfig,
+            Metrics(_) => ErrorCode::Metrics,
+            #[cfg(target_arch = "x86_64")]
+            Migration(_) => ErrorCode::Migration,
+            NetworkConfig(_) => ErrorCode::NetworkConfig,
+            OperationInProgress(_) => ErrorCode::OperationInProgress,
+            OperationNotFound(_) => ErrorCode::OperationNotFound,
+            OperationNotSupportedPostBoot => ErrorCode::OperationNotSupportedPostBoot,
+            OperationNotSupportedPreBoot => ErrorCode::OperationNotSupportedPreBoot,
+            PciPassthroughConfig(_) => ErrorCode::PciPassthroughConfig,
+            ProbeConfig(_) => ErrorCode::ProbeConfig,
+            RateLimitPolicy(_) => ErrorCode::RateLimitPolicy,
+            RtcConfig(_) => ErrorCode::RtcConfig,
+            #[cfg(feature = "sev")]
+            SevConfig(_) => ErrorCode::SevConfig,
+            SharedMemoryConfig(_) => ErrorCode::SharedMemoryConfig,
+            SoundConfig(_) => ErrorCode::SoundConfig,
+            StartMicrovm(StartMicrovmError::InconsistentConfig(_)) => {
+                ErrorCode::InvalidConfiguration
+            }
+            StartMicrovm(_) => ErrorCode::StartMicrovm,
+            ValidateConfiguration(_) => ErrorCode::InvalidConfiguration,
+            VcpuState(_) => ErrorCode::VcpuState,
+            VirtioFeaturePolicy(_) => ErrorCode::VirtioFeaturePolicy,
+            VsockConfig(_) => ErrorCode::VsockConfig,
+            MmdsConfig(_) => ErrorCode::MmdsConfig,
+        }
+    }
+
+    /// Who can fix the cause of the error. The errors of starting, live updating and migrating
+    /// the microVM have many causes, so their category depends on the cause rather than on
+    /// their code.
+    pub fn error_category(&self) -> ErrorCategory {
+        use self::VmmActionError::*;
+
+        match self {
+            StartMicrovm(err) => err.category(),
+            #[cfg(target_arch = "x86_64")]
+            LiveUpdate(err) => err.category(),
+            #[cfg(target_arch = "x86_64")]
+            Migration(err) => err.category(),
+            _ => self.error_code().category(),
+        }
+    }
+
+    /// Specifies if the same request may succeed when retried later, without any other action.
+    pub fn retriable(&self) -> bool {
+        use self::VmmActionError::*;
+
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            LiveUpdate(err) => err.retriable(),
+            #[cfg(target_arch = "x86_64")]
+            Migration(err) => err.retriable(),
+            _ => self.error_code().retriable(),
+        }
+    }
+
+    /// The problems found by cross-checking the resources of the microVM, when the action failed
+    /// because of them.
+    pub fn config_issues(&self) -> &[ConfigIssue] {
+        use self::VmmActionError::*;
+
+        match self {
+            StartMicrovm(StartMicrovmError::InconsistentConfig(issues))
+            | ValidateConfiguration(resources::Error::InconsistentConfig(issues)) => &issues.0,
+            _ => &[],
+        }
+    }
+}
+
+/// The version of the wire schema of `VmmData`, bumped whenever a response changes in a way
+/// which older consumers can't parse.
+pub const VMM_DATA_SCHEMA_VERSION: u32 = 1;
+
 /// The enum represents the response sent by the VMM in case of success. The response is either
 /// empty, when no data needs to be sent, or an internal VMM structure.
-#[derive(Debug)]
+///
+/// Serialized, the kind of response is named by `type`, and the structure it carries, if any, is
+/// held by `data`. The API serves the `data` alone.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum VmmData {
+    /// The devices attached to the microVM.
+    DeviceList(Vec<DeviceDescription>),
+    /// The whole state of a virtio device.
+    DeviceState(Box<DeviceStateDump>),
     /// No data is sent on the channel.
     Empty,
+    /// The events surfaced by the VMM since they were last retrieved.
+    Events(Vec<VmmEvent>),
+    /// The whole configuration of the microVM.
+    FullConfiguration(Box<VmmConfig>),
+    /// The records of the kernel log of the guest.
+    GuestDmesg(Vec<LogRecord>),
+    /// What the host offers to the microVMs.
+    HostCapabilities(HostCapabilities),
+    /// The launch measurement of the SEV guest.
+    #[cfg(feature = "sev")]
+    LaunchMeasurement(LaunchMeasurement),
     /// The microVM configuration represented by `VmConfig`.
     MachineConfiguration(VmConfig),
+    /// The status of an operation running in the background or finished.
+    Operation(OperationStatus),
     /// No data is sent on the channel as the operation doesn't
     /// have a handler implemented yet.
     // This should be removed once we add an implementation for it.
     NotFound,
+    /// The state of the rate limiters in effect.
+    RateLimiterStats(Vec<RateLimiterDescription>),
+    /// The host resources used by the VMM process.
+    ResourceUsage(VmmResourceUsage),
+    /// The connections of the vsock device.
+    VsockConnections(Vec<VsockConnectionDescription>),
+    /// The configuration of a vsock device, with the guest CID leased from its pool.
+    VsockDevice(VsockDeviceConfig),
+}
+
+impl VmmData {
+    /// Serializes the structure carried by the response, as served by the API, or returns `None`
+    /// when the response carries none.
+    pub fn payload_json(&self) -> Option<String> {
+        let payload = match self {
+            VmmData::DeviceList(devices) => serde_json::to_string(devices),
+            VmmData::DeviceState(dump) => serde_json::to_string(dump),
+            VmmData::Empty | VmmData::NotFound => return None,
+            VmmData::Events(events) => serde_json::to_string(events),
+            VmmData::FullConfiguration(config) => serde_json::to_string(config),
+            VmmData::GuestDmesg(records) => serde_json::to_string(records),
+            VmmData::HostCapabilities(capabilities) => serde_json::to_string(capabilities),
+            #[cfg(feature = "sev")]
+            VmmData::LaunchMeasurement(measurement) => serde_json::to_string(measurement),
+            // The API has always served the machine configuration in its own format.
+            VmmData::MachineConfiguration(vm_config) => Ok(vm_config.to_string()),
+            VmmData::Operation(status) => serde_json::to_string(status),
+            VmmData::RateLimiterStats(rate_limiters) => serde_json::to_string(rate_limiters),
+            VmmData::ResourceUsage(usage) => serde_json::to_string(usage),
+            VmmData::VsockConnections(connections) => serde_json::to_string(connections),
+            VmmData::VsockDevice(config) => serde_json::to_string(config),
+        };
+        // Serializing plain data structures cannot fail.
+        Some(payload.expect("Cannot serialize the VMM data"))
+    }
+}
+
+/// A `VmmData` along with the version of its wire schema, for the consumers which exchange the
+/// responses of the VMM outside of the API.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct VersionedVmmData {
+    /// The version of the wire schema of `response`.
+    pub schema_version: u32,
+    /// The response.
+    pub response: VmmData,
+}
+
+impl VersionedVmmData {
+    /// Returns the response, or the version of its schema if it isn't the current one.
+    pub fn into_data(self) -> result::Result<VmmData, u32> {
+        if self.schema_version == VMM_DATA_SCHEMA_VERSION {
+            Ok(self.response)
+        } else {
+            Err(self.schema_version)
+        }
+    }
+}
+
+impl From<VmmData> for VersionedVmmData {
+    fn from(response: VmmData) -> Self {
+        VersionedVmmData {
+            schema_version: VMM_DATA_SCHEMA_VERSION,
+            response,
+        }
+    }
 }
 
 /// Enables pre-boot setup and instantiation of a Firecracker VMM.
@@ -173,6 +730,10 @@ pub struct PrebootApiController<'a> {
     vm_resources: &'a mut VmResources,
     event_manager: &'a mut EventManager,
     built_vmm: Option<Arc<Mutex<Vmm>>>,
+    // Whether the microVM was configured for boot, which rules out loading a snapshot.
+    boot_path: bool,
+    idempotency: IdempotencyCache,
+    action_policy: Arc<dyn ActionPolicy>,
 }
 
 impl<'a> PrebootApiController<'a> {
@@ -189,32 +750,50 @@ impl<'a> PrebootApiController<'a> {
             vm_resources,
             event_manager,
             built_vmm: None,
+            boot_path: false,
+            idempotency: IdempotencyCache::default(),
+            action_policy: Arc::new(AllowAll),
         }
     }
 
+    /// Makes `action_policy` decide which of the following actions are carried out.
+    pub fn set_action_policy(&mut self, action_policy: Arc<dyn ActionPolicy>) {
+        self.action_policy = action_policy;
+    }
+
     /// Default implementation for the function that builds and starts a microVM.
     /// It takes two closures `recv_req` and `respond` as params which abstract away
     /// the message transport.
     ///
-    /// Returns a populated `VmResources` object and a running `Vmm` object.
+    /// The devices are backed by the files in `preopened_fds` when given, instead of the files
+    /// at the paths in their configuration. The boot arguments can refer to `instance_id`.
+    ///
+    /// Returns a populated `VmResources` object, a running `Vmm` object, and the idempotency keys
+    /// of the actions which succeeded, so that their retries still aren't applied after boot.
     pub fn build_microvm_from_requests<F, G>(
         seccomp_filter: BpfProgram,
         event_manager: &mut EventManager,
         firecracker_version: String,
+        action_policy: Arc<dyn ActionPolicy>,
+        preopened_fds: PreopenedFds,
+        instance_id: String,
         recv_req: F,
         respond: G,
-    ) -> (VmResources, Arc<Mutex<Vmm>>)
+    ) -> (VmResources, Arc<Mutex<Vmm>>, IdempotencyCache)
     where
         F: Fn() -> VmmAction,
         G: Fn(result::Result<VmmData, VmmActionError>),
     {
         let mut vm_resources = VmResources::default();
+        vm_resources.set_preopened_fds(preopened_fds);
+        vm_resources.set_instance_id(instance_id);
         let mut preboot_controller = PrebootApiController::new(
             seccomp_filter,
             firecracker_version,
             &mut vm_resources,
             event_manager,
         );
+        preboot_controller.set_action_policy(action_policy);
         // Configure and start microVM through successive API calls.
         // Iterate through API calls to configure microVm.
         // The loop breaks when a microVM is successfully started, and a running Vmm is built.
@@ -225,62 +804,220 @@ impl<'a> PrebootApiController<'a> {
 
         // Safe to unwrap because previous loop cannot end on None.
         let vmm = preboot_controller.built_vmm.unwrap();
-        (vm_resources, vmm)
+        let idempotency = preboot_controller.idempotency;
+        (vm_resources, vmm, idempotency)
     }
 
     /// Handles the incoming preboot request and provides a response for it.
-    /// Returns a built/running `Vmm` after handling a successful `StartMicroVm` request.
+    /// Returns a built/running `Vmm` after handling a successful `StartMicroVm` request, or a
+    /// paused `Vmm` after handling a successful `LoadSnapshot` request.
     pub fn handle_preboot_request(
         &mut self,
         request: VmmAction,
     ) -> result::Result<VmmData, VmmActionError> {
         use self::VmmAction::*;
+        let _timer = ActionLatencyTimer::start(&request);
 
-        match request {
+        match action_policy::apply(&*self.action_policy, request)? {
             // Supported operations allowed pre-boot.
-            ConfigureBootSource(boot_source_body) => self
-                .vm_resources
-                .set_boot_source(boot_source_body)
-                .map(|_| VmmData::Empty)
-                .map_err(VmmActionError::BootSource),
-            ConfigureLogger(logger_cfg) => {
-                vmm_config::logger::init_logger(logger_cfg, &self.firecracker_version)
+            ConfigureBootSource(boot_source_body) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .set_boot_source(boot_source_body)
                     .map(|_| VmmData::Empty)
-                    .map_err(VmmActionError::Logger)
+                    .map_err(VmmActionError::BootSource)
             }
-            ConfigureMetrics(metrics_cfg) => vmm_config::metrics::init_metrics(metrics_cfg)
+            ConfigureLogger(logger_cfg) => self
+                .vm_resources
+                .set_logger_config(logger_cfg, &self.firecracker_version)
+                .map(|_| VmmData::Empty)
+                .map_err(VmmActionError::Logger),
+            ConfigureMetrics(metrics_cfg) => self
+                .vm_resources
+                .set_metrics_config(metrics_cfg)
                 .map(|_| VmmData::Empty)
                 .map_err(VmmActionError::Metrics),
+            GetEvents => Ok(VmmData::Events(Vec::new())),
+            GetFullConfiguration => Ok(VmmData::FullConfiguration(Box::new(VmmConfig::from(
+                &*self.vm_resources,
+            )))),
+            GetHostCapabilities => Ok(VmmData::HostCapabilities(host_capabilities::probe())),
+            GetOperationStatus(id) => Err(VmmActionError::OperationNotFound(id)),
+            GetRateLimiterStats => Ok(VmmData::RateLimiterStats(Vec::new())),
             GetVmConfiguration => Ok(VmmData::MachineConfiguration(
                 self.vm_resources.vm_config().clone(),
             )),
-            InsertBlockDevice(block_device_config) => self
-                .vm_resources
-                .set_block_device(block_device_config)
-                .map(|_| VmmData::Empty)
-                .map_err(VmmActionError::DriveConfig),
-            InsertNetworkDevice(netif_body) => self
-                .vm_resources
-                .build_net_device(netif_body)
-                .map(|_| VmmData::Empty)
-                .map_err(VmmActionError::NetworkConfig),
+            // Before boot, there is no guest memory to tell apart.
+            GetVmmResourceUsage => resource_usage::sample(None)
+                .map(VmmData::ResourceUsage)
+                .map_err(|e| VmmActionError::InternalVmm(VmmError::ResourceUsage(e))),
+            ListDevices => Ok(VmmData::DeviceList(Vec::new())),
+            ListVsockConnections => Ok(VmmData::VsockConnections(Vec::new())),
+            Idempotent(key, action) => {
+                if let Some(outcome) = self.idempotency.lookup(&key, &action)? {
+                    return Ok(outcome);
+                }
+                let recorded = (*action).clone();
+                let outcome = self.handle_preboot_request(*action);
+                self.idempotency.record(key, recorded, &outcome);
+                outcome
+            }
+            InsertBlockDevice(block_device_config) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .set_block_device(block_device_config)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::DriveConfig)
+            }
+            InsertNetworkDevice(netif_body) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .build_net_device(netif_body)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::NetworkConfig)
+            }
+            InsertPciPassthroughDevice(config) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .set_pci_passthrough_device(config)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::PciPassthroughConfig)
+            }
+            InsertProbe(probe_config) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .set_probe(probe_config)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::ProbeConfig)
+            }
+            InsertSharedMemoryDevice(config) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .set_shared_memory_device(config)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::SharedMemoryConfig)
+            }
+            InsertVsockDevice(vsock_cfg) => {
+                self.boot_path = true;
+                self.set_vsock_device(vsock_cfg, false)
+                    .map_err(VmmActionError::VsockConfig)
+            }
+            #[cfg(target_arch = "x86_64")]
+            LoadSnapshot(snapshot_load_cfg) => self.load_snapshot(&snapshot_load_cfg),
+            #[cfg(target_arch = "aarch64")]
             LoadSnapshot(_snapshot_load_cfg) => Ok(VmmData::NotFound),
-            Resume => Ok(VmmData::NotFound),
-            SetVsockDevice(vsock_cfg) => self
+            RemoveBlockDevice(drive_id) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .remove_block_device(&drive_id)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::DriveConfig)
+            }
+            RemoveNetworkInterface(iface_id) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .remove_net_device(&iface_id)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::NetworkConfig)
+            }
+            SetBalloonDevice(balloon_cfg) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .set_balloon_device(balloon_cfg)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::BalloonConfig)
+            }
+            SetCloudInit(cloud_init) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .set_cloud_init(cloud_init)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::CloudInit)
+            }
+            SetEntropyDevice(entropy_cfg) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .set_entropy_device(entropy_cfg)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::EntropyConfig)
+            }
+            SetGpuDevice(gpu_cfg) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .set_gpu_device(gpu_cfg)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::GpuConfig)
+            }
+            SetInputDevice(input_cfg) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .set_input_device(input_cfg)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::InputConfig)
+            }
+            SetVsockDevice(vsock_cfg) => {
+                self.boot_path = true;
+                self.set_vsock_device(vsock_cfg, true)
+                    .map_err(VmmActionError::VsockConfig)
+            }
+            SetVmConfiguration(machine_config_body) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .set_vm_config(&machine_config_body)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::MachineConfig)
+            }
+            SetMmdsConfiguration(mmds_config) => self
                 .vm_resources
-                .set_vsock_device(vsock_cfg)
+                .set_mmds_config(mmds_config)
                 .map(|_| VmmData::Empty)
-                .map_err(VmmActionError::VsockConfig),
-            SetVmConfiguration(machine_config_body) => self
+                .map_err(VmmActionError::MmdsConfig),
+            SetMemoryLimits(memory_limits) => self
                 .vm_resources
-                .set_vm_config(&machine_config_body)
+                .set_memory_limits(memory_limits)
                 .map(|_| VmmData::Empty)
-                .map_err(VmmActionError::MachineConfig),
-            SetMmdsConfiguration(mmds_config) => self
+                .map_err(VmmActionError::MemoryLimitsConfig),
+            SetConsoleConfiguration(console_config) => self
                 .vm_resources
-                .set_mmds_config(mmds_config)
+                .set_console_config(console_config)
                 .map(|_| VmmData::Empty)
-                .map_err(VmmActionError::MmdsConfig),
+                .map_err(VmmActionError::ConsoleConfig),
+            SetRateLimitPolicy(policy) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .set_rate_limit_policy(policy)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::RateLimitPolicy)
+            }
+            SetRtcConfiguration(rtc_config) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .set_rtc_config(rtc_config)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::RtcConfig)
+            }
+            #[cfg(feature = "sev")]
+            SetSevConfiguration(sev_config) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .set_sev_config(sev_config)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::SevConfig)
+            }
+            SetSoundDevice(sound_cfg) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .set_sound_device(sound_cfg)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::SoundConfig)
+            }
+            SetVirtioFeaturePolicy(policy) => {
+                self.boot_path = true;
+                self.vm_resources
+                    .set_virtio_feature_policy(policy)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::VirtioFeaturePolicy)
+            }
             StartMicroVm => super::builder::build_microvm(
                 &self.vm_resources,
                 &mut self.event_manager,
@@ -290,19 +1027,234 @@ impl<'a> PrebootApiController<'a> {
                 self.built_vmm = Some(vmm);
                 VmmData::Empty
             })
-            .map_err(VmmActionError::StartMicrovm),
+            .map_err(|err| {
+                enforce_oom_policy(
+                    self.vm_resources.memory_limits().oom_policy,
+                    err.is_out_of_guest_memory(),
+                    &err,
+                );
+                VmmActionError::StartMicrovm(err)
+            }),
+            ValidateConfiguration(config_json) => VmResources::validate_json(&config_json)
+                .map(|_| VmmData::Empty)
+                .map_err(VmmActionError::ValidateConfiguration),
             // Operations not allowed pre-boot.
             CreateSnapshot(_)
+            | DrainVsockConnections
+            | DumpDeviceState(_)
             | FlushMetrics
             | Pause
-            | UpdateBlockDevicePath(_, _)
-            | UpdateNetworkInterface(_) => Err(VmmActionError::OperationNotSupportedPreBoot),
+            | Resume
+            | SendInputEvent(_)
+            | SetBlockDeviceState(_)
+            | SetNetworkLinkState(_)
+            | SetVcpuState(_)
+            | SnapshotBlockDevice(_)
+            | UpdateBalloon(_)
+            | UpdateBlockDevice(_)
+            | UpdateNetworkInterface(_)
+            | WriteToConsole(_) => Err(VmmActionError::OperationNotSupportedPreBoot),
             #[cfg(target_arch = "x86_64")]
-            SendCtrlAltDel => Err(VmmActionError::OperationNotSupportedPreBoot),
+            LiveUpdate(_) | Migrate(_) | SendCtrlAltDel => {
+                Err(VmmActionError::OperationNotSupportedPreBoot)
+            }
+            #[cfg(feature = "sev")]
+            GetLaunchMeasurement => Err(VmmActionError::OperationNotSupportedPreBoot),
+            GetGuestDmesg(_) => Err(VmmActionError::OperationNotSupportedPreBoot),
+        }
+    }
+
+    // Sets the vsock device, as the only one when `replace_all` is set, and returns its
+    // configuration when its guest CID was leased from a pool, so that the control plane learns
+    // the CID.
+    fn set_vsock_device(
+        &mut self,
+        vsock_cfg: VsockDeviceConfig,
+        replace_all: bool,
+    ) -> result::Result<VmmData, VsockConfigError> {
+        let leased_cfg = vsock_cfg.cid_pool.as_ref().map(|_| vsock_cfg.clone());
+        if replace_all {
+            self.vm_resources.set_vsock_device(vsock_cfg)?;
+        } else {
+            self.vm_resources.insert_vsock_device(vsock_cfg)?;
+        }
+        Ok(match leased_cfg {
+            Some(mut cfg) => {
+                if let Some(guest_cid) = self.vm_resources.vsock.guest_cid(&cfg.vsock_id) {
+                    cfg.guest_cid = guest_cid;
+                }
+                VmmData::VsockDevice(cfg)
+            }
+            None => VmmData::Empty,
+        })
+    }
+
+    /// Loads the microVM from a snapshot, leaving it paused.
+    #[cfg(target_arch = "x86_64")]
+    fn load_snapshot(
+        &mut self,
+        load_params: &LoadSnapshotParams,
+    ) -> result::Result<VmmData, VmmActionError> {
+        // The microVM configuration comes from the snapshot.
+        if self.boot_path {
+            return Err(VmmActionError::LoadSnapshotNotAllowed);
+        }
+
+        persist::load_snapshot(
+            self.vm_resources,
+            self.event_manager,
+            &self.seccomp_filter,
+            load_params,
+        )
+        .map(|vmm| {
+            self.built_vmm = Some(vmm);
+            VmmData::Empty
+        })
+        .map_err(|err| {
+            enforce_oom_policy(
+                self.vm_resources.memory_limits().oom_policy,
+                err.is_out_of_guest_memory(),
+                &err,
+            );
+            VmmActionError::LoadSnapshot(err)
+        })
+    }
+}
+
+// Makes Firecracker exit with `FcExitCode::OutOfMemory`, rather than fail the action, when the
+// host could not provide the guest memory and the OOM policy says so.
+fn enforce_oom_policy(policy: OomPolicy, out_of_memory: bool, err: &dyn Display) {
+    if out_of_memory && policy == OomPolicy::Exit {
+        error!("Cannot allocate the guest memory, exiting: {}", err);
+        // Write the metrics before exiting.
+        if let Err(e) = METRICS.write() {
+            error!("Failed to write metrics while stopping: {}", e);
+        }
+        std::process::exit(i32::from(FcExitCode::OutOfMemory));
+    }
+}
+
+// Records the latency of an action in its histogram once dropped, when the action is handled.
+struct ActionLatencyTimer {
+    histogram: Option<&'static LatencyHistogram>,
+    start: Instant,
+}
+
+impl ActionLatencyTimer {
+    fn start(action: &VmmAction) -> Self {
+        ActionLatencyTimer {
+            histogram: latency_histogram(action),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for ActionLatencyTimer {
+    fn drop(&mut self) {
+        if let Some(histogram) = self.histogram {
+            histogram.record_since(self.start);
         }
     }
 }
 
+// Returns the histogram of the latencies of `action`. The actions wrapping another one have
+// none, the latency of the wrapped action being recorded instead.
+fn latency_histogram(action: &VmmAction) -> Option<&'static LatencyHistogram> {
+    use self::VmmAction::*;
+    let latencies = &METRICS.vmm_action_latency;
+    Some(match action {
+        ConfigureBootSource(_) => &latencies.configure_boot_source,
+        ConfigureLogger(_) => &latencies.configure_logger,
+        ConfigureMetrics(_) => &latencies.configure_metrics,
+        CreateSnapshot(_) => &latencies.create_snapshot,
+        DrainVsockConnections => &latencies.drain_vsock_connections,
+        DumpDeviceState(_) => &latencies.dump_device_state,
+        GetEvents => &latencies.get_events,
+        GetRateLimiterStats => &latencies.get_rate_limiter_stats,
+        GetFullConfiguration => &latencies.get_full_configuration,
+        #[cfg(feature = "sev")]
+        GetLaunchMeasurement => &latencies.get_launch_measurement,
+        GetOperationStatus(_) => &latencies.get_operation_status,
+        GetVmConfiguration => &latencies.get_vm_configuration,
+        GetGuestDmesg(_) => &latencies.get_guest_dmesg,
+        GetHostCapabilities => &latencies.get_host_capabilities,
+        GetVmmResourceUsage => &latencies.get_vmm_resource_usage,
+        ListDevices => &latencies.list_devices,
+        ListVsockConnections => &latencies.list_vsock_connections,
+        FlushMetrics => &latencies.flush_metrics,
+        InsertBlockDevice(_) => &latencies.insert_block_device,
+        InsertNetworkDevice(_) => &latencies.insert_network_device,
+        InsertPciPassthroughDevice(_) => &latencies.insert_pci_passthrough_device,
+        InsertProbe(_) => &latencies.insert_probe,
+        InsertSharedMemoryDevice(_) => &latencies.insert_shared_memory_device,
+        InsertVsockDevice(_) => &latencies.insert_vsock_device,
+        #[cfg(target_arch = "x86_64")]
+        LiveUpdate(_) => &latencies.live_update,
+        LoadSnapshot(_) => &latencies.load_snapshot,
+        #[cfg(target_arch = "x86_64")]
+        Migrate(_) => &latencies.migrate,
+        Pause => &latencies.pause,
+        RemoveBlockDevice(_) => &latencies.remove_block_device,
+        RemoveNetworkInterface(_) => &latencies.remove_network_interface,
+        Resume => &latencies.resume,
+        SetBalloonDevice(_) => &latencies.set_balloon_device,
+        SetBlockDeviceState(_) => &latencies.set_block_device_state,
+        SetCloudInit(_) => &latencies.set_cloud_init,
+        SetConsoleConfiguration(_) => &latencies.set_console_configuration,
+        SetEntropyDevice(_) => &latencies.set_entropy_device,
+        SetGpuDevice(_) => &latencies.set_gpu_device,
+        SetInputDevice(_) => &latencies.set_input_device,
+        SetMemoryLimits(_) => &latencies.set_memory_limits,
+        SetNetworkLinkState(_) => &latencies.set_network_link_state,
+        SetRateLimitPolicy(_) => &latencies.set_rate_limit_policy,
+        SetRtcConfiguration(_) => &latencies.set_rtc_configuration,
+        #[cfg(feature = "sev")]
+        SetSevConfiguration(_) => &latencies.set_sev_configuration,
+        SetSoundDevice(_) => &latencies.set_sound_device,
+        SetVcpuState(_) => &latencies.set_vcpu_state,
+        SetVirtioFeaturePolicy(_) => &latencies.set_virtio_feature_policy,
+        SetVsockDevice(_) => &latencies.set_vsock_device,
+        SetVmConfiguration(_) => &latencies.set_vm_configuration,
+        SnapshotBlockDevice(_) => &latencies.snapshot_block_device,
+        StartMicroVm => &latencies.start_micro_vm,
+        #[cfg(target_arch = "x86_64")]
+        SendCtrlAltDel => &latencies.send_ctrl_alt_del,
+        SendInputEvent(_) => &latencies.send_input_event,
+        UpdateBalloon(_) => &latencies.update_balloon,
+        UpdateBlockDevice(_) => &latencies.update_block_device,
+        UpdateNetworkInterface(_) => &latencies.update_network_interface,
+        ValidateConfiguration(_) => &latencies.validate_configuration,
+        WriteToConsole(_) => &latencies.write_to_console,
+        SetMmdsConfiguration(_) => &latencies.set_mmds_configuration,
+        Idempotent(..) => return None,
+    })
+}
+
+// Whether `action` can be carried out while an operation runs in the background, which it
+// would otherwise race with: the action leaves the microVM, its devices and the guest memory
+// alone.
+fn allowed_during_operation(action: &VmmAction) -> bool {
+    use self::VmmAction::*;
+    match action {
+        DumpDeviceState(_)
+        | FlushMetrics
+        | GetEvents
+        | GetGuestDmesg(_)
+        | GetHostCapabilities
+        | GetOperationStatus(_)
+        | GetRateLimiterStats
+        | GetVmConfiguration
+        | GetVmmResourceUsage
+        | ListDevices
+        | ListVsockConnections
+        | ValidateConfiguration(_) => true,
+        #[cfg(feature = "sev")]
+        GetLaunchMeasurement => true,
+        Idempotent(_, action) => allowed_during_operation(action),
+        _ => false,
+    }
+}
+
 /// Shorthand result type for external VMM commands.
 pub type ActionResult = result::Result<(), VmmActionError>;
 
@@ -310,50 +1262,246 @@ pub type ActionResult = result::Result<(), VmmActionError>;
 pub struct RuntimeApiController {
     vmm: Arc<Mutex<Vmm>>,
     vm_config: VmConfig,
+    idempotency: IdempotencyCache,
+    action_policy: Arc<dyn ActionPolicy>,
+    // The pollables still dispatched while the devices are held.
+    api_pollables: Vec<RawFd>,
+    // The resources the microVM was built from, kept in step with the devices updated after boot.
+    vm_resources: Option<Arc<Mutex<VmResources>>>,
 }
 
 impl RuntimeApiController {
-    /// Handles the incoming runtime `VmmAction` request and provides a response for it.
+    /// Handles the incoming runtime `VmmAction` request and provides a response for it. The
+    /// devices registered to `event_manager` are held while the operations started by the
+    /// request run in the background.
     pub fn handle_request(
         &mut self,
         request: VmmAction,
+        event_manager: &mut EventManager,
     ) -> result::Result<VmmData, VmmActionError> {
         use self::VmmAction::*;
+        let _timer = ActionLatencyTimer::start(&request);
+        let request = action_policy::apply(&*self.action_policy, request)?;
+        if let Some(id) = self.vmm.lock().expect("Poisoned lock").running_operation() {
+            if !allowed_during_operation(&request) {
+                return Err(VmmActionError::OperationInProgress(id));
+            }
+        }
         match request {
             // Supported operations allowed post-boot.
+            #[cfg(target_arch = "x86_64")]
+            CreateSnapshot(snapshot_create_cfg) => {
+                self.create_snapshot(&snapshot_create_cfg, event_manager)
+            }
+            #[cfg(target_arch = "aarch64")]
             CreateSnapshot(_snapshot_create_cfg) => Ok(VmmData::NotFound),
+            DrainVsockConnections => self
+                .drain_vsock_connections()
+                .map(|_| VmmData::Empty)
+                .map_err(VmmActionError::VsockConfig),
+            DumpDeviceState(device_id) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .dump_device_state(&device_id)
+                .map(|dump| VmmData::DeviceState(Box::new(dump)))
+                .ok_or(VmmActionError::DeviceNotFound(device_id)),
             FlushMetrics => self.flush_metrics().map(|_| VmmData::Empty),
+            Idempotent(key, action) => {
+                if let Some(outcome) = self.idempotency.lookup(&key, &action)? {
+                    return Ok(outcome);
+                }
+                let recorded = (*action).clone();
+                let outcome = self.handle_request(*action, event_manager);
+                self.idempotency.record(key, recorded, &outcome);
+                outcome
+            }
+            GetEvents => Ok(VmmData::Events(self.vmm.lock().unwrap().drain_events())),
+            GetRateLimiterStats => Ok(VmmData::RateLimiterStats(
+                self.vmm.lock().expect("Poisoned lock").rate_limiter_stats(),
+            )),
+            #[cfg(feature = "sev")]
+            GetLaunchMeasurement => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .launch_measurement()
+                .map(|measurement| VmmData::LaunchMeasurement(LaunchMeasurement::new(measurement)))
+                .ok_or(VmmActionError::SevConfig(SevConfigError::SevNotEnabled)),
+            GetGuestDmesg(symbols) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .guest_dmesg(symbols.as_ref())
+                .map(VmmData::GuestDmesg)
+                .map_err(VmmActionError::GuestDmesg),
+            GetHostCapabilities => Ok(VmmData::HostCapabilities(host_capabilities::probe())),
+            GetOperationStatus(id) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .operation_status(id)
+                .map(VmmData::Operation)
+                .ok_or(VmmActionError::OperationNotFound(id)),
             GetVmConfiguration => Ok(VmmData::MachineConfiguration(self.vm_config.clone())),
-            Pause | Resume => Ok(VmmData::NotFound),
+            GetVmmResourceUsage => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .resource_usage()
+                .map(VmmData::ResourceUsage)
+                .map_err(VmmActionError::InternalVmm),
+            ListDevices => Ok(VmmData::DeviceList(
+                self.vmm.lock().expect("Poisoned lock").list_devices(),
+            )),
+            ListVsockConnections => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .list_vsock_connections()
+                .map(VmmData::VsockConnections)
+                .map_err(VmmActionError::VsockConfig),
+            #[cfg(target_arch = "x86_64")]
+            LiveUpdate(live_update_params) => self
+                .live_update(&live_update_params.socket_path)
+                .map(|_| VmmData::Empty),
+            #[cfg(target_arch = "x86_64")]
+            Migrate(migration_params) => self
+                .migrate(&migration_params.socket_path)
+                .map(|_| VmmData::Empty),
+            Pause => self.pause().map(|_| VmmData::Empty),
+            Resume => self.resume().map(|_| VmmData::Empty),
             #[cfg(target_arch = "x86_64")]
             SendCtrlAltDel => self.send_ctrl_alt_del().map(|_| VmmData::Empty),
-            UpdateBlockDevicePath(drive_id, path_on_host) => self
-                .update_block_device_path(&drive_id, path_on_host)
+            SendInputEvent(events) => self
+                .send_input_events(&events)
+                .map(|_| VmmData::Empty)
+                .map_err(VmmActionError::InputConfig),
+            SetBlockDeviceState(drive_state) => self
+                .set_block_device_state(drive_state)
                 .map(|_| VmmData::Empty)
                 .map_err(VmmActionError::DriveConfig),
+            SetNetworkLinkState(link_state) => self
+                .set_net_link_state(link_state)
+                .map(|_| VmmData::Empty)
+                .map_err(VmmActionError::NetworkConfig),
+            SetVcpuState(vcpu_state) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .set_vcpu_state(vcpu_state.vcpu_id, vcpu_state.paused)
+                .map(|_| VmmData::Empty)
+                .map_err(VmmActionError::VcpuState),
+            SnapshotBlockDevice(snapshot_params) => self.snapshot_block_device(snapshot_params),
+            UpdateBalloon(balloon_update) => self
+                .update_balloon(balloon_update)
+                .map(|_| VmmData::Empty)
+                .map_err(VmmActionError::BalloonConfig),
+            UpdateBlockDevice(drive_update) => match drive_update.guest_freeze.clone() {
+                Some(guest_freeze) => self.update_block_device_frozen(drive_update, guest_freeze),
+                None => self
+                    .update_block_device(drive_update)
+                    .map(|_| VmmData::Empty)
+                    .map_err(VmmActionError::DriveConfig),
+            },
             UpdateNetworkInterface(netif_update) => self
                 .update_net_rate_limiters(netif_update)
                 .map(|_| VmmData::Empty),
+            ValidateConfiguration(config_json) => VmResources::validate_json(&config_json)
+                .map(|_| VmmData::Empty)
+                .map_err(VmmActionError::ValidateConfiguration),
+            WriteToConsole(bytes) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .write_to_console(&bytes)
+                .map(|_| VmmData::Empty)
+                .map_err(VmmActionError::ConsoleConfig),
 
             // Operations not allowed post-boot.
             ConfigureBootSource(_)
             | ConfigureLogger(_)
             | ConfigureMetrics(_)
+            | GetFullConfiguration
             | InsertBlockDevice(_)
             | InsertNetworkDevice(_)
+            | InsertPciPassthroughDevice(_)
+            | InsertProbe(_)
+            | InsertSharedMemoryDevice(_)
+            | InsertVsockDevice(_)
             | LoadSnapshot(_)
+            | RemoveBlockDevice(_)
+            | RemoveNetworkInterface(_)
+            | SetBalloonDevice(_)
+            | SetCloudInit(_)
+            | SetConsoleConfiguration(_)
+            | SetEntropyDevice(_)
+            | SetGpuDevice(_)
+            | SetInputDevice(_)
+            | SetMemoryLimits(_)
+            | SetRateLimitPolicy(_)
+            | SetRtcConfiguration(_)
+            | SetSoundDevice(_)
+            | SetVirtioFeaturePolicy(_)
             | SetVsockDevice(_)
             | SetMmdsConfiguration(_)
             | SetVmConfiguration(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            #[cfg(feature = "sev")]
+            SetSevConfiguration(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
             StartMicroVm => Err(VmmActionError::StartMicrovm(
                 StartMicrovmError::MicroVMAlreadyRunning,
             )),
         }
     }
 
-    /// Creates a new `RuntimeApiController`.
-    pub fn new(vm_config: VmConfig, vmm: Arc<Mutex<Vmm>>) -> Self {
-        Self { vm_config, vmm }
+    /// Creates a new `RuntimeApiController`, which keeps replaying the actions already applied
+    /// under the keys in `idempotency`, and lets `action_policy` decide which actions are carried
+    /// out, as it did before boot.
+    pub fn new(
+        vm_config: VmConfig,
+        vmm: Arc<Mutex<Vmm>>,
+        idempotency: IdempotencyCache,
+        action_policy: Arc<dyn ActionPolicy>,
+    ) -> Self {
+        Self {
+            vm_config,
+            vmm,
+            idempotency,
+            action_policy,
+            api_pollables: Vec::new(),
+            vm_resources: None,
+        }
+    }
+
+    /// Makes `action_policy` decide which of the following actions are carried out.
+    pub fn set_action_policy(&mut self, action_policy: Arc<dyn ActionPolicy>) {
+        self.action_policy = action_policy;
+    }
+
+    /// Reads `len` bytes of guest memory at the guest physical address `addr`, e.g. the log
+    /// buffer of the guest kernel. The microVM has to be paused.
+    pub fn read_guest_memory(
+        &self,
+        addr: u64,
+        len: usize,
+    ) -> result::Result<Vec<u8>, GuestMemoryAccessError> {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .read_guest_memory(addr, len)
+    }
+
+    /// Writes `data` to the guest memory at the guest physical address `addr`, e.g. to inject a
+    /// configuration blob. The microVM has to be paused.
+    pub fn write_guest_memory(
+        &mut self,
+        addr: u64,
+        data: &[u8],
+    ) -> result::Result<(), GuestMemoryAccessError> {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .write_guest_memory(addr, data)
     }
 
     /// Write the metrics on user demand (flush). We use the word `flush` here to highlight the fact
@@ -369,6 +1517,91 @@ impl RuntimeApiController {
             .map_err(VmmActionError::InternalVmm)
     }
 
+    /// Pauses the microVM by pausing its vCPUs.
+    fn pause(&mut self) -> ActionResult {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .pause_vcpus()
+            .map_err(VmmActionError::InternalVmm)
+    }
+
+    /// Resumes the microVM by resuming its vCPUs.
+    fn resume(&mut self) -> ActionResult {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .resume_vcpus()
+            .map_err(VmmActionError::InternalVmm)
+    }
+
+    /// Creates a snapshot of the paused microVM, or starts writing it in the background and
+    /// returns the status of the operation. The devices registered to `event_manager` are held
+    /// before the snapshot starts being written, so that the microVM stays as it was saved.
+    #[cfg(target_arch = "x86_64")]
+    fn create_snapshot(
+        &mut self,
+        create_params: &CreateSnapshotParams,
+        event_manager: &mut EventManager,
+    ) -> result::Result<VmmData, VmmActionError> {
+        let mut vmm = self.vmm.lock().expect("Poisoned lock");
+        if !create_params.background {
+            return persist::create_snapshot(&mut vmm, &self.vm_config, create_params)
+                .map(|_| VmmData::Empty)
+                .map_err(VmmActionError::CreateSnapshot);
+        }
+
+        let snapshot = persist::prepare_snapshot(&mut vmm, &self.vm_config, create_params)
+            .map_err(VmmActionError::CreateSnapshot)?;
+        let follow_up: Option<FollowUp> = if snapshot.resets_dirty_bitmap() {
+            Some(Box::new(|vmm: &mut Vmm| {
+                persist::reset_dirty_bitmap(vmm)
+                    .map_err(|err| VmmActionError::CreateSnapshot(err).to_string())
+            }))
+        } else {
+            None
+        };
+        let started = vmm
+            .hold_devices(event_manager, &self.api_pollables)
+            .map_err(VmmActionError::InternalVmm)
+            .and_then(|_| {
+                vmm.start_operation(
+                    OperationKind::CreateSnapshot,
+                    Box::new(move || {
+                        snapshot
+                            .write()
+                            .map_err(|err| VmmActionError::CreateSnapshot(err).to_string())
+                    }),
+                    follow_up,
+                )
+                .map_err(operation_error)
+            });
+        if started.is_err() {
+            // The snapshot won't be written, so the devices can run again.
+            if event_manager.is_held() {
+                if let Err(err) = event_manager.release() {
+                    error!("Cannot release the devices: {:?}", err);
+                }
+            }
+            if let Err(err) = vmm.resume_device_threads() {
+                error!("{}", err);
+            }
+        }
+        started.map(VmmData::Operation)
+    }
+
+    /// Keeps dispatching the events of `pollables`, e.g. the API ones, while the devices are
+    /// held for an operation running in the background.
+    pub fn set_api_pollables(&mut self, pollables: Vec<RawFd>) {
+        self.api_pollables = pollables;
+    }
+
+    /// Records the rate limiters and the impairments updated after boot in `vm_resources`, the
+    /// resources the microVM was built from, e.g. for its checkpoints to reflect them.
+    pub fn set_vm_resources(&mut self, vm_resources: Arc<Mutex<VmResources>>) {
+        self.vm_resources = Some(vm_resources);
+    }
+
     /// Injects CTRL+ALT+DEL keystroke combo to the inner Vmm (if present).
     #[cfg(target_arch = "x86_64")]
     fn send_ctrl_alt_del(&mut self) -> ActionResult {
@@ -379,79 +1612,474 @@ impl RuntimeApiController {
             .map_err(VmmActionError::InternalVmm)
     }
 
-    /// Updates the path of the host file backing the emulated block device with id `drive_id`.
-    /// We update the disk image on the device and its virtio configuration.
-    fn update_block_device_path<P: AsRef<Path>>(
+    /// Hands the `events` to the guest through the input device.
+    fn send_input_events(&mut self, events: &[InputEvent]) -> result::Result<(), InputConfigError> {
+        if let Some(busdev) = self
+            .vmm
+            .lock()
+            .unwrap()
+            .get_bus_device(DeviceType::Virtio(TYPE_INPUT), INPUT_DEV_ID)
+        {
+            let virtio_device = busdev
+                .lock()
+                .expect("Poisoned device lock")
+                .as_any()
+                .downcast_ref::<MmioTransport>()
+                // Only MmioTransport implements BusDevice at this point.
+                .expect("Unexpected BusDevice type")
+                .device();
+
+            let events: Vec<VirtioInputEvent> = events.iter().map(|e| (*e).into()).collect();
+            let mut locked_device = virtio_device.lock().expect("Poisoned device lock");
+            locked_device
+                .as_mut_any()
+                .downcast_mut::<Input>()
+                .expect("Unexpected VirtioDevice type")
+                .send_events(&events)
+                .map_err(InputConfigError::SendEvents)
+        } else {
+            Err(InputConfigError::DeviceNotFound)
+        }
+    }
+
+    /// Drops the connections of the vsock device and resets its transport.
+    fn drain_vsock_connections(&mut self) -> result::Result<(), VsockConfigError> {
+        let drained = self
+            .vmm
+            .lock()
+            .expect("Poisoned lock")
+            .drain_vsock_connections()?;
+        info!("Drained {} vsock connections.", drained);
+        Ok(())
+    }
+
+    /// Hands over the microVM to the Firecracker process listening on `socket_path`, then
+    /// terminates this process.
+    #[cfg(target_arch = "x86_64")]
+    fn live_update(&mut self, socket_path: &Path) -> ActionResult {
+        let mut vmm = self.vmm.lock().expect("Poisoned lock");
+        live_update::send_microvm(&mut vmm, &self.vm_config, socket_path)
+            .map_err(VmmActionError::LiveUpdate)?;
+        // The microVM belongs to the new process now.
+        vmm.stop(super::FcExitCode::Ok);
+        Ok(())
+    }
+
+    /// Migrates the microVM to the Firecracker process listening on `socket_path`, then
+    /// terminates this process.
+    #[cfg(target_arch = "x86_64")]
+    fn migrate(&mut self, socket_path: &Path) -> ActionResult {
+        let mut vmm = self.vmm.lock().expect("Poisoned lock");
+        migration::send_microvm(&mut vmm, &self.vm_config, socket_path)
+            .map_err(VmmActionError::Migration)?;
+        // The microVM belongs to the destination process now.
+        vmm.stop(super::FcExitCode::Ok);
+        Ok(())
+    }
+
+    /// Updates the target size of the balloon device, asking the guest to inflate or deflate it.
+    fn update_balloon(
         &mut self,
-        drive_id: &str,
-        path_on_host: P,
-    ) -> result::Result<(), DriveError> {
+        new_cfg: BalloonUpdateConfig,
+    ) -> result::Result<(), BalloonConfigError> {
+        // The balloon cannot have a target size greater than the size of the guest memory.
+        if new_cfg.amount_mib as usize > self.vm_config.mem_size_mib.unwrap_or_default() {
+            return Err(BalloonConfigError::TooManyPagesRequested);
+        }
+
         if let Some(busdev) = self
             .vmm
             .lock()
             .unwrap()
-            .get_bus_device(DeviceType::Virtio(TYPE_BLOCK), drive_id)
+            .get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
         {
-            let new_size;
-            // Call the update_disk_image() handler on Block. Release the lock when done.
+            let virtio_device = busdev
+                .lock()
+                .expect("Poisoned device lock")
+                .as_any()
+                .downcast_ref::<MmioTransport>()
+                // Only MmioTransport implements BusDevice at this point.
+                .expect("Unexpected BusDevice type")
+                .device();
+
+            let mut locked_device = virtio_device.lock().expect("Poisoned device lock");
+            locked_device
+                .as_mut_any()
+                .downcast_mut::<Balloon>()
+                .expect("Unexpected VirtioDevice type")
+                .update_size(new_cfg.amount_mib)
+                .map_err(BalloonConfigError::UpdateFailed)
+        } else {
+            Err(BalloonConfigError::DeviceNotFound)
+        }
+    }
+
+    /// Updates the emulated block device as described in `new_cfg`.
+    fn update_block_device(
+        &mut self,
+        new_cfg: BlockDeviceUpdateConfig,
+    ) -> result::Result<(), DriveError> {
+        if let Some(path_on_host) = new_cfg.path_on_host {
+            self.update_block_device_path(&new_cfg.drive_id, path_on_host)?;
+        }
+        if let Some(rate_limiter) = new_cfg.rate_limiter {
+            self.update_block_rate_limiter(
+                &new_cfg.drive_id,
+                rate_limiter
+                    .bandwidth
+                    .map(vmm_config::TokenBucketConfig::into),
+                rate_limiter.ops.map(vmm_config::TokenBucketConfig::into),
+            )?;
+            self.record_block_rate_limiter(&new_cfg.drive_id, &rate_limiter);
+        }
+        Ok(())
+    }
+
+    // Records the rate limiter updated on the block device `drive_id` in the resources the
+    // microVM was built from, if they are kept.
+    fn record_block_rate_limiter(
+        &self,
+        drive_id: &str,
+        rate_limiter: &vmm_config::RateLimiterConfig,
+    ) {
+        if let Some(ref vm_resources) = self.vm_resources {
+            vm_resources
+                .lock()
+                .expect("Poisoned lock")
+                .block
+                .update_rate_limiter(drive_id, rate_limiter);
+        }
+    }
+
+    /// Starts replacing the backing file of a drive in the background, once the guest agent
+    /// described by `guest_freeze` froze the filesystems of the guest, and returns the status of
+    /// the operation. The agent is told to thaw the filesystems once the backing file is
+    /// replaced, or failed to be. The rate limiters are updated right away.
+    fn update_block_device_frozen(
+        &mut self,
+        new_cfg: BlockDeviceUpdateConfig,
+        guest_freeze: GuestFreezeConfig,
+    ) -> result::Result<VmmData, VmmActionError> {
+        let path_on_host = new_cfg
+            .path_on_host
+            .clone()
+            .ok_or(VmmActionError::DriveConfig(
+                DriveError::GuestFreezeWithoutPath,
+            ))?;
+        if guest_freeze.timeout_ms == 0 {
+            return Err(VmmActionError::DriveConfig(
+                DriveError::InvalidGuestFreezeTimeout,
+            ));
+        }
+        let uds_path = {
+            let vmm = self.vmm.lock().expect("Poisoned lock");
+            if vmm
+                .get_bus_device(DeviceType::Virtio(TYPE_BLOCK), &new_cfg.drive_id)
+                .is_none()
             {
-                let virtio_dev = busdev
-                    .lock()
-                    .expect("Poisoned device lock")
-                    .as_any()
-                    // Only MmioTransport implements BusDevice at this point.
-                    .downcast_ref::<MmioTransport>()
-                    .expect("Unexpected BusDevice type")
-                    // Here we get a *new* clone of Arc<Mutex<dyn VirtioDevice>>.
-                    .device();
-
-                // We need this bound to a variable so that it lives as long as the 'block' ref.
-                let mut locked_device = virtio_dev.lock().expect("Poisoned device lock");
-                // Get a '&mut Block' ref from the above MutexGuard<dyn VirtioDevice>.
-                let block = locked_device
-                    .as_mut_any()
-                    // We know this is a block device from the HashMap.
-                    .downcast_mut::<Block>()
-                    .expect("Unexpected VirtioDevice type");
-
-                // Try to open the file specified by path_on_host using the permissions of the block_device.
-                let mut disk_image = OpenOptions::new()
-                    .read(true)
-                    .write(!block.is_read_only())
-                    .open(path_on_host)
-                    .map_err(DriveError::OpenBlockDevice)?;
+                return Err(VmmActionError::DriveConfig(
+                    DriveError::InvalidBlockDeviceID,
+                ));
+            }
+            vmm.list_devices()
+                .into_iter()
+                .find(|device| {
+                    device.device_type == DeviceKind::Vsock
+                        && guest_freeze
+                            .vsock_id
+                            .as_ref()
+                            .map_or(true, |vsock_id| *vsock_id == device.id)
+                })
+                .and_then(|device| match device.backend {
+                    Some(DeviceBackend::Uds { uds_path }) => Some(uds_path),
+                    _ => None,
+                })
+                .ok_or_else(|| {
+                    VmmActionError::DriveConfig(DriveError::GuestFreezeVsock(
+                        guest_freeze.vsock_id.clone(),
+                    ))
+                })?
+        };
+        if let Some(rate_limiter) = new_cfg.rate_limiter {
+            self.update_block_rate_limiter(
+                &new_cfg.drive_id,
+                rate_limiter
+                    .bandwidth
+                    .map(vmm_config::TokenBucketConfig::into),
+                rate_limiter.ops.map(vmm_config::TokenBucketConfig::into),
+            )
+            .map_err(VmmActionError::DriveConfig)?;
+            self.record_block_rate_limiter(&new_cfg.drive_id, &rate_limiter);
+        }
 
-                // Use seek() instead of stat() (std::fs::Metadata) to support block devices.
-                new_size = disk_image
-                    .seek(SeekFrom::End(0))
-                    .map_err(|_| DriveError::BlockDeviceUpdateFailed)?;
-                // Return cursor to the start of the file.
-                disk_image
-                    .seek(SeekFrom::Start(0))
-                    .map_err(|_| DriveError::BlockDeviceUpdateFailed)?;
-
-                // Now we have a Block, so call its update handler.
-                block
-                    .update_disk_image(disk_image)
-                    .map_err(|_| DriveError::BlockDeviceUpdateFailed)?;
-            }
-
-            // Update the virtio config space and kick the driver to pick up the changes.
-            let new_cfg = devices::virtio::block::device::build_config_space(new_size);
-            let mut locked_dev = busdev.lock().expect("Poisoned device lock");
-            locked_dev.write(MMIO_CFG_SPACE_OFF, &new_cfg[..]);
-            locked_dev
-                .interrupt(devices::virtio::VIRTIO_MMIO_INT_CONFIG)
-                .map_err(|_| DriveError::BlockDeviceUpdateFailed)?;
+        // The vsock device is emulated on the VMM thread, so the agent is waited for on the
+        // worker thread, while the devices keep running.
+        let frozen = Arc::new(Mutex::new(None));
+        let job_frozen = frozen.clone();
+        let port = guest_freeze.vsock_port;
+        let timeout = Duration::from_millis(guest_freeze.timeout_ms);
+        let job: Job = Box::new(move || {
+            let freeze = GuestFreeze::freeze(&uds_path, port, timeout)
+                .map_err(|err| DriveError::GuestFreeze(err).to_string())?;
+            *job_frozen.lock().expect("Poisoned lock") = Some(freeze);
+            Ok(())
+        });
+        let drive_id = new_cfg.drive_id;
+        let follow_up: FollowUp = Box::new(move |vmm: &mut Vmm| {
+            let updated =
+                update_block_device_path(vmm, &drive_id, path_on_host).map_err(|e| e.to_string());
+            let thawed = match frozen.lock().expect("Poisoned lock").take() {
+                Some(freeze) => freeze
+                    .thaw()
+                    .map_err(|e| format!("Cannot thaw the filesystems of the guest: {}", e)),
+                None => Ok(()),
+            };
+            updated.and(thawed)
+        });
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .start_operation(OperationKind::UpdateBlockDevice, job, Some(follow_up))
+            .map(VmmData::Operation)
+            .map_err(operation_error)
+    }
+
+    /// Pauses or resumes the emulated block device, as described in `drive_state`.
+    fn set_block_device_state(
+        &mut self,
+        drive_state: BlockDeviceStateConfig,
+    ) -> result::Result<(), DriveError> {
+        let busdev = self
+            .vmm
+            .lock()
+            .expect("Poisoned lock")
+            .get_bus_device(DeviceType::Virtio(TYPE_BLOCK), &drive_state.drive_id)
+            .ok_or(DriveError::InvalidBlockDeviceID)?;
+        let virtio_device = busdev
+            .lock()
+            .expect("Poisoned device lock")
+            .as_any()
+            .downcast_ref::<MmioTransport>()
+            // Only MmioTransport implements BusDevice at this point.
+            .expect("Unexpected BusDevice type")
+            .device();
+        virtio_device
+            .lock()
+            .expect("Poisoned device lock")
+            .as_mut_any()
+            // We know this is a block device from the HashMap.
+            .downcast_mut::<Block>()
+            .expect("Unexpected VirtioDevice type")
+            .set_paused(drive_state.paused)
+            .map_err(|_| DriveError::BlockDeviceUpdateFailed)?;
+        Ok(())
+    }
 
+    /// Copies the host file backing the emulated block device to `snapshot_params.dest_path`.
+    /// The device is quiesced, so that the copy holds the requests it completed, then paused
+    /// while the file is copied, without holding back the other devices meanwhile. A reflink is
+    /// done right away, while a full copy runs on the worker thread, and returns the status of
+    /// the operation.
+    fn snapshot_block_device(
+        &mut self,
+        snapshot_params: BlockDeviceSnapshotParams,
+    ) -> result::Result<VmmData, VmmActionError> {
+        let busdev = self
+            .vmm
+            .lock()
+            .expect("Poisoned lock")
+            .get_bus_device(DeviceType::Virtio(TYPE_BLOCK), &snapshot_params.drive_id)
+            .ok_or(VmmActionError::DriveConfig(
+                DriveError::InvalidBlockDeviceID,
+            ))?;
+        let virtio_device = busdev
+            .lock()
+            .expect("Poisoned device lock")
+            .as_any()
+            .downcast_ref::<MmioTransport>()
+            // Only MmioTransport implements BusDevice at this point.
+            .expect("Unexpected BusDevice type")
+            .device();
+
+        let (disk_image, was_paused) = {
+            let mut locked_device = virtio_device.lock().expect("Poisoned device lock");
+            let was_paused = locked_device.is_paused();
+            let block = locked_device
+                .as_mut_any()
+                // We know this is a block device from the HashMap.
+                .downcast_mut::<Block>()
+                .expect("Unexpected VirtioDevice type");
+            block
+                .quiesce()
+                .map_err(|e| VmmActionError::DriveConfig(DriveError::FlushBlockDevice(e)))?;
+            block
+                .set_paused(true)
+                .map_err(|_| VmmActionError::DriveConfig(DriveError::BlockDeviceUpdateFailed))?;
+            (block.disk_image().clone(), was_paused)
+        };
+        // Leave the devices paused through the API paused.
+        let resume = move |virtio_device: &Arc<Mutex<dyn VirtioDevice>>| {
+            if was_paused {
+                return Ok(());
+            }
+            virtio_device
+                .lock()
+                .expect("Poisoned device lock")
+                .as_mut_any()
+                .downcast_mut::<Block>()
+                .expect("Unexpected VirtioDevice type")
+                .set_paused(false)
+                .map_err(|_| DriveError::BlockDeviceUpdateFailed)
+        };
+
+        let dest_path = snapshot_params.dest_path;
+        let reflinked = reflink_disk_image(&disk_image, Path::new(&dest_path));
+        if reflinked.as_ref().map_or(true, |reflinked| *reflinked) {
+            let resumed = resume(&virtio_device);
+            reflinked
+                .map_err(DriveError::SnapshotBlockDevice)
+                .and(resumed)
+                .map_err(VmmActionError::DriveConfig)?;
+            info!(
+                "Reflinked the backing file of drive {} to {}.",
+                snapshot_params.drive_id, dest_path
+            );
+            return Ok(VmmData::Empty);
+        }
+
+        // The data is read on the worker thread, since the VMM thread emulates the devices. The
+        // follow-up resumes the drive whether the copy succeeded or not, so the job keeps the
+        // outcome of the copy for it.
+        let copied = Arc::new(Mutex::new(None));
+        let job_copied = copied.clone();
+        let job_dest_path = dest_path.clone();
+        let job: Job = Box::new(move || {
+            let result = copy_disk_image(&disk_image, Path::new(&job_dest_path));
+            *job_copied.lock().expect("Poisoned lock") = Some(result);
             Ok(())
-        } else {
-            Err(DriveError::InvalidBlockDeviceID)
+        });
+        let drive_id = snapshot_params.drive_id;
+        let follow_up_device = virtio_device.clone();
+        let follow_up: FollowUp = Box::new(move |_: &mut Vmm| {
+            let resumed = resume(&follow_up_device);
+            copied
+                .lock()
+                .expect("Poisoned lock")
+                .take()
+                .expect("The backing file wasn't copied")
+                .map_err(DriveError::SnapshotBlockDevice)
+                .and(resumed)
+                .map_err(|e| e.to_string())?;
+            info!(
+                "Copied the backing file of drive {} to {}.",
+                drive_id, dest_path
+            );
+            Ok(())
+        });
+        let started = self.vmm.lock().expect("Poisoned lock").start_operation(
+            OperationKind::SnapshotBlockDevice,
+            job,
+            Some(follow_up),
+        );
+        if started.is_err() {
+            // The file won't be copied, so the drive can run again.
+            if let Err(err) = resume(&virtio_device) {
+                error!("Cannot resume the drive: {}", err);
+            }
         }
+        started.map(VmmData::Operation).map_err(operation_error)
+    }
+
+    /// Updates the rate limiter of the emulated block device with id `drive_id`. The buckets
+    /// which are not provided are left unchanged.
+    fn update_block_rate_limiter(
+        &mut self,
+        drive_id: &str,
+        bytes: Option<TokenBucket>,
+        ops: Option<TokenBucket>,
+    ) -> result::Result<(), DriveError> {
+        let busdev = self
+            .vmm
+            .lock()
+            .unwrap()
+            .get_bus_device(DeviceType::Virtio(TYPE_BLOCK), drive_id)
+            .ok_or(DriveError::InvalidBlockDeviceID)?;
+        let virtio_device = busdev
+            .lock()
+            .expect("Poisoned device lock")
+            .as_any()
+            .downcast_ref::<MmioTransport>()
+            // Only MmioTransport implements BusDevice at this point.
+            .expect("Unexpected BusDevice type")
+            .device();
+        virtio_device
+            .lock()
+            .expect("Poisoned device lock")
+            .as_mut_any()
+            // We know this is a block device from the HashMap.
+            .downcast_mut::<Block>()
+            .expect("Unexpected VirtioDevice type")
+            .update_rate_limiter(bytes, ops);
+        Ok(())
+    }
+
+    /// Updates the path of the host file backing the emulated block device with id `drive_id`.
+    fn update_block_device_path<P: AsRef<Path>>(
+        &mut self,
+        drive_id: &str,
+        path_on_host: P,
+    ) -> result::Result<(), DriveError> {
+        update_block_device_path(
+            &self.vmm.lock().expect("Poisoned lock"),
+            drive_id,
+            path_on_host,
+        )
+    }
+
+    /// Brings the link of a net device up or down, as described in `link_state`.
+    fn set_net_link_state(
+        &mut self,
+        link_state: NetworkLinkStateConfig,
+    ) -> result::Result<(), NetworkInterfaceError> {
+        let busdev = self
+            .vmm
+            .lock()
+            .expect("Poisoned lock")
+            .get_bus_device(DeviceType::Virtio(TYPE_NET), &link_state.iface_id)
+            .ok_or(NetworkInterfaceError::DeviceIdNotFound)?;
+        let virtio_device = busdev
+            .lock()
+            .expect("Poisoned device lock")
+            .as_any()
+            .downcast_ref::<MmioTransport>()
+            // Only MmioTransport implements BusDevice at this point.
+            .expect("Unexpected BusDevice type")
+            .device();
+        virtio_device
+            .lock()
+            .expect("Poisoned device lock")
+            .as_mut_any()
+            .downcast_mut::<Net>()
+            .expect("Unexpected VirtioDevice type")
+            .set_link_up(link_state.up)
+            .map_err(NetworkInterfaceError::SetLinkState)?;
+        Ok(())
     }
 
     /// Updates configuration for an emulated net device as described in `new_cfg`.
     fn update_net_rate_limiters(&mut self, new_cfg: NetworkInterfaceUpdateConfig) -> ActionResult {
+        let rx_impairment: Option<ImpairmentParams> = new_cfg
+            .rx_impairment
+            .clone()
+            .map(TryInto::try_into)
+            .transpose()
+            .map_err(VmmActionError::NetworkConfig)?;
+        let tx_impairment: Option<ImpairmentParams> = new_cfg
+            .tx_impairment
+            .clone()
+            .map(TryInto::try_into)
+            .transpose()
+            .map_err(VmmActionError::NetworkConfig)?;
         if let Some(busdev) = self
             .vmm
             .lock()
@@ -476,24 +2104,269 @@ impl RuntimeApiController {
                 }};
             }
 
-            virtio_device
-                .lock()
-                .expect("Poisoned device lock")
-                .as_mut_any()
-                .downcast_mut::<Net>()
-                .unwrap()
-                .patch_rate_limiters(
-                    get_handler_arg!(rx_rate_limiter, bandwidth),
-                    get_handler_arg!(rx_rate_limiter, ops),
-                    get_handler_arg!(tx_rate_limiter, bandwidth),
-                    get_handler_arg!(tx_rate_limiter, ops),
-                );
+            let mut device = virtio_device.lock().expect("Poisoned device lock");
+            let net = device.as_mut_any().downcast_mut::<Net>().unwrap();
+            net.patch_rate_limiters(
+                get_handler_arg!(rx_rate_limiter, bandwidth),
+                get_handler_arg!(rx_rate_limiter, ops),
+                get_handler_arg!(tx_rate_limiter, bandwidth),
+                get_handler_arg!(tx_rate_limiter, ops),
+            );
+            net.set_impairments(rx_impairment, tx_impairment);
         } else {
             return Err(VmmActionError::NetworkConfig(
                 NetworkInterfaceError::DeviceIdNotFound,
             ));
         }
 
+        if let Some(ref vm_resources) = self.vm_resources {
+            vm_resources
+                .lock()
+                .expect("Poisoned lock")
+                .net_builder
+                .update_config(&new_cfg);
+        }
+        Ok(())
+    }
+}
+
+// Maps the errors starting an operation in the background.
+fn operation_error(err: OperationError) -> VmmActionError {
+    match err {
+        OperationError::InProgress(id) => VmmActionError::OperationInProgress(id),
+        err => VmmActionError::InternalVmm(VmmError::Operations(err)),
+    }
+}
+
+/// Updates the path of the host file backing the emulated block device with id `drive_id`.
+/// We quiesce the device, so that the requests it completed all reach the old disk image,
+/// then update the disk image on the device and its virtio configuration.
+fn update_block_device_path<P: AsRef<Path>>(
+    vmm: &Vmm,
+    drive_id: &str,
+    path_on_host: P,
+) -> result::Result<(), DriveError> {
+    if let Some(busdev) = vmm.get_bus_device(DeviceType::Virtio(TYPE_BLOCK), drive_id) {
+        let new_size;
+        // Call the update_disk_image() handler on Block. Release the lock when done.
+        {
+            let virtio_dev = busdev
+                .lock()
+                .expect("Poisoned device lock")
+                .as_any()
+                // Only MmioTransport implements BusDevice at this point.
+                .downcast_ref::<MmioTransport>()
+                .expect("Unexpected BusDevice type")
+                // Here we get a *new* clone of Arc<Mutex<dyn VirtioDevice>>.
+                .device();
+
+            // We need this bound to a variable so that it lives as long as the 'block' ref.
+            let mut locked_device = virtio_dev.lock().expect("Poisoned device lock");
+            // Get a '&mut Block' ref from the above MutexGuard<dyn VirtioDevice>.
+            let block = locked_device
+                .as_mut_any()
+                // We know this is a block device from the HashMap.
+                .downcast_mut::<Block>()
+                .expect("Unexpected VirtioDevice type");
+            // The hash tree only matches the current disk image.
+            if block.is_verified() {
+                return Err(DriveError::UpdateVerifiedDrive);
+            }
+
+            // Try to open the file specified by path_on_host using the permissions of the block_device.
+            let disk_image_path = path_on_host.as_ref().to_string_lossy().into_owned();
+            let disk_image =
+                open_disk_image(&disk_image_path, block.is_read_only(), block.no_atime())
+                    .map_err(DriveError::OpenBlockDevice)?;
+
+            // Use seek() instead of stat() (std::fs::Metadata) to support block devices.
+            new_size = (&*disk_image)
+                .seek(SeekFrom::End(0))
+                .map_err(|_| DriveError::BlockDeviceUpdateFailed)?;
+            // Return cursor to the start of the file.
+            (&*disk_image)
+                .seek(SeekFrom::Start(0))
+                .map_err(|_| DriveError::BlockDeviceUpdateFailed)?;
+
+            // Nothing reaches the old disk image past this point, since the device lock is
+            // held until the new one is in place.
+            block.quiesce().map_err(DriveError::FlushBlockDevice)?;
+
+            // Now we have a Block, so call its update handler.
+            block
+                .update_disk_image(disk_image, disk_image_path)
+                .map_err(|_| DriveError::BlockDeviceUpdateFailed)?;
+        }
+
+        // Update the virtio config space and kick the driver to pick up the changes.
+        let new_cfg = devices::virtio::block::device::build_config_space(new_size);
+        let mut locked_dev = busdev.lock().expect("Poisoned device lock");
+        locked_dev.write(MMIO_CFG_SPACE_OFF, &new_cfg[..]);
+        locked_dev
+            .interrupt(devices::virtio::VIRTIO_MMIO_INT_CONFIG)
+            .map_err(|_| DriveError::BlockDeviceUpdateFailed)?;
+
         Ok(())
+    } else {
+        Err(DriveError::InvalidBlockDeviceID)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use device_list::{DeviceKind, RateLimiterPath, TokenBucketDescription};
+    use operations::{OperationKind, OperationState};
+    use probes::ProbeState;
+    use vmm_config::probe::ProbeKind;
+
+    #[test]
+    fn test_vmm_data_serialization() {
+        let data = VmmData::Events(vec![VmmEvent::FreePagesReported { amount_kib: 8 }]);
+        let json = serde_json::to_string(&data).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"events","data":[{"type":"free_pages_reported","amount_kib":8}]}"#
+        );
+        assert_eq!(serde_json::from_str::<VmmData>(&json).unwrap(), data);
+        assert_eq!(
+            data.payload_json().unwrap(),
+            r#"[{"type":"free_pages_reported","amount_kib":8}]"#
+        );
+
+        let json = serde_json::to_string(&VmmData::Empty).unwrap();
+        assert_eq!(json, r#"{"type":"empty"}"#);
+        assert_eq!(
+            serde_json::from_str::<VmmData>(&json).unwrap(),
+            VmmData::Empty
+        );
+        assert!(VmmData::Empty.payload_json().is_none());
+        assert!(VmmData::NotFound.payload_json().is_none());
+
+        let data = VmmData::MachineConfiguration(VmConfig::default());
+        let json = serde_json::to_string(&data).unwrap();
+        assert_eq!(serde_json::from_str::<VmmData>(&json).unwrap(), data);
+        assert_eq!(
+            data.payload_json().unwrap(),
+            VmConfig::default().to_string()
+        );
+
+        let data = VmmData::RateLimiterStats(vec![RateLimiterDescription {
+            device_type: DeviceKind::Block,
+            id: "rootfs".to_string(),
+            path: RateLimiterPath::Io,
+            bandwidth: None,
+            ops: Some(TokenBucketDescription {
+                size: 100,
+                budget: 40,
+                one_time_burst: 0,
+                refill_time: 1000,
+            }),
+            blocked: false,
+            blocked_count: 2,
+            blocked_time_us: 1500,
+        }]);
+        let json = serde_json::to_string(&data).unwrap();
+        assert_eq!(serde_json::from_str::<VmmData>(&json).unwrap(), data);
+
+        let data = VmmData::HostCapabilities(HostCapabilities::default());
+        let json = serde_json::to_string(&data).unwrap();
+        assert_eq!(serde_json::from_str::<VmmData>(&json).unwrap(), data);
+
+        let data = VmmData::Events(vec![VmmEvent::ProbeStateChanged {
+            probe_id: "ready".to_string(),
+            kind: ProbeKind::Readiness,
+            state: ProbeState::Passing,
+        }]);
+        let json = serde_json::to_string(&data).unwrap();
+        assert_eq!(serde_json::from_str::<VmmData>(&json).unwrap(), data);
+
+        let data = VmmData::Operation(OperationStatus {
+            operation_id: 2,
+            kind: OperationKind::CreateSnapshot,
+            state: OperationState::Running,
+            error: None,
+        });
+        let json = serde_json::to_string(&data).unwrap();
+        assert_eq!(serde_json::from_str::<VmmData>(&json).unwrap(), data);
+        assert_eq!(
+            data.payload_json().unwrap(),
+            r#"{"operation_id":2,"kind":"create_snapshot","state":"running"}"#
+        );
+
+        assert!(serde_json::from_str::<VmmData>(r#"{"type":"unknown"}"#).is_err());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_error_category() {
+        let error = VmmActionError::StartMicrovm(StartMicrovmError::MissingKernelConfig);
+        assert_eq!(error.error_code(), ErrorCode::StartMicrovm);
+        assert_eq!(error.error_category(), ErrorCategory::InvalidArgument);
+        let error = VmmActionError::StartMicrovm(StartMicrovmError::OpenBlockDevice(
+            std::io::Error::from_raw_os_error(libc::ENOENT),
+        ));
+        assert_eq!(error.error_category(), ErrorCategory::External);
+        assert!(!error.retriable());
+
+        let error = VmmActionError::LiveUpdate(LiveUpdateError::Connect(
+            std::io::Error::from_raw_os_error(libc::ECONNREFUSED),
+        ));
+        assert_eq!(error.error_category(), ErrorCategory::External);
+        assert!(error.retriable());
+        let error = VmmActionError::LiveUpdate(LiveUpdateError::SaveState(
+            persist::MicrovmStateError::NotAllowed("PCI".to_string()),
+        ));
+        assert_eq!(error.error_category(), ErrorCategory::InvalidState);
+        assert!(!error.retriable());
+        let error = VmmActionError::Migration(MigrationError::DirtyPageTrackingDisabled);
+        assert_eq!(error.error_category(), ErrorCategory::InvalidState);
+        assert!(!error.retriable());
+        let error = VmmActionError::Migration(MigrationError::Rejected);
+        assert!(error.retriable());
+
+        let error = VmmActionError::ActionQueueFull(16);
+        assert_eq!(error.error_category(), ErrorCategory::InvalidState);
+        assert!(error.retriable());
+    }
+
+    #[test]
+    fn test_allowed_during_operation() {
+        assert!(allowed_during_operation(&VmmAction::GetOperationStatus(1)));
+        assert!(allowed_during_operation(&VmmAction::ListDevices));
+        assert!(allowed_during_operation(&VmmAction::Idempotent(
+            "key".to_string(),
+            Box::new(VmmAction::FlushMetrics)
+        )));
+        assert!(!allowed_during_operation(&VmmAction::Resume));
+        assert!(!allowed_during_operation(&VmmAction::Idempotent(
+            "key".to_string(),
+            Box::new(VmmAction::Pause)
+        )));
+    }
+
+    #[test]
+    fn test_versioned_vmm_data() {
+        let versioned = VersionedVmmData::from(VmmData::GuestDmesg(vec![LogRecord {
+            timestamp_ns: 1000,
+            text: "Linux version".to_string(),
+        }]));
+        let json = serde_json::to_string(&versioned).unwrap();
+        assert!(json.starts_with(&format!(
+            r#"{{"schema_version":{},"response":{{"type":"guest_dmesg","data":"#,
+            VMM_DATA_SCHEMA_VERSION
+        )));
+        let versioned: VersionedVmmData = serde_json::from_str(&json).unwrap();
+        match versioned.into_data() {
+            Ok(VmmData::GuestDmesg(records)) => assert_eq!(records[0].timestamp_ns, 1000),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let versioned = VersionedVmmData {
+            schema_version: VMM_DATA_SCHEMA_VERSION + 1,
+            response: VmmData::Empty,
+        };
+        assert_eq!(versioned.into_data(), Err(VMM_DATA_SCHEMA_VERSION + 1));
     }
 }
@@ -0,0 +1,257 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Launches AMD SEV guests, whose memory (and vCPU state, with SEV-ES) is encrypted by the AMD
+//! Secure Processor of the host with a key the host can't access.
+//!
+//! KVM drives the Secure Processor on behalf of the VM, through the `/dev/sev` device:
+//! - the SEV context of the VM is initialized before the vCPUs are created;
+//! - the guest memory is registered as encrypted, which pins it;
+//! - once the guest memory and the vCPUs are set up for boot, their initial contents are
+//!   encrypted in place and measured, and the launch is finished. From then on, the host can't
+//!   write plaintext to the guest memory anymore.
+//!
+//! The page tables Firecracker sets up for booting have the encryption bit (C-bit) set, so that
+//! the guest starts with its memory mapped as encrypted.
+
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::result;
+
+use kvm_ioctls::VmFd;
+use utils::ioctl::ioctl_with_mut_ref;
+use vm_memory::{GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+use vmm_config::sev::SevConfig;
+
+/// Path to the host SEV device.
+pub const SEV_DEVICE_PATH: &str = "/dev/sev";
+
+/// The size of a launch measurement: a 32-byte HMAC followed by a 16-byte nonce.
+pub const LAUNCH_MEASUREMENT_SIZE: usize = 48;
+
+// The CPUID leaf describing the memory encryption support of the host.
+const CPUID_MEMORY_ENCRYPTION: u32 = 0x8000_001f;
+// The bits of EBX of `CPUID_MEMORY_ENCRYPTION` giving the position of the C-bit.
+const CBIT_POSITION_MASK: u32 = 0x3f;
+
+// The largest page aligned length of a LAUNCH_UPDATE_DATA command.
+const LAUNCH_UPDATE_MAX_LEN: u64 = 1 << 31;
+
+// Memory encryption ioctls, as defined in `include/uapi/linux/kvm.h`.
+const KVM_MEMORY_ENCRYPT_OP: u64 = 0xc008_aeba;
+const KVM_MEMORY_ENCRYPT_REG_REGION: u64 = 0x8010_aebb;
+
+// SEV commands, as defined by `enum sev_cmd_id` in `include/uapi/linux/kvm.h`.
+const KVM_SEV_INIT: u32 = 0;
+const KVM_SEV_ES_INIT: u32 = 1;
+const KVM_SEV_LAUNCH_START: u32 = 2;
+const KVM_SEV_LAUNCH_UPDATE_DATA: u32 = 3;
+const KVM_SEV_LAUNCH_UPDATE_VMSA: u32 = 4;
+const KVM_SEV_LAUNCH_MEASURE: u32 = 6;
+const KVM_SEV_LAUNCH_FINISH: u32 = 7;
+
+// Mirrors `struct kvm_sev_cmd`.
+#[repr(C)]
+#[derive(Default)]
+struct KvmSevCmd {
+    id: u32,
+    data: u64,
+    error: u32,
+    sev_fd: u32,
+}
+
+// Mirrors `struct kvm_sev_launch_start`. Without a guest owner Diffie-Hellman key and session
+// blob, the Secure Processor generates the guest keys on its own.
+#[repr(C)]
+#[derive(Default)]
+struct KvmSevLaunchStart {
+    handle: u32,
+    policy: u32,
+    dh_uaddr: u64,
+    dh_len: u32,
+    session_uaddr: u64,
+    session_len: u32,
+}
+
+// Mirrors both `struct kvm_sev_launch_update_data` and `struct kvm_sev_launch_measure`.
+#[repr(C)]
+#[derive(Default)]
+struct KvmSevBuffer {
+    uaddr: u64,
+    len: u32,
+}
+
+// Mirrors `struct kvm_enc_region`.
+#[repr(C)]
+#[derive(Default)]
+struct KvmEncRegion {
+    addr: u64,
+    size: u64,
+}
+
+/// Errors associated with launching a SEV guest.
+#[derive(Debug)]
+pub enum Error {
+    /// Cannot open the SEV device.
+    OpenSevDevice(io::Error),
+    /// Cannot register the guest memory as encrypted.
+    RegisterMemory(io::Error),
+    /// A SEV command failed, with the given Secure Processor error code.
+    SevCommand(&'static str, io::Error, u32),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::Error::*;
+
+        match self {
+            OpenSevDevice(err) => write!(f, "Cannot open {}: {}", SEV_DEVICE_PATH, err),
+            RegisterMemory(err) => write!(f, "Cannot register the encrypted memory: {}", err),
+            SevCommand(cmd, err, code) => write!(
+                f,
+                "The SEV command {} failed: {} (firmware error {:#x})",
+                cmd, err, code
+            ),
+        }
+    }
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Returns the bit to set in the page table entries mapping the guest memory as encrypted.
+pub fn memory_encryption_mask() -> u64 {
+    // Safe because CPUID has no side effects, and the leaf exists on the hosts supporting SEV.
+    let ebx = unsafe { std::arch::x86_64::__cpuid(CPUID_MEMORY_ENCRYPTION) }.ebx;
+    1 << (ebx & CBIT_POSITION_MASK)
+}
+
+/// Runs the launch sequence of a SEV guest.
+pub struct SevLauncher {
+    sev_file: File,
+    config: SevConfig,
+}
+
+impl SevLauncher {
+    /// Opens the host SEV device for launching a guest as configured by `config`.
+    pub fn new(config: &SevConfig) -> Result<Self> {
+        let sev_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(SEV_DEVICE_PATH)
+            .map_err(Error::OpenSevDevice)?;
+        Ok(SevLauncher {
+            sev_file,
+            config: config.clone(),
+        })
+    }
+
+    /// Initializes the SEV context of the VM. Has to be called before creating the vCPUs.
+    pub fn init(&self, vm_fd: &VmFd) -> Result<()> {
+        if self.config.es {
+            self.sev_command(vm_fd, KVM_SEV_ES_INIT, "ES_INIT", 0)
+        } else {
+            self.sev_command(vm_fd, KVM_SEV_INIT, "INIT", 0)
+        }
+    }
+
+    /// Registers the guest memory as encrypted, which pins it in the host memory.
+    pub fn register_memory(&self, vm_fd: &VmFd, guest_memory: &GuestMemoryMmap) -> Result<()> {
+        guest_memory.with_regions(|_, region| {
+            let mut enc_region = KvmEncRegion {
+                addr: region.as_ptr() as u64,
+                size: region.len(),
+            };
+            // Safe because the region outlives the VM and the kernel only reads `enc_region`.
+            let ret = unsafe {
+                ioctl_with_mut_ref(vm_fd, KVM_MEMORY_ENCRYPT_REG_REGION, &mut enc_region)
+            };
+            if ret < 0 {
+                return Err(Error::RegisterMemory(io::Error::last_os_error()));
+            }
+            Ok(())
+        })
+    }
+
+    /// Encrypts and measures the initial guest memory and, with SEV-ES, the vCPU state, then
+    /// finishes the launch. Has to be called once the guest is set up for boot, before running
+    /// the vCPUs.
+    ///
+    /// Returns the launch measurement.
+    pub fn launch(&self, vm_fd: &VmFd, guest_memory: &GuestMemoryMmap) -> Result<Vec<u8>> {
+        let mut start = KvmSevLaunchStart {
+            policy: self.config.policy,
+            ..Default::default()
+        };
+        self.sev_command(
+            vm_fd,
+            KVM_SEV_LAUNCH_START,
+            "LAUNCH_START",
+            &mut start as *mut _ as u64,
+        )?;
+
+        // The boot setup is spread across the guest memory, so all of it is encrypted.
+        // The length of an update is held on 32 bits, so large regions are split.
+        guest_memory.with_regions(|_, region| {
+            let region_addr = region.as_ptr() as u64;
+            let mut offset = 0;
+            while offset < region.len() {
+                let len = std::cmp::min(region.len() - offset, LAUNCH_UPDATE_MAX_LEN);
+                let mut update = KvmSevBuffer {
+                    uaddr: region_addr + offset,
+                    len: len as u32,
+                };
+                self.sev_command(
+                    vm_fd,
+                    KVM_SEV_LAUNCH_UPDATE_DATA,
+                    "LAUNCH_UPDATE_DATA",
+                    &mut update as *mut _ as u64,
+                )?;
+                offset += len;
+            }
+            Ok(())
+        })?;
+        if self.config.es {
+            self.sev_command(vm_fd, KVM_SEV_LAUNCH_UPDATE_VMSA, "LAUNCH_UPDATE_VMSA", 0)?;
+        }
+
+        let mut measurement = vec![0u8; LAUNCH_MEASUREMENT_SIZE];
+        let mut measure = KvmSevBuffer {
+            uaddr: measurement.as_mut_ptr() as u64,
+            len: LAUNCH_MEASUREMENT_SIZE as u32,
+        };
+        self.sev_command(
+            vm_fd,
+            KVM_SEV_LAUNCH_MEASURE,
+            "LAUNCH_MEASURE",
+            &mut measure as *mut _ as u64,
+        )?;
+        measurement.truncate(measure.len as usize);
+
+        self.sev_command(vm_fd, KVM_SEV_LAUNCH_FINISH, "LAUNCH_FINISH", 0)?;
+        Ok(measurement)
+    }
+
+    // Issues the SEV command `id`, which reads and/or writes the structure at the address
+    // `data`, if any.
+    fn sev_command(&self, vm_fd: &VmFd, id: u32, name: &'static str, data: u64) -> Result<()> {
+        let mut cmd = KvmSevCmd {
+            id,
+            data,
+            sev_fd: self.sev_file.as_raw_fd() as u32,
+            ..Default::default()
+        };
+        // Safe because `data` points to the structure the kernel expects for the command `id`,
+        // which outlives the call along with `cmd`.
+        let ret = unsafe { ioctl_with_mut_ref(vm_fd, KVM_MEMORY_ENCRYPT_OP, &mut cmd) };
+        if ret < 0 {
+            return Err(Error::SevCommand(
+                name,
+                io::Error::last_os_error(),
+                cmd.error,
+            ));
+        }
+        Ok(())
+    }
+}
@@ -27,7 +27,7 @@ extern "C" fn sigsys_handler(num: c_int, info: *mut siginfo_t, _unused: *mut c_v
     // Sanity check. The condition should never be true.
     if num != si_signo || num != SIGSYS || si_code != SYS_SECCOMP_CODE as i32 {
         // Safe because we're terminating the process anyway.
-        unsafe { _exit(i32::from(super::FC_EXIT_CODE_UNEXPECTED_ERROR)) };
+        unsafe { _exit(i32::from(super::FcExitCode::UnexpectedError)) };
     }
 
     // Other signals which might do async unsafe things incompatible with the rest of this
@@ -42,12 +42,14 @@ extern "C" fn sigsys_handler(num: c_int, info: *mut siginfo_t, _unused: *mut c_v
     if let Err(e) = METRICS.write() {
         error!("Failed to write metrics while stopping: {}", e);
     }
+    // Give the terminal back to the shell.
+    super::console::restore_console();
 
     // Safe because we're terminating the process anyway. We don't actually do anything when
     // running unit tests.
     #[cfg(not(test))]
     unsafe {
-        _exit(i32::from(super::FC_EXIT_CODE_BAD_SYSCALL))
+        _exit(i32::from(super::FcExitCode::BadSyscall))
     };
 }
 
@@ -62,7 +64,7 @@ extern "C" fn sigbus_sigsegv_handler(num: c_int, info: *mut siginfo_t, _unused:
     // Sanity check. The condition should never be true.
     if num != si_signo || (num != SIGBUS && num != SIGSEGV) {
         // Safe because we're terminating the process anyway.
-        unsafe { _exit(i32::from(super::FC_EXIT_CODE_UNEXPECTED_ERROR)) };
+        unsafe { _exit(i32::from(super::FcExitCode::UnexpectedError)) };
     }
 
     // Other signals which might do async unsafe things incompatible with the rest of this
@@ -81,15 +83,17 @@ extern "C" fn sigbus_sigsegv_handler(num: c_int, info: *mut siginfo_t, _unused:
     if let Err(e) = METRICS.write() {
         error!("Failed to write metrics while stopping: {}", e);
     }
+    // Give the terminal back to the shell.
+    super::console::restore_console();
 
     // Safe because we're terminating the process anyway. We don't actually do anything when
     // running unit tests.
     #[cfg(not(test))]
     unsafe {
         _exit(i32::from(match si_signo {
-            SIGBUS => super::FC_EXIT_CODE_SIGBUS,
-            SIGSEGV => super::FC_EXIT_CODE_SIGSEGV,
-            _ => super::FC_EXIT_CODE_UNEXPECTED_ERROR,
+            SIGBUS => super::FcExitCode::SigBus,
+            SIGSEGV => super::FcExitCode::SigSegv,
+            _ => super::FcExitCode::UnexpectedError,
         }))
     };
 }
@@ -0,0 +1,622 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Restores the guest memory of a snapshot on demand, through userfaultfd.
+//!
+//! The guest memory is created as empty anonymous mappings registered with a userfaultfd. The
+//! first access to a guest page raises a page fault which is served either by a handler thread
+//! of this process, reading the page from the guest memory file, or by an external page server
+//! to which the userfaultfd is sent over a Unix domain socket. A page server can thus back many
+//! microVMs restored from the same snapshot, each of them only getting copies of the pages it
+//! actually touches.
+//!
+//! The page server receives a single message, carrying the userfaultfd as ancillary data and the
+//! JSON serialized list of `GuestRegionUffdMapping`s.
+//!
+//! The page faults served by the handler thread can be profiled into the `page_faults` metrics,
+//! which count the guest pages faulted in, i.e. the working set of the restored microVM, how many
+//! of them follow the page faulted in before them, and the time spent serving a sample of them.
+//!
+//! The handler thread can also record the guest pages first faulted in to a prefetch manifest,
+//! listing the offsets of the pages in the guest memory file in the order they were accessed.
+//! The next restores of the snapshot can then prefetch these pages from another thread, ahead of
+//! the guest accessing them.
+
+// Currently only supports x86_64.
+#![cfg(target_arch = "x86_64")]
+
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use logger::{Metric, METRICS};
+use memory_snapshot::GuestMemoryState;
+use seccomp::{BpfProgram, SeccompFilter};
+use userfaultfd::{Event, FeatureFlags, Uffd, UffdBuilder};
+use utils::sock_ctrl_msg::ScmSocket;
+use vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap};
+use FcExitCode;
+
+// Granularity at which the guest memory is served.
+const PAGE_SIZE: usize = 4096;
+
+/// One in this many page faults profiled is timed.
+pub const FAULT_SAMPLE_PERIOD: u64 = 16;
+
+/// The most guest pages recorded in a prefetch manifest.
+pub const MAX_MANIFEST_PAGES: usize = 1 << 16;
+
+/// Errors associated with restoring the guest memory through userfaultfd.
+#[derive(Debug)]
+pub enum UffdError {
+    /// Cannot connect to the page server.
+    Connect(io::Error),
+    /// Cannot create the userfaultfd.
+    Create(userfaultfd::Error),
+    /// Cannot create the prefetch manifest.
+    CreateManifest(io::Error),
+    /// Cannot create the guest memory.
+    CreateMemory(vm_memory::Error),
+    /// Cannot get the host address of a guest memory region.
+    HostAddress(vm_memory::GuestMemoryError),
+    /// Cannot register the guest memory with the userfaultfd.
+    Register(userfaultfd::Error),
+    /// Cannot read the prefetch manifest.
+    ReadManifest(io::Error),
+    /// Cannot send the userfaultfd to the page server.
+    Send(io::Error),
+    /// Cannot serialize the guest memory layout.
+    Serialize(serde_json::Error),
+    /// Cannot spawn the page fault handler thread.
+    SpawnHandler(io::Error),
+}
+
+impl Display for UffdError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::UffdError::*;
+        match self {
+            Connect(err) => write!(f, "Cannot connect to the page server: {}", err),
+            Create(err) => write!(f, "Cannot create the userfaultfd: {:?}", err),
+            CreateManifest(err) => write!(f, "Cannot create the prefetch manifest: {}", err),
+            CreateMemory(err) => write!(f, "Cannot create the guest memory: {:?}", err),
+            HostAddress(err) => write!(f, "Cannot get the guest memory address: {:?}", err),
+            Register(err) => write!(f, "Cannot register the guest memory: {:?}", err),
+            ReadManifest(err) => write!(f, "Cannot read the prefetch manifest: {}", err),
+            Send(err) => write!(f, "Cannot send the userfaultfd: {}", err),
+            Serialize(err) => write!(f, "Cannot serialize the guest memory layout: {}", err),
+            SpawnHandler(err) => write!(f, "Cannot spawn the page fault handler: {}", err),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, UffdError>;
+
+/// Describes where a guest memory region lives, in this process and in the guest memory file.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct GuestRegionUffdMapping {
+    /// Host virtual address of the start of the region.
+    pub base_host_virt_addr: u64,
+    /// Region size.
+    pub size: usize,
+    /// Offset in the guest memory file where the region is saved.
+    pub offset: u64,
+}
+
+impl GuestRegionUffdMapping {
+    fn contains(&self, addr: u64) -> bool {
+        addr >= self.base_host_virt_addr && addr - self.base_host_virt_addr < self.size as u64
+    }
+
+    fn contains_offset(&self, offset: u64) -> bool {
+        offset >= self.offset && offset - self.offset < self.size as u64
+    }
+}
+
+/// Creates the guest memory described by `state`, registered with a new userfaultfd.
+///
+/// Returns the guest memory, the userfaultfd through which its pages have to be served, and the
+/// location of each guest memory region.
+pub fn create_guest_memory(
+    state: &GuestMemoryState,
+) -> Result<(GuestMemoryMmap, Uffd, Vec<GuestRegionUffdMapping>)> {
+    let ranges: Vec<_> = state
+        .regions
+        .iter()
+        .map(|region| (GuestAddress(region.base_address), region.size as usize))
+        .collect();
+    let guest_memory = GuestMemoryMmap::from_ranges(&ranges).map_err(UffdError::CreateMemory)?;
+
+    // Pages discarded by the balloon device have to be served as zeroes afterwards, rather than
+    // with their snapshot contents.
+    let uffd = UffdBuilder::new()
+        .close_on_exec(true)
+        .non_blocking(false)
+        .require_features(FeatureFlags::EVENT_REMOVE)
+        .create()
+        .map_err(UffdError::Create)?;
+
+    let mut mappings = Vec::with_capacity(state.regions.len());
+    for region in state.regions.iter() {
+        let host_addr = guest_memory
+            .get_host_address(GuestAddress(region.base_address))
+            .map_err(UffdError::HostAddress)?;
+        uffd.register(host_addr as _, region.size as usize)
+            .map_err(UffdError::Register)?;
+        mappings.push(GuestRegionUffdMapping {
+            base_host_virt_addr: host_addr as u64,
+            size: region.size as usize,
+            offset: region.offset,
+        });
+    }
+
+    Ok((guest_memory, uffd, mappings))
+}
+
+/// Hands over the page faults of `uffd` to the page server listening on `socket_path`.
+pub fn send_to_page_server(
+    uffd: &Uffd,
+    mappings: &[GuestRegionUffdMapping],
+    socket_path: &Path,
+) -> Result<()> {
+    let stream = UnixStream::connect(socket_path).map_err(UffdError::Connect)?;
+    let layout = serde_json::to_vec(mappings).map_err(UffdError::Serialize)?;
+    stream
+        .send_with_fd(&layout[..], uffd.as_raw_fd())
+        .map_err(|e| UffdError::Send(io::Error::from_raw_os_error(e.errno())))?;
+    Ok(())
+}
+
+/// How the page fault handler profiles the guest page faults and prefetches the guest pages.
+#[derive(Debug, Default)]
+pub struct FaultHandlerOptions {
+    /// Profile the page faults into the `page_faults` metrics.
+    pub profile: bool,
+    /// The prefetch manifest the guest pages first faulted in are recorded to.
+    pub record_manifest: Option<File>,
+    /// The offsets in the guest memory file of the guest pages to prefetch, in order.
+    pub prefetch_pages: Vec<u64>,
+}
+
+/// Reads the prefetch manifest at `path`, returning the offsets in the guest memory file of the
+/// guest pages it lists, which have to belong to the guest memory regions in `mappings`.
+pub fn read_manifest(path: &Path, mappings: &[GuestRegionUffdMapping]) -> Result<Vec<u64>> {
+    File::open(path)
+        .and_then(|manifest| parse_manifest(BufReader::new(manifest), mappings))
+        .map_err(UffdError::ReadManifest)
+}
+
+// Parses the page offsets in `manifest`, one hexadecimal offset per line.
+fn parse_manifest<R: BufRead>(
+    manifest: R,
+    mappings: &[GuestRegionUffdMapping],
+) -> io::Result<Vec<u64>> {
+    let mut pages = Vec::new();
+    for line in manifest.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let offset = Some(line)
+            .filter(|line| line.starts_with("0x"))
+            .and_then(|line| u64::from_str_radix(&line[2..], 16).ok())
+            .filter(|&offset| {
+                offset % PAGE_SIZE as u64 == 0
+                    && mappings
+                        .iter()
+                        .any(|mapping| mapping.contains_offset(offset))
+            })
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid guest page offset {}.", line),
+                )
+            })?;
+        pages.push(offset);
+    }
+    Ok(pages)
+}
+
+/// Spawns a thread serving the page faults of `uffd` with the contents of `mem_file`, profiling
+/// them as requested by `options`. If there are pages to prefetch, another thread copies them
+/// into the guest memory meanwhile, unless the guest accesses them first.
+///
+/// The threads run under `seccomp_filter`. The page fault handler runs as long as the process,
+/// and makes it exit when a page fault can't be served, while the prefetcher returns once done.
+pub fn spawn_fault_handler(
+    uffd: Uffd,
+    mem_file: File,
+    mappings: Vec<GuestRegionUffdMapping>,
+    options: FaultHandlerOptions,
+    seccomp_filter: BpfProgram,
+) -> Result<()> {
+    let uffd = Arc::new(uffd);
+    let mem_file = Arc::new(mem_file);
+
+    if !options.prefetch_pages.is_empty() {
+        let uffd = uffd.clone();
+        let mem_file = mem_file.clone();
+        let mappings = mappings.clone();
+        let seccomp_filter = seccomp_filter.clone();
+        let prefetch_pages = options.prefetch_pages;
+        thread::Builder::new()
+            .name("fc_uffd_prefetch".to_string())
+            .spawn(move || {
+                // Execution panics if filters cannot be loaded, use --seccomp-level=0 if skipping
+                // filters altogether is the desired behaviour.
+                if let Err(e) = SeccompFilter::apply(seccomp_filter) {
+                    panic!(
+                        "Failed to set the requested seccomp filters on the page prefetcher: \
+                         Error: {}",
+                        e
+                    );
+                }
+                prefetch(&uffd, &mem_file, &mappings, &prefetch_pages);
+            })
+            .map_err(UffdError::SpawnHandler)?;
+    }
+
+    let profiler = if options.profile {
+        Some(FaultProfiler::default())
+    } else {
+        None
+    };
+    let record_manifest = options.record_manifest;
+    thread::Builder::new()
+        .name("fc_uffd_handler".to_string())
+        .spawn(move || {
+            // Execution panics if filters cannot be loaded, use --seccomp-level=0 if skipping
+            // filters altogether is the desired behaviour.
+            if let Err(e) = SeccompFilter::apply(seccomp_filter) {
+                panic!(
+                    "Failed to set the requested seccomp filters on the page fault handler: \
+                     Error: {}",
+                    e
+                );
+            }
+            PageFaultHandler::new(uffd, mem_file, mappings, profiler, record_manifest).run();
+        })
+        .map_err(UffdError::SpawnHandler)?;
+    Ok(())
+}
+
+// Copies the guest pages at `offsets` in `mem_file` into the guest memory, in order, skipping
+// the ones the guest accessed first.
+fn prefetch(uffd: &Uffd, mem_file: &File, mappings: &[GuestRegionUffdMapping], offsets: &[u64]) {
+    let mut page = vec![0; PAGE_SIZE];
+    for &offset in offsets {
+        // The offsets were checked against the mappings when reading the manifest.
+        let mapping = match mappings.iter().find(|m| m.contains_offset(offset)) {
+            Some(mapping) => mapping,
+            None => continue,
+        };
+        if let Err(e) = mem_file.read_exact_at(&mut page, offset) {
+            error!(
+                "Cannot prefetch the guest page at offset {:#x}: {}",
+                offset, e
+            );
+            return;
+        }
+        let addr = mapping.base_host_virt_addr + offset - mapping.offset;
+        // Safe because the source buffer is one page long and the destination page belongs to a
+        // guest memory region registered with `uffd`.
+        match unsafe { uffd.copy(page.as_ptr() as _, addr as _, PAGE_SIZE, true) } {
+            Ok(_) => METRICS.page_faults.prefetched_pages.inc(),
+            // The page was faulted in first, or the guest memory is being changed, e.g. by the
+            // balloon device, in which case the page is left to be faulted in.
+            Err(userfaultfd::Error::CopyFailed(errno))
+                if errno as i32 == libc::EEXIST || errno as i32 == libc::EAGAIN => {}
+            Err(e) => {
+                error!(
+                    "Cannot prefetch the guest page at offset {:#x}: {:?}",
+                    offset, e
+                );
+                return;
+            }
+        }
+    }
+}
+
+// Profiles the page faults into the `page_faults` metrics.
+#[derive(Default)]
+struct FaultProfiler {
+    faults: u64,
+    // Host address of the page faulted in last.
+    last_page: Option<u64>,
+}
+
+impl FaultProfiler {
+    // Accounts for the page at `addr` being faulted in, as zeroes if `zero_page` is set. Returns
+    // whether serving the page fault has to be timed.
+    fn record(&mut self, addr: u64, zero_page: bool) -> bool {
+        METRICS.page_faults.faults.inc();
+        if self
+            .last_page
+            .map_or(false, |last| last + PAGE_SIZE as u64 == addr)
+        {
+            METRICS.page_faults.sequential_faults.inc();
+        }
+        if zero_page {
+            METRICS.page_faults.zero_pages.inc();
+        }
+        self.last_page = Some(addr);
+        self.faults += 1;
+        // The first page fault is timed too, as it is usually the one the vCPUs resume on.
+        self.faults % FAULT_SAMPLE_PERIOD == 1
+    }
+}
+
+struct PageFaultHandler {
+    uffd: Arc<Uffd>,
+    mem_file: Arc<File>,
+    mappings: Vec<GuestRegionUffdMapping>,
+    // Host addresses of the pages discarded since the restore.
+    removed_pages: HashSet<u64>,
+    page: Vec<u8>,
+    profiler: Option<FaultProfiler>,
+    // The prefetch manifest being recorded, and the number of pages it lists.
+    record_manifest: Option<File>,
+    recorded_pages: usize,
+}
+
+impl PageFaultHandler {
+    fn new(
+        uffd: Arc<Uffd>,
+        mem_file: Arc<File>,
+        mappings: Vec<GuestRegionUffdMapping>,
+        profiler: Option<FaultProfiler>,
+        record_manifest: Option<File>,
+    ) -> Self {
+        PageFaultHandler {
+            uffd,
+            mem_file,
+            mappings,
+            removed_pages: HashSet::new(),
+            page: vec![0; PAGE_SIZE],
+            profiler,
+            record_manifest,
+            recorded_pages: 0,
+        }
+    }
+
+    fn run(&mut self) {
+        loop {
+            match self.uffd.read_event() {
+                Ok(Some(Event::Pagefault { addr, .. })) => {
+                    let page = addr as u64 & !(PAGE_SIZE as u64 - 1);
+                    let zero_page = self.removed_pages.contains(&page);
+                    let start = match self.profiler.as_mut() {
+                        Some(profiler) if profiler.record(page, zero_page) => Some(Instant::now()),
+                        _ => None,
+                    };
+                    if let Err(e) = self.serve(page) {
+                        METRICS.page_faults.serve_fails.inc();
+                        fail(&format!("Cannot serve the guest page at {:?}: {}", addr, e));
+                    }
+                    if let Some(start) = start {
+                        METRICS.page_faults.sampled_faults.inc();
+                        METRICS
+                            .page_faults
+                            .sampled_serve_us
+                            .add(start.elapsed().as_micros() as usize);
+                    }
+                    // The pages served as zeroes are not in the guest memory file.
+                    if !zero_page {
+                        self.record_page(page);
+                    }
+                }
+                Ok(Some(Event::Remove { start, end })) => {
+                    for addr in (start as u64..end as u64).step_by(PAGE_SIZE) {
+                        self.removed_pages.insert(addr);
+                    }
+                }
+                Ok(_) => (),
+                Err(e) => fail(&format!("Cannot read the page faults: {:?}", e)),
+            }
+        }
+    }
+
+    // Appends the page at `addr` to the prefetch manifest being recorded, if any, until it lists
+    // `MAX_MANIFEST_PAGES` pages.
+    fn record_page(&mut self, addr: u64) {
+        if self.recorded_pages >= MAX_MANIFEST_PAGES {
+            return;
+        }
+        let offset = match self.mappings.iter().find(|mapping| mapping.contains(addr)) {
+            Some(mapping) => mapping.offset + addr - mapping.base_host_virt_addr,
+            None => return,
+        };
+        if let Some(manifest) = self.record_manifest.as_mut() {
+            // Each page is written at once, so that the manifest is complete up to the last
+            // page recorded whenever the process exits.
+            if let Err(e) = manifest.write_all(format!("{:#x}\n", offset).as_bytes()) {
+                error!("Cannot record the prefetch manifest: {}", e);
+                self.record_manifest = None;
+                return;
+            }
+            self.recorded_pages += 1;
+        }
+    }
+
+    fn serve(&mut self, addr: u64) -> io::Result<()> {
+        if self.removed_pages.remove(&addr) {
+            // Safe because the page belongs to a guest memory region registered with `uffd`.
+            return match unsafe { self.uffd.zeropage(addr as _, PAGE_SIZE, true) } {
+                Ok(_) => Ok(()),
+                Err(e) => ignore_populated(e),
+            };
+        }
+
+        let mapping = self
+            .mappings
+            .iter()
+            .find(|mapping| mapping.contains(addr))
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        self.mem_file.read_exact_at(
+            &mut self.page,
+            mapping.offset + addr - mapping.base_host_virt_addr,
+        )?;
+        // Safe because the source buffer is one page long and the destination page belongs to a
+        // guest memory region registered with `uffd`.
+        match unsafe {
+            self.uffd
+                .copy(self.page.as_ptr() as _, addr as _, PAGE_SIZE, true)
+        } {
+            Ok(_) => Ok(()),
+            Err(e) => ignore_populated(e),
+        }
+    }
+}
+
+// Makes Firecracker exit, since the vCPU waiting for a guest page that can't be served would be
+// stuck for good.
+fn fail(msg: &str) -> ! {
+    error!("{}, exiting.", msg);
+    // Write the metrics before exiting.
+    if let Err(e) = METRICS.write() {
+        error!("Failed to write metrics while stopping: {}", e);
+    }
+    std::process::exit(i32::from(FcExitCode::GenericError));
+}
+
+// Maps the failure to populate a guest page to an I/O error, unless the page was populated in
+// the meantime, e.g. by the prefetcher, which also woke up the faulting thread.
+fn ignore_populated(err: userfaultfd::Error) -> io::Result<()> {
+    match err {
+        userfaultfd::Error::CopyFailed(errno) | userfaultfd::Error::ZeropageFailed(errno)
+            if errno as i32 == libc::EEXIST =>
+        {
+            Ok(())
+        }
+        err => Err(io::Error::new(io::ErrorKind::Other, format!("{:?}", err))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapping_contains() {
+        let mapping = GuestRegionUffdMapping {
+            base_host_virt_addr: 0x1000,
+            size: 0x2000,
+            offset: 0,
+        };
+        assert!(!mapping.contains(0xfff));
+        assert!(mapping.contains(0x1000));
+        assert!(mapping.contains(0x2fff));
+        assert!(!mapping.contains(0x3000));
+
+        let mapping = GuestRegionUffdMapping {
+            base_host_virt_addr: 0x1000,
+            size: 0x2000,
+            offset: 0x4000,
+        };
+        assert!(!mapping.contains_offset(0x3fff));
+        assert!(mapping.contains_offset(0x4000));
+        assert!(mapping.contains_offset(0x5fff));
+        assert!(!mapping.contains_offset(0x6000));
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let mappings = [
+            GuestRegionUffdMapping {
+                base_host_virt_addr: 0x7f00_0000_0000,
+                size: 0x2000,
+                offset: 0,
+            },
+            GuestRegionUffdMapping {
+                base_host_virt_addr: 0x7f10_0000_0000,
+                size: 0x1000,
+                offset: 0x2000,
+            },
+        ];
+        let manifest = "0x2000\n0x0\n\n0x1000\n";
+        assert_eq!(
+            parse_manifest(manifest.as_bytes(), &mappings).unwrap(),
+            vec![0x2000, 0, 0x1000]
+        );
+        assert!(parse_manifest("".as_bytes(), &mappings).unwrap().is_empty());
+
+        // The offsets have to be hexadecimal, page aligned and within the guest memory file.
+        for manifest in &["4096\n", "0xfoo\n", "0x800\n", "0x3000\n"] {
+            assert_eq!(
+                parse_manifest(manifest.as_bytes(), &mappings)
+                    .unwrap_err()
+                    .kind(),
+                io::ErrorKind::InvalidData
+            );
+        }
+        assert_eq!(
+            parse_manifest("0x0\n0x3000\n".as_bytes(), &mappings)
+                .unwrap_err()
+                .to_string(),
+            "Invalid guest page offset 0x3000."
+        );
+    }
+
+    #[test]
+    fn test_mapping_serialization() {
+        let mappings = vec![GuestRegionUffdMapping {
+            base_host_virt_addr: 0x7f00_0000_0000,
+            size: 0x1000,
+            offset: 0x2000,
+        }];
+        let layout = serde_json::to_string(&mappings).unwrap();
+        assert_eq!(
+            layout,
+            r#"[{"base_host_virt_addr":139637976727552,"size":4096,"offset":8192}]"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Vec<GuestRegionUffdMapping>>(&layout).unwrap(),
+            mappings
+        );
+    }
+
+    #[test]
+    fn test_fault_profiler() {
+        let mut profiler = FaultProfiler::default();
+        let sequential_faults = METRICS.page_faults.sequential_faults.count();
+        let zero_pages = METRICS.page_faults.zero_pages.count();
+
+        assert!(profiler.record(0x2000, false));
+        assert!(!profiler.record(0x3000, true));
+        assert!(!profiler.record(0x1000, false));
+        assert_eq!(profiler.last_page, Some(0x1000));
+        // The counters are shared with the other tests.
+        assert!(METRICS.page_faults.sequential_faults.count() >= sequential_faults + 1);
+        assert!(METRICS.page_faults.zero_pages.count() >= zero_pages + 1);
+
+        let timed = (0..2 * FAULT_SAMPLE_PERIOD)
+            .filter(|&page| profiler.record(page * PAGE_SIZE as u64, false))
+            .count();
+        assert_eq!(timed, 2);
+        assert_eq!(profiler.faults, 2 * FAULT_SAMPLE_PERIOD + 3);
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let err = UffdError::Connect(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = UffdError::CreateManifest(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = UffdError::CreateMemory(vm_memory::Error::NoMemoryRegion);
+        let _ = format!("{}{:?}", err, err);
+        let err = UffdError::ReadManifest(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = UffdError::Send(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = UffdError::SpawnHandler(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use devices::virtio::balloon::Error as BalloonError;
+use devices::virtio::Balloon;
+
+type MutexBalloon = Arc<Mutex<Balloon>>;
+
+/// Errors associated with the operations allowed on the balloon.
+#[derive(Debug)]
+pub enum BalloonConfigError {
+    /// Failed to create the balloon device.
+    CreateBalloonDevice(BalloonError),
+    /// The user made a request on an inexistent balloon device.
+    DeviceNotFound,
+    /// The requested balloon size exceeds the guest memory size.
+    TooManyPagesRequested,
+    /// Failed to update the balloon size.
+    UpdateFailed(devices::Error),
+}
+
+impl fmt::Display for BalloonConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::BalloonConfigError::*;
+        match *self {
+            CreateBalloonDevice(ref e) => write!(f, "Cannot create balloon device: {:?}", e),
+            DeviceNotFound => write!(f, "No balloon device found."),
+            TooManyPagesRequested => {
+                write!(
+                    f,
+                    "Amount of pages requested cannot fit in the guest memory."
+                )
+            }
+            UpdateFailed(ref e) => write!(f, "Cannot update the balloon device: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for BalloonConfigError {}
+
+type Result<T> = std::result::Result<T, BalloonConfigError>;
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from balloon related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BalloonDeviceConfig {
+    /// Target balloon size in MiB.
+    pub amount_mib: u32,
+    /// Option to deflate the balloon in case the guest is out of memory.
+    pub deflate_on_oom: bool,
+    /// Option to let the guest report its free pages to the host.
+    #[serde(default)]
+    pub free_page_reporting: bool,
+}
+
+/// The data fed into a balloon update request. Currently, only the target size
+/// of the balloon can be updated after boot.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BalloonUpdateConfig {
+    /// Target balloon size in MiB.
+    pub amount_mib: u32,
+}
+
+/// A builder for `Balloon` devices from 'BalloonDeviceConfig'.
+#[derive(Default)]
+pub struct BalloonBuilder {
+    inner: Option<MutexBalloon>,
+}
+
+impl BalloonBuilder {
+    /// Creates an empty Balloon Store.
+    pub fn new() -> Self {
+        Self { inner: None }
+    }
+
+    /// Inserts a Balloon device in the store.
+    /// If an entry already exists, it will overwrite it.
+    pub fn set(&mut self, cfg: BalloonDeviceConfig) -> Result<()> {
+        self.inner = Some(Arc::new(Mutex::new(
+            Balloon::new(cfg.amount_mib, cfg.deflate_on_oom, cfg.free_page_reporting)
+                .map_err(BalloonConfigError::CreateBalloonDevice)?,
+        )));
+        Ok(())
+    }
+
+    /// Provides a reference to the Balloon if present.
+    pub fn get(&self) -> Option<&MutexBalloon> {
+        self.inner.as_ref()
+    }
+
+    /// Returns the configuration of the Balloon device, if present.
+    pub fn get_config(&self) -> Option<BalloonDeviceConfig> {
+        self.inner.as_ref().map(|balloon| {
+            let balloon = balloon.lock().expect("Poisoned lock");
+            BalloonDeviceConfig {
+                amount_mib: balloon.size_mib(),
+                deflate_on_oom: balloon.deflate_on_oom(),
+                free_page_reporting: balloon.free_page_reporting(),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn default_config() -> BalloonDeviceConfig {
+        BalloonDeviceConfig {
+            amount_mib: 0,
+            deflate_on_oom: false,
+            free_page_reporting: false,
+        }
+    }
+
+    #[test]
+    fn test_balloon_create() {
+        let default_balloon_config = default_config();
+        let balloon_config = BalloonDeviceConfig {
+            amount_mib: 0,
+            deflate_on_oom: false,
+            free_page_reporting: false,
+        };
+        assert_eq!(default_balloon_config, balloon_config);
+
+        let mut builder = BalloonBuilder::new();
+        assert!(builder.get().is_none());
+        assert!(builder.get_config().is_none());
+
+        builder.set(balloon_config).unwrap();
+        assert_eq!(builder.get().unwrap().lock().unwrap().size_mib(), 0);
+
+        let balloon_config = BalloonDeviceConfig {
+            amount_mib: 16,
+            deflate_on_oom: true,
+            free_page_reporting: true,
+        };
+        builder.set(balloon_config.clone()).unwrap();
+        assert_eq!(builder.get_config().unwrap(), balloon_config);
+    }
+
+    #[test]
+    fn test_balloon_config_deserialization() {
+        let config: BalloonDeviceConfig =
+            serde_json::from_str(r#"{"amount_mib": 8, "deflate_on_oom": true}"#).unwrap();
+        assert!(!config.free_page_reporting);
+        assert!(serde_json::from_str::<BalloonDeviceConfig>(
+            r#"{"amount_mib": 8, "deflate_on_oom": true, "foo": 0}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use super::BalloonConfigError::*;
+        let err = CreateBalloonDevice(BalloonError::TooManyPagesRequested);
+        let _ = format!("{}{:?}", err, err);
+        let err = DeviceNotFound;
+        let _ = format!("{}{:?}", err, err);
+        let err = TooManyPagesRequested;
+        let _ = format!("{}{:?}", err, err);
+        let err = UpdateFailed(devices::Error::Balloon(BalloonError::TooManyPagesRequested));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -1,8 +1,10 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::fmt::{Display, Formatter, Result};
+use std::fmt::{Debug, Display, Formatter, Result};
+use std::fs::File;
 use std::io;
+use std::sync::Arc;
 
 /// Default guest kernel command line:
 /// - `reboot=k` shut down the guest on reboot, instead of well... rebooting;
@@ -17,19 +19,66 @@ use std::io;
 pub const DEFAULT_KERNEL_CMDLINE: &str = "reboot=k panic=1 pci=off nomodules 8250.nr_uarts=0 \
                                           i8042.noaux i8042.nomux i8042.nopnp i8042.dumbkbd";
 
+/// How the guest kernel finds the virtio-mmio devices. Only x86_64 guests can be told about them
+/// on the kernel command line: the aarch64 ones always find them in the device tree built by
+/// Firecracker.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MmioDeviceEnumeration {
+    /// A `virtio_mmio.device=` kernel command line parameter per device, in MMIO address order,
+    /// which is the order the guest kernel probes the devices in.
+    Cmdline,
+    /// No kernel command line parameter: the guest kernel finds the devices in the device tree or
+    /// the ACPI tables handed over by its firmware or bootloader.
+    Firmware,
+}
+
+impl Default for MmioDeviceEnumeration {
+    fn default() -> Self {
+        MmioDeviceEnumeration::Cmdline
+    }
+}
+
 /// Strongly typed data structure used to configure the boot source of the
 /// microvm.
-#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct BootSourceConfig {
-    /// Path of the kernel image.
+    /// Path of the kernel image, or `fd://<fd>` naming a file descriptor opened on it.
     pub kernel_image_path: String,
-    /// Path of the initrd, if there is one.
+    /// Path of the initrd, if there is one, or `fd://<fd>` naming a file descriptor opened on it.
     pub initrd_path: Option<String>,
+    /// The kernel image held in memory, booted instead of the file at `kernel_image_path`. Only
+    /// embedders can set it, it can't be given through the API.
+    #[serde(skip)]
+    pub kernel_bytes: Option<KernelBytes>,
     /// The boot arguments to pass to the kernel. If this field is uninitialized, the default
     /// kernel command line is used: `reboot=k panic=1 pci=off nomodules 8250.nr_uarts=0`.
+    /// `${name}` placeholders are replaced by the value of the `BootArgsVariable` named `name`
+    /// when the microVM boots, and `$${` stands for a literal `${`.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub boot_args: Option<String>,
+    /// How the guest kernel finds the virtio-mmio devices.
+    #[serde(default)]
+    pub device_enumeration: MmioDeviceEnumeration,
+}
+
+/// A kernel image held in memory, e.g. compiled into the binary of the embedder or fetched over
+/// the network, so that it needn't be written to a file to be booted.
+#[derive(Clone, PartialEq)]
+pub struct KernelBytes(pub Arc<Vec<u8>>);
+
+impl From<Vec<u8>> for KernelBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        KernelBytes(Arc::new(bytes))
+    }
+}
+
+// The kernel images are megabytes long, so only their length is worth printing.
+impl Debug for KernelBytes {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "KernelBytes({} bytes)", self.0.len())
+    }
 }
 
 /// Errors associated with actions on `BootSourceConfig`.
@@ -61,13 +110,188 @@ impl Display for BootSourceConfigError {
     }
 }
 
+impl std::error::Error for BootSourceConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use self::BootSourceConfigError::*;
+        match self {
+            InvalidKernelPath(e) | InvalidInitrdPath(e) => Some(e),
+            InvalidKernelCommandLine(_) => None,
+        }
+    }
+}
+
+/// The variables the boot arguments can refer to, whose values are only known to the VMM.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BootArgsVariable {
+    /// `${instance_id}`: the ID of the Firecracker instance, given with `--id`.
+    InstanceId,
+    /// `${mmds_ip}`: the IPv4 address the MMDS answers at in the guest.
+    MmdsIp,
+    /// `${root_device}`: the root device, as the `root=` kernel parameter names it: either
+    /// `PARTUUID=<partuuid>` or `/dev/vda`.
+    RootDevice,
+}
+
+impl BootArgsVariable {
+    /// Returns the variable named `name` in the placeholders.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "instance_id" => Some(BootArgsVariable::InstanceId),
+            "mmds_ip" => Some(BootArgsVariable::MmdsIp),
+            "root_device" => Some(BootArgsVariable::RootDevice),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the variable in the placeholders.
+    pub fn name(self) -> &'static str {
+        match self {
+            BootArgsVariable::InstanceId => "instance_id",
+            BootArgsVariable::MmdsIp => "mmds_ip",
+            BootArgsVariable::RootDevice => "root_device",
+        }
+    }
+}
+
+/// Errors associated with filling the placeholders of the boot arguments.
+#[derive(Debug, PartialEq)]
+pub enum BootArgsError {
+    /// A placeholder isn't closed by a `}`.
+    UnterminatedPlaceholder,
+    /// A placeholder names no known variable.
+    UnknownVariable(String),
+    /// The variable has no value for this microVM, e.g. the root device when there is none.
+    UnavailableVariable(BootArgsVariable),
+}
+
+impl Display for BootArgsError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        use self::BootArgsError::*;
+        match *self {
+            UnterminatedPlaceholder => {
+                write!(f, "A placeholder of the boot arguments is not closed.")
+            }
+            UnknownVariable(ref name) => {
+                write!(
+                    f,
+                    "The boot arguments refer to an unknown variable: {}",
+                    name
+                )
+            }
+            UnavailableVariable(variable) => write!(
+                f,
+                "The boot arguments refer to {}, which has no value for this microVM.",
+                variable.name()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BootArgsError {}
+
+/// Replaces the `${name}` placeholders of `boot_args` with the values `value_of` gives to their
+/// variables. `$${` stands for a literal `${`.
+pub fn fill_boot_args<F>(
+    boot_args: &str,
+    mut value_of: F,
+) -> std::result::Result<String, BootArgsError>
+where
+    F: FnMut(BootArgsVariable) -> Option<String>,
+{
+    let mut filled = String::with_capacity(boot_args.len());
+    let mut rest = boot_args;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            filled.push_str(&rest[..start - 1]);
+            filled.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        let end = start
+            + rest[start..]
+                .find('}')
+                .ok_or(BootArgsError::UnterminatedPlaceholder)?;
+        let name = &rest[start + 2..end];
+        let variable = BootArgsVariable::from_name(name)
+            .ok_or_else(|| BootArgsError::UnknownVariable(name.to_string()))?;
+        let value = value_of(variable).ok_or(BootArgsError::UnavailableVariable(variable))?;
+        filled.push_str(&rest[..start]);
+        filled.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    filled.push_str(rest);
+    Ok(filled)
+}
+
+/// The kernel image the microVM boots.
+#[derive(Debug)]
+pub enum KernelImage {
+    /// The descriptor to the kernel file.
+    File(File),
+    /// The kernel image held in memory.
+    Bytes(KernelBytes),
+}
+
 /// Holds the kernel configuration.
 #[derive(Debug)]
 pub struct BootConfig {
     /// The commandline validated against correctness.
     pub cmdline: kernel::cmdline::Cmdline,
-    /// The descriptor to the kernel file.
-    pub kernel_file: std::fs::File,
+    /// The kernel image.
+    pub kernel_image: KernelImage,
     /// The descriptor to the initrd file, if there is one
     pub initrd_file: Option<std::fs::File>,
+    /// How the guest kernel finds the virtio-mmio devices.
+    pub device_enumeration: MmioDeviceEnumeration,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_boot_args() {
+        let value_of = |variable| match variable {
+            BootArgsVariable::InstanceId => Some("vm0".to_string()),
+            BootArgsVariable::MmdsIp => Some("169.254.169.254".to_string()),
+            BootArgsVariable::RootDevice => None,
+        };
+
+        assert_eq!(
+            fill_boot_args("console=ttyS0 reboot=k", value_of).unwrap(),
+            "console=ttyS0 reboot=k"
+        );
+        assert_eq!(
+            fill_boot_args(
+                "hostname=${instance_id} ds=nocloud-net;s=http://${mmds_ip}/${instance_id}/",
+                value_of
+            )
+            .unwrap(),
+            "hostname=vm0 ds=nocloud-net;s=http://169.254.169.254/vm0/"
+        );
+        // Only `${` starts a placeholder, and `$${` escapes it.
+        assert_eq!(
+            fill_boot_args("a=$HOME b={x} c=$${instance_id} d=$$${mmds_ip}", value_of).unwrap(),
+            "a=$HOME b={x} c=${instance_id} d=$${mmds_ip}"
+        );
+
+        assert_eq!(
+            fill_boot_args("root=${root_device}", value_of),
+            Err(BootArgsError::UnavailableVariable(
+                BootArgsVariable::RootDevice
+            ))
+        );
+        assert_eq!(
+            fill_boot_args("ip=${guest_ip}", value_of),
+            Err(BootArgsError::UnknownVariable("guest_ip".to_string()))
+        );
+        assert_eq!(
+            fill_boot_args("ip=${mmds_ip", value_of),
+            Err(BootArgsError::UnterminatedPlaceholder)
+        );
+        assert_eq!(
+            BootArgsError::UnavailableVariable(BootArgsVariable::RootDevice).to_string(),
+            "The boot arguments refer to root_device, which has no value for this microVM."
+        );
+    }
 }
@@ -0,0 +1,135 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+
+/// The largest size of the user-data and the meta-data, together.
+pub const MAX_CLOUD_INIT_DATA_SIZE: usize = 16 << 20;
+/// The meta-data handed to the guest when none is configured. cloud-init requires an instance ID.
+pub const DEFAULT_META_DATA: &str = "instance-id: iid-firecracker\n";
+
+/// Errors associated with the cloud-init configuration.
+#[derive(Debug, PartialEq)]
+pub enum CloudInitConfigError {
+    /// The user-data and the meta-data are too large.
+    DataTooLarge(usize),
+}
+
+impl fmt::Display for CloudInitConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::CloudInitConfigError::*;
+        match *self {
+            DataTooLarge(size) => write!(
+                f,
+                "The user-data and the meta-data take {} bytes, more than the {} bytes allowed.",
+                size, MAX_CLOUD_INIT_DATA_SIZE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CloudInitConfigError {}
+
+/// How the cloud-init data is handed to the guest.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CloudInitTransport {
+    /// A read-only drive holding a NoCloud seed: a FAT filesystem labelled `CIDATA`, with the
+    /// `user-data` and `meta-data` files.
+    Seed,
+    /// The `user-data` and `meta-data` keys of the MMDS, which the kernel command line points
+    /// the NoCloud datasource at.
+    Mmds,
+}
+
+impl Default for CloudInitTransport {
+    fn default() -> Self {
+        CloudInitTransport::Seed
+    }
+}
+
+/// Configures the NoCloud data cloud-init finds in the guest, so that stock cloud images boot
+/// unmodified.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CloudInitConfig {
+    /// The user-data, e.g. a `#cloud-config` document or a script.
+    pub user_data: String,
+    /// The meta-data, in YAML. `DEFAULT_META_DATA` if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta_data: Option<String>,
+    /// How the data is handed to the guest.
+    #[serde(default)]
+    pub transport: CloudInitTransport,
+}
+
+impl CloudInitConfig {
+    /// Checks that the data fits in the seed.
+    pub fn validate(&self) -> Result<(), CloudInitConfigError> {
+        let size = self.user_data.len() + self.meta_data().len();
+        if size > MAX_CLOUD_INIT_DATA_SIZE {
+            return Err(CloudInitConfigError::DataTooLarge(size));
+        }
+        Ok(())
+    }
+
+    /// Returns the meta-data handed to the guest.
+    pub fn meta_data(&self) -> &str {
+        self.meta_data
+            .as_ref()
+            .map_or(DEFAULT_META_DATA, String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cloud_init_config() {
+        let mut config: CloudInitConfig =
+            serde_json::from_str(r##"{"user_data": "#cloud-config\n"}"##).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.transport, CloudInitTransport::Seed);
+        assert_eq!(config.meta_data(), DEFAULT_META_DATA);
+
+        config = serde_json::from_str(
+            r#"{
+                "user_data": "",
+                "meta_data": "instance-id: vm0\n",
+                "transport": "mmds"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.transport, CloudInitTransport::Mmds);
+        assert_eq!(config.meta_data(), "instance-id: vm0\n");
+
+        assert!(serde_json::from_str::<CloudInitConfig>(r#"{"meta_data": ""}"#).is_err());
+        assert!(serde_json::from_str::<CloudInitConfig>(
+            r#"{"user_data": "", "transport": "iso"}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut config = CloudInitConfig {
+            user_data: "x".repeat(MAX_CLOUD_INIT_DATA_SIZE - DEFAULT_META_DATA.len()),
+            meta_data: None,
+            transport: CloudInitTransport::Seed,
+        };
+        config.validate().unwrap();
+        config.meta_data = Some(format!("{}\n", DEFAULT_META_DATA));
+        assert_eq!(
+            config.validate(),
+            Err(CloudInitConfigError::DataTooLarge(
+                MAX_CLOUD_INIT_DATA_SIZE + 1
+            ))
+        );
+        assert_eq!(
+            CloudInitConfigError::DataTooLarge(17 << 20).to_string(),
+            "The user-data and the meta-data take 17825792 bytes, more than the 16777216 bytes \
+             allowed."
+        );
+    }
+}
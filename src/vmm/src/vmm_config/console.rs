@@ -0,0 +1,146 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::io;
+
+/// Errors associated with the serial console.
+#[derive(Debug)]
+pub enum ConsoleConfigError {
+    /// The input of the serial console doesn't come from the API.
+    InputNotFromApi,
+    /// The `socket` input needs the path of the socket.
+    MissingSocketPath,
+    /// Only the `socket` input takes the path of a socket.
+    UnexpectedSocketPath,
+    /// Cannot hand the input to the serial console.
+    WriteInput(io::Error),
+}
+
+impl fmt::Display for ConsoleConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ConsoleConfigError::*;
+        match self {
+            InputNotFromApi => write!(
+                f,
+                "The input of the serial console doesn't come from the API."
+            ),
+            MissingSocketPath => write!(f, "The socket console input needs a socket path."),
+            UnexpectedSocketPath => write!(f, "Only the socket console input takes a socket path."),
+            WriteInput(err) => write!(f, "Cannot write to the serial console: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ConsoleConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConsoleConfigError::WriteInput(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Where the input of the serial console comes from.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsoleInput {
+    /// The standard input of the Firecracker process, which is switched to raw mode while the
+    /// microVM runs.
+    Stdin,
+    /// The `WriteToConsole` action. The terminal of the Firecracker process is left untouched.
+    Api,
+    /// The client connected to a Unix socket Firecracker listens on. The terminal of the
+    /// Firecracker process is left untouched.
+    Socket,
+}
+
+impl Default for ConsoleInput {
+    fn default() -> Self {
+        ConsoleInput::Stdin
+    }
+}
+
+/// Configures the serial console of the microVM. The output of the serial console always goes
+/// to the standard output of the Firecracker process.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConsoleConfig {
+    /// Where the input of the serial console comes from.
+    #[serde(default)]
+    pub input: ConsoleInput,
+    /// Path of the Unix socket to listen on, for the `socket` input.
+    #[serde(default)]
+    pub socket_path: Option<String>,
+}
+
+impl ConsoleConfig {
+    /// Checks that the socket path is given exactly when the input comes from a socket.
+    pub fn validate(&self) -> Result<(), ConsoleConfigError> {
+        match (self.input, self.socket_path.is_some()) {
+            (ConsoleInput::Socket, false) => Err(ConsoleConfigError::MissingSocketPath),
+            (ConsoleInput::Stdin, true) | (ConsoleInput::Api, true) => {
+                Err(ConsoleConfigError::UnexpectedSocketPath)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_console_config() {
+        assert!(ConsoleConfig::default().validate().is_ok());
+
+        let mut config = ConsoleConfig {
+            input: ConsoleInput::Socket,
+            socket_path: Some("/tmp/console.sock".to_string()),
+        };
+        assert!(config.validate().is_ok());
+
+        config.input = ConsoleInput::Api;
+        match config.validate() {
+            Err(ConsoleConfigError::UnexpectedSocketPath) => (),
+            _ => panic!("Unexpected result"),
+        }
+        config.socket_path = None;
+        assert!(config.validate().is_ok());
+
+        config.input = ConsoleInput::Socket;
+        match config.validate() {
+            Err(ConsoleConfigError::MissingSocketPath) => (),
+            _ => panic!("Unexpected result"),
+        }
+    }
+
+    #[test]
+    fn test_console_config_deserialization() {
+        let config: ConsoleConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, ConsoleConfig::default());
+        assert_eq!(config.input, ConsoleInput::Stdin);
+
+        let config: ConsoleConfig =
+            serde_json::from_str(r#"{"input": "socket", "socket_path": "/tmp/console.sock"}"#)
+                .unwrap();
+        assert_eq!(config.input, ConsoleInput::Socket);
+        assert_eq!(config.socket_path, Some("/tmp/console.sock".to_string()));
+
+        assert!(serde_json::from_str::<ConsoleConfig>(r#"{"input": "tty"}"#).is_err());
+        assert!(serde_json::from_str::<ConsoleConfig>(r#"{"output": "stdout"}"#).is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let err = ConsoleConfigError::InputNotFromApi;
+        let _ = format!("{}{:?}", err, err);
+        let err = ConsoleConfigError::MissingSocketPath;
+        let _ = format!("{}{:?}", err, err);
+        let err = ConsoleConfigError::UnexpectedSocketPath;
+        let _ = format!("{}{:?}", err, err);
+        let err = ConsoleConfigError::WriteInput(io::Error::from_raw_os_error(libc::EIO));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -1,16 +1,25 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::convert::TryInto;
 use std::fmt::{Display, Formatter};
-use std::io;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::io::FromRawFd;
 use std::path::PathBuf;
 use std::result;
 use std::sync::{Arc, Mutex};
 
-use super::RateLimiterConfig;
+use super::preopened_fds::PreopenedFds;
+use super::{Identifier, RateLimiterConfig};
+use cloud_init::SEED_DRIVE_ID;
+use devices::virtio::block::encryption::{DiskCipher, AES_256_XTS_KEY_LEN};
+use devices::virtio::block::scheduler::{MAX_IO_WEIGHT, MIN_IO_WEIGHT};
+use devices::virtio::block::verity::{HashTree, VerityError};
+use devices::virtio::block::{MAX_PHYSICAL_BLOCK_SIZE, SECTOR_SIZE};
 use devices::virtio::Block;
+use utils::fd_uri;
 
 type Result<T> = result::Result<T, DriveError>;
 
@@ -24,14 +33,53 @@ pub enum DriveError {
     CreateBlockDevice(io::Error),
     /// Failed to create a `RateLimiter` object.
     CreateRateLimiter(io::Error),
+    /// Cannot flush the backing file before replacing it.
+    FlushBlockDevice(io::Error),
+    /// The guest agent didn't freeze the filesystems of the guest.
+    GuestFreeze(io::Error),
+    /// The guest filesystems can only be frozen to replace the backing file of the drive.
+    GuestFreezeWithoutPath,
+    /// The vsock device to reach the guest agent through doesn't exist, or has no Unix socket.
+    GuestFreezeVsock(Option<String>),
     /// The block device ID is invalid.
     InvalidBlockDeviceID,
     /// The block device path is invalid.
-    InvalidBlockDevicePath,
+    InvalidBlockDevicePath(io::Error),
+    /// The encryption configuration doesn't specify exactly one source for the key.
+    InvalidEncryptionConfig,
+    /// The encryption key has an invalid length.
+    InvalidEncryptionKey(usize),
+    /// The timeout of the guest freeze is 0.
+    InvalidGuestFreezeTimeout,
+    /// The hash tree doesn't match the drive.
+    InvalidHashTree(VerityError),
+    /// The I/O weight is out of range.
+    InvalidIoWeight(u32),
+    /// The physical block size is not a power of two or is out of range.
+    InvalidPhysicalBlockSize(u32),
+    /// The range of the backing file to prefetch, given by its offset and length, ends beyond
+    /// the largest file offset.
+    InvalidPrefetchRange(u64, u64),
+    /// The serial number is too long or has characters which are not printable ASCII.
+    InvalidSerial(String),
+    /// The hash tree is set on a drive which is writable or encrypted.
+    InvalidVerityConfig,
+    /// The root hash or the salt of the hash tree is not a hexadecimal string.
+    InvalidVerityHex,
     /// Cannot open block device due to invalid permissions or path.
     OpenBlockDevice(io::Error),
+    /// Cannot open the file holding the hash tree.
+    OpenHashTree(io::Error),
+    /// Cannot read the encryption key.
+    ReadEncryptionKey(io::Error),
+    /// The drive ID is reserved to the cloud-init seed drive.
+    ReservedDriveId,
     /// A root block device was already added.
     RootBlockDeviceAlreadyAdded,
+    /// Cannot copy the backing file of the block device.
+    SnapshotBlockDevice(io::Error),
+    /// The disk image of a drive with a hash tree cannot be replaced.
+    UpdateVerifiedDrive,
 }
 
 impl Display for DriveError {
@@ -46,25 +94,319 @@ impl Display for DriveError {
             ),
             BlockDeviceUpdateFailed => write!(f, "The update operation failed!"),
             CreateRateLimiter(ref e) => write!(f, "Cannot create RateLimiter: {}", e),
+            FlushBlockDevice(ref e) => write!(
+                f,
+                "Cannot flush the backing file of the block device before replacing it: {}",
+                e
+            ),
+            GuestFreeze(ref e) => {
+                write!(f, "Cannot freeze the filesystems of the guest: {}", e)
+            }
+            GuestFreezeWithoutPath => write!(
+                f,
+                "The filesystems of the guest can only be frozen to replace the backing file."
+            ),
+            GuestFreezeVsock(Some(ref vsock_id)) => write!(
+                f,
+                "The vsock device {} doesn't exist or has no Unix socket.",
+                vsock_id
+            ),
+            GuestFreezeVsock(None) => write!(
+                f,
+                "There is no vsock device with a Unix socket to reach the guest agent through."
+            ),
             InvalidBlockDeviceID => write!(f, "Invalid block device ID!"),
-            InvalidBlockDevicePath => write!(f, "Invalid block device path!"),
+            InvalidBlockDevicePath(ref e) => write!(f, "Invalid block device path: {}", e),
+            InvalidEncryptionConfig => write!(
+                f,
+                "The encryption key must be given by either a path or a file descriptor."
+            ),
+            InvalidEncryptionKey(len) => write!(
+                f,
+                "Invalid encryption key of {} bytes, AES-XTS keys have 32 or 64 bytes.",
+                len
+            ),
+            InvalidGuestFreezeTimeout => {
+                write!(f, "The timeout of the guest freeze must be at least 1 ms.")
+            }
+            InvalidHashTree(ref e) => write!(f, "Invalid hash tree: {}", e),
+            InvalidIoWeight(weight) => write!(
+                f,
+                "Invalid I/O weight {}, it must be between {} and {}.",
+                weight, MIN_IO_WEIGHT, MAX_IO_WEIGHT
+            ),
+            InvalidPhysicalBlockSize(size) => write!(
+                f,
+                "Invalid physical block size {}, it must be a power of two between {} and {}.",
+                size, SECTOR_SIZE, MAX_PHYSICAL_BLOCK_SIZE
+            ),
+            InvalidPrefetchRange(offset, len) => write!(
+                f,
+                "Invalid prefetch range of {} bytes at offset {}, it must end before offset {}.",
+                len,
+                offset,
+                i64::max_value()
+            ),
+            InvalidSerial(ref serial) => write!(
+                f,
+                "Invalid serial number {:?}, it must have at most {} printable ASCII characters.",
+                serial, MAX_SERIAL_LEN
+            ),
+            InvalidVerityConfig => write!(
+                f,
+                "A hash tree can only be set on a read-only drive which is not encrypted."
+            ),
+            InvalidVerityHex => write!(
+                f,
+                "The root hash and the salt of the hash tree must be hexadecimal strings."
+            ),
             OpenBlockDevice(ref e) => write!(
                 f,
                 "Cannot open block device. Invalid permission/path: {}",
                 e
             ),
+            OpenHashTree(ref e) => write!(f, "Cannot open the hash tree: {}", e),
+            ReadEncryptionKey(ref e) => write!(f, "Cannot read the encryption key: {}", e),
+            ReservedDriveId => write!(
+                f,
+                "The drive ID {} is reserved to the cloud-init seed drive.",
+                SEED_DRIVE_ID
+            ),
             RootBlockDeviceAlreadyAdded => write!(f, "A root block device already exists!"),
+            SnapshotBlockDevice(ref e) => {
+                write!(f, "Cannot copy the backing file of the block device: {}", e)
+            }
+            UpdateVerifiedDrive => write!(
+                f,
+                "The disk image of a drive with a hash tree cannot be replaced."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DriveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use self::DriveError::*;
+        match self {
+            CreateBlockDevice(e)
+            | CreateRateLimiter(e)
+            | FlushBlockDevice(e)
+            | GuestFreeze(e)
+            | InvalidBlockDevicePath(e)
+            | OpenBlockDevice(e)
+            | OpenHashTree(e)
+            | ReadEncryptionKey(e)
+            | SnapshotBlockDevice(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// The longest serial number of a drive, which the guest reads in a 20 bytes buffer.
+pub const MAX_SERIAL_LEN: usize = 20;
+
+/// Where to read the raw AES-XTS key encrypting the sectors of a drive from. The key has 32
+/// bytes for AES-128-XTS, or 64 bytes for AES-256-XTS.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlockEncryptionConfig {
+    /// Path of the file holding the key.
+    pub key_path: Option<String>,
+    /// File descriptor, inherited by Firecracker, from which the key is read. Firecracker reads
+    /// it from its current offset and doesn't close it.
+    pub key_fd: Option<i32>,
+}
+
+impl BlockEncryptionConfig {
+    /// Reads the key and creates the cipher encrypting the sectors of the drive.
+    pub fn cipher(&self) -> Result<DiskCipher> {
+        let file = match (&self.key_path, self.key_fd) {
+            (Some(key_path), None) => {
+                File::open(key_path).map_err(DriveError::ReadEncryptionKey)?
+            }
+            (None, Some(key_fd)) => {
+                // Duplicate the descriptor, so that the file can be dropped without closing it.
+                // Safe because we check the return value.
+                let fd = unsafe { libc::fcntl(key_fd, libc::F_DUPFD_CLOEXEC, 0) };
+                if fd < 0 {
+                    return Err(DriveError::ReadEncryptionKey(io::Error::last_os_error()));
+                }
+                // Safe because we own the duplicated descriptor.
+                unsafe { File::from_raw_fd(fd) }
+            }
+            _ => return Err(DriveError::InvalidEncryptionConfig),
+        };
+
+        // Read one byte more than the longest key, to reject longer files.
+        let mut key = Vec::with_capacity(AES_256_XTS_KEY_LEN + 1);
+        let result = file
+            .take(AES_256_XTS_KEY_LEN as u64 + 1)
+            .read_to_end(&mut key)
+            .map_err(DriveError::ReadEncryptionKey)
+            .and_then(|len| DiskCipher::new(&key).ok_or(DriveError::InvalidEncryptionKey(len)));
+        // Don't leave the key lying around in memory once the cipher has been set up.
+        for byte in key.iter_mut() {
+            // Safe because `byte` is a valid reference.
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+        result
+    }
+}
+
+/// The hash tree, as built by `veritysetup format` with its default SHA-256 hash and 4 KiB
+/// blocks, against which the reads from a read-only drive are checked.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlockVerityConfig {
+    /// Path of the file holding the hash tree.
+    pub hash_path: String,
+    /// Offset of the hash tree in the file, in bytes. The hash tree of `veritysetup format`
+    /// starts after a 4096 bytes superblock.
+    #[serde(default)]
+    pub hash_offset: u64,
+    /// The trusted root hash of the tree, in hexadecimal.
+    pub root_hash: String,
+    /// The salt of the tree, in hexadecimal.
+    #[serde(default)]
+    pub salt: String,
+}
+
+// Decodes a hexadecimal string.
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+impl BlockVerityConfig {
+    /// Opens the hash tree covering `data_size` bytes of data.
+    pub fn hash_tree(&self, data_size: u64) -> Result<HashTree> {
+        let root_hash = decode_hex(&self.root_hash).ok_or(DriveError::InvalidVerityHex)?;
+        let salt = decode_hex(&self.salt).ok_or(DriveError::InvalidVerityHex)?;
+        let hash_file = File::open(&self.hash_path).map_err(DriveError::OpenHashTree)?;
+        HashTree::new(hash_file, self.hash_offset, data_size, root_hash, salt)
+            .map_err(DriveError::InvalidHashTree)
+    }
+}
+
+/// What a drive does when a write fails because the host ran out of space for its backing
+/// file, e.g. a sparse file on a full volume.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnospcPolicy {
+    /// The request fails with an I/O error, like any other failure.
+    Error,
+    /// The drive is paused until it is resumed through the API, and the request is retried
+    /// then.
+    PauseDrive,
+    /// Firecracker exits with the `DriveOutOfSpace` exit code.
+    StopVm,
+}
+
+impl Default for EnospcPolicy {
+    fn default() -> Self {
+        EnospcPolicy::Error
+    }
+}
+
+impl From<EnospcPolicy> for devices::virtio::EnospcPolicy {
+    fn from(policy: EnospcPolicy) -> Self {
+        match policy {
+            EnospcPolicy::Error => devices::virtio::EnospcPolicy::Error,
+            EnospcPolicy::PauseDrive => devices::virtio::EnospcPolicy::PauseDrive,
+            EnospcPolicy::StopVm => devices::virtio::EnospcPolicy::StopVm,
+        }
+    }
+}
+
+impl From<devices::virtio::EnospcPolicy> for EnospcPolicy {
+    fn from(policy: devices::virtio::EnospcPolicy) -> Self {
+        match policy {
+            devices::virtio::EnospcPolicy::Error => EnospcPolicy::Error,
+            devices::virtio::EnospcPolicy::PauseDrive => EnospcPolicy::PauseDrive,
+            devices::virtio::EnospcPolicy::StopVm => EnospcPolicy::StopVm,
         }
     }
 }
 
+/// How the guest is expected to access a drive, hinted to the host page cache.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageCacheAccess {
+    /// No particular pattern, the host reads ahead as usual.
+    Normal,
+    /// Sequential accesses, e.g. to a drive streamed through, for which the host reads further
+    /// ahead.
+    Sequential,
+    /// Random accesses, e.g. to a database, for which the host doesn't read ahead.
+    Random,
+}
+
+impl Default for PageCacheAccess {
+    fn default() -> Self {
+        PageCacheAccess::Normal
+    }
+}
+
+impl From<PageCacheAccess> for devices::virtio::PageCacheAccess {
+    fn from(access: PageCacheAccess) -> Self {
+        match access {
+            PageCacheAccess::Normal => devices::virtio::PageCacheAccess::Normal,
+            PageCacheAccess::Sequential => devices::virtio::PageCacheAccess::Sequential,
+            PageCacheAccess::Random => devices::virtio::PageCacheAccess::Random,
+        }
+    }
+}
+
+impl From<devices::virtio::PageCacheAccess> for PageCacheAccess {
+    fn from(access: devices::virtio::PageCacheAccess) -> Self {
+        match access {
+            devices::virtio::PageCacheAccess::Normal => PageCacheAccess::Normal,
+            devices::virtio::PageCacheAccess::Sequential => PageCacheAccess::Sequential,
+            devices::virtio::PageCacheAccess::Random => PageCacheAccess::Random,
+        }
+    }
+}
+
+/// A range of the backing file of a drive.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlockRange {
+    /// Offset of the range, in bytes.
+    pub offset: u64,
+    /// Length of the range, in bytes. 0 extends the range to the end of the file.
+    pub len: u64,
+}
+
+/// Hints to the host page cache about the backing file of a drive, which keep the page cache
+/// used by the drives of many microVMs in check.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlockPageCacheConfig {
+    /// How the guest is expected to access the drive.
+    #[serde(default)]
+    pub access: PageCacheAccess,
+    /// If set to true, the pages written by the guest are dropped from the host page cache
+    /// after each write.
+    #[serde(default)]
+    pub drop_after_write: bool,
+    /// Ranges of the backing file read into the host page cache when the microVM starts, e.g.
+    /// the ones the guest reads at boot.
+    #[serde(default)]
+    pub prefetch: Vec<BlockRange>,
+}
+
 /// Use this structure to set up the Block Device before booting the kernel.
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct BlockDeviceConfig {
     /// Unique identifier of the drive.
-    pub drive_id: String,
-    /// Path of the drive.
+    pub drive_id: Identifier,
+    /// Path of the drive, or `fd://<fd>` naming a file descriptor opened on it.
     pub path_on_host: String,
     /// If set to true, it makes the current device the root block device.
     /// Setting this flag to true will mount the block device in the
@@ -76,8 +418,87 @@ pub struct BlockDeviceConfig {
     /// If set to true, the drive is opened in read-only mode. Otherwise, the
     /// drive is opened as read-write.
     pub is_read_only: bool,
+    /// If set to true, the drive is opened with `O_NOATIME`, so that guest reads don't update
+    /// the access time of the host file.
+    #[serde(default)]
+    pub no_atime: bool,
     /// Rate Limiter for I/O operations.
     pub rate_limiter: Option<RateLimiterConfig>,
+    /// Encrypts the sectors of the drive on the host, transparently to the guest.
+    pub encryption: Option<BlockEncryptionConfig>,
+    /// Checks the reads from a read-only drive against a hash tree.
+    pub verity: Option<BlockVerityConfig>,
+    /// Weight, from 1 to 1000, of the drive in sharing the throughput of its host device with
+    /// the other weighted drives it backs. Drives without a weight are not scheduled.
+    pub io_weight: Option<u32>,
+    /// Serial number of the drive, read by the guest, which names it by in `/dev/disk/by-id`.
+    /// At most 20 printable ASCII characters. The drive ID, truncated to 20 characters, if not
+    /// set.
+    pub serial: Option<String>,
+    /// Size, in bytes, of the physical blocks the guest should align its I/O on: a power of two
+    /// from 512 to 65536. The guest is given no topology if not set.
+    pub physical_block_size: Option<u32>,
+    /// What the drive does when the host runs out of space for its backing file.
+    #[serde(default)]
+    pub on_enospc: EnospcPolicy,
+    /// Hints to the host page cache about the backing file.
+    pub page_cache: Option<BlockPageCacheConfig>,
+}
+
+fn default_guest_freeze_timeout_ms() -> u64 {
+    5000
+}
+
+/// How to reach the guest agent freezing the filesystems of the guest while the backing file of
+/// a drive is replaced.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GuestFreezeConfig {
+    /// The guest vsock port the agent listens on.
+    pub vsock_port: u32,
+    /// The ID of the vsock device to reach the agent through, the first vsock device if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vsock_id: Option<String>,
+    /// How long to wait for the agent to freeze the filesystems.
+    #[serde(default = "default_guest_freeze_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+/// The data fed into a drive update request. Only the provided properties are updated.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BlockDeviceUpdateConfig {
+    /// The drive ID, as provided by the user at drive creation time.
+    pub drive_id: String,
+    /// New path of the host file backing the drive. The guest is notified of the new size.
+    pub path_on_host: Option<String>,
+    /// New rate limiter config. Only the provided token buckets are updated, and they start off
+    /// full, with their whole one time burst.
+    pub rate_limiter: Option<RateLimiterConfig>,
+    /// The guest agent to freeze the filesystems of the guest through, before the backing file
+    /// is replaced. The backing file is then replaced in the background.
+    #[serde(default)]
+    pub guest_freeze: Option<GuestFreezeConfig>,
+}
+
+/// The data fed into a request pausing or resuming a drive, after microVM start.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BlockDeviceStateConfig {
+    /// The drive ID, as provided by the user at drive creation time.
+    pub drive_id: String,
+    /// Whether the requests of the guest to the drive are held back.
+    pub paused: bool,
+}
+
+/// The data fed into a request copying the backing file of a drive, after microVM start.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BlockDeviceSnapshotParams {
+    /// The drive ID, as provided by the user at drive creation time.
+    pub drive_id: String,
+    /// Path of the copy, which must not exist.
+    pub dest_path: String,
 }
 
 /// Wrapper for the collection that holds all the Block Devices
@@ -89,6 +510,10 @@ pub struct BlockBuilder {
     // specified in order to avoid bugs in case of switching from partuuid boot
     // scenarios to /dev/vda boot type.
     pub list: VecDeque<Arc<Mutex<Block>>>,
+    // The configurations the block devices were created from, by drive ID.
+    configs: HashMap<String, BlockDeviceConfig>,
+    // The disk images opened on behalf of Firecracker.
+    preopened_fds: Arc<PreopenedFds>,
 }
 
 impl BlockBuilder {
@@ -96,6 +521,48 @@ impl BlockBuilder {
     pub fn new() -> Self {
         Self {
             list: VecDeque::<Arc<Mutex<Block>>>::new(),
+            configs: HashMap::new(),
+            preopened_fds: Arc::new(PreopenedFds::default()),
+        }
+    }
+
+    /// Backs the block devices inserted from now on with the disk images in `preopened_fds`,
+    /// instead of opening their `path_on_host`.
+    pub fn set_preopened_fds(&mut self, preopened_fds: Arc<PreopenedFds>) {
+        self.preopened_fds = preopened_fds;
+    }
+
+    /// Returns the configuration the block device `drive_id` was created from.
+    pub fn config(&self, drive_id: &str) -> Option<&BlockDeviceConfig> {
+        self.configs.get(drive_id)
+    }
+
+    /// Returns the configurations of the block devices, in the order of the list, with the
+    /// backing files the devices use now.
+    pub fn configs(&self) -> Vec<BlockDeviceConfig> {
+        self.list
+            .iter()
+            .filter_map(|block| {
+                let block = block.lock().unwrap();
+                // The backing file may have been replaced after boot.
+                self.configs
+                    .get(block.id())
+                    .map(|config| BlockDeviceConfig {
+                        path_on_host: block.disk_image_path().to_string(),
+                        ..config.clone()
+                    })
+            })
+            .collect()
+    }
+
+    /// Merges the token buckets of `rate_limiter`, set on the block device `drive_id` after
+    /// boot, into its configuration.
+    pub fn update_rate_limiter(&mut self, drive_id: &str, rate_limiter: &RateLimiterConfig) {
+        if let Some(config) = self.configs.get_mut(drive_id) {
+            config
+                .rate_limiter
+                .get_or_insert_with(RateLimiterConfig::default)
+                .update(rate_limiter);
         }
     }
 
@@ -130,7 +597,14 @@ impl BlockBuilder {
             return Err(DriveError::RootBlockDeviceAlreadyAdded);
         }
 
-        let block_dev = Arc::new(Mutex::new(Self::create_block(config)?));
+        let drive_id = String::from(config.drive_id.as_str());
+        let disk_image = self
+            .preopened_fds
+            .disk_image(&config.path_on_host)
+            .transpose()
+            .map_err(DriveError::OpenBlockDevice)?;
+        let block_dev = Arc::new(Mutex::new(Self::create_block(config.clone(), disk_image)?));
+        self.configs.insert(drive_id, config);
         // If the id of the drive already exists in the list, the operation is update/overwrite.
         match position {
             // New block device.
@@ -155,35 +629,158 @@ impl BlockBuilder {
         Ok(())
     }
 
-    /// Creates a Block device from a BlockDeviceConfig.
-    pub fn create_block(block_device_config: BlockDeviceConfig) -> Result<Block> {
-        // check if the path exists
+    /// Removes the block device `drive_id` from the list, keeping the order of the others.
+    pub fn remove(&mut self, drive_id: &str) -> Result<()> {
+        let index = self
+            .get_index_of_drive_id(drive_id)
+            .ok_or(DriveError::InvalidBlockDeviceID)?;
+        self.list.remove(index);
+        self.configs.remove(drive_id);
+        Ok(())
+    }
+
+    /// Checks the parameters of the block device `config` describes, without opening its disk
+    /// image nor reading its encryption key.
+    pub fn validate(config: &BlockDeviceConfig) -> Result<()> {
+        if config.drive_id == SEED_DRIVE_ID {
+            return Err(DriveError::ReservedDriveId);
+        }
+
+        if let Some(weight) = config.io_weight {
+            if weight < MIN_IO_WEIGHT || weight > MAX_IO_WEIGHT {
+                return Err(DriveError::InvalidIoWeight(weight));
+            }
+        }
+
+        if let Some(size) = config.physical_block_size {
+            if !size.is_power_of_two()
+                || u64::from(size) < SECTOR_SIZE
+                || size > MAX_PHYSICAL_BLOCK_SIZE
+            {
+                return Err(DriveError::InvalidPhysicalBlockSize(size));
+            }
+        }
+
+        if let Some(ref page_cache) = config.page_cache {
+            for range in page_cache.prefetch.iter() {
+                if range
+                    .offset
+                    .checked_add(range.len)
+                    .map_or(true, |end| end > i64::max_value() as u64)
+                {
+                    return Err(DriveError::InvalidPrefetchRange(range.offset, range.len));
+                }
+            }
+        }
+
+        if let Some(ref serial) = config.serial {
+            if serial.len() > MAX_SERIAL_LEN
+                || !serial.bytes().all(|b| b.is_ascii_graphic() || b == b' ')
+            {
+                return Err(DriveError::InvalidSerial(serial.clone()));
+            }
+        }
+
+        if let Some(ref encryption) = config.encryption {
+            if encryption.key_path.is_some() == encryption.key_fd.is_some() {
+                return Err(DriveError::InvalidEncryptionConfig);
+            }
+        }
+
+        if config.verity.is_some() && (!config.is_read_only || config.encryption.is_some()) {
+            return Err(DriveError::InvalidVerityConfig);
+        }
+        Ok(())
+    }
+
+    /// Creates a Block device from a BlockDeviceConfig, backed by `disk_image` if it is the disk
+    /// image already opened, or by the file `path_on_host` names otherwise: a path, or a
+    /// `fd://<fd>` URI.
+    pub fn create_block(
+        block_device_config: BlockDeviceConfig,
+        disk_image: Option<File>,
+    ) -> Result<Block> {
+        let disk_image = match disk_image {
+            Some(disk_image) => Some(disk_image),
+            None => fd_uri::dup(&block_device_config.path_on_host)
+                .transpose()
+                .map_err(DriveError::InvalidBlockDevicePath)?,
+        };
+        // Check that the path can be resolved, keeping the reason it cannot, e.g. it doesn't
+        // exist or it isn't accessible.
         let path_on_host = PathBuf::from(&block_device_config.path_on_host);
-        if !path_on_host.exists() {
-            return Err(DriveError::InvalidBlockDevicePath);
+        if disk_image.is_none() {
+            path_on_host
+                .metadata()
+                .map_err(DriveError::InvalidBlockDevicePath)?;
         }
 
+        Self::validate(&block_device_config)?;
+
         let rate_limiter = block_device_config
             .rate_limiter
             .map(super::RateLimiterConfig::try_into)
             .transpose()
             .map_err(DriveError::CreateRateLimiter)?;
 
+        let serial = block_device_config
+            .serial
+            .clone()
+            .unwrap_or_else(|| block_device_config.drive_id.to_string());
+
+        let cipher = block_device_config
+            .encryption
+            .as_ref()
+            .map(BlockEncryptionConfig::cipher)
+            .transpose()?;
+
+        let hash_tree = match block_device_config.verity {
+            Some(ref verity) => {
+                let data_size = match disk_image {
+                    Some(ref file) => (&*file).seek(SeekFrom::End(0)),
+                    None => {
+                        File::open(&path_on_host).and_then(|mut file| file.seek(SeekFrom::End(0)))
+                    }
+                }
+                .map_err(DriveError::OpenBlockDevice)?;
+                Some(verity.hash_tree(data_size)?)
+            }
+            None => None,
+        };
+
         // Create and return the Block device
-        devices::virtio::Block::new(
-            block_device_config.drive_id,
+        let mut block = devices::virtio::Block::new(
+            block_device_config.drive_id.into(),
             block_device_config.partuuid,
             block_device_config.path_on_host,
+            disk_image,
             block_device_config.is_read_only,
+            block_device_config.no_atime,
             block_device_config.is_root_device,
             rate_limiter.unwrap_or_default(),
+            cipher,
+            hash_tree,
         )
-        .map_err(DriveError::CreateBlockDevice)
+        .map_err(DriveError::CreateBlockDevice)?;
+        block
+            .set_io_weight(block_device_config.io_weight)
+            .map_err(DriveError::CreateBlockDevice)?;
+        block.set_serial(Some(serial));
+        block.set_physical_block_size(block_device_config.physical_block_size);
+        block.set_enospc_policy(block_device_config.on_enospc.into());
+        if let Some(page_cache) = block_device_config.page_cache {
+            block
+                .set_page_cache_access(page_cache.access.into())
+                .map_err(DriveError::CreateBlockDevice)?;
+            block.set_drop_written_pages(page_cache.drop_after_write);
+        }
+        Ok(block)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::convert::TryFrom;
 
     use super::*;
     use utils::tempfile::TempFile;
@@ -194,21 +791,6 @@ mod tests {
         }
     }
 
-    // This implementation is used only in tests.
-    // We cannot directly derive clone because RateLimiter does not implement clone.
-    impl Clone for BlockDeviceConfig {
-        fn clone(&self) -> Self {
-            BlockDeviceConfig {
-                path_on_host: self.path_on_host.clone(),
-                is_root_device: self.is_root_device,
-                partuuid: self.partuuid.clone(),
-                is_read_only: self.is_read_only,
-                drive_id: self.drive_id.clone(),
-                rate_limiter: None,
-            }
-        }
-    }
-
     #[test]
     fn test_create_block_devs() {
         let block_devs = BlockBuilder::new();
@@ -219,14 +801,22 @@ mod tests {
     fn test_add_non_root_block_device() {
         let dummy_file = TempFile::new().unwrap();
         let dummy_path = dummy_file.as_path().to_str().unwrap().to_string();
-        let dummy_id = String::from("1");
+        let dummy_id = Identifier::try_from("1").unwrap();
         let dummy_block_device = BlockDeviceConfig {
             path_on_host: dummy_path.clone(),
             is_root_device: false,
             partuuid: None,
             is_read_only: false,
+            no_atime: false,
             drive_id: dummy_id.clone(),
             rate_limiter: None,
+            encryption: None,
+            verity: None,
+            io_weight: None,
+            serial: None,
+            physical_block_size: None,
+            on_enospc: EnospcPolicy::Error,
+            page_cache: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -254,8 +844,16 @@ mod tests {
             is_root_device: true,
             partuuid: None,
             is_read_only: true,
-            drive_id: String::from("1"),
+            no_atime: false,
+            drive_id: Identifier::try_from("1").unwrap(),
             rate_limiter: None,
+            encryption: None,
+            verity: None,
+            io_weight: None,
+            serial: None,
+            physical_block_size: None,
+            on_enospc: EnospcPolicy::Error,
+            page_cache: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -280,8 +878,16 @@ mod tests {
             is_root_device: true,
             partuuid: None,
             is_read_only: false,
-            drive_id: String::from("1"),
+            no_atime: false,
+            drive_id: Identifier::try_from("1").unwrap(),
             rate_limiter: None,
+            encryption: None,
+            verity: None,
+            io_weight: None,
+            serial: None,
+            physical_block_size: None,
+            on_enospc: EnospcPolicy::Error,
+            page_cache: None,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
@@ -291,8 +897,16 @@ mod tests {
             is_root_device: true,
             partuuid: None,
             is_read_only: false,
-            drive_id: String::from("2"),
+            no_atime: false,
+            drive_id: Identifier::try_from("2").unwrap(),
             rate_limiter: None,
+            encryption: None,
+            verity: None,
+            io_weight: None,
+            serial: None,
+            physical_block_size: None,
+            on_enospc: EnospcPolicy::Error,
+            page_cache: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -313,8 +927,16 @@ mod tests {
             is_root_device: true,
             partuuid: None,
             is_read_only: false,
-            drive_id: String::from("1"),
+            no_atime: false,
+            drive_id: Identifier::try_from("1").unwrap(),
             rate_limiter: None,
+            encryption: None,
+            verity: None,
+            io_weight: None,
+            serial: None,
+            physical_block_size: None,
+            on_enospc: EnospcPolicy::Error,
+            page_cache: None,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
@@ -324,8 +946,16 @@ mod tests {
             is_root_device: false,
             partuuid: None,
             is_read_only: false,
-            drive_id: String::from("2"),
+            no_atime: false,
+            drive_id: Identifier::try_from("2").unwrap(),
             rate_limiter: None,
+            encryption: None,
+            verity: None,
+            io_weight: None,
+            serial: None,
+            physical_block_size: None,
+            on_enospc: EnospcPolicy::Error,
+            page_cache: None,
         };
 
         let dummy_file_3 = TempFile::new().unwrap();
@@ -335,8 +965,16 @@ mod tests {
             is_root_device: false,
             partuuid: None,
             is_read_only: false,
-            drive_id: String::from("3"),
+            no_atime: false,
+            drive_id: Identifier::try_from("3").unwrap(),
             rate_limiter: None,
+            encryption: None,
+            verity: None,
+            io_weight: None,
+            serial: None,
+            physical_block_size: None,
+            on_enospc: EnospcPolicy::Error,
+            page_cache: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -371,8 +1009,16 @@ mod tests {
             is_root_device: true,
             partuuid: None,
             is_read_only: false,
-            drive_id: String::from("1"),
+            no_atime: false,
+            drive_id: Identifier::try_from("1").unwrap(),
             rate_limiter: None,
+            encryption: None,
+            verity: None,
+            io_weight: None,
+            serial: None,
+            physical_block_size: None,
+            on_enospc: EnospcPolicy::Error,
+            page_cache: None,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
@@ -382,8 +1028,16 @@ mod tests {
             is_root_device: false,
             partuuid: None,
             is_read_only: false,
-            drive_id: String::from("2"),
+            no_atime: false,
+            drive_id: Identifier::try_from("2").unwrap(),
             rate_limiter: None,
+            encryption: None,
+            verity: None,
+            io_weight: None,
+            serial: None,
+            physical_block_size: None,
+            on_enospc: EnospcPolicy::Error,
+            page_cache: None,
         };
 
         let dummy_file_3 = TempFile::new().unwrap();
@@ -393,8 +1047,16 @@ mod tests {
             is_root_device: false,
             partuuid: None,
             is_read_only: false,
-            drive_id: String::from("3"),
+            no_atime: false,
+            drive_id: Identifier::try_from("3").unwrap(),
             rate_limiter: None,
+            encryption: None,
+            verity: None,
+            io_weight: None,
+            serial: None,
+            physical_block_size: None,
+            on_enospc: EnospcPolicy::Error,
+            page_cache: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -430,8 +1092,16 @@ mod tests {
             is_root_device: true,
             partuuid: None,
             is_read_only: false,
-            drive_id: String::from("1"),
+            no_atime: false,
+            drive_id: Identifier::try_from("1").unwrap(),
             rate_limiter: None,
+            encryption: None,
+            verity: None,
+            io_weight: None,
+            serial: None,
+            physical_block_size: None,
+            on_enospc: EnospcPolicy::Error,
+            page_cache: None,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
@@ -441,8 +1111,16 @@ mod tests {
             is_root_device: false,
             partuuid: None,
             is_read_only: false,
-            drive_id: String::from("2"),
+            no_atime: false,
+            drive_id: Identifier::try_from("2").unwrap(),
             rate_limiter: None,
+            encryption: None,
+            verity: None,
+            io_weight: None,
+            serial: None,
+            physical_block_size: None,
+            on_enospc: EnospcPolicy::Error,
+            page_cache: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -483,8 +1161,16 @@ mod tests {
         dummy_block_device_2.path_on_host = dummy_path_3;
         assert_eq!(
             block_devs.insert(dummy_block_device_2.clone()),
-            Err(DriveError::InvalidBlockDevicePath)
+            Err(DriveError::InvalidBlockDevicePath(
+                io::Error::from_raw_os_error(libc::ENOENT)
+            ))
         );
+        // The reason the path is invalid can be told apart.
+        let err = block_devs.insert(dummy_block_device_2.clone()).unwrap_err();
+        let source = std::error::Error::source(&err)
+            .and_then(|e| e.downcast_ref::<io::Error>())
+            .unwrap();
+        assert_eq!(source.kind(), io::ErrorKind::NotFound);
 
         // Update with 2 root block devices.
         dummy_block_device_2.path_on_host = dummy_path_2.clone();
@@ -499,8 +1185,16 @@ mod tests {
             is_root_device: true,
             partuuid: None,
             is_read_only: false,
-            drive_id: String::from("1"),
+            no_atime: false,
+            drive_id: Identifier::try_from("1").unwrap(),
             rate_limiter: None,
+            encryption: None,
+            verity: None,
+            io_weight: None,
+            serial: None,
+            physical_block_size: None,
+            on_enospc: EnospcPolicy::Error,
+            page_cache: None,
         };
         // Switch roots and add a PARTUUID for the new one.
         let mut root_block_device_old = root_block_device;
@@ -510,8 +1204,16 @@ mod tests {
             is_root_device: true,
             partuuid: Some("0eaa91a0-01".to_string()),
             is_read_only: false,
-            drive_id: String::from("2"),
+            no_atime: false,
+            drive_id: Identifier::try_from("2").unwrap(),
             rate_limiter: None,
+            encryption: None,
+            verity: None,
+            io_weight: None,
+            serial: None,
+            physical_block_size: None,
+            on_enospc: EnospcPolicy::Error,
+            page_cache: None,
         };
         assert!(block_devs.insert(root_block_device_old).is_ok());
         let root_block_id = root_block_device_new.drive_id.clone();
@@ -521,6 +1223,49 @@ mod tests {
         assert_eq!(block_devs.list[0].lock().unwrap().id(), &root_block_id);
     }
 
+    #[test]
+    fn test_configs_after_boot() {
+        let dummy_file = TempFile::new().unwrap();
+        let mut block_config: BlockDeviceConfig = serde_json::from_str(&format!(
+            r#"{{"drive_id": "scratch", "path_on_host": "{}", "is_root_device": false,
+                "is_read_only": false}}"#,
+            dummy_file.as_path().to_str().unwrap()
+        ))
+        .unwrap();
+        let mut block_devs = BlockBuilder::new();
+        block_devs.insert(block_config.clone()).unwrap();
+        assert_eq!(block_devs.configs(), vec![block_config.clone()]);
+
+        // The backing file replaced after boot is the one the configuration holds.
+        let new_file = TempFile::new().unwrap();
+        let new_path = new_file.as_path().to_str().unwrap().to_string();
+        block_devs.list[0]
+            .lock()
+            .unwrap()
+            .update_disk_image(Arc::new(File::open(&new_path).unwrap()), new_path.clone())
+            .unwrap();
+        // So are the token buckets updated after boot.
+        let ops = super::super::TokenBucketConfig {
+            size: 100,
+            one_time_burst: None,
+            refill_time: 1000,
+        };
+        block_devs.update_rate_limiter(
+            "scratch",
+            &RateLimiterConfig {
+                bandwidth: None,
+                ops: Some(ops),
+            },
+        );
+        block_devs.update_rate_limiter("missing", &RateLimiterConfig::default());
+        block_config.path_on_host = new_path;
+        block_config.rate_limiter = Some(RateLimiterConfig {
+            bandwidth: None,
+            ops: Some(ops),
+        });
+        assert_eq!(block_devs.configs(), vec![block_config]);
+    }
+
     #[test]
     fn test_block_config() {
         let dummy_block_file = TempFile::new().unwrap();
@@ -528,12 +1273,20 @@ mod tests {
         let expected_is_read_only = true;
 
         let block_config = BlockDeviceConfig {
-            drive_id: "dummy_drive".to_string(),
+            drive_id: Identifier::try_from("dummy_drive").unwrap(),
             path_on_host: dummy_block_file.as_path().to_str().unwrap().to_string(),
             is_root_device: false,
             partuuid: Some("0eaa91a0-01".to_string()),
             is_read_only: true,
+            no_atime: false,
             rate_limiter: None,
+            encryption: None,
+            verity: None,
+            io_weight: None,
+            serial: None,
+            physical_block_size: None,
+            on_enospc: EnospcPolicy::Error,
+            page_cache: None,
         };
 
         assert_eq!(
@@ -546,4 +1299,443 @@ mod tests {
         );
         assert_eq!(block_config.is_read_only, expected_is_read_only);
     }
+
+    #[test]
+    fn test_no_atime() {
+        let dummy_block_file = TempFile::new().unwrap();
+        let block_config: BlockDeviceConfig = serde_json::from_str(&format!(
+            r#"{{"drive_id": "scratch", "path_on_host": "{}", "is_root_device": false,
+                "is_read_only": true}}"#,
+            dummy_block_file.as_path().to_str().unwrap()
+        ))
+        .unwrap();
+        assert!(!block_config.no_atime);
+
+        let mut block_config = block_config;
+        block_config.no_atime = true;
+        let block = BlockBuilder::create_block(block_config, None).unwrap();
+        assert!(block.no_atime());
+        assert!(block.is_read_only());
+    }
+
+    #[test]
+    fn test_preopened_disk_image() {
+        use std::os::unix::io::IntoRawFd;
+
+        let dummy_block_file = TempFile::new().unwrap();
+        let disk_image_fd = File::open(dummy_block_file.as_path())
+            .unwrap()
+            .into_raw_fd();
+        // The path is only the name of the disk image, it needn't exist in the jail.
+        let disk_image_fds = format!("/jailed/rootfs.ext4={}", disk_image_fd);
+        let preopened_fds =
+            unsafe { PreopenedFds::from_args(None, None, Some(&disk_image_fds)) }.unwrap();
+        let mut block_devs = BlockBuilder::new();
+        block_devs.set_preopened_fds(Arc::new(preopened_fds));
+
+        let mut block_config: BlockDeviceConfig = serde_json::from_str(
+            r#"{"drive_id": "rootfs", "path_on_host": "/jailed/rootfs.ext4",
+                "is_root_device": true, "is_read_only": true}"#,
+        )
+        .unwrap();
+        assert!(block_devs.insert(block_config.clone()).is_ok());
+        assert_eq!(block_devs.list.len(), 1);
+
+        // The disk image was opened read-only, so it can't back a writable drive.
+        block_config.is_read_only = false;
+        assert!(block_devs.insert(block_config.clone()).is_err());
+
+        // The drives without a pre-opened disk image are opened by path.
+        block_config.path_on_host = "/jailed/scratch.ext4".to_string();
+        match block_devs.insert(block_config) {
+            Err(DriveError::InvalidBlockDevicePath(_)) => (),
+            _ => panic!("The drives without a pre-opened disk image are opened by path."),
+        }
+    }
+
+    #[test]
+    fn test_fd_uri() {
+        use std::os::unix::io::AsRawFd;
+
+        let dummy_block_file = TempFile::new().unwrap();
+        fd_uri::record_inherited_fds().unwrap();
+        let mut block_config: BlockDeviceConfig = serde_json::from_str(&format!(
+            r#"{{"drive_id": "scratch", "path_on_host": "fd://{}", "is_root_device": false,
+                "is_read_only": false}}"#,
+            dummy_block_file.as_file().as_raw_fd()
+        ))
+        .unwrap();
+        let block = BlockBuilder::create_block(block_config.clone(), None).unwrap();
+        assert!(!block.is_read_only());
+
+        block_config.path_on_host = "fd://1000000".to_string();
+        match BlockBuilder::create_block(block_config, None) {
+            Err(DriveError::InvalidBlockDevicePath(_)) => (),
+            _ => panic!("A closed file descriptor can't back a drive."),
+        }
+    }
+
+    #[test]
+    fn test_encryption() {
+        use std::io::{Seek, SeekFrom, Write};
+        use std::os::unix::io::AsRawFd;
+
+        let dummy_block_file = TempFile::new().unwrap();
+        let mut block_config: BlockDeviceConfig = serde_json::from_str(&format!(
+            r#"{{"drive_id": "scratch", "path_on_host": "{}", "is_root_device": false,
+                "is_read_only": false, "encryption": {{"key_path": "/dev/null"}}}}"#,
+            dummy_block_file.as_path().to_str().unwrap()
+        ))
+        .unwrap();
+        let encryption = block_config.encryption.clone().unwrap();
+        assert_eq!(encryption.key_path, Some("/dev/null".to_string()));
+        assert_eq!(encryption.key_fd, None);
+        assert_eq!(
+            BlockBuilder::create_block(block_config.clone(), None).err(),
+            Some(DriveError::InvalidEncryptionKey(0))
+        );
+
+        // The key has to come from exactly one source.
+        block_config.encryption = Some(BlockEncryptionConfig {
+            key_path: Some("/dev/null".to_string()),
+            key_fd: Some(0),
+        });
+        assert_eq!(
+            BlockBuilder::create_block(block_config.clone(), None).err(),
+            Some(DriveError::InvalidEncryptionConfig)
+        );
+        block_config.encryption = Some(BlockEncryptionConfig {
+            key_path: None,
+            key_fd: None,
+        });
+        assert_eq!(
+            BlockBuilder::create_block(block_config.clone(), None).err(),
+            Some(DriveError::InvalidEncryptionConfig)
+        );
+
+        block_config.encryption = Some(BlockEncryptionConfig {
+            key_path: None,
+            key_fd: Some(-1),
+        });
+        assert_eq!(
+            BlockBuilder::create_block(block_config.clone(), None).err(),
+            Some(DriveError::ReadEncryptionKey(io::Error::from_raw_os_error(
+                libc::EBADF
+            )))
+        );
+
+        let key_file = TempFile::new().unwrap();
+        key_file.as_file().write_all(&[0x42; 65]).unwrap();
+        block_config.encryption = Some(BlockEncryptionConfig {
+            key_path: Some(key_file.as_path().to_str().unwrap().to_string()),
+            key_fd: None,
+        });
+        assert_eq!(
+            BlockBuilder::create_block(block_config.clone(), None).err(),
+            Some(DriveError::InvalidEncryptionKey(65))
+        );
+        key_file.as_file().set_len(64).unwrap();
+        let block = BlockBuilder::create_block(block_config.clone(), None).unwrap();
+        assert!(block.is_encrypted());
+
+        // The key is read from the current offset of the descriptor, which stays open.
+        let key_file = TempFile::new().unwrap();
+        let mut key_fd = key_file.as_file();
+        key_fd.write_all(&[0x42; 32]).unwrap();
+        key_fd.seek(SeekFrom::Start(0)).unwrap();
+        block_config.encryption = Some(BlockEncryptionConfig {
+            key_path: None,
+            key_fd: Some(key_fd.as_raw_fd()),
+        });
+        let block = BlockBuilder::create_block(block_config.clone(), None).unwrap();
+        assert!(block.is_encrypted());
+        assert_eq!(
+            BlockBuilder::create_block(block_config, None).err(),
+            Some(DriveError::InvalidEncryptionKey(0))
+        );
+
+        assert!(serde_json::from_str::<BlockEncryptionConfig>(r#"{"key": "00"}"#).is_err());
+    }
+
+    #[test]
+    fn test_verity() {
+        use sha2::{Digest, Sha256};
+
+        assert_eq!(decode_hex("00a5FF"), Some(vec![0x00, 0xa5, 0xff]));
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("0"), None);
+        assert_eq!(decode_hex("+1"), None);
+        assert_eq!(decode_hex("zz"), None);
+
+        // The hash tree of a single data block is its salted digest.
+        let data_file = TempFile::new().unwrap();
+        data_file.as_file().set_len(4096).unwrap();
+        let mut hasher = Sha256::new();
+        hasher.update(&[0x5a]);
+        hasher.update(&[0u8; 4096][..]);
+        let root_hash: String = hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let hash_file = TempFile::new().unwrap();
+
+        let mut block_config: BlockDeviceConfig = serde_json::from_str(&format!(
+            r#"{{"drive_id": "rootfs", "path_on_host": "{}", "is_root_device": true,
+                "is_read_only": false, "verity": {{"hash_path": "{}", "root_hash": "{}"}}}}"#,
+            data_file.as_path().to_str().unwrap(),
+            hash_file.as_path().to_str().unwrap(),
+            root_hash
+        ))
+        .unwrap();
+        let mut verity = block_config.verity.clone().unwrap();
+        assert_eq!(verity.hash_offset, 0);
+        assert_eq!(verity.salt, "");
+
+        // The drive has to be read-only.
+        assert_eq!(
+            BlockBuilder::create_block(block_config.clone(), None).err(),
+            Some(DriveError::InvalidVerityConfig)
+        );
+        block_config.is_read_only = true;
+        let key_file = TempFile::new().unwrap();
+        key_file.as_file().set_len(64).unwrap();
+        block_config.encryption = Some(BlockEncryptionConfig {
+            key_path: Some(key_file.as_path().to_str().unwrap().to_string()),
+            key_fd: None,
+        });
+        // Nor can it be encrypted.
+        assert_eq!(
+            BlockBuilder::create_block(block_config.clone(), None).err(),
+            Some(DriveError::InvalidVerityConfig)
+        );
+        block_config.encryption = None;
+
+        verity.salt = "5a".to_string();
+        block_config.verity = Some(verity.clone());
+        let block = BlockBuilder::create_block(block_config.clone(), None).unwrap();
+        assert!(block.is_verified());
+
+        verity.salt = "5".to_string();
+        block_config.verity = Some(verity.clone());
+        assert_eq!(
+            BlockBuilder::create_block(block_config.clone(), None).err(),
+            Some(DriveError::InvalidVerityHex)
+        );
+        verity.salt = "5a".to_string();
+        verity.root_hash = "00".to_string();
+        block_config.verity = Some(verity.clone());
+        assert_eq!(
+            BlockBuilder::create_block(block_config.clone(), None).err(),
+            Some(DriveError::InvalidHashTree(VerityError::InvalidRootHash))
+        );
+        verity.root_hash = root_hash;
+        verity.hash_path = "/does/not/exist".to_string();
+        block_config.verity = Some(verity.clone());
+        assert_eq!(
+            BlockBuilder::create_block(block_config.clone(), None).err(),
+            Some(DriveError::OpenHashTree(io::Error::from_raw_os_error(
+                libc::ENOENT
+            )))
+        );
+    }
+
+    #[test]
+    fn test_io_weight() {
+        let dummy_block_file = TempFile::new().unwrap();
+        let mut block_config: BlockDeviceConfig = serde_json::from_str(&format!(
+            r#"{{"drive_id": "scratch", "path_on_host": "{}", "is_root_device": false,
+                "is_read_only": false, "io_weight": 200}}"#,
+            dummy_block_file.as_path().to_str().unwrap()
+        ))
+        .unwrap();
+        assert_eq!(block_config.io_weight, Some(200));
+        let block = BlockBuilder::create_block(block_config.clone(), None).unwrap();
+        assert_eq!(block.io_weight(), Some(200));
+
+        block_config.io_weight = None;
+        let block = BlockBuilder::create_block(block_config.clone(), None).unwrap();
+        assert_eq!(block.io_weight(), None);
+
+        block_config.io_weight = Some(0);
+        assert_eq!(
+            BlockBuilder::create_block(block_config.clone(), None).err(),
+            Some(DriveError::InvalidIoWeight(0))
+        );
+        block_config.io_weight = Some(1001);
+        assert_eq!(
+            BlockBuilder::create_block(block_config, None).err(),
+            Some(DriveError::InvalidIoWeight(1001))
+        );
+    }
+
+    #[test]
+    fn test_serial_and_topology() {
+        let dummy_block_file = TempFile::new().unwrap();
+        let mut block_config: BlockDeviceConfig = serde_json::from_str(&format!(
+            r#"{{"drive_id": "scratch", "path_on_host": "{}", "is_root_device": false,
+                "is_read_only": false, "serial": "SN-0001",
+                "physical_block_size": 4096}}"#,
+            dummy_block_file.as_path().to_str().unwrap()
+        ))
+        .unwrap();
+        let block = BlockBuilder::create_block(block_config.clone(), None).unwrap();
+        assert_eq!(block.serial(), Some("SN-0001"));
+        assert_eq!(block.physical_block_size(), Some(4096));
+
+        // The serial defaults to the drive ID.
+        block_config.serial = None;
+        block_config.physical_block_size = None;
+        let block = BlockBuilder::create_block(block_config.clone(), None).unwrap();
+        assert_eq!(block.serial(), Some("scratch"));
+        assert_eq!(block.physical_block_size(), None);
+
+        for serial in &["s".repeat(MAX_SERIAL_LEN + 1), "SN\n".to_string()] {
+            block_config.serial = Some(serial.clone());
+            assert_eq!(
+                BlockBuilder::create_block(block_config.clone(), None).err(),
+                Some(DriveError::InvalidSerial(serial.clone()))
+            );
+        }
+        block_config.serial = Some("s".repeat(MAX_SERIAL_LEN));
+        BlockBuilder::create_block(block_config.clone(), None).unwrap();
+
+        block_config.drive_id = SEED_DRIVE_ID.to_string();
+        assert_eq!(
+            BlockBuilder::create_block(block_config.clone(), None).err(),
+            Some(DriveError::ReservedDriveId)
+        );
+        block_config.drive_id = "scratch".to_string();
+
+        for size in &[256, 3072, 128 << 10] {
+            block_config.physical_block_size = Some(*size);
+            assert_eq!(
+                BlockBuilder::create_block(block_config.clone(), None).err(),
+                Some(DriveError::InvalidPhysicalBlockSize(*size))
+            );
+        }
+    }
+
+    #[test]
+    fn test_enospc_policy() {
+        let dummy_block_file = TempFile::new().unwrap();
+        let path = dummy_block_file.as_path().to_str().unwrap();
+        let block_config: BlockDeviceConfig = serde_json::from_str(&format!(
+            r#"{{"drive_id": "scratch", "path_on_host": "{}", "is_root_device": false,
+                "is_read_only": false}}"#,
+            path
+        ))
+        .unwrap();
+        assert_eq!(block_config.on_enospc, EnospcPolicy::Error);
+
+        for (name, policy) in &[
+            ("error", EnospcPolicy::Error),
+            ("pause_drive", EnospcPolicy::PauseDrive),
+            ("stop_vm", EnospcPolicy::StopVm),
+        ] {
+            let block_config: BlockDeviceConfig = serde_json::from_str(&format!(
+                r#"{{"drive_id": "scratch", "path_on_host": "{}", "is_root_device": false,
+                    "is_read_only": false, "on_enospc": "{}"}}"#,
+                path, name
+            ))
+            .unwrap();
+            assert_eq!(block_config.on_enospc, *policy);
+            let block = BlockBuilder::create_block(block_config, None).unwrap();
+            assert_eq!(EnospcPolicy::from(block.enospc_policy()), *policy);
+        }
+
+        assert!(serde_json::from_str::<BlockDeviceConfig>(&format!(
+            r#"{{"drive_id": "scratch", "path_on_host": "{}", "is_root_device": false,
+                "is_read_only": false, "on_enospc": "retry"}}"#,
+            path
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_page_cache() {
+        let dummy_block_file = TempFile::new().unwrap();
+        let path = dummy_block_file.as_path().to_str().unwrap();
+        let mut block_config: BlockDeviceConfig = serde_json::from_str(&format!(
+            r#"{{"drive_id": "rootfs", "path_on_host": "{}", "is_root_device": true,
+                "is_read_only": true, "page_cache": {{"access": "sequential",
+                "prefetch": [{{"offset": 0, "len": 1048576}}]}}}}"#,
+            path
+        ))
+        .unwrap();
+        let page_cache = block_config.page_cache.clone().unwrap();
+        assert_eq!(page_cache.access, PageCacheAccess::Sequential);
+        assert!(!page_cache.drop_after_write);
+        assert_eq!(
+            page_cache.prefetch,
+            vec![BlockRange {
+                offset: 0,
+                len: 1 << 20
+            }]
+        );
+        let block = BlockBuilder::create_block(block_config.clone(), None).unwrap();
+        assert_eq!(
+            PageCacheAccess::from(block.page_cache_access()),
+            PageCacheAccess::Sequential
+        );
+        assert!(!block.drop_written_pages());
+
+        block_config.page_cache = Some(BlockPageCacheConfig {
+            drop_after_write: true,
+            ..Default::default()
+        });
+        let block = BlockBuilder::create_block(block_config.clone(), None).unwrap();
+        assert_eq!(
+            PageCacheAccess::from(block.page_cache_access()),
+            PageCacheAccess::Normal
+        );
+        assert!(block.drop_written_pages());
+
+        // The ranges must end before the largest file offset.
+        block_config.page_cache = Some(BlockPageCacheConfig {
+            prefetch: vec![BlockRange {
+                offset: 1 << 62,
+                len: 1 << 62,
+            }],
+            ..Default::default()
+        });
+        match BlockBuilder::create_block(block_config, None) {
+            Err(DriveError::InvalidPrefetchRange(offset, len)) => {
+                assert_eq!((offset, len), (1 << 62, 1 << 62))
+            }
+            _ => unreachable!(),
+        }
+
+        assert!(serde_json::from_str::<BlockPageCacheConfig>(r#"{"access": "backward"}"#).is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::DriveError::*;
+
+        for err in &[
+            FlushBlockDevice(io::Error::from_raw_os_error(libc::EIO)),
+            GuestFreeze(io::Error::from_raw_os_error(libc::ETIMEDOUT)),
+            GuestFreezeWithoutPath,
+            GuestFreezeVsock(Some("vsock".to_string())),
+            GuestFreezeVsock(None),
+            InvalidGuestFreezeTimeout,
+            InvalidEncryptionConfig,
+            InvalidEncryptionKey(16),
+            InvalidHashTree(VerityError::InvalidRootHash),
+            InvalidIoWeight(0),
+            InvalidPhysicalBlockSize(0),
+            InvalidPrefetchRange(u64::max_value(), 1),
+            InvalidSerial(String::new()),
+            InvalidVerityConfig,
+            InvalidVerityHex,
+            OpenHashTree(io::Error::from_raw_os_error(0)),
+            ReadEncryptionKey(io::Error::from_raw_os_error(0)),
+            ReservedDriveId,
+            SnapshotBlockDevice(io::Error::from_raw_os_error(libc::EEXIST)),
+            UpdateVerifiedDrive,
+        ] {
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
 }
@@ -0,0 +1,99 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use devices::virtio::rng::Error as EntropyError;
+use devices::virtio::Entropy;
+
+type MutexEntropy = Arc<Mutex<Entropy>>;
+
+/// Errors associated with the operations allowed on the entropy device.
+#[derive(Debug)]
+pub enum EntropyConfigError {
+    /// Failed to create the entropy device.
+    CreateEntropyDevice(EntropyError),
+}
+
+impl fmt::Display for EntropyConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::EntropyConfigError::*;
+        match *self {
+            CreateEntropyDevice(ref e) => write!(f, "Cannot create entropy device: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for EntropyConfigError {}
+
+type Result<T> = std::result::Result<T, EntropyConfigError>;
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from entropy device related requests. The device currently has no tunables.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct EntropyDeviceConfig {}
+
+/// A builder for `Entropy` devices from 'EntropyDeviceConfig'.
+#[derive(Default)]
+pub struct EntropyBuilder {
+    inner: Option<MutexEntropy>,
+}
+
+impl EntropyBuilder {
+    /// Creates an empty Entropy Store.
+    pub fn new() -> Self {
+        Self { inner: None }
+    }
+
+    /// Inserts an Entropy device in the store.
+    /// If an entry already exists, it will overwrite it.
+    pub fn set(&mut self, _cfg: EntropyDeviceConfig) -> Result<()> {
+        self.inner = Some(Arc::new(Mutex::new(
+            Entropy::new().map_err(EntropyConfigError::CreateEntropyDevice)?,
+        )));
+        Ok(())
+    }
+
+    /// Provides a reference to the Entropy device if present.
+    pub fn get(&self) -> Option<&MutexEntropy> {
+        self.inner.as_ref()
+    }
+
+    /// Returns the configuration of the Entropy device, if present.
+    pub fn get_config(&self) -> Option<EntropyDeviceConfig> {
+        self.inner.as_ref().map(|_| EntropyDeviceConfig {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entropy_create() {
+        let mut builder = EntropyBuilder::new();
+        assert!(builder.get().is_none());
+        assert!(builder.get_config().is_none());
+
+        builder.set(EntropyDeviceConfig::default()).unwrap();
+        assert!(builder.get().is_some());
+        assert_eq!(builder.get_config().unwrap(), EntropyDeviceConfig {});
+    }
+
+    #[test]
+    fn test_entropy_config_deserialization() {
+        let config: EntropyDeviceConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, EntropyDeviceConfig {});
+        assert!(serde_json::from_str::<EntropyDeviceConfig>(r#"{"foo": 0}"#).is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let err = EntropyConfigError::CreateEntropyDevice(EntropyError::EventFd(
+            std::io::Error::from_raw_os_error(0),
+        ));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use devices::virtio::gpu::Error as GpuError;
+use devices::virtio::Gpu;
+
+type MutexGpu = Arc<Mutex<Gpu>>;
+
+/// The largest width or height of the display.
+pub const MAX_DISPLAY_DIMENSION: u32 = 4096;
+
+/// Errors associated with the operations allowed on the GPU device.
+#[derive(Debug)]
+pub enum GpuConfigError {
+    /// The display dimensions are zero or larger than `MAX_DISPLAY_DIMENSION`.
+    InvalidDisplaySize(u32, u32),
+    /// Failed to remove the socket of the previous GPU device.
+    RemoveSocket(std::io::Error),
+    /// Failed to create the GPU device.
+    CreateGpuDevice(GpuError),
+}
+
+impl fmt::Display for GpuConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::GpuConfigError::*;
+        match *self {
+            InvalidDisplaySize(width, height) => write!(
+                f,
+                "Invalid display size {}x{}, each dimension must be between 1 and {}.",
+                width, height, MAX_DISPLAY_DIMENSION
+            ),
+            RemoveSocket(ref e) => write!(f, "Cannot remove the scanout socket: {}", e),
+            CreateGpuDevice(ref e) => write!(f, "Cannot create GPU device: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for GpuConfigError {}
+
+type Result<T> = std::result::Result<T, GpuConfigError>;
+
+fn default_width() -> u32 {
+    1024
+}
+
+fn default_height() -> u32 {
+    768
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from GPU device related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GpuDeviceConfig {
+    /// Path of the Unix socket serving the contents of the display.
+    pub socket_path: String,
+    /// Width of the display, in pixels.
+    #[serde(default = "default_width")]
+    pub width: u32,
+    /// Height of the display, in pixels.
+    #[serde(default = "default_height")]
+    pub height: u32,
+    /// Lets the guest display resources living in its own memory, without copying them.
+    #[serde(default)]
+    pub blob: bool,
+}
+
+/// A builder for `Gpu` devices from 'GpuDeviceConfig'.
+#[derive(Default)]
+pub struct GpuBuilder {
+    inner: Option<(MutexGpu, GpuDeviceConfig)>,
+}
+
+impl GpuBuilder {
+    /// Creates an empty Gpu Store.
+    pub fn new() -> Self {
+        Self { inner: None }
+    }
+
+    /// Checks the parameters of the Gpu device `cfg` describes, without binding its socket.
+    pub fn validate(cfg: &GpuDeviceConfig) -> Result<()> {
+        for dimension in &[cfg.width, cfg.height] {
+            if *dimension == 0 || *dimension > MAX_DISPLAY_DIMENSION {
+                return Err(GpuConfigError::InvalidDisplaySize(cfg.width, cfg.height));
+            }
+        }
+        Ok(())
+    }
+
+    /// Inserts a Gpu device in the store.
+    /// If an entry already exists, it will overwrite it.
+    pub fn set(&mut self, cfg: GpuDeviceConfig) -> Result<()> {
+        Self::validate(&cfg)?;
+        // Make sure to drop the old one and remove its socket before creating a new one.
+        if let Some((_, old_cfg)) = self.inner.take() {
+            std::fs::remove_file(old_cfg.socket_path).map_err(GpuConfigError::RemoveSocket)?;
+        }
+        let gpu = Gpu::new(cfg.width, cfg.height, cfg.blob, &cfg.socket_path)
+            .map_err(GpuConfigError::CreateGpuDevice)?;
+        self.inner = Some((Arc::new(Mutex::new(gpu)), cfg));
+        Ok(())
+    }
+
+    /// Provides a reference to the Gpu device if present.
+    pub fn get(&self) -> Option<&MutexGpu> {
+        self.inner.as_ref().map(|(gpu, _)| gpu)
+    }
+
+    /// Returns the configuration of the Gpu device, if present.
+    pub fn get_config(&self) -> Option<GpuDeviceConfig> {
+        self.inner.as_ref().map(|(_, cfg)| cfg.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tempfile::TempFile;
+
+    fn socket_path() -> String {
+        let mut socket = TempFile::new().unwrap();
+        // Remove the file so the path can be used by the socket.
+        socket.remove().unwrap();
+        String::from(socket.as_path().to_str().unwrap())
+    }
+
+    fn default_config(socket_path: &str) -> GpuDeviceConfig {
+        GpuDeviceConfig {
+            socket_path: socket_path.to_string(),
+            width: default_width(),
+            height: default_height(),
+            blob: false,
+        }
+    }
+
+    #[test]
+    fn test_gpu_create() {
+        let mut builder = GpuBuilder::new();
+        assert!(builder.get().is_none());
+        assert!(builder.get_config().is_none());
+
+        let path = socket_path();
+        builder.set(default_config(&path)).unwrap();
+        assert!(builder.get().is_some());
+        assert_eq!(builder.get_config().unwrap(), default_config(&path));
+
+        // Overwriting the device frees its socket.
+        let mut config = default_config(&path);
+        config.blob = true;
+        builder.set(config.clone()).unwrap();
+        assert_eq!(builder.get_config().unwrap(), config);
+        std::fs::remove_file(&path).unwrap();
+
+        // The display size is bounded.
+        let mut builder = GpuBuilder::new();
+        for (width, height) in &[(0, 768), (1024, MAX_DISPLAY_DIMENSION + 1)] {
+            let mut config = default_config(&path);
+            config.width = *width;
+            config.height = *height;
+            match builder.set(config) {
+                Err(GpuConfigError::InvalidDisplaySize(w, h)) => {
+                    assert_eq!((w, h), (*width, *height))
+                }
+                _ => panic!("Invalid display size."),
+            }
+        }
+        assert!(builder.get().is_none());
+    }
+
+    #[test]
+    fn test_gpu_config_deserialization() {
+        let config: GpuDeviceConfig =
+            serde_json::from_str(r#"{"socket_path": "/tmp/gpu.sock"}"#).unwrap();
+        assert_eq!(config, default_config("/tmp/gpu.sock"));
+        let config: GpuDeviceConfig = serde_json::from_str(
+            r#"{"socket_path": "/tmp/gpu.sock", "width": 640, "height": 480, "blob": true}"#,
+        )
+        .unwrap();
+        assert_eq!((config.width, config.height, config.blob), (640, 480, true));
+        assert!(serde_json::from_str::<GpuDeviceConfig>(r#"{"width": 640}"#).is_err());
+        assert!(serde_json::from_str::<GpuDeviceConfig>(
+            r#"{"socket_path": "/tmp/gpu.sock", "foo": 0}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::GpuConfigError::*;
+
+        let err = InvalidDisplaySize(0, 0);
+        let _ = format!("{}{:?}", err, err);
+        let err = RemoveSocket(std::io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = CreateGpuDevice(GpuError::ScanoutSocket(std::io::Error::from_raw_os_error(
+            0,
+        )));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
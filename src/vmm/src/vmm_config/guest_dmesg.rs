@@ -0,0 +1,17 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+/// The addresses of the kernel symbols locating the log buffer of the guest, in hexadecimal as
+/// listed in the `System.map` of the guest kernel, e.g. `ffffffff82a3c5e8`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct KernelLogSymbols {
+    /// Address of `log_buf`, the pointer to the log buffer.
+    pub log_buf: String,
+    /// Address of `log_buf_len`, the size of the log buffer.
+    pub log_buf_len: String,
+    /// Address of `log_first_idx`, the offset of the oldest record in the log buffer.
+    pub log_first_idx: String,
+    /// Address of `log_next_idx`, the offset of the next record in the log buffer.
+    pub log_next_idx: String,
+}
@@ -0,0 +1,140 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use devices::virtio::input::Error as InputError;
+use devices::virtio::{Input, VirtioInputEvent};
+
+type MutexInput = Arc<Mutex<Input>>;
+
+/// Errors associated with the operations allowed on the input device.
+#[derive(Debug)]
+pub enum InputConfigError {
+    /// Failed to create the input device.
+    CreateInputDevice(InputError),
+    /// The input device was not found.
+    DeviceNotFound,
+    /// Failed to send the events to the guest.
+    SendEvents(InputError),
+}
+
+impl fmt::Display for InputConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::InputConfigError::*;
+        match *self {
+            CreateInputDevice(ref e) => write!(f, "Cannot create input device: {:?}", e),
+            DeviceNotFound => write!(f, "No input device found."),
+            SendEvents(ref e) => write!(f, "Cannot send the input events: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for InputConfigError {}
+
+type Result<T> = std::result::Result<T, InputConfigError>;
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from input device related requests. The device currently has no tunables.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputDeviceConfig {}
+
+/// An evdev event to inject through the input device, with the type and code values of
+/// `include/uapi/linux/input-event-codes.h`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputEvent {
+    /// The event type, e.g. 1 (`EV_KEY`) or 2 (`EV_REL`).
+    #[serde(rename = "type")]
+    pub event_type: u16,
+    /// The event code, e.g. the key or the axis.
+    pub code: u16,
+    /// The event value, e.g. 1 for a key press or the motion along an axis.
+    pub value: i32,
+}
+
+impl From<InputEvent> for VirtioInputEvent {
+    fn from(event: InputEvent) -> Self {
+        VirtioInputEvent::new(event.event_type, event.code, event.value)
+    }
+}
+
+/// A builder for `Input` devices from 'InputDeviceConfig'.
+#[derive(Default)]
+pub struct InputBuilder {
+    inner: Option<MutexInput>,
+}
+
+impl InputBuilder {
+    /// Creates an empty Input Store.
+    pub fn new() -> Self {
+        Self { inner: None }
+    }
+
+    /// Inserts an Input device in the store.
+    /// If an entry already exists, it will overwrite it.
+    pub fn set(&mut self, _cfg: InputDeviceConfig) -> Result<()> {
+        self.inner = Some(Arc::new(Mutex::new(
+            Input::new().map_err(InputConfigError::CreateInputDevice)?,
+        )));
+        Ok(())
+    }
+
+    /// Provides a reference to the Input device if present.
+    pub fn get(&self) -> Option<&MutexInput> {
+        self.inner.as_ref()
+    }
+
+    /// Returns the configuration of the Input device, if present.
+    pub fn get_config(&self) -> Option<InputDeviceConfig> {
+        self.inner.as_ref().map(|_| InputDeviceConfig {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_create() {
+        let mut builder = InputBuilder::new();
+        assert!(builder.get().is_none());
+        assert!(builder.get_config().is_none());
+
+        builder.set(InputDeviceConfig::default()).unwrap();
+        assert!(builder.get().is_some());
+        assert_eq!(builder.get_config().unwrap(), InputDeviceConfig {});
+    }
+
+    #[test]
+    fn test_input_config_deserialization() {
+        let config: InputDeviceConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, InputDeviceConfig {});
+        assert!(serde_json::from_str::<InputDeviceConfig>(r#"{"foo": 0}"#).is_err());
+
+        let event: InputEvent =
+            serde_json::from_str(r#"{"type": 2, "code": 1, "value": -10}"#).unwrap();
+        assert_eq!(
+            VirtioInputEvent::from(event),
+            VirtioInputEvent::new(2, 1, -10)
+        );
+        assert!(serde_json::from_str::<InputEvent>(r#"{"type": 1, "code": 30}"#).is_err());
+        assert!(
+            serde_json::from_str::<InputEvent>(r#"{"type": 1, "code": -1, "value": 1}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::InputConfigError::*;
+
+        let err = CreateInputDevice(InputError::EventFd(std::io::Error::from_raw_os_error(0)));
+        let _ = format!("{}{:?}", err, err);
+        let err = DeviceNotFound;
+        let _ = format!("{}{:?}", err, err);
+        let err = SendEvents(InputError::NotActivated);
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -1,8 +1,10 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use probes::ProbeStatuses;
+
 /// The strongly typed that contains general information about the microVM.
-#[derive(Debug, Serialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct InstanceInfo {
     /// The ID of the microVM.
     pub id: String,
@@ -12,4 +14,7 @@ pub struct InstanceInfo {
     pub vmm_version: String,
     /// The name of the application that runs the microVM.
     pub app_name: String,
+    /// The states of the probes of the workload inside the guest, once the microVM is started.
+    #[serde(default, skip_serializing_if = "ProbeStatuses::is_empty")]
+    pub probes: ProbeStatuses,
 }
@@ -110,6 +110,8 @@ impl Display for LoggerConfigError {
     }
 }
 
+impl std::error::Error for LoggerConfigError {}
+
 /// Configures the logger as described in `logger_cfg`.
 pub fn init_logger(
     logger_cfg: LoggerConfig,
@@ -132,6 +134,18 @@ pub fn init_logger(
         .map_err(|e| LoggerConfigError::InitializationFailure(e.to_string()))
 }
 
+/// Traces the accesses of the guest drivers to the virtio devices registered from now on to the
+/// fifo or the file at `trace_path`.
+#[cfg(feature = "mmio-trace")]
+pub fn init_mmio_trace(trace_path: &PathBuf) -> std::result::Result<(), LoggerConfigError> {
+    let writer = FcLineWriter::new(
+        open_file_nonblock(trace_path)
+            .map_err(|e| LoggerConfigError::InitializationFailure(e.to_string()))?,
+    );
+    devices::virtio::mmio_trace::set_sink(Box::new(writer));
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::{BufRead, BufReader};
@@ -3,10 +3,24 @@
 
 use serde::{de, Deserialize};
 use std::fmt;
+use std::time::Duration;
 
+#[cfg(target_arch = "aarch64")]
+use arch::aarch64::gic::{GICConfig, GICVersion};
+#[cfg(target_arch = "x86_64")]
+use arch::MemoryLayout;
+
+/// The maximum number of vCPUs supported. On x86_64, the vCPUs and the IOAPIC need distinct
+/// 8-bit APIC IDs, the broadcast ID excluded.
+#[cfg(target_arch = "x86_64")]
+pub const MAX_SUPPORTED_VCPUS: u8 = 254;
 /// Firecracker aims to support small scale workloads only, so limit the maximum
 /// vCPUs supported.
+#[cfg(target_arch = "aarch64")]
 pub const MAX_SUPPORTED_VCPUS: u8 = 32;
+/// The maximum number of vCPUs handled by a GICv2.
+#[cfg(target_arch = "aarch64")]
+pub const GICV2_MAX_VCPUS: u8 = 8;
 
 /// Errors associated with configuring the microVM.
 #[derive(Debug, PartialEq)]
@@ -16,6 +30,57 @@ pub enum VmConfigError {
     InvalidVcpuCount,
     /// The memory size is invalid. The memory can only be an unsigned integer.
     InvalidMemorySize,
+    /// The guest memory doesn't fit in the guest physical address space, which holds at most
+    /// the given MiB of guest memory.
+    GuestMemoryTooLarge(u64),
+    /// The size of the 32-bit MMIO hole is out of range.
+    #[cfg(target_arch = "x86_64")]
+    InvalidMmio32HoleSize,
+    /// The 64-bit MMIO window doesn't fit in the guest physical address space.
+    #[cfg(target_arch = "x86_64")]
+    InvalidMmio64WindowSize,
+    /// The memory layout can't be configured on this architecture.
+    #[cfg(target_arch = "aarch64")]
+    MemoryLayoutNotSupported,
+    /// The vCPUs can't be split in sockets of `cores_per_socket` cores.
+    #[cfg(target_arch = "x86_64")]
+    InvalidCoresPerSocket,
+    /// The CPU topology can't be configured on this architecture.
+    #[cfg(target_arch = "aarch64")]
+    CpuTopologyNotSupported,
+    /// The CPU feature is unknown.
+    #[cfg(target_arch = "x86_64")]
+    InvalidCpuFeature(String),
+    /// The CPU features can't be configured on this architecture.
+    #[cfg(target_arch = "aarch64")]
+    CpuFeaturesNotSupported,
+    /// Nested virtualization can't be enabled on this architecture.
+    #[cfg(target_arch = "aarch64")]
+    NestedVirtualizationNotSupported,
+    /// The TSC frequency is invalid. It has to be a positive number of kHz.
+    #[cfg(target_arch = "x86_64")]
+    InvalidTscFrequency,
+    /// The TSC frequency can't be configured on this architecture.
+    #[cfg(target_arch = "aarch64")]
+    TscFrequencyNotSupported,
+    /// The GIC can't be created: the ITS requires a GICv3, and a GICv2 handles at most
+    /// `GICV2_MAX_VCPUS` vCPUs.
+    #[cfg(target_arch = "aarch64")]
+    InvalidGicConfig,
+    /// The interrupt controller can't be configured on this architecture.
+    #[cfg(target_arch = "x86_64")]
+    GicNotSupported,
+    /// The split irqchip can't be enabled on this architecture.
+    #[cfg(target_arch = "aarch64")]
+    SplitIrqchipNotSupported,
+    /// The PIT can't be omitted on this architecture.
+    #[cfg(target_arch = "aarch64")]
+    TicklessNotSupported,
+    /// The maximum interrupt rate of the devices is invalid. It has to be a positive number of
+    /// interrupts per second.
+    InvalidDeviceIrqRate,
+    /// The retry policy of the vCPUs is invalid. The first backoff can't exceed the longest one.
+    InvalidVcpuRetry,
 }
 
 impl fmt::Display for VmConfigError {
@@ -28,10 +93,93 @@ impl fmt::Display for VmConfigError {
                  be 1 or an even number when hyperthreading is enabled.",
             ),
             InvalidMemorySize => write!(f, "The memory size (MiB) is invalid.",),
+            GuestMemoryTooLarge(max_mib) => write!(
+                f,
+                "The memory size (MiB) is too large. The guest physical address space holds at \
+                 most {} MiB of guest memory.",
+                max_mib
+            ),
+            #[cfg(target_arch = "x86_64")]
+            InvalidMmio32HoleSize => write!(
+                f,
+                "The size of the 32-bit MMIO hole (MiB) is invalid. It has to be between {} \
+                 and {} MiB.",
+                arch::x86_64::MMIO32_HOLE_MIN_SIZE >> 20,
+                arch::x86_64::MMIO32_HOLE_MAX_SIZE >> 20
+            ),
+            #[cfg(target_arch = "x86_64")]
+            InvalidMmio64WindowSize => write!(
+                f,
+                "The 64-bit MMIO window (MiB) is invalid. Placed after the guest memory, it \
+                 has to end below {} GiB.",
+                arch::x86_64::guest_phys_addr_end() >> 30
+            ),
+            #[cfg(target_arch = "aarch64")]
+            MemoryLayoutNotSupported => {
+                write!(f, "The memory layout can only be configured on x86_64.")
+            }
+            #[cfg(target_arch = "x86_64")]
+            InvalidCoresPerSocket => write!(
+                f,
+                "The number of cores per socket is invalid. The cores have to be evenly split \
+                 in sockets of at most {} cores and {} threads, and the number of cores per \
+                 socket has to be a power of 2 when there are several sockets.",
+                cpuid::MAX_CORES_PER_SOCKET,
+                cpuid::MAX_CPUS_PER_SOCKET
+            ),
+            #[cfg(target_arch = "aarch64")]
+            CpuTopologyNotSupported => {
+                write!(f, "The CPU topology can only be configured on x86_64.")
+            }
+            #[cfg(target_arch = "x86_64")]
+            InvalidCpuFeature(ref name) => write!(f, "Unknown CPU feature: {}.", name),
+            #[cfg(target_arch = "aarch64")]
+            CpuFeaturesNotSupported => {
+                write!(f, "The CPU features can only be configured on x86_64.")
+            }
+            #[cfg(target_arch = "aarch64")]
+            NestedVirtualizationNotSupported => {
+                write!(f, "Nested virtualization can only be enabled on x86_64.")
+            }
+            #[cfg(target_arch = "x86_64")]
+            InvalidTscFrequency => write!(f, "The TSC frequency (kHz) is invalid."),
+            #[cfg(target_arch = "aarch64")]
+            TscFrequencyNotSupported => {
+                write!(f, "The TSC frequency can only be configured on x86_64.")
+            }
+            #[cfg(target_arch = "aarch64")]
+            InvalidGicConfig => write!(
+                f,
+                "The GIC configuration is invalid. The ITS requires a GICv3, and a GICv2 \
+                 supports at most {} vCPUs.",
+                GICV2_MAX_VCPUS
+            ),
+            #[cfg(target_arch = "x86_64")]
+            GicNotSupported => write!(
+                f,
+                "The interrupt controller (GIC) can only be configured on aarch64."
+            ),
+            #[cfg(target_arch = "aarch64")]
+            SplitIrqchipNotSupported => {
+                write!(f, "The split irqchip can only be enabled on x86_64.")
+            }
+            #[cfg(target_arch = "aarch64")]
+            TicklessNotSupported => write!(f, "The PIT can only be omitted on x86_64."),
+            InvalidDeviceIrqRate => write!(
+                f,
+                "The maximum interrupt rate of the devices (interrupts per second) is invalid."
+            ),
+            InvalidVcpuRetry => write!(
+                f,
+                "The vCPU retry policy is invalid. The initial backoff can't exceed the maximum \
+                 backoff."
+            ),
         }
     }
 }
 
+impl std::error::Error for VmConfigError {}
+
 /// Strongly typed structure that represents the configuration of the
 /// microvm.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -50,12 +198,65 @@ pub struct VmConfig {
     /// Enables or disabled hyperthreading.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ht_enabled: Option<bool>,
+    /// Number of cores per socket. All the cores are placed in the same socket by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cores_per_socket: Option<u8>,
     /// A CPU template that it is used to filter the CPU features exposed to the guest.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cpu_template: Option<CpuFeaturesTemplate>,
+    /// The host CPU features exposed to the guest on top of the CPU template.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_features: Option<CpuFeaturesConfig>,
+    /// Exposes the hardware virtualization extensions (VMX or SVM) of the host to the guest.
+    /// They are hidden when disabled or when KVM doesn't allow nested virtualization.
+    #[serde(default)]
+    pub nested: bool,
+    /// Frequency of the guest TSC, in kHz. The TSC ticks at the frequency of the host by
+    /// default, which changes when a snapshot is restored on a different host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tsc_khz: Option<u32>,
     /// Enables or disables dirty page tracking. Enabling allows incremental snapshots.
     #[serde(default)]
     pub track_dirty_pages: bool,
+    /// Backs the guest memory with an anonymous memory file, which allows handing the microVM
+    /// over to a new Firecracker process on live update.
+    #[serde(default)]
+    pub memfd_backed: bool,
+    /// Size in MiB of the hole at the end of the 32-bit address space. Defaults to 768 MiB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mmio32_hole_size_mib: Option<u64>,
+    /// Size in MiB of the 64-bit MMIO window, placed after the guest memory. When set, the MMIO
+    /// devices are placed in the window instead of the 32-bit hole.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mmio64_window_size_mib: Option<u64>,
+    /// Version of the GIC (aarch64 only). A GICv3 is used by default if the host supports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gic_version: Option<GicVersion>,
+    /// Adds an ITS to the GICv3 (aarch64 only), so that the devices can use MSIs.
+    #[serde(default)]
+    pub gic_its: bool,
+    /// Splits the irqchip (x86_64 only): KVM only emulates the local APICs, while the IOAPIC is
+    /// emulated by Firecracker, and the PIC and the PIT are left out.
+    #[serde(default)]
+    pub split_irqchip: bool,
+    /// Omits the PIT (x86_64 only), leaving the guest with kvm-clock and the TSC deadline mode
+    /// of the LAPIC timer, so that idle guests don't get periodic timer interrupts.
+    #[serde(default)]
+    pub tickless: bool,
+    /// Maximum number of interrupts per second each virtio device may inject into the guest.
+    /// The interrupts in excess are deferred and coalesced. Not limited by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_device_irq_rate: Option<u32>,
+    /// The legacy devices emulated besides the virtio devices. All of them by default.
+    #[serde(default)]
+    pub legacy_devices: LegacyDevicesConfig,
+    /// How the vCPUs retry the transient failures of `KVM_RUN`.
+    #[serde(default)]
+    pub vcpu_retry: VcpuRetryConfig,
+    /// Fails the start of the microVM on the problems found by cross-checking its resources,
+    /// which are only logged otherwise.
+    #[serde(default)]
+    pub strict_config: bool,
 }
 
 impl Default for VmConfig {
@@ -64,8 +265,51 @@ impl Default for VmConfig {
             vcpu_count: Some(1),
             mem_size_mib: Some(128),
             ht_enabled: Some(false),
+            cores_per_socket: None,
             cpu_template: None,
+            cpu_features: None,
+            nested: false,
+            tsc_khz: None,
             track_dirty_pages: false,
+            memfd_backed: false,
+            mmio32_hole_size_mib: None,
+            mmio64_window_size_mib: None,
+            gic_version: None,
+            gic_its: false,
+            split_irqchip: false,
+            tickless: false,
+            max_device_irq_rate: None,
+            legacy_devices: LegacyDevicesConfig::default(),
+            vcpu_retry: VcpuRetryConfig::default(),
+            strict_config: false,
+        }
+    }
+}
+
+impl VmConfig {
+    /// Returns the layout of the guest physical address space.
+    #[cfg(target_arch = "x86_64")]
+    pub fn memory_layout(&self) -> MemoryLayout {
+        let default_layout = MemoryLayout::default();
+        MemoryLayout {
+            mmio32_hole_size: self
+                .mmio32_hole_size_mib
+                .map_or(default_layout.mmio32_hole_size, |size_mib| size_mib << 20),
+            mmio64_window_size: self
+                .mmio64_window_size_mib
+                .map_or(default_layout.mmio64_window_size, |size_mib| size_mib << 20),
+        }
+    }
+
+    /// Returns the configuration of the interrupt controller.
+    #[cfg(target_arch = "aarch64")]
+    pub fn gic_config(&self) -> GICConfig {
+        GICConfig {
+            version: self.gic_version.map(|version| match version {
+                GicVersion::V2 => GICVersion::GICV2,
+                GicVersion::V3 => GICVersion::GICV3,
+            }),
+            its: self.gic_its,
         }
     }
 }
@@ -81,8 +325,15 @@ impl fmt::Display for VmConfig {
         write!(
             f,
             "{{ \"vcpu_count\": {:?}, \"mem_size_mib\": {:?}, \"ht_enabled\": {:?}, \
-             \"cpu_template\": {:?}, \"track_dirty_pages\": {:?} }}",
-            vcpu_count, mem_size, ht_enabled, cpu_template, self.track_dirty_pages
+             \"cpu_template\": {:?}, \"nested\": {:?}, \"track_dirty_pages\": {:?}, \
+             \"memfd_backed\": {:?} }}",
+            vcpu_count,
+            mem_size,
+            ht_enabled,
+            cpu_template,
+            self.nested,
+            self.track_dirty_pages,
+            self.memfd_backed
         )
     }
 }
@@ -111,6 +362,8 @@ pub enum CpuFeaturesTemplate {
     C3,
     /// T2 Template.
     T2,
+    /// T2A Template, the equivalent of T2 on AMD hosts.
+    T2A,
 }
 
 impl fmt::Display for CpuFeaturesTemplate {
@@ -118,10 +371,142 @@ impl fmt::Display for CpuFeaturesTemplate {
         match self {
             CpuFeaturesTemplate::C3 => write!(f, "C3"),
             CpuFeaturesTemplate::T2 => write!(f, "T2"),
+            CpuFeaturesTemplate::T2A => write!(f, "T2A"),
+        }
+    }
+}
+
+/// Predefined sets of host CPU features exposed to the guest.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CpuFeaturesProfile {
+    /// Exposes all the optional features supported by the host.
+    Passthrough,
+    /// Hides all the optional features.
+    Minimal,
+}
+
+/// The optional host CPU features, such as AVX-512 or SHA, exposed to the guest. The features
+/// not selected are hidden even when the CPU template would expose them.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum CpuFeaturesConfig {
+    /// A predefined set of features.
+    Profile(CpuFeaturesProfile),
+    /// An explicit list of feature names, e.g. `["avx2", "avx512f"]`.
+    List(Vec<String>),
+}
+
+/// Selects the legacy devices of the microVM, so that guests which only use virtio devices can
+/// run without the others.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LegacyDevicesConfig {
+    /// The serial ports: the four UARTs on x86_64, the console one among them, and the MMIO
+    /// serial console on aarch64.
+    pub serial: bool,
+    /// The i8042 keyboard controller (x86_64 only), through which the guest reboots. It doesn't
+    /// exist on aarch64.
+    pub i8042: bool,
+    /// The real time clock: the CMOS on x86_64 and the PL031 on aarch64.
+    pub rtc: bool,
+}
+
+impl Default for LegacyDevicesConfig {
+    fn default() -> Self {
+        LegacyDevicesConfig {
+            serial: true,
+            i8042: true,
+            rtc: true,
         }
     }
 }
 
+/// Configures how the vCPUs retry `KVM_RUN` when it fails with an error which may go away, e.g.
+/// when the host is short of memory, instead of stopping the microVM right away.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VcpuRetryConfig {
+    /// Number of consecutive transient failures a vCPU retries. The microVM is stopped on the
+    /// next one.
+    pub max_retries: u32,
+    /// Delay before the first retry, in microseconds. It doubles on each consecutive failure.
+    pub initial_backoff_us: u64,
+    /// Longest delay between two retries, in microseconds.
+    pub max_backoff_us: u64,
+}
+
+impl Default for VcpuRetryConfig {
+    fn default() -> Self {
+        VcpuRetryConfig {
+            max_retries: 10,
+            initial_backoff_us: 100,
+            max_backoff_us: 100_000,
+        }
+    }
+}
+
+impl VcpuRetryConfig {
+    /// Returns the delay before the `retry`-th consecutive retry, counted from 1.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u64
+            .checked_shl(retry.saturating_sub(1))
+            .unwrap_or_else(u64::max_value);
+        Duration::from_micros(
+            self.initial_backoff_us
+                .saturating_mul(factor)
+                .min(self.max_backoff_us),
+        )
+    }
+}
+
+/// The data fed into a request parking or unparking a vCPU, after microVM start.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct VcpuStateConfig {
+    /// The index of the vCPU, from 0.
+    pub vcpu_id: u8,
+    /// Whether the vCPU is kept paused while the others run.
+    pub paused: bool,
+}
+
+/// Errors associated with parking or unparking a vCPU.
+#[derive(Debug, PartialEq)]
+pub enum VcpuStateError {
+    /// The microVM has no vCPU with the given index.
+    InvalidVcpuId(u8),
+    /// Parking the vCPU would leave none running.
+    LastRunningVcpu(u8),
+    /// The vCPU thread didn't acknowledge the change.
+    NoResponse(u8),
+}
+
+impl fmt::Display for VcpuStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::VcpuStateError::*;
+        match *self {
+            InvalidVcpuId(id) => write!(f, "The microVM has no vCPU {}.", id),
+            LastRunningVcpu(id) => write!(
+                f,
+                "Cannot park vCPU {}, the last one running. Pause the microVM instead.",
+                id
+            ),
+            NoResponse(id) => write!(f, "vCPU {} didn't acknowledge its new state.", id),
+        }
+    }
+}
+
+impl std::error::Error for VcpuStateError {}
+
+/// Versions of the Generic Interrupt Controller available on aarch64.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum GicVersion {
+    /// GICv2, supporting up to 8 vCPUs.
+    V2,
+    /// GICv3.
+    V3,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -130,6 +515,80 @@ mod tests {
     fn test_display_cpu_features_template() {
         assert_eq!(CpuFeaturesTemplate::C3.to_string(), "C3".to_string());
         assert_eq!(CpuFeaturesTemplate::T2.to_string(), "T2".to_string());
+        assert_eq!(CpuFeaturesTemplate::T2A.to_string(), "T2A".to_string());
+    }
+
+    #[test]
+    fn test_deserialize_cpu_features_config() {
+        assert_eq!(
+            serde_json::from_str::<CpuFeaturesConfig>("\"passthrough\"").unwrap(),
+            CpuFeaturesConfig::Profile(CpuFeaturesProfile::Passthrough)
+        );
+        assert_eq!(
+            serde_json::from_str::<CpuFeaturesConfig>("\"minimal\"").unwrap(),
+            CpuFeaturesConfig::Profile(CpuFeaturesProfile::Minimal)
+        );
+        assert_eq!(
+            serde_json::from_str::<CpuFeaturesConfig>("[\"avx2\", \"sha_ni\"]").unwrap(),
+            CpuFeaturesConfig::List(vec!["avx2".to_string(), "sha_ni".to_string()])
+        );
+        assert!(serde_json::from_str::<CpuFeaturesConfig>("\"all\"").is_err());
+    }
+
+    #[test]
+    fn test_vcpu_retry_config() {
+        let vm_config: VmConfig =
+            serde_json::from_str(r#"{"vcpu_count": 1, "mem_size_mib": 128}"#).unwrap();
+        assert_eq!(vm_config.vcpu_retry, VcpuRetryConfig::default());
+
+        let config: VcpuRetryConfig =
+            serde_json::from_str(r#"{"max_retries": 3, "max_backoff_us": 250}"#).unwrap();
+        assert_eq!(config.max_retries, 3);
+        assert_eq!(config.backoff(1), Duration::from_micros(100));
+        assert_eq!(config.backoff(2), Duration::from_micros(200));
+        assert_eq!(config.backoff(3), Duration::from_micros(250));
+        assert_eq!(config.backoff(100), Duration::from_micros(250));
+        assert!(serde_json::from_str::<VcpuRetryConfig>(r#"{"retries": 3}"#).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_legacy_devices_config() {
+        let vm_config: VmConfig =
+            serde_json::from_str(r#"{"vcpu_count": 1, "mem_size_mib": 128}"#).unwrap();
+        assert_eq!(vm_config.legacy_devices, LegacyDevicesConfig::default());
+
+        let vm_config: VmConfig = serde_json::from_str(
+            r#"{"vcpu_count": 1, "mem_size_mib": 128, "legacy_devices": {"i8042": false}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            vm_config.legacy_devices,
+            LegacyDevicesConfig {
+                serial: true,
+                i8042: false,
+                rtc: true,
+            }
+        );
+        assert!(serde_json::from_str::<LegacyDevicesConfig>(r#"{"pit": false}"#).is_err());
+    }
+
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn test_gic_config() {
+        assert_eq!(VmConfig::default().gic_config(), GICConfig::default());
+
+        let vm_config = VmConfig {
+            gic_version: Some(GicVersion::V3),
+            gic_its: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            vm_config.gic_config(),
+            GICConfig {
+                version: Some(GICVersion::GICV3),
+                its: true,
+            }
+        );
     }
 
     #[test]
@@ -141,4 +600,26 @@ mod tests {
         let expected_str = "The memory size (MiB) is invalid.";
         assert_eq!(VmConfigError::InvalidMemorySize.to_string(), expected_str);
     }
+
+    #[test]
+    fn test_vcpu_state_config() {
+        let state: VcpuStateConfig =
+            serde_json::from_str(r#"{"vcpu_id": 3, "paused": true}"#).unwrap();
+        assert_eq!(
+            state,
+            VcpuStateConfig {
+                vcpu_id: 3,
+                paused: true,
+            }
+        );
+        assert!(serde_json::from_str::<VcpuStateConfig>(r#"{"vcpu_id": 3}"#).is_err());
+        assert!(
+            serde_json::from_str::<VcpuStateConfig>(r#"{"vcpu_id": 256, "paused": true}"#).is_err()
+        );
+
+        assert_eq!(
+            VcpuStateError::LastRunningVcpu(0).to_string(),
+            "Cannot park vCPU 0, the last one running. Pause the microVM instead."
+        );
+    }
 }
@@ -0,0 +1,188 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::io;
+
+/// Errors associated with the memory limits of the Firecracker process.
+#[derive(Debug)]
+pub enum MemoryLimitsConfigError {
+    /// The limit, in MiB, is 0 or too large.
+    InvalidLimit(u64),
+    /// Cannot set the resource limit.
+    SetLimit(io::Error),
+}
+
+impl fmt::Display for MemoryLimitsConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::MemoryLimitsConfigError::*;
+        match self {
+            InvalidLimit(limit) => write!(f, "Invalid memory limit: {} MiB.", limit),
+            SetLimit(err) => write!(f, "Cannot set the memory limit: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for MemoryLimitsConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MemoryLimitsConfigError::InvalidLimit(_) => None,
+            MemoryLimitsConfigError::SetLimit(err) => Some(err),
+        }
+    }
+}
+
+/// What Firecracker does when the host cannot provide the guest memory.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OomPolicy {
+    /// The action allocating the guest memory fails, and Firecracker keeps running.
+    FailAction,
+    /// Firecracker exits with the `OutOfMemory` exit code.
+    Exit,
+}
+
+impl Default for OomPolicy {
+    fn default() -> Self {
+        OomPolicy::FailAction
+    }
+}
+
+/// Limits the memory the Firecracker process can use, so that it fails in a controlled way
+/// instead of getting killed by the host OOM killer.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryLimitsConfig {
+    /// Soft limit of the address space of the process (`RLIMIT_AS`), in MiB. It has to leave
+    /// room for the guest memory, which is mapped in the address space of the process.
+    #[serde(default)]
+    pub address_space_mib: Option<u64>,
+    /// Soft limit of the data segment of the process (`RLIMIT_DATA`), in MiB.
+    #[serde(default)]
+    pub data_mib: Option<u64>,
+    /// What Firecracker does when the guest memory cannot be allocated.
+    #[serde(default)]
+    pub oom_policy: OomPolicy,
+}
+
+impl MemoryLimitsConfig {
+    /// Checks that the limits can be set.
+    pub fn validate(&self) -> Result<(), MemoryLimitsConfigError> {
+        for limit_mib in self.address_space_mib.iter().chain(self.data_mib.iter()) {
+            limit_bytes(*limit_mib)?;
+        }
+        Ok(())
+    }
+
+    /// Sets the soft limits of the Firecracker process. The hard limits are left untouched, so
+    /// that the soft limits can be raised again.
+    pub fn apply(&self) -> Result<(), MemoryLimitsConfigError> {
+        self.validate()?;
+        if let Some(limit_mib) = self.address_space_mib {
+            set_soft_limit(libc::RLIMIT_AS, limit_bytes(limit_mib)?)?;
+        }
+        if let Some(limit_mib) = self.data_mib {
+            set_soft_limit(libc::RLIMIT_DATA, limit_bytes(limit_mib)?)?;
+        }
+        Ok(())
+    }
+}
+
+fn limit_bytes(limit_mib: u64) -> Result<libc::rlim_t, MemoryLimitsConfigError> {
+    match limit_mib.checked_mul(1 << 20) {
+        Some(limit) if limit_mib > 0 && limit != libc::RLIM_INFINITY => Ok(limit),
+        _ => Err(MemoryLimitsConfigError::InvalidLimit(limit_mib)),
+    }
+}
+
+#[cfg(target_env = "gnu")]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(target_env = "musl")]
+type Resource = libc::c_int;
+
+fn set_soft_limit(resource: Resource, limit: libc::rlim_t) -> Result<(), MemoryLimitsConfigError> {
+    let mut rlimit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // Safe because the kernel only writes to `rlimit`, which is valid, and we check the result.
+    if unsafe { libc::getrlimit(resource, &mut rlimit) } < 0 {
+        return Err(MemoryLimitsConfigError::SetLimit(io::Error::last_os_error()));
+    }
+    rlimit.rlim_cur = limit;
+    // Safe because the kernel only reads `rlimit`, which is valid, and we check the result.
+    if unsafe { libc::setrlimit(resource, &rlimit) } < 0 {
+        return Err(MemoryLimitsConfigError::SetLimit(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_limits_config() {
+        assert!(MemoryLimitsConfig::default().apply().is_ok());
+
+        let config = MemoryLimitsConfig {
+            address_space_mib: Some(0),
+            ..Default::default()
+        };
+        match config.validate() {
+            Err(MemoryLimitsConfigError::InvalidLimit(0)) => (),
+            _ => panic!("Unexpected result"),
+        }
+        let config = MemoryLimitsConfig {
+            data_mib: Some(u64::max_value()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        // The soft limit can't exceed the hard limit.
+        let mut rlimit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        assert_eq!(
+            unsafe { libc::getrlimit(libc::RLIMIT_DATA, &mut rlimit) },
+            0
+        );
+        if rlimit.rlim_max != libc::RLIM_INFINITY {
+            let config = MemoryLimitsConfig {
+                data_mib: Some((rlimit.rlim_max >> 20) + 1),
+                ..Default::default()
+            };
+            match config.apply() {
+                Err(MemoryLimitsConfigError::SetLimit(_)) => (),
+                _ => panic!("Unexpected result"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_memory_limits_config_deserialization() {
+        let config: MemoryLimitsConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, MemoryLimitsConfig::default());
+        assert_eq!(config.oom_policy, OomPolicy::FailAction);
+
+        let config: MemoryLimitsConfig = serde_json::from_str(
+            r#"{"address_space_mib": 4096, "data_mib": 512, "oom_policy": "exit"}"#,
+        )
+        .unwrap();
+        assert_eq!(config.address_space_mib, Some(4096));
+        assert_eq!(config.data_mib, Some(512));
+        assert_eq!(config.oom_policy, OomPolicy::Exit);
+
+        assert!(serde_json::from_str::<MemoryLimitsConfig>(r#"{"oom_policy": "kill"}"#).is_err());
+        assert!(serde_json::from_str::<MemoryLimitsConfig>(r#"{"memory_max": 1}"#).is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let err = MemoryLimitsConfigError::InvalidLimit(0);
+        let _ = format!("{}{:?}", err, err);
+        let err = MemoryLimitsConfigError::SetLimit(io::Error::from_raw_os_error(libc::EPERM));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
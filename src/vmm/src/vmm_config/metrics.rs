@@ -8,14 +8,57 @@ extern crate logger as logger_crate;
 use std::fmt::{Display, Formatter};
 use std::path::PathBuf;
 
-use self::logger_crate::METRICS;
+use self::logger_crate::{MetricsExporter, OtlpExporter, StatsdExporter, METRICS};
 use super::{open_file_nonblock, FcLineWriter};
 
+/// Describes an exporter pushing the flushed metrics to an external telemetry system.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum MetricsExporterConfig {
+    /// Sends the metrics as statsd counters over UDP.
+    Statsd {
+        /// Address (`host:port`) of the statsd daemon.
+        address: String,
+        /// Prefix prepended to the name of each metric.
+        #[serde(default)]
+        prefix: String,
+    },
+    /// Posts the metrics to an OpenTelemetry collector, using OTLP over HTTP/JSON.
+    Otlp {
+        /// Address (`host:port`) of the collector's OTLP/HTTP receiver.
+        endpoint: String,
+        /// Value of the `service.name` resource attribute.
+        #[serde(default = "default_service_name")]
+        service_name: String,
+    },
+}
+
+fn default_service_name() -> String {
+    String::from("firecracker")
+}
+
+impl MetricsExporterConfig {
+    fn build(&self) -> std::io::Result<Box<dyn MetricsExporter>> {
+        match self {
+            MetricsExporterConfig::Statsd { address, prefix } => {
+                Ok(Box::new(StatsdExporter::new(address, prefix)?))
+            }
+            MetricsExporterConfig::Otlp {
+                endpoint,
+                service_name,
+            } => Ok(Box::new(OtlpExporter::new(endpoint, service_name)?)),
+        }
+    }
+}
+
 /// Strongly typed structure used to describe the metrics system.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct MetricsConfig {
     /// Named pipe or file used as output for metrics.
     pub metrics_path: PathBuf,
+    /// Exporters which also receive the metrics, each time they are flushed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exporters: Vec<MetricsExporterConfig>,
 }
 
 /// Errors associated with actions on the `MetricsConfig`.
@@ -34,15 +77,29 @@ impl Display for MetricsConfigError {
     }
 }
 
+impl std::error::Error for MetricsConfigError {}
+
 /// Configures the metrics as described in `metrics_cfg`.
 pub fn init_metrics(metrics_cfg: MetricsConfig) -> std::result::Result<(), MetricsConfigError> {
     let writer = FcLineWriter::new(
         open_file_nonblock(&metrics_cfg.metrics_path)
             .map_err(|e| MetricsConfigError::InitializationFailure(e.to_string()))?,
     );
+    // Set up the exporters beforehand, so that a bad exporter config leaves the metrics system
+    // uninitialized and the whole configuration can be retried.
+    let exporters = metrics_cfg
+        .exporters
+        .iter()
+        .map(MetricsExporterConfig::build)
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(|e| MetricsConfigError::InitializationFailure(e.to_string()))?;
     METRICS
         .init(Box::new(writer))
-        .map_err(|e| MetricsConfigError::InitializationFailure(e.to_string()))
+        .map_err(|e| MetricsConfigError::InitializationFailure(e.to_string()))?;
+    for exporter in exporters {
+        METRICS.add_exporter(exporter);
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -55,19 +112,70 @@ mod tests {
         // Error case: initializing metrics with invalid pipe returns error.
         let desc = MetricsConfig {
             metrics_path: PathBuf::from("not_found_file_metrics"),
+            exporters: vec![],
         };
         assert!(init_metrics(desc).is_err());
 
-        // Initializing metrics with valid pipe is ok.
+        // Error case: initializing metrics with an unresolvable exporter endpoint returns error.
         let metrics_file = TempFile::new().unwrap();
         let desc = MetricsConfig {
             metrics_path: metrics_file.as_path().to_path_buf(),
+            exporters: vec![MetricsExporterConfig::Otlp {
+                endpoint: String::from("invalid endpoint"),
+                service_name: default_service_name(),
+            }],
+        };
+        assert!(init_metrics(desc).is_err());
+
+        // Initializing metrics with valid pipe is ok.
+        let desc = MetricsConfig {
+            metrics_path: metrics_file.as_path().to_path_buf(),
+            exporters: vec![MetricsExporterConfig::Statsd {
+                address: String::from("127.0.0.1:8125"),
+                prefix: String::from("fc"),
+            }],
         };
 
         assert!(init_metrics(desc.clone()).is_ok());
         assert!(init_metrics(desc).is_err());
     }
 
+    #[test]
+    fn test_metrics_config_deserialization() {
+        let cfg: MetricsConfig = serde_json::from_str(
+            r#"{
+                "metrics_path": "metrics",
+                "exporters": [
+                    { "type": "statsd", "address": "127.0.0.1:8125" },
+                    { "type": "otlp", "endpoint": "127.0.0.1:4318", "service_name": "vm0" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            cfg.exporters,
+            vec![
+                MetricsExporterConfig::Statsd {
+                    address: String::from("127.0.0.1:8125"),
+                    prefix: String::new(),
+                },
+                MetricsExporterConfig::Otlp {
+                    endpoint: String::from("127.0.0.1:4318"),
+                    service_name: String::from("vm0"),
+                },
+            ]
+        );
+
+        // The exporters are optional.
+        let cfg: MetricsConfig = serde_json::from_str(r#"{"metrics_path": "metrics"}"#).unwrap();
+        assert!(cfg.exporters.is_empty());
+
+        assert!(serde_json::from_str::<MetricsConfig>(
+            r#"{"metrics_path": "metrics", "exporters": [{ "type": "prometheus" }]}"#
+        )
+        .is_err());
+    }
+
     #[test]
     fn test_error_display() {
         assert_eq!(
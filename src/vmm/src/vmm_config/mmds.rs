@@ -6,7 +6,7 @@ use std::fmt::{Display, Result};
 use std::net::Ipv4Addr;
 
 /// Keeps the MMDS configuration.
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct MmdsConfig {
     /// MMDS IPv4 configured address.
@@ -37,3 +37,5 @@ impl Display for MmdsConfigError {
         }
     }
 }
+
+impl std::error::Error for MmdsConfigError {}
@@ -1,9 +1,11 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
+use std::fmt::{Display, Formatter};
 use std::fs::{File, OpenOptions};
 use std::io;
+use std::ops::Deref;
 use std::os::unix::fs::OpenOptionsExt;
 use std::path::PathBuf;
 
@@ -11,24 +13,59 @@ use libc::O_NONBLOCK;
 
 use rate_limiter::{RateLimiter, TokenBucket};
 
+/// Wrapper for configuring the balloon device.
+pub mod balloon;
 /// Wrapper for configuring the microVM boot source.
 pub mod boot_source;
+/// Wrapper for configuring the cloud-init data handed to the guest.
+pub mod cloud_init;
+/// Wrapper for configuring the serial console.
+pub mod console;
 /// Wrapper for configuring the block devices.
 pub mod drive;
+/// Wrapper for configuring the entropy device.
+pub mod entropy;
+/// Wrapper for configuring the GPU device.
+pub mod gpu;
+/// Wrapper for locating the kernel log of the guest.
+pub mod guest_dmesg;
+/// Wrapper for configuring the input device.
+pub mod input;
 /// Wrapper over the microVM general information attached to the microVM.
 pub mod instance_info;
 /// Wrapper for configuring the logger.
 pub mod logger;
 /// Wrapper for configuring the memory and CPU of the microVM.
 pub mod machine_config;
+/// Wrapper for configuring the memory limits of the Firecracker process.
+pub mod memory_limits;
 /// Wrapper for configuring the metrics.
 pub mod metrics;
 /// Wrapper for configuring the MMDS.
 pub mod mmds;
 /// Wrapper for configuring the network devices attached to the microVM.
 pub mod net;
+/// Wrapper for configuring the host PCI devices passed through to the microVM.
+pub mod pci_passthrough;
+/// Wrapper for the file descriptors opened on behalf of Firecracker by the process launching it.
+pub mod preopened_fds;
+/// Wrapper for configuring the probes of the workload inside the guest.
+pub mod probe;
+/// Wrapper for configuring the adaptive rate limiting of the devices.
+pub mod rate_limit_policy;
+/// Wrapper for configuring the guest real time clock.
+pub mod rtc;
+/// Wrapper for configuring the launch of AMD SEV guests.
+#[cfg(feature = "sev")]
+pub mod sev;
+/// Wrapper for configuring the memory regions shared between the host and the guest.
+pub mod shared_memory;
 /// Wrapper for configuring microVM snapshots and the microVM state.
 pub mod snapshot;
+/// Wrapper for configuring the sound device.
+pub mod sound;
+/// Wrapper for configuring the virtio features offered to the guest drivers.
+pub mod virtio_features;
 /// Wrapper for configuring the vsock devices attached to the microVM.
 pub mod vsock;
 
@@ -43,7 +80,7 @@ pub mod vsock;
 
 /// A public-facing, stateless structure, holding all the data we need to create a TokenBucket
 /// (live) object.
-#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct TokenBucketConfig {
     /// See TokenBucket::size.
     pub size: u64,
@@ -61,7 +98,7 @@ impl Into<TokenBucket> for TokenBucketConfig {
 
 /// A public-facing, stateless structure, holding all the data we need to create a RateLimiter
 /// (live) object.
-#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct RateLimiterConfig {
     /// Data used to initialize the RateLimiter::bandwidth bucket.
@@ -99,6 +136,144 @@ impl TryInto<RateLimiter> for RateLimiterConfig {
     }
 }
 
+/// The maximum length of a device identifier.
+pub const MAX_IDENTIFIER_LEN: usize = 64;
+
+/// Errors associated with validating a device identifier.
+#[derive(Debug, PartialEq)]
+pub enum IdentifierError {
+    /// The identifier is empty.
+    Empty,
+    /// The identifier contains a character outside of `[a-zA-Z0-9_]`.
+    InvalidChar(String, char, usize),
+    /// The identifier is longer than `MAX_IDENTIFIER_LEN` bytes.
+    TooLong(String, usize),
+}
+
+impl Display for IdentifierError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::IdentifierError::*;
+        match self {
+            Empty => write!(f, "The device ID cannot be empty."),
+            InvalidChar(id, ch, pos) => write!(
+                f,
+                "Invalid character {:?} at position {} in device ID {:?}. Only ASCII letters, \
+                 digits and '_' are allowed.",
+                ch, pos, id
+            ),
+            TooLong(id, len) => write!(
+                f,
+                "The device ID {:?} is {} characters long. The maximum length is {}.",
+                id, len, MAX_IDENTIFIER_LEN
+            ),
+        }
+    }
+}
+
+/// The ID of a device, as configured through the API.
+///
+/// Device IDs key the device manager and the device event handlers and end up in the kernel
+/// command line, so they are restricted to 1 to `MAX_IDENTIFIER_LEN` ASCII letters, digits and
+/// '_', the same characters the API server accepts in the request path. The restriction is
+/// enforced when the configuration is deserialized.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(try_from = "String")]
+pub struct Identifier(String);
+
+impl Identifier {
+    /// Returns the identifier as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for Identifier {
+    type Error = IdentifierError;
+
+    fn try_from(id: String) -> std::result::Result<Self, Self::Error> {
+        if id.is_empty() {
+            return Err(IdentifierError::Empty);
+        }
+        if let Some((pos, ch)) = id
+            .chars()
+            .enumerate()
+            .find(|(_, ch)| !(ch.is_ascii_alphanumeric() || *ch == '_'))
+        {
+            return Err(IdentifierError::InvalidChar(id, ch, pos));
+        }
+        // All the characters are ASCII at this point, so the length is the character count.
+        if id.len() > MAX_IDENTIFIER_LEN {
+            let len = id.len();
+            return Err(IdentifierError::TooLong(id, len));
+        }
+        Ok(Identifier(id))
+    }
+}
+
+impl TryFrom<&str> for Identifier {
+    type Error = IdentifierError;
+
+    fn try_from(id: &str) -> std::result::Result<Self, Self::Error> {
+        Identifier::try_from(id.to_string())
+    }
+}
+
+impl From<Identifier> for String {
+    fn from(id: Identifier) -> Self {
+        id.0
+    }
+}
+
+impl Deref for Identifier {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Identifier {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for Identifier {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl PartialEq<str> for Identifier {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Identifier {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialEq<String> for Identifier {
+    fn eq(&self, other: &String) -> bool {
+        &self.0 == other
+    }
+}
+
+impl PartialEq<Identifier> for str {
+    fn eq(&self, other: &Identifier) -> bool {
+        self == other.0
+    }
+}
+
+impl PartialEq<Identifier> for String {
+    fn eq(&self, other: &Identifier) -> bool {
+        self == &other.0
+    }
+}
+
 type Result<T> = std::result::Result<T, std::io::Error>;
 
 /// Create and opens a File for writing to it.
@@ -178,6 +353,71 @@ mod tests {
         assert_eq!(rlconf.ops.unwrap().refill_time, REFILL_TIME * 2);
     }
 
+    #[test]
+    fn test_identifier() {
+        let max_len_id = "a".repeat(MAX_IDENTIFIER_LEN);
+        for id in &["1", "rootfs", "eth0", "scratch_disk_2", max_len_id.as_str()] {
+            let identifier = Identifier::try_from(*id).unwrap();
+            assert_eq!(identifier, *id);
+            assert_eq!(identifier.to_string(), *id);
+            assert_eq!(String::from(identifier), *id);
+        }
+
+        assert_eq!(Identifier::try_from(""), Err(IdentifierError::Empty));
+        assert_eq!(
+            Identifier::try_from("root fs"),
+            Err(IdentifierError::InvalidChar(
+                String::from("root fs"),
+                ' ',
+                4
+            ))
+        );
+        assert_eq!(
+            Identifier::try_from("eth-0"),
+            Err(IdentifierError::InvalidChar(String::from("eth-0"), '-', 3))
+        );
+        assert_eq!(
+            Identifier::try_from("vsock\u{e9}"),
+            Err(IdentifierError::InvalidChar(
+                String::from("vsock\u{e9}"),
+                '\u{e9}',
+                5
+            ))
+        );
+        let long_id = "a".repeat(MAX_IDENTIFIER_LEN + 1);
+        assert_eq!(
+            Identifier::try_from(long_id.as_str()),
+            Err(IdentifierError::TooLong(
+                long_id.clone(),
+                MAX_IDENTIFIER_LEN + 1
+            ))
+        );
+    }
+
+    #[test]
+    fn test_identifier_serde() {
+        let identifier: Identifier = serde_json::from_str("\"rootfs\"").unwrap();
+        assert_eq!(identifier, "rootfs");
+        assert_eq!(serde_json::to_string(&identifier).unwrap(), "\"rootfs\"");
+
+        let err = serde_json::from_str::<Identifier>("\"root.fs\"")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Invalid character '.' at position 4 in device ID \"root.fs\""));
+        assert!(serde_json::from_str::<Identifier>("\"\"").is_err());
+    }
+
+    #[test]
+    fn test_identifier_error_messages() {
+        use self::IdentifierError::*;
+        let err = Empty;
+        let _ = format!("{}{:?}", err, err);
+        let err = InvalidChar(String::from("a b"), ' ', 1);
+        let _ = format!("{}{:?}", err, err);
+        let err = TooLong(String::from("abc"), 3);
+        let _ = format!("{}{:?}", err, err);
+    }
+
     #[test]
     fn test_fifo_line_writer() {
         let log_file_temp =
@@ -1,27 +1,148 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt;
+use std::fs::File;
+#[cfg(feature = "xdp")]
+use std::path::PathBuf;
 use std::result;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use super::RateLimiterConfig;
+use super::preopened_fds::PreopenedFds;
+use super::{Identifier, RateLimiterConfig};
+use device_thread::MAX_CPUS;
+#[cfg(feature = "xdp")]
+use devices::virtio::net::xdp::{self, XdpOptions, XdpSocket};
+use devices::virtio::net::{ImpairmentParams, NetBackend, MAX_IMPAIRMENT_DELAY, MAX_MTU, MIN_MTU};
 use devices::virtio::Net;
 use dumbo::MacAddr;
-use utils::net::TapError;
+use utils::net::{Tap, TapError};
+
+/// The kind of host interface backing a guest network interface.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetBackendType {
+    /// A tap interface.
+    Tap,
+    /// An `AF_XDP` socket, bound to a queue of a host NIC. Only available in the builds with the
+    /// `xdp` feature.
+    Xdp,
+}
+
+impl Default for NetBackendType {
+    fn default() -> Self {
+        NetBackendType::Tap
+    }
+}
+
+/// The sizing of the `AF_XDP` socket of a network interface with the `xdp` backend.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct XdpConfig {
+    /// The queue of the host NIC the socket is bound to.
+    #[serde(default)]
+    pub queue_id: u32,
+    /// The number of entries of each ring of the socket, a power of 2.
+    #[serde(default = "default_xdp_ring_size")]
+    pub ring_size: u32,
+    /// The number of 4 KiB frames of the UMEM, the memory the frames go through, at least twice
+    /// `ring_size`.
+    #[serde(default = "default_xdp_umem_frames")]
+    pub umem_frames: u32,
+    /// Whether the NIC accesses the UMEM directly, which its driver has to support. Otherwise,
+    /// the host kernel copies the frames.
+    #[serde(default = "default_xdp_zero_copy")]
+    pub zero_copy: bool,
+    /// Path of the pinned `XSKMAP` of the XDP program redirecting the frames of the queue, which
+    /// the socket is inserted into at the index `queue_id`.
+    #[serde(default)]
+    pub xsks_map: Option<String>,
+}
+
+impl Default for XdpConfig {
+    fn default() -> Self {
+        XdpConfig {
+            queue_id: 0,
+            ring_size: default_xdp_ring_size(),
+            umem_frames: default_xdp_umem_frames(),
+            zero_copy: default_xdp_zero_copy(),
+            xsks_map: None,
+        }
+    }
+}
+
+/// How a direction of the network path of an interface is degraded, to test the guest under
+/// degraded networks. The frames are held back for the delay, varying with the jitter, and lost
+/// or duplicated at random.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkImpairmentConfig {
+    /// The time the frames are held back for, in milliseconds.
+    #[serde(default)]
+    pub delay_ms: u64,
+    /// The most the delay of a frame varies by, either way, in milliseconds.
+    #[serde(default)]
+    pub jitter_ms: u64,
+    /// The percentage of the frames lost.
+    #[serde(default)]
+    pub loss_percent: f64,
+    /// The percentage of the frames duplicated.
+    #[serde(default)]
+    pub duplicate_percent: f64,
+}
+
+impl TryInto<ImpairmentParams> for NetworkImpairmentConfig {
+    type Error = NetworkInterfaceError;
+
+    fn try_into(self) -> Result<ImpairmentParams> {
+        let probability = |percent: f64| {
+            if percent >= 0.0 && percent <= 100.0 {
+                Ok(percent / 100.0)
+            } else {
+                Err(NetworkInterfaceError::InvalidImpairment)
+            }
+        };
+        let duration = |ms: u64| {
+            let duration = Duration::from_millis(ms);
+            if duration <= MAX_IMPAIRMENT_DELAY {
+                Ok(duration)
+            } else {
+                Err(NetworkInterfaceError::InvalidImpairment)
+            }
+        };
+        Ok(ImpairmentParams {
+            delay: duration(self.delay_ms)?,
+            jitter: duration(self.jitter_ms)?,
+            loss: probability(self.loss_percent)?,
+            duplication: probability(self.duplicate_percent)?,
+        })
+    }
+}
 
 /// This struct represents the strongly typed equivalent of the json body from net iface
 /// related requests.
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct NetworkInterfaceConfig {
     /// ID of the guest network interface.
-    pub iface_id: String,
+    pub iface_id: Identifier,
     /// Host level path for the guest network interface.
     pub host_dev_name: String,
+    /// The kind of the host interface `host_dev_name`.
+    #[serde(default)]
+    pub backend: NetBackendType,
+    /// The sizing of the `AF_XDP` socket, with the `xdp` backend. Defaults apply if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xdp: Option<XdpConfig>,
     /// Guest MAC address.
     pub guest_mac: Option<MacAddr>,
+    /// MTU of both the host tap and the guest interface. If not set, the guest interface
+    /// defaults to 1500 bytes and the host tap is left as it is.
+    #[serde(default)]
+    pub mtu: Option<u16>,
     /// Rate Limiter for received packages.
     pub rx_rate_limiter: Option<RateLimiterConfig>,
     /// Rate Limiter for transmitted packages.
@@ -33,6 +154,21 @@ pub struct NetworkInterfaceConfig {
     /// same address are intercepted by the device model, and do not reach
     /// the associated TAP device.
     pub allow_mmds_requests: bool,
+    /// Whether the guest may turn the promiscuous mode of the interface on, and receive the
+    /// frames addressed to other MAC addresses. Otherwise, such requests of the guest driver are
+    /// denied.
+    #[serde(default = "default_allow_promiscuous")]
+    pub allow_promiscuous: bool,
+    /// How the frames received by the guest are degraded. Not degraded if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rx_impairment: Option<NetworkImpairmentConfig>,
+    /// How the frames transmitted by the guest are degraded. Not degraded if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_impairment: Option<NetworkImpairmentConfig>,
+    /// Host CPUs the emulation of the device runs on. If set, the device is emulated on a thread
+    /// of its own, pinned to these CPUs, instead of on the VMM thread.
+    #[serde(default)]
+    pub affinity: Option<Vec<usize>>,
 }
 
 // Serde does not allow specifying a default value for a field
@@ -42,8 +178,24 @@ fn default_allow_mmds_requests() -> bool {
     false
 }
 
+fn default_allow_promiscuous() -> bool {
+    true
+}
+
+fn default_xdp_ring_size() -> u32 {
+    2048
+}
+
+fn default_xdp_umem_frames() -> u32 {
+    4096
+}
+
+fn default_xdp_zero_copy() -> bool {
+    true
+}
+
 /// The data fed into a network iface update request. Currently, only the RX and TX rate limiters
-/// can be updated.
+/// and impairments can be updated.
 #[derive(Debug, Deserialize, PartialEq, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct NetworkInterfaceUpdateConfig {
@@ -55,6 +207,22 @@ pub struct NetworkInterfaceUpdateConfig {
     /// New TX rate limiter config. Only provided data will be updated. I.e. if any optional data
     /// is missing, it will not be nullified, but left unchanged.
     pub tx_rate_limiter: Option<RateLimiterConfig>,
+    /// New impairment of the received frames, replacing the current one.
+    #[serde(default)]
+    pub rx_impairment: Option<NetworkImpairmentConfig>,
+    /// New impairment of the transmitted frames, replacing the current one.
+    #[serde(default)]
+    pub tx_impairment: Option<NetworkImpairmentConfig>,
+}
+
+/// The data fed into a request changing the link state of a net iface, after microVM start.
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkLinkStateConfig {
+    /// The net iface ID, as provided by the user at iface creation time.
+    pub iface_id: String,
+    /// Whether the link is up, i.e. the cable of the interface is plugged in.
+    pub up: bool,
 }
 
 /// Errors associated with `NetworkInterfaceConfig`.
@@ -68,8 +236,25 @@ pub enum NetworkInterfaceError {
     GuestMacAddressInUse(String),
     /// Couldn't find the interface to update (patch).
     DeviceIdNotFound,
+    /// The CPU affinity is empty or lists CPUs the host can't have.
+    InvalidCpuAffinity,
+    /// A delay or a percentage of an impairment is out of range.
+    InvalidImpairment,
+    /// The MTU is out of the range the device supports.
+    InvalidMtu(u16),
     /// Cannot open/create tap device.
     OpenTap(TapError),
+    /// Cannot notify the guest of the new link state.
+    SetLinkState(devices::Error),
+    /// Cannot set the MTU of the tap device, e.g. without `CAP_NET_ADMIN`.
+    SetTapMtu(u16, TapError),
+    /// The `xdp` settings are given for an interface with another backend.
+    UnexpectedXdpConfig,
+    /// Firecracker was built without the `xdp` feature.
+    UnsupportedBackend,
+    /// Cannot open the `AF_XDP` socket.
+    #[cfg(feature = "xdp")]
+    OpenXdp(xdp::Error),
 }
 
 impl fmt::Display for NetworkInterfaceError {
@@ -84,6 +269,22 @@ impl fmt::Display for NetworkInterfaceError {
                 format!("The guest MAC address {} is already in use.", mac_addr)
             ),
             DeviceIdNotFound => write!(f, "Invalid interface ID - not found."),
+            InvalidCpuAffinity => write!(
+                f,
+                "The CPU affinity must list at least one host CPU, all lower than {}.",
+                MAX_CPUS
+            ),
+            InvalidImpairment => write!(
+                f,
+                "The delay and the jitter of an impairment must be at most {} ms, and its loss \
+                 and duplicate percentages between 0 and 100.",
+                MAX_IMPAIRMENT_DELAY.as_millis()
+            ),
+            InvalidMtu(mtu) => write!(
+                f,
+                "Invalid MTU {}. The MTU must be between {} and {}.",
+                mtu, MIN_MTU, MAX_MTU
+            ),
             OpenTap(ref e) => {
                 // We are propagating the Tap Error. This error can contain
                 // imbricated quotes which would result in an invalid json.
@@ -97,16 +298,39 @@ impl fmt::Display for NetworkInterfaceError {
                     tap_err
                 )
             }
+            SetLinkState(ref e) => write!(f, "Cannot set the link state: {:?}", e),
+            SetTapMtu(mtu, ref e) => write!(
+                f,
+                "Cannot set the MTU of the tap device to {}: {:?}. This needs CAP_NET_ADMIN, \
+                 unless the tap device has this MTU already.",
+                mtu, e
+            ),
+            UnexpectedXdpConfig => write!(
+                f,
+                "The xdp settings only apply to the network interfaces with the xdp backend."
+            ),
+            UnsupportedBackend => write!(
+                f,
+                "The xdp backend is not supported: Firecracker was built without the xdp feature."
+            ),
+            #[cfg(feature = "xdp")]
+            OpenXdp(ref e) => write!(f, "Cannot open the AF_XDP socket: {}", e),
         }
     }
 }
 
+impl std::error::Error for NetworkInterfaceError {}
+
 type Result<T> = result::Result<T, NetworkInterfaceError>;
 
 /// Builder for a list of network devices.
 #[derive(Default)]
 pub struct NetBuilder {
     net_devices: Vec<Arc<Mutex<Net>>>,
+    // The configurations the network devices were created from, by interface ID.
+    configs: HashMap<String, NetworkInterfaceConfig>,
+    // The descriptors of `/dev/net/tun` opened on behalf of Firecracker.
+    preopened_fds: Arc<PreopenedFds>,
 }
 
 impl NetBuilder {
@@ -115,9 +339,17 @@ impl NetBuilder {
         NetBuilder {
             /// List of built network devices.
             net_devices: Vec::new(),
+            configs: HashMap::new(),
+            preopened_fds: Arc::new(PreopenedFds::default()),
         }
     }
 
+    /// Attaches the taps of the network devices built from now on to the descriptors of
+    /// `/dev/net/tun` in `preopened_fds`, instead of opening it.
+    pub fn set_preopened_fds(&mut self, preopened_fds: Arc<PreopenedFds>) {
+        self.preopened_fds = preopened_fds;
+    }
+
     /// Returns a immutable iterator over the network devices.
     pub fn iter(&self) -> ::std::slice::Iter<Arc<Mutex<Net>>> {
         self.net_devices.iter()
@@ -128,9 +360,79 @@ impl NetBuilder {
         self.net_devices.iter_mut()
     }
 
+    /// Returns the host CPUs the emulation of the network device `iface_id` runs on, if it runs
+    /// on a thread of its own.
+    pub fn affinity(&self, iface_id: &str) -> Option<&[usize]> {
+        self.configs
+            .get(iface_id)
+            .and_then(|config| config.affinity.as_ref())
+            .map(Vec::as_slice)
+    }
+
+    /// Returns the configurations of the network devices, in the order of the list.
+    pub fn configs(&self) -> Vec<NetworkInterfaceConfig> {
+        self.net_devices
+            .iter()
+            .filter_map(|net| self.configs.get(net.lock().unwrap().id()))
+            .cloned()
+            .collect()
+    }
+
+    /// Records the rate limiters and the impairments `update` sets on a network device after
+    /// boot in its configuration.
+    pub fn update_config(&mut self, update: &NetworkInterfaceUpdateConfig) {
+        let config = match self.configs.get_mut(&update.iface_id) {
+            Some(config) => config,
+            None => return,
+        };
+        if let Some(ref rate_limiter) = update.rx_rate_limiter {
+            config
+                .rx_rate_limiter
+                .get_or_insert_with(RateLimiterConfig::default)
+                .update(rate_limiter);
+        }
+        if let Some(ref rate_limiter) = update.tx_rate_limiter {
+            config
+                .tx_rate_limiter
+                .get_or_insert_with(RateLimiterConfig::default)
+                .update(rate_limiter);
+        }
+        if update.rx_impairment.is_some() {
+            config.rx_impairment = update.rx_impairment.clone();
+        }
+        if update.tx_impairment.is_some() {
+            config.tx_impairment = update.tx_impairment.clone();
+        }
+    }
+
+    /// Checks the parameters of the network device `netif_config` describes, without opening
+    /// its tap nor its XDP socket.
+    pub fn validate(netif_config: &NetworkInterfaceConfig) -> Result<()> {
+        if let Some(cpus) = netif_config.affinity.as_ref() {
+            if cpus.is_empty() || cpus.iter().any(|&cpu| cpu >= MAX_CPUS) {
+                return Err(NetworkInterfaceError::InvalidCpuAffinity);
+            }
+        }
+        if let Some(mtu) = netif_config.mtu {
+            if mtu < MIN_MTU || mtu > MAX_MTU {
+                return Err(NetworkInterfaceError::InvalidMtu(mtu));
+            }
+        }
+        if netif_config.xdp.is_some() && netif_config.backend != NetBackendType::Xdp {
+            return Err(NetworkInterfaceError::UnexpectedXdpConfig);
+        }
+        let impairments = netif_config.rx_impairment.iter();
+        for impairment in impairments.chain(&netif_config.tx_impairment) {
+            TryInto::<ImpairmentParams>::try_into(impairment.clone())?;
+        }
+        Ok(())
+    }
+
     /// Builds a network device based on a network interface config. Keeps a device reference
     /// in the builder's internal list.
     pub fn build(&mut self, netif_config: NetworkInterfaceConfig) -> Result<Arc<Mutex<Net>>> {
+        Self::validate(&netif_config)?;
+
         let mac_conflict = |net: &Arc<Mutex<Net>>| {
             let net = net.lock().unwrap();
             // Check if another net dev has same MAC.
@@ -157,14 +459,37 @@ impl NetBuilder {
         }
 
         // Add new device.
-        let net = Arc::new(Mutex::new(Self::create_net(netif_config)?));
+        let iface_id = String::from(netif_config.iface_id.as_str());
+        let tun = match netif_config.backend {
+            NetBackendType::Tap => self
+                .preopened_fds
+                .tun(&netif_config.host_dev_name)
+                .transpose()
+                .map_err(|e| NetworkInterfaceError::OpenTap(TapError::OpenTun(e)))?,
+            NetBackendType::Xdp => None,
+        };
+        let net = Arc::new(Mutex::new(Self::create_net(netif_config.clone(), tun)?));
         self.net_devices.push(net.clone());
+        self.configs.insert(iface_id, netif_config);
 
         Ok(net)
     }
 
-    /// Creates a Net device from a NetworkInterfaceConfig.
-    pub fn create_net(cfg: NetworkInterfaceConfig) -> Result<Net> {
+    /// Removes the network device `iface_id` from the list, keeping the order of the others.
+    pub fn remove(&mut self, iface_id: &str) -> Result<()> {
+        let index = self
+            .net_devices
+            .iter()
+            .position(|net| net.lock().unwrap().id() == iface_id)
+            .ok_or(NetworkInterfaceError::DeviceIdNotFound)?;
+        self.net_devices.remove(index);
+        self.configs.remove(iface_id);
+        Ok(())
+    }
+
+    /// Creates a Net device from a NetworkInterfaceConfig. The tap of the device is attached to
+    /// `tun` if it is a descriptor of `/dev/net/tun` already opened.
+    pub fn create_net(cfg: NetworkInterfaceConfig, tun: Option<File>) -> Result<Net> {
         let rx_rate_limiter = cfg
             .rx_rate_limiter
             .map(super::RateLimiterConfig::try_into)
@@ -175,17 +500,88 @@ impl NetBuilder {
             .map(super::RateLimiterConfig::try_into)
             .transpose()
             .map_err(NetworkInterfaceError::CreateRateLimiter)?;
+        let rx_impairment: Option<ImpairmentParams> = cfg
+            .rx_impairment
+            .clone()
+            .map(TryInto::try_into)
+            .transpose()?;
+        let tx_impairment: Option<ImpairmentParams> = cfg
+            .tx_impairment
+            .clone()
+            .map(TryInto::try_into)
+            .transpose()?;
+
+        let backend = match cfg.backend {
+            NetBackendType::Tap => None,
+            NetBackendType::Xdp => Some(Self::open_xdp(&cfg)?),
+        };
+        let tap = tun
+            .map(|tun| Tap::attach(tun, &cfg.host_dev_name))
+            .transpose()
+            .map_err(NetworkInterfaceError::OpenTap)?;
 
         // Create and return the Net device
-        devices::virtio::net::Net::new_with_tap(
-            cfg.iface_id,
-            cfg.host_dev_name.clone(),
-            cfg.guest_mac.as_ref(),
-            rx_rate_limiter.unwrap_or_default(),
-            tx_rate_limiter.unwrap_or_default(),
-            cfg.allow_mmds_requests,
-        )
-        .map_err(NetworkInterfaceError::CreateNetworkDevice)
+        let mtu = cfg.mtu;
+        let mut net = match (backend, tap) {
+            (Some(backend), _) => Net::new_with_backend(
+                cfg.iface_id.into(),
+                cfg.host_dev_name.clone(),
+                backend,
+                cfg.guest_mac.as_ref(),
+                cfg.mtu,
+                rx_rate_limiter.unwrap_or_default(),
+                tx_rate_limiter.unwrap_or_default(),
+                cfg.allow_mmds_requests,
+            ),
+            (None, Some(tap)) => Net::new_with_opened_tap(
+                cfg.iface_id.into(),
+                tap,
+                cfg.guest_mac.as_ref(),
+                cfg.mtu,
+                rx_rate_limiter.unwrap_or_default(),
+                tx_rate_limiter.unwrap_or_default(),
+                cfg.allow_mmds_requests,
+            ),
+            (None, None) => Net::new_with_tap(
+                cfg.iface_id.into(),
+                cfg.host_dev_name.clone(),
+                cfg.guest_mac.as_ref(),
+                cfg.mtu,
+                rx_rate_limiter.unwrap_or_default(),
+                tx_rate_limiter.unwrap_or_default(),
+                cfg.allow_mmds_requests,
+            ),
+        }
+        .map_err(|err| match (err, mtu) {
+            (devices::virtio::net::Error::TapSetMtu(err), Some(mtu)) => {
+                NetworkInterfaceError::SetTapMtu(mtu, err)
+            }
+            (err, _) => NetworkInterfaceError::CreateNetworkDevice(err),
+        })?;
+        net.set_promisc_allowed(cfg.allow_promiscuous);
+        net.set_impairments(rx_impairment, tx_impairment);
+        Ok(net)
+    }
+
+    // Opens the `AF_XDP` socket bound to the queue of the host NIC.
+    #[cfg(feature = "xdp")]
+    fn open_xdp(cfg: &NetworkInterfaceConfig) -> Result<Box<dyn NetBackend>> {
+        let xdp_config = cfg.xdp.clone().unwrap_or_default();
+        let options = XdpOptions {
+            queue_id: xdp_config.queue_id,
+            ring_size: xdp_config.ring_size,
+            umem_frames: xdp_config.umem_frames,
+            zero_copy: xdp_config.zero_copy,
+            xsks_map: xdp_config.xsks_map.map(PathBuf::from),
+        };
+        let socket = XdpSocket::open(&cfg.host_dev_name, cfg.mtu, &options)
+            .map_err(NetworkInterfaceError::OpenXdp)?;
+        Ok(Box::new(socket))
+    }
+
+    #[cfg(not(feature = "xdp"))]
+    fn open_xdp(_: &NetworkInterfaceConfig) -> Result<Box<dyn NetBackend>> {
+        Err(NetworkInterfaceError::UnsupportedBackend)
     }
 
     #[cfg(test)]
@@ -201,31 +597,28 @@ impl NetBuilder {
 
 #[cfg(test)]
 mod tests {
+    use std::convert::TryFrom;
+    use std::fs::OpenOptions;
+    use std::os::unix::io::IntoRawFd;
     use std::str;
 
     use super::*;
 
     fn create_netif(id: &str, name: &str, mac: &str) -> NetworkInterfaceConfig {
         NetworkInterfaceConfig {
-            iface_id: String::from(id),
+            iface_id: Identifier::try_from(id).unwrap(),
             host_dev_name: String::from(name),
+            backend: NetBackendType::Tap,
+            xdp: None,
             guest_mac: Some(MacAddr::parse_str(mac).unwrap()),
+            mtu: None,
             rx_rate_limiter: Some(RateLimiterConfig::default()),
             tx_rate_limiter: Some(RateLimiterConfig::default()),
             allow_mmds_requests: false,
-        }
-    }
-
-    impl Clone for NetworkInterfaceConfig {
-        fn clone(&self) -> Self {
-            NetworkInterfaceConfig {
-                iface_id: self.iface_id.clone(),
-                host_dev_name: self.host_dev_name.clone(),
-                guest_mac: self.guest_mac,
-                rx_rate_limiter: None,
-                tx_rate_limiter: None,
-                allow_mmds_requests: self.allow_mmds_requests,
-            }
+            allow_promiscuous: true,
+            rx_impairment: None,
+            tx_impairment: None,
+            affinity: None,
         }
     }
 
@@ -339,6 +732,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_affinity() {
+        let mut net_builder = NetBuilder::new();
+
+        let mut netif = create_netif("id_1", "dev5", "01:23:45:67:89:0c");
+        netif.affinity = Some(vec![]);
+        match net_builder.build(netif.clone()) {
+            Err(NetworkInterfaceError::InvalidCpuAffinity) => (),
+            _ => panic!("Unexpected result"),
+        }
+        netif.affinity = Some(vec![0, MAX_CPUS]);
+        match net_builder.build(netif.clone()) {
+            Err(NetworkInterfaceError::InvalidCpuAffinity) => (),
+            _ => panic!("Unexpected result"),
+        }
+        assert!(net_builder.is_empty());
+
+        netif.affinity = Some(vec![0, 1]);
+        assert!(net_builder.build(netif.clone()).is_ok());
+        assert_eq!(net_builder.affinity("id_1"), Some(&[0, 1][..]));
+
+        // Updating the device without an affinity moves it back to the VMM thread.
+        netif.affinity = None;
+        assert!(net_builder.build(netif).is_ok());
+        assert_eq!(net_builder.affinity("id_1"), None);
+    }
+
+    #[test]
+    fn test_mtu() {
+        let mut net_builder = NetBuilder::new();
+
+        let mut netif = create_netif("id_1", "dev6", "01:23:45:67:89:0d");
+        for &mtu in &[0, MIN_MTU - 1, MAX_MTU + 1] {
+            netif.mtu = Some(mtu);
+            match net_builder.build(netif.clone()) {
+                Err(NetworkInterfaceError::InvalidMtu(err_mtu)) => assert_eq!(err_mtu, mtu),
+                _ => panic!("Unexpected result"),
+            }
+        }
+        assert!(net_builder.is_empty());
+
+        netif.mtu = Some(9000);
+        let net = net_builder.build(netif).unwrap();
+        assert_eq!(net.lock().unwrap().mtu(), Some(9000));
+    }
+
     #[test]
     fn test_error_display() {
         // FIXME: use macro
@@ -353,11 +792,99 @@ mod tests {
             NetworkInterfaceError::DeviceIdNotFound,
             NetworkInterfaceError::DeviceIdNotFound
         );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::InvalidCpuAffinity,
+            NetworkInterfaceError::InvalidCpuAffinity
+        );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::InvalidMtu(0),
+            NetworkInterfaceError::InvalidMtu(0)
+        );
         let _ = format!(
             "{}{:?}",
             NetworkInterfaceError::OpenTap(TapError::InvalidIfname),
             NetworkInterfaceError::OpenTap(TapError::InvalidIfname)
         );
+        let err = NetworkInterfaceError::SetTapMtu(
+            9000,
+            TapError::IoctlError(std::io::Error::from_raw_os_error(0)),
+        );
+        let _ = format!("{}{:?}", err, err);
+        let err = NetworkInterfaceError::SetLinkState(devices::Error::FailedSignalingUsedQueue(
+            std::io::Error::from_raw_os_error(0),
+        ));
+        let _ = format!("{}{:?}", err, err);
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::UnexpectedXdpConfig,
+            NetworkInterfaceError::UnexpectedXdpConfig
+        );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::UnsupportedBackend,
+            NetworkInterfaceError::UnsupportedBackend
+        );
+        #[cfg(feature = "xdp")]
+        {
+            let err = NetworkInterfaceError::OpenXdp(xdp::Error::InvalidRingSize(3));
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+
+    #[test]
+    fn test_xdp_config() {
+        let config: NetworkInterfaceConfig = serde_json::from_str(
+            r#"{"iface_id": "eth0", "host_dev_name": "eth0", "backend": "xdp",
+                "xdp": {"queue_id": 3, "zero_copy": false}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.backend, NetBackendType::Xdp);
+        assert_eq!(
+            config.xdp.unwrap(),
+            XdpConfig {
+                queue_id: 3,
+                zero_copy: false,
+                ..Default::default()
+            }
+        );
+
+        let config: NetworkInterfaceConfig =
+            serde_json::from_str(r#"{"iface_id": "eth0", "host_dev_name": "tap0"}"#).unwrap();
+        assert_eq!(config.backend, NetBackendType::Tap);
+        assert!(config.xdp.is_none());
+
+        assert!(serde_json::from_str::<NetworkInterfaceConfig>(
+            r#"{"iface_id": "eth0", "host_dev_name": "eth0", "backend": "vhost"}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<NetworkInterfaceConfig>(
+            r#"{"iface_id": "eth0", "host_dev_name": "eth0", "xdp": {"rings": 64}}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_xdp_backend() {
+        let mut net_builder = NetBuilder::new();
+
+        let mut netif = create_netif("id_1", "fcnoxdp0", "01:23:45:67:89:0f");
+        netif.xdp = Some(XdpConfig::default());
+        match net_builder.build(netif.clone()) {
+            Err(NetworkInterfaceError::UnexpectedXdpConfig) => (),
+            _ => panic!("Unexpected result"),
+        }
+
+        netif.backend = NetBackendType::Xdp;
+        match net_builder.build(netif) {
+            #[cfg(not(feature = "xdp"))]
+            Err(NetworkInterfaceError::UnsupportedBackend) => (),
+            #[cfg(feature = "xdp")]
+            Err(NetworkInterfaceError::OpenXdp(xdp::Error::InterfaceNotFound(_))) => (),
+            _ => panic!("Unexpected result"),
+        }
+        assert!(net_builder.is_empty());
     }
 
     #[test]
@@ -373,4 +900,147 @@ mod tests {
         );
         assert_eq!(net_if.allow_mmds_requests, false);
     }
+
+    #[test]
+    fn test_allow_promiscuous() {
+        let mut net_builder = NetBuilder::new();
+
+        let mut netif = create_netif("id_1", "dev7", "01:23:45:67:89:0e");
+        let net = net_builder.build(netif.clone()).unwrap();
+        assert!(net.lock().unwrap().rx_filter().allow_promisc());
+
+        netif.allow_promiscuous = false;
+        let net = net_builder.build(netif).unwrap();
+        assert!(!net.lock().unwrap().rx_filter().allow_promisc());
+        assert!(!net.lock().unwrap().rx_filter().promisc());
+
+        // The promiscuous mode is allowed by default.
+        let netif = serde_json::from_str::<NetworkInterfaceConfig>(
+            r#"{"iface_id": "id_2", "host_dev_name": "dev8"}"#,
+        )
+        .unwrap();
+        assert!(netif.allow_promiscuous);
+    }
+
+    #[test]
+    fn test_impairments() {
+        let mut net_builder = NetBuilder::new();
+
+        let mut netif = create_netif("id_1", "dev9", "01:23:45:67:89:10");
+        netif.tx_impairment = Some(NetworkImpairmentConfig {
+            delay_ms: 100,
+            jitter_ms: 10,
+            loss_percent: 1.5,
+            duplicate_percent: 0.0,
+        });
+        let net = net_builder.build(netif.clone()).unwrap();
+        assert_eq!(
+            *net.lock().unwrap().tx_impairment(),
+            ImpairmentParams {
+                delay: Duration::from_millis(100),
+                jitter: Duration::from_millis(10),
+                loss: 0.015,
+                duplication: 0.0,
+            }
+        );
+        assert_eq!(
+            *net.lock().unwrap().rx_impairment(),
+            ImpairmentParams::default()
+        );
+
+        // An invalid update leaves the interface as it is.
+        netif.rx_impairment = Some(NetworkImpairmentConfig {
+            duplicate_percent: 101.0,
+            ..Default::default()
+        });
+        match net_builder.build(netif.clone()) {
+            Err(NetworkInterfaceError::InvalidImpairment) => (),
+            _ => panic!("Unexpected result"),
+        }
+        netif.rx_impairment = Some(NetworkImpairmentConfig {
+            jitter_ms: 60_001,
+            ..Default::default()
+        });
+        match net_builder.build(netif.clone()) {
+            Err(NetworkInterfaceError::InvalidImpairment) => (),
+            _ => panic!("Unexpected result"),
+        }
+        netif.rx_impairment = Some(NetworkImpairmentConfig {
+            delay_ms: u64::max_value(),
+            ..Default::default()
+        });
+        match net_builder.build(netif) {
+            Err(NetworkInterfaceError::InvalidImpairment) => (),
+            _ => panic!("Unexpected result"),
+        }
+        assert_eq!(net_builder.len(), 1);
+
+        // The updates made after boot are recorded in the configuration.
+        let bandwidth = super::super::TokenBucketConfig {
+            size: 1000,
+            one_time_burst: None,
+            refill_time: 100,
+        };
+        let rx_impairment = NetworkImpairmentConfig {
+            loss_percent: 5.0,
+            ..Default::default()
+        };
+        net_builder.update_config(&NetworkInterfaceUpdateConfig {
+            iface_id: "id_1".to_string(),
+            rx_rate_limiter: Some(RateLimiterConfig {
+                bandwidth: Some(bandwidth),
+                ops: None,
+            }),
+            tx_rate_limiter: None,
+            rx_impairment: Some(rx_impairment.clone()),
+            tx_impairment: None,
+        });
+        let config = net_builder.configs().remove(0);
+        assert_eq!(config.rx_rate_limiter.unwrap().bandwidth, Some(bandwidth));
+        assert_eq!(config.tx_rate_limiter, None);
+        assert_eq!(config.rx_impairment, Some(rx_impairment));
+        assert_eq!(config.tx_impairment.unwrap().delay_ms, 100);
+
+        assert!(serde_json::from_str::<NetworkImpairmentConfig>(r#"{"delay": 10}"#).is_err());
+        assert_eq!(
+            serde_json::from_str::<NetworkImpairmentConfig>(r#"{"loss_percent": 5}"#).unwrap(),
+            NetworkImpairmentConfig {
+                loss_percent: 5.0,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_preopened_tun() {
+        let tun_fd = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/net/tun")
+            .unwrap()
+            .into_raw_fd();
+        let null_fd = File::open("/dev/null").unwrap().into_raw_fd();
+        let tun_fds = format!("dev9={},dev10={}", tun_fd, null_fd);
+        let preopened_fds = unsafe { PreopenedFds::from_args(None, Some(&tun_fds), None) }.unwrap();
+        let mut net_builder = NetBuilder::new();
+        net_builder.set_preopened_fds(Arc::new(preopened_fds));
+
+        // The tap is attached to the descriptor given for its interface.
+        let net = net_builder
+            .build(create_netif("id_1", "dev9", "01:23:45:67:89:0f"))
+            .unwrap();
+        assert_eq!(net.lock().unwrap().tap_if_name(), "dev9");
+        assert!(net.lock().unwrap().is_tap_backed());
+
+        // A descriptor which isn't of `/dev/net/tun` can't back a tap.
+        match net_builder.build(create_netif("id_2", "dev10", "01:23:45:67:89:10")) {
+            Err(NetworkInterfaceError::OpenTap(TapError::CreateTap(_))) => (),
+            _ => panic!("Only a descriptor of /dev/net/tun can back a tap."),
+        }
+
+        // The other interfaces open `/dev/net/tun`.
+        assert!(net_builder
+            .build(create_netif("id_3", "dev11", "01:23:45:67:89:11"))
+            .is_ok());
+    }
 }
@@ -0,0 +1,272 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+
+#[cfg(target_arch = "x86_64")]
+use arch::x86_64::layout::{PCI_IRQ_BASE, PCI_IRQ_MAX};
+
+/// The largest number of PCI passthrough devices, each of them taking one interrupt line.
+#[cfg(target_arch = "x86_64")]
+pub const MAX_PCI_PASSTHROUGH_DEVICES: usize = (PCI_IRQ_MAX - PCI_IRQ_BASE + 1) as usize;
+
+/// Errors associated with the PCI passthrough devices.
+#[derive(Debug, PartialEq)]
+pub enum PciPassthroughConfigError {
+    /// The host device address is not formatted as `domain:bus:device.function`.
+    InvalidHostBdf(String),
+    /// The host device is not bound to the `vfio-pci` driver, or doesn't exist.
+    HostDeviceNotFound(String),
+    /// The host device is already passed through under another ID.
+    HostDeviceInUse(String),
+    /// No more PCI passthrough devices can be attached to the microVM.
+    TooManyDevices,
+    /// PCI passthrough is not supported on this architecture.
+    NotSupported,
+}
+
+impl fmt::Display for PciPassthroughConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::PciPassthroughConfigError::*;
+        match self {
+            InvalidHostBdf(bdf) => write!(
+                f,
+                "Invalid host device address {}, expected domain:bus:device.function, e.g. \
+                 0000:01:00.0.",
+                bdf
+            ),
+            HostDeviceNotFound(bdf) => write!(
+                f,
+                "The host device {} doesn't exist or doesn't belong to an IOMMU group.",
+                bdf
+            ),
+            HostDeviceInUse(bdf) => {
+                write!(f, "The host device {} is already passed through.", bdf)
+            }
+            TooManyDevices => write!(f, "Too many PCI passthrough devices."),
+            NotSupported => write!(f, "PCI passthrough is not supported on this architecture."),
+        }
+    }
+}
+
+impl std::error::Error for PciPassthroughConfigError {}
+
+type Result<T> = std::result::Result<T, PciPassthroughConfigError>;
+
+/// Passes a host PCI device, bound to the `vfio-pci` driver, through to the guest.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PciPassthroughConfig {
+    /// ID of the device.
+    pub dev_id: String,
+    /// PCI address of the host device, as `domain:bus:device.function`, e.g. `0000:01:00.0`.
+    pub host_bdf: String,
+}
+
+// Checks that `bdf` is formatted as `dddd:bb:dd.f`, in lowercase hexadecimal digits as in sysfs.
+fn validate_host_bdf(bdf: &str) -> Result<()> {
+    let is_hex = |s: &str, len: usize| {
+        s.len() == len
+            && s.chars()
+                .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+    };
+    let invalid = || PciPassthroughConfigError::InvalidHostBdf(bdf.to_string());
+
+    let parts: Vec<&str> = bdf.split(':').collect();
+    if parts.len() != 3 {
+        return Err(invalid());
+    }
+    let mut dev_fn = parts[2].split('.');
+    let (device, function) = match (dev_fn.next(), dev_fn.next(), dev_fn.next()) {
+        (Some(device), Some(function), None) => (device, function),
+        _ => return Err(invalid()),
+    };
+    if !is_hex(parts[0], 4) || !is_hex(parts[1], 2) || !is_hex(device, 2) || !is_hex(function, 1) {
+        return Err(invalid());
+    }
+    // There are 32 devices on a bus, with 8 functions each.
+    match (
+        u8::from_str_radix(device, 16),
+        u8::from_str_radix(function, 16),
+    ) {
+        (Ok(device), Ok(function)) if device < 32 && function < 8 => Ok(()),
+        _ => Err(invalid()),
+    }
+}
+
+/// The PCI passthrough devices to attach to the microVM.
+#[derive(Default)]
+pub struct PciPassthroughBuilder {
+    configs: Vec<PciPassthroughConfig>,
+}
+
+impl PciPassthroughBuilder {
+    /// Creates an empty builder.
+    pub fn new() -> Self {
+        PciPassthroughBuilder {
+            configs: Vec::new(),
+        }
+    }
+
+    /// Adds a PCI passthrough device, or updates the one with the same ID.
+    #[cfg(target_arch = "x86_64")]
+    pub fn insert(&mut self, config: PciPassthroughConfig) -> Result<()> {
+        validate_host_bdf(&config.host_bdf)?;
+        let sysfs_path = devices::vfio::pci_sysfs_path(&config.host_bdf);
+        if !sysfs_path.join("iommu_group").exists() {
+            return Err(PciPassthroughConfigError::HostDeviceNotFound(
+                config.host_bdf,
+            ));
+        }
+        self.add(config)
+    }
+
+    /// Adds a PCI passthrough device, or updates the one with the same ID.
+    #[cfg(target_arch = "aarch64")]
+    pub fn insert(&mut self, _config: PciPassthroughConfig) -> Result<()> {
+        Err(PciPassthroughConfigError::NotSupported)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn add(&mut self, config: PciPassthroughConfig) -> Result<()> {
+        if self
+            .configs
+            .iter()
+            .any(|other| other.host_bdf == config.host_bdf && other.dev_id != config.dev_id)
+        {
+            return Err(PciPassthroughConfigError::HostDeviceInUse(config.host_bdf));
+        }
+        match self
+            .configs
+            .iter_mut()
+            .find(|other| other.dev_id == config.dev_id)
+        {
+            Some(other) => *other = config,
+            None if self.configs.len() >= MAX_PCI_PASSTHROUGH_DEVICES => {
+                return Err(PciPassthroughConfigError::TooManyDevices)
+            }
+            None => self.configs.push(config),
+        }
+        Ok(())
+    }
+
+    /// Returns the configurations of the PCI passthrough devices.
+    pub fn configs(&self) -> &[PciPassthroughConfig] {
+        &self.configs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(dev_id: &str, host_bdf: &str) -> PciPassthroughConfig {
+        PciPassthroughConfig {
+            dev_id: dev_id.to_string(),
+            host_bdf: host_bdf.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_validate_host_bdf() {
+        assert!(validate_host_bdf("0000:01:00.0").is_ok());
+        assert!(validate_host_bdf("ffff:ff:1f.7").is_ok());
+
+        for bdf in &[
+            "",
+            "01:00.0",
+            "0000:01:00",
+            "0000:01:00.0.0",
+            "0000:01:00.8",
+            "0000:01:20.0",
+            "0000:01:0g.0",
+            "0000:0A:00.0",
+            "000:01:00.0",
+            "0000:001:00.0",
+            "0000:01:00.00",
+            "0000:01:00.+1",
+            "0000:01:00:0.0",
+        ] {
+            assert_eq!(
+                validate_host_bdf(bdf),
+                Err(PciPassthroughConfigError::InvalidHostBdf(bdf.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut builder = PciPassthroughBuilder::new();
+        assert!(builder.configs().is_empty());
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            assert_eq!(
+                builder.insert(config("gpu", "0000:01:0z.0")),
+                Err(PciPassthroughConfigError::InvalidHostBdf(
+                    "0000:01:0z.0".to_string()
+                ))
+            );
+            // No host device sits in the last function of the last device of the last bus.
+            assert_eq!(
+                builder.insert(config("gpu", "ffff:ff:1f.7")),
+                Err(PciPassthroughConfigError::HostDeviceNotFound(
+                    "ffff:ff:1f.7".to_string()
+                ))
+            );
+
+            builder.add(config("gpu", "0000:01:00.0")).unwrap();
+            builder.add(config("nic", "0000:02:00.0")).unwrap();
+            // Update the device with the same ID.
+            builder.add(config("gpu", "0000:03:00.0")).unwrap();
+            assert_eq!(
+                builder.configs(),
+                &[config("gpu", "0000:03:00.0"), config("nic", "0000:02:00.0")]
+            );
+            assert_eq!(
+                builder.add(config("nic2", "0000:02:00.0")),
+                Err(PciPassthroughConfigError::HostDeviceInUse(
+                    "0000:02:00.0".to_string()
+                ))
+            );
+
+            for i in builder.configs().len()..MAX_PCI_PASSTHROUGH_DEVICES {
+                builder
+                    .add(config(&i.to_string(), &format!("0000:10:{:02x}.0", i)))
+                    .unwrap();
+            }
+            assert_eq!(
+                builder.add(config("extra", "0000:11:00.0")),
+                Err(PciPassthroughConfigError::TooManyDevices)
+            );
+        }
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            builder.insert(config("gpu", "0000:01:00.0")),
+            Err(PciPassthroughConfigError::NotSupported)
+        );
+    }
+
+    #[test]
+    fn test_config_deserialization() {
+        let config: PciPassthroughConfig =
+            serde_json::from_str(r#"{"dev_id": "gpu", "host_bdf": "0000:01:00.0"}"#).unwrap();
+        assert_eq!(config, self::config("gpu", "0000:01:00.0"));
+        assert!(serde_json::from_str::<PciPassthroughConfig>(r#"{"dev_id": "gpu"}"#).is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::PciPassthroughConfigError::*;
+
+        for err in &[
+            InvalidHostBdf("0000".to_string()),
+            HostDeviceNotFound("0000:01:00.0".to_string()),
+            HostDeviceInUse("0000:01:00.0".to_string()),
+            TooManyDevices,
+            NotSupported,
+        ] {
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+}
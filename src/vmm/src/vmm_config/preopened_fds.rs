@@ -0,0 +1,236 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The file descriptors opened on behalf of Firecracker by the process launching it, for the
+//! sandboxed deployments where `/dev/kvm`, `/dev/net/tun` or the disk images aren't reachable
+//! by path from inside the jail.
+//!
+//! The file descriptors are inherited by Firecracker and named on its command line. The devices
+//! built from them get duplicates, so that they can be reconfigured before the microVM starts.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::result;
+
+/// Errors associated with the pre-opened file descriptors.
+#[derive(Debug)]
+pub enum PreopenedFdsError {
+    /// The file descriptor is not open.
+    ClosedFd(RawFd, io::Error),
+    /// The file descriptor is given more than once.
+    DuplicateFd(RawFd),
+    /// An entry of a list is not `<name>=<fd>`.
+    InvalidEntry(String),
+    /// The file descriptor is not a number above the standard streams.
+    InvalidFd(String),
+}
+
+impl fmt::Display for PreopenedFdsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::PreopenedFdsError::*;
+        match self {
+            ClosedFd(fd, err) => write!(f, "The file descriptor {} is not open: {}", fd, err),
+            DuplicateFd(fd) => write!(f, "The file descriptor {} is given twice.", fd),
+            InvalidEntry(entry) => write!(f, "Invalid entry {}, expected <name>=<fd>.", entry),
+            InvalidFd(fd) => write!(
+                f,
+                "Invalid file descriptor {}, expected a number greater than 2.",
+                fd
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PreopenedFdsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PreopenedFdsError::ClosedFd(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+type Result<T> = result::Result<T, PreopenedFdsError>;
+
+/// The file descriptors opened on behalf of Firecracker, which it uses instead of opening the
+/// files by path.
+#[derive(Debug, Default)]
+pub struct PreopenedFds {
+    kvm: Option<File>,
+    // The descriptors of `/dev/net/tun`, by the name of the TAP interface they are attached to.
+    tuns: HashMap<String, File>,
+    // The disk images, by the `path_on_host` of their drives.
+    disk_images: HashMap<String, File>,
+}
+
+impl PreopenedFds {
+    /// Takes ownership of the inherited file descriptors: `kvm_fd` of `/dev/kvm`, and the
+    /// comma separated `<name>=<fd>` lists `tun_fds`, of `/dev/net/tun` by the name of a TAP
+    /// interface, and `disk_image_fds`, of the disk images by the `path_on_host` of a drive.
+    ///
+    /// The file descriptors are closed on exec.
+    ///
+    /// # Safety
+    ///
+    /// The file descriptors must not be owned by anything else in the process, which is the
+    /// case for the ones inherited, as long as this is called before opening any other file.
+    pub unsafe fn from_args(
+        kvm_fd: Option<&str>,
+        tun_fds: Option<&str>,
+        disk_image_fds: Option<&str>,
+    ) -> Result<Self> {
+        let mut adopted = Vec::new();
+        let mut adopt = |fd: &str| -> Result<File> {
+            let fd = fd
+                .parse::<RawFd>()
+                .ok()
+                .filter(|&fd| fd > libc::STDERR_FILENO)
+                .ok_or_else(|| PreopenedFdsError::InvalidFd(fd.to_string()))?;
+            if adopted.contains(&fd) {
+                return Err(PreopenedFdsError::DuplicateFd(fd));
+            }
+            if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) < 0 {
+                return Err(PreopenedFdsError::ClosedFd(fd, io::Error::last_os_error()));
+            }
+            adopted.push(fd);
+            Ok(File::from_raw_fd(fd))
+        };
+
+        let kvm = kvm_fd.map(|fd| adopt(fd)).transpose()?;
+        let mut tuns = HashMap::new();
+        for (name, fd) in parse_list(tun_fds)? {
+            tuns.insert(name.to_string(), adopt(fd)?);
+        }
+        let mut disk_images = HashMap::new();
+        for (path, fd) in parse_list(disk_image_fds)? {
+            disk_images.insert(path.to_string(), adopt(fd)?);
+        }
+        Ok(PreopenedFds {
+            kvm,
+            tuns,
+            disk_images,
+        })
+    }
+
+    /// Returns a duplicate of the descriptor of `/dev/kvm`, if it was given.
+    pub fn kvm(&self) -> Option<io::Result<File>> {
+        self.kvm.as_ref().map(File::try_clone)
+    }
+
+    /// Returns a duplicate of the descriptor of `/dev/net/tun` for the TAP interface `if_name`,
+    /// if it was given.
+    pub fn tun(&self, if_name: &str) -> Option<io::Result<File>> {
+        self.tuns.get(if_name).map(File::try_clone)
+    }
+
+    /// Returns a duplicate of the descriptor of the disk image at `path_on_host`, if it was
+    /// given.
+    pub fn disk_image(&self, path_on_host: &str) -> Option<io::Result<File>> {
+        self.disk_images.get(path_on_host).map(File::try_clone)
+    }
+}
+
+// Splits the `<name>=<fd>` entries of `list`. The names may contain `=`, but not `,`.
+fn parse_list(list: Option<&str>) -> Result<Vec<(&str, &str)>> {
+    list.into_iter()
+        .flat_map(|list| list.split(','))
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let mut parts = entry.rsplitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(fd), Some(name)) if !name.is_empty() => Ok((name, fd)),
+                _ => Err(PreopenedFdsError::InvalidEntry(entry.to_string())),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::os::unix::io::{AsRawFd, IntoRawFd};
+
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_list() {
+        assert!(parse_list(None).unwrap().is_empty());
+        assert_eq!(
+            parse_list(Some("tap0=3,tap1=4,")).unwrap(),
+            vec![("tap0", "3"), ("tap1", "4")]
+        );
+        assert_eq!(
+            parse_list(Some("/images/a=b.ext4=5")).unwrap(),
+            vec![("/images/a=b.ext4", "5")]
+        );
+        assert_eq!(
+            parse_list(Some("tap0=3,tap1")).unwrap_err().to_string(),
+            "Invalid entry tap1, expected <name>=<fd>."
+        );
+        assert!(parse_list(Some("=3")).is_err());
+    }
+
+    #[test]
+    fn test_from_args() {
+        let tmp_file = TempFile::new().unwrap();
+        tmp_file.as_file().write_all(b"disk").unwrap();
+        let disk_image_fd = File::open(tmp_file.as_path()).unwrap().into_raw_fd();
+        let kvm_fd = File::open("/dev/kvm").unwrap().into_raw_fd();
+        let tun_fd = File::open("/dev/null").unwrap().into_raw_fd();
+        let path = tmp_file.as_path().to_str().unwrap();
+
+        let fds = unsafe {
+            PreopenedFds::from_args(
+                Some(&kvm_fd.to_string()),
+                Some(&format!("tap0={}", tun_fd)),
+                Some(&format!("{}={}", path, disk_image_fd)),
+            )
+        }
+        .unwrap();
+        let flags = unsafe { libc::fcntl(kvm_fd, libc::F_GETFD) };
+        assert_eq!(flags & libc::FD_CLOEXEC, libc::FD_CLOEXEC);
+
+        // The devices get duplicates.
+        let kvm = fds.kvm().unwrap().unwrap();
+        assert_ne!(kvm.as_raw_fd(), kvm_fd);
+        assert!(fds.tun("tap0").unwrap().is_ok());
+        assert!(fds.tun("tap1").is_none());
+        let mut disk_image = fds.disk_image(path).unwrap().unwrap();
+        let mut content = String::new();
+        disk_image.seek(SeekFrom::Start(0)).unwrap();
+        disk_image.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "disk");
+        assert!(fds.disk_image("/invalid/path").is_none());
+        assert!(PreopenedFds::default().kvm().is_none());
+    }
+
+    #[test]
+    fn test_from_args_errors() {
+        let fd = File::open("/dev/null").unwrap().into_raw_fd();
+        let fd_list = format!("tap0={},tap1={}", fd, fd);
+        match unsafe { PreopenedFds::from_args(None, Some(&fd_list), None) } {
+            Err(PreopenedFdsError::DuplicateFd(dup_fd)) => assert_eq!(dup_fd, fd),
+            _ => panic!("A file descriptor given twice should be rejected."),
+        }
+        match unsafe { PreopenedFds::from_args(Some("1000000"), None, None) } {
+            Err(PreopenedFdsError::ClosedFd(closed_fd, _)) => assert_eq!(closed_fd, 1_000_000),
+            _ => panic!("A closed file descriptor should be rejected."),
+        }
+
+        for invalid_fd in &["kvm", "-1", "2"] {
+            match unsafe { PreopenedFds::from_args(Some(invalid_fd), None, None) } {
+                Err(PreopenedFdsError::InvalidFd(_)) => (),
+                _ => panic!("Invalid file descriptor {} should be rejected.", invalid_fd),
+            }
+        }
+        assert_eq!(
+            PreopenedFdsError::InvalidFd("2".to_string()).to_string(),
+            "Invalid file descriptor 2, expected a number greater than 2."
+        );
+    }
+}
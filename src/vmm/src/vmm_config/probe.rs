@@ -0,0 +1,375 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::path::PathBuf;
+
+use vmm_config::vsock::{VsockBackendType, VsockDeviceConfig};
+
+/// The shortest period between two evaluations of a probe.
+pub const MIN_PROBE_PERIOD_MS: u64 = 100;
+/// The largest number of probes of a microVM.
+pub const MAX_PROBES: usize = 16;
+
+/// Errors associated with the probes.
+#[derive(Debug, PartialEq)]
+pub enum ProbeConfigError {
+    /// The serial pattern of the probe is empty.
+    EmptyPattern,
+    /// The failure threshold of the probe is 0.
+    InvalidFailureThreshold,
+    /// The period of the probe is shorter than `MIN_PROBE_PERIOD_MS`.
+    InvalidPeriod(u64),
+    /// The vsock device the probe connects through doesn't exist, or has no Unix socket.
+    InvalidVsockDevice(Option<String>),
+    /// No more probes can be added to the microVM.
+    TooManyProbes,
+}
+
+impl fmt::Display for ProbeConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ProbeConfigError::*;
+        match self {
+            EmptyPattern => write!(f, "The serial pattern of the probe is empty."),
+            InvalidFailureThreshold => write!(f, "The failure threshold must be at least 1."),
+            InvalidPeriod(period_ms) => write!(
+                f,
+                "Invalid probe period {} ms, it must be at least {} ms.",
+                period_ms, MIN_PROBE_PERIOD_MS
+            ),
+            InvalidVsockDevice(Some(vsock_id)) => write!(
+                f,
+                "The vsock device {} doesn't exist or has no Unix socket.",
+                vsock_id
+            ),
+            InvalidVsockDevice(None) => {
+                write!(f, "There is no vsock device with a Unix socket to probe.")
+            }
+            TooManyProbes => write!(f, "Too many probes."),
+        }
+    }
+}
+
+impl std::error::Error for ProbeConfigError {}
+
+type Result<T> = std::result::Result<T, ProbeConfigError>;
+
+/// What the control plane learns from a probe.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeKind {
+    /// Whether the workload inside the guest is up and ready to serve.
+    Readiness,
+    /// Whether the workload inside the guest is still alive.
+    Liveness,
+}
+
+/// How the VMM checks the guest.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ProbeCheck {
+    /// Passes when the guest accepts a connection on the vsock `port`. The connection goes
+    /// through the Unix socket of the vsock device `vsock_id`, or of the first vsock device.
+    VsockConnect {
+        /// The guest vsock port to connect to.
+        port: u32,
+        /// The ID of the vsock device to connect through.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        vsock_id: Option<String>,
+    },
+    /// Passes once the guest printed `pattern` on the serial console.
+    SerialPattern {
+        /// The text to look for in the output of the serial console.
+        pattern: String,
+    },
+    /// Passes when the guest sent packets to the MMDS since the previous evaluation, e.g. a
+    /// guest agent polling its metadata.
+    MmdsHeartbeat,
+}
+
+fn default_period_ms() -> u64 {
+    1000
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+/// Configures a check of the workload inside the guest, evaluated periodically by the VMM once
+/// the microVM is started.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProbeConfig {
+    /// ID of the probe.
+    pub probe_id: String,
+    /// What the probe tells the control plane.
+    pub kind: ProbeKind,
+    /// How the guest is checked.
+    pub check: ProbeCheck,
+    /// Time from the start of the microVM to the first evaluation, on top of the period.
+    #[serde(default)]
+    pub initial_delay_ms: u64,
+    /// Time between two evaluations.
+    #[serde(default = "default_period_ms")]
+    pub period_ms: u64,
+    /// Number of consecutive failed evaluations after which the probe fails.
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+}
+
+impl ProbeConfig {
+    fn validate(&self) -> Result<()> {
+        if self.period_ms < MIN_PROBE_PERIOD_MS {
+            return Err(ProbeConfigError::InvalidPeriod(self.period_ms));
+        }
+        if self.failure_threshold == 0 {
+            return Err(ProbeConfigError::InvalidFailureThreshold);
+        }
+        match &self.check {
+            ProbeCheck::SerialPattern { pattern } if pattern.is_empty() => {
+                Err(ProbeConfigError::EmptyPattern)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns the Unix socket of the vsock device `vsock_id`, or of the first vsock device, among
+/// `vsock_configs`.
+pub fn vsock_uds_path(
+    vsock_configs: &[VsockDeviceConfig],
+    vsock_id: Option<&String>,
+) -> Result<PathBuf> {
+    vsock_configs
+        .iter()
+        .find(|config| match vsock_id {
+            Some(vsock_id) => config.vsock_id.as_str() == vsock_id.as_str(),
+            None => true,
+        })
+        .filter(|config| config.backend == VsockBackendType::Uds)
+        .map(|config| PathBuf::from(&config.uds_path))
+        .ok_or_else(|| ProbeConfigError::InvalidVsockDevice(vsock_id.cloned()))
+}
+
+/// The probes of the microVM.
+#[derive(Default)]
+pub struct ProbeBuilder {
+    configs: Vec<ProbeConfig>,
+}
+
+impl ProbeBuilder {
+    /// Creates an empty builder.
+    pub fn new() -> Self {
+        ProbeBuilder {
+            configs: Vec::new(),
+        }
+    }
+
+    /// Adds a probe, or updates the one with the same ID. The vsock probes have to connect
+    /// through one of `vsock_configs`.
+    pub fn insert(
+        &mut self,
+        config: ProbeConfig,
+        vsock_configs: &[VsockDeviceConfig],
+    ) -> Result<()> {
+        config.validate()?;
+        if let ProbeCheck::VsockConnect { vsock_id, .. } = &config.check {
+            vsock_uds_path(vsock_configs, vsock_id.as_ref())?;
+        }
+        match self
+            .configs
+            .iter_mut()
+            .find(|other| other.probe_id == config.probe_id)
+        {
+            Some(other) => *other = config,
+            None if self.configs.len() >= MAX_PROBES => {
+                return Err(ProbeConfigError::TooManyProbes)
+            }
+            None => self.configs.push(config),
+        }
+        Ok(())
+    }
+
+    /// Returns the configurations of the probes.
+    pub fn configs(&self) -> &[ProbeConfig] {
+        &self.configs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use vmm_config::Identifier;
+
+    fn config(probe_id: &str, check: ProbeCheck) -> ProbeConfig {
+        ProbeConfig {
+            probe_id: probe_id.to_string(),
+            kind: ProbeKind::Readiness,
+            check,
+            initial_delay_ms: 0,
+            period_ms: default_period_ms(),
+            failure_threshold: default_failure_threshold(),
+        }
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut builder = ProbeBuilder::new();
+        assert!(builder.configs().is_empty());
+
+        let mut invalid = config("ready", ProbeCheck::MmdsHeartbeat);
+        invalid.period_ms = 10;
+        assert_eq!(
+            builder.insert(invalid.clone(), &[]),
+            Err(ProbeConfigError::InvalidPeriod(10))
+        );
+        invalid.period_ms = MIN_PROBE_PERIOD_MS;
+        invalid.failure_threshold = 0;
+        assert_eq!(
+            builder.insert(invalid, &[]),
+            Err(ProbeConfigError::InvalidFailureThreshold)
+        );
+        assert_eq!(
+            builder.insert(
+                config(
+                    "ready",
+                    ProbeCheck::SerialPattern {
+                        pattern: String::new()
+                    }
+                ),
+                &[]
+            ),
+            Err(ProbeConfigError::EmptyPattern)
+        );
+
+        builder
+            .insert(config("ready", ProbeCheck::MmdsHeartbeat), &[])
+            .unwrap();
+        // The vsock probes connect through a vsock device with a Unix socket.
+        let alive = config(
+            "alive",
+            ProbeCheck::VsockConnect {
+                port: 52,
+                vsock_id: None,
+            },
+        );
+        assert_eq!(
+            builder.insert(alive.clone(), &[]),
+            Err(ProbeConfigError::InvalidVsockDevice(None))
+        );
+        let mut vsock_config = VsockDeviceConfig {
+            vsock_id: Identifier::try_from("vsock").unwrap(),
+            guest_cid: 3,
+            uds_path: "/tmp/vsock.sock".to_string(),
+            backend: VsockBackendType::Vhost,
+            uds_mode: None,
+            uds_uid: None,
+            uds_gid: None,
+            cid_pool: None,
+        };
+        assert_eq!(
+            builder.insert(alive.clone(), &[vsock_config.clone()]),
+            Err(ProbeConfigError::InvalidVsockDevice(None))
+        );
+        vsock_config.backend = VsockBackendType::Uds;
+        let mut other = alive.clone();
+        other.check = ProbeCheck::VsockConnect {
+            port: 52,
+            vsock_id: Some("other".to_string()),
+        };
+        assert_eq!(
+            builder.insert(other, &[vsock_config.clone()]),
+            Err(ProbeConfigError::InvalidVsockDevice(Some(
+                "other".to_string()
+            )))
+        );
+        builder.insert(alive, &[vsock_config]).unwrap();
+        // Update the probe with the same ID.
+        let updated = config(
+            "ready",
+            ProbeCheck::SerialPattern {
+                pattern: "login:".to_string(),
+            },
+        );
+        builder.insert(updated.clone(), &[]).unwrap();
+        assert_eq!(builder.configs().len(), 2);
+        assert_eq!(builder.configs()[0], updated);
+
+        for i in builder.configs().len()..MAX_PROBES {
+            builder
+                .insert(config(&i.to_string(), ProbeCheck::MmdsHeartbeat), &[])
+                .unwrap();
+        }
+        assert_eq!(
+            builder.insert(config("extra", ProbeCheck::MmdsHeartbeat), &[]),
+            Err(ProbeConfigError::TooManyProbes)
+        );
+    }
+
+    #[test]
+    fn test_config_deserialization() {
+        let config: ProbeConfig = serde_json::from_str(
+            r#"{
+                "probe_id": "ready",
+                "kind": "readiness",
+                "check": {"type": "vsock_connect", "port": 52}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            self::config(
+                "ready",
+                ProbeCheck::VsockConnect {
+                    port: 52,
+                    vsock_id: None
+                }
+            )
+        );
+
+        let config: ProbeConfig = serde_json::from_str(
+            r#"{
+                "probe_id": "alive",
+                "kind": "liveness",
+                "check": {"type": "mmds_heartbeat"},
+                "initial_delay_ms": 5000,
+                "period_ms": 500,
+                "failure_threshold": 1
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.kind, ProbeKind::Liveness);
+        assert_eq!(config.check, ProbeCheck::MmdsHeartbeat);
+        assert_eq!(config.initial_delay_ms, 5000);
+        assert_eq!(config.period_ms, 500);
+        assert_eq!(config.failure_threshold, 1);
+
+        assert!(serde_json::from_str::<ProbeConfig>(
+            r#"{"probe_id": "ready", "kind": "startup", "check": {"type": "mmds_heartbeat"}}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<ProbeConfig>(
+            r#"{"probe_id": "ready", "kind": "readiness", "check": {"type": "serial_pattern"}}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::ProbeConfigError::*;
+
+        for err in &[
+            EmptyPattern,
+            InvalidFailureThreshold,
+            InvalidPeriod(10),
+            InvalidVsockDevice(Some("vsock".to_string())),
+            InvalidVsockDevice(None),
+            TooManyProbes,
+        ] {
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+}
@@ -0,0 +1,324 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::path::PathBuf;
+
+/// The shortest period between two adjustments of the rate limiters.
+pub const MIN_POLICY_PERIOD_MS: u64 = 100;
+
+/// Errors associated with the adaptive rate limiting policy.
+#[derive(Debug, PartialEq)]
+pub enum RateLimitPolicyError {
+    /// The host block device whose latency is observed has an invalid name.
+    InvalidDevice(String),
+    /// The percentage by which the limits are lowered is not between 1 and 99.
+    InvalidDecrease(u8),
+    /// The percentage by which the limits are raised is not between 1 and 100.
+    InvalidIncrease(u8),
+    /// The lowest percentage the limits can be lowered to is not between 1 and 100.
+    InvalidFloor(u8),
+    /// The period of the policy is shorter than `MIN_POLICY_PERIOD_MS`.
+    InvalidPeriod(u64),
+    /// The low watermark is not below the high watermark.
+    InvalidWatermarks(u64, u64),
+}
+
+impl fmt::Display for RateLimitPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::RateLimitPolicyError::*;
+        match self {
+            InvalidDevice(device) => write!(f, "Invalid host block device name: {:?}.", device),
+            InvalidDecrease(percent) => write!(
+                f,
+                "Invalid decrease of {}%, it must be between 1% and 99%.",
+                percent
+            ),
+            InvalidIncrease(percent) => write!(
+                f,
+                "Invalid increase of {}%, it must be between 1% and 100%.",
+                percent
+            ),
+            InvalidFloor(percent) => write!(
+                f,
+                "Invalid floor of {}%, it must be between 1% and 100%.",
+                percent
+            ),
+            InvalidPeriod(period_ms) => write!(
+                f,
+                "Invalid policy period {} ms, it must be at least {} ms.",
+                period_ms, MIN_POLICY_PERIOD_MS
+            ),
+            InvalidWatermarks(low, high) => write!(
+                f,
+                "The low watermark {} must be below the high watermark {}.",
+                low, high
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RateLimitPolicyError {}
+
+type Result<T> = std::result::Result<T, RateLimitPolicyError>;
+
+/// A host resource whose pressure stall information is observed.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PsiResource {
+    /// Time the tasks of the host waited for a CPU.
+    Cpu,
+    /// Time the tasks of the host waited for I/O.
+    Io,
+    /// Time the tasks of the host waited for memory.
+    Memory,
+}
+
+/// The host pressure signal the rate limiters are adjusted to.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PressureSignal {
+    /// The share of time, in percent, some tasks of the host stalled on `resource` over the last
+    /// 10 seconds, as reported by `/proc/pressure`.
+    Psi {
+        /// The resource to observe.
+        resource: PsiResource,
+    },
+    /// The average latency, in microseconds, of the requests the host block device `device`
+    /// completed since the previous adjustment, as reported by `/sys/block`.
+    DiskLatency {
+        /// The name of the host block device, e.g. `nvme0n1`.
+        device: String,
+    },
+}
+
+impl PressureSignal {
+    /// Returns the file the signal is read from.
+    pub fn path(&self) -> PathBuf {
+        match self {
+            PressureSignal::Psi { resource } => {
+                let resource = match resource {
+                    PsiResource::Cpu => "cpu",
+                    PsiResource::Io => "io",
+                    PsiResource::Memory => "memory",
+                };
+                PathBuf::from("/proc/pressure").join(resource)
+            }
+            PressureSignal::DiskLatency { device } => {
+                PathBuf::from("/sys/block").join(device).join("stat")
+            }
+        }
+    }
+}
+
+fn default_period_ms() -> u64 {
+    1000
+}
+
+fn default_decrease_percent() -> u8 {
+    25
+}
+
+fn default_increase_percent() -> u8 {
+    10
+}
+
+fn default_floor_percent() -> u8 {
+    10
+}
+
+/// Configures how the VMM adjusts the rate limiters of the network interfaces and drives to the
+/// pressure on the host, between the updates of the control plane.
+///
+/// Every period, the limits are lowered by `decrease_percent` when the signal is above
+/// `high_watermark`, down to `floor_percent` of the limits set by the control plane, and raised
+/// by `increase_percent` of these limits when the signal is below `low_watermark`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitPolicyConfig {
+    /// The host pressure signal to observe.
+    pub signal: PressureSignal,
+    /// The value of the signal above which the limits are lowered.
+    pub high_watermark: u64,
+    /// The value of the signal below which the limits are raised.
+    pub low_watermark: u64,
+    /// Time between two adjustments.
+    #[serde(default = "default_period_ms")]
+    pub period_ms: u64,
+    /// Percentage of the current limits by which they are lowered.
+    #[serde(default = "default_decrease_percent")]
+    pub decrease_percent: u8,
+    /// Percentage of the limits set by the control plane by which the limits are raised.
+    #[serde(default = "default_increase_percent")]
+    pub increase_percent: u8,
+    /// The lowest percentage of the limits set by the control plane the limits are lowered to.
+    #[serde(default = "default_floor_percent")]
+    pub floor_percent: u8,
+}
+
+impl RateLimitPolicyConfig {
+    /// Checks that the policy is consistent.
+    pub fn validate(&self) -> Result<()> {
+        if let PressureSignal::DiskLatency { device } = &self.signal {
+            if device.is_empty() || device.contains('/') || device.starts_with('.') {
+                return Err(RateLimitPolicyError::InvalidDevice(device.clone()));
+            }
+        }
+        if self.low_watermark >= self.high_watermark {
+            return Err(RateLimitPolicyError::InvalidWatermarks(
+                self.low_watermark,
+                self.high_watermark,
+            ));
+        }
+        if self.period_ms < MIN_POLICY_PERIOD_MS {
+            return Err(RateLimitPolicyError::InvalidPeriod(self.period_ms));
+        }
+        if self.decrease_percent == 0 || self.decrease_percent > 99 {
+            return Err(RateLimitPolicyError::InvalidDecrease(self.decrease_percent));
+        }
+        if self.increase_percent == 0 || self.increase_percent > 100 {
+            return Err(RateLimitPolicyError::InvalidIncrease(self.increase_percent));
+        }
+        if self.floor_percent == 0 || self.floor_percent > 100 {
+            return Err(RateLimitPolicyError::InvalidFloor(self.floor_percent));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(signal: PressureSignal) -> RateLimitPolicyConfig {
+        RateLimitPolicyConfig {
+            signal,
+            high_watermark: 40,
+            low_watermark: 10,
+            period_ms: default_period_ms(),
+            decrease_percent: default_decrease_percent(),
+            increase_percent: default_increase_percent(),
+            floor_percent: default_floor_percent(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let psi = PressureSignal::Psi {
+            resource: PsiResource::Io,
+        };
+        assert!(config(psi.clone()).validate().is_ok());
+
+        for device in &["", "../sda", "sda/stat", "."] {
+            assert_eq!(
+                config(PressureSignal::DiskLatency {
+                    device: device.to_string()
+                })
+                .validate(),
+                Err(RateLimitPolicyError::InvalidDevice(device.to_string()))
+            );
+        }
+
+        let mut invalid = config(psi.clone());
+        invalid.low_watermark = 40;
+        assert_eq!(
+            invalid.validate(),
+            Err(RateLimitPolicyError::InvalidWatermarks(40, 40))
+        );
+
+        let mut invalid = config(psi.clone());
+        invalid.period_ms = MIN_POLICY_PERIOD_MS - 1;
+        assert_eq!(
+            invalid.validate(),
+            Err(RateLimitPolicyError::InvalidPeriod(
+                MIN_POLICY_PERIOD_MS - 1
+            ))
+        );
+
+        let mut invalid = config(psi.clone());
+        invalid.decrease_percent = 100;
+        assert_eq!(
+            invalid.validate(),
+            Err(RateLimitPolicyError::InvalidDecrease(100))
+        );
+
+        let mut invalid = config(psi.clone());
+        invalid.increase_percent = 0;
+        assert_eq!(
+            invalid.validate(),
+            Err(RateLimitPolicyError::InvalidIncrease(0))
+        );
+
+        let mut invalid = config(psi);
+        invalid.floor_percent = 101;
+        assert_eq!(
+            invalid.validate(),
+            Err(RateLimitPolicyError::InvalidFloor(101))
+        );
+    }
+
+    #[test]
+    fn test_config_deserialization() {
+        let config: RateLimitPolicyConfig = serde_json::from_str(
+            r#"{
+                "signal": {"type": "psi", "resource": "io"},
+                "high_watermark": 40,
+                "low_watermark": 10
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            self::config(PressureSignal::Psi {
+                resource: PsiResource::Io
+            })
+        );
+        assert_eq!(config.signal.path(), PathBuf::from("/proc/pressure/io"));
+
+        let config: RateLimitPolicyConfig = serde_json::from_str(
+            r#"{
+                "signal": {"type": "disk_latency", "device": "nvme0n1"},
+                "high_watermark": 5000,
+                "low_watermark": 1000,
+                "period_ms": 500,
+                "decrease_percent": 50,
+                "increase_percent": 5,
+                "floor_percent": 20
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.signal.path(),
+            PathBuf::from("/sys/block/nvme0n1/stat")
+        );
+        assert_eq!(config.period_ms, 500);
+        assert_eq!(config.decrease_percent, 50);
+        assert_eq!(config.increase_percent, 5);
+        assert_eq!(config.floor_percent, 20);
+
+        assert!(serde_json::from_str::<RateLimitPolicyConfig>(
+            r#"{"signal": {"type": "psi", "resource": "net"}, "high_watermark": 4, "low_watermark": 1}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<RateLimitPolicyConfig>(
+            r#"{"signal": {"type": "psi", "resource": "io"}, "high_watermark": 4}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::RateLimitPolicyError::*;
+
+        for err in &[
+            InvalidDevice("../sda".to_string()),
+            InvalidDecrease(0),
+            InvalidIncrease(0),
+            InvalidFloor(0),
+            InvalidPeriod(10),
+            InvalidWatermarks(4, 1),
+        ] {
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+
+use utils::time::{get_time, ClockType, NANOS_PER_SECOND};
+
+/// The latest time the RTC can start at, 2099-12-31T23:59:59, in seconds since the Unix epoch.
+/// Guests infer the century of the CMOS RTC, and the PL031 RTC counts 32 bits of seconds.
+pub const MAX_RTC_TIME: i64 = 4_102_444_799;
+/// The largest offset of the RTC time from UTC, in minutes.
+pub const MAX_UTC_OFFSET_MINUTES: i32 = 24 * 60 - 1;
+
+/// Errors associated with the RTC configuration.
+#[derive(Debug, PartialEq)]
+pub enum RtcConfigError {
+    /// The RTC would start before the Unix epoch or after `MAX_RTC_TIME`.
+    InvalidBaseTime(i64),
+    /// The offset from UTC is larger than a day.
+    InvalidUtcOffset(i32),
+}
+
+impl fmt::Display for RtcConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::RtcConfigError::*;
+        match *self {
+            InvalidBaseTime(base_time) => write!(
+                f,
+                "Invalid base time {}, the RTC has to start between 0 and {}.",
+                base_time, MAX_RTC_TIME
+            ),
+            InvalidUtcOffset(offset) => write!(
+                f,
+                "Invalid UTC offset {}, it must be between -{} and {} minutes.",
+                offset, MAX_UTC_OFFSET_MINUTES, MAX_UTC_OFFSET_MINUTES
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RtcConfigError {}
+
+/// Configures the time at which the real time clock of the guest starts.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RtcConfig {
+    /// Wall-clock time of the guest at boot, in seconds since the Unix epoch. The RTC follows
+    /// the host time when it's missing.
+    #[serde(default)]
+    pub base_time: Option<i64>,
+    /// Offset of the RTC time from UTC, in minutes, for guests which expect the RTC to hold
+    /// their local time.
+    #[serde(default)]
+    pub utc_offset_minutes: i32,
+}
+
+impl RtcConfig {
+    /// Checks that the RTC can hold the configured time.
+    pub fn validate(&self) -> Result<(), RtcConfigError> {
+        if self.utc_offset_minutes.abs() > MAX_UTC_OFFSET_MINUTES {
+            return Err(RtcConfigError::InvalidUtcOffset(self.utc_offset_minutes));
+        }
+        if let Some(base_time) = self.base_time {
+            let rtc_time = base_time.saturating_add(i64::from(self.utc_offset_minutes) * 60);
+            if rtc_time < 0 || rtc_time > MAX_RTC_TIME {
+                return Err(RtcConfigError::InvalidBaseTime(base_time));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the time the RTC starts at, in seconds since the Unix epoch.
+    pub fn rtc_time(&self) -> i64 {
+        let base_time = self
+            .base_time
+            .unwrap_or_else(|| (get_time(ClockType::Real) / NANOS_PER_SECOND) as i64);
+        base_time + i64::from(self.utc_offset_minutes) * 60
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_rtc_config() {
+        assert!(RtcConfig::default().validate().is_ok());
+
+        let mut config = RtcConfig {
+            base_time: Some(0),
+            utc_offset_minutes: 120,
+        };
+        assert!(config.validate().is_ok());
+        assert_eq!(config.rtc_time(), 7200);
+
+        // The RTC can't start before the epoch.
+        config.utc_offset_minutes = -120;
+        assert_eq!(config.validate(), Err(RtcConfigError::InvalidBaseTime(0)));
+
+        config.base_time = Some(MAX_RTC_TIME + 1);
+        assert_eq!(
+            config.validate(),
+            Err(RtcConfigError::InvalidBaseTime(MAX_RTC_TIME + 1))
+        );
+        config.base_time = None;
+        assert!(config.validate().is_ok());
+
+        config.utc_offset_minutes = 24 * 60;
+        assert_eq!(
+            config.validate(),
+            Err(RtcConfigError::InvalidUtcOffset(24 * 60))
+        );
+    }
+
+    #[test]
+    fn test_rtc_config_deserialization() {
+        let config: RtcConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, RtcConfig::default());
+        let config: RtcConfig =
+            serde_json::from_str(r#"{"base_time": 946684800, "utc_offset_minutes": -300}"#)
+                .unwrap();
+        assert_eq!(config.base_time, Some(946_684_800));
+        assert_eq!(config.utc_offset_minutes, -300);
+        assert!(serde_json::from_str::<RtcConfig>(r#"{"timezone": "UTC"}"#).is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let err = RtcConfigError::InvalidBaseTime(-1);
+        let _ = format!("{}{:?}", err, err);
+        let err = RtcConfigError::InvalidUtcOffset(0);
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+
+/// The guest policy bit requiring the vCPU state to be encrypted (SEV-ES).
+pub const SEV_POLICY_ES: u32 = 1 << 2;
+
+/// Errors associated with the SEV configuration.
+#[derive(Debug, PartialEq)]
+pub enum SevConfigError {
+    /// SEV-ES is enabled but the guest policy doesn't require it, or the other way around.
+    InvalidPolicy,
+    /// The microVM was not launched as a SEV guest.
+    SevNotEnabled,
+}
+
+impl fmt::Display for SevConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::SevConfigError::*;
+        match *self {
+            InvalidPolicy => write!(
+                f,
+                "The SEV guest policy is invalid. Its ES bit has to be set if and only if \
+                 SEV-ES is enabled."
+            ),
+            SevNotEnabled => write!(f, "The microVM was not launched as a SEV guest."),
+        }
+    }
+}
+
+impl std::error::Error for SevConfigError {}
+
+/// Launches the microVM as an AMD SEV guest, whose memory is encrypted with a key owned by the
+/// AMD Secure Processor of the host.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SevConfig {
+    /// The guest policy enforced by the Secure Processor, as defined by the SEV API
+    /// specification (e.g. bit 0 forbids debugging the guest).
+    #[serde(default)]
+    pub policy: u32,
+    /// Also encrypts the vCPU state (SEV-ES).
+    #[serde(default)]
+    pub es: bool,
+}
+
+impl SevConfig {
+    /// Checks that the guest policy is consistent with the SEV flavour.
+    pub fn validate(&self) -> Result<(), SevConfigError> {
+        if (self.policy & SEV_POLICY_ES != 0) != self.es {
+            return Err(SevConfigError::InvalidPolicy);
+        }
+        Ok(())
+    }
+}
+
+/// The measurement of the initial guest memory and vCPU state computed by the Secure
+/// Processor upon launch, which the guest owner checks before trusting the guest.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LaunchMeasurement {
+    /// The measurement, hex-encoded.
+    pub measurement: String,
+}
+
+impl LaunchMeasurement {
+    /// Hex-encodes the raw measurement returned by the Secure Processor.
+    pub fn new(raw: &[u8]) -> Self {
+        LaunchMeasurement {
+            measurement: raw.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_sev_config() {
+        assert!(SevConfig::default().validate().is_ok());
+        let config = SevConfig {
+            policy: SEV_POLICY_ES | 1,
+            es: true,
+        };
+        assert!(config.validate().is_ok());
+
+        let config = SevConfig {
+            policy: SEV_POLICY_ES,
+            es: false,
+        };
+        assert_eq!(config.validate(), Err(SevConfigError::InvalidPolicy));
+        let config = SevConfig {
+            policy: 1,
+            es: true,
+        };
+        assert_eq!(config.validate(), Err(SevConfigError::InvalidPolicy));
+    }
+
+    #[test]
+    fn test_launch_measurement() {
+        assert_eq!(
+            LaunchMeasurement::new(&[0x00, 0x1f, 0xa0, 0xff]).measurement,
+            "001fa0ff"
+        );
+    }
+}
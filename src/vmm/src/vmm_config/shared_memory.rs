@@ -0,0 +1,225 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::path::PathBuf;
+
+#[cfg(target_arch = "x86_64")]
+use super::pci_passthrough::MAX_PCI_PASSTHROUGH_DEVICES;
+
+/// The smallest shared memory region, as KVM maps memory with a page granularity.
+pub const MIN_SHARED_MEMORY_SIZE: u64 = 0x1000;
+/// The largest shared memory region, which has to fit in the upper half of the 64-bit MMIO
+/// window along with the other regions.
+pub const MAX_SHARED_MEMORY_SIZE: u64 = 1 << 36;
+/// The largest number of shared memory devices, each of them taking one interrupt line.
+#[cfg(target_arch = "x86_64")]
+pub const MAX_SHARED_MEMORY_DEVICES: usize = MAX_PCI_PASSTHROUGH_DEVICES;
+
+/// Errors associated with the shared memory devices.
+#[derive(Debug, PartialEq)]
+pub enum SharedMemoryConfigError {
+    /// The file backing the shared memory doesn't exist, or is not a regular file.
+    MemFileNotFound(PathBuf),
+    /// The size of the file backing the shared memory is not a power of 2 between
+    /// `MIN_SHARED_MEMORY_SIZE` and `MAX_SHARED_MEMORY_SIZE`.
+    InvalidMemSize(u64),
+    /// No more shared memory devices can be attached to the microVM.
+    TooManyDevices,
+    /// Shared memory devices are not supported on this architecture.
+    NotSupported,
+}
+
+impl fmt::Display for SharedMemoryConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::SharedMemoryConfigError::*;
+        match self {
+            MemFileNotFound(path) => write!(
+                f,
+                "The shared memory file {} doesn't exist or is not a regular file.",
+                path.display()
+            ),
+            InvalidMemSize(size) => write!(
+                f,
+                "Invalid shared memory size {}, it must be a power of 2 between {} and {} bytes.",
+                size, MIN_SHARED_MEMORY_SIZE, MAX_SHARED_MEMORY_SIZE
+            ),
+            TooManyDevices => write!(f, "Too many shared memory devices."),
+            NotSupported => write!(
+                f,
+                "Shared memory devices are not supported on this architecture."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SharedMemoryConfigError {}
+
+type Result<T> = std::result::Result<T, SharedMemoryConfigError>;
+
+/// Maps a host file, such as a memfd or a file in `/dev/shm`, in the guest physical address
+/// space through an ivshmem-compatible PCI device.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SharedMemoryConfig {
+    /// ID of the device.
+    pub shm_id: String,
+    /// Path of the file backing the shared memory. A memfd of another process can be passed
+    /// as `/proc/<pid>/fd/<fd>`. The size of the file is the size of the shared memory.
+    pub mem_path: PathBuf,
+    /// Path of a Unix socket, on which a host process listens for the doorbells rung by the
+    /// guest and sends back the interrupts to raise in the guest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_socket: Option<PathBuf>,
+}
+
+// Checks that the file at `path` can back a shared memory region.
+#[cfg(target_arch = "x86_64")]
+fn validate_mem_file(path: &PathBuf) -> Result<()> {
+    let metadata = std::fs::metadata(path)
+        .ok()
+        .filter(|metadata| metadata.is_file())
+        .ok_or_else(|| SharedMemoryConfigError::MemFileNotFound(path.clone()))?;
+    let size = metadata.len();
+    if !size.is_power_of_two() || size < MIN_SHARED_MEMORY_SIZE || size > MAX_SHARED_MEMORY_SIZE {
+        return Err(SharedMemoryConfigError::InvalidMemSize(size));
+    }
+    Ok(())
+}
+
+/// The shared memory devices to attach to the microVM.
+#[derive(Default)]
+pub struct SharedMemoryBuilder {
+    configs: Vec<SharedMemoryConfig>,
+}
+
+impl SharedMemoryBuilder {
+    /// Creates an empty builder.
+    pub fn new() -> Self {
+        SharedMemoryBuilder {
+            configs: Vec::new(),
+        }
+    }
+
+    /// Adds a shared memory device, or updates the one with the same ID.
+    #[cfg(target_arch = "x86_64")]
+    pub fn insert(&mut self, config: SharedMemoryConfig) -> Result<()> {
+        validate_mem_file(&config.mem_path)?;
+        match self
+            .configs
+            .iter_mut()
+            .find(|other| other.shm_id == config.shm_id)
+        {
+            Some(other) => *other = config,
+            None if self.configs.len() >= MAX_SHARED_MEMORY_DEVICES => {
+                return Err(SharedMemoryConfigError::TooManyDevices)
+            }
+            None => self.configs.push(config),
+        }
+        Ok(())
+    }
+
+    /// Adds a shared memory device, or updates the one with the same ID.
+    #[cfg(target_arch = "aarch64")]
+    pub fn insert(&mut self, _config: SharedMemoryConfig) -> Result<()> {
+        Err(SharedMemoryConfigError::NotSupported)
+    }
+
+    /// Returns the configurations of the shared memory devices.
+    pub fn configs(&self) -> &[SharedMemoryConfig] {
+        &self.configs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use utils::tempfile::TempFile;
+
+    fn config(shm_id: &str, mem_path: &PathBuf) -> SharedMemoryConfig {
+        SharedMemoryConfig {
+            shm_id: shm_id.to_string(),
+            mem_path: mem_path.clone(),
+            peer_socket: None,
+        }
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut builder = SharedMemoryBuilder::new();
+        assert!(builder.configs().is_empty());
+
+        let mem_file = TempFile::new().unwrap();
+        let mem_path = mem_file.as_path().to_path_buf();
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            // The size of the file has to be a power of 2 of at least a page.
+            assert_eq!(
+                builder.insert(config("shm", &mem_path)),
+                Err(SharedMemoryConfigError::InvalidMemSize(0))
+            );
+            mem_file.as_file().set_len(0x3000).unwrap();
+            assert_eq!(
+                builder.insert(config("shm", &mem_path)),
+                Err(SharedMemoryConfigError::InvalidMemSize(0x3000))
+            );
+            assert_eq!(
+                builder.insert(config("shm", &PathBuf::from("/dev/null"))),
+                Err(SharedMemoryConfigError::MemFileNotFound(PathBuf::from(
+                    "/dev/null"
+                )))
+            );
+
+            mem_file.as_file().set_len(0x4000).unwrap();
+            builder.insert(config("shm", &mem_path)).unwrap();
+            builder.insert(config("shm2", &mem_path)).unwrap();
+            // Update the device with the same ID.
+            let mut updated = config("shm", &mem_path);
+            updated.peer_socket = Some(PathBuf::from("/tmp/peer.sock"));
+            builder.insert(updated.clone()).unwrap();
+            assert_eq!(builder.configs(), &[updated, config("shm2", &mem_path)]);
+
+            for i in builder.configs().len()..MAX_SHARED_MEMORY_DEVICES {
+                builder.insert(config(&i.to_string(), &mem_path)).unwrap();
+            }
+            assert_eq!(
+                builder.insert(config("extra", &mem_path)),
+                Err(SharedMemoryConfigError::TooManyDevices)
+            );
+        }
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            builder.insert(config("shm", &mem_path)),
+            Err(SharedMemoryConfigError::NotSupported)
+        );
+    }
+
+    #[test]
+    fn test_config_deserialization() {
+        let config: SharedMemoryConfig =
+            serde_json::from_str(r#"{"shm_id": "shm", "mem_path": "/dev/shm/data"}"#).unwrap();
+        assert_eq!(config, self::config("shm", &PathBuf::from("/dev/shm/data")));
+        let config: SharedMemoryConfig = serde_json::from_str(
+            r#"{"shm_id": "shm", "mem_path": "/dev/shm/data", "peer_socket": "/tmp/peer.sock"}"#,
+        )
+        .unwrap();
+        assert_eq!(config.peer_socket, Some(PathBuf::from("/tmp/peer.sock")));
+        assert!(serde_json::from_str::<SharedMemoryConfig>(r#"{"shm_id": "shm"}"#).is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::SharedMemoryConfigError::*;
+
+        for err in &[
+            MemFileNotFound(PathBuf::from("/dev/shm/data")),
+            InvalidMemSize(0x3000),
+            TooManyDevices,
+            NotSupported,
+        ] {
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+}
@@ -7,7 +7,7 @@ use std::path::PathBuf;
 
 /// The snapshot type options that are available when
 /// creating a new snapshot.
-#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum SnapshotType {
     /// Diff snapshot.
     Diff,
@@ -21,8 +21,25 @@ impl Default for SnapshotType {
     }
 }
 
+/// The compression options for the guest memory file of a snapshot.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum MemoryCompression {
+    /// The guest memory is not compressed.
+    None,
+    /// The guest memory is compressed with LZ4.
+    Lz4,
+    /// The guest memory is compressed with Zstandard.
+    Zstd,
+}
+
+impl Default for MemoryCompression {
+    fn default() -> MemoryCompression {
+        MemoryCompression::None
+    }
+}
+
 /// Stores the configuration that will be used for creating a snapshot.
-#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct CreateSnapshotParams {
     /// This marks the type of snapshot we want to create.
@@ -36,10 +53,55 @@ pub struct CreateSnapshotParams {
     ///  Optional field for the snapshot format version. The default
     /// value is the current app version.
     pub version: Option<u16>,
+    /// Compression of the guest memory file. Only supported by full snapshots.
+    #[serde(default)]
+    pub compression: MemoryCompression,
+    /// Setting this flag will save a checksum of each guest memory region, validated when
+    /// loading the snapshot.
+    #[serde(default)]
+    pub checksums: bool,
+    /// Setting this flag writes the snapshot in the background: the action returns as soon as
+    /// the microVM state is saved, with the ID of the operation writing the files.
+    #[serde(default)]
+    pub background: bool,
+}
+
+/// The backends the guest memory of a loaded snapshot can be restored from.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum MemBackendType {
+    /// The guest memory file is copied into the guest memory when loading the snapshot.
+    File,
+    /// The guest memory file is mapped privately: the clean pages are shared with the other
+    /// microVMs restored from the same file, while the written pages are copied.
+    PrivateMapping,
+    /// The guest pages are served on first access, through userfaultfd.
+    Uffd,
+}
+
+impl Default for MemBackendType {
+    fn default() -> MemBackendType {
+        MemBackendType::File
+    }
+}
+
+/// The actions taken on a loaded snapshot before it is resumed, letting the microVMs restored
+/// from the same snapshot diverge.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PostRestoreConfig {
+    /// Setting this flag will hand fresh entropy to the guest through the entropy device.
+    #[serde(default)]
+    pub reseed_entropy: bool,
+    /// Replaces the MMDS contents, e.g. with the identity of the restored microVM.
+    pub mmds_data: Option<serde_json::Value>,
+    /// Setting this flag will notify the guest of the restore through the vsock device, which
+    /// resets the guest vsock connections.
+    #[serde(default)]
+    pub notify_guest: bool,
 }
 
 /// Stores the configuration that will be used for loading a snapshot.
-#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct LoadSnapshotParams {
     /// Path to the file that contains the microVM state to be loaded.
@@ -50,6 +112,43 @@ pub struct LoadSnapshotParams {
     /// allow taking subsequent incremental snapshots.
     #[serde(default)]
     pub enable_diff_snapshots: bool,
+    /// Backend the guest memory is restored from. The default value is `File`.
+    #[serde(default)]
+    pub mem_backend: MemBackendType,
+    /// Path to the Unix domain socket of the page server serving the guest pages, only allowed
+    /// with the `Uffd` backend. The guest pages are served by this process if missing.
+    pub uffd_socket_path: Option<PathBuf>,
+    /// Setting this flag will profile the page faults served by this process with the `Uffd`
+    /// backend into the `page_faults` metrics.
+    #[serde(default)]
+    pub profile_page_faults: bool,
+    /// Path to the prefetch manifest the guest pages first faulted in are recorded to, with the
+    /// `Uffd` backend, for prefetching them on the next loads of the snapshot.
+    pub record_manifest_path: Option<PathBuf>,
+    /// Path to a prefetch manifest recorded on a previous load of the snapshot, listing the guest
+    /// pages prefetched ahead of the guest accessing them, with the `Uffd` backend.
+    pub prefetch_manifest_path: Option<PathBuf>,
+    /// Actions taken on the loaded microVM before it is resumed. None by default.
+    #[serde(default)]
+    pub post_restore: PostRestoreConfig,
+}
+
+/// Stores the configuration that will be used for handing over the microVM to a new
+/// Firecracker process.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LiveUpdateParams {
+    /// Path to the Unix domain socket the new Firecracker process listens on.
+    pub socket_path: PathBuf,
+}
+
+/// Stores the configuration that will be used for migrating the microVM to another Firecracker
+/// process.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MigrationParams {
+    /// Path to the Unix domain socket the destination Firecracker process listens on.
+    pub socket_path: PathBuf,
 }
 
 /// The microVM state options.
@@ -0,0 +1,234 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use devices::virtio::sound::protocol::pcm_rate_index;
+use devices::virtio::sound::{Error as SoundError, MAX_CHANNELS};
+use devices::virtio::{PcmSink, Sound};
+
+type MutexSound = Arc<Mutex<Sound>>;
+
+/// Errors associated with the operations allowed on the sound device.
+#[derive(Debug)]
+pub enum SoundConfigError {
+    /// The frame rate can't be described by virtio-snd.
+    UnsupportedRate(u32),
+    /// The number of channels is zero or larger than `MAX_CHANNELS`.
+    InvalidChannels(u8),
+    /// Failed to open the host endpoint of the sound device.
+    OpenBackend(std::io::Error),
+    /// Failed to create the sound device.
+    CreateSoundDevice(SoundError),
+}
+
+impl fmt::Display for SoundConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::SoundConfigError::*;
+        match *self {
+            UnsupportedRate(rate) => write!(f, "Unsupported frame rate: {}.", rate),
+            InvalidChannels(channels) => write!(
+                f,
+                "Invalid number of channels {}, it must be between 1 and {}.",
+                channels, MAX_CHANNELS
+            ),
+            OpenBackend(ref e) => write!(f, "Cannot open the sound backend: {}", e),
+            CreateSoundDevice(ref e) => write!(f, "Cannot create sound device: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for SoundConfigError {}
+
+type Result<T> = std::result::Result<T, SoundConfigError>;
+
+/// The kind of host endpoint receiving the samples played by the guest.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SoundBackendType {
+    /// A regular file, created if needed, or a FIFO which already has a reader.
+    File,
+    /// A listening Unix stream socket, like the one of the PipeWire `protocol-simple` module.
+    Socket,
+}
+
+fn default_rate() -> u32 {
+    48000
+}
+
+fn default_channels() -> u8 {
+    2
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from sound device related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SoundDeviceConfig {
+    /// The kind of host endpoint receiving the samples.
+    pub backend: SoundBackendType,
+    /// Path of the host endpoint.
+    pub path: String,
+    /// Frame rate of the output stream.
+    #[serde(default = "default_rate")]
+    pub rate: u32,
+    /// Number of channels of the output stream.
+    #[serde(default = "default_channels")]
+    pub channels: u8,
+}
+
+/// A builder for `Sound` devices from 'SoundDeviceConfig'.
+#[derive(Default)]
+pub struct SoundBuilder {
+    inner: Option<(MutexSound, SoundDeviceConfig)>,
+}
+
+impl SoundBuilder {
+    /// Creates an empty Sound Store.
+    pub fn new() -> Self {
+        Self { inner: None }
+    }
+
+    /// Checks the parameters of the Sound device `cfg` describes, without opening its backend.
+    pub fn validate(cfg: &SoundDeviceConfig) -> Result<()> {
+        if pcm_rate_index(cfg.rate).is_none() {
+            return Err(SoundConfigError::UnsupportedRate(cfg.rate));
+        }
+        if cfg.channels == 0 || cfg.channels > MAX_CHANNELS {
+            return Err(SoundConfigError::InvalidChannels(cfg.channels));
+        }
+        Ok(())
+    }
+
+    /// Inserts a Sound device in the store.
+    /// If an entry already exists, it will overwrite it.
+    pub fn set(&mut self, cfg: SoundDeviceConfig) -> Result<()> {
+        Self::validate(&cfg)?;
+        // Make sure to close the old backend before opening the new one.
+        self.inner = None;
+        let sink = match cfg.backend {
+            SoundBackendType::File => PcmSink::open_file(&cfg.path),
+            SoundBackendType::Socket => PcmSink::connect(&cfg.path),
+        }
+        .map_err(SoundConfigError::OpenBackend)?;
+        let sound = Sound::new(cfg.rate, cfg.channels, sink)
+            .map_err(SoundConfigError::CreateSoundDevice)?;
+        self.inner = Some((Arc::new(Mutex::new(sound)), cfg));
+        Ok(())
+    }
+
+    /// Provides a reference to the Sound device if present.
+    pub fn get(&self) -> Option<&MutexSound> {
+        self.inner.as_ref().map(|(sound, _)| sound)
+    }
+
+    /// Returns the configuration of the Sound device, if present.
+    pub fn get_config(&self) -> Option<SoundDeviceConfig> {
+        self.inner.as_ref().map(|(_, cfg)| cfg.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+    use utils::tempfile::TempFile;
+
+    fn temp_path() -> String {
+        let mut file = TempFile::new().unwrap();
+        // Remove the file so the path can be reused.
+        file.remove().unwrap();
+        String::from(file.as_path().to_str().unwrap())
+    }
+
+    fn default_config(backend: SoundBackendType, path: &str) -> SoundDeviceConfig {
+        SoundDeviceConfig {
+            backend,
+            path: path.to_string(),
+            rate: default_rate(),
+            channels: default_channels(),
+        }
+    }
+
+    #[test]
+    fn test_sound_create() {
+        let mut builder = SoundBuilder::new();
+        assert!(builder.get().is_none());
+        assert!(builder.get_config().is_none());
+
+        let path = temp_path();
+        let config = default_config(SoundBackendType::File, &path);
+        builder.set(config.clone()).unwrap();
+        assert!(builder.get().is_some());
+        assert_eq!(builder.get_config().unwrap(), config);
+        std::fs::remove_file(&path).unwrap();
+
+        // Nothing listens on the socket.
+        let config = default_config(SoundBackendType::Socket, &path);
+        match builder.set(config.clone()) {
+            Err(SoundConfigError::OpenBackend(_)) => (),
+            _ => panic!("The socket doesn't exist."),
+        }
+        assert!(builder.get().is_none());
+
+        let _listener = UnixListener::bind(&path).unwrap();
+        builder.set(config.clone()).unwrap();
+        assert_eq!(builder.get_config().unwrap(), config);
+        std::fs::remove_file(&path).unwrap();
+
+        // The stream parameters are validated.
+        let mut config = default_config(SoundBackendType::File, &path);
+        config.rate = 44000;
+        match builder.set(config) {
+            Err(SoundConfigError::UnsupportedRate(44000)) => (),
+            _ => panic!("Unsupported rate."),
+        }
+        for channels in &[0, MAX_CHANNELS + 1] {
+            let mut config = default_config(SoundBackendType::File, &path);
+            config.channels = *channels;
+            match builder.set(config) {
+                Err(SoundConfigError::InvalidChannels(c)) => assert_eq!(c, *channels),
+                _ => panic!("Invalid number of channels."),
+            }
+        }
+    }
+
+    #[test]
+    fn test_sound_config_deserialization() {
+        let config: SoundDeviceConfig =
+            serde_json::from_str(r#"{"backend": "file", "path": "/tmp/sound.pcm"}"#).unwrap();
+        assert_eq!(
+            config,
+            default_config(SoundBackendType::File, "/tmp/sound.pcm")
+        );
+        let config: SoundDeviceConfig = serde_json::from_str(
+            r#"{"backend": "socket", "path": "/tmp/sound.sock", "rate": 44100, "channels": 1}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            (config.backend, config.rate, config.channels),
+            (SoundBackendType::Socket, 44100, 1)
+        );
+        assert!(serde_json::from_str::<SoundDeviceConfig>(r#"{"path": "/tmp/sound"}"#).is_err());
+        assert!(serde_json::from_str::<SoundDeviceConfig>(
+            r#"{"backend": "pipe", "path": "/tmp/sound"}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::SoundConfigError::*;
+
+        let err = UnsupportedRate(0);
+        let _ = format!("{}{:?}", err, err);
+        let err = InvalidChannels(0);
+        let _ = format!("{}{:?}", err, err);
+        let err = OpenBackend(std::io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = CreateSoundDevice(SoundError::UnsupportedRate(0));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+
+use devices::virtio::{
+    TYPE_BALLOON, TYPE_BLOCK, TYPE_GPU, TYPE_INPUT, TYPE_NET, TYPE_RNG, TYPE_SOUND, TYPE_VSOCK,
+};
+
+/// The feature bit of the virtio 1.0 devices, which the MMIO transport requires.
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// Errors associated with the virtio feature policy.
+#[derive(Debug, PartialEq)]
+pub enum VirtioFeaturePolicyError {
+    /// The mask of the device type withholds `VIRTIO_F_VERSION_1`.
+    VersionWithheld(&'static str),
+}
+
+impl fmt::Display for VirtioFeaturePolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::VirtioFeaturePolicyError::*;
+        match self {
+            VersionWithheld(device_type) => write!(
+                f,
+                "The {} feature mask withholds VIRTIO_F_VERSION_1 (bit 32), which the guest \
+                 drivers require.",
+                device_type
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VirtioFeaturePolicyError {}
+
+/// The feature bits the devices of a type may offer to their guest drivers.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VirtioFeatureMask {
+    /// The feature bits which may be offered. All of them by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow: Option<u64>,
+    /// The feature bits which are never offered, even when allowed.
+    #[serde(default)]
+    pub deny: u64,
+}
+
+impl VirtioFeatureMask {
+    /// Returns the feature bits the devices of the type may offer.
+    pub fn mask(&self) -> u64 {
+        self.allow.unwrap_or(!0) & !self.deny
+    }
+}
+
+/// Configures the feature bits the virtio devices offer to their guest drivers, by device type.
+///
+/// The devices offer the features they support within the mask of their type, so that the
+/// features seen by the guest drivers don't change when Firecracker supports new ones. The
+/// devices of the types without a mask offer all the features they support.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VirtioFeaturePolicyConfig {
+    /// The mask of the balloon device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balloon: Option<VirtioFeatureMask>,
+    /// The mask of the block devices.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block: Option<VirtioFeatureMask>,
+    /// The mask of the entropy device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entropy: Option<VirtioFeatureMask>,
+    /// The mask of the GPU device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpu: Option<VirtioFeatureMask>,
+    /// The mask of the input device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<VirtioFeatureMask>,
+    /// The mask of the network devices.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub net: Option<VirtioFeatureMask>,
+    /// The mask of the sound device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sound: Option<VirtioFeatureMask>,
+    /// The mask of the vsock devices.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vsock: Option<VirtioFeatureMask>,
+}
+
+impl VirtioFeaturePolicyConfig {
+    /// Checks that the masks leave the features the guest drivers require.
+    pub fn validate(&self) -> Result<(), VirtioFeaturePolicyError> {
+        let masks = [
+            ("balloon", &self.balloon),
+            ("block", &self.block),
+            ("entropy", &self.entropy),
+            ("gpu", &self.gpu),
+            ("input", &self.input),
+            ("net", &self.net),
+            ("sound", &self.sound),
+            ("vsock", &self.vsock),
+        ];
+        for &(device_type, mask) in masks.iter() {
+            if let Some(mask) = mask {
+                if mask.mask() & VIRTIO_F_VERSION_1 == 0 {
+                    return Err(VirtioFeaturePolicyError::VersionWithheld(device_type));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the feature bits the devices of the virtio type `device_type` may offer.
+    pub fn mask(&self, device_type: u32) -> u64 {
+        let mask = match device_type {
+            TYPE_BALLOON => self.balloon,
+            TYPE_BLOCK => self.block,
+            TYPE_RNG => self.entropy,
+            TYPE_GPU => self.gpu,
+            TYPE_INPUT => self.input,
+            TYPE_NET => self.net,
+            TYPE_SOUND => self.sound,
+            TYPE_VSOCK => self.vsock,
+            _ => None,
+        };
+        mask.map_or(!0, |mask| mask.mask())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask() {
+        let policy: VirtioFeaturePolicyConfig = serde_json::from_str(
+            r#"{
+                "net": {"allow": 4294967296, "deny": 0},
+                "block": {"deny": 32}
+            }"#,
+        )
+        .unwrap();
+        policy.validate().unwrap();
+        assert_eq!(policy.mask(TYPE_NET), VIRTIO_F_VERSION_1);
+        assert_eq!(policy.mask(TYPE_BLOCK), !32);
+        assert_eq!(policy.mask(TYPE_VSOCK), !0);
+        assert_eq!(VirtioFeaturePolicyConfig::default().mask(TYPE_NET), !0);
+
+        assert!(serde_json::from_str::<VirtioFeaturePolicyConfig>(r#"{"pmem": {}}"#).is_err());
+        assert!(serde_json::from_str::<VirtioFeatureMask>(r#"{"offer": 1}"#).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut policy = VirtioFeaturePolicyConfig {
+            vsock: Some(VirtioFeatureMask {
+                allow: Some(0xffff_ffff),
+                deny: 0,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.validate(),
+            Err(VirtioFeaturePolicyError::VersionWithheld("vsock"))
+        );
+        policy.vsock = Some(VirtioFeatureMask {
+            allow: None,
+            deny: VIRTIO_F_VERSION_1,
+        });
+        assert_eq!(
+            policy.validate().unwrap_err().to_string(),
+            "The vsock feature mask withholds VIRTIO_F_VERSION_1 (bit 32), which the guest \
+             drivers require."
+        );
+    }
+}
@@ -1,20 +1,65 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::ffi::CString;
 use std::fmt;
+use std::fs::{self, File, Permissions};
+use std::io;
+use std::mem;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
-use devices::virtio::{Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError};
+use super::Identifier;
+use cid_registry::CidLease;
+use devices::virtio::{
+    VhostVsock, VhostVsockError, Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError,
+};
+use utils::fd_uri;
 
 type MutexVsockUnix = Arc<Mutex<Vsock<VsockUnixBackend>>>;
+type MutexVhostVsock = Arc<Mutex<VhostVsock>>;
 
-/// Errors associated with `NetworkInterfaceConfig`.
+/// Errors associated with `VsockDeviceConfig`.
 #[derive(Debug)]
 pub enum VsockConfigError {
     /// Failed to create the backend for the vsock device.
     CreateVsockBackend(VsockUnixBackendError),
     /// Failed to create the vsock device.
     CreateVsockDevice(VsockError),
+    /// Failed to create the vhost-vsock device.
+    CreateVhostVsockDevice(VhostVsockError),
+    /// Cannot lease the guest CID from the registry of the pool.
+    CidRegistry(io::Error),
+    /// Every guest CID of the pool is leased.
+    CidPoolExhausted(u32, u32),
+    /// The microVM has no vsock device.
+    DeviceNotFound,
+    /// The guest CID is already used by another vsock device.
+    GuestCidInUse(u32),
+    /// The guest CID is out of the pool it is leased from.
+    GuestCidOutOfPool(u32),
+    /// The socket named by a `fd://<fd>` URI is not a Unix socket bound to a path and
+    /// listening.
+    InheritedUds(io::Error),
+    /// The permissions of a socket named by a `fd://<fd>` URI are up to the process passing it.
+    InheritedUdsPermissions,
+    /// The pool of guest CIDs is empty, or holds reserved CIDs.
+    InvalidCidPool,
+    /// The mode of the Unix domain socket is not an octal number of permission bits.
+    InvalidUdsMode(String),
+    /// Neither a guest CID nor a pool to lease it from was given.
+    MissingGuestCid,
+    /// Cannot set the mode or the owner of the Unix domain socket.
+    UdsPermissions(io::Error),
+    /// The Unix domain socket is already used by another vsock device.
+    UdsPathInUse(String),
+    /// The connections of a vhost-vsock device are handled by the host kernel.
+    VhostConnections,
+    /// Failed to notify the guest driver of the transport reset.
+    ResetTransport(devices::Error),
 }
 
 impl fmt::Display for VsockConfigError {
@@ -25,76 +70,502 @@ impl fmt::Display for VsockConfigError {
                 write!(f, "Cannot create backend for vsock device: {:?}", e)
             }
             CreateVsockDevice(ref e) => write!(f, "Cannot create vsock device: {:?}", e),
+            CreateVhostVsockDevice(ref e) => {
+                write!(f, "Cannot create vhost-vsock device: {:?}", e)
+            }
+            CidRegistry(ref e) => write!(f, "Cannot lease the guest CID: {}", e),
+            CidPoolExhausted(first, last) => write!(
+                f,
+                "Every guest CID from {} to {} is already leased.",
+                first, last
+            ),
+            DeviceNotFound => write!(f, "The microVM has no vsock device."),
+            GuestCidInUse(cid) => write!(f, "The guest CID {} is already in use.", cid),
+            GuestCidOutOfPool(cid) => write!(f, "The guest CID {} is out of the pool.", cid),
+            InheritedUds(ref e) => write!(f, "Invalid inherited vsock socket: {}", e),
+            InheritedUdsPermissions => write!(
+                f,
+                "The mode and the owner of an inherited socket cannot be set."
+            ),
+            InvalidCidPool => write!(
+                f,
+                "The pool of guest CIDs is invalid. It has to range from 3 to {} at most.",
+                u32::max_value() - 1
+            ),
+            InvalidUdsMode(ref mode) => write!(
+                f,
+                "Invalid socket mode {}, expected octal permission bits, e.g. 0660.",
+                mode
+            ),
+            MissingGuestCid => write!(
+                f,
+                "The guest CID is missing, and there is no pool to lease it from."
+            ),
+            UdsPermissions(ref e) => write!(f, "Cannot set the socket permissions: {}", e),
+            UdsPathInUse(ref path) => write!(f, "The socket {} is already in use.", path),
+            VhostConnections => write!(
+                f,
+                "The connections of a vhost-vsock device are handled by the host kernel."
+            ),
+            ResetTransport(ref e) => write!(f, "Cannot reset the vsock transport: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for VsockConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VsockConfigError::CidRegistry(err)
+            | VsockConfigError::InheritedUds(err)
+            | VsockConfigError::UdsPermissions(err) => Some(err),
+            _ => None,
         }
     }
 }
 
 type Result<T> = std::result::Result<T, VsockConfigError>;
 
+/// The implementation backing the guest vsock connections.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VsockBackendType {
+    /// The connections are multiplexed in Firecracker over host Unix domain sockets.
+    Uds,
+    /// The connections are offloaded to the host kernel through `/dev/vhost-vsock`, and reach
+    /// the host `AF_VSOCK` sockets.
+    Vhost,
+}
+
+impl Default for VsockBackendType {
+    fn default() -> Self {
+        VsockBackendType::Uds
+    }
+}
+
+/// A range of guest CIDs shared by the microVMs of a host, which lease them from a registry.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VsockCidPool {
+    /// The first CID of the pool.
+    pub first_cid: u32,
+    /// The last CID of the pool.
+    pub last_cid: u32,
+    /// The registry directory, shared by the microVMs leasing CIDs from the pool.
+    pub registry_dir: String,
+}
+
+impl VsockCidPool {
+    // Checks that the pool holds no reserved CID, and that it holds `guest_cid` unless 0.
+    fn validate(&self, guest_cid: u32) -> Result<()> {
+        if self.first_cid < 3 || self.first_cid > self.last_cid || self.last_cid == u32::max_value()
+        {
+            return Err(VsockConfigError::InvalidCidPool);
+        }
+        if guest_cid != 0 && (guest_cid < self.first_cid || guest_cid > self.last_cid) {
+            return Err(VsockConfigError::GuestCidOutOfPool(guest_cid));
+        }
+        Ok(())
+    }
+
+    // Leases `guest_cid` from the pool, or the first CID of the pool neither leased nor in
+    // `taken` when `guest_cid` is 0.
+    fn lease(&self, guest_cid: u32, taken: &[u32], owner: &str) -> Result<CidLease> {
+        self.validate(guest_cid)?;
+        let registry_dir = Path::new(&self.registry_dir);
+        if guest_cid != 0 {
+            return CidLease::acquire(registry_dir, guest_cid, owner)
+                .map_err(VsockConfigError::CidRegistry)?
+                .ok_or(VsockConfigError::GuestCidInUse(guest_cid));
+        }
+        for cid in (self.first_cid..=self.last_cid).filter(|cid| !taken.contains(cid)) {
+            if let Some(lease) = CidLease::acquire(registry_dir, cid, owner)
+                .map_err(VsockConfigError::CidRegistry)?
+            {
+                return Ok(lease);
+            }
+        }
+        Err(VsockConfigError::CidPoolExhausted(
+            self.first_cid,
+            self.last_cid,
+        ))
+    }
+}
+
 /// This struct represents the strongly typed equivalent of the json body
 /// from vsock related requests.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct VsockDeviceConfig {
     /// ID of the vsock device.
-    pub vsock_id: String,
-    /// A 32-bit Context Identifier (CID) used to identify the guest.
+    pub vsock_id: Identifier,
+    /// A 32-bit Context Identifier (CID) used to identify the guest. When `cid_pool` is given,
+    /// the CID is leased from the pool, and 0, the default, leases any CID available.
+    #[serde(default)]
     pub guest_cid: u32,
-    /// Path to local unix socket.
+    /// Path to local unix socket. Only used by the `uds` backend. A `fd://<fd>` URI names a
+    /// socket inherited by Firecracker, already bound and listening, which is neither created
+    /// nor removed.
+    #[serde(default)]
     pub uds_path: String,
+    /// The permissions of the Unix socket created, in octal, e.g. `0660`. The umask of the
+    /// process applies by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uds_mode: Option<String>,
+    /// The user owning the Unix socket created. Firecracker's user by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uds_uid: Option<u32>,
+    /// The group owning the Unix socket created. Firecracker's group by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uds_gid: Option<u32>,
+    /// The implementation backing the guest vsock connections.
+    #[serde(default)]
+    pub backend: VsockBackendType,
+    /// The pool the guest CID is leased from, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cid_pool: Option<VsockCidPool>,
+}
+
+enum VsockAndBackend {
+    Unix(MutexVsockUnix),
+    Vhost(MutexVhostVsock),
 }
 
-struct VsockAndUnixPath {
-    vsock: MutexVsockUnix,
-    uds_path: String,
+struct VsockEntry {
+    config: VsockDeviceConfig,
+    device: VsockAndBackend,
+    // The lease of the guest CID, when leased from a pool.
+    cid_lease: Option<Arc<CidLease>>,
 }
 
-/// A builder of Vsock with Unix backend from 'VsockDeviceConfig'.
+/// A builder of the Vsock devices with Unix backend and of the vhost-vsock devices, from
+/// `VsockDeviceConfig`s. The devices are kept in the order they were first inserted in.
 #[derive(Default)]
 pub struct VsockBuilder {
-    inner: Option<VsockAndUnixPath>,
+    entries: Vec<VsockEntry>,
+    // The ID of the instance, recorded in the registries of the CID pools.
+    instance_id: String,
 }
 
 impl VsockBuilder {
-    /// Creates an empty Vsock with Unix backend Store.
+    /// Creates an empty store.
     pub fn new() -> Self {
-        Self { inner: None }
+        Self {
+            entries: Vec::new(),
+            instance_id: String::new(),
+        }
+    }
+
+    /// Sets the ID of the instance, which leases the guest CIDs from the pools.
+    pub fn set_instance_id(&mut self, instance_id: &str) -> Result<()> {
+        self.instance_id = instance_id.to_string();
+        for lease in self.cid_leases() {
+            lease
+                .set_owner(instance_id)
+                .map_err(VsockConfigError::CidRegistry)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the parameters of the vsock device `cfg` describes, without leasing its guest CID
+    /// nor binding its socket.
+    pub fn validate(cfg: &VsockDeviceConfig) -> Result<()> {
+        match cfg.cid_pool {
+            Some(ref pool) => pool.validate(cfg.guest_cid)?,
+            None if cfg.guest_cid == 0 => return Err(VsockConfigError::MissingGuestCid),
+            None => (),
+        }
+        if cfg.backend == VsockBackendType::Vhost {
+            return Ok(());
+        }
+        if let Some(ref mode) = cfg.uds_mode {
+            parse_mode(mode)?;
+        }
+        if fd_uri::parse(&cfg.uds_path).is_some() {
+            if cfg.uds_mode.is_some() || cfg.uds_uid.is_some() || cfg.uds_gid.is_some() {
+                return Err(VsockConfigError::InheritedUdsPermissions);
+            }
+            fd_uri::check(&cfg.uds_path).map_err(VsockConfigError::InheritedUds)?;
+        }
+        Ok(())
+    }
+
+    /// Inserts a Unix backend Vsock or a vhost-vsock device in the store, depending on the
+    /// configured backend. The guest CID is leased first, if the configuration gives a pool.
+    /// If an entry with the same ID already exists, it will overwrite it.
+    pub fn insert(&mut self, mut cfg: VsockDeviceConfig) -> Result<()> {
+        Self::validate(&cfg)?;
+
+        // Each device has its own CID, and its own socket, which binding would steal from the
+        // device it belongs to.
+        for other in self.entries.iter() {
+            if other.config.vsock_id == cfg.vsock_id {
+                continue;
+            }
+            if other.config.guest_cid == cfg.guest_cid {
+                return Err(VsockConfigError::GuestCidInUse(cfg.guest_cid));
+            }
+            if other.config.backend == VsockBackendType::Uds
+                && cfg.backend == VsockBackendType::Uds
+                && other.config.uds_path == cfg.uds_path
+            {
+                return Err(VsockConfigError::UdsPathInUse(cfg.uds_path));
+            }
+        }
+
+        // Make sure to drop the old one and remove the socket before creating a new one. The old
+        // vhost-vsock device has to be dropped as well, to release its CID.
+        let index = self
+            .entries
+            .iter()
+            .position(|entry| entry.config.vsock_id == cfg.vsock_id);
+        if let Some(index) = index {
+            self.remove_entry(index)?;
+        }
+        // The lease of the replaced device, if any, was released above, so the device keeps
+        // its CID unless another instance took it meanwhile.
+        let cid_lease = match cfg.cid_pool {
+            Some(ref pool) => {
+                let taken: Vec<u32> = self
+                    .entries
+                    .iter()
+                    .map(|entry| entry.config.guest_cid)
+                    .collect();
+                let lease = pool.lease(cfg.guest_cid, &taken, &self.instance_id)?;
+                cfg.guest_cid = lease.cid();
+                Some(Arc::new(lease))
+            }
+            None => None,
+        };
+        let device = match cfg.backend {
+            VsockBackendType::Uds => VsockAndBackend::Unix(Arc::new(Mutex::new(
+                Self::create_unixsock_vsock(cfg.clone())?,
+            ))),
+            VsockBackendType::Vhost => {
+                VsockAndBackend::Vhost(Arc::new(Mutex::new(Self::create_vhost_vsock(cfg.clone())?)))
+            }
+        };
+        let entry = VsockEntry {
+            config: cfg,
+            device,
+            cid_lease,
+        };
+        match index {
+            Some(index) => self.entries.insert(index, entry),
+            None => self.entries.push(entry),
+        }
+        Ok(())
+    }
+
+    /// Sets the only Vsock or vhost-vsock device of the store, replacing all the devices
+    /// inserted before, as `insert` does for the device with the same ID. The devices are kept
+    /// when the configuration is invalid.
+    pub fn set(&mut self, cfg: VsockDeviceConfig) -> Result<()> {
+        Self::validate(&cfg)?;
+        while let Some(index) = self
+            .entries
+            .iter()
+            .position(|entry| entry.config.vsock_id != cfg.vsock_id)
+        {
+            self.remove_entry(index)?;
+        }
+        self.insert(cfg)
     }
 
-    /// Inserts a Unix backend Vsock in the store.
-    /// If an entry already exists, it will overwrite it.
-    pub fn insert(&mut self, cfg: VsockDeviceConfig) -> Result<()> {
-        // Make sure to drop the old one and remove the socket before creating a new one.
-        if let Some(existing) = self.inner.take() {
-            std::fs::remove_file(existing.uds_path)
+    // Drops the device at `index`, releasing its CID, and removes its socket unless inherited.
+    fn remove_entry(&mut self, index: usize) -> Result<()> {
+        let old = self.entries.remove(index);
+        let inherited = fd_uri::parse(&old.config.uds_path).is_some();
+        if let (VsockAndBackend::Unix(_), false) = (old.device, inherited) {
+            std::fs::remove_file(old.config.uds_path)
                 .map_err(VsockUnixBackendError::UnixBind)
                 .map_err(VsockConfigError::CreateVsockBackend)?;
         }
-        self.inner = Some(VsockAndUnixPath {
-            uds_path: cfg.uds_path.clone(),
-            vsock: Arc::new(Mutex::new(Self::create_unixsock_vsock(cfg)?)),
-        });
         Ok(())
     }
 
-    /// Provides a reference to the Vsock if present.
-    pub fn get(&self) -> Option<&MutexVsockUnix> {
-        self.inner.as_ref().map(|pair| &pair.vsock)
+    /// Provides a reference to the Vsock with Unix backend with the given ID, if present.
+    pub fn get(&self, vsock_id: &str) -> Option<&MutexVsockUnix> {
+        match self.entry(vsock_id)?.device {
+            VsockAndBackend::Unix(ref vsock) => Some(vsock),
+            VsockAndBackend::Vhost(_) => None,
+        }
+    }
+
+    /// Provides a reference to the vhost-vsock device with the given ID, if present.
+    pub fn get_vhost(&self, vsock_id: &str) -> Option<&MutexVhostVsock> {
+        match self.entry(vsock_id)?.device {
+            VsockAndBackend::Vhost(ref vhost_vsock) => Some(vhost_vsock),
+            VsockAndBackend::Unix(_) => None,
+        }
+    }
+
+    /// Returns the configurations of the devices, in the order they were first inserted in.
+    pub fn configs(&self) -> Vec<VsockDeviceConfig> {
+        self.entries
+            .iter()
+            .map(|entry| entry.config.clone())
+            .collect()
+    }
+
+    fn entry(&self, vsock_id: &str) -> Option<&VsockEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.config.vsock_id == vsock_id)
+    }
+
+    /// Returns an iterator over the Vsock devices with Unix backend.
+    pub fn unix_devices(&self) -> impl Iterator<Item = &MutexVsockUnix> {
+        self.entries.iter().filter_map(|entry| match entry.device {
+            VsockAndBackend::Unix(ref vsock) => Some(vsock),
+            VsockAndBackend::Vhost(_) => None,
+        })
+    }
+
+    /// Returns an iterator over the vhost-vsock devices.
+    pub fn vhost_devices(&self) -> impl Iterator<Item = &MutexVhostVsock> {
+        self.entries.iter().filter_map(|entry| match entry.device {
+            VsockAndBackend::Vhost(ref vhost_vsock) => Some(vhost_vsock),
+            VsockAndBackend::Unix(_) => None,
+        })
+    }
+
+    /// Returns the leases of the guest CIDs leased from pools, which have to be held for as long
+    /// as the devices run.
+    pub fn cid_leases(&self) -> impl Iterator<Item = &Arc<CidLease>> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.cid_lease.as_ref())
+    }
+
+    /// Returns the guest CID of the device with the given ID, if present.
+    pub fn guest_cid(&self, vsock_id: &str) -> Option<u32> {
+        self.entry(vsock_id).map(|entry| entry.config.guest_cid)
+    }
+
+    /// Returns the number of vsock devices.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if there are no vsock devices.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
     }
 
     /// Creates a Vsock device from a VsockDeviceConfig.
     pub fn create_unixsock_vsock(cfg: VsockDeviceConfig) -> Result<Vsock<VsockUnixBackend>> {
-        let backend = VsockUnixBackend::new(u64::from(cfg.guest_cid), cfg.uds_path)
-            .map_err(VsockConfigError::CreateVsockBackend)?;
+        let mode = match cfg.uds_mode {
+            Some(ref mode) => Some(parse_mode(mode)?),
+            None => None,
+        };
+        let backend = match fd_uri::dup(&cfg.uds_path) {
+            Some(file) => {
+                if mode.is_some() || cfg.uds_uid.is_some() || cfg.uds_gid.is_some() {
+                    return Err(VsockConfigError::InheritedUdsPermissions);
+                }
+                inherited_backend(cfg.guest_cid, file.map_err(VsockConfigError::InheritedUds)?)?
+            }
+            None => {
+                let backend = VsockUnixBackend::new(u64::from(cfg.guest_cid), cfg.uds_path.clone())
+                    .map_err(VsockConfigError::CreateVsockBackend)?;
+                if let Err(err) = set_permissions(&cfg.uds_path, mode, cfg.uds_uid, cfg.uds_gid) {
+                    let _ = fs::remove_file(&cfg.uds_path);
+                    return Err(VsockConfigError::UdsPermissions(err));
+                }
+                backend
+            }
+        };
 
         Ok(Vsock::new(u64::from(cfg.guest_cid), backend)
             .map_err(VsockConfigError::CreateVsockDevice)?)
     }
+
+    /// Creates a vhost-vsock device from a VsockDeviceConfig.
+    pub fn create_vhost_vsock(cfg: VsockDeviceConfig) -> Result<VhostVsock> {
+        VhostVsock::new(u64::from(cfg.guest_cid)).map_err(VsockConfigError::CreateVhostVsockDevice)
+    }
+}
+
+// Parses the octal permission bits `mode`, e.g. `0660`.
+fn parse_mode(mode: &str) -> Result<u32> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|&bits| bits <= 0o7777)
+        .ok_or_else(|| VsockConfigError::InvalidUdsMode(mode.to_string()))
+}
+
+// Sets the permission bits and the owner of the socket at `path`, where given.
+fn set_permissions(
+    path: &str,
+    mode: Option<u32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+) -> io::Result<()> {
+    if let Some(mode) = mode {
+        fs::set_permissions(path, Permissions::from_mode(mode))?;
+    }
+    if uid.is_some() || gid.is_some() {
+        let path =
+            CString::new(path).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        // The IDs left at -1 are not changed.
+        let uid = uid.unwrap_or(u32::max_value()) as libc::uid_t;
+        let gid = gid.unwrap_or(u32::max_value()) as libc::gid_t;
+        // Safe because the path is a valid C string, and the return value is checked.
+        if unsafe { libc::chown(path.as_ptr(), uid, gid) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// Builds a backend accepting the host-initiated connections on the inherited socket `file`,
+// which has to be a Unix socket bound to a path, and listening. The guest-initiated connections
+// are forwarded to `<path>_<port>`.
+fn inherited_backend(guest_cid: u32, file: File) -> Result<VsockUnixBackend> {
+    let invalid = |msg: &str| {
+        VsockConfigError::InheritedUds(io::Error::new(io::ErrorKind::InvalidInput, msg))
+    };
+    // Safe because the descriptor is a duplicate owned by the file, which hands it over.
+    let listener = unsafe { UnixListener::from_raw_fd(file.into_raw_fd()) };
+
+    let mut listening: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // Safe because the descriptor is valid, the option fits the buffer given, and the return
+    // value is checked.
+    let ret = unsafe {
+        libc::getsockopt(
+            listener.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_ACCEPTCONN,
+            &mut listening as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return Err(VsockConfigError::InheritedUds(io::Error::last_os_error()));
+    }
+    if listening == 0 {
+        return Err(invalid("The socket is not listening."));
+    }
+    let path = listener
+        .local_addr()
+        .map_err(VsockConfigError::InheritedUds)?
+        .as_pathname()
+        .and_then(Path::to_str)
+        .map(String::from)
+        .ok_or_else(|| invalid("The socket is not bound to a path."))?;
+
+    VsockUnixBackend::with_listener(u64::from(guest_cid), listener, path)
+        .map_err(VsockConfigError::CreateVsockBackend)
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
+    use std::convert::TryFrom;
+
     use super::*;
+    use cid_registry;
+    use utils::tempdir::TempDir;
     use utils::tempfile::TempFile;
 
     // Placeholder for the path where a socket file will be created.
@@ -123,9 +594,14 @@ pub(crate) mod tests {
     pub(crate) fn default_config(tmp_sock_file: &TempSockFile) -> VsockDeviceConfig {
         let vsock_dev_id = "vsock";
         VsockDeviceConfig {
-            vsock_id: vsock_dev_id.to_string(),
+            vsock_id: Identifier::try_from(vsock_dev_id).unwrap(),
             guest_cid: 3,
             uds_path: tmp_sock_file.path().clone(),
+            backend: VsockBackendType::Uds,
+            uds_mode: None,
+            uds_uid: None,
+            uds_gid: None,
+            cid_pool: None,
         }
     }
 
@@ -143,14 +619,289 @@ pub(crate) mod tests {
         let mut vsock_config = default_config(&tmp_sock_file);
 
         store.insert(vsock_config.clone()).unwrap();
-        let vsock = store.get().unwrap();
+        let vsock = store.get("vsock").unwrap();
         assert_eq!(vsock.lock().unwrap().id(), &vsock_config.vsock_id);
 
         let new_cid = vsock_config.guest_cid + 1;
         vsock_config.guest_cid = new_cid;
         store.insert(vsock_config).unwrap();
-        let vsock = store.get().unwrap();
+        let vsock = store.get("vsock").unwrap();
         assert_eq!(vsock.lock().unwrap().cid(), new_cid as u64);
+        assert!(store.get_vhost("vsock").is_none());
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn test_multiple_vsock_insert() {
+        let mut store = VsockBuilder::new();
+        assert!(store.is_empty());
+        let tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        let other_tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        let vsock_config = default_config(&tmp_sock_file);
+        let mut other_config = default_config(&other_tmp_sock_file);
+        other_config.vsock_id = Identifier::try_from("other_vsock").unwrap();
+        other_config.guest_cid = vsock_config.guest_cid + 1;
+
+        store.insert(vsock_config.clone()).unwrap();
+        store.insert(other_config.clone()).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get("other_vsock").unwrap().lock().unwrap().cid(), 4);
+
+        // Each device needs its own CID and its own socket.
+        let mut conflicting_config = other_config.clone();
+        conflicting_config.guest_cid = vsock_config.guest_cid;
+        match store.insert(conflicting_config) {
+            Err(VsockConfigError::GuestCidInUse(3)) => (),
+            _ => panic!("Unexpected result"),
+        }
+        let mut conflicting_config = other_config.clone();
+        conflicting_config.uds_path = vsock_config.uds_path.clone();
+        match store.insert(conflicting_config) {
+            Err(VsockConfigError::UdsPathInUse(_)) => (),
+            _ => panic!("Unexpected result"),
+        }
+        assert_eq!(store.len(), 2);
+
+        // Updating a device keeps its place.
+        other_config.guest_cid += 1;
+        store.insert(other_config).unwrap();
+        let ids: Vec<String> = store
+            .unix_devices()
+            .map(|vsock| vsock.lock().unwrap().id().to_string())
+            .collect();
+        assert_eq!(ids, vec!["vsock", "other_vsock"]);
+        assert_eq!(store.get("other_vsock").unwrap().lock().unwrap().cid(), 5);
+        assert_eq!(store.vhost_devices().count(), 0);
+    }
+
+    #[test]
+    fn test_vsock_set() {
+        let mut store = VsockBuilder::new();
+        let tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        let other_tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        let vsock_config = default_config(&tmp_sock_file);
+        let mut other_config = default_config(&other_tmp_sock_file);
+        other_config.vsock_id = Identifier::try_from("other_vsock").unwrap();
+        other_config.guest_cid = vsock_config.guest_cid + 1;
+
+        store.insert(vsock_config.clone()).unwrap();
+        store.insert(other_config.clone()).unwrap();
+        assert_eq!(store.len(), 2);
+
+        // Setting a device replaces all the others, and removes their sockets.
+        store.set(other_config.clone()).unwrap();
+        assert_eq!(store.len(), 1);
+        assert!(store.get("vsock").is_none());
+        assert!(!std::path::Path::new(&vsock_config.uds_path).exists());
+        assert_eq!(store.get("other_vsock").unwrap().lock().unwrap().cid(), 4);
+
+        // The CID of the replaced devices can be taken again.
+        let mut config = vsock_config;
+        config.guest_cid = other_config.guest_cid;
+        store.set(config).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get("vsock").unwrap().lock().unwrap().cid(), 4);
+
+        // An invalid configuration replaces nothing.
+        let mut config = other_config;
+        config.guest_cid = 5;
+        store.insert(config.clone()).unwrap();
+        config.guest_cid = 0;
+        match store.set(config.clone()) {
+            Err(VsockConfigError::MissingGuestCid) => (),
+            _ => panic!("Unexpected result"),
+        }
+        config.guest_cid = 6;
+        config.uds_mode = Some("0999".to_string());
+        match store.set(config) {
+            Err(VsockConfigError::InvalidUdsMode(_)) => (),
+            _ => panic!("Unexpected result"),
+        }
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn test_vhost_vsock_insert() {
+        let mut store = VsockBuilder::new();
+        let tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        let mut vsock_config = default_config(&tmp_sock_file);
+        store.insert(vsock_config.clone()).unwrap();
+
+        vsock_config.backend = VsockBackendType::Vhost;
+        match store.insert(vsock_config) {
+            Ok(()) => {
+                assert!(store.get("vsock").is_none());
+                assert_eq!(store.get_vhost("vsock").unwrap().lock().unwrap().cid(), 3);
+            }
+            // The host may not have the vhost-vsock module loaded.
+            Err(VsockConfigError::CreateVhostVsockDevice(VhostVsockError::OpenVhostDevice(_))) => {
+                assert!(store.get("vsock").is_none());
+                assert!(store.get_vhost("vsock").is_none());
+            }
+            Err(e) => panic!("Cannot create the vhost-vsock device: {:?}", e),
+        }
+        // The socket of the replaced Unix backend was removed.
+        assert!(!std::path::Path::new(tmp_sock_file.path()).exists());
+    }
+
+    #[test]
+    fn test_vsock_cid_pool() {
+        let registry = TempDir::new().unwrap();
+        let pool = VsockCidPool {
+            first_cid: 3,
+            last_cid: 4,
+            registry_dir: registry.as_path().to_str().unwrap().to_string(),
+        };
+        let mut store = VsockBuilder::new();
+        let tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        let mut vsock_config = default_config(&tmp_sock_file);
+        vsock_config.guest_cid = 0;
+        match store.insert(vsock_config.clone()) {
+            Err(VsockConfigError::MissingGuestCid) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // Another instance leases the first CID of the pool.
+        let other_lease = CidLease::acquire(registry.as_path(), 3, "other")
+            .unwrap()
+            .unwrap();
+        vsock_config.cid_pool = Some(pool.clone());
+        store.insert(vsock_config.clone()).unwrap();
+        assert_eq!(store.guest_cid("vsock"), Some(4));
+        assert_eq!(store.get("vsock").unwrap().lock().unwrap().cid(), 4);
+        assert_eq!(
+            cid_registry::owner(registry.as_path(), 4).unwrap().unwrap(),
+            ""
+        );
+        store.set_instance_id("vm0").unwrap();
+        assert_eq!(
+            cid_registry::owner(registry.as_path(), 4).unwrap().unwrap(),
+            "vm0"
+        );
+        assert_eq!(store.cid_leases().count(), 1);
+
+        // Updating the device keeps its CID.
+        store.insert(vsock_config.clone()).unwrap();
+        assert_eq!(store.guest_cid("vsock"), Some(4));
+
+        let other_tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        let mut other_config = default_config(&other_tmp_sock_file);
+        other_config.vsock_id = Identifier::try_from("other_vsock").unwrap();
+        other_config.guest_cid = 0;
+        other_config.cid_pool = Some(pool.clone());
+        match store.insert(other_config.clone()) {
+            Err(VsockConfigError::CidPoolExhausted(3, 4)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        other_config.guest_cid = 3;
+        match store.insert(other_config.clone()) {
+            Err(VsockConfigError::GuestCidInUse(3)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        other_config.guest_cid = 5;
+        match store.insert(other_config.clone()) {
+            Err(VsockConfigError::GuestCidOutOfPool(5)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // The CIDs released are leased again.
+        drop(other_lease);
+        other_config.guest_cid = 0;
+        store.insert(other_config.clone()).unwrap();
+        assert_eq!(store.guest_cid("other_vsock"), Some(3));
+        assert_eq!(
+            cid_registry::owner(registry.as_path(), 3).unwrap().unwrap(),
+            "vm0"
+        );
+
+        other_config.cid_pool = Some(VsockCidPool {
+            first_cid: 2,
+            ..pool
+        });
+        match store.insert(other_config) {
+            Err(VsockConfigError::InvalidCidPool) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_uds_permissions() {
+        let tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        let mut vsock_config = default_config(&tmp_sock_file);
+        vsock_config.uds_mode = Some("0999".to_string());
+        match VsockBuilder::create_unixsock_vsock(vsock_config.clone()) {
+            Err(VsockConfigError::InvalidUdsMode(ref mode)) if mode == "0999" => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert!(!Path::new(tmp_sock_file.path()).exists());
+
+        // Safe because these calls have no side effects.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        vsock_config.uds_mode = Some("0660".to_string());
+        vsock_config.uds_uid = Some(uid);
+        vsock_config.uds_gid = Some(gid);
+        let _vsock = VsockBuilder::create_unixsock_vsock(vsock_config).unwrap();
+        let metadata = fs::metadata(tmp_sock_file.path()).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o660);
+    }
+
+    #[test]
+    fn test_inherited_uds() {
+        use std::os::unix::net::{UnixDatagram, UnixStream};
+
+        let tmp_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        let listener = UnixListener::bind(tmp_sock_file.path()).unwrap();
+        // Only the file descriptors recorded as inherited can be named.
+        let datagram_sock_file = TempSockFile::new(TempFile::new().unwrap());
+        let datagram = UnixDatagram::bind(datagram_sock_file.path()).unwrap();
+        let file = TempFile::new().unwrap();
+        fd_uri::record_inherited_fds().unwrap();
+        let mut store = VsockBuilder::new();
+        let mut vsock_config = default_config(&tmp_sock_file);
+        vsock_config.uds_path = format!("fd://{}", listener.as_raw_fd());
+        vsock_config.uds_mode = Some("0600".to_string());
+        match store.insert(vsock_config.clone()) {
+            Err(VsockConfigError::InheritedUdsPermissions) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        vsock_config.uds_mode = None;
+        store.insert(vsock_config.clone()).unwrap();
+        // Replacing the device leaves the inherited socket alone.
+        store.insert(vsock_config.clone()).unwrap();
+        assert!(Path::new(tmp_sock_file.path()).exists());
+        UnixStream::connect(tmp_sock_file.path()).unwrap();
+
+        // Only the Unix sockets bound to a path, and listening, can be inherited.
+        vsock_config.uds_path = format!("fd://{}", datagram.as_raw_fd());
+        match VsockBuilder::create_unixsock_vsock(vsock_config.clone()) {
+            Err(VsockConfigError::InheritedUds(_)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        vsock_config.uds_path = format!("fd://{}", file.as_file().as_raw_fd());
+        match VsockBuilder::create_unixsock_vsock(vsock_config) {
+            Err(VsockConfigError::InheritedUds(_)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_vsock_backend_type() {
+        let config: VsockDeviceConfig =
+            serde_json::from_str(r#"{"vsock_id": "vsock", "guest_cid": 3, "uds_path": "v.sock"}"#)
+                .unwrap();
+        assert_eq!(config.backend, VsockBackendType::Uds);
+
+        let config: VsockDeviceConfig =
+            serde_json::from_str(r#"{"vsock_id": "vsock", "guest_cid": 3, "backend": "vhost"}"#)
+                .unwrap();
+        assert_eq!(config.backend, VsockBackendType::Vhost);
+        assert!(config.uds_path.is_empty());
+
+        assert!(serde_json::from_str::<VsockDeviceConfig>(
+            r#"{"vsock_id": "vsock", "guest_cid": 3, "backend": "vsock"}"#
+        )
+        .is_err());
     }
 
     #[test]
@@ -166,5 +917,52 @@ pub(crate) mod tests {
             io::Error::from_raw_os_error(0),
         ));
         let _ = format!("{}{:?}", err, err);
+
+        let err = CreateVhostVsockDevice(devices::virtio::VhostVsockError::OpenVhostDevice(
+            io::Error::from_raw_os_error(0),
+        ));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = DeviceNotFound;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = GuestCidInUse(3);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = GuestCidOutOfPool(5);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = CidPoolExhausted(3, 4);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = CidRegistry(io::Error::from_raw_os_error(libc::EACCES));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InheritedUds(io::Error::from_raw_os_error(libc::ENOTSOCK));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InheritedUdsPermissions;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidCidPool;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidUdsMode(String::from("0999"));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = MissingGuestCid;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = UdsPathInUse(String::from("v.sock"));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = UdsPermissions(io::Error::from_raw_os_error(libc::EPERM));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = VhostConnections;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = ResetTransport(devices::Error::NoAvailBuffers);
+        let _ = format!("{}{:?}", err, err);
     }
 }
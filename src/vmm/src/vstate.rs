@@ -8,7 +8,13 @@
 use libc::{c_int, c_void, siginfo_t};
 use std::cell::Cell;
 use std::fmt::{Display, Formatter};
+use std::fs::File;
 use std::io;
+#[cfg(target_arch = "x86_64")]
+use std::mem;
+use std::os::unix::io::IntoRawFd;
+#[cfg(target_arch = "x86_64")]
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::result;
 use std::sync::atomic::{fence, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
@@ -16,26 +22,35 @@ use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 use std::sync::Barrier;
 use std::thread;
 
+use super::FcExitCode;
 use super::TimestampUs;
-use super::{FC_EXIT_CODE_GENERIC_ERROR, FC_EXIT_CODE_OK};
+use events::{EventSender, VmmEvent};
 
 use arch;
 #[cfg(target_arch = "aarch64")]
-use arch::aarch64::gic::GICDevice;
+use arch::aarch64::gic::{GICConfig, GICDevice};
+#[cfg(target_arch = "x86_64")]
+use cpuid::{
+    c3, filter_cpuid, find_cpu_feature, set_cpu_features, set_invariant_tsc,
+    set_nested_virtualization, set_tsc_deadline_timer, t2, t2a, VmSpec, CPU_FEATURES,
+};
 #[cfg(target_arch = "x86_64")]
-use cpuid::{c3, filter_cpuid, t2, VmSpec};
+use devices::legacy::{MsiRoute, MsiRouter, IOAPIC_NUM_PINS};
 #[cfg(target_arch = "x86_64")]
 use kvm_bindings::{
-    kvm_clock_data, kvm_debugregs, kvm_irqchip, kvm_lapic_state, kvm_mp_state, kvm_pit_config,
+    kvm_clock_data, kvm_debugregs, kvm_enable_cap, kvm_irq_routing, kvm_irq_routing_entry,
+    kvm_irq_routing_msi, kvm_irqchip, kvm_lapic_state, kvm_mp_state, kvm_pit_config,
     kvm_pit_state2, kvm_regs, kvm_sregs, kvm_vcpu_events, kvm_xcrs, kvm_xsave, CpuId, MsrList,
-    Msrs, KVM_CLOCK_TSC_STABLE, KVM_IRQCHIP_IOAPIC, KVM_IRQCHIP_PIC_MASTER, KVM_IRQCHIP_PIC_SLAVE,
-    KVM_MAX_CPUID_ENTRIES, KVM_PIT_SPEAKER_DUMMY,
+    Msrs, KVM_CAP_SPLIT_IRQCHIP, KVM_CLOCK_TSC_STABLE, KVM_IRQCHIP_IOAPIC, KVM_IRQCHIP_PIC_MASTER,
+    KVM_IRQCHIP_PIC_SLAVE, KVM_IRQ_ROUTING_MSI, KVM_MAX_CPUID_ENTRIES, KVM_PIT_SPEAKER_DUMMY,
 };
 use kvm_bindings::{kvm_userspace_memory_region, KVM_API_VERSION, KVM_MEM_LOG_DIRTY_PAGES};
 use kvm_ioctls::*;
 use logger::{Metric, METRICS};
 use seccomp::{BpfProgram, SeccompFilter};
 use utils::eventfd::EventFd;
+#[cfg(target_arch = "x86_64")]
+use utils::ioctl::{ioctl, ioctl_with_ref, ioctl_with_val};
 use utils::signal::{register_signal_handler, sigrtmin, Killable};
 use utils::sm::StateMachine;
 #[cfg(target_arch = "x86_64")]
@@ -45,7 +60,9 @@ use versionize_derive::Versionize;
 use vm_memory::{
     Address, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap, GuestMemoryRegion,
 };
-use vmm_config::machine_config::CpuFeaturesTemplate;
+#[cfg(target_arch = "x86_64")]
+use vmm_config::machine_config::CpuFeaturesProfile;
+use vmm_config::machine_config::{CpuFeaturesConfig, CpuFeaturesTemplate, VcpuRetryConfig};
 
 #[cfg(target_arch = "x86_64")]
 const MAGIC_IOPORT_SIGNAL_GUEST_BOOT_COMPLETE: u64 = 0x03f0;
@@ -53,6 +70,17 @@ const MAGIC_IOPORT_SIGNAL_GUEST_BOOT_COMPLETE: u64 = 0x03f0;
 const MAGIC_IOPORT_SIGNAL_GUEST_BOOT_COMPLETE: u64 = 0x40000000;
 const MAGIC_VALUE_SIGNAL_GUEST_BOOT_COMPLETE: u8 = 123;
 
+// The ioctls reading and scaling the TSC frequency of a vCPU, which aren't wrapped by `VcpuFd`.
+// The seccomp filter allows them too.
+pub(crate) const KVM_SET_TSC_KHZ: u64 = 0xaea2;
+pub(crate) const KVM_GET_TSC_KHZ: u64 = 0xaea3;
+
+// The ioctls splitting the irqchip and routing the GSIs, which aren't wrapped by `VmFd`. The
+// seccomp filter only allows the latter.
+#[cfg(target_arch = "x86_64")]
+const KVM_ENABLE_CAP: u64 = 0x4068_aea3;
+pub(crate) const KVM_SET_GSI_ROUTING: u64 = 0x4008_ae6a;
+
 /// Signal number (SIGRTMIN) used to kick Vcpus.
 pub(crate) const VCPU_RTSIG_OFFSET: i32 = 0;
 
@@ -76,16 +104,18 @@ pub enum Error {
     Irq(kvm_ioctls::Error),
     /// The host kernel reports an invalid KVM API version.
     KvmApiVersion(i32),
-    /// Cannot initialize the KVM context due to missing capabilities.
-    KvmCap(kvm_ioctls::Cap),
+    /// The host kernel lacks KVM extensions the microVM needs, named as in the KVM API.
+    KvmCaps(Vec<&'static str>),
+    /// Cannot open `/dev/kvm`.
+    KvmOpen(kvm_ioctls::Error),
     #[cfg(target_arch = "x86_64")]
     /// Cannot set the local interruption due to bad configuration.
     LocalIntConfiguration(arch::x86_64::interrupts::Error),
     #[cfg(target_arch = "x86_64")]
     /// Error configuring the MSR registers
     MSRSConfiguration(arch::x86_64::msr::Error),
-    /// The number of configured slots is bigger than the maximum reported by KVM.
-    NotEnoughMemorySlots,
+    /// The guest memory needs more memory slots, the first number, than KVM has, the second.
+    NotEnoughMemorySlots(usize, usize),
     #[cfg(target_arch = "aarch64")]
     /// Error configuring the general purpose aarch64 registers.
     REGSConfiguration(arch::aarch64::regs::Error),
@@ -100,6 +130,12 @@ pub enum Error {
     /// Failed to signal Vcpu.
     SignalVcpu(utils::errno::Error),
     #[cfg(target_arch = "x86_64")]
+    /// Cannot split the irqchip.
+    SplitIrqchip(io::Error),
+    #[cfg(target_arch = "x86_64")]
+    /// The state of a VM with a split irqchip can't be saved.
+    SplitIrqchipState,
+    #[cfg(target_arch = "x86_64")]
     /// Error configuring the special registers
     SREGSConfiguration(arch::x86_64::regs::Error),
     #[cfg(target_arch = "aarch64")]
@@ -131,6 +167,9 @@ pub enum Error {
     /// Failed to get KVM vcpu sregs.
     VcpuGetSregs(kvm_ioctls::Error),
     #[cfg(target_arch = "x86_64")]
+    /// Failed to get the TSC frequency of the KVM vcpu.
+    VcpuGetTscKhz(io::Error),
+    #[cfg(target_arch = "x86_64")]
     /// Failed to get KVM vcpu event.
     VcpuGetVcpuEvents(kvm_ioctls::Error),
     #[cfg(target_arch = "x86_64")]
@@ -163,6 +202,9 @@ pub enum Error {
     /// Failed to set KVM vcpu sregs.
     VcpuSetSregs(kvm_ioctls::Error),
     #[cfg(target_arch = "x86_64")]
+    /// Failed to set the TSC frequency of the KVM vcpu.
+    VcpuSetTscKhz(io::Error),
+    #[cfg(target_arch = "x86_64")]
     /// Failed to set KVM vcpu event.
     VcpuSetVcpuEvents(kvm_ioctls::Error),
     #[cfg(target_arch = "x86_64")]
@@ -219,15 +261,21 @@ impl Display for Error {
                 "The host kernel reports an invalid KVM API version: {}",
                 v
             ),
-            KvmCap(cap) => write!(f, "Missing KVM capabilities: {:?}", cap),
+            KvmCaps(names) => write!(
+                f,
+                "The host kernel lacks the KVM extensions: {}",
+                names.join(", ")
+            ),
+            KvmOpen(e) => write!(f, "Cannot open /dev/kvm: {}", e),
             VcpuCountNotInitialized => write!(f, "vCPU count is not initialized"),
             VmFd(e) => write!(f, "Cannot open the VM file descriptor: {}", e),
             VcpuFd(e) => write!(f, "Cannot open the VCPU file descriptor: {}", e),
             VmSetup(e) => write!(f, "Cannot configure the microvm: {}", e),
             VcpuRun(e) => write!(f, "Cannot run the VCPUs: {}", e),
-            NotEnoughMemorySlots => write!(
+            NotEnoughMemorySlots(needed, max) => write!(
                 f,
-                "The number of configured slots is bigger than the maximum reported by KVM"
+                "The guest memory needs {} memory slots, but KVM only has {}.",
+                needed, max
             ),
             #[cfg(target_arch = "x86_64")]
             LocalIntConfiguration(e) => write!(
@@ -238,6 +286,13 @@ impl Display for Error {
             SetUserMemoryRegion(e) => write!(f, "Cannot set the memory regions: {}", e),
             SignalVcpu(e) => write!(f, "Failed to signal Vcpu: {}", e),
             #[cfg(target_arch = "x86_64")]
+            SplitIrqchip(e) => write!(f, "Cannot split the irqchip: {}", e),
+            #[cfg(target_arch = "x86_64")]
+            SplitIrqchipState => write!(
+                f,
+                "The state of a microVM with a split irqchip can't be saved."
+            ),
+            #[cfg(target_arch = "x86_64")]
             MSRSConfiguration(e) => write!(f, "Error configuring the MSR registers: {:?}", e),
             #[cfg(target_arch = "aarch64")]
             REGSConfiguration(e) => write!(
@@ -273,6 +328,8 @@ impl Display for Error {
             #[cfg(target_arch = "x86_64")]
             VcpuGetSregs(e) => write!(f, "Failed to get KVM vcpu sregs: {}", e),
             #[cfg(target_arch = "x86_64")]
+            VcpuGetTscKhz(ref e) => write!(f, "Failed to get KVM vcpu TSC frequency: {}", e),
+            #[cfg(target_arch = "x86_64")]
             VcpuGetVcpuEvents(e) => write!(f, "Failed to get KVM vcpu event: {}", e),
             #[cfg(target_arch = "x86_64")]
             VcpuGetXcrs(e) => write!(f, "Failed to get KVM vcpu xcrs: {}", e),
@@ -293,6 +350,8 @@ impl Display for Error {
             #[cfg(target_arch = "x86_64")]
             VcpuSetSregs(e) => write!(f, "Failed to set KVM vcpu sregs: {}", e),
             #[cfg(target_arch = "x86_64")]
+            VcpuSetTscKhz(ref e) => write!(f, "Failed to set KVM vcpu TSC frequency: {}", e),
+            #[cfg(target_arch = "x86_64")]
             VcpuSetVcpuEvents(e) => write!(f, "Failed to set KVM vcpu event: {}", e),
             #[cfg(target_arch = "x86_64")]
             VcpuSetXcrs(e) => write!(f, "Failed to set KVM vcpu xcrs: {}", e),
@@ -340,35 +399,91 @@ pub struct KvmContext {
     max_memslots: usize,
 }
 
+/// The KVM capabilities the microVMs can't do without, along with their names in the KVM API.
+#[cfg(target_arch = "x86_64")]
+pub(crate) const REQUIRED_KVM_CAPS: [(kvm_ioctls::Cap, &str); 6] = [
+    (kvm_ioctls::Cap::Irqchip, "KVM_CAP_IRQCHIP"),
+    (kvm_ioctls::Cap::Ioeventfd, "KVM_CAP_IOEVENTFD"),
+    (kvm_ioctls::Cap::Irqfd, "KVM_CAP_IRQFD"),
+    (kvm_ioctls::Cap::UserMemory, "KVM_CAP_USER_MEMORY"),
+    (kvm_ioctls::Cap::SetTssAddr, "KVM_CAP_SET_TSS_ADDR"),
+    (kvm_ioctls::Cap::Pit2, "KVM_CAP_PIT2"),
+];
+
+/// The KVM capabilities the microVMs can't do without, along with their names in the KVM API.
+#[cfg(target_arch = "aarch64")]
+pub(crate) const REQUIRED_KVM_CAPS: [(kvm_ioctls::Cap, &str); 5] = [
+    (kvm_ioctls::Cap::Irqchip, "KVM_CAP_IRQCHIP"),
+    (kvm_ioctls::Cap::Ioeventfd, "KVM_CAP_IOEVENTFD"),
+    (kvm_ioctls::Cap::Irqfd, "KVM_CAP_IRQFD"),
+    (kvm_ioctls::Cap::UserMemory, "KVM_CAP_USER_MEMORY"),
+    (kvm_ioctls::Cap::ArmPsci02, "KVM_CAP_ARM_PSCI_0_2"),
+];
+
+/// The KVM capabilities the microVMs fall back from when the host kernel lacks them, along
+/// with their names in the KVM API and the fallbacks.
+#[cfg(target_arch = "x86_64")]
+const OPTIONAL_KVM_CAPS: [(kvm_ioctls::Cap, &str, &str); 3] = [
+    (
+        kvm_ioctls::Cap::TscDeadlineTimer,
+        "KVM_CAP_TSC_DEADLINE_TIMER",
+        "the guests use the one-shot mode of the LAPIC timer",
+    ),
+    (
+        kvm_ioctls::Cap::GetTscKhz,
+        "KVM_CAP_GET_TSC_KHZ",
+        "the snapshots don't record the TSC frequency",
+    ),
+    (
+        kvm_ioctls::Cap::TscControl,
+        "KVM_CAP_TSC_CONTROL",
+        "the TSC frequency can't be pinned",
+    ),
+];
+
+/// Returns the names of the capabilities in `caps` which `kvm` lacks.
+pub(crate) fn missing_kvm_caps(
+    kvm: &Kvm,
+    caps: &[(kvm_ioctls::Cap, &'static str)],
+) -> Vec<&'static str> {
+    caps.iter()
+        .filter(|&&(cap, _)| !kvm.check_extension(cap))
+        .map(|&(_, name)| name)
+        .collect()
+}
+
 impl KvmContext {
     pub fn new() -> Result<Self> {
-        use kvm_ioctls::Cap::*;
-        let kvm = Kvm::new().expect("Error creating the Kvm object");
+        Self::with_kvm(Kvm::new().map_err(Error::KvmOpen)?)
+    }
+
+    /// Creates the KVM context from `kvm_file`, the already opened `/dev/kvm`.
+    pub fn from_file(kvm_file: File) -> Result<Self> {
+        // Safe because the descriptor is taken out of the file, so it's owned by the `Kvm` only.
+        Self::with_kvm(unsafe { Kvm::new_with_fd_number(kvm_file.into_raw_fd()) })
+    }
 
+    fn with_kvm(kvm: Kvm) -> Result<Self> {
         // Check that KVM has the correct version.
         if kvm.get_api_version() != KVM_API_VERSION as i32 {
             return Err(Error::KvmApiVersion(kvm.get_api_version()));
         }
 
-        // A list of KVM capabilities we want to check.
-        #[cfg(target_arch = "x86_64")]
-        let capabilities = vec![Irqchip, Ioeventfd, Irqfd, UserMemory, SetTssAddr];
-
-        #[cfg(target_arch = "aarch64")]
-        let capabilities = vec![Irqchip, Ioeventfd, Irqfd, UserMemory, ArmPsci02];
-
         // Check that all desired capabilities are supported.
-        match capabilities
-            .iter()
-            .find(|&capability| !kvm.check_extension(*capability))
-        {
-            None => {
-                let max_memslots = kvm.get_nr_memslots();
-                Ok(KvmContext { kvm, max_memslots })
-            }
+        let missing_caps = missing_kvm_caps(&kvm, &REQUIRED_KVM_CAPS);
+        if !missing_caps.is_empty() {
+            return Err(Error::KvmCaps(missing_caps));
+        }
 
-            Some(c) => Err(Error::KvmCap(*c)),
+        #[cfg(target_arch = "x86_64")]
+        for &(cap, name, fallback) in OPTIONAL_KVM_CAPS.iter() {
+            if !kvm.check_extension(cap) {
+                warn!("The host kernel lacks {}, so {}.", name, fallback);
+            }
         }
+
+        let max_memslots = kvm.get_nr_memslots();
+        Ok(KvmContext { kvm, max_memslots })
     }
 
     pub fn fd(&self) -> &Kvm {
@@ -381,6 +496,29 @@ impl KvmContext {
     }
 }
 
+/// The optional KVM capabilities of the host, which the vCPUs fall back from when missing.
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OptionalKvmCaps {
+    /// Whether KVM emulates the TSC deadline mode of the LAPIC timer.
+    pub tsc_deadline_timer: bool,
+    /// Whether KVM reports the TSC frequency of the vCPUs.
+    pub get_tsc_khz: bool,
+    /// Whether KVM scales the TSC frequency of the vCPUs.
+    pub tsc_control: bool,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl OptionalKvmCaps {
+    fn probe(kvm: &Kvm) -> Self {
+        OptionalKvmCaps {
+            tsc_deadline_timer: kvm.check_extension(Cap::TscDeadlineTimer),
+            get_tsc_khz: kvm.check_extension(Cap::GetTscKhz),
+            tsc_control: kvm.check_extension(Cap::TscControl),
+        }
+    }
+}
+
 /// A wrapper around creating and using a VM.
 pub struct Vm {
     fd: VmFd,
@@ -390,6 +528,12 @@ pub struct Vm {
     supported_cpuid: CpuId,
     #[cfg(target_arch = "x86_64")]
     supported_msrs: MsrList,
+    #[cfg(target_arch = "x86_64")]
+    optional_kvm_caps: OptionalKvmCaps,
+    #[cfg(target_arch = "x86_64")]
+    split_irqchip: bool,
+    #[cfg(target_arch = "x86_64")]
+    pit: bool,
 
     // Arm specific fields.
     // On aarch64 we need to keep around the fd obtained by creating the VGIC device.
@@ -417,6 +561,12 @@ impl Vm {
             supported_cpuid,
             #[cfg(target_arch = "x86_64")]
             supported_msrs,
+            #[cfg(target_arch = "x86_64")]
+            optional_kvm_caps: OptionalKvmCaps::probe(kvm),
+            #[cfg(target_arch = "x86_64")]
+            split_irqchip: false,
+            #[cfg(target_arch = "x86_64")]
+            pit: false,
             #[cfg(target_arch = "aarch64")]
             irqchip_handle: None,
         })
@@ -434,6 +584,12 @@ impl Vm {
         &self.supported_msrs
     }
 
+    /// Returns the optional KVM capabilities of the host.
+    #[cfg(target_arch = "x86_64")]
+    pub fn optional_kvm_caps(&self) -> OptionalKvmCaps {
+        self.optional_kvm_caps
+    }
+
     /// Initializes the guest memory.
     pub fn memory_init(
         &mut self,
@@ -442,7 +598,10 @@ impl Vm {
         track_dirty_pages: bool,
     ) -> Result<()> {
         if guest_mem.num_regions() > kvm_max_memslots {
-            return Err(Error::NotEnoughMemorySlots);
+            return Err(Error::NotEnoughMemorySlots(
+                guest_mem.num_regions(),
+                kvm_max_memslots,
+            ));
         }
         let flags = if track_dirty_pages {
             KVM_MEM_LOG_DIRTY_PAGES
@@ -476,22 +635,67 @@ impl Vm {
         Ok(())
     }
 
-    /// Creates the irq chip and an in-kernel device model for the PIT.
+    /// Creates the irq chip and, when `pit` is true, an in-kernel device model for the PIT.
     #[cfg(target_arch = "x86_64")]
-    pub fn setup_irqchip(&self) -> Result<()> {
+    pub fn setup_irqchip(&mut self, pit: bool) -> Result<()> {
         self.fd.create_irq_chip().map_err(Error::VmSetup)?;
+        if !pit {
+            return Ok(());
+        }
         let mut pit_config = kvm_pit_config::default();
         // We need to enable the emulation of a dummy speaker port stub so that writing to port 0x61
         // (i.e. KVM_SPEAKER_BASE_ADDRESS) does not trigger an exit to user space.
         pit_config.flags = KVM_PIT_SPEAKER_DUMMY;
-        self.fd.create_pit2(pit_config).map_err(Error::VmSetup)
+        self.fd.create_pit2(pit_config).map_err(Error::VmSetup)?;
+        self.pit = true;
+        Ok(())
+    }
+
+    /// Splits the irqchip: KVM only emulates the local APICs, leaving the IOAPIC to userspace,
+    /// without a PIC nor a PIT.
+    #[cfg(target_arch = "x86_64")]
+    pub fn setup_split_irqchip(&mut self) -> Result<()> {
+        let mut cap = kvm_enable_cap::default();
+        cap.cap = KVM_CAP_SPLIT_IRQCHIP;
+        // The GSIs reserved for the pins of the IOAPIC.
+        cap.args[0] = IOAPIC_NUM_PINS as u64;
+        // Safe because we know that our file is a VM fd, we know the kernel will only read the
+        // correct amount of memory from our pointer, and we verify the return result.
+        let ret = unsafe { ioctl_with_ref(&self.fd, KVM_ENABLE_CAP, &cap) };
+        if ret < 0 {
+            return Err(Error::SplitIrqchip(io::Error::last_os_error()));
+        }
+        self.split_irqchip = true;
+        Ok(())
+    }
+
+    /// Returns whether the irqchip is split.
+    #[cfg(target_arch = "x86_64")]
+    pub fn split_irqchip(&self) -> bool {
+        self.split_irqchip
+    }
+
+    /// Returns the router of the pins of the IOAPIC emulated in userspace, along with a split
+    /// irqchip.
+    #[cfg(target_arch = "x86_64")]
+    pub fn msi_router(&self) -> Result<KvmMsiRouter> {
+        // Safe because the duplicate is a new descriptor, owned by the router only, and we check
+        // the return value.
+        let fd = unsafe { libc::dup(self.fd.as_raw_fd()) };
+        if fd < 0 {
+            return Err(Error::SplitIrqchip(io::Error::last_os_error()));
+        }
+        Ok(KvmMsiRouter {
+            vm_fd: unsafe { File::from_raw_fd(fd) },
+        })
     }
 
     /// Creates the GIC (Global Interrupt Controller).
     #[cfg(target_arch = "aarch64")]
-    pub fn setup_irqchip(&mut self, vcpu_count: u8) -> Result<()> {
+    pub fn setup_irqchip(&mut self, vcpu_count: u8, gic_config: &GICConfig) -> Result<()> {
         self.irqchip_handle = Some(
-            arch::aarch64::gic::create_gic(&self.fd, vcpu_count.into()).map_err(Error::SetupGIC)?,
+            arch::aarch64::gic::create_gic(&self.fd, vcpu_count.into(), gic_config)
+                .map_err(Error::SetupGIC)?,
         );
         Ok(())
     }
@@ -507,11 +711,19 @@ impl Vm {
         &self.fd
     }
 
-    #[allow(unused)]
     #[cfg(target_arch = "x86_64")]
     /// Saves and returns the Kvm Vm state.
     pub fn save_state(&self) -> Result<VmState> {
-        let pitstate = self.fd.get_pit2().map_err(Error::VmGetPit2)?;
+        // The state of the IOAPIC emulated in userspace isn't saved.
+        if self.split_irqchip {
+            return Err(Error::SplitIrqchipState);
+        }
+        // The state of a missing PIT is left empty.
+        let pitstate = if self.pit {
+            self.fd.get_pit2().map_err(Error::VmGetPit2)?
+        } else {
+            kvm_pit_state2::default()
+        };
 
         let mut clock = self.fd.get_clock().map_err(Error::VmGetClock)?;
         // This bit is not accepted in SET_CLOCK, clear it.
@@ -544,13 +756,14 @@ impl Vm {
         })
     }
 
-    #[allow(unused)]
     #[cfg(target_arch = "x86_64")]
     /// Restores the Kvm Vm state.
     pub fn restore_state(&self, state: &VmState) -> Result<()> {
-        self.fd
-            .set_pit2(&state.pitstate)
-            .map_err(Error::VmSetPit2)?;
+        if self.pit {
+            self.fd
+                .set_pit2(&state.pitstate)
+                .map_err(Error::VmSetPit2)?;
+        }
         self.fd.set_clock(&state.clock).map_err(Error::VmSetClock)?;
         self.fd
             .set_irqchip(&state.pic_master)
@@ -565,7 +778,64 @@ impl Vm {
     }
 }
 
-#[allow(unused)]
+/// Routes the pins of the IOAPIC emulated in userspace to the local APICs emulated by KVM, by
+/// replacing the GSI routing table of the VM.
+#[cfg(target_arch = "x86_64")]
+pub struct KvmMsiRouter {
+    vm_fd: File,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl MsiRouter for KvmMsiRouter {
+    fn set_routes(&self, routes: &[Option<MsiRoute>]) -> io::Result<()> {
+        let entries: Vec<kvm_irq_routing_entry> = routes
+            .iter()
+            .enumerate()
+            .filter_map(|(gsi, route)| {
+                route.map(|route| {
+                    let mut entry = kvm_irq_routing_entry {
+                        gsi: gsi as u32,
+                        type_: KVM_IRQ_ROUTING_MSI,
+                        ..Default::default()
+                    };
+                    entry.u.msi = kvm_irq_routing_msi {
+                        address_lo: route.address as u32,
+                        address_hi: (route.address >> 32) as u32,
+                        data: route.data,
+                        ..Default::default()
+                    };
+                    entry
+                })
+            })
+            .collect();
+
+        // The routing table is a header followed by its entries, laid out in a vector of headers
+        // large enough to hold them.
+        let header_size = mem::size_of::<kvm_irq_routing>();
+        let entries_size = entries.len() * mem::size_of::<kvm_irq_routing_entry>();
+        let mut routing = Vec::<kvm_irq_routing>::new();
+        routing.resize_with(
+            1 + (entries_size + header_size - 1) / header_size,
+            Default::default,
+        );
+        routing[0].nr = entries.len() as u32;
+        // Safe because the vector has room for the entries after the header.
+        unsafe {
+            routing[0]
+                .entries
+                .as_mut_slice(entries.len())
+                .copy_from_slice(&entries);
+        }
+        // Safe because we know that our file is a VM fd, we know the kernel will only read the
+        // header and the entries it counts, and we verify the return result.
+        let ret = unsafe { ioctl_with_ref(&self.vm_fd, KVM_SET_GSI_ROUTING, &routing[0]) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
 #[cfg(target_arch = "x86_64")]
 #[derive(Versionize)]
 /// Structure holding VM kvm state.
@@ -584,8 +854,23 @@ pub struct VcpuConfig {
     pub vcpu_count: u8,
     /// Enable hyperthreading in the CPUID configuration.
     pub ht_enabled: bool,
+    /// Number of cores per socket in the CPUID configuration.
+    pub cores_per_socket: Option<u8>,
     /// CPUID template to use.
     pub cpu_template: Option<CpuFeaturesTemplate>,
+    /// Host CPU features exposed on top of the CPUID template.
+    pub cpu_features: Option<CpuFeaturesConfig>,
+    /// Expose the hardware virtualization extensions of the host in the CPUID configuration.
+    pub nested: bool,
+    /// Frequency the guest TSC is pinned to in kHz, which also exposes the invariant TSC of the
+    /// host in the CPUID configuration.
+    pub tsc_khz: Option<u32>,
+    /// Expose the TSC deadline mode of the LAPIC timer in the CPUID configuration, on which
+    /// the guests booted without a PIT rely.
+    pub tickless: bool,
+    /// The bit set in the boot page table entries to map the guest memory as encrypted, for SEV
+    /// guests, or 0.
+    pub memory_encryption_mask: u64,
 }
 
 // Using this for easier explicit type-casting to help IDEs interpret the code.
@@ -606,6 +891,11 @@ pub struct Vcpu {
     cpuid: CpuId,
     #[cfg(target_arch = "x86_64")]
     msr_list: MsrList,
+    #[cfg(target_arch = "x86_64")]
+    kvm_caps: OptionalKvmCaps,
+    // The frequency the TSC is pinned to, in kHz, which the snapshots keep.
+    #[cfg(target_arch = "x86_64")]
+    tsc_khz: Option<u32>,
 
     #[cfg(target_arch = "aarch64")]
     mpidr: u64,
@@ -618,6 +908,14 @@ pub struct Vcpu {
     response_receiver: Option<Receiver<VcpuResponse>>,
     // The transmitting end of the responses channel owned by the vcpu side.
     response_sender: Sender<VcpuResponse>,
+
+    // How the transient failures of KVM_RUN are retried.
+    retry_config: VcpuRetryConfig,
+    // The consecutive transient failures of KVM_RUN, and the error number of the last one.
+    kvm_run_failures: u32,
+    kvm_run_errno: i32,
+    // Surfaces the retries of KVM_RUN to the control plane.
+    events: Option<EventSender>,
 }
 
 impl Vcpu {
@@ -711,6 +1009,7 @@ impl Vcpu {
     /// * `vm_fd` - The kvm `VmFd` for the virtual machine this vcpu will get attached to.
     /// * `cpuid` - The `CpuId` listing the supported capabilities of this vcpu.
     /// * `msr_list` - The `MsrList` listing the supported MSRs for this vcpu.
+    /// * `kvm_caps` - The optional KVM capabilities of the host.
     /// * `io_bus` - The io-bus used to access port-io devices.
     /// * `exit_evt` - An `EventFd` that will be written into when this vcpu exits.
     /// * `create_ts` - A timestamp used by the vcpu to calculate its lifetime.
@@ -720,6 +1019,7 @@ impl Vcpu {
         vm_fd: &VmFd,
         cpuid: CpuId,
         msr_list: MsrList,
+        kvm_caps: OptionalKvmCaps,
         io_bus: devices::Bus,
         exit_evt: EventFd,
         create_ts: TimestampUs,
@@ -738,10 +1038,16 @@ impl Vcpu {
             io_bus,
             cpuid,
             msr_list,
+            kvm_caps,
+            tsc_khz: None,
             event_receiver,
             event_sender: Some(event_sender),
             response_receiver: Some(response_receiver),
             response_sender,
+            retry_config: VcpuRetryConfig::default(),
+            kvm_run_failures: 0,
+            kvm_run_errno: 0,
+            events: None,
         })
     }
 
@@ -775,6 +1081,10 @@ impl Vcpu {
             event_sender: Some(event_sender),
             response_receiver: Some(response_receiver),
             response_sender,
+            retry_config: VcpuRetryConfig::default(),
+            kvm_run_failures: 0,
+            kvm_run_errno: 0,
+            events: None,
         })
     }
 
@@ -794,6 +1104,16 @@ impl Vcpu {
         self.mmio_bus = Some(mmio_bus);
     }
 
+    /// Sets how the vcpu retries the transient failures of `KVM_RUN`.
+    pub fn set_retry_config(&mut self, retry_config: VcpuRetryConfig) {
+        self.retry_config = retry_config;
+    }
+
+    /// Surfaces the retries of `KVM_RUN` to the control plane through `events`.
+    pub fn set_event_sender(&mut self, events: EventSender) {
+        self.events = Some(events);
+    }
+
     #[cfg(target_arch = "x86_64")]
     /// Configures a x86_64 specific vcpu and should be called once per vcpu.
     ///
@@ -808,8 +1128,15 @@ impl Vcpu {
         kernel_start_addr: GuestAddress,
         vcpu_config: &VcpuConfig,
     ) -> Result<()> {
-        let cpuid_vm_spec = VmSpec::new(self.id, vcpu_config.vcpu_count, vcpu_config.ht_enabled)
-            .map_err(Error::CpuId)?;
+        let cpuid_vm_spec = VmSpec::with_cores_per_socket(
+            self.id,
+            vcpu_config.vcpu_count,
+            vcpu_config.ht_enabled,
+            vcpu_config.cores_per_socket,
+        )
+        .map_err(Error::CpuId)?;
+        // The CPUID supported by KVM on this host.
+        let supported_cpuid = self.cpuid.clone();
 
         filter_cpuid(&mut self.cpuid, &cpuid_vm_spec).map_err(|e| {
             METRICS.vcpu.filter_cpuid.inc();
@@ -825,9 +1152,60 @@ impl Vcpu {
                 CpuFeaturesTemplate::C3 => {
                     c3::set_cpuid_entries(&mut self.cpuid, &cpuid_vm_spec).map_err(Error::CpuId)?
                 }
+                CpuFeaturesTemplate::T2A => {
+                    t2a::set_cpuid_entries(&mut self.cpuid, &cpuid_vm_spec).map_err(Error::CpuId)?
+                }
             }
         }
 
+        if let Some(ref cpu_features) = vcpu_config.cpu_features {
+            let features: Vec<_> = match *cpu_features {
+                CpuFeaturesConfig::Profile(CpuFeaturesProfile::Passthrough) => CPU_FEATURES
+                    .iter()
+                    .filter(|feature| feature.is_enabled(&supported_cpuid))
+                    .collect(),
+                CpuFeaturesConfig::Profile(CpuFeaturesProfile::Minimal) => Vec::new(),
+                // The names are validated when configuring the microVM.
+                CpuFeaturesConfig::List(ref names) => names
+                    .iter()
+                    .filter_map(|name| find_cpu_feature(name))
+                    .collect(),
+            };
+            set_cpu_features(&mut self.cpuid, &supported_cpuid, &features).map_err(Error::CpuId)?;
+        }
+
+        let nested_supported =
+            set_nested_virtualization(&mut self.cpuid, &supported_cpuid, vcpu_config.nested);
+        if vcpu_config.nested && !nested_supported {
+            warn!(
+                "Nested virtualization is not allowed by KVM on this host, vcpu {} will not \
+                 support it.",
+                self.id
+            );
+        }
+
+        // The TSC is only invariant across snapshots when its frequency is pinned, so the
+        // invariant TSC is otherwise left as the CPUID template has it.
+        if vcpu_config.tsc_khz.is_some()
+            && !set_invariant_tsc(&mut self.cpuid, &supported_cpuid, true)
+        {
+            warn!(
+                "The host TSC is not invariant, vcpu {} will not report an invariant TSC.",
+                self.id
+            );
+        }
+        // The guest falls back to the one-shot mode of the LAPIC timer, unless it is tickless,
+        // in which case the host has been checked to have the TSC deadline timer.
+        if vcpu_config.tickless {
+            set_tsc_deadline_timer(&mut self.cpuid, true);
+        } else if !self.kvm_caps.tsc_deadline_timer {
+            set_tsc_deadline_timer(&mut self.cpuid, false);
+        }
+        if let Some(tsc_khz) = vcpu_config.tsc_khz {
+            self.set_tsc_khz(tsc_khz)?;
+        }
+        self.tsc_khz = vcpu_config.tsc_khz;
+
         self.fd
             .set_cpuid2(&self.cpuid)
             .map_err(Error::VcpuSetCpuid)?;
@@ -836,7 +1214,8 @@ impl Vcpu {
         arch::x86_64::regs::setup_regs(&self.fd, kernel_start_addr.raw_value() as u64)
             .map_err(Error::REGSConfiguration)?;
         arch::x86_64::regs::setup_fpu(&self.fd).map_err(Error::FPUConfiguration)?;
-        arch::x86_64::regs::setup_sregs(guest_mem, &self.fd).map_err(Error::SREGSConfiguration)?;
+        arch::x86_64::regs::setup_sregs(guest_mem, &self.fd, vcpu_config.memory_encryption_mask)
+            .map_err(Error::SREGSConfiguration)?;
         arch::x86_64::interrupts::set_lint(&self.fd).map_err(Error::LocalIntConfiguration)?;
         Ok(())
     }
@@ -907,7 +1286,33 @@ impl Vcpu {
         }
     }
 
-    #[allow(unused)]
+    /// Returns the frequency of the TSC of the vCPU, in kHz.
+    #[cfg(target_arch = "x86_64")]
+    fn get_tsc_khz(&self) -> Result<u32> {
+        // Safe because the ioctl doesn't access our memory, and we check the return value.
+        let ret = unsafe { ioctl(&self.fd, KVM_GET_TSC_KHZ) };
+        if ret < 0 {
+            return Err(Error::VcpuGetTscKhz(io::Error::last_os_error()));
+        }
+        Ok(ret as u32)
+    }
+
+    /// Scales the TSC of the vCPU to `tsc_khz`. Without TSC scaling support on the host, KVM
+    /// fails unless `tsc_khz` is close to or above the frequency of the host TSC.
+    #[cfg(target_arch = "x86_64")]
+    fn set_tsc_khz(&self, tsc_khz: u32) -> Result<()> {
+        if !self.kvm_caps.tsc_control {
+            return Err(Error::KvmCaps(vec!["KVM_CAP_TSC_CONTROL"]));
+        }
+        // Safe because the ioctl doesn't access our memory, and we check the return value.
+        let ret =
+            unsafe { ioctl_with_val(&self.fd, KVM_SET_TSC_KHZ, libc::c_ulong::from(tsc_khz)) };
+        if ret < 0 {
+            return Err(Error::VcpuSetTscKhz(io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
     #[cfg(target_arch = "x86_64")]
     fn save_state(&self) -> Result<VcpuState> {
         /*
@@ -968,12 +1373,15 @@ impl Vcpu {
             vcpu_events,
             xcrs,
             xsave,
+            // Unless it is pinned, the TSC ticks at the rate of the target host once restored.
+            tsc_khz: self.tsc_khz,
         })
     }
 
-    #[allow(unused)]
+    /// Restores the vCPU KVM state. Must be called before the vCPU thread is started, or while
+    /// all the other vCPUs are paused.
     #[cfg(target_arch = "x86_64")]
-    fn restore_state(&self, state: VcpuState) -> Result<()> {
+    pub(crate) fn restore_state(&mut self, state: VcpuState) -> Result<()> {
         /*
          * Ordering requirements:
          *
@@ -995,7 +1403,18 @@ impl Vcpu {
          *
          * SET_LAPIC must come before SET_MSRS, because the TSC deadline MSR
          * only restores successfully, when the LAPIC is correctly configured.
+         *
+         * SET_TSC_KHZ must come before SET_MSRS, because KVM computes the TSC
+         * offset from the TSC frequency when restoring the TSC MSR.
          */
+        if let Some(tsc_khz) = state.tsc_khz {
+            // Keep the pinned TSC ticking at the same rate, which needs no scaling when the
+            // target host TSC already has it.
+            if !self.kvm_caps.get_tsc_khz || self.get_tsc_khz()? != tsc_khz {
+                self.set_tsc_khz(tsc_khz)?;
+            }
+        }
+        self.tsc_khz = state.tsc_khz;
         self.fd
             .set_cpuid2(&state.cpuid)
             .map_err(Error::VcpuSetCpuid)?;
@@ -1060,6 +1479,10 @@ impl Vcpu {
                     }
                     Ok(VcpuEmulation::Handled)
                 }
+                // The end of the level-triggered interrupts routed through the IOAPIC emulated
+                // in userspace. The devices signal them again as long as they are pending.
+                #[cfg(target_arch = "x86_64")]
+                VcpuExit::IoapicEoi(_) => Ok(VcpuEmulation::Handled),
                 VcpuExit::Hlt => {
                     info!("Received KVM_EXIT_HLT signal");
                     Ok(VcpuEmulation::Stopped)
@@ -1092,12 +1515,15 @@ impl Vcpu {
             // error in our code in which case it is better to panic.
             Err(ref e) => {
                 match e.errno() {
-                    libc::EAGAIN => Ok(VcpuEmulation::Handled),
                     libc::EINTR => {
                         self.fd.set_kvm_immediate_exit(0);
                         // Notify that this KVM_RUN was interrupted.
                         Ok(VcpuEmulation::Interrupted)
                     }
+                    // These may go away, e.g. once the host reclaims some memory.
+                    errno @ libc::EAGAIN | errno @ libc::ENOMEM => {
+                        Ok(VcpuEmulation::TransientFailure(errno))
+                    }
                     _ => {
                         METRICS.vcpu.failures.inc();
                         error!("Failure during vcpu run: {}", e);
@@ -1108,6 +1534,52 @@ impl Vcpu {
         }
     }
 
+    // Backs off before `KVM_RUN` is retried after the transient failure `errno`. Returns false once
+    // the consecutive failures exhaust the retries, in which case the failure is fatal.
+    fn back_off_kvm_run(&mut self, errno: i32) -> bool {
+        self.kvm_run_failures += 1;
+        self.kvm_run_errno = errno;
+        if self.kvm_run_failures > self.retry_config.max_retries {
+            METRICS.vcpu.failures.inc();
+            error!(
+                "vCPU {} gave up on KVM_RUN after {} failures: {}",
+                self.id,
+                self.kvm_run_failures,
+                io::Error::from_raw_os_error(errno)
+            );
+            self.send_retry_event(false);
+            return false;
+        }
+        METRICS.vcpu.kvm_run_retries.inc();
+        thread::sleep(self.retry_config.backoff(self.kvm_run_failures));
+        true
+    }
+
+    // Closes the streak of transient failures of `KVM_RUN`, if any, once it succeeds.
+    fn kvm_run_succeeded(&mut self) {
+        if self.kvm_run_failures > 0 {
+            warn!(
+                "vCPU {} recovered after {} failures of KVM_RUN: {}",
+                self.id,
+                self.kvm_run_failures,
+                io::Error::from_raw_os_error(self.kvm_run_errno)
+            );
+            self.send_retry_event(true);
+            self.kvm_run_failures = 0;
+        }
+    }
+
+    fn send_retry_event(&self, recovered: bool) {
+        if let Some(events) = self.events.as_ref() {
+            events.send(VmmEvent::VcpuRetried {
+                vcpu_id: self.id,
+                errno: self.kvm_run_errno,
+                failures: self.kvm_run_failures,
+                recovered,
+            });
+        }
+    }
+
     /// Main loop of the vCPU thread.
     ///
     /// Runs the vCPU in KVM context in a loop. Handles KVM_EXITs then goes back in.
@@ -1135,9 +1607,16 @@ impl Vcpu {
         loop {
             match self.run_emulation() {
                 // Emulation ran successfully, continue.
-                Ok(VcpuEmulation::Handled) => (),
+                Ok(VcpuEmulation::Handled) => self.kvm_run_succeeded(),
                 // Emulation was interrupted, check external events.
                 Ok(VcpuEmulation::Interrupted) => break,
+                // KVM_RUN failed, but may succeed if retried. The vCPU is unresponsive while it
+                // backs off, which is short.
+                Ok(VcpuEmulation::TransientFailure(errno)) => {
+                    if !self.back_off_kvm_run(errno) {
+                        return self.exit(FcExitCode::GenericError);
+                    }
+                }
                 // If the guest was rebooted or halted:
                 // - vCPU0 will always exit out of `KVM_RUN` with KVM_EXIT_SHUTDOWN or
                 //   KVM_EXIT_HLT.
@@ -1145,9 +1624,9 @@ impl Vcpu {
                 // Moreover if we allow the vCPU0 thread to finish execution, this might generate a
                 // seccomp failure because musl calls `sigprocmask` as part of `pthread_exit`.
                 // So we pause vCPU0 and send a signal to the emulation thread to stop the VMM.
-                Ok(VcpuEmulation::Stopped) => return self.exit(FC_EXIT_CODE_OK),
+                Ok(VcpuEmulation::Stopped) => return self.exit(FcExitCode::Ok),
                 // Emulation errors lead to vCPU exit.
-                Err(_) => return self.exit(FC_EXIT_CODE_GENERIC_ERROR),
+                Err(_) => return self.exit(FcExitCode::GenericError),
             }
         }
 
@@ -1174,10 +1653,19 @@ impl Vcpu {
                     .send(VcpuResponse::Resumed)
                     .expect("failed to send resume status");
             }
+            #[cfg(target_arch = "x86_64")]
+            Ok(VcpuEvent::SaveState) => {
+                // The vCPU state can only be consistently saved while paused.
+                self.response_sender
+                    .send(VcpuResponse::NotAllowed(
+                        "save/restore unavailable while running".to_string(),
+                    ))
+                    .expect("failed to send save not allowed status");
+            }
             // Unhandled exit of the other end.
             Err(TryRecvError::Disconnected) => {
                 // Move to 'exited' state.
-                state = self.exit(FC_EXIT_CODE_GENERIC_ERROR);
+                state = self.exit(FcExitCode::GenericError);
             }
             // All other events or lack thereof have no effect on current 'running' state.
             Err(TryRecvError::Empty) => (),
@@ -1198,19 +1686,31 @@ impl Vcpu {
                 // Move to 'running' state.
                 StateMachine::next(Self::running)
             }
+            #[cfg(target_arch = "x86_64")]
+            Ok(VcpuEvent::SaveState) => {
+                // Save vcpu state.
+                let response = match self.save_state() {
+                    Ok(vcpu_state) => VcpuResponse::SavedState(Box::new(vcpu_state)),
+                    Err(e) => VcpuResponse::Error(e),
+                };
+                self.response_sender
+                    .send(response)
+                    .expect("failed to send save vcpu state status");
+                StateMachine::next(Self::paused)
+            }
             // All other events have no effect on current 'paused' state.
             Ok(_) => StateMachine::next(Self::paused),
             // Unhandled exit of the other end.
             Err(_) => {
                 // Move to 'exited' state.
-                self.exit(FC_EXIT_CODE_GENERIC_ERROR)
+                self.exit(FcExitCode::GenericError)
             }
         }
     }
 
     #[cfg(not(test))]
     // Transition to the exited state.
-    fn exit(&mut self, exit_code: u8) -> StateMachine<Self> {
+    fn exit(&mut self, exit_code: FcExitCode) -> StateMachine<Self> {
         self.response_sender
             .send(VcpuResponse::Exited(exit_code))
             .expect("failed to send Exited status");
@@ -1265,10 +1765,17 @@ pub struct VcpuState {
     vcpu_events: kvm_vcpu_events,
     xcrs: kvm_xcrs,
     xsave: kvm_xsave,
+    #[version(start = 2, default_fn = "default_tsc_khz")]
+    tsc_khz: Option<u32>,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl VcpuState {
+    fn default_tsc_khz(_: u16) -> Option<u32> {
+        None
+    }
 }
 
-// Allow currently unused Pause and Exit events. These will be used by the vmm later on.
-#[allow(unused)]
 #[derive(Debug)]
 /// List of events that the Vcpu can receive.
 pub enum VcpuEvent {
@@ -1276,18 +1783,41 @@ pub enum VcpuEvent {
     Pause,
     /// Event that should resume the Vcpu.
     Resume,
-    // Serialize and Deserialize to follow after we get the support from kvm-ioctls.
+    /// Save the Vcpu KVM state. Only allowed while the Vcpu is paused.
+    #[cfg(target_arch = "x86_64")]
+    SaveState,
 }
 
-#[derive(Debug, PartialEq)]
 /// List of responses that the Vcpu reports.
 pub enum VcpuResponse {
+    /// Requested action encountered an error.
+    Error(Error),
+    /// Vcpu is stopped.
+    Exited(FcExitCode),
+    /// Requested action not allowed.
+    NotAllowed(String),
     /// Vcpu is paused.
     Paused,
     /// Vcpu is resumed.
     Resumed,
-    /// Vcpu is stopped.
-    Exited(u8),
+    /// Vcpu state is saved.
+    #[cfg(target_arch = "x86_64")]
+    SavedState(Box<VcpuState>),
+}
+
+impl std::fmt::Debug for VcpuResponse {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::VcpuResponse::*;
+        match self {
+            Error(e) => write!(f, "VcpuResponse::Error({:?})", e),
+            Exited(code) => write!(f, "VcpuResponse::Exited({:?})", code),
+            NotAllowed(reason) => write!(f, "VcpuResponse::NotAllowed({})", reason),
+            Paused => write!(f, "VcpuResponse::Paused"),
+            Resumed => write!(f, "VcpuResponse::Resumed"),
+            #[cfg(target_arch = "x86_64")]
+            SavedState(_) => write!(f, "VcpuResponse::SavedState"),
+        }
+    }
 }
 
 /// Wrapper over Vcpu that hides the underlying interactions with the Vcpu thread.
@@ -1336,13 +1866,13 @@ enum VcpuEmulation {
     Handled,
     Interrupted,
     Stopped,
+    TransientFailure(i32),
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
     #[cfg(target_arch = "x86_64")]
     use std::convert::TryInto;
-    use std::fs::File;
     #[cfg(target_arch = "x86_64")]
     use std::os::unix::io::AsRawFd;
     #[cfg(target_arch = "x86_64")]
@@ -1355,9 +1885,25 @@ pub(crate) mod tests {
 
     use super::super::devices;
     use super::*;
+    use events::EventChannel;
 
     use utils::signal::validate_signal_num;
 
+    impl PartialEq for VcpuResponse {
+        fn eq(&self, other: &Self) -> bool {
+            use super::VcpuResponse::*;
+            // The saved states and the errors are not comparable, so only their kind is checked.
+            match (self, other) {
+                (Paused, Paused) | (Resumed, Resumed) => true,
+                (Exited(code), Exited(other_code)) => code == other_code,
+                (NotAllowed(_), NotAllowed(_)) | (Error(_), Error(_)) => true,
+                #[cfg(target_arch = "x86_64")]
+                (SavedState(_), SavedState(_)) => true,
+                _ => false,
+            }
+        }
+    }
+
     // In tests we need to close any pending Vcpu threads on test completion.
     impl Drop for VcpuHandle {
         fn drop(&mut self) {
@@ -1383,12 +1929,13 @@ pub(crate) mod tests {
         let vcpu;
         #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
         {
-            vm.setup_irqchip().unwrap();
+            vm.setup_irqchip(true).unwrap();
             vcpu = Vcpu::new_x86_64(
                 1,
                 vm.fd(),
                 vm.supported_cpuid().clone(),
                 vm.supported_msrs().clone(),
+                vm.optional_kvm_caps(),
                 devices::Bus::new(),
                 exit_evt,
                 super::super::TimestampUs::default(),
@@ -1399,7 +1946,8 @@ pub(crate) mod tests {
         {
             vcpu = Vcpu::new_aarch64(1, vm.fd(), exit_evt, super::super::TimestampUs::default())
                 .unwrap();
-            vm.setup_irqchip(1).expect("Cannot setup irqchip");
+            vm.setup_irqchip(1, &GICConfig::default())
+                .expect("Cannot setup irqchip");
         }
 
         (vm, vcpu, gm)
@@ -1418,6 +1966,7 @@ pub(crate) mod tests {
             vcpu_events: Default::default(),
             xcrs: Default::default(),
             xsave: Default::default(),
+            tsc_khz: None,
         }
     }
 
@@ -1429,6 +1978,52 @@ pub(crate) mod tests {
         assert!(vcpu.mmio_bus.is_some());
     }
 
+    #[test]
+    fn test_kvm_run_retries() {
+        let (_vm, mut vcpu, _mem) = setup_vcpu(0x1000);
+        let channel = EventChannel::new(4);
+        vcpu.set_event_sender(channel.sender());
+        vcpu.set_retry_config(VcpuRetryConfig {
+            max_retries: 2,
+            initial_backoff_us: 1,
+            max_backoff_us: 1,
+        });
+
+        // Nothing to report while KVM_RUN keeps succeeding.
+        vcpu.kvm_run_succeeded();
+        assert!(channel.drain().is_empty());
+
+        let retries = METRICS.vcpu.kvm_run_retries.count();
+        assert!(vcpu.back_off_kvm_run(libc::EAGAIN));
+        assert!(vcpu.back_off_kvm_run(libc::ENOMEM));
+        assert_eq!(METRICS.vcpu.kvm_run_retries.count(), retries + 2);
+        assert!(channel.drain().is_empty());
+        vcpu.kvm_run_succeeded();
+        assert_eq!(
+            channel.drain(),
+            vec![VmmEvent::VcpuRetried {
+                vcpu_id: 1,
+                errno: libc::ENOMEM,
+                failures: 2,
+                recovered: true,
+            }]
+        );
+
+        // The failures are counted again from the first one.
+        assert!(vcpu.back_off_kvm_run(libc::ENOMEM));
+        assert!(vcpu.back_off_kvm_run(libc::ENOMEM));
+        assert!(!vcpu.back_off_kvm_run(libc::ENOMEM));
+        assert_eq!(
+            channel.drain(),
+            vec![VmmEvent::VcpuRetried {
+                vcpu_id: 1,
+                errno: libc::ENOMEM,
+                failures: 3,
+                recovered: false,
+            }]
+        );
+    }
+
     #[test]
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn test_get_supported_cpuid() {
@@ -1460,35 +2055,76 @@ pub(crate) mod tests {
             (GuestAddress(0x1001), 0x2000),
         ])
         .unwrap();
-        assert!(vm
-            .memory_init(&gm, kvm_context.max_memslots(), true)
-            .is_err());
+        match vm.memory_init(&gm, kvm_context.max_memslots(), true) {
+            Err(Error::NotEnoughMemorySlots(2, 1)) => (),
+            _ => panic!("The guest memory needs more memory slots than KVM has."),
+        }
     }
 
     #[cfg(target_arch = "x86_64")]
     #[test]
     fn test_setup_irqchip() {
         let kvm_context = KvmContext::new().unwrap();
-        let vm = Vm::new(kvm_context.fd()).expect("Cannot create new vm");
+        let mut vm = Vm::new(kvm_context.fd()).expect("Cannot create new vm");
 
-        vm.setup_irqchip().expect("Cannot setup irqchip");
+        vm.setup_irqchip(true).expect("Cannot setup irqchip");
         // Trying to setup two irqchips will result in EEXIST error. At the moment
         // there is no good way of testing the actual error because io::Error does not implement
         // PartialEq.
-        assert!(vm.setup_irqchip().is_err());
+        assert!(vm.setup_irqchip(true).is_err());
 
         let _vcpu = Vcpu::new_x86_64(
             1,
             vm.fd(),
             vm.supported_cpuid().clone(),
             vm.supported_msrs().clone(),
+            vm.optional_kvm_caps(),
             devices::Bus::new(),
             EventFd::new(libc::EFD_NONBLOCK).unwrap(),
             super::super::TimestampUs::default(),
         )
         .unwrap();
         // Trying to setup irqchip after KVM_VCPU_CREATE was called will result in error.
-        assert!(vm.setup_irqchip().is_err());
+        assert!(vm.setup_irqchip(true).is_err());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_setup_split_irqchip() {
+        let kvm_context = KvmContext::new().unwrap();
+        let mut vm = Vm::new(kvm_context.fd()).expect("Cannot create new vm");
+        assert!(!vm.split_irqchip());
+
+        vm.setup_split_irqchip().expect("Cannot split irqchip");
+        assert!(vm.split_irqchip());
+        // The irqchip is already created.
+        assert!(vm.setup_irqchip(true).is_err());
+        assert!(vm.setup_split_irqchip().is_err());
+
+        let mut routes = vec![None; IOAPIC_NUM_PINS];
+        routes[4] = Some(MsiRoute {
+            address: 0xfee0_0000,
+            data: 0x24,
+        });
+        let router = vm.msi_router().unwrap();
+        router.set_routes(&routes).unwrap();
+        router.set_routes(&[]).unwrap();
+
+        let _vcpu = Vcpu::new_x86_64(
+            1,
+            vm.fd(),
+            vm.supported_cpuid().clone(),
+            vm.supported_msrs().clone(),
+            vm.optional_kvm_caps(),
+            devices::Bus::new(),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            super::super::TimestampUs::default(),
+        )
+        .unwrap();
+        match vm.save_state() {
+            Err(Error::SplitIrqchipState) => (),
+            _ => panic!("The state of a split irqchip should not be saved."),
+        }
     }
 
     #[cfg(target_arch = "aarch64")]
@@ -1506,9 +2142,10 @@ pub(crate) mod tests {
         )
         .unwrap();
 
-        vm.setup_irqchip(vcpu_count).expect("Cannot setup irqchip");
+        vm.setup_irqchip(vcpu_count, &GICConfig::default())
+            .expect("Cannot setup irqchip");
         // Trying to setup two irqchips will result in EEXIST error.
-        assert!(vm.setup_irqchip(vcpu_count).is_err());
+        assert!(vm.setup_irqchip(vcpu_count, &GICConfig::default()).is_err());
     }
 
     #[cfg(target_arch = "x86_64")]
@@ -1519,7 +2156,13 @@ pub(crate) mod tests {
         let mut vcpu_config = VcpuConfig {
             vcpu_count: 1,
             ht_enabled: false,
+            cores_per_socket: None,
             cpu_template: None,
+            cpu_features: None,
+            nested: false,
+            tsc_khz: None,
+            tickless: false,
+            memory_encryption_mask: 0,
         };
 
         assert!(vcpu
@@ -1537,6 +2180,43 @@ pub(crate) mod tests {
         assert!(vcpu
             .configure_x86_64(&vm_mem, GuestAddress(0), &vcpu_config)
             .is_ok());
+
+        // Test configure while exposing the host CPU features on top of the template.
+        vcpu_config.cpu_features =
+            Some(CpuFeaturesConfig::Profile(CpuFeaturesProfile::Passthrough));
+        assert!(vcpu
+            .configure_x86_64(&vm_mem, GuestAddress(0), &vcpu_config)
+            .is_ok());
+
+        vcpu_config.cpu_features = Some(CpuFeaturesConfig::Profile(CpuFeaturesProfile::Minimal));
+        assert!(vcpu
+            .configure_x86_64(&vm_mem, GuestAddress(0), &vcpu_config)
+            .is_ok());
+
+        // Test configure while exposing the virtualization extensions, whether the host allows
+        // nested virtualization or not.
+        vcpu_config.nested = true;
+        assert!(vcpu
+            .configure_x86_64(&vm_mem, GuestAddress(0), &vcpu_config)
+            .is_ok());
+
+        // Test configure while pinning the TSC to its current frequency, which KVM accepts
+        // whether the host supports TSC scaling or not.
+        vcpu_config.tsc_khz = Some(vcpu.get_tsc_khz().unwrap());
+        assert!(vcpu
+            .configure_x86_64(&vm_mem, GuestAddress(0), &vcpu_config)
+            .is_ok());
+        assert_eq!(vcpu.get_tsc_khz().unwrap(), vcpu_config.tsc_khz.unwrap());
+        assert_eq!(vcpu.tsc_khz, vcpu_config.tsc_khz);
+
+        // Test configure while using the T2A template, which only applies to AMD hosts.
+        vcpu_config.cpu_template = Some(CpuFeaturesTemplate::T2A);
+        let is_amd_host = VmSpec::new(0, 1, false).unwrap().cpu_vendor_id() == b"AuthenticAMD";
+        assert_eq!(
+            vcpu.configure_x86_64(&vm_mem, GuestAddress(0), &vcpu_config)
+                .is_ok(),
+            is_amd_host
+        );
     }
 
     #[cfg(target_arch = "aarch64")]
@@ -1582,6 +2262,14 @@ pub(crate) mod tests {
         let c = KvmContext::new().unwrap();
 
         assert!(c.max_memslots >= 32);
+        assert!(missing_kvm_caps(c.fd(), &REQUIRED_KVM_CAPS).is_empty());
+
+        let c = KvmContext::from_file(File::open("/dev/kvm").unwrap()).unwrap();
+        assert!(c.max_memslots >= 32);
+        match KvmContext::from_file(File::open("/dev/null").unwrap()) {
+            Err(Error::KvmApiVersion(_)) => (),
+            _ => panic!("Only /dev/kvm can back a KVM context."),
+        }
 
         let kvm = Kvm::new().unwrap();
         let f = unsafe { File::from_raw_fd(kvm.as_raw_fd()) };
@@ -1751,7 +2439,13 @@ pub(crate) mod tests {
         let vcpu_config = VcpuConfig {
             vcpu_count: 1,
             ht_enabled: false,
+            cores_per_socket: None,
             cpu_template: None,
+            cpu_features: None,
+            nested: false,
+            tsc_khz: None,
+            tickless: false,
+            memory_encryption_mask: 0,
         };
         vcpu.configure_x86_64(&vm_mem, entry_addr, &vcpu_config)
             .expect("failed to configure vcpu");
@@ -1785,6 +2479,33 @@ pub(crate) mod tests {
 
         // Queue a Resume event, expect a response.
         queue_event_expect_response(&vcpu_handle, VcpuEvent::Resume, VcpuResponse::Resumed);
+
+        // Saving the state of a running vcpu is not allowed.
+        queue_event_expect_response(
+            &vcpu_handle,
+            VcpuEvent::SaveState,
+            VcpuResponse::NotAllowed(String::new()),
+        );
+
+        // Saving the state of a paused vcpu is allowed.
+        queue_event_expect_response(&vcpu_handle, VcpuEvent::Pause, VcpuResponse::Paused);
+        handle_save_state_response(&vcpu_handle);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    // Asks a paused vcpu for its state and expects to get it.
+    fn handle_save_state_response(handle: &VcpuHandle) {
+        handle
+            .send_event(VcpuEvent::SaveState)
+            .expect("failed to send event to vcpu");
+        match handle
+            .response_receiver()
+            .recv_timeout(Duration::from_millis(100))
+            .expect("did not receive event response from vcpu")
+        {
+            VcpuResponse::SavedState(_) => (),
+            response => panic!("unexpected response: {:?}", response),
+        }
     }
 
     #[test]
@@ -1813,19 +2534,89 @@ pub(crate) mod tests {
 
         let (vm, _, _mem) = setup_vcpu(0x1000);
         assert!(vm.restore_state(&vm_state).is_ok());
+
+        // The state of a missing PIT is left empty.
+        let kvm_context = KvmContext::new().unwrap();
+        let mut vm = Vm::new(kvm_context.fd()).unwrap();
+        vm.setup_irqchip(false).unwrap();
+        assert!(vm.fd().get_pit2().is_err());
+        let vm_state = vm.save_state().unwrap();
+        assert_eq!(vm_state.pitstate.flags, 0);
+        assert!(vm.restore_state(&vm_state).is_ok());
     }
 
     #[cfg(target_arch = "x86_64")]
     #[test]
     fn test_vcpu_save_restore_state() {
-        let (_vm, vcpu, _mem) = setup_vcpu(0x1000);
-        let state = vcpu.save_state();
-        assert!(state.is_ok());
-        assert!(vcpu.restore_state(state.unwrap()).is_ok());
+        let (_vm, mut vcpu, _mem) = setup_vcpu(0x1000);
+        // The TSC frequency is only saved when it is pinned.
+        let state = vcpu.save_state().unwrap();
+        assert_eq!(state.tsc_khz, None);
+        assert!(vcpu.restore_state(state).is_ok());
+
+        let mut state = vcpu.save_state().unwrap();
+        state.tsc_khz = Some(vcpu.get_tsc_khz().unwrap());
+        assert!(vcpu.restore_state(state).is_ok());
+        assert_eq!(vcpu.save_state().unwrap().tsc_khz, vcpu.tsc_khz);
+        assert!(vcpu.tsc_khz.is_some());
 
         unsafe { libc::close(vcpu.fd.as_raw_fd()) };
         let state = default_vcpu_state();
         // Setting default state should always fail.
         assert!(vcpu.restore_state(state).is_err());
     }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_optional_kvm_caps() {
+        let tsc_deadline_timer = |vcpu: &Vcpu| {
+            let leaf = vcpu
+                .cpuid
+                .as_slice()
+                .iter()
+                .find(|entry| entry.function == 1);
+            leaf.unwrap().ecx & (1 << 24) != 0
+        };
+        let (_vm, mut vcpu, vm_mem) = setup_vcpu(0x10000);
+        let mut vcpu_config = VcpuConfig {
+            vcpu_count: 1,
+            ht_enabled: false,
+            cores_per_socket: None,
+            cpu_template: None,
+            cpu_features: None,
+            nested: false,
+            tsc_khz: None,
+            tickless: false,
+            memory_encryption_mask: 0,
+        };
+        vcpu.configure_x86_64(&vm_mem, GuestAddress(0), &vcpu_config)
+            .unwrap();
+        // The hosts running the tests are recent enough to have all of them.
+        assert!(tsc_deadline_timer(&vcpu));
+
+        let (_vm, mut vcpu, vm_mem) = setup_vcpu(0x10000);
+        vcpu.kvm_caps = OptionalKvmCaps {
+            tsc_deadline_timer: false,
+            get_tsc_khz: false,
+            tsc_control: false,
+        };
+        vcpu.configure_x86_64(&vm_mem, GuestAddress(0), &vcpu_config)
+            .unwrap();
+        assert!(!tsc_deadline_timer(&vcpu));
+        // The snapshots leave out the TSC frequency.
+        let state = vcpu.save_state().unwrap();
+        assert_eq!(state.tsc_khz, None);
+        assert!(vcpu.restore_state(state).is_ok());
+
+        // The TSC frequency can't be pinned.
+        vcpu_config.tsc_khz = Some(1_000_000);
+        match vcpu.configure_x86_64(&vm_mem, GuestAddress(0), &vcpu_config) {
+            Err(Error::KvmCaps(ref names)) => assert_eq!(names, &["KVM_CAP_TSC_CONTROL"]),
+            _ => panic!("Pinning the TSC frequency should need KVM_CAP_TSC_CONTROL."),
+        }
+        assert_eq!(
+            Error::KvmCaps(vec!["KVM_CAP_TSC_CONTROL", "KVM_CAP_PIT2"]).to_string(),
+            "The host kernel lacks the KVM extensions: KVM_CAP_TSC_CONTROL, KVM_CAP_PIT2"
+        );
+    }
 }
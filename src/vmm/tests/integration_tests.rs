@@ -25,6 +25,7 @@ use vmm::builder::{build_microvm, setup_serial_device};
 use vmm::default_syscalls::get_seccomp_filter;
 use vmm::resources::VmResources;
 use vmm::vmm_config::boot_source::BootSourceConfig;
+use vmm::FcExitCode;
 use vmm_sys_util::tempfile::TempFile;
 
 use mock_devices::MockSerialInput;
@@ -68,7 +69,7 @@ fn test_build_microvm() {
 
         let vmm = build_microvm(&resources, &mut event_manager, &empty_seccomp_filter).unwrap();
         // This exits the process, so we won't get the output from cargo.
-        vmm.lock().unwrap().stop(0);
+        vmm.lock().unwrap().stop(FcExitCode::Ok);
     }
 }
 
@@ -90,5 +91,5 @@ fn test_vmm_seccomp() {
     thread::sleep(Duration::from_millis(30));
     assert!(unsafe { SIGSYS_RECEIVED });
     // This exits the process, so we won't get the output from cargo.
-    vmm.lock().unwrap().stop(0);
+    vmm.lock().unwrap().stop(FcExitCode::Ok);
 }
@@ -34,6 +34,7 @@ impl MockBootSourceConfig {
         MockBootSourceConfig(BootSourceConfig {
             kernel_image_path: default_kernel_image_path(),
             initrd_path: None,
+            kernel_bytes: None,
             boot_args: None,
         })
     }
@@ -304,7 +304,8 @@ class MachineConfigure():
             mem_size_mib=None,
             ht_enabled=None,
             cpu_template=None,
-            track_dirty_pages=None):
+            track_dirty_pages=None,
+            memfd_backed=None):
         """Compose the json associated to this type of API request."""
         datax = {}
         if vcpu_count is not None:
@@ -322,6 +323,9 @@ class MachineConfigure():
         if track_dirty_pages is not None:
             datax['track_dirty_pages'] = track_dirty_pages
 
+        if memfd_backed is not None:
+            datax['memfd_backed'] = memfd_backed
+
         return datax
 
 